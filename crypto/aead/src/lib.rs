@@ -8,7 +8,7 @@ use argon2::{Argon2, Params};
 use rand::rngs::OsRng;
 use rand::Rng;
 use ring::aead::Nonce;
-pub use ring::aead::{Aad, LessSafeKey, UnboundKey, NONCE_LEN};
+pub use ring::aead::{Aad, LessSafeKey, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
 
 /// Get a random nonce.
 pub fn get_random_nonce() -> ring::aead::Nonce {
@@ -5,8 +5,8 @@ mod bench {
     extern crate test;
 
     use tbs::{
-        blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, unblind_signature,
-        verify, BlindingKey, Message,
+        blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, sign_blinded_msgs,
+        unblind_signature, verify, verify_blind_share, verify_blind_shares, BlindingKey, Message,
     };
     use test::Bencher;
 
@@ -29,6 +29,56 @@ mod bench {
         bencher.iter(|| sign_blinded_msg(bmsg, sks[0]));
     }
 
+    #[bench]
+    fn bench_batch_signing(bencher: &mut Bencher) {
+        let (_pk, _pks, sks) = dealer_keygen(4, 5);
+        let batch = (0..100u8)
+            .map(|i| {
+                let msg = Message::from_bytes(&[i]);
+                (blind_message(msg, BlindingKey::random()), sks[0])
+            })
+            .collect::<Vec<_>>();
+
+        bencher.iter(|| sign_blinded_msgs(&batch));
+    }
+
+    /// Verifies the shares of a peer for a reissuance of 500 notes one by one,
+    /// the key shares of the dealer stand in for the keys of five amount tiers
+    #[bench]
+    fn bench_share_verification(bencher: &mut Bencher) {
+        let (_pk, pks, sks) = dealer_keygen(4, 5);
+        let batch = (0..500u16)
+            .map(|i| {
+                let msg = Message::from_bytes(&i.to_be_bytes());
+                let bmsg = blind_message(msg, BlindingKey::random());
+                let tier = i as usize % 5;
+                (bmsg, sign_blinded_msg(bmsg, sks[tier]), pks[tier])
+            })
+            .collect::<Vec<_>>();
+
+        bencher.iter(|| {
+            batch
+                .iter()
+                .all(|(bmsg, share, pk)| verify_blind_share(*bmsg, *share, *pk))
+        });
+    }
+
+    /// Verifies the same shares as [`bench_share_verification`] in a batch
+    #[bench]
+    fn bench_batch_share_verification(bencher: &mut Bencher) {
+        let (_pk, pks, sks) = dealer_keygen(4, 5);
+        let batch = (0..500u16)
+            .map(|i| {
+                let msg = Message::from_bytes(&i.to_be_bytes());
+                let bmsg = blind_message(msg, BlindingKey::random());
+                let tier = i as usize % 5;
+                (bmsg, sign_blinded_msg(bmsg, sks[tier]), pks[tier])
+            })
+            .collect::<Vec<_>>();
+
+        bencher.iter(|| verify_blind_shares(&batch));
+    }
+
     #[bench]
     fn bench_combine(bencher: &mut Bencher) {
         let msg = Message::from_bytes(b"Hello World!");
@@ -5,7 +5,9 @@
 
 use std::hash::Hasher;
 
-use bls12_381::{pairing, G1Affine, G1Projective, G2Affine, G2Projective};
+use bls12_381::{
+    multi_miller_loop, pairing, G1Affine, G1Projective, G2Affine, G2Prepared, G2Projective, Gt,
+};
 pub use bls12_381::{G1Affine as MessagePoint, G2Affine as PubKeyPoint, Scalar};
 use ff::Field;
 use group::Curve;
@@ -129,6 +131,27 @@ impl SecretKeyShare {
     pub fn to_pub_key_share(self) -> PublicKeyShare {
         PublicKeyShare((G2Projective::generator() * self.0).to_affine())
     }
+
+    /// The share of the key `self + factor * other`. Since shares are linear,
+    /// the shares of all peers combine to the same sum of the shared keys.
+    pub fn add_multiple(self, other: SecretKeyShare, factor: Scalar) -> SecretKeyShare {
+        SecretKeyShare(self.0 + other.0 * factor)
+    }
+}
+
+impl PublicKeyShare {
+    /// The public key share of [`SecretKeyShare::add_multiple`]
+    pub fn add_multiple(self, other: PublicKeyShare, factor: Scalar) -> PublicKeyShare {
+        PublicKeyShare((G2Projective::from(self.0) + other.0 * factor).to_affine())
+    }
+}
+
+impl AggregatePublicKey {
+    /// The aggregate of the public key shares of
+    /// [`PublicKeyShare::add_multiple`]
+    pub fn add_multiple(self, other: AggregatePublicKey, factor: Scalar) -> AggregatePublicKey {
+        AggregatePublicKey((G2Projective::from(self.0) + other.0 * factor).to_affine())
+    }
 }
 
 impl BlindingKey {
@@ -174,6 +197,21 @@ pub fn sign_blinded_msg(msg: BlindedMessage, sks: SecretKeyShare) -> BlindedSign
     BlindedSignatureShare(sig.to_affine())
 }
 
+/// Signs a batch of blinded messages, each with its own key share. Compared to
+/// calling [`sign_blinded_msg`] for every message this converts all
+/// signatures to affine coordinates at once, sharing a single field inversion.
+pub fn sign_blinded_msgs(batch: &[(BlindedMessage, SecretKeyShare)]) -> Vec<BlindedSignatureShare> {
+    let sigs = batch
+        .iter()
+        .map(|(msg, sks)| msg.0 * sks.0)
+        .collect::<Vec<G1Projective>>();
+
+    let mut affine_sigs = vec![G1Affine::identity(); sigs.len()];
+    G1Projective::batch_normalize(&sigs, &mut affine_sigs);
+
+    affine_sigs.into_iter().map(BlindedSignatureShare).collect()
+}
+
 /// Combines a sufficient amount of valid blinded signature shares to a blinded
 /// signature. The responsibility of verifying the supplied shares lies with the
 /// caller.
@@ -227,6 +265,50 @@ pub fn verify_blind_share(
     pairing(&msg.0, &pk.0) == pairing(&sig.0, &G2Affine::generator())
 }
 
+/// Verifies a batch of blind signature shares at once. Every share is weighted
+/// with a random factor, such that a batch containing an invalid share only
+/// verifies with negligible probability, which lets us check all shares with a
+/// single multi-pairing over one term per distinct public key share instead of
+/// two pairings per share.
+pub fn verify_blind_shares(
+    batch: &[(BlindedMessage, BlindedSignatureShare, PublicKeyShare)],
+) -> bool {
+    let mut sig_sum = G1Projective::identity();
+    let mut msg_sums: Vec<(PublicKeyShare, G1Projective)> = vec![];
+
+    for (msg, sig, pk) in batch {
+        // a 128 bit factor suffices to make forging a batch infeasible
+        let factor = Scalar::from_raw([OsRng.next_u64(), OsRng.next_u64(), 0, 0]);
+
+        sig_sum += sig.0 * factor;
+
+        match msg_sums.iter_mut().find(|(sum_pk, _)| sum_pk == pk) {
+            Some((_, msg_sum)) => *msg_sum += msg.0 * factor,
+            None => msg_sums.push((*pk, msg.0 * factor)),
+        }
+    }
+
+    let mut points = vec![G1Affine::identity(); msg_sums.len() + 1];
+    G1Projective::batch_normalize(
+        &msg_sums
+            .iter()
+            .map(|(_, msg_sum)| *msg_sum)
+            .chain(std::iter::once(-sig_sum))
+            .collect::<Vec<_>>(),
+        &mut points,
+    );
+
+    let keys = msg_sums
+        .iter()
+        .map(|(pk, _)| G2Prepared::from(pk.0))
+        .chain(std::iter::once(G2Prepared::from(G2Affine::generator())))
+        .collect::<Vec<_>>();
+
+    let terms = points.iter().zip(keys.iter()).collect::<Vec<_>>();
+
+    multi_miller_loop(&terms).final_exponentiation() == Gt::identity()
+}
+
 pub trait Aggregatable {
     type Aggregate;
 
@@ -254,8 +336,9 @@ impl Aggregatable for Vec<PublicKeyShare> {
 #[cfg(test)]
 mod tests {
     use crate::{
-        blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, unblind_signature,
-        verify, Aggregatable, BlindingKey, Message,
+        blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, sign_blinded_msgs,
+        unblind_signature, verify, verify_blind_shares, Aggregatable, BlindedSignatureShare,
+        BlindingKey, Message, Scalar,
     };
 
     #[test]
@@ -304,6 +387,91 @@ mod tests {
         assert!(verify(msg, sig, pk));
     }
 
+    #[test]
+    fn test_batch_signing() {
+        let (_, _pks, sks) = dealer_keygen(3, 4);
+
+        let batch = (0..10u8)
+            .map(|i| {
+                let msg = Message::from_bytes(&[i]);
+                (
+                    blind_message(msg, BlindingKey::random()),
+                    sks[i as usize % 4],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let batch_sigs = sign_blinded_msgs(&batch);
+        assert_eq!(batch_sigs.len(), batch.len());
+
+        for ((bmsg, sk), batch_sig) in batch.iter().zip(batch_sigs) {
+            assert_eq!(sign_blinded_msg(*bmsg, *sk).0, batch_sig.0);
+        }
+
+        assert!(sign_blinded_msgs(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_batch_share_verification() {
+        let (_, pks, sks) = dealer_keygen(3, 4);
+
+        let mut batch = (0..10u8)
+            .map(|i| {
+                let bmsg = blind_message(Message::from_bytes(&[i]), BlindingKey::random());
+                let idx = i as usize % 2;
+                (bmsg, sign_blinded_msg(bmsg, sks[idx]), pks[idx])
+            })
+            .collect::<Vec<_>>();
+
+        assert!(verify_blind_shares(&batch));
+        assert!(verify_blind_shares(&[]));
+
+        // a share signed with the key of another peer invalidates the batch
+        let (bmsg, _, pk) = batch[3];
+        batch[3] = (bmsg, sign_blinded_msg(bmsg, sks[2]), pk);
+        assert!(!verify_blind_shares(&batch));
+
+        // as does a share for another message
+        batch[3] = (bmsg, BlindedSignatureShare(batch[4].1 .0), pk);
+        assert!(!verify_blind_shares(&batch));
+    }
+
+    #[test]
+    fn test_linear_key_combination() {
+        let threshold = 3;
+        let factor = Scalar::from(7);
+        let msg = Message::from_bytes(b"Hello World!");
+        let bkey = BlindingKey::random();
+        let bmsg = blind_message(msg, bkey);
+
+        let (pk, pks, sks) = dealer_keygen(threshold, 4);
+        let (other_pk, other_pks, other_sks) = dealer_keygen(threshold, 4);
+
+        let combined_pks = pks
+            .iter()
+            .zip(&other_pks)
+            .map(|(pk, other)| pk.add_multiple(*other, factor))
+            .collect::<Vec<_>>();
+
+        let sigs = sks
+            .iter()
+            .zip(&other_sks)
+            .enumerate()
+            .map(|(idx, (sk, other))| {
+                let sk = sk.add_multiple(*other, factor);
+                assert_eq!(sk.to_pub_key_share(), combined_pks[idx]);
+                (idx, sign_blinded_msg(bmsg, sk))
+            })
+            .collect::<Vec<_>>();
+
+        let combined_pk = pk.add_multiple(other_pk, factor);
+        assert_eq!(combined_pks.aggregate(threshold), combined_pk);
+
+        let sig = unblind_signature(bkey, combine_valid_shares(sigs, threshold));
+        assert!(verify(msg, sig, combined_pk));
+        assert!(!verify(msg, sig, pk));
+    }
+
     #[test]
     #[should_panic(expected = "Not enough signature shares")]
     fn test_insufficient_shares() {
@@ -17,8 +17,7 @@ use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{ApiAuth, ModuleCommon};
 use fedimint_core::util::SafeUrl;
 use fedimint_core::PeerId;
-use fedimint_server::config::ConfigGenParams;
-use fedimint_testing::federation::local_config_gen_params;
+use fedimint_server::config::{local_config_gen_params, ConfigGenParams};
 use fedimint_wallet_client::config::WalletClientConfig;
 use fedimintd::attach_default_module_init_params;
 use fedimintd::fedimintd::FM_EXTRA_DKG_META_VAR;
@@ -504,6 +503,11 @@ async fn set_config_gen_params(
         &mut server_gen_params,
         Network::Regtest,
         10,
+        vec![],
+        None,
+        Default::default(),
+        vec![],
+        &[],
     );
     // Since we are not actually calling `fedimintd` binary, parse and handle
     // `FM_EXTRA_META_DATA` like it would do.
@@ -520,6 +524,8 @@ async fn set_config_gen_params(
     let request = ConfigGenParamsRequest {
         meta,
         modules: server_gen_params,
+        limits: Default::default(),
+        fees: Default::default(),
     };
     client.set_config_gen_params(request, auth.clone()).await?;
     Ok(())
@@ -162,5 +162,18 @@ declare_vars! {
         FM_API_URL: String = params.consensus.peers[&params.local.our_id].api_url.to_string();
         FM_BIND_METRICS_API: String = format!("127.0.0.1:{}", globals.FM_PORT_FEDIMINTD_BASE as usize + 2 * globals.FM_FED_SIZE + params.local.our_id.to_usize());
         FM_DATA_DIR: PathBuf = mkdir(globals.FM_DATA_DIR.join(format!("server-{}", params.local.our_id.to_usize()))).await?;
+        FM_RED_TEAM_FAULTS: Option<String> = red_team_faults(params.local.our_id.to_usize());
     }
 }
+
+/// The faults of the guardian `FM_DEVFED_RED_TEAM_PEER` if it is the given
+/// peer, such that the other guardians stay honest
+fn red_team_faults(peer: usize) -> Option<String> {
+    let red_team_peer = std::env::var("FM_DEVFED_RED_TEAM_PEER").ok()?;
+
+    if red_team_peer.parse::<usize>().ok()? != peer {
+        return None;
+    }
+
+    std::env::var("FM_DEVFED_RED_TEAM_FAULTS").ok()
+}
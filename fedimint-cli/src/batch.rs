@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Context};
+use bitcoin_hashes::hex::ToHex;
+use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use fedimint_ln_client::{
+    InternalPayState, LightningClientExt, LnPayState, OutgoingLightningPayment, PayType,
+};
+use fedimint_mint_client::MintClientExt;
+use fedimint_wallet_client::{WalletClientExt, WithdrawState};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+use crate::client::parse_fedimint_amount;
+
+/// How long the recipients of e-cash have to reissue it before we try to
+/// take it back, which is long since they may only come online days later
+const BATCH_SPEND_TIMEOUT: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// A row of a batch file. JSON files contain an array of objects with these
+/// fields, CSV files a header naming the columns.
+///
+/// Fields of CSV files can not be quoted, which none of the fields needs.
+#[derive(Debug, Clone, Deserialize)]
+struct BatchRow {
+    /// One of `ln_pay`, `withdraw` or `spend_ecash`
+    #[serde(rename = "type")]
+    operation_type: String,
+    /// The invoice to pay or the address to withdraw to
+    #[serde(default)]
+    destination: String,
+    /// The amount to withdraw or spend, in msat unless a denomination like
+    /// `sat` is given
+    #[serde(default)]
+    amount: String,
+    /// Free text copied to the results, e.g. the name of the recipient
+    #[serde(default)]
+    reference: String,
+}
+
+#[derive(Debug, Clone)]
+enum BatchOperation {
+    LnPay(lightning_invoice::Bolt11Invoice),
+    Withdraw {
+        address: bitcoin::Address,
+        amount: bitcoin::Amount,
+    },
+    SpendEcash(Amount),
+}
+
+impl TryFrom<&BatchRow> for BatchOperation {
+    type Error = anyhow::Error;
+
+    fn try_from(row: &BatchRow) -> Result<Self, Self::Error> {
+        match row.operation_type.as_str() {
+            "ln_pay" => Ok(BatchOperation::LnPay(
+                lightning_invoice::Bolt11Invoice::from_str(&row.destination)
+                    .map_err(|e| anyhow!("Invalid invoice: {e}"))?,
+            )),
+            "withdraw" => {
+                let amount = parse_fedimint_amount(&row.amount).context("Invalid amount")?;
+                ensure!(
+                    amount.msats % 1000 == 0,
+                    "Withdrawals have to be whole satoshis"
+                );
+
+                Ok(BatchOperation::Withdraw {
+                    address: bitcoin::Address::from_str(&row.destination)
+                        .context("Invalid address")?,
+                    amount: bitcoin::Amount::from_sat(amount.msats / 1000),
+                })
+            }
+            "spend_ecash" => Ok(BatchOperation::SpendEcash(
+                parse_fedimint_amount(&row.amount).context("Invalid amount")?,
+            )),
+            other => bail!("Unknown operation type {other}"),
+        }
+    }
+}
+
+/// The outcome of a row, written to the results file as one JSON object per
+/// line
+#[derive(Debug, Serialize)]
+struct BatchResult {
+    /// Index of the row in the batch file, starting at 0
+    row: usize,
+    #[serde(rename = "type")]
+    operation_type: String,
+    reference: String,
+    #[serde(flatten)]
+    status: BatchStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum BatchStatus {
+    Succeeded {
+        operation_id: OperationId,
+        output: serde_json::Value,
+    },
+    Failed {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        operation_id: Option<OperationId>,
+        error: String,
+    },
+}
+
+/// Executes the operations of a JSON or CSV batch file, at most
+/// `concurrency` at a time, and writes the outcome of every row to
+/// `results_path` as soon as it is known. The file is checked completely
+/// before any operation is started.
+pub async fn run_batch(
+    client: &ClientArc,
+    batch_path: &Path,
+    results_path: &Path,
+    concurrency: usize,
+) -> anyhow::Result<serde_json::Value> {
+    ensure!(0 < concurrency, "Concurrency has to be at least 1");
+
+    let content = std::fs::read_to_string(batch_path).context("Reading batch file failed")?;
+    let rows = if batch_path.extension().map_or(false, |ext| ext == "csv") {
+        parse_csv_rows(&content)?
+    } else {
+        serde_json::from_str::<Vec<BatchRow>>(&content).context("Invalid JSON batch file")?
+    };
+
+    let operations = rows
+        .iter()
+        .enumerate()
+        .map(|(idx, row)| BatchOperation::try_from(row).with_context(|| format!("Row {idx}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if operations
+        .iter()
+        .any(|operation| matches!(operation, BatchOperation::LnPay(_)))
+    {
+        client.select_active_gateway().await?;
+    }
+
+    let mut results_file = File::create(results_path).context("Creating results file failed")?;
+    let mut results = futures::stream::iter(operations.into_iter().enumerate())
+        .map(|(idx, operation)| async move { (idx, execute_operation(client, operation).await) })
+        .buffer_unordered(concurrency);
+
+    let (mut succeeded, mut failed) = (0, 0);
+    while let Some((idx, (operation_id, outcome))) = results.next().await {
+        let status = match (operation_id, outcome) {
+            (Some(operation_id), Ok(output)) => {
+                succeeded += 1;
+                BatchStatus::Succeeded {
+                    operation_id,
+                    output,
+                }
+            }
+            (operation_id, Err(e)) => {
+                failed += 1;
+                BatchStatus::Failed {
+                    operation_id,
+                    error: e.to_string(),
+                }
+            }
+            (None, Ok(_)) => unreachable!("Only started operations have an outcome"),
+        };
+        info!(row = idx, ?status, "Batch row finished");
+
+        let result = BatchResult {
+            row: idx,
+            operation_type: rows[idx].operation_type.clone(),
+            reference: rows[idx].reference.clone(),
+            status,
+        };
+        writeln!(results_file, "{}", serde_json::to_string(&result)?)?;
+        results_file.flush()?;
+    }
+
+    Ok(json!({
+        "succeeded": succeeded,
+        "failed": failed,
+    }))
+}
+
+/// Starts the operation and waits for its outcome, the operation id is
+/// returned even if the operation failed later on
+async fn execute_operation(
+    client: &ClientArc,
+    operation: BatchOperation,
+) -> (Option<OperationId>, anyhow::Result<serde_json::Value>) {
+    match operation {
+        BatchOperation::LnPay(invoice) => {
+            let payment = match client.pay_bolt11_invoice(invoice).await {
+                Ok(payment) => payment,
+                Err(e) => return (None, Err(e)),
+            };
+            let operation_id = match payment.payment_type {
+                PayType::Internal(operation_id) | PayType::Lightning(operation_id) => operation_id,
+            };
+            (Some(operation_id), await_ln_pay(client, payment).await)
+        }
+        BatchOperation::Withdraw { address, amount } => {
+            let operation_id = async {
+                let fees = client.get_withdraw_fee(address.clone(), amount).await?;
+                client.withdraw(address, amount, fees).await
+            };
+            let operation_id = match operation_id.await {
+                Ok(operation_id) => operation_id,
+                Err(e) => return (None, Err(e)),
+            };
+            (
+                Some(operation_id),
+                await_withdraw(client, operation_id).await,
+            )
+        }
+        BatchOperation::SpendEcash(amount) => {
+            match client.spend_notes(amount, BATCH_SPEND_TIMEOUT, ()).await {
+                Ok((operation_id, notes)) => (
+                    Some(operation_id),
+                    Ok(json!({ "notes": notes.to_string() })),
+                ),
+                Err(e) => (None, Err(e)),
+            }
+        }
+    }
+}
+
+async fn await_ln_pay(
+    client: &ClientArc,
+    payment: OutgoingLightningPayment,
+) -> anyhow::Result<serde_json::Value> {
+    match payment.payment_type {
+        PayType::Internal(operation_id) => {
+            let mut updates = client
+                .subscribe_internal_pay(operation_id)
+                .await?
+                .into_stream();
+
+            while let Some(update) = updates.next().await {
+                match update {
+                    InternalPayState::Preimage(preimage) => {
+                        return Ok(json!({ "preimage": preimage.0.to_hex() }));
+                    }
+                    InternalPayState::RefundSuccess { error, .. }
+                    | InternalPayState::RefundError { error, .. }
+                    | InternalPayState::FundingFailed { error } => {
+                        bail!("Internal payment failed: {error}");
+                    }
+                    InternalPayState::UnexpectedError(e) => bail!(e),
+                    InternalPayState::Funding => {}
+                }
+            }
+        }
+        PayType::Lightning(operation_id) => {
+            let mut updates = client.subscribe_ln_pay(operation_id).await?.into_stream();
+
+            while let Some(update) = updates.next().await {
+                match update {
+                    LnPayState::Success { preimage } => {
+                        return Ok(json!({
+                            "preimage": preimage,
+                            "fee_msat": payment.fee.msats,
+                        }));
+                    }
+                    LnPayState::Canceled => bail!("Payment was canceled"),
+                    LnPayState::Refunded { gateway_error } => {
+                        bail!("Payment was refunded: {gateway_error}")
+                    }
+                    LnPayState::UnexpectedError { error_message } => bail!(error_message),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    bail!("Update stream ended without outcome")
+}
+
+async fn await_withdraw(
+    client: &ClientArc,
+    operation_id: OperationId,
+) -> anyhow::Result<serde_json::Value> {
+    let mut updates = client
+        .subscribe_withdraw_updates(operation_id)
+        .await?
+        .into_stream();
+
+    while let Some(update) = updates.next().await {
+        match update {
+            WithdrawState::Succeeded(txid) => return Ok(json!({ "txid": txid.to_hex() })),
+            WithdrawState::Failed(e) => bail!("Withdraw failed: {e}"),
+            WithdrawState::Created => {}
+        }
+    }
+
+    bail!("Update stream ended without outcome")
+}
+
+/// Parses CSV with a header naming the columns of [`BatchRow`]
+fn parse_csv_rows(content: &str) -> anyhow::Result<Vec<BatchRow>> {
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+
+    let header = lines
+        .next()
+        .context("Batch file has no header")?
+        .split(',')
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    lines
+        .enumerate()
+        .map(|(idx, line)| {
+            ensure!(
+                !line.contains('"'),
+                "Row {idx}: Quoted fields are not supported"
+            );
+
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            ensure!(
+                fields.len() == header.len(),
+                "Row {idx}: Expected {} fields, got {}",
+                header.len(),
+                fields.len()
+            );
+
+            let row = header
+                .iter()
+                .zip(fields)
+                .map(|(column, field)| (column.to_string(), field.to_string()))
+                .collect::<BTreeMap<_, _>>();
+
+            serde_json::to_value(row)
+                .and_then(serde_json::from_value)
+                .with_context(|| format!("Row {idx}"))
+        })
+        .collect()
+}
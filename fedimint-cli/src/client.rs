@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::ffi;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::{Duration, UNIX_EPOCH};
 
@@ -9,6 +10,7 @@ use bitcoin_hashes::hex;
 use bitcoin_hashes::hex::ToHex;
 use clap::Subcommand;
 use fedimint_client::backup::Metadata;
+use fedimint_client::oplog::OperationLogQuery;
 use fedimint_client::ClientArc;
 use fedimint_core::config::{ClientConfig, FederationId};
 use fedimint_core::core::{ModuleInstanceId, ModuleKind, OperationId};
@@ -16,10 +18,13 @@ use fedimint_core::time::now;
 use fedimint_core::{Amount, ParseAmountError, TieredSummary};
 use fedimint_ln_client::{
     InternalPayState, LightningClientExt, LnPayState, LnReceiveState, OutgoingLightningPayment,
-    PayType,
+    OutgoingTransfer, PayType, TransferReceiveState, TransferSendState,
 };
+use fedimint_ln_common::contracts::transfer::TransferRequest;
 use fedimint_ln_common::contracts::ContractId;
-use fedimint_mint_client::{MintClientExt, MintClientModule, OOBNotes};
+use fedimint_mint_client::{
+    MintClientExt, MintClientModule, OOBNotes, OfflineNotes, OfflineNotesChunk, OfflineNotesKey,
+};
 use fedimint_wallet_client::{WalletClientExt, WalletClientModule, WithdrawState};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
@@ -28,6 +33,7 @@ use time::format_description::well_known::iso8601;
 use time::OffsetDateTime;
 use tracing::info;
 
+use crate::batch::run_batch;
 use crate::{metadata_from_clap_cli, LnInvoiceResponse};
 
 #[derive(Debug, Clone)]
@@ -62,6 +68,19 @@ pub enum ClientCmd {
     /// Verifies the signatures of e-cash notes, but *not* if they have been
     /// spent already
     Validate { oob_notes: OOBNotes },
+    /// Prepare encrypted notes to hand over as QR codes to a third party who
+    /// reissues them once they are online
+    SpendOffline {
+        #[clap(value_parser = parse_fedimint_amount)]
+        amount: Amount,
+    },
+    /// Reissue encrypted notes handed over by a third party, unless some of
+    /// them were spent already
+    ReissueOffline {
+        #[clap(long)]
+        key: OfflineNotesKey,
+        chunks: Vec<OfflineNotesChunk>,
+    },
     /// Create a lightning invoice to receive payment via gateway
     LnInvoice {
         #[clap(long, value_parser = parse_fedimint_amount)]
@@ -77,6 +96,18 @@ pub enum ClientCmd {
     LnPay {
         bolt11: lightning_invoice::Bolt11Invoice,
     },
+    /// Request a transfer from a user of another federation served by the
+    /// active gateway
+    TransferRequest {
+        #[clap(long, value_parser = parse_fedimint_amount)]
+        amount: Amount,
+        #[clap(long)]
+        expiry_time: Option<u64>,
+    },
+    /// Wait for a requested transfer to be received
+    AwaitTransfer { operation_id: OperationId },
+    /// Pay the transfer request of a user of another federation
+    PayTransfer { request: TransferRequest },
     /// List registered gateways
     ListGateways,
     /// Switch active gateway
@@ -121,9 +152,23 @@ pub enum ClientCmd {
     },
     /// Print the secret key of the client
     PrintSecret,
+    /// List the latest operations of the client, optionally filtered
     ListOperations {
         #[clap(long, default_value = "10")]
         limit: usize,
+        /// Only operations of modules of this kind, can be given multiple
+        /// times
+        #[clap(long = "module")]
+        module_kinds: Vec<String>,
+        /// Only operations with this text in their meta data, ignoring case
+        #[clap(long)]
+        search: Option<String>,
+        /// Only operations created at or after this unix timestamp
+        #[clap(long)]
+        since: Option<u64>,
+        /// Only operations created before this unix timestamp
+        #[clap(long)]
+        until: Option<u64>,
     },
     /// Call a module subcommand
     Module {
@@ -134,6 +179,20 @@ pub enum ClientCmd {
     },
     /// Returns the client config
     Config,
+    /// Execute the lightning payments, withdrawals and e-cash spends of a
+    /// JSON or CSV file and write the outcome of every row to a results file
+    /// as line-delimited JSON
+    Batch {
+        /// JSON array or CSV file with a header, of rows with the fields
+        /// `type` (`ln_pay`, `withdraw` or `spend_ecash`), `destination`,
+        /// `amount` and `reference`
+        file: PathBuf,
+        #[clap(long)]
+        results: PathBuf,
+        /// How many operations are executed at the same time
+        #[clap(long, default_value = "4")]
+        concurrency: usize,
+    },
 }
 
 pub fn parse_gateway_id(s: &str) -> Result<secp256k1::PublicKey, secp256k1::Error> {
@@ -188,6 +247,42 @@ pub async fn handle_command(
                 "amount_msat": amount,
             }))
         }
+        ClientCmd::SpendOffline { amount } => {
+            // the recipient may only come online days later
+            let (operation, offline_notes) = client
+                .spend_notes_offline(amount, Duration::from_secs(7 * 24 * 3600), ())
+                .await?;
+            info!("Spend e-cash operation: {operation}");
+
+            Ok(json!({
+                "key": offline_notes.key.to_string(),
+                "chunks": offline_notes
+                    .chunks
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            }))
+        }
+        ClientCmd::ReissueOffline { key, chunks } => {
+            let offline_notes = OfflineNotes { key, chunks };
+            let amount = offline_notes.decrypt()?.total_amount();
+
+            let operation_id = client.reissue_offline_notes(offline_notes, ()).await?;
+            let mut updates = client
+                .subscribe_reissue_external_notes(operation_id)
+                .await?
+                .into_stream();
+
+            while let Some(update) = updates.next().await {
+                if let fedimint_mint_client::ReissueExternalNotesState::Failed(e) = update {
+                    bail!("Reissue failed: {e}");
+                }
+
+                info!("Update: {:?}", update);
+            }
+
+            Ok(serde_json::to_value(amount).unwrap())
+        }
         ClientCmd::LnInvoice {
             amount,
             description,
@@ -296,6 +391,76 @@ pub async fn handle_command(
 
             Err(anyhow::anyhow!("Lightning Payment failed"))
         }
+        ClientCmd::TransferRequest {
+            amount,
+            expiry_time,
+        } => {
+            let (operation_id, request) = client
+                .create_transfer_request(amount, expiry_time, ())
+                .await?;
+            Ok(serde_json::to_value(TransferRequestResponse {
+                operation_id,
+                request: request.to_string(),
+            })
+            .unwrap())
+        }
+        ClientCmd::AwaitTransfer { operation_id } => {
+            let mut updates = client
+                .subscribe_transfer_receive(operation_id)
+                .await?
+                .into_stream();
+            while let Some(update) = updates.next().await {
+                match update {
+                    TransferReceiveState::Claimed => {
+                        return get_note_summary(&client).await;
+                    }
+                    TransferReceiveState::Canceled { reason } => {
+                        return Err(reason.into());
+                    }
+                    _ => {}
+                }
+
+                info!("Update: {:?}", update);
+            }
+
+            Err(anyhow::anyhow!(
+                "Unexpected end of update stream. Transfer receive failed"
+            ))
+        }
+        ClientCmd::PayTransfer { request } => {
+            let OutgoingTransfer {
+                operation_id,
+                contract_id,
+                fee,
+            } = client.pay_transfer_request(request).await?;
+            info!("Gateway fee: {fee}");
+
+            let mut updates = client
+                .subscribe_transfer_send(operation_id)
+                .await?
+                .into_stream();
+            while let Some(update) = updates.next().await {
+                match update {
+                    TransferSendState::Success { preimage } => {
+                        return Ok(serde_json::to_value(PayInvoiceResponse {
+                            operation_id,
+                            contract_id,
+                            preimage: preimage.0.to_hex(),
+                        })
+                        .unwrap());
+                    }
+                    TransferSendState::Refunded { gateway_error } => {
+                        info!("{gateway_error}");
+                        return get_note_summary(&client).await;
+                    }
+                    _ => {}
+                }
+
+                info!("Update: {:?}", update);
+            }
+
+            Err(anyhow::anyhow!("Transfer failed"))
+        }
         ClientCmd::ListGateways => {
             let gateways = client.fetch_registered_gateways().await?;
             if gateways.is_empty() {
@@ -375,7 +540,13 @@ pub async fn handle_command(
                 "secret": hex_secret,
             }))
         }
-        ClientCmd::ListOperations { limit } => {
+        ClientCmd::ListOperations {
+            limit,
+            module_kinds,
+            search,
+            since,
+            until,
+        } => {
             #[derive(Serialize)]
             #[serde(rename_all = "snake_case")]
             struct OperationOutput {
@@ -390,9 +561,15 @@ pub async fn handle_command(
             const ISO8601_CONFIG: iso8601::EncodedConfig = iso8601::Config::DEFAULT
                 .set_formatted_components(iso8601::FormattedComponents::DateTime)
                 .encode();
+            let query = OperationLogQuery {
+                since: since.map(|since| UNIX_EPOCH + Duration::from_secs(since)),
+                until: until.map(|until| UNIX_EPOCH + Duration::from_secs(until)),
+                module_kinds,
+                search,
+            };
             let operations = client
                 .operation_log()
-                .list_operations(limit, None)
+                .query_operations(&query, limit, None)
                 .await
                 .into_iter()
                 .map(|(k, v)| {
@@ -473,6 +650,11 @@ pub async fn handle_command(
             let config = client.get_config_json();
             Ok(serde_json::to_value(config).expect("Client config is serializable"))
         }
+        ClientCmd::Batch {
+            file,
+            results,
+            concurrency,
+        } => run_batch(&client, &file, &results, concurrency).await,
     }
 }
 
@@ -528,3 +710,10 @@ struct PayInvoiceResponse {
     contract_id: ContractId,
     preimage: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct TransferRequestResponse {
+    operation_id: OperationId,
+    request: String,
+}
@@ -1,5 +1,7 @@
+mod batch;
 mod client;
 mod utils;
+mod watch;
 
 use core::fmt;
 use std::collections::BTreeMap;
@@ -14,6 +16,7 @@ use std::{fs, result};
 
 use clap::{CommandFactory, Parser, Subcommand};
 use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key};
+use fedimint_announcements_client::AnnouncementsClientGen;
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitRegistry};
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::{get_invite_code_from_db, ClientBuilder, FederationInfo};
@@ -27,15 +30,20 @@ use fedimint_core::core::OperationId;
 use fedimint_core::db::DatabaseValue;
 use fedimint_core::encoding::Encodable;
 use fedimint_core::module::{ApiAuth, ApiRequestErased};
+use fedimint_core::net::proxy::Socks5Proxy;
 use fedimint_core::query::ThresholdConsensus;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::{task, PeerId, TieredMulti};
 use fedimint_ln_client::LightningClientGen;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_client::{MintClientExt, MintClientGen, SpendableNote};
+use fedimint_notarization_client::NotarizationClientGen;
+use fedimint_oracle_client::OracleClientGen;
+use fedimint_payout_client::PayoutClientGen;
 use fedimint_server::config::io::SALT_FILE;
 use fedimint_wallet_client::api::WalletFederationApi;
 use fedimint_wallet_client::{WalletClientGen, WalletClientModule};
+use futures::StreamExt;
 use rand::thread_rng;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -240,6 +248,11 @@ struct Opts {
     #[arg(long, env = "FM_PASSWORD")]
     password: Option<String>,
 
+    /// SOCKS5 proxy (e.g. Tor at `socks5://127.0.0.1:9050`) to tunnel the
+    /// connections to the guardians through
+    #[arg(long, env = "FM_SOCKS5_PROXY")]
+    socks5_proxy: Option<SafeUrl>,
+
     #[clap(subcommand)]
     command: Command,
 }
@@ -263,7 +276,11 @@ impl Opts {
             .expect("Endpoint exists")
             .url
             .clone();
-        Ok(WsAdminClient::new(url))
+        Ok(WsAdminClient::new_with_proxy(url, self.socks5_proxy()))
+    }
+
+    fn socks5_proxy(&self) -> Option<Socks5Proxy> {
+        self.socks5_proxy.clone().map(Socks5Proxy::all)
     }
 
     fn auth(&self) -> CliResult<ApiAuth> {
@@ -317,9 +334,12 @@ impl Opts {
         let mut client_builder = ClientBuilder::default();
         client_builder.with_module_inits(module_inits.clone());
         client_builder.with_primary_module(1);
+        if let Some(proxy) = self.socks5_proxy() {
+            client_builder.with_socks5_proxy(proxy);
+        }
         if let Some(invite_code) = invite_code {
             client_builder.with_federation_info(
-                FederationInfo::from_invite_code(invite_code)
+                FederationInfo::from_invite_code_with_proxy(invite_code, self.socks5_proxy())
                     .await
                     .map_err_cli_general()?,
             );
@@ -446,6 +466,21 @@ Examples:
 
     /// Decode a transaction hex string and print it to stdout
     DecodeTransaction { hex_string: String },
+
+    /// Print the sessions completed by the federation, their transactions and
+    /// changes of the guardians' status as line-delimited JSON
+    Watch {
+        /// How often to poll the federation in milliseconds
+        #[arg(long, default_value = "1000")]
+        interval_ms: u64,
+        /// Session to start printing at, defaults to the next session
+        #[arg(long)]
+        from_session: Option<u64>,
+        /// Stop after printing this many sessions, runs until interrupted
+        /// otherwise
+        #[arg(long)]
+        sessions: Option<u64>,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -490,8 +525,12 @@ impl FedimintCli {
 
     pub fn with_default_modules(self) -> Self {
         self.with_module(LightningClientGen)
-            .with_module(MintClientGen)
+            .with_module(MintClientGen::default())
             .with_module(WalletClientGen::default())
+            .with_module(PayoutClientGen)
+            .with_module(OracleClientGen)
+            .with_module(AnnouncementsClientGen)
+            .with_module(NotarizationClientGen)
     }
 
     pub async fn run(&mut self) {
@@ -570,6 +609,11 @@ impl FedimintCli {
                     .0;
 
                 info!("Waiting for restore to complete");
+                let mut restore_progress = client.subscribe_restore_progress().await;
+                while let Some(progress) = restore_progress.next().await {
+                    info!(percent = ?progress.percent(), ?progress, "Restore progress");
+                }
+
                 let restored_amount = client
                     .await_restore_finished()
                     .await
@@ -628,12 +672,15 @@ impl FedimintCli {
                 let params: Value = serde_json::from_str(&params)
                     .map_err_cli_msg(CliErrorKind::InvalidValue, "Invalid JSON-RPC parameters")?;
                 let params = ApiRequestErased::new(params);
-                let ws_api: Arc<_> = WsFederationApi::from_config(
+                let mut ws_api = WsFederationApi::from_config(
                     cli.build_client_ng(&self.module_inits, None)
                         .await?
                         .get_config(),
-                )
-                .into();
+                );
+                if let Some(proxy) = cli.socks5_proxy() {
+                    ws_api = ws_api.with_socks5_proxy(proxy);
+                }
+                let ws_api: Arc<_> = ws_api.into();
                 let response: Value = match peer_id {
                     Some(peer_id) => ws_api
                         .request_raw(peer_id.into(), &method, &[params.to_json()])
@@ -694,6 +741,24 @@ impl FedimintCli {
                     peer_id,
                 },
             }),
+            Command::Dev(DevCmd::Watch {
+                interval_ms,
+                from_session,
+                sessions,
+            }) => {
+                let client = cli.build_client_ng(&self.module_inits, None).await?;
+                watch::watch(
+                    client.api(),
+                    client.decoders(),
+                    Duration::from_millis(interval_ms),
+                    from_session,
+                    sessions,
+                )
+                .await
+                .map_err_cli_general()?;
+
+                Ok(CliOutput::Raw(Value::Null))
+            }
             Command::Dev(DevCmd::FedimintBlockCount) => {
                 let count = cli
                     .build_client_ng(&self.module_inits, None)
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use fedimint_core::api::{
+    GlobalFederationApi, IGlobalFederationApi, PeerConnectionStatus, SessionRange, StatusResponse,
+    MAX_SESSION_PAGE_SIZE,
+};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::endpoint_constants::STATUS_ENDPOINT;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::{task, PeerId, TransactionId};
+use serde::Serialize;
+use tracing::warn;
+
+/// Activity of the federation printed by `fedimint-cli dev watch`, one JSON
+/// object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum WatchEvent {
+    /// A session was completed, it is followed by the transactions accepted
+    /// in it
+    Session {
+        session_index: u64,
+        transactions: usize,
+    },
+    Transaction {
+        session_index: u64,
+        item_index: u64,
+        txid: TransactionId,
+        /// The module instances of the inputs
+        inputs: Vec<ModuleInstanceId>,
+        /// The module instances of the outputs
+        outputs: Vec<ModuleInstanceId>,
+    },
+    /// The API of a guardian became reachable or unreachable
+    Guardian { peer: PeerId, reachable: bool },
+    /// How a guardian sees its connection to another guardian changed
+    PeerStatus {
+        reporting_peer: PeerId,
+        peer: PeerId,
+        connection_status: PeerConnectionStatus,
+        flagged: bool,
+    },
+}
+
+/// What we last printed about a guardian, to only print changes
+#[derive(Debug, Default)]
+struct GuardianState {
+    reachable: Option<bool>,
+    peers: BTreeMap<PeerId, (PeerConnectionStatus, bool)>,
+}
+
+/// Polls the federation every `interval` and prints the sessions completed
+/// since `start_session`, their transactions and changes of the guardians'
+/// status as line-delimited JSON. Only returns once `session_limit` sessions
+/// were printed, failed requests are retried.
+pub async fn watch(
+    api: &(dyn IGlobalFederationApi + 'static),
+    decoders: &ModuleDecoderRegistry,
+    interval: Duration,
+    start_session: Option<u64>,
+    session_limit: Option<u64>,
+) -> anyhow::Result<()> {
+    let mut next_session = match start_session {
+        Some(start_session) => start_session,
+        None => api.fetch_block_count().await?,
+    };
+    let end_session = session_limit.map(|limit| next_session.saturating_add(limit));
+    let mut guardians: BTreeMap<PeerId, GuardianState> = BTreeMap::new();
+
+    loop {
+        if end_session.map_or(false, |end_session| end_session <= next_session) {
+            return Ok(());
+        }
+
+        match api.fetch_block_count().await {
+            Ok(session_count) => {
+                let session_count =
+                    end_session.map_or(session_count, |end_session| session_count.min(end_session));
+                while next_session < session_count {
+                    match print_sessions(api, decoders, next_session, session_count).await {
+                        Ok(printed_until) => next_session = printed_until,
+                        Err(e) => {
+                            warn!(
+                                "Fetching the transactions of session {next_session} failed: {e}"
+                            );
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!("Fetching the session count failed: {e}"),
+        }
+
+        for peer in api.all_peers() {
+            let state = guardians.entry(*peer).or_default();
+            print_guardian_status(api, *peer, state).await;
+        }
+
+        task::sleep(interval).await;
+    }
+}
+
+/// Prints a page of the sessions from `start_session` on, which ends before
+/// `session_count`, returns the index of the next session to print
+async fn print_sessions(
+    api: &(dyn IGlobalFederationApi + 'static),
+    decoders: &ModuleDecoderRegistry,
+    start_session: u64,
+    session_count: u64,
+) -> anyhow::Result<u64> {
+    let range = SessionRange {
+        start_index: start_session,
+        limit: (session_count - start_session).min(MAX_SESSION_PAGE_SIZE),
+    };
+    let transactions = api.fetch_session_transactions(range, decoders).await?.value;
+
+    for session_index in range.indices() {
+        let session_transactions = transactions
+            .iter()
+            .filter(|transaction| transaction.location.session_index == session_index)
+            .collect::<Vec<_>>();
+
+        print_event(&WatchEvent::Session {
+            session_index,
+            transactions: session_transactions.len(),
+        });
+
+        for located in session_transactions {
+            print_event(&WatchEvent::Transaction {
+                session_index,
+                item_index: located.location.item_index,
+                txid: located.transaction.tx_hash(),
+                inputs: located
+                    .transaction
+                    .inputs
+                    .iter()
+                    .map(|input| input.module_instance_id())
+                    .collect(),
+                outputs: located
+                    .transaction
+                    .outputs
+                    .iter()
+                    .map(|output| output.module_instance_id())
+                    .collect(),
+            });
+        }
+    }
+
+    Ok(range.indices().end)
+}
+
+async fn print_guardian_status(
+    api: &(dyn IGlobalFederationApi + 'static),
+    peer: PeerId,
+    state: &mut GuardianState,
+) {
+    let status = api
+        .request_raw(
+            peer,
+            STATUS_ENDPOINT,
+            &[ApiRequestErased::default().to_json()],
+        )
+        .await
+        .ok()
+        .and_then(|status| serde_json::from_value::<StatusResponse>(status).ok());
+
+    let reachable = status.is_some();
+    if state.reachable != Some(reachable) {
+        state.reachable = Some(reachable);
+        print_event(&WatchEvent::Guardian { peer, reachable });
+    }
+
+    let Some(federation_status) = status.and_then(|status| status.federation) else {
+        return;
+    };
+
+    for (other_peer, peer_status) in federation_status.status_by_peer {
+        let current = (peer_status.connection_status, peer_status.flagged);
+        if state.peers.insert(other_peer, current) != Some(current) {
+            print_event(&WatchEvent::PeerStatus {
+                reporting_peer: peer,
+                peer: other_peer,
+                connection_status: peer_status.connection_status,
+                flagged: peer_status.flagged,
+            });
+        }
+    }
+}
+
+fn print_event(event: &WatchEvent) {
+    println!(
+        "{}",
+        serde_json::to_string(event).expect("Events are serializable")
+    );
+}
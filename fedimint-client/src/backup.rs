@@ -1,23 +1,37 @@
-use std::cmp::Reverse;
 use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
 
 use anyhow::Result;
 use bitcoin::secp256k1;
 use fedimint_core::api::GlobalFederationApi;
 use fedimint_core::core::backup::{BackupRequest, SignedBackupRequest};
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::time::now;
 use fedimint_derive_secret::DerivableSecret;
 use fedimint_logging::{LOG_CLIENT, LOG_CLIENT_BACKUP, LOG_CLIENT_RECOVERY};
+use futures::StreamExt;
 use secp256k1_zkp::{KeyPair, Secp256k1};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 
 use super::Client;
+use crate::db::{BackupRecordKey, BackupRecordKeyPrefix};
 use crate::get_decoded_client_secret;
 use crate::secret::DeriveableSecretClientExt;
 
+/// Number of uploaded backups we keep a [`BackupRecord`] of, older records
+/// are pruned. The federation only keeps the latest backup of a client anyway.
+pub const MAX_BACKUP_RECORDS: usize = 10;
+
+/// How long we wait before retrying a scheduled backup that failed
+const SCHEDULED_BACKUP_RETRY_DELAY: Duration = Duration::from_secs(60);
+
 /// Backup metadata
 ///
 /// A backup can have a blob of extra data encoded in it. We provide methods to
@@ -106,6 +120,18 @@ impl ClientBackup {
     }
 }
 
+/// What we remember about a backup we uploaded to the federation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct BackupRecord {
+    /// Epoch count taken right before taking the backup, restoring from it
+    /// only scans the history since then
+    pub fedimint_block_count: u64,
+    /// Size of the encrypted backup in bytes
+    pub size: u64,
+    /// Application metadata, which scheduled backups include again
+    pub metadata: Metadata,
+}
+
 /// Encrypted version of [`ClientBackup`].
 pub struct EncryptedClientBackup(Vec<u8>);
 
@@ -181,13 +207,105 @@ impl Client {
 
     /// Prepare an encrypted backup and send it to federation for storing
     pub async fn backup_to_federation(&self, metadata: Metadata) -> Result<()> {
-        let backup = self.create_encrypted_backup(metadata).await?;
+        let backup = self.create_backup(metadata).await?;
+        let encrypted = backup.encrypt_to(&self.get_derived_backup_encryption_key())?;
+        let size = encrypted.len() as u64;
 
-        self.upload_backup(backup).await?;
+        self.upload_backup(encrypted).await?;
+
+        self.record_backup(BackupRecord {
+            fedimint_block_count: backup.fedimint_block_count,
+            size,
+            metadata: backup.metadata,
+        })
+        .await;
 
         Ok(())
     }
 
+    /// Records of the backups we uploaded to the federation, newest first
+    pub async fn backup_records(&self) -> Vec<(SystemTime, BackupRecord)> {
+        self.db()
+            .begin_transaction()
+            .await
+            .find_by_prefix_sorted_descending(&BackupRecordKeyPrefix)
+            .await
+            .map(|(key, record)| (key.timestamp, record))
+            .collect()
+            .await
+    }
+
+    /// Records an uploaded backup and prunes the records exceeding
+    /// [`MAX_BACKUP_RECORDS`]
+    async fn record_backup(&self, record: BackupRecord) {
+        let mut dbtx = self.db().begin_transaction().await;
+        dbtx.insert_entry(&BackupRecordKey { timestamp: now() }, &record)
+            .await;
+
+        let outdated = dbtx
+            .find_by_prefix_sorted_descending(&BackupRecordKeyPrefix)
+            .await
+            .skip(MAX_BACKUP_RECORDS)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        for key in outdated {
+            dbtx.remove_entry(&key).await;
+        }
+
+        if let Err(e) = dbtx.commit_tx_result().await {
+            warn!(target: LOG_CLIENT_BACKUP, "Failed to record backup: {e}");
+        }
+    }
+
+    /// Start a background process uploading a backup whenever `interval`
+    /// passed since the latest one, including the metadata of the latest
+    /// backup. Besides saving new e-cash, refreshing the backup shortens the
+    /// history a restore has to scan.
+    pub(crate) async fn spawn_scheduled_backups(self: &Arc<Self>, interval: Duration) {
+        let weak_client = Arc::downgrade(self);
+        // Separate task group, the task ends by itself once the client shuts down
+        TaskGroup::new()
+            .spawn("scheduled_backups", move |_| async move {
+                loop {
+                    let Some(client) = Self::upgrade_running(&weak_client) else {
+                        break;
+                    };
+
+                    let latest = client.backup_records().await.into_iter().next();
+                    let mut delay = latest.as_ref().map_or(Duration::ZERO, |(timestamp, _)| {
+                        (*timestamp + interval)
+                            .duration_since(now())
+                            .unwrap_or_default()
+                    });
+
+                    if delay.is_zero() {
+                        let metadata =
+                            latest.map_or_else(Metadata::empty, |(_, record)| record.metadata);
+
+                        match client.backup_to_federation(metadata).await {
+                            Ok(()) => continue,
+                            Err(e) => {
+                                warn!(target: LOG_CLIENT_BACKUP, "Scheduled backup failed: {e}");
+                                delay = SCHEDULED_BACKUP_RETRY_DELAY.min(interval);
+                            }
+                        }
+                    }
+
+                    // the client must not be kept alive while we wait
+                    drop(client);
+                    sleep(delay).await;
+                }
+            })
+            .await;
+    }
+
+    fn upgrade_running(weak_client: &Weak<Self>) -> Option<Arc<Self>> {
+        weak_client
+            .upgrade()
+            .filter(|client| client.client_count.load(Ordering::Relaxed) != 0)
+    }
+
     /// Wipe the client state (including module state)
     pub async fn wipe_state(&self) -> Result<()> {
         let mut dbtx = self.db().begin_transaction().await;
@@ -292,36 +410,39 @@ impl Client {
 
     /// Download most recent valid backup found from the Federation
     pub async fn download_backup_from_federation(&self) -> Result<Option<ClientBackup>> {
-        let mut responses: Vec<_> = self
-            .api
-            .download_backup(&self.get_backup_id())
-            .await?
-            .into_iter()
-            .filter_map(|backup| {
-                match EncryptedClientBackup(backup.data)
-                    .decrypt_with(&self.get_derived_backup_encryption_key())
-                {
-                    Ok(valid) => Some(valid),
-                    Err(e) => {
-                        warn!(
-                            target: LOG_CLIENT_RECOVERY,
-                            "Invalid backup returned by one of the peers: {e}"
-                        );
-                        None
+        let mut valid_responses = 0;
+        let mut newest: Option<ClientBackup> = None;
+
+        // Backups of clients with many notes are large, so we decrypt the responses one
+        // at a time and only keep the newest (highest epoch)
+        for backup in self.api.download_backup(&self.get_backup_id()).await? {
+            match EncryptedClientBackup(backup.data)
+                .decrypt_with(&self.get_derived_backup_encryption_key())
+            {
+                Ok(valid) => {
+                    valid_responses += 1;
+
+                    if newest.as_ref().map_or(true, |newest| {
+                        newest.fedimint_block_count < valid.fedimint_block_count
+                    }) {
+                        newest = Some(valid);
                     }
                 }
-            })
-            .collect();
+                Err(e) => {
+                    warn!(
+                        target: LOG_CLIENT_RECOVERY,
+                        "Invalid backup returned by one of the peers: {e}"
+                    );
+                }
+            }
+        }
 
         debug!(
             target: LOG_CLIENT_RECOVERY,
-            "Received {} valid responses",
-            responses.len()
+            "Received {valid_responses} valid responses"
         );
-        // Use the newest (highest epoch)
-        responses.sort_by_key(|backup| Reverse(backup.fedimint_block_count));
 
-        Ok(responses.into_iter().next())
+        Ok(newest)
     }
 
     /// Backup id derived from the root secret key (public key used to self-sign
@@ -0,0 +1,285 @@
+//! Breakdown of the client balance by module instance and by the state of the
+//! funds, which allows wallet UIs to distinguish pending from available funds
+//! without relying on module internals. The funds and fees of operations that
+//! did not complete yet are broken down in [`PendingOperation`] likewise.
+//!
+//! The serialized form is part of the public client API, so fields may be
+//! added but never renamed or removed.
+
+use std::collections::BTreeMap;
+
+use fedimint_core::core::{ModuleInstanceId, ModuleKind, OperationId};
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+/// The funds of the client broken down by module instance
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceBreakdown {
+    /// Funds available for funding transactions
+    pub spendable: Amount,
+    /// Funds expected to become spendable, or to be refunded, once an
+    /// operation completes
+    pub pending: Amount,
+    pub modules: BTreeMap<ModuleInstanceId, ModuleBalance>,
+}
+
+impl BalanceBreakdown {
+    pub fn new(modules: BTreeMap<ModuleInstanceId, ModuleBalance>) -> Self {
+        BalanceBreakdown {
+            spendable: modules.values().map(|module| module.spendable).sum(),
+            pending: modules.values().map(|module| module.pending).sum(),
+            modules,
+        }
+    }
+}
+
+/// The funds held by a single module instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleBalance {
+    pub kind: ModuleKind,
+    pub spendable: Amount,
+    pub pending: Amount,
+    pub items: Vec<BalanceItem>,
+}
+
+impl ModuleBalance {
+    pub fn new(kind: ModuleKind, items: Vec<BalanceItem>) -> Self {
+        let (spendable, pending) = items
+            .iter()
+            .partition::<Vec<_>, _>(|item| item.is_spendable());
+
+        ModuleBalance {
+            kind,
+            spendable: spendable.into_iter().map(BalanceItem::amount).sum(),
+            pending: pending.into_iter().map(BalanceItem::amount).sum(),
+            items,
+        }
+    }
+}
+
+/// Funds in a particular state, as reported by the module holding them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BalanceItem {
+    /// Spendable e-cash notes of a single denomination
+    Notes { denomination: Amount, count: usize },
+    /// E-cash notes that are waiting to be issued by the federation
+    PendingNotes {
+        operation_id: OperationId,
+        amount: Amount,
+    },
+    /// A peg-in that can be claimed once its transaction has enough
+    /// confirmations
+    PegIn {
+        operation_id: OperationId,
+        txid: bitcoin::Txid,
+        amount: Amount,
+        /// Unknown while the transaction is unconfirmed or the bitcoin
+        /// backend can not be reached
+        confirmations_remaining: Option<u64>,
+    },
+    /// Funds locked in a lightning contract until it is settled or refunded
+    LightningContract {
+        operation_id: OperationId,
+        contract_id: String,
+        direction: ContractDirection,
+        amount: Amount,
+        /// The block height at which an outgoing contract can be refunded
+        expires_at_block: Option<u32>,
+    },
+}
+
+impl BalanceItem {
+    pub fn amount(&self) -> Amount {
+        match self {
+            BalanceItem::Notes {
+                denomination,
+                count,
+            } => *denomination * *count as u64,
+            BalanceItem::PendingNotes { amount, .. }
+            | BalanceItem::PegIn { amount, .. }
+            | BalanceItem::LightningContract { amount, .. } => *amount,
+        }
+    }
+
+    /// Whether the funds can be used to fund transactions right away
+    pub fn is_spendable(&self) -> bool {
+        matches!(self, BalanceItem::Notes { .. })
+    }
+}
+
+/// Whether a lightning contract pays to or from the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// An operation that did not complete yet, with the funds it locks and the
+/// fees it costs summed up over the modules involved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingOperation {
+    pub operation_id: OperationId,
+    /// Kind of the module that started the operation
+    pub operation_module_kind: String,
+    pub locked: Amount,
+    pub fees: OperationFees,
+    /// The stages of the operation by the module instances whose state
+    /// machines are still active, modules may report no stages at all
+    pub modules: BTreeMap<ModuleInstanceId, Vec<PendingStage>>,
+}
+
+impl PendingOperation {
+    pub fn new(
+        operation_id: OperationId,
+        operation_module_kind: String,
+        modules: BTreeMap<ModuleInstanceId, Vec<PendingStage>>,
+    ) -> Self {
+        let stages = modules.values().flatten();
+
+        PendingOperation {
+            operation_id,
+            operation_module_kind,
+            locked: stages.clone().map(|stage| stage.locked).sum(),
+            fees: stages.fold(OperationFees::default(), |fees, stage| fees + stage.fees),
+            modules,
+        }
+    }
+}
+
+/// The stage a state machine of a pending operation is in, as reported by
+/// the module running it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingStage {
+    /// Name of the state the state machine is in, e.g. `funded`
+    pub stage: String,
+    /// Funds that can not be spent until the stage completes, e.g. the
+    /// amount of a lightning contract or of notes waiting to be issued
+    pub locked: Amount,
+    /// Fees that are paid for the stage, whether they were already deducted
+    /// from the balance or not
+    pub fees: OperationFees,
+}
+
+/// Fees of an operation by whom they are paid to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationFees {
+    /// Fees charged by the federation for the inputs and outputs of the
+    /// transactions
+    pub federation: Amount,
+    /// Fees charged by the lightning gateway for routing a payment
+    pub gateway: Amount,
+    /// Fees of the bitcoin transactions, e.g. of a peg-out
+    pub on_chain: Amount,
+}
+
+impl OperationFees {
+    pub fn federation(federation: Amount) -> Self {
+        OperationFees {
+            federation,
+            ..OperationFees::default()
+        }
+    }
+
+    pub fn total(&self) -> Amount {
+        self.federation + self.gateway + self.on_chain
+    }
+}
+
+impl std::ops::Add for OperationFees {
+    type Output = OperationFees;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        OperationFees {
+            federation: self.federation + rhs.federation,
+            gateway: self.gateway + rhs.gateway,
+            on_chain: self.on_chain + rhs.on_chain,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::core::{ModuleKind, OperationId};
+    use fedimint_core::Amount;
+
+    use super::{
+        BalanceBreakdown, BalanceItem, ModuleBalance, OperationFees, PendingOperation, PendingStage,
+    };
+
+    #[test]
+    fn sums_spendable_and_pending_funds() {
+        let mint = ModuleBalance::new(
+            ModuleKind::from_static_str("mint"),
+            vec![
+                BalanceItem::Notes {
+                    denomination: Amount::from_msats(1024),
+                    count: 3,
+                },
+                BalanceItem::PendingNotes {
+                    operation_id: OperationId([0; 32]),
+                    amount: Amount::from_msats(100),
+                },
+            ],
+        );
+
+        assert_eq!(mint.spendable, Amount::from_msats(3072));
+        assert_eq!(mint.pending, Amount::from_msats(100));
+
+        let breakdown = BalanceBreakdown::new(BTreeMap::from([
+            (0, mint),
+            (
+                1,
+                ModuleBalance::new(ModuleKind::from_static_str("ln"), vec![]),
+            ),
+        ]));
+
+        assert_eq!(breakdown.spendable, Amount::from_msats(3072));
+        assert_eq!(breakdown.pending, Amount::from_msats(100));
+
+        let json = serde_json::to_value(&breakdown.modules[&0].items[0]).expect("Serializable");
+
+        assert_eq!(json["type"], "notes");
+        assert_eq!(json["count"], 3);
+    }
+
+    #[test]
+    fn sums_locked_funds_and_fees_of_pending_operation() {
+        let stage = |locked, fees| PendingStage {
+            stage: "created".to_string(),
+            locked: Amount::from_msats(locked),
+            fees,
+        };
+
+        let operation = PendingOperation::new(
+            OperationId([0; 32]),
+            "ln".to_string(),
+            BTreeMap::from([
+                (
+                    0,
+                    vec![stage(10, OperationFees::federation(Amount::from_msats(1)))],
+                ),
+                (
+                    1,
+                    vec![stage(
+                        1000,
+                        OperationFees {
+                            federation: Amount::from_msats(2),
+                            gateway: Amount::from_msats(3),
+                            on_chain: Amount::ZERO,
+                        },
+                    )],
+                ),
+                (2, vec![]),
+            ]),
+        );
+
+        assert_eq!(operation.locked, Amount::from_msats(1010));
+        assert_eq!(operation.fees.federation, Amount::from_msats(3));
+        assert_eq!(operation.fees.gateway, Amount::from_msats(3));
+        assert_eq!(operation.fees.total(), Amount::from_msats(6));
+    }
+}
@@ -1,11 +1,26 @@
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
 use fedimint_core::api::{ApiVersionSet, InviteCode};
-use fedimint_core::config::{ClientConfig, FederationId};
-use fedimint_core::core::OperationId;
+use fedimint_core::config::{
+    ClientConfig, ClientModuleConfig, FederationId, GlobalClientConfig, PeerUrl,
+};
+use fedimint_core::core::{ModuleInstanceId, OperationId};
+use fedimint_core::db::{
+    apply_migrations, Database, DatabaseTransaction, DatabaseVersion, DatabaseVersionKey,
+    IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped, MigrationMap,
+};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::endpoint_update::ApiEndpointUpdate;
+use fedimint_core::module::CoreConsensusVersion;
+use fedimint_core::query::PeerLatencyHistory;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{impl_db_lookup, impl_db_record, PeerId};
+use futures::{FutureExt, StreamExt};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::backup::BackupRecord;
 use crate::oplog::OperationLogEntry;
 
 #[repr(u8)]
@@ -18,6 +33,14 @@ pub enum DbKeyPrefix {
     CommonApiVersionCache = 0x2e,
     ClientConfig = 0x2f,
     ClientInviteCode = 0x30,
+    PeerLatencyHistory = 0x31,
+    ApiEndpointUpdate = 0x32,
+    BackupRecord = 0x33,
+    JoinedFederation = 0x34,
+    /// Prefix of the databases of the clients of a
+    /// [`MultiClient`](crate::multi::MultiClient), followed by the federation
+    /// id
+    FederationClientDatabase = 0x35,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -102,6 +125,49 @@ impl_db_record!(
 
 impl_db_lookup!(key = ClientConfigKey, query_prefix = ClientConfigKeyPrefix);
 
+/// The [`PeerUrl`] before it had fallback URLs
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerUrlV0 {
+    pub url: SafeUrl,
+    pub name: String,
+}
+
+/// The [`GlobalClientConfig`] before it had the broadcast public keys, the
+/// transaction limits and the fee schedule
+#[derive(Debug, Encodable, Decodable)]
+pub struct GlobalClientConfigV0 {
+    pub federation_id: FederationId,
+    pub api_endpoints: BTreeMap<PeerId, PeerUrlV0>,
+    pub epoch_pk: threshold_crypto::PublicKey,
+    pub consensus_version: CoreConsensusVersion,
+    pub meta: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ClientConfigV0 {
+    pub global: GlobalClientConfigV0,
+    pub modules: BTreeMap<ModuleInstanceId, ClientModuleConfig>,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ClientConfigKeyV0 {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct ClientConfigKeyPrefixV0;
+
+impl_db_record!(
+    key = ClientConfigKeyV0,
+    value = ClientConfigV0,
+    db_prefix = DbKeyPrefix::ClientConfig
+);
+
+impl_db_lookup!(
+    key = ClientConfigKeyV0,
+    query_prefix = ClientConfigKeyPrefixV0
+);
+
 #[derive(Debug, Encodable, Decodable)]
 pub struct ClientInviteCodeKey;
 
@@ -118,3 +184,374 @@ impl_db_lookup!(
     key = ClientInviteCodeKey,
     query_prefix = ClientInviteCodeKeyPrefix
 );
+
+/// Latency history of the API requests the client made to a guardian
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerLatencyHistoryKey(pub PeerId);
+
+#[derive(Debug, Encodable)]
+pub struct PeerLatencyHistoryKeyPrefix;
+
+impl_db_record!(
+    key = PeerLatencyHistoryKey,
+    value = PeerLatencyHistory,
+    db_prefix = DbKeyPrefix::PeerLatencyHistory
+);
+
+impl_db_lookup!(
+    key = PeerLatencyHistoryKey,
+    query_prefix = PeerLatencyHistoryKeyPrefix
+);
+
+/// The latest verified update of a guardian's API endpoint
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApiEndpointUpdateKey(pub PeerId);
+
+#[derive(Debug, Encodable)]
+pub struct ApiEndpointUpdateKeyPrefix;
+
+impl_db_record!(
+    key = ApiEndpointUpdateKey,
+    value = ApiEndpointUpdate,
+    db_prefix = DbKeyPrefix::ApiEndpointUpdate
+);
+
+impl_db_lookup!(
+    key = ApiEndpointUpdateKey,
+    query_prefix = ApiEndpointUpdateKeyPrefix
+);
+
+/// Record of a backup uploaded to the federation at the time of the key
+#[derive(Debug, Encodable, Decodable)]
+pub struct BackupRecordKey {
+    pub timestamp: std::time::SystemTime,
+}
+
+#[derive(Debug, Encodable)]
+pub struct BackupRecordKeyPrefix;
+
+impl_db_record!(
+    key = BackupRecordKey,
+    value = BackupRecord,
+    db_prefix = DbKeyPrefix::BackupRecord
+);
+
+impl_db_lookup!(key = BackupRecordKey, query_prefix = BackupRecordKeyPrefix);
+
+/// Federation joined by a [`MultiClient`](crate::multi::MultiClient)
+#[derive(Debug, Encodable, Decodable)]
+pub struct JoinedFederationKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct JoinedFederationKeyPrefix;
+
+impl_db_record!(
+    key = JoinedFederationKey,
+    value = (),
+    db_prefix = DbKeyPrefix::JoinedFederation
+);
+
+impl_db_lookup!(
+    key = JoinedFederationKey,
+    query_prefix = JoinedFederationKeyPrefix
+);
+
+/// Version of the database of the client itself, the modules version their
+/// databases separately
+pub const CORE_CLIENT_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+
+pub fn get_core_client_database_migrations<'a>() -> MigrationMap<'a> {
+    let mut migrations = MigrationMap::new();
+    migrations.insert(DatabaseVersion(0), move |dbtx| migrate_to_v1(dbtx).boxed());
+    migrations
+}
+
+/// Migrates the database of the client to [`CORE_CLIENT_DATABASE_VERSION`]
+///
+/// The database of a client that joined before the database was versioned
+/// has no version yet, so we start from version 0 if it holds a config.
+pub async fn apply_core_client_migrations(db: &Database) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+
+    if dbtx.get_value(&DatabaseVersionKey).await.is_none()
+        && dbtx
+            .raw_find_by_prefix(&[DbKeyPrefix::ClientConfig as u8])
+            .await?
+            .next()
+            .await
+            .is_some()
+    {
+        dbtx.insert_new_entry(&DatabaseVersionKey, &DatabaseVersion(0))
+            .await;
+    }
+
+    dbtx.commit_tx_result().await?;
+
+    apply_migrations(
+        db,
+        "Client".to_string(),
+        CORE_CLIENT_DATABASE_VERSION,
+        get_core_client_database_migrations(),
+    )
+    .await
+}
+
+/// Re-encodes the stored client config with the fields added to it since,
+/// which are left empty or at their defaults until the client completes the
+/// config with [`complete_migrated_config`] before it starts
+async fn migrate_to_v1(dbtx: &mut DatabaseTransaction<'_>) -> anyhow::Result<()> {
+    let configs = dbtx
+        .find_by_prefix(&ClientConfigKeyPrefixV0)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    dbtx.remove_by_prefix(&ClientConfigKeyPrefixV0).await;
+
+    for (key, config) in configs {
+        let global = config.global;
+        let config = ClientConfig {
+            global: GlobalClientConfig {
+                federation_id: global.federation_id,
+                api_endpoints: global
+                    .api_endpoints
+                    .into_iter()
+                    .map(|(peer, url)| {
+                        let url = PeerUrl {
+                            url: url.url,
+                            name: url.name,
+                            fallback_urls: vec![],
+                        };
+
+                        (peer, url)
+                    })
+                    .collect(),
+                epoch_pk: global.epoch_pk,
+                broadcast_public_keys: BTreeMap::new(),
+                consensus_version: global.consensus_version,
+                meta: global.meta,
+                transaction_limits: Default::default(),
+                fees: Default::default(),
+            },
+            modules: config.modules,
+        };
+
+        dbtx.insert_new_entry(&ClientConfigKey { id: key.id }, &config)
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Completes a config migrated by [`migrate_to_v1`] with the fields the
+/// federation serves in the downloaded config
+pub fn complete_migrated_config(
+    mut config: ClientConfig,
+    downloaded: ClientConfig,
+) -> anyhow::Result<ClientConfig> {
+    ensure!(
+        downloaded.global.federation_id == config.global.federation_id,
+        "Downloaded client config belongs to another federation"
+    );
+
+    for (peer, url) in &mut config.global.api_endpoints {
+        if let Some(downloaded_url) = downloaded.global.api_endpoints.get(peer) {
+            if downloaded_url.url == url.url {
+                url.fallback_urls = downloaded_url.fallback_urls.clone();
+            }
+        }
+    }
+
+    config.global.broadcast_public_keys = downloaded.global.broadcast_public_keys;
+    config.global.transaction_limits = downloaded.global.transaction_limits;
+    config.global.fees = downloaded.global.fees;
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use fedimint_core::config::{ClientConfig, FederationId, GlobalClientConfig, PeerUrl};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{
+        Database, DatabaseVersion, DatabaseVersionKey, IDatabaseTransactionOpsCoreTyped,
+    };
+    use fedimint_core::fee::{FeeRate, FeeSchedule, ModuleFeeSchedule};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::module::CoreConsensusVersion;
+    use fedimint_core::transaction::TransactionLimits;
+    use fedimint_core::util::SafeUrl;
+    use fedimint_core::{Amount, PeerId};
+    use threshold_crypto::SecretKey;
+
+    use super::{
+        apply_core_client_migrations, complete_migrated_config, ClientConfigKey, ClientConfigKeyV0,
+        ClientConfigV0, GlobalClientConfigV0, PeerUrlV0, CORE_CLIENT_DATABASE_VERSION,
+    };
+
+    #[tokio::test]
+    async fn migrates_unversioned_client_config() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let federation_id = FederationId::dummy();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &ClientConfigKeyV0 { id: federation_id },
+            &ClientConfigV0 {
+                global: GlobalClientConfigV0 {
+                    federation_id,
+                    api_endpoints: BTreeMap::from([(
+                        PeerId::from(0),
+                        PeerUrlV0 {
+                            url: "wss://guardian.example".parse().expect("Valid url"),
+                            name: "guardian".to_string(),
+                        },
+                    )]),
+                    epoch_pk: SecretKey::random().public_key(),
+                    consensus_version: CoreConsensusVersion { major: 0, minor: 0 },
+                    meta: BTreeMap::new(),
+                },
+                modules: BTreeMap::new(),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        apply_core_client_migrations(&db).await.unwrap();
+
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.get_value(&DatabaseVersionKey).await,
+            Some(CORE_CLIENT_DATABASE_VERSION)
+        );
+
+        let config = dbtx
+            .get_value(&ClientConfigKey { id: federation_id })
+            .await
+            .expect("Config was migrated");
+        assert_eq!(config.global.federation_id, federation_id);
+        assert_eq!(
+            config.global.api_endpoints[&PeerId::from(0)].name,
+            "guardian"
+        );
+        assert!(config.global.broadcast_public_keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn completed_migrated_config_hashes_like_federation_config() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let federation_id = FederationId::dummy();
+        let url = "wss://guardian.example"
+            .parse::<SafeUrl>()
+            .expect("Valid url");
+
+        let federation_config = ClientConfig {
+            global: GlobalClientConfig {
+                federation_id,
+                api_endpoints: BTreeMap::from([(
+                    PeerId::from(0),
+                    PeerUrl {
+                        url: url.clone(),
+                        name: "guardian".to_string(),
+                        fallback_urls: vec!["wss://fallback.example".parse().expect("Valid url")],
+                    },
+                )]),
+                epoch_pk: SecretKey::random().public_key(),
+                broadcast_public_keys: BTreeMap::from([(
+                    PeerId::from(0),
+                    secp256k1_zkp::PublicKey::from_str(
+                        "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+                    )
+                    .expect("Valid key"),
+                )]),
+                consensus_version: CoreConsensusVersion { major: 0, minor: 0 },
+                meta: BTreeMap::new(),
+                transaction_limits: TransactionLimits {
+                    max_bytes: 20_000,
+                    max_inputs: 64,
+                    max_outputs: 64,
+                },
+                fees: FeeSchedule {
+                    modules: BTreeMap::from([(
+                        0,
+                        ModuleFeeSchedule {
+                            input: FeeRate {
+                                base: Amount::from_sats(1),
+                                parts_per_million: 100,
+                            },
+                            output: FeeRate::ZERO,
+                        },
+                    )]),
+                },
+            },
+            modules: BTreeMap::new(),
+        };
+
+        let global = federation_config.global.clone();
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &ClientConfigKeyV0 { id: federation_id },
+            &ClientConfigV0 {
+                global: GlobalClientConfigV0 {
+                    federation_id,
+                    api_endpoints: BTreeMap::from([(
+                        PeerId::from(0),
+                        PeerUrlV0 {
+                            url,
+                            name: "guardian".to_string(),
+                        },
+                    )]),
+                    epoch_pk: global.epoch_pk,
+                    consensus_version: global.consensus_version,
+                    meta: global.meta,
+                },
+                modules: BTreeMap::new(),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        apply_core_client_migrations(&db).await.unwrap();
+
+        let migrated = db
+            .begin_transaction()
+            .await
+            .get_value(&ClientConfigKey { id: federation_id })
+            .await
+            .expect("Config was migrated");
+        assert_ne!(
+            migrated.consensus_hash(),
+            federation_config.consensus_hash()
+        );
+
+        let completed = complete_migrated_config(migrated, federation_config.clone()).unwrap();
+        assert_eq!(
+            completed.consensus_hash(),
+            federation_config.consensus_hash()
+        );
+
+        let mut other_federation = federation_config;
+        other_federation.global.federation_id = FederationId::dummy();
+        assert!(complete_migrated_config(completed, other_federation).is_err());
+    }
+
+    #[tokio::test]
+    async fn fresh_database_starts_at_current_version() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+
+        apply_core_client_migrations(&db).await.unwrap();
+
+        assert_eq!(
+            db.begin_transaction()
+                .await
+                .get_value(&DatabaseVersionKey)
+                .await,
+            Some(DatabaseVersion(1))
+        );
+    }
+}
@@ -77,16 +77,19 @@ use std::time::Duration;
 use anyhow::{anyhow, bail, ensure, Context};
 use async_stream::stream;
 use db::{
-    CachedApiVersionSet, CachedApiVersionSetKey, ClientConfigKey, ClientConfigKeyPrefix,
-    ClientInviteCodeKey, ClientInviteCodeKeyPrefix, EncodedClientSecretKey,
+    apply_core_client_migrations, complete_migrated_config, ApiEndpointUpdateKey,
+    ApiEndpointUpdateKeyPrefix, CachedApiVersionSet, CachedApiVersionSetKey, ClientConfigKey,
+    ClientConfigKeyPrefix, ClientInviteCodeKey, ClientInviteCodeKeyPrefix, EncodedClientSecretKey,
+    PeerLatencyHistoryKey, PeerLatencyHistoryKeyPrefix,
 };
 use fedimint_core::api::{
     ApiVersionSet, DynGlobalApi, DynModuleApi, GlobalFederationApi, IGlobalFederationApi,
     InviteCode, WsFederationApi,
 };
+use fedimint_core::block::TransactionReceipt;
 use fedimint_core::config::{
     ClientConfig, ClientModuleConfig, FederationId, JsonClientConfig, JsonWithKind,
-    ModuleInitRegistry,
+    ModuleInitRegistry, PeerUrl,
 };
 use fedimint_core::core::{
     DynInput, DynOutput, IInput, IOutput, ModuleInstanceId, ModuleKind, OperationId,
@@ -95,18 +98,24 @@ use fedimint_core::db::{
     AutocommitError, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped, IRawDatabase,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::endpoint_update::apply_endpoint_updates;
+use fedimint_core::invite::ClientJoinId;
+use fedimint_core::meta::FederationMeta;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{
     ApiVersion, MultiApiVersion, SupportedApiVersionsSummary, SupportedCoreApiVersions,
     SupportedModuleApiVersions,
 };
+use fedimint_core::net::proxy::Socks5Proxy;
+use fedimint_core::query::{PeerLatencyTracker, QueryPolicies};
+use fedimint_core::rotation::broadcast_public_keys_at;
 use fedimint_core::task::{sleep, MaybeSend, MaybeSync, TaskGroup};
 use fedimint_core::time::now;
 use fedimint_core::transaction::Transaction;
 use fedimint_core::util::{BoxStream, NextOrPending};
 use fedimint_core::{
     apply, async_trait_maybe_send, dyn_newtype_define, maybe_add_send_sync, Amount, OutPoint,
-    TransactionId,
+    PeerId, TransactionId,
 };
 pub use fedimint_derive_secret as derivable_secret;
 use fedimint_derive_secret::{ChildId, DerivableSecret};
@@ -120,6 +129,7 @@ use tokio::runtime::{Handle as RuntimeHandle, RuntimeFlavor};
 use tracing::{debug, error, info, warn};
 
 use crate::backup::Metadata;
+use crate::balance::{BalanceBreakdown, ModuleBalance, PendingOperation};
 use crate::module::init::{
     ClientModuleInit, ClientModuleInitRegistry, DynClientModuleInit, IClientModuleInit,
 };
@@ -140,10 +150,14 @@ use crate::transaction::{
 
 /// Client backup
 pub mod backup;
+/// Breakdown of the client balance by module and state
+pub mod balance;
 /// Database keys used by the client
 pub mod db;
 /// Module client interface definitions
 pub mod module;
+/// Wallet holding funds in several federations at once
+pub mod multi;
 /// Operation log subsystem of the client
 pub mod oplog;
 /// Secret handling & derivation
@@ -183,13 +197,13 @@ pub trait IGlobalClientContext: Debug + MaybeSend + MaybeSync + 'static {
     fn decoders(&self) -> &ModuleDecoderRegistry;
 
     /// This function is mostly meant for internal use, you are probably looking
-    /// for [`DynGlobalClientContext::claim_input`].
+    /// for [`DynGlobalClientContext::claim_inputs`].
     /// Returns transaction id of the funding transaction and an optional
     /// `OutPoint` that represents change if change was added.
-    async fn claim_input_dyn(
+    async fn claim_inputs_dyn(
         &self,
         dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
-        input: InstancelessDynClientInput,
+        inputs: Vec<InstancelessDynClientInput>,
     ) -> (TransactionId, Vec<OutPoint>);
 
     /// This function is mostly meant for internal use, you are probably looking
@@ -262,13 +276,35 @@ impl DynGlobalClientContext {
         I: IInput + MaybeSend + MaybeSync + 'static,
         S: IState<DynGlobalClientContext> + MaybeSend + MaybeSync + 'static,
     {
-        self.claim_input_dyn(
+        self.claim_inputs(dbtx, vec![input]).await
+    }
+
+    /// Creates a single transaction with an output of the primary module,
+    /// claiming all the given inputs at once, see
+    /// [`DynGlobalClientContext::claim_input`].
+    ///
+    /// Claiming many inputs together requires fewer transactions to be
+    /// submitted to and processed by the federation than claiming each on its
+    /// own.
+    pub async fn claim_inputs<I, S>(
+        &self,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        inputs: Vec<ClientInput<I, S>>,
+    ) -> (TransactionId, Vec<OutPoint>)
+    where
+        I: IInput + MaybeSend + MaybeSync + 'static,
+        S: IState<DynGlobalClientContext> + MaybeSend + MaybeSync + 'static,
+    {
+        self.claim_inputs_dyn(
             dbtx,
-            InstancelessDynClientInput {
-                input: Box::new(input.input),
-                keys: input.keys,
-                state_machines: states_to_instanceless_dyn(input.state_machines),
-            },
+            inputs
+                .into_iter()
+                .map(|input| InstancelessDynClientInput {
+                    input: Box::new(input.input),
+                    keys: input.keys,
+                    state_machines: states_to_instanceless_dyn(input.state_machines),
+                })
+                .collect(),
         )
         .await
     }
@@ -383,22 +419,25 @@ impl IGlobalClientContext for ModuleGlobalClientContext {
         self.client.config()
     }
 
-    async fn claim_input_dyn(
+    async fn claim_inputs_dyn(
         &self,
         dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
-        input: InstancelessDynClientInput,
+        inputs: Vec<InstancelessDynClientInput>,
     ) -> (TransactionId, Vec<OutPoint>) {
-        let instance_input = ClientInput {
-            input: DynInput::from_parts(self.module_instance_id, input.input),
-            keys: input.keys,
-            state_machines: states_add_instance(self.module_instance_id, input.state_machines),
-        };
+        let instance_inputs = inputs
+            .into_iter()
+            .map(|input| ClientInput {
+                input: DynInput::from_parts(self.module_instance_id, input.input),
+                keys: input.keys,
+                state_machines: states_add_instance(self.module_instance_id, input.state_machines),
+            })
+            .collect();
 
         self.client
             .finalize_and_submit_transaction_inner(
                 dbtx.global_tx(),
                 self.operation,
-                TransactionBuilder::new().with_input(instance_input),
+                TransactionBuilder::new().with_inputs(instance_inputs),
             )
             .await
             .expect("Can only fail if additional funding is needed")
@@ -561,6 +600,9 @@ impl Drop for ClientArc {
 const SUPPORTED_CORE_API_VERSIONS: &[fedimint_core::module::ApiVersion] =
     &[ApiVersion { major: 0, minor: 0 }];
 
+/// How often the latency history of the guardians is written to the database
+const PEER_LATENCY_HISTORY_PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
 pub type ModuleGlobalContextGen = ContextGen<DynGlobalClientContext>;
 
 /// Resources particular to a module instance
@@ -665,6 +707,8 @@ impl Client {
         let mut out_amount = Amount::ZERO;
         let mut fee_amount = Amount::ZERO;
 
+        let (module_inputs, module_outputs) = self.module_amounts(builder);
+
         for input in &builder.inputs {
             let module = self.get_module(input.input.module_instance_id());
             let item_amount = module.input_amount(&input.input);
@@ -679,6 +723,12 @@ impl Client {
             fee_amount += item_amount.fee;
         }
 
+        fee_amount += self
+            .config
+            .global
+            .fees
+            .transaction_fee(&module_inputs, &module_outputs);
+
         let total_out_amount = out_amount + fee_amount;
 
         match total_out_amount.cmp(&in_amount) {
@@ -690,6 +740,41 @@ impl Client {
         }
     }
 
+    /// Sums up the amounts of the inputs and of the outputs of the transaction
+    /// per module, which the fee schedule of the federation charges
+    fn module_amounts(
+        &self,
+        builder: &TransactionBuilder,
+    ) -> (
+        BTreeMap<ModuleInstanceId, Amount>,
+        BTreeMap<ModuleInstanceId, Amount>,
+    ) {
+        let mut module_inputs = BTreeMap::new();
+        let mut module_outputs = BTreeMap::new();
+
+        for input in &builder.inputs {
+            let module_instance_id = input.input.module_instance_id();
+            let amount = self
+                .get_module(module_instance_id)
+                .input_amount(&input.input);
+            *module_inputs
+                .entry(module_instance_id)
+                .or_insert(Amount::ZERO) += amount.amount;
+        }
+
+        for output in &builder.outputs {
+            let module_instance_id = output.output.module_instance_id();
+            let amount = self
+                .get_module(module_instance_id)
+                .output_amount(&output.output);
+            *module_outputs
+                .entry(module_instance_id)
+                .or_insert(Amount::ZERO) += amount.amount;
+        }
+
+        (module_inputs, module_outputs)
+    }
+
     pub fn get_internal_payment_markers(&self) -> anyhow::Result<(PublicKey, u64)> {
         Ok((self.federation_id().to_fake_ln_pub_key(&self.secp_ctx)?, 0))
     }
@@ -698,6 +783,15 @@ impl Client {
         self.federation_meta.get(key).cloned()
     }
 
+    /// Fetches the latest metadata the guardians announced after setup, which
+    /// is only returned if it was signed by the federation
+    pub async fn fetch_signed_meta(&self) -> anyhow::Result<Option<FederationMeta>> {
+        let federation_id = self.federation_id();
+        let signed = self.api.fetch_federation_meta(&federation_id).await?;
+
+        Ok(signed.map(|signed| signed.meta))
+    }
+
     fn root_secret(&self) -> DerivableSecret {
         self.root_secret.clone()
     }
@@ -738,7 +832,9 @@ impl Client {
         Vec<DynState<DynGlobalClientContext>>,
         Range<u64>,
     )> {
-        if let TransactionBuilderBalance::Underfunded(missing_amount) =
+        // the inputs added for the missing amount increase the fees of the transaction,
+        // which we may have to fund with another input
+        while let TransactionBuilderBalance::Underfunded(missing_amount) =
             self.transaction_builder_balance(&partial_transaction)
         {
             let inputs = self
@@ -750,6 +846,10 @@ impl Client {
                     missing_amount,
                 )
                 .await?;
+            ensure!(
+                !inputs.is_empty(),
+                "Primary module created no inputs to fund {missing_amount}"
+            );
             partial_transaction.inputs.extend(inputs);
         }
 
@@ -764,31 +864,48 @@ impl Client {
         if let TransactionBuilderBalance::Overfunded(excess_amount) =
             self.transaction_builder_balance(&partial_transaction)
         {
-            let change_outputs = self
-                .primary_module()
-                .create_exact_output(
-                    self.primary_module_instance,
-                    dbtx,
-                    operation_id,
-                    excess_amount,
-                )
-                .await;
+            // the change has to cover the fees it causes, what is left over is
+            // collected by the federation
+            let (_, module_outputs) = self.module_amounts(&partial_transaction);
+            let change_amount = self.config.global.fees.max_change(
+                self.primary_module_instance,
+                module_outputs.get(&self.primary_module_instance).copied(),
+                excess_amount,
+            );
+
+            if change_amount != Amount::ZERO {
+                let change_outputs = self
+                    .primary_module()
+                    .create_exact_output(
+                        self.primary_module_instance,
+                        dbtx,
+                        operation_id,
+                        change_amount,
+                    )
+                    .await;
 
-            // We add our new mint outputs to the change range
-            change_range.end += change_outputs.len() as u64;
-            partial_transaction.outputs.extend(change_outputs);
+                // We add our new mint outputs to the change range
+                change_range.end += change_outputs.len() as u64;
+                partial_transaction.outputs.extend(change_outputs);
+            }
         }
 
         assert!(
-            matches!(
-                self.transaction_builder_balance(&partial_transaction),
-                TransactionBuilderBalance::Balanced
-            ),
+            match self.transaction_builder_balance(&partial_transaction) {
+                TransactionBuilderBalance::Balanced => true,
+                TransactionBuilderBalance::Overfunded(residue) => {
+                    residue <= self.config.global.fees.max_change_residue()
+                }
+                TransactionBuilderBalance::Underfunded(_) => false,
+            },
             "Transaction is balanced after the previous two operations"
         );
 
         let (tx, states) = partial_transaction.build(&self.secp_ctx, thread_rng());
 
+        // the federation would reject the transaction without ever accepting it
+        self.config.global.transaction_limits.check(&tx)?;
+
         Ok((tx, states, change_range))
     }
 
@@ -1085,6 +1202,77 @@ impl Client {
             .await
     }
 
+    /// Funds of the client broken down by module instance and by their state,
+    /// including pending funds that are not part of [`Self::get_balance`]
+    pub async fn get_balance_breakdown(&self) -> BalanceBreakdown {
+        let active_states = self
+            .executor
+            .get_active_states()
+            .await
+            .into_iter()
+            .map(|(state, _)| state)
+            .collect::<Vec<_>>();
+
+        let mut dbtx = self.db().begin_transaction().await;
+        let mut modules = BTreeMap::new();
+
+        for (module_instance_id, kind, module) in self.modules.iter_modules() {
+            let items = module
+                .get_balance_breakdown(module_instance_id, &mut dbtx, &active_states)
+                .await;
+
+            modules.insert(module_instance_id, ModuleBalance::new(kind.clone(), items));
+        }
+
+        BalanceBreakdown::new(modules)
+    }
+
+    /// The funds locked and the fees paid by every operation that still has
+    /// active state machines, broken down by the stages of the modules
+    /// involved
+    pub async fn get_pending_operations(&self) -> Vec<PendingOperation> {
+        let mut operations: BTreeMap<OperationId, Vec<_>> = BTreeMap::new();
+        for (state, _) in self.executor.get_active_states().await {
+            operations
+                .entry(state.operation_id())
+                .or_default()
+                .push(state);
+        }
+
+        let mut dbtx = self.db().begin_transaction().await;
+        let mut pending_operations = Vec::with_capacity(operations.len());
+
+        for (operation_id, active_states) in operations {
+            // operations like restoring a backup are not logged
+            let Some(operation) = self.operation_log.get_operation(operation_id).await else {
+                continue;
+            };
+
+            let mut modules = BTreeMap::new();
+            for (module_instance_id, _, module) in self.modules.iter_modules() {
+                if active_states
+                    .iter()
+                    .all(|state| state.module_instance_id() != module_instance_id)
+                {
+                    continue;
+                }
+
+                let stages = module
+                    .get_pending_stages(module_instance_id, &mut dbtx, &operation, &active_states)
+                    .await;
+                modules.insert(module_instance_id, stages);
+            }
+
+            pending_operations.push(PendingOperation::new(
+                operation_id,
+                operation.operation_module_kind().to_string(),
+                modules,
+            ));
+        }
+
+        pending_operations
+    }
+
     /// Returns a stream that yields the current client balance every time it
     /// changes.
     pub async fn subscribe_balance_changes(&self) -> BoxStream<'static, Amount> {
@@ -1112,6 +1300,55 @@ impl Client {
         })
     }
 
+    /// Verifies that the federation accepted `transaction` without
+    /// downloading the block it was included in. Instead we check a merkle
+    /// proof of its inclusion against the threshold signed header of the
+    /// block, so the cost is independent of the number of items in the
+    /// session. Returns the index of the block.
+    pub async fn verify_transaction_inclusion(
+        &self,
+        transaction: &Transaction,
+    ) -> anyhow::Result<u64> {
+        Ok(self
+            .transaction_receipt(transaction)
+            .await?
+            .proof
+            .session_index)
+    }
+
+    /// Fetches and verifies a receipt that the federation accepted
+    /// `transaction`, which can be archived as a cryptographic proof of
+    /// payment. It can be verified later with [`TransactionReceipt::verify`]
+    /// against the broadcast public keys of the federation at the session of
+    /// the receipt.
+    pub async fn transaction_receipt(
+        &self,
+        transaction: &Transaction,
+    ) -> anyhow::Result<TransactionReceipt> {
+        let txid = transaction.tx_hash();
+        let proof = self.api().await_transaction_proof(txid).await?;
+
+        // the block may have been signed with broadcast keys replaced since
+        let key_epochs = self.api().key_epochs().await?;
+        let broadcast_public_keys = broadcast_public_keys_at(
+            &key_epochs,
+            &self.config.global.broadcast_public_keys,
+            proof.session_index,
+        );
+
+        let receipt = self
+            .api()
+            .await_transaction_receipt(txid, broadcast_public_keys)
+            .await?;
+
+        ensure!(
+            receipt.verify(transaction, broadcast_public_keys),
+            "Invalid receipt for transaction {txid}"
+        );
+
+        Ok(receipt)
+    }
+
     pub async fn discover_common_api_version(&self) -> anyhow::Result<ApiVersionSet> {
         Ok(self
             .api()
@@ -1210,6 +1447,158 @@ impl Client {
         Self::refresh_common_api_version_static(config, module_inits, api, db).await
     }
 
+    /// Load the latency history of the guardians and start a background
+    /// process persisting it periodically for as long as the returned tracker
+    /// is in use
+    async fn load_and_persist_peer_latency_history_static(db: &Database) -> PeerLatencyTracker {
+        let histories = db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&PeerLatencyHistoryKeyPrefix)
+            .await
+            .map(|(key, history)| (key.0, history))
+            .collect()
+            .await;
+        let latency = PeerLatencyTracker::from_histories(histories);
+
+        let weak_latency = latency.downgrade();
+        let db = db.clone();
+        // Separate task group, the task ends by itself once the client is dropped
+        TaskGroup::new()
+            .spawn("persist_peer_latency_history", |_| async move {
+                loop {
+                    sleep(PEER_LATENCY_HISTORY_PERSIST_INTERVAL).await;
+
+                    let Some(latency) = PeerLatencyTracker::upgrade(&weak_latency) else {
+                        break;
+                    };
+
+                    let mut dbtx = db.begin_transaction().await;
+                    for (peer_id, history) in latency.get_all() {
+                        dbtx.insert_entry(&PeerLatencyHistoryKey(peer_id), &history)
+                            .await;
+                    }
+                    if let Err(e) = dbtx.commit_tx_result().await {
+                        warn!("Failed to persist peer latency history: {e}");
+                    }
+                }
+            })
+            .await;
+
+        latency
+    }
+
+    /// Load the API endpoints of the guardians, with the URLs of the endpoint
+    /// updates we verified before in place of the ones in the config
+    async fn load_api_endpoints_static(
+        config: &ClientConfig,
+        db: &Database,
+    ) -> BTreeMap<PeerId, PeerUrl> {
+        let updates = db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&ApiEndpointUpdateKeyPrefix)
+            .await
+            .map(|(_, update)| update)
+            .collect::<Vec<_>>()
+            .await;
+
+        apply_endpoint_updates(
+            &config.global.api_endpoints,
+            updates,
+            &config.global.broadcast_public_keys,
+        )
+    }
+
+    /// Start a background process fetching the endpoint updates the guardians
+    /// published, which are used the next time a [`Client`] is built
+    async fn refresh_api_endpoints_static(
+        config: &ClientConfig,
+        api: &DynGlobalApi,
+        db: &Database,
+    ) {
+        let broadcast_public_keys = config.global.broadcast_public_keys.clone();
+        let api = api.clone();
+        let db = db.clone();
+        // Separate task group, because refreshing the endpoints is just best effort
+        TaskGroup::new()
+            .spawn("refresh_api_endpoints_static", |_| async move {
+                let updates = match api.api_endpoint_updates().await {
+                    Ok(updates) => updates,
+                    Err(e) => {
+                        warn!("Failed to fetch api endpoint updates: {e}");
+                        return;
+                    }
+                };
+
+                let mut dbtx = db.begin_transaction().await;
+                for update in updates {
+                    // only the guardian itself can move its endpoint
+                    if !update.verify(&broadcast_public_keys) {
+                        warn!("Ignoring api endpoint update with invalid signature");
+                        continue;
+                    }
+
+                    let key = ApiEndpointUpdateKey(update.peer);
+                    let is_newer = dbtx
+                        .get_value(&key)
+                        .await
+                        .map_or(true, |stored| stored.sequence < update.sequence);
+
+                    if is_newer {
+                        info!(
+                            "Guardian {} moved its api to {}",
+                            update.peer, update.url.url
+                        );
+                        dbtx.insert_entry(&key, &update).await;
+                    }
+                }
+                if let Err(e) = dbtx.commit_tx_result().await {
+                    warn!("Failed to persist api endpoint updates: {e}");
+                }
+            })
+            .await;
+    }
+
+    /// Downloads the fields a config migrated from an older client is missing
+    /// and persists the completed config
+    ///
+    /// Without them the client can neither verify signed block headers nor pay
+    /// the fees of the federation, so we fail instead of running with the
+    /// placeholders of the migration.
+    async fn complete_migrated_config_static(
+        config: ClientConfig,
+        api: &DynGlobalApi,
+        db: &Database,
+    ) -> anyhow::Result<ClientConfig> {
+        // every federation has broadcast public keys, so they are only missing
+        // after the migration
+        if !config.global.broadcast_public_keys.is_empty() {
+            return Ok(config);
+        }
+
+        let invite_code = get_invite_code_from_db(db)
+            .await
+            .context("Cannot complete the migrated client config without an invite code")?;
+        let downloaded = api
+            .download_client_config(&invite_code)
+            .await
+            .context("Failed to download the client config to complete the migrated one")?;
+        let config = complete_migrated_config(config, downloaded)?;
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &ClientConfigKey {
+                id: config.global.federation_id,
+            },
+            &config,
+        )
+        .await;
+        dbtx.commit_tx_result().await?;
+
+        Ok(config)
+    }
+
     async fn refresh_common_api_version_static(
         config: &ClientConfig,
         module_inits: &ModuleInitRegistry<DynClientModuleInit>,
@@ -1267,15 +1656,26 @@ pub struct FederationInfo {
     config: ClientConfig,
     // TODO: make non-optional or remove
     invite_code: Option<InviteCode>,
+    socks5_proxy: Option<Socks5Proxy>,
 }
 
 impl FederationInfo {
     /// Download federation info using invitation code
     pub async fn from_invite_code(invite: InviteCode) -> anyhow::Result<FederationInfo> {
-        let config = try_download_config(invite.clone(), 10).await?;
+        Self::from_invite_code_with_proxy(invite, None).await
+    }
+
+    /// Download federation info using invitation code, tunneling the
+    /// connections through `proxy` if given
+    pub async fn from_invite_code_with_proxy(
+        invite: InviteCode,
+        proxy: Option<Socks5Proxy>,
+    ) -> anyhow::Result<FederationInfo> {
+        let config = try_download_config(invite.clone(), proxy.clone(), 10).await?;
         Ok(FederationInfo {
             config,
             invite_code: Some(invite),
+            socks5_proxy: proxy,
         })
     }
 
@@ -1287,6 +1687,7 @@ impl FederationInfo {
         Ok(FederationInfo {
             config,
             invite_code: None,
+            socks5_proxy: None,
         })
     }
 
@@ -1315,7 +1716,12 @@ impl FederationInfo {
 
     /// Creates an API client for the federation
     pub fn api(&self) -> DynGlobalApi {
-        DynGlobalApi::from(WsFederationApi::from_config(&self.config))
+        let mut api = WsFederationApi::from_config(&self.config);
+        if let Some(proxy) = &self.socks5_proxy {
+            api = api.with_socks5_proxy(proxy.clone());
+        }
+
+        DynGlobalApi::from(api)
     }
 
     pub fn federation_id(&self) -> FederationId {
@@ -1329,6 +1735,9 @@ pub struct ClientBuilder {
     primary_module_instance: Option<ModuleInstanceId>,
     config: Option<FederationInfo>,
     db: Option<DatabaseSource>,
+    query_policies: QueryPolicies,
+    backup_interval: Option<Duration>,
+    socks5_proxy: Option<Socks5Proxy>,
 }
 
 pub enum DatabaseSource {
@@ -1383,6 +1792,25 @@ impl ClientBuilder {
         )
     }
 
+    /// Configures how many guardians have to agree on the responses to the
+    /// client's requests, see [`QueryPolicies`]
+    pub fn with_query_policies(&mut self, query_policies: QueryPolicies) {
+        self.query_policies = query_policies;
+    }
+
+    /// Backs up the client state to the federation whenever `interval` passed
+    /// since the latest backup while the client is running, see
+    /// [`Client::backup_to_federation`]
+    pub fn with_backup_interval(&mut self, interval: Duration) {
+        self.backup_interval = Some(interval);
+    }
+
+    /// Tunnels the connections to the guardians through the SOCKS5 `proxy`,
+    /// e.g. Tor to hide our IP address or to reach onion services
+    pub fn with_socks5_proxy(&mut self, proxy: Socks5Proxy) {
+        self.socks5_proxy = Some(proxy);
+    }
+
     // TODO: impl config from file
     // TODO: impl config from federation
 
@@ -1457,7 +1885,7 @@ impl ClientBuilder {
     }
 
     pub async fn build_restoring_from_backup(
-        self,
+        mut self,
         root_secret: DerivableSecret,
     ) -> anyhow::Result<(ClientArc, Metadata)> {
         // TODO: assert DB is empty (what does that mean? maybe needs a method that
@@ -1475,16 +1903,27 @@ impl ClientBuilder {
         //     "Database is not empty, cannot restore from backup"
         // );
 
+        // scheduled backups must not replace the backup we restore from
+        let backup_interval = self.backup_interval.take();
+
         let client = self.build(root_secret).await?;
         let metadata = client.restore_from_backup().await?;
 
+        if let Some(interval) = backup_interval {
+            client.spawn_scheduled_backups(interval).await;
+        }
+
         Ok((client, metadata))
     }
 
     /// Build a [`Client`] and start its executor
     pub async fn build(self, root_secret: DerivableSecret) -> anyhow::Result<ClientArc> {
+        let backup_interval = self.backup_interval;
         let client = self.build_stopped(root_secret).await?;
         client.start_executor().await;
+        if let Some(interval) = backup_interval {
+            client.spawn_scheduled_backups(interval).await;
+        }
         Ok(client)
     }
 
@@ -1492,6 +1931,9 @@ impl ClientBuilder {
     pub async fn build_stopped(self, root_secret: DerivableSecret) -> anyhow::Result<ClientArc> {
         let (config, decoders, db) = match self.db.ok_or(anyhow!("No database was provided"))? {
             DatabaseSource::Fresh(db) => {
+                // The config is read before the modules are initialized, so it has to be
+                // migrated first
+                apply_core_client_migrations(&db).await?;
                 let config = get_config(&db, self.config.clone()).await?;
 
                 let mut decoders = client_decoders(
@@ -1528,7 +1970,18 @@ impl ClientBuilder {
             .ok_or(anyhow!("No primary module instance id was provided"))?;
 
         let notifier = Notifier::new(db.clone());
-        let api = DynGlobalApi::from(WsFederationApi::from_config(&config));
+        let latency = Client::load_and_persist_peer_latency_history_static(&db).await;
+        let api_endpoints = Client::load_api_endpoints_static(&config, &db).await;
+        let mut api = WsFederationApi::from_endpoints(&api_endpoints)
+            .with_latency_tracker(latency)
+            .with_query_policies(self.query_policies.clone());
+        if let Some(proxy) = self.socks5_proxy.clone() {
+            api = api.with_socks5_proxy(proxy);
+        }
+        let api = DynGlobalApi::from(api);
+
+        Client::refresh_api_endpoints_static(&config, &api, &db).await;
+        let config = Client::complete_migrated_config_static(config, &api, &db).await?;
 
         let common_api_versions = Client::load_and_refresh_common_api_version_static(
             &config,
@@ -1683,17 +2136,23 @@ pub async fn get_invite_code_from_db(db: &Database) -> Option<InviteCode> {
 /// attempts up to `retries` number times
 async fn try_download_config(
     invite_code: InviteCode,
+    proxy: Option<Socks5Proxy>,
     max_retries: usize,
 ) -> anyhow::Result<ClientConfig> {
-    let api = Arc::new(WsFederationApi::from_invite_code(&[invite_code.clone()]))
-        as Arc<dyn IGlobalFederationApi + Send + Sync + 'static>;
+    let mut api = WsFederationApi::from_invite_code(&[invite_code.clone()]);
+    if let Some(proxy) = proxy {
+        api = api.with_socks5_proxy(proxy);
+    }
+    let api = Arc::new(api) as Arc<dyn IGlobalFederationApi + Send + Sync + 'static>;
+    // retries join under the same id, so they do not use up the invite code
+    let client_id = ClientJoinId::new_random();
     let mut num_retries = 0;
     let wait_millis = 500;
     loop {
         if num_retries > max_retries {
             break Err(anyhow!("Failed to download client config"));
         }
-        match api.download_client_config(&invite_code).await {
+        match api.join_federation(&invite_code, client_id).await {
             Ok(cfg) => {
                 break Ok(cfg);
             }
@@ -19,6 +19,8 @@ use fedimint_core::{
 };
 use futures::Future;
 
+use crate::balance::{BalanceItem, PendingStage};
+use crate::oplog::OperationLogEntry;
 use crate::sm::{Context, DynContext, DynState, Executor, State};
 use crate::transaction::{ClientInput, ClientOutput};
 use crate::{Client, ClientArc, ClientWeak, DynGlobalClientContext};
@@ -237,6 +239,30 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
         unimplemented!()
     }
 
+    /// Returns the funds held by this module broken down by their state, see
+    /// [`crate::balance`]. The active states of the module's state machines
+    /// are passed in since they track the funds of pending operations.
+    async fn get_balance_breakdown(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _active_states: Vec<Self::States>,
+    ) -> Vec<BalanceItem> {
+        vec![]
+    }
+
+    /// Returns the stages of the module's state machines that are still
+    /// active in a pending operation, see
+    /// [`crate::balance::PendingOperation`]. The log entry of the operation is
+    /// passed in since some funds and fees are only recorded in its meta data.
+    async fn get_pending_stages(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _operation: &OperationLogEntry,
+        _active_states: Vec<Self::States>,
+    ) -> Vec<PendingStage> {
+        vec![]
+    }
+
     /// Leave the federation
     ///
     /// While technically there's nothing stopping the client from just
@@ -380,6 +406,21 @@ pub trait IClientModule: Debug {
     ) -> Amount;
 
     async fn subscribe_balance_changes(&self) -> BoxStream<'static, ()>;
+
+    async fn get_balance_breakdown(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        active_states: &[DynState<DynGlobalClientContext>],
+    ) -> Vec<BalanceItem>;
+
+    async fn get_pending_stages(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation: &OperationLogEntry,
+        active_states: &[DynState<DynGlobalClientContext>],
+    ) -> Vec<PendingStage>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -527,6 +568,54 @@ where
     async fn subscribe_balance_changes(&self) -> BoxStream<'static, ()> {
         <T as ClientModule>::subscribe_balance_changes(self).await
     }
+
+    async fn get_balance_breakdown(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        active_states: &[DynState<DynGlobalClientContext>],
+    ) -> Vec<BalanceItem> {
+        <T as ClientModule>::get_balance_breakdown(
+            self,
+            &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance),
+            typed_states::<T>(module_instance, active_states),
+        )
+        .await
+    }
+
+    async fn get_pending_stages(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation: &OperationLogEntry,
+        active_states: &[DynState<DynGlobalClientContext>],
+    ) -> Vec<PendingStage> {
+        <T as ClientModule>::get_pending_stages(
+            self,
+            &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance),
+            operation,
+            typed_states::<T>(module_instance, active_states),
+        )
+        .await
+    }
+}
+
+/// The states of the module instance among `active_states`
+fn typed_states<T: ClientModule>(
+    module_instance: ModuleInstanceId,
+    active_states: &[DynState<DynGlobalClientContext>],
+) -> Vec<T::States> {
+    active_states
+        .iter()
+        .filter(|state| state.module_instance_id() == module_instance)
+        .map(|state| {
+            state
+                .as_any()
+                .downcast_ref::<T::States>()
+                .expect("Dispatched to correct module")
+                .clone()
+        })
+        .collect()
 }
 
 dyn_newtype_define!(
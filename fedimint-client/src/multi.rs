@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Context};
+use fedimint_core::api::InviteCode;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::Amount;
+use fedimint_derive_secret::DerivableSecret;
+use futures::StreamExt;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::db::{
+    ChronologicalOperationLogKey, DbKeyPrefix, JoinedFederationKey, JoinedFederationKeyPrefix,
+};
+use crate::module::init::ClientModuleInitRegistry;
+use crate::oplog::{OperationLogEntry, OperationLogQuery};
+use crate::secret::DeriveableSecretClientExt;
+use crate::{Client, ClientArc, FederationInfo};
+
+/// Wallet holding funds in several federations at once
+///
+/// Every joined federation is served by its own [`Client`], whose state and
+/// state machines are kept in a separate partition of the database and whose
+/// root secret is derived from the one of the wallet, see
+/// [`DeriveableSecretClientExt::derive_federation_secret`]. Balances and
+/// operations can be queried across all federations, while operations are
+/// started on the [`Client`] of the federation returned by
+/// [`MultiClient::get`].
+pub struct MultiClient {
+    db: Database,
+    module_inits: ClientModuleInitRegistry,
+    primary_module_instance: ModuleInstanceId,
+    root_secret: DerivableSecret,
+    clients: Mutex<BTreeMap<FederationId, ClientArc>>,
+}
+
+impl MultiClient {
+    /// Opens the wallet stored in `db`, starting the clients of all
+    /// federations joined before
+    pub async fn new(
+        db: Database,
+        module_inits: ClientModuleInitRegistry,
+        primary_module_instance: ModuleInstanceId,
+        root_secret: DerivableSecret,
+    ) -> anyhow::Result<MultiClient> {
+        let federation_ids = db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&JoinedFederationKeyPrefix)
+            .await
+            .map(|(key, ())| key.id)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut multi_client = MultiClient {
+            db,
+            module_inits,
+            primary_module_instance,
+            root_secret,
+            clients: Mutex::new(BTreeMap::new()),
+        };
+
+        let mut clients = BTreeMap::new();
+        for federation_id in federation_ids {
+            let client = multi_client.build_client(federation_id, None).await?;
+            clients.insert(federation_id, client);
+        }
+        *multi_client.clients.get_mut() = clients;
+
+        Ok(multi_client)
+    }
+
+    /// Joins the federation of `invite_code` and returns its client, which is
+    /// started again whenever the wallet is opened. Returns the existing
+    /// client if the federation was joined before.
+    pub async fn join(&self, invite_code: InviteCode) -> anyhow::Result<ClientArc> {
+        let federation_info = FederationInfo::from_invite_code(invite_code).await?;
+        let federation_id = federation_info.federation_id();
+
+        let mut clients = self.clients.lock().await;
+        if let Some(client) = clients.get(&federation_id) {
+            return Ok(client.clone());
+        }
+
+        let client = self
+            .build_client(federation_id, Some(federation_info))
+            .await?;
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&JoinedFederationKey { id: federation_id }, &())
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        info!(%federation_id, "Joined federation");
+        clients.insert(federation_id, client.clone());
+
+        Ok(client)
+    }
+
+    /// Leaves the federation, stopping its client and deleting its state
+    /// unless the federation is joined again later. Fails while the wallet
+    /// holds funds in the federation, since they would be lost.
+    pub async fn leave(&self, federation_id: FederationId) -> anyhow::Result<()> {
+        let mut clients = self.clients.lock().await;
+        let client = clients
+            .get(&federation_id)
+            .context("The federation was not joined")?;
+        ensure!(
+            client.get_balance().await == Amount::ZERO,
+            "Can not leave a federation while holding funds in it"
+        );
+
+        // the client stops once the last handle to it is dropped
+        clients.remove(&federation_id);
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.remove_entry(&JoinedFederationKey { id: federation_id })
+            .await;
+        dbtx.raw_remove_by_prefix(&Self::federation_db_prefix(federation_id))
+            .await?;
+        dbtx.commit_tx_result().await?;
+
+        info!(%federation_id, "Left federation");
+
+        Ok(())
+    }
+
+    /// The client of a joined federation
+    pub async fn get(&self, federation_id: FederationId) -> Option<ClientArc> {
+        self.clients.lock().await.get(&federation_id).cloned()
+    }
+
+    /// The federations joined so far
+    pub async fn federation_ids(&self) -> Vec<FederationId> {
+        self.clients.lock().await.keys().copied().collect()
+    }
+
+    /// Balance available for spending across all joined federations
+    pub async fn get_balance(&self) -> Amount {
+        self.get_balances().await.into_values().sum()
+    }
+
+    /// Balance available for spending in each of the joined federations
+    pub async fn get_balances(&self) -> BTreeMap<FederationId, Amount> {
+        let mut balances = BTreeMap::new();
+        for (federation_id, client) in self.clients().await {
+            balances.insert(federation_id, client.get_balance().await);
+        }
+        balances
+    }
+
+    /// Returns the last `limit` operations across all joined federations,
+    /// newest first. To fetch the next page, pass the last operation's
+    /// [`ChronologicalOperationLogKey`] as `start_after`.
+    pub async fn list_operations(
+        &self,
+        limit: usize,
+        start_after: Option<ChronologicalOperationLogKey>,
+    ) -> Vec<(
+        FederationId,
+        ChronologicalOperationLogKey,
+        OperationLogEntry,
+    )> {
+        self.query_operations(&OperationLogQuery::default(), limit, start_after)
+            .await
+    }
+
+    /// Returns the last `limit` operations across all joined federations
+    /// matching `query`, paginated like [`Self::list_operations`]
+    pub async fn query_operations(
+        &self,
+        query: &OperationLogQuery,
+        limit: usize,
+        start_after: Option<ChronologicalOperationLogKey>,
+    ) -> Vec<(
+        FederationId,
+        ChronologicalOperationLogKey,
+        OperationLogEntry,
+    )> {
+        let mut operations = vec![];
+        for (federation_id, client) in self.clients().await {
+            let federation_operations = client
+                .operation_log()
+                .query_operations(query, limit, start_after)
+                .await;
+
+            operations.extend(
+                federation_operations
+                    .into_iter()
+                    .map(|(key, entry)| (federation_id, key, entry)),
+            );
+        }
+
+        operations.sort_by(|(_, a, _), (_, b, _)| b.creation_time.cmp(&a.creation_time));
+        operations.truncate(limit);
+        operations
+    }
+
+    /// Copies of the client handles, so the lock is not held while using them
+    async fn clients(&self) -> Vec<(FederationId, ClientArc)> {
+        self.clients
+            .lock()
+            .await
+            .iter()
+            .map(|(federation_id, client)| (*federation_id, client.clone()))
+            .collect()
+    }
+
+    async fn build_client(
+        &self,
+        federation_id: FederationId,
+        federation_info: Option<FederationInfo>,
+    ) -> anyhow::Result<ClientArc> {
+        let mut client_builder = Client::builder();
+        client_builder.with_module_inits(self.module_inits.clone());
+        client_builder.with_primary_module(self.primary_module_instance);
+        client_builder.with_database(self.federation_db(federation_id));
+        if let Some(federation_info) = federation_info {
+            client_builder.with_federation_info(federation_info);
+        }
+
+        client_builder
+            .build(self.root_secret.derive_federation_secret(&federation_id))
+            .await
+    }
+
+    /// Partition of the database holding the state of the client of a
+    /// federation
+    fn federation_db(&self, federation_id: FederationId) -> Database {
+        self.db
+            .with_prefix(Self::federation_db_prefix(federation_id))
+    }
+
+    fn federation_db_prefix(federation_id: FederationId) -> Vec<u8> {
+        let mut prefix = vec![DbKeyPrefix::FederationClientDatabase as u8];
+        prefix.extend(
+            federation_id
+                .consensus_encode_to_vec()
+                .expect("Encoding to vec can't fail"),
+        );
+        prefix
+    }
+}
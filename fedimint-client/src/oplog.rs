@@ -1,6 +1,7 @@
 use std::fmt::Debug;
 use std::future;
 use std::io::{Read, Write};
+use std::time::SystemTime;
 
 use async_stream::stream;
 use fedimint_core::core::OperationId;
@@ -63,42 +64,70 @@ impl OperationLog {
         limit: usize,
         start_after: Option<ChronologicalOperationLogKey>,
     ) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
+        self.query_operations(&OperationLogQuery::default(), limit, start_after)
+            .await
+    }
+
+    /// Returns the last `limit` operations matching `query`, paginated like
+    /// [`Self::list_operations`].
+    ///
+    /// The operations are read one by one from the chronological index,
+    /// starting at the end of the time range of the query and stopping at its
+    /// beginning or once the page is full, so neither the history nor the
+    /// operations that do not match are kept in memory.
+    pub async fn query_operations(
+        &self,
+        query: &OperationLogQuery,
+        limit: usize,
+        start_after: Option<ChronologicalOperationLogKey>,
+    ) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
+        let until = match (start_after, query.until) {
+            (Some(start_after), Some(until)) => Some(start_after.creation_time.min(until)),
+            (start_after, until) => start_after.map(|key| key.creation_time).or(until),
+        };
+        let since = query.since;
+        let search = query.search.as_ref().map(|search| search.to_lowercase());
+
+        // The entries are looked up in a second transaction, which sees every
+        // operation the first one does since it begins afterwards
+        let mut index_dbtx = self.db.begin_transaction().await;
         let mut dbtx = self.db.begin_transaction().await;
-        let operations: Vec<ChronologicalOperationLogKey> = dbtx
+
+        // FIXME: this is a schlemil-the-painter algorithm that will take longer the further
+        // back in history one goes. To avoid that I see two options:
+        //   1. Add a reference to the previous operation to each operation log entry,
+        //      essentially creating a linked list, which seem a little bit inelegant.
+        //   2. Add an option to prefix queries that allows to specify a start key
+        //
+        // The current implementation may also skip operations due to `SystemTime` not being
+        // guaranteed to be monotonous. The linked list approach would also fix that.
+        let mut operations = index_dbtx
             .find_by_prefix_sorted_descending(&ChronologicalOperationLogKeyPrefix)
             .await
             .map(|(key, _)| key)
-            // FIXME: this is a schlemil-the-painter algorithm that will take longer the further
-            // back in history one goes. To avoid that I see two options:
-            //   1. Add a reference to the previous operation to each operation log entry,
-            //      essentially creating a linked list, which seem a little bit inelegant.
-            //   2. Add an option to prefix queries that allows to specify a start key
-            //
-            // The current implementation may also skip operations due to `SystemTime` not being
-            // guaranteed to be monotonous. The linked list approach would also fix that.
             .skip_while(move |key| {
-                let skip = if let Some(start_after) = start_after {
-                    key.creation_time >= start_after.creation_time
-                } else {
-                    false
-                };
-
-                std::future::ready(skip)
+                future::ready(until.map_or(false, |until| until <= key.creation_time))
             })
-            .take(limit)
-            .collect::<Vec<_>>()
-            .await;
+            .take_while(move |key| {
+                future::ready(since.map_or(true, |since| since <= key.creation_time))
+            });
 
-        let mut operation_entries = Vec::with_capacity(operations.len());
+        let mut operation_entries = vec![];
+        while operation_entries.len() < limit {
+            let Some(operation) = operations.next().await else {
+                break;
+            };
 
-        for operation in operations {
             let entry = dbtx
                 .get_value(&OperationLogKey {
                     operation_id: operation.operation_id,
                 })
                 .await
                 .expect("Inconsistent DB");
-            operation_entries.push((operation, entry));
+
+            if entry.matches(query, search.as_deref()) {
+                operation_entries.push((operation, entry));
+            }
         }
 
         operation_entries
@@ -151,6 +180,23 @@ impl OperationLog {
     }
 }
 
+/// Filters of [`OperationLog::query_operations`], an operation has to match
+/// all of them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationLogQuery {
+    /// Only operations created at or after this time
+    pub since: Option<SystemTime>,
+    /// Only operations created before this time
+    pub until: Option<SystemTime>,
+    /// Only operations of modules of one of these kinds, operations of all
+    /// modules if empty
+    pub module_kinds: Vec<String>,
+    /// Only operations with this text in any string of their meta data,
+    /// ignoring case. This includes memos the user provided as extra meta data
+    /// when starting the operation.
+    pub search: Option<String>,
+}
+
 /// Represents an operation triggered by a user, typically related to sending or
 /// receiving money.
 ///
@@ -179,6 +225,18 @@ pub struct OperationLogEntry {
 }
 
 impl OperationLogEntry {
+    /// Whether the operation matches the module kinds and `search` text of the
+    /// query, the latter of which has to be lowercase already
+    fn matches(&self, query: &OperationLogQuery, search: Option<&str>) -> bool {
+        let kind_matches = query.module_kinds.is_empty()
+            || query
+                .module_kinds
+                .iter()
+                .any(|kind| kind == &self.operation_module_kind);
+
+        kind_matches && search.map_or(true, |search| json_contains_text(&self.meta, search))
+    }
+
     /// Returns the kind of the module that generated the operation
     pub fn operation_module_kind(&self) -> &str {
         &self.operation_module_kind
@@ -283,6 +341,20 @@ impl Decodable for OperationLogEntry {
     }
 }
 
+/// Whether any string in `value` contains `text` when converted to lowercase
+fn json_contains_text(value: &serde_json::Value, text: &str) -> bool {
+    match value {
+        serde_json::Value::String(string) => string.to_lowercase().contains(text),
+        serde_json::Value::Array(values) => {
+            values.iter().any(|value| json_contains_text(value, text))
+        }
+        serde_json::Value::Object(values) => {
+            values.values().any(|value| json_contains_text(value, text))
+        }
+        _ => false,
+    }
+}
+
 /// Either a stream of operation updates if the operation hasn't finished yet or
 /// its outcome otherwise.
 pub enum UpdateStreamOrOutcome<U> {
@@ -337,6 +409,8 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
     use fedimint_core::core::OperationId;
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::{Database, IRawDatabaseExt};
@@ -345,7 +419,7 @@ mod tests {
 
     use super::UpdateStreamOrOutcome;
     use crate::db::ChronologicalOperationLogKey;
-    use crate::oplog::{OperationLog, OperationLogEntry};
+    use crate::oplog::{OperationLog, OperationLogEntry, OperationLogQuery};
 
     #[test]
     fn test_operation_log_entry_serde() {
@@ -486,4 +560,93 @@ mod tests {
         assert_eq!(page.len(), 8);
         assert_page_entries(page, 9);
     }
+
+    #[tokio::test]
+    async fn test_query_operations() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let op_log = OperationLog::new(db.clone());
+
+        let operations = [
+            (
+                "mint",
+                serde_json::json!({ "extra_meta": { "memo": "Coffee" } }),
+            ),
+            (
+                "ln",
+                serde_json::json!({ "extra_meta": { "memo": "Rent" } }),
+            ),
+            ("ln", serde_json::json!({ "extra_meta": ["Coffee beans"] })),
+            ("wallet", serde_json::json!({ "extra_meta": null })),
+        ];
+        for (operation_idx, (kind, meta)) in operations.into_iter().enumerate() {
+            let mut dbtx = db.begin_transaction().await;
+            op_log
+                .add_operation_log_entry(
+                    &mut dbtx,
+                    OperationId([operation_idx as u8; 32]),
+                    kind,
+                    meta,
+                )
+                .await;
+            dbtx.commit_tx().await;
+        }
+
+        let query_ids = |query: OperationLogQuery| {
+            let op_log = op_log.clone();
+            async move {
+                op_log
+                    .query_operations(&query, 10, None)
+                    .await
+                    .into_iter()
+                    .map(|(key, _)| key.operation_id.0[0])
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            query_ids(OperationLogQuery::default()).await,
+            vec![3, 2, 1, 0]
+        );
+
+        let ln = OperationLogQuery {
+            module_kinds: vec!["ln".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(query_ids(ln).await, vec![2, 1]);
+
+        let coffee = OperationLogQuery {
+            search: Some("COFFEE".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query_ids(coffee).await, vec![2, 0]);
+
+        let ln_coffee = OperationLogQuery {
+            module_kinds: vec!["ln".to_string()],
+            search: Some("coffee".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query_ids(ln_coffee).await, vec![2]);
+
+        let future = OperationLogQuery {
+            since: Some(fedimint_core::time::now() + Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert!(query_ids(future).await.is_empty());
+
+        let past = OperationLogQuery {
+            until: Some(UNIX_EPOCH),
+            ..Default::default()
+        };
+        assert!(query_ids(past).await.is_empty());
+
+        // only the first page is full
+        let first_page = op_log
+            .query_operations(&OperationLogQuery::default(), 3, None)
+            .await;
+        assert_eq!(first_page.len(), 3);
+        let second_page = op_log
+            .query_operations(&OperationLogQuery::default(), 3, Some(first_page[2].0))
+            .await;
+        assert_eq!(second_page.len(), 1);
+    }
 }
@@ -1,6 +1,7 @@
 use std::fmt::Debug;
 use std::io::{Read, Write};
 
+use fedimint_core::config::FederationId;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_derive_secret::{ChildId, DerivableSecret};
@@ -8,10 +9,14 @@ use rand::{CryptoRng, Rng, RngCore};
 
 const TYPE_MODULE: ChildId = ChildId(0);
 const TYPE_BACKUP: ChildId = ChildId(1);
+const TYPE_FEDERATION: ChildId = ChildId(2);
 
 pub trait DeriveableSecretClientExt {
     fn derive_module_secret(&self, module_instance_id: ModuleInstanceId) -> DerivableSecret;
     fn derive_backup_secret(&self) -> DerivableSecret;
+    /// Root secret of the client of one of the federations joined by a
+    /// [`MultiClient`](crate::multi::MultiClient)
+    fn derive_federation_secret(&self, federation_id: &FederationId) -> DerivableSecret;
 }
 
 impl DeriveableSecretClientExt for DerivableSecret {
@@ -25,6 +30,15 @@ impl DeriveableSecretClientExt for DerivableSecret {
         assert_eq!(self.level(), 0);
         self.child_key(TYPE_BACKUP)
     }
+
+    fn derive_federation_secret(&self, federation_id: &FederationId) -> DerivableSecret {
+        assert_eq!(self.level(), 0);
+        let root_key = self.child_key(TYPE_FEDERATION).to_random_bytes::<64>();
+        let salt = federation_id
+            .consensus_encode_to_vec()
+            .expect("Encoding to vec can't fail");
+        DerivableSecret::new_root(&root_key, &salt)
+    }
 }
 
 /// Trait defining a way to generate, serialize and deserialize a root secret.
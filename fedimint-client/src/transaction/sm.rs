@@ -171,8 +171,10 @@ async fn trigger_created_submit(
     loop {
         match context.api().submit_transaction(tx.clone()).await {
             Err(e) if e.is_retryable() => {
-                debug!("Got {e} while submitting transaction, will sleep for {RESUBMISSION_INTERVAL:?}");
-                sleep(RESUBMISSION_INTERVAL).await;
+                // busy guardians tell us when they expect to have room again
+                let delay = e.retry_after().unwrap_or(RESUBMISSION_INTERVAL);
+                debug!("Got {e} while submitting transaction, will sleep for {delay:?}");
+                sleep(delay).await;
             }
             res => return res.map_err(|e| e.to_string()),
         }
@@ -366,10 +368,10 @@ mod tests {
             unimplemented!()
         }
 
-        async fn claim_input_dyn(
+        async fn claim_inputs_dyn(
             &self,
             _dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
-            _input: InstancelessDynClientInput,
+            _inputs: Vec<InstancelessDynClientInput>,
         ) -> (TransactionId, Vec<OutPoint>) {
             unimplemented!()
         }
@@ -9,17 +9,43 @@ use serde::{Deserialize, Serialize};
 use tokio_rustls::rustls;
 
 use crate::api::{
-    DynGlobalApi, FederationApiExt, FederationResult, ServerStatus, StatusResponse, WsFederationApi,
+    ClientConfigDownloadToken, ConsensusItemLogging, CreateScopedTokenRequest, DiagnosticsDump,
+    DynGlobalApi, FederationApiExt, FederationResult, GuardianCheckpoint, InviteCode,
+    ModuleFailure, PeerHealth, SafetyHaltOverride, SafetyViolation, ScopedToken, ServerStatus,
+    StallDiagnostics, StatusResponse, StorageFailure, WsFederationApi,
 };
-use crate::config::ServerModuleConfigGenParamsRegistry;
+use crate::config::{ConfigBundle, PeerUrl, ServerModuleConfigGenParamsRegistry};
+use crate::core::ModuleInstanceId;
+use crate::db::conflicts::ConflictReport;
 use crate::endpoint_constants::{
-    ADD_CONFIG_GEN_PEER_ENDPOINT, AUDIT_ENDPOINT, AUTH_ENDPOINT, GET_CONFIG_GEN_PEERS_ENDPOINT,
+    ADD_CONFIG_GEN_PEER_ENDPOINT, API_USAGE_ENDPOINT, APPROVE_MODULE_ENDPOINT,
+    ATTEST_FINAL_STATE_ENDPOINT, AUDIT_ENDPOINT, AUTH_ENDPOINT, CHECKPOINTS_ENDPOINT,
+    CONSENSUS_ITEM_LOGGING_ENDPOINT, CREATE_CHECKPOINT_ENDPOINT, CREATE_INVITE_CODE_ENDPOINT,
+    CREATE_SCOPED_TOKEN_ENDPOINT, DB_CONFLICTS_ENDPOINT, DKG_STATUS_ENDPOINT,
+    DUMP_DIAGNOSTICS_ENDPOINT, EXPORT_CONFIG_BUNDLE_ENDPOINT, GET_CONFIG_GEN_PEERS_ENDPOINT,
     GET_CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT, GET_DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT,
-    GET_VERIFY_CONFIG_HASH_ENDPOINT, RUN_DKG_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT,
-    SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT,
-    STATUS_ENDPOINT,
+    GET_VERIFY_CONFIG_HASH_ENDPOINT, INVITE_CODES_ENDPOINT, KEY_ROTATION_ENDPOINT,
+    MODULE_FAILURES_ENDPOINT, MODULE_PROPOSALS_ENDPOINT, MODULE_UPGRADES_ENDPOINT,
+    OVERRIDE_SAFETY_HALT_ENDPOINT, PEER_HEALTH_ENDPOINT, PROPOSE_FEDERATION_META_ENDPOINT,
+    PROPOSE_MODULE_ENDPOINT, RESTORE_CHECKPOINT_ENDPOINT, REVOKE_INVITE_CODE_ENDPOINT,
+    REVOKE_SCOPED_TOKEN_ENDPOINT, ROTATE_KEYS_ENDPOINT, RUN_DKG_ENDPOINT, SAFETY_HALT_ENDPOINT,
+    SAFE_MODE_ENDPOINT, SCHEDULE_UPGRADE_ENDPOINT, SCOPED_TOKENS_ENDPOINT,
+    SET_CONFIG_GEN_CONNECTIONS_ENDPOINT, SET_CONFIG_GEN_PARAMS_ENDPOINT,
+    SET_CONSENSUS_ITEM_LOGGING_ENDPOINT, SET_PASSWORD_ENDPOINT, STALL_DIAGNOSTICS_ENDPOINT,
+    START_CONSENSUS_ENDPOINT, STATUS_ENDPOINT, UPDATE_API_ENDPOINT_ENDPOINT,
 };
+use crate::epoch::ConsensusLimits;
+use crate::fee::FeeSchedule;
+use crate::invite::{CreateInviteCodeRequest, InviteCodeStatus};
+use crate::lifecycle::{
+    ModuleProposalStatus, ModuleUpgrade, ModuleUpgradeStatus, ProposeModuleRequest,
+};
+use crate::meta::FederationMeta;
+use crate::migration::FinalStateAttestation;
 use crate::module::{ApiAuth, ApiRequestErased};
+use crate::net::proxy::Socks5Proxy;
+use crate::rotation::KeyRotationStatus;
+use crate::usage::ApiUsageReport;
 use crate::PeerId;
 
 /// For a guardian to communicate with their server
@@ -31,11 +57,21 @@ pub struct WsAdminClient {
 
 impl WsAdminClient {
     pub fn new(url: SafeUrl) -> Self {
+        Self::new_with_proxy(url, None)
+    }
+
+    /// Creates a client whose connection is tunneled through `proxy`, if given
+    pub fn new_with_proxy(url: SafeUrl, proxy: Option<Socks5Proxy>) -> Self {
         // The peer ids given to the federation API are only useful when connected to
         // multiple peers so errors can be attributed. The admin client has no use for
         // them.
+        let mut inner = WsFederationApi::new(vec![(PeerId(0), url.clone())]);
+        if let Some(proxy) = proxy {
+            inner = inner.with_socks5_proxy(proxy);
+        }
+
         Self {
-            inner: WsFederationApi::new(vec![(PeerId(0), url.clone())]).into(),
+            inner: inner.into(),
             url,
         }
     }
@@ -140,6 +176,13 @@ impl WsAdminClient {
         .await
     }
 
+    /// Shows which round of the DKG each peer completed for each key, also
+    /// while the DKG is running
+    pub async fn dkg_status(&self) -> FederationResult<DkgStatusResponse> {
+        self.request(DKG_STATUS_ENDPOINT, ApiRequestErased::default())
+            .await
+    }
+
     /// After DKG, returns the hash of the consensus config tweaked with our id.
     /// We need to share this with all other peers to complete verification.
     pub async fn get_verify_config_hash(
@@ -178,6 +221,366 @@ impl WsAdminClient {
             .await
     }
 
+    /// Show the health of our P2P connections to the other guardians
+    pub async fn peer_health(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<BTreeMap<PeerId, PeerHealth>> {
+        self.request(
+            PEER_HEALTH_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Show the modules that were halted by the consensus and the local calls
+    /// into modules that panicked
+    pub async fn module_failures(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<BTreeMap<ModuleInstanceId, ModuleFailure>> {
+        self.request(
+            MODULE_FAILURES_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Show why the guardian is in safe mode, if its storage ran full
+    pub async fn safe_mode(&self, auth: ApiAuth) -> FederationResult<Option<StorageFailure>> {
+        self.request(
+            SAFE_MODE_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Show why the guardian halted its consensus, if a safety invariant of the
+    /// federation was violated
+    pub async fn safety_halt(&self, auth: ApiAuth) -> FederationResult<Option<SafetyViolation>> {
+        self.request(
+            SAFETY_HALT_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Resume the halted consensus or shut the guardian down
+    pub async fn override_safety_halt(
+        &self,
+        halt_override: SafetyHaltOverride,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request(
+            OVERRIDE_SAFETY_HALT_ENDPOINT,
+            ApiRequestErased::new(halt_override).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Show the diagnostics gathered since the consensus of the guardian did not
+    /// complete the current session in time, if it is stalled
+    pub async fn stall_diagnostics(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<Option<StallDiagnostics>> {
+        self.request(
+            STALL_DIAGNOSTICS_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Show which consensus items the guardian logs at debug level
+    pub async fn consensus_item_logging(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<ConsensusItemLogging> {
+        self.request(
+            CONSENSUS_ITEM_LOGGING_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Change which consensus items the guardian logs at debug level, until it
+    /// is restarted
+    pub async fn set_consensus_item_logging(
+        &self,
+        logging: ConsensusItemLogging,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request(
+            SET_CONSENSUS_ITEM_LOGGING_ENDPOINT,
+            ApiRequestErased::new(logging).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Sign the attestation that the federation is shutting down, which takes
+    /// effect once a threshold of guardians signed the same attestation
+    pub async fn attest_final_state(
+        &self,
+        attestation: FinalStateAttestation,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request(
+            ATTEST_FINAL_STATE_ENDPOINT,
+            ApiRequestErased::new(attestation).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Sign the announcement of the federation's metadata, which takes effect
+    /// once a threshold of guardians signed the same metadata
+    pub async fn propose_federation_meta(
+        &self,
+        meta: FederationMeta,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request(
+            PROPOSE_FEDERATION_META_ENDPOINT,
+            ApiRequestErased::new(meta).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Propose adding a module instance to the running federation, returns
+    /// the id of the proposal, which counts as our approval
+    pub async fn propose_module(
+        &self,
+        request: ProposeModuleRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<sha256::Hash> {
+        self.request(
+            PROPOSE_MODULE_ENDPOINT,
+            ApiRequestErased::new(request).with_auth(auth),
+        )
+        .await
+    }
+
+    /// The module instances proposed by any guardian that are not active yet
+    pub async fn module_proposals(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<Vec<ModuleProposalStatus>> {
+        self.request(
+            MODULE_PROPOSALS_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Approve a proposed module instance, which is added once all guardians
+    /// approved it
+    pub async fn approve_module(&self, id: sha256::Hash, auth: ApiAuth) -> FederationResult<()> {
+        self.request(
+            APPROVE_MODULE_ENDPOINT,
+            ApiRequestErased::new(id).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Schedule the activation of a module consensus version, which happens
+    /// once all guardians scheduled it
+    pub async fn schedule_upgrade(
+        &self,
+        upgrade: ModuleUpgrade,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request(
+            SCHEDULE_UPGRADE_ENDPOINT,
+            ApiRequestErased::new(upgrade).with_auth(auth),
+        )
+        .await
+    }
+
+    /// List the module upgrades that were not activated yet
+    pub async fn module_upgrades(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<Vec<ModuleUpgradeStatus>> {
+        self.request(
+            MODULE_UPGRADES_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Deal new broadcast and auth keys, which are activated once all
+    /// guardians dealt and confirmed their keys
+    pub async fn rotate_keys(&self, auth: ApiAuth) -> FederationResult<()> {
+        self.request(
+            ROTATE_KEYS_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// The key rotation in progress, if any guardian dealt keys for one
+    pub async fn key_rotation(&self, auth: ApiAuth) -> FederationResult<Option<KeyRotationStatus>> {
+        self.request(
+            KEY_ROTATION_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Move the API of the guardian to the given URL, which its peers and the
+    /// clients connect to once the signed update has been ordered
+    pub async fn update_api_endpoint(&self, url: PeerUrl, auth: ApiAuth) -> FederationResult<()> {
+        self.request(
+            UPDATE_API_ENDPOINT_ENDPOINT,
+            ApiRequestErased::new(url).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Dump the internals of the guardian, which it also writes to its
+    /// diagnostics directory
+    pub async fn dump_diagnostics(&self, auth: ApiAuth) -> FederationResult<DiagnosticsDump> {
+        self.request(
+            DUMP_DIAGNOSTICS_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Checkpoint our config and database under the given name
+    pub async fn create_checkpoint(
+        &self,
+        name: String,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianCheckpoint> {
+        self.request(
+            CREATE_CHECKPOINT_ENDPOINT,
+            ApiRequestErased::new(name).with_auth(auth),
+        )
+        .await
+    }
+
+    /// The keys our database commits conflicted on, `None` unless fedimintd
+    /// runs with the conflict diagnostics enabled, see
+    /// [`crate::db::conflicts`]
+    pub async fn db_conflicts(&self, auth: ApiAuth) -> FederationResult<Option<ConflictReport>> {
+        self.request(
+            DB_CONFLICTS_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// The checkpoints of our config and database, oldest first
+    pub async fn checkpoints(&self, auth: ApiAuth) -> FederationResult<Vec<GuardianCheckpoint>> {
+        self.request(
+            CHECKPOINTS_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Restore the checkpoint the next time fedimintd starts, the guardian
+    /// then catches up with the sessions completed since
+    pub async fn restore_checkpoint(&self, name: String, auth: ApiAuth) -> FederationResult<()> {
+        self.request(
+            RESTORE_CHECKPOINT_ENDPOINT,
+            ApiRequestErased::new(name).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Export our full config as a bundle encrypted with the passphrase, which
+    /// fedimintd can be provisioned with on fresh hardware
+    pub async fn export_config_bundle(
+        &self,
+        passphrase: String,
+        auth: ApiAuth,
+    ) -> FederationResult<ConfigBundle> {
+        self.request(
+            EXPORT_CONFIG_BUNDLE_ENDPOINT,
+            ApiRequestErased::new(passphrase).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Usage of our API by API token over the given number of most recent days
+    pub async fn api_usage(&self, days: u64, auth: ApiAuth) -> FederationResult<ApiUsageReport> {
+        self.request(
+            API_USAGE_ENDPOINT,
+            ApiRequestErased::new(days).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Create an invite code with its own download token, which clients can
+    /// join with until it expires, is used up or revoked
+    pub async fn create_invite_code(
+        &self,
+        request: CreateInviteCodeRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<InviteCode> {
+        self.request(
+            CREATE_INVITE_CODE_ENDPOINT,
+            ApiRequestErased::new(request).with_auth(auth),
+        )
+        .await
+    }
+
+    /// The invite codes created by the admin and the clients that joined with
+    /// them
+    pub async fn invite_codes(&self, auth: ApiAuth) -> FederationResult<Vec<InviteCodeStatus>> {
+        self.request(
+            INVITE_CODES_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Stop clients from joining with the invite code of the download token
+    pub async fn revoke_invite_code(
+        &self,
+        download_token: ClientConfigDownloadToken,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request(
+            REVOKE_INVITE_CODE_ENDPOINT,
+            ApiRequestErased::new(download_token).with_auth(auth),
+        )
+        .await
+    }
+
+    /// Create a token that grants the capabilities on the endpoints of a
+    /// single module, returns the token which can not be retrieved later
+    pub async fn create_scoped_token(
+        &self,
+        request: CreateScopedTokenRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<ApiAuth> {
+        self.request(
+            CREATE_SCOPED_TOKEN_ENDPOINT,
+            ApiRequestErased::new(request).with_auth(auth),
+        )
+        .await
+    }
+
+    /// The module-scoped tokens created by the admin
+    pub async fn scoped_tokens(&self, auth: ApiAuth) -> FederationResult<Vec<ScopedToken>> {
+        self.request(
+            SCOPED_TOKENS_ENDPOINT,
+            ApiRequestErased::default().with_auth(auth),
+        )
+        .await
+    }
+
+    /// Stop accepting the module-scoped token with the given name
+    pub async fn revoke_scoped_token(&self, name: String, auth: ApiAuth) -> FederationResult<()> {
+        self.request(
+            REVOKE_SCOPED_TOKEN_ENDPOINT,
+            ApiRequestErased::new(name).with_auth(auth),
+        )
+        .await
+    }
+
     /// Check auth credentials
     pub async fn auth(&self, auth: ApiAuth) -> FederationResult<()> {
         self.request(AUTH_ENDPOINT, ApiRequestErased::default().with_auth(auth))
@@ -214,6 +617,12 @@ pub struct PeerServerParams {
     pub p2p_url: SafeUrl,
     /// API for secure websocket requests
     pub api_url: SafeUrl,
+    /// Further P2P URLs in order of preference, e.g. IPv6 or onion addresses
+    #[serde(default)]
+    pub p2p_fallback_urls: Vec<SafeUrl>,
+    /// Further API URLs in order of preference
+    #[serde(default)]
+    pub api_fallback_urls: Vec<SafeUrl>,
     /// Name of the peer, used in TLS auth
     pub name: String,
     /// Status of the peer if known
@@ -230,6 +639,12 @@ pub struct ConfigGenParamsConsensus {
     pub meta: BTreeMap<String, String>,
     /// Module init params (also contains local params from us)
     pub modules: ServerModuleConfigGenParamsRegistry,
+    /// Size limits for the consensus items and transactions
+    #[serde(default)]
+    pub limits: ConsensusLimits,
+    /// Fees charged on the inputs and outputs of the modules
+    #[serde(default)]
+    pub fees: FeeSchedule,
 }
 
 /// The config gen params response which includes our peer id
@@ -248,6 +663,39 @@ pub struct ConfigGenParamsRequest {
     pub meta: BTreeMap<String, String>,
     /// Set the params (if leader) or just the local params (if follower)
     pub modules: ServerModuleConfigGenParamsRegistry,
+    /// Size limits for the consensus items and transactions (ignored if
+    /// follower)
+    #[serde(default)]
+    pub limits: ConsensusLimits,
+    /// Fees charged on the inputs and outputs of the modules (ignored if
+    /// follower)
+    #[serde(default)]
+    pub fees: FeeSchedule,
+}
+
+/// Message of the DKG a guardian received from a peer or sent itself, in the
+/// order they are exchanged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DkgRound {
+    /// Public keys exchanged without a DKG, e.g. by the wallet module
+    PublicKey,
+    HashedCommit,
+    Commit,
+    Share,
+    Extract,
+    /// The peer generated all its configs
+    Done,
+}
+
+/// Progress of the DKG ceremony as seen by a guardian
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct DkgStatusResponse {
+    /// Whether the guardian restarted during the ceremony and resumed it
+    pub resumed: bool,
+    /// The last round completed by each peer, by the key generated, named
+    /// `<module instance id>/<key>`
+    pub keys: BTreeMap<String, BTreeMap<PeerId, DkgRound>>,
 }
 
 mod serde_tls_cert {
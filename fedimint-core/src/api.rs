@@ -5,8 +5,8 @@ use std::io::{Cursor, Read};
 use std::ops::Add;
 use std::pin::Pin;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use std::{cmp, result};
 
 use anyhow::{anyhow, ensure};
@@ -14,13 +14,15 @@ use bech32::Variant::Bech32m;
 use bech32::{FromBase32, ToBase32};
 use bitcoin::secp256k1;
 use bitcoin_hashes::sha256;
-use fedimint_core::config::{ClientConfig, ClientConfigResponse, FederationId};
+use fedimint_core::config::{
+    ClientConfig, ClientConfigResponse, ConsensusConfigVersion, FederationId, PeerUrl,
+};
 use fedimint_core::core::{DynOutputOutcome, ModuleInstanceId};
 use fedimint_core::encoding::Encodable;
 use fedimint_core::endpoint_constants::AWAIT_BLOCK_ENDPOINT;
 use fedimint_core::fmt_utils::AbbreviateDebug;
-use fedimint_core::module::SerdeModuleEncoding;
-use fedimint_core::task::{MaybeSend, MaybeSync, RwLock, RwLockWriteGuard};
+use fedimint_core::module::{ModuleHealth, SerdeModuleEncoding};
+use fedimint_core::task::{MaybeSend, MaybeSync, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use fedimint_core::time::now;
 use fedimint_core::{
     apply, async_trait_maybe_send, dyn_newtype_define, ModuleDecoderRegistry, NumPeers, OutPoint,
@@ -43,20 +45,40 @@ use threshold_crypto::{PublicKey, PK_SIZE};
 use tracing::{debug, error, instrument, trace, warn};
 
 use crate::backup::ClientBackupSnapshot;
-use crate::block::Block;
+use crate::block::{
+    AcceptedItemProof, Block, LocatedTransaction, SignedBlock, SignedBlockHeader,
+    TransactionLocation, TransactionReceipt,
+};
 use crate::core::backup::SignedBackupRequest;
-use crate::core::{Decoder, OutputOutcome};
+use crate::core::{Decoder, ModuleKind, OutputOutcome};
 use crate::endpoint_constants::{
-    AWAIT_OUTPUT_OUTCOME_ENDPOINT, BACKUP_ENDPOINT, CONFIG_ENDPOINT, CONFIG_HASH_ENDPOINT,
-    FETCH_BLOCK_COUNT_ENDPOINT, RECOVER_ENDPOINT, TRANSACTION_ENDPOINT, VERSION_ENDPOINT,
+    API_ENDPOINT_UPDATES_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT,
+    AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT, AWAIT_TRANSACTION_OUTCOME_ENDPOINT,
+    AWAIT_TRANSACTION_PROOF_ENDPOINT, AWAIT_TRANSACTION_RECEIPT_ENDPOINT, BACKUP_ENDPOINT,
+    CONFIG_ENDPOINT, CONFIG_HASH_ENDPOINT, CONSENSUS_CONFIG_VERSIONS_ENDPOINT,
+    FEDERATION_META_ENDPOINT, FETCH_BLOCK_COUNT_ENDPOINT, FINAL_STATE_ATTESTATION_ENDPOINT,
+    JOIN_ENDPOINT, KEY_EPOCHS_ENDPOINT, RECOVER_ENDPOINT, SESSION_TRANSACTIONS_ENDPOINT,
+    SIGNED_BLOCKS_ENDPOINT, STATE_PROOF_ENDPOINT, TRANSACTION_DEPENDENCIES_ENDPOINT,
+    TRANSACTION_ENDPOINT, TRANSACTION_LOCATION_ENDPOINT, VERSION_ENDPOINT,
     WAIT_TRANSACTION_ENDPOINT,
 };
+use crate::endpoint_update::ApiEndpointUpdate;
+use crate::invite::{ClientJoinId, JoinRequest};
+use crate::meta::SignedFederationMeta;
+use crate::migration::SignedFinalStateAttestation;
 use crate::module::{ApiRequestErased, ApiVersion, SupportedApiVersionsSummary};
+use crate::net::addresses::PeerAddresses;
+use crate::net::proxy::Socks5Proxy;
 use crate::query::{
-    DiscoverApiVersionSet, FilterMap, QueryStep, QueryStrategy, ThresholdConsensus,
+    DiscoverApiVersionSet, EndpointClass, FilterMap, PeerLatencyTracker, QueryPolicies,
+    QueryPolicy, QueryStep, QueryStrategy, ThresholdConsensus, TrustedPeer, UnionResponses,
     UnionResponsesSingle,
 };
-use crate::transaction::{SerdeTransaction, Transaction};
+use crate::rotation::KeyEpoch;
+use crate::state_proof::StateProof;
+use crate::transaction::{
+    SerdeTransaction, Transaction, TransactionDependencies, TransactionOutcome,
+};
 use crate::util::SafeUrl;
 use crate::{serde_as_encodable_hex, task};
 
@@ -89,12 +111,23 @@ impl PeerError {
                 JsonRpcError::MaxSlotsExceeded => true,
                 JsonRpcError::RequestTimeout => true,
                 JsonRpcError::RestartNeeded(_) => true,
-                JsonRpcError::Call(e) => e.code() == 404,
+                JsonRpcError::Call(e) => e.code() == 404 || e.code() == FEDERATION_BUSY_CODE,
                 _ => false,
             },
             PeerError::InvalidResponse(_) => false,
         }
     }
+
+    /// When a busy guardian suggested to retry the request
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            PeerError::Rpc(JsonRpcError::Call(e)) if e.code() == FEDERATION_BUSY_CODE => e
+                .data()
+                .and_then(|data| serde_json::from_str::<FederationBusy>(data.get()).ok())
+                .map(|busy| busy.retry_after),
+            _ => None,
+        }
+    }
 }
 
 /// An API request error when calling an entire federation
@@ -125,9 +158,22 @@ impl Display for FederationError {
 }
 
 impl FederationError {
+    pub fn general(error: impl Into<anyhow::Error>) -> FederationError {
+        FederationError {
+            general: Some(error.into()),
+            peers: Default::default(),
+        }
+    }
+
     pub fn is_retryable(&self) -> bool {
         self.peers.iter().any(|(_, e)| e.is_retryable())
     }
+
+    /// The earliest retry suggested by a busy guardian, since any guardian
+    /// with room for the request suffices
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.peers.values().filter_map(PeerError::retry_after).min()
+    }
 }
 
 type OutputOutcomeResult<O> = result::Result<O, OutputOutcomeError>;
@@ -160,6 +206,18 @@ pub trait IFederationApi: Debug + MaybeSend + MaybeSync {
     /// API call to the federation would be inconvenient.
     fn all_peers(&self) -> &BTreeSet<PeerId>;
 
+    /// All peers ordered by how fast and reliable they have been historically,
+    /// best first
+    fn ranked_peers(&self) -> Vec<PeerId> {
+        self.all_peers().iter().copied().collect()
+    }
+
+    /// How the responses of the global endpoints of `class` are verified, see
+    /// [`FederationApiExt::request_with_policy`]
+    fn query_policy(&self, _class: EndpointClass) -> QueryPolicy {
+        QueryPolicy::Threshold
+    }
+
     fn with_module(&self, id: ModuleInstanceId) -> DynModuleApi;
 
     /// Make request to a specific federation peer by `peer_id`
@@ -193,18 +251,33 @@ pub trait FederationApiExt: IFederationApi {
         params: ApiRequestErased,
     ) -> FederationResult<FedRet> {
         let timeout = strategy.request_timeout();
+        let stagger = strategy.request_stagger();
+        let request_peers = strategy.request_peers();
 
         #[cfg(not(target_family = "wasm"))]
         let mut futures = FuturesUnordered::<Pin<Box<dyn Future<Output = _> + Send>>>::new();
         #[cfg(target_family = "wasm")]
         let mut futures = FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
 
-        let peers = self.all_peers();
+        for (rank, peer_id) in self
+            .ranked_peers()
+            .into_iter()
+            .filter(|peer_id| {
+                request_peers
+                    .as_ref()
+                    .map_or(true, |peers| peers.contains(peer_id))
+            })
+            .enumerate()
+        {
+            let method = &method;
+            let params = &params;
+            futures.push(Box::pin(async move {
+                if let Some(stagger) = stagger {
+                    task::sleep(stagger * rank as u32).await;
+                }
 
-        for peer_id in peers {
-            futures.push(Box::pin(async {
                 let request = async {
-                    self.request_raw(*peer_id, &method, &[params.to_json()])
+                    self.request_raw(peer_id, method, &[params.to_json()])
                         .await
                         .map(AbbreviateDebug)
                 };
@@ -219,7 +292,7 @@ pub trait FederationApiExt: IFederationApi {
                 };
 
                 PeerResponse {
-                    peer: *peer_id,
+                    peer: peer_id,
                     result,
                 }
             }));
@@ -308,6 +381,40 @@ pub trait FederationApiExt: IFederationApi {
         )
         .await
     }
+
+    /// Make a request to the federation, accepting the response as required
+    /// by the [`QueryPolicy`] configured for the `class` of the endpoint
+    async fn request_with_policy<Ret>(
+        &self,
+        class: EndpointClass,
+        method: String,
+        params: ApiRequestErased,
+    ) -> FederationResult<Ret>
+    where
+        Ret: serde::de::DeserializeOwned + Eq + Debug + Clone + MaybeSend,
+    {
+        match self.query_policy(class) {
+            QueryPolicy::TrustPeer(peer_id) => {
+                if !self.all_peers().contains(&peer_id) {
+                    return Err(FederationError::general(anyhow!(
+                        "Trusted peer {peer_id} is not a guardian of the federation"
+                    )));
+                }
+
+                self.request_with_strategy(TrustedPeer::new(peer_id), method, params)
+                    .await
+            }
+            QueryPolicy::Threshold => self.request_current_consensus(method, params).await,
+            QueryPolicy::VerifyAll => {
+                self.request_with_strategy(
+                    ThresholdConsensus::full_participation(self.all_peers().total()),
+                    method,
+                    params,
+                )
+                .await
+            }
+        }
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -348,8 +455,77 @@ pub trait GlobalFederationApi {
 
     async fn fetch_block_count(&self) -> FederationResult<u64>;
 
+    /// Fetches the header of a signed block from the first peer that returns
+    /// a header with a valid threshold signature of the atomic broadcast
+    async fn await_signed_block_header(
+        &self,
+        block_index: u64,
+        broadcast_public_keys: &BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+    ) -> FederationResult<SignedBlockHeader>;
+
+    /// Fetches a merkle proof that the transaction has been included in a
+    /// signed block, which still has to be verified against the blocks header
+    async fn await_transaction_proof(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<AcceptedItemProof>;
+
+    /// Fetches a receipt that the transaction has been accepted, whose header
+    /// carries a valid threshold signature of `broadcast_public_keys`. The
+    /// inclusion proof still has to be verified against the transaction with
+    /// [`TransactionReceipt::verify`].
+    async fn await_transaction_receipt(
+        &self,
+        txid: TransactionId,
+        broadcast_public_keys: &BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+    ) -> FederationResult<TransactionReceipt>;
+
+    /// Fetches a proof that the database entry with the given key was part of
+    /// the latest signed snapshot of the consensus state, which still has to
+    /// be verified against the header of [`AcceptedItemProof::session_index`]
+    /// of its commitment proof
+    async fn state_proof(&self, key: Vec<u8>) -> FederationResult<StateProof>;
+
+    /// Fetches the signed blocks of a range of sessions, at most
+    /// [`MAX_SESSION_PAGE_SIZE`] at a time
+    async fn fetch_signed_blocks(
+        &self,
+        range: SessionRange,
+        decoders: &ModuleDecoderRegistry,
+    ) -> FederationResult<SnapshotResponse<Vec<SignedBlock>>>;
+
+    /// Fetches the transactions accepted in a range of sessions, at most
+    /// [`MAX_SESSION_PAGE_SIZE`] sessions at a time
+    async fn fetch_session_transactions(
+        &self,
+        range: SessionRange,
+        decoders: &ModuleDecoderRegistry,
+    ) -> FederationResult<SnapshotResponse<Vec<LocatedTransaction>>>;
+
+    /// Looks up where in the consensus history the transaction was accepted,
+    /// returns `None` if it has not been included in a signed block yet
+    async fn fetch_transaction_location(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<SnapshotResponse<Option<TransactionLocation>>>;
+
+    /// Fetches the accepted transactions the transaction spends outputs of and
+    /// the accepted transactions spending its outputs, returns `None` if it
+    /// has not been accepted
+    async fn fetch_transaction_dependencies(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<Option<TransactionDependencies>>;
+
     async fn await_transaction(&self, txid: TransactionId) -> FederationResult<TransactionId>;
 
+    /// Waits a bounded amount of time for the transaction to be accepted or
+    /// rejected by consensus, otherwise it is reported as pending
+    async fn await_transaction_outcome(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<TransactionOutcome>;
+
     async fn await_output_outcome<R>(
         &self,
         outpoint: OutPoint,
@@ -362,9 +538,45 @@ pub trait GlobalFederationApi {
     /// Fetch client configuration info only if verified against a federation id
     async fn download_client_config(&self, info: &InviteCode) -> FederationResult<ClientConfig>;
 
+    /// Downloads the client config like [`Self::download_client_config`],
+    /// letting the guardian record that the client joined with the invite code
+    async fn join_federation(
+        &self,
+        info: &InviteCode,
+        client_id: ClientJoinId,
+    ) -> FederationResult<ClientConfig>;
+
     /// Fetches the server consensus hash if enough peers agree on it
     async fn consensus_config_hash(&self) -> FederationResult<sha256::Hash>;
 
+    /// Fetches the broadcast public keys that were replaced by key rotations
+    /// if enough peers agree on them, which are needed to verify the blocks
+    /// signed before the rotations
+    async fn key_epochs(&self) -> FederationResult<Vec<KeyEpoch>>;
+
+    /// Fetches every version of the consensus config the federation ran, oldest
+    /// first, if enough peers agree on them, which are needed to verify the
+    /// blocks signed under earlier configs
+    async fn consensus_config_versions(&self) -> FederationResult<Vec<ConsensusConfigVersion>>;
+
+    /// Fetches the ordered updates of the guardians' API endpoints, which are
+    /// signed by the guardians themselves and have to be verified
+    async fn api_endpoint_updates(&self) -> FederationResult<Vec<ApiEndpointUpdate>>;
+
+    /// Fetches the attestation that the federation is shutting down, if any
+    /// guardian serves one that was signed by the given federation
+    async fn fetch_final_state_attestation(
+        &self,
+        federation_id: &FederationId,
+    ) -> FederationResult<Option<SignedFinalStateAttestation>>;
+
+    /// Fetches the federation's metadata with the highest version that any
+    /// guardian serves signed by the given federation
+    async fn fetch_federation_meta(
+        &self,
+        federation_id: &FederationId,
+    ) -> FederationResult<Option<SignedFederationMeta>>;
+
     async fn upload_backup(&self, request: &SignedBackupRequest) -> FederationResult<()>;
 
     async fn download_backup(
@@ -407,7 +619,8 @@ where
 {
     /// Submit a transaction for inclusion
     async fn submit_transaction(&self, tx: Transaction) -> FederationResult<TransactionId> {
-        self.request_current_consensus(
+        self.request_with_policy(
+            EndpointClass::Submission,
             TRANSACTION_ENDPOINT.to_owned(),
             ApiRequestErased::new(&SerdeTransaction::from(&tx)),
         )
@@ -419,7 +632,8 @@ where
         block_index: u64,
         decoders: &ModuleDecoderRegistry,
     ) -> anyhow::Result<Block> {
-        self.request_current_consensus::<SerdeModuleEncoding<Block>>(
+        self.request_with_policy::<SerdeModuleEncoding<Block>>(
+            EndpointClass::History,
             AWAIT_BLOCK_ENDPOINT.to_string(),
             ApiRequestErased::new(block_index),
         )
@@ -429,21 +643,196 @@ where
     }
 
     async fn fetch_block_count(&self) -> FederationResult<u64> {
-        self.request_current_consensus(
+        self.request_with_policy(
+            EndpointClass::History,
             FETCH_BLOCK_COUNT_ENDPOINT.to_owned(),
             ApiRequestErased::default(),
         )
         .await
     }
 
+    async fn await_signed_block_header(
+        &self,
+        block_index: u64,
+        broadcast_public_keys: &BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+    ) -> FederationResult<SignedBlockHeader> {
+        let broadcast_public_keys = broadcast_public_keys.clone();
+
+        self.request_with_strategy(
+            FilterMap::new(
+                move |response: SerdeModuleEncoding<SignedBlockHeader>| {
+                    let header = response
+                        .try_into_inner(&ModuleDecoderRegistry::default())
+                        .map_err(|e| anyhow!(e.to_string()))?;
+
+                    ensure!(header.index() == block_index, "Header has the wrong index");
+                    ensure!(
+                        header.verify(&broadcast_public_keys),
+                        "Invalid threshold signature"
+                    );
+
+                    Ok(header)
+                },
+                self.all_peers().total(),
+            ),
+            AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT.to_owned(),
+            ApiRequestErased::new(block_index),
+        )
+        .await
+    }
+
+    async fn await_transaction_proof(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<AcceptedItemProof> {
+        self.request_with_policy::<SerdeModuleEncoding<AcceptedItemProof>>(
+            EndpointClass::History,
+            AWAIT_TRANSACTION_PROOF_ENDPOINT.to_owned(),
+            ApiRequestErased::new(txid),
+        )
+        .await?
+        .try_into_inner(&ModuleDecoderRegistry::default())
+        .map_err(|e| FederationError::general(anyhow!(e.to_string())))
+    }
+
+    async fn await_transaction_receipt(
+        &self,
+        txid: TransactionId,
+        broadcast_public_keys: &BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+    ) -> FederationResult<TransactionReceipt> {
+        let broadcast_public_keys = broadcast_public_keys.clone();
+
+        self.request_with_strategy(
+            FilterMap::new(
+                move |response: SerdeModuleEncoding<TransactionReceipt>| {
+                    let receipt = response
+                        .try_into_inner(&ModuleDecoderRegistry::default())
+                        .map_err(|e| anyhow!(e.to_string()))?;
+
+                    ensure!(receipt.txid == txid, "Receipt is for the wrong transaction");
+                    ensure!(
+                        receipt.signed_header.index() == receipt.proof.session_index,
+                        "Header is not for the block of the proof"
+                    );
+                    ensure!(
+                        receipt.signed_header.verify(&broadcast_public_keys),
+                        "Invalid threshold signature"
+                    );
+
+                    Ok(receipt)
+                },
+                self.all_peers().total(),
+            ),
+            AWAIT_TRANSACTION_RECEIPT_ENDPOINT.to_owned(),
+            ApiRequestErased::new(txid),
+        )
+        .await
+    }
+
+    async fn state_proof(&self, key: Vec<u8>) -> FederationResult<StateProof> {
+        self.request_with_policy::<SerdeModuleEncoding<StateProof>>(
+            EndpointClass::History,
+            STATE_PROOF_ENDPOINT.to_owned(),
+            ApiRequestErased::new(key),
+        )
+        .await?
+        .try_into_inner(&ModuleDecoderRegistry::default())
+        .map_err(|e| FederationError::general(anyhow!(e.to_string())))
+    }
+
+    async fn fetch_signed_blocks(
+        &self,
+        range: SessionRange,
+        decoders: &ModuleDecoderRegistry,
+    ) -> FederationResult<SnapshotResponse<Vec<SignedBlock>>> {
+        let response: SnapshotResponse<SerdeModuleEncoding<Vec<SignedBlock>>> = self
+            .request_with_policy(
+                EndpointClass::History,
+                SIGNED_BLOCKS_ENDPOINT.to_owned(),
+                ApiRequestErased::new(range),
+            )
+            .await?;
+
+        let signed_blocks = response
+            .value
+            .try_into_inner(decoders)
+            .map_err(|e| FederationError::general(anyhow!(e.to_string())))?;
+
+        Ok(SnapshotResponse {
+            value: signed_blocks,
+            snapshot: response.snapshot,
+        })
+    }
+
+    async fn fetch_session_transactions(
+        &self,
+        range: SessionRange,
+        decoders: &ModuleDecoderRegistry,
+    ) -> FederationResult<SnapshotResponse<Vec<LocatedTransaction>>> {
+        let response: SnapshotResponse<SerdeModuleEncoding<Vec<LocatedTransaction>>> = self
+            .request_with_policy(
+                EndpointClass::History,
+                SESSION_TRANSACTIONS_ENDPOINT.to_owned(),
+                ApiRequestErased::new(range),
+            )
+            .await?;
+
+        let transactions = response
+            .value
+            .try_into_inner(decoders)
+            .map_err(|e| FederationError::general(anyhow!(e.to_string())))?;
+
+        Ok(SnapshotResponse {
+            value: transactions,
+            snapshot: response.snapshot,
+        })
+    }
+
+    async fn fetch_transaction_location(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<SnapshotResponse<Option<TransactionLocation>>> {
+        self.request_with_policy(
+            EndpointClass::History,
+            TRANSACTION_LOCATION_ENDPOINT.to_owned(),
+            ApiRequestErased::new(txid),
+        )
+        .await
+    }
+
+    async fn fetch_transaction_dependencies(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<Option<TransactionDependencies>> {
+        self.request_with_policy(
+            EndpointClass::History,
+            TRANSACTION_DEPENDENCIES_ENDPOINT.to_owned(),
+            ApiRequestErased::new(txid),
+        )
+        .await
+    }
+
     async fn await_transaction(&self, txid: TransactionId) -> FederationResult<TransactionId> {
-        self.request_current_consensus(
+        self.request_with_policy(
+            EndpointClass::Outcome,
             WAIT_TRANSACTION_ENDPOINT.to_owned(),
             ApiRequestErased::new(txid),
         )
         .await
     }
 
+    async fn await_transaction_outcome(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<TransactionOutcome> {
+        self.request_with_policy(
+            EndpointClass::Outcome,
+            AWAIT_TRANSACTION_OUTCOME_ENDPOINT.to_owned(),
+            ApiRequestErased::new(txid),
+        )
+        .await
+    }
+
     // TODO should become part of the API
     async fn await_output_outcome<R>(
         &self,
@@ -456,7 +845,8 @@ where
     {
         fedimint_core::task::timeout(timeout, async move {
             let outcome: SerdeOutputOutcome = self
-                .request_current_consensus(
+                .request_with_policy(
+                    EndpointClass::Outcome,
                     AWAIT_OUTPUT_OUTCOME_ENDPOINT.to_owned(),
                     ApiRequestErased::new(outpoint),
                 )
@@ -493,14 +883,120 @@ where
         .map(|cfg: ClientConfigResponse| cfg.client_config)
     }
 
+    async fn join_federation(
+        &self,
+        info: &InviteCode,
+        client_id: ClientJoinId,
+    ) -> FederationResult<ClientConfig> {
+        let id = info.id;
+        let qs = FilterMap::new(
+            move |config: ClientConfigResponse| match id
+                .0
+                .verify(&config.signature.0, config.client_config.consensus_hash())
+            {
+                true => Ok(config),
+                false => Err(anyhow!("Invalid signature")),
+            },
+            self.all_peers().total(),
+        )
+        .with_request_timeout(Duration::from_secs(5));
+
+        self.request_with_strategy(
+            qs,
+            JOIN_ENDPOINT.to_owned(),
+            ApiRequestErased::new(JoinRequest {
+                invite_code: info.clone(),
+                client_id,
+            }),
+        )
+        .await
+        .map(|cfg: ClientConfigResponse| cfg.client_config)
+    }
+
     async fn consensus_config_hash(&self) -> FederationResult<sha256::Hash> {
-        self.request_current_consensus(CONFIG_HASH_ENDPOINT.to_owned(), ApiRequestErased::default())
-            .await
+        self.request_with_policy(
+            EndpointClass::Config,
+            CONFIG_HASH_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn key_epochs(&self) -> FederationResult<Vec<KeyEpoch>> {
+        self.request_with_policy(
+            EndpointClass::Config,
+            KEY_EPOCHS_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn consensus_config_versions(&self) -> FederationResult<Vec<ConsensusConfigVersion>> {
+        self.request_with_policy(
+            EndpointClass::Config,
+            CONSENSUS_CONFIG_VERSIONS_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn api_endpoint_updates(&self) -> FederationResult<Vec<ApiEndpointUpdate>> {
+        // every update is signed by the guardian it moves, so we do not need the
+        // guardians to agree on them
+        self.request_with_strategy(
+            UnionResponses::new(self.all_peers().total()),
+            API_ENDPOINT_UPDATES_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn fetch_final_state_attestation(
+        &self,
+        federation_id: &FederationId,
+    ) -> FederationResult<Option<SignedFinalStateAttestation>> {
+        // the attestation is signed by the federation, so a single guardian serving
+        // it is enough
+        Ok(self
+            .request_with_strategy(
+                UnionResponsesSingle::<Option<SignedFinalStateAttestation>>::new(
+                    self.all_peers().total(),
+                ),
+                FINAL_STATE_ATTESTATION_ENDPOINT.to_owned(),
+                ApiRequestErased::default(),
+            )
+            .await?
+            .into_iter()
+            .flatten()
+            .find(|attestation| attestation.verify(federation_id).is_ok()))
+    }
+
+    async fn fetch_federation_meta(
+        &self,
+        federation_id: &FederationId,
+    ) -> FederationResult<Option<SignedFederationMeta>> {
+        // the metadata is signed by the federation, but a guardian may serve an outdated
+        // version
+        Ok(self
+            .request_with_strategy(
+                UnionResponsesSingle::<Option<SignedFederationMeta>>::new(self.all_peers().total()),
+                FEDERATION_META_ENDPOINT.to_owned(),
+                ApiRequestErased::default(),
+            )
+            .await?
+            .into_iter()
+            .flatten()
+            .filter(|signed| signed.verify(federation_id).is_ok())
+            .max_by_key(|signed| signed.meta.version))
     }
 
     async fn upload_backup(&self, request: &SignedBackupRequest) -> FederationResult<()> {
-        self.request_current_consensus(BACKUP_ENDPOINT.to_owned(), ApiRequestErased::new(request))
-            .await
+        self.request_with_policy(
+            EndpointClass::Submission,
+            BACKUP_ENDPOINT.to_owned(),
+            ApiRequestErased::new(request),
+        )
+        .await
     }
 
     async fn download_backup(
@@ -545,19 +1041,22 @@ pub struct WsFederationApi<C = WsClient> {
     peer_ids: BTreeSet<PeerId>,
     peers: Arc<Vec<FederationPeer<C>>>,
     module_id: Option<ModuleInstanceId>,
+    latency: PeerLatencyTracker,
+    policies: QueryPolicies,
 }
 
 #[derive(Debug)]
 struct FederationPeer<C> {
-    url: SafeUrl,
+    addresses: Mutex<PeerAddresses>,
     peer_id: PeerId,
     client: RwLock<Option<C>>,
+    proxy: Option<Socks5Proxy>,
 }
 
 /// Information required for client to construct [`WsFederationApi`] instance
 ///
 /// Can be used to download the configs and bootstrap a client
-#[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Encodable, Decodable)]
 pub struct InviteCode {
     /// URL to reach an API that we can download configs from
     pub url: SafeUrl,
@@ -573,7 +1072,7 @@ pub struct InviteCode {
 const CONFIG_DOWNLOAD_TOKEN_BYTES: usize = 12;
 
 /// Allows a client to download the config
-#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, PartialOrd, Ord)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, PartialOrd, Ord)]
 pub struct ClientConfigDownloadToken(pub [u8; CONFIG_DOWNLOAD_TOKEN_BYTES]);
 
 serde_as_encodable_hex!(ClientConfigDownloadToken);
@@ -671,11 +1170,21 @@ impl<C: JsonRpcClient + Debug + 'static> IFederationApi for WsFederationApi<C> {
         &self.peer_ids
     }
 
+    fn ranked_peers(&self) -> Vec<PeerId> {
+        self.latency.rank_peers(&self.peer_ids)
+    }
+
+    fn query_policy(&self, class: EndpointClass) -> QueryPolicy {
+        self.policies.get(class)
+    }
+
     fn with_module(&self, id: ModuleInstanceId) -> DynModuleApi {
         WsFederationApi {
             peer_ids: self.peer_ids.clone(),
             peers: self.peers.clone(),
             module_id: Some(id),
+            latency: self.latency.clone(),
+            policies: self.policies.clone(),
         }
         .into()
     }
@@ -696,19 +1205,36 @@ impl<C: JsonRpcClient + Debug + 'static> IFederationApi for WsFederationApi<C> {
             None => method.to_string(),
             Some(id) => format!("module_{id}_{method}"),
         };
-        peer.request(&method, params).await
+
+        let start = now();
+        let result = peer.request(&method, params).await;
+        self.record_latency(peer_id, &method, start, &result);
+
+        result
     }
 }
 
 #[apply(async_trait_maybe_send!)]
 pub trait JsonRpcClient: ClientT + Sized + MaybeSend + MaybeSync {
-    async fn connect(url: &SafeUrl) -> result::Result<Self, JsonRpcError>;
+    /// Connects to `url`, tunneled through the SOCKS5 `proxy` if one is given
+    async fn connect(url: &SafeUrl, proxy: Option<&SafeUrl>) -> result::Result<Self, JsonRpcError>;
     fn is_connected(&self) -> bool;
 }
 
 #[apply(async_trait_maybe_send!)]
 impl JsonRpcClient for WsClient {
-    async fn connect(url: &SafeUrl) -> result::Result<Self, JsonRpcError> {
+    async fn connect(url: &SafeUrl, proxy: Option<&SafeUrl>) -> result::Result<Self, JsonRpcError> {
+        #[cfg(not(target_family = "wasm"))]
+        if let Some(proxy) = proxy {
+            let (sender, receiver) = crate::net::socks::connect_ws_socks5(url, proxy)
+                .await
+                .map_err(JsonRpcError::Transport)?;
+
+            return Ok(jsonrpsee_core::client::ClientBuilder::default()
+                .max_concurrent_requests(u16::MAX as usize)
+                .build_with_tokio(sender, receiver));
+        }
+
         #[cfg(not(target_family = "wasm"))]
         return WsClientBuilder::default()
             .use_webpki_rustls()
@@ -716,6 +1242,14 @@ impl JsonRpcClient for WsClient {
             .build(url_to_string_with_default_port(url)) // Hack for default ports, see fn docs
             .await;
 
+        // browsers do not let us open raw connections to a proxy
+        #[cfg(target_family = "wasm")]
+        if proxy.is_some() {
+            return Err(JsonRpcError::Custom(
+                "SOCKS5 proxies are not supported in the browser".to_string(),
+            ));
+        }
+
         #[cfg(target_family = "wasm")]
         WsClientBuilder::default()
             .max_concurrent_requests(u16::MAX as usize)
@@ -731,21 +1265,24 @@ impl JsonRpcClient for WsClient {
 impl WsFederationApi<WsClient> {
     /// Creates a new API client
     pub fn new(peers: Vec<(PeerId, SafeUrl)>) -> Self {
-        Self::new_with_client(peers)
+        Self::new_with_client(peers.into_iter().map(|(id, url)| (id, vec![url])).collect())
     }
 
-    /// Creates a new API client from a client config
-    pub fn from_config(config: &ClientConfig) -> Self {
-        Self::new(
-            config
-                .global
-                .api_endpoints
+    /// Creates a new API client that tries all the URLs the peers advertise
+    pub fn from_endpoints(endpoints: &BTreeMap<PeerId, PeerUrl>) -> Self {
+        Self::new_with_client(
+            endpoints
                 .iter()
-                .map(|(id, peer)| (*id, peer.url.clone()))
+                .map(|(id, peer)| (*id, peer.urls()))
                 .collect(),
         )
     }
 
+    /// Creates a new API client from a client config
+    pub fn from_config(config: &ClientConfig) -> Self {
+        Self::from_endpoints(&config.global.api_endpoints)
+    }
+
     /// Creates a new API client from a invite code, assumes they are in peer
     /// id order
     pub fn from_invite_code(info: &[InviteCode]) -> Self {
@@ -763,33 +1300,103 @@ impl<C> WsFederationApi<C> {
         self.peers.iter().map(|peer| peer.peer_id).collect()
     }
 
-    /// Creates a new API client
-    pub fn new_with_client(peers: Vec<(PeerId, SafeUrl)>) -> Self {
+    /// Uses `latency` to record request latencies and rank peers, e.g. to
+    /// share a tracker whose histories are persisted in a database
+    pub fn with_latency_tracker(mut self, latency: PeerLatencyTracker) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn latency_tracker(&self) -> &PeerLatencyTracker {
+        &self.latency
+    }
+
+    /// Uses `policies` to decide how many guardians have to agree on the
+    /// responses of each class of global endpoints
+    pub fn with_query_policies(mut self, policies: QueryPolicies) -> Self {
+        self.policies = policies;
+        self
+    }
+
+    /// Tunnels the connections to the peers through the SOCKS5 `proxy`, drops
+    /// the connections opened before
+    pub fn with_socks5_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.peers = Arc::new(
+            self.peers
+                .iter()
+                .map(|peer| FederationPeer {
+                    addresses: Mutex::new(peer.addresses.lock().expect("lock poisoned").clone()),
+                    peer_id: peer.peer_id,
+                    client: RwLock::new(None),
+                    proxy: Some(proxy.clone()),
+                })
+                .collect(),
+        );
+        self
+    }
+
+    fn record_latency<T>(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        start: SystemTime,
+        result: &JsonRpcResult<T>,
+    ) {
+        match result {
+            // long-polling endpoints only return once the awaited event occurred, so
+            // their latency says nothing about the peer
+            Ok(_) | Err(JsonRpcError::Call(_)) if is_long_polling(method) => {}
+            // an error returned by the peer still means it responded to us
+            Ok(_) | Err(JsonRpcError::Call(_)) => self
+                .latency
+                .record(peer_id, Ok(now().duration_since(start).unwrap_or_default())),
+            Err(_) => self.latency.record(peer_id, Err(())),
+        }
+    }
+
+    /// Creates a new API client from the URLs of each peer in order of
+    /// preference
+    pub fn new_with_client(peers: Vec<(PeerId, Vec<SafeUrl>)>) -> Self {
         WsFederationApi {
             peer_ids: peers.iter().map(|m| m.0).collect(),
             peers: Arc::new(
                 peers
                     .into_iter()
-                    .map(|(peer_id, url)| {
-                        assert!(
-                            url.port_or_known_default().is_some(),
-                            "API client requires a port"
-                        );
-                        assert!(url.host().is_some(), "API client requires a target host");
+                    .map(|(peer_id, urls)| {
+                        for url in &urls {
+                            assert!(
+                                url.port_or_known_default().is_some(),
+                                "API client requires a port"
+                            );
+                            assert!(url.host().is_some(), "API client requires a target host");
+                        }
 
                         FederationPeer {
                             peer_id,
-                            url,
+                            addresses: Mutex::new(PeerAddresses::new(urls)),
                             client: RwLock::new(None),
+                            proxy: None,
                         }
                     })
                     .collect(),
             ),
             module_id: None,
+            latency: PeerLatencyTracker::default(),
+            policies: QueryPolicies::default(),
         }
     }
 }
 
+fn is_long_polling(method: &str) -> bool {
+    // module endpoints are prefixed with `module_{id}_`
+    let method = method
+        .strip_prefix("module_")
+        .and_then(|method| method.split_once('_'))
+        .map_or(method, |(_, method)| method);
+
+    method.starts_with("await_") || method.starts_with("wait_")
+}
+
 #[derive(Debug)]
 pub struct PeerResponse<R> {
     pub peer: PeerId,
@@ -799,53 +1406,87 @@ pub struct PeerResponse<R> {
 impl<C: JsonRpcClient> FederationPeer<C> {
     #[instrument(level = "trace", fields(peer = %self.peer_id, %method), skip_all)]
     pub async fn request(&self, method: &str, params: &[Value]) -> JsonRpcResult<Value> {
+        let client = self.connected_client().await?;
+
+        client
+            .as_ref()
+            .expect("Client is connected")
+            .request::<_, _>(method, params)
+            .await
+    }
+
+    /// Returns our client for the peer, reconnecting if it is not connected
+    async fn connected_client(&self) -> JsonRpcResult<RwLockReadGuard<'_, Option<C>>> {
         let rclient = self.client.read().await;
-        match &*rclient {
-            Some(client) if client.is_connected() => {
-                return client.request::<_, _>(method, params).await;
-            }
-            _ => {}
-        };
+
+        if matches!(&*rclient, Some(client) if client.is_connected()) {
+            return Ok(rclient);
+        }
 
         debug!("web socket not connected, reconnecting");
 
         drop(rclient);
         let mut wclient = self.client.write().await;
-        Ok(match &*wclient {
-            Some(client) if client.is_connected() => {
-                // other task has already connected it
-                let rclient = RwLockWriteGuard::downgrade(wclient);
-                rclient
-                    .as_ref()
-                    .unwrap()
-                    .request::<_, _>(method, params)
-                    .await?
+
+        // write lock is acquired before creating a new client so only one task will
+        // try to create a new client, another task might have already connected it
+        if !matches!(&*wclient, Some(client) if client.is_connected()) {
+            match self.connect().await {
+                Ok(client) => *wclient = Some(client),
+                Err(err) => {
+                    // Warn instead of Error because we will probably retry connecting later
+                    warn!(
+                        target: LOG_NET_API,
+                        peer_id = %self.peer_id,
+                        %err, "Unable to connect to peer");
+                    return Err(err);
+                }
             }
-            _ => {
-                // write lock is acquired before creating a new client
-                // so only one task will try to create a new client
-                match C::connect(&self.url).await {
-                    Ok(client) => {
-                        *wclient = Some(client);
-                        // drop the write lock before making the request
-                        let rclient = RwLockWriteGuard::downgrade(wclient);
-                        rclient
-                            .as_ref()
-                            .unwrap()
-                            .request::<_, _>(method, params)
-                            .await?
-                    }
-                    Err(err) => {
-                        // Warn instead of Error because we will probably retry connecting later
-                        warn!(
-                            target: LOG_NET_API,
-                            peer_id = %self.peer_id,
-                            %err, "Unable to connect to peer");
-                        return Err(err)?;
-                    }
+        }
+
+        // drop the write lock before making the request
+        Ok(RwLockWriteGuard::downgrade(wclient))
+    }
+
+    /// Tries the URLs of the peer in the order of their health and preference
+    /// until a connection succeeds
+    async fn connect(&self) -> JsonRpcResult<C> {
+        let urls = self
+            .addresses
+            .lock()
+            .expect("lock poisoned")
+            .connection_order(now());
+        let mut last_err = None;
+
+        for url in urls {
+            let proxy = self.proxy.as_ref().and_then(|proxy| proxy.proxy_for(&url));
+
+            match C::connect(&url, proxy).await {
+                Ok(client) => {
+                    self.addresses
+                        .lock()
+                        .expect("lock poisoned")
+                        .record_success(&url);
+                    return Ok(client);
+                }
+                Err(err) => {
+                    debug!(
+                        target: LOG_NET_API,
+                        peer_id = %self.peer_id,
+                        %url,
+                        %err,
+                        "Unable to connect to url"
+                    );
+                    self.addresses
+                        .lock()
+                        .expect("lock poisoned")
+                        .record_failure(&url, now());
+                    last_err = Some(err);
                 }
             }
-        })
+        }
+
+        Err(last_err.expect("A peer has at least one url"))
     }
 }
 
@@ -879,6 +1520,17 @@ pub struct FederationStatus {
     /// This should always be 0 if everything is okay, so a monitoring tool
     /// should generate an alert if this is not the case.
     pub peers_flagged: u64,
+    /// Transactions submitted to the guardian waiting to be ordered
+    #[serde(default)]
+    pub submission_queue_len: u64,
+    /// Once the queue is full the guardian rejects submissions with
+    /// [`FederationBusy`]
+    #[serde(default)]
+    pub submission_queue_capacity: u64,
+    /// The health of the modules depending on anything outside of the
+    /// consensus, e.g. the bitcoin backend of the wallet
+    #[serde(default)]
+    pub module_health: BTreeMap<ModuleInstanceId, ModuleHealth>,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -888,8 +1540,276 @@ pub struct PeerStatus {
     /// Indicates that this peer needs attention from the operator since
     /// it has not contributed to the consensus in a long time
     pub flagged: bool,
+    /// Clock of the peer minus ours in milliseconds, as last measured over the
+    /// P2P connection
+    #[serde(default)]
+    pub clock_offset_ms: Option<i64>,
+}
+
+/// Health of the P2P connection to a peer as observed by a single guardian
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerHealth {
+    pub connection_status: PeerConnectionStatus,
+    /// Number of messages we have received from the peer
+    pub messages_received: u64,
+    /// Number of messages we have sent to the peer
+    pub messages_sent: u64,
+    /// Bytes of messages we have received from the peer
+    pub bytes_received: u64,
+    /// Bytes of messages we have sent to the peer
+    pub bytes_sent: u64,
+    /// Number of messages we dropped since our outgoing queue to the peer was
+    /// full, e.g. because we throttle the bandwidth to it
+    pub messages_dropped: u64,
+    /// Number of connections that were dropped due to an IO error
+    pub connection_errors: u64,
+    /// Number of times a connection to the peer has been established
+    pub reconnects: u64,
+    /// Number of consecutive connection attempts that failed the handshake
+    pub failed_handshakes: u64,
+    /// Seconds since we have last received a message from the peer
+    pub last_message_secs_ago: Option<u64>,
+    /// We stop sending messages to a quarantined peer until we manage to
+    /// connect to it again
+    pub quarantined: bool,
+    /// Clock of the peer minus ours in milliseconds, as last measured over the
+    /// P2P connection
+    #[serde(default)]
+    pub clock_offset_ms: Option<i64>,
+}
+
+/// Maximum number of sessions served by a single request of the paginated
+/// block explorer endpoints
+pub const MAX_SESSION_PAGE_SIZE: u64 = 16;
+
+/// A page of consensus history starting at session `start_index`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRange {
+    pub start_index: u64,
+    /// Capped at [`MAX_SESSION_PAGE_SIZE`]
+    pub limit: u64,
+}
+
+impl SessionRange {
+    /// The session indices of the page, respecting [`MAX_SESSION_PAGE_SIZE`]
+    pub fn indices(&self) -> std::ops::Range<u64> {
+        let limit = self.limit.min(MAX_SESSION_PAGE_SIZE);
+
+        self.start_index..self.start_index.saturating_add(limit)
+    }
+}
+
+/// A call into a module that panicked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ModuleFailure {
+    pub kind: ModuleKind,
+    /// The module trait call that panicked, e.g. `process_consensus_item`
+    pub operation: String,
+    /// The panic message
+    pub message: String,
+    /// The session during which the call panicked
+    pub session_index: Option<u64>,
+    /// Whether the consensus halted the module, otherwise the guardian only
+    /// stopped making the local call that panicked
+    pub halted: bool,
+}
+
+/// JSON-RPC error code of a guardian whose buffer of submitted transactions is
+/// full, the error data is a [`FederationBusy`], or that is handling too many
+/// requests at once
+pub const FEDERATION_BUSY_CODE: i32 = 503;
+
+/// The guardian rejected a submission since its buffer of transactions waiting
+/// to be ordered is full
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Error)]
+#[error("Federation is busy with {queue_len} queued transactions, retry after {retry_after:?}")]
+pub struct FederationBusy {
+    pub queue_len: u64,
+    /// How long the guardian expects the consensus to take to make room
+    pub retry_after: Duration,
+}
+
+/// The guardian entered safe mode after its storage ran full, it stops
+/// processing consensus items until space is available again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageFailure {
+    /// The write that failed, e.g. `process_consensus_item`
+    pub operation: String,
+    /// The error reported by the database
+    pub message: String,
+    /// When the guardian entered safe mode
+    pub since: SystemTime,
+}
+
+/// The guardian halted its consensus since a safety invariant of the
+/// federation was violated, it stops processing consensus items until an admin
+/// overrides the halt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SafetyViolation {
+    /// What was violated, e.g. the audit of the balance sheet
+    pub message: String,
+    /// The session during which the guardian halted
+    pub session_index: u64,
+    /// When the guardian halted
+    pub since: SystemTime,
+}
+
+/// How an admin resolves a safety halt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SafetyHaltOverride {
+    /// Accepts the consensus item that violated the invariant and continues
+    /// processing items
+    Resume,
+    /// Shuts down the guardian without processing any further items
+    Shutdown,
 }
 
+/// Diagnostics gathered by a guardian whose consensus did not complete a
+/// session within the expected time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StallDiagnostics {
+    /// The session that did not complete
+    pub session_index: u64,
+    /// How long the session has been running
+    pub session_duration: Duration,
+    /// The latest round of the atomic broadcast we created a unit in
+    pub aleph_round: Option<u64>,
+    /// Number of batches the atomic broadcast ordered in this session
+    pub ordered_batches: u64,
+    /// Number of consensus items we accepted in this session
+    pub accepted_items: u64,
+    /// Consensus items waiting to be included in one of our batches
+    pub submission_queue_len: u64,
+    /// Ordered batches waiting to be processed
+    pub ordered_queue_len: u64,
+    pub federation_status: Option<FederationStatus>,
+    pub peer_health: BTreeMap<PeerId, PeerHealth>,
+    pub safe_mode: Option<StorageFailure>,
+    pub safety_halt: Option<SafetyViolation>,
+    /// When the diagnostics were gathered
+    pub created_at: SystemTime,
+}
+
+/// Progress of the session a guardian's consensus is currently running
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionDiagnostics {
+    pub session_index: u64,
+    /// How long the session has been running
+    pub session_duration: Duration,
+    /// The latest round of the atomic broadcast we created a unit in
+    pub aleph_round: Option<u64>,
+    /// Number of batches the atomic broadcast ordered in this session
+    pub ordered_batches: u64,
+    /// Number of consensus items we accepted in this session
+    pub accepted_items: u64,
+    /// Ordered batches waiting to be processed
+    pub ordered_queue_len: u64,
+}
+
+/// Size of the data stored under a database key prefix
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbPrefixStats {
+    pub entries: u64,
+    /// Size of the keys and values in bytes
+    pub bytes: u64,
+}
+
+/// Snapshot of a guardian's internals, dumped on request for post-incident
+/// analysis
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnosticsDump {
+    pub session: SessionDiagnostics,
+    /// Consensus items waiting to be included in one of our batches
+    pub submission_queue_len: u64,
+    /// API requests that are being handled
+    pub api_requests_in_flight: u64,
+    /// The tasks of the guardian that did not finish yet
+    pub running_tasks: Vec<String>,
+    /// Size of the data of the guardian and of every module in the database
+    pub db_stats: BTreeMap<String, DbPrefixStats>,
+    /// When the dump was created
+    pub created_at: SystemTime,
+}
+
+/// A copy of a guardian's config and database it can be restored to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuardianCheckpoint {
+    pub name: String,
+    /// Why the checkpoint was created, e.g. the change applied after it
+    pub reason: String,
+    pub created_at: SystemTime,
+}
+
+/// Request of an admin to create a token that authenticates requests to the
+/// endpoints of a single module which require one of the capabilities
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateScopedTokenRequest {
+    pub name: String,
+    pub module_instance_id: ModuleInstanceId,
+    /// Capabilities defined by the module, e.g. `fees` for the wallet
+    pub capabilities: BTreeSet<String>,
+}
+
+/// A module-scoped token created by the admin, the token itself is only
+/// returned on creation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ScopedToken {
+    pub name: String,
+    pub module_instance_id: ModuleInstanceId,
+    pub capabilities: BTreeSet<String>,
+    pub created_at: SystemTime,
+}
+
+/// Which consensus items a guardian logs at debug level, tunable at runtime via
+/// the admin API since logging every item is expensive on busy federations
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsensusItemLogging {
+    pub enabled: bool,
+    /// Log only every n-th item that passes the module filter, 1 logs all of
+    /// them
+    pub sample_every: u64,
+    /// Log only module items and transactions with inputs or outputs of these
+    /// modules, all items if unset
+    pub modules: Option<BTreeSet<ModuleInstanceId>>,
+}
+
+impl Default for ConsensusItemLogging {
+    fn default() -> Self {
+        ConsensusItemLogging {
+            enabled: true,
+            sample_every: 1,
+            modules: None,
+        }
+    }
+}
+
+/// The read-only snapshot of the consensus history a response was served from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    /// Number of completed sessions contained in the snapshot
+    pub session_count: u64,
+    /// How old the snapshot was when the response was created
+    pub age: Duration,
+}
+
+/// A response served from a snapshot of the consensus history
+///
+/// Every guardian refreshes its snapshot independently, so responses are
+/// compared by their value only when querying the federation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotResponse<T> {
+    pub value: T,
+    pub snapshot: SnapshotInfo,
+}
+
+impl<T: PartialEq> PartialEq for SnapshotResponse<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq> Eq for SnapshotResponse<T> {}
+
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PeerConnectionStatus {
     #[default]
@@ -964,7 +1884,7 @@ mod tests {
             self.0.is_connected()
         }
 
-        async fn connect(_url: &SafeUrl) -> Result<Self> {
+        async fn connect(_url: &SafeUrl, _proxy: Option<&SafeUrl>) -> Result<Self> {
             Ok(Self(C::connect().await?))
         }
     }
@@ -1000,7 +1920,9 @@ mod tests {
 
     fn federation_peer<C: SimpleClient + MaybeSend + MaybeSync>() -> FederationPeer<Client<C>> {
         FederationPeer {
-            url: SafeUrl::parse("http://127.0.0.1").expect("Could not parse"),
+            addresses: Mutex::new(PeerAddresses::new(vec![
+                SafeUrl::parse("http://127.0.0.1").expect("Could not parse")
+            ])),
             peer_id: PeerId::from(0),
             client: RwLock::new(None),
         }
@@ -1171,4 +2093,25 @@ mod tests {
         let connect_parsed_json: InviteCode = serde_json::from_str(&json).unwrap();
         assert_eq!(connect_parsed_json, connect_parsed);
     }
+
+    #[test]
+    fn session_range_is_capped() {
+        let range = SessionRange {
+            start_index: 10,
+            limit: 5,
+        };
+        assert_eq!(range.indices(), 10..15);
+
+        let range = SessionRange {
+            start_index: 10,
+            limit: u64::MAX,
+        };
+        assert_eq!(range.indices(), 10..10 + MAX_SESSION_PAGE_SIZE);
+
+        let range = SessionRange {
+            start_index: u64::MAX,
+            limit: 5,
+        };
+        assert!(range.indices().is_empty());
+    }
 }
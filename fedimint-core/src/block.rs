@@ -1,9 +1,16 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
 use bitcoin30::hashes::{sha256, Hash};
 use parity_scale_codec::{Decode, Encode};
+use secp256k1_zkp::{schnorr, Message, PublicKey, SECP256K1};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 use crate::encoding::{Decodable, Encodable};
 use crate::epoch::ConsensusItem;
-use crate::PeerId;
+use crate::transaction::Transaction;
+use crate::{PeerId, TransactionId};
 
 /// If two correct nodes obtain two ordered items from the broadcast they
 /// are guaranteed to be in the same order. However, an ordered items is
@@ -45,6 +52,191 @@ impl Block {
 
         header
     }
+
+    /// The transactions accepted in this block in the order they were
+    /// accepted, together with their location in the consensus history
+    pub fn located_transactions(
+        &self,
+        session_index: u64,
+    ) -> impl Iterator<Item = LocatedTransaction> + '_ {
+        self.items
+            .iter()
+            .enumerate()
+            .filter_map(
+                move |(item_index, accepted_item)| match &accepted_item.item {
+                    ConsensusItem::Transaction(transaction) => Some(LocatedTransaction {
+                        location: TransactionLocation {
+                            session_index,
+                            item_index: item_index as u64,
+                        },
+                        transaction: transaction.clone(),
+                    }),
+                    _ => None,
+                },
+            )
+    }
+
+    /// Creates a merkle inclusion proof for the [AcceptedItem] at
+    /// `item_index`, which allows a light client to verify that the item is
+    /// part of this block knowing only its signed header.
+    pub fn accepted_item_proof(
+        &self,
+        session_index: u64,
+        item_index: u64,
+    ) -> Option<AcceptedItemProof> {
+        let leaves = self
+            .items
+            .iter()
+            .map(|item| consensus_hash_sha256(item).to_byte_array())
+            .collect();
+
+        let branch = merkle_branch(leaves, usize::try_from(item_index).ok()?)?;
+
+        Some(AcceptedItemProof {
+            session_index,
+            item_index,
+            peer: self.items[item_index as usize].peer,
+            branch,
+        })
+    }
+}
+
+/// A merkle inclusion proof for an accepted item in a block. Together with a
+/// [SignedBlockHeader] it proves to a light client that the federation has
+/// accepted the item without the client having to download the entire block.
+#[derive(Clone, Debug, PartialEq, Eq, Encodable, Decodable)]
+pub struct AcceptedItemProof {
+    /// The index of the block containing the item
+    pub session_index: u64,
+    /// The index of the item within its block
+    pub item_index: u64,
+    /// The peer that contributed the item, which is part of the merkle leaf
+    pub peer: PeerId,
+    /// The sibling hashes from the leaf up to the merkle root
+    pub branch: Vec<[u8; 32]>,
+}
+
+impl AcceptedItemProof {
+    /// Verifies that `item` was accepted in the block with the given `header`
+    pub fn verify(&self, item: &ConsensusItem, header: &[u8; 40]) -> bool {
+        let leaf = AcceptedItem {
+            item: item.clone(),
+            peer: self.peer,
+        };
+
+        let Some(root) = merkle_root_of_branch(
+            consensus_hash_sha256(&leaf).to_byte_array(),
+            self.item_index,
+            &self.branch,
+        ) else {
+            return false;
+        };
+
+        header[..8] == self.session_index.to_be_bytes() && header[8..] == root
+    }
+}
+
+/// The sibling hashes from the leaf at `index` up to the merkle root, `None`
+/// if the index is out of range
+///
+/// This mirrors the tree construction of bitcoins merkle root calculation used
+/// in [`Block::header`], where the last hash of a level with an odd number of
+/// hashes is paired with itself.
+pub(crate) fn merkle_branch(mut level: Vec<[u8; 32]>, mut index: usize) -> Option<Vec<[u8; 32]>> {
+    if index >= level.len() {
+        return None;
+    }
+
+    let mut branch = vec![];
+
+    while level.len() > 1 {
+        let sibling = if index % 2 == 0 {
+            level.get(index + 1).copied().unwrap_or(level[index])
+        } else {
+            level[index - 1]
+        };
+
+        branch.push(sibling);
+
+        level = level
+            .chunks(2)
+            .map(|pair| merkle_parent(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+
+        index /= 2;
+    }
+
+    Some(branch)
+}
+
+/// The merkle root of a tree built like in [`merkle_branch`], `None` if there
+/// are no leaves
+pub(crate) fn merkle_root(leaves: Vec<[u8; 32]>) -> Option<[u8; 32]> {
+    let mut level = leaves;
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| merkle_parent(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+    }
+
+    level.first().copied()
+}
+
+/// Computes the merkle root from a leaf and its branch, `None` if the index
+/// lies beyond the tree
+pub(crate) fn merkle_root_of_branch(
+    leaf: [u8; 32],
+    mut index: u64,
+    branch: &[[u8; 32]],
+) -> Option<[u8; 32]> {
+    let mut hash = leaf;
+
+    for sibling in branch {
+        hash = if index % 2 == 0 {
+            merkle_parent(&hash, sibling)
+        } else {
+            merkle_parent(sibling, &hash)
+        };
+
+        index /= 2;
+    }
+
+    // any remaining bits would indicate an index beyond the tree
+    (index == 0).then_some(hash)
+}
+
+fn merkle_parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut engine = sha256::HashEngine::default();
+
+    engine
+        .write_all(left)
+        .expect("Writing to a hash engine cannot fail");
+
+    engine
+        .write_all(right)
+        .expect("Writing to a hash engine cannot fail");
+
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// The position of an accepted transaction in the consensus history, which
+/// allows explorers to fetch the one block containing it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct TransactionLocation {
+    /// The index of the block containing the transaction
+    pub session_index: u64,
+    /// The index of the [AcceptedItem] within its block
+    pub item_index: u64,
+}
+
+/// A transaction accepted by the federation together with its
+/// [TransactionLocation]
+#[derive(Clone, Debug, PartialEq, Eq, Encodable, Decodable)]
+pub struct LocatedTransaction {
+    pub location: TransactionLocation,
+    pub transaction: Transaction,
 }
 
 #[derive(Clone, Debug, Encodable, Decodable, Encode, Decode, PartialEq, Eq, Hash)]
@@ -60,6 +252,142 @@ pub struct SignedBlock {
     pub signatures: std::collections::BTreeMap<PeerId, SchnorrSignature>,
 }
 
+impl SignedBlock {
+    /// Strips the items from the signed block, leaving only what a light
+    /// client needs to follow the federations consensus history.
+    pub fn signed_header(&self, index: u64) -> SignedBlockHeader {
+        SignedBlockHeader {
+            header: self.block.header(index),
+            signatures: self.signatures.clone(),
+        }
+    }
+}
+
+/// The header of a [SignedBlock] together with the threshold signature of the
+/// federation. Its size is independent of the number of items in the block,
+/// so clients can verify the consensus history at a cost of a few hundred
+/// bytes per session.
+#[derive(Clone, Debug, Encodable, Decodable, Eq, PartialEq)]
+pub struct SignedBlockHeader {
+    pub header: [u8; 40],
+    pub signatures: BTreeMap<PeerId, SchnorrSignature>,
+}
+
+impl SignedBlockHeader {
+    /// The index of the session this header was created in
+    pub fn index(&self) -> u64 {
+        let mut index = [0; 8];
+        index.copy_from_slice(&self.header[..8]);
+        u64::from_be_bytes(index)
+    }
+
+    /// Verifies the threshold signature of the header against the broadcast
+    /// public keys of the federation.
+    pub fn verify(&self, public_keys: &BTreeMap<PeerId, PublicKey>) -> bool {
+        self.check_signatures(public_keys).is_ok()
+    }
+
+    /// Like [`Self::verify`], but reports why the threshold signature is
+    /// invalid
+    pub fn check_signatures(
+        &self,
+        public_keys: &BTreeMap<PeerId, PublicKey>,
+    ) -> Result<(), HeaderSignatureError> {
+        let threshold = (2 * public_keys.len()) / 3 + 1;
+
+        if self.signatures.len() < threshold {
+            return Err(HeaderSignatureError::InsufficientSignatures {
+                signatures: self.signatures.len(),
+                threshold,
+            });
+        }
+
+        let message = broadcast_message_hash(public_keys, &self.header);
+
+        for (peer_id, signature) in &self.signatures {
+            let public_key = public_keys
+                .get(peer_id)
+                .ok_or(HeaderSignatureError::UnknownPeer(*peer_id))?;
+
+            schnorr::Signature::from_slice(&signature.0)
+                .and_then(|signature| {
+                    SECP256K1.verify_schnorr(
+                        &signature,
+                        &message,
+                        &public_key.x_only_public_key().0,
+                    )
+                })
+                .map_err(|_| HeaderSignatureError::InvalidSignature(*peer_id))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A receipt that the federation accepted a transaction, consisting of the
+/// inclusion proof of the transaction and the signed header of its block.
+/// Since it is verified against the broadcast public keys of the federation
+/// alone, it can be archived, e.g. by exchanges or accounting software, and
+/// checked later without contacting the federation.
+#[derive(Clone, Debug, Encodable, Decodable, Eq, PartialEq)]
+pub struct TransactionReceipt {
+    pub txid: TransactionId,
+    pub proof: AcceptedItemProof,
+    pub signed_header: SignedBlockHeader,
+}
+
+impl TransactionReceipt {
+    /// Verifies that `transaction` was accepted in the block of the signed
+    /// header, where `public_keys` are the broadcast public keys of the
+    /// federation at [`AcceptedItemProof::session_index`]
+    pub fn verify(
+        &self,
+        transaction: &Transaction,
+        public_keys: &BTreeMap<PeerId, PublicKey>,
+    ) -> bool {
+        transaction.tx_hash() == self.txid
+            && self.signed_header.index() == self.proof.session_index
+            && self.proof.verify(
+                &ConsensusItem::Transaction(transaction.clone()),
+                &self.signed_header.header,
+            )
+            && self.signed_header.verify(public_keys)
+    }
+}
+
+/// The reason why the threshold signature of a [SignedBlockHeader] is invalid
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum HeaderSignatureError {
+    #[error("The header has {signatures} signatures but the threshold is {threshold}")]
+    InsufficientSignatures { signatures: usize, threshold: usize },
+    #[error("The header is signed by unknown peer {0}")]
+    UnknownPeer(PeerId),
+    #[error("The signature of peer {0} is invalid")]
+    InvalidSignature(PeerId),
+}
+
+/// The message signed by the atomic broadcast is tagged with the hash of the
+/// federations broadcast public keys, such that signatures can not be reused
+/// across federations.
+pub fn broadcast_message_hash(
+    public_keys: &BTreeMap<PeerId, PublicKey>,
+    message: &[u8],
+) -> Message {
+    let public_key_tag = consensus_hash_sha256(public_keys);
+    let mut engine = sha256::HashEngine::default();
+
+    engine
+        .write_all(public_key_tag.as_ref())
+        .expect("Writing to a hash engine can not fail");
+
+    engine
+        .write_all(message)
+        .expect("Writing to a hash engine can not fail");
+
+    Message::from_slice(&sha256::Hash::from_engine(engine).to_byte_array())
+        .expect("A sha256 hash is a valid message")
+}
+
 // TODO: remove this as soon as we bump bitcoin_hashes in fedimint_core to
 // 0.12.0
 pub fn consensus_hash_sha256<E: Encodable>(encodable: &E) -> sha256::Hash {
@@ -69,3 +397,102 @@ pub fn consensus_hash_sha256<E: Encodable>(encodable: &E) -> sha256::Hash {
         .expect("Writing to HashEngine cannot fail");
     sha256::Hash::from_engine(engine)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    fn block(n_items: u16) -> Block {
+        let items = (0..n_items)
+            .map(|peer| AcceptedItem {
+                item: ConsensusItem::Transaction(Transaction {
+                    inputs: vec![],
+                    outputs: vec![],
+                    signature: None,
+                }),
+                peer: PeerId::from(peer),
+            })
+            .collect();
+
+        Block { items }
+    }
+
+    #[test]
+    fn accepted_item_proofs_verify_against_header() {
+        for n_items in 1..10 {
+            let block = block(n_items);
+            let header = block.header(42);
+
+            for (item_index, accepted_item) in block.items.iter().enumerate() {
+                let proof = block
+                    .accepted_item_proof(42, item_index as u64)
+                    .expect("Item index is in range");
+
+                assert!(proof.verify(&accepted_item.item, &header));
+                assert!(!proof.verify(&accepted_item.item, &block.header(43)));
+
+                let mut wrong_peer = proof.clone();
+                wrong_peer.peer = PeerId::from(n_items);
+                assert!(!wrong_peer.verify(&accepted_item.item, &header));
+            }
+
+            assert!(block.accepted_item_proof(42, n_items as u64).is_none());
+        }
+    }
+
+    #[test]
+    fn transaction_receipts_verify_against_broadcast_keys() {
+        let keypairs = (0..4u8)
+            .map(|i| {
+                secp256k1_zkp::SecretKey::from_slice(&[i + 1; 32])
+                    .expect("Valid secret key")
+                    .keypair(SECP256K1)
+            })
+            .collect::<Vec<_>>();
+        let public_keys = keypairs
+            .iter()
+            .enumerate()
+            .map(|(peer, keypair)| (PeerId::from(peer as u16), keypair.public_key()))
+            .collect::<BTreeMap<_, _>>();
+
+        let block = block(3);
+        let ConsensusItem::Transaction(transaction) = block.items[1].item.clone() else {
+            unreachable!("The block only contains transactions");
+        };
+
+        let header = block.header(7);
+        let message = broadcast_message_hash(&public_keys, &header);
+        let signatures = keypairs
+            .iter()
+            .enumerate()
+            .map(|(peer, keypair)| {
+                let signature = SECP256K1.sign_schnorr_no_aux_rand(&message, keypair);
+                (
+                    PeerId::from(peer as u16),
+                    SchnorrSignature(signature.as_ref().to_owned()),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let receipt = TransactionReceipt {
+            txid: transaction.tx_hash(),
+            proof: block.accepted_item_proof(7, 1).expect("Item exists"),
+            signed_header: SignedBlockHeader { header, signatures },
+        };
+
+        assert!(receipt.verify(&transaction, &public_keys));
+
+        let mut other_keys = public_keys.clone();
+        other_keys.insert(PeerId::from(0), keypairs[1].public_key());
+        assert!(!receipt.verify(&transaction, &other_keys));
+
+        let mut unsigned = receipt.clone();
+        unsigned.signed_header.signatures.clear();
+        assert!(!unsigned.verify(&transaction, &public_keys));
+
+        let mut wrong_session = receipt;
+        wrong_session.proof.session_index = 8;
+        assert!(!wrong_session.verify(&transaction, &public_keys));
+    }
+}
@@ -29,10 +29,12 @@ use tracing::warn;
 
 use crate::core::DynClientConfig;
 use crate::encoding::Decodable;
+use crate::fee::FeeSchedule;
 use crate::module::{
     CoreConsensusVersion, DynCommonModuleInit, DynServerModuleInit, IDynCommonModuleInit,
     ModuleConsensusVersion,
 };
+use crate::transaction::TransactionLimits;
 use crate::{maybe_add_send_sync, PeerId};
 
 /// [`serde_json::Value`] that must contain `kind: String` field
@@ -104,6 +106,19 @@ pub struct PeerUrl {
     pub url: SafeUrl,
     /// The peer's name
     pub name: String,
+    /// Further URLs of the peer in order of preference, e.g. its IPv6 or
+    /// onion address, tried if `url` is unreachable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_urls: Vec<SafeUrl>,
+}
+
+impl PeerUrl {
+    /// All URLs of the peer in order of preference
+    pub fn urls(&self) -> Vec<SafeUrl> {
+        std::iter::once(self.url.clone())
+            .chain(self.fallback_urls.iter().cloned())
+            .collect()
+    }
 }
 
 /// Total client config
@@ -154,11 +169,20 @@ pub struct GlobalClientConfig {
     pub api_endpoints: BTreeMap<PeerId, PeerUrl>,
     /// Threshold pubkey for authenticating epoch history
     pub epoch_pk: threshold_crypto::PublicKey,
+    /// Public keys of the atomic broadcast for verifying signed block headers
+    #[serde(default, deserialize_with = "de_int_key")]
+    pub broadcast_public_keys: BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
     /// Core consensus version
     pub consensus_version: CoreConsensusVersion,
     // TODO: make it a String -> serde_json::Value map?
     /// Additional config the federation wants to transmit to the clients
     pub meta: BTreeMap<String, String>,
+    /// Limits on the transactions the federation accepts
+    #[serde(default)]
+    pub transaction_limits: TransactionLimits,
+    /// Fees the federation charges on the inputs and outputs of the modules
+    #[serde(default)]
+    pub fees: FeeSchedule,
 }
 
 impl ClientConfig {
@@ -178,6 +202,10 @@ impl ClientConfig {
     }
 }
 
+/// Path under which guardians can serve the [`ClientConfigResponse`] over
+/// HTTP, such that users only need the domain of a federation to join it
+pub const WELL_KNOWN_CONFIG_PATH: &str = "/.well-known/fedimint/config";
+
 /// The API response for client config requests, signed by the Federation
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ClientConfigResponse {
@@ -187,6 +215,60 @@ pub struct ClientConfigResponse {
     pub signature: SerdeSignature,
 }
 
+/// A version of the consensus config the federation ran, recorded by the
+/// guardians once they start running it. Clients recovering old backups use
+/// the client config of a version to verify the blocks signed under it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ConsensusConfigVersion {
+    /// The consensus hash of the guardians' consensus config
+    pub consensus_hash: sha256::Hash,
+    /// The first session run under this version
+    pub activation_session: u64,
+    /// The client config derived from the consensus config
+    pub client_config: ClientConfig,
+}
+
+impl ClientConfigResponse {
+    /// Returns the URL the config of the federation at `domain` is served at
+    pub fn well_known_url(domain: &str) -> anyhow::Result<SafeUrl> {
+        Ok(SafeUrl::parse(&format!(
+            "https://{domain}{WELL_KNOWN_CONFIG_PATH}"
+        ))?)
+    }
+
+    /// Checks that the config is signed by the federation it belongs to
+    ///
+    /// When fetching the config from a domain we don't know the federation id
+    /// in advance like with an invite code, so the domain is what vouches for
+    /// the federation.
+    pub fn verify_signature(&self) -> bool {
+        self.client_config
+            .global
+            .federation_id
+            .0
+            .verify(&self.signature.0, self.client_config.consensus_hash())
+    }
+}
+
+/// The full config of a guardian in a single file, such that it can be
+/// provisioned onto fresh hardware
+///
+/// The private section is encrypted with a key derived from a passphrase and
+/// also authenticates the plaintext sections.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ConfigBundle {
+    /// Version of the bundle format
+    pub version: u16,
+    /// Salt for deriving the encryption key from the passphrase
+    pub salt: String,
+    /// The consensus config of the guardian
+    pub consensus: serde_json::Value,
+    /// The local config of the guardian
+    pub local: serde_json::Value,
+    /// The hex encoded ciphertext of the private config
+    pub private: String,
+}
+
 /// The federation id is a copy of the authentication threshold public key of
 /// the federation
 ///
@@ -579,7 +661,7 @@ pub trait ModuleInitParams: serde::Serialize + serde::de::DeserializeOwned {
     fn to_parts(self) -> (Self::Local, Self::Consensus);
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub struct ServerModuleConsensusConfig {
     pub kind: ModuleKind,
     pub version: ModuleConsensusVersion,
@@ -13,10 +13,11 @@ use fedimint_core::{apply, async_trait_maybe_send, OutPoint, PeerId};
 use crate::core::{Any, Decoder, DynInput, DynModuleConsensusItem, DynOutput, DynOutputOutcome};
 use crate::db::DatabaseTransactionRef;
 use crate::dyn_newtype_define;
+use crate::fee::FeeIncome;
 use crate::module::registry::ModuleInstanceId;
 use crate::module::{
     ApiEndpoint, ApiEndpointContext, ApiRequestErased, InputMeta, ModuleCommon, ModuleError,
-    ServerModule, TransactionItemAmount,
+    ModuleHealth, ServerModule, TransactionItemAmount,
 };
 
 /// Backend side module interface
@@ -36,6 +37,10 @@ pub trait IServerModule: Debug {
         module_instance_id: ModuleInstanceId,
     ) -> Vec<DynModuleConsensusItem>;
 
+    /// The class of items only the round-robin leaders of the class propose,
+    /// if any
+    fn consensus_item_class(&self, consensus_item: &DynModuleConsensusItem) -> Option<u64>;
+
     /// This function is called once for every consensus item. The function
     /// returns an error if any only if the consensus item does not change
     /// our state and therefore may be safely discarded by the atomic broadcast.
@@ -56,6 +61,13 @@ pub trait IServerModule: Debug {
         input: &'b DynInput,
     ) -> Result<InputMeta, ModuleError>;
 
+    /// The outputs of accepted transactions the input spends
+    async fn input_dependencies(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        input: &DynInput,
+    ) -> Vec<OutPoint>;
+
     /// Try to create an output (e.g. issue notes, peg-out BTC, …). On success
     /// all necessary updates to the database will be part of the database
     /// transaction. On failure (e.g. double spend) the database transaction
@@ -71,10 +83,28 @@ pub trait IServerModule: Debug {
         out_point: OutPoint,
     ) -> Result<TransactionItemAmount, ModuleError>;
 
+    /// Called once all items of a session have been processed, before the
+    /// session is completed
+    async fn end_session(&self, dbtx: &mut DatabaseTransactionRef<'_>, session_index: u64);
+
+    /// Called at the end of every session with the fee income of the
+    /// federation, right before `end_session`
+    async fn update_fee_income(&self, dbtx: &mut DatabaseTransactionRef<'_>, fee_income: FeeIncome);
+
+    /// The prefixes of the module's keys that are committed to in the state
+    /// snapshots for light clients
+    fn committed_state_prefixes(&self) -> Vec<u8>;
+
+    /// The health of the module as surfaced on the status endpoint, if it
+    /// depends on anything outside of the consensus
+    async fn health(&self) -> Option<ModuleHealth>;
+
     /// Retrieve the current status of the output. Depending on the module this
     /// might contain data needed by the client to access funds or give an
     /// estimate of when funds will be available. Returns `None` if the
-    /// output is unknown, **NOT** if it is just not ready yet.
+    /// output is unknown, **NOT** if it is just not ready yet. The only
+    /// exception are outcomes produced in `end_session`, which are `None`
+    /// until the session that accepted the output is completed.
     async fn output_status(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -132,6 +162,18 @@ where
             .collect()
     }
 
+    /// The class of items only the round-robin leaders of the class propose,
+    /// if any
+    fn consensus_item_class(&self, consensus_item: &DynModuleConsensusItem) -> Option<u64> {
+        <Self as ServerModule>::consensus_item_class(
+            self,
+            consensus_item
+                .as_any()
+                .downcast_ref::<<<Self as ServerModule>::Common as ModuleCommon>::ConsensusItem>()
+                .expect("incorrect consensus item type passed to module plugin"),
+        )
+    }
+
     /// This function is called once for every consensus item. The function
     /// returns an error if any only if the consensus item does not change
     /// our state and therefore may be safely discarded by the atomic broadcast.
@@ -175,6 +217,23 @@ where
         .map(Into::into)
     }
 
+    /// The outputs of accepted transactions the input spends
+    async fn input_dependencies(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        input: &DynInput,
+    ) -> Vec<OutPoint> {
+        <Self as ServerModule>::input_dependencies(
+            self,
+            dbtx,
+            input
+                .as_any()
+                .downcast_ref::<<<Self as ServerModule>::Common as ModuleCommon>::Input>()
+                .expect("incorrect input type passed to module plugin"),
+        )
+        .await
+    }
+
     /// Try to create an output (e.g. issue notes, peg-out BTC, …). On success
     /// all necessary updates to the database will be part of the database
     /// transaction. On failure (e.g. double spend) the database transaction
@@ -201,10 +260,40 @@ where
         .await
     }
 
+    /// Called once all items of a session have been processed, before the
+    /// session is completed
+    async fn end_session(&self, dbtx: &mut DatabaseTransactionRef<'_>, session_index: u64) {
+        <Self as ServerModule>::end_session(self, dbtx, session_index).await
+    }
+
+    /// Called at the end of every session with the fee income of the
+    /// federation, right before `end_session`
+    async fn update_fee_income(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        fee_income: FeeIncome,
+    ) {
+        <Self as ServerModule>::update_fee_income(self, dbtx, fee_income).await
+    }
+
+    /// The prefixes of the module's keys that are committed to in the state
+    /// snapshots for light clients
+    fn committed_state_prefixes(&self) -> Vec<u8> {
+        <Self as ServerModule>::committed_state_prefixes(self)
+    }
+
+    /// The health of the module as surfaced on the status endpoint, if it
+    /// depends on anything outside of the consensus
+    async fn health(&self) -> Option<ModuleHealth> {
+        <Self as ServerModule>::health(self).await
+    }
+
     /// Retrieve the current status of the output. Depending on the module this
     /// might contain data needed by the client to access funds or give an
     /// estimate of when funds will be available. Returns `None` if the
-    /// output is unknown, **NOT** if it is just not ready yet.
+    /// output is unknown, **NOT** if it is just not ready yet. The only
+    /// exception are outcomes produced in `end_session`, which are `None`
+    /// until the session that accepted the output is completed.
     async fn output_status(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -0,0 +1,265 @@
+//! Diagnostics of database transactions whose commit conflicted
+//!
+//! A transaction fails to commit if a concurrent transaction committed a write
+//! to one of the keys it wrote in the meantime. Such conflicts are retried, but
+//! if the same keys conflict frequently the transactions touching them are
+//! effectively serialized. To help module authors find these keys, the
+//! database can run in a diagnostic mode, enabled by setting
+//! [`FM_DB_CONFLICT_DIAGNOSTICS_ENV`]. In this mode it remembers which commit
+//! last wrote every key, determines the keys a failed commit conflicted on and
+//! aggregates them per module and key prefix into a [`ConflictReport`]. Keys
+//! that are hot spots can then be restructured, e.g. a counter that every
+//! transaction updates can be split into one key per transaction and summed
+//! when read.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use fedimint_logging::LOG_DB;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::MODULE_GLOBAL_PREFIX;
+use crate::core::{ModuleInstanceId, ModuleKind};
+use crate::encoding::Decodable;
+use crate::module::registry::ModuleDecoderRegistry;
+
+/// Enables the diagnostic mode if set to any value
+pub const FM_DB_CONFLICT_DIAGNOSTICS_ENV: &str = "FM_DB_CONFLICT_DIAGNOSTICS";
+
+/// Upper bound on the number of keys whose last write we remember, once it is
+/// reached we start over and attribute conflicts to all the keys written
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+/// Number of conflicting keys we keep as examples of every hot spot
+const MAX_SAMPLE_KEYS: usize = 5;
+
+/// The module and the key prefix a conflicting key belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ConflictSite {
+    module_instance_id: Option<ModuleInstanceId>,
+    key_prefix: Option<u8>,
+}
+
+impl ConflictSite {
+    fn of_key(key: &[u8]) -> Self {
+        if key.first() == Some(&MODULE_GLOBAL_PREFIX) {
+            let mut rest = &key[1..];
+
+            if let Ok(module_instance_id) =
+                ModuleInstanceId::consensus_decode(&mut rest, &ModuleDecoderRegistry::default())
+            {
+                return ConflictSite {
+                    module_instance_id: Some(module_instance_id),
+                    key_prefix: rest.first().copied(),
+                };
+            }
+        }
+
+        ConflictSite {
+            module_instance_id: None,
+            key_prefix: key.first().copied(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct HotSpot {
+    conflicts: u64,
+    sample_keys: BTreeSet<Vec<u8>>,
+}
+
+#[derive(Debug, Default)]
+struct DiagnosticsState {
+    /// Number of commits so far
+    commits: u64,
+    /// The sequence number of the commit that last wrote each key
+    last_writes: HashMap<Vec<u8>, u64>,
+    conflicts: u64,
+    hot_spots: BTreeMap<ConflictSite, HotSpot>,
+}
+
+/// Tracks the writes and conflicts of the transactions of a database
+#[derive(Debug, Default)]
+pub struct ConflictDiagnostics {
+    state: Mutex<DiagnosticsState>,
+}
+
+impl ConflictDiagnostics {
+    /// Enables the diagnostics if [`FM_DB_CONFLICT_DIAGNOSTICS_ENV`] is set
+    pub fn from_env() -> Option<Arc<Self>> {
+        std::env::var_os(FM_DB_CONFLICT_DIAGNOSTICS_ENV)
+            .map(|_| Arc::new(ConflictDiagnostics::default()))
+    }
+
+    /// Called when a transaction begins, returns the number of commits so far
+    pub fn begin(&self) -> u64 {
+        self.state.lock().expect("poisoned").commits
+    }
+
+    /// Remembers the keys written by a successful commit
+    pub fn record_commit(&self, keys: &BTreeSet<Vec<u8>>) {
+        let mut state = self.state.lock().expect("poisoned");
+
+        if MAX_TRACKED_KEYS < state.last_writes.len() + keys.len() {
+            state.last_writes.clear();
+        }
+
+        let commit = state.commits;
+
+        for key in keys {
+            state.last_writes.insert(key.clone(), commit);
+        }
+
+        state.commits += 1;
+    }
+
+    /// Attributes a failed commit to the keys that were written by another
+    /// transaction since the transaction began
+    ///
+    /// If none of them is known, e.g. since we stopped tracking the writes of
+    /// older commits, the conflict is attributed to all keys written.
+    pub fn record_conflict(&self, began_at: u64, keys: &BTreeSet<Vec<u8>>) {
+        let mut state = self.state.lock().expect("poisoned");
+
+        let mut conflicting = keys
+            .iter()
+            .filter(|key| {
+                state
+                    .last_writes
+                    .get(*key)
+                    .map_or(false, |commit| began_at <= *commit)
+            })
+            .collect::<Vec<_>>();
+
+        if conflicting.is_empty() {
+            conflicting = keys.iter().collect();
+        }
+
+        state.conflicts += 1;
+
+        let sites = conflicting
+            .into_iter()
+            .map(|key| (ConflictSite::of_key(key), key))
+            .collect::<BTreeMap<_, _>>();
+
+        for (site, key) in sites {
+            info!(
+                target: LOG_DB,
+                module_instance_id = ?site.module_instance_id,
+                key_prefix = ?site.key_prefix,
+                key = %hex::encode(key),
+                "Database commit conflicted"
+            );
+
+            let hot_spot = state.hot_spots.entry(site).or_default();
+            hot_spot.conflicts += 1;
+
+            if hot_spot.sample_keys.len() < MAX_SAMPLE_KEYS {
+                hot_spot.sample_keys.insert(key.clone());
+            }
+        }
+    }
+
+    /// The conflicts so far, hot spots with the most conflicts first
+    pub fn report(&self) -> ConflictReport {
+        let state = self.state.lock().expect("poisoned");
+
+        let mut hot_spots = state
+            .hot_spots
+            .iter()
+            .map(|(site, hot_spot)| ConflictHotSpot {
+                module_instance_id: site.module_instance_id,
+                module_kind: None,
+                key_prefix: site.key_prefix,
+                conflicts: hot_spot.conflicts,
+                sample_keys: hot_spot.sample_keys.iter().map(hex::encode).collect(),
+            })
+            .collect::<Vec<_>>();
+
+        hot_spots.sort_by(|a, b| b.conflicts.cmp(&a.conflicts));
+
+        ConflictReport {
+            commits: state.commits,
+            conflicts: state.conflicts,
+            hot_spots,
+        }
+    }
+}
+
+/// Conflicts of the transactions of a database aggregated into hot spots
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictReport {
+    /// Number of successful commits
+    pub commits: u64,
+    /// Number of commits that conflicted
+    pub conflicts: u64,
+    pub hot_spots: Vec<ConflictHotSpot>,
+}
+
+/// The conflicts on the keys with a given prefix of a module, or of the keys
+/// outside of any module if `module_instance_id` is `None`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictHotSpot {
+    pub module_instance_id: Option<ModuleInstanceId>,
+    /// Only known to the user of the database, e.g. the guardian fills it in
+    /// from its config
+    #[serde(default)]
+    pub module_kind: Option<ModuleKind>,
+    /// The first byte of the key within the module
+    pub key_prefix: Option<u8>,
+    /// Number of conflicted commits that wrote keys with the prefix
+    pub conflicts: u64,
+    /// Hex encoded examples of the conflicting keys
+    pub sample_keys: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::ConflictDiagnostics;
+    use crate::encoding::Encodable;
+
+    fn module_key(module_instance_id: u16, key: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0xff];
+        module_instance_id.consensus_encode(&mut bytes).unwrap();
+        bytes.extend_from_slice(key);
+        bytes
+    }
+
+    #[test]
+    fn attributes_conflicts_to_keys_written_concurrently() {
+        let diagnostics = ConflictDiagnostics::default();
+        let counter = module_key(1, &[0x01]);
+        let other = module_key(1, &[0x02, 7]);
+        let global = vec![0x04, 1];
+
+        let began_at = diagnostics.begin();
+        diagnostics.record_commit(&BTreeSet::from([counter.clone(), global.clone()]));
+        diagnostics.record_conflict(began_at, &BTreeSet::from([counter.clone(), other.clone()]));
+
+        let began_at = diagnostics.begin();
+        diagnostics.record_commit(&BTreeSet::from([counter.clone()]));
+        diagnostics.record_conflict(began_at, &BTreeSet::from([counter.clone(), global.clone()]));
+
+        let report = diagnostics.report();
+
+        assert_eq!(report.commits, 2);
+        assert_eq!(report.conflicts, 2);
+        assert_eq!(report.hot_spots.len(), 1);
+        assert_eq!(report.hot_spots[0].module_instance_id, Some(1));
+        assert_eq!(report.hot_spots[0].key_prefix, Some(0x01));
+        assert_eq!(report.hot_spots[0].conflicts, 2);
+        assert_eq!(report.hot_spots[0].sample_keys, vec![hex::encode(&counter)]);
+
+        // without a known concurrent write we attribute the conflict to every key
+        diagnostics.record_conflict(diagnostics.begin(), &BTreeSet::from([global]));
+
+        let report = diagnostics.report();
+
+        assert_eq!(report.hot_spots.len(), 2);
+        assert_eq!(report.hot_spots[1].module_instance_id, None);
+        assert_eq!(report.hot_spots[1].key_prefix, Some(0x04));
+    }
+}
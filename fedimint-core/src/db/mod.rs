@@ -34,7 +34,7 @@
 //! Prevented      | Prevented   | | Sqlite   | Prevented          | Prevented
 //! | Prevented           | Prevented      | Prevented   |
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 use std::fmt::{self, Debug};
 use std::pin::Pin;
@@ -56,11 +56,13 @@ use crate::fmt_utils::AbbreviateHexBytes;
 use crate::task::{MaybeSend, MaybeSync};
 use crate::{async_trait_maybe_send, maybe_add_send, timing};
 
+pub mod conflicts;
 pub mod mem_impl;
 pub mod notifications;
 
 pub use test_utils::*;
 
+use self::conflicts::{ConflictDiagnostics, ConflictReport};
 use self::notifications::{Notifications, NotifyQueue};
 use crate::module::registry::ModuleDecoderRegistry;
 
@@ -142,6 +144,38 @@ pub enum AutocommitError<E> {
     },
 }
 
+/// Error returned by a database backend when a commit could not be persisted
+/// because the underlying storage ran out of space
+///
+/// Unlike other commit failures this condition is expected to resolve once the
+/// operator frees up space, so callers may want to retry instead of giving up.
+#[derive(Debug, Error)]
+#[error("Database storage is full: {0}")]
+pub struct StorageFullError(pub String);
+
+impl StorageFullError {
+    /// Checks whether any cause of the error is a [`StorageFullError`]
+    pub fn is_cause_of(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| cause.is::<StorageFullError>())
+    }
+}
+
+/// Error returned by a database backend when a commit conflicted with a
+/// concurrent transaction
+///
+/// The transaction can be run again on the new state of the database, so
+/// callers may want to retry instead of giving up.
+#[derive(Debug, Error)]
+#[error("Database transaction conflicted: {0}")]
+pub struct CommitConflictError(pub String);
+
+impl CommitConflictError {
+    /// Checks whether any cause of the error is a [`CommitConflictError`]
+    pub fn is_cause_of(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| cause.is::<CommitConflictError>())
+    }
+}
+
 /// Raw database implementation
 ///
 /// This and [`IRawDatabaseTransaction`] are meant to be implemented
@@ -202,6 +236,10 @@ pub trait IDatabase: Debug + MaybeSend + MaybeSync + 'static {
     async fn register(&self, key: &[u8]);
     /// Notify about `key` update (creation, modification, deletion)
     async fn notify(&self, key: &[u8]);
+    /// The commit conflicts so far, if the conflict diagnostics are enabled
+    fn conflict_report(&self) -> Option<ConflictReport> {
+        None
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -218,6 +256,9 @@ where
     async fn notify(&self, key: &[u8]) {
         (**self).notify(key).await
     }
+    fn conflict_report(&self) -> Option<ConflictReport> {
+        (**self).conflict_report()
+    }
 }
 
 /// Base functionality around [`IRawDatabase`] to make it a [`IDatabase`]
@@ -225,6 +266,7 @@ where
 /// Mostly notification system, but also run-time single-commit handling.
 struct BaseDatabase<RawDatabase> {
     notifications: Arc<Notifications>,
+    conflict_diagnostics: Option<Arc<ConflictDiagnostics>>,
     raw: RawDatabase,
 }
 
@@ -237,9 +279,21 @@ impl<RawDatabase> fmt::Debug for BaseDatabase<RawDatabase> {
 #[apply(async_trait_maybe_send!)]
 impl<RawDatabase: IRawDatabase + MaybeSend + 'static> IDatabase for BaseDatabase<RawDatabase> {
     async fn begin_transaction<'a>(&'a self) -> Box<dyn IDatabaseTransaction + 'a> {
+        // we count the commits before beginning, so commits racing with the begin count as
+        // concurrent
+        let conflict_tracking =
+            self.conflict_diagnostics
+                .clone()
+                .map(|diagnostics| ConflictTracking {
+                    began_at: diagnostics.begin(),
+                    diagnostics,
+                    writes: BTreeSet::new(),
+                });
+
         Box::new(BaseDatabaseTransaction::new(
             self.raw.begin_transaction().await,
             self.notifications.clone(),
+            conflict_tracking,
         ))
     }
     async fn register(&self, key: &[u8]) {
@@ -248,6 +302,11 @@ impl<RawDatabase: IRawDatabase + MaybeSend + 'static> IDatabase for BaseDatabase
     async fn notify(&self, key: &[u8]) {
         self.notifications.notify(key).await
     }
+    fn conflict_report(&self) -> Option<ConflictReport> {
+        self.conflict_diagnostics
+            .as_ref()
+            .map(|diagnostics| diagnostics.report())
+    }
 }
 
 /// A public-facing newtype over `IDatabase`
@@ -265,11 +324,14 @@ impl Database {
     /// Creates a new Fedimint database from any object implementing
     /// [`IDatabase`].
     ///
+    /// The conflict diagnostics are enabled by
+    /// [`FM_DB_CONFLICT_DIAGNOSTICS_ENV`](conflicts::FM_DB_CONFLICT_DIAGNOSTICS_ENV).
     /// See also [`Database::new_from_arc`].
     pub fn new(raw: impl IRawDatabase + 'static, module_decoders: ModuleDecoderRegistry) -> Self {
         let inner = BaseDatabase {
             raw,
             notifications: Arc::new(Notifications::new()),
+            conflict_diagnostics: ConflictDiagnostics::from_env(),
         };
         Self::new_from_arc(
             Arc::new(inner) as Arc<dyn IDatabase + 'static>,
@@ -313,6 +375,12 @@ impl Database {
         }
     }
 
+    /// The keys commits conflicted on so far aggregated into hot spots, `None`
+    /// unless the conflict diagnostics are enabled, see [`conflicts`]
+    pub fn conflict_report(&self) -> Option<ConflictReport> {
+        self.inner.conflict_report()
+    }
+
     /// Begin a database transaction
     pub async fn begin_transaction<'s, 'tx>(&'s self) -> DatabaseTransaction<'tx>
     where
@@ -515,6 +583,9 @@ where
     async fn notify(&self, key: &[u8]) {
         self.inner.notify(&self.get_full_key(key)).await
     }
+    fn conflict_report(&self) -> Option<ConflictReport> {
+        self.inner.conflict_report()
+    }
 }
 
 /// A database transactions that wraps an `inner` one and adds a prefix to all
@@ -1011,17 +1082,30 @@ struct BaseDatabaseTransaction<Tx> {
     raw: Option<Tx>,
     notify_queue: Option<NotifyQueue>,
     notifications: Arc<Notifications>,
+    conflict_tracking: Option<ConflictTracking>,
+}
+
+/// The writes of a transaction, tracked if the conflict diagnostics are enabled
+struct ConflictTracking {
+    diagnostics: Arc<ConflictDiagnostics>,
+    began_at: u64,
+    writes: BTreeSet<Vec<u8>>,
 }
 
 impl<Tx> BaseDatabaseTransaction<Tx>
 where
     Tx: IRawDatabaseTransaction,
 {
-    fn new(dbtx: Tx, notifications: Arc<Notifications>) -> BaseDatabaseTransaction<Tx> {
+    fn new(
+        dbtx: Tx,
+        notifications: Arc<Notifications>,
+        conflict_tracking: Option<ConflictTracking>,
+    ) -> BaseDatabaseTransaction<Tx> {
         BaseDatabaseTransaction {
             raw: Some(dbtx),
             notifications,
             notify_queue: Some(NotifyQueue::new()),
+            conflict_tracking,
         }
     }
 
@@ -1030,6 +1114,11 @@ where
             .as_mut()
             .context("can not call add_notification_key after commit")?
             .add(&key);
+
+        if let Some(tracking) = self.conflict_tracking.as_mut() {
+            tracking.writes.insert(key.to_vec());
+        }
+
         Ok(())
     }
 }
@@ -1114,11 +1203,25 @@ impl<Tx: IRawDatabaseTransaction> IDatabaseTransactionOps for BaseDatabaseTransa
 #[apply(async_trait_maybe_send!)]
 impl<Tx: IRawDatabaseTransaction> IDatabaseTransaction for BaseDatabaseTransaction<Tx> {
     async fn commit_tx(&mut self) -> Result<()> {
-        self.raw
+        let result = self
+            .raw
             .take()
             .context("Cannot commit an already committed transaction")?
             .commit_tx()
-            .await?;
+            .await;
+
+        if let Some(tracking) = self.conflict_tracking.as_ref() {
+            match &result {
+                Ok(()) => tracking.diagnostics.record_commit(&tracking.writes),
+                Err(e) if CommitConflictError::is_cause_of(e) => tracking
+                    .diagnostics
+                    .record_conflict(tracking.began_at, &tracking.writes),
+                Err(_) => {}
+            }
+        }
+
+        result?;
+
         self.notifications.submit_queue(
             self.notify_queue
                 .take()
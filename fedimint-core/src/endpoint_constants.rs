@@ -1,35 +1,109 @@
 pub const ACCOUNT_ENDPOINT: &str = "account";
 pub const ADD_CONFIG_GEN_PEER_ENDPOINT: &str = "add_config_gen_peer";
+pub const ADD_NOTE_TIERS_ENDPOINT: &str = "add_note_tiers";
+pub const ANNOUNCEMENT_VOTE_ENDPOINT: &str = "announcement_vote";
+pub const ANNOUNCEMENT_VOTES_ENDPOINT: &str = "announcement_votes";
+pub const ANNOUNCEMENTS_ENDPOINT: &str = "announcements";
+pub const ATTEST_FINAL_STATE_ENDPOINT: &str = "attest_final_state";
+pub const API_ENDPOINT_UPDATES_ENDPOINT: &str = "api_endpoint_updates";
+pub const API_USAGE_ENDPOINT: &str = "api_usage";
+pub const APPROVE_MODULE_ENDPOINT: &str = "approve_module";
 pub const AUDIT_ENDPOINT: &str = "audit";
 pub const AUTH_ENDPOINT: &str = "auth";
+pub const AWAIT_ANNOUNCEMENT_ENDPOINT: &str = "await_announcement";
 pub const AWAIT_OUTPUT_OUTCOME_ENDPOINT: &str = "await_output_outcome";
+pub const AWAIT_OUTPUT_OUTCOMES_ENDPOINT: &str = "await_output_outcomes";
 pub const BACKUP_ENDPOINT: &str = "backup";
 pub const BLOCK_COUNT_ENDPOINT: &str = "block_count";
 pub const BLOCK_COUNT_LOCAL_ENDPOINT: &str = "block_count_local";
+pub const BUMP_PEG_OUT_FEE_ENDPOINT: &str = "bump_peg_out_fee";
+pub const CHECKPOINTS_ENDPOINT: &str = "checkpoints";
 pub const CONFIG_ENDPOINT: &str = "config";
 pub const CONFIG_HASH_ENDPOINT: &str = "config_hash";
+pub const CONSENSUS_CONFIG_VERSIONS_ENDPOINT: &str = "consensus_config_versions";
+pub const CONSENSUS_ITEM_LOGGING_ENDPOINT: &str = "consensus_item_logging";
+pub const CREATE_CHECKPOINT_ENDPOINT: &str = "create_checkpoint";
+pub const CREATE_INVITE_CODE_ENDPOINT: &str = "create_invite_code";
+pub const CREATE_SCOPED_TOKEN_ENDPOINT: &str = "create_scoped_token";
+pub const DB_CONFLICTS_ENDPOINT: &str = "db_conflicts";
+pub const DKG_STATUS_ENDPOINT: &str = "dkg_status";
+pub const DUMP_DIAGNOSTICS_ENDPOINT: &str = "dump_diagnostics";
+pub const EXPORT_CONFIG_BUNDLE_ENDPOINT: &str = "export_config_bundle";
+pub const FEDERATION_META_ENDPOINT: &str = "federation_meta";
+pub const FEE_INCOME_ENDPOINT: &str = "fee_income";
 pub const FETCH_BLOCK_COUNT_ENDPOINT: &str = "fetch_block_count";
+pub const FINAL_STATE_ATTESTATION_ENDPOINT: &str = "final_state_attestation";
 pub const AWAIT_BLOCK_ENDPOINT: &str = "await_block";
+pub const AWAIT_EVENTS_ENDPOINT: &str = "await_events";
 pub const AWAIT_SIGNED_BLOCK_ENDPOINT: &str = "await_signed_block";
+pub const AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT: &str = "await_signed_block_header";
+pub const AWAIT_TRANSACTION_OUTCOME_ENDPOINT: &str = "await_transaction_outcome";
+pub const AWAIT_TRANSACTION_PROOF_ENDPOINT: &str = "await_transaction_proof";
+pub const AWAIT_TRANSACTION_RECEIPT_ENDPOINT: &str = "await_transaction_receipt";
 pub const GET_CONFIG_GEN_PEERS_ENDPOINT: &str = "get_config_gen_peers";
 pub const GET_CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT: &str = "get_consensus_config_gen_params";
 pub const GET_DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT: &str = "get_default_config_gen_params";
 pub const GET_VERIFY_CONFIG_HASH_ENDPOINT: &str = "get_verify_config_hash";
 pub const INVITE_CODE_ENDPOINT: &str = "invite_code";
+pub const INVITE_CODES_ENDPOINT: &str = "invite_codes";
+pub const JOIN_ENDPOINT: &str = "join";
+pub const KEY_EPOCHS_ENDPOINT: &str = "key_epochs";
+pub const KEY_ROTATION_ENDPOINT: &str = "key_rotation";
+pub const KV_ENTRIES_ENDPOINT: &str = "kv_entries";
+pub const KV_ENTRY_ENDPOINT: &str = "kv_entry";
+pub const KV_VOTE_ENDPOINT: &str = "kv_vote";
+pub const KV_VOTES_ENDPOINT: &str = "kv_votes";
 pub const LIST_GATEWAYS_ENDPOINT: &str = "list_gateways";
+pub const MIGRATE_TO_TAPROOT_ENDPOINT: &str = "migrate_to_taproot";
 pub const MODULES_CONFIG_JSON_ENDPOINT: &str = "modules_config_json";
+pub const MODULE_FAILURES_ENDPOINT: &str = "module_failures";
+pub const MODULE_PROPOSALS_ENDPOINT: &str = "module_proposals";
+pub const MODULE_UPGRADES_ENDPOINT: &str = "module_upgrades";
+pub const NOTARIZATION_ENDPOINT: &str = "notarization";
+pub const NOTE_TIER_ADDITION_ENDPOINT: &str = "note_tier_addition";
 pub const OFFER_ENDPOINT: &str = "offer";
+pub const ORACLE_RATE_ENDPOINT: &str = "oracle_rate";
+pub const ORACLE_RATES_ENDPOINT: &str = "oracle_rates";
+pub const ORACLE_SUBMISSIONS_ENDPOINT: &str = "oracle_submissions";
+pub const OVERRIDE_SAFETY_HALT_ENDPOINT: &str = "override_safety_halt";
+pub const PAYOUT_SHARES_ENDPOINT: &str = "payout_shares";
+pub const PAYOUT_VOTE_ENDPOINT: &str = "payout_vote";
+pub const PAYOUT_VOTES_ENDPOINT: &str = "payout_votes";
+pub const PAYOUTS_ENDPOINT: &str = "payouts";
+pub const PEER_HEALTH_ENDPOINT: &str = "peer_health";
 pub const PEG_OUT_FEES_ENDPOINT: &str = "peg_out_fees";
+pub const PROPOSE_FEDERATION_META_ENDPOINT: &str = "propose_federation_meta";
+pub const PROPOSE_MODULE_ENDPOINT: &str = "propose_module";
 pub const RECOVER_ENDPOINT: &str = "recover";
 pub const REGISTER_GATEWAY_ENDPOINT: &str = "register_gateway";
+pub const RESTORE_CHECKPOINT_ENDPOINT: &str = "restore_checkpoint";
+pub const REVOKE_INVITE_CODE_ENDPOINT: &str = "revoke_invite_code";
+pub const REVOKE_SCOPED_TOKEN_ENDPOINT: &str = "revoke_scoped_token";
+pub const ROTATE_KEYS_ENDPOINT: &str = "rotate_keys";
 pub const RUN_DKG_ENDPOINT: &str = "run_dkg";
+pub const SAFE_MODE_ENDPOINT: &str = "safe_mode";
+pub const SAFETY_HALT_ENDPOINT: &str = "safety_halt";
+pub const SCHEDULE_UPGRADE_ENDPOINT: &str = "schedule_upgrade";
+pub const SCOPED_TOKENS_ENDPOINT: &str = "scoped_tokens";
+pub const SERVER_TIME_ENDPOINT: &str = "server_time";
+pub const SESSION_TRANSACTIONS_ENDPOINT: &str = "session_transactions";
 pub const SET_CONFIG_GEN_CONNECTIONS_ENDPOINT: &str = "set_config_gen_connections";
 pub const SET_CONFIG_GEN_PARAMS_ENDPOINT: &str = "set_config_gen_params";
+pub const SET_CONSENSUS_ITEM_LOGGING_ENDPOINT: &str = "set_consensus_item_logging";
+pub const SET_FEE_RATE_FLOOR_ENDPOINT: &str = "set_fee_rate_floor";
 pub const SET_PASSWORD_ENDPOINT: &str = "set_password";
 pub const SIGN_MESSAGE_ENDPOINT: &str = "sign_message";
+pub const SIGNED_BLOCKS_ENDPOINT: &str = "signed_blocks";
+pub const SPENT_NOTES_ENDPOINT: &str = "spent_notes";
+pub const STALL_DIAGNOSTICS_ENDPOINT: &str = "stall_diagnostics";
 pub const START_CONSENSUS_ENDPOINT: &str = "start_consensus";
+pub const STATE_PROOF_ENDPOINT: &str = "state_proof";
 pub const STATUS_ENDPOINT: &str = "status";
+pub const TAPROOT_MIGRATION_ENDPOINT: &str = "taproot_migration";
 pub const TRANSACTION_ENDPOINT: &str = "transaction";
+pub const TRANSACTION_DEPENDENCIES_ENDPOINT: &str = "transaction_dependencies";
+pub const TRANSACTION_LOCATION_ENDPOINT: &str = "transaction_location";
+pub const UPDATE_API_ENDPOINT_ENDPOINT: &str = "update_api_endpoint";
 pub const VERIFIED_CONFIGS_ENDPOINT: &str = "verified_configs";
 pub const VERSION_ENDPOINT: &str = "version";
 pub const WAIT_ACCOUNT_ENDPOINT: &str = "wait_account";
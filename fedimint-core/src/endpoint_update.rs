@@ -0,0 +1,170 @@
+//! Moving the API of a guardian to a new URL
+//!
+//! The API endpoints in the client config are signed by the federation, so
+//! they can not change after config generation. A guardian moving hosts
+//! instead signs an [`ApiEndpointUpdate`] with its broadcast key once their
+//! admin requested it and submits it as a
+//! [`ConsensusItem::ApiEndpointUpdate`]. The guardians serve the latest
+//! ordered update of every peer from their API, and clients and peers connect
+//! to the updated URLs after verifying the updates against the broadcast
+//! public keys. Every update carries a sequence number that has to increase,
+//! so an old update can not be replayed to move a guardian back.
+//!
+//! An update signed before a key rotation no longer verifies with the rotated
+//! keys, so the guardian signs and submits its latest URL again.
+//!
+//! [`ConsensusItem::ApiEndpointUpdate`]: crate::epoch::ConsensusItem::ApiEndpointUpdate
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use secp256k1_zkp::{schnorr, Message, PublicKey, SECP256K1};
+
+use crate::config::PeerUrl;
+use crate::encoding::{Decodable, Encodable};
+use crate::{serde_as_encodable_hex, PeerId};
+
+/// Tag of the signed message, such that an update signature can not be
+/// mistaken for a signature of the atomic broadcast
+const UPDATE_TAG: &[u8] = b"fedimint-api-endpoint-update";
+
+/// A new API endpoint of a guardian, signed with its broadcast key
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct ApiEndpointUpdate {
+    pub peer: PeerId,
+    /// Has to be larger than the sequence of the previous update of the peer
+    pub sequence: u64,
+    pub url: PeerUrl,
+    pub signature: schnorr::Signature,
+}
+
+serde_as_encodable_hex!(ApiEndpointUpdate);
+
+impl ApiEndpointUpdate {
+    /// The message the guardian signs with its broadcast key
+    pub fn message(peer: PeerId, sequence: u64, url: &PeerUrl) -> Message {
+        let mut engine = sha256::HashEngine::default();
+
+        engine
+            .write_all(UPDATE_TAG)
+            .expect("Writing to a hash engine can not fail");
+
+        (peer, sequence, url.clone())
+            .consensus_encode(&mut engine)
+            .expect("Writing to a hash engine can not fail");
+
+        Message::from_slice(&sha256::Hash::from_engine(engine).into_inner())
+            .expect("A sha256 hash is a valid message")
+    }
+
+    /// Checks the signature against the broadcast public key of the peer
+    pub fn verify(&self, broadcast_public_keys: &BTreeMap<PeerId, PublicKey>) -> bool {
+        let Some(public_key) = broadcast_public_keys.get(&self.peer) else {
+            return false;
+        };
+
+        SECP256K1
+            .verify_schnorr(
+                &self.signature,
+                &Self::message(self.peer, self.sequence, &self.url),
+                &public_key.x_only_public_key().0,
+            )
+            .is_ok()
+    }
+}
+
+/// Replaces the API endpoints of the guardians with the URLs of the valid
+/// update with the highest sequence for each of them
+pub fn apply_endpoint_updates(
+    endpoints: &BTreeMap<PeerId, PeerUrl>,
+    updates: impl IntoIterator<Item = ApiEndpointUpdate>,
+    broadcast_public_keys: &BTreeMap<PeerId, PublicKey>,
+) -> BTreeMap<PeerId, PeerUrl> {
+    let mut latest: BTreeMap<PeerId, ApiEndpointUpdate> = BTreeMap::new();
+
+    for update in updates {
+        if !endpoints.contains_key(&update.peer) || !update.verify(broadcast_public_keys) {
+            continue;
+        }
+
+        if latest
+            .get(&update.peer)
+            .map_or(true, |previous| previous.sequence < update.sequence)
+        {
+            latest.insert(update.peer, update);
+        }
+    }
+
+    endpoints
+        .iter()
+        .map(|(peer, url)| match latest.remove(peer) {
+            Some(update) => (*peer, update.url),
+            None => (*peer, url.clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use secp256k1_zkp::{KeyPair, PublicKey, SecretKey, SECP256K1};
+
+    use super::{apply_endpoint_updates, ApiEndpointUpdate};
+    use crate::config::PeerUrl;
+    use crate::PeerId;
+
+    fn peer_url(url: &str) -> PeerUrl {
+        PeerUrl {
+            url: url.parse().expect("Valid url"),
+            name: "guardian".to_string(),
+            fallback_urls: vec![],
+        }
+    }
+
+    fn update(keypair: &KeyPair, peer: PeerId, sequence: u64, url: &str) -> ApiEndpointUpdate {
+        let url = peer_url(url);
+        let message = ApiEndpointUpdate::message(peer, sequence, &url);
+
+        ApiEndpointUpdate {
+            peer,
+            sequence,
+            url,
+            signature: SECP256K1.sign_schnorr_no_aux_rand(&message, keypair),
+        }
+    }
+
+    #[test]
+    fn applies_latest_valid_update() {
+        let keypairs = (0..2u8)
+            .map(|i| {
+                SecretKey::from_slice(&[i + 1; 32])
+                    .expect("Valid secret key")
+                    .keypair(SECP256K1)
+            })
+            .collect::<Vec<_>>();
+        let public_keys: BTreeMap<PeerId, PublicKey> = keypairs
+            .iter()
+            .enumerate()
+            .map(|(i, keypair)| (PeerId::from(i as u16), keypair.public_key()))
+            .collect();
+        let endpoints = BTreeMap::from([
+            (PeerId::from(0), peer_url("wss://old-0.example")),
+            (PeerId::from(1), peer_url("wss://old-1.example")),
+        ]);
+
+        let updates = vec![
+            update(&keypairs[0], PeerId::from(0), 2, "wss://new-0.example"),
+            // replayed older update
+            update(&keypairs[0], PeerId::from(0), 1, "wss://stale-0.example"),
+            // signed by another guardian
+            update(&keypairs[0], PeerId::from(1), 1, "wss://forged-1.example"),
+        ];
+
+        let updated = apply_endpoint_updates(&endpoints, updates, &public_keys);
+
+        assert_eq!(updated[&PeerId::from(0)], peer_url("wss://new-0.example"));
+        assert_eq!(updated[&PeerId::from(1)], peer_url("wss://old-1.example"));
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::{BTreeMap, BTreeSet};
 
+use anyhow::ensure;
 use fedimint_core::core::DynModuleConsensusItem as ModuleConsensusItem;
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable, UnzipConsensus};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
@@ -7,8 +8,14 @@ use fedimint_core::PeerId;
 use serde::{Deserialize, Serialize};
 use threshold_crypto::{PublicKeySet, Signature, SignatureShare};
 
+use crate::endpoint_update::ApiEndpointUpdate;
+use crate::lifecycle::{AddModuleProposal, ModuleUpgrade};
+use crate::meta::FederationMetaShare;
+use crate::migration::FinalStateAttestationShare;
+use crate::rotation::{KeyRotationConfirmation, KeyRotationDeal};
 use crate::serde_as_encodable_hex;
-use crate::transaction::Transaction;
+use crate::state_proof::StateCommitment;
+use crate::transaction::{Transaction, TransactionLimits};
 
 /// All the items that may be produced during a consensus epoch
 #[derive(Debug, Clone, Eq, PartialEq, Hash, UnzipConsensus, Encodable, Decodable)]
@@ -19,6 +26,105 @@ pub enum ConsensusItem {
     Transaction(Transaction),
     /// Any data that modules require consensus on
     Module(ModuleConsensusItem),
+    /// Threshold sign an attestation that the federation is shutting down
+    FinalStateAttestationShare(FinalStateAttestationShare),
+    /// Approve adding a module instance to the running federation
+    AddModule(AddModuleProposal),
+    /// Schedule the activation of a new module consensus version
+    ScheduleUpgrade(ModuleUpgrade),
+    /// Deal new keys for a rotation of the broadcast and auth keys
+    KeyRotationDeal(KeyRotationDeal),
+    /// Confirm the keys dealt by all guardians for a key rotation
+    KeyRotationConfirmation(KeyRotationConfirmation),
+    /// Move the API of the submitting guardian to a new URL
+    ApiEndpointUpdate(ApiEndpointUpdate),
+    /// Threshold sign an announcement of the federation's metadata
+    FederationMetaShare(FederationMetaShare),
+    /// Commit to the consensus state after the previous session
+    StateCommitment(StateCommitment),
+}
+
+/// Size limits for the batches of consensus items the guardians attach to the
+/// units of the atomic broadcast, which bound the memory a malicious guardian
+/// can make us allocate per unit
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ConsensusLimits {
+    /// Maximum size of a single encoded consensus item in bytes
+    pub max_item_bytes: u32,
+    /// Maximum size of an encoded batch of consensus items in bytes
+    pub max_batch_bytes: u32,
+    /// Limits on the transactions we accept, which are advertised to the
+    /// clients
+    #[serde(default)]
+    pub transaction: TransactionLimits,
+}
+
+impl Default for ConsensusLimits {
+    fn default() -> Self {
+        // This limits the RAM consumption of a unit to roughly 10kB
+        Self {
+            max_item_bytes: 10_000,
+            max_batch_bytes: 10_000,
+            transaction: TransactionLimits::default(),
+        }
+    }
+}
+
+impl ConsensusLimits {
+    /// Upper bound for [`Self::max_batch_bytes`] since the atomic broadcast
+    /// keeps all units of a session in memory
+    pub const MAX_BATCH_BYTES: u32 = 1_000_000;
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.max_batch_bytes <= Self::MAX_BATCH_BYTES,
+            "Batch size limit exceeds {} bytes",
+            Self::MAX_BATCH_BYTES
+        );
+        ensure!(
+            self.max_item_bytes <= self.max_batch_bytes,
+            "Item size limit exceeds the batch size limit"
+        );
+        ensure!(
+            self.transaction.max_bytes <= self.max_item_bytes,
+            "Transaction size limit exceeds the item size limit"
+        );
+
+        Ok(())
+    }
+
+    /// Decodes a batch of consensus items attached to a unit by a peer,
+    /// failing if the batch or any of its items exceed our limits
+    pub fn decode_batch(
+        &self,
+        bytes: &[u8],
+        decoders: &ModuleDecoderRegistry,
+    ) -> Result<Vec<ConsensusItem>, DecodeError> {
+        if bytes.len() > self.max_batch_bytes as usize {
+            return Err(DecodeError::from_str("Batch exceeds the size limit"));
+        }
+
+        let mut reader = bytes;
+        let len = u64::consensus_decode(&mut reader, decoders)?;
+
+        // the length is not trusted, we run out of bytes to decode first
+        let mut items = Vec::new();
+
+        for _ in 0..len {
+            let remaining = reader.len();
+            let item = ConsensusItem::consensus_decode(&mut reader, decoders)?;
+
+            if remaining - reader.len() > self.max_item_bytes as usize {
+                return Err(DecodeError::from_str(
+                    "Consensus item exceeds the size limit",
+                ));
+            }
+
+            items.push(item);
+        }
+
+        Ok(items)
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -95,12 +201,15 @@ impl Decodable for SerdeSignatureShare {
 mod tests {
     use std::collections::{BTreeMap, BTreeSet};
 
-    use fedimint_core::epoch::combine_sigs;
+    use fedimint_core::encoding::Encodable;
+    use fedimint_core::epoch::{combine_sigs, ConsensusItem, ConsensusLimits};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::PeerId;
     use rand::rngs::OsRng;
     use threshold_crypto::SecretKeySet;
 
     use crate::epoch::SerdeSignatureShare;
+    use crate::transaction::{Transaction, TransactionError, TransactionLimits};
 
     #[test]
     fn combines_single_share() {
@@ -143,4 +252,69 @@ mod tests {
             Err(BTreeSet::from([PeerId::from(1)]))
         );
     }
+
+    #[test]
+    fn decode_batch_enforces_limits() {
+        let share = SerdeSignatureShare(
+            SecretKeySet::random(0, &mut OsRng)
+                .secret_key_share(0)
+                .sign("test message"),
+        );
+        let items = vec![ConsensusItem::ClientConfigSignatureShare(share); 2];
+        let bytes = items.consensus_encode_to_vec().unwrap();
+        let item_bytes = items[0].consensus_encode_to_vec().unwrap().len() as u32;
+        let decoders = ModuleDecoderRegistry::default();
+
+        let limits = ConsensusLimits {
+            max_item_bytes: item_bytes,
+            max_batch_bytes: bytes.len() as u32,
+            ..ConsensusLimits::default()
+        };
+        assert_eq!(limits.decode_batch(&bytes, &decoders).unwrap(), items);
+
+        let limits = ConsensusLimits {
+            max_item_bytes: item_bytes - 1,
+            max_batch_bytes: bytes.len() as u32,
+            ..ConsensusLimits::default()
+        };
+        assert!(limits.decode_batch(&bytes, &decoders).is_err());
+
+        let limits = ConsensusLimits {
+            max_item_bytes: item_bytes,
+            max_batch_bytes: bytes.len() as u32 - 1,
+            ..ConsensusLimits::default()
+        };
+        assert!(limits.decode_batch(&bytes, &decoders).is_err());
+    }
+
+    #[test]
+    fn transaction_limits() {
+        let limits = ConsensusLimits::default();
+        assert!(limits.validate().is_ok());
+
+        let transaction = Transaction {
+            inputs: vec![],
+            outputs: vec![],
+            signature: None,
+        };
+        assert!(limits.transaction.check(&transaction).is_ok());
+
+        let tiny = TransactionLimits {
+            max_bytes: 1,
+            ..limits.transaction
+        };
+        assert!(matches!(
+            tiny.check(&transaction),
+            Err(TransactionError::TooLarge { limit: 1, .. })
+        ));
+
+        let oversized = ConsensusLimits {
+            transaction: TransactionLimits {
+                max_bytes: limits.max_item_bytes + 1,
+                ..limits.transaction
+            },
+            ..limits
+        };
+        assert!(oversized.validate().is_err());
+    }
 }
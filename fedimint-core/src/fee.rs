@@ -0,0 +1,285 @@
+//! Fees the federation charges on transactions on top of the fees of the
+//! modules
+//!
+//! The [`FeeSchedule`] is part of the consensus config and charges a flat and
+//! a proportional fee on the inputs and on the outputs every module contributes
+//! to a transaction. The fees of all transactions accumulate as the
+//! [`FeeIncome`] of the federation, which modules pay out to the guardians via
+//! [`InputMeta::fee_payout`](crate::module::InputMeta::fee_payout).
+
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
+use serde::{Deserialize, Serialize};
+
+use crate::core::ModuleInstanceId;
+use crate::encoding::{Decodable, Encodable};
+use crate::Amount;
+
+/// A flat fee plus a fee proportional to an amount
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct FeeRate {
+    pub base: Amount,
+    pub parts_per_million: u64,
+}
+
+impl FeeRate {
+    /// Upper bound for [`Self::parts_per_million`], a fee of 100%
+    pub const MAX_PARTS_PER_MILLION: u64 = 1_000_000;
+
+    pub const ZERO: FeeRate = FeeRate {
+        base: Amount::ZERO,
+        parts_per_million: 0,
+    };
+
+    /// The fee for `amount`, rounded down to the next msat
+    pub fn fee(&self, amount: Amount) -> Amount {
+        let proportional = u128::from(amount.msats) * u128::from(self.parts_per_million)
+            / u128::from(Self::MAX_PARTS_PER_MILLION);
+
+        self.base + Amount::from_msats(proportional as u64)
+    }
+}
+
+impl Default for FeeRate {
+    fn default() -> Self {
+        FeeRate::ZERO
+    }
+}
+
+/// The fees a module charges on its inputs and outputs in a transaction
+#[derive(
+    Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct ModuleFeeSchedule {
+    /// Charged on the total amount of the module's inputs in a transaction
+    pub input: FeeRate,
+    /// Charged on the total amount of the module's outputs in a transaction
+    pub output: FeeRate,
+}
+
+/// The fees charged for the module instances of the federation, modules
+/// without an entry charge no fees
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct FeeSchedule {
+    pub modules: BTreeMap<ModuleInstanceId, ModuleFeeSchedule>,
+}
+
+impl FeeSchedule {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (module_instance_id, schedule) in &self.modules {
+            for rate in [schedule.input, schedule.output] {
+                ensure!(
+                    rate.parts_per_million <= FeeRate::MAX_PARTS_PER_MILLION,
+                    "Fee of module {module_instance_id} exceeds 100%"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn module(&self, module_instance_id: ModuleInstanceId) -> ModuleFeeSchedule {
+        self.modules
+            .get(&module_instance_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The fee of a transaction whose inputs and outputs sum up to the given
+    /// amounts per module, a module is only charged for the side it has items
+    /// on
+    pub fn transaction_fee(
+        &self,
+        inputs: &BTreeMap<ModuleInstanceId, Amount>,
+        outputs: &BTreeMap<ModuleInstanceId, Amount>,
+    ) -> Amount {
+        let input_fees = inputs
+            .iter()
+            .map(|(id, amount)| self.module(*id).input.fee(*amount));
+        let output_fees = outputs
+            .iter()
+            .map(|(id, amount)| self.module(*id).output.fee(*amount));
+
+        input_fees.chain(output_fees).sum()
+    }
+
+    /// The largest amount of change the module can add as outputs to a
+    /// transaction, such that the change and the fees it causes do not exceed
+    /// `excess`. The module's outputs in the transaction so far sum up to
+    /// `outputs`, if it has any.
+    ///
+    /// Due to the flat fee and the rounding of the proportional fee the result
+    /// may leave a few msats of the excess, at most
+    /// [`Self::max_change_residue`], which the federation collects as fees.
+    pub fn max_change(
+        &self,
+        module_instance_id: ModuleInstanceId,
+        outputs: Option<Amount>,
+        excess: Amount,
+    ) -> Amount {
+        let rate = self.module(module_instance_id).output;
+        let fee_before = outputs.map_or(Amount::ZERO, |outputs| rate.fee(outputs));
+        let cost = |change: Amount| {
+            change + rate.fee(outputs.unwrap_or(Amount::ZERO) + change) - fee_before
+        };
+
+        // start from the exact solution ignoring the rounding and correct it, the
+        // rounding error is at most one msat
+        let mut change = Amount::from_msats(
+            (u128::from(excess.saturating_sub(rate.base).msats)
+                * u128::from(FeeRate::MAX_PARTS_PER_MILLION)
+                / u128::from(FeeRate::MAX_PARTS_PER_MILLION + rate.parts_per_million))
+                as u64,
+        );
+
+        while change != Amount::ZERO && cost(change) > excess {
+            change = change - Amount::from_msats(1);
+        }
+
+        while cost(change + Amount::from_msats(1)) <= excess {
+            change += Amount::from_msats(1);
+        }
+
+        change
+    }
+
+    /// The largest excess [`Self::max_change`] may leave of a transaction for
+    /// any module creating the change, which is the only amount a transaction
+    /// may be overfunded by. Without any output fees transactions have to be
+    /// balanced exactly.
+    pub fn max_change_residue(&self) -> Amount {
+        self.modules
+            .values()
+            .map(|schedule| {
+                let rounding = u64::from(schedule.output.parts_per_million != 0);
+
+                schedule.output.base + Amount::from_msats(rounding)
+            })
+            .max()
+            .unwrap_or(Amount::ZERO)
+    }
+}
+
+/// The fees the federation collected from transactions and paid out to the
+/// guardians so far
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct FeeIncome {
+    pub collected: Amount,
+    pub paid_out: Amount,
+}
+
+impl Default for FeeIncome {
+    fn default() -> Self {
+        FeeIncome {
+            collected: Amount::ZERO,
+            paid_out: Amount::ZERO,
+        }
+    }
+}
+
+impl FeeIncome {
+    /// The fees that are not paid out yet
+    pub fn available(&self) -> Amount {
+        self.collected.saturating_sub(self.paid_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{FeeRate, FeeSchedule, ModuleFeeSchedule};
+    use crate::Amount;
+
+    fn schedule(base: u64, parts_per_million: u64) -> FeeSchedule {
+        let rate = FeeRate {
+            base: Amount::from_msats(base),
+            parts_per_million,
+        };
+
+        FeeSchedule {
+            modules: BTreeMap::from([(
+                0,
+                ModuleFeeSchedule {
+                    input: rate,
+                    output: rate,
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn charges_modules_with_items() {
+        let fees = schedule(100, 1_000);
+        let inputs = BTreeMap::from([(0, Amount::from_msats(10_000))]);
+        let outputs = BTreeMap::from([(1, Amount::from_msats(10_000))]);
+
+        // the second module has no fees configured
+        assert_eq!(
+            fees.transaction_fee(&inputs, &outputs),
+            Amount::from_msats(110)
+        );
+        assert_eq!(
+            fees.transaction_fee(&BTreeMap::new(), &BTreeMap::new()),
+            Amount::ZERO
+        );
+    }
+
+    #[test]
+    fn max_change_covers_its_fees() {
+        for (base, parts_per_million) in [(0, 0), (100, 0), (0, 2_500), (1_000, 10_000)] {
+            let fees = schedule(base, parts_per_million);
+            let rate = fees.module(0).output;
+
+            for excess in [0, 1, 99, 100, 1_000, 123_456, 10_000_000] {
+                let excess = Amount::from_msats(excess);
+
+                for outputs in [None, Some(Amount::from_msats(5_000))] {
+                    let change = fees.max_change(0, outputs, excess);
+                    let cost = |change: Amount| match outputs {
+                        Some(outputs) => change + rate.fee(outputs + change) - rate.fee(outputs),
+                        None => change + rate.fee(change),
+                    };
+
+                    // without change the whole excess is left over
+                    let residue = if change == Amount::ZERO {
+                        excess
+                    } else {
+                        assert!(cost(change) <= excess);
+                        excess - cost(change)
+                    };
+                    assert!(cost(change + Amount::from_msats(1)) > excess);
+                    assert!(residue <= fees.max_change_residue());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_residue_without_output_fees() {
+        assert_eq!(FeeSchedule::default().max_change_residue(), Amount::ZERO);
+
+        let fees = FeeSchedule {
+            modules: BTreeMap::from([(
+                0,
+                ModuleFeeSchedule {
+                    input: FeeRate {
+                        base: Amount::from_msats(100),
+                        parts_per_million: 1_000,
+                    },
+                    output: FeeRate::ZERO,
+                },
+            )]),
+        };
+        assert_eq!(fees.max_change_residue(), Amount::ZERO);
+    }
+
+    #[test]
+    fn rejects_fees_above_100_percent() {
+        assert!(schedule(0, 1_000_000).validate().is_ok());
+        assert!(schedule(0, 1_000_001).validate().is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Invite codes created by the admin of a guardian
+//!
+//! Besides the invite code of its config, a guardian can hand out further
+//! invite codes, e.g. one per community it onboards, each with a label, an
+//! optional expiry and an optional limit on the clients joining with it. Its
+//! admin can revoke them at any time. Clients join under a random
+//! [`ClientJoinId`], which lets the guardian tell which code onboarded which
+//! client without learning anything else about it.
+
+use std::time::SystemTime;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::api::InviteCode;
+use crate::encoding::{Decodable, Encodable};
+use crate::serde_as_encodable_hex;
+
+/// Random id a client joins a federation under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Encodable, Decodable)]
+pub struct ClientJoinId(pub [u8; 16]);
+
+serde_as_encodable_hex!(ClientJoinId);
+
+impl ClientJoinId {
+    pub fn new_random() -> Self {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(bytes)
+    }
+}
+
+/// Request of a client to join the federation with an invite code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub invite_code: InviteCode,
+    /// Joining again under the same id does not count as another use
+    pub client_id: ClientJoinId,
+}
+
+/// Request of an admin to create an invite code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateInviteCodeRequest {
+    pub label: String,
+    #[serde(default)]
+    pub expires_at: Option<SystemTime>,
+    /// How many clients can join with the code, unlimited if not set
+    #[serde(default)]
+    pub max_uses: Option<u64>,
+}
+
+/// An invite code created by the admin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ManagedInviteCode {
+    pub label: String,
+    pub created_at: SystemTime,
+    pub expires_at: Option<SystemTime>,
+    pub max_uses: Option<u64>,
+    pub revoked: bool,
+}
+
+impl ManagedInviteCode {
+    /// Whether clients can still join with the code, not counting its uses
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        !self.revoked && self.expires_at.map_or(true, |expires_at| now < expires_at)
+    }
+}
+
+/// A client that joined with an invite code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InviteCodeRedemption {
+    pub client_id: ClientJoinId,
+    pub joined_at: SystemTime,
+}
+
+/// An invite code created by the admin and the clients that joined with it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InviteCodeStatus {
+    pub invite_code: InviteCode,
+    pub code: ManagedInviteCode,
+    /// Includes config downloads that did not join under a client id
+    pub uses: u64,
+    pub redemptions: Vec<InviteCodeRedemption>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::ManagedInviteCode;
+
+    #[test]
+    fn expired_and_revoked_codes_are_inactive() {
+        let created_at = UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut code = ManagedInviteCode {
+            label: "meetup".to_string(),
+            created_at,
+            expires_at: Some(created_at + Duration::from_secs(60)),
+            max_uses: Some(10),
+            revoked: false,
+        };
+
+        assert!(code.is_active(created_at));
+        assert!(code.is_active(created_at + Duration::from_secs(59)));
+        assert!(!code.is_active(created_at + Duration::from_secs(60)));
+
+        code.expires_at = None;
+        assert!(code.is_active(created_at + Duration::from_secs(60)));
+
+        code.revoked = true;
+        assert!(!code.is_active(created_at));
+    }
+}
@@ -34,14 +34,22 @@ pub mod core;
 pub mod db;
 pub mod encoding;
 pub mod endpoint_constants;
+pub mod endpoint_update;
 pub mod epoch;
+pub mod fee;
 pub mod fmt_utils;
 pub mod hex;
+pub mod invite;
+pub mod lifecycle;
 #[macro_use]
 pub mod macros;
+pub mod meta;
+pub mod migration;
 pub mod module;
 pub mod net;
 pub mod query;
+pub mod rotation;
+pub mod state_proof;
 pub mod task;
 pub mod tiered;
 pub mod tiered_multi;
@@ -49,6 +57,7 @@ pub mod time;
 pub mod timing;
 pub mod transaction;
 pub mod txoproof;
+pub mod usage;
 pub mod util;
 
 /// Atomic BFT unit containing consensus items
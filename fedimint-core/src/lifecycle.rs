@@ -0,0 +1,102 @@
+//! Adding module instances to a running federation
+//!
+//! An admin proposes a new module instance, for which their guardian generates
+//! the module config of every guardian as a trusted dealer. Only the consensus
+//! config is public, the config of every guardian is encrypted to their
+//! broadcast key. A guardian submits the proposal as a
+//! [`ConsensusItem::AddModule`] once their admin approved it, which counts as
+//! their approval. After all guardians approved, every guardian initializes
+//! the module at the end of the session that ordered the last approval, such
+//! that all of them start processing its items from the same session on.
+//!
+//! Upgrading the consensus version of a module instance works similarly: a
+//! guardian submits a [`ModuleUpgrade`] as a [`ConsensusItem::ScheduleUpgrade`]
+//! once their admin scheduled it. After all guardians did so, every guardian
+//! stops the consensus before the activation session and only continues with
+//! the new version, so a guardian whose binary does not support it refuses to
+//! run the session instead of forking from the federation.
+//!
+//! [`ConsensusItem::AddModule`]: crate::epoch::ConsensusItem::AddModule
+//! [`ConsensusItem::ScheduleUpgrade`]: crate::epoch::ConsensusItem::ScheduleUpgrade
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin_hashes::sha256;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigGenModuleParams, ServerModuleConsensusConfig};
+use crate::core::{ModuleInstanceId, ModuleKind};
+use crate::encoding::{Decodable, Encodable};
+use crate::module::ModuleConsensusVersion;
+use crate::PeerId;
+
+/// Proposal to add a module instance to a running federation
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct AddModuleProposal {
+    /// The guardian that generated the module configs
+    pub proposer: PeerId,
+    pub module_instance_id: ModuleInstanceId,
+    /// The module config all guardians have to agree on
+    pub consensus: ServerModuleConsensusConfig,
+    /// The module config of every guardian, encrypted to their broadcast key
+    pub peer_configs: BTreeMap<PeerId, Vec<u8>>,
+}
+
+impl AddModuleProposal {
+    /// The id admins use to approve the proposal
+    pub fn id(&self) -> sha256::Hash {
+        self.consensus_hash()
+    }
+}
+
+/// Request of an admin to add a module instance of the given kind
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProposeModuleRequest {
+    pub kind: ModuleKind,
+    pub params: ConfigGenModuleParams,
+}
+
+/// A proposed module instance as seen by the consensus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleProposalStatus {
+    pub id: sha256::Hash,
+    pub proposer: PeerId,
+    pub module_instance_id: ModuleInstanceId,
+    pub kind: ModuleKind,
+    /// The guardians whose approval has been ordered so far
+    pub approvals: BTreeSet<PeerId>,
+}
+
+/// Schedule to activate a consensus version of a module instance
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    Encodable,
+    Decodable,
+)]
+pub struct ModuleUpgrade {
+    pub module_instance_id: ModuleInstanceId,
+    pub version: ModuleConsensusVersion,
+    /// The first session processed with the new version
+    pub activation_session: u64,
+}
+
+/// An upgrade as seen by the consensus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleUpgradeStatus {
+    pub upgrade: ModuleUpgrade,
+    /// The guardians whose schedule has been ordered so far
+    pub approvals: BTreeSet<PeerId>,
+    /// Whether all guardians scheduled the upgrade
+    pub scheduled: bool,
+    /// Whether the binary of this guardian supports the new version
+    pub supported: bool,
+}
@@ -0,0 +1,127 @@
+//! Federation metadata announced after setup
+//!
+//! The `meta` fields of the client config are fixed once the federation is
+//! set up. Guardians change what they announce to their users instead by
+//! threshold signing a [`FederationMeta`] with the key that authenticates
+//! their client config. Clients verify the announcement against the
+//! [`FederationId`] and use the valid announcement with the highest
+//! [`version`](FederationMeta::version), so a guardian can not replay an
+//! outdated one.
+
+use bitcoin_hashes::sha256;
+use serde::{Deserialize, Serialize};
+
+use crate::config::FederationId;
+use crate::encoding::{Decodable, Encodable};
+use crate::epoch::{SerdeSignature, SerdeSignatureShare};
+use crate::util::SafeUrl;
+use crate::Amount;
+
+/// Metadata of the federation announced to its users
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct FederationMeta {
+    pub federation_id: FederationId,
+    /// Has to be larger than the version of the previous announcement
+    pub version: u64,
+    pub name: Option<String>,
+    pub icon_url: Option<SafeUrl>,
+    /// Shown to users when they join the federation
+    pub welcome_message: Option<String>,
+    /// The balance the guardians recommend users not to exceed
+    pub max_balance: Option<Amount>,
+}
+
+impl FederationMeta {
+    /// The message the guardians threshold sign
+    pub fn message(&self) -> sha256::Hash {
+        self.consensus_hash()
+    }
+}
+
+/// A guardian's signature share for an announcement, submitted as a consensus
+/// item such that every guardian combines the same shares
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct FederationMetaShare {
+    pub meta: FederationMeta,
+    pub share: SerdeSignatureShare,
+}
+
+/// An announcement threshold signed by the guardians
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SignedFederationMeta {
+    pub meta: FederationMeta,
+    pub signature: SerdeSignature,
+}
+
+impl SignedFederationMeta {
+    /// Verifies that the guardians of the given federation signed the
+    /// announcement
+    pub fn verify(&self, federation_id: &FederationId) -> anyhow::Result<&FederationMeta> {
+        anyhow::ensure!(
+            self.meta.federation_id == *federation_id,
+            "Metadata is for a different federation"
+        );
+
+        anyhow::ensure!(
+            federation_id
+                .0
+                .verify(&self.signature.0, self.meta.message()),
+            "Metadata signature is invalid"
+        );
+
+        Ok(&self.meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+    use threshold_crypto::SecretKeySet;
+
+    use super::{FederationMeta, SignedFederationMeta};
+    use crate::config::FederationId;
+    use crate::epoch::{combine_sigs, SerdeSignatureShare};
+    use crate::util::SafeUrl;
+    use crate::{Amount, PeerId};
+
+    #[test]
+    fn verify_federation_meta() {
+        let sks = SecretKeySet::random(1, &mut OsRng);
+        let federation_id = FederationId(sks.public_keys().public_key());
+        let other_id = FederationId(
+            SecretKeySet::random(1, &mut OsRng)
+                .public_keys()
+                .public_key(),
+        );
+
+        let meta = FederationMeta {
+            federation_id,
+            version: 1,
+            name: Some("Community Mint".to_string()),
+            icon_url: Some(SafeUrl::parse("https://mint.example/icon.png").unwrap()),
+            welcome_message: None,
+            max_balance: Some(Amount::from_sats(100_000)),
+        };
+
+        let shares = [0usize, 1]
+            .into_iter()
+            .map(|peer| {
+                let share = sks.secret_key_share(peer).sign(meta.message());
+
+                (PeerId::from(peer as u16), SerdeSignatureShare(share))
+            })
+            .collect();
+
+        let signed = SignedFederationMeta {
+            signature: combine_sigs(&sks.public_keys(), &shares, &meta.message()).unwrap(),
+            meta,
+        };
+
+        assert!(signed.verify(&federation_id).is_ok());
+        assert!(signed.verify(&other_id).is_err());
+
+        let mut forged = signed.clone();
+        forged.meta.version += 1;
+        assert!(forged.verify(&federation_id).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Attestations for migrating users from a federation that is shutting down
+//!
+//! The guardians of a closing federation threshold sign a
+//! [`FinalStateAttestation`] with the same key that authenticates their client
+//! config. Since the key is the [`FederationId`] every client can verify the
+//! attestation before moving its funds to the successor federation named in
+//! it, without trusting a single guardian.
+
+use bitcoin_hashes::sha256;
+use serde::{Deserialize, Serialize};
+
+use crate::api::InviteCode;
+use crate::config::FederationId;
+use crate::encoding::{Decodable, Encodable};
+use crate::epoch::{SerdeSignature, SerdeSignatureShare};
+
+/// Statement of the guardians that their federation is shutting down and that
+/// users should migrate their funds to the successor federation
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct FinalStateAttestation {
+    /// The federation that is shutting down
+    pub federation_id: FederationId,
+    /// The federation the users should migrate to
+    pub successor: InviteCode,
+    /// Unix timestamp after which the guardians may stop serving requests
+    pub shutdown_at: u64,
+}
+
+impl FinalStateAttestation {
+    /// The message the guardians threshold sign
+    pub fn message(&self) -> sha256::Hash {
+        self.consensus_hash()
+    }
+}
+
+/// A guardian's signature share for an attestation, submitted as a consensus
+/// item such that every guardian combines the same shares
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct FinalStateAttestationShare {
+    pub attestation: FinalStateAttestation,
+    pub share: SerdeSignatureShare,
+}
+
+/// An attestation threshold signed by the guardians of the closing federation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SignedFinalStateAttestation {
+    pub attestation: FinalStateAttestation,
+    pub signature: SerdeSignature,
+}
+
+impl SignedFinalStateAttestation {
+    /// Verifies that the guardians of the given federation signed the
+    /// attestation
+    pub fn verify(&self, federation_id: &FederationId) -> anyhow::Result<&FinalStateAttestation> {
+        anyhow::ensure!(
+            self.attestation.federation_id == *federation_id,
+            "Attestation is for a different federation"
+        );
+
+        anyhow::ensure!(
+            federation_id
+                .0
+                .verify(&self.signature.0, self.attestation.message()),
+            "Attestation signature is invalid"
+        );
+
+        Ok(&self.attestation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+    use threshold_crypto::SecretKeySet;
+
+    use super::{FinalStateAttestation, SignedFinalStateAttestation};
+    use crate::api::{ClientConfigDownloadToken, InviteCode};
+    use crate::config::FederationId;
+    use crate::epoch::{combine_sigs, SerdeSignatureShare};
+    use crate::util::SafeUrl;
+    use crate::PeerId;
+
+    fn random_federation() -> (SecretKeySet, FederationId) {
+        let sks = SecretKeySet::random(1, &mut OsRng);
+        let federation_id = FederationId(sks.public_keys().public_key());
+
+        (sks, federation_id)
+    }
+
+    #[test]
+    fn verify_final_state_attestation() {
+        let (sks, federation_id) = random_federation();
+        let (_, successor_id) = random_federation();
+
+        let attestation = FinalStateAttestation {
+            federation_id,
+            successor: InviteCode {
+                url: SafeUrl::parse("ws://successor:5000").unwrap(),
+                download_token: ClientConfigDownloadToken([0; 12]),
+                id: successor_id,
+                peer_id: PeerId::from(0),
+            },
+            shutdown_at: 1_700_000_000,
+        };
+
+        let shares = [0usize, 1]
+            .into_iter()
+            .map(|peer| {
+                let share = sks.secret_key_share(peer).sign(attestation.message());
+
+                (PeerId::from(peer as u16), SerdeSignatureShare(share))
+            })
+            .collect();
+
+        let signed = SignedFinalStateAttestation {
+            signature: combine_sigs(&sks.public_keys(), &shares, &attestation.message()).unwrap(),
+            attestation,
+        };
+
+        assert!(signed.verify(&federation_id).is_ok());
+        assert!(signed.verify(&successor_id).is_err());
+
+        let mut forged = signed.clone();
+        forged.attestation.shutdown_at += 1;
+        assert!(forged.verify(&federation_id).is_err());
+    }
+}
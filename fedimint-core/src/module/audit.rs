@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Formatter};
 
 use fedimint_core::core::ModuleInstanceId;
@@ -53,6 +53,15 @@ impl Audit {
             .await;
         self.items.append(&mut new_items);
     }
+
+    /// Adds an item of the federation itself rather than of one of its modules
+    pub fn add_federation_item(&mut self, name: String, milli_sat: i64) {
+        self.items.push(AuditItem {
+            name,
+            milli_sat,
+            module_instance_id: None,
+        });
+    }
 }
 
 impl Display for Audit {
@@ -82,6 +91,10 @@ impl Display for AuditItem {
 pub struct AuditSummary {
     pub net_assets: i64,
     pub module_summaries: HashMap<ModuleInstanceId, ModuleSummary>,
+    /// Items of the federation itself by name, e.g. the fee income owed to
+    /// the guardians
+    #[serde(default)]
+    pub federation_items: BTreeMap<String, i64>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
@@ -105,6 +118,12 @@ impl AuditSummary {
                 audit.items.iter().chain(&empty_module_placeholders),
                 module_instance_id_to_kind,
             ),
+            federation_items: audit
+                .items
+                .iter()
+                .filter(|item| item.module_instance_id.is_none())
+                .map(|item| (item.name.clone(), item.milli_sat))
+                .collect(),
         }
     }
 }
@@ -227,6 +246,7 @@ fn creates_audit_summary_from_audit() {
                 },
             ),
         ]),
+        federation_items: BTreeMap::new(),
     };
 
     assert_eq!(audit_summary, expected_audit_summary);
@@ -267,7 +287,23 @@ fn audit_summary_includes_placeholders() {
                 },
             ),
         ]),
+        federation_items: BTreeMap::new(),
     };
 
     assert_eq!(audit_summary, expected_audit_summary);
 }
+
+#[test]
+fn audit_summary_includes_federation_items() {
+    let mut audit = Audit::default();
+    audit.add_federation_item("Fee income".to_string(), -1_000);
+
+    let audit_summary = AuditSummary::from_audit(&audit, &HashMap::new());
+
+    assert_eq!(audit_summary.net_assets, -1_000);
+    assert!(audit_summary.module_summaries.is_empty());
+    assert_eq!(
+        audit_summary.federation_items,
+        BTreeMap::from([("Fee income".to_string(), -1_000)])
+    );
+}
@@ -0,0 +1,308 @@
+//! Typed events emitted by server modules that clients can subscribe to
+//!
+//! Instead of every module growing its own long-polling endpoints for "tell
+//! me when X happens", modules append [`ModuleEvent`]s to a per-module event
+//! log with [`emit_event`]. Every module automatically exposes the log via the
+//! [`AWAIT_EVENTS_ENDPOINT`] long-polling endpoint, which returns the events
+//! matching an [`EventFilter`]. Clients consume it as a stream of typed events
+//! via [`subscribe_events`].
+//!
+//! The event log is local to each guardian: events emitted while processing
+//! consensus items appear in the same order on all guardians, while events
+//! emitted from API requests (e.g. gateway registrations) do not. Events are
+//! therefore only a notification mechanism, clients have to query the
+//! consensus state if they need it to be agreed upon.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::time::Duration;
+
+use fedimint_logging::LOG_CLIENT_NET_API;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::{api_endpoint, ApiEndpoint, ApiRequestErased};
+use crate::api::DynModuleApi;
+use crate::db::{Database, DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped};
+use crate::encoding::{Decodable, Encodable};
+use crate::endpoint_constants::AWAIT_EVENTS_ENDPOINT;
+use crate::server::DynServerModule;
+use crate::task::{sleep, MaybeSend};
+use crate::util::BoxStream;
+use crate::{impl_db_record, PeerId};
+
+/// Maximum number of events returned by a single call to the
+/// [`AWAIT_EVENTS_ENDPOINT`]
+pub const MAX_EVENTS_PER_RESPONSE: u64 = 100;
+
+/// How long a subscription waits before retrying after a failed request
+const SUBSCRIPTION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// An event a server module can emit, identified by its `KIND`
+///
+/// The `KIND` has to be unique within the module emitting the event, it is
+/// used by clients to filter for the events they are interested in.
+pub trait ModuleEvent: Serialize + DeserializeOwned {
+    const KIND: &'static str;
+}
+
+/// Prefixes reserved for the event log in the isolated database of every
+/// module, modules must not use them for their own keys
+#[repr(u8)]
+#[derive(Clone, Debug)]
+pub enum DbKeyPrefix {
+    ModuleEventHead = 0xfd,
+    ModuleEvent = 0xfe,
+}
+
+/// Index of the next event to be appended to the event log
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ModuleEventHeadKey;
+
+impl_db_record!(
+    key = ModuleEventHeadKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ModuleEventHead,
+    notify_on_modify = true
+);
+
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ModuleEventKey(pub u64);
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize)]
+pub struct ModuleEventRecord {
+    pub kind: String,
+    /// The event serialized as JSON
+    pub payload: String,
+}
+
+impl_db_record!(
+    key = ModuleEventKey,
+    value = ModuleEventRecord,
+    db_prefix = DbKeyPrefix::ModuleEvent,
+);
+
+/// Appends `event` to the event log of the module `dbtx` is isolated to
+///
+/// All events are appended to the same log, so concurrent database
+/// transactions emitting events conflict with each other on commit.
+pub async fn emit_event<E: ModuleEvent>(dbtx: &mut DatabaseTransactionRef<'_>, event: &E) {
+    let index = dbtx.get_value(&ModuleEventHeadKey).await.unwrap_or(0);
+
+    let record = ModuleEventRecord {
+        kind: E::KIND.to_string(),
+        payload: serde_json::to_string(event).expect("Event serialization can't fail"),
+    };
+
+    dbtx.insert_new_entry(&ModuleEventKey(index), &record).await;
+    dbtx.insert_entry(&ModuleEventHeadKey, &(index + 1)).await;
+}
+
+/// Selects the events returned by the [`AWAIT_EVENTS_ENDPOINT`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventFilter {
+    /// Index of the first event to consider
+    pub from: u64,
+    /// Only return events of these kinds, all kinds if `None`
+    pub kinds: Option<BTreeSet<String>>,
+}
+
+impl EventFilter {
+    /// Filter for events of type `E` starting at index `from`
+    pub fn for_event<E: ModuleEvent>(from: u64) -> Self {
+        Self {
+            from,
+            kinds: Some(BTreeSet::from([E::KIND.to_string()])),
+        }
+    }
+
+    pub fn matches(&self, kind: &str) -> bool {
+        match &self.kinds {
+            Some(kinds) => kinds.contains(kind),
+            None => true,
+        }
+    }
+}
+
+/// An event as returned by the [`AWAIT_EVENTS_ENDPOINT`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleEventEntry {
+    /// Position of the event in the event log of the guardian
+    pub index: u64,
+    pub kind: String,
+    pub payload: serde_json::Value,
+}
+
+impl ModuleEventEntry {
+    /// Decodes the payload as `E`, returns `None` if the entry is of a
+    /// different kind
+    pub fn to_event<E: ModuleEvent>(&self) -> Result<Option<E>, serde_json::Error> {
+        if self.kind != E::KIND {
+            return Ok(None);
+        }
+
+        serde_json::from_value(self.payload.clone()).map(Some)
+    }
+}
+
+/// Waits until at least one event matching `filter` has been emitted and
+/// returns up to [`MAX_EVENTS_PER_RESPONSE`] of them
+pub async fn await_events(db: &Database, filter: &EventFilter) -> Vec<ModuleEventEntry> {
+    let mut from = filter.from;
+
+    loop {
+        let (head, mut dbtx) = db
+            .wait_key_check(&ModuleEventHeadKey, |head| head.filter(|head| from < *head))
+            .await;
+
+        let to = head.min(from.saturating_add(MAX_EVENTS_PER_RESPONSE));
+        let mut events = vec![];
+
+        for index in from..to {
+            let record = dbtx
+                .get_value(&ModuleEventKey(index))
+                .await
+                .expect("Event log has no gaps");
+
+            if filter.matches(&record.kind) {
+                events.push(ModuleEventEntry {
+                    index,
+                    kind: record.kind,
+                    payload: serde_json::from_str(&record.payload)
+                        .expect("Events are stored as valid JSON"),
+                });
+            }
+        }
+
+        if !events.is_empty() {
+            return events;
+        }
+
+        from = to;
+    }
+}
+
+/// The [`AWAIT_EVENTS_ENDPOINT`] that is attached to every module
+pub fn await_events_endpoint() -> ApiEndpoint<DynServerModule> {
+    api_endpoint! {
+        AWAIT_EVENTS_ENDPOINT,
+        async |_module: &DynServerModule, context, filter: EventFilter| -> Vec<ModuleEventEntry> {
+            let db = context.db.clone();
+            Ok(await_events(&db, &filter).await)
+        }
+    }
+}
+
+/// Streams the events of type `E` emitted by the module of `api` on the
+/// guardian `peer_id`, starting at the event log index `from`
+///
+/// Every item is returned together with its index, so the subscription can
+/// be resumed later. Failed requests are retried indefinitely.
+pub fn subscribe_events<E>(
+    api: DynModuleApi,
+    peer_id: PeerId,
+    from: u64,
+) -> BoxStream<'static, (u64, E)>
+where
+    E: ModuleEvent + MaybeSend + 'static,
+{
+    Box::pin(futures::stream::unfold(
+        (api, from, VecDeque::new()),
+        move |(api, mut from, mut pending)| async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((event, (api, from, pending)));
+                }
+
+                let params = ApiRequestErased::new(EventFilter::for_event::<E>(from));
+                let entries = match api
+                    .request_raw(peer_id, AWAIT_EVENTS_ENDPOINT, &[params.to_json()])
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|response| {
+                        serde_json::from_value::<Vec<ModuleEventEntry>>(response)
+                            .map_err(anyhow::Error::from)
+                    }) {
+                    Ok(entries) => entries,
+                    Err(error) => {
+                        debug!(target: LOG_CLIENT_NET_API, %peer_id, %error, "Awaiting module events failed, retrying");
+                        sleep(SUBSCRIPTION_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+
+                for entry in entries {
+                    from = from.max(entry.index + 1);
+
+                    match entry.to_event::<E>() {
+                        Ok(Some(event)) => pending.push_back((entry.index, event)),
+                        Ok(None) => {}
+                        Err(error) => {
+                            debug!(target: LOG_CLIENT_NET_API, %peer_id, index = entry.index, %error, "Skipping undecodable module event");
+                        }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::{await_events, emit_event, EventFilter, ModuleEvent};
+    use crate::db::mem_impl::MemDatabase;
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct Foo(u64);
+
+    impl ModuleEvent for Foo {
+        const KIND: &'static str = "foo";
+    }
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct Bar;
+
+    impl ModuleEvent for Bar {
+        const KIND: &'static str = "bar";
+    }
+
+    #[tokio::test]
+    async fn await_filtered_events() {
+        let db = MemDatabase::new().into_database();
+
+        let mut dbtx = db.begin_transaction().await;
+        emit_event(&mut dbtx.dbtx_ref(), &Foo(0)).await;
+        emit_event(&mut dbtx.dbtx_ref(), &Bar).await;
+        emit_event(&mut dbtx.dbtx_ref(), &Foo(2)).await;
+        dbtx.commit_tx().await;
+
+        let events = await_events(&db, &EventFilter::for_event::<Foo>(0)).await;
+        let foos = events
+            .iter()
+            .map(|entry| (entry.index, entry.to_event::<Foo>().unwrap().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(foos, vec![(0, Foo(0)), (2, Foo(2))]);
+
+        let events = await_events(&db, &EventFilter::default()).await;
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[1].to_event::<Foo>().unwrap(), None);
+        assert_eq!(events[1].to_event::<Bar>().unwrap(), Some(Bar));
+
+        // waits for the next matching event to be emitted
+        let waiting = tokio::spawn({
+            let db = db.clone();
+            async move { await_events(&db, &EventFilter::for_event::<Foo>(3)).await }
+        });
+
+        let mut dbtx = db.begin_transaction().await;
+        emit_event(&mut dbtx.dbtx_ref(), &Bar).await;
+        emit_event(&mut dbtx.dbtx_ref(), &Foo(4)).await;
+        dbtx.commit_tx().await;
+
+        let events = waiting.await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].index, 4);
+    }
+}
@@ -1,7 +1,8 @@
 pub mod audit;
+pub mod event;
 pub mod registry;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
 use std::io::Read;
 use std::marker::{self, PhantomData};
@@ -19,6 +20,7 @@ use tracing::instrument;
 // TODO: Make this module public and remove the wildcard `pub use` below
 mod version;
 pub use self::version::*;
+use crate::api::{FederationBusy, FEDERATION_BUSY_CODE};
 use crate::config::{
     ClientModuleConfig, ConfigGenModuleParams, DkgPeerMsg, ModuleInitParams, ServerModuleConfig,
     ServerModuleConsensusConfig,
@@ -28,10 +30,11 @@ use crate::core::{
     ModuleKind, Output, OutputOutcome,
 };
 use crate::db::{
-    Database, DatabaseKey, DatabaseKeyWithNotify, DatabaseRecord, DatabaseTransaction,
-    DatabaseTransactionRef, DatabaseVersion, MigrationMap,
+    CommitConflictError, Database, DatabaseKey, DatabaseKeyWithNotify, DatabaseRecord,
+    DatabaseTransaction, DatabaseTransactionRef, DatabaseVersion, MigrationMap,
 };
 use crate::encoding::{Decodable, DecodeError, Encodable};
+use crate::fee::FeeIncome;
 use crate::module::audit::Audit;
 use crate::net::peers::MuxPeerConnections;
 use crate::server::DynServerModule;
@@ -41,10 +44,23 @@ use crate::{
     OutPoint, PeerId,
 };
 
+/// The health of what a module depends on outside of the consensus, e.g. the
+/// bitcoin backend of the wallet, as observed by a single guardian
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleHealth {
+    /// Whether the module is able to make progress
+    pub healthy: bool,
+    /// Module specific details for the operator
+    pub details: JsonValue,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct InputMeta {
     pub amount: TransactionItemAmount,
     pub pub_keys: Vec<XOnlyPublicKey>,
+    /// Part of the input's amount that is paid out of the fee income of the
+    /// federation, see [`crate::fee`]
+    pub fee_payout: Amount,
 }
 
 /// Information about the amount represented by an input or output.
@@ -66,7 +82,7 @@ impl TransactionItemAmount {
 }
 
 /// All requests from client to server contain these fields
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiRequest<T> {
     /// Hashed user password if the API requires authentication
     pub auth: Option<ApiAuth>,
@@ -129,11 +145,17 @@ impl Debug for ApiAuth {
 pub struct ApiError {
     pub code: i32,
     pub message: String,
+    /// Details clients can act on, e.g. a [`FederationBusy`]
+    pub data: Option<serde_json::Value>,
 }
 
 impl ApiError {
     pub fn new(code: i32, message: String) -> Self {
-        Self { code, message }
+        Self {
+            code,
+            message,
+            data: None,
+        }
     }
 
     pub fn not_found(message: String) -> Self {
@@ -151,6 +173,36 @@ impl ApiError {
     pub fn server_error(message: String) -> Self {
         Self::new(500, message)
     }
+
+    /// The API token of the request exhausted its quota
+    pub fn over_quota(message: String) -> Self {
+        Self::new(429, message)
+    }
+
+    /// Writing the result of the request conflicted with a concurrent write,
+    /// handling the request again may succeed
+    pub fn conflict(message: String) -> Self {
+        Self::new(409, message)
+    }
+
+    pub fn is_conflict(&self) -> bool {
+        self.code == 409
+    }
+
+    /// The guardian is handling too many requests at once, clients retry it
+    /// like a busy federation
+    pub fn overloaded(message: String) -> Self {
+        Self::new(FEDERATION_BUSY_CODE, message)
+    }
+
+    /// The guardian has no room for the submitted transaction
+    pub fn federation_busy(busy: &FederationBusy) -> Self {
+        Self {
+            code: FEDERATION_BUSY_CODE,
+            message: busy.to_string(),
+            data: Some(serde_json::to_value(busy).expect("Serialization can't fail")),
+        }
+    }
 }
 
 /// State made available to all API endpoints for handling a request
@@ -159,6 +211,7 @@ pub struct ApiEndpointContext<'dbtx> {
     dbtx: DatabaseTransaction<'dbtx>,
     has_auth: bool,
     request_auth: Option<ApiAuth>,
+    capabilities: BTreeSet<String>,
 }
 
 impl<'a> ApiEndpointContext<'a> {
@@ -174,9 +227,17 @@ impl<'a> ApiEndpointContext<'a> {
             dbtx,
             has_auth,
             request_auth,
+            capabilities: BTreeSet::new(),
         }
     }
 
+    /// Grants the capabilities of the module-scoped token the request was
+    /// authenticated with
+    pub fn with_capabilities(mut self, capabilities: BTreeSet<String>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
     /// Database tx handle, will be committed
     pub fn dbtx<'s, 'mtx>(&'s mut self) -> DatabaseTransactionRef<'mtx>
     where
@@ -199,6 +260,22 @@ impl<'a> ApiEndpointContext<'a> {
         self.has_auth
     }
 
+    /// Whether the request was authenticated as the guardian or with a token
+    /// scoped to this module that grants the capability
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.has_auth || self.capabilities.contains(capability)
+    }
+
+    /// Rejects requests without the capability, modules call this at the
+    /// start of their authenticated endpoints
+    pub fn check_capability(&self, capability: &str) -> Result<(), ApiError> {
+        if self.has_capability(capability) {
+            Ok(())
+        } else {
+            Err(ApiError::unauthorized())
+        }
+    }
+
     /// Waits for key to be present in database.
     pub fn wait_key_exists<K>(&self, key: K) -> impl Future<Output = K::Value>
     where
@@ -226,16 +303,19 @@ impl<'a> ApiEndpointContext<'a> {
     /// Attempts to commit the dbtx or returns an ApiError
     pub async fn commit_tx_result(self, path: &'static str) -> Result<(), ApiError> {
         self.dbtx.commit_tx_result().await.map_err(|_err| {
+            if CommitConflictError::is_cause_of(&_err) {
+                return ApiError::conflict(
+                    "API server error when writing to database: conflicting write".to_string(),
+                );
+            }
+
             tracing::warn!(
                 target: fedimint_logging::LOG_NET_API,
                 path,
                 "API server error when writing to database: {:?}",
                 _err
             );
-            ApiError {
-                code: 500,
-                message: "API server error when writing to database".to_string(),
-            }
+            ApiError::server_error("API server error when writing to database".to_string())
         })
     }
 }
@@ -492,6 +572,8 @@ pub trait IServerModuleInit: IDynCommonModuleInit {
 
     fn database_version(&self) -> DatabaseVersion;
 
+    fn versions(&self, core: CoreConsensusVersion) -> &[ModuleConsensusVersion];
+
     /// Initialize the [`DynServerModule`] instance from its config
     async fn init(
         &self,
@@ -506,6 +588,24 @@ pub trait IServerModuleInit: IDynCommonModuleInit {
     /// indexed on the from version.
     fn get_database_migrations(&self) -> MigrationMap;
 
+    /// Migrates the module state when the federation activates a new
+    /// consensus version, see [`ServerModuleInit::migrate_consensus_version`]
+    async fn migrate_consensus_version(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        from: ModuleConsensusVersion,
+        to: ModuleConsensusVersion,
+    ) -> anyhow::Result<()>;
+
+    /// The config the module instance has to be restarted with, see
+    /// [`ServerModuleInit::update_config`]
+    async fn update_config(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        identity: &PeerId,
+        config: &ServerModuleConfig,
+    ) -> anyhow::Result<Option<ServerModuleConfig>>;
+
     fn validate_params(&self, params: &ConfigGenModuleParams) -> anyhow::Result<()>;
 
     fn trusted_dealer_gen(
@@ -649,6 +749,35 @@ pub trait ServerModuleInit: ExtendsCommonModuleInit + Sized {
         MigrationMap::new()
     }
 
+    /// Migrates the module state from one consensus version to another at the
+    /// activation session of a module upgrade scheduled by all guardians. The
+    /// migration runs before the module is initialized with the new version,
+    /// so it has to be deterministic for all guardians to keep agreeing on the
+    /// consensus.
+    async fn migrate_consensus_version(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _from: ModuleConsensusVersion,
+        _to: ModuleConsensusVersion,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the config the module instance has to be restarted with once
+    /// its consensus state requires a new one, e.g. after the guardians
+    /// generated keys for it. The consensus stops at the end of the session
+    /// the state changed in and the returned config replaces the current one
+    /// before it restarts, so it has to be deterministic as well. Must not
+    /// write to the database.
+    async fn update_config(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _identity: &PeerId,
+        _config: &ServerModuleConfig,
+    ) -> anyhow::Result<Option<ServerModuleConfig>> {
+        Ok(None)
+    }
+
     fn parse_params(&self, params: &ConfigGenModuleParams) -> anyhow::Result<Self::Params> {
         params.to_typed::<Self::Params>()
     }
@@ -691,6 +820,10 @@ where
         <Self as ServerModuleInit>::DATABASE_VERSION
     }
 
+    fn versions(&self, core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        <Self as ServerModuleInit>::versions(self, core)
+    }
+
     async fn init(
         &self,
         cfg: ServerModuleConfig,
@@ -715,6 +848,24 @@ where
         <Self as ServerModuleInit>::get_database_migrations(self)
     }
 
+    async fn migrate_consensus_version(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        from: ModuleConsensusVersion,
+        to: ModuleConsensusVersion,
+    ) -> anyhow::Result<()> {
+        <Self as ServerModuleInit>::migrate_consensus_version(self, dbtx, from, to).await
+    }
+
+    async fn update_config(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        identity: &PeerId,
+        config: &ServerModuleConfig,
+    ) -> anyhow::Result<Option<ServerModuleConfig>> {
+        <Self as ServerModuleInit>::update_config(self, dbtx, identity, config).await
+    }
+
     fn validate_params(&self, params: &ConfigGenModuleParams) -> anyhow::Result<()> {
         <Self as ServerModuleInit>::parse_params(self, params)?;
         Ok(())
@@ -804,6 +955,19 @@ pub trait ServerModule: Debug + Sized {
         dbtx: &mut DatabaseTransactionRef<'_>,
     ) -> Vec<<Self::Common as ModuleCommon>::ConsensusItem>;
 
+    /// Items of the same class are only proposed by the round-robin leaders of
+    /// the class for the current session, a threshold of guardians, unless
+    /// they stay unordered for too long. Returning a class is only correct for
+    /// items of which the ones proposed by any threshold of guardians suffice,
+    /// e.g. signature shares, but not for votes every guardian has to cast.
+    /// Items without a class are proposed by every guardian.
+    fn consensus_item_class(
+        &self,
+        _consensus_item: &<Self::Common as ModuleCommon>::ConsensusItem,
+    ) -> Option<u64> {
+        None
+    }
+
     /// This function is called once for every consensus item. The function
     /// returns an error if and only if the consensus item does not change
     /// our state and therefore may be safely discarded by the atomic broadcast.
@@ -824,6 +988,18 @@ pub trait ServerModule: Debug + Sized {
         input: &'b <Self::Common as ModuleCommon>::Input,
     ) -> Result<InputMeta, ModuleError>;
 
+    /// The outputs of accepted transactions the input spends, which the
+    /// guardians index to serve wallets the dependencies of a transaction.
+    /// Called before the input is processed. Inputs that can not be linked to
+    /// an output, e.g. e-cash notes or peg-ins, have none.
+    async fn input_dependencies<'a, 'b>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'b>,
+        _input: &'a <Self::Common as ModuleCommon>::Input,
+    ) -> Vec<OutPoint> {
+        vec![]
+    }
+
     /// Try to create an output (e.g. issue notes, peg-out BTC, …). On success
     /// all necessary updates to the database will be part of the database
     /// transaction. On failure (e.g. double spend) the database transaction
@@ -839,10 +1015,46 @@ pub trait ServerModule: Debug + Sized {
         out_point: OutPoint,
     ) -> Result<TransactionItemAmount, ModuleError>;
 
+    /// Called once all items of a session have been processed, before the
+    /// session is completed. Modules can use it to do work they deferred while
+    /// processing the items in one batch, e.g. the mint signs all notes issued
+    /// in the session at once.
+    async fn end_session(&self, _dbtx: &mut DatabaseTransactionRef<'_>, _session_index: u64) {}
+
+    /// Called at the end of every session with the fee income of the
+    /// federation, right before [`Self::end_session`]. Modules paying out the
+    /// fee income via [`InputMeta::fee_payout`] learn from it how much was
+    /// collected, as the fee income is not part of their database.
+    async fn update_fee_income(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _fee_income: FeeIncome,
+    ) {
+    }
+
+    /// The prefixes of the module's keys whose entries are committed to at the
+    /// end of every session, such that light clients can verify them with a
+    /// [`StateProof`](crate::state_proof::StateProof). Only entries that are
+    /// equal on all guardians, i.e. written while processing consensus items
+    /// and transactions, may be committed to.
+    fn committed_state_prefixes(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// The health of the module as surfaced on the status endpoint of the
+    /// guardian, `None` if the module does not depend on anything outside of
+    /// the consensus. Should return quickly, so modules are expected to check
+    /// their dependencies in the background.
+    async fn health(&self) -> Option<ModuleHealth> {
+        None
+    }
+
     /// Retrieve the current status of the output. Depending on the module this
     /// might contain data needed by the client to access funds or give an
     /// estimate of when funds will be available. Returns `None` if the
-    /// output is unknown, **NOT** if it is just not ready yet.
+    /// output is unknown, **NOT** if it is just not ready yet. The only
+    /// exception are outcomes produced in `end_session`, which are `None`
+    /// until the session that accepted the output is completed.
     async fn output_status(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -945,6 +1157,10 @@ pub struct PeerHandle<'a> {
     pub our_id: PeerId,
     #[doc(hidden)]
     pub peers: Vec<PeerId>,
+    /// Seed of the randomness used by the config gen, such that a guardian
+    /// restarting during the DKG generates the same keys again
+    #[doc(hidden)]
+    pub seed: [u8; 32],
 }
 
 impl<'a> PeerHandle<'a> {
@@ -953,12 +1169,14 @@ impl<'a> PeerHandle<'a> {
         module_instance_id: ModuleInstanceId,
         our_id: PeerId,
         peers: Vec<PeerId>,
+        seed: [u8; 32],
     ) -> Self {
         Self {
             connections,
             module_instance_id,
             our_id,
             peers,
+            seed,
         }
     }
 
@@ -100,7 +100,20 @@ impl From<u32> for CoreConsensusVersion {
 /// the same time (each of different `ModuleKind` version), allow users to
 /// slowly migrate to a new one. This avoids complex and error-prone server-side
 /// consensus-migration logic.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    Encodable,
+    Decodable,
+)]
 pub struct ModuleConsensusVersion(pub u32);
 
 impl From<u32> for ModuleConsensusVersion {
@@ -0,0 +1,164 @@
+//! Selection among the addresses a peer advertises
+//!
+//! A peer can be reachable under several addresses, e.g. over IPv4, IPv6 and
+//! an onion service, which it advertises in order of preference. We try them
+//! in that order, but move addresses that failed recently to the back until
+//! their backoff expired, so an unreachable address does not delay every
+//! connection attempt.
+
+use std::cmp;
+use std::time::{Duration, SystemTime};
+
+use crate::util::SafeUrl;
+
+/// Backoff after the first failure of an address, doubled with every
+/// consecutive failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// The addresses of a peer and how reliably we could connect to them
+#[derive(Debug, Clone)]
+pub struct PeerAddresses {
+    /// Addresses in order of preference
+    addresses: Vec<AddressHealth>,
+}
+
+#[derive(Debug, Clone)]
+struct AddressHealth {
+    url: SafeUrl,
+    consecutive_failures: u32,
+    last_failure: Option<SystemTime>,
+}
+
+impl AddressHealth {
+    fn backoff(&self) -> Duration {
+        let exponent = self.consecutive_failures.saturating_sub(1).min(16);
+
+        cmp::min(MIN_BACKOFF * 2u32.pow(exponent), MAX_BACKOFF)
+    }
+
+    fn is_backing_off(&self, now: SystemTime) -> bool {
+        self.last_failure.map_or(false, |last_failure| {
+            now.duration_since(last_failure).unwrap_or_default() < self.backoff()
+        })
+    }
+}
+
+impl PeerAddresses {
+    /// Creates the addresses from urls in order of preference
+    ///
+    /// # Panics
+    /// If `urls` is empty
+    pub fn new(urls: Vec<SafeUrl>) -> Self {
+        assert!(!urls.is_empty(), "A peer needs at least one address");
+
+        PeerAddresses {
+            addresses: urls
+                .into_iter()
+                .map(|url| AddressHealth {
+                    url,
+                    consecutive_failures: 0,
+                    last_failure: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// The most preferred address
+    pub fn preferred(&self) -> &SafeUrl {
+        &self.addresses[0].url
+    }
+
+    /// Returns the addresses in the order we should try to connect to them:
+    /// the healthy addresses in order of preference, then the ones backing off
+    /// after a failure, starting with those that failed least often
+    pub fn connection_order(&self, now: SystemTime) -> Vec<SafeUrl> {
+        let mut addresses = self.addresses.iter().collect::<Vec<_>>();
+
+        // the sort is stable, so ties keep the order of preference
+        addresses.sort_by_key(|address| {
+            let backing_off = address.is_backing_off(now);
+            (
+                backing_off,
+                backing_off.then_some(address.consecutive_failures),
+            )
+        });
+
+        addresses
+            .into_iter()
+            .map(|address| address.url.clone())
+            .collect()
+    }
+
+    pub fn record_success(&mut self, url: &SafeUrl) {
+        if let Some(address) = self.get_mut(url) {
+            address.consecutive_failures = 0;
+            address.last_failure = None;
+        }
+    }
+
+    pub fn record_failure(&mut self, url: &SafeUrl, now: SystemTime) {
+        if let Some(address) = self.get_mut(url) {
+            address.consecutive_failures = address.consecutive_failures.saturating_add(1);
+            address.last_failure = Some(now);
+        }
+    }
+
+    fn get_mut(&mut self, url: &SafeUrl) -> Option<&mut AddressHealth> {
+        self.addresses
+            .iter_mut()
+            .find(|address| &address.url == url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::PeerAddresses;
+    use crate::util::SafeUrl;
+
+    #[test]
+    fn prefers_healthy_addresses() {
+        let urls: Vec<SafeUrl> = [
+            "wss://198.51.100.1:8174",
+            "wss://[2001:db8::1]:8174",
+            "ws://guardianexampleonionaddress.onion:8174",
+        ]
+        .iter()
+        .map(|url| url.parse().expect("Valid url"))
+        .collect();
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut addresses = PeerAddresses::new(urls.clone());
+
+        assert_eq!(addresses.preferred(), &urls[0]);
+        assert_eq!(addresses.connection_order(start), urls);
+
+        addresses.record_failure(&urls[0], start);
+        addresses.record_failure(&urls[0], start);
+        addresses.record_failure(&urls[1], start);
+        assert_eq!(
+            addresses.connection_order(start),
+            vec![urls[2].clone(), urls[1].clone(), urls[0].clone()]
+        );
+
+        // the backoff of the address that failed once expired first
+        assert_eq!(
+            addresses.connection_order(start + Duration::from_secs(1)),
+            vec![urls[1].clone(), urls[2].clone(), urls[0].clone()]
+        );
+        assert_eq!(
+            addresses.connection_order(start + Duration::from_secs(2)),
+            urls
+        );
+
+        addresses.record_success(&urls[1]);
+        addresses.record_failure(&urls[0], start + Duration::from_secs(2));
+        assert_eq!(
+            addresses.connection_order(start + Duration::from_secs(2)),
+            vec![urls[1].clone(), urls[2].clone(), urls[0].clone()]
+        );
+    }
+}
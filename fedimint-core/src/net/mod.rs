@@ -1 +1,5 @@
+pub mod addresses;
 pub mod peers;
+pub mod proxy;
+#[cfg(not(target_family = "wasm"))]
+pub mod socks;
@@ -0,0 +1,59 @@
+//! SOCKS5 proxies, e.g. Tor, the API client tunnels its connections through
+
+use crate::util::SafeUrl;
+
+/// A SOCKS5 proxy the API client tunnels its connections through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    pub url: SafeUrl,
+    /// Only tunnel the connections to onion services, which cannot be reached
+    /// otherwise, instead of all connections
+    pub onion_only: bool,
+}
+
+impl Socks5Proxy {
+    /// Tunnels all connections, which hides our IP address from the guardians
+    pub fn all(url: SafeUrl) -> Self {
+        Socks5Proxy {
+            url,
+            onion_only: false,
+        }
+    }
+
+    /// Tunnels only the connections to onion services
+    pub fn onion_only(url: SafeUrl) -> Self {
+        Socks5Proxy {
+            url,
+            onion_only: true,
+        }
+    }
+
+    /// The proxy to connect to `url` through, if any
+    pub fn proxy_for(&self, url: &SafeUrl) -> Option<&SafeUrl> {
+        (!self.onion_only || url.is_onion_address()).then_some(&self.url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Socks5Proxy;
+    use crate::util::SafeUrl;
+
+    #[test]
+    fn onion_only_proxy_skips_clearnet() {
+        let proxy = SafeUrl::parse("socks5://127.0.0.1:9050").unwrap();
+        let onion = SafeUrl::parse(
+            "ws://vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:8174",
+        )
+        .unwrap();
+        let clearnet = SafeUrl::parse("wss://guardian.example.com").unwrap();
+
+        let onion_only = Socks5Proxy::onion_only(proxy.clone());
+        assert_eq!(onion_only.proxy_for(&onion), Some(&proxy));
+        assert_eq!(onion_only.proxy_for(&clearnet), None);
+
+        let all = Socks5Proxy::all(proxy.clone());
+        assert_eq!(all.proxy_for(&onion), Some(&proxy));
+        assert_eq!(all.proxy_for(&clearnet), Some(&proxy));
+    }
+}
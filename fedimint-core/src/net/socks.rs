@@ -0,0 +1,146 @@
+//! Connections tunneled through a SOCKS5 proxy, e.g. Tor
+//!
+//! Peers running behind onion services can only be reached through a Tor
+//! proxy, and clients may want to tunnel all their connections through Tor to
+//! hide their IP address from the guardians. The proxy resolves the host names
+//! for us, which is required for onion addresses and avoids leaking DNS
+//! requests.
+
+use std::sync::Arc;
+
+use anyhow::{bail, format_err};
+use async_trait::async_trait;
+use jsonrpsee_core::client::{ReceivedMessage, TransportReceiverT, TransportSenderT};
+use soketto::connection::Error as WsError;
+use soketto::handshake::{Client as WsHandshake, ServerResponse};
+use soketto::{connection, Data, Incoming};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use tokio_socks::tcp::Socks5Stream;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+use crate::util::SafeUrl;
+
+/// Opens a TCP connection to `host` tunneled through the SOCKS5 `proxy`
+pub async fn connect_socks5(proxy: &SafeUrl, host: &str, port: u16) -> anyhow::Result<TcpStream> {
+    if !matches!(proxy.scheme(), "socks5" | "socks5h") {
+        bail!("Unsupported proxy scheme in {proxy}, expected socks5");
+    }
+
+    let proxy_host = proxy
+        .host_str()
+        .ok_or_else(|| format_err!("Missing host in {proxy}"))?;
+    let proxy_port = proxy
+        .port()
+        .ok_or_else(|| format_err!("Missing port in {proxy}"))?;
+
+    let stream = Socks5Stream::connect((proxy_host, proxy_port), (host, port)).await?;
+
+    Ok(stream.into_inner())
+}
+
+trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+type ProxyStream = Compat<Box<dyn AsyncStream>>;
+
+/// Opens a web socket to `url` tunneled through the SOCKS5 `proxy`, for
+/// `wss` URLs the TLS session is established through the tunnel as well
+pub async fn connect_ws_socks5(
+    url: &SafeUrl,
+    proxy: &SafeUrl,
+) -> anyhow::Result<(WsSender, WsReceiver)> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| format_err!("Missing host in {url}"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| format_err!("Missing port in {url}"))?;
+
+    let tcp = connect_socks5(proxy, host, port).await?;
+
+    let stream: Box<dyn AsyncStream> = match url.scheme() {
+        "ws" => Box::new(tcp),
+        "wss" => Box::new(
+            tls_connector()
+                .connect(ServerName::try_from(host)?, tcp)
+                .await?,
+        ),
+        scheme => bail!("Unsupported scheme {scheme} in {url}"),
+    };
+
+    let host_header = format!("{host}:{port}");
+    let mut handshake = WsHandshake::new(stream.compat(), &host_header, url.path());
+
+    match handshake.handshake().await? {
+        ServerResponse::Accepted { .. } => {}
+        ServerResponse::Redirect { status_code, .. } | ServerResponse::Rejected { status_code } => {
+            bail!("Web socket handshake with {url} failed with status {status_code}")
+        }
+    }
+
+    let (sender, receiver) = handshake.into_builder().finish();
+
+    Ok((WsSender(sender), WsReceiver(receiver)))
+}
+
+fn tls_connector() -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Sending half of a web socket tunneled through a proxy
+pub struct WsSender(connection::Sender<ProxyStream>);
+
+/// Receiving half of a web socket tunneled through a proxy
+pub struct WsReceiver(connection::Receiver<ProxyStream>);
+
+#[async_trait]
+impl TransportSenderT for WsSender {
+    type Error = WsError;
+
+    async fn send(&mut self, body: String) -> Result<(), Self::Error> {
+        self.0.send_text(body).await?;
+        self.0.flush().await
+    }
+
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        self.0.close().await
+    }
+}
+
+#[async_trait]
+impl TransportReceiverT for WsReceiver {
+    type Error = WsError;
+
+    async fn receive(&mut self) -> Result<ReceivedMessage, Self::Error> {
+        loop {
+            let mut message = Vec::new();
+
+            // the client parses text and binary messages alike
+            match self.0.receive(&mut message).await? {
+                Incoming::Data(Data::Text(_) | Data::Binary(_)) => {
+                    return Ok(ReceivedMessage::Bytes(message))
+                }
+                Incoming::Pong(_) => return Ok(ReceivedMessage::Pong),
+                _ => continue,
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::Debug;
 use std::mem;
+use std::sync::{Arc, Mutex, Weak};
 use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, format_err};
@@ -10,6 +11,7 @@ use fedimint_core::time::now;
 use fedimint_core::{maybe_add_send_sync, PeerId};
 
 use crate::api::{self, ApiVersionSet, PeerError};
+use crate::encoding::{Decodable, Encodable};
 use crate::module::{
     ApiVersion, SupportedApiVersionsSummary, SupportedCoreApiVersions, SupportedModuleApiVersions,
 };
@@ -25,6 +27,19 @@ pub trait QueryStrategy<IR, OR = IR> {
     fn request_timeout(&self) -> Option<Duration> {
         None
     }
+    /// Delay between sending the request to consecutive peers in the order of
+    /// their [`PeerLatencyTracker`] ranking
+    ///
+    /// Strategies that only need a single response can use this to query the
+    /// historically best peers first, falling back to the others only if they
+    /// take too long to respond.
+    fn request_stagger(&self) -> Option<Duration> {
+        None
+    }
+    /// Peers the request is sent to, all peers of the federation if `None`
+    fn request_peers(&self) -> Option<BTreeSet<PeerId>> {
+        None
+    }
     fn process(&mut self, peer_id: PeerId, response: api::PeerResult<IR>) -> QueryStep<OR>;
 
     fn with_request_timeout(
@@ -61,6 +76,12 @@ where
     fn request_timeout(&self) -> Option<Duration> {
         Some(self.timeout)
     }
+    fn request_stagger(&self) -> Option<Duration> {
+        self.inner.request_stagger()
+    }
+    fn request_peers(&self) -> Option<BTreeSet<PeerId>> {
+        self.inner.request_peers()
+    }
 }
 
 /// Results from the strategy handling a response from a peer
@@ -154,7 +175,14 @@ impl<R, T> FilterMap<R, T> {
     }
 }
 
+/// Delay after which [`FilterMap`] queries the next peer if none of the
+/// previously queried peers returned a valid response yet
+const FILTER_MAP_REQUEST_STAGGER: Duration = Duration::from_millis(200);
+
 impl<R: Debug + Eq + Clone, T> QueryStrategy<R, T> for FilterMap<R, T> {
+    fn request_stagger(&self) -> Option<Duration> {
+        Some(FILTER_MAP_REQUEST_STAGGER)
+    }
     fn process(&mut self, peer: PeerId, result: PeerResult<R>) -> QueryStep<T> {
         match result {
             Ok(response) => match (self.filter_map)(response) {
@@ -293,6 +321,41 @@ impl<R: Eq + Clone + Debug> QueryStrategy<R> for ThresholdConsensus<R> {
     }
 }
 
+/// Returns the response of a single trusted peer without querying the others
+pub struct TrustedPeer<R> {
+    peer: PeerId,
+    _response: std::marker::PhantomData<R>,
+}
+
+impl<R> TrustedPeer<R> {
+    pub fn new(peer: PeerId) -> Self {
+        Self {
+            peer,
+            _response: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<R> QueryStrategy<R> for TrustedPeer<R> {
+    fn request_peers(&self) -> Option<BTreeSet<PeerId>> {
+        Some(BTreeSet::from([self.peer]))
+    }
+
+    fn process(&mut self, peer: PeerId, result: api::PeerResult<R>) -> QueryStep<R> {
+        if peer != self.peer {
+            return QueryStep::Continue;
+        }
+
+        match result {
+            Ok(response) => QueryStep::Success(response),
+            Err(error) => QueryStep::Failure {
+                general: None,
+                peers: BTreeMap::from([(peer, error)]),
+            },
+        }
+    }
+}
+
 /// Returns the deduplicated union of a threshold of responses
 pub struct UnionResponses<R> {
     error_strategy: ErrorStrategy,
@@ -481,6 +544,298 @@ impl QueryStrategy<SupportedApiVersionsSummary, ApiVersionSet> for DiscoverApiVe
     }
 }
 
+/// Classes of global endpoints that can be queried with different
+/// [`QueryPolicy`]s
+///
+/// Endpoints whose responses the client verifies itself, like the client
+/// config and signed block headers, are not part of any class since a single
+/// honest response is sufficient for them anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EndpointClass {
+    /// Submitting transactions and backups
+    Submission,
+    /// Awaiting the outcome of transactions and their outputs
+    Outcome,
+    /// Reading the consensus history, like blocks and transaction proofs
+    History,
+    /// Reading the consensus config hash
+    Config,
+}
+
+/// How many guardians have to agree on a response before the client accepts
+/// it, which trades the latency of a request for the strength of its
+/// verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueryPolicy {
+    /// Only queries this guardian and accepts whatever it responds
+    TrustPeer(PeerId),
+    /// Requires enough guardians to agree that the malicious ones cannot
+    /// outvote the honest ones
+    #[default]
+    Threshold,
+    /// Requires all guardians to agree, such that a single unavailable
+    /// guardian makes the request fail
+    VerifyAll,
+}
+
+/// The [`QueryPolicy`] for every [`EndpointClass`], which defaults to
+/// [`QueryPolicy::Threshold`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryPolicies(BTreeMap<EndpointClass, QueryPolicy>);
+
+impl QueryPolicies {
+    /// Uses `policy` for all endpoints of `class`
+    pub fn with(mut self, class: EndpointClass, policy: QueryPolicy) -> Self {
+        self.0.insert(class, policy);
+        self
+    }
+
+    pub fn get(&self, class: EndpointClass) -> QueryPolicy {
+        self.0.get(&class).copied().unwrap_or_default()
+    }
+}
+
+/// Upper bounds of the [`PeerLatencyHistory`] buckets in milliseconds
+const LATENCY_BUCKETS_MS: [u64; 8] = [50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000];
+
+/// Once a history contains more samples than this all counts are halved, so
+/// that recent behavior of a peer outweighs its distant past
+const MAX_LATENCY_SAMPLES: u64 = 1_000;
+
+/// Long-term histogram of the request latencies and error count of a peer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encodable, Decodable)]
+pub struct PeerLatencyHistory {
+    /// Successful requests per bucket of [`LATENCY_BUCKETS_MS`], the last
+    /// entry counts requests slower than the largest bucket
+    pub latency_buckets: Vec<u64>,
+    /// Number of failed requests
+    pub errors: u64,
+}
+
+impl PeerLatencyHistory {
+    pub fn record_success(&mut self, latency: Duration) {
+        let latency_ms = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| latency_ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+
+        self.latency_buckets.resize(LATENCY_BUCKETS_MS.len() + 1, 0);
+        self.latency_buckets[bucket] += 1;
+        self.decay();
+    }
+
+    pub fn record_error(&mut self) {
+        self.errors += 1;
+        self.decay();
+    }
+
+    pub fn successes(&self) -> u64 {
+        self.latency_buckets.iter().sum()
+    }
+
+    fn decay(&mut self) {
+        if MAX_LATENCY_SAMPLES < self.successes() + self.errors {
+            for count in &mut self.latency_buckets {
+                *count /= 2;
+            }
+            self.errors /= 2;
+        }
+    }
+
+    /// Upper bound of the bucket containing the median latency of successful
+    /// requests
+    pub fn median_latency(&self) -> Option<Duration> {
+        let successes = self.successes();
+        if successes == 0 {
+            return None;
+        }
+
+        let mut seen = 0;
+        for (bucket, count) in self.latency_buckets.iter().enumerate() {
+            seen += count;
+            if successes <= 2 * seen {
+                // slower than the largest bucket, assume twice its bound
+                let bound_ms = LATENCY_BUCKETS_MS
+                    .get(bucket)
+                    .copied()
+                    .unwrap_or(2 * LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1]);
+                return Some(Duration::from_millis(bound_ms));
+            }
+        }
+
+        unreachable!("median has to be in one of the buckets")
+    }
+
+    /// Expected time in milliseconds until a request to this peer succeeds,
+    /// lower is better. Returns `None` if there is no history yet.
+    pub fn score(&self) -> Option<f64> {
+        let successes = self.successes();
+        let total = successes + self.errors;
+
+        if total == 0 {
+            return None;
+        }
+
+        match self.median_latency() {
+            // every failed request costs us another attempt on average
+            Some(median) => Some(median.as_millis() as f64 * total as f64 / successes as f64),
+            None => Some(f64::INFINITY),
+        }
+    }
+}
+
+/// Collects the [`PeerLatencyHistory`] of all peers of a federation
+///
+/// Shared between all API clients for the same federation. The histories are
+/// kept in memory, it is up to the owner to load and persist them.
+#[derive(Debug, Clone, Default)]
+pub struct PeerLatencyTracker(Arc<Mutex<BTreeMap<PeerId, PeerLatencyHistory>>>);
+
+impl PeerLatencyTracker {
+    pub fn from_histories(histories: BTreeMap<PeerId, PeerLatencyHistory>) -> Self {
+        PeerLatencyTracker(Arc::new(Mutex::new(histories)))
+    }
+
+    /// Returns a weak handle that does not keep the tracker alive, useful for
+    /// background tasks persisting the histories
+    pub fn downgrade(&self) -> Weak<Mutex<BTreeMap<PeerId, PeerLatencyHistory>>> {
+        Arc::downgrade(&self.0)
+    }
+
+    pub fn upgrade(weak: &Weak<Mutex<BTreeMap<PeerId, PeerLatencyHistory>>>) -> Option<Self> {
+        weak.upgrade().map(PeerLatencyTracker)
+    }
+
+    pub fn record(&self, peer: PeerId, result: Result<Duration, ()>) {
+        let mut histories = self.0.lock().expect("poisoned");
+        let history = histories.entry(peer).or_default();
+
+        match result {
+            Ok(latency) => history.record_success(latency),
+            Err(()) => history.record_error(),
+        }
+    }
+
+    pub fn get_all(&self) -> BTreeMap<PeerId, PeerLatencyHistory> {
+        self.0.lock().expect("poisoned").clone()
+    }
+
+    /// Orders `peers` by their [`PeerLatencyHistory::score`], best first
+    ///
+    /// Peers we know nothing about yet come first, so that we learn about
+    /// them.
+    pub fn rank_peers(&self, peers: &BTreeSet<PeerId>) -> Vec<PeerId> {
+        let histories = self.0.lock().expect("poisoned");
+
+        let mut ranked = peers
+            .iter()
+            .map(|peer| {
+                let score = histories.get(peer).and_then(PeerLatencyHistory::score);
+                (*peer, score.unwrap_or(f64::NEG_INFINITY))
+            })
+            .collect::<Vec<_>>();
+        // the sort is stable, so peers with equal scores stay in peer id order
+        ranked.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        ranked.into_iter().map(|(peer, _)| peer).collect()
+    }
+}
+
+#[test]
+fn rank_peers_by_latency_history() {
+    let tracker = PeerLatencyTracker::default();
+    let peers = (0..4).map(PeerId::from).collect::<BTreeSet<_>>();
+
+    for _ in 0..10 {
+        tracker.record(PeerId::from(0), Ok(Duration::from_millis(800)));
+        tracker.record(PeerId::from(1), Ok(Duration::from_millis(20)));
+        tracker.record(PeerId::from(2), Err(()));
+    }
+    // failures count against the latency of a peer
+    for _ in 0..5 {
+        tracker.record(PeerId::from(3), Ok(Duration::from_millis(200)));
+        tracker.record(PeerId::from(3), Err(()));
+    }
+
+    assert_eq!(
+        tracker.rank_peers(&peers),
+        vec![1, 3, 0, 2]
+            .into_iter()
+            .map(PeerId::from)
+            .collect::<Vec<_>>()
+    );
+
+    // peers without history are tried first
+    let peers = (0..5).map(PeerId::from).collect::<BTreeSet<_>>();
+    assert_eq!(tracker.rank_peers(&peers)[0], PeerId::from(4));
+}
+
+#[test]
+fn latency_history_decays() {
+    let mut history = PeerLatencyHistory::default();
+
+    for _ in 0..MAX_LATENCY_SAMPLES {
+        history.record_error();
+    }
+    for _ in 0..MAX_LATENCY_SAMPLES {
+        history.record_success(Duration::from_millis(1));
+    }
+
+    assert!(history.successes() + history.errors <= MAX_LATENCY_SAMPLES);
+    assert!(history.errors < history.successes());
+    assert_eq!(history.median_latency(), Some(Duration::from_millis(50)));
+}
+
+#[test]
+fn trusted_peer_ignores_other_peers() {
+    let mut strategy = TrustedPeer::new(PeerId::from(2));
+
+    assert_eq!(
+        strategy.request_peers(),
+        Some(BTreeSet::from([PeerId::from(2)]))
+    );
+    assert!(matches!(
+        strategy.process(PeerId::from(0), Ok(0)),
+        QueryStep::Continue
+    ));
+    assert!(matches!(
+        strategy.process(PeerId::from(2), Ok(1)),
+        QueryStep::Success(1)
+    ));
+
+    let mut strategy = TrustedPeer::<u64>::new(PeerId::from(2));
+
+    assert!(matches!(
+        strategy.process(
+            PeerId::from(2),
+            Err(PeerError::InvalidResponse("wrong".to_string()))
+        ),
+        QueryStep::Failure { peers, .. } if peers.contains_key(&PeerId::from(2))
+    ));
+}
+
+#[test]
+fn query_policies_default_to_threshold() {
+    let policies = QueryPolicies::default()
+        .with(
+            EndpointClass::History,
+            QueryPolicy::TrustPeer(PeerId::from(1)),
+        )
+        .with(EndpointClass::Submission, QueryPolicy::VerifyAll);
+
+    assert_eq!(
+        policies.get(EndpointClass::History),
+        QueryPolicy::TrustPeer(PeerId::from(1))
+    );
+    assert_eq!(
+        policies.get(EndpointClass::Submission),
+        QueryPolicy::VerifyAll
+    );
+    assert_eq!(policies.get(EndpointClass::Outcome), QueryPolicy::Threshold);
+    assert_eq!(policies.get(EndpointClass::Config), QueryPolicy::Threshold);
+}
+
 fn discover_common_core_api_version(
     client_versions: &SupportedCoreApiVersions,
     peer_versions: BTreeMap<PeerId, SupportedCoreApiVersions>,
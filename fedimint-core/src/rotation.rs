@@ -0,0 +1,127 @@
+//! Rotating the broadcast and auth keys of a running federation
+//!
+//! Once their admin requested a rotation, every guardian deals a new broadcast
+//! key pair for themselves and a random polynomial with a zero constant term
+//! as a [`ConsensusItem::KeyRotationDeal`]. Adding the evaluations of these
+//! polynomials to the auth key shares refreshes the shares without changing
+//! the auth public key, so the federation id stays the same while shares
+//! leaked before the rotation become useless. The evaluations are encrypted to
+//! the broadcast key of their recipient.
+//!
+//! After all deals have been ordered, every guardian checks the evaluations
+//! dealt to them and confirms the deals with a
+//! [`ConsensusItem::KeyRotationConfirmation`]. Once all guardians confirmed,
+//! every guardian activates the new keys before the next session. The
+//! broadcast public keys used before are kept as a [`KeyEpoch`], such that the
+//! blocks signed with them remain verifiable.
+//!
+//! [`ConsensusItem::KeyRotationDeal`]: crate::epoch::ConsensusItem::KeyRotationDeal
+//! [`ConsensusItem::KeyRotationConfirmation`]: crate::epoch::ConsensusItem::KeyRotationConfirmation
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin_hashes::sha256;
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{Decodable, Encodable};
+use crate::PeerId;
+
+/// The keys a guardian deals for a key rotation
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct KeyRotationDeal {
+    /// The index of the key epoch that starts with the rotation
+    pub epoch: u64,
+    /// The new broadcast public key of the dealing guardian
+    pub broadcast_public_key: secp256k1_zkp::PublicKey,
+    /// Commitment to a random polynomial with a zero constant term
+    pub auth_commitment: threshold_crypto::PublicKeySet,
+    /// The evaluation of the polynomial for every guardian, encrypted to
+    /// their broadcast key
+    pub auth_shares: BTreeMap<PeerId, Vec<u8>>,
+}
+
+/// Confirms that the evaluations dealt to a guardian match the commitments
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct KeyRotationConfirmation {
+    pub epoch: u64,
+    /// The hash of the deals of all guardians by their peer id
+    pub deals: sha256::Hash,
+}
+
+/// Broadcast public keys that were replaced by a key rotation
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct KeyEpoch {
+    /// The first session signed with the keys of the next epoch
+    pub rotated_at_session: u64,
+    pub broadcast_public_keys: BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+}
+
+/// Returns the broadcast public keys that signed the block of the given
+/// session, given the replaced keys in the order they were rotated and the
+/// keys in use since the last rotation
+pub fn broadcast_public_keys_at<'a>(
+    key_epochs: &'a [KeyEpoch],
+    current: &'a BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+    session_index: u64,
+) -> &'a BTreeMap<PeerId, secp256k1_zkp::PublicKey> {
+    key_epochs
+        .iter()
+        .find(|epoch| session_index < epoch.rotated_at_session)
+        .map_or(current, |epoch| &epoch.broadcast_public_keys)
+}
+
+/// A key rotation as seen by the consensus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotationStatus {
+    /// The index of the key epoch that starts with the rotation
+    pub epoch: u64,
+    /// The guardians whose deal has been ordered so far
+    pub deals: BTreeSet<PeerId>,
+    /// The guardians that confirmed the current deals
+    pub confirmations: BTreeSet<PeerId>,
+    /// The first session signed with the new keys once all guardians confirmed
+    pub activation_session: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use secp256k1_zkp::{Secp256k1, SecretKey};
+
+    use super::{broadcast_public_keys_at, KeyEpoch};
+    use crate::PeerId;
+
+    #[test]
+    fn selects_the_keys_of_the_session() {
+        let secp = Secp256k1::new();
+        let keys = |byte: u8| {
+            let sk = SecretKey::from_slice(&[byte; 32]).expect("Valid secret key");
+            BTreeMap::from([(PeerId::from(0), sk.public_key(&secp))])
+        };
+
+        let key_epochs = vec![
+            KeyEpoch {
+                rotated_at_session: 10,
+                broadcast_public_keys: keys(1),
+            },
+            KeyEpoch {
+                rotated_at_session: 20,
+                broadcast_public_keys: keys(2),
+            },
+        ];
+        let current = keys(3);
+
+        assert_eq!(broadcast_public_keys_at(&key_epochs, &current, 0), &keys(1));
+        assert_eq!(broadcast_public_keys_at(&key_epochs, &current, 9), &keys(1));
+        assert_eq!(
+            broadcast_public_keys_at(&key_epochs, &current, 10),
+            &keys(2)
+        );
+        assert_eq!(
+            broadcast_public_keys_at(&key_epochs, &current, 20),
+            &current
+        );
+        assert_eq!(broadcast_public_keys_at(&[], &current, 0), &current);
+    }
+}
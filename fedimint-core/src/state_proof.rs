@@ -0,0 +1,141 @@
+//! Proofs of the consensus state for light clients
+//!
+//! At the end of every session the guardians snapshot the entries of the
+//! database that are part of the consensus state, i.e. the accepted
+//! transactions and the state the modules commit to, like the accounts of
+//! contracts or the pending withdrawals. The entries are hashed into the leaves
+//! of a merkle tree ordered by key and its root is ordered as a
+//! [`ConsensusItem::StateCommitment`] in the next session. The commitment is
+//! thereby covered by the signed header of that session, so a light client
+//! can verify a [`StateProof`] for a single entry, e.g. that its transaction
+//! was accepted, knowing only the header instead of the blocks of the
+//! federation.
+
+use bitcoin30::hashes::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::block::{
+    consensus_hash_sha256, merkle_branch, merkle_root, merkle_root_of_branch, AcceptedItemProof,
+};
+use crate::encoding::{Decodable, Encodable};
+use crate::epoch::ConsensusItem;
+
+/// The root of the merkle tree over the state entries after a session
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct StateCommitment {
+    /// The session after which the snapshot of the state was taken
+    pub session_index: u64,
+    /// Number of entries in the snapshot
+    pub entries: u64,
+    /// The merkle root of the entries or 32 zero bytes if there are none
+    pub root: [u8; 32],
+}
+
+impl StateCommitment {
+    /// Commits to the leaves of the snapshot, which are ordered by key
+    pub fn new(session_index: u64, leaves: Vec<[u8; 32]>) -> Self {
+        StateCommitment {
+            session_index,
+            entries: leaves.len() as u64,
+            root: merkle_root(leaves).unwrap_or([0; 32]),
+        }
+    }
+}
+
+/// The leaf of a state entry, which is identified by its database key
+pub fn state_leaf(key: &[u8], value: &[u8]) -> [u8; 32] {
+    consensus_hash_sha256(&(key.to_vec(), value.to_vec())).to_byte_array()
+}
+
+/// The merkle branch of the leaf at `index` among the leaves of a snapshot
+pub fn state_branch(leaves: Vec<[u8; 32]>, index: usize) -> Option<Vec<[u8; 32]>> {
+    merkle_branch(leaves, index)
+}
+
+/// Proves that a database entry was part of the consensus state after a
+/// session
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct StateProof {
+    pub commitment: StateCommitment,
+    /// Proves that the commitment was accepted in a later session
+    pub commitment_proof: AcceptedItemProof,
+    /// The database key of the entry, including the prefix of its module
+    pub key: Vec<u8>,
+    /// The consensus encoded value of the entry
+    pub value: Vec<u8>,
+    /// The index of the entry's leaf in the snapshot
+    pub leaf_index: u64,
+    /// The sibling hashes from the leaf up to the root of the commitment
+    pub branch: Vec<[u8; 32]>,
+}
+
+impl StateProof {
+    /// Verifies the entry against the header of the session that accepted the
+    /// commitment, which is [`AcceptedItemProof::session_index`]
+    ///
+    /// The threshold signature of the header has to be verified separately,
+    /// see [`SignedBlockHeader::verify`](crate::block::SignedBlockHeader::verify).
+    pub fn verify(&self, header: &[u8; 40]) -> bool {
+        let item = ConsensusItem::StateCommitment(self.commitment.clone());
+
+        self.leaf_index < self.commitment.entries
+            && self.commitment_proof.verify(&item, header)
+            && merkle_root_of_branch(
+                state_leaf(&self.key, &self.value),
+                self.leaf_index,
+                &self.branch,
+            ) == Some(self.commitment.root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{state_branch, state_leaf, StateCommitment, StateProof};
+    use crate::block::{AcceptedItem, Block};
+    use crate::epoch::ConsensusItem;
+    use crate::PeerId;
+
+    #[test]
+    fn state_proofs_verify_against_header() {
+        let entries = (0..5u8)
+            .map(|i| (vec![0x02, i], vec![i; 3]))
+            .collect::<Vec<_>>();
+        let leaves = entries
+            .iter()
+            .map(|(key, value)| state_leaf(key, value))
+            .collect::<Vec<_>>();
+        let commitment = StateCommitment::new(6, leaves.clone());
+
+        let block = Block {
+            items: vec![AcceptedItem {
+                item: ConsensusItem::StateCommitment(commitment.clone()),
+                peer: PeerId::from(1),
+            }],
+        };
+        let header = block.header(7);
+
+        for (index, (key, value)) in entries.iter().enumerate() {
+            let proof = StateProof {
+                commitment: commitment.clone(),
+                commitment_proof: block.accepted_item_proof(7, 0).expect("Item exists"),
+                key: key.clone(),
+                value: value.clone(),
+                leaf_index: index as u64,
+                branch: state_branch(leaves.clone(), index).expect("Leaf exists"),
+            };
+
+            assert!(proof.verify(&header));
+            assert!(!proof.verify(&block.header(8)));
+
+            let mut wrong_value = proof.clone();
+            wrong_value.value = vec![0xff];
+            assert!(!wrong_value.verify(&header));
+
+            let mut wrong_index = proof.clone();
+            wrong_index.leaf_index += 1;
+            assert!(!wrong_index.verify(&header));
+        }
+
+        assert!(state_branch(leaves, entries.len()).is_none());
+    }
+}
@@ -127,6 +127,38 @@ impl TaskGroup {
         self.join_all(join_timeout).await
     }
 
+    /// The names of the tasks of this group and its subgroups that did not
+    /// finish yet
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn running_tasks(&self) -> Vec<String> {
+        let mut groups = vec![self.clone()];
+        let mut tasks = vec![];
+
+        while let Some(group) = groups.pop() {
+            tasks.extend(
+                group
+                    .inner
+                    .join
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|(_, join)| !join.is_finished())
+                    .map(|(name, _)| name.clone()),
+            );
+
+            groups.extend(
+                group
+                    .inner
+                    .subgroups
+                    .lock()
+                    .expect("locking failed")
+                    .clone(),
+            );
+        }
+
+        tasks
+    }
+
     #[cfg(not(target_family = "wasm"))]
     pub fn install_kill_handler(&self) {
         use tokio::signal;
@@ -441,6 +473,11 @@ mod imp {
         tokio::time::sleep_until(deadline.into()).await
     }
 
+    /// Lets other tasks run before continuing, for long running computations
+    pub async fn yield_now() {
+        tokio::task::yield_now().await
+    }
+
     pub async fn timeout<T>(duration: Duration, future: T) -> Result<T::Output, Elapsed>
     where
         T: Future,
@@ -493,6 +530,13 @@ mod imp {
         sleep(deadline.saturating_duration_since(Instant::now())).await
     }
 
+    /// Lets the browser handle its event loop before continuing, for long
+    /// running computations
+    pub async fn yield_now() {
+        // a zero timeout returns control to the event loop, unlike a resolved promise
+        sleep(Duration::ZERO).await
+    }
+
     pub async fn timeout<T>(duration: Duration, future: T) -> Result<T::Output, Elapsed>
     where
         T: Future,
@@ -657,6 +701,25 @@ mod tests {
         Ok(())
     }
 
+    #[test_log::test(tokio::test)]
+    async fn lists_running_tasks_of_subgroups() -> anyhow::Result<()> {
+        let mut tg = TaskGroup::new();
+        tg.spawn("finished", |_| async {}).await;
+        tg.make_subgroup()
+            .await
+            .spawn("shutdown waiter", |handle| async move {
+                handle.make_shutdown_rx().await.await
+            })
+            .await;
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            tg.running_tasks().await,
+            vec!["shutdown waiter".to_string()]
+        );
+        tg.shutdown_join_all(None).await?;
+        Ok(())
+    }
+
     #[test_log::test(tokio::test)]
     async fn shutdown_task_subgroup_before() -> anyhow::Result<()> {
         let tg = TaskGroup::new();
@@ -1,12 +1,13 @@
 use bitcoin::hashes::Hash as BitcoinHash;
 use bitcoin::XOnlyPublicKey;
 use bitcoin_hashes::hex::ToHex;
-use fedimint_core::core::{DynInput, DynOutput};
+use fedimint_core::core::{DynInput, DynOutput, ModuleInstanceId};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::SerdeModuleEncoding;
-use fedimint_core::{Amount, TransactionId};
+use fedimint_core::{Amount, OutPoint, TransactionId};
 use rand::Rng;
 use secp256k1_zkp::{schnorr, Secp256k1, Signing, Verification};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// An atomic value transfer operation within the Fedimint system and consensus
@@ -161,6 +162,63 @@ where
     session.partial_sig_agg(&partial_sigs)
 }
 
+/// Limits on the size of transactions set by the federation during config
+/// generation, which bound the work a single transaction makes every guardian
+/// do
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct TransactionLimits {
+    /// Maximum size of an encoded transaction in bytes
+    pub max_bytes: u32,
+    /// Maximum number of inputs of a transaction
+    pub max_inputs: u32,
+    /// Maximum number of outputs of a transaction
+    pub max_outputs: u32,
+}
+
+impl Default for TransactionLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 10_000,
+            max_inputs: 256,
+            max_outputs: 256,
+        }
+    }
+}
+
+impl TransactionLimits {
+    /// Checks that the transaction does not exceed any of the limits
+    pub fn check(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let inputs = transaction.inputs.len() as u64;
+        if inputs > u64::from(self.max_inputs) {
+            return Err(TransactionError::TooManyInputs {
+                inputs,
+                limit: self.max_inputs,
+            });
+        }
+
+        let outputs = transaction.outputs.len() as u64;
+        if outputs > u64::from(self.max_outputs) {
+            return Err(TransactionError::TooManyOutputs {
+                outputs,
+                limit: self.max_outputs,
+            });
+        }
+
+        let bytes = transaction
+            .consensus_encode_to_vec()
+            .expect("Writing to a vector cant fail")
+            .len() as u64;
+        if bytes > u64::from(self.max_bytes) {
+            return Err(TransactionError::TooLarge {
+                bytes,
+                limit: self.max_bytes,
+            });
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum TransactionError {
     #[error("The transaction is unbalanced (in={inputs}, out={outputs}, fee={fee})")]
@@ -178,4 +236,103 @@ pub enum TransactionError {
     },
     #[error("The transaction did not have a signature although there were inputs to be signed")]
     MissingSignature,
+    #[error("The transaction has {inputs} inputs, the limit is {limit}")]
+    TooManyInputs { inputs: u64, limit: u32 },
+    #[error("The transaction has {outputs} outputs, the limit is {limit}")]
+    TooManyOutputs { outputs: u64, limit: u32 },
+    #[error("The transaction has {bytes} bytes, the limit is {limit}")]
+    TooLarge { bytes: u64, limit: u32 },
+    #[error("The transaction pays out {payout} of fee income, only {available} is available")]
+    InsufficientFeeIncome { payout: Amount, available: Amount },
+}
+
+/// Why the federation rejected a transaction that was ordered by consensus
+#[derive(Debug, Clone, Eq, PartialEq, Error, Encodable, Decodable, Serialize, Deserialize)]
+pub enum TransactionRejection {
+    #[error("Input {index} was rejected by module {module_instance_id}: {reason}")]
+    InvalidInput {
+        index: u64,
+        module_instance_id: ModuleInstanceId,
+        reason: String,
+    },
+    #[error("Output {index} was rejected by module {module_instance_id}: {reason}")]
+    InvalidOutput {
+        index: u64,
+        module_instance_id: ModuleInstanceId,
+        reason: String,
+    },
+    #[error("The transaction is unbalanced (in={inputs}, out={outputs}, fee={fee})")]
+    UnbalancedTransaction {
+        inputs: Amount,
+        outputs: Amount,
+        fee: Amount,
+    },
+    #[error("The transaction's signature is invalid")]
+    InvalidSignature,
+    #[error("The transaction did not have a signature although there were inputs to be signed")]
+    MissingSignature,
+    #[error("The transaction uses the halted module {0}")]
+    ModuleHalted(ModuleInstanceId),
+    #[error("The transaction has {inputs} inputs, the limit is {limit}")]
+    TooManyInputs { inputs: u64, limit: u32 },
+    #[error("The transaction has {outputs} outputs, the limit is {limit}")]
+    TooManyOutputs { outputs: u64, limit: u32 },
+    #[error("The transaction has {bytes} bytes, the limit is {limit}")]
+    TooLarge { bytes: u64, limit: u32 },
+    #[error("The transaction pays out {payout} of fee income, only {available} is available")]
+    InsufficientFeeIncome { payout: Amount, available: Amount },
+}
+
+impl From<TransactionError> for TransactionRejection {
+    fn from(error: TransactionError) -> Self {
+        match error {
+            TransactionError::UnbalancedTransaction {
+                inputs,
+                outputs,
+                fee,
+            } => TransactionRejection::UnbalancedTransaction {
+                inputs,
+                outputs,
+                fee,
+            },
+            TransactionError::InvalidSignature { .. } => TransactionRejection::InvalidSignature,
+            TransactionError::MissingSignature => TransactionRejection::MissingSignature,
+            TransactionError::TooManyInputs { inputs, limit } => {
+                TransactionRejection::TooManyInputs { inputs, limit }
+            }
+            TransactionError::TooManyOutputs { outputs, limit } => {
+                TransactionRejection::TooManyOutputs { outputs, limit }
+            }
+            TransactionError::TooLarge { bytes, limit } => {
+                TransactionRejection::TooLarge { bytes, limit }
+            }
+            TransactionError::InsufficientFeeIncome { payout, available } => {
+                TransactionRejection::InsufficientFeeIncome { payout, available }
+            }
+        }
+    }
+}
+
+/// The status of a submitted transaction as seen by a guardian
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionOutcome {
+    /// The transaction has been accepted by consensus
+    Accepted,
+    /// The transaction has been ordered by consensus but failed to process
+    Rejected(TransactionRejection),
+    /// The transaction has not been ordered by consensus yet
+    Pending,
+}
+
+/// How an accepted transaction is linked to the other accepted transactions of
+/// the federation, e.g. a lightning contract funded by one transaction and
+/// claimed by another. E-cash notes can not be linked to the transaction that
+/// issued them, so spending notes never creates a dependency.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TransactionDependencies {
+    /// The outputs of earlier transactions spent by the transaction
+    pub upstream: Vec<OutPoint>,
+    /// The later transactions spending outputs of the transaction
+    pub downstream: Vec<TransactionId>,
 }
@@ -0,0 +1,113 @@
+//! Accounting of the API usage of clients by their API token
+//!
+//! Federations offering paid or limited access to their API hand out API
+//! tokens, which clients send in the `auth` field of their requests. The
+//! guardians count the requests and bytes of every token per day and reject
+//! the requests of a token that exceeded its [`ApiQuota`].
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{Decodable, Encodable};
+
+/// Length of the periods usage is accounted and limited in
+pub const USAGE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns the index of the day the time falls into, counted from the unix
+/// epoch
+pub fn usage_day(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / USAGE_PERIOD.as_secs()
+}
+
+/// Usage of the API by one token within a day
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ApiUsage {
+    pub requests: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    /// Number of requests by API endpoint
+    pub requests_by_endpoint: BTreeMap<String, u64>,
+}
+
+impl ApiUsage {
+    pub fn record(&mut self, endpoint: &str, request_bytes: u64, response_bytes: u64) {
+        self.requests += 1;
+        self.request_bytes += request_bytes;
+        self.response_bytes += response_bytes;
+        *self
+            .requests_by_endpoint
+            .entry(endpoint.to_owned())
+            .or_default() += 1;
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.request_bytes + self.response_bytes
+    }
+}
+
+/// Limits on the daily usage of an API token, unlimited if not set
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiQuota {
+    #[serde(default)]
+    pub max_requests_per_day: Option<u64>,
+    /// Limit on the request and response bytes combined
+    #[serde(default)]
+    pub max_bytes_per_day: Option<u64>,
+}
+
+impl ApiQuota {
+    /// Returns a description of the exceeded limit if the usage exhausted the
+    /// quota
+    pub fn exceeded_by(&self, usage: &ApiUsage) -> Option<String> {
+        if let Some(max_requests) = self.max_requests_per_day {
+            if max_requests <= usage.requests {
+                return Some(format!("{max_requests} requests per day"));
+            }
+        }
+
+        if let Some(max_bytes) = self.max_bytes_per_day {
+            if max_bytes <= usage.total_bytes() {
+                return Some(format!("{max_bytes} bytes per day"));
+            }
+        }
+
+        None
+    }
+}
+
+/// Usage of the API by day and token name
+pub type ApiUsageReport = BTreeMap<u64, BTreeMap<String, ApiUsage>>;
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiQuota, ApiUsage};
+
+    #[test]
+    fn quota_is_exceeded_once_a_limit_is_reached() {
+        let quota = ApiQuota {
+            max_requests_per_day: Some(2),
+            max_bytes_per_day: Some(100),
+        };
+        let mut usage = ApiUsage::default();
+
+        assert_eq!(quota.exceeded_by(&usage), None);
+
+        usage.record("session_count", 10, 20);
+        assert_eq!(quota.exceeded_by(&usage), None);
+
+        usage.record("session_count", 10, 20);
+        assert!(quota.exceeded_by(&usage).is_some());
+        assert_eq!(usage.requests_by_endpoint["session_count"], 2);
+
+        let mut usage = ApiUsage::default();
+        usage.record("fetch_block", 10, 90);
+        assert!(quota.exceeded_by(&usage).is_some());
+
+        assert_eq!(ApiQuota::default().exceeded_by(&usage), None);
+    }
+}
@@ -107,6 +107,14 @@ impl SafeUrl {
     pub fn path(&self) -> &str {
         self.0.path()
     }
+    /// Whether the host is a Tor onion service, which can only be reached
+    /// through a Tor proxy
+    pub fn is_onion_address(&self) -> bool {
+        match self.0.host() {
+            Some(Host::Domain(domain)) => domain.trim_end_matches('.').ends_with(".onion"),
+            _ => false,
+        }
+    }
     /// Warning: This will expose username & password if present.
     pub fn as_str(&self) -> &str {
         self.0.as_str()
@@ -265,6 +273,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_onion_address() {
+        let onion = SafeUrl::parse(
+            "ws://vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:8174",
+        )
+        .unwrap();
+        assert!(onion.is_onion_address());
+
+        let clearnet = SafeUrl::parse("ws://onion.example.com:8174").unwrap();
+        assert!(!clearnet.is_onion_address());
+
+        let ip = SafeUrl::parse("ws://127.0.0.1:8174").unwrap();
+        assert!(!ip.is_onion_address());
+    }
+
     #[tokio::test]
     async fn test_next_or_pending() {
         let mut stream = futures::stream::iter(vec![1, 2]);
@@ -14,7 +14,7 @@ use fedimint_core::encoding::Encodable;
 use fedimint_core::epoch::SerdeSignatureShare;
 use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::{push_db_pair_items, push_db_pair_items_no_serde};
+use fedimint_core::{push_db_key_items, push_db_pair_items, push_db_pair_items_no_serde};
 use fedimint_rocksdb::RocksDbReadOnly;
 use fedimint_server::config::io::read_server_config;
 use fedimint_server::config::ServerConfig;
@@ -325,6 +325,361 @@ impl DatabaseDump {
                         "Client Config Download"
                     );
                 }
+                ConsensusRange::DbKeyPrefix::AcceptedTransactionLocation => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::AcceptedTransactionLocationPrefix,
+                        ConsensusRange::AcceptedTransactionLocationKey,
+                        fedimint_core::block::TransactionLocation,
+                        consensus,
+                        "Accepted Transaction Locations"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::PeerLatencyHistory => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::PeerLatencyHistoryPrefix,
+                        ConsensusRange::PeerLatencyHistoryKey,
+                        fedimint_core::query::PeerLatencyHistory,
+                        consensus,
+                        "Peer Latency History"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ArchivedSessionCount => {
+                    let count = dbtx
+                        .get_value(&ConsensusRange::ArchivedSessionCountKey)
+                        .await;
+
+                    if let Some(count) = count {
+                        consensus.insert("Archived Session Count".to_string(), Box::new(count));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::RejectedTransaction => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::RejectedTransactionPrefix,
+                        ConsensusRange::RejectedTransactionKey,
+                        fedimint_core::transaction::TransactionRejection,
+                        consensus,
+                        "Rejected Transactions"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::FinalStateAttestationShare => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::FinalStateAttestationSharePrefix,
+                        ConsensusRange::FinalStateAttestationShareKey,
+                        fedimint_core::migration::FinalStateAttestationShare,
+                        consensus,
+                        "Final State Attestation Shares"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::FinalStateAttestation => {
+                    let attestation = dbtx
+                        .get_value(&ConsensusRange::FinalStateAttestationKey)
+                        .await;
+
+                    if let Some(attestation) = attestation {
+                        consensus
+                            .insert("Final State Attestation".to_string(), Box::new(attestation));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ProposedFinalStateAttestation => {
+                    let attestation = dbtx
+                        .get_value(&ConsensusRange::ProposedFinalStateAttestationKey)
+                        .await;
+
+                    if let Some(attestation) = attestation {
+                        consensus.insert(
+                            "Proposed Final State Attestation".to_string(),
+                            Box::new(attestation),
+                        );
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ModuleProposal => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::ModuleProposalPrefix,
+                        ConsensusRange::ModuleProposalKey,
+                        fedimint_core::lifecycle::AddModuleProposal,
+                        consensus,
+                        "Module Proposals"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ModuleApproval => {
+                    push_db_key_items!(
+                        dbtx,
+                        ConsensusRange::ModuleApprovalPrefix,
+                        ConsensusRange::ModuleApprovalKey,
+                        consensus,
+                        "Module Approvals"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ApprovedModule => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::ApprovedModulePrefix,
+                        ConsensusRange::ApprovedModuleKey,
+                        fedimint_core::lifecycle::AddModuleProposal,
+                        consensus,
+                        "Approved Modules"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::PendingModule => {
+                    let proposal = dbtx.get_value(&ConsensusRange::PendingModuleKey).await;
+
+                    if let Some(proposal) = proposal {
+                        consensus.insert(
+                            "Pending Module".to_string(),
+                            Box::new(SerdeWrapper::from_encodable(proposal)),
+                        );
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::UpgradeApproval => {
+                    push_db_key_items!(
+                        dbtx,
+                        ConsensusRange::UpgradeApprovalPrefix,
+                        ConsensusRange::UpgradeApprovalKey,
+                        consensus,
+                        "Upgrade Approvals"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ApprovedUpgrade => {
+                    push_db_key_items!(
+                        dbtx,
+                        ConsensusRange::ApprovedUpgradePrefix,
+                        ConsensusRange::ApprovedUpgradeKey,
+                        consensus,
+                        "Approved Upgrades"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ScheduledUpgrade => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ScheduledUpgradePrefix,
+                        ConsensusRange::ScheduledUpgradeKey,
+                        fedimint_core::lifecycle::ModuleUpgrade,
+                        consensus,
+                        "Scheduled Upgrades"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ActiveModuleVersion => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ActiveModuleVersionPrefix,
+                        ConsensusRange::ActiveModuleVersionKey,
+                        fedimint_core::module::ModuleConsensusVersion,
+                        consensus,
+                        "Active Module Versions"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::KeyRotationDeal => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::KeyRotationDealPrefix,
+                        ConsensusRange::KeyRotationDealKey,
+                        fedimint_core::rotation::KeyRotationDeal,
+                        consensus,
+                        "Key Rotation Deals"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::KeyRotationConfirmation => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::KeyRotationConfirmationPrefix,
+                        ConsensusRange::KeyRotationConfirmationKey,
+                        bitcoin_hashes::sha256::Hash,
+                        consensus,
+                        "Key Rotation Confirmations"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ScheduledKeyRotation => {
+                    let activation_session = dbtx
+                        .get_value(&ConsensusRange::ScheduledKeyRotationKey)
+                        .await;
+
+                    if let Some(activation_session) = activation_session {
+                        consensus.insert(
+                            "Scheduled Key Rotation".to_string(),
+                            Box::new(activation_session),
+                        );
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::OurKeyRotation => {
+                    let rotation = dbtx.get_value(&ConsensusRange::OurKeyRotationKey).await;
+
+                    if let Some(rotation) = rotation {
+                        consensus.insert(
+                            "Our Key Rotation".to_string(),
+                            Box::new(SerdeWrapper::from_encodable(rotation)),
+                        );
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ApiUsage => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ApiUsagePrefix,
+                        ConsensusRange::ApiUsageKey,
+                        fedimint_core::usage::ApiUsage,
+                        consensus,
+                        "API Usage"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ApiEndpointUpdate => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ApiEndpointUpdatePrefix,
+                        ConsensusRange::ApiEndpointUpdateKey,
+                        fedimint_core::endpoint_update::ApiEndpointUpdate,
+                        consensus,
+                        "API Endpoint Updates"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::RequestedApiEndpoint => {
+                    let endpoint = dbtx
+                        .get_value(&ConsensusRange::RequestedApiEndpointKey)
+                        .await;
+
+                    if let Some(endpoint) = endpoint {
+                        consensus.insert("Requested API Endpoint".to_string(), Box::new(endpoint));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ManagedInviteCode => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ManagedInviteCodePrefix,
+                        ConsensusRange::ManagedInviteCodeKey,
+                        fedimint_core::invite::ManagedInviteCode,
+                        consensus,
+                        "Managed Invite Codes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::InviteCodeRedemption => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::InviteCodeRedemptionPrefix,
+                        ConsensusRange::InviteCodeRedemptionKey,
+                        std::time::SystemTime,
+                        consensus,
+                        "Invite Code Redemptions"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::FederationMetaShare => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::FederationMetaSharePrefix,
+                        ConsensusRange::FederationMetaShareKey,
+                        fedimint_core::meta::FederationMetaShare,
+                        consensus,
+                        "Federation Meta Shares"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::FederationMeta => {
+                    let meta = dbtx.get_value(&ConsensusRange::FederationMetaKey).await;
+
+                    if let Some(meta) = meta {
+                        consensus.insert("Federation Meta".to_string(), Box::new(meta));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ProposedFederationMeta => {
+                    let meta = dbtx
+                        .get_value(&ConsensusRange::ProposedFederationMetaKey)
+                        .await;
+
+                    if let Some(meta) = meta {
+                        consensus.insert("Proposed Federation Meta".to_string(), Box::new(meta));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::TransactionUpstream => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::TransactionUpstreamPrefix,
+                        ConsensusRange::TransactionUpstreamKey,
+                        Vec<fedimint_core::OutPoint>,
+                        consensus,
+                        "Transaction Upstream"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::TransactionDownstream => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::TransactionDownstreamPrefix,
+                        ConsensusRange::TransactionDownstreamKey,
+                        (),
+                        consensus,
+                        "Transaction Downstream"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ScopedToken => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ScopedTokenPrefix,
+                        ConsensusRange::ScopedTokenKey,
+                        fedimint_core::api::ScopedToken,
+                        consensus,
+                        "Scoped Tokens"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::StateSnapshot => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::StateSnapshotPrefix,
+                        ConsensusRange::StateSnapshotKey,
+                        fedimint_server::consensus::state_snapshot::StateSnapshot,
+                        consensus,
+                        "State Snapshots"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::LocalStateCommitment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::LocalStateCommitmentPrefix,
+                        ConsensusRange::LocalStateCommitmentKey,
+                        fedimint_core::state_proof::StateCommitment,
+                        consensus,
+                        "Local State Commitments"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::StateCommitment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::StateCommitmentPrefix,
+                        ConsensusRange::StateCommitmentKey,
+                        fedimint_core::state_proof::StateCommitment,
+                        consensus,
+                        "State Commitments"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ConsensusConfigVersion => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ConsensusConfigVersionPrefix,
+                        ConsensusRange::ConsensusConfigVersionKey,
+                        fedimint_core::config::ConsensusConfigVersion,
+                        consensus,
+                        "Consensus Config Versions"
+                    );
+                }
+                // The running DKG ceremony contains our secrets
+                ConsensusRange::DbKeyPrefix::DkgCeremony
+                | ConsensusRange::DbKeyPrefix::DkgJournal => {}
+                ConsensusRange::DbKeyPrefix::FeeIncome => {
+                    let income = dbtx.get_value(&ConsensusRange::FeeIncomeKey).await;
+
+                    if let Some(income) = income {
+                        consensus.insert("Fee Income".to_string(), Box::new(income));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ModuleHalt => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ModuleHaltPrefix,
+                        ConsensusRange::ModuleHaltKey,
+                        fedimint_core::api::ModuleFailure,
+                        consensus,
+                        "Module Halts"
+                    );
+                }
                 // Module is a global prefix for all module data
                 ConsensusRange::DbKeyPrefix::Module => {}
             }
@@ -162,7 +162,7 @@ async fn main() -> Result<()> {
             } else {
                 vec![
                     DynClientModuleInit::from(WalletClientGen::default()),
-                    DynClientModuleInit::from(MintClientGen),
+                    DynClientModuleInit::from(MintClientGen::default()),
                     DynClientModuleInit::from(LightningClientGen),
                 ]
             });
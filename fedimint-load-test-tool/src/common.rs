@@ -123,7 +123,7 @@ pub async fn build_client(
     rocksdb: Option<&PathBuf>,
 ) -> anyhow::Result<ClientArc> {
     let mut client_builder = ClientBuilder::default();
-    client_builder.with_module(MintClientGen);
+    client_builder.with_module(MintClientGen::default());
     client_builder.with_module(LightningClientGen);
     client_builder.with_module(WalletClientGen::default());
     client_builder.with_primary_module(1);
@@ -20,6 +20,7 @@ pub const LOG_TASK: &str = "task";
 pub const LOG_TEST: &str = "test";
 pub const LOG_TIMING: &str = "timing";
 pub const LOG_WALLET: &str = "wallet";
+pub const LOG_WATCHDOG: &str = "watchdog";
 pub const LOG_CLIENT: &str = "client";
 pub const LOG_CLIENT_NET_API: &str = "client::net::api";
 pub const LOG_CLIENT_BACKUP: &str = "client::backup";
@@ -35,7 +36,10 @@ pub struct TracingSetup {
     with_jaeger: bool,
     #[cfg(feature = "telemetry")]
     with_chrome: bool,
+    #[cfg(feature = "telemetry")]
+    otlp_endpoint: Option<String>,
     with_file: Option<File>,
+    with_json: bool,
 }
 
 impl TracingSetup {
@@ -60,11 +64,26 @@ impl TracingSetup {
         self
     }
 
+    /// Export traces to an OpenTelemetry collector through OTLP/gRPC
+    /// <https://docs.rs/opentelemetry-otlp>
+    #[cfg(feature = "telemetry")]
+    pub fn with_otlp(&mut self, endpoint: Option<String>) -> &mut Self {
+        self.otlp_endpoint = endpoint;
+        self
+    }
+
     pub fn with_file(&mut self, file: Option<File>) -> &mut Self {
         self.with_file = file;
         self
     }
 
+    /// Log one JSON object per event, including the fields of its spans, for
+    /// ingestion by log aggregators like Loki or Elasticsearch
+    pub fn with_json(&mut self, enabled: bool) -> &mut Self {
+        self.with_json = enabled;
+        self
+    }
+
     /// Initialize the logging, must be called for tracing to begin
     pub fn init(&mut self) -> anyhow::Result<()> {
         use tracing_subscriber::fmt::writer::{BoxMakeWriter, Tee};
@@ -79,8 +98,18 @@ impl TracingSetup {
 
         let fmt_layer = tracing_subscriber::fmt::layer()
             .with_thread_names(false) // can be enabled for debugging
-            .with_writer(fmt_writer)
-            .with_filter(filter_layer);
+            .with_writer(fmt_writer);
+
+        let fmt_layer: Box<dyn Layer<_> + Send + Sync + 'static> = if self.with_json {
+            fmt_layer
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_filter(filter_layer)
+                .boxed()
+        } else {
+            fmt_layer.with_filter(filter_layer).boxed()
+        };
 
         let console_opt = || -> Option<Box<dyn Layer<_> + Send + Sync + 'static>> {
             #[cfg(feature = "telemetry")]
@@ -109,6 +138,42 @@ impl TracingSetup {
             None
         };
 
+        let otlp_layer_opt = || -> Option<Box<dyn Layer<_> + Send + Sync + 'static>> {
+            #[cfg(feature = "telemetry")]
+            if let Some(endpoint) = self.otlp_endpoint.clone() {
+                use opentelemetry_otlp::WithExportConfig;
+
+                let tracer = opentelemetry_otlp::new_pipeline()
+                    .tracing()
+                    .with_exporter(
+                        opentelemetry_otlp::new_exporter()
+                            .tonic()
+                            .with_endpoint(endpoint),
+                    )
+                    .with_trace_config(opentelemetry::sdk::trace::config().with_resource(
+                        opentelemetry::sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                            "service.name",
+                            "fedimint",
+                        )]),
+                    ))
+                    .install_batch(opentelemetry::runtime::Tokio)
+                    .unwrap();
+
+                // exporting every trace level span would slow down the guardian, so
+                // we export the same spans we log
+                let filter =
+                    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+                return Some(
+                    tracing_opentelemetry::layer()
+                        .with_tracer(tracer)
+                        .with_filter(filter)
+                        .boxed(),
+                );
+            }
+            None
+        };
+
         let chrome_layer_opt = || -> Option<Box<dyn Layer<_> + Send + Sync + 'static>> {
             #[cfg(feature = "telemetry")]
             if self.with_chrome {
@@ -128,6 +193,7 @@ impl TracingSetup {
             .with(fmt_layer)
             .with(console_opt())
             .with(telemetry_layer_opt())
+            .with(otlp_layer_opt())
             .with(chrome_layer_opt())
             .try_init()?;
         Ok(())
@@ -6,8 +6,8 @@ use axum::Router;
 use fedimint_core::task::{TaskGroup, TaskShutdownToken};
 pub use lazy_static::lazy_static;
 pub use prometheus::{
-    self, histogram_opts, opts, register_histogram, register_int_counter, Encoder, Histogram,
-    IntCounter, TextEncoder,
+    self, histogram_opts, opts, register_histogram, register_int_counter, register_int_counter_vec,
+    Encoder, Histogram, IntCounter, IntCounterVec, TextEncoder,
 };
 use tracing::error;
 
@@ -0,0 +1,251 @@
+//! The global endpoints of the federation API, answered from the ledger
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bitcoin_hashes::sha256;
+use fedimint_core::api::{InviteCode, SerdeOutputOutcome, SessionRange, SnapshotResponse};
+use fedimint_core::backup::ClientBackupSnapshot;
+use fedimint_core::block::{
+    AcceptedItemProof, Block, LocatedTransaction, SignedBlock, SignedBlockHeader,
+    TransactionLocation,
+};
+use fedimint_core::config::ClientConfigResponse;
+use fedimint_core::core::backup::SignedBackupRequest;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::endpoint_constants::{
+    AWAIT_BLOCK_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT, AWAIT_SIGNED_BLOCK_ENDPOINT,
+    AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT, AWAIT_TRANSACTION_OUTCOME_ENDPOINT,
+    AWAIT_TRANSACTION_PROOF_ENDPOINT, BACKUP_ENDPOINT, CONFIG_ENDPOINT, CONFIG_HASH_ENDPOINT,
+    FETCH_BLOCK_COUNT_ENDPOINT, INVITE_CODE_ENDPOINT, RECOVER_ENDPOINT,
+    SESSION_TRANSACTIONS_ENDPOINT, SIGNED_BLOCKS_ENDPOINT, TRANSACTION_ENDPOINT,
+    TRANSACTION_LOCATION_ENDPOINT, VERSION_ENDPOINT, WAIT_TRANSACTION_ENDPOINT,
+};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::{
+    api_endpoint, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased, SerdeModuleEncoding,
+    SupportedApiVersionsSummary,
+};
+use fedimint_core::task::sleep;
+use fedimint_core::transaction::{SerdeTransaction, TransactionOutcome};
+use fedimint_core::{OutPoint, TransactionId};
+use jsonrpsee::types::error::CallError;
+use jsonrpsee::types::ErrorObject;
+use jsonrpsee::RpcModule;
+
+use crate::faults::Fault;
+use crate::MockPeer;
+
+/// How long we wait for a transaction to be ordered before reporting it as
+/// pending, like the guardians do
+const TRANSACTION_OUTCOME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Attaches `endpoints` to the `RpcModule`, prefixing their paths with the
+/// module instance id like the guardians do
+pub fn attach_endpoints(
+    rpc_module: &mut RpcModule<MockPeer>,
+    endpoints: Vec<ApiEndpoint<MockPeer>>,
+    module_instance_id: Option<ModuleInstanceId>,
+) {
+    for endpoint in endpoints {
+        let path: &'static str = match module_instance_id {
+            // This memory leak is fine because it only happens when a mock
+            // federation is built
+            Some(id) => Box::leak(format!("module_{id}_{}", endpoint.path).into_boxed_str()),
+            None => endpoint.path,
+        };
+
+        let endpoint = Arc::new(endpoint);
+
+        rpc_module
+            .register_async_method(path, move |params, peer| {
+                let endpoint = endpoint.clone();
+
+                async move {
+                    let params = params.one::<serde_json::Value>()?;
+
+                    let mut response = None;
+
+                    for fault in peer.faults.faults(peer.peer_id, path) {
+                        match fault {
+                            Fault::Delay(delay) => sleep(delay).await,
+                            Fault::Error(message) => {
+                                response = Some(Err(ApiError::server_error(message)));
+                            }
+                            Fault::Respond(value) => response = Some(Ok(value)),
+                        }
+                    }
+
+                    let response = match response {
+                        Some(response) => response,
+                        None => handle(&peer, &endpoint, params).await,
+                    };
+
+                    response.map_err(|e| {
+                        jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
+                            e.code, e.message, None::<()>,
+                        )))
+                    })
+                }
+            })
+            .expect("Failed to register async method");
+    }
+}
+
+async fn handle(
+    peer: &MockPeer,
+    endpoint: &ApiEndpoint<MockPeer>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, ApiError> {
+    let request: ApiRequestErased =
+        serde_json::from_value(params).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    let context = ApiEndpointContext::new(
+        peer.db.clone(),
+        peer.db.begin_transaction().await,
+        false,
+        request.auth.clone(),
+    );
+
+    (endpoint.handler)(peer, context, request).await
+}
+
+pub fn server_endpoints() -> Vec<ApiEndpoint<MockPeer>> {
+    vec![
+        api_endpoint! {
+            VERSION_ENDPOINT,
+            async |peer: &MockPeer, _context, _v: ()| -> SupportedApiVersionsSummary {
+                Ok(peer.api_versions.clone())
+            }
+        },
+        api_endpoint! {
+            TRANSACTION_ENDPOINT,
+            async |peer: &MockPeer, _context, serde_transaction: SerdeTransaction| -> TransactionId {
+                // without decoders the inputs and outputs are kept as raw bytes
+                let transaction = serde_transaction
+                    .try_into_inner(&ModuleDecoderRegistry::default().with_fallback())
+                    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                Ok(peer.ledger.submit_transaction(transaction))
+            }
+        },
+        api_endpoint! {
+            WAIT_TRANSACTION_ENDPOINT,
+            async |peer: &MockPeer, _context, txid: TransactionId| -> TransactionId {
+                peer.ledger.await_transaction(txid).await;
+
+                Ok(txid)
+            }
+        },
+        api_endpoint! {
+            AWAIT_TRANSACTION_OUTCOME_ENDPOINT,
+            async |peer: &MockPeer, _context, txid: TransactionId| -> TransactionOutcome {
+                Ok(peer.ledger.await_transaction_outcome(txid, TRANSACTION_OUTCOME_TIMEOUT).await)
+            }
+        },
+        api_endpoint! {
+            AWAIT_OUTPUT_OUTCOME_ENDPOINT,
+            async |peer: &MockPeer, _context, outpoint: OutPoint| -> SerdeOutputOutcome {
+                Ok(peer.ledger.await_output_outcome(outpoint).await)
+            }
+        },
+        api_endpoint! {
+            INVITE_CODE_ENDPOINT,
+            async |peer: &MockPeer, _context, _v: ()| -> String {
+                Ok(peer.invite_code.to_string())
+            }
+        },
+        api_endpoint! {
+            CONFIG_ENDPOINT,
+            async |peer: &MockPeer, _context, invite_code: String| -> ClientConfigResponse {
+                let invite_code: InviteCode = invite_code
+                    .parse()
+                    .map_err(|_| ApiError::bad_request("Could not parse invite code".to_string()))?;
+
+                if invite_code != peer.invite_code {
+                    return Err(ApiError::bad_request("Wrong invite code".to_string()));
+                }
+
+                Ok(peer.client_config.clone())
+            }
+        },
+        api_endpoint! {
+            CONFIG_HASH_ENDPOINT,
+            async |peer: &MockPeer, _context, _v: ()| -> sha256::Hash {
+                Ok(peer.client_config.client_config.consensus_hash())
+            }
+        },
+        api_endpoint! {
+            FETCH_BLOCK_COUNT_ENDPOINT,
+            async |peer: &MockPeer, _context, _v: ()| -> u64 {
+                Ok(peer.ledger.block_count())
+            }
+        },
+        api_endpoint! {
+            AWAIT_BLOCK_ENDPOINT,
+            async |peer: &MockPeer, _context, index: u64| -> SerdeModuleEncoding<Block> {
+                Ok((&peer.ledger.await_signed_block(index).await.block).into())
+            }
+        },
+        api_endpoint! {
+            AWAIT_SIGNED_BLOCK_ENDPOINT,
+            async |peer: &MockPeer, _context, index: u64| -> SerdeModuleEncoding<SignedBlock> {
+                Ok((&peer.ledger.await_signed_block(index).await).into())
+            }
+        },
+        api_endpoint! {
+            AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT,
+            async |peer: &MockPeer, _context, index: u64| -> SerdeModuleEncoding<SignedBlockHeader> {
+                Ok((&peer.ledger.await_signed_block(index).await.signed_header(index)).into())
+            }
+        },
+        api_endpoint! {
+            AWAIT_TRANSACTION_PROOF_ENDPOINT,
+            async |peer: &MockPeer, _context, txid: TransactionId| -> SerdeModuleEncoding<AcceptedItemProof> {
+                Ok((&peer.ledger.await_transaction_proof(txid).await).into())
+            }
+        },
+        api_endpoint! {
+            SIGNED_BLOCKS_ENDPOINT,
+            async |peer: &MockPeer, _context, range: SessionRange| -> SnapshotResponse<SerdeModuleEncoding<Vec<SignedBlock>>> {
+                let response = peer.ledger.signed_blocks(range);
+
+                Ok(SnapshotResponse {
+                    value: (&response.value).into(),
+                    snapshot: response.snapshot,
+                })
+            }
+        },
+        api_endpoint! {
+            SESSION_TRANSACTIONS_ENDPOINT,
+            async |peer: &MockPeer, _context, range: SessionRange| -> SnapshotResponse<SerdeModuleEncoding<Vec<LocatedTransaction>>> {
+                let response = peer.ledger.session_transactions(range);
+
+                Ok(SnapshotResponse {
+                    value: (&response.value).into(),
+                    snapshot: response.snapshot,
+                })
+            }
+        },
+        api_endpoint! {
+            TRANSACTION_LOCATION_ENDPOINT,
+            async |peer: &MockPeer, _context, txid: TransactionId| -> SnapshotResponse<Option<TransactionLocation>> {
+                Ok(peer.ledger.transaction_location(txid))
+            }
+        },
+        api_endpoint! {
+            BACKUP_ENDPOINT,
+            async |peer: &MockPeer, _context, request: SignedBackupRequest| -> () {
+                peer.ledger
+                    .store_backup(&request)
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            RECOVER_ENDPOINT,
+            async |peer: &MockPeer, _context, id: secp256k1_zkp::XOnlyPublicKey| -> Option<ClientBackupSnapshot> {
+                Ok(peer.ledger.backup(id))
+            }
+        },
+    ]
+}
@@ -0,0 +1,68 @@
+//! Scriptable deviations of the mock peers from the honest answer
+//!
+//! Faults are looked up on every request, so a test can change them between
+//! two requests of the client under test, e.g. to make a peer disagree with
+//! the others only for a single endpoint.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use fedimint_core::PeerId;
+use serde_json::Value;
+
+/// How a peer deviates from the honest answer to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Answers after the given delay
+    Delay(Duration),
+    /// Answers with an error instead
+    Error(String),
+    /// Answers with the given value instead, e.g. to disagree with the other
+    /// peers
+    Respond(Value),
+}
+
+/// The faults of all peers, shared with their API servers
+#[derive(Debug, Clone, Default)]
+pub struct FaultScript {
+    faults: Arc<Mutex<BTreeMap<PeerId, Vec<(Option<String>, Fault)>>>>,
+}
+
+impl FaultScript {
+    /// Applies the fault to all requests to `peer`, or only to those calling
+    /// `method` if it is given
+    pub fn inject(&self, peer: PeerId, method: Option<&str>, fault: Fault) {
+        self.faults
+            .lock()
+            .expect("Fault lock poisoned")
+            .entry(peer)
+            .or_default()
+            .push((method.map(str::to_owned), fault));
+    }
+
+    /// Makes a peer honest again
+    pub fn clear(&self, peer: PeerId) {
+        self.faults
+            .lock()
+            .expect("Fault lock poisoned")
+            .remove(&peer);
+    }
+
+    pub fn clear_all(&self) {
+        self.faults.lock().expect("Fault lock poisoned").clear();
+    }
+
+    /// The faults to apply to a request, in the order they were injected
+    pub(crate) fn faults(&self, peer: PeerId, method: &str) -> Vec<Fault> {
+        self.faults
+            .lock()
+            .expect("Fault lock poisoned")
+            .get(&peer)
+            .into_iter()
+            .flatten()
+            .filter(|(target, _)| target.as_deref().map_or(true, |target| target == method))
+            .map(|(_, fault)| fault.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,303 @@
+//! Deterministic in-memory ledger shared by all peers of a mock federation
+//!
+//! Submitted transactions are accepted in the order they arrive, without being
+//! validated by any module, unless a rejection has been scripted for them. The
+//! ledger never closes a session on its own, the test decides when the pending
+//! items form the next signed block via [`Ledger::close_session`].
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use fedimint_core::api::{SerdeOutputOutcome, SessionRange, SnapshotInfo, SnapshotResponse};
+use fedimint_core::backup::ClientBackupSnapshot;
+use fedimint_core::block::{
+    broadcast_message_hash, AcceptedItem, AcceptedItemProof, Block, LocatedTransaction,
+    SchnorrSignature, SignedBlock, TransactionLocation,
+};
+use fedimint_core::core::backup::SignedBackupRequest;
+use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::transaction::{Transaction, TransactionOutcome, TransactionRejection};
+use fedimint_core::{OutPoint, PeerId, TransactionId};
+use secp256k1_zkp::{KeyPair, PublicKey, XOnlyPublicKey, SECP256K1};
+use tokio::sync::watch;
+
+#[derive(Debug, Default)]
+struct LedgerState {
+    signed_blocks: Vec<SignedBlock>,
+    pending_items: Vec<AcceptedItem>,
+    outcomes: BTreeMap<TransactionId, TransactionOutcome>,
+    locations: BTreeMap<TransactionId, TransactionLocation>,
+    rejections: BTreeMap<TransactionId, TransactionRejection>,
+    output_outcomes: BTreeMap<OutPoint, SerdeOutputOutcome>,
+    backups: BTreeMap<XOnlyPublicKey, ClientBackupSnapshot>,
+}
+
+/// The consensus history of a mock federation
+#[derive(Debug, Clone)]
+pub struct Ledger {
+    keypairs: Arc<BTreeMap<PeerId, KeyPair>>,
+    public_keys: Arc<BTreeMap<PeerId, PublicKey>>,
+    state: Arc<watch::Sender<LedgerState>>,
+}
+
+impl Ledger {
+    /// Creates an empty ledger whose blocks are signed with the broadcast keys
+    /// of all peers
+    pub fn new(keypairs: BTreeMap<PeerId, KeyPair>) -> Self {
+        let public_keys = keypairs
+            .iter()
+            .map(|(peer, keypair)| (*peer, keypair.public_key()))
+            .collect();
+
+        Self {
+            keypairs: Arc::new(keypairs),
+            public_keys: Arc::new(public_keys),
+            state: Arc::new(watch::channel(LedgerState::default()).0),
+        }
+    }
+
+    pub fn public_keys(&self) -> &BTreeMap<PeerId, PublicKey> {
+        &self.public_keys
+    }
+
+    /// Waits until `f` returns a value for the state of the ledger
+    async fn wait_for<T>(&self, f: impl Fn(&LedgerState) -> Option<T>) -> T {
+        let mut receiver = self.state.subscribe();
+
+        loop {
+            if let Some(value) = f(&receiver.borrow_and_update()) {
+                return value;
+            }
+
+            receiver
+                .changed()
+                .await
+                .expect("The ledger owns the sender");
+        }
+    }
+
+    /// Orders the transaction in the current session as if it was proposed by
+    /// the first peer, or rejects it if a rejection has been scripted for it
+    pub fn submit_transaction(&self, transaction: Transaction) -> TransactionId {
+        let txid = transaction.tx_hash();
+
+        self.state.send_modify(|state| {
+            if state.outcomes.contains_key(&txid) {
+                return;
+            }
+
+            if let Some(rejection) = state.rejections.get(&txid) {
+                state
+                    .outcomes
+                    .insert(txid, TransactionOutcome::Rejected(rejection.clone()));
+
+                return;
+            }
+
+            let location = TransactionLocation {
+                session_index: state.signed_blocks.len() as u64,
+                item_index: state.pending_items.len() as u64,
+            };
+
+            state.pending_items.push(AcceptedItem {
+                item: ConsensusItem::Transaction(transaction),
+                peer: PeerId::from(0),
+            });
+
+            state.locations.insert(txid, location);
+            state.outcomes.insert(txid, TransactionOutcome::Accepted);
+        });
+
+        txid
+    }
+
+    /// Rejects the transaction once it is submitted
+    pub fn reject_transaction(&self, txid: TransactionId, rejection: TransactionRejection) {
+        self.state.send_modify(|state| {
+            state.rejections.insert(txid, rejection);
+        });
+    }
+
+    /// Sets the outcome returned for an output once its transaction has been
+    /// accepted, since the ledger does not run the modules that would create it
+    pub fn set_output_outcome(&self, outpoint: OutPoint, outcome: SerdeOutputOutcome) {
+        self.state.send_modify(|state| {
+            state.output_outcomes.insert(outpoint, outcome);
+        });
+    }
+
+    /// Signs a block containing all items ordered since the last session was
+    /// closed and returns its index
+    pub fn close_session(&self) -> u64 {
+        let mut index = 0;
+
+        self.state.send_modify(|state| {
+            index = state.signed_blocks.len() as u64;
+
+            let block = Block {
+                items: std::mem::take(&mut state.pending_items),
+            };
+
+            let message = broadcast_message_hash(&self.public_keys, &block.header(index));
+
+            let signatures = self
+                .keypairs
+                .iter()
+                .map(|(peer, keypair)| {
+                    // without auxiliary randomness the signed blocks are reproducible
+                    let signature = SECP256K1.sign_schnorr_no_aux_rand(&message, keypair);
+
+                    (*peer, SchnorrSignature(signature.as_ref().to_owned()))
+                })
+                .collect();
+
+            state.signed_blocks.push(SignedBlock { block, signatures });
+        });
+
+        index
+    }
+
+    pub fn block_count(&self) -> u64 {
+        self.state.borrow().signed_blocks.len() as u64
+    }
+
+    pub async fn await_signed_block(&self, index: u64) -> SignedBlock {
+        self.wait_for(|state| state.signed_blocks.get(index as usize).cloned())
+            .await
+    }
+
+    fn snapshot<T>(&self, value: T) -> SnapshotResponse<T> {
+        SnapshotResponse {
+            value,
+            snapshot: SnapshotInfo {
+                session_count: self.block_count(),
+                age: Duration::ZERO,
+            },
+        }
+    }
+
+    pub fn signed_blocks(&self, range: SessionRange) -> SnapshotResponse<Vec<SignedBlock>> {
+        let signed_blocks = range
+            .indices()
+            .map_while(|index| {
+                self.state
+                    .borrow()
+                    .signed_blocks
+                    .get(index as usize)
+                    .cloned()
+            })
+            .collect();
+
+        self.snapshot(signed_blocks)
+    }
+
+    pub fn session_transactions(
+        &self,
+        range: SessionRange,
+    ) -> SnapshotResponse<Vec<LocatedTransaction>> {
+        let transactions = self
+            .signed_blocks(range)
+            .value
+            .into_iter()
+            .zip(range.indices())
+            .flat_map(|(signed_block, index)| {
+                signed_block
+                    .block
+                    .located_transactions(index)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        self.snapshot(transactions)
+    }
+
+    /// Returns the location of a transaction once its session has been closed
+    pub fn transaction_location(
+        &self,
+        txid: TransactionId,
+    ) -> SnapshotResponse<Option<TransactionLocation>> {
+        let block_count = self.block_count();
+
+        let location = self
+            .state
+            .borrow()
+            .locations
+            .get(&txid)
+            .copied()
+            .filter(|location| location.session_index < block_count);
+
+        self.snapshot(location)
+    }
+
+    pub async fn await_transaction(&self, txid: TransactionId) {
+        self.wait_for(|state| match state.outcomes.get(&txid) {
+            Some(TransactionOutcome::Accepted) => Some(()),
+            _ => None,
+        })
+        .await;
+    }
+
+    /// Waits for the transaction to be ordered or until `timeout` has elapsed
+    pub async fn await_transaction_outcome(
+        &self,
+        txid: TransactionId,
+        timeout: Duration,
+    ) -> TransactionOutcome {
+        fedimint_core::task::timeout(
+            timeout,
+            self.wait_for(|state| state.outcomes.get(&txid).cloned()),
+        )
+        .await
+        .unwrap_or(TransactionOutcome::Pending)
+    }
+
+    /// Waits for the session containing the transaction to be closed
+    pub async fn await_transaction_proof(&self, txid: TransactionId) -> AcceptedItemProof {
+        let location = self
+            .wait_for(|state| state.locations.get(&txid).copied())
+            .await;
+
+        self.await_signed_block(location.session_index)
+            .await
+            .block
+            .accepted_item_proof(location.session_index, location.item_index)
+            .expect("The location points into the block")
+    }
+
+    pub async fn await_output_outcome(&self, outpoint: OutPoint) -> SerdeOutputOutcome {
+        self.await_transaction(outpoint.txid).await;
+
+        self.wait_for(|state| state.output_outcomes.get(&outpoint).cloned())
+            .await
+    }
+
+    /// Stores a client backup unless a newer one is stored already
+    pub fn store_backup(&self, request: &SignedBackupRequest) -> anyhow::Result<()> {
+        let request = request.verify_valid(SECP256K1)?;
+        let mut result = Ok(());
+
+        self.state.send_modify(|state| {
+            if let Some(previous) = state.backups.get(&request.id) {
+                if request.timestamp <= previous.timestamp {
+                    result = Err(anyhow::anyhow!("Timestamp too small"));
+                    return;
+                }
+            }
+
+            state.backups.insert(
+                request.id,
+                ClientBackupSnapshot {
+                    timestamp: request.timestamp,
+                    data: request.payload.to_vec(),
+                },
+            );
+        });
+
+        result
+    }
+
+    pub fn backup(&self, id: XOnlyPublicKey) -> Option<ClientBackupSnapshot> {
+        self.state.borrow().backups.get(&id).cloned()
+    }
+}
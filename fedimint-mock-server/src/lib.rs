@@ -0,0 +1,410 @@
+//! Mock federation for client integration tests
+//!
+//! Serves the federation API of every peer on a local websocket, answering
+//! from a shared in-memory [`Ledger`] instead of running the atomic broadcast
+//! and the consensus. Since clients connect to it exactly like to a real
+//! federation, wallet developers can run their integration tests against it
+//! without spinning up guardians. The test drives the consensus itself: it
+//! closes sessions, scripts rejections and output outcomes on the [`Ledger`]
+//! and makes individual peers misbehave via the [`FaultScript`].
+//!
+//! The mock does not run any server modules, so it neither validates the
+//! inputs and outputs of transactions nor serves module endpoints, unless the
+//! test registers them via [`MockFederationBuilder::with_module_endpoints`].
+
+mod api;
+pub mod faults;
+pub mod ledger;
+
+use std::collections::BTreeMap;
+
+use fedimint_core::api::{ClientConfigDownloadToken, DynGlobalApi, InviteCode, WsFederationApi};
+use fedimint_core::config::{
+    ClientConfig, ClientConfigResponse, ClientModuleConfig, FederationId, GlobalClientConfig,
+    PeerUrl, META_FEDERATION_NAME_KEY,
+};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
+use fedimint_core::epoch::SerdeSignature;
+use fedimint_core::fee::FeeSchedule;
+use fedimint_core::module::{
+    ApiEndpoint, ApiVersion, CoreConsensusVersion, MultiApiVersion, SupportedApiVersionsSummary,
+    SupportedCoreApiVersions, SupportedModuleApiVersions,
+};
+use fedimint_core::transaction::TransactionLimits;
+use fedimint_core::{NumPeers, PeerId};
+use fedimint_logging::LOG_NET_API;
+use jsonrpsee::server::{ServerBuilder, ServerHandle};
+use jsonrpsee::RpcModule;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use secp256k1_zkp::{KeyPair, SECP256K1};
+use threshold_crypto::SecretKeySet;
+use tracing::info;
+
+pub use self::faults::{Fault, FaultScript};
+pub use self::ledger::Ledger;
+
+/// The core consensus version announced by the mock, matching the guardians
+const CORE_CONSENSUS_VERSION: CoreConsensusVersion = CoreConsensusVersion(u32::MAX);
+
+/// The state of a single mock peer that is passed to its API endpoints
+pub struct MockPeer {
+    pub peer_id: PeerId,
+    pub ledger: Ledger,
+    faults: FaultScript,
+    client_config: ClientConfigResponse,
+    invite_code: InviteCode,
+    api_versions: SupportedApiVersionsSummary,
+    db: Database,
+}
+
+/// Configures a [`MockFederation`] before its API servers are started
+pub struct MockFederationBuilder {
+    num_peers: u16,
+    seed: u64,
+    meta: BTreeMap<String, String>,
+    modules: BTreeMap<ModuleInstanceId, (ClientModuleConfig, SupportedModuleApiVersions)>,
+    module_endpoints: BTreeMap<ModuleInstanceId, fn() -> Vec<ApiEndpoint<MockPeer>>>,
+}
+
+impl Default for MockFederationBuilder {
+    fn default() -> Self {
+        Self {
+            num_peers: 4,
+            seed: 0,
+            meta: BTreeMap::from([(META_FEDERATION_NAME_KEY.to_owned(), "mock".to_owned())]),
+            modules: BTreeMap::new(),
+            module_endpoints: BTreeMap::new(),
+        }
+    }
+}
+
+impl MockFederationBuilder {
+    pub fn num_peers(mut self, num_peers: u16) -> Self {
+        self.num_peers = num_peers;
+        self
+    }
+
+    /// Seeds the generation of all keys, such that the federation id and the
+    /// signed blocks are the same in every run
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_meta(mut self, key: &str, value: &str) -> Self {
+        self.meta.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Announces a module to the clients in the client config
+    pub fn with_module(
+        mut self,
+        id: ModuleInstanceId,
+        config: ClientModuleConfig,
+        api_versions: SupportedModuleApiVersions,
+    ) -> Self {
+        self.modules.insert(id, (config, api_versions));
+        self
+    }
+
+    /// Serves `endpoints` as the API of a module, e.g. to answer the requests
+    /// of a client module from the ledger
+    pub fn with_module_endpoints(
+        mut self,
+        id: ModuleInstanceId,
+        endpoints: fn() -> Vec<ApiEndpoint<MockPeer>>,
+    ) -> Self {
+        self.module_endpoints.insert(id, endpoints);
+        self
+    }
+
+    /// Generates the keys of the federation and starts the API of every peer
+    /// on a free local port
+    pub async fn build(self) -> MockFederation {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let peers = (0..self.num_peers).map(PeerId::from).collect::<Vec<_>>();
+
+        let auth_keys = SecretKeySet::random(peers.degree(), &mut rng);
+
+        let keypairs = peers
+            .iter()
+            .map(|peer| {
+                let (secret_key, _) = secp256k1_zkp::generate_keypair(&mut rng);
+
+                (*peer, KeyPair::from_secret_key(SECP256K1, &secret_key))
+            })
+            .collect();
+
+        let ledger = Ledger::new(keypairs);
+
+        // we bind all servers first since the client config contains their urls
+        let mut servers = BTreeMap::new();
+
+        for peer in &peers {
+            let server = ServerBuilder::new()
+                .build("127.0.0.1:0")
+                .await
+                .expect("Could not build API server");
+
+            servers.insert(*peer, server);
+        }
+
+        let api_endpoints = servers
+            .iter()
+            .map(|(peer, server)| {
+                let address = server.local_addr().expect("Server is bound");
+
+                let url = PeerUrl {
+                    url: format!("ws://{address}").parse().expect("Valid url"),
+                    name: format!("peer-{peer}"),
+                    fallback_urls: vec![],
+                };
+
+                (*peer, url)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let client_config = ClientConfig {
+            global: GlobalClientConfig {
+                federation_id: FederationId(auth_keys.public_keys().public_key()),
+                api_endpoints: api_endpoints.clone(),
+                epoch_pk: auth_keys.public_keys().public_key(),
+                broadcast_public_keys: ledger.public_keys().clone(),
+                consensus_version: CORE_CONSENSUS_VERSION,
+                meta: self.meta,
+                transaction_limits: TransactionLimits::default(),
+                fees: FeeSchedule::default(),
+            },
+            modules: self
+                .modules
+                .iter()
+                .map(|(id, (config, _))| (*id, config.clone()))
+                .collect(),
+        };
+
+        let client_config = ClientConfigResponse {
+            signature: SerdeSignature(auth_keys.secret_key().sign(client_config.consensus_hash())),
+            client_config,
+        };
+
+        let api_versions = SupportedApiVersionsSummary {
+            core: SupportedCoreApiVersions {
+                core_consensus: CORE_CONSENSUS_VERSION,
+                api: MultiApiVersion::try_from_iter([ApiVersion { major: 0, minor: 0 }])
+                    .expect("no version conflicts"),
+            },
+            modules: self
+                .modules
+                .into_iter()
+                .map(|(id, (_, api_versions))| (id, api_versions))
+                .collect(),
+        };
+
+        let download_token = ClientConfigDownloadToken(rng.gen());
+        let faults = FaultScript::default();
+        let mut handles = vec![];
+
+        for (peer, server) in servers {
+            let invite_code = invite_code(&client_config.client_config, &download_token, peer);
+
+            let mut rpc_module = RpcModule::new(MockPeer {
+                peer_id: peer,
+                ledger: ledger.clone(),
+                faults: faults.clone(),
+                client_config: client_config.clone(),
+                invite_code,
+                api_versions: api_versions.clone(),
+                db: Database::new(MemDatabase::new(), Default::default()),
+            });
+
+            api::attach_endpoints(&mut rpc_module, api::server_endpoints(), None);
+
+            for (id, endpoints) in &self.module_endpoints {
+                api::attach_endpoints(&mut rpc_module, endpoints(), Some(*id));
+            }
+
+            info!(target: LOG_NET_API, %peer, url = %api_endpoints[&peer].url, "Starting mock api");
+
+            handles.push(
+                server
+                    .start(rpc_module)
+                    .expect("Could not start API server"),
+            );
+        }
+
+        MockFederation {
+            ledger,
+            faults,
+            client_config: client_config.client_config,
+            download_token,
+            handles,
+        }
+    }
+}
+
+/// A federation whose peers answer from the same in-memory [`Ledger`]
+///
+/// The API servers are stopped when it is dropped.
+pub struct MockFederation {
+    ledger: Ledger,
+    faults: FaultScript,
+    client_config: ClientConfig,
+    download_token: ClientConfigDownloadToken,
+    handles: Vec<ServerHandle>,
+}
+
+impl MockFederation {
+    pub fn builder() -> MockFederationBuilder {
+        MockFederationBuilder::default()
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn faults(&self) -> &FaultScript {
+        &self.faults
+    }
+
+    pub fn client_config(&self) -> &ClientConfig {
+        &self.client_config
+    }
+
+    /// An invite code for downloading the client config from `peer`
+    pub fn invite_code(&self, peer: PeerId) -> InviteCode {
+        invite_code(&self.client_config, &self.download_token, peer)
+    }
+
+    pub fn api(&self) -> DynGlobalApi {
+        WsFederationApi::from_config(&self.client_config).into()
+    }
+}
+
+fn invite_code(
+    client_config: &ClientConfig,
+    download_token: &ClientConfigDownloadToken,
+    peer: PeerId,
+) -> InviteCode {
+    InviteCode {
+        url: client_config.global.api_endpoints[&peer].url.clone(),
+        download_token: download_token.clone(),
+        id: client_config.global.federation_id,
+        peer_id: peer,
+    }
+}
+
+impl Drop for MockFederation {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            // the server may have been stopped already if its runtime shut down
+            handle.stop().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::api::{ApiRequestErased, GlobalFederationApi, IFederationApi};
+    use fedimint_core::endpoint_constants::FETCH_BLOCK_COUNT_ENDPOINT;
+    use fedimint_core::transaction::{Transaction, TransactionOutcome, TransactionRejection};
+    use fedimint_core::PeerId;
+    use serde_json::json;
+
+    use super::{Fault, MockFederation};
+
+    fn transaction(signature: u8) -> Transaction {
+        Transaction {
+            inputs: vec![],
+            outputs: vec![],
+            signature: Some(
+                secp256k1_zkp::schnorr::Signature::from_slice(&[signature; 64])
+                    .expect("Any 64 bytes form a signature"),
+            ),
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn client_follows_the_ledger() {
+        let federation = MockFederation::builder().seed(1).build().await;
+        let api = federation.api();
+
+        let client_config = api
+            .download_client_config(&federation.invite_code(PeerId::from(0)))
+            .await
+            .unwrap();
+
+        assert_eq!(&client_config, federation.client_config());
+
+        let txid = api.submit_transaction(transaction(1)).await.unwrap();
+
+        assert_eq!(api.await_transaction(txid).await.unwrap(), txid);
+        assert_eq!(api.fetch_block_count().await.unwrap(), 0);
+
+        let index = federation.ledger().close_session();
+
+        let header = api
+            .await_signed_block_header(index, &client_config.global.broadcast_public_keys)
+            .await
+            .unwrap();
+
+        let proof = api.await_transaction_proof(txid).await.unwrap();
+
+        assert_eq!(proof.session_index, index);
+        assert_eq!(header.index(), index);
+        assert_eq!(api.fetch_block_count().await.unwrap(), 1);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn scripted_rejection_is_reported() {
+        let federation = MockFederation::builder().build().await;
+        let api = federation.api();
+
+        let transaction = transaction(2);
+        let txid = transaction.tx_hash();
+
+        federation
+            .ledger()
+            .reject_transaction(txid, TransactionRejection::InvalidSignature);
+
+        api.submit_transaction(transaction).await.unwrap();
+
+        assert_eq!(
+            api.await_transaction_outcome(txid).await.unwrap(),
+            TransactionOutcome::Rejected(TransactionRejection::InvalidSignature)
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn faulty_peer_is_outvoted() {
+        let federation = MockFederation::builder().build().await;
+        let api = federation.api();
+
+        federation.faults().inject(
+            PeerId::from(3),
+            Some(FETCH_BLOCK_COUNT_ENDPOINT),
+            Fault::Respond(json!(7)),
+        );
+
+        let params = [serde_json::to_value(ApiRequestErased::default()).unwrap()];
+
+        let answer = api
+            .request_raw(PeerId::from(3), FETCH_BLOCK_COUNT_ENDPOINT, &params)
+            .await
+            .unwrap();
+
+        assert_eq!(answer, json!(7));
+        assert_eq!(api.fetch_block_count().await.unwrap(), 0);
+
+        federation.faults().clear(PeerId::from(3));
+
+        let answer = api
+            .request_raw(PeerId::from(3), FETCH_BLOCK_COUNT_ENDPOINT, &params)
+            .await
+            .unwrap();
+
+        assert_eq!(answer, json!(0));
+    }
+}
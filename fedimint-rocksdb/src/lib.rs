@@ -4,12 +4,12 @@ use std::path::Path;
 use anyhow::Result;
 use async_trait::async_trait;
 use fedimint_core::db::{
-    IDatabaseTransactionOps, IDatabaseTransactionOpsCore, IRawDatabase, IRawDatabaseTransaction,
-    PrefixStream,
+    CommitConflictError, IDatabaseTransactionOps, IDatabaseTransactionOpsCore, IRawDatabase,
+    IRawDatabaseTransaction, PrefixStream, StorageFullError,
 };
 use futures::stream;
 pub use rocksdb;
-use rocksdb::{OptimisticTransactionDB, OptimisticTransactionOptions, WriteOptions};
+use rocksdb::{ErrorKind, OptimisticTransactionDB, OptimisticTransactionOptions, WriteOptions};
 
 #[derive(Debug)]
 pub struct RocksDb(rocksdb::OptimisticTransactionDB);
@@ -235,12 +235,24 @@ impl<'a> IDatabaseTransactionOps for RocksDbTransaction<'a> {
 impl<'a> IRawDatabaseTransaction for RocksDbTransaction<'a> {
     async fn commit_tx(self) -> Result<()> {
         fedimint_core::task::block_in_place(|| {
-            self.0.commit()?;
+            self.0.commit().map_err(map_commit_error)?;
             Ok(())
         })
     }
 }
 
+/// RocksDB reports a full disk as a generic IO error, so we have to inspect
+/// the message to surface it as a [`StorageFullError`]
+fn map_commit_error(error: rocksdb::Error) -> anyhow::Error {
+    if error.as_ref().contains("No space left on device") {
+        StorageFullError(error.into_string()).into()
+    } else if matches!(error.kind(), ErrorKind::Busy | ErrorKind::TryAgain) {
+        CommitConflictError(error.into_string()).into()
+    } else {
+        error.into()
+    }
+}
+
 #[async_trait]
 impl<'a> IDatabaseTransactionOpsCore for RocksDbReadOnlyTransaction<'a> {
     async fn raw_insert_bytes(&mut self, _key: &[u8], _value: &[u8]) -> Result<Option<Vec<u8>>> {
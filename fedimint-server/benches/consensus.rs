@@ -0,0 +1,304 @@
+#![cfg_attr(feature = "unstable", feature(test))]
+
+//! Throughput of the consensus item pipeline of a single guardian
+//!
+//! The transactions are generated and signed before the measurement. As every
+//! transaction can only be accepted once, the guardian starts over on an empty
+//! database whenever the pool of transactions is used up.
+
+#[cfg(feature = "unstable")]
+mod bench {
+    extern crate test;
+
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use bitcoin::secp256k1;
+    use fedimint_core::block::SignedBlock;
+    use fedimint_core::config::{ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry};
+    use fedimint_core::core::{DynInput, DynOutput, ModuleInstanceId};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::module::{DynServerModuleInit, ServerModuleInit};
+    use fedimint_core::task::TaskGroup;
+    use fedimint_core::transaction::{agg_sign, Transaction};
+    use fedimint_core::{Amount, PeerId};
+    use fedimint_dummy_common::config::DummyGenParams;
+    use fedimint_dummy_common::{fed_key_pair, DummyInput, DummyOutput};
+    use fedimint_dummy_server::DummyGen;
+    use fedimint_mint_server::common::config::MintGenParams;
+    use fedimint_mint_server::common::{BlindNonce, MintOutput};
+    use fedimint_mint_server::MintGen;
+    use fedimint_server::config::{local_config_gen_params, ServerConfig};
+    use fedimint_server::consensus::server::ConsensusServer;
+    use fedimint_server::net::connect::mock::{MockNetwork, StreamReliability};
+    use fedimint_server::net::connect::Connector;
+    use fedimint_server::net::peers::DelayCalculator;
+    use fedimint_server::signer::LocalSigner;
+    use rand::rngs::OsRng;
+    use rand::Rng;
+    use secp256k1_zkp::{All, Secp256k1};
+    use tbs::{blind_message, BlindingKey, Message};
+    use test::Bencher;
+    use tokio::runtime::Runtime;
+
+    const DUMMY_INSTANCE: ModuleInstanceId = 0;
+    const MINT_INSTANCE: ModuleInstanceId = 1;
+
+    /// The guardian never binds its ports, the P2P network is kept in memory
+    const BASE_PORT: u16 = 18_000;
+
+    /// Number of transactions generated before the measurement
+    const POOL_SIZE: usize = 1_000;
+
+    /// Notes issued by every transaction of [`Workload::Mint`]
+    const NOTES_PER_TRANSACTION: usize = 8;
+
+    /// Denomination of the issued notes, a power of two as the mint's default
+    /// amount tiers
+    const NOTE_AMOUNT: Amount = Amount::from_msats(1024);
+
+    #[derive(Debug, Clone, Copy)]
+    enum Workload {
+        /// Pays from the dummy module's federation account to a new user
+        /// account, which only updates balances in the database
+        Dummy,
+        /// Issues ecash notes paid from the dummy module's federation account,
+        /// which the mint signs once the session is completed
+        Mint,
+    }
+
+    impl Workload {
+        fn transaction(self, secp: &Secp256k1<All>) -> Transaction {
+            let (amount, outputs) = match self {
+                Workload::Dummy => {
+                    let amount = Amount::from_msats(OsRng.gen_range(1..1_000_000));
+                    let (_, public_key) = secp256k1::generate_keypair(&mut OsRng);
+                    let account = public_key.x_only_public_key().0;
+                    let output =
+                        DynOutput::from_typed(DUMMY_INSTANCE, DummyOutput { amount, account });
+
+                    (amount, vec![output])
+                }
+                Workload::Mint => {
+                    let outputs = (0..NOTES_PER_TRANSACTION)
+                        .map(|_| {
+                            let nonce = Message::from_bytes(&OsRng.gen::<[u8; 32]>());
+                            let blind_nonce =
+                                BlindNonce(blind_message(nonce, BlindingKey::random()));
+
+                            DynOutput::from_typed(
+                                MINT_INSTANCE,
+                                MintOutput {
+                                    amount: NOTE_AMOUNT,
+                                    blind_nonce,
+                                },
+                            )
+                        })
+                        .collect();
+
+                    (NOTE_AMOUNT * NOTES_PER_TRANSACTION as u64, outputs)
+                }
+            };
+
+            // the federation account of the dummy module prints any amount, the
+            // fees of both modules are zero by default
+            let inputs = vec![DynInput::from_typed(
+                DUMMY_INSTANCE,
+                DummyInput {
+                    amount,
+                    account: fed_key_pair().x_only_public_key().0,
+                },
+            )];
+
+            let txid = Transaction::tx_hash_from_parts(&inputs, &outputs);
+            let signature = agg_sign(&[fed_key_pair()], txid.as_hash(), secp, OsRng);
+
+            Transaction {
+                inputs,
+                outputs,
+                signature: Some(signature),
+            }
+        }
+    }
+
+    /// A federation consisting of a single guardian running the dummy and the
+    /// mint module
+    struct Guardian {
+        cfg: ServerConfig,
+        registry: ServerModuleInitRegistry,
+        server: ConsensusServer,
+        task_group: TaskGroup,
+        session_index: u64,
+        item_index: u64,
+    }
+
+    impl Guardian {
+        async fn new() -> Guardian {
+            let peer = PeerId::from(0);
+
+            let mut module_params = ServerModuleConfigGenParamsRegistry::default();
+            module_params.attach_config_gen_params(
+                DUMMY_INSTANCE,
+                DummyGen::kind(),
+                DummyGenParams::default(),
+            );
+            module_params.attach_config_gen_params(
+                MINT_INSTANCE,
+                MintGen::kind(),
+                MintGenParams::default(),
+            );
+
+            let registry = ServerModuleInitRegistry::from(vec![
+                DynServerModuleInit::from(DummyGen),
+                DynServerModuleInit::from(MintGen),
+            ]);
+            let params =
+                local_config_gen_params(&[peer], BASE_PORT, module_params).expect("Valid params");
+            let cfg = ServerConfig::trusted_dealer_gen(&params, registry.clone())
+                .remove(&peer)
+                .expect("Config of our peer");
+
+            Guardian::start(cfg, registry).await
+        }
+
+        async fn start(cfg: ServerConfig, registry: ServerModuleInitRegistry) -> Guardian {
+            let mut task_group = TaskGroup::new();
+            let decoders = registry
+                .available_decoders(cfg.consensus.iter_module_instances())
+                .expect("All modules are registered");
+            let connector = MockNetwork::new()
+                .connector(cfg.local.identity, StreamReliability::FullyReliable)
+                .into_dyn();
+
+            let (server, _api) = ConsensusServer::new_with(
+                cfg.clone(),
+                Database::new(MemDatabase::new(), decoders),
+                registry.clone(),
+                Arc::new(LocalSigner::new(&cfg)),
+                connector,
+                DelayCalculator::TEST_DEFAULT,
+                &mut task_group,
+            )
+            .await
+            .expect("Guardian starts");
+
+            Guardian {
+                cfg,
+                registry,
+                server,
+                task_group,
+                session_index: 0,
+                item_index: 0,
+            }
+        }
+
+        /// Starts over on an empty database
+        async fn restart(&mut self) {
+            self.task_group.shutdown();
+            *self = Guardian::start(self.cfg.clone(), self.registry.clone()).await;
+        }
+
+        async fn process(&mut self, transaction: Transaction) {
+            self.server
+                .process_consensus_item(
+                    self.session_index,
+                    self.item_index,
+                    ConsensusItem::Transaction(transaction),
+                    self.cfg.local.identity,
+                )
+                .await
+                .expect("Transaction is valid");
+
+            self.item_index += 1;
+        }
+
+        /// Completes the session like the single guardian consensus loop, the
+        /// signature of the block is left out as it is not checked
+        async fn complete_session(&mut self) {
+            let block = self.server.build_block().await;
+
+            self.server
+                .complete_session(
+                    self.session_index,
+                    SignedBlock {
+                        block,
+                        signatures: BTreeMap::new(),
+                    },
+                )
+                .await;
+
+            self.session_index += 1;
+            self.item_index = 0;
+        }
+    }
+
+    /// Processes `transactions_per_iteration` transactions of `workload` per
+    /// iteration, completing the session after every iteration if
+    /// `complete_sessions` is set
+    fn bench_workload(
+        bencher: &mut Bencher,
+        workload: Workload,
+        transactions_per_iteration: usize,
+        complete_sessions: bool,
+    ) {
+        let runtime = Runtime::new().expect("Runtime starts");
+        let secp = Secp256k1::new();
+        let pool = (0..POOL_SIZE)
+            .map(|_| workload.transaction(&secp))
+            .collect::<Vec<_>>();
+        let mut guardian = runtime.block_on(Guardian::new());
+        let mut next = 0;
+
+        bencher.iter(|| {
+            runtime.block_on(async {
+                if POOL_SIZE < next + transactions_per_iteration {
+                    guardian.restart().await;
+                    next = 0;
+                }
+
+                for transaction in &pool[next..next + transactions_per_iteration] {
+                    guardian.process(transaction.clone()).await;
+                }
+                next += transactions_per_iteration;
+
+                if complete_sessions {
+                    guardian.complete_session().await;
+                }
+            })
+        });
+
+        guardian.task_group.shutdown();
+    }
+
+    #[bench]
+    fn bench_process_dummy_transaction(bencher: &mut Bencher) {
+        bench_workload(bencher, Workload::Dummy, 1, false);
+    }
+
+    #[bench]
+    fn bench_process_mint_transaction(bencher: &mut Bencher) {
+        bench_workload(bencher, Workload::Mint, 1, false);
+    }
+
+    #[bench]
+    fn bench_session_of_10_dummy_transactions(bencher: &mut Bencher) {
+        bench_workload(bencher, Workload::Dummy, 10, true);
+    }
+
+    #[bench]
+    fn bench_session_of_100_dummy_transactions(bencher: &mut Bencher) {
+        bench_workload(bencher, Workload::Dummy, 100, true);
+    }
+
+    #[bench]
+    fn bench_session_of_10_mint_transactions(bencher: &mut Bencher) {
+        bench_workload(bencher, Workload::Mint, 10, true);
+    }
+
+    #[bench]
+    fn bench_session_of_100_mint_transactions(bencher: &mut Bencher) {
+        bench_workload(bencher, Workload::Mint, 100, true);
+    }
+}
@@ -0,0 +1,658 @@
+//! Alerts the operator of a guardian about critical events
+//!
+//! The [`AlertMonitor`] periodically checks for conditions that need the
+//! attention of the operator, like a stalled consensus, a peer that has been
+//! offline for a long time, negative net assets or a disk that is running
+//! full, and raises an [`Alert`] for them. Alerts are delivered to every
+//! configured [`AlertSink`], which are posting them to webhooks or sending
+//! them by mail. An alert whose cause persists is repeated after
+//! [`AlertConfig::repeat_secs`] instead of on every check.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use fedimint_core::api::{PeerConnectionStatus, SafetyViolation};
+use fedimint_core::task::{sleep, spawn, TaskGroup, TaskHandle};
+use fedimint_core::time::now;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CORE;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::consensus::safety_halt::AlertHook;
+use crate::net::api::ConsensusApi;
+
+/// How often the monitor checks for critical events
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long we wait for a sink to deliver an alert
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where and when the operator is alerted, configured in the local config of
+/// the guardian
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    /// URLs the alerts are posted to as JSON
+    pub webhooks: Vec<SafeUrl>,
+    /// Mail relay the alerts are sent through, if any
+    pub smtp: Option<SmtpAlertConfig>,
+    /// Alert if no session was completed for this long
+    pub consensus_stall_secs: u64,
+    /// Alert if we were disconnected from a peer for this long
+    pub peer_offline_secs: u64,
+    /// Alert if less space is available in the data directory
+    pub min_free_disk_bytes: u64,
+    /// How long we wait before repeating an alert whose cause persists
+    pub repeat_secs: u64,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            webhooks: vec![],
+            smtp: None,
+            consensus_stall_secs: 1200,
+            peer_offline_secs: 600,
+            min_free_disk_bytes: 1 << 30,
+            repeat_secs: 3600,
+        }
+    }
+}
+
+/// An SMTP relay the alerts are sent through, e.g. the submission server of
+/// the operator's mail provider
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtpAlertConfig {
+    /// Host name of the relay, e.g. `smtp.example.com`
+    pub host: String,
+    /// Port of the relay, defaults to the standard port of the `tls` mode
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    /// Used to log in to the relay if set
+    #[serde(default)]
+    pub credentials: Option<SmtpCredentials>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// How the connection to the SMTP relay is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// TLS from the start of the connection, on port 465 by default
+    Tls,
+    /// Upgrades the connection to TLS before sending anything, on port 587 by
+    /// default
+    #[default]
+    StartTls,
+    /// Sends the alerts in plain text, on port 25 by default, only use this
+    /// for a relay on the guardian's host
+    None,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtpCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Debug for SmtpCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SmtpCredentials({}, ****)", self.username)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    ConsensusStall,
+    PeerOffline(PeerId),
+    AuditAnomaly,
+    DiskSpace,
+    SafetyHalt,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// The guardian raising the alert
+    pub guardian: PeerId,
+    pub message: String,
+    pub time: SystemTime,
+}
+
+/// Delivers alerts to the operator
+#[async_trait]
+pub trait AlertSink: Debug + Send + Sync {
+    async fn send(&self, alert: &Alert) -> anyhow::Result<()>;
+}
+
+pub type DynAlertSink = Arc<dyn AlertSink>;
+
+/// Posts alerts as JSON to a webhook
+#[derive(Debug, Clone)]
+pub struct WebhookAlertSink {
+    pub url: SafeUrl,
+    http: reqwest::Client,
+}
+
+impl WebhookAlertSink {
+    pub fn new(url: SafeUrl) -> Self {
+        WebhookAlertSink {
+            url,
+            http: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl AlertSink for WebhookAlertSink {
+    async fn send(&self, alert: &Alert) -> anyhow::Result<()> {
+        self.http
+            .post(self.url.clone().reap_guts())
+            .json(alert)
+            .timeout(SEND_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Sends alerts by mail through an SMTP relay
+#[derive(Debug)]
+pub struct SmtpAlertSink {
+    pub config: SmtpAlertConfig,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpAlertSink {
+    pub fn new(config: SmtpAlertConfig) -> anyhow::Result<Self> {
+        let mut builder = match config.tls {
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        }
+        .timeout(Some(SEND_TIMEOUT));
+
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+
+        if let Some(credentials) = &config.credentials {
+            builder = builder.credentials(Credentials::new(
+                credentials.username.clone(),
+                credentials.password.clone(),
+            ));
+        }
+
+        Ok(SmtpAlertSink {
+            transport: builder.build(),
+            config,
+        })
+    }
+
+    fn message(&self, alert: &Alert) -> anyhow::Result<Message> {
+        let mut message = Message::builder()
+            .from(
+                self.config
+                    .from
+                    .parse::<Mailbox>()
+                    .context("Invalid sender address")?,
+            )
+            .subject(format!(
+                "Fedimint guardian {} alert: {:?}",
+                alert.guardian, alert.kind
+            ));
+
+        for to in &self.config.to {
+            message = message.to(to
+                .parse::<Mailbox>()
+                .with_context(|| format!("Invalid recipient address {to}"))?);
+        }
+
+        Ok(message.body(alert.message.clone())?)
+    }
+}
+
+#[async_trait]
+impl AlertSink for SmtpAlertSink {
+    async fn send(&self, alert: &Alert) -> anyhow::Result<()> {
+        self.transport
+            .send(self.message(alert)?)
+            .await
+            .context("SMTP relay did not accept the alert")?;
+
+        Ok(())
+    }
+}
+
+/// The sinks created from an [`AlertConfig`]
+#[derive(Debug)]
+struct ConfiguredSinks {
+    cfg: AlertConfig,
+    sinks: Vec<DynAlertSink>,
+}
+
+impl ConfiguredSinks {
+    fn new(cfg: &AlertConfig) -> Self {
+        let mut sinks = cfg
+            .webhooks
+            .iter()
+            .map(|url| Arc::new(WebhookAlertSink::new(url.clone())) as DynAlertSink)
+            .collect::<Vec<_>>();
+
+        if let Some(smtp) = cfg.smtp.clone() {
+            match SmtpAlertSink::new(smtp) {
+                Ok(sink) => sinks.push(Arc::new(sink)),
+                Err(e) => warn!(target: LOG_CORE, error = %e, "Could not set up SMTP alerts"),
+            }
+        }
+
+        ConfiguredSinks {
+            cfg: cfg.clone(),
+            sinks,
+        }
+    }
+}
+
+/// Raises alerts to all configured sinks, unless the same alert was raised
+/// recently
+#[derive(Debug, Clone)]
+pub struct Alerts {
+    guardian: PeerId,
+    configured: Arc<RwLock<ConfiguredSinks>>,
+    /// Sinks added via [`Self::with_sink`], which are kept when reconfiguring
+    sinks: Vec<DynAlertSink>,
+    raised: Arc<Mutex<HashMap<AlertKind, Instant>>>,
+}
+
+impl Alerts {
+    pub fn new(guardian: PeerId, cfg: &AlertConfig) -> Self {
+        Alerts {
+            guardian,
+            configured: Arc::new(RwLock::new(ConfiguredSinks::new(cfg))),
+            sinks: vec![],
+            raised: Default::default(),
+        }
+    }
+
+    pub fn with_sink(mut self, sink: DynAlertSink) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// The configuration currently in effect
+    pub fn config(&self) -> AlertConfig {
+        self.configured.read().expect("Lock poisoned").cfg.clone()
+    }
+
+    /// Replaces the configured sinks and intervals, e.g. after the local
+    /// config was reloaded
+    pub fn reconfigure(&self, cfg: &AlertConfig) {
+        let mut configured = self.configured.write().expect("Lock poisoned");
+
+        if configured.cfg != *cfg {
+            *configured = ConfiguredSinks::new(cfg);
+
+            info!(target: LOG_CORE, "Reconfigured alerts");
+        }
+    }
+
+    pub fn raise(&self, kind: AlertKind, message: String) {
+        let (repeat_interval, sinks) = {
+            let configured = self.configured.read().expect("Lock poisoned");
+
+            let sinks = configured
+                .sinks
+                .iter()
+                .chain(&self.sinks)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            (Duration::from_secs(configured.cfg.repeat_secs), sinks)
+        };
+
+        {
+            let mut raised = self.raised.lock().expect("Lock poisoned");
+
+            if raised
+                .get(&kind)
+                .map_or(false, |time| time.elapsed() < repeat_interval)
+            {
+                debug!(target: LOG_CORE, ?kind, "Alert was raised recently");
+                return;
+            }
+
+            raised.insert(kind, Instant::now());
+        }
+
+        warn!(target: LOG_CORE, ?kind, %message, "Raising alert");
+
+        let alert = Alert {
+            kind,
+            guardian: self.guardian,
+            message,
+            time: now(),
+        };
+
+        for sink in sinks {
+            let alert = alert.clone();
+
+            spawn("send alert", async move {
+                if let Err(e) = sink.send(&alert).await {
+                    warn!(target: LOG_CORE, ?sink, error = %e, "Could not deliver alert");
+                }
+            });
+        }
+    }
+
+    /// Marks the cause of an alert as resolved, such that the alert is raised
+    /// immediately if the cause occurs again
+    pub fn resolve(&self, kind: AlertKind) {
+        if self
+            .raised
+            .lock()
+            .expect("Lock poisoned")
+            .remove(&kind)
+            .is_some()
+        {
+            info!(target: LOG_CORE, ?kind, "Alert resolved");
+        }
+    }
+}
+
+impl AlertHook for Alerts {
+    fn alert(&self, violation: &SafetyViolation) {
+        self.raise(AlertKind::SafetyHalt, violation.message.clone());
+    }
+}
+
+/// Background task that checks for critical events and raises alerts for them
+///
+/// Picks up changes to the [`AlertConfig`] from the reloaded local config of
+/// the guardian before every check.
+pub struct AlertMonitor {
+    alerts: Alerts,
+    api: ConsensusApi,
+    data_dir: PathBuf,
+}
+
+impl AlertMonitor {
+    pub fn new(alerts: Alerts, api: ConsensusApi, data_dir: PathBuf) -> Self {
+        AlertMonitor {
+            alerts,
+            api,
+            data_dir,
+        }
+    }
+
+    pub async fn spawn(self, task_group: &mut TaskGroup) {
+        task_group
+            .spawn("alert monitor", move |task_handle| async move {
+                self.run(task_handle).await
+            })
+            .await;
+    }
+
+    async fn run(&self, task_handle: TaskHandle) {
+        let mut last_session = (self.api.fetch_block_count().await, Instant::now());
+        let mut offline_since = HashMap::new();
+
+        while !task_handle.is_shutting_down() {
+            let cfg = self.api.live_config.get().alerts;
+
+            self.alerts.reconfigure(&cfg);
+
+            self.check_consensus_progress(&cfg, &mut last_session).await;
+            self.check_peers(&cfg, &mut offline_since).await;
+            self.check_audit().await;
+            self.check_disk_space(&cfg).await;
+
+            sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn check_consensus_progress(&self, cfg: &AlertConfig, last_session: &mut (u64, Instant)) {
+        let session_count = self.api.fetch_block_count().await;
+
+        if session_count != last_session.0 {
+            *last_session = (session_count, Instant::now());
+            self.alerts.resolve(AlertKind::ConsensusStall);
+            return;
+        }
+
+        let stalled = last_session.1.elapsed();
+
+        if stalled >= Duration::from_secs(cfg.consensus_stall_secs) {
+            self.alerts.raise(
+                AlertKind::ConsensusStall,
+                format!(
+                    "No session was completed for {}s, the last one was session {}",
+                    stalled.as_secs(),
+                    session_count.saturating_sub(1)
+                ),
+            );
+        }
+    }
+
+    async fn check_peers(&self, cfg: &AlertConfig, offline_since: &mut HashMap<PeerId, Instant>) {
+        for (peer, health) in self.api.get_peer_health().await {
+            if health.connection_status == PeerConnectionStatus::Connected {
+                offline_since.remove(&peer);
+                self.alerts.resolve(AlertKind::PeerOffline(peer));
+                continue;
+            }
+
+            let offline = offline_since.entry(peer).or_insert_with(Instant::now);
+
+            if offline.elapsed() >= Duration::from_secs(cfg.peer_offline_secs) {
+                self.alerts.raise(
+                    AlertKind::PeerOffline(peer),
+                    format!(
+                        "Peer {peer} has been offline for {}s",
+                        offline.elapsed().as_secs()
+                    ),
+                );
+            }
+        }
+    }
+
+    async fn check_audit(&self) {
+        let audit = match self.api.get_federation_audit().await {
+            Ok(audit) => audit,
+            Err(e) => {
+                debug!(target: LOG_CORE, error = ?e, "Could not audit the federation");
+                return;
+            }
+        };
+
+        if audit.net_assets < 0 {
+            self.alerts.raise(
+                AlertKind::AuditAnomaly,
+                format!(
+                    "The net assets of the federation are negative: {} msat",
+                    audit.net_assets
+                ),
+            );
+        } else {
+            self.alerts.resolve(AlertKind::AuditAnomaly);
+        }
+    }
+
+    async fn check_disk_space(&self, cfg: &AlertConfig) {
+        if let Some(failure) = self.api.safe_mode.get().await {
+            self.alerts.raise(
+                AlertKind::DiskSpace,
+                format!("Guardian is in safe mode: {}", failure.message),
+            );
+            return;
+        }
+
+        let available = match fs2::available_space(&self.data_dir) {
+            Ok(available) => available,
+            Err(e) => {
+                debug!(target: LOG_CORE, error = %e, "Could not determine available disk space");
+                return;
+            }
+        };
+
+        if available < cfg.min_free_disk_bytes {
+            self.alerts.raise(
+                AlertKind::DiskSpace,
+                format!(
+                    "Only {available} bytes are available in {}",
+                    self.data_dir.display()
+                ),
+            );
+        } else {
+            self.alerts.resolve(AlertKind::DiskSpace);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    use async_trait::async_trait;
+    use fedimint_core::PeerId;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::{
+        Alert, AlertConfig, AlertKind, AlertSink, Alerts, SmtpAlertConfig, SmtpAlertSink, SmtpTls,
+    };
+
+    #[derive(Debug, Default)]
+    struct RecordingSink(Mutex<Vec<Alert>>);
+
+    #[async_trait]
+    impl AlertSink for RecordingSink {
+        async fn send(&self, alert: &Alert) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(alert.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn alerts_are_not_repeated_until_resolved() {
+        let sink = Arc::new(RecordingSink::default());
+        let alerts = Alerts::new(PeerId::from(0), &AlertConfig::default()).with_sink(sink.clone());
+
+        alerts.raise(AlertKind::DiskSpace, "disk full".to_string());
+        alerts.raise(AlertKind::DiskSpace, "disk full".to_string());
+        alerts.raise(AlertKind::ConsensusStall, "stalled".to_string());
+        alerts.resolve(AlertKind::DiskSpace);
+        alerts.raise(AlertKind::DiskSpace, "disk full".to_string());
+
+        while sink.0.lock().unwrap().len() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        let mut kinds = sink
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|alert| format!("{:?}", alert.kind))
+            .collect::<Vec<_>>();
+        kinds.sort();
+
+        assert_eq!(kinds, vec!["ConsensusStall", "DiskSpace", "DiskSpace"]);
+    }
+
+    #[tokio::test]
+    async fn reconfiguring_keeps_added_sinks() {
+        let sink = Arc::new(RecordingSink::default());
+        let alerts = Alerts::new(PeerId::from(0), &AlertConfig::default()).with_sink(sink.clone());
+
+        alerts.raise(AlertKind::DiskSpace, "disk full".to_string());
+        alerts.reconfigure(&AlertConfig {
+            repeat_secs: 0,
+            ..AlertConfig::default()
+        });
+        alerts.raise(AlertKind::DiskSpace, "disk full".to_string());
+
+        while sink.0.lock().unwrap().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(alerts.config().repeat_secs, 0);
+    }
+
+    #[tokio::test]
+    async fn smtp_sink_delivers_mail() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let sink = SmtpAlertSink::new(SmtpAlertConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(listener.local_addr().unwrap().port()),
+            tls: SmtpTls::None,
+            credentials: None,
+            from: "guardian@example.com".to_string(),
+            to: vec!["operator@example.com".to_string()],
+        })
+        .unwrap();
+
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut data = vec![];
+
+            writer.write_all(b"220 relay\r\n").await.unwrap();
+
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = match line.as_str() {
+                    "DATA" => b"354 go ahead\r\n",
+                    "QUIT" => b"221 bye\r\n",
+                    _ if line.starts_with("EHLO") => b"250-relay\r\n250 8BITMIME\r\n",
+                    _ if line.starts_with("MAIL FROM") || line.starts_with("RCPT TO") => {
+                        b"250 ok\r\n"
+                    }
+                    "." => b"250 queued\r\n",
+                    _ => {
+                        data.push(line);
+                        continue;
+                    }
+                };
+
+                writer.write_all(reply).await.unwrap();
+            }
+
+            data
+        });
+
+        sink.send(&Alert {
+            kind: AlertKind::ConsensusStall,
+            guardian: PeerId::from(1),
+            message: "first line\n.second line".to_string(),
+            time: SystemTime::UNIX_EPOCH,
+        })
+        .await
+        .expect("Relay accepts the mail");
+
+        let data = relay.await.unwrap();
+
+        assert!(data.contains(&"To: operator@example.com".to_string()));
+        assert!(data.contains(&"first line".to_string()));
+        assert!(data.contains(&"..second line".to_string()));
+    }
+}
@@ -2,13 +2,14 @@ use std::io::Cursor;
 
 use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
 
+use crate::consensus::safe_mode::{commit_unless_full, SafeMode};
 use crate::db::AlephUnitsKey;
 use crate::LOG_CONSENSUS;
 
 /// This function loads the aleph bft backup from disk and creates a UnitSaver
 /// instance which allows aleph bft to append further bytes to the existing
 /// backup
-pub async fn load_session(db: Database) -> (Cursor<Vec<u8>>, UnitSaver) {
+pub async fn load_session(db: Database, safe_mode: SafeMode) -> (Cursor<Vec<u8>>, UnitSaver) {
     let mut buffer = vec![];
     let mut units_index = 0;
     let mut dbtx = db.begin_transaction().await;
@@ -26,7 +27,7 @@ pub async fn load_session(db: Database) -> (Cursor<Vec<u8>>, UnitSaver) {
     let unit_loader = Cursor::new(buffer);
 
     // we pass the first free unit index to the UnitSaver as an offset
-    let unit_saver = UnitSaver::new(db, units_index);
+    let unit_saver = UnitSaver::new(db, safe_mode, units_index);
 
     (unit_loader, unit_saver)
 }
@@ -37,14 +38,16 @@ pub async fn load_session(db: Database) -> (Cursor<Vec<u8>>, UnitSaver) {
 /// similar to a open file in append mode.
 pub struct UnitSaver {
     db: Database,
+    safe_mode: SafeMode,
     units_index: u64,
     buffer: Vec<u8>,
 }
 
 impl UnitSaver {
-    fn new(db: Database, units_index: u64) -> Self {
+    fn new(db: Database, safe_mode: SafeMode, units_index: u64) -> Self {
         Self {
             db,
+            safe_mode,
             units_index,
             buffer: vec![],
         }
@@ -58,16 +61,18 @@ impl std::io::Write for UnitSaver {
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        futures::executor::block_on(async {
-            let mut dbtx = self.db.begin_transaction().await;
-
-            dbtx.insert_new_entry(&AlephUnitsKey(self.units_index), &self.buffer)
-                .await;
-
-            dbtx.commit_tx_result()
-                .await
-                .expect("This is the only place where we write to this key");
-        });
+        futures::executor::block_on(self.safe_mode.retry_while_full(
+            "save_aleph_units",
+            || async {
+                let mut dbtx = self.db.begin_transaction().await;
+
+                dbtx.insert_new_entry(&AlephUnitsKey(self.units_index), &self.buffer)
+                    .await;
+
+                commit_unless_full(dbtx, "This is the only place where we write to this key").await
+            },
+        ))
+        .expect("Any other error panics on commit");
 
         self.buffer.clear();
         self.units_index += 1;
@@ -3,14 +3,11 @@ use std::collections::BTreeSet;
 use bitcoin_hashes_12::sha256;
 use fedimint_core::block::{consensus_hash_sha256, SchnorrSignature};
 use fedimint_core::encoding::Encodable;
-use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::epoch::{ConsensusItem, ConsensusLimits};
 use tokio::sync::watch;
 
 use crate::LOG_CONSENSUS;
 
-// This limits the RAM consumption of a Unit to roughly 10kB
-const BYTE_LIMIT: usize = 10_000;
-
 #[derive(
     Clone, Debug, PartialEq, Eq, Hash, parity_scale_codec::Encode, parity_scale_codec::Decode,
 )]
@@ -22,10 +19,10 @@ pub enum UnitData {
 impl UnitData {
     // in order to bound the RAM consumption of a session we have to bound an
     // individual units size, hence the size of its attached unit data in memory
-    pub fn is_valid(&self) -> bool {
+    pub fn is_valid(&self, limits: &ConsensusLimits) -> bool {
         match self {
             UnitData::Signature(..) => true,
-            UnitData::Batch(bytes, ..) => bytes.len() <= BYTE_LIMIT,
+            UnitData::Batch(bytes, ..) => bytes.len() <= limits.max_batch_bytes as usize,
         }
     }
 }
@@ -35,20 +32,35 @@ pub struct DataProvider {
     signature_receiver: watch::Receiver<Option<SchnorrSignature>>,
     submitted_items: BTreeSet<sha256::Hash>,
     leftover_item: Option<ConsensusItem>,
+    limits: ConsensusLimits,
+    #[cfg(any(test, feature = "red-team"))]
+    faults: crate::red_team::Faults,
 }
 
 impl DataProvider {
     pub fn new(
         mempool_item_receiver: async_channel::Receiver<ConsensusItem>,
         signature_receiver: watch::Receiver<Option<SchnorrSignature>>,
+        limits: ConsensusLimits,
     ) -> Self {
         Self {
             mempool_item_receiver,
             signature_receiver,
             submitted_items: BTreeSet::new(),
             leftover_item: None,
+            limits,
+            #[cfg(any(test, feature = "red-team"))]
+            faults: Default::default(),
         }
     }
+
+    /// Makes us deviate from the protocol in order to test how our peers
+    /// handle a malicious guardian
+    #[cfg(any(test, feature = "red-team"))]
+    pub(crate) fn with_faults(mut self, faults: crate::red_team::Faults) -> Self {
+        self.faults = faults;
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -56,9 +68,15 @@ impl aleph_bft::DataProvider<UnitData> for DataProvider {
     async fn get_data(&mut self) -> Option<UnitData> {
         // we only attach our signature as no more items can be ordered in this session
         if let Some(signature) = self.signature_receiver.borrow().clone() {
+            #[cfg(any(test, feature = "red-team"))]
+            let signature = self.faults.tamper_signature(signature);
+
             return Some(UnitData::Signature(signature));
         }
 
+        let max_item_bytes = self.limits.max_item_bytes as usize;
+        let max_batch_bytes = self.limits.max_batch_bytes as usize;
+
         // the length of a vector is encoded in at most 9 bytes
         let mut n_bytes = 9;
         let mut items = Vec::new();
@@ -69,11 +87,11 @@ impl aleph_bft::DataProvider<UnitData> for DataProvider {
                 .expect("Writing to a vector cant fail")
                 .len();
 
-            if n_bytes_item + n_bytes <= BYTE_LIMIT {
+            if n_bytes_item + n_bytes <= max_batch_bytes {
                 n_bytes += n_bytes_item;
                 items.push(item);
             } else {
-                tracing::warn!(target: LOG_CONSENSUS,"Consensus item length is over BYTE_LIMIT");
+                tracing::warn!(target: LOG_CONSENSUS,"Consensus item length is over the batch size limit");
             }
         }
 
@@ -89,7 +107,13 @@ impl aleph_bft::DataProvider<UnitData> for DataProvider {
                 .expect("Writing to a vector cant fail")
                 .len();
 
-            if n_bytes + n_bytes_item <= BYTE_LIMIT {
+            // our peers would discard the whole batch
+            if n_bytes_item > max_item_bytes {
+                tracing::warn!(target: LOG_CONSENSUS, "Consensus item length is over the item size limit");
+                continue;
+            }
+
+            if n_bytes + n_bytes_item <= max_batch_bytes {
                 n_bytes += n_bytes_item;
                 items.push(item);
             } else {
@@ -102,7 +126,10 @@ impl aleph_bft::DataProvider<UnitData> for DataProvider {
             .consensus_encode_to_vec()
             .expect("Writing to a vector cant fail");
 
-        assert!(bytes.len() <= BYTE_LIMIT);
+        assert!(bytes.len() <= max_batch_bytes);
+
+        #[cfg(any(test, feature = "red-team"))]
+        let bytes = self.faults.tamper_batch(&items, bytes, &self.limits);
 
         return Some(UnitData::Batch(bytes));
     }
@@ -1,17 +1,17 @@
 use std::collections::BTreeMap;
-use std::io::Write;
 
 use aleph_bft::Keychain as KeychainTrait;
-use fedimint_core::block::{consensus_hash_sha256, SchnorrSignature};
-use fedimint_core::{BitcoinHash, PeerId};
-use secp256k1_zkp::hashes::sha256;
-use secp256k1_zkp::{schnorr, All, KeyPair, Message, PublicKey, Secp256k1, SecretKey};
+use fedimint_core::block::{broadcast_message_hash, SchnorrSignature};
+use fedimint_core::PeerId;
+use secp256k1_zkp::{schnorr, All, Message, PublicKey, Secp256k1};
+
+use crate::signer::DynSigner;
 
 #[derive(Clone, Debug)]
 pub struct Keychain {
     peer_id: PeerId,
     public_keys: BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
-    keypair: KeyPair,
+    signer: DynSigner,
     secp: Secp256k1<All>,
 }
 
@@ -19,16 +19,13 @@ impl Keychain {
     pub fn new(
         peer_id: PeerId,
         public_keys: BTreeMap<PeerId, PublicKey>,
-        secret_key: SecretKey,
+        signer: DynSigner,
     ) -> Self {
-        let secp = Secp256k1::new();
-        let keypair = secret_key.keypair(&secp);
-
         Keychain {
             peer_id,
             public_keys,
-            keypair,
-            secp,
+            signer,
+            secp: Secp256k1::new(),
         }
     }
 
@@ -45,20 +42,7 @@ impl Keychain {
     }
 
     fn tagged_hash(&self, message: &[u8]) -> Message {
-        let public_key_tag = consensus_hash_sha256(&self.public_keys);
-        let mut engine = sha256::HashEngine::default();
-
-        engine
-            .write_all(public_key_tag.as_ref())
-            .expect("Writing to a hash engine can not fail");
-
-        engine
-            .write_all(message)
-            .expect("Writing to a hash engine can not fail");
-
-        let hash = sha256::Hash::from_engine(engine);
-
-        Message::from(hash)
+        broadcast_message_hash(&self.public_keys, message)
     }
 }
 
@@ -78,8 +62,8 @@ impl aleph_bft::Keychain for Keychain {
 
     fn sign(&self, message: &[u8]) -> Self::Signature {
         SchnorrSignature(
-            self.secp
-                .sign_schnorr(&self.tagged_hash(message), &self.keypair)
+            self.signer
+                .sign_broadcast(&self.tagged_hash(message))
                 .as_ref()
                 .to_owned(),
         )
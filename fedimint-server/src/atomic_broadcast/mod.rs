@@ -100,7 +100,7 @@ use serde::{Deserialize, Serialize};
 /// necessary retry logic. Therefore, the caller can discard a message
 /// immediately if its intended recipient is offline.
 #[derive(Clone, Debug, Encodable, Decodable, Serialize, Deserialize)]
-pub struct Message(Vec<u8>);
+pub struct Message(pub(crate) Vec<u8>);
 
 /// This enum defines the intended destination of a [Message].
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -1,8 +1,11 @@
 use std::io::Write;
 
 use bitcoin_hashes_12::{sha256, Hash};
+use fedimint_core::epoch::ConsensusLimits;
 use fedimint_core::net::peers::IPeerConnections;
+use fedimint_logging::LOG_NET_PEER;
 use parity_scale_codec::{Decode, Encode, IoReader};
+use tracing::{debug_span, trace};
 
 use super::data_provider::UnitData;
 use super::keychain::Keychain;
@@ -34,17 +37,41 @@ pub type NetworkData = aleph_bft::NetworkData<
 
 pub struct Network {
     connections: ReconnectPeerConnections<Message>,
+    limits: ConsensusLimits,
+    #[cfg(any(test, feature = "red-team"))]
+    faults: crate::red_team::Faults,
 }
 
 impl Network {
-    pub fn new(connections: ReconnectPeerConnections<Message>) -> Self {
-        Self { connections }
+    pub fn new(connections: ReconnectPeerConnections<Message>, limits: ConsensusLimits) -> Self {
+        Self {
+            connections,
+            limits,
+            #[cfg(any(test, feature = "red-team"))]
+            faults: Default::default(),
+        }
+    }
+
+    /// Makes us deviate from the protocol in order to test how our peers
+    /// handle a malicious guardian
+    #[cfg(any(test, feature = "red-team"))]
+    pub(crate) fn with_faults(mut self, faults: crate::red_team::Faults) -> Self {
+        self.faults = faults;
+        self
     }
 }
 
 #[async_trait::async_trait]
 impl aleph_bft::Network<NetworkData> for Network {
     fn send(&self, network_data: NetworkData, recipient: aleph_bft::Recipient) {
+        let _span = debug_span!("aleph_send", ?recipient).entered();
+
+        // only units carry unit data
+        #[cfg(any(test, feature = "red-team"))]
+        if self.faults.withhold_units && !network_data.included_data().is_empty() {
+            return;
+        }
+
         // convert from aleph_bft::Recipient to session::Recipient
         let recipient = match recipient {
             aleph_bft::Recipient::Node(node_index) => {
@@ -56,8 +83,15 @@ impl aleph_bft::Network<NetworkData> for Network {
         // since NetworkData does not implement Encodable we use
         // parity_scale_codec::Encode to serialize it such that Message can
         // implement Encodable
-        self.connections
+        let dropped = self
+            .connections
             .send_sync(Message(network_data.encode()), recipient);
+
+        // aleph tolerates lost messages since it re-requests missing units, so we
+        // rather drop messages to slow or throttled peers than queue them forever
+        if !dropped.is_empty() {
+            trace!(target: LOG_NET_PEER, ?dropped, "Dropped message since the outgoing queue is full");
+        }
     }
 
     async fn next_event(&mut self) -> Option<NetworkData> {
@@ -65,7 +99,11 @@ impl aleph_bft::Network<NetworkData> for Network {
             if let Ok(network_data) = NetworkData::decode(&mut IoReader(message.1 .0.as_slice())) {
                 // in order to bound the RAM consumption of a session we have to bound an
                 // individual units size, hence the size of its attached unitdata in memory
-                if network_data.included_data().iter().all(UnitData::is_valid) {
+                if network_data
+                    .included_data()
+                    .iter()
+                    .all(|unit_data| unit_data.is_valid(&self.limits))
+                {
                     return Some(network_data);
                 }
             }
@@ -0,0 +1,282 @@
+//! Checkpoints of the guardian's config and database
+//!
+//! Before the guardian applies a change it can not undo by itself, i.e. adding
+//! a module, upgrading the consensus version of a module or rotating its keys,
+//! it copies its config files and its database into a named checkpoint in the
+//! [`CHECKPOINTS_DIR`]. Admins can create further checkpoints via the API.
+//! Restoring a checkpoint is scheduled via the admin API and happens on the
+//! next start of the guardian, before it reads its config, since neither the
+//! config nor the database can be replaced while the consensus is running. The
+//! guardian then catches up with the sessions its peers completed since.
+
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context};
+use fedimint_core::api::GuardianCheckpoint;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCore};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::time::now;
+use fedimint_logging::LOG_CORE;
+use futures::StreamExt;
+use tracing::{info, warn};
+
+use crate::config::io::{
+    CLIENT_CONFIG, CLIENT_INVITE_CODE_FILE, CONSENSUS_CONFIG, ENCRYPTED_EXT, JSON_EXT,
+    LOCAL_CONFIG, PRIVATE_CONFIG, SALT_FILE,
+};
+
+/// Directory in the data dir the checkpoints are stored in
+pub const CHECKPOINTS_DIR: &str = "checkpoints";
+
+/// Names the checkpoint to restore on the next start, can not collide with the
+/// name of a checkpoint
+const RESTORE_FILE: &str = ".restore";
+
+/// Describes the checkpoint in its directory
+const INFO_FILE: &str = "checkpoint.json";
+
+/// Contains the consensus encoded entries of the database
+const DB_ENTRIES_FILE: &str = "database";
+
+/// The files of the config, the password file is left untouched
+fn config_files() -> [String; 6] {
+    [
+        format!("{CLIENT_CONFIG}.{JSON_EXT}"),
+        format!("{CONSENSUS_CONFIG}.{JSON_EXT}"),
+        format!("{LOCAL_CONFIG}.{JSON_EXT}"),
+        format!("{PRIVATE_CONFIG}.{ENCRYPTED_EXT}"),
+        CLIENT_INVITE_CODE_FILE.to_string(),
+        SALT_FILE.to_string(),
+    ]
+}
+
+fn check_name(name: &str) -> anyhow::Result<()> {
+    ensure!(
+        !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "Checkpoint names consist of up to 64 letters, digits, dashes and underscores"
+    );
+
+    Ok(())
+}
+
+/// The checkpoints of a guardian
+#[derive(Debug, Clone)]
+pub struct Checkpoints {
+    data_dir: PathBuf,
+    db: Database,
+}
+
+impl Checkpoints {
+    pub fn new(data_dir: PathBuf, db: Database) -> Self {
+        Checkpoints { data_dir, db }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.data_dir.join(CHECKPOINTS_DIR)
+    }
+
+    /// Copies the config files and the database, the checkpoint is only listed
+    /// once it is complete
+    pub async fn create(&self, name: &str, reason: &str) -> anyhow::Result<GuardianCheckpoint> {
+        check_name(name)?;
+
+        let path = self.dir().join(name);
+
+        ensure!(!path.exists(), "Checkpoint {name} already exists");
+
+        let staging = self.dir().join(format!(".{name}"));
+
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+
+        fs::create_dir_all(&staging)?;
+
+        for file in config_files() {
+            fs::copy(self.data_dir.join(&file), staging.join(&file))
+                .with_context(|| format!("Failed to copy {file}"))?;
+        }
+
+        let mut writer = BufWriter::new(fs::File::create(staging.join(DB_ENTRIES_FILE))?);
+
+        // a single transaction reads a consistent state of the database
+        let mut dbtx = self.db.begin_transaction().await;
+        let mut entries = dbtx.raw_find_by_prefix(&[]).await?;
+
+        while let Some(entry) = entries.next().await {
+            entry.consensus_encode(&mut writer)?;
+        }
+
+        writer.into_inner()?.sync_all()?;
+
+        let checkpoint = GuardianCheckpoint {
+            name: name.to_string(),
+            reason: reason.to_string(),
+            created_at: now(),
+        };
+
+        fs::write(
+            staging.join(INFO_FILE),
+            serde_json::to_vec_pretty(&checkpoint)?,
+        )?;
+
+        fs::rename(staging, path)?;
+
+        info!(target: LOG_CORE, checkpoint = name, reason, "Created checkpoint");
+
+        Ok(checkpoint)
+    }
+
+    /// Returns the complete checkpoints, oldest first
+    pub fn list(&self) -> anyhow::Result<Vec<GuardianCheckpoint>> {
+        if !self.dir().exists() {
+            return Ok(vec![]);
+        }
+
+        let mut checkpoints = vec![];
+
+        for entry in fs::read_dir(self.dir())? {
+            let info = entry?.path().join(INFO_FILE);
+
+            if info.exists() {
+                let checkpoint: GuardianCheckpoint = serde_json::from_slice(&fs::read(info)?)?;
+                checkpoints.push(checkpoint);
+            }
+        }
+
+        checkpoints.sort_by_key(|checkpoint| checkpoint.created_at);
+
+        Ok(checkpoints)
+    }
+
+    /// Restores the checkpoint the next time the guardian starts, replacing a
+    /// previously scheduled one
+    pub fn schedule_restore(&self, name: &str) -> anyhow::Result<()> {
+        check_name(name)?;
+
+        ensure!(
+            self.dir().join(name).join(INFO_FILE).exists(),
+            "Unknown checkpoint {name}"
+        );
+
+        fs::write(self.dir().join(RESTORE_FILE), name)?;
+
+        warn!(target: LOG_CORE, checkpoint = name, "Restoring checkpoint on the next start");
+
+        Ok(())
+    }
+}
+
+/// Restores the checkpoint scheduled with [`Checkpoints::schedule_restore`],
+/// if any, has to be called before the config is read
+pub async fn restore_scheduled_checkpoint(data_dir: &Path, db: &Database) -> anyhow::Result<()> {
+    let dir = data_dir.join(CHECKPOINTS_DIR);
+
+    let Ok(name) = fs::read_to_string(dir.join(RESTORE_FILE)) else {
+        return Ok(());
+    };
+
+    check_name(&name)?;
+
+    warn!(target: LOG_CORE, checkpoint = %name, "Restoring checkpoint");
+
+    let path = dir.join(&name);
+    let mut reader = BufReader::new(fs::File::open(path.join(DB_ENTRIES_FILE))?);
+    let mut dbtx = db.begin_transaction().await;
+
+    let keys = dbtx
+        .raw_find_by_prefix(&[])
+        .await?
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>()
+        .await;
+
+    for key in keys {
+        dbtx.raw_remove_entry(&key).await?;
+    }
+
+    while !reader.fill_buf()?.is_empty() {
+        let (key, value) =
+            <(Vec<u8>, Vec<u8>)>::consensus_decode(&mut reader, &ModuleDecoderRegistry::default())?;
+
+        dbtx.raw_insert_bytes(&key, &value).await?;
+    }
+
+    // if we crash before the restore file is removed we restore again
+    for file in config_files() {
+        fs::copy(path.join(&file), data_dir.join(&file))?;
+    }
+
+    dbtx.commit_tx_result().await?;
+
+    fs::remove_file(dir.join(RESTORE_FILE))?;
+
+    info!(target: LOG_CORE, checkpoint = %name, "Restored checkpoint");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCore};
+
+    use super::{config_files, restore_scheduled_checkpoint, Checkpoints};
+
+    #[tokio::test]
+    async fn restores_config_and_database() {
+        let data_dir = tempfile::tempdir().expect("Creates temp dir");
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let checkpoints = Checkpoints::new(data_dir.path().to_owned(), db.clone());
+
+        for file in config_files() {
+            fs::write(data_dir.path().join(file), "before").unwrap();
+        }
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[1], &[1]).await.unwrap();
+        dbtx.commit_tx().await;
+
+        checkpoints.create("before", "test").await.unwrap();
+        assert!(checkpoints.create("before", "test").await.is_err());
+        assert!(checkpoints.create("../escape", "test").await.is_err());
+
+        for file in config_files() {
+            fs::write(data_dir.path().join(file), "after").unwrap();
+        }
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_remove_entry(&[1]).await.unwrap();
+        dbtx.raw_insert_bytes(&[2], &[2]).await.unwrap();
+        dbtx.commit_tx().await;
+
+        assert!(checkpoints.schedule_restore("unknown").is_err());
+        checkpoints.schedule_restore("before").unwrap();
+
+        restore_scheduled_checkpoint(data_dir.path(), &db)
+            .await
+            .unwrap();
+
+        for file in config_files() {
+            assert_eq!(
+                fs::read_to_string(data_dir.path().join(file)).unwrap(),
+                "before"
+            );
+        }
+
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(dbtx.raw_get_bytes(&[1]).await.unwrap(), Some(vec![1]));
+        assert_eq!(dbtx.raw_get_bytes(&[2]).await.unwrap(), None);
+
+        assert_eq!(checkpoints.list().unwrap().len(), 1);
+    }
+}
@@ -10,7 +10,7 @@ use bitcoin_hashes::{sha256, Hash};
 use fedimint_aead::random_salt;
 use fedimint_core::admin_client::{
     ConfigGenConnectionsRequest, ConfigGenParamsConsensus, ConfigGenParamsRequest,
-    ConfigGenParamsResponse, PeerServerParams, WsAdminClient,
+    ConfigGenParamsResponse, DkgStatusResponse, PeerServerParams, WsAdminClient,
 };
 use fedimint_core::api::{ServerStatus, StatusResponse};
 use fedimint_core::config::{
@@ -20,25 +20,29 @@ use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::Database;
 use fedimint_core::encoding::Encodable;
 use fedimint_core::endpoint_constants::{
-    ADD_CONFIG_GEN_PEER_ENDPOINT, AUTH_ENDPOINT, GET_CONFIG_GEN_PEERS_ENDPOINT,
-    GET_CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT, GET_DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT,
-    GET_VERIFY_CONFIG_HASH_ENDPOINT, RUN_DKG_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT,
-    SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT,
-    STATUS_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
+    ADD_CONFIG_GEN_PEER_ENDPOINT, AUTH_ENDPOINT, DKG_STATUS_ENDPOINT,
+    GET_CONFIG_GEN_PEERS_ENDPOINT, GET_CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT,
+    GET_DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT, GET_VERIFY_CONFIG_HASH_ENDPOINT, RUN_DKG_ENDPOINT,
+    SET_CONFIG_GEN_CONNECTIONS_ENDPOINT, SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT,
+    START_CONSENSUS_ENDPOINT, STATUS_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
 };
 use fedimint_core::module::{
     api_endpoint, ApiAuth, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased,
 };
+use fedimint_core::net::proxy::Socks5Proxy;
 use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::util::{write_new, SafeUrl};
 use fedimint_core::PeerId;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
 use tokio_rustls::rustls;
 use tracing::error;
 
+use crate::config::ceremony::DkgCeremony;
 use crate::config::io::{read_server_config, write_server_config, PLAINTEXT_PASSWORD, SALT_FILE};
 use crate::config::{gen_cert_and_key, ConfigGenParams, ServerConfig};
+use crate::net::api_tls::ApiTlsConfig;
 use crate::net::peers::DelayCalculator;
 use crate::{check_auth, ApiResult, HasApiContext};
 
@@ -49,12 +53,14 @@ pub struct ConfigGenApi {
     data_dir: PathBuf,
     /// In-memory state machine
     state: Arc<Mutex<ConfigGenState>>,
-    /// DB not really used
+    /// DB the running DKG ceremony is persisted in
     db: Database,
     /// Tracks when the config is generated
     config_generated_tx: Sender<ServerConfig>,
     /// Task group for running DKG
     task_group: TaskGroup,
+    /// Progress of the running DKG ceremony
+    dkg_status: Arc<Mutex<DkgStatusResponse>>,
 }
 
 impl ConfigGenApi {
@@ -71,6 +77,7 @@ impl ConfigGenApi {
             db,
             config_generated_tx,
             task_group: task_group.clone(),
+            dkg_status: Arc::new(Mutex::new(DkgStatusResponse::default())),
         }
     }
 
@@ -109,7 +116,8 @@ impl ConfigGenApi {
         let local = state.local.clone();
 
         if let Some(url) = local.and_then(|local| local.leader_api_url) {
-            WsAdminClient::new(url)
+            state
+                .admin_client(url)
                 .add_config_gen_peer(state.our_peer_info()?)
                 .await
                 .map_err(|_| ApiError::not_found("Unable to connect to the leader".to_string()))?;
@@ -164,7 +172,7 @@ impl ConfigGenApi {
 
         let consensus = match local.and_then(|local| local.leader_api_url) {
             Some(leader_url) => {
-                let client = WsAdminClient::new(leader_url.clone());
+                let client = state.admin_client(leader_url.clone());
                 let response = client.get_consensus_config_gen_params().await;
                 response
                     .map_err(|_| ApiError::not_found("Cannot get leader params".to_string()))?
@@ -174,6 +182,8 @@ impl ConfigGenApi {
                 peers: state.get_peer_info(),
                 meta: request.meta.clone(),
                 modules: request.modules.clone(),
+                limits: request.limits,
+                fees: request.fees.clone(),
             },
         };
 
@@ -200,38 +210,93 @@ impl ConfigGenApi {
             state
                 .local
                 .clone()
-                .and_then(|local| local.leader_api_url.map(WsAdminClient::new))
+                .and_then(|local| local.leader_api_url)
+                .map(|url| state.admin_client(url))
         };
 
         self.update_leader().await?;
+        self.spawn_dkg(leader, None).await;
+
+        Ok(())
+    }
+
+    /// Resumes the DKG ceremony that was running when we stopped, returns
+    /// whether there was one to resume
+    pub async fn resume_dkg(&self) -> anyhow::Result<bool> {
+        if self.data_dir.join(SALT_FILE).exists() {
+            // The configs were written, we only stopped before cleaning up
+            DkgCeremony::remove(&self.db).await;
+            return Ok(false);
+        }
+
+        let Some((params, ceremony)) = DkgCeremony::load(&self.db, self.dkg_status.clone()).await?
+        else {
+            return Ok(false);
+        };
 
+        {
+            let mut state = self.state.lock().expect("lock poisoned");
+            state.auth = Some(params.local.api_auth.clone());
+            state.status = ServerStatus::ReadyForConfigGen;
+        }
+
+        self.spawn_dkg(None, Some((params, ceremony))).await;
+        Ok(true)
+    }
+
+    /// Spawns the task running DKG, either starting a new ceremony once the
+    /// `leader` is ready or resuming a persisted one
+    async fn spawn_dkg(
+        &self,
+        leader: Option<WsAdminClient>,
+        resume: Option<(ConfigGenParams, DkgCeremony)>,
+    ) {
         let self_clone = self.clone();
         let mut sub_group = self.task_group.make_subgroup().await;
         sub_group
             .spawn("run dkg", move |_handle| async move {
-                // Followers wait for leader to signal readiness for DKG
-                if let Some(client) = leader {
-                    loop {
-                        let status = client.status().await.map_err(|_| {
-                            ApiError::not_found("Unable to connect to the leader".to_string())
+                let (params, ceremony) = match resume {
+                    Some(resume) => resume,
+                    None => {
+                        // Followers wait for leader to signal readiness for DKG
+                        if let Some(client) = leader {
+                            loop {
+                                let status = client.status().await.map_err(|_| {
+                                    ApiError::not_found(
+                                        "Unable to connect to the leader".to_string(),
+                                    )
+                                })?;
+                                if status.server == ServerStatus::ReadyForConfigGen {
+                                    break;
+                                }
+                                sleep(Duration::from_millis(100)).await;
+                            }
+                        };
+
+                        // Get params
+                        let request = self_clone.get_requested_params()?;
+                        let response = self_clone.get_consensus_config_gen_params(&request).await?;
+                        let params = self_clone
+                            .require_status(ServerStatus::ReadyForConfigGen)?
+                            .get_config_gen_params(&request, response.consensus)?;
+
+                        let ceremony = DkgCeremony::start(
+                            &self_clone.db,
+                            &params,
+                            self_clone.dkg_status.clone(),
+                        )
+                        .await
+                        .map_err(|e| {
+                            ApiError::server_error(format!("Unable to persist DKG {e:?}"))
                         })?;
-                        if status.server == ServerStatus::ReadyForConfigGen {
-                            break;
-                        }
-                        sleep(Duration::from_millis(100)).await;
+                        (params, ceremony)
                     }
                 };
-
-                // Get params and registry
-                let request = self_clone.get_requested_params()?;
-                let response = self_clone.get_consensus_config_gen_params(&request).await?;
-                let (params, registry) = {
-                    let state: MutexGuard<'_, ConfigGenState> =
-                        self_clone.require_status(ServerStatus::ReadyForConfigGen)?;
-                    let params = state.get_config_gen_params(&request, response.consensus)?;
-                    let registry = state.settings.registry.clone();
-                    (params, registry)
-                };
+                let registry = self_clone
+                    .require_status(ServerStatus::ReadyForConfigGen)?
+                    .settings
+                    .registry
+                    .clone();
 
                 // Run DKG
                 let mut task_group: TaskGroup = self_clone.task_group.make_subgroup().await;
@@ -240,6 +305,7 @@ impl ConfigGenApi {
                     registry,
                     DelayCalculator::PROD_DEFAULT,
                     &mut task_group,
+                    &ceremony,
                 )
                 .await;
                 task_group
@@ -247,13 +313,14 @@ impl ConfigGenApi {
                     .await
                     .expect("shuts down");
 
-                {
+                let written = {
                     let mut state = self_clone.state.lock().expect("lock poisoned");
                     match config {
                         Ok(config) => {
                             self_clone.write_configs(&config, &state)?;
                             state.status = ServerStatus::VerifyingConfigs;
                             state.config = Some(config);
+                            true
                         }
                         Err(e) => {
                             error!(
@@ -261,14 +328,21 @@ impl ConfigGenApi {
                                 "DKG failed with {:?}", e
                             );
                             state.status = ServerStatus::ConfigGenFailed;
+                            false
                         }
                     }
+                };
+                if written {
+                    DkgCeremony::remove(&self_clone.db).await;
                 }
                 self_clone.update_leader().await
             })
             .await;
+    }
 
-        Ok(())
+    /// Returns the progress of every peer in the running DKG ceremony
+    pub fn dkg_status(&self) -> DkgStatusResponse {
+        self.dkg_status.lock().expect("lock poisoned").clone()
     }
 
     /// Returns the consensus config hash, tweaked by our TLS cert, to be shared
@@ -297,8 +371,9 @@ impl ConfigGenApi {
     fn write_configs(&self, config: &ServerConfig, state: &ConfigGenState) -> ApiResult<()> {
         let auth = config.private.api_auth.0.clone();
         let io_error = |e| ApiError::server_error(format!("Unable to write to data dir {e:?}"));
-        // TODO: Make writing password optional
-        write_new(self.data_dir.join(PLAINTEXT_PASSWORD), &auth).map_err(io_error)?;
+        if state.settings.persist_password {
+            write_new(self.data_dir.join(PLAINTEXT_PASSWORD), &auth).map_err(io_error)?;
+        }
         write_new(self.data_dir.join(SALT_FILE), random_salt()).map_err(io_error)?;
         write_server_config(
             config,
@@ -344,11 +419,12 @@ impl ConfigGenApi {
 }
 
 /// Config gen params that are only used locally, shouldn't be shared
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigGenParamsLocal {
     /// Our peer id
     pub our_id: PeerId,
     /// Our TLS private key
+    #[serde(with = "crate::config::serde_tls_key")]
     pub our_private_key: rustls::PrivateKey,
     /// Secret API auth string
     pub api_auth: ApiAuth,
@@ -360,6 +436,12 @@ pub struct ConfigGenParamsLocal {
     pub download_token_limit: Option<u64>,
     /// How many API connections we will accept
     pub max_connections: u32,
+    /// SOCKS5 proxy used to connect to peers running behind onion services
+    pub socks5_proxy: Option<SafeUrl>,
+    /// Limit on the outbound bandwidth to each peer in bytes per second
+    pub p2p_max_outbound_bytes_per_sec: Option<u64>,
+    /// Certificate to serve the API over TLS with
+    pub api_tls: Option<ApiTlsConfig>,
 }
 
 /// All the info we configure prior to config gen starting
@@ -375,12 +457,26 @@ pub struct ConfigGenSettings {
     pub p2p_url: SafeUrl,
     /// URL for our API connection
     pub api_url: SafeUrl,
+    /// Further URLs for our P2P connection in order of preference, e.g. our
+    /// IPv6 or onion address
+    pub p2p_fallback_urls: Vec<SafeUrl>,
+    /// Further URLs for our API connection in order of preference
+    pub api_fallback_urls: Vec<SafeUrl>,
     /// The default params for the modules
     pub default_params: ConfigGenParamsRequest,
     /// How many API connections we will accept
     pub max_connections: u32,
+    /// SOCKS5 proxy used to connect to peers running behind onion services
+    pub socks5_proxy: Option<SafeUrl>,
+    /// Limit on the outbound bandwidth to each peer in bytes per second
+    pub p2p_max_outbound_bytes_per_sec: Option<u64>,
+    /// Certificate to serve the API over TLS with
+    pub api_tls: Option<ApiTlsConfig>,
     /// Registry for config gen
     pub registry: ServerModuleInitRegistry,
+    /// Whether to write the password to the data dir after config gen, such
+    /// that the guardian restarts without it being provided again
+    pub persist_password: bool,
 }
 
 /// State held by the API after receiving a `ConfigGenConnectionsRequest`
@@ -430,6 +526,18 @@ impl ConfigGenState {
         }
     }
 
+    /// Client for the API of the leader, tunneled through our SOCKS5 proxy if
+    /// the leader runs behind an onion service
+    fn admin_client(&self, url: SafeUrl) -> WsAdminClient {
+        WsAdminClient::new_with_proxy(
+            url,
+            self.settings
+                .socks5_proxy
+                .clone()
+                .map(Socks5Proxy::onion_only),
+        )
+    }
+
     fn set_request(&mut self, request: ConfigGenConnectionsRequest) -> ApiResult<()> {
         let (tls_cert, tls_private) = gen_cert_and_key(&request.our_name)
             .map_err(|_| ApiError::server_error("Unable to generate TLS keys".to_string()))?;
@@ -460,6 +568,8 @@ impl ConfigGenState {
             cert: local.tls_cert.clone(),
             p2p_url: self.settings.p2p_url.clone(),
             api_url: self.settings.api_url.clone(),
+            p2p_fallback_urls: self.settings.p2p_fallback_urls.clone(),
+            api_fallback_urls: self.settings.api_fallback_urls.clone(),
             name: local.our_name,
             status: Some(self.status.clone()),
         })
@@ -515,6 +625,16 @@ impl ConfigGenState {
         }
         consensus.modules = ServerModuleConfigGenParamsRegistry::from_iter(combined_params);
 
+        consensus
+            .limits
+            .validate()
+            .map_err(|e| ApiError::bad_request(format!("Consensus limits invalid: {e}")))?;
+
+        consensus
+            .fees
+            .validate()
+            .map_err(|e| ApiError::bad_request(format!("Fee schedule invalid: {e}")))?;
+
         let local = ConfigGenParamsLocal {
             our_id: *our_id,
             our_private_key: local_connection.tls_private,
@@ -523,6 +643,9 @@ impl ConfigGenState {
             api_bind: self.settings.api_bind,
             download_token_limit: self.settings.download_token_limit,
             max_connections: self.settings.max_connections,
+            socks5_proxy: self.settings.socks5_proxy.clone(),
+            p2p_max_outbound_bytes_per_sec: self.settings.p2p_max_outbound_bytes_per_sec,
+            api_tls: self.settings.api_tls.clone(),
         };
 
         Ok(ConfigGenParams { local, consensus })
@@ -668,6 +791,12 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConfigGenApi>> {
                 })
             }
         },
+        api_endpoint! {
+            DKG_STATUS_ENDPOINT,
+            async |config: &ConfigGenApi, _context, _v: ()| -> DkgStatusResponse {
+                Ok(config.dkg_status())
+            }
+        },
         api_endpoint! {
             AUTH_ENDPOINT,
             async |_config: &ConfigGenApi, context, _v: ()| -> () {
@@ -744,6 +873,8 @@ mod tests {
             let default_params = ConfigGenParamsRequest {
                 meta: Default::default(),
                 modules,
+                limits: Default::default(),
+                fees: Default::default(),
             };
             let settings = ConfigGenSettings {
                 download_token_limit: None,
@@ -751,9 +882,15 @@ mod tests {
                 api_bind,
                 p2p_url,
                 api_url: api_url.clone(),
+                p2p_fallback_urls: vec![],
+                api_fallback_urls: vec![],
                 default_params,
                 max_connections: DEFAULT_MAX_CLIENT_CONNECTIONS,
+                socks5_proxy: None,
+                p2p_max_outbound_bytes_per_sec: None,
+                api_tls: None,
                 registry: ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]),
+                persist_password: true,
             };
             let dir = data_dir.join(name_suffix.to_string());
             fs::create_dir_all(dir.clone()).expect("Unable to create test dir");
@@ -762,6 +899,11 @@ mod tests {
                 data_dir: dir.clone(),
                 settings: settings.clone(),
                 db,
+                archive: None,
+                well_known_bind: None,
+                alert_command: None,
+                remote_signer: None,
+                password: None,
             };
 
             // our id doesn't really exist at this point
@@ -845,6 +987,8 @@ mod tests {
             let request = ConfigGenParamsRequest {
                 meta: BTreeMap::from([("test".to_string(), self.name.clone())]),
                 modules,
+                limits: Default::default(),
+                fees: Default::default(),
             };
 
             self.client
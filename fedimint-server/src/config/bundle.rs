@@ -0,0 +1,188 @@
+//! Export and import of the full config of a guardian as a single file
+//!
+//! A [`ConfigBundle`] contains the consensus and local config in plaintext and
+//! the private config encrypted with a key derived from a passphrase. The
+//! encrypted section also contains the hash of the plaintext sections, so a
+//! bundle that was tampered with or corrupted is rejected on import.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Context};
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_aead::{decrypt, encrypt, get_encryption_key, random_salt};
+use fedimint_core::config::{ConfigBundle, ServerModuleInitRegistry};
+use fedimint_core::util::write_new;
+use serde::{Deserialize, Serialize};
+
+use crate::config::io::{write_server_config, PLAINTEXT_PASSWORD, SALT_FILE};
+use crate::config::{ServerConfig, ServerConfigConsensus, ServerConfigLocal, ServerConfigPrivate};
+
+/// Version of the bundles we export, bundles of other versions are rejected
+pub const CONFIG_BUNDLE_VERSION: u16 = 1;
+
+/// The encrypted section of a bundle
+#[derive(Serialize, Deserialize)]
+struct EncryptedSection {
+    /// Hash of the plaintext sections of the bundle
+    public_hash: sha256::Hash,
+    private: ServerConfigPrivate,
+}
+
+fn public_hash(consensus: &ServerConfigConsensus, local: &ServerConfigLocal) -> sha256::Hash {
+    let public = serde_json::to_vec(&(consensus, local)).expect("Config serializes to JSON");
+
+    sha256::Hash::hash(&public)
+}
+
+/// Bundles the config of the guardian, encrypting the private config with the
+/// passphrase
+pub fn export_config_bundle(cfg: &ServerConfig, passphrase: &str) -> anyhow::Result<ConfigBundle> {
+    ensure!(!passphrase.is_empty(), "The passphrase must not be empty");
+
+    let salt = random_salt();
+    let key = get_encryption_key(passphrase, &salt)?;
+
+    let section = EncryptedSection {
+        public_hash: public_hash(&cfg.consensus, &cfg.local),
+        private: cfg.private.clone(),
+    };
+
+    Ok(ConfigBundle {
+        version: CONFIG_BUNDLE_VERSION,
+        salt,
+        consensus: serde_json::to_value(&cfg.consensus)?,
+        local: serde_json::to_value(&cfg.local)?,
+        private: encrypt(serde_json::to_vec(&section)?, &key)?.to_hex(),
+    })
+}
+
+/// Decrypts the private config of the bundle and checks that the plaintext
+/// sections have not been modified
+pub fn open_config_bundle(bundle: &ConfigBundle, passphrase: &str) -> anyhow::Result<ServerConfig> {
+    if bundle.version != CONFIG_BUNDLE_VERSION {
+        bail!(
+            "Config bundle has version {}, but only version {CONFIG_BUNDLE_VERSION} is supported",
+            bundle.version
+        );
+    }
+
+    let consensus: ServerConfigConsensus = serde_json::from_value(bundle.consensus.clone())
+        .context("Invalid consensus config in bundle")?;
+    let local: ServerConfigLocal =
+        serde_json::from_value(bundle.local.clone()).context("Invalid local config in bundle")?;
+
+    let key = get_encryption_key(passphrase, &bundle.salt)?;
+    let mut ciphertext = Vec::<u8>::from_hex(&bundle.private)?;
+    let section: EncryptedSection = serde_json::from_slice(
+        decrypt(&mut ciphertext, &key).context("Wrong passphrase or corrupted bundle")?,
+    )?;
+
+    ensure!(
+        section.public_hash == public_hash(&consensus, &local),
+        "The plaintext sections of the bundle have been modified"
+    );
+
+    Ok(ServerConfig {
+        consensus,
+        local,
+        private: section.private,
+    })
+}
+
+/// Writes the config of the bundle to the empty data dir of a guardian, which
+/// then starts with it like with a config it generated
+pub fn import_config_bundle(
+    bundle: &ConfigBundle,
+    passphrase: &str,
+    data_dir: PathBuf,
+    module_inits: &ServerModuleInitRegistry,
+    persist_password: bool,
+) -> anyhow::Result<ServerConfig> {
+    let cfg = open_config_bundle(bundle, passphrase)?;
+
+    cfg.validate_config(&cfg.local.identity, module_inits)?;
+
+    ensure!(
+        !data_dir.join(SALT_FILE).exists(),
+        "The data dir {} already contains a config",
+        data_dir.display()
+    );
+
+    // like after config generation the config is encrypted with the api password
+    let auth = cfg.private.api_auth.0.clone();
+    if persist_password {
+        write_new(data_dir.join(PLAINTEXT_PASSWORD), &auth)?;
+    }
+    write_new(data_dir.join(SALT_FILE), random_salt())?;
+    write_server_config(&cfg, data_dir, &auth, module_inits)?;
+
+    Ok(cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_core::config::ServerModuleInitRegistry;
+    use fedimint_core::module::DynServerModuleInit;
+    use fedimint_core::PeerId;
+    use fedimint_dummy_server::DummyGen;
+
+    use super::{export_config_bundle, import_config_bundle, open_config_bundle};
+    use crate::config::io::read_server_config;
+    use crate::config::ServerConfig;
+    use crate::simulation::config_gen_params;
+
+    #[test]
+    fn imports_exported_bundle() {
+        let peers = (0..4).map(PeerId::from).collect::<BTreeSet<_>>();
+        let registry = ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]);
+        let cfgs = ServerConfig::trusted_dealer_gen(&config_gen_params(&peers), registry.clone());
+        let cfg = &cfgs[&PeerId::from(0)];
+
+        let bundle = export_config_bundle(cfg, "correct horse").expect("Export succeeds");
+
+        assert!(open_config_bundle(&bundle, "wrong horse").is_err());
+
+        let mut tampered = bundle.clone();
+        tampered.local = export_config_bundle(&cfgs[&PeerId::from(1)], "correct horse")
+            .expect("Export succeeds")
+            .local;
+        assert!(open_config_bundle(&tampered, "correct horse").is_err());
+
+        let mut unsupported = bundle.clone();
+        unsupported.version += 1;
+        assert!(open_config_bundle(&unsupported, "correct horse").is_err());
+
+        let data_dir = tempfile::tempdir().expect("Creates temp dir");
+        let imported = import_config_bundle(
+            &bundle,
+            "correct horse",
+            data_dir.path().to_owned(),
+            &registry,
+            true,
+        )
+        .expect("Import succeeds");
+
+        let read = read_server_config(&cfg.private.api_auth.0, data_dir.path().to_owned())
+            .expect("Reads imported config");
+
+        for restored in [&imported, &read] {
+            assert_eq!(
+                serde_json::to_value(restored).expect("Serializes"),
+                serde_json::to_value(cfg).expect("Serializes")
+            );
+        }
+
+        // the data dir is not overwritten
+        assert!(import_config_bundle(
+            &bundle,
+            "correct horse",
+            data_dir.path().to_owned(),
+            &registry,
+            true
+        )
+        .is_err());
+    }
+}
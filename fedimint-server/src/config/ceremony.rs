@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use fedimint_core::admin_client::{DkgRound, DkgStatusResponse};
+use fedimint_core::cancellable::Cancellable;
+use fedimint_core::config::{DkgGroup, DkgMessage, DkgPeerMsg, SupportedDkgMessage};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::net::peers::{IMuxPeerConnections, MuxPeerConnections};
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_NET_PEER_DKG;
+use futures::StreamExt;
+use rand::rngs::OsRng;
+use rand::Rng;
+use tracing::{info, warn};
+
+use crate::config::ConfigGenParams;
+use crate::db::{
+    DkgCeremonyKey, DkgJournalEntry, DkgJournalKey, DkgJournalPrefix, PersistedDkgCeremony,
+};
+
+type MuxKey = (ModuleInstanceId, String);
+
+/// A DKG ceremony whose progress is persisted in the database, such that a
+/// guardian restarting during it resumes where it stopped instead of failing
+/// the ceremony for everyone
+///
+/// All randomness of our part of the ceremony is derived from a persisted
+/// seed, so we send the same messages again after a restart, which our peers
+/// ignore as duplicates. The messages we received are journaled and replayed
+/// after a restart, the ones our peers sent while we were down are delivered
+/// once we reconnect.
+#[derive(Clone)]
+pub struct DkgCeremony {
+    db: Database,
+    seed: [u8; 32],
+    status: Arc<Mutex<DkgStatusResponse>>,
+}
+
+impl DkgCeremony {
+    /// Starts a new ceremony for `params`, discarding what a previous attempt
+    /// left behind
+    pub async fn start(
+        db: &Database,
+        params: &ConfigGenParams,
+        status: Arc<Mutex<DkgStatusResponse>>,
+    ) -> anyhow::Result<DkgCeremony> {
+        let seed: [u8; 32] = OsRng.gen();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.remove_by_prefix(&DkgJournalPrefix).await;
+        dbtx.insert_entry(
+            &DkgCeremonyKey,
+            &PersistedDkgCeremony {
+                params: serde_json::to_string(params)?,
+                seed,
+            },
+        )
+        .await;
+        dbtx.commit_tx_result().await?;
+
+        *status.lock().expect("lock poisoned") = DkgStatusResponse::default();
+
+        Ok(DkgCeremony {
+            db: db.clone(),
+            seed,
+            status,
+        })
+    }
+
+    /// Loads the ceremony that was running when the guardian stopped, if any
+    pub async fn load(
+        db: &Database,
+        status: Arc<Mutex<DkgStatusResponse>>,
+    ) -> anyhow::Result<Option<(ConfigGenParams, DkgCeremony)>> {
+        let Some(persisted) = db
+            .begin_transaction()
+            .await
+            .get_value(&DkgCeremonyKey)
+            .await
+        else {
+            return Ok(None);
+        };
+
+        let params: ConfigGenParams =
+            serde_json::from_str(&persisted.params).context("Invalid persisted DKG params")?;
+
+        *status.lock().expect("lock poisoned") = DkgStatusResponse {
+            resumed: true,
+            keys: BTreeMap::new(),
+        };
+
+        let ceremony = DkgCeremony {
+            db: db.clone(),
+            seed: persisted.seed,
+            status,
+        };
+
+        Ok(Some((params, ceremony)))
+    }
+
+    /// Removes the persisted ceremony once the configs were written
+    pub async fn remove(db: &Database) {
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.remove_entry(&DkgCeremonyKey).await;
+        dbtx.remove_by_prefix(&DkgJournalPrefix).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Seed all randomness of our part of the ceremony is derived from
+    pub fn seed(&self) -> [u8; 32] {
+        self.seed
+    }
+
+    /// Wraps the DKG connections, journaling every message received and
+    /// replaying the ones journaled before a restart first
+    pub async fn journaled(
+        &self,
+        our_id: PeerId,
+        connections: MuxPeerConnections<MuxKey, DkgPeerMsg>,
+    ) -> MuxPeerConnections<MuxKey, DkgPeerMsg> {
+        let mut replay: BTreeMap<MuxKey, VecDeque<(PeerId, DkgPeerMsg)>> = BTreeMap::new();
+        let mut next_index: BTreeMap<MuxKey, u64> = BTreeMap::new();
+
+        let mut journal = self
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&DkgJournalPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        journal.sort_by_key(|(key, _)| (key.module_instance_id, key.key.clone(), key.index));
+
+        for (key, entry) in journal {
+            let mux_key = (key.module_instance_id, key.key);
+            next_index.insert(mux_key.clone(), key.index + 1);
+
+            match serde_json::from_str::<DkgPeerMsg>(&entry.message) {
+                Ok(message) => replay
+                    .entry(mux_key)
+                    .or_default()
+                    .push_back((entry.peer, message)),
+                Err(error) => {
+                    warn!(target: LOG_NET_PEER_DKG, ?error, "Dropping invalid journaled message")
+                }
+            }
+        }
+
+        if !replay.is_empty() {
+            info!(
+                target: LOG_NET_PEER_DKG,
+                messages = replay.values().map(VecDeque::len).sum::<usize>(),
+                "Resuming DKG with the messages received before the restart"
+            );
+        }
+
+        JournaledConnections {
+            inner: connections,
+            db: self.db.clone(),
+            our_id,
+            replay: Mutex::new(replay),
+            next_index: Mutex::new(next_index),
+            status: self.status.clone(),
+        }
+        .into_dyn()
+    }
+}
+
+struct JournaledConnections {
+    inner: MuxPeerConnections<MuxKey, DkgPeerMsg>,
+    db: Database,
+    our_id: PeerId,
+    /// Messages received before a restart that were not handed out again yet
+    replay: Mutex<BTreeMap<MuxKey, VecDeque<(PeerId, DkgPeerMsg)>>>,
+    next_index: Mutex<BTreeMap<MuxKey, u64>>,
+    status: Arc<Mutex<DkgStatusResponse>>,
+}
+
+impl JournaledConnections {
+    fn record_round(&self, key: &MuxKey, peer: PeerId, msg: &DkgPeerMsg) {
+        let round = dkg_round(msg);
+        let mut status = self.status.lock().expect("lock poisoned");
+        let peer_round = status
+            .keys
+            .entry(format!("{}/{}", key.0, key.1))
+            .or_default()
+            .entry(peer)
+            .or_insert(round);
+        *peer_round = (*peer_round).max(round);
+    }
+
+    async fn journal(&self, key: &MuxKey, peer: PeerId, msg: &DkgPeerMsg) {
+        let index = {
+            let mut next_index = self.next_index.lock().expect("lock poisoned");
+            let index = next_index.entry(key.clone()).or_default();
+            *index += 1;
+            *index - 1
+        };
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(
+            &DkgJournalKey {
+                module_instance_id: key.0,
+                key: key.1.clone(),
+                index,
+            },
+            &DkgJournalEntry {
+                peer,
+                message: serde_json::to_string(msg).expect("serialization can't fail"),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+    }
+}
+
+#[async_trait]
+impl IMuxPeerConnections<MuxKey, DkgPeerMsg> for JournaledConnections {
+    async fn send(&self, peers: &[PeerId], key: MuxKey, msg: DkgPeerMsg) -> Cancellable<()> {
+        self.record_round(&key, self.our_id, &msg);
+        self.inner.send(peers, key, msg).await
+    }
+
+    async fn receive(&self, key: MuxKey) -> Cancellable<(PeerId, DkgPeerMsg)> {
+        let replayed = self
+            .replay
+            .lock()
+            .expect("lock poisoned")
+            .get_mut(&key)
+            .and_then(VecDeque::pop_front);
+
+        let (peer, msg) = match replayed {
+            Some(replayed) => replayed,
+            None => {
+                let (peer, msg) = self.inner.receive(key.clone()).await?;
+                self.journal(&key, peer, &msg).await;
+                (peer, msg)
+            }
+        };
+
+        self.record_round(&key, peer, &msg);
+        Ok((peer, msg))
+    }
+
+    async fn ban_peer(&self, peer: PeerId) {
+        self.inner.ban_peer(peer).await;
+    }
+}
+
+fn dkg_round(msg: &DkgPeerMsg) -> DkgRound {
+    fn round<G: DkgGroup>(msg: &DkgMessage<G>) -> DkgRound {
+        match msg {
+            DkgMessage::HashedCommit(_) => DkgRound::HashedCommit,
+            DkgMessage::Commit(_) => DkgRound::Commit,
+            DkgMessage::Share(_, _) => DkgRound::Share,
+            DkgMessage::Extract(_) => DkgRound::Extract,
+        }
+    }
+
+    match msg {
+        DkgPeerMsg::PublicKey(_) => DkgRound::PublicKey,
+        DkgPeerMsg::DistributedGen(SupportedDkgMessage::G1(msg)) => round(msg),
+        DkgPeerMsg::DistributedGen(SupportedDkgMessage::G2(msg)) => round(msg),
+        DkgPeerMsg::Done => DkgRound::Done,
+    }
+}
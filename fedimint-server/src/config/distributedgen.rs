@@ -15,7 +15,8 @@ use fedimint_core::task::spawn;
 use fedimint_core::{BitcoinHash, NumPeers, PeerId};
 use hbbft::crypto::poly::Commitment;
 use hbbft::crypto::{G1Projective, G2Projective, PublicKeySet, SecretKeyShare};
-use rand::rngs::OsRng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tbs::hash::hash_bytes_to_curve;
@@ -234,10 +235,21 @@ pub fn scalar(peer: &PeerId) -> Scalar {
     Scalar::from(peer.to_usize() as u64 + 1)
 }
 
+/// Random number generator for `purpose` in the config gen of a module,
+/// derived from the `seed` of the DKG ceremony
+pub fn dkg_rng(seed: &[u8; 32], module_id: ModuleInstanceId, purpose: &str) -> StdRng {
+    let mut engine = HashEngine::default();
+    engine.write_all(seed).expect("hashes");
+    engine.write_all(&module_id.to_be_bytes()).expect("hashes");
+    engine.write_all(purpose.as_bytes()).expect("hashes");
+    StdRng::from_seed(Sha256::from_engine(engine).into_inner())
+}
+
 pub struct DkgRunner<T> {
     peers: Vec<PeerId>,
     our_id: PeerId,
     dkg_config: HashMap<T, usize>,
+    seed: [u8; 32],
 }
 
 /// Helper for running multiple DKGs over the same peer connections
@@ -247,20 +259,34 @@ impl<T> DkgRunner<T>
 where
     T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash,
 {
-    /// Create multiple DKGs with the same `threshold` signatures required
-    pub fn multi(keys: Vec<T>, threshold: usize, our_id: &PeerId, peers: &[PeerId]) -> Self {
+    /// Create multiple DKGs with the same `threshold` signatures required,
+    /// whose polynomials are derived from `seed`
+    pub fn multi(
+        keys: Vec<T>,
+        threshold: usize,
+        our_id: &PeerId,
+        peers: &[PeerId],
+        seed: [u8; 32],
+    ) -> Self {
         let dkg_config = keys.into_iter().map(|key| (key, threshold)).collect();
 
         Self {
             our_id: *our_id,
             peers: peers.to_vec(),
             dkg_config,
+            seed,
         }
     }
 
     /// Create a single DKG with `threshold` signatures required
-    pub fn new(key: T, threshold: usize, our_id: &PeerId, peers: &[PeerId]) -> Self {
-        Self::multi(vec![key], threshold, our_id, peers)
+    pub fn new(
+        key: T,
+        threshold: usize,
+        our_id: &PeerId,
+        peers: &[PeerId],
+        seed: [u8; 32],
+    ) -> Self {
+        Self::multi(vec![key], threshold, our_id, peers, seed)
     }
 
     /// Create another DKG with `threshold` signatures required
@@ -314,9 +340,10 @@ where
                 let connections = connections.clone();
                 let key = serde_json::to_string(&key).expect("serialization can't fail");
                 let send = send.clone();
+                let mut rng = dkg_rng(&self.seed, module_id, &key);
 
                 spawn("dkg runner", async move {
-                    let (dkg, step) = Dkg::new(group, our_id, peers, threshold, &mut OsRng);
+                    let (dkg, step) = Dkg::new(group, our_id, peers, threshold, &mut rng);
                     let result =
                         Self::run_dkg_key((module_id, key.clone()), connections, dkg, step).await;
                     send.send((key, result)).await.expect("channel open");
@@ -427,7 +454,9 @@ mod tests {
     use hbbft::crypto::{G1Projective, G2Projective};
     use rand::rngs::OsRng;
 
-    use crate::config::distributedgen::{scalar, Dkg, DkgGroup, DkgKeys, DkgStep, ThresholdKeys};
+    use crate::config::distributedgen::{
+        dkg_rng, scalar, Dkg, DkgGroup, DkgKeys, DkgStep, ThresholdKeys,
+    };
 
     #[test_log::test]
     fn test_dkg() {
@@ -453,6 +482,29 @@ mod tests {
         }
     }
 
+    #[test_log::test]
+    fn test_dkg_rng_is_deterministic() {
+        let peers = (0..4u16).map(PeerId::from).collect::<Vec<_>>();
+        let first_step = |seed: [u8; 32], purpose: &str| {
+            let mut rng = dkg_rng(&seed, 0, purpose);
+            let (_, step) = Dkg::new(
+                G1Projective::generator(),
+                peers[0],
+                peers.clone(),
+                3,
+                &mut rng,
+            );
+            match step {
+                DkgStep::Messages(messages) => serde_json::to_string(&messages).unwrap(),
+                DkgStep::Result(_) => panic!("DKG can't finish in the first step"),
+            }
+        };
+
+        assert_eq!(first_step([1; 32], "hbbft"), first_step([1; 32], "hbbft"));
+        assert_ne!(first_step([1; 32], "hbbft"), first_step([2; 32], "hbbft"));
+        assert_ne!(first_step([1; 32], "hbbft"), first_step([1; 32], "epoch"));
+    }
+
     fn run<G: DkgGroup>(group: G) -> HashMap<PeerId, DkgKeys<G>> {
         let mut rng = OsRng;
         let num_peers = 4;
@@ -506,6 +558,10 @@ pub trait PeerHandleOps {
         dkg_key: String,
         key: secp256k1::PublicKey,
     ) -> DkgResult<BTreeMap<PeerId, secp256k1::PublicKey>>;
+
+    /// Random number generator for keys the module generates itself, which
+    /// yields the same keys if the guardian restarts during the DKG
+    fn dkg_rng(&self, purpose: &str) -> StdRng;
 }
 
 #[async_trait]
@@ -514,7 +570,13 @@ impl<'a> PeerHandleOps for PeerHandle<'a> {
     where
         T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash + Sync,
     {
-        let mut dkg = DkgRunner::new(v, self.peers.threshold(), &self.our_id, &self.peers);
+        let mut dkg = DkgRunner::new(
+            v,
+            self.peers.threshold(),
+            &self.our_id,
+            &self.peers,
+            self.seed,
+        );
         dkg.run_g1(self.module_instance_id, self.connections).await
     }
 
@@ -522,7 +584,13 @@ impl<'a> PeerHandleOps for PeerHandle<'a> {
     where
         T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash + Sync,
     {
-        let mut dkg = DkgRunner::multi(v, self.peers.threshold(), &self.our_id, &self.peers);
+        let mut dkg = DkgRunner::multi(
+            v,
+            self.peers.threshold(),
+            &self.our_id,
+            &self.peers,
+            self.seed,
+        );
 
         dkg.run_g2(self.module_instance_id, self.connections).await
     }
@@ -562,4 +630,8 @@ impl<'a> PeerHandleOps for PeerHandle<'a> {
 
         Ok(peer_peg_in_keys)
     }
+
+    fn dkg_rng(&self, purpose: &str) -> StdRng {
+        dkg_rng(&self.seed, self.module_instance_id, purpose)
+    }
 }
@@ -3,6 +3,7 @@ use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+use anyhow::Context;
 use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key, LessSafeKey};
 use fedimint_core::config::ServerModuleInitRegistry;
 use serde::de::DeserializeOwned;
@@ -38,9 +39,13 @@ pub const PLAINTEXT_PASSWORD: &str = "password.private";
 /// Database file name
 pub const DB_FILE: &str = "database";
 
+/// Directory the config files are written to before they replace the current
+/// ones
+const STAGING_DIR: &str = "staging";
+
 pub const JSON_EXT: &str = "json";
 
-const ENCRYPTED_EXT: &str = "encrypt";
+pub const ENCRYPTED_EXT: &str = "encrypt";
 
 /// Reads the server from the local, private, and consensus cfg files
 pub fn read_server_config(password: &str, path: PathBuf) -> anyhow::Result<ServerConfig> {
@@ -54,8 +59,27 @@ pub fn read_server_config(password: &str, path: PathBuf) -> anyhow::Result<Serve
     })
 }
 
+/// Removes the plaintext password from the data dir of an existing guardian,
+/// after checking that it decrypts the private config
+///
+/// The guardian then has to be given the password on every start, e.g. through
+/// the admin API or from a key management service.
+pub fn remove_password_file(path: PathBuf) -> anyhow::Result<()> {
+    let password_file = path.join(PLAINTEXT_PASSWORD);
+    let password = fs::read_to_string(&password_file)
+        .with_context(|| format!("No password file found in {}", path.display()))?;
+
+    read_server_config(&password, path).context("Password file does not decrypt the config")?;
+
+    fs::remove_file(password_file)?;
+
+    Ok(())
+}
+
 /// Reads a plaintext json file into a struct
-fn plaintext_json_read<T: Serialize + DeserializeOwned>(path: PathBuf) -> anyhow::Result<T> {
+pub(crate) fn plaintext_json_read<T: Serialize + DeserializeOwned>(
+    path: PathBuf,
+) -> anyhow::Result<T> {
     let string = fs::read_to_string(path.with_extension(JSON_EXT))?;
     Ok(serde_json::from_str(&string)?)
 }
@@ -91,6 +115,38 @@ pub fn write_server_config(
     encrypted_json_write(&server.private, &key, path.join(PRIVATE_CONFIG))
 }
 
+/// Replaces the configuration files of a running server, e.g. after a module
+/// was added
+///
+/// The files are written to a staging directory first such that a crash leaves
+/// every file either in its previous or its new state.
+pub fn rewrite_server_config(
+    server: &ServerConfig,
+    path: PathBuf,
+    password: &str,
+    module_config_gens: &ServerModuleInitRegistry,
+) -> anyhow::Result<()> {
+    let staging = path.join(STAGING_DIR);
+
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+
+    fs::create_dir(&staging)?;
+    fs::copy(path.join(SALT_FILE), staging.join(SALT_FILE))?;
+
+    write_server_config(server, staging.clone(), password, module_config_gens)?;
+
+    for entry in fs::read_dir(&staging)? {
+        let entry = entry?;
+        fs::rename(entry.path(), path.join(entry.file_name()))?;
+    }
+
+    fs::remove_dir(staging)?;
+
+    Ok(())
+}
+
 /// Writes struct into a plaintext json file
 fn plaintext_json_write<T: Serialize + DeserializeOwned>(
     obj: &T,
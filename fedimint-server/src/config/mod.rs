@@ -4,7 +4,7 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use anyhow::{bail, format_err};
-use fedimint_core::admin_client::ConfigGenParamsConsensus;
+use fedimint_core::admin_client::{ConfigGenParamsConsensus, PeerServerParams};
 use fedimint_core::api::{ClientConfigDownloadToken, InviteCode};
 use fedimint_core::cancellable::Cancelled;
 pub use fedimint_core::config::{
@@ -12,13 +12,19 @@ pub use fedimint_core::config::{
     GlobalClientConfig, JsonWithKind, ModuleInitRegistry, PeerUrl, ServerModuleConfig,
     ServerModuleConsensusConfig, ServerModuleInitRegistry, TypedServerModuleConfig,
 };
+use fedimint_core::config::{ServerModuleConfigGenParamsRegistry, META_FEDERATION_NAME_KEY};
 use fedimint_core::core::{ModuleInstanceId, ModuleKind, MODULE_INSTANCE_ID_GLOBAL};
+use fedimint_core::epoch::ConsensusLimits;
+use fedimint_core::fee::FeeSchedule;
 use fedimint_core::module::{
     ApiAuth, ApiVersion, CoreConsensusVersion, DynServerModuleInit, MultiApiVersion, PeerHandle,
     SupportedApiVersionsSummary, SupportedCoreApiVersions,
 };
 use fedimint_core::net::peers::{IMuxPeerConnections, IPeerConnections, PeerConnections};
+use fedimint_core::net::proxy::Socks5Proxy;
+use fedimint_core::rotation::KeyEpoch;
 use fedimint_core::task::{timeout, Elapsed, TaskGroup};
+use fedimint_core::util::SafeUrl;
 use fedimint_core::{timing, PeerId};
 use fedimint_logging::{LOG_NET_PEER, LOG_NET_PEER_DKG};
 use futures::future::join_all;
@@ -32,19 +38,28 @@ use serde::{Deserialize, Serialize};
 use tokio_rustls::rustls;
 use tracing::{error, info};
 
+use crate::alerts::AlertConfig;
 use crate::config::api::ConfigGenParamsLocal;
-use crate::config::distributedgen::{DkgRunner, PeerHandleOps, ThresholdKeys};
+use crate::config::ceremony::DkgCeremony;
+use crate::config::distributedgen::{dkg_rng, DkgRunner, PeerHandleOps, ThresholdKeys};
 use crate::config::io::CODE_VERSION;
 use crate::fedimint_core::encoding::Encodable;
 use crate::fedimint_core::NumPeers;
 use crate::multiplexed::PeerConnectionMultiplexer;
-use crate::net::connect::{dns_sanitize, Connector, TlsConfig};
+use crate::net::api_guard::ApiLimits;
+use crate::net::api_tls::ApiTlsConfig;
+use crate::net::connect::{dns_sanitize, parse_host_port, Connector, TlsConfig};
 use crate::net::peers::{DelayCalculator, NetworkConfig};
+use crate::net::usage::ApiTokenConfig;
 use crate::{ReconnectPeerConnections, TlsTcpConnector};
 
 pub mod api;
+pub mod bundle;
+pub mod ceremony;
 pub mod distributedgen;
 pub mod io;
+pub mod reload;
+pub mod versions;
 
 /// The default maximum open connections the API can handle
 const DEFAULT_MAX_CLIENT_CONNECTIONS: u32 = 1000;
@@ -145,6 +160,16 @@ pub struct ServerConfigConsensus {
     pub modules_json: BTreeMap<ModuleInstanceId, JsonWithKind>,
     /// Additional config the federation wants to transmit to the clients
     pub meta: BTreeMap<String, String>,
+    /// Size limits for the consensus items and transactions
+    #[serde(default)]
+    pub limits: ConsensusLimits,
+    /// Broadcast public keys replaced by key rotations, in the order they were
+    /// rotated
+    #[serde(default)]
+    pub key_epochs: Vec<KeyEpoch>,
+    /// Fees charged on the inputs and outputs of the modules
+    #[serde(default)]
+    pub fees: FeeSchedule,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -165,9 +190,51 @@ pub struct ServerConfigLocal {
     pub download_token: ClientConfigDownloadToken,
     /// Limit on the number of times a config download token can be used
     pub download_token_limit: Option<u64>,
+    /// SOCKS5 proxies used to connect to individual peers, e.g. Tor for peers
+    /// running behind onion services
+    #[serde(default)]
+    pub p2p_proxies: BTreeMap<PeerId, SafeUrl>,
+    /// SOCKS5 proxy used to connect to the APIs of peers running behind onion
+    /// services
+    #[serde(default)]
+    pub socks5_proxy: Option<SafeUrl>,
+    /// Transport used for the connections to our peers
+    #[serde(default)]
+    pub p2p_transport: PeerTransport,
+    /// Limit on the outbound bandwidth to each peer in bytes per second
+    #[serde(default)]
+    pub p2p_max_outbound_bytes_per_sec: Option<u64>,
+    /// Where and when to alert the operator about critical events
+    #[serde(default)]
+    pub alerts: AlertConfig,
+    /// Certificate to serve the API over TLS with, plaintext if not set
+    #[serde(default)]
+    pub api_tls: Option<ApiTlsConfig>,
+    /// Limits on the API connections and requests of clients
+    #[serde(default)]
+    pub api_limits: ApiLimits,
+    /// API tokens handed out to clients by name, whose usage we account
+    #[serde(default)]
+    pub api_tokens: BTreeMap<String, ApiTokenConfig>,
+    /// Upper bound of the delay of our consensus proposals per module, such
+    /// that the guardians do not all propose the same items at once
+    #[serde(default)]
+    pub proposal_jitter_ms: BTreeMap<ModuleInstanceId, u64>,
 }
 
-#[derive(Debug, Clone)]
+/// Transport protocol of the connections between guardians, all guardians of
+/// a federation have to use the same one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerTransport {
+    /// TLS over TCP
+    #[default]
+    TlsTcp,
+    /// QUIC, which has lower latency on lossy links and survives NAT rebinding
+    Quic,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// All the parameters necessary for generating the `ServerConfig` during setup
 ///
 /// * Guardians can create the parameters using a setup UI or CLI tool
@@ -177,6 +244,18 @@ pub struct ConfigGenParams {
     pub consensus: ConfigGenParamsConsensus,
 }
 
+impl ServerConfigLocal {
+    /// Routes our API connections to peers running behind onion services
+    /// through our SOCKS5 proxy
+    pub fn api_proxy(&self) -> Option<Socks5Proxy> {
+        self.socks5_proxy
+            .clone()
+            // configs from before the API connections used the proxy only stored it per peer
+            .or_else(|| self.p2p_proxies.values().next().cloned())
+            .map(Socks5Proxy::onion_only)
+    }
+}
+
 impl ServerConfigConsensus {
     pub fn iter_module_instances(
         &self,
@@ -192,9 +271,12 @@ impl ServerConfigConsensus {
             global: GlobalClientConfig {
                 federation_id: self.federation_id(),
                 epoch_pk: self.epoch_pk_set.public_key(),
+                broadcast_public_keys: self.broadcast_public_keys.clone(),
                 api_endpoints: self.api_endpoints.clone(),
                 consensus_version: self.version,
                 meta: self.meta.clone(),
+                transaction_limits: self.limits.transaction,
+                fees: self.fees.clone(),
             },
             modules: self
                 .modules
@@ -257,6 +339,15 @@ impl ServerConfig {
             modules: Default::default(),
             download_token: ClientConfigDownloadToken(OsRng.gen()),
             download_token_limit: params.local.download_token_limit,
+            p2p_proxies: params.p2p_proxies(),
+            socks5_proxy: params.local.socks5_proxy.clone(),
+            p2p_transport: PeerTransport::default(),
+            p2p_max_outbound_bytes_per_sec: params.local.p2p_max_outbound_bytes_per_sec,
+            alerts: AlertConfig::default(),
+            api_tls: params.local.api_tls.clone(),
+            api_limits: ApiLimits::default(),
+            api_tokens: BTreeMap::new(),
+            proposal_jitter_ms: BTreeMap::new(),
         };
         let consensus = ServerConfigConsensus {
             code_version: CODE_VERSION.to_string(),
@@ -270,6 +361,9 @@ impl ServerConfig {
             modules: Default::default(),
             modules_json: Default::default(),
             meta: params.consensus.meta,
+            limits: params.consensus.limits,
+            key_epochs: vec![],
+            fees: params.consensus.fees.clone(),
         };
         let mut cfg = Self {
             consensus,
@@ -394,12 +488,32 @@ impl ServerConfig {
         if private.hbbft_sks.public_key_share() != consensus.hbbft_pk_set.public_key_share(id) {
             bail!("HBBFT private key doesn't match pubkey share");
         }
+        consensus.limits.validate()?;
+        consensus.fees.validate()?;
+        if let Some(id) = consensus
+            .fees
+            .modules
+            .keys()
+            .find(|id| !consensus.modules.contains_key(id))
+        {
+            bail!("Fee schedule contains unknown module instance {id}");
+        }
         if peers.keys().max().copied().map(|id| id.to_usize()) != Some(peers.len() - 1) {
             bail!("Peer ids are not indexed from 0");
         }
         if peers.keys().min().copied() != Some(PeerId::from(0)) {
             bail!("Peer ids are not indexed from 0");
         }
+        for (peer_id, endpoint) in &peers {
+            if peer_id != identity
+                && endpoint.urls().iter().all(SafeUrl::is_onion_address)
+                && !self.local.p2p_proxies.contains_key(peer_id)
+            {
+                bail!(
+                    "Peer {peer_id} is an onion service but no SOCKS5 proxy is configured for it"
+                );
+            }
+        }
 
         for (module_id, module_kind) in self
             .consensus
@@ -483,12 +597,14 @@ impl ServerConfig {
         }
     }
 
-    /// Runs the distributed key gen algorithm
+    /// Runs the distributed key gen algorithm as part of `ceremony`, which
+    /// lets it resume if we restart while it is running
     pub async fn distributed_gen(
         params: &ConfigGenParams,
         registry: ServerModuleInitRegistry,
         delay_calculator: DelayCalculator,
         task_group: &mut TaskGroup,
+        ceremony: &DkgCeremony,
     ) -> DkgResult<Self> {
         let _timing /* logs on drop */ = timing::TimeReporter::new("distributed-gen").info();
         let server_conn = connect(
@@ -498,19 +614,30 @@ impl ServerConfig {
             task_group,
         )
         .await;
-        let connections = PeerConnectionMultiplexer::new(server_conn).into_dyn();
 
         let peers = &params.peer_ids();
         let our_id = &params.local.our_id;
 
+        let connections = ceremony
+            .journaled(
+                *our_id,
+                PeerConnectionMultiplexer::new(server_conn).into_dyn(),
+            )
+            .await;
+
         let broadcast_keys_exchange = PeerHandle::new(
             &connections,
             MODULE_INSTANCE_ID_GLOBAL,
             *our_id,
             peers.clone(),
+            ceremony.seed(),
         );
 
-        let (broadcast_sk, broadcast_pk) = secp256k1_zkp::generate_keypair(&mut OsRng);
+        let (broadcast_sk, broadcast_pk) = secp256k1_zkp::generate_keypair(&mut dkg_rng(
+            &ceremony.seed(),
+            MODULE_INSTANCE_ID_GLOBAL,
+            "broadcast",
+        ));
 
         let broadcast_public_keys = broadcast_keys_exchange
             .exchange_pubkeys("broadcast".to_string(), broadcast_pk)
@@ -528,7 +655,13 @@ impl ServerConfig {
         );
 
         // hbbft uses a lower threshold of signing keys (f+1)
-        let mut dkg = DkgRunner::new(KeyType::Hbbft, peers.one_honest(), our_id, peers);
+        let mut dkg = DkgRunner::new(
+            KeyType::Hbbft,
+            peers.one_honest(),
+            our_id,
+            peers,
+            ceremony.seed(),
+        );
         dkg.add(KeyType::Auth, peers.threshold());
         dkg.add(KeyType::Epoch, peers.threshold());
 
@@ -542,7 +675,13 @@ impl ServerConfig {
         let mut module_cfgs: BTreeMap<ModuleInstanceId, ServerModuleConfig> = Default::default();
         let modules = params.consensus.modules.iter_modules();
         let modules_runner = modules.map(|(module_instance_id, kind, module_params)| {
-            let dkg = PeerHandle::new(&connections, module_instance_id, *our_id, peers.clone());
+            let dkg = PeerHandle::new(
+                &connections,
+                module_instance_id,
+                *our_id,
+                peers.clone(),
+                ceremony.seed(),
+            );
             let registry = registry.clone();
 
             async move {
@@ -642,8 +781,9 @@ impl ServerConfig {
                 .local
                 .p2p_endpoints
                 .iter()
-                .map(|(&id, endpoint)| (id, endpoint.url.clone()))
+                .map(|(&id, endpoint)| (id, endpoint.urls()))
                 .collect(),
+            max_outbound_bytes_per_sec: self.local.p2p_max_outbound_bytes_per_sec,
         }
     }
 
@@ -657,6 +797,7 @@ impl ServerConfig {
                 .iter()
                 .map(|(id, endpoint)| (*id, endpoint.name.to_string()))
                 .collect(),
+            peer_proxies: self.local.p2p_proxies.clone(),
         }
     }
 
@@ -677,8 +818,9 @@ impl ConfigGenParams {
             peers: self
                 .p2p_urls()
                 .into_iter()
-                .map(|(id, peer)| (id, peer.url))
+                .map(|(id, peer)| (id, peer.urls()))
                 .collect(),
+            max_outbound_bytes_per_sec: self.local.p2p_max_outbound_bytes_per_sec,
         }
     }
 
@@ -691,9 +833,24 @@ impl ConfigGenParams {
                 .into_iter()
                 .map(|(id, peer)| (id, peer.name))
                 .collect(),
+            peer_proxies: self.p2p_proxies(),
         }
     }
 
+    /// Routes our connections to peers running behind onion services through
+    /// our SOCKS5 proxy
+    pub fn p2p_proxies(&self) -> BTreeMap<PeerId, SafeUrl> {
+        let Some(proxy) = &self.local.socks5_proxy else {
+            return BTreeMap::new();
+        };
+
+        self.p2p_urls()
+            .into_iter()
+            .filter(|(_, peer)| peer.urls().iter().any(SafeUrl::is_onion_address))
+            .map(|(id, _)| (id, proxy.clone()))
+            .collect()
+    }
+
     pub fn tls_certs(&self) -> BTreeMap<PeerId, rustls::Certificate> {
         self.consensus
             .peers
@@ -712,6 +869,7 @@ impl ConfigGenParams {
                     PeerUrl {
                         name: peer.name.clone(),
                         url: peer.p2p_url.clone(),
+                        fallback_urls: peer.p2p_fallback_urls.clone(),
                     },
                 )
             })
@@ -728,6 +886,7 @@ impl ConfigGenParams {
                     PeerUrl {
                         name: peer.name.clone(),
                         url: peer.api_url.clone(),
+                        fallback_urls: peer.api_fallback_urls.clone(),
                     },
                 )
             })
@@ -780,6 +939,81 @@ pub fn gen_cert_and_key(
     ))
 }
 
+/// Creates the config gen params for each peer
+///
+/// Uses peers * 2 ports offset from `base_port`
+pub fn local_config_gen_params(
+    peers: &[PeerId],
+    base_port: u16,
+    server_config_gen: ServerModuleConfigGenParamsRegistry,
+) -> anyhow::Result<HashMap<PeerId, ConfigGenParams>> {
+    // Generate TLS cert and private key
+    let tls_keys: HashMap<PeerId, (rustls::Certificate, rustls::PrivateKey)> = peers
+        .iter()
+        .map(|peer| {
+            (
+                *peer,
+                gen_cert_and_key(&format!("peer-{}", peer.to_usize())).unwrap(),
+            )
+        })
+        .collect();
+
+    // Generate the P2P and API URL on 2 different ports for each peer
+    let connections: BTreeMap<PeerId, PeerServerParams> = peers
+        .iter()
+        .map(|peer| {
+            let peer_port = base_port + u16::from(*peer) * 2;
+            let p2p_url = format!("fedimint://127.0.0.1:{peer_port}");
+            let api_url = format!("ws://127.0.0.1:{}", peer_port + 1);
+
+            let params: PeerServerParams = PeerServerParams {
+                cert: tls_keys[peer].0.clone(),
+                p2p_url: p2p_url.parse().expect("Should parse"),
+                api_url: api_url.parse().expect("Should parse"),
+                p2p_fallback_urls: vec![],
+                api_fallback_urls: vec![],
+                name: format!("peer-{}", peer.to_usize()),
+                status: None,
+            };
+            (*peer, params)
+        })
+        .collect();
+
+    peers
+        .iter()
+        .map(|peer| {
+            let p2p_bind = parse_host_port(connections[peer].clone().p2p_url)?;
+            let api_bind = parse_host_port(connections[peer].clone().api_url)?;
+
+            let params = ConfigGenParams {
+                local: ConfigGenParamsLocal {
+                    our_id: *peer,
+                    our_private_key: tls_keys[peer].1.clone(),
+                    api_auth: ApiAuth("pass".to_string()),
+                    p2p_bind: p2p_bind.parse().expect("Valid address"),
+                    api_bind: api_bind.parse().expect("Valid address"),
+                    download_token_limit: None,
+                    max_connections: 10,
+                    socks5_proxy: None,
+                    p2p_max_outbound_bytes_per_sec: None,
+                    api_tls: None,
+                },
+                consensus: ConfigGenParamsConsensus {
+                    peers: connections.clone(),
+                    meta: BTreeMap::from([(
+                        META_FEDERATION_NAME_KEY.to_owned(),
+                        "federation_name".to_string(),
+                    )]),
+                    modules: server_config_gen.clone(),
+                    limits: ConsensusLimits::default(),
+                    fees: FeeSchedule::default(),
+                },
+            };
+            Ok((*peer, params))
+        })
+        .collect::<anyhow::Result<HashMap<_, _>>>()
+}
+
 mod serde_tls_cert_map {
     use std::borrow::Cow;
     use std::collections::BTreeMap;
@@ -0,0 +1,262 @@
+//! Reloads the operational settings of a running guardian
+//!
+//! The [`ConfigWatcher`] periodically reads the config files from the data
+//! directory. Changes to the whitelisted settings of the local config, see
+//! [`ReloadableConfig`], are applied through the shared [`LiveConfig`] without
+//! restarting the guardian. Any other change is rejected and logged, since it
+//! would either change the consensus hash of the federation or require us to
+//! reconnect to our peers.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::ensure;
+use bitcoin_hashes::sha256;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::task::{sleep, TaskGroup, TaskHandle};
+use fedimint_logging::LOG_CORE;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::alerts::AlertConfig;
+use crate::config::io::{plaintext_json_read, CONSENSUS_CONFIG, LOCAL_CONFIG};
+use crate::config::{ServerConfig, ServerConfigConsensus, ServerConfigLocal};
+use crate::net::api_guard::ApiLimits;
+use crate::net::usage::ApiTokenConfig;
+
+/// How often the watcher checks the config files for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The fields of [`ServerConfigLocal`] that can change while we are running
+pub const RELOADABLE_FIELDS: &[&str] = &[
+    "api_bind",
+    "max_connections",
+    "api_limits",
+    "download_token_limit",
+    "alerts",
+    "api_tokens",
+];
+
+/// The settings of [`ServerConfigLocal`] that can change while we are running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReloadableConfig {
+    pub api_bind: SocketAddr,
+    pub max_connections: u32,
+    pub api_limits: ApiLimits,
+    pub download_token_limit: Option<u64>,
+    pub alerts: AlertConfig,
+    pub api_tokens: BTreeMap<String, ApiTokenConfig>,
+}
+
+impl ReloadableConfig {
+    pub fn from_local(local: &ServerConfigLocal) -> Self {
+        ReloadableConfig {
+            api_bind: local.api_bind,
+            max_connections: local.max_connections,
+            api_limits: local.api_limits,
+            download_token_limit: local.download_token_limit,
+            alerts: local.alerts.clone(),
+            api_tokens: local.api_tokens.clone(),
+        }
+    }
+
+    fn apply(&self, local: &mut ServerConfigLocal) {
+        local.api_bind = self.api_bind;
+        local.max_connections = self.max_connections;
+        local.api_limits = self.api_limits;
+        local.download_token_limit = self.download_token_limit;
+        local.alerts = self.alerts.clone();
+        local.api_tokens = self.api_tokens.clone();
+    }
+}
+
+/// The reloadable settings currently in effect, shared by everything that
+/// uses them
+#[derive(Debug, Clone)]
+pub struct LiveConfig(Arc<watch::Sender<ReloadableConfig>>);
+
+impl LiveConfig {
+    pub fn new(local: &ServerConfigLocal) -> Self {
+        LiveConfig(Arc::new(
+            watch::channel(ReloadableConfig::from_local(local)).0,
+        ))
+    }
+
+    pub fn get(&self) -> ReloadableConfig {
+        self.0.borrow().clone()
+    }
+
+    /// Calls `f` with the current settings without cloning them
+    pub fn with<R>(&self, f: impl FnOnce(&ReloadableConfig) -> R) -> R {
+        f(&self.0.borrow())
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ReloadableConfig> {
+        self.0.subscribe()
+    }
+
+    /// Returns whether the settings changed
+    fn update(&self, reloaded: ReloadableConfig) -> bool {
+        self.0.send_if_modified(|current| {
+            if *current == reloaded {
+                return false;
+            }
+
+            *current = reloaded;
+
+            true
+        })
+    }
+}
+
+/// Checks that the reloaded config files only differ from the config we are
+/// running with in reloadable settings
+pub fn check_reload(
+    running: &ServerConfig,
+    consensus: &ServerConfigConsensus,
+    local: &ServerConfigLocal,
+) -> anyhow::Result<ReloadableConfig> {
+    ensure!(
+        consensus.consensus_hash::<sha256::Hash>() == running.consensus.consensus_hash(),
+        "The consensus config changed, which would change the consensus hash of the federation"
+    );
+
+    let reloaded = ReloadableConfig::from_local(local);
+
+    let mut expected = running.local.clone();
+    reloaded.apply(&mut expected);
+
+    let expected = serde_json::to_value(expected)?;
+    let local = serde_json::to_value(local)?;
+
+    let changed = expected
+        .as_object()
+        .expect("Local config is a struct")
+        .iter()
+        .filter(|(field, value)| local.get(field.as_str()) != Some(*value))
+        .map(|(field, _)| field.as_str())
+        .collect::<Vec<_>>();
+
+    ensure!(
+        changed.is_empty(),
+        "Only {RELOADABLE_FIELDS:?} of the local config can be reloaded, but {changed:?} changed"
+    );
+
+    Ok(reloaded)
+}
+
+/// Background task that applies changes to the config files to the
+/// [`LiveConfig`]
+pub struct ConfigWatcher {
+    data_dir: PathBuf,
+    cfg: ServerConfig,
+    live_config: LiveConfig,
+}
+
+impl ConfigWatcher {
+    pub fn new(data_dir: PathBuf, cfg: ServerConfig, live_config: LiveConfig) -> Self {
+        ConfigWatcher {
+            data_dir,
+            cfg,
+            live_config,
+        }
+    }
+
+    pub async fn spawn(self, task_group: &mut TaskGroup) {
+        task_group
+            .spawn("config watcher", move |task_handle| async move {
+                self.run(task_handle).await
+            })
+            .await;
+    }
+
+    async fn run(&self, task_handle: TaskHandle) {
+        // we only log a rejected change once instead of on every check
+        let mut rejected = None;
+
+        while !task_handle.is_shutting_down() {
+            sleep(RELOAD_INTERVAL).await;
+
+            match self.reload() {
+                Ok(changed) => {
+                    rejected = None;
+
+                    if changed {
+                        info!(target: LOG_CORE, config = ?self.live_config.get(), "Reloaded local config");
+                    }
+                }
+                Err(e) => {
+                    let error = e.to_string();
+
+                    if rejected.as_ref() != Some(&error) {
+                        warn!(target: LOG_CORE, %error, "Rejected config change, restart the guardian to apply it");
+                        rejected = Some(error);
+                    }
+                }
+            }
+        }
+    }
+
+    fn reload(&self) -> anyhow::Result<bool> {
+        let consensus = plaintext_json_read(self.data_dir.join(CONSENSUS_CONFIG))?;
+        let local = plaintext_json_read(self.data_dir.join(LOCAL_CONFIG))?;
+
+        Ok(self
+            .live_config
+            .update(check_reload(&self.cfg, &consensus, &local)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_core::config::ServerModuleInitRegistry;
+    use fedimint_core::module::DynServerModuleInit;
+    use fedimint_core::PeerId;
+    use fedimint_dummy_server::DummyGen;
+
+    use super::{check_reload, LiveConfig, ReloadableConfig};
+    use crate::config::ServerConfig;
+    use crate::simulation::config_gen_params;
+
+    fn server_config() -> ServerConfig {
+        let peers = BTreeSet::from([PeerId::from(0)]);
+        let registry = ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]);
+
+        ServerConfig::trusted_dealer_gen(&config_gen_params(&peers), registry)
+            .remove(&PeerId::from(0))
+            .expect("Config for our peer")
+    }
+
+    #[test]
+    fn reloads_only_whitelisted_settings() {
+        let cfg = server_config();
+        let live_config = LiveConfig::new(&cfg.local);
+
+        let mut local = cfg.local.clone();
+        local.max_connections += 1;
+        local.alerts.repeat_secs = 60;
+
+        let reloaded = check_reload(&cfg, &cfg.consensus, &local).expect("Reloadable change");
+
+        assert_eq!(reloaded, ReloadableConfig::from_local(&local));
+        assert!(live_config.update(reloaded.clone()));
+        assert!(!live_config.update(reloaded));
+
+        let mut local = cfg.local.clone();
+        local.fed_bind = "127.0.0.1:1".parse().unwrap();
+
+        assert!(check_reload(&cfg, &cfg.consensus, &local).is_err());
+
+        let mut consensus = cfg.consensus.clone();
+        consensus
+            .meta
+            .insert("federation_name".to_string(), "renamed".to_string());
+
+        assert!(check_reload(&cfg, &consensus, &cfg.local).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! History of the consensus config versions the federation ran
+//!
+//! Upgrades and key rotations rewrite the consensus config of the guardians,
+//! after which clients can no longer verify older blocks against the current
+//! config. Therefore every guardian records the config it runs, together with
+//! its consensus hash and the first session it ran under, whenever it starts
+//! running a new version. Clients recovering old backups fetch these versions
+//! to verify the blocks signed under earlier configs.
+
+use bitcoin_hashes::sha256;
+use fedimint_core::config::{ClientConfig, ConsensusConfigVersion};
+use fedimint_core::db::{Database, DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::Encodable;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use tracing::info;
+
+use crate::config::ServerConfigConsensus;
+use crate::db::{ConsensusConfigVersionKey, ConsensusConfigVersionPrefix, SignedBlockPrefix};
+
+/// Records the config we are about to run unless it is the latest version
+pub async fn record_config_version(
+    db: &Database,
+    consensus: &ServerConfigConsensus,
+    client_config: &ClientConfig,
+) {
+    let mut dbtx = db.begin_transaction().await;
+    let consensus_hash = consensus.consensus_hash::<sha256::Hash>();
+
+    let latest = dbtx
+        .find_by_prefix_sorted_descending(&ConsensusConfigVersionPrefix)
+        .await
+        .next()
+        .await;
+
+    if let Some((_, version)) = &latest {
+        if version.consensus_hash == consensus_hash {
+            return;
+        }
+    }
+
+    let activation_session = dbtx.find_by_prefix(&SignedBlockPrefix).await.count().await as u64;
+    let index = latest.map_or(0, |(key, _)| key.0 + 1);
+
+    dbtx.insert_new_entry(
+        &ConsensusConfigVersionKey(index),
+        &ConsensusConfigVersion {
+            consensus_hash,
+            activation_session,
+            client_config: client_config.clone(),
+        },
+    )
+    .await;
+
+    dbtx.commit_tx().await;
+
+    info!(
+        target: LOG_CONSENSUS,
+        %consensus_hash,
+        activation_session,
+        "Recorded new consensus config version"
+    );
+}
+
+/// The consensus config versions we ran, oldest first
+pub async fn consensus_config_versions(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+) -> Vec<ConsensusConfigVersion> {
+    dbtx.find_by_prefix(&ConsensusConfigVersionPrefix)
+        .await
+        .map(|(_, version)| version)
+        .collect::<Vec<_>>()
+        .await
+}
@@ -0,0 +1,157 @@
+//! Retrying database transactions whose commit conflicted
+//!
+//! Our database transactions are optimistic, so a commit fails if a concurrent
+//! transaction wrote to the same keys in the meantime, e.g. when the API
+//! records a config download while another request does the same. Such a
+//! conflict resolves by running the transaction again on the new state of the
+//! database, so instead of panicking we retry it after an exponential backoff.
+//! Every conflict is counted per operation in the
+//! `db_commit_conflicts_total` metric, which lets operators spot contention.
+
+use std::future::Future;
+use std::time::Duration;
+
+use fedimint_core::db::CommitConflictError;
+use fedimint_core::task::sleep;
+use fedimint_logging::LOG_DB;
+use fedimint_metrics::{lazy_static, opts, register_int_counter_vec, IntCounterVec};
+use rand::Rng;
+use tracing::{debug, warn};
+
+/// How long we wait before retrying after the first conflict
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Upper bound on the backoff, which doubles with every conflict
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How often [`retry_on_conflict`] attempts a transaction before giving up
+pub const MAX_ATTEMPTS: u32 = 10;
+
+lazy_static! {
+    static ref DB_COMMIT_CONFLICTS: IntCounterVec = register_int_counter_vec!(
+        opts!(
+            "db_commit_conflicts_total",
+            "Commits of database transactions that conflicted with a concurrent transaction"
+        ),
+        &["operation"]
+    )
+    .unwrap();
+}
+
+/// Backoff between the attempts of a transaction whose commit conflicted
+#[derive(Debug)]
+pub struct ConflictBackoff {
+    operation: &'static str,
+    conflicts: u32,
+}
+
+impl ConflictBackoff {
+    pub fn new(operation: &'static str) -> Self {
+        ConflictBackoff {
+            operation,
+            conflicts: 0,
+        }
+    }
+
+    /// The number of conflicts so far
+    pub fn conflicts(&self) -> u32 {
+        self.conflicts
+    }
+
+    /// Counts the conflict and waits before the next attempt, the jitter keeps
+    /// the conflicting writers from retrying in lockstep
+    pub async fn wait(&mut self) {
+        DB_COMMIT_CONFLICTS
+            .with_label_values(&[self.operation])
+            .inc();
+
+        let backoff = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.conflicts))
+            .min(MAX_BACKOFF);
+        let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64 / 2);
+
+        self.conflicts += 1;
+
+        debug!(
+            target: LOG_DB,
+            operation = self.operation,
+            conflicts = self.conflicts,
+            "Database commit conflicted, retrying"
+        );
+
+        sleep(backoff + Duration::from_millis(jitter)).await;
+    }
+}
+
+/// Runs a transaction, retrying it for as long as its commit conflicts, up to
+/// [`MAX_ATTEMPTS`] times
+///
+/// Every attempt has to begin a new database transaction. Any other error is
+/// returned as is.
+pub async fn retry_on_conflict<T, F, Fut>(
+    operation: &'static str,
+    mut attempt: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut backoff = ConflictBackoff::new(operation);
+
+    loop {
+        match attempt().await {
+            Err(e) if CommitConflictError::is_cause_of(&e) => {
+                if MAX_ATTEMPTS <= backoff.conflicts() + 1 {
+                    warn!(
+                        target: LOG_DB,
+                        operation,
+                        attempts = MAX_ATTEMPTS,
+                        "Giving up on a database transaction that keeps conflicting"
+                    );
+
+                    return Err(e);
+                }
+
+                backoff.wait().await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use anyhow::anyhow;
+    use fedimint_core::db::CommitConflictError;
+
+    use super::retry_on_conflict;
+
+    #[tokio::test]
+    async fn retries_conflicts_only() {
+        let attempts = AtomicU32::new(0);
+
+        let result = retry_on_conflict("test", || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(CommitConflictError("conflict".into()).into()),
+                attempt => Ok(attempt),
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 2);
+
+        let attempts = AtomicU32::new(0);
+
+        let result = retry_on_conflict("test", || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+
+            Err::<(), _>(anyhow!("other error"))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,12 +1,110 @@
+use std::collections::BTreeSet;
 use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 
+use anyhow::ensure;
+use fedimint_core::api::ConsensusItemLogging;
+use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::transaction::Transaction;
 
 use crate::ConsensusItem;
 
+/// Decides which consensus items are logged, shared by the consensus and the
+/// admin API
+#[derive(Debug, Clone, Default)]
+pub struct ItemLogFilter {
+    logging: Arc<RwLock<ConsensusItemLogging>>,
+    /// Number of items that passed the module filter so far
+    matched: Arc<AtomicU64>,
+}
+
+impl ItemLogFilter {
+    pub fn get(&self) -> ConsensusItemLogging {
+        self.logging.read().expect("Lock poisoned").clone()
+    }
+
+    pub fn set(&self, logging: ConsensusItemLogging) -> anyhow::Result<()> {
+        ensure!(
+            logging.sample_every != 0,
+            "Sample rate has to be at least 1"
+        );
+
+        *self.logging.write().expect("Lock poisoned") = logging;
+        self.matched.store(0, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    pub fn should_log(&self, item: &ConsensusItem) -> bool {
+        let logging = self.logging.read().expect("Lock poisoned");
+
+        if !logging.enabled {
+            return false;
+        }
+
+        if let Some(modules) = &logging.modules {
+            if !touches_modules(item, modules) {
+                return false;
+            }
+        }
+
+        self.matched.fetch_add(1, Ordering::Relaxed) % logging.sample_every == 0
+    }
+}
+
+fn touches_modules(item: &ConsensusItem, modules: &BTreeSet<ModuleInstanceId>) -> bool {
+    match item {
+        ConsensusItem::Module(mci) => modules.contains(&mci.module_instance_id()),
+        ConsensusItem::Transaction(Transaction {
+            inputs, outputs, ..
+        }) => inputs
+            .iter()
+            .map(|input| input.module_instance_id())
+            .chain(outputs.iter().map(|output| output.module_instance_id()))
+            .any(|id| modules.contains(&id)),
+        ConsensusItem::AddModule(proposal) => modules.contains(&proposal.module_instance_id),
+        ConsensusItem::ScheduleUpgrade(upgrade) => modules.contains(&upgrade.module_instance_id),
+        ConsensusItem::ClientConfigSignatureShare(_)
+        | ConsensusItem::FinalStateAttestationShare(_)
+        | ConsensusItem::KeyRotationDeal(_)
+        | ConsensusItem::KeyRotationConfirmation(_)
+        | ConsensusItem::ApiEndpointUpdate(_)
+        | ConsensusItem::FederationMetaShare(_)
+        | ConsensusItem::StateCommitment(_) => false,
+    }
+}
+
 pub fn item_message(item: &ConsensusItem) -> String {
     match item {
         ConsensusItem::ClientConfigSignatureShare(_) => "Client Config Signature".to_string(),
+        ConsensusItem::FinalStateAttestationShare(_) => "Final State Attestation".to_string(),
+        ConsensusItem::AddModule(proposal) => format!(
+            "Add Module: module={} kind={}",
+            proposal.module_instance_id, proposal.consensus.kind
+        ),
+        ConsensusItem::ScheduleUpgrade(upgrade) => format!(
+            "Schedule Upgrade: module={} version={} session={}",
+            upgrade.module_instance_id, upgrade.version.0, upgrade.activation_session
+        ),
+        ConsensusItem::KeyRotationDeal(deal) => {
+            format!("Key Rotation Deal: epoch={}", deal.epoch)
+        }
+        ConsensusItem::KeyRotationConfirmation(confirmation) => format!(
+            "Key Rotation Confirmation: epoch={} deals={}",
+            confirmation.epoch, confirmation.deals
+        ),
+        ConsensusItem::ApiEndpointUpdate(update) => format!(
+            "API Endpoint Update: peer={} sequence={} url={}",
+            update.peer, update.sequence, update.url.url
+        ),
+        ConsensusItem::FederationMetaShare(share) => {
+            format!("Federation Meta: version={}", share.meta.version)
+        }
+        ConsensusItem::StateCommitment(commitment) => format!(
+            "State Commitment: session={} entries={}",
+            commitment.session_index, commitment.entries
+        ),
         // TODO: make this nice again
         ConsensusItem::Module(mci) => {
             format!("Module CI: module={} ci={}", mci.module_instance_id(), mci)
@@ -26,3 +124,56 @@ pub fn item_message(item: &ConsensusItem) -> String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_core::api::ConsensusItemLogging;
+    use fedimint_core::transaction::Transaction;
+
+    use super::ItemLogFilter;
+    use crate::ConsensusItem;
+
+    fn empty_transaction() -> ConsensusItem {
+        ConsensusItem::Transaction(Transaction {
+            inputs: vec![],
+            outputs: vec![],
+            signature: None,
+        })
+    }
+
+    #[test]
+    fn samples_and_filters_items() {
+        let filter = ItemLogFilter::default();
+
+        assert!(filter.should_log(&empty_transaction()));
+
+        filter
+            .set(ConsensusItemLogging {
+                sample_every: 3,
+                ..ConsensusItemLogging::default()
+            })
+            .expect("Valid sample rate");
+
+        let logged = (0..9)
+            .filter(|_| filter.should_log(&empty_transaction()))
+            .count();
+        assert_eq!(logged, 3);
+
+        filter
+            .set(ConsensusItemLogging {
+                modules: Some(BTreeSet::from([0])),
+                ..ConsensusItemLogging::default()
+            })
+            .expect("Valid sample rate");
+        assert!(!filter.should_log(&empty_transaction()));
+
+        assert!(filter
+            .set(ConsensusItemLogging {
+                sample_every: 0,
+                ..ConsensusItemLogging::default()
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Panic boundaries around calls into server modules
+//!
+//! A bug in a single module should not take down the entire guardian. How a
+//! panic is handled depends on whether the call decides the consensus:
+//!
+//! * `process_consensus_item`, `process_input`, `process_output` and
+//!   `end_session` only depend on the ordered items, so every guardian panics
+//!   on the same item. The caller discards the writes of the call, and the
+//!   consensus halts the module by persisting the failure under
+//!   [`ModuleHaltKey`]. From then on every transaction using the module is
+//!   rejected, which all guardians agree on, also after a restart.
+//! * `consensus_proposal` and `audit` are local to this guardian, so a panic
+//!   must not change which items we accept. We stop making the call and report
+//!   the failure via the admin API, but keep processing the items of the
+//!   module like our peers do.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+use fedimint_core::api::ModuleFailure;
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::task::RwLock;
+use fedimint_core::transaction::{Transaction, TransactionRejection};
+use futures::{FutureExt, StreamExt};
+use thiserror::Error;
+use tracing::error;
+
+use crate::db::{ModuleHaltKey, ModuleHaltPrefix};
+use crate::LOG_CONSENSUS;
+
+/// A call into a module that panicked
+#[derive(Debug, Clone, Error)]
+#[error("Module {module_instance_id} panicked in {}: {}", failure.operation, failure.message)]
+pub struct ModulePanic {
+    pub module_instance_id: ModuleInstanceId,
+    pub failure: ModuleFailure,
+}
+
+/// Runs a call into a module, converting a panic into a [`ModulePanic`]
+///
+/// The database writes of a call that panicked have to be discarded by not
+/// committing the transaction, such that we do not end up with a partially
+/// applied state in the database.
+pub async fn catch_panic<F: Future>(
+    module_instance_id: ModuleInstanceId,
+    kind: &ModuleKind,
+    operation: &'static str,
+    session_index: Option<u64>,
+    call: F,
+) -> Result<F::Output, ModulePanic> {
+    AssertUnwindSafe(call)
+        .catch_unwind()
+        .await
+        .map_err(|payload| {
+            let message = panic_message(payload.as_ref());
+
+            error!(
+                target: LOG_CONSENSUS,
+                module_instance_id,
+                %kind,
+                operation,
+                %message,
+                "Module panicked. DO NOT IGNORE, FIX IT!!!"
+            );
+
+            ModulePanic {
+                module_instance_id,
+                failure: ModuleFailure {
+                    kind: kind.clone(),
+                    operation: operation.to_string(),
+                    message,
+                    session_index,
+                    halted: false,
+                },
+            }
+        })
+}
+
+/// Whether the consensus halted the module
+pub async fn is_halted(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_instance_id: ModuleInstanceId,
+) -> bool {
+    dbtx.get_value(&ModuleHaltKey(module_instance_id))
+        .await
+        .is_some()
+}
+
+/// Halts the module after a call deciding the consensus panicked
+pub async fn halt_module(dbtx: &mut DatabaseTransaction<'_>, panic: &ModulePanic) {
+    let failure = ModuleFailure {
+        halted: true,
+        ..panic.failure.clone()
+    };
+
+    dbtx.insert_entry(&ModuleHaltKey(panic.module_instance_id), &failure)
+        .await;
+}
+
+/// Rejects transactions with inputs or outputs belonging to a halted module,
+/// since the module can no longer process them
+pub async fn check_transaction(
+    dbtx: &mut DatabaseTransaction<'_>,
+    transaction: &Transaction,
+) -> Result<(), TransactionRejection> {
+    let module_instance_ids = transaction
+        .inputs
+        .iter()
+        .map(|input| input.module_instance_id())
+        .chain(
+            transaction
+                .outputs
+                .iter()
+                .map(|output| output.module_instance_id()),
+        );
+
+    for module_instance_id in module_instance_ids {
+        if is_halted(dbtx, module_instance_id).await {
+            return Err(TransactionRejection::ModuleHalted(module_instance_id));
+        }
+    }
+
+    Ok(())
+}
+
+/// The local calls into modules that panicked since the guardian was started,
+/// by module and operation
+#[derive(Debug, Clone, Default)]
+pub struct ModuleFailures(Arc<RwLock<BTreeMap<(ModuleInstanceId, String), ModuleFailure>>>);
+
+impl ModuleFailures {
+    /// Returns the modules halted by the consensus and the local failures of
+    /// the other modules
+    pub async fn get_all(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> BTreeMap<ModuleInstanceId, ModuleFailure> {
+        let mut failures = BTreeMap::new();
+
+        // the API reports a single failure per module, a halt takes precedence
+        for ((module_instance_id, _), failure) in self.0.read().await.iter() {
+            failures
+                .entry(*module_instance_id)
+                .or_insert_with(|| failure.clone());
+        }
+
+        failures.extend(
+            dbtx.find_by_prefix(&ModuleHaltPrefix)
+                .await
+                .map(|(key, failure)| (key.0, failure))
+                .collect::<Vec<_>>()
+                .await,
+        );
+
+        failures
+    }
+
+    /// Whether the local call into the module panicked before
+    pub async fn has_failed(&self, module_instance_id: ModuleInstanceId, operation: &str) -> bool {
+        self.0
+            .read()
+            .await
+            .contains_key(&(module_instance_id, operation.to_string()))
+    }
+
+    /// Runs a local call into a module, which only affects this guardian
+    ///
+    /// Returns `None` if the call panics, in which case the failure is
+    /// reported via the admin API. The caller should not make the call again,
+    /// see [`Self::has_failed`].
+    pub async fn call_local<F: Future>(
+        &self,
+        module_instance_id: ModuleInstanceId,
+        kind: &ModuleKind,
+        operation: &'static str,
+        session_index: Option<u64>,
+        call: F,
+    ) -> Option<F::Output> {
+        match catch_panic(module_instance_id, kind, operation, session_index, call).await {
+            Ok(output) => Some(output),
+            Err(panic) => {
+                self.0
+                    .write()
+                    .await
+                    .entry((module_instance_id, operation.to_string()))
+                    .or_insert(panic.failure);
+
+                None
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::core::{DynOutput, ModuleKind};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::transaction::{Transaction, TransactionRejection};
+    use fedimint_core::Amount;
+    use fedimint_dummy_common::{fed_public_key, DummyOutput};
+
+    use super::{catch_panic, check_transaction, halt_module, is_halted, ModuleFailures};
+
+    #[tokio::test]
+    async fn consensus_panic_halts_module_persistently() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let kind = ModuleKind::from_static_str("dummy");
+
+        let output = catch_panic(0, &kind, "process_input", Some(3), async { 42 }).await;
+        assert_eq!(output.unwrap(), 42);
+
+        let panic = catch_panic(1, &kind, "process_consensus_item", Some(3), async {
+            panic!("module bug")
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(panic.failure.message, "module bug");
+
+        let mut dbtx = db.begin_transaction().await;
+        halt_module(&mut dbtx, &panic).await;
+        dbtx.commit_tx().await;
+
+        // the halt survives a restart since it is read from the database
+        let mut dbtx = db.begin_transaction().await;
+        assert!(!is_halted(&mut dbtx, 0).await);
+        assert!(is_halted(&mut dbtx, 1).await);
+
+        let transaction = Transaction {
+            inputs: vec![],
+            outputs: vec![DynOutput::from_typed(
+                1,
+                DummyOutput {
+                    amount: Amount::ZERO,
+                    account: fed_public_key(),
+                },
+            )],
+            signature: None,
+        };
+        assert_eq!(
+            check_transaction(&mut dbtx, &transaction).await,
+            Err(TransactionRejection::ModuleHalted(1))
+        );
+
+        let failure = ModuleFailures::default()
+            .get_all(&mut dbtx)
+            .await
+            .remove(&1)
+            .unwrap();
+        assert!(failure.halted);
+        assert_eq!(failure.operation, "process_consensus_item");
+        assert_eq!(failure.session_index, Some(3));
+    }
+
+    #[tokio::test]
+    async fn local_panic_is_only_reported() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let kind = ModuleKind::from_static_str("dummy");
+        let failures = ModuleFailures::default();
+
+        let output = failures
+            .call_local(1, &kind, "consensus_proposal", None, async {
+                panic!("module bug")
+            })
+            .await;
+        assert!(output.is_none());
+        assert!(failures.has_failed(1, "consensus_proposal").await);
+        assert!(!failures.has_failed(1, "audit").await);
+
+        // the module keeps processing consensus items
+        let mut dbtx = db.begin_transaction().await;
+        assert!(!is_halted(&mut dbtx, 1).await);
+
+        let failure = failures.get_all(&mut dbtx).await.remove(&1).unwrap();
+        assert!(!failure.halted);
+        assert_eq!(failure.message, "module bug");
+    }
+}
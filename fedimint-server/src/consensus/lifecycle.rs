@@ -0,0 +1,578 @@
+//! Adding module instances to a running federation, see
+//! [`fedimint_core::lifecycle`]
+//!
+//! The proposing guardian generates the new module config as a trusted dealer,
+//! so unlike the distributed config generation during setup it learns the
+//! private module config of every guardian. Admins should therefore only
+//! approve modules proposed by a guardian they trust with the secrets of the
+//! new module instance.
+//!
+//! Module upgrades scheduled by all guardians are activated here as well,
+//! before the consensus is restarted for their activation session, and so are
+//! the module configs their modules require to be replaced, e.g. after the
+//! mint generated the keys for new note tiers.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, ensure};
+use fedimint_aead::{decrypt, encrypt, LessSafeKey, UnboundKey, CHACHA20_POLY1305};
+use fedimint_core::config::{ConfigGenModuleParams, ServerModuleConfig, ServerModuleInitRegistry};
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+use fedimint_core::db::{
+    Database, DatabaseTransaction, DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::lifecycle::{AddModuleProposal, ModuleUpgrade};
+use fedimint_core::module::ModuleConsensusVersion;
+use fedimint_core::PeerId;
+use futures::StreamExt;
+use secp256k1_zkp::ecdh::SharedSecret;
+use tracing::info;
+
+use crate::config::io::rewrite_server_config;
+use crate::config::ServerConfig;
+use crate::db::{
+    ActiveModuleVersionKey, ApprovedModulePrefix, ApprovedUpgradeKey, ClientConfigSignatureKey,
+    ClientConfigSignatureSharePrefix, PendingModuleKey, ScheduledUpgradeKey,
+    ScheduledUpgradePrefix, SignedBlockPrefix,
+};
+use crate::LOG_CONSENSUS;
+
+/// Generates the module config of every guardian for a new instance of the
+/// given module kind, using the lowest unused module instance id
+pub fn propose_module(
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+    kind: &ModuleKind,
+    params: &ConfigGenModuleParams,
+) -> anyhow::Result<AddModuleProposal> {
+    let init = module_inits
+        .get(kind)
+        .ok_or_else(|| anyhow!("Module kind {kind} is not supported"))?;
+
+    let module_instance_id = cfg.consensus.modules.keys().max().map_or(0, |id| id + 1);
+
+    let peers = cfg
+        .consensus
+        .broadcast_public_keys
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+
+    let configs = init.trusted_dealer_gen(&peers, params);
+
+    let consensus = configs
+        .get(&cfg.local.identity)
+        .ok_or_else(|| anyhow!("Module {kind} generated no config for us"))?
+        .consensus
+        .clone();
+
+    let peer_configs = configs
+        .into_iter()
+        .map(|(peer, config)| {
+            let key = peer_key(cfg, peer)?;
+
+            Ok((peer, encrypt(serde_json::to_vec(&config)?, &key)?))
+        })
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+    Ok(AddModuleProposal {
+        proposer: cfg.local.identity,
+        module_instance_id,
+        consensus,
+        peer_configs,
+    })
+}
+
+/// Checks a proposal ordered by consensus, which needs to be deterministic
+/// since all guardians have to accept the same consensus items
+pub fn check_proposal(cfg: &ServerConfig, proposal: &AddModuleProposal) -> anyhow::Result<()> {
+    let peers = cfg
+        .consensus
+        .broadcast_public_keys
+        .keys()
+        .collect::<BTreeSet<_>>();
+
+    ensure!(
+        peers.contains(&proposal.proposer),
+        "The proposer is not a guardian"
+    );
+
+    ensure!(
+        !cfg.consensus
+            .modules
+            .contains_key(&proposal.module_instance_id),
+        "Module instance id {} is already in use",
+        proposal.module_instance_id
+    );
+
+    ensure!(
+        proposal.peer_configs.keys().collect::<BTreeSet<_>>() == peers,
+        "The proposal does not contain a module config for every guardian"
+    );
+
+    Ok(())
+}
+
+/// Decrypts and validates our module config from a proposal, which we do
+/// before our admin may approve it
+pub fn our_module_config(
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+    proposal: &AddModuleProposal,
+) -> anyhow::Result<ServerModuleConfig> {
+    check_proposal(cfg, proposal)?;
+
+    let mut ciphertext = proposal
+        .peer_configs
+        .get(&cfg.local.identity)
+        .ok_or_else(|| anyhow!("The proposal contains no module config for us"))?
+        .clone();
+
+    let key = peer_key(cfg, proposal.proposer)?;
+    let config: ServerModuleConfig = serde_json::from_slice(decrypt(&mut ciphertext, &key)?)?;
+
+    ensure!(
+        config.consensus == proposal.consensus,
+        "Our module config does not match the proposed consensus config"
+    );
+
+    let kind = &config.consensus.kind;
+    let init = module_inits
+        .get(kind)
+        .ok_or_else(|| anyhow!("Module kind {kind} is not supported"))?;
+
+    init.validate_config(&cfg.local.identity, config.clone())?;
+
+    Ok(config)
+}
+
+/// Our config with the module instance of the proposal added
+pub fn config_with_module(
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+    proposal: &AddModuleProposal,
+) -> anyhow::Result<ServerConfig> {
+    let module_cfg = our_module_config(cfg, module_inits, proposal)?;
+
+    let mut cfg = cfg.clone();
+
+    cfg.add_modules(BTreeMap::from([(proposal.module_instance_id, module_cfg)]));
+    cfg.validate_config(&cfg.local.identity, module_inits)?;
+
+    Ok(cfg)
+}
+
+/// Adds the module instance approved by all guardians to our config files if
+/// there is one, which requires restarting the consensus with the returned
+/// config
+pub async fn add_pending_module(
+    db: &Database,
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+    data_dir: PathBuf,
+) -> anyhow::Result<Option<ServerConfig>> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let Some(proposal) = dbtx.get_value(&PendingModuleKey).await else {
+        return Ok(None);
+    };
+
+    // we may have crashed after writing the config files in a previous attempt
+    let cfg = if cfg
+        .consensus
+        .modules
+        .contains_key(&proposal.module_instance_id)
+    {
+        cfg.clone()
+    } else {
+        let updated = config_with_module(cfg, module_inits, &proposal)?;
+
+        rewrite_server_config(&updated, data_dir, &cfg.private.api_auth.0, module_inits)?;
+
+        updated
+    };
+
+    dbtx.remove_entry(&PendingModuleKey).await;
+
+    // approvals of our admin for module instance ids that are now in use are void
+    let approved = dbtx
+        .find_by_prefix(&ApprovedModulePrefix)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    for (key, approved) in approved {
+        if cfg
+            .consensus
+            .modules
+            .contains_key(&approved.module_instance_id)
+        {
+            dbtx.remove_entry(&key).await;
+        }
+    }
+
+    dbtx.commit_tx_result().await?;
+
+    info!(
+        target: LOG_CONSENSUS,
+        module_instance_id = proposal.module_instance_id,
+        kind = %proposal.consensus.kind,
+        "Added module instance approved by all guardians"
+    );
+
+    Ok(Some(cfg))
+}
+
+/// The consensus version a module instance currently runs with
+pub async fn active_version(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    cfg: &ServerConfig,
+    module_instance_id: ModuleInstanceId,
+) -> anyhow::Result<ModuleConsensusVersion> {
+    if let Some(version) = dbtx
+        .get_value(&ActiveModuleVersionKey(module_instance_id))
+        .await
+    {
+        return Ok(version);
+    }
+
+    cfg.consensus
+        .modules
+        .get(&module_instance_id)
+        .map(|module| module.version)
+        .ok_or_else(|| anyhow!("Module instance {module_instance_id} does not exist"))
+}
+
+/// Checks a module upgrade before it is scheduled for the given session or
+/// later, which needs to be deterministic since all guardians have to accept
+/// the same consensus items
+pub async fn check_upgrade(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    cfg: &ServerConfig,
+    upgrade: &ModuleUpgrade,
+    session_index: u64,
+) -> anyhow::Result<()> {
+    let active = active_version(dbtx, cfg, upgrade.module_instance_id).await?;
+
+    ensure!(
+        active < upgrade.version,
+        "Module instance {} already runs consensus version {}",
+        upgrade.module_instance_id,
+        active.0
+    );
+
+    ensure!(
+        session_index < upgrade.activation_session,
+        "The activation session {} has already started",
+        upgrade.activation_session
+    );
+
+    ensure!(
+        dbtx.get_value(&ScheduledUpgradeKey(upgrade.module_instance_id))
+            .await
+            .is_none(),
+        "Another upgrade of module instance {} is already scheduled",
+        upgrade.module_instance_id
+    );
+
+    Ok(())
+}
+
+/// Whether our binary is able to run the module instance with the given
+/// consensus version
+pub fn supports_version(
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+    module_instance_id: ModuleInstanceId,
+    version: ModuleConsensusVersion,
+) -> bool {
+    cfg.consensus
+        .modules
+        .get(&module_instance_id)
+        .and_then(|module| module_inits.get(&module.kind))
+        .map_or(false, |init| {
+            init.versions(cfg.consensus.version).contains(&version)
+        })
+}
+
+/// The scheduled upgrades that have to be activated before we may run the
+/// given session
+pub async fn due_upgrades(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    session_index: u64,
+) -> Vec<ModuleUpgrade> {
+    dbtx.find_by_prefix(&ScheduledUpgradePrefix)
+        .await
+        .map(|(_, upgrade)| upgrade)
+        .filter(|upgrade| std::future::ready(upgrade.activation_session <= session_index))
+        .collect()
+        .await
+}
+
+/// Activates the module upgrades scheduled for the next session, which
+/// requires restarting the consensus such that the modules are initialized
+/// with their new version. Fails if our binary does not support a new version,
+/// since we must not run the activation session without it.
+pub async fn activate_due_upgrades(
+    db: &Database,
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let session_index = dbtx.find_by_prefix(&SignedBlockPrefix).await.count().await as u64;
+
+    for upgrade in due_upgrades(&mut dbtx.dbtx_ref(), session_index).await {
+        let module_instance_id = upgrade.module_instance_id;
+        let kind = &cfg.consensus.modules[&module_instance_id].kind;
+
+        ensure!(
+            supports_version(cfg, module_inits, module_instance_id, upgrade.version),
+            "Module instance {module_instance_id} ({kind}) activates consensus version {} in \
+             session {}, which this binary does not support. Upgrade to a release supporting \
+             it to continue running the consensus.",
+            upgrade.version.0,
+            upgrade.activation_session
+        );
+
+        let active = active_version(&mut dbtx.dbtx_ref(), cfg, module_instance_id).await?;
+
+        module_inits
+            .get(kind)
+            .expect("Support was checked above")
+            .migrate_consensus_version(
+                &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id),
+                active,
+                upgrade.version,
+            )
+            .await?;
+
+        dbtx.insert_entry(
+            &ActiveModuleVersionKey(module_instance_id),
+            &upgrade.version,
+        )
+        .await;
+        dbtx.remove_entry(&ScheduledUpgradeKey(module_instance_id))
+            .await;
+        dbtx.remove_entry(&ApprovedUpgradeKey(upgrade)).await;
+
+        info!(
+            target: LOG_CONSENSUS,
+            module_instance_id,
+            %kind,
+            from = active.0,
+            to = upgrade.version.0,
+            "Activated module consensus version"
+        );
+    }
+
+    dbtx.commit_tx_result().await
+}
+
+/// The module configs that have to replace ours before the next session, see
+/// [`ServerModuleInit::update_config`](fedimint_core::module::ServerModuleInit::update_config)
+pub async fn module_config_updates(
+    dbtx: &mut DatabaseTransaction<'_>,
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+) -> anyhow::Result<BTreeMap<ModuleInstanceId, ServerModuleConfig>> {
+    let mut updates = BTreeMap::new();
+
+    for (module_instance_id, module) in &cfg.consensus.modules {
+        let init = module_inits
+            .get(&module.kind)
+            .ok_or_else(|| anyhow!("Module kind {} is not supported", module.kind))?;
+
+        let module_cfg = cfg.get_module_config(*module_instance_id)?;
+
+        if let Some(updated) = init
+            .update_config(
+                &mut dbtx.dbtx_ref_with_prefix_module_id(*module_instance_id),
+                &cfg.local.identity,
+                &module_cfg,
+            )
+            .await?
+        {
+            updates.insert(*module_instance_id, updated);
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Replaces the module configs their modules require to be replaced in our
+/// config files, which requires restarting the consensus with the returned
+/// config
+pub async fn apply_module_config_updates(
+    db: &Database,
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+    data_dir: PathBuf,
+) -> anyhow::Result<Option<ServerConfig>> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let updates = module_config_updates(&mut dbtx, cfg, module_inits).await?;
+
+    if updates.is_empty() {
+        return Ok(None);
+    }
+
+    let module_instance_ids = updates.keys().copied().collect::<Vec<_>>();
+
+    let mut updated = cfg.clone();
+
+    updated.add_modules(updates);
+    updated.validate_config(&updated.local.identity, module_inits)?;
+
+    rewrite_server_config(&updated, data_dir, &cfg.private.api_auth.0, module_inits)?;
+
+    // the client config contains the module configs, so the guardians have to
+    // sign it again
+    dbtx.remove_entry(&ClientConfigSignatureKey).await;
+    dbtx.remove_by_prefix(&ClientConfigSignatureSharePrefix)
+        .await;
+
+    dbtx.commit_tx_result().await?;
+
+    info!(
+        target: LOG_CONSENSUS,
+        ?module_instance_ids,
+        "Replaced the module configs required by their modules"
+    );
+
+    Ok(Some(updated))
+}
+
+/// The key shared by us and the given guardian via ECDH of our broadcast keys
+pub(crate) fn peer_key(cfg: &ServerConfig, peer: PeerId) -> anyhow::Result<LessSafeKey> {
+    let public_key = cfg
+        .consensus
+        .broadcast_public_keys
+        .get(&peer)
+        .ok_or_else(|| anyhow!("Peer {peer} is not a guardian"))?;
+
+    let secret = SharedSecret::new(public_key, &cfg.private.broadcast_secret_key);
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &secret.secret_bytes())
+        .map_err(|_| anyhow!("Unable to create key"))?;
+
+    Ok(LessSafeKey::new(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_core::config::{ConfigGenModuleParams, ServerModuleInitRegistry};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+    use fedimint_core::lifecycle::ModuleUpgrade;
+    use fedimint_core::module::{DynServerModuleInit, ModuleConsensusVersion};
+    use fedimint_core::PeerId;
+    use fedimint_dummy_common::config::DummyGenParams;
+    use fedimint_dummy_server::DummyGen;
+
+    use super::{
+        activate_due_upgrades, active_version, check_proposal, check_upgrade, config_with_module,
+        our_module_config, propose_module,
+    };
+    use crate::config::ServerConfig;
+    use crate::db::ScheduledUpgradeKey;
+    use crate::simulation::config_gen_params;
+
+    #[test]
+    fn every_guardian_decrypts_its_module_config() {
+        let peers = (0..4).map(PeerId::from).collect::<BTreeSet<_>>();
+        let registry = ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]);
+        let cfgs = ServerConfig::trusted_dealer_gen(&config_gen_params(&peers), registry.clone());
+
+        let params =
+            ConfigGenModuleParams::from_typed(DummyGenParams::default()).expect("Valid params");
+        let proposal = propose_module(
+            &cfgs[&PeerId::from(0)],
+            &registry,
+            &DummyGen::kind(),
+            &params,
+        )
+        .expect("Dummy module is supported");
+
+        assert_eq!(proposal.module_instance_id, 1);
+
+        for cfg in cfgs.values() {
+            let module_cfg =
+                our_module_config(cfg, &registry, &proposal).expect("Config is encrypted to us");
+            let cfg_with_module =
+                config_with_module(cfg, &registry, &proposal).expect("Config is valid");
+
+            assert_eq!(module_cfg.consensus, proposal.consensus);
+            assert!(check_proposal(&cfg_with_module, &proposal).is_err());
+        }
+
+        let mut tampered = proposal.clone();
+        tampered.proposer = PeerId::from(1);
+
+        assert!(our_module_config(&cfgs[&PeerId::from(2)], &registry, &tampered).is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_to_activate_unsupported_upgrades() {
+        let peers = BTreeSet::from([PeerId::from(0)]);
+        let registry = ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]);
+        let cfg = ServerConfig::trusted_dealer_gen(&config_gen_params(&peers), registry.clone())
+            .remove(&PeerId::from(0))
+            .expect("Config for our peer");
+        let db = MemDatabase::new().into_database();
+
+        let upgrade = ModuleUpgrade {
+            module_instance_id: 0,
+            version: ModuleConsensusVersion(1),
+            activation_session: 1,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+
+        assert!(check_upgrade(&mut dbtx.dbtx_ref(), &cfg, &upgrade, 0)
+            .await
+            .is_ok());
+        assert!(check_upgrade(&mut dbtx.dbtx_ref(), &cfg, &upgrade, 1)
+            .await
+            .is_err());
+
+        let downgrade = ModuleUpgrade {
+            version: ModuleConsensusVersion(0),
+            ..upgrade
+        };
+
+        assert!(check_upgrade(&mut dbtx.dbtx_ref(), &cfg, &downgrade, 0)
+            .await
+            .is_err());
+
+        dbtx.insert_entry(&ScheduledUpgradeKey(0), &upgrade).await;
+        dbtx.commit_tx().await;
+
+        // the upgrade is not due before its activation session
+        activate_due_upgrades(&db, &cfg, &registry)
+            .await
+            .expect("No upgrade is due");
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &ScheduledUpgradeKey(0),
+            &ModuleUpgrade {
+                activation_session: 0,
+                ..upgrade
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        // the dummy module only supports its initial consensus version
+        assert!(activate_due_upgrades(&db, &cfg, &registry).await.is_err());
+        assert_eq!(
+            active_version(&mut db.begin_transaction().await.dbtx_ref(), &cfg, 0)
+                .await
+                .expect("Module exists"),
+            ModuleConsensusVersion(0)
+        );
+    }
+}
@@ -1,83 +1,241 @@
 #![allow(clippy::let_unit_value)]
 
+pub mod conflict;
 pub mod debug;
+pub mod isolation;
+pub mod lifecycle;
+pub mod proposal;
+pub mod rotation;
+pub mod safe_mode;
+pub mod safety_halt;
 pub mod server;
+pub mod state_snapshot;
+pub mod watchdog;
 
-use fedimint_core::db::DatabaseTransaction;
+use std::collections::BTreeMap;
+
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::fee::{FeeIncome, FeeSchedule};
+use fedimint_core::module::audit::Audit;
 use fedimint_core::module::registry::ServerModuleRegistry;
-use fedimint_core::module::TransactionItemAmount;
-use fedimint_core::transaction::{Transaction, TransactionError};
+use fedimint_core::module::{InputMeta, TransactionItemAmount};
+use fedimint_core::transaction::{
+    Transaction, TransactionError, TransactionLimits, TransactionRejection,
+};
 use fedimint_core::{Amount, OutPoint};
 
+use crate::consensus::isolation::catch_panic;
+use crate::db::FeeIncomeKey;
+
+/// Processes the inputs and outputs of a transaction ordered by consensus
+///
+/// Fails with a [`TransactionRejection`] if the transaction is invalid or with
+/// a [`ModulePanic`](isolation::ModulePanic) if a module panicked while processing it, in which case
+/// the caller has to halt the module.
 pub async fn process_transaction_with_dbtx(
     modules: ServerModuleRegistry,
     dbtx: &mut DatabaseTransaction<'_>,
+    session_index: u64,
     transaction: Transaction,
+    limits: &TransactionLimits,
+    fees: &FeeSchedule,
 ) -> anyhow::Result<()> {
+    limits
+        .check(&transaction)
+        .map_err(TransactionRejection::from)?;
+
     let txid = transaction.tx_hash();
     let mut funding_verifier = FundingVerifier::default();
     let mut public_keys = Vec::new();
 
-    for input in transaction.inputs.iter() {
-        let meta = modules
-            .get_expect(input.module_instance_id())
-            .process_input(
-                &mut dbtx.dbtx_ref_with_prefix_module_id(input.module_instance_id()),
+    for (input, index) in transaction.inputs.iter().zip(0u64..) {
+        let module_instance_id = input.module_instance_id();
+        let (kind, module) = modules
+            .get_with_kind(module_instance_id)
+            .expect("Decoding rejects unknown module instance ids");
+
+        let meta = catch_panic(
+            module_instance_id,
+            kind,
+            "process_input",
+            Some(session_index),
+            module.process_input(
+                &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id),
                 input,
-            )
-            .await?;
+            ),
+        )
+        .await?
+        .map_err(|error| TransactionRejection::InvalidInput {
+            index,
+            module_instance_id,
+            reason: error.to_string(),
+        })?;
 
-        funding_verifier.add_input(meta.amount);
+        funding_verifier.add_input(module_instance_id, &meta);
         public_keys.push(meta.pub_keys);
     }
 
-    transaction.validate_signature(public_keys.into_iter().flatten())?;
+    transaction
+        .validate_signature(public_keys.into_iter().flatten())
+        .map_err(TransactionRejection::from)?;
 
     for (output, out_idx) in transaction.outputs.iter().zip(0u64..) {
-        let amount = modules
-            .get_expect(output.module_instance_id())
-            .process_output(
-                &mut dbtx.dbtx_ref_with_prefix_module_id(output.module_instance_id()),
+        let module_instance_id = output.module_instance_id();
+        let (kind, module) = modules
+            .get_with_kind(module_instance_id)
+            .expect("Decoding rejects unknown module instance ids");
+
+        let amount = catch_panic(
+            module_instance_id,
+            kind,
+            "process_output",
+            Some(session_index),
+            module.process_output(
+                &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id),
                 output,
                 OutPoint { txid, out_idx },
-            )
-            .await?;
+            ),
+        )
+        .await?
+        .map_err(|error| TransactionRejection::InvalidOutput {
+            index: out_idx,
+            module_instance_id,
+            reason: error.to_string(),
+        })?;
+
+        funding_verifier.add_output(module_instance_id, amount);
+    }
+
+    let fee_income = funding_verifier
+        .verify_funding(fees)
+        .map_err(TransactionRejection::from)?;
+
+    record_fee_income(dbtx, fee_income)
+        .await
+        .map_err(TransactionRejection::from)?;
+
+    Ok(())
+}
 
-        funding_verifier.add_output(amount);
+/// Adds the fee income that is not paid out yet to the audit as a liability,
+/// since the federation owes it to the guardians
+pub async fn audit_fee_income(dbtx: &mut DatabaseTransaction<'_>, audit: &mut Audit) {
+    let income = dbtx.get_value(&FeeIncomeKey).await.unwrap_or_default();
+
+    audit.add_federation_item(
+        "Fee income owed to guardians".to_string(),
+        -(income.available().msats as i64),
+    );
+}
+
+/// Adds the fees collected and paid out by a transaction to the fee income of
+/// the federation, failing if the transaction pays out more than is available
+pub async fn record_fee_income(
+    dbtx: &mut DatabaseTransaction<'_>,
+    transaction_income: FeeIncome,
+) -> Result<(), TransactionError> {
+    let mut income = dbtx.get_value(&FeeIncomeKey).await.unwrap_or_default();
+
+    if transaction_income.paid_out > income.available() {
+        return Err(TransactionError::InsufficientFeeIncome {
+            payout: transaction_income.paid_out,
+            available: income.available(),
+        });
     }
 
-    funding_verifier.verify_funding()?;
+    income.collected += transaction_income.collected;
+    income.paid_out += transaction_income.paid_out;
+
+    dbtx.insert_entry(&FeeIncomeKey, &income).await;
 
     Ok(())
 }
 
+/// The outputs of earlier transactions spent by the inputs of the transaction,
+/// has to be called before the transaction is processed
+pub async fn transaction_dependencies(
+    modules: &ServerModuleRegistry,
+    dbtx: &mut DatabaseTransaction<'_>,
+    transaction: &Transaction,
+) -> Vec<OutPoint> {
+    let mut upstream = vec![];
+
+    for input in &transaction.inputs {
+        let module_instance_id = input.module_instance_id();
+
+        upstream.extend(
+            modules
+                .get_expect(module_instance_id)
+                .input_dependencies(
+                    &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id),
+                    input,
+                )
+                .await,
+        );
+    }
+
+    upstream
+}
+
 pub struct FundingVerifier {
     input_amount: Amount,
     output_amount: Amount,
     fee_amount: Amount,
+    fee_payout: Amount,
+    module_inputs: BTreeMap<ModuleInstanceId, Amount>,
+    module_outputs: BTreeMap<ModuleInstanceId, Amount>,
 }
 
 impl FundingVerifier {
-    pub fn add_input(&mut self, input_amount: TransactionItemAmount) {
-        self.input_amount += input_amount.amount;
-        self.fee_amount += input_amount.fee;
+    pub fn add_input(&mut self, module_instance_id: ModuleInstanceId, meta: &InputMeta) {
+        self.input_amount += meta.amount.amount;
+        self.fee_amount += meta.amount.fee;
+        self.fee_payout += meta.fee_payout;
+        *self
+            .module_inputs
+            .entry(module_instance_id)
+            .or_insert(Amount::ZERO) += meta.amount.amount;
     }
 
-    pub fn add_output(&mut self, output_amount: TransactionItemAmount) {
+    pub fn add_output(
+        &mut self,
+        module_instance_id: ModuleInstanceId,
+        output_amount: TransactionItemAmount,
+    ) {
         self.output_amount += output_amount.amount;
         self.fee_amount += output_amount.fee;
+        *self
+            .module_outputs
+            .entry(module_instance_id)
+            .or_insert(Amount::ZERO) += output_amount.amount;
     }
 
-    pub fn verify_funding(self) -> Result<(), TransactionError> {
-        if self.input_amount == (self.output_amount + self.fee_amount) {
-            Ok(())
-        } else {
-            Err(TransactionError::UnbalancedTransaction {
+    /// Verifies that the inputs fund exactly the outputs, the fees of the
+    /// modules and the fees of the schedule, returning the fee income of the
+    /// transaction.
+    ///
+    /// The fees of the modules are left to the modules, the fee income consists
+    /// of the fees of the schedule. A transaction may only be overfunded by
+    /// [`FeeSchedule::max_change_residue`], since clients can not create change
+    /// for amounts smaller than its fees, which is collected as well.
+    pub fn verify_funding(self, fees: &FeeSchedule) -> Result<FeeIncome, TransactionError> {
+        let fee_amount =
+            self.fee_amount + fees.transaction_fee(&self.module_inputs, &self.module_outputs);
+        let funded = self.output_amount + fee_amount;
+
+        if self.input_amount < funded || self.input_amount - funded > fees.max_change_residue() {
+            return Err(TransactionError::UnbalancedTransaction {
                 inputs: self.input_amount,
                 outputs: self.output_amount,
-                fee: self.fee_amount,
-            })
+                fee: fee_amount,
+            });
         }
+
+        Ok(FeeIncome {
+            collected: self.input_amount - self.output_amount - self.fee_amount,
+            paid_out: self.fee_payout,
+        })
     }
 }
 
@@ -87,6 +245,121 @@ impl Default for FundingVerifier {
             input_amount: Amount::ZERO,
             output_amount: Amount::ZERO,
             fee_amount: Amount::ZERO,
+            fee_payout: Amount::ZERO,
+            module_inputs: BTreeMap::new(),
+            module_outputs: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::fee::{FeeIncome, FeeRate, FeeSchedule, ModuleFeeSchedule};
+    use fedimint_core::module::{InputMeta, TransactionItemAmount};
+    use fedimint_core::Amount;
+
+    use super::FundingVerifier;
+
+    fn input(amount: u64, fee_payout: u64) -> InputMeta {
+        InputMeta {
+            amount: TransactionItemAmount {
+                amount: Amount::from_msats(amount),
+                fee: Amount::from_msats(10),
+            },
+            pub_keys: vec![],
+            fee_payout: Amount::from_msats(fee_payout),
         }
     }
+
+    fn output(amount: u64) -> TransactionItemAmount {
+        TransactionItemAmount {
+            amount: Amount::from_msats(amount),
+            fee: Amount::ZERO,
+        }
+    }
+
+    #[test]
+    fn verify_funding_charges_fee_schedule() {
+        let fees = FeeSchedule {
+            modules: BTreeMap::from([(
+                0,
+                ModuleFeeSchedule {
+                    input: FeeRate {
+                        base: Amount::from_msats(100),
+                        parts_per_million: 10_000,
+                    },
+                    output: FeeRate::ZERO,
+                },
+            )]),
+        };
+
+        // the input pays the module fee of 10 and the scheduled fee of 110
+        let mut verifier = FundingVerifier::default();
+        verifier.add_input(0, &input(1_000, 0));
+        verifier.add_output(1, output(880));
+        assert_eq!(
+            verifier.verify_funding(&fees).unwrap(),
+            FeeIncome {
+                collected: Amount::from_msats(110),
+                paid_out: Amount::ZERO,
+            }
+        );
+
+        let mut verifier = FundingVerifier::default();
+        verifier.add_input(0, &input(1_000, 0));
+        verifier.add_output(1, output(881));
+        assert!(verifier.verify_funding(&fees).is_err());
+
+        // without output fees the transaction has to be balanced exactly
+        let mut verifier = FundingVerifier::default();
+        verifier.add_input(1, &input(1_000, 1_000));
+        verifier.add_output(1, output(980));
+        assert!(verifier.verify_funding(&fees).is_err());
+
+        let mut verifier = FundingVerifier::default();
+        verifier.add_input(1, &input(1_000, 1_000));
+        verifier.add_output(1, output(990));
+        assert_eq!(
+            verifier.verify_funding(&fees).unwrap(),
+            FeeIncome {
+                collected: Amount::ZERO,
+                paid_out: Amount::from_msats(1_000),
+            }
+        );
+    }
+
+    #[test]
+    fn verify_funding_allows_change_residue() {
+        let fees = FeeSchedule {
+            modules: BTreeMap::from([(
+                0,
+                ModuleFeeSchedule {
+                    input: FeeRate::ZERO,
+                    output: FeeRate {
+                        base: Amount::from_msats(5),
+                        parts_per_million: 0,
+                    },
+                },
+            )]),
+        };
+
+        // the residue of 5 msats is too small for change and collected as fees
+        let mut verifier = FundingVerifier::default();
+        verifier.add_input(1, &input(1_000, 0));
+        verifier.add_output(1, output(985));
+        assert_eq!(
+            verifier.verify_funding(&fees).unwrap(),
+            FeeIncome {
+                collected: Amount::from_msats(5),
+                paid_out: Amount::ZERO,
+            }
+        );
+
+        let mut verifier = FundingVerifier::default();
+        verifier.add_input(1, &input(1_000, 0));
+        verifier.add_output(1, output(984));
+        assert!(verifier.verify_funding(&fees).is_err());
+    }
 }
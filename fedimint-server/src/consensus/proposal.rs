@@ -0,0 +1,304 @@
+//! Scheduling of the consensus proposals of the modules
+//!
+//! If every guardian proposed the pending items of every module at the same
+//! moment, items of which a threshold suffices, like the signature shares of a
+//! peg-out, would be ordered once per guardian. Therefore every guardian delays
+//! the proposals of a module by a jitter derived from its peer id, bounded by
+//! the module's entry in
+//! [`ServerConfigLocal::proposal_jitter_ms`](crate::config::ServerConfigLocal),
+//! and only the round-robin leaders of the session, a threshold of guardians,
+//! propose the items a module assigns a
+//! [class](fedimint_core::module::ServerModule::consensus_item_class). Since a
+//! leader may be offline, every guardian proposes an item that is still pending
+//! after [`LEADER_TIMEOUT`], so the schedule never changes which items are
+//! ordered eventually.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use async_channel::Sender;
+use bitcoin_hashes::sha256;
+use fedimint_core::core::{DynModuleConsensusItem, ModuleInstanceId};
+use fedimint_core::db::Database;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::module::registry::ServerModuleRegistry;
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::{NumPeers, PeerId};
+
+use crate::config::ServerConfig;
+use crate::consensus::isolation::ModuleFailures;
+use crate::consensus::watchdog::SessionProgress;
+
+/// How often we propose the pending items of a module
+const PROPOSAL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long an item of a class may stay pending before we propose it even
+/// though we are not a leader of its class
+pub const LEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The delay of our proposals of the module, which is the same every time we
+/// start such that the guardians stay de-synchronized
+pub fn proposal_jitter(
+    peer: PeerId,
+    module_instance_id: ModuleInstanceId,
+    max_jitter: Duration,
+) -> Duration {
+    let max_millis = max_jitter.as_millis() as u64;
+
+    if max_millis == 0 {
+        return Duration::ZERO;
+    }
+
+    let hash = (peer, module_instance_id).consensus_hash::<sha256::Hash>();
+    let seed = u64::from_be_bytes(hash[..8].try_into().expect("Hash has 32 bytes"));
+
+    Duration::from_millis(seed % max_millis)
+}
+
+/// Decides which of the pending items of a module we propose
+#[derive(Debug)]
+pub struct LeaderSchedule {
+    our_id: PeerId,
+    peers: Vec<PeerId>,
+    /// The number of leaders of every class, a threshold of the peers
+    leaders: usize,
+    /// When we first saw each pending item that has a class
+    pending_since: HashMap<DynModuleConsensusItem, Instant>,
+}
+
+impl LeaderSchedule {
+    pub fn new(our_id: PeerId, peers: impl IntoIterator<Item = PeerId>) -> Self {
+        let mut peers = peers.into_iter().collect::<Vec<_>>();
+        peers.sort();
+        let leaders = peers.threshold();
+
+        LeaderSchedule {
+            our_id,
+            peers,
+            leaders,
+            pending_since: HashMap::new(),
+        }
+    }
+
+    /// The guardians proposing the items of the class during the session,
+    /// which are the threshold of peers following the first leader in order
+    pub fn leaders(&self, class: u64, session_index: u64) -> impl Iterator<Item = PeerId> + '_ {
+        let first = class.wrapping_add(session_index) % self.peers.len() as u64;
+
+        self.peers
+            .iter()
+            .cycle()
+            .skip(first as usize)
+            .take(self.leaders)
+            .copied()
+    }
+
+    /// Returns the items we propose out of the module's proposal, given with
+    /// their class
+    pub fn filter(
+        &mut self,
+        session_index: u64,
+        items: Vec<(DynModuleConsensusItem, Option<u64>)>,
+        now: Instant,
+    ) -> Vec<DynModuleConsensusItem> {
+        let mut pending_since = HashMap::new();
+        let mut proposal = vec![];
+
+        for (item, class) in items {
+            let Some(class) = class else {
+                proposal.push(item);
+                continue;
+            };
+
+            let since = self.pending_since.get(&item).copied().unwrap_or(now);
+
+            if self
+                .leaders(class, session_index)
+                .any(|peer| peer == self.our_id)
+                || LEADER_TIMEOUT <= now.duration_since(since)
+            {
+                proposal.push(item.clone());
+            }
+
+            pending_since.insert(item, since);
+        }
+
+        // items that are no longer pending have been ordered
+        self.pending_since = pending_since;
+
+        proposal
+    }
+}
+
+/// Spawns a task per module submitting the items of its consensus proposal
+/// according to the jitter and [`LeaderSchedule`] of the module
+pub async fn submit_module_proposals(
+    task_group: &mut TaskGroup,
+    db: Database,
+    modules: ServerModuleRegistry,
+    cfg: ServerConfig,
+    submission_sender: Sender<ConsensusItem>,
+    module_failures: ModuleFailures,
+    session_progress: SessionProgress,
+) {
+    for (instance_id, kind, module) in modules.iter_modules() {
+        let max_jitter = cfg
+            .local
+            .proposal_jitter_ms
+            .get(&instance_id)
+            .map_or(Duration::ZERO, |millis| Duration::from_millis(*millis));
+        let jitter = proposal_jitter(cfg.local.identity, instance_id, max_jitter);
+        let mut schedule = LeaderSchedule::new(
+            cfg.local.identity,
+            cfg.consensus.broadcast_public_keys.keys().copied(),
+        );
+
+        let db = db.clone();
+        let kind = kind.clone();
+        let module = module.clone();
+        let submission_sender = submission_sender.clone();
+        let module_failures = module_failures.clone();
+        let session_progress = session_progress.clone();
+
+        task_group
+            .spawn(
+                format!("submit_module_proposal-{instance_id}"),
+                move |task_handle| async move {
+                    sleep(jitter).await;
+
+                    while !task_handle.is_shutting_down() {
+                        let mut dbtx = db.begin_transaction().await;
+
+                        // We ignore any writes
+                        dbtx.ignore_uncommitted();
+
+                        let proposal = module_failures
+                            .call_local(
+                                instance_id,
+                                &kind,
+                                "consensus_proposal",
+                                None,
+                                module.consensus_proposal(
+                                    &mut dbtx.dbtx_ref_with_prefix_module_id(instance_id),
+                                    instance_id,
+                                ),
+                            )
+                            .await;
+
+                        // the proposal is local to us, so we stop proposing for the module
+                        // but keep processing its items like our peers do
+                        let Some(items) = proposal else {
+                            break;
+                        };
+
+                        let items = items
+                            .into_iter()
+                            .map(|item| {
+                                let class = module.consensus_item_class(&item);
+                                (item, class)
+                            })
+                            .collect();
+
+                        let session_index = session_progress.session_index();
+
+                        for item in schedule.filter(session_index, items, Instant::now()) {
+                            submission_sender
+                                .send(ConsensusItem::Module(item))
+                                .await
+                                .ok();
+                        }
+
+                        sleep(PROPOSAL_INTERVAL).await;
+                    }
+                },
+            )
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use fedimint_core::core::DynModuleConsensusItem;
+    use fedimint_core::epoch::SerdeSignatureShare;
+    use fedimint_core::PeerId;
+    use fedimint_dummy_common::DummyConsensusItem;
+    use rand::rngs::OsRng;
+    use threshold_crypto::SecretKeySet;
+
+    use super::{proposal_jitter, LeaderSchedule, LEADER_TIMEOUT};
+
+    fn item(message: &str) -> DynModuleConsensusItem {
+        let sk_share = SecretKeySet::random(0, &mut OsRng).secret_key_share(0);
+
+        DynModuleConsensusItem::from_typed(
+            0,
+            DummyConsensusItem::Sign(
+                message.to_string(),
+                SerdeSignatureShare(sk_share.sign(message)),
+            ),
+        )
+    }
+
+    #[test]
+    fn jitter_is_deterministic_and_bounded() {
+        let max_jitter = Duration::from_millis(500);
+
+        for peer in (0..4).map(PeerId::from) {
+            let jitter = proposal_jitter(peer, 0, max_jitter);
+            assert!(jitter < max_jitter);
+            assert_eq!(jitter, proposal_jitter(peer, 0, max_jitter));
+        }
+
+        assert_eq!(
+            proposal_jitter(PeerId::from(0), 0, Duration::ZERO),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn only_leaders_propose_until_timeout() {
+        let peers = (0..4).map(PeerId::from);
+        let mut leader = LeaderSchedule::new(PeerId::from(1), peers.clone());
+        let mut follower = LeaderSchedule::new(PeerId::from(0), peers);
+
+        // a threshold of peers lead class 1, starting at peer 1 in session 0
+        // and at peer 2 in session 1
+        assert_eq!(
+            leader.leaders(1, 0).collect::<Vec<_>>(),
+            [1, 2, 3].map(PeerId::from)
+        );
+        assert_eq!(
+            leader.leaders(1, 1).collect::<Vec<_>>(),
+            [2, 3, 0].map(PeerId::from)
+        );
+
+        let start = Instant::now();
+        let (classified, unclassified) = (item("classified"), item("unclassified"));
+        let items = || vec![(classified.clone(), Some(1)), (unclassified.clone(), None)];
+        let all = vec![classified.clone(), unclassified.clone()];
+
+        assert_eq!(leader.filter(0, items(), start), all);
+        assert_eq!(
+            follower.filter(0, items(), start),
+            vec![unclassified.clone()]
+        );
+
+        // the follower leads the class in the next session
+        assert_eq!(follower.filter(1, items(), start), all);
+
+        // the leaders' proposals were not ordered in time
+        assert_eq!(follower.filter(0, items(), start + LEADER_TIMEOUT), all);
+
+        // an ordered item is no longer pending, so its timeout starts over
+        assert!(follower
+            .filter(0, vec![], start + LEADER_TIMEOUT)
+            .is_empty());
+        assert_eq!(
+            follower.filter(0, items(), start + LEADER_TIMEOUT),
+            vec![unclassified]
+        );
+    }
+}
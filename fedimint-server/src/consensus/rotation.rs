@@ -0,0 +1,498 @@
+//! Rotating the broadcast and auth keys of a running federation, see
+//! [`fedimint_core::rotation`]
+//!
+//! The rotation refreshes the auth key shares like a distributed key
+//! generation whose shared secret is zero: every guardian adds the evaluations
+//! dealt to them to their share, while the public key set is the sum of the
+//! old one and the commitments of all dealers. Since all guardians have to
+//! confirm their evaluations, the rotation stalls rather than activating keys
+//! any guardian is unable to sign with. A guardian may deal again to replace
+//! a faulty deal, which voids all confirmations.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, ensure};
+use bitcoin_hashes::sha256;
+use fedimint_aead::{decrypt, encrypt};
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::db::{Database, DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::rotation::{KeyEpoch, KeyRotationDeal, KeyRotationStatus};
+use fedimint_core::PeerId;
+use futures::StreamExt;
+use hbbft::crypto::poly::Commitment;
+use hbbft::crypto::serde_impl::SerdeSecret;
+use hbbft::crypto::{G1Projective, PublicKeySet, SecretKeyShare};
+use rand::rngs::OsRng;
+use secp256k1_zkp::{SecretKey, SECP256K1};
+use tbs::poly::Poly;
+use tbs::Scalar;
+use tracing::info;
+
+use crate::config::distributedgen::scalar;
+use crate::config::io::rewrite_server_config;
+use crate::config::ServerConfig;
+use crate::consensus::lifecycle::peer_key;
+use crate::db::{
+    ClientConfigSignatureKey, ClientConfigSignatureSharePrefix, KeyRotationConfirmationPrefix,
+    KeyRotationDealPrefix, OurKeyRotationKey, ScheduledKeyRotationKey, SignedBlockPrefix,
+};
+use crate::LOG_CONSENSUS;
+
+/// The keys we dealt for a key rotation
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct OurKeyRotation {
+    pub deal: KeyRotationDeal,
+    /// Our new broadcast secret key, encrypted to our current broadcast key
+    pub broadcast_secret_key: Vec<u8>,
+}
+
+/// The index of the key epoch that starts with the next key rotation
+pub fn next_epoch(cfg: &ServerConfig) -> u64 {
+    cfg.consensus.key_epochs.len() as u64 + 1
+}
+
+/// Deals a new broadcast key pair for us and a random polynomial with a zero
+/// constant term that refreshes the auth key shares of all guardians
+pub fn deal_keys(cfg: &ServerConfig) -> anyhow::Result<OurKeyRotation> {
+    let (broadcast_sk, broadcast_pk) = secp256k1_zkp::generate_keypair(&mut OsRng);
+
+    let threshold = coefficients(&cfg.consensus.auth_pk_set).len();
+
+    // the constant term is added to the auth secret key, which defines the
+    // federation id and therefore must not change
+    let random: Poly<Scalar, Scalar> = Poly::random(threshold - 1, &mut OsRng);
+    let poly: Poly<Scalar, Scalar> = Poly::from(
+        std::iter::once(Scalar::from(0))
+            .chain(random.coefficients().skip(1).copied())
+            .collect(),
+    );
+
+    let auth_commitment = PublicKeySet::from(Commitment::from(
+        poly.coefficients()
+            .map(|coefficient| G1Projective::generator() * coefficient)
+            .collect::<Vec<_>>(),
+    ));
+
+    let auth_shares = cfg
+        .consensus
+        .broadcast_public_keys
+        .keys()
+        .map(|peer| {
+            let share = poly.evaluate(scalar(peer)).to_bytes().to_vec();
+
+            Ok((*peer, encrypt(share, &peer_key(cfg, *peer)?)?))
+        })
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+    let broadcast_secret_key = encrypt(
+        broadcast_sk.secret_bytes().to_vec(),
+        &peer_key(cfg, cfg.local.identity)?,
+    )?;
+
+    Ok(OurKeyRotation {
+        deal: KeyRotationDeal {
+            epoch: next_epoch(cfg),
+            broadcast_public_key: broadcast_pk,
+            auth_commitment,
+            auth_shares,
+        },
+        broadcast_secret_key,
+    })
+}
+
+/// Checks a deal ordered by consensus, which needs to be deterministic since
+/// all guardians have to accept the same consensus items
+pub fn check_deal(
+    cfg: &ServerConfig,
+    dealer: PeerId,
+    deal: &KeyRotationDeal,
+) -> anyhow::Result<()> {
+    ensure!(
+        deal.epoch == next_epoch(cfg),
+        "The deal is for key epoch {}, but the next key epoch is {}",
+        deal.epoch,
+        next_epoch(cfg)
+    );
+
+    ensure!(
+        deal.auth_shares
+            .keys()
+            .eq(cfg.consensus.broadcast_public_keys.keys()),
+        "The deal does not contain a share for every guardian"
+    );
+
+    ensure!(
+        cfg.consensus.broadcast_public_keys.get(&dealer) != Some(&deal.broadcast_public_key),
+        "The deal does not rotate the broadcast key of guardian {dealer}"
+    );
+
+    let commitment = coefficients(&deal.auth_commitment);
+
+    ensure!(
+        commitment.len() == coefficients(&cfg.consensus.auth_pk_set).len(),
+        "The commitment has {} coefficients, but the auth key threshold requires {}",
+        commitment.len(),
+        coefficients(&cfg.consensus.auth_pk_set).len()
+    );
+
+    ensure!(
+        commitment.first() == Some(&G1Projective::identity()),
+        "The deal changes the auth public key"
+    );
+
+    Ok(())
+}
+
+/// The deals ordered for the next key rotation by dealer
+pub async fn rotation_deals(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+) -> BTreeMap<PeerId, KeyRotationDeal> {
+    dbtx.find_by_prefix(&KeyRotationDealPrefix)
+        .await
+        .map(|(key, deal)| (key.0, deal))
+        .collect()
+        .await
+}
+
+/// The hash guardians confirm the deals of all guardians with
+pub fn deals_hash(deals: &BTreeMap<PeerId, KeyRotationDeal>) -> sha256::Hash {
+    deals.consensus_hash()
+}
+
+/// Decrypts the evaluations dealt to us and checks them against the
+/// commitments of their dealers, which we do before we confirm the deals.
+/// Returns the sum of the evaluations, which is added to our auth key share.
+pub fn our_auth_share_delta(
+    cfg: &ServerConfig,
+    deals: &BTreeMap<PeerId, KeyRotationDeal>,
+) -> anyhow::Result<Scalar> {
+    ensure!(
+        deals.keys().eq(cfg.consensus.broadcast_public_keys.keys()),
+        "Not all guardians dealt their keys yet"
+    );
+
+    let mut delta = Scalar::from(0);
+
+    for (dealer, deal) in deals {
+        let mut ciphertext = deal
+            .auth_shares
+            .get(&cfg.local.identity)
+            .ok_or_else(|| anyhow!("The deal of guardian {dealer} contains no share for us"))?
+            .clone();
+
+        let bytes: [u8; 32] = decrypt(&mut ciphertext, &peer_key(cfg, *dealer)?)?
+            .try_into()
+            .map_err(|_| anyhow!("The share dealt by guardian {dealer} has the wrong length"))?;
+
+        let share = Option::<Scalar>::from(Scalar::from_bytes(&bytes))
+            .ok_or_else(|| anyhow!("The share dealt by guardian {dealer} is not a scalar"))?;
+
+        ensure!(
+            deal.auth_commitment
+                .public_key_share(cfg.local.identity.to_usize())
+                == SecretKeyShare::from_mut(&mut share.clone()).public_key_share(),
+            "The share dealt by guardian {dealer} does not match its commitment"
+        );
+
+        delta += share;
+    }
+
+    Ok(delta)
+}
+
+/// Our config with the keys dealt by all guardians, which are used from the
+/// activation session on
+pub fn config_with_rotated_keys(
+    cfg: &ServerConfig,
+    rotation: &OurKeyRotation,
+    deals: &BTreeMap<PeerId, KeyRotationDeal>,
+    activation_session: u64,
+) -> anyhow::Result<ServerConfig> {
+    ensure!(
+        deals.get(&cfg.local.identity) == Some(&rotation.deal),
+        "The ordered deal for us is not the one we dealt"
+    );
+
+    let mut auth_share = share_scalar(&cfg.private.auth_sks)? + our_auth_share_delta(cfg, deals)?;
+    let auth_sks = SecretKeyShare::from_mut(&mut auth_share);
+
+    let mut auth_coefficients = coefficients(&cfg.consensus.auth_pk_set);
+
+    for deal in deals.values() {
+        for (coefficient, delta) in auth_coefficients
+            .iter_mut()
+            .zip(coefficients(&deal.auth_commitment))
+        {
+            *coefficient += delta;
+        }
+    }
+
+    let auth_pk_set = PublicKeySet::from(Commitment::from(auth_coefficients));
+
+    ensure!(
+        auth_pk_set.public_key() == cfg.consensus.auth_pk_set.public_key(),
+        "The rotation changes the federation id"
+    );
+
+    ensure!(
+        auth_pk_set.public_key_share(cfg.local.identity.to_usize()) == auth_sks.public_key_share(),
+        "Our new auth key share does not match the new public key set"
+    );
+
+    let mut ciphertext = rotation.broadcast_secret_key.clone();
+    let broadcast_secret_key = SecretKey::from_slice(decrypt(
+        &mut ciphertext,
+        &peer_key(cfg, cfg.local.identity)?,
+    )?)?;
+
+    ensure!(
+        broadcast_secret_key.public_key(SECP256K1) == rotation.deal.broadcast_public_key,
+        "Our new broadcast secret key does not match the key we dealt"
+    );
+
+    let mut updated = cfg.clone();
+
+    updated.consensus.key_epochs.push(KeyEpoch {
+        rotated_at_session: activation_session,
+        broadcast_public_keys: cfg.consensus.broadcast_public_keys.clone(),
+    });
+    updated.consensus.broadcast_public_keys = deals
+        .iter()
+        .map(|(peer, deal)| (*peer, deal.broadcast_public_key))
+        .collect();
+    updated.consensus.auth_pk_set = auth_pk_set;
+    updated.private.broadcast_secret_key = broadcast_secret_key;
+    updated.private.auth_sks = SerdeSecret(auth_sks);
+
+    Ok(updated)
+}
+
+/// The key rotation in progress, if any guardian dealt keys for one
+pub async fn key_rotation_status(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    cfg: &ServerConfig,
+) -> Option<KeyRotationStatus> {
+    let deals = rotation_deals(dbtx).await;
+
+    if deals.is_empty() {
+        return None;
+    }
+
+    let confirmations = dbtx
+        .find_by_prefix(&KeyRotationConfirmationPrefix)
+        .await
+        .map(|(key, _)| key.0)
+        .collect::<BTreeSet<_>>()
+        .await;
+
+    Some(KeyRotationStatus {
+        epoch: next_epoch(cfg),
+        deals: deals.into_keys().collect(),
+        confirmations,
+        activation_session: dbtx.get_value(&ScheduledKeyRotationKey).await,
+    })
+}
+
+/// Activates the rotated keys if the activation session is next, which
+/// requires restarting the consensus with the returned config
+pub async fn rotate_due_keys(
+    db: &Database,
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleInitRegistry,
+    data_dir: PathBuf,
+) -> anyhow::Result<Option<ServerConfig>> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let session_index = dbtx.find_by_prefix(&SignedBlockPrefix).await.count().await as u64;
+
+    let Some(activation_session) = dbtx.get_value(&ScheduledKeyRotationKey).await else {
+        return Ok(None);
+    };
+
+    if session_index < activation_session {
+        return Ok(None);
+    }
+
+    // we may have crashed after writing the config files in a previous attempt
+    let cfg = if cfg.consensus.key_epochs.last().map_or(false, |epoch| {
+        epoch.rotated_at_session == activation_session
+    }) {
+        cfg.clone()
+    } else {
+        let rotation = dbtx
+            .get_value(&OurKeyRotationKey)
+            .await
+            .ok_or_else(|| anyhow!("The keys we dealt for the scheduled rotation are missing"))?;
+        let deals = rotation_deals(&mut dbtx.dbtx_ref()).await;
+
+        let updated = config_with_rotated_keys(cfg, &rotation, &deals, activation_session)?;
+
+        rewrite_server_config(&updated, data_dir, &cfg.private.api_auth.0, module_inits)?;
+
+        updated
+    };
+
+    dbtx.remove_entry(&ScheduledKeyRotationKey).await;
+    dbtx.remove_entry(&OurKeyRotationKey).await;
+    dbtx.remove_by_prefix(&KeyRotationDealPrefix).await;
+    dbtx.remove_by_prefix(&KeyRotationConfirmationPrefix).await;
+
+    // the client config contains the broadcast public keys, so the guardians
+    // have to sign it again
+    dbtx.remove_entry(&ClientConfigSignatureKey).await;
+    dbtx.remove_by_prefix(&ClientConfigSignatureSharePrefix)
+        .await;
+
+    dbtx.commit_tx_result().await?;
+
+    info!(
+        target: LOG_CONSENSUS,
+        epoch = cfg.consensus.key_epochs.len(),
+        activation_session,
+        "Activated the rotated broadcast and auth keys"
+    );
+
+    Ok(Some(cfg))
+}
+
+/// The coefficients of a public key set, starting with the public key
+fn coefficients(set: &PublicKeySet) -> Vec<G1Projective> {
+    let mut coefficients = Vec::new();
+    coefficients.extend(set.coefficients());
+    coefficients
+}
+
+/// The scalar of an auth key share, which threshold_crypto does not expose.
+/// The serialization of a share ends with the little-endian bytes of its
+/// scalar, which we check by deriving the public key share from it.
+fn share_scalar(share: &SerdeSecret<SecretKeyShare>) -> anyhow::Result<Scalar> {
+    let bytes = bincode::serialize(share)?;
+
+    let bytes: [u8; 32] = bytes[bytes.len().saturating_sub(32)..]
+        .try_into()
+        .map_err(|_| anyhow!("The serialized auth key share is too short"))?;
+
+    let scalar = Option::<Scalar>::from(Scalar::from_bytes(&bytes))
+        .ok_or_else(|| anyhow!("The serialized auth key share is not a scalar"))?;
+
+    ensure!(
+        SecretKeyShare::from_mut(&mut scalar.clone()).public_key_share()
+            == share.0.public_key_share(),
+        "Unable to read the scalar of our auth key share"
+    );
+
+    Ok(scalar)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use fedimint_core::config::ServerModuleInitRegistry;
+    use fedimint_core::epoch::{combine_sigs, SerdeSignatureShare};
+    use fedimint_core::module::DynServerModuleInit;
+    use fedimint_core::PeerId;
+    use fedimint_dummy_server::DummyGen;
+
+    use super::{check_deal, config_with_rotated_keys, deal_keys, our_auth_share_delta};
+    use crate::config::ServerConfig;
+    use crate::simulation::config_gen_params;
+
+    #[test]
+    fn rotation_keeps_the_federation_id() {
+        let peers = (0..4).map(PeerId::from).collect::<BTreeSet<_>>();
+        let registry = ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]);
+        let cfgs = ServerConfig::trusted_dealer_gen(&config_gen_params(&peers), registry);
+
+        let rotations = cfgs
+            .iter()
+            .map(|(peer, cfg)| (*peer, deal_keys(cfg).expect("Dealing succeeds")))
+            .collect::<BTreeMap<_, _>>();
+
+        let deals = rotations
+            .iter()
+            .map(|(peer, rotation)| (*peer, rotation.deal.clone()))
+            .collect::<BTreeMap<_, _>>();
+
+        for (dealer, deal) in &deals {
+            assert!(check_deal(&cfgs[&PeerId::from(0)], *dealer, deal).is_ok());
+        }
+
+        let rotated = cfgs
+            .iter()
+            .map(|(peer, cfg)| {
+                let rotated = config_with_rotated_keys(cfg, &rotations[peer], &deals, 5)
+                    .expect("Rotation succeeds");
+
+                (*peer, rotated)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let message = b"signed with the rotated keys";
+        let shares = rotated
+            .iter()
+            .take(3)
+            .map(|(peer, cfg)| {
+                (
+                    *peer,
+                    SerdeSignatureShare(cfg.private.auth_sks.0.sign(message)),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        for (peer, cfg) in &rotated {
+            assert_eq!(
+                cfg.consensus.federation_id(),
+                cfgs[peer].consensus.federation_id()
+            );
+            assert_eq!(cfg.consensus.key_epochs.len(), 1);
+            assert_eq!(cfg.consensus.key_epochs[0].rotated_at_session, 5);
+            assert_eq!(
+                cfg.consensus.key_epochs[0].broadcast_public_keys,
+                cfgs[peer].consensus.broadcast_public_keys
+            );
+            assert_eq!(
+                cfg.consensus.broadcast_public_keys[peer],
+                rotations[peer].deal.broadcast_public_key
+            );
+
+            // the shares of the old keys do not combine with the new ones anymore
+            assert_ne!(
+                cfg.consensus.auth_pk_set.public_key_share(peer.to_usize()),
+                cfgs[peer]
+                    .consensus
+                    .auth_pk_set
+                    .public_key_share(peer.to_usize())
+            );
+
+            let signature = combine_sigs(&cfg.consensus.auth_pk_set, &shares, message)
+                .expect("Shares of the rotated keys combine");
+
+            assert!(cfg
+                .consensus
+                .auth_pk_set
+                .public_key()
+                .verify(&signature.0, message));
+        }
+
+        // a deal for the same key epoch is rejected after the rotation
+        assert!(check_deal(
+            &rotated[&PeerId::from(0)],
+            PeerId::from(0),
+            &deals[&PeerId::from(0)]
+        )
+        .is_err());
+
+        // a guardian does not confirm shares that do not match the commitment
+        let mut tampered = deals.clone();
+        tampered
+            .get_mut(&PeerId::from(2))
+            .expect("Deal exists")
+            .auth_commitment = deals[&PeerId::from(1)].auth_commitment.clone();
+
+        assert!(our_auth_share_delta(&cfgs[&PeerId::from(0)], &deals).is_ok());
+        assert!(our_auth_share_delta(&cfgs[&PeerId::from(0)], &tampered).is_err());
+    }
+}
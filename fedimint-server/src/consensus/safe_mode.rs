@@ -0,0 +1,176 @@
+//! Safe mode for when the guardians storage runs full
+//!
+//! Committing the result of a consensus item must not fail, since we can not
+//! skip an item without diverging from our peers. If a commit fails because
+//! the disk is full we therefore enter safe mode instead of panicking: the
+//! consensus stops processing items, the API rejects new transactions but
+//! keeps serving reads, and the failed write is retried periodically until the
+//! operator frees up space, at which point we resume automatically.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+use fedimint_core::api::StorageFailure;
+use fedimint_core::db::{CommitConflictError, DatabaseTransaction, StorageFullError};
+use fedimint_core::task::{sleep, RwLock};
+use fedimint_core::time::now;
+use tracing::{error, info, instrument};
+
+use crate::consensus::conflict::ConflictBackoff;
+use crate::LOG_CONSENSUS;
+
+/// How often we retry a write that failed because the storage is full
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Whether this guardian is in safe mode since its storage ran full
+#[derive(Debug, Clone, Default)]
+pub struct SafeMode(Arc<RwLock<Option<StorageFailure>>>);
+
+impl SafeMode {
+    pub async fn get(&self) -> Option<StorageFailure> {
+        self.0.read().await.clone()
+    }
+
+    /// Rejects requests that would cause new writes, e.g. submitting a
+    /// transaction, while we are in safe mode
+    pub async fn check_writable(&self) -> anyhow::Result<()> {
+        if let Some(failure) = self.0.read().await.as_ref() {
+            bail!(
+                "Guardian is in safe mode since its storage is full: {}",
+                failure.message
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Runs a write, retrying it in safe mode for as long as it fails because
+    /// the storage is full
+    ///
+    /// The write has to be idempotent as long as it fails, which holds if it
+    /// only commits a single database transaction. Since we can not give up
+    /// on the write, it is also retried for as long as its commit conflicts,
+    /// see [`ConflictBackoff`]. Any other error is returned as is.
+    pub async fn retry_while_full<T, F, Fut>(
+        &self,
+        operation: &'static str,
+        mut write: F,
+    ) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut conflicts = ConflictBackoff::new(operation);
+
+        loop {
+            match write().await {
+                Err(e) if CommitConflictError::is_cause_of(&e) => conflicts.wait().await,
+                Err(e) if StorageFullError::is_cause_of(&e) => {
+                    self.enter(operation, &e).await;
+
+                    sleep(RETRY_INTERVAL).await;
+                }
+                result => {
+                    self.exit().await;
+
+                    return result;
+                }
+            }
+        }
+    }
+
+    async fn enter(&self, operation: &'static str, e: &anyhow::Error) {
+        let mut failure = self.0.write().await;
+
+        if failure.is_none() {
+            error!(
+                target: LOG_CONSENSUS,
+                operation,
+                error = %e,
+                "Storage is full, entering safe mode until space is available. FREE UP DISK SPACE!!!"
+            );
+
+            *failure = Some(StorageFailure {
+                operation: operation.to_string(),
+                message: e.to_string(),
+                since: now(),
+            });
+        }
+    }
+
+    async fn exit(&self) {
+        // avoid taking the write lock for every successful write
+        if self.0.read().await.is_none() {
+            return;
+        }
+
+        if self.0.write().await.take().is_some() {
+            info!(target: LOG_CONSENSUS, "Storage is available again, leaving safe mode");
+        }
+    }
+}
+
+/// Commits a transaction whose changes have to be persisted
+///
+/// Panics on any error other than the storage running full or a conflict,
+/// which are returned such that the write can be retried via
+/// [`SafeMode::retry_while_full`].
+#[instrument(name = "dbtx_commit", skip(dbtx))]
+pub async fn commit_unless_full(dbtx: DatabaseTransaction<'_>, msg: &str) -> anyhow::Result<()> {
+    match dbtx.commit_tx_result().await {
+        Err(e) if !StorageFullError::is_cause_of(&e) && !CommitConflictError::is_cause_of(&e) => {
+            panic!("{msg}: {e}")
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::db::StorageFullError;
+
+    use super::SafeMode;
+
+    #[tokio::test]
+    async fn enters_and_leaves_safe_mode() {
+        let safe_mode = SafeMode::default();
+
+        let write = safe_mode.retry_while_full("test", || async {
+            Err::<(), _>(StorageFullError("No space left on device".into()).into())
+        });
+
+        assert!(tokio::time::timeout(Duration::from_millis(100), write)
+            .await
+            .is_err());
+
+        let failure = safe_mode.get().await.expect("Entered safe mode");
+        assert_eq!(failure.operation, "test");
+        assert!(safe_mode.check_writable().await.is_err());
+
+        safe_mode
+            .retry_while_full("test", || async { Ok(()) })
+            .await
+            .expect("Storage is available");
+
+        assert!(safe_mode.get().await.is_none());
+        assert!(safe_mode.check_writable().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_returned() {
+        let safe_mode = SafeMode::default();
+
+        let result = safe_mode
+            .retry_while_full("test", || async {
+                Err::<(), _>(anyhow::anyhow!("conflict"))
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert!(safe_mode.get().await.is_none());
+    }
+}
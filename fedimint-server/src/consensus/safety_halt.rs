@@ -0,0 +1,293 @@
+//! Coordinated halt for when a safety invariant of the federation is violated
+//!
+//! If processing a consensus item would leave the federation with negative net
+//! assets we can neither skip the item, since we would diverge from our peers,
+//! nor accept it without an operator looking into it. Instead of panicking we
+//! therefore halt: the consensus stops processing items, the API rejects new
+//! transactions but keeps serving reads, the operator is alerted via the
+//! configured [`AlertHook`] and an admin has to either resume or shut down the
+//! guardian via the authenticated admin API.
+
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use anyhow::bail;
+use fedimint_core::api::{SafetyHaltOverride, SafetyViolation};
+use fedimint_core::task::spawn;
+use fedimint_core::time::now;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use crate::LOG_CONSENSUS;
+
+/// Notifies the operator of a safety halt, e.g. via their monitoring system
+pub trait AlertHook: Debug + Send + Sync {
+    fn alert(&self, violation: &SafetyViolation);
+}
+
+pub type DynAlertHook = Arc<dyn AlertHook>;
+
+/// Runs a command with the violation serialized as JSON as its only argument
+#[derive(Debug, Clone)]
+pub struct CommandAlertHook {
+    pub command: PathBuf,
+}
+
+impl AlertHook for CommandAlertHook {
+    fn alert(&self, violation: &SafetyViolation) {
+        let mut command = tokio::process::Command::new(&self.command);
+
+        command.arg(serde_json::to_string(violation).expect("Serialization can't fail"));
+
+        spawn("safety halt alert command", async move {
+            match command.status().await {
+                Ok(status) if status.success() => {}
+                Ok(status) => {
+                    warn!(target: LOG_CONSENSUS, %status, "Alert command failed");
+                }
+                Err(e) => {
+                    warn!(target: LOG_CONSENSUS, error = %e, "Could not run alert command");
+                }
+            }
+        });
+    }
+}
+
+/// The net assets of the federation dropped below what the guardian tolerates
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Balance sheet of the fed has gone negative: {audit}")]
+pub struct NegativeNetAssets {
+    pub net_assets_msat: i64,
+    pub audit: String,
+}
+
+#[derive(Debug, Clone)]
+enum HaltState {
+    Running,
+    Halted(SafetyViolation),
+    Overridden(SafetyHaltOverride),
+}
+
+/// Whether this guardian halted its consensus since a safety invariant was
+/// violated
+#[derive(Debug, Clone)]
+pub struct SafetyHalt {
+    state: Arc<watch::Sender<HaltState>>,
+    /// The lowest net assets an admin has accepted by resuming the consensus
+    tolerated_net_assets_msat: Arc<AtomicI64>,
+}
+
+impl Default for SafetyHalt {
+    fn default() -> Self {
+        SafetyHalt {
+            state: Arc::new(watch::channel(HaltState::Running).0),
+            tolerated_net_assets_msat: Arc::new(AtomicI64::new(0)),
+        }
+    }
+}
+
+impl SafetyHalt {
+    pub fn get(&self) -> Option<SafetyViolation> {
+        match &*self.state.borrow() {
+            HaltState::Halted(violation) => Some(violation.clone()),
+            _ => None,
+        }
+    }
+
+    /// Rejects requests that would cause new writes, e.g. submitting a
+    /// transaction, while we are halted
+    pub fn check_writable(&self) -> anyhow::Result<()> {
+        match &*self.state.borrow() {
+            HaltState::Running => Ok(()),
+            HaltState::Halted(violation) => bail!(
+                "Guardian halted its consensus after a safety violation: {}",
+                violation.message
+            ),
+            HaltState::Overridden(..) => bail!("Guardian is shutting down after a safety halt"),
+        }
+    }
+
+    /// Returns an error if the net assets are below zero, or below the net
+    /// assets accepted by an admin when resuming from a previous halt
+    pub fn check_net_assets(&self, net_assets_msat: i64, audit: String) -> anyhow::Result<()> {
+        if net_assets_msat < self.tolerated_net_assets_msat.load(Ordering::SeqCst) {
+            return Err(NegativeNetAssets {
+                net_assets_msat,
+                audit,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Halts until an admin resumes the consensus, after which the violated
+    /// net assets are tolerated
+    ///
+    /// Never returns if the admin decides to shut down instead, the consensus
+    /// is stopped via [`Self::await_shutdown`] in that case.
+    pub async fn halt(
+        &self,
+        session_index: u64,
+        violation: &NegativeNetAssets,
+        alert_hooks: &[DynAlertHook],
+    ) {
+        let violation_info = SafetyViolation {
+            message: violation.to_string(),
+            session_index,
+            since: now(),
+        };
+
+        error!(
+            target: LOG_CONSENSUS,
+            session_index,
+            net_assets_msat = violation.net_assets_msat,
+            audit = %violation.audit,
+            "Safety invariant violated, halting consensus until an admin resumes or shuts down the guardian!!!"
+        );
+
+        for alert_hook in alert_hooks {
+            alert_hook.alert(&violation_info);
+        }
+
+        self.state.send_replace(HaltState::Halted(violation_info));
+
+        let halt_override = self
+            .wait_for(|state| match state {
+                HaltState::Overridden(halt_override) => Some(*halt_override),
+                _ => None,
+            })
+            .await;
+
+        match halt_override {
+            SafetyHaltOverride::Resume => {
+                self.tolerated_net_assets_msat
+                    .fetch_min(violation.net_assets_msat, Ordering::SeqCst);
+
+                self.state.send_replace(HaltState::Running);
+
+                info!(target: LOG_CONSENSUS, "Admin resumed the consensus after a safety halt");
+            }
+            SafetyHaltOverride::Shutdown => std::future::pending().await,
+        }
+    }
+
+    /// Resolves a halt as decided by an admin
+    pub fn override_halt(&self, halt_override: SafetyHaltOverride) -> anyhow::Result<()> {
+        let mut result = Ok(());
+
+        self.state.send_if_modified(|state| match state {
+            HaltState::Halted(..) => {
+                *state = HaltState::Overridden(halt_override);
+
+                true
+            }
+            _ => {
+                result = Err(anyhow::anyhow!("Guardian is not halted"));
+
+                false
+            }
+        });
+
+        result
+    }
+
+    /// Resolves once an admin decided to shut down the halted guardian
+    pub async fn await_shutdown(&self) {
+        self.wait_for(|state| match state {
+            HaltState::Overridden(SafetyHaltOverride::Shutdown) => Some(()),
+            _ => None,
+        })
+        .await;
+    }
+
+    async fn wait_for<T>(&self, f: impl Fn(&HaltState) -> Option<T>) -> T {
+        let mut receiver = self.state.subscribe();
+
+        loop {
+            if let Some(value) = f(&receiver.borrow_and_update()) {
+                return value;
+            }
+
+            receiver
+                .changed()
+                .await
+                .expect("The safety halt owns the sender");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::api::SafetyHaltOverride;
+
+    use super::{NegativeNetAssets, SafetyHalt};
+
+    fn violation() -> NegativeNetAssets {
+        NegativeNetAssets {
+            net_assets_msat: -1000,
+            audit: "audit".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn halts_until_resumed() {
+        let safety_halt = SafetyHalt::default();
+
+        assert!(safety_halt.check_net_assets(-1000, "audit".into()).is_err());
+        assert!(safety_halt
+            .override_halt(SafetyHaltOverride::Resume)
+            .is_err());
+
+        let halt = tokio::spawn({
+            let safety_halt = safety_halt.clone();
+            async move { safety_halt.halt(0, &violation(), &[]).await }
+        });
+
+        while safety_halt.get().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(safety_halt.check_writable().is_err());
+
+        safety_halt
+            .override_halt(SafetyHaltOverride::Resume)
+            .expect("Guardian is halted");
+
+        halt.await.expect("Halt resumes");
+
+        assert!(safety_halt.get().is_none());
+        assert!(safety_halt.check_writable().is_ok());
+        assert!(safety_halt.check_net_assets(-1000, "audit".into()).is_ok());
+        assert!(safety_halt.check_net_assets(-1001, "audit".into()).is_err());
+    }
+
+    #[tokio::test]
+    async fn shuts_down_when_requested() {
+        let safety_halt = SafetyHalt::default();
+
+        let halt = tokio::spawn({
+            let safety_halt = safety_halt.clone();
+            async move { safety_halt.halt(0, &violation(), &[]).await }
+        });
+
+        while safety_halt.get().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        safety_halt
+            .override_halt(SafetyHaltOverride::Shutdown)
+            .expect("Guardian is halted");
+
+        tokio::time::timeout(Duration::from_secs(1), safety_halt.await_shutdown())
+            .await
+            .expect("Shutdown was requested");
+
+        assert!(safety_halt.check_writable().is_err());
+        assert!(!halt.is_finished());
+    }
+}
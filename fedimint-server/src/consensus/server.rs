@@ -1,68 +1,121 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 
 use aleph_bft::Keychain as KeychainTrait;
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, ensure};
 use async_channel::{Receiver, Sender};
 use bitcoin_hashes::sha256;
-use fedimint_core::api::{FederationApiExt, GlobalFederationApi, WsFederationApi};
-use fedimint_core::block::{AcceptedItem, Block, SchnorrSignature, SignedBlock};
-use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::api::{DynGlobalApi, FederationApiExt, GlobalFederationApi, WsFederationApi};
+use fedimint_core::block::{
+    AcceptedItem, Block, SchnorrSignature, SignedBlock, TransactionLocation,
+};
+use fedimint_core::config::{PeerUrl, ServerModuleInitRegistry};
 use fedimint_core::db::{
     apply_migrations, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
 };
-use fedimint_core::encoding::Decodable;
 use fedimint_core::endpoint_constants::AWAIT_SIGNED_BLOCK_ENDPOINT;
+use fedimint_core::endpoint_update::{apply_endpoint_updates, ApiEndpointUpdate};
 use fedimint_core::epoch::{ConsensusItem, SerdeSignature, SerdeSignatureShare};
 use fedimint_core::fmt_utils::OptStacktrace;
+use fedimint_core::lifecycle::{AddModuleProposal, ModuleUpgrade};
+use fedimint_core::meta::{FederationMetaShare, SignedFederationMeta};
+use fedimint_core::migration::{FinalStateAttestationShare, SignedFinalStateAttestation};
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::registry::{
     ModuleDecoderRegistry, ModuleRegistry, ServerModuleRegistry,
 };
 use fedimint_core::module::{ApiRequestErased, SerdeModuleEncoding};
-use fedimint_core::query::FilterMap;
+use fedimint_core::query::{FilterMap, PeerLatencyTracker};
+use fedimint_core::rotation::KeyRotationConfirmation;
 use fedimint_core::task::{sleep, spawn, RwLock, TaskGroup, TaskHandle};
-use fedimint_core::util::SafeUrl;
-use fedimint_core::{timing, PeerId};
+use fedimint_core::transaction::TransactionRejection;
+use fedimint_core::{timing, PeerId, TransactionId};
 use futures::StreamExt;
 use tokio::sync::watch;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
 
 use crate::atomic_broadcast::data_provider::{DataProvider, UnitData};
 use crate::atomic_broadcast::finalization_handler::FinalizationHandler;
 use crate::atomic_broadcast::network::Network;
 use crate::atomic_broadcast::spawner::Spawner;
 use crate::atomic_broadcast::{to_node_index, Keychain, Message};
-use crate::config::ServerConfig;
-use crate::consensus::process_transaction_with_dbtx;
+use crate::config::reload::LiveConfig;
+use crate::config::versions::record_config_version;
+use crate::config::{PeerTransport, ServerConfig};
+use crate::consensus::debug::ItemLogFilter;
+use crate::consensus::isolation::{self, catch_panic, ModuleFailures, ModulePanic};
+use crate::consensus::lifecycle::{
+    active_version, check_proposal, check_upgrade, due_upgrades, module_config_updates,
+    supports_version,
+};
+use crate::consensus::proposal::submit_module_proposals;
+use crate::consensus::rotation::{
+    check_deal, deals_hash, next_epoch, our_auth_share_delta, rotation_deals,
+};
+use crate::consensus::safe_mode::{commit_unless_full, SafeMode};
+use crate::consensus::safety_halt::{DynAlertHook, NegativeNetAssets, SafetyHalt};
+use crate::consensus::state_snapshot::take_state_snapshot;
+use crate::consensus::watchdog::{SessionProgress, StallWatchdog};
+use crate::consensus::{audit_fee_income, process_transaction_with_dbtx, transaction_dependencies};
 use crate::db::{
     get_global_database_migrations, AcceptedItemKey, AcceptedItemPrefix, AcceptedTransactionKey,
-    AlephUnitsPrefix, ClientConfigSignatureKey, ClientConfigSignatureShareKey,
-    ClientConfigSignatureSharePrefix, SignedBlockKey, SignedBlockPrefix, GLOBAL_DATABASE_VERSION,
+    AcceptedTransactionLocationKey, AlephUnitsPrefix, ApiEndpointUpdateKey,
+    ApiEndpointUpdatePrefix, ApprovedModulePrefix, ApprovedUpgradePrefix, ClientConfigSignatureKey,
+    ClientConfigSignatureShareKey, ClientConfigSignatureSharePrefix, FederationMetaKey,
+    FederationMetaShareKey, FederationMetaSharePrefix, FeeIncomeKey, FinalStateAttestationKey,
+    FinalStateAttestationShareKey, FinalStateAttestationSharePrefix, KeyRotationConfirmationKey,
+    KeyRotationConfirmationPrefix, KeyRotationDealKey, LocalStateCommitmentKey,
+    LocalStateCommitmentPrefix, ModuleApprovalIdPrefix, ModuleApprovalKey, ModuleApprovalPrefix,
+    ModuleProposalKey, ModuleProposalPrefix, OurKeyRotationKey, PeerLatencyHistoryKey,
+    PeerLatencyHistoryPrefix, PendingModuleKey, ProposedFederationMetaKey,
+    ProposedFinalStateAttestationKey, RejectedTransactionKey, RequestedApiEndpointKey,
+    ScheduledKeyRotationKey, ScheduledUpgradeKey, SignedBlockKey, SignedBlockPrefix,
+    StateCommitmentKey, TransactionDownstreamKey, TransactionUpstreamKey, UpgradeApprovalKey,
+    UpgradeApprovalPrefix, UpgradeApprovalUpgradePrefix, GLOBAL_DATABASE_VERSION,
 };
+use crate::diagnostics::DumpRequests;
 use crate::fedimint_core::encoding::Encodable;
-use crate::net::api::{ConsensusApi, ExpiringCache, InvitationCodesTracker};
-use crate::net::connect::{Connector, TlsTcpConnector};
+use crate::net::api::{ConsensusApi, ExpiringCache, InvitationCodesTracker, RequestsInFlight};
+use crate::net::connect::{Connector, QuicConnector, TlsTcpConnector};
 use crate::net::peers::{DelayCalculator, PeerConnector, ReconnectPeerConnections};
+use crate::net::replica::HistoryReplica;
+use crate::net::usage::ApiUsageTracker;
+use crate::signer::DynSigner;
 use crate::{atomic_broadcast, LOG_CONSENSUS, LOG_CORE};
 
 /// How many txs can be stored in memory before blocking the API
 const TRANSACTION_BUFFER: usize = 1000;
 
+/// Delay between the rounds of the atomic broadcast before the exponential
+/// slowdown kicks in
+const ROUND_DELAY: Duration = Duration::from_millis(250);
+
 pub(crate) type LatestContributionByPeer = HashMap<PeerId, u64>;
 
 /// Runs the main server consensus loop
 pub struct ConsensusServer {
     modules: ServerModuleRegistry,
+    module_inits: ServerModuleInitRegistry,
     db: Database,
     connections: ReconnectPeerConnections<Message>,
     keychain: Keychain,
     client_cfg_hash: sha256::Hash,
-    api_endpoints: Vec<(PeerId, SafeUrl)>,
+    api_endpoints: BTreeMap<PeerId, PeerUrl>,
     cfg: ServerConfig,
     submission_receiver: Receiver<ConsensusItem>,
     latest_contribution_by_peer: Arc<RwLock<LatestContributionByPeer>>,
+    module_failures: ModuleFailures,
+    safe_mode: SafeMode,
+    safety_halt: SafetyHalt,
+    session_progress: SessionProgress,
+    item_log_filter: ItemLogFilter,
+    alert_hooks: Vec<DynAlertHook>,
+    /// Replaces the websocket API of our peers, e.g. in simulations
+    peer_api: Option<DynGlobalApi>,
+    round_delay: Duration,
+    #[cfg(any(test, feature = "red-team"))]
+    faults: crate::red_team::Faults,
 }
 
 impl ConsensusServer {
@@ -71,15 +124,23 @@ impl ConsensusServer {
         cfg: ServerConfig,
         db: Database,
         module_inits: ServerModuleInitRegistry,
+        signer: DynSigner,
         task_group: &mut TaskGroup,
     ) -> anyhow::Result<(Self, ConsensusApi)> {
-        let connector: PeerConnector<Message> =
-            TlsTcpConnector::new(cfg.tls_config(), cfg.local.identity).into_dyn();
+        let connector: PeerConnector<Message> = match cfg.local.p2p_transport {
+            PeerTransport::TlsTcp => {
+                TlsTcpConnector::new(cfg.tls_config(), cfg.local.identity).into_dyn()
+            }
+            PeerTransport::Quic => {
+                QuicConnector::new(cfg.tls_config(), cfg.local.identity).into_dyn()
+            }
+        };
 
         Self::new_with(
             cfg,
             db,
             module_inits,
+            signer,
             connector,
             DelayCalculator::PROD_DEFAULT,
             task_group,
@@ -94,6 +155,7 @@ impl ConsensusServer {
         cfg: ServerConfig,
         db: Database,
         module_inits: ServerModuleInitRegistry,
+        signer: DynSigner,
         connector: PeerConnector<Message>,
         delay_calculator: DelayCalculator,
         task_group: &mut TaskGroup,
@@ -130,13 +192,23 @@ impl ConsensusServer {
             )
             .await?;
 
+            // an upgrade scheduled by all guardians overrides the version in our config
+            let mut dbtx = db.begin_transaction().await;
+            let version = active_version(&mut dbtx.dbtx_ref(), &cfg, *module_id).await?;
+            drop(dbtx);
+
+            ensure!(
+                supports_version(&cfg, &module_inits, *module_id, version),
+                "Module instance {module_id} ({kind}) runs consensus version {}, which this \
+                 binary does not support",
+                version.0
+            );
+
+            let mut module_cfg = cfg.get_module_config(*module_id)?;
+            module_cfg.consensus.version = version;
+
             let module = init
-                .init(
-                    cfg.get_module_config(*module_id)?,
-                    isolated_db,
-                    task_group,
-                    cfg.local.identity,
-                )
+                .init(module_cfg, isolated_db, task_group, cfg.local.identity)
                 .await?;
 
             modules.insert(*module_id, (kind, module));
@@ -147,7 +219,7 @@ impl ConsensusServer {
         let keychain = Keychain::new(
             cfg.local.identity,
             cfg.consensus.broadcast_public_keys.clone(),
-            cfg.private.broadcast_secret_key,
+            signer.clone(),
         );
 
         let (submission_sender, submission_receiver) = async_channel::bounded(TRANSACTION_BUFFER);
@@ -163,12 +235,24 @@ impl ConsensusServer {
 
         // Build API that can handle requests
         let latest_contribution_by_peer = Default::default();
+        let module_failures = ModuleFailures::default();
+        let safe_mode = SafeMode::default();
+        let safety_halt = SafetyHalt::default();
+        let stall_watchdog = StallWatchdog::default();
+        let item_log_filter = ItemLogFilter::default();
+        let history = HistoryReplica::new(db.clone(), modules.decoder_registry()).await;
+
+        history.spawn(task_group).await;
+
+        let live_config = LiveConfig::new(&cfg.local);
+        let api_usage = ApiUsageTracker::new(db.clone(), live_config.clone(), task_group).await;
 
         let consensus_api = ConsensusApi {
             cfg: cfg.clone(),
             invitation_codes_tracker: InvitationCodesTracker::new(db.clone(), task_group).await,
             db: db.clone(),
             modules: modules.clone(),
+            module_inits: module_inits.clone(),
             client_cfg: cfg.consensus.to_client_config(&module_inits)?,
             submission_sender: submission_sender.clone(),
             supported_api_versions: ServerConfig::supported_api_versions_summary(
@@ -177,47 +261,151 @@ impl ConsensusServer {
             ),
             latest_contribution_by_peer: Arc::clone(&latest_contribution_by_peer),
             peer_status_channels,
+            module_failures: module_failures.clone(),
+            safe_mode: safe_mode.clone(),
+            safety_halt: safety_halt.clone(),
+            stall_watchdog: stall_watchdog.clone(),
+            item_log_filter: item_log_filter.clone(),
+            live_config,
+            history,
+            requests_in_flight: RequestsInFlight::default(),
+            api_usage,
+            diagnostics_dumps: DumpRequests::default(),
+            checkpoints: None,
             consensus_status_cache: ExpiringCache::new(Duration::from_millis(500)),
         };
 
-        submit_module_consensus_items(
+        record_config_version(&db, &cfg.consensus, &consensus_api.client_cfg).await;
+
+        submit_consensus_items(
             task_group,
             db.clone(),
-            modules.clone(),
             cfg.clone(),
+            signer,
             consensus_api.client_cfg.consensus_hash(),
             submission_sender.clone(),
         )
         .await;
 
-        let api_endpoints: Vec<_> = cfg
-            .consensus
-            .api_endpoints
-            .clone()
-            .into_iter()
-            .map(|(id, node)| (id, node.url))
-            .collect();
+        submit_module_proposals(
+            task_group,
+            db.clone(),
+            modules.clone(),
+            cfg.clone(),
+            submission_sender.clone(),
+            module_failures.clone(),
+            stall_watchdog.progress(),
+        )
+        .await;
 
         let consensus_server = ConsensusServer {
             connections,
             db,
             keychain,
             client_cfg_hash: consensus_api.client_cfg.consensus_hash(),
-            api_endpoints,
+            api_endpoints: cfg.consensus.api_endpoints.clone(),
             cfg: cfg.clone(),
             submission_receiver,
             latest_contribution_by_peer,
             modules,
+            module_inits,
+            module_failures,
+            safe_mode,
+            safety_halt,
+            session_progress: stall_watchdog.progress(),
+            item_log_filter,
+            alert_hooks: vec![],
+            peer_api: None,
+            round_delay: ROUND_DELAY,
+            #[cfg(any(test, feature = "red-team"))]
+            faults: Default::default(),
         };
 
         Ok((consensus_server, consensus_api))
     }
 
+    /// Alerts the operator via the given hook when the consensus halts since
+    /// a safety invariant was violated, in addition to any hooks added before
+    pub fn with_alert_hook(mut self, alert_hook: DynAlertHook) -> Self {
+        self.alert_hooks.push(alert_hook);
+        self
+    }
+
+    /// Uses the given API instead of connecting to the API endpoints of our
+    /// peers, which allows running the consensus without any real network
+    #[cfg(test)]
+    pub(crate) fn with_peer_api(mut self, peer_api: DynGlobalApi) -> Self {
+        self.peer_api = Some(peer_api);
+        self
+    }
+
+    /// Shortens the delay between the rounds of the atomic broadcast, since
+    /// the timers of the broadcast are not driven by the tokio clock
+    #[cfg(test)]
+    pub(crate) fn with_round_delay(mut self, round_delay: Duration) -> Self {
+        self.round_delay = round_delay;
+        self
+    }
+
+    /// Makes this guardian deviate from the protocol in order to test how its
+    /// peers handle a malicious guardian
+    #[cfg(any(test, feature = "red-team"))]
+    pub(crate) fn with_faults(mut self, faults: crate::red_team::Faults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    async fn peer_api(&self, latency: Option<PeerLatencyTracker>) -> DynGlobalApi {
+        if let Some(peer_api) = &self.peer_api {
+            return peer_api.clone();
+        }
+
+        // peers that moved their API are reachable under the url of their latest update
+        let updates = self
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&ApiEndpointUpdatePrefix)
+            .await
+            .map(|(_, update)| update)
+            .collect::<Vec<_>>()
+            .await;
+
+        let api_endpoints = apply_endpoint_updates(
+            &self.api_endpoints,
+            updates,
+            &self.cfg.consensus.broadcast_public_keys,
+        );
+
+        let mut federation_api = WsFederationApi::from_endpoints(&api_endpoints);
+        if let Some(proxy) = self.cfg.local.api_proxy() {
+            federation_api = federation_api.with_socks5_proxy(proxy);
+        }
+
+        match latency {
+            Some(latency) => federation_api.with_latency_tracker(latency).into(),
+            None => federation_api.into(),
+        }
+    }
+
     pub async fn run(&self, task_handle: TaskHandle) -> anyhow::Result<()> {
-        if self.cfg.consensus.broadcast_public_keys.len() == 1 {
-            self.run_single_guardian(task_handle).await
-        } else {
-            self.run_consensus(task_handle).await
+        let consensus = async {
+            if self.cfg.consensus.broadcast_public_keys.len() == 1 {
+                self.run_single_guardian(task_handle).await
+            } else {
+                self.run_consensus(task_handle).await
+            }
+        };
+
+        // a halted consensus is not processing any items, so we can stop it at any
+        // point once an admin decided to shut down
+        tokio::select! {
+            result = consensus => result,
+            () = self.safety_halt.await_shutdown() => {
+                info!(target: LOG_CONSENSUS, "Shutting down after safety halt");
+
+                Ok(())
+            }
         }
     }
 
@@ -225,6 +413,16 @@ impl ConsensusServer {
         assert_eq!(self.cfg.consensus.broadcast_public_keys.len(), 1);
 
         while !task_handle.is_shutting_down() {
+            if self.upgrade_due().await {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to activate the scheduled upgrade");
+                break;
+            }
+
+            if self.key_rotation_due().await {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to activate the rotated keys");
+                break;
+            }
+
             let session_index = self
                 .db
                 .begin_transaction()
@@ -236,6 +434,11 @@ impl ConsensusServer {
 
             let mut item_index = self.build_block().await.items.len() as u64;
 
+            // the items are processed in the same span as by the atomic broadcast
+            let span = info_span!("session", session_index);
+
+            self.session_progress.start_session(session_index, None);
+
             let session_start_time = std::time::Instant::now();
 
             while let Ok(item) = self.submission_receiver.recv().await {
@@ -246,6 +449,7 @@ impl ConsensusServer {
                         item,
                         self.cfg.local.identity,
                     )
+                    .instrument(span.clone())
                     .await
                     .is_ok()
                 {
@@ -264,14 +468,25 @@ impl ConsensusServer {
             let signatures = BTreeMap::from_iter([(self.cfg.local.identity, signature)]);
 
             self.complete_session(session_index, SignedBlock { block, signatures })
+                .instrument(span)
                 .await;
 
-            info!(target: LOG_CONSENSUS, "Session completed");
+            info!(target: LOG_CONSENSUS, session_index, "Session completed");
 
             // if the submission channel is closed we are shutting down
             if self.submission_receiver.is_closed() {
                 break;
             }
+
+            if self.pending_module().await.is_some() {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to add the approved module");
+                break;
+            }
+
+            if self.module_config_update_due().await {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to update the module configs");
+                break;
+            }
         }
 
         info!(target: LOG_CONSENSUS, "Consensus task shut down");
@@ -286,6 +501,20 @@ impl ConsensusServer {
         self.confirm_consensus_config_hash().await?;
 
         while !task_handle.is_shutting_down() {
+            // all guardians stop before the same session, since the upgrade was
+            // scheduled by items ordered in an earlier one
+            if self.upgrade_due().await {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to activate the scheduled upgrade");
+                break;
+            }
+
+            // the rotation is scheduled the same way, so all guardians switch to
+            // the new keys before the same session
+            if self.key_rotation_due().await {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to activate the rotated keys");
+                break;
+            }
+
             let session_index = self
                 .db
                 .begin_transaction()
@@ -297,7 +526,21 @@ impl ConsensusServer {
 
             self.run_session(session_index).await?;
 
-            info!(target: LOG_CONSENSUS, "Session completed");
+            info!(target: LOG_CONSENSUS, session_index, "Session completed");
+
+            // all guardians stop after the same session, since the module was
+            // approved by an item ordered in it
+            if self.pending_module().await.is_some() {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to add the approved module");
+                break;
+            }
+
+            // the module state requiring the new config was changed by items
+            // ordered in this session as well
+            if self.module_config_update_due().await {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to update the module configs");
+                break;
+            }
         }
 
         info!(target: LOG_CONSENSUS, "Consensus task shut down");
@@ -305,9 +548,64 @@ impl ConsensusServer {
         Ok(())
     }
 
+    /// The module instance approved by all guardians, for which the consensus
+    /// stops at the end of the current session such that it can be restarted
+    /// with the module added
+    pub async fn pending_module(&self) -> Option<AddModuleProposal> {
+        self.db
+            .begin_transaction()
+            .await
+            .get_value(&PendingModuleKey)
+            .await
+    }
+
+    /// Whether a module requires its config to be replaced before the next
+    /// session, for which the consensus stops such that it can be restarted
+    /// with the new module config
+    pub async fn module_config_update_due(&self) -> bool {
+        let mut dbtx = self.db.begin_transaction().await;
+
+        match module_config_updates(&mut dbtx, &self.cfg, &self.module_inits).await {
+            Ok(updates) => !updates.is_empty(),
+            // we stop such that applying the updates fails loudly instead of
+            // diverging from the guardians that update their configs
+            Err(e) => {
+                warn!(target: LOG_CONSENSUS, "Could not check for module config updates: {e}");
+                true
+            }
+        }
+    }
+
+    /// Whether a module upgrade has to be activated before the next session,
+    /// for which the consensus stops such that it can be restarted with the
+    /// new module version
+    pub async fn upgrade_due(&self) -> bool {
+        let mut dbtx = self.db.begin_transaction().await;
+
+        let session_index = dbtx.find_by_prefix(&SignedBlockPrefix).await.count().await as u64;
+
+        !due_upgrades(&mut dbtx.dbtx_ref(), session_index)
+            .await
+            .is_empty()
+    }
+
+    /// Whether the rotated keys have to be activated before the next session,
+    /// for which the consensus stops such that it can be restarted with them
+    pub async fn key_rotation_due(&self) -> bool {
+        let mut dbtx = self.db.begin_transaction().await;
+
+        let session_index = dbtx.find_by_prefix(&SignedBlockPrefix).await.count().await as u64;
+
+        dbtx.get_value(&ScheduledKeyRotationKey)
+            .await
+            .map_or(false, |activation_session| {
+                activation_session <= session_index
+            })
+    }
+
     async fn confirm_consensus_config_hash(&self) -> anyhow::Result<()> {
         let our_hash = self.cfg.consensus.consensus_hash();
-        let federation_api = WsFederationApi::new(self.api_endpoints.clone());
+        let federation_api = self.peer_api(None).await;
 
         info!(target: LOG_CONSENSUS, "Waiting for peers config {our_hash}");
 
@@ -331,6 +629,7 @@ impl ConsensusServer {
         }
     }
 
+    #[instrument(name = "session", skip(self))]
     pub async fn run_session(&self, session_index: u64) -> anyhow::Result<()> {
         // if all nodes are correct the session will take 45 to 60 seconds. The
         // more nodes go offline the longer the session will take to complete.
@@ -339,7 +638,6 @@ impl ConsensusServer {
         // can never reach MAX_ROUNDs.
         const EXPONENTIAL_SLOWDOWN_OFFSET: usize = 3 * EXPECTED_ROUNDS_PER_SESSION;
         const MAX_ROUND: u16 = 5000;
-        const ROUND_DELAY: f64 = 250.0;
         const BASE: f64 = 1.01;
 
         // this is the minimum number of unit data that will be ordered before we reach
@@ -355,12 +653,18 @@ impl ConsensusServer {
         // such that MAX_ROUND would only be reached after roughly 350 years.
         // In case of such an attack the broadcast stops ordering any items until the
         // attack subsides as not items are ordered while the signatures are collected.
+        let round_delay = self.round_delay.as_millis() as f64;
+        let session_progress = self.session_progress.clone();
+
         let mut delay_config = aleph_bft::default_delay_config();
-        delay_config.unit_creation_delay = std::sync::Arc::new(|round_index| {
+        delay_config.unit_creation_delay = std::sync::Arc::new(move |round_index| {
+            // we are asked for the delay whenever we create a unit for a new round
+            session_progress.record_round(round_index);
+
             let delay = if round_index == 0 {
                 0.0
             } else {
-                ROUND_DELAY
+                round_delay
                     * BASE.powf(round_index.saturating_sub(EXPONENTIAL_SLOWDOWN_OFFSET) as f64)
             };
 
@@ -382,19 +686,44 @@ impl ConsensusServer {
         let (signature_sender, signature_receiver) = watch::channel(None);
         let (terminator_sender, terminator_receiver) = futures::channel::oneshot::channel();
 
-        let (loader, saver) = atomic_broadcast::backup::load_session(self.db.clone()).await;
+        self.session_progress
+            .start_session(session_index, Some(unit_data_receiver.clone()));
+
+        #[cfg(any(test, feature = "red-team"))]
+        if self.faults.equivocate {
+            let mut dbtx = self.db.begin_transaction().await;
+            dbtx.remove_by_prefix(&AlephUnitsPrefix).await;
+            dbtx.commit_tx().await;
+        }
+
+        let (loader, saver) =
+            atomic_broadcast::backup::load_session(self.db.clone(), self.safe_mode.clone()).await;
+
+        let data_provider = DataProvider::new(
+            self.submission_receiver.clone(),
+            signature_receiver,
+            self.cfg.consensus.limits,
+        );
+
+        let network = Network::new(self.connections.clone(), self.cfg.consensus.limits);
+
+        #[cfg(any(test, feature = "red-team"))]
+        let (data_provider, network) = (
+            data_provider.with_faults(self.faults),
+            network.with_faults(self.faults),
+        );
 
         let aleph_handle = spawn(
             "aleph run session",
             aleph_bft::run_session(
                 config,
                 aleph_bft::LocalIO::new(
-                    DataProvider::new(self.submission_receiver.clone(), signature_receiver),
+                    data_provider,
                     FinalizationHandler::new(unit_data_sender),
                     saver,
                     loader,
                 ),
-                Network::new(self.connections.clone()),
+                network,
                 self.keychain.clone(),
                 Spawner::new(),
                 aleph_bft_types::Terminator::create_root(terminator_receiver, "Terminator"),
@@ -437,7 +766,7 @@ impl ConsensusServer {
             tokio::select! {
                 unit_data = unit_data_receiver.recv() => {
                     if let (UnitData::Batch(bytes), peer) = unit_data? {
-                        if let Ok(items) = Vec::<ConsensusItem>::consensus_decode(&mut bytes.as_slice(), &self.decoders()){
+                        if let Ok(items) = self.cfg.consensus.limits.decode_batch(&bytes, &self.decoders()){
                             for item in items {
                                 if self.process_consensus_item(
                                     session_index,
@@ -451,6 +780,7 @@ impl ConsensusServer {
                             }
                         }
                         num_batches += 1;
+                        self.session_progress.record_batch();
                     }
                 },
                 signed_block = self.request_signed_block(session_index) => {
@@ -529,6 +859,71 @@ impl ConsensusServer {
     }
 
     pub async fn complete_session(&self, session_index: u64, signed_block: SignedBlock) {
+        self.end_module_sessions(session_index).await;
+
+        self.safe_mode
+            .retry_while_full("complete_session", || {
+                self.try_complete_session(session_index, &signed_block)
+            })
+            .await
+            .expect("Any other error panics on commit");
+    }
+
+    /// Lets the modules do the work they deferred to the end of the session
+    ///
+    /// Every module commits in its own transaction, so a module that panics is
+    /// halted by the consensus without affecting the others. Should we crash
+    /// before the session is completed, the modules end it again after the
+    /// restart, which they have to handle.
+    async fn end_module_sessions(&self, session_index: u64) {
+        for (module_instance_id, kind, module) in self.modules.iter_modules() {
+            self.safe_mode
+                .retry_while_full("end_session", || async {
+                    let mut dbtx = self.db.begin_transaction().await;
+
+                    // the state of a halted module is frozen
+                    if isolation::is_halted(&mut dbtx, module_instance_id).await {
+                        return Ok(());
+                    }
+
+                    let fee_income = dbtx.get_value(&FeeIncomeKey).await.unwrap_or_default();
+
+                    let result = catch_panic(
+                        module_instance_id,
+                        kind,
+                        "end_session",
+                        Some(session_index),
+                        async {
+                            let mut module_dbtx =
+                                dbtx.dbtx_ref_with_prefix_module_id(module_instance_id);
+
+                            module.update_fee_income(&mut module_dbtx, fee_income).await;
+                            module.end_session(&mut module_dbtx, session_index).await;
+                        },
+                    )
+                    .await;
+
+                    if let Err(panic) = result {
+                        // discard the writes of the module and only persist the halt
+                        let mut dbtx = self.db.begin_transaction().await;
+
+                        isolation::halt_module(&mut dbtx, &panic).await;
+
+                        return commit_unless_full(dbtx, "Committing module halt failed").await;
+                    }
+
+                    commit_unless_full(dbtx, "Committing the end of the session failed").await
+                })
+                .await
+                .expect("Any other error panics on commit");
+        }
+    }
+
+    async fn try_complete_session(
+        &self,
+        session_index: u64,
+        signed_block: &SignedBlock,
+    ) -> anyhow::Result<()> {
         let mut dbtx = self.db.begin_transaction().await;
 
         dbtx.remove_by_prefix(&AlephUnitsPrefix).await;
@@ -536,18 +931,23 @@ impl ConsensusServer {
         dbtx.remove_by_prefix(&AcceptedItemPrefix).await;
 
         if dbtx
-            .insert_entry(&SignedBlockKey(session_index), &signed_block)
+            .insert_entry(&SignedBlockKey(session_index), signed_block)
             .await
             .is_some()
         {
             panic!("We tried to overwrite a signed block");
         }
 
-        dbtx.commit_tx_result()
-            .await
-            .expect("This is the only place where we write to this key");
+        take_state_snapshot(&mut dbtx, &self.modules, session_index).await?;
+
+        commit_unless_full(dbtx, "This is the only place where we write to this key").await
     }
 
+    #[instrument(
+        name = "consensus_item",
+        skip_all,
+        fields(%peer, item_index, module_instance_id = tracing::field::Empty)
+    )]
     pub async fn process_consensus_item(
         &self,
         session_index: u64,
@@ -557,13 +957,61 @@ impl ConsensusServer {
     ) -> anyhow::Result<()> {
         let _timing /* logs on drop */ = timing::TimeReporter::new("process_consensus_item");
 
-        debug!("Peer {peer}: {}", super::debug::item_message(&item));
+        if let ConsensusItem::Module(module_item) = &item {
+            tracing::Span::current().record("module_instance_id", module_item.module_instance_id());
+        }
+
+        if self.item_log_filter.should_log(&item) {
+            debug!(
+                target: LOG_CONSENSUS,
+                item = %super::debug::item_message(&item),
+                "Processing consensus item"
+            );
+        }
 
         self.latest_contribution_by_peer
             .write()
             .await
             .insert(peer, session_index);
 
+        loop {
+            // if our storage is full we stop processing items until space is available
+            // again, since skipping the item would make us diverge from our peers
+            let result = self
+                .safe_mode
+                .retry_while_full("process_consensus_item", || {
+                    self.try_process_consensus_item(session_index, item_index, item.clone(), peer)
+                })
+                .await;
+
+            // for the same reason we halt if the item violates a safety invariant
+            // until an admin decides to accept the item or to shut down
+            match result {
+                Err(e) if e.is::<NegativeNetAssets>() => {
+                    let violation = e.downcast::<NegativeNetAssets>().expect("Checked above");
+
+                    self.safety_halt
+                        .halt(session_index, &violation, &self.alert_hooks)
+                        .await;
+                }
+                result => {
+                    if result.is_ok() {
+                        self.session_progress.record_accepted_item();
+                    }
+
+                    return result;
+                }
+            }
+        }
+    }
+
+    async fn try_process_consensus_item(
+        &self,
+        session_index: u64,
+        item_index: u64,
+        item: ConsensusItem,
+        peer: PeerId,
+    ) -> anyhow::Result<()> {
         let mut dbtx = self.db.begin_transaction().await;
 
         if let Some(accepted_item) = dbtx
@@ -577,38 +1025,125 @@ impl ConsensusServer {
             bail!("Consensus item was discarded before recovery");
         }
 
-        self.process_consensus_item_with_db_transaction(&mut dbtx, item.clone(), peer)
-            .await?;
+        if let Err(error) = self
+            .process_consensus_item_with_db_transaction(
+                &mut dbtx,
+                session_index,
+                item_index,
+                item.clone(),
+                peer,
+            )
+            .await
+        {
+            drop(dbtx);
+
+            // every guardian panics on the same item, so the consensus halts the module
+            let rejection = match error.downcast_ref::<ModulePanic>() {
+                Some(panic) => {
+                    self.halt_module(panic).await;
+
+                    Some(TransactionRejection::ModuleHalted(panic.module_instance_id))
+                }
+                None => error.downcast_ref::<TransactionRejection>().cloned(),
+            };
+
+            if let (ConsensusItem::Transaction(transaction), Some(rejection)) = (&item, rejection) {
+                self.record_transaction_rejection(transaction.tx_hash(), &rejection)
+                    .await;
+            }
+
+            return Err(error);
+        }
 
         dbtx.insert_entry(&AcceptedItemKey(item_index), &AcceptedItem { item, peer })
             .await;
 
         let mut audit = Audit::default();
+        let mut audit_complete = true;
+
+        // a halted module is still audited, since its frozen balance sheet is
+        // part of the net assets of the federation
+        for (module_instance_id, kind, module) in self.modules.iter_modules() {
+            if self
+                .module_failures
+                .has_failed(module_instance_id, "audit")
+                .await
+            {
+                audit_complete = false;
+                continue;
+            }
 
-        for (module_instance_id, _, module) in self.modules.iter_modules() {
-            module
-                .audit(
-                    &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id),
-                    &mut audit,
+            let audited = self
+                .module_failures
+                .call_local(
                     module_instance_id,
+                    kind,
+                    "audit",
+                    Some(session_index),
+                    module.audit(
+                        &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id),
+                        &mut audit,
+                        module_instance_id,
+                    ),
                 )
-                .await
+                .await;
+
+            audit_complete &= audited.is_some();
         }
 
-        if audit.net_assets().milli_sat < 0 {
-            panic!("Balance sheet of the fed has gone negative, this should never happen! {audit}")
+        audit_fee_income(&mut dbtx, &mut audit).await;
+
+        // the audit is local to this guardian, so without the balance sheet of
+        // every module we skip the check rather than diverge from our peers
+        if audit_complete {
+            self.safety_halt
+                .check_net_assets(audit.net_assets().milli_sat, audit.to_string())?;
         }
 
-        dbtx.commit_tx_result()
+        commit_unless_full(dbtx, "Committing consensus epoch failed").await
+    }
+
+    /// Persists why a transaction ordered by consensus was rejected, such
+    /// that the client can query the reason via the API
+    async fn record_transaction_rejection(
+        &self,
+        txid: TransactionId,
+        rejection: &TransactionRejection,
+    ) {
+        debug!(target: LOG_CONSENSUS, %txid, %rejection, "Rejected transaction");
+
+        self.safe_mode
+            .retry_while_full("record_transaction_rejection", || async {
+                let mut dbtx = self.db.begin_transaction().await;
+
+                dbtx.insert_entry(&RejectedTransactionKey(txid), rejection)
+                    .await;
+
+                commit_unless_full(dbtx, "Committing transaction rejection failed").await
+            })
             .await
-            .expect("Committing consensus epoch failed");
+            .expect("Any other error panics on commit");
+    }
 
-        Ok(())
+    /// Persists that the consensus halted the module whose call panicked
+    async fn halt_module(&self, panic: &ModulePanic) {
+        self.safe_mode
+            .retry_while_full("halt_module", || async {
+                let mut dbtx = self.db.begin_transaction().await;
+
+                isolation::halt_module(&mut dbtx, panic).await;
+
+                commit_unless_full(dbtx, "Committing module halt failed").await
+            })
+            .await
+            .expect("Any other error panics on commit");
     }
 
     async fn process_consensus_item_with_db_transaction(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
+        session_index: u64,
+        item_index: u64,
         consensus_item: ConsensusItem,
         peer_id: PeerId,
     ) -> anyhow::Result<()> {
@@ -618,13 +1153,27 @@ impl ConsensusServer {
 
         match consensus_item {
             ConsensusItem::Module(module_item) => {
-                let moduletx =
-                    &mut dbtx.dbtx_ref_with_prefix_module_id(module_item.module_instance_id());
+                let module_instance_id = module_item.module_instance_id();
 
-                self.modules
-                    .get_expect(module_item.module_instance_id())
-                    .process_consensus_item(moduletx, module_item, peer_id)
-                    .await
+                let (kind, module) = self
+                    .modules
+                    .get_with_kind(module_instance_id)
+                    .expect("Decoding rejects unknown module instance ids");
+
+                if isolation::is_halted(dbtx, module_instance_id).await {
+                    bail!("Module {module_instance_id} ({kind}) is halted");
+                }
+
+                let moduletx = &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id);
+
+                catch_panic(
+                    module_instance_id,
+                    kind,
+                    "process_consensus_item",
+                    Some(session_index),
+                    module.process_consensus_item(moduletx, module_item, peer_id),
+                )
+                .await?
             }
             ConsensusItem::Transaction(transaction) => {
                 if dbtx
@@ -635,6 +1184,8 @@ impl ConsensusServer {
                     bail!("The transaction is already accepted");
                 }
 
+                isolation::check_transaction(dbtx, &transaction).await?;
+
                 let txid = transaction.tx_hash();
                 let modules_ids = transaction
                     .outputs
@@ -642,11 +1193,47 @@ impl ConsensusServer {
                     .map(|output| output.module_instance_id())
                     .collect::<Vec<_>>();
 
-                process_transaction_with_dbtx(self.modules.clone(), dbtx, transaction).await?;
+                let upstream = transaction_dependencies(&self.modules, dbtx, &transaction).await;
+
+                process_transaction_with_dbtx(
+                    self.modules.clone(),
+                    dbtx,
+                    session_index,
+                    transaction,
+                    &self.cfg.consensus.limits.transaction,
+                    &self.cfg.consensus.fees,
+                )
+                .await?;
 
                 dbtx.insert_entry(&AcceptedTransactionKey(txid), &modules_ids)
                     .await;
 
+                dbtx.insert_entry(
+                    &AcceptedTransactionLocationKey(txid),
+                    &TransactionLocation {
+                        session_index,
+                        item_index,
+                    },
+                )
+                .await;
+
+                let upstream_txids = upstream
+                    .iter()
+                    .map(|out_point| out_point.txid)
+                    .collect::<BTreeSet<_>>();
+
+                for upstream_txid in upstream_txids {
+                    dbtx.insert_entry(&TransactionDownstreamKey(upstream_txid, txid), &())
+                        .await;
+                }
+
+                if !upstream.is_empty() {
+                    dbtx.insert_entry(&TransactionUpstreamKey(txid), &upstream)
+                        .await;
+                }
+
+                dbtx.remove_entry(&RejectedTransactionKey(txid)).await;
+
                 Ok(())
             }
             ConsensusItem::ClientConfigSignatureShare(signature_share) => {
@@ -705,6 +1292,386 @@ impl ConsensusServer {
                 )
                 .await;
 
+                Ok(())
+            }
+            ConsensusItem::FinalStateAttestationShare(attestation_share) => {
+                if dbtx.get_value(&FinalStateAttestationKey).await.is_some() {
+                    bail!("Final state attestation is already signed");
+                }
+
+                if attestation_share.attestation.federation_id != self.cfg.consensus.federation_id()
+                {
+                    bail!("Final state attestation is for a different federation");
+                }
+
+                // a guardian may replace its share if its admin changed the attestation
+                if dbtx
+                    .get_value(&FinalStateAttestationShareKey(peer_id))
+                    .await
+                    .as_ref()
+                    == Some(&attestation_share)
+                {
+                    bail!("Already received this signature share for this peer");
+                }
+
+                let pks = self.cfg.consensus.auth_pk_set.clone();
+
+                if !pks.public_key_share(peer_id.to_usize()).verify(
+                    &attestation_share.share.0,
+                    attestation_share.attestation.message(),
+                ) {
+                    bail!("Final state attestation signature share is invalid");
+                }
+
+                dbtx.insert_entry(&FinalStateAttestationShareKey(peer_id), &attestation_share)
+                    .await;
+
+                // collect the valid signature shares for the same attestation
+                let signature_shares = dbtx
+                    .find_by_prefix(&FinalStateAttestationSharePrefix)
+                    .await
+                    .filter(|(_, share)| {
+                        std::future::ready(share.attestation == attestation_share.attestation)
+                    })
+                    .map(|(key, share)| (key.0.to_usize(), share.share.0))
+                    .collect::<Vec<_>>()
+                    .await;
+
+                if signature_shares.len() <= pks.threshold() {
+                    return Ok(());
+                }
+
+                let threshold_signature = pks
+                    .combine_signatures(signature_shares.iter().map(|(peer, share)| (peer, share)))
+                    .expect("All signature shares are valid");
+
+                dbtx.remove_by_prefix(&FinalStateAttestationSharePrefix)
+                    .await;
+
+                info!(
+                    target: LOG_CONSENSUS,
+                    successor = %attestation_share.attestation.successor,
+                    "Guardians signed the final state attestation"
+                );
+
+                dbtx.insert_entry(
+                    &FinalStateAttestationKey,
+                    &SignedFinalStateAttestation {
+                        attestation: attestation_share.attestation,
+                        signature: SerdeSignature(threshold_signature),
+                    },
+                )
+                .await;
+
+                Ok(())
+            }
+            ConsensusItem::AddModule(proposal) => {
+                if dbtx.get_value(&PendingModuleKey).await.is_some() {
+                    bail!("Another module instance is about to be added");
+                }
+
+                check_proposal(&self.cfg, &proposal)?;
+
+                let id = proposal.id();
+
+                // submitting the proposal counts as the approval of the guardian
+                if dbtx
+                    .insert_entry(&ModuleApprovalKey(id, peer_id), &())
+                    .await
+                    .is_some()
+                {
+                    bail!("Already received an approval for this module from this peer");
+                }
+
+                dbtx.insert_entry(&ModuleProposalKey(id), &proposal).await;
+
+                let approvals = dbtx
+                    .find_by_prefix(&ModuleApprovalIdPrefix(id))
+                    .await
+                    .count()
+                    .await;
+
+                if approvals < self.cfg.consensus.broadcast_public_keys.len() {
+                    return Ok(());
+                }
+
+                dbtx.remove_by_prefix(&ModuleProposalPrefix).await;
+                dbtx.remove_by_prefix(&ModuleApprovalPrefix).await;
+
+                info!(
+                    target: LOG_CONSENSUS,
+                    module_instance_id = proposal.module_instance_id,
+                    kind = %proposal.consensus.kind,
+                    "All guardians approved the module, adding it at the end of the session"
+                );
+
+                dbtx.insert_entry(&PendingModuleKey, &proposal).await;
+
+                Ok(())
+            }
+            ConsensusItem::ScheduleUpgrade(upgrade) => {
+                check_upgrade(&mut dbtx.dbtx_ref(), &self.cfg, &upgrade, session_index).await?;
+
+                // submitting the upgrade counts as the schedule of the guardian
+                if dbtx
+                    .insert_entry(&UpgradeApprovalKey(upgrade, peer_id), &())
+                    .await
+                    .is_some()
+                {
+                    bail!("Already received a schedule for this upgrade from this peer");
+                }
+
+                let approvals = dbtx
+                    .find_by_prefix(&UpgradeApprovalUpgradePrefix(upgrade))
+                    .await
+                    .count()
+                    .await;
+
+                if approvals < self.cfg.consensus.broadcast_public_keys.len() {
+                    return Ok(());
+                }
+
+                // competing upgrades of the module can not be scheduled anymore
+                let void = dbtx
+                    .find_by_prefix(&UpgradeApprovalPrefix)
+                    .await
+                    .map(|(key, ())| key)
+                    .filter(|key| {
+                        std::future::ready(key.0.module_instance_id == upgrade.module_instance_id)
+                    })
+                    .collect::<Vec<_>>()
+                    .await;
+
+                for key in void {
+                    dbtx.remove_entry(&key).await;
+                }
+
+                info!(
+                    target: LOG_CONSENSUS,
+                    module_instance_id = upgrade.module_instance_id,
+                    version = upgrade.version.0,
+                    activation_session = upgrade.activation_session,
+                    "All guardians scheduled the module upgrade"
+                );
+
+                dbtx.insert_entry(&ScheduledUpgradeKey(upgrade.module_instance_id), &upgrade)
+                    .await;
+
+                Ok(())
+            }
+            ConsensusItem::KeyRotationDeal(deal) => {
+                if dbtx.get_value(&ScheduledKeyRotationKey).await.is_some() {
+                    bail!("The key rotation is already scheduled");
+                }
+
+                check_deal(&self.cfg, peer_id, &deal)?;
+
+                if dbtx
+                    .insert_entry(&KeyRotationDealKey(peer_id), &deal)
+                    .await
+                    .as_ref()
+                    == Some(&deal)
+                {
+                    bail!("Already received this deal from this peer");
+                }
+
+                // the confirmations were for the deal this one replaces
+                dbtx.remove_by_prefix(&KeyRotationConfirmationPrefix).await;
+
+                Ok(())
+            }
+            ConsensusItem::KeyRotationConfirmation(confirmation) => {
+                if dbtx.get_value(&ScheduledKeyRotationKey).await.is_some() {
+                    bail!("The key rotation is already scheduled");
+                }
+
+                ensure!(
+                    confirmation.epoch == next_epoch(&self.cfg),
+                    "The confirmation is for another key epoch"
+                );
+
+                let deals = rotation_deals(&mut dbtx.dbtx_ref()).await;
+
+                ensure!(
+                    deals.len() == self.cfg.consensus.broadcast_public_keys.len(),
+                    "Not all guardians dealt their keys yet"
+                );
+
+                ensure!(
+                    confirmation.deals == deals_hash(&deals),
+                    "The confirmation is for other deals"
+                );
+
+                if dbtx
+                    .insert_entry(&KeyRotationConfirmationKey(peer_id), &confirmation.deals)
+                    .await
+                    .is_some()
+                {
+                    bail!("Already received a confirmation of these deals from this peer");
+                }
+
+                let confirmations = dbtx
+                    .find_by_prefix(&KeyRotationConfirmationPrefix)
+                    .await
+                    .count()
+                    .await;
+
+                if confirmations < self.cfg.consensus.broadcast_public_keys.len() {
+                    return Ok(());
+                }
+
+                // all guardians complete the current session with the old keys
+                let activation_session = session_index + 1;
+
+                info!(
+                    target: LOG_CONSENSUS,
+                    epoch = confirmation.epoch,
+                    activation_session,
+                    "All guardians confirmed the key rotation"
+                );
+
+                dbtx.insert_entry(&ScheduledKeyRotationKey, &activation_session)
+                    .await;
+
+                Ok(())
+            }
+            ConsensusItem::ApiEndpointUpdate(update) => {
+                ensure!(
+                    update.peer == peer_id,
+                    "A guardian can only update its own API endpoint"
+                );
+
+                ensure!(
+                    update.verify(&self.cfg.consensus.broadcast_public_keys),
+                    "The update is not signed by the guardian"
+                );
+
+                if let Some(previous) = dbtx.get_value(&ApiEndpointUpdateKey(peer_id)).await {
+                    ensure!(
+                        previous.sequence < update.sequence,
+                        "The update does not replace the previous update"
+                    );
+                }
+
+                info!(
+                    target: LOG_CONSENSUS,
+                    %peer_id,
+                    url = %update.url.url,
+                    "Guardian moved its API endpoint"
+                );
+
+                dbtx.insert_entry(&ApiEndpointUpdateKey(peer_id), &update)
+                    .await;
+
+                Ok(())
+            }
+            ConsensusItem::FederationMetaShare(meta_share) => {
+                if meta_share.meta.federation_id != self.cfg.consensus.federation_id() {
+                    bail!("Federation meta is for a different federation");
+                }
+
+                if let Some(signed) = dbtx.get_value(&FederationMetaKey).await {
+                    ensure!(
+                        signed.meta.version < meta_share.meta.version,
+                        "Federation meta does not replace the signed federation meta"
+                    );
+                }
+
+                // a guardian may replace its share if its admin changed the metadata
+                if dbtx
+                    .get_value(&FederationMetaShareKey(peer_id))
+                    .await
+                    .as_ref()
+                    == Some(&meta_share)
+                {
+                    bail!("Already received this signature share for this peer");
+                }
+
+                let pks = self.cfg.consensus.auth_pk_set.clone();
+
+                if !pks
+                    .public_key_share(peer_id.to_usize())
+                    .verify(&meta_share.share.0, meta_share.meta.message())
+                {
+                    bail!("Federation meta signature share is invalid");
+                }
+
+                dbtx.insert_entry(&FederationMetaShareKey(peer_id), &meta_share)
+                    .await;
+
+                // collect the valid signature shares for the same metadata
+                let signature_shares = dbtx
+                    .find_by_prefix(&FederationMetaSharePrefix)
+                    .await
+                    .filter(|(_, share)| std::future::ready(share.meta == meta_share.meta))
+                    .map(|(key, share)| (key.0.to_usize(), share.share.0))
+                    .collect::<Vec<_>>()
+                    .await;
+
+                if signature_shares.len() <= pks.threshold() {
+                    return Ok(());
+                }
+
+                let threshold_signature = pks
+                    .combine_signatures(signature_shares.iter().map(|(peer, share)| (peer, share)))
+                    .expect("All signature shares are valid");
+
+                // shares for other metadata of the same or a lower version can not be signed
+                // anymore, while guardians proposing a later version submit their share again
+                dbtx.remove_by_prefix(&FederationMetaSharePrefix).await;
+
+                info!(
+                    target: LOG_CONSENSUS,
+                    version = meta_share.meta.version,
+                    "Guardians signed the federation meta"
+                );
+
+                dbtx.insert_entry(
+                    &FederationMetaKey,
+                    &SignedFederationMeta {
+                        meta: meta_share.meta,
+                        signature: SerdeSignature(threshold_signature),
+                    },
+                )
+                .await;
+
+                Ok(())
+            }
+            ConsensusItem::StateCommitment(commitment) => {
+                ensure!(
+                    commitment.session_index.checked_add(1) == Some(session_index),
+                    "The state commitment is not for the previous session"
+                );
+
+                if dbtx
+                    .get_value(&StateCommitmentKey(commitment.session_index))
+                    .await
+                    .is_some()
+                {
+                    bail!("The state after the session is committed to already");
+                }
+
+                // Whether we accept the commitment must not depend on our local state, otherwise
+                // a guardian with a diverging snapshot would disagree with the others about the
+                // outcome of the session
+                let local_commitment = dbtx
+                    .get_value(&LocalStateCommitmentKey(commitment.session_index))
+                    .await;
+
+                if local_commitment.as_ref() != Some(&commitment) {
+                    error!(
+                        target: LOG_CONSENSUS,
+                        session_index = commitment.session_index,
+                        %peer_id,
+                        ?commitment,
+                        ?local_commitment,
+                        "The accepted state commitment does not match our snapshot, we will not serve proofs for it"
+                    );
+                }
+
+                dbtx.insert_entry(&StateCommitmentKey(commitment.session_index), &commitment)
+                    .await;
+
                 Ok(())
             }
         }
@@ -734,7 +1701,8 @@ impl ConsensusServer {
             Err(error) => Err(anyhow!(error.to_string())),
         };
 
-        let federation_api = WsFederationApi::new(self.api_endpoints.clone());
+        let latency = self.load_peer_latency_history().await;
+        let federation_api = self.peer_api(Some(latency.clone())).await;
 
         loop {
             // we wait until we have stalled
@@ -748,25 +1716,56 @@ impl ConsensusServer {
                 )
                 .await;
 
+            self.persist_peer_latency_history(&latency).await;
+
             match result {
                 Ok(signed_block) => return signed_block,
                 Err(error) => tracing::error!("Error while requesting signed block: {}", error),
             }
         }
     }
+
+    async fn load_peer_latency_history(&self) -> PeerLatencyTracker {
+        let histories = self
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&PeerLatencyHistoryPrefix)
+            .await
+            .map(|(key, history)| (key.0, history))
+            .collect()
+            .await;
+
+        PeerLatencyTracker::from_histories(histories)
+    }
+
+    async fn persist_peer_latency_history(&self, latency: &PeerLatencyTracker) {
+        let mut dbtx = self.db.begin_transaction().await;
+
+        for (peer_id, history) in latency.get_all() {
+            dbtx.insert_entry(&PeerLatencyHistoryKey(peer_id), &history)
+                .await;
+        }
+
+        // the history is only a hint for the query strategies, so losing an update is
+        // not a problem
+        if let Err(e) = dbtx.commit_tx_result().await {
+            warn!(target: LOG_CONSENSUS, "Could not persist peer latency history: {e}");
+        }
+    }
 }
 
-async fn submit_module_consensus_items(
+async fn submit_consensus_items(
     task_group: &mut TaskGroup,
     db: Database,
-    modules: ServerModuleRegistry,
     cfg: ServerConfig,
+    signer: DynSigner,
     client_cfg_hash: sha256::Hash,
     submission_sender: Sender<ConsensusItem>,
 ) {
     task_group
         .spawn(
-            "submit_module_consensus_items",
+            "submit_consensus_items",
             move |task_handle| async move {
                 while !task_handle.is_shutting_down() {
                     let mut dbtx = db.begin_transaction().await;
@@ -776,31 +1775,182 @@ async fn submit_module_consensus_items(
 
                     let mut consensus_items = Vec::new();
 
-                    for (instance_id, _, module) in modules.iter_modules() {
-                        let items = module
-                            .consensus_proposal(
-                                &mut dbtx.dbtx_ref_with_prefix_module_id(instance_id),
-                                instance_id,
-                            )
-                            .await
-                            .into_iter()
-                            .map(ConsensusItem::Module);
-
-                        consensus_items.extend(items);
-                    }
-
                     // Add a signature share for the client config hash
                     let sig = dbtx.dbtx_ref().get_value(&ClientConfigSignatureKey).await;
 
                     if sig.is_none() {
                         let timing = timing::TimeReporter::new("sign client config");
-                        let share = cfg.private.auth_sks.0.sign(client_cfg_hash);
+                        let share = signer.sign_auth(client_cfg_hash.as_ref());
                         drop(timing);
                         let item =
                             ConsensusItem::ClientConfigSignatureShare(SerdeSignatureShare(share));
                         consensus_items.push(item);
                     }
 
+                    // Add a signature share for the final state attestation proposed by our admin
+                    if dbtx.get_value(&FinalStateAttestationKey).await.is_none() {
+                        if let Some(attestation) =
+                            dbtx.get_value(&ProposedFinalStateAttestationKey).await
+                        {
+                            let share = signer.sign_auth(attestation.message().as_ref());
+
+                            consensus_items.push(ConsensusItem::FinalStateAttestationShare(
+                                FinalStateAttestationShare {
+                                    attestation,
+                                    share: SerdeSignatureShare(share),
+                                },
+                            ));
+                        }
+                    }
+
+                    // Add a signature share for the federation meta proposed by our admin until
+                    // it or a later version is signed
+                    if let Some(meta) = dbtx.get_value(&ProposedFederationMetaKey).await {
+                        let signed = dbtx.get_value(&FederationMetaKey).await;
+                        let submitted = dbtx
+                            .get_value(&FederationMetaShareKey(cfg.local.identity))
+                            .await;
+
+                        if signed.map_or(true, |signed| signed.meta.version < meta.version)
+                            && submitted.map_or(true, |submitted| submitted.meta != meta)
+                        {
+                            let share = signer.sign_auth(meta.message().as_ref());
+
+                            consensus_items.push(ConsensusItem::FederationMetaShare(
+                                FederationMetaShare {
+                                    meta,
+                                    share: SerdeSignatureShare(share),
+                                },
+                            ));
+                        }
+                    }
+
+                    // Approve the module instances approved by our admin until one is added
+                    if dbtx.get_value(&PendingModuleKey).await.is_none() {
+                        let approved = dbtx
+                            .find_by_prefix(&ApprovedModulePrefix)
+                            .await
+                            .map(|(key, proposal)| (key.0, proposal))
+                            .collect::<Vec<_>>()
+                            .await;
+
+                        for (id, proposal) in approved {
+                            let key = ModuleApprovalKey(id, cfg.local.identity);
+
+                            if dbtx.get_value(&key).await.is_none() {
+                                consensus_items.push(ConsensusItem::AddModule(proposal));
+                            }
+                        }
+                    }
+
+                    // Submit the module upgrades scheduled by our admin until all guardians did
+                    let approved = dbtx
+                        .find_by_prefix(&ApprovedUpgradePrefix)
+                        .await
+                        .map(|(key, ())| key.0)
+                        .collect::<Vec<ModuleUpgrade>>()
+                        .await;
+
+                    for upgrade in approved {
+                        let key = UpgradeApprovalKey(upgrade, cfg.local.identity);
+                        let scheduled = ScheduledUpgradeKey(upgrade.module_instance_id);
+
+                        // the upgrade can not be scheduled once the session before its
+                        // activation session is complete
+                        let expired = match upgrade.activation_session.checked_sub(1) {
+                            Some(session_index) => dbtx
+                                .get_value(&SignedBlockKey(session_index))
+                                .await
+                                .is_some(),
+                            None => true,
+                        };
+
+                        if !expired
+                            && dbtx.get_value(&key).await.is_none()
+                            && dbtx.get_value(&scheduled).await.is_none()
+                        {
+                            consensus_items.push(ConsensusItem::ScheduleUpgrade(upgrade));
+                        }
+                    }
+
+                    // Submit the keys dealt after our admin requested a key rotation until they
+                    // are ordered, then confirm the deals of all guardians once we verified them
+                    if dbtx.get_value(&ScheduledKeyRotationKey).await.is_none() {
+                        if let Some(rotation) = dbtx.get_value(&OurKeyRotationKey).await {
+                            let deals = rotation_deals(&mut dbtx.dbtx_ref()).await;
+                            let confirmed = dbtx
+                                .get_value(&KeyRotationConfirmationKey(cfg.local.identity))
+                                .await;
+
+                            if deals.get(&cfg.local.identity) != Some(&rotation.deal) {
+                                consensus_items.push(ConsensusItem::KeyRotationDeal(rotation.deal));
+                            } else if deals.len() == cfg.consensus.broadcast_public_keys.len()
+                                && confirmed != Some(deals_hash(&deals))
+                            {
+                                match our_auth_share_delta(&cfg, &deals) {
+                                    Ok(_) => consensus_items.push(
+                                        ConsensusItem::KeyRotationConfirmation(
+                                            KeyRotationConfirmation {
+                                                epoch: rotation.deal.epoch,
+                                                deals: deals_hash(&deals),
+                                            },
+                                        ),
+                                    ),
+                                    Err(e) => {
+                                        warn!(target: LOG_CONSENSUS, "Not confirming the key rotation: {e}");
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    // Sign and submit the API endpoint our admin moved us to until the update is
+                    // ordered, which has to be signed again after our broadcast key was rotated
+                    if let Some(url) = dbtx.get_value(&RequestedApiEndpointKey).await {
+                        let ordered = dbtx
+                            .get_value(&ApiEndpointUpdateKey(cfg.local.identity))
+                            .await;
+
+                        let is_current = ordered.as_ref().map_or(false, |update| {
+                            update.url == url && update.verify(&cfg.consensus.broadcast_public_keys)
+                        });
+
+                        if !is_current {
+                            let peer = cfg.local.identity;
+                            let sequence = ordered.map_or(1, |update| update.sequence + 1);
+                            let message = ApiEndpointUpdate::message(peer, sequence, &url);
+
+                            consensus_items.push(ConsensusItem::ApiEndpointUpdate(
+                                ApiEndpointUpdate {
+                                    peer,
+                                    sequence,
+                                    signature: signer.sign_broadcast(&message),
+                                    url,
+                                },
+                            ));
+                        }
+                    }
+
+                    // Commit to our snapshot of the state after the last session until a
+                    // commitment to it is ordered
+                    let latest_snapshot = dbtx
+                        .find_by_prefix(&LocalStateCommitmentPrefix)
+                        .await
+                        .map(|(key, commitment)| (key.0, commitment))
+                        .collect::<BTreeMap<_, _>>()
+                        .await
+                        .pop_last();
+
+                    if let Some((session_index, commitment)) = latest_snapshot {
+                        if dbtx
+                            .get_value(&StateCommitmentKey(session_index))
+                            .await
+                            .is_none()
+                        {
+                            consensus_items.push(ConsensusItem::StateCommitment(commitment));
+                        }
+                    }
+
                     for item in consensus_items {
                         submission_sender.send(item).await.ok();
                     }
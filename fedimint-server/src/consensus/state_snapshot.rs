@@ -0,0 +1,319 @@
+//! Snapshots of the consensus state that light clients verify proofs against
+//!
+//! At the end of every session we copy the entries of the consensus state,
+//! that is the accepted transactions and the entries under the
+//! [`committed_state_prefixes`](fedimint_core::module::ServerModule::committed_state_prefixes)
+//! of every module, into a [`StateSnapshot`] and compute its
+//! [`StateCommitment`]. In the next session every guardian proposes its
+//! commitment and the first one proposed is accepted. Once the session that
+//! accepted it is signed, we serve [`StateProof`]s for the entries of the
+//! snapshot, unless the accepted commitment does not match our own. Only the latest committed snapshot and the ones
+//! not yet committed are kept, so proofs always refer to the latest committed
+//! state.
+
+use std::collections::BTreeSet;
+
+use fedimint_core::db::{
+    DatabaseTransaction, DatabaseTransactionRef, IDatabaseTransactionOpsCore,
+    IDatabaseTransactionOpsCoreTyped, MODULE_GLOBAL_PREFIX,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::module::registry::ServerModuleRegistry;
+use fedimint_core::module::ApiError;
+use fedimint_core::state_proof::{state_branch, state_leaf, StateCommitment, StateProof};
+use futures::StreamExt;
+use serde::Serialize;
+
+use crate::db::{
+    DbKeyPrefix, LocalStateCommitmentKey, LocalStateCommitmentPrefix, SignedBlockKey,
+    StateCommitmentKey, StateSnapshotKey,
+};
+
+/// The entries of the consensus state after a session, ordered by key
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize)]
+pub struct StateSnapshot {
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl StateSnapshot {
+    fn leaves(&self) -> Vec<[u8; 32]> {
+        self.entries
+            .iter()
+            .map(|(key, value)| state_leaf(key, value))
+            .collect()
+    }
+
+    pub fn commitment(&self, session_index: u64) -> StateCommitment {
+        StateCommitment::new(session_index, self.leaves())
+    }
+}
+
+/// The prefixes of the database keys that are part of the consensus state
+fn committed_prefixes(modules: &ServerModuleRegistry) -> Vec<Vec<u8>> {
+    let mut prefixes = vec![vec![DbKeyPrefix::AcceptedTransaction as u8]];
+
+    for (module_instance_id, _, module) in modules.iter_modules() {
+        for prefix in module.committed_state_prefixes() {
+            let mut key_prefix = vec![MODULE_GLOBAL_PREFIX];
+            module_instance_id
+                .consensus_encode(&mut key_prefix)
+                .expect("Writing to a vec can't fail");
+            key_prefix.push(prefix);
+
+            prefixes.push(key_prefix);
+        }
+    }
+
+    prefixes
+}
+
+/// Snapshots the consensus state after the session and removes the snapshots
+/// we do not serve proofs for anymore
+pub async fn take_state_snapshot(
+    dbtx: &mut DatabaseTransaction<'_>,
+    modules: &ServerModuleRegistry,
+    session_index: u64,
+) -> anyhow::Result<()> {
+    let mut entries = vec![];
+
+    for prefix in committed_prefixes(modules) {
+        entries.extend(
+            dbtx.raw_find_by_prefix(&prefix)
+                .await?
+                .collect::<Vec<_>>()
+                .await,
+        );
+    }
+
+    entries.sort();
+
+    let snapshot = StateSnapshot { entries };
+
+    dbtx.insert_entry(
+        &LocalStateCommitmentKey(session_index),
+        &snapshot.commitment(session_index),
+    )
+    .await;
+    dbtx.insert_entry(&StateSnapshotKey(session_index), &snapshot)
+        .await;
+
+    let snapshots = dbtx
+        .find_by_prefix(&LocalStateCommitmentPrefix)
+        .await
+        .map(|(key, _)| key.0)
+        .collect::<BTreeSet<_>>()
+        .await;
+
+    let mut latest_committed = None;
+
+    for snapshot_session in &snapshots {
+        if dbtx
+            .get_value(&StateCommitmentKey(*snapshot_session))
+            .await
+            .is_some()
+        {
+            latest_committed = Some(*snapshot_session);
+        }
+    }
+
+    for snapshot_session in snapshots {
+        if Some(snapshot_session) == latest_committed || session_index <= snapshot_session + 1 {
+            continue;
+        }
+
+        dbtx.remove_entry(&LocalStateCommitmentKey(snapshot_session))
+            .await;
+        dbtx.remove_entry(&StateSnapshotKey(snapshot_session)).await;
+    }
+
+    Ok(())
+}
+
+/// Proves that the entry with the given key is part of the latest snapshot
+/// whose commitment was accepted in a signed session
+pub async fn state_proof(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    key: Vec<u8>,
+) -> Result<StateProof, ApiError> {
+    let snapshots = dbtx
+        .find_by_prefix(&LocalStateCommitmentPrefix)
+        .await
+        .map(|(key, _)| key.0)
+        .collect::<BTreeSet<_>>()
+        .await;
+
+    for snapshot_session in snapshots.into_iter().rev() {
+        let Some(commitment) = dbtx.get_value(&StateCommitmentKey(snapshot_session)).await else {
+            continue;
+        };
+
+        let Some(signed_block) = dbtx.get_value(&SignedBlockKey(snapshot_session + 1)).await else {
+            continue;
+        };
+
+        let snapshot = dbtx
+            .get_value(&StateSnapshotKey(snapshot_session))
+            .await
+            .ok_or_else(|| ApiError::server_error("The snapshot is missing".to_string()))?;
+
+        if dbtx
+            .get_value(&LocalStateCommitmentKey(snapshot_session))
+            .await
+            .as_ref()
+            != Some(&commitment)
+        {
+            return Err(ApiError::server_error(format!(
+                "Our snapshot after session {snapshot_session} does not match the accepted commitment"
+            )));
+        }
+
+        let item = ConsensusItem::StateCommitment(commitment.clone());
+        let commitment_proof = signed_block
+            .block
+            .items
+            .iter()
+            .position(|accepted_item| accepted_item.item == item)
+            .and_then(|item_index| {
+                signed_block
+                    .block
+                    .accepted_item_proof(snapshot_session + 1, item_index as u64)
+            })
+            .ok_or_else(|| {
+                ApiError::server_error(format!(
+                    "The commitment to snapshot {snapshot_session} is missing from its block"
+                ))
+            })?;
+
+        let leaf_index = snapshot
+            .entries
+            .binary_search_by(|(entry_key, _)| entry_key.cmp(&key))
+            .map_err(|_| {
+                ApiError::not_found(format!(
+                    "The key is not part of the state after session {snapshot_session}"
+                ))
+            })?;
+
+        let branch = state_branch(snapshot.leaves(), leaf_index).expect("Leaf index is in range");
+
+        return Ok(StateProof {
+            commitment,
+            commitment_proof,
+            key,
+            value: snapshot.entries[leaf_index].1.clone(),
+            leaf_index: leaf_index as u64,
+            branch,
+        });
+    }
+
+    Err(ApiError::not_found(
+        "No snapshot of the state has been committed to yet".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::block::{AcceptedItem, Block, SignedBlock};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{
+        Database, IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped,
+    };
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::module::registry::ServerModuleRegistry;
+    use fedimint_core::state_proof::StateCommitment;
+    use fedimint_core::PeerId;
+
+    use super::{state_proof, take_state_snapshot};
+    use crate::db::{
+        LocalStateCommitmentKey, SignedBlockKey, StateCommitmentKey, StateSnapshotKey,
+    };
+
+    #[tokio::test]
+    async fn serves_proofs_of_latest_committed_snapshot() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let modules = ServerModuleRegistry::default();
+        let accepted_tx = vec![0x02, 1];
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&accepted_tx, &[0]).await.unwrap();
+        take_state_snapshot(&mut dbtx, &modules, 0).await.unwrap();
+
+        assert!(state_proof(&mut dbtx.dbtx_ref(), accepted_tx.clone())
+            .await
+            .is_err());
+
+        let commitment = dbtx
+            .get_value(&LocalStateCommitmentKey(0))
+            .await
+            .expect("Snapshot was taken");
+        let block = Block {
+            items: vec![AcceptedItem {
+                item: ConsensusItem::StateCommitment(commitment.clone()),
+                peer: PeerId::from(0),
+            }],
+        };
+
+        dbtx.insert_entry(&StateCommitmentKey(0), &commitment).await;
+        dbtx.insert_entry(
+            &SignedBlockKey(1),
+            &SignedBlock {
+                block: block.clone(),
+                signatures: Default::default(),
+            },
+        )
+        .await;
+
+        let proof = state_proof(&mut dbtx.dbtx_ref(), accepted_tx.clone())
+            .await
+            .expect("Snapshot is committed");
+
+        assert_eq!(proof.value, vec![0]);
+        assert!(proof.verify(&block.header(1)));
+        assert!(state_proof(&mut dbtx.dbtx_ref(), vec![0x02, 2])
+            .await
+            .is_err());
+
+        // the committed snapshot is kept until a later one is committed
+        take_state_snapshot(&mut dbtx, &modules, 1).await.unwrap();
+        take_state_snapshot(&mut dbtx, &modules, 2).await.unwrap();
+        take_state_snapshot(&mut dbtx, &modules, 3).await.unwrap();
+
+        assert!(dbtx.get_value(&StateSnapshotKey(0)).await.is_some());
+        assert!(dbtx.get_value(&StateSnapshotKey(1)).await.is_none());
+        assert!(dbtx.get_value(&StateSnapshotKey(2)).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn refuses_proofs_if_accepted_commitment_differs() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let modules = ServerModuleRegistry::default();
+        let accepted_tx = vec![0x02, 1];
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&accepted_tx, &[0]).await.unwrap();
+        take_state_snapshot(&mut dbtx, &modules, 0).await.unwrap();
+
+        // the other guardians committed to a state without our transaction
+        let commitment = StateCommitment::new(0, vec![]);
+        let block = Block {
+            items: vec![AcceptedItem {
+                item: ConsensusItem::StateCommitment(commitment.clone()),
+                peer: PeerId::from(1),
+            }],
+        };
+
+        dbtx.insert_entry(&StateCommitmentKey(0), &commitment).await;
+        dbtx.insert_entry(
+            &SignedBlockKey(1),
+            &SignedBlock {
+                block,
+                signatures: Default::default(),
+            },
+        )
+        .await;
+
+        assert!(state_proof(&mut dbtx.dbtx_ref(), accepted_tx)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! Watchdog for a consensus that stopped completing sessions
+//!
+//! If all guardians are correct a session takes about a minute. Once the
+//! current session has been running for much longer than that the watchdog
+//! gathers [`StallDiagnostics`], writes them to the diagnostics directory of
+//! the guardian and serves them on the admin API, such that operators can
+//! compare the view of every guardian when debugging a stuck federation.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use async_channel::Receiver;
+use fedimint_core::api::{SessionDiagnostics, StallDiagnostics};
+use fedimint_core::task::{sleep, RwLock, TaskGroup, TaskHandle};
+use fedimint_core::time::now;
+use fedimint_core::PeerId;
+use tracing::{info, warn};
+
+use crate::atomic_broadcast::data_provider::UnitData;
+use crate::net::api::ConsensusApi;
+use crate::LOG_CONSENSUS;
+
+/// How long a session may run before we consider the consensus stalled
+const STALL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often the watchdog checks the progress of the session
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Bounds on how long we ask clients to wait before resubmitting to a full
+/// submission queue
+const MIN_RETRY_AFTER: Duration = Duration::from_secs(1);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Name of the directory in the data directory the diagnostics are written to
+pub const DIAGNOSTICS_DIR: &str = "diagnostics";
+
+struct Progress {
+    session_index: u64,
+    started: Instant,
+    aleph_round: Option<u64>,
+    ordered_batches: u64,
+    accepted_items: u64,
+    ordered_queue: Option<Receiver<(UnitData, PeerId)>>,
+}
+
+/// Progress of the current session, as reported by the consensus
+#[derive(Clone)]
+pub struct SessionProgress(Arc<Mutex<Progress>>);
+
+impl Default for SessionProgress {
+    fn default() -> Self {
+        SessionProgress(Arc::new(Mutex::new(Progress {
+            session_index: 0,
+            started: Instant::now(),
+            aleph_round: None,
+            ordered_batches: 0,
+            accepted_items: 0,
+            ordered_queue: None,
+        })))
+    }
+}
+
+impl SessionProgress {
+    /// Resets the progress for a new session, whose ordered batches are
+    /// received from the given channel if we run the atomic broadcast
+    pub fn start_session(
+        &self,
+        session_index: u64,
+        ordered_queue: Option<Receiver<(UnitData, PeerId)>>,
+    ) {
+        *self.lock() = Progress {
+            session_index,
+            started: Instant::now(),
+            aleph_round: None,
+            ordered_batches: 0,
+            accepted_items: 0,
+            ordered_queue,
+        };
+    }
+
+    /// The index of the session the consensus is running
+    pub fn session_index(&self) -> u64 {
+        self.lock().session_index
+    }
+
+    pub fn record_round(&self, round: usize) {
+        self.lock().aleph_round = Some(round as u64);
+    }
+
+    pub fn record_batch(&self) {
+        self.lock().ordered_batches += 1;
+    }
+
+    pub fn record_accepted_item(&self) {
+        self.lock().accepted_items += 1;
+    }
+
+    pub fn diagnostics(&self) -> SessionDiagnostics {
+        let progress = self.lock();
+
+        SessionDiagnostics {
+            session_index: progress.session_index,
+            session_duration: progress.started.elapsed(),
+            aleph_round: progress.aleph_round,
+            ordered_batches: progress.ordered_batches,
+            accepted_items: progress.accepted_items,
+            ordered_queue_len: progress
+                .ordered_queue
+                .as_ref()
+                .map_or(0, |queue| queue.len() as u64),
+        }
+    }
+
+    /// Estimates how long the consensus takes to accept the given number of
+    /// queued items from the rate at which it accepted items this session
+    pub fn estimate_drain_time(&self, queued_items: u64) -> Duration {
+        let progress = self.lock();
+
+        let estimate = if progress.accepted_items == 0 {
+            // without any progress this session we can only ask for patience
+            MAX_RETRY_AFTER
+        } else {
+            progress
+                .started
+                .elapsed()
+                .mul_f64(queued_items as f64 / progress.accepted_items as f64)
+        };
+
+        estimate.clamp(MIN_RETRY_AFTER, MAX_RETRY_AFTER)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Progress> {
+        self.0.lock().expect("Lock poisoned")
+    }
+}
+
+/// Detects a stalled consensus and keeps the diagnostics gathered for it
+#[derive(Clone, Default)]
+pub struct StallWatchdog {
+    progress: SessionProgress,
+    diagnostics: Arc<RwLock<Option<StallDiagnostics>>>,
+}
+
+impl StallWatchdog {
+    pub fn progress(&self) -> SessionProgress {
+        self.progress.clone()
+    }
+
+    /// The diagnostics of the current session if it is stalled
+    pub async fn diagnostics(&self) -> Option<StallDiagnostics> {
+        self.diagnostics.read().await.clone()
+    }
+
+    pub async fn spawn(self, api: ConsensusApi, dir: PathBuf, task_group: &mut TaskGroup) {
+        task_group
+            .spawn("stall watchdog", move |task_handle| async move {
+                self.run(api, dir, task_handle).await
+            })
+            .await;
+    }
+
+    async fn run(&self, api: ConsensusApi, dir: PathBuf, task_handle: TaskHandle) {
+        let mut reported_session = None;
+
+        while !task_handle.is_shutting_down() {
+            sleep(CHECK_INTERVAL).await;
+
+            let (session_index, elapsed) = {
+                let progress = self.progress.lock();
+                (progress.session_index, progress.started.elapsed())
+            };
+
+            if elapsed < STALL_TIMEOUT {
+                *self.diagnostics.write().await = None;
+                continue;
+            }
+
+            let diagnostics = self.gather(&api).await;
+
+            // we write the diagnostics to disk once per stalled session, the API serves
+            // the latest ones
+            if reported_session != Some(session_index) {
+                warn!(
+                    target: LOG_CONSENSUS,
+                    session_index,
+                    duration_secs = elapsed.as_secs(),
+                    "Consensus is stalled, gathered diagnostics"
+                );
+
+                if let Err(e) = write_diagnostics(&dir, &diagnostics).await {
+                    warn!(target: LOG_CONSENSUS, error = %e, "Could not write stall diagnostics");
+                }
+
+                reported_session = Some(session_index);
+            }
+
+            *self.diagnostics.write().await = Some(diagnostics);
+        }
+    }
+
+    async fn gather(&self, api: &ConsensusApi) -> StallDiagnostics {
+        let federation_status = api.get_federation_status().await.ok();
+        let peer_health = api.get_peer_health().await;
+        let safe_mode = api.safe_mode.get().await;
+
+        let progress = self.progress.lock();
+
+        StallDiagnostics {
+            session_index: progress.session_index,
+            session_duration: progress.started.elapsed(),
+            aleph_round: progress.aleph_round,
+            ordered_batches: progress.ordered_batches,
+            accepted_items: progress.accepted_items,
+            submission_queue_len: api.submission_sender.len() as u64,
+            ordered_queue_len: progress
+                .ordered_queue
+                .as_ref()
+                .map_or(0, |queue| queue.len() as u64),
+            federation_status,
+            peer_health,
+            safe_mode,
+            safety_halt: api.safety_halt.get(),
+            created_at: now(),
+        }
+    }
+}
+
+async fn write_diagnostics(dir: &Path, diagnostics: &StallDiagnostics) -> anyhow::Result<()> {
+    let created_at = diagnostics
+        .created_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = dir.join(format!(
+        "stall-session-{}-{created_at}.json",
+        diagnostics.session_index
+    ));
+
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(&path, serde_json::to_vec_pretty(diagnostics)?).await?;
+
+    info!(target: LOG_CONSENSUS, path = %path.display(), "Wrote stall diagnostics");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{SessionProgress, MAX_RETRY_AFTER, MIN_RETRY_AFTER};
+
+    #[test]
+    fn starting_a_session_resets_the_progress() {
+        let progress = SessionProgress::default();
+
+        progress.record_round(7);
+        progress.record_batch();
+        progress.record_accepted_item();
+        progress.start_session(1, None);
+
+        let progress = progress.lock();
+
+        assert_eq!(progress.session_index, 1);
+        assert_eq!(progress.aleph_round, None);
+        assert_eq!(progress.ordered_batches, 0);
+        assert_eq!(progress.accepted_items, 0);
+    }
+
+    #[test]
+    fn drain_time_is_bounded() {
+        let progress = SessionProgress::default();
+
+        assert_eq!(progress.estimate_drain_time(10), MAX_RETRY_AFTER);
+
+        progress.record_accepted_item();
+
+        // a single item was accepted in a few microseconds
+        assert_eq!(progress.estimate_drain_time(10), MIN_RETRY_AFTER);
+
+        progress.lock().started = Instant::now() - Duration::from_secs(10);
+
+        let drain_time = progress.estimate_drain_time(3);
+        assert!(Duration::from_secs(30) <= drain_time && drain_time < Duration::from_secs(31));
+        assert_eq!(progress.estimate_drain_time(1000), MAX_RETRY_AFTER);
+    }
+}
@@ -1,16 +1,40 @@
 use std::fmt::Debug;
+use std::time::SystemTime;
 
-use fedimint_core::api::ClientConfigDownloadToken;
-use fedimint_core::block::{AcceptedItem, SignedBlock};
+use bitcoin_hashes::sha256;
+use fedimint_core::api::{ClientConfigDownloadToken, ModuleFailure, ScopedToken};
+use fedimint_core::block::{AcceptedItem, SignedBlock, TransactionLocation};
+use fedimint_core::config::{ConsensusConfigVersion, PeerUrl};
 use fedimint_core::core::ModuleInstanceId;
-use fedimint_core::db::{DatabaseVersion, MigrationMap, MODULE_GLOBAL_PREFIX};
+use fedimint_core::db::{
+    DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped, MigrationMap,
+    MODULE_GLOBAL_PREFIX,
+};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::epoch::{SerdeSignature, SerdeSignatureShare};
-use fedimint_core::{impl_db_lookup, impl_db_record, PeerId, TransactionId};
+use fedimint_core::endpoint_update::ApiEndpointUpdate;
+use fedimint_core::epoch::{ConsensusItem, SerdeSignature, SerdeSignatureShare};
+use fedimint_core::fee::FeeIncome;
+use fedimint_core::invite::{ClientJoinId, ManagedInviteCode};
+use fedimint_core::lifecycle::{AddModuleProposal, ModuleUpgrade};
+use fedimint_core::meta::{FederationMeta, FederationMetaShare, SignedFederationMeta};
+use fedimint_core::migration::{
+    FinalStateAttestation, FinalStateAttestationShare, SignedFinalStateAttestation,
+};
+use fedimint_core::module::ModuleConsensusVersion;
+use fedimint_core::query::PeerLatencyHistory;
+use fedimint_core::rotation::KeyRotationDeal;
+use fedimint_core::state_proof::StateCommitment;
+use fedimint_core::transaction::TransactionRejection;
+use fedimint_core::usage::ApiUsage;
+use fedimint_core::{impl_db_lookup, impl_db_record, OutPoint, PeerId, TransactionId};
+use futures::{FutureExt, StreamExt};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
-pub const GLOBAL_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+use crate::consensus::rotation::OurKeyRotation;
+use crate::consensus::state_snapshot::StateSnapshot;
+
+pub const GLOBAL_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -22,6 +46,44 @@ pub enum DbKeyPrefix {
     ClientConfigSignature = 0x07,
     ClientConfigSignatureShare = 0x3,
     ClientConfigDownload = 0x09,
+    AcceptedTransactionLocation = 0x0a,
+    PeerLatencyHistory = 0x0b,
+    ArchivedSessionCount = 0x0c,
+    RejectedTransaction = 0x0d,
+    FinalStateAttestationShare = 0x0e,
+    FinalStateAttestation = 0x0f,
+    ProposedFinalStateAttestation = 0x10,
+    ModuleProposal = 0x11,
+    ModuleApproval = 0x12,
+    ApprovedModule = 0x13,
+    PendingModule = 0x14,
+    UpgradeApproval = 0x15,
+    ApprovedUpgrade = 0x16,
+    ScheduledUpgrade = 0x17,
+    ActiveModuleVersion = 0x18,
+    KeyRotationDeal = 0x19,
+    KeyRotationConfirmation = 0x1a,
+    ScheduledKeyRotation = 0x1b,
+    OurKeyRotation = 0x1c,
+    ApiUsage = 0x1d,
+    ApiEndpointUpdate = 0x1e,
+    RequestedApiEndpoint = 0x1f,
+    ManagedInviteCode = 0x20,
+    InviteCodeRedemption = 0x21,
+    FederationMetaShare = 0x22,
+    FederationMeta = 0x23,
+    ProposedFederationMeta = 0x24,
+    TransactionUpstream = 0x25,
+    TransactionDownstream = 0x26,
+    ScopedToken = 0x27,
+    StateSnapshot = 0x28,
+    LocalStateCommitment = 0x29,
+    StateCommitment = 0x2a,
+    ConsensusConfigVersion = 0x2b,
+    DkgCeremony = 0x2c,
+    DkgJournal = 0x2d,
+    FeeIncome = 0x2e,
+    ModuleHalt = 0x2f,
     Module = MODULE_GLOBAL_PREFIX,
 }
 
@@ -62,6 +124,27 @@ impl_db_lookup!(
     query_prefix = AcceptedTransactionKeyPrefix
 );
 
+/// Records where in the consensus history a transaction was accepted as soon
+/// as it is processed, so that we can serve inclusion proofs once its block
+/// is signed and explorers can look up transactions without scanning every
+/// block
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct AcceptedTransactionLocationKey(pub TransactionId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct AcceptedTransactionLocationPrefix;
+
+impl_db_record!(
+    key = AcceptedTransactionLocationKey,
+    value = TransactionLocation,
+    db_prefix = DbKeyPrefix::AcceptedTransactionLocation,
+    notify_on_modify = true,
+);
+impl_db_lookup!(
+    key = AcceptedTransactionLocationKey,
+    query_prefix = AcceptedTransactionLocationPrefix
+);
+
 #[derive(Debug, Encodable, Decodable)]
 pub struct SignedBlockKey(pub u64);
 
@@ -133,8 +216,665 @@ impl_db_lookup!(
     query_prefix = ClientConfigDownloadKeyPrefix
 );
 
+/// Latency history of the API requests we made to a peer, e.g. to download
+/// signed blocks when catching up
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PeerLatencyHistoryKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerLatencyHistoryPrefix;
+
+impl_db_record!(
+    key = PeerLatencyHistoryKey,
+    value = PeerLatencyHistory,
+    db_prefix = DbKeyPrefix::PeerLatencyHistory,
+);
+impl_db_lookup!(
+    key = PeerLatencyHistoryKey,
+    query_prefix = PeerLatencyHistoryPrefix
+);
+
+/// Number of sessions whose signed block has been uploaded by the
+/// [`BlockArchiver`](crate::archive::BlockArchiver)
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ArchivedSessionCountKey;
+
+impl_db_record!(
+    key = ArchivedSessionCountKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ArchivedSessionCount,
+);
+
+/// Why a transaction ordered by consensus failed to process, so that clients
+/// can learn about the rejection instead of waiting for the transaction
+/// forever. The entry is removed if the transaction is accepted later on.
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct RejectedTransactionKey(pub TransactionId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RejectedTransactionPrefix;
+
+impl_db_record!(
+    key = RejectedTransactionKey,
+    value = TransactionRejection,
+    db_prefix = DbKeyPrefix::RejectedTransaction,
+    notify_on_modify = true,
+);
+impl_db_lookup!(
+    key = RejectedTransactionKey,
+    query_prefix = RejectedTransactionPrefix
+);
+
+/// The latest signature share of every guardian for an attestation that the
+/// federation is shutting down, until a threshold of them signed the same one
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct FinalStateAttestationShareKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FinalStateAttestationSharePrefix;
+
+impl_db_record!(
+    key = FinalStateAttestationShareKey,
+    value = FinalStateAttestationShare,
+    db_prefix = DbKeyPrefix::FinalStateAttestationShare,
+);
+impl_db_lookup!(
+    key = FinalStateAttestationShareKey,
+    query_prefix = FinalStateAttestationSharePrefix
+);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct FinalStateAttestationKey;
+
+impl_db_record!(
+    key = FinalStateAttestationKey,
+    value = SignedFinalStateAttestation,
+    db_prefix = DbKeyPrefix::FinalStateAttestation,
+    notify_on_modify = true,
+);
+
+/// The attestation our admin asked us to sign, which we propose until the
+/// federation signed an attestation
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ProposedFinalStateAttestationKey;
+
+impl_db_record!(
+    key = ProposedFinalStateAttestationKey,
+    value = FinalStateAttestation,
+    db_prefix = DbKeyPrefix::ProposedFinalStateAttestation,
+);
+
+/// The module instances proposed by any guardian, until one of them has been
+/// approved by all guardians
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ModuleProposalKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleProposalPrefix;
+
+impl_db_record!(
+    key = ModuleProposalKey,
+    value = AddModuleProposal,
+    db_prefix = DbKeyPrefix::ModuleProposal,
+);
+impl_db_lookup!(key = ModuleProposalKey, query_prefix = ModuleProposalPrefix);
+
+/// The guardians whose approval of a proposed module instance has been ordered
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ModuleApprovalKey(pub sha256::Hash, pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleApprovalPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleApprovalIdPrefix(pub sha256::Hash);
+
+impl_db_record!(
+    key = ModuleApprovalKey,
+    value = (),
+    db_prefix = DbKeyPrefix::ModuleApproval,
+);
+impl_db_lookup!(
+    key = ModuleApprovalKey,
+    query_prefix = ModuleApprovalPrefix,
+    query_prefix = ModuleApprovalIdPrefix
+);
+
+/// The module instances our admin approved, which we submit until they have
+/// been added
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ApprovedModuleKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApprovedModulePrefix;
+
+impl_db_record!(
+    key = ApprovedModuleKey,
+    value = AddModuleProposal,
+    db_prefix = DbKeyPrefix::ApprovedModule,
+);
+impl_db_lookup!(key = ApprovedModuleKey, query_prefix = ApprovedModulePrefix);
+
+/// The module instance approved by all guardians, which we add at the end of
+/// the current session
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PendingModuleKey;
+
+impl_db_record!(
+    key = PendingModuleKey,
+    value = AddModuleProposal,
+    db_prefix = DbKeyPrefix::PendingModule,
+    notify_on_modify = true,
+);
+
+/// The guardians whose schedule of a module upgrade has been ordered
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct UpgradeApprovalKey(pub ModuleUpgrade, pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct UpgradeApprovalPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct UpgradeApprovalUpgradePrefix(pub ModuleUpgrade);
+
+impl_db_record!(
+    key = UpgradeApprovalKey,
+    value = (),
+    db_prefix = DbKeyPrefix::UpgradeApproval,
+);
+impl_db_lookup!(
+    key = UpgradeApprovalKey,
+    query_prefix = UpgradeApprovalPrefix,
+    query_prefix = UpgradeApprovalUpgradePrefix
+);
+
+/// The module upgrades our admin scheduled, which we submit until they have
+/// been scheduled by all guardians
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ApprovedUpgradeKey(pub ModuleUpgrade);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApprovedUpgradePrefix;
+
+impl_db_record!(
+    key = ApprovedUpgradeKey,
+    value = (),
+    db_prefix = DbKeyPrefix::ApprovedUpgrade,
+);
+impl_db_lookup!(
+    key = ApprovedUpgradeKey,
+    query_prefix = ApprovedUpgradePrefix
+);
+
+/// The upgrade scheduled by all guardians for a module instance, which we
+/// activate before running its activation session
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ScheduledUpgradeKey(pub ModuleInstanceId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ScheduledUpgradePrefix;
+
+impl_db_record!(
+    key = ScheduledUpgradeKey,
+    value = ModuleUpgrade,
+    db_prefix = DbKeyPrefix::ScheduledUpgrade,
+    notify_on_modify = true,
+);
+impl_db_lookup!(
+    key = ScheduledUpgradeKey,
+    query_prefix = ScheduledUpgradePrefix
+);
+
+/// The consensus version of a module instance after its last upgrade, which
+/// takes precedence over the version in its config
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ActiveModuleVersionKey(pub ModuleInstanceId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ActiveModuleVersionPrefix;
+
+impl_db_record!(
+    key = ActiveModuleVersionKey,
+    value = ModuleConsensusVersion,
+    db_prefix = DbKeyPrefix::ActiveModuleVersion,
+);
+impl_db_lookup!(
+    key = ActiveModuleVersionKey,
+    query_prefix = ActiveModuleVersionPrefix
+);
+
+/// The keys dealt by the guardians for the next key rotation
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct KeyRotationDealKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct KeyRotationDealPrefix;
+
+impl_db_record!(
+    key = KeyRotationDealKey,
+    value = KeyRotationDeal,
+    db_prefix = DbKeyPrefix::KeyRotationDeal,
+);
+impl_db_lookup!(
+    key = KeyRotationDealKey,
+    query_prefix = KeyRotationDealPrefix
+);
+
+/// The hash of the deals each guardian confirmed, a new deal voids all
+/// confirmations
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct KeyRotationConfirmationKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct KeyRotationConfirmationPrefix;
+
+impl_db_record!(
+    key = KeyRotationConfirmationKey,
+    value = sha256::Hash,
+    db_prefix = DbKeyPrefix::KeyRotationConfirmation,
+);
+impl_db_lookup!(
+    key = KeyRotationConfirmationKey,
+    query_prefix = KeyRotationConfirmationPrefix
+);
+
+/// The first session signed with the new keys once all guardians confirmed
+/// the deals, before which we activate them
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ScheduledKeyRotationKey;
+
+impl_db_record!(
+    key = ScheduledKeyRotationKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ScheduledKeyRotation,
+    notify_on_modify = true,
+);
+
+/// The keys we dealt after our admin requested a key rotation, which we
+/// submit until they have been ordered
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OurKeyRotationKey;
+
+impl_db_record!(
+    key = OurKeyRotationKey,
+    value = OurKeyRotation,
+    db_prefix = DbKeyPrefix::OurKeyRotation,
+);
+
+/// The usage of the API by an API token within a day
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ApiUsageKey {
+    pub day: u64,
+    pub token: String,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApiUsageDayPrefix(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApiUsagePrefix;
+
+impl_db_record!(
+    key = ApiUsageKey,
+    value = ApiUsage,
+    db_prefix = DbKeyPrefix::ApiUsage,
+);
+impl_db_lookup!(
+    key = ApiUsageKey,
+    query_prefix = ApiUsageDayPrefix,
+    query_prefix = ApiUsagePrefix
+);
+
+/// The latest ordered update of the API endpoint of each guardian
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ApiEndpointUpdateKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApiEndpointUpdatePrefix;
+
+impl_db_record!(
+    key = ApiEndpointUpdateKey,
+    value = ApiEndpointUpdate,
+    db_prefix = DbKeyPrefix::ApiEndpointUpdate,
+);
+impl_db_lookup!(
+    key = ApiEndpointUpdateKey,
+    query_prefix = ApiEndpointUpdatePrefix
+);
+
+/// The API endpoint our admin moved us to, which we sign and submit until an
+/// update to it has been ordered
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct RequestedApiEndpointKey;
+
+impl_db_record!(
+    key = RequestedApiEndpointKey,
+    value = PeerUrl,
+    db_prefix = DbKeyPrefix::RequestedApiEndpoint,
+);
+
+/// The invite codes created by our admin by their download token
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ManagedInviteCodeKey(pub ClientConfigDownloadToken);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ManagedInviteCodePrefix;
+
+impl_db_record!(
+    key = ManagedInviteCodeKey,
+    value = ManagedInviteCode,
+    db_prefix = DbKeyPrefix::ManagedInviteCode,
+);
+impl_db_lookup!(
+    key = ManagedInviteCodeKey,
+    query_prefix = ManagedInviteCodePrefix
+);
+
+/// When a client joined with the invite code of the download token
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct InviteCodeRedemptionKey {
+    pub download_token: ClientConfigDownloadToken,
+    pub client_id: ClientJoinId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct InviteCodeRedemptionTokenPrefix(pub ClientConfigDownloadToken);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct InviteCodeRedemptionPrefix;
+
+impl_db_record!(
+    key = InviteCodeRedemptionKey,
+    value = SystemTime,
+    db_prefix = DbKeyPrefix::InviteCodeRedemption,
+);
+impl_db_lookup!(
+    key = InviteCodeRedemptionKey,
+    query_prefix = InviteCodeRedemptionTokenPrefix,
+    query_prefix = InviteCodeRedemptionPrefix
+);
+
+/// The latest signature share of every guardian for an announcement of the
+/// federation's metadata, until a threshold of them signed the same one
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct FederationMetaShareKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationMetaSharePrefix;
+
+impl_db_record!(
+    key = FederationMetaShareKey,
+    value = FederationMetaShare,
+    db_prefix = DbKeyPrefix::FederationMetaShare,
+);
+impl_db_lookup!(
+    key = FederationMetaShareKey,
+    query_prefix = FederationMetaSharePrefix
+);
+
+/// The latest announcement signed by the federation
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct FederationMetaKey;
+
+impl_db_record!(
+    key = FederationMetaKey,
+    value = SignedFederationMeta,
+    db_prefix = DbKeyPrefix::FederationMeta,
+    notify_on_modify = true,
+);
+
+/// The announcement our admin asked us to sign, which we propose until the
+/// federation signed it or a later one
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ProposedFederationMetaKey;
+
+impl_db_record!(
+    key = ProposedFederationMetaKey,
+    value = FederationMeta,
+    db_prefix = DbKeyPrefix::ProposedFederationMeta,
+);
+
+/// The outputs of earlier transactions spent by an accepted transaction, as
+/// reported by the modules of its inputs
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct TransactionUpstreamKey(pub TransactionId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransactionUpstreamPrefix;
+
+impl_db_record!(
+    key = TransactionUpstreamKey,
+    value = Vec<OutPoint>,
+    db_prefix = DbKeyPrefix::TransactionUpstream,
+);
+impl_db_lookup!(
+    key = TransactionUpstreamKey,
+    query_prefix = TransactionUpstreamPrefix
+);
+
+/// Indexes the accepted transactions spending an output of a transaction, the
+/// first id, by the spending transaction, the second id
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct TransactionDownstreamKey(pub TransactionId, pub TransactionId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransactionDownstreamPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransactionDownstreamTxidPrefix(pub TransactionId);
+
+impl_db_record!(
+    key = TransactionDownstreamKey,
+    value = (),
+    db_prefix = DbKeyPrefix::TransactionDownstream,
+);
+impl_db_lookup!(
+    key = TransactionDownstreamKey,
+    query_prefix = TransactionDownstreamPrefix,
+    query_prefix = TransactionDownstreamTxidPrefix
+);
+
+/// The module-scoped tokens created by our admin by the hash of the token
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ScopedTokenKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ScopedTokenPrefix;
+
+impl_db_record!(
+    key = ScopedTokenKey,
+    value = ScopedToken,
+    db_prefix = DbKeyPrefix::ScopedToken,
+);
+impl_db_lookup!(key = ScopedTokenKey, query_prefix = ScopedTokenPrefix);
+
+/// The consensus state after a session, kept until a later snapshot is
+/// committed to
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct StateSnapshotKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct StateSnapshotPrefix;
+
+impl_db_record!(
+    key = StateSnapshotKey,
+    value = StateSnapshot,
+    db_prefix = DbKeyPrefix::StateSnapshot,
+);
+impl_db_lookup!(key = StateSnapshotKey, query_prefix = StateSnapshotPrefix);
+
+/// The commitment to our snapshot of the state after a session, which we
+/// propose and compare the proposals of our peers to
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct LocalStateCommitmentKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LocalStateCommitmentPrefix;
+
+impl_db_record!(
+    key = LocalStateCommitmentKey,
+    value = StateCommitment,
+    db_prefix = DbKeyPrefix::LocalStateCommitment,
+);
+impl_db_lookup!(
+    key = LocalStateCommitmentKey,
+    query_prefix = LocalStateCommitmentPrefix
+);
+
+/// The commitments to the state after a session accepted by consensus
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct StateCommitmentKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct StateCommitmentPrefix;
+
+impl_db_record!(
+    key = StateCommitmentKey,
+    value = StateCommitment,
+    db_prefix = DbKeyPrefix::StateCommitment,
+);
+impl_db_lookup!(
+    key = StateCommitmentKey,
+    query_prefix = StateCommitmentPrefix
+);
+
+/// Every version of the consensus config we ran, by the order we started
+/// running them in
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ConsensusConfigVersionKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ConsensusConfigVersionPrefix;
+
+impl_db_record!(
+    key = ConsensusConfigVersionKey,
+    value = ConsensusConfigVersion,
+    db_prefix = DbKeyPrefix::ConsensusConfigVersion,
+);
+impl_db_lookup!(
+    key = ConsensusConfigVersionKey,
+    query_prefix = ConsensusConfigVersionPrefix
+);
+
+/// The DKG ceremony running while the guardian has no config yet, removed once
+/// the configs were written
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct DkgCeremonyKey;
+
+/// What a guardian needs to resume the DKG ceremony after a restart
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct PersistedDkgCeremony {
+    /// The [`crate::config::ConfigGenParams`] of the ceremony as JSON
+    pub params: String,
+    /// Seed of the randomness of our part of the ceremony
+    pub seed: [u8; 32],
+}
+
+impl_db_record!(
+    key = DkgCeremonyKey,
+    value = PersistedDkgCeremony,
+    db_prefix = DbKeyPrefix::DkgCeremony,
+);
+
+/// The messages we received during the DKG ceremony, by the key they were
+/// multiplexed with and the order we received them in
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct DkgJournalKey {
+    pub module_instance_id: ModuleInstanceId,
+    pub key: String,
+    pub index: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct DkgJournalPrefix;
+
+/// A message received during the DKG ceremony
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct DkgJournalEntry {
+    pub peer: PeerId,
+    /// The [`fedimint_core::config::DkgPeerMsg`] as JSON
+    pub message: String,
+}
+
+impl_db_record!(
+    key = DkgJournalKey,
+    value = DkgJournalEntry,
+    db_prefix = DbKeyPrefix::DkgJournal,
+);
+impl_db_lookup!(key = DkgJournalKey, query_prefix = DkgJournalPrefix);
+
+/// The fees collected from all accepted transactions and paid out to the
+/// guardians
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct FeeIncomeKey;
+
+impl_db_record!(
+    key = FeeIncomeKey,
+    value = FeeIncome,
+    db_prefix = DbKeyPrefix::FeeIncome,
+);
+
+/// The modules halted by the consensus after a call deciding the consensus
+/// panicked, every transaction using them is rejected
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ModuleHaltKey(pub ModuleInstanceId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleHaltPrefix;
+
+impl_db_record!(
+    key = ModuleHaltKey,
+    value = ModuleFailure,
+    db_prefix = DbKeyPrefix::ModuleHalt,
+);
+impl_db_lookup!(key = ModuleHaltKey, query_prefix = ModuleHaltPrefix);
+
 pub fn get_global_database_migrations<'a>() -> MigrationMap<'a> {
-    MigrationMap::new()
+    let mut migrations = MigrationMap::new();
+    migrations.insert(DatabaseVersion(0), move |dbtx| migrate_to_v1(dbtx).boxed());
+    migrations
+}
+
+/// Backfills the [`TransactionLocation`] of the transactions accepted before
+/// it was recorded while processing them
+async fn migrate_to_v1(dbtx: &mut DatabaseTransaction<'_>) -> anyhow::Result<()> {
+    let mut session_index = 0;
+
+    // we load one block at a time, since the history can be large
+    while let Some(signed_block) = dbtx.get_value(&SignedBlockKey(session_index)).await {
+        for located in signed_block.block.located_transactions(session_index) {
+            dbtx.insert_entry(
+                &AcceptedTransactionLocationKey(located.transaction.tx_hash()),
+                &located.location,
+            )
+            .await;
+        }
+
+        session_index += 1;
+    }
+
+    // the items of the current session are only part of a block once it is
+    // completed
+    let accepted_items = dbtx
+        .find_by_prefix(&AcceptedItemPrefix)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    for (AcceptedItemKey(item_index), accepted_item) in accepted_items {
+        if let ConsensusItem::Transaction(transaction) = accepted_item.item {
+            dbtx.insert_entry(
+                &AcceptedTransactionLocationKey(transaction.tx_hash()),
+                &TransactionLocation {
+                    session_index,
+                    item_index,
+                },
+            )
+            .await;
+        }
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -146,10 +886,12 @@ mod fedimint_migration_tests {
     use bitcoin::{secp256k1, KeyPair};
     use bitcoin_hashes::Hash;
     use fedimint_core::api::ClientConfigDownloadToken;
-    use fedimint_core::block::{Block, SignedBlock};
+    use fedimint_core::block::{Block, SignedBlock, TransactionLocation};
     use fedimint_core::core::{DynInput, DynOutput};
+    use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::{
-        apply_migrations, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
+        apply_migrations, Database, DatabaseTransaction, DatabaseVersion, DatabaseVersionKey,
+        IDatabaseTransactionOpsCoreTyped,
     };
     use fedimint_core::epoch::{ConsensusItem, SerdeSignature, SerdeSignatureShare};
     use fedimint_core::module::registry::ModuleDecoderRegistry;
@@ -172,9 +914,10 @@ mod fedimint_migration_tests {
     };
     use crate::db::{
         get_global_database_migrations, AcceptedItem, AcceptedItemKey, AcceptedItemPrefix,
-        AcceptedTransactionKeyPrefix, AlephUnitsKey, AlephUnitsPrefix, ClientConfigDownloadKey,
-        ClientConfigDownloadKeyPrefix, ClientConfigSignatureShareKey, DbKeyPrefix, SignedBlockKey,
-        SignedBlockPrefix, GLOBAL_DATABASE_VERSION,
+        AcceptedTransactionKeyPrefix, AcceptedTransactionLocationKey,
+        AcceptedTransactionLocationPrefix, AlephUnitsKey, AlephUnitsPrefix,
+        ClientConfigDownloadKey, ClientConfigDownloadKeyPrefix, ClientConfigSignatureShareKey,
+        DbKeyPrefix, SignedBlockKey, SignedBlockPrefix, GLOBAL_DATABASE_VERSION,
     };
 
     /// Create a database with version 0 data. The database produced is not
@@ -264,6 +1007,134 @@ mod fedimint_migration_tests {
         dbtx.commit_tx().await;
     }
 
+    fn dummy_transaction() -> Transaction {
+        let (sk, _) = secp256k1::generate_keypair(&mut OsRng);
+        let secp = secp256k1::Secp256k1::new();
+        let key_pair = KeyPair::from_secret_key(&secp, &sk);
+        let schnorr = secp.sign_schnorr(&Message::from_slice(&BYTE_32).unwrap(), &key_pair);
+
+        Transaction {
+            inputs: vec![DynInput::from_typed(
+                0,
+                DummyInput {
+                    amount: Amount::ZERO,
+                    account: key_pair.x_only_public_key().0,
+                },
+            )],
+            outputs: vec![DynOutput::from_typed(
+                0,
+                DummyOutput {
+                    amount: Amount::ZERO,
+                    account: key_pair.x_only_public_key().0,
+                },
+            )],
+            signature: Some(schnorr),
+        }
+    }
+
+    fn accepted_item(item: ConsensusItem) -> AcceptedItem {
+        AcceptedItem {
+            item,
+            peer: PeerId::from(0),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn migrate_to_v1_backfills_transaction_locations() -> anyhow::Result<()> {
+        let db = Database::new(
+            MemDatabase::new(),
+            ModuleDecoderRegistry::from_iter([(
+                0,
+                DummyCommonGen::KIND,
+                <Dummy as ServerModule>::decoder(),
+            )]),
+        );
+
+        let signature_share = || {
+            ConsensusItem::ClientConfigSignatureShare(SerdeSignatureShare(SignatureShare(
+                Standard.sample(&mut OsRng),
+            )))
+        };
+        let transactions = (0..4).map(|_| dummy_transaction()).collect::<Vec<_>>();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(&DatabaseVersionKey, &DatabaseVersion(0))
+            .await;
+
+        dbtx.insert_new_entry(
+            &SignedBlockKey(0),
+            &SignedBlock {
+                block: Block {
+                    items: vec![
+                        accepted_item(signature_share()),
+                        accepted_item(ConsensusItem::Transaction(transactions[0].clone())),
+                        accepted_item(ConsensusItem::Transaction(transactions[1].clone())),
+                    ],
+                },
+                signatures: BTreeMap::new(),
+            },
+        )
+        .await;
+
+        dbtx.insert_new_entry(
+            &SignedBlockKey(1),
+            &SignedBlock {
+                block: Block {
+                    items: vec![accepted_item(ConsensusItem::Transaction(
+                        transactions[2].clone(),
+                    ))],
+                },
+                signatures: BTreeMap::new(),
+            },
+        )
+        .await;
+
+        // the session that was in progress when the guardian was shut down
+        dbtx.insert_new_entry(&AcceptedItemKey(0), &accepted_item(signature_share()))
+            .await;
+        dbtx.insert_new_entry(
+            &AcceptedItemKey(1),
+            &accepted_item(ConsensusItem::Transaction(transactions[3].clone())),
+        )
+        .await;
+
+        dbtx.commit_tx().await;
+
+        apply_migrations(
+            &db,
+            "Global".to_string(),
+            GLOBAL_DATABASE_VERSION,
+            get_global_database_migrations(),
+        )
+        .await?;
+
+        let expected_locations = [(0, 1), (0, 2), (1, 0), (2, 1)];
+
+        let mut dbtx = db.begin_transaction().await;
+        for (transaction, (session_index, item_index)) in
+            transactions.iter().zip(expected_locations)
+        {
+            assert_eq!(
+                dbtx.get_value(&AcceptedTransactionLocationKey(transaction.tx_hash()))
+                    .await,
+                Some(TransactionLocation {
+                    session_index,
+                    item_index,
+                })
+            );
+        }
+
+        assert_eq!(
+            dbtx.find_by_prefix(&AcceptedTransactionLocationPrefix)
+                .await
+                .count()
+                .await,
+            transactions.len()
+        );
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn prepare_db_migration_snapshots() -> anyhow::Result<()> {
         prepare_db_migration_snapshot(
@@ -381,6 +1252,56 @@ mod fedimint_migration_tests {
                                 "validate_migrations was not able to read any ClientConfigDownloadKey"
                             );
                         }
+                        DbKeyPrefix::AcceptedTransactionLocation => {
+                            // backfilled by the migration to v1
+                            let locations = dbtx
+                                .find_by_prefix(&AcceptedTransactionLocationPrefix)
+                                .await
+                                .collect::<Vec<_>>()
+                                .await;
+                            ensure!(
+                                !locations.is_empty(),
+                                "validate_migrations was not able to read any AcceptedTransactionLocations"
+                            );
+                        }
+                        // Introduced after the v0 snapshot was created
+                        DbKeyPrefix::PeerLatencyHistory => {}
+                        DbKeyPrefix::ArchivedSessionCount => {}
+                        DbKeyPrefix::RejectedTransaction => {}
+                        DbKeyPrefix::FinalStateAttestationShare => {}
+                        DbKeyPrefix::FinalStateAttestation => {}
+                        DbKeyPrefix::ProposedFinalStateAttestation => {}
+                        DbKeyPrefix::ModuleProposal => {}
+                        DbKeyPrefix::ModuleApproval => {}
+                        DbKeyPrefix::ApprovedModule => {}
+                        DbKeyPrefix::PendingModule => {}
+                        DbKeyPrefix::UpgradeApproval => {}
+                        DbKeyPrefix::ApprovedUpgrade => {}
+                        DbKeyPrefix::ScheduledUpgrade => {}
+                        DbKeyPrefix::ActiveModuleVersion => {}
+                        DbKeyPrefix::KeyRotationDeal => {}
+                        DbKeyPrefix::KeyRotationConfirmation => {}
+                        DbKeyPrefix::ScheduledKeyRotation => {}
+                        DbKeyPrefix::OurKeyRotation => {}
+                        DbKeyPrefix::ApiUsage => {}
+                        DbKeyPrefix::ApiEndpointUpdate => {}
+                        DbKeyPrefix::RequestedApiEndpoint => {}
+                        DbKeyPrefix::ManagedInviteCode => {}
+                        DbKeyPrefix::InviteCodeRedemption => {}
+                        DbKeyPrefix::FederationMetaShare => {}
+                        DbKeyPrefix::FederationMeta => {}
+                        DbKeyPrefix::ProposedFederationMeta => {}
+                        DbKeyPrefix::TransactionUpstream => {}
+                        DbKeyPrefix::TransactionDownstream => {}
+                        DbKeyPrefix::ScopedToken => {}
+                        DbKeyPrefix::StateSnapshot => {}
+                        DbKeyPrefix::LocalStateCommitment => {}
+                        DbKeyPrefix::StateCommitment => {}
+                        DbKeyPrefix::ConsensusConfigVersion => {}
+                        DbKeyPrefix::DkgCeremony => {}
+                        DbKeyPrefix::DkgJournal => {}
+                        DbKeyPrefix::FeeIncome => {}
+                        DbKeyPrefix::ModuleHalt => {}
                         // Module prefix is reserved for modules, no migration testing is needed
                         DbKeyPrefix::Module => {}
                     }
@@ -0,0 +1,189 @@
+//! Dumps of the guardian's internals for post-incident analysis
+//!
+//! When the guardian receives `SIGUSR1` or an admin requests a dump via the
+//! API, the [`DiagnosticsDumper`] writes the running tasks, the depth of the
+//! consensus queues, the number of API requests being handled, the progress of
+//! the current session and the size of the database to the diagnostics
+//! directory. This allows operators to inspect a misbehaving production
+//! guardian without attaching a debugger to it.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use fedimint_core::api::{DbPrefixStats, DiagnosticsDump};
+use fedimint_core::db::{DatabaseTransactionRef, IDatabaseTransactionOpsCore};
+use fedimint_core::task::{TaskGroup, TaskHandle};
+use fedimint_core::time::now;
+use fedimint_logging::LOG_CORE;
+use futures::StreamExt;
+use strum::IntoEnumIterator;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::db::DbKeyPrefix;
+use crate::net::api::ConsensusApi;
+
+/// Requests for a dump from the [`DiagnosticsDumper`], e.g. by the admin API
+#[derive(Debug, Clone)]
+pub struct DumpRequests {
+    sender: async_channel::Sender<oneshot::Sender<DiagnosticsDump>>,
+    receiver: async_channel::Receiver<oneshot::Sender<DiagnosticsDump>>,
+}
+
+impl Default for DumpRequests {
+    fn default() -> Self {
+        let (sender, receiver) = async_channel::bounded(8);
+
+        DumpRequests { sender, receiver }
+    }
+}
+
+impl DumpRequests {
+    /// Waits until the dump has been written and returns it
+    pub async fn request(&self) -> anyhow::Result<DiagnosticsDump> {
+        let (sender, receiver) = oneshot::channel();
+
+        self.sender
+            .try_send(sender)
+            .map_err(|_| anyhow!("Too many diagnostics dumps requested at once"))?;
+
+        Ok(receiver.await?)
+    }
+}
+
+/// Background task that writes a [`DiagnosticsDump`] whenever one is
+/// requested
+pub struct DiagnosticsDumper {
+    api: ConsensusApi,
+    task_group: TaskGroup,
+    dir: PathBuf,
+}
+
+impl DiagnosticsDumper {
+    pub fn new(api: ConsensusApi, task_group: TaskGroup, dir: PathBuf) -> Self {
+        DiagnosticsDumper {
+            api,
+            task_group,
+            dir,
+        }
+    }
+
+    pub async fn spawn(self, task_group: &mut TaskGroup) {
+        task_group
+            .spawn("diagnostics dumper", move |task_handle| async move {
+                self.run(task_handle).await
+            })
+            .await;
+    }
+
+    async fn run(&self, task_handle: TaskHandle) {
+        let mut signals = match signal(SignalKind::user_defined1()) {
+            Ok(signals) => Some(signals),
+            Err(e) => {
+                warn!(target: LOG_CORE, error = %e, "Could not install the diagnostics dump signal handler");
+                None
+            }
+        };
+
+        let requests = self.api.diagnostics_dumps.receiver.clone();
+
+        loop {
+            let reply = tokio::select! {
+                _ = task_handle.make_shutdown_rx().await => break,
+                () = next_signal(&mut signals) => None,
+                Ok(reply) = requests.recv() => Some(reply),
+            };
+
+            let dump = self.gather().await;
+
+            if let Err(e) = write_dump(&self.dir, &dump).await {
+                warn!(target: LOG_CORE, error = %e, "Could not write diagnostics dump");
+            }
+
+            if let Some(reply) = reply {
+                reply.send(dump).ok();
+            }
+        }
+    }
+
+    async fn gather(&self) -> DiagnosticsDump {
+        DiagnosticsDump {
+            session: self.api.stall_watchdog.progress().diagnostics(),
+            submission_queue_len: self.api.submission_sender.len() as u64,
+            api_requests_in_flight: self.api.requests_in_flight.get(),
+            running_tasks: self.task_group.running_tasks().await,
+            db_stats: self.db_stats().await,
+            created_at: now(),
+        }
+    }
+
+    async fn db_stats(&self) -> BTreeMap<String, DbPrefixStats> {
+        let mut dbtx = self.api.db.begin_transaction().await;
+        let mut stats = BTreeMap::new();
+
+        // the module prefix is broken down by module instance below
+        for prefix in DbKeyPrefix::iter().filter(|prefix| !matches!(prefix, DbKeyPrefix::Module)) {
+            let prefix_stats = prefix_stats(&mut dbtx.dbtx_ref(), &[prefix.clone() as u8]).await;
+
+            stats.insert(format!("{prefix:?}"), prefix_stats);
+        }
+
+        for (module_instance_id, kind, _) in self.api.modules.iter_modules() {
+            let prefix_stats = prefix_stats(
+                &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id),
+                &[],
+            )
+            .await;
+
+            stats.insert(
+                format!("Module {module_instance_id} ({kind})"),
+                prefix_stats,
+            );
+        }
+
+        stats
+    }
+}
+
+/// Resolves whenever we receive `SIGUSR1`, never if we could not install the
+/// signal handler
+async fn next_signal(signals: &mut Option<Signal>) {
+    match signals {
+        Some(signals) if signals.recv().await.is_some() => {}
+        _ => std::future::pending().await,
+    }
+}
+
+async fn prefix_stats(dbtx: &mut DatabaseTransactionRef<'_>, prefix: &[u8]) -> DbPrefixStats {
+    let Ok(entries) = dbtx.raw_find_by_prefix(prefix).await else {
+        return DbPrefixStats::default();
+    };
+
+    entries
+        .fold(DbPrefixStats::default(), |stats, (key, value)| async move {
+            DbPrefixStats {
+                entries: stats.entries + 1,
+                bytes: stats.bytes + (key.len() + value.len()) as u64,
+            }
+        })
+        .await
+}
+
+async fn write_dump(dir: &Path, dump: &DiagnosticsDump) -> anyhow::Result<()> {
+    let created_at = dump
+        .created_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = dir.join(format!("dump-{created_at}.json"));
+
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(&path, serde_json::to_vec_pretty(dump)?).await?;
+
+    info!(target: LOG_CORE, path = %path.display(), "Wrote diagnostics dump");
+
+    Ok(())
+}
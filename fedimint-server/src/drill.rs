@@ -0,0 +1,345 @@
+//! Disaster drills rehearsing the loss of a guardian
+//!
+//! `fedimintd drill` runs a guardian as if it had lost its database, e.g. to a
+//! failed disk, and had to be recovered from its config files alone. Before
+//! the guardian starts, the drill asks its peers how many sessions they
+//! completed, checkpoints the config and the database and wipes the database.
+//! It then waits until the guardian caught up with these sessions, compares the
+//! headers of the most recent of them to those of its peers and writes a
+//! [`DrillReport`] to the [`DRILLS_DIR`]. Unless the drill keeps the recovered
+//! database, the checkpoint is restored on the next start. Losing several
+//! guardians is rehearsed by running the drill on several of them at once, as
+//! long as enough guardians remain to reach consensus.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+use fedimint_core::api::{IFederationApi, StatusResponse, WsFederationApi};
+use fedimint_core::block::SignedBlockHeader;
+use fedimint_core::config::PeerUrl;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCore};
+use fedimint_core::endpoint_constants::{AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT, STATUS_ENDPOINT};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::{ApiRequestErased, SerdeModuleEncoding};
+use fedimint_core::task::{sleep, timeout};
+use fedimint_core::time::now;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CORE;
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::checkpoint::Checkpoints;
+use crate::config::io::{plaintext_json_read, CONSENSUS_CONFIG, JSON_EXT, LOCAL_CONFIG};
+use crate::config::{ServerConfigConsensus, ServerConfigLocal};
+
+/// Directory in the data dir the reports of the drills are written to
+pub const DRILLS_DIR: &str = "drills";
+
+/// How often we check whether the guardian caught up
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long we wait for a response of a guardian
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of the most recent sessions whose headers we compare to our peers'
+const VERIFIED_SESSIONS: u64 = 16;
+
+/// Outcome of a disaster drill
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DrillReport {
+    pub peer: PeerId,
+    pub started_at: SystemTime,
+    /// Number of database entries we lost
+    pub wiped_entries: u64,
+    /// The highest session count of our peers when the drill started
+    pub target_session_count: u64,
+    /// Our session count when the drill ended
+    pub recovered_session_count: u64,
+    /// How long it took to catch up with our peers, if we did
+    pub recovery_time: Option<Duration>,
+    /// Sessions whose headers match the ones of our peers
+    pub verified_sessions: Vec<u64>,
+    /// Sessions whose headers differ from the ones of our peers
+    pub mismatched_sessions: Vec<u64>,
+    /// The checkpoint taken before the database was wiped
+    pub checkpoint: String,
+    /// Whether the checkpoint is restored on the next start
+    pub restore_scheduled: bool,
+}
+
+impl DrillReport {
+    /// The guardian recovered within the timeout and agrees with its peers
+    pub fn passed(&self) -> bool {
+        self.recovery_time.is_some() && self.mismatched_sessions.is_empty()
+    }
+}
+
+/// A drill on the guardian whose database was just wiped
+pub struct Drill {
+    data_dir: PathBuf,
+    checkpoints: Checkpoints,
+    our_id: PeerId,
+    api: WsFederationApi,
+    started_at: SystemTime,
+    wiped_entries: u64,
+    target_session_count: u64,
+    checkpoint: String,
+}
+
+impl Drill {
+    /// Prepares the drill, has to be called before the guardian starts
+    pub async fn start(data_dir: PathBuf, db: Database) -> anyhow::Result<Drill> {
+        let consensus: ServerConfigConsensus = plaintext_json_read(data_dir.join(CONSENSUS_CONFIG))
+            .context("The drill needs the config of a guardian that completed its setup")?;
+        let local: ServerConfigLocal = plaintext_json_read(data_dir.join(LOCAL_CONFIG))?;
+        let our_id = local.identity;
+        let mut api = WsFederationApi::from_endpoints(&consensus.api_endpoints);
+        if let Some(proxy) = local.api_proxy() {
+            api = api.with_socks5_proxy(proxy);
+        }
+
+        let target_session_count =
+            peers_session_count(&api, &consensus.api_endpoints, our_id).await?;
+
+        let started_at = now();
+        let checkpoint = format!("drill-{}", started_at.duration_since(UNIX_EPOCH)?.as_secs());
+
+        let checkpoints = Checkpoints::new(data_dir.clone(), db.clone());
+        checkpoints
+            .create(&checkpoint, "Before the disaster drill")
+            .await?;
+
+        let wiped_entries = wipe_database(&db).await?;
+
+        warn!(
+            target: LOG_CORE,
+            %checkpoint,
+            wiped_entries,
+            target_session_count,
+            "Wiped the database for the disaster drill"
+        );
+
+        Ok(Drill {
+            data_dir,
+            checkpoints,
+            our_id,
+            api,
+            started_at,
+            wiped_entries,
+            target_session_count,
+            checkpoint,
+        })
+    }
+
+    /// Waits until the guardian caught up or the timeout expired and writes
+    /// the report
+    pub async fn run(
+        self,
+        max_duration: Duration,
+        keep_recovered: bool,
+    ) -> anyhow::Result<DrillReport> {
+        let mut recovered_session_count = 0;
+        let mut recovery_time = None;
+
+        loop {
+            if let Ok(status) = self
+                .request::<StatusResponse>(
+                    self.our_id,
+                    STATUS_ENDPOINT,
+                    ApiRequestErased::default(),
+                )
+                .await
+            {
+                if let Some(federation) = status.federation {
+                    recovered_session_count = federation.session_count;
+                }
+            }
+
+            let elapsed = now().duration_since(self.started_at).unwrap_or_default();
+
+            if self.target_session_count <= recovered_session_count {
+                recovery_time = Some(elapsed);
+                break;
+            }
+
+            if max_duration <= elapsed {
+                warn!(target: LOG_CORE, recovered_session_count, "Disaster drill timed out");
+                break;
+            }
+
+            sleep(POLL_INTERVAL).await;
+        }
+
+        let mut verified_sessions = vec![];
+        let mut mismatched_sessions = vec![];
+
+        for session in self.target_session_count.saturating_sub(VERIFIED_SESSIONS)
+            ..self.target_session_count.min(recovered_session_count)
+        {
+            if self.headers_match(session).await? {
+                verified_sessions.push(session);
+            } else {
+                mismatched_sessions.push(session);
+            }
+        }
+
+        let restore_scheduled = !keep_recovered;
+
+        if restore_scheduled {
+            self.checkpoints.schedule_restore(&self.checkpoint)?;
+        }
+
+        let report = DrillReport {
+            peer: self.our_id,
+            started_at: self.started_at,
+            wiped_entries: self.wiped_entries,
+            target_session_count: self.target_session_count,
+            recovered_session_count,
+            recovery_time,
+            verified_sessions,
+            mismatched_sessions,
+            checkpoint: self.checkpoint.clone(),
+            restore_scheduled,
+        };
+
+        let dir = self.data_dir.join(DRILLS_DIR);
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join(&self.checkpoint).with_extension(JSON_EXT),
+            serde_json::to_vec_pretty(&report)?,
+        )?;
+
+        info!(target: LOG_CORE, passed = report.passed(), ?report, "Disaster drill finished");
+
+        Ok(report)
+    }
+
+    /// Compares the header of the session to the one of the first peer that
+    /// returns it
+    async fn headers_match(&self, session: u64) -> anyhow::Result<bool> {
+        let ours = self.session_header(self.our_id, session).await?;
+
+        for peer in self.api.peers() {
+            if peer == self.our_id {
+                continue;
+            }
+
+            if let Ok(theirs) = self.session_header(peer, session).await {
+                return Ok(ours == theirs);
+            }
+        }
+
+        bail!("None of our peers returned the header of session {session}")
+    }
+
+    async fn session_header(&self, peer: PeerId, session: u64) -> anyhow::Result<[u8; 40]> {
+        let header: SerdeModuleEncoding<SignedBlockHeader> = self
+            .request(
+                peer,
+                AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT,
+                ApiRequestErased::new(session),
+            )
+            .await?;
+
+        Ok(header
+            .try_into_inner(&ModuleDecoderRegistry::default())?
+            .header)
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        peer: PeerId,
+        method: &str,
+        params: ApiRequestErased,
+    ) -> anyhow::Result<T> {
+        request(&self.api, peer, method, params).await
+    }
+}
+
+async fn request<T: DeserializeOwned>(
+    api: &WsFederationApi,
+    peer: PeerId,
+    method: &str,
+    params: ApiRequestErased,
+) -> anyhow::Result<T> {
+    let response = timeout(
+        REQUEST_TIMEOUT,
+        api.request_raw(peer, method, &[params.to_json()]),
+    )
+    .await??;
+
+    Ok(serde_json::from_value(response)?)
+}
+
+/// The highest session count among the peers that respond
+async fn peers_session_count(
+    api: &WsFederationApi,
+    api_endpoints: &BTreeMap<PeerId, PeerUrl>,
+    our_id: PeerId,
+) -> anyhow::Result<u64> {
+    let mut session_counts = vec![];
+
+    for peer in api_endpoints.keys().filter(|peer| **peer != our_id) {
+        match request::<StatusResponse>(api, *peer, STATUS_ENDPOINT, ApiRequestErased::default())
+            .await
+        {
+            Ok(StatusResponse {
+                federation: Some(federation),
+                ..
+            }) => session_counts.push(federation.session_count),
+            Ok(_) => warn!(target: LOG_CORE, %peer, "Peer is not running the consensus"),
+            Err(e) => warn!(target: LOG_CORE, %peer, "Peer is unreachable: {e:?}"),
+        }
+    }
+
+    session_counts
+        .into_iter()
+        .max()
+        .context("None of our peers is running the consensus, the drill needs them to recover")
+}
+
+/// Removes every entry of the database, returns how many there were
+async fn wipe_database(db: &Database) -> anyhow::Result<u64> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let keys = dbtx
+        .raw_find_by_prefix(&[])
+        .await?
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>()
+        .await;
+
+    for key in &keys {
+        dbtx.raw_remove_entry(key).await?;
+    }
+
+    dbtx.commit_tx_result().await?;
+
+    Ok(keys.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCore};
+
+    use super::wipe_database;
+
+    #[tokio::test]
+    async fn wipes_every_entry() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[1], &[1]).await.unwrap();
+        dbtx.raw_insert_bytes(&[0xff, 0, 1], &[2]).await.unwrap();
+        dbtx.commit_tx().await;
+
+        assert_eq!(wipe_database(&db).await.unwrap(), 2);
+        assert_eq!(wipe_database(&db).await.unwrap(), 0);
+    }
+}
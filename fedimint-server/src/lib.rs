@@ -5,7 +5,8 @@ use std::fs;
 use std::net::SocketAddr;
 use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::{anyhow as format_err, Context};
 use async_trait::async_trait;
@@ -14,8 +15,11 @@ use config::ServerConfig;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::Database;
 use fedimint_core::epoch::ConsensusItem;
-use fedimint_core::module::{ApiAuth, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased};
+use fedimint_core::module::{
+    event, ApiAuth, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased,
+};
 use fedimint_core::task::TaskGroup;
+use fedimint_core::time::now;
 use fedimint_logging::{LOG_CONSENSUS, LOG_CORE, LOG_NET_API};
 use futures::FutureExt;
 use jsonrpsee::server::{ServerBuilder, ServerHandle};
@@ -23,22 +27,61 @@ use jsonrpsee::types::error::CallError;
 use jsonrpsee::types::ErrorObject;
 use jsonrpsee::RpcModule;
 use tokio::runtime::Runtime;
-use tracing::{error, info};
+use tokio::task::JoinHandle;
+use tracing::{error, info, info_span, Instrument};
 
+use crate::alerts::{AlertMonitor, Alerts};
+use crate::archive::{BlockArchiveConfig, BlockArchiver};
+use crate::checkpoint::{restore_scheduled_checkpoint, Checkpoints};
 use crate::config::api::{ConfigGenApi, ConfigGenSettings};
+use crate::config::reload::ConfigWatcher;
+use crate::consensus::conflict::{ConflictBackoff, MAX_ATTEMPTS};
+use crate::consensus::lifecycle::{
+    activate_due_upgrades, add_pending_module, apply_module_config_updates,
+};
+use crate::consensus::rotation::rotate_due_keys;
+use crate::consensus::safety_halt::CommandAlertHook;
 use crate::consensus::server::ConsensusServer;
+use crate::consensus::watchdog::DIAGNOSTICS_DIR;
+use crate::diagnostics::DiagnosticsDumper;
 use crate::net::api::{ConsensusApi, RpcHandlerCtx};
+use crate::net::api_guard::{ApiGuard, ApiLimits};
+use crate::net::api_tls::ApiTls;
 use crate::net::connect::TlsTcpConnector;
 use crate::net::peers::ReconnectPeerConnections;
+use crate::net::usage::response_bytes;
+use crate::net::well_known::spawn_well_known_server;
+use crate::signer::{DynSigner, LocalSigner, RemoteSigner, RemoteSignerConfig};
 
 pub mod atomic_broadcast;
 
+/// Alerting the operator about critical events
+pub mod alerts;
+
+/// Archival of signed blocks to object storage
+pub mod archive;
+
+/// Checkpoints of the guardian's config and database
+pub mod checkpoint;
+
 /// The actual implementation of consensus
 pub mod consensus;
 
+/// Dumps of the guardian's internals for post-incident analysis
+pub mod diagnostics;
+
+/// Disaster drills rehearsing the loss of a guardian
+pub mod drill;
+
+/// Pre-flight checks of a guardian's config before it is launched
+pub mod preflight;
+
 /// Provides interfaces for ACID-compliant data store backends
 pub mod db;
 
+/// Signing with the guardian's keys, which may be held by a remote signer
+pub mod signer;
+
 /// Networking for mint-to-mint and client-to-mint communiccation
 pub mod net;
 
@@ -48,6 +91,14 @@ pub mod config;
 /// Implementation of multiplexed peer connections
 pub mod multiplexed;
 
+/// Simulation of a federation on an in-memory network
+#[cfg(test)]
+mod simulation;
+
+/// Malicious guardians for security testing
+#[cfg(any(test, feature = "red-team"))]
+pub mod red_team;
+
 /// How long to wait before timing out client connections
 const API_ENDPOINT_TIMEOUT: Duration = Duration::from_secs(60);
 
@@ -72,44 +123,206 @@ pub struct FedimintServer {
     pub settings: ConfigGenSettings,
     /// Database shared by the API and consensus
     pub db: Database,
+    /// Bucket to archive signed blocks in, if any
+    pub archive: Option<BlockArchiveConfig>,
+    /// Address to serve the signed client config on over HTTP, if any
+    pub well_known_bind: Option<SocketAddr>,
+    /// Command alerting the operator when the consensus halts, if any
+    pub alert_command: Option<PathBuf>,
+    /// Signer daemon holding our keys, if they are not kept in memory
+    pub remote_signer: Option<RemoteSignerConfig>,
+    /// Password to decrypt the private config with, read from the password
+    /// file in the data dir if not given
+    pub password: Option<String>,
 }
 
 impl FedimintServer {
     /// Starts the `ConfigGenApi` unless configs already exist
     /// After configs are generated, start `ConsensusApi` and `ConsensusServer`
-    pub async fn run(&mut self, mut task_group: TaskGroup) -> anyhow::Result<()> {
+    pub async fn run(&mut self, task_group: TaskGroup) -> anyhow::Result<()> {
+        restore_scheduled_checkpoint(&self.data_dir, &self.db).await?;
+
         info!(target: LOG_CONSENSUS, "Starting config gen");
-        let cfg = self
+        let mut cfg = self
             .run_config_gen(task_group.make_subgroup().await)
             .await?;
 
-        let (consensus_server, consensus_api) = ConsensusServer::new(
-            cfg,
+        // the consensus stops at the end of a session in which all guardians
+        // approved a new module or before the activation session of a module
+        // upgrade or key rotation, we then restart it with the module added or
+        // upgraded or the keys rotated
+        loop {
+            if let Some(updated) = add_pending_module(
+                &self.db,
+                &cfg,
+                &self.settings.registry,
+                self.data_dir.clone(),
+            )
+            .await?
+            {
+                cfg = updated;
+            }
+
+            activate_due_upgrades(&self.db, &cfg, &self.settings.registry).await?;
+
+            if let Some(updated) = rotate_due_keys(
+                &self.db,
+                &cfg,
+                &self.settings.registry,
+                self.data_dir.clone(),
+            )
+            .await?
+            {
+                cfg = updated;
+            }
+
+            if let Some(updated) = apply_module_config_updates(
+                &self.db,
+                &cfg,
+                &self.settings.registry,
+                self.data_dir.clone(),
+            )
+            .await?
+            {
+                cfg = updated;
+            }
+
+            let consensus_group = task_group.make_subgroup().await;
+
+            if !self.run_consensus(cfg.clone(), consensus_group).await? {
+                break;
+            }
+
+            // the changes are applied after the restart and can not be undone otherwise
+            let created_at = now().duration_since(UNIX_EPOCH)?.as_secs();
+            Checkpoints::new(self.data_dir.clone(), self.db.clone())
+                .create(
+                    &format!("auto-{created_at}"),
+                    "Before applying the config changes",
+                )
+                .await?;
+
+            info!(target: LOG_CONSENSUS, "Restarting consensus to apply the config changes");
+        }
+
+        task_group.shutdown();
+
+        Ok(())
+    }
+
+    /// Runs the `ConsensusApi` and `ConsensusServer` with the given config
+    /// until the consensus stops, returns whether it stopped to add or upgrade a
+    /// module, to update a module config or to rotate the keys
+    async fn run_consensus(
+        &self,
+        cfg: ServerConfig,
+        mut task_group: TaskGroup,
+    ) -> anyhow::Result<bool> {
+        let alerts = Alerts::new(cfg.local.identity, &cfg.local.alerts);
+
+        let signer: DynSigner = match self.remote_signer.clone() {
+            Some(remote_signer) => Arc::new(RemoteSigner::connect(remote_signer, &cfg).await?),
+            None => Arc::new(LocalSigner::new(&cfg)),
+        };
+
+        let (consensus_server, mut consensus_api) = ConsensusServer::new(
+            cfg.clone(),
             self.db.clone(),
             self.settings.registry.clone(),
+            signer,
             &mut task_group,
         )
         .await
         .unwrap();
 
+        let consensus_server = consensus_server.with_alert_hook(Arc::new(alerts.clone()));
+
+        #[cfg(feature = "red-team")]
+        let consensus_server = consensus_server.with_faults(red_team::Faults::from_env()?);
+
+        let consensus_server = match self.alert_command.clone() {
+            Some(command) => {
+                consensus_server.with_alert_hook(Arc::new(CommandAlertHook { command }))
+            }
+            None => consensus_server,
+        };
+
+        if let Some(archive) = self.archive.clone() {
+            BlockArchiver::new(archive, self.db.clone(), consensus_api.client_cfg.clone())
+                .spawn(&mut task_group)
+                .await;
+        }
+
+        if let Some(well_known_bind) = self.well_known_bind {
+            spawn_well_known_server(
+                well_known_bind,
+                self.db.clone(),
+                consensus_api.client_cfg.clone(),
+                &mut task_group,
+            )
+            .await?;
+        }
+
+        AlertMonitor::new(alerts, consensus_api.clone(), self.data_dir.clone())
+            .spawn(&mut task_group)
+            .await;
+
+        ConfigWatcher::new(
+            self.data_dir.clone(),
+            cfg,
+            consensus_api.live_config.clone(),
+        )
+        .spawn(&mut task_group)
+        .await;
+
+        DiagnosticsDumper::new(
+            consensus_api.clone(),
+            task_group.clone(),
+            self.data_dir.join(DIAGNOSTICS_DIR),
+        )
+        .spawn(&mut task_group)
+        .await;
+
+        consensus_api
+            .stall_watchdog
+            .clone()
+            .spawn(
+                consensus_api.clone(),
+                self.data_dir.join(DIAGNOSTICS_DIR),
+                &mut task_group,
+            )
+            .await;
+
+        consensus_api.checkpoints = Some(Checkpoints::new(self.data_dir.clone(), self.db.clone()));
+
         info!(target: LOG_CONSENSUS, "Starting consensus API");
 
-        let handler = Self::spawn_consensus_api(consensus_api, true).await;
+        let api_tls = cfg.local.api_tls.clone().map(ApiTls::new).transpose()?;
+
+        if let Some(api_tls) = &api_tls {
+            api_tls.spawn_renewal(&mut task_group).await;
+        }
+
+        Self::spawn_reloading_consensus_api(consensus_api, api_tls, &mut task_group).await;
 
         consensus_server.run(task_group.make_handle()).await?;
 
-        handler.stop().await;
+        let restart = consensus_server.pending_module().await.is_some()
+            || consensus_server.upgrade_due().await
+            || consensus_server.key_rotation_due().await
+            || consensus_server.module_config_update_due().await;
 
         info!(target: LOG_CONSENSUS, "Shutting down tasks");
-        task_group.shutdown();
+        task_group.shutdown_join_all(None).await?;
 
-        Ok(())
+        Ok(restart)
     }
 
     /// Generates the `ServerConfig`
     ///
-    /// If a local password file exists, will try to read the configs from the
-    /// filesystem.  Otherwise, it will start the `ConfigGenApi`.
+    /// If we were given a password or a local password file exists, will try to
+    /// read the configs from the filesystem.  Otherwise, it will start the
+    /// `ConfigGenApi`, which also accepts the password of existing configs.
     async fn run_config_gen(&self, mut task_group: TaskGroup) -> anyhow::Result<ServerConfig> {
         let (config_generated_tx, mut config_generated_rx) = tokio::sync::mpsc::channel(1);
         let config_gen = ConfigGenApi::new(
@@ -121,11 +334,16 @@ impl FedimintServer {
         );
 
         // Attempt get the config with local password, otherwise start config gen
-        if let Ok(password) = fs::read_to_string(self.data_dir.join(PLAINTEXT_PASSWORD)) {
+        let password = match self.password.clone() {
+            Some(password) => Some(password),
+            None => fs::read_to_string(self.data_dir.join(PLAINTEXT_PASSWORD)).ok(),
+        };
+
+        if let Some(password) = password {
             config_gen
                 .set_password(ApiAuth(password.clone()))
                 .map_err(|_| format_err!("Unable to use local password"))?;
-            info!(target: LOG_CONSENSUS, "Setting password from local file");
+            info!(target: LOG_CONSENSUS, "Setting password from options or local file");
 
             if config_gen.start_consensus(ApiAuth(password)).await.is_ok() {
                 info!(target: LOG_CONSENSUS, "Configs found locally");
@@ -133,10 +351,23 @@ impl FedimintServer {
             }
         }
 
+        if config_gen.resume_dkg().await? {
+            info!(target: LOG_CONSENSUS, "Resuming the DKG that was running before the restart");
+        }
+
+        let api_tls = self.settings.api_tls.clone().map(ApiTls::new).transpose()?;
         let mut rpc_module = RpcHandlerCtx::new_module(config_gen);
         Self::attach_endpoints(&mut rpc_module, config::api::server_endpoints(), None);
-        let handler =
-            Self::spawn_api("config-gen", &self.settings.api_bind, rpc_module, 10, true).await;
+        let handler = Self::spawn_api(
+            "config-gen",
+            &self.settings.api_bind,
+            rpc_module,
+            10,
+            ApiLimits::default(),
+            api_tls,
+            true,
+        )
+        .await;
 
         let cfg = config_generated_rx.recv().await.expect("should not close");
         handler.stop().await;
@@ -147,13 +378,21 @@ impl FedimintServer {
     /// running.
     pub async fn spawn_consensus_api(
         api: ConsensusApi,
+        api_tls: Option<ApiTls>,
         force_shutdown: bool,
     ) -> FedimintApiHandler {
-        let cfg = &api.cfg.local;
-        let mut rpc_module = RpcHandlerCtx::new_module(api.clone());
+        let cfg = api.live_config.get();
+        let mut rpc_module = RpcHandlerCtx::new_tracked_module(
+            api.clone(),
+            api.requests_in_flight.clone(),
+            cfg.api_limits.max_requests_in_flight,
+            Some(api.api_usage.clone()),
+        );
         Self::attach_endpoints(&mut rpc_module, net::api::server_endpoints(), None);
         for (id, _, module) in api.modules.iter_modules() {
-            Self::attach_endpoints(&mut rpc_module, module.api_endpoints(), Some(id));
+            let mut endpoints = module.api_endpoints();
+            endpoints.push(event::await_events_endpoint());
+            Self::attach_endpoints(&mut rpc_module, endpoints, Some(id));
         }
 
         Self::spawn_api(
@@ -161,26 +400,86 @@ impl FedimintServer {
             &cfg.api_bind,
             rpc_module,
             cfg.max_connections,
+            cfg.api_limits,
+            api_tls,
             force_shutdown,
         )
         .await
     }
 
+    /// Runs the `ConsensusApi` until the task group shuts down, restarting it
+    /// whenever the bind address or connection limits are reloaded
+    async fn spawn_reloading_consensus_api(
+        api: ConsensusApi,
+        api_tls: Option<ApiTls>,
+        task_group: &mut TaskGroup,
+    ) {
+        task_group
+            .spawn("consensus api", move |task_handle| async move {
+                let mut live_config = api.live_config.subscribe();
+                let mut running = live_config.borrow_and_update().clone();
+                let mut handler =
+                    Self::spawn_consensus_api(api.clone(), api_tls.clone(), true).await;
+
+                loop {
+                    tokio::select! {
+                        _ = task_handle.make_shutdown_rx().await => break,
+                        changed = live_config.changed() => {
+                            if changed.is_err() {
+                                break;
+                            }
+
+                            let reloaded = live_config.borrow_and_update().clone();
+
+                            if (reloaded.api_bind, reloaded.max_connections, reloaded.api_limits)
+                                == (running.api_bind, running.max_connections, running.api_limits)
+                            {
+                                continue;
+                            }
+
+                            info!(
+                                target: LOG_NET_API,
+                                api_bind = %reloaded.api_bind,
+                                max_connections = reloaded.max_connections,
+                                "Restarting consensus API"
+                            );
+
+                            handler.stop().await;
+                            handler =
+                                Self::spawn_consensus_api(api.clone(), api_tls.clone(), true)
+                                    .await;
+                            running = reloaded;
+                        }
+                    }
+                }
+
+                handler.stop().await;
+            })
+            .await;
+    }
+
     /// Spawns an API server
     ///
     /// `force_shutdown` runs the API in a new runtime that the
     /// `FedimintApiHandler` can force to shutdown, otherwise the task cannot
-    /// easily be killed.
+    /// easily be killed. The API server listens on localhost behind an
+    /// [`ApiGuard`] on `api_bind`, which enforces the `limits` and terminates
+    /// TLS if `api_tls` is set.
     async fn spawn_api<T>(
         name: &'static str,
         api_bind: &SocketAddr,
         module: RpcModule<RpcHandlerCtx<T>>,
         max_connections: u32,
+        limits: ApiLimits,
+        api_tls: Option<ApiTls>,
         force_shutdown: bool,
     ) -> FedimintApiHandler {
         let mut builder = ServerBuilder::new()
             .max_connections(max_connections)
-            .ping_interval(Duration::from_secs(10));
+            .ping_interval(Duration::from_secs(10))
+            // lets clients send several requests in one JSON-RPC batch to save round trips on
+            // high-latency links
+            .batch_requests_supported(true);
 
         let runtime = if force_shutdown {
             let runtime = Runtime::new().expect("Creates runtime");
@@ -190,17 +489,44 @@ impl FedimintServer {
             None
         };
 
-        let handle = builder
-            .build(&api_bind.to_string())
+        // clients connect through the guard in front of the API server
+        let server_bind = SocketAddr::from(([127, 0, 0, 1], 0));
+
+        let server = builder
+            .build(&server_bind.to_string())
             .await
+            .context(format!("Bind address: {server_bind}"))
+            .context(format!("API name: {name}"))
+            .expect("Could not build API server");
+
+        let scheme = if api_tls.is_some() { "wss" } else { "ws" };
+        let backend = server.local_addr().expect("API server has an address");
+        let listener = std::net::TcpListener::bind(api_bind)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
             .context(format!("Bind address: {api_bind}"))
             .context(format!("API name: {name}"))
-            .expect("Could not build API server")
-            .start(module)
-            .expect("Could not start API server");
-        info!(target: LOG_NET_API, "Starting api on ws://{api_bind}");
+            .expect("Could not bind API listener");
+
+        let serve = async move {
+            let listener = tokio::net::TcpListener::from_std(listener).expect("Registers listener");
+            ApiGuard::new(limits, api_tls)
+                .serve(listener, backend)
+                .await;
+        };
 
-        FedimintApiHandler { handle, runtime }
+        let guard = match &runtime {
+            Some(runtime) => runtime.spawn(serve),
+            None => tokio::spawn(serve),
+        };
+
+        let handle = server.start(module).expect("Could not start API server");
+        info!(target: LOG_NET_API, "Starting api on {scheme}://{api_bind}");
+
+        FedimintApiHandler {
+            handle,
+            runtime,
+            guard,
+        }
     }
 
     /// Attaches `endpoints` to the `RpcModule`
@@ -233,22 +559,66 @@ impl FedimintServer {
 
             rpc_module
                 .register_async_method(path, move |params, rpc_state| async move {
+                    let request_bytes = params.as_str().map_or(0, |params| params.len() as u64);
                     let params = params.one::<serde_json::Value>()?;
                     let rpc_context = &rpc_state.rpc_context;
+                    let _in_flight = rpc_state
+                        .requests_in_flight
+                        .try_start(rpc_state.max_requests_in_flight)
+                        .ok_or_else(|| {
+                            let e = ApiError::overloaded("Too many requests in flight".to_string());
+                            jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
+                                e.code, e.message, e.data,
+                            )))
+                        })?;
 
                     // Using AssertUnwindSafe here is far from ideal. In theory this means we could
                     // end up with an inconsistent state in theory. In practice most API functions
                     // are only reading and the few that do write anything are atomic. Lastly, this
                     // is only the last line of defense
                     AssertUnwindSafe(tokio::time::timeout(API_ENDPOINT_TIMEOUT, async {
-                        let request = serde_json::from_value(params)
+                        let request: ApiRequestErased = serde_json::from_value(params)
                             .map_err(|e| ApiError::bad_request(e.to_string()))?;
-                        let (state, context) =
-                            rpc_context.context(&request, module_instance_id).await;
 
-                        (handler)(state, context, request).await
+                        let token = match &rpc_state.api_usage {
+                            Some(api_usage) => api_usage.token(&request.auth),
+                            None => None,
+                        };
+
+                        if let (Some(api_usage), Some((name, quota))) =
+                            (&rpc_state.api_usage, &token)
+                        {
+                            api_usage.check_quota(name, quota)?;
+                        }
+
+                        let mut conflicts = ConflictBackoff::new(path);
+
+                        // the writes of a conflicting request were discarded, so we can
+                        // handle it again on the new state of the database
+                        let response = loop {
+                            let (state, context) =
+                                rpc_context.context(&request, module_instance_id).await;
+
+                            match (handler)(state, context, request.clone()).await {
+                                Err(e)
+                                    if e.is_conflict()
+                                        && conflicts.conflicts() + 1 < MAX_ATTEMPTS =>
+                                {
+                                    conflicts.wait().await;
+                                }
+                                response => break response,
+                            }
+                        };
+
+                        if let (Some(api_usage), Some((name, _))) = (&rpc_state.api_usage, token) {
+                            let response_bytes = response.as_ref().map_or(0, response_bytes);
+                            api_usage.record(name, path, request_bytes, response_bytes);
+                        }
+
+                        response
                     }))
                     .catch_unwind()
+                    .instrument(info_span!("api_request", path, ?module_instance_id))
                     .await
                     .map_err(|_| {
                         error!(
@@ -266,7 +636,7 @@ impl FedimintServer {
                     })?
                     .map_err(|e| {
                         jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
-                            e.code, e.message, None::<()>,
+                            e.code, e.message, e.data,
                         )))
                     })
                 })
@@ -278,11 +648,14 @@ impl FedimintServer {
 pub struct FedimintApiHandler {
     runtime: Option<Runtime>,
     handle: ServerHandle,
+    /// Task accepting the connections in front of the API server
+    guard: JoinHandle<()>,
 }
 
 impl FedimintApiHandler {
     /// Attempts to stop the API
     pub async fn stop(self) {
+        self.guard.abort();
         let _ = self.handle.stop();
         if let Some(runtime) = self.runtime {
             runtime.shutdown_background();
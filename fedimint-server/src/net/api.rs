@@ -1,77 +1,214 @@
 //! Implements the client API through which users interact with the federation
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use async_channel::TrySendError;
 use async_trait::async_trait;
 use bitcoin_hashes::sha256;
 use fedimint_core::api::{
-    ClientConfigDownloadToken, FederationStatus, InviteCode, PeerConnectionStatus, PeerStatus,
-    ServerStatus, StatusResponse,
+    ClientConfigDownloadToken, ConsensusItemLogging, CreateScopedTokenRequest, DiagnosticsDump,
+    FederationBusy, FederationStatus, GuardianCheckpoint, InviteCode, ModuleFailure,
+    PeerConnectionStatus, PeerHealth, PeerStatus, SafetyHaltOverride, SafetyViolation, ScopedToken,
+    ServerStatus, SessionRange, SnapshotResponse, StallDiagnostics, StatusResponse, StorageFailure,
 };
 use fedimint_core::backup::{ClientBackupKey, ClientBackupSnapshot};
-use fedimint_core::block::{Block, SignedBlock};
-use fedimint_core::config::{ClientConfig, ClientConfigResponse, JsonWithKind};
+use fedimint_core::block::{
+    AcceptedItemProof, Block, LocatedTransaction, SignedBlock, SignedBlockHeader,
+    TransactionLocation, TransactionReceipt,
+};
+use fedimint_core::config::{
+    ClientConfig, ClientConfigResponse, ConfigBundle, ConsensusConfigVersion, JsonWithKind,
+    PeerUrl, ServerModuleInitRegistry,
+};
 use fedimint_core::core::backup::SignedBackupRequest;
 use fedimint_core::core::{DynOutputOutcome, ModuleInstanceId};
+use fedimint_core::db::conflicts::ConflictReport;
 use fedimint_core::db::{
     Database, DatabaseTransaction, DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped,
 };
 use fedimint_core::endpoint_constants::{
-    AUDIT_ENDPOINT, AUTH_ENDPOINT, AWAIT_BLOCK_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT,
-    AWAIT_SIGNED_BLOCK_ENDPOINT, BACKUP_ENDPOINT, CONFIG_ENDPOINT, CONFIG_HASH_ENDPOINT,
-    FETCH_BLOCK_COUNT_ENDPOINT, GET_VERIFY_CONFIG_HASH_ENDPOINT, INVITE_CODE_ENDPOINT,
-    MODULES_CONFIG_JSON_ENDPOINT, RECOVER_ENDPOINT, STATUS_ENDPOINT, TRANSACTION_ENDPOINT,
-    VERSION_ENDPOINT, WAIT_TRANSACTION_ENDPOINT,
+    API_ENDPOINT_UPDATES_ENDPOINT, API_USAGE_ENDPOINT, APPROVE_MODULE_ENDPOINT,
+    ATTEST_FINAL_STATE_ENDPOINT, AUDIT_ENDPOINT, AUTH_ENDPOINT, AWAIT_BLOCK_ENDPOINT,
+    AWAIT_OUTPUT_OUTCOME_ENDPOINT, AWAIT_SIGNED_BLOCK_ENDPOINT, AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT,
+    AWAIT_TRANSACTION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_PROOF_ENDPOINT,
+    AWAIT_TRANSACTION_RECEIPT_ENDPOINT, BACKUP_ENDPOINT, CHECKPOINTS_ENDPOINT, CONFIG_ENDPOINT,
+    CONFIG_HASH_ENDPOINT, CONSENSUS_CONFIG_VERSIONS_ENDPOINT, CONSENSUS_ITEM_LOGGING_ENDPOINT,
+    CREATE_CHECKPOINT_ENDPOINT, CREATE_INVITE_CODE_ENDPOINT, CREATE_SCOPED_TOKEN_ENDPOINT,
+    DB_CONFLICTS_ENDPOINT, DUMP_DIAGNOSTICS_ENDPOINT, EXPORT_CONFIG_BUNDLE_ENDPOINT,
+    FEDERATION_META_ENDPOINT, FEE_INCOME_ENDPOINT, FETCH_BLOCK_COUNT_ENDPOINT,
+    FINAL_STATE_ATTESTATION_ENDPOINT, GET_VERIFY_CONFIG_HASH_ENDPOINT, INVITE_CODES_ENDPOINT,
+    INVITE_CODE_ENDPOINT, JOIN_ENDPOINT, KEY_EPOCHS_ENDPOINT, KEY_ROTATION_ENDPOINT,
+    MODULES_CONFIG_JSON_ENDPOINT, MODULE_FAILURES_ENDPOINT, MODULE_PROPOSALS_ENDPOINT,
+    MODULE_UPGRADES_ENDPOINT, OVERRIDE_SAFETY_HALT_ENDPOINT, PEER_HEALTH_ENDPOINT,
+    PROPOSE_FEDERATION_META_ENDPOINT, PROPOSE_MODULE_ENDPOINT, RECOVER_ENDPOINT,
+    RESTORE_CHECKPOINT_ENDPOINT, REVOKE_INVITE_CODE_ENDPOINT, REVOKE_SCOPED_TOKEN_ENDPOINT,
+    ROTATE_KEYS_ENDPOINT, SAFETY_HALT_ENDPOINT, SAFE_MODE_ENDPOINT, SCHEDULE_UPGRADE_ENDPOINT,
+    SCOPED_TOKENS_ENDPOINT, SERVER_TIME_ENDPOINT, SESSION_TRANSACTIONS_ENDPOINT,
+    SET_CONSENSUS_ITEM_LOGGING_ENDPOINT, SIGNED_BLOCKS_ENDPOINT, STALL_DIAGNOSTICS_ENDPOINT,
+    STATE_PROOF_ENDPOINT, STATUS_ENDPOINT, TRANSACTION_DEPENDENCIES_ENDPOINT, TRANSACTION_ENDPOINT,
+    TRANSACTION_LOCATION_ENDPOINT, UPDATE_API_ENDPOINT_ENDPOINT, VERSION_ENDPOINT,
+    WAIT_TRANSACTION_ENDPOINT,
 };
+use fedimint_core::endpoint_update::ApiEndpointUpdate;
 use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::fee::FeeIncome;
+use fedimint_core::invite::{
+    CreateInviteCodeRequest, InviteCodeRedemption, InviteCodeStatus, JoinRequest, ManagedInviteCode,
+};
+use fedimint_core::lifecycle::{
+    ModuleProposalStatus, ModuleUpgrade, ModuleUpgradeStatus, ProposeModuleRequest,
+};
+use fedimint_core::meta::{FederationMeta, SignedFederationMeta};
+use fedimint_core::migration::{FinalStateAttestation, SignedFinalStateAttestation};
 use fedimint_core::module::audit::{Audit, AuditSummary};
 use fedimint_core::module::registry::ServerModuleRegistry;
 use fedimint_core::module::{
-    api_endpoint, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased, SerdeModuleEncoding,
-    SupportedApiVersionsSummary,
+    api_endpoint, ApiAuth, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased,
+    SerdeModuleEncoding, SupportedApiVersionsSummary,
 };
+use fedimint_core::rotation::{KeyEpoch, KeyRotationStatus};
 use fedimint_core::server::DynServerModule;
+use fedimint_core::state_proof::StateProof;
 use fedimint_core::task::TaskGroup;
-use fedimint_core::transaction::{SerdeTransaction, Transaction};
+use fedimint_core::transaction::{
+    SerdeTransaction, Transaction, TransactionDependencies, TransactionOutcome,
+};
+use fedimint_core::usage::ApiUsageReport;
 use fedimint_core::{OutPoint, PeerId, TransactionId};
 use fedimint_logging::LOG_NET_API;
 use futures::StreamExt;
 use jsonrpsee::RpcModule;
+use rand::rngs::OsRng;
+use rand::Rng;
 use secp256k1_zkp::SECP256K1;
 use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
+use super::api_guard::ApiLimits;
 use super::peers::PeerStatusChannels;
+use super::replica::HistoryReplica;
+use super::scoped_tokens::{
+    create_scoped_token, revoke_scoped_token, scoped_capabilities, scoped_tokens,
+};
+use crate::checkpoint::Checkpoints;
 use crate::config::api::get_verification_hashes;
+use crate::config::bundle::export_config_bundle;
+use crate::config::reload::LiveConfig;
+use crate::config::versions::consensus_config_versions;
 use crate::config::ServerConfig;
+use crate::consensus::conflict::retry_on_conflict;
+use crate::consensus::debug::ItemLogFilter;
+use crate::consensus::isolation::{self, catch_panic, ModuleFailures};
+use crate::consensus::lifecycle::{
+    check_upgrade, our_module_config, propose_module, supports_version,
+};
+use crate::consensus::rotation::{deal_keys, key_rotation_status};
+use crate::consensus::safe_mode::SafeMode;
+use crate::consensus::safety_halt::SafetyHalt;
 use crate::consensus::server::LatestContributionByPeer;
-use crate::consensus::FundingVerifier;
+use crate::consensus::state_snapshot::state_proof;
+use crate::consensus::watchdog::StallWatchdog;
+use crate::consensus::{audit_fee_income, record_fee_income, FundingVerifier};
 use crate::db::{
-    AcceptedTransactionKey, ClientConfigDownloadKey, ClientConfigDownloadKeyPrefix,
-    ClientConfigSignatureKey, SignedBlockKey, SignedBlockPrefix,
+    AcceptedTransactionKey, AcceptedTransactionLocationKey, ApiEndpointUpdatePrefix,
+    ApprovedModuleKey, ApprovedUpgradeKey, ClientConfigDownloadKey, ClientConfigDownloadKeyPrefix,
+    ClientConfigSignatureKey, FederationMetaKey, FeeIncomeKey, FinalStateAttestationKey,
+    InviteCodeRedemptionKey, InviteCodeRedemptionTokenPrefix, ManagedInviteCodeKey,
+    ManagedInviteCodePrefix, ModuleApprovalPrefix, ModuleProposalKey, ModuleProposalPrefix,
+    OurKeyRotationKey, ProposedFederationMetaKey, ProposedFinalStateAttestationKey,
+    RejectedTransactionKey, RequestedApiEndpointKey, ScheduledKeyRotationKey,
+    ScheduledUpgradePrefix, SignedBlockKey, SignedBlockPrefix, TransactionDownstreamTxidPrefix,
+    TransactionUpstreamKey, UpgradeApprovalPrefix,
 };
+use crate::diagnostics::DumpRequests;
 use crate::fedimint_core::encoding::Encodable;
+use crate::net::usage::ApiUsageTracker;
 use crate::{check_auth, ApiResult, HasApiContext};
 
 pub type SerdeOutputOutcome = SerdeModuleEncoding<DynOutputOutcome>;
 
+/// How long we wait for a transaction to be accepted or rejected before
+/// reporting it as pending, so clients can poll without holding a request open
+/// indefinitely
+const TRANSACTION_OUTCOME_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// A state that has context for the API, passed to each rpc handler callback
 #[derive(Clone)]
 pub struct RpcHandlerCtx<M> {
     pub rpc_context: Arc<M>,
+    pub requests_in_flight: RequestsInFlight,
+    /// Further requests are rejected while this many are in flight
+    pub max_requests_in_flight: u64,
+    /// Accounts the requests sent with an API token, if any
+    pub api_usage: Option<ApiUsageTracker>,
 }
 
 impl<M> RpcHandlerCtx<M> {
     pub fn new_module(state: M) -> RpcModule<RpcHandlerCtx<M>> {
+        Self::new_tracked_module(
+            state,
+            RequestsInFlight::default(),
+            ApiLimits::default().max_requests_in_flight,
+            None,
+        )
+    }
+
+    /// Creates a module whose requests are counted by the given trackers
+    pub fn new_tracked_module(
+        state: M,
+        requests_in_flight: RequestsInFlight,
+        max_requests_in_flight: u64,
+        api_usage: Option<ApiUsageTracker>,
+    ) -> RpcModule<RpcHandlerCtx<M>> {
         RpcModule::new(Self {
             rpc_context: Arc::new(state),
+            requests_in_flight,
+            max_requests_in_flight,
+            api_usage,
         })
     }
 }
 
+/// Counts the API requests that are currently being handled
+#[derive(Debug, Clone, Default)]
+pub struct RequestsInFlight(Arc<AtomicU64>);
+
+impl RequestsInFlight {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Counts a request until the returned guard is dropped
+    pub fn start(&self) -> RequestInFlight {
+        self.0.fetch_add(1, Ordering::Relaxed);
+
+        RequestInFlight(self.0.clone())
+    }
+
+    /// Counts a request unless `max` requests are already in flight
+    pub fn try_start(&self, max: u64) -> Option<RequestInFlight> {
+        let guard = self.start();
+
+        if max < self.get() {
+            return None;
+        }
+
+        Some(guard)
+    }
+}
+
+pub struct RequestInFlight(Arc<AtomicU64>);
+
+impl Drop for RequestInFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 impl<M: Debug> Debug for RpcHandlerCtx<M> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("State { ... }")
@@ -131,15 +268,25 @@ impl InvitationCodesTracker {
                         })
                         .collect();
 
-                    let mut dbtx = db.begin_transaction().await;
+                    let result = retry_on_conflict("record_config_downloads", || async {
+                        let mut dbtx = db.begin_transaction().await;
 
-                    for (token, count) in changed_counts {
-                        dbtx.insert_entry(&ClientConfigDownloadKey(token.clone()), &count)
-                            .await;
-                        local_counts.insert(token, count);
-                    }
+                        for (token, count) in &changed_counts {
+                            dbtx.insert_entry(&ClientConfigDownloadKey(token.clone()), count)
+                                .await;
+                        }
+
+                        dbtx.commit_tx_result().await
+                    })
+                    .await;
 
-                    dbtx.commit_tx().await;
+                    // counts we failed to persist are written with the next change
+                    match result {
+                        Ok(()) => local_counts.extend(changed_counts),
+                        Err(e) => {
+                            warn!(target: LOG_NET_API, "Could not persist config downloads: {e}");
+                        }
+                    }
                 }
             }
         })
@@ -174,6 +321,16 @@ impl InvitationCodesTracker {
 
         Ok(())
     }
+
+    /// How often the token has been used so far
+    pub async fn uses(&self, token: &ClientConfigDownloadToken) -> u64 {
+        self.counts
+            .lock()
+            .await
+            .get(token)
+            .copied()
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Clone)]
@@ -185,11 +342,35 @@ pub struct ConsensusApi {
     pub invitation_codes_tracker: InvitationCodesTracker,
     /// Modules registered with the federation
     pub modules: ServerModuleRegistry,
+    /// Module kinds supported by this guardian, which we may add at runtime
+    pub module_inits: ServerModuleInitRegistry,
     /// Cached client config
     pub client_cfg: ClientConfig,
     /// For sending API events to consensus such as transactions
     pub submission_sender: async_channel::Sender<ConsensusItem>,
     pub peer_status_channels: PeerStatusChannels,
+    /// Modules halted after they panicked
+    pub module_failures: ModuleFailures,
+    /// Set while our storage is full and we stop processing consensus items
+    pub safe_mode: SafeMode,
+    /// Set while our consensus is halted after a safety invariant was violated
+    pub safety_halt: SafetyHalt,
+    /// Diagnostics gathered while our consensus is stalled
+    pub stall_watchdog: StallWatchdog,
+    /// Which consensus items we log at debug level
+    pub item_log_filter: ItemLogFilter,
+    /// Settings of our local config that are reloaded while we are running
+    pub live_config: LiveConfig,
+    /// Snapshot of the consensus history that historical reads are served from
+    pub history: HistoryReplica,
+    /// API requests that are being handled
+    pub requests_in_flight: RequestsInFlight,
+    /// Usage of the API by API token
+    pub api_usage: ApiUsageTracker,
+    /// Requests for a dump of our internals
+    pub diagnostics_dumps: DumpRequests,
+    /// Checkpoints of our config and database, if we run with a data dir
+    pub checkpoints: Option<Checkpoints>,
     pub latest_contribution_by_peer: Arc<RwLock<LatestContributionByPeer>>,
     pub consensus_status_cache: ExpiringCache<ApiResult<FederationStatus>>,
     pub supported_api_versions: SupportedApiVersionsSummary,
@@ -217,52 +398,96 @@ impl ConsensusApi {
             return Ok(());
         }
 
+        self.cfg.consensus.limits.transaction.check(&transaction)?;
+
+        self.safe_mode.check_writable().await?;
+
+        self.safety_halt.check_writable()?;
+
         // Create read-only DB tx so that the read state is consistent
         let mut dbtx = self.db.begin_transaction().await;
 
         // We ignore any writes, as we only verify if the transaction is valid here
         dbtx.ignore_uncommitted();
 
+        isolation::check_transaction(&mut dbtx, &transaction).await?;
+
         let mut funding_verifier = FundingVerifier::default();
         let mut public_keys = Vec::new();
 
+        // a panic only rejects the submission, the consensus halts the module once
+        // it panics on an ordered transaction
         for input in transaction.inputs.iter() {
-            let meta = self
+            let (kind, module) = self
                 .modules
-                .get_expect(input.module_instance_id())
-                .process_input(
+                .get_with_kind(input.module_instance_id())
+                .expect("Checked by the decoder");
+
+            let meta = catch_panic(
+                input.module_instance_id(),
+                kind,
+                "process_input",
+                None,
+                module.process_input(
                     &mut dbtx.dbtx_ref_with_prefix_module_id(input.module_instance_id()),
                     input,
-                )
-                .await?;
+                ),
+            )
+            .await??;
 
-            funding_verifier.add_input(meta.amount);
+            funding_verifier.add_input(input.module_instance_id(), &meta);
             public_keys.push(meta.pub_keys);
         }
 
         transaction.validate_signature(public_keys.into_iter().flatten())?;
 
         for (output, out_idx) in transaction.outputs.iter().zip(0u64..) {
-            let amount = self
+            let (kind, module) = self
                 .modules
-                .get_expect(output.module_instance_id())
-                .process_output(
+                .get_with_kind(output.module_instance_id())
+                .expect("Checked by the decoder");
+
+            let amount = catch_panic(
+                output.module_instance_id(),
+                kind,
+                "process_output",
+                None,
+                module.process_output(
                     &mut dbtx.dbtx_ref_with_prefix_module_id(output.module_instance_id()),
                     output,
                     OutPoint { txid, out_idx },
-                )
-                .await?;
+                ),
+            )
+            .await??;
 
-            funding_verifier.add_output(amount);
+            funding_verifier.add_output(output.module_instance_id(), amount);
         }
 
-        funding_verifier.verify_funding()?;
+        let fee_income = funding_verifier.verify_funding(&self.cfg.consensus.fees)?;
 
-        self.submission_sender
-            .send(ConsensusItem::Transaction(transaction))
-            .await?;
+        record_fee_income(&mut dbtx, fee_income).await?;
 
-        Ok(())
+        // instead of letting the client hang until the queue has room we tell it
+        // when to resubmit
+        match self
+            .submission_sender
+            .try_send(ConsensusItem::Transaction(transaction))
+        {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                let queue_len = self.submission_sender.len() as u64;
+
+                Err(FederationBusy {
+                    queue_len,
+                    retry_after: self
+                        .stall_watchdog
+                        .progress()
+                        .estimate_drain_time(queue_len),
+                }
+                .into())
+            }
+            Err(TrySendError::Closed(_)) => bail!("Consensus is shutting down"),
+        }
     }
 
     pub async fn await_transaction(
@@ -274,6 +499,27 @@ impl ConsensusApi {
             .await
     }
 
+    /// Waits until the transaction has either been accepted or rejected by
+    /// consensus, returns [`TransactionOutcome::Pending`] if neither happens
+    /// within [`TRANSACTION_OUTCOME_TIMEOUT`]
+    pub async fn await_transaction_outcome(&self, txid: TransactionId) -> TransactionOutcome {
+        let outcome = async {
+            // a rejected transaction may still be accepted if it is submitted again, in
+            // which case the rejection is removed, so acceptance takes precedence
+            tokio::select! {
+                biased;
+                _ = self.await_transaction(txid) => TransactionOutcome::Accepted,
+                rejection = self.db.wait_key_exists(&RejectedTransactionKey(txid)) => {
+                    TransactionOutcome::Rejected(rejection)
+                }
+            }
+        };
+
+        fedimint_core::task::timeout(TRANSACTION_OUTCOME_TIMEOUT, outcome)
+            .await
+            .unwrap_or(TransactionOutcome::Pending)
+    }
+
     pub async fn await_output_outcome(&self, outpoint: OutPoint) -> Result<SerdeOutputOutcome> {
         let (module_ids, mut dbtx) = self.await_transaction(outpoint.txid).await;
 
@@ -282,18 +528,32 @@ impl ConsensusApi {
             .nth(outpoint.out_idx as usize)
             .ok_or(anyhow!("Outpoint index out of bounds {:?}", outpoint))?;
 
-        let outcome = self
-            .modules
-            .get_expect(module_id)
-            .output_status(
-                &mut dbtx.dbtx_ref_with_prefix_module_id(module_id),
-                outpoint,
-                module_id,
-            )
-            .await
-            .expect("The transaction is accepted");
+        loop {
+            if let Some(outcome) = self
+                .modules
+                .get_expect(module_id)
+                .output_status(
+                    &mut dbtx.dbtx_ref_with_prefix_module_id(module_id),
+                    outpoint,
+                    module_id,
+                )
+                .await
+            {
+                return Ok((&outcome).into());
+            }
+
+            // the module produces the outcome at the end of the session, which
+            // is completed once its signed block is written
+            let session_count = dbtx.find_by_prefix(&SignedBlockPrefix).await.count().await;
 
-        Ok((&outcome).into())
+            (_, dbtx) = self
+                .db
+                .wait_key_check(
+                    &SignedBlockKey(session_count as u64),
+                    std::convert::identity,
+                )
+                .await;
+        }
     }
 
     pub async fn fetch_block_count(&self) -> u64 {
@@ -307,15 +567,179 @@ impl ConsensusApi {
     }
 
     pub async fn await_signed_block(&self, index: u64) -> SignedBlock {
+        if let Some(signed_block) = self.history.signed_block(index).await {
+            return signed_block;
+        }
+
         self.db
             .wait_key_check(&SignedBlockKey(index), std::convert::identity)
             .await
             .0
     }
 
+    /// Waits until the transaction has been included in a signed block and
+    /// returns a merkle proof of its inclusion
+    pub async fn await_transaction_proof(
+        &self,
+        txid: TransactionId,
+    ) -> ApiResult<AcceptedItemProof> {
+        let TransactionLocation {
+            session_index,
+            item_index,
+        } = self
+            .db
+            .wait_key_check(
+                &AcceptedTransactionLocationKey(txid),
+                std::convert::identity,
+            )
+            .await
+            .0;
+
+        self.await_signed_block(session_index)
+            .await
+            .block
+            .accepted_item_proof(session_index, item_index)
+            .ok_or_else(|| {
+                ApiError::server_error(format!(
+                    "Transaction {txid} is missing from block {session_index}"
+                ))
+            })
+    }
+
+    /// Waits until the transaction has been included in a signed block and
+    /// returns its inclusion proof together with the signed header of the
+    /// block
+    pub async fn await_transaction_receipt(
+        &self,
+        txid: TransactionId,
+    ) -> ApiResult<TransactionReceipt> {
+        let proof = self.await_transaction_proof(txid).await?;
+        let signed_header = self
+            .await_signed_block(proof.session_index)
+            .await
+            .signed_header(proof.session_index);
+
+        Ok(TransactionReceipt {
+            txid,
+            proof,
+            signed_header,
+        })
+    }
+
+    /// Returns the signed blocks of the sessions in `range` that are contained
+    /// in the latest snapshot of the consensus history
+    pub async fn get_signed_blocks(
+        &self,
+        range: SessionRange,
+    ) -> SnapshotResponse<Vec<SignedBlock>> {
+        self.history.signed_blocks(range).await
+    }
+
+    /// Returns the transactions accepted in the sessions in `range` that are
+    /// contained in the latest snapshot of the consensus history
+    pub async fn get_session_transactions(
+        &self,
+        range: SessionRange,
+    ) -> SnapshotResponse<Vec<LocatedTransaction>> {
+        let start_index = range.start_index;
+        let response = self.get_signed_blocks(range).await;
+
+        SnapshotResponse {
+            value: response
+                .value
+                .into_iter()
+                .zip(start_index..)
+                .flat_map(|(signed_block, session_index)| {
+                    signed_block
+                        .block
+                        .located_transactions(session_index)
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            snapshot: response.snapshot,
+        }
+    }
+
+    pub async fn get_transaction_location(
+        &self,
+        txid: TransactionId,
+    ) -> SnapshotResponse<Option<TransactionLocation>> {
+        self.history.transaction_location(txid).await
+    }
+
+    /// Returns `None` if the transaction has not been accepted
+    pub async fn get_transaction_dependencies(
+        &self,
+        txid: TransactionId,
+    ) -> Option<TransactionDependencies> {
+        let mut dbtx = self.db.begin_transaction().await;
+
+        dbtx.get_value(&AcceptedTransactionKey(txid)).await?;
+
+        let upstream = dbtx
+            .get_value(&TransactionUpstreamKey(txid))
+            .await
+            .unwrap_or_default();
+
+        let downstream = dbtx
+            .find_by_prefix(&TransactionDownstreamTxidPrefix(txid))
+            .await
+            .map(|(key, ())| key.1)
+            .collect::<Vec<_>>()
+            .await;
+
+        Some(TransactionDependencies {
+            upstream,
+            downstream,
+        })
+    }
+
+    fn checkpoints(&self) -> ApiResult<&Checkpoints> {
+        self.checkpoints
+            .as_ref()
+            .ok_or_else(|| ApiError::server_error("Checkpoints are not available".to_string()))
+    }
+
     pub async fn download_client_config(&self, info: InviteCode) -> ApiResult<ClientConfig> {
-        let token = self.cfg.local.download_token.clone();
+        let mut dbtx = self.db.begin_transaction().await;
+        let limit = self.check_invite_code(&mut dbtx.dbtx_ref(), &info).await?;
 
+        self.use_invite_code(&info.download_token, limit).await?;
+
+        Ok(self.client_cfg.clone())
+    }
+
+    /// Hands out the client config to a client joining with an invite code
+    /// and records which code the client joined with
+    pub async fn join(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        request: JoinRequest,
+    ) -> ApiResult<ClientConfig> {
+        let limit = self.check_invite_code(dbtx, &request.invite_code).await?;
+
+        let key = InviteCodeRedemptionKey {
+            download_token: request.invite_code.download_token.clone(),
+            client_id: request.client_id,
+        };
+
+        // a client retrying to join does not use up the code
+        if dbtx.get_value(&key).await.is_none() {
+            self.use_invite_code(&key.download_token, limit).await?;
+
+            dbtx.insert_entry(&key, &fedimint_core::time::now()).await;
+        }
+
+        Ok(self.client_cfg.clone())
+    }
+
+    /// Checks that clients can join with the invite code and returns how
+    /// often it can be used
+    async fn check_invite_code(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        info: &InviteCode,
+    ) -> ApiResult<Option<u64>> {
         if self.cfg.consensus.federation_id() != info.id {
             return Err(ApiError::bad_request("Wrong Federation Id".to_string()));
         }
@@ -323,41 +747,118 @@ impl ConsensusApi {
         if self.cfg.local.identity != info.peer_id {
             return Err(ApiError::bad_request("Wrong Peer Id".to_string()));
         }
-        if info.download_token != token {
-            return Err(ApiError::bad_request(
-                "Download token not found".to_string(),
-            ));
+
+        if info.download_token == self.cfg.local.download_token {
+            return Ok(self.live_config.get().download_token_limit);
         }
 
-        if self
-            .invitation_codes_tracker
-            .use_token(&token, self.cfg.local.download_token_limit)
+        let code = dbtx
+            .get_value(&ManagedInviteCodeKey(info.download_token.clone()))
             .await
-            .is_err()
-        {
+            .ok_or_else(|| ApiError::bad_request("Download token not found".to_string()))?;
+
+        if !code.is_active(fedimint_core::time::now()) {
             return Err(ApiError::bad_request(
-                "Download token used too many times".to_string(),
+                "Invite code was revoked or expired".to_string(),
             ));
         }
 
-        Ok(self.client_cfg.clone())
+        Ok(code.max_uses)
+    }
+
+    async fn use_invite_code(
+        &self,
+        token: &ClientConfigDownloadToken,
+        limit: Option<u64>,
+    ) -> ApiResult<()> {
+        self.invitation_codes_tracker
+            .use_token(token, limit)
+            .await
+            .map_err(|()| ApiError::bad_request("Download token used too many times".to_string()))
+    }
+
+    /// Creates an invite code with a new download token
+    pub async fn create_invite_code(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        request: CreateInviteCodeRequest,
+    ) -> InviteCode {
+        let invite_code = InviteCode {
+            download_token: ClientConfigDownloadToken(OsRng.gen()),
+            ..self.cfg.get_invite_code()
+        };
+
+        let code = ManagedInviteCode {
+            label: request.label,
+            created_at: fedimint_core::time::now(),
+            expires_at: request.expires_at,
+            max_uses: request.max_uses,
+            revoked: false,
+        };
+
+        dbtx.insert_new_entry(
+            &ManagedInviteCodeKey(invite_code.download_token.clone()),
+            &code,
+        )
+        .await;
+
+        invite_code
+    }
+
+    /// The invite codes created by our admin and the clients that joined
+    /// with them
+    pub async fn invite_codes(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> Vec<InviteCodeStatus> {
+        let codes = dbtx
+            .find_by_prefix(&ManagedInviteCodePrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut statuses = vec![];
+
+        for (key, code) in codes {
+            let redemptions = dbtx
+                .find_by_prefix(&InviteCodeRedemptionTokenPrefix(key.0.clone()))
+                .await
+                .map(|(key, joined_at)| InviteCodeRedemption {
+                    client_id: key.client_id,
+                    joined_at,
+                })
+                .collect()
+                .await;
+
+            statuses.push(InviteCodeStatus {
+                invite_code: InviteCode {
+                    download_token: key.0.clone(),
+                    ..self.cfg.get_invite_code()
+                },
+                code,
+                uses: self.invitation_codes_tracker.uses(&key.0).await,
+                redemptions,
+            });
+        }
+
+        statuses
     }
 
     pub async fn get_federation_status(&self) -> ApiResult<FederationStatus> {
-        let peers_connection_status = self.peer_status_channels.get_all_status().await;
+        let peers_health = self.peer_status_channels.get_all_health().await;
         let latest_contribution_by_peer = self.latest_contribution_by_peer.read().await.clone();
         let session_count = self.fetch_block_count().await;
 
-        let status_by_peer = peers_connection_status
+        let status_by_peer = peers_health
             .into_iter()
-            .map(|(peer, connection_status)| {
+            .map(|(peer, health)| {
                 let last_contribution = latest_contribution_by_peer.get(&peer).cloned();
                 let flagged = last_contribution.unwrap_or(0) + 1 < session_count;
-                let connection_status = match connection_status {
-                    Ok(status) => status,
+                let (connection_status, clock_offset_ms) = match health {
+                    Ok(health) => (health.connection_status, health.clock_offset_ms),
                     Err(e) => {
                         debug!(target: LOG_NET_API, %peer, "Unable to get peer connection status: {e}");
-                        PeerConnectionStatus::Disconnected
+                        (PeerConnectionStatus::Disconnected, None)
                     }
                 };
 
@@ -365,6 +866,7 @@ impl ConsensusApi {
                     last_contribution,
                     flagged,
                     connection_status,
+                    clock_offset_ms,
                 };
 
                 (peer, consensus_status)
@@ -386,6 +888,13 @@ impl ConsensusApi {
             .filter(|status| status.connection_status == PeerConnectionStatus::Disconnected)
             .count() as u64;
 
+        let mut module_health = BTreeMap::new();
+        for (module_instance_id, _, module) in self.modules.iter_modules() {
+            if let Some(health) = module.health().await {
+                module_health.insert(module_instance_id, health);
+            }
+        }
+
         Ok(FederationStatus {
             // the naming is in preparation for aleph bft since we will switch to
             // the session count here and want to keep the public API stable
@@ -394,10 +903,108 @@ impl ConsensusApi {
             peers_offline,
             peers_flagged,
             status_by_peer,
+            submission_queue_len: self.submission_sender.len() as u64,
+            submission_queue_capacity: self.submission_sender.capacity().unwrap_or_default() as u64,
+            module_health,
         })
     }
 
-    async fn get_federation_audit(&self) -> ApiResult<AuditSummary> {
+    /// The module instances proposed by any guardian that were not added yet
+    pub async fn module_proposals(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> Vec<ModuleProposalStatus> {
+        let mut approvals = BTreeMap::<_, BTreeSet<_>>::new();
+
+        let approval_keys = dbtx
+            .find_by_prefix(&ModuleApprovalPrefix)
+            .await
+            .map(|(key, ())| key)
+            .collect::<Vec<_>>()
+            .await;
+
+        for key in approval_keys {
+            approvals.entry(key.0).or_default().insert(key.1);
+        }
+
+        dbtx.find_by_prefix(&ModuleProposalPrefix)
+            .await
+            .map(|(key, proposal)| ModuleProposalStatus {
+                id: key.0,
+                proposer: proposal.proposer,
+                module_instance_id: proposal.module_instance_id,
+                kind: proposal.consensus.kind,
+                approvals: approvals.remove(&key.0).unwrap_or_default(),
+            })
+            .collect()
+            .await
+    }
+
+    /// The module upgrades scheduled by any guardian that were not activated
+    /// yet
+    pub async fn module_upgrades(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> Vec<ModuleUpgradeStatus> {
+        let mut approvals = BTreeMap::<_, BTreeSet<_>>::new();
+
+        let approval_keys = dbtx
+            .find_by_prefix(&UpgradeApprovalPrefix)
+            .await
+            .map(|(key, ())| key)
+            .collect::<Vec<_>>()
+            .await;
+
+        for key in approval_keys {
+            approvals.entry(key.0).or_default().insert(key.1);
+        }
+
+        let scheduled = dbtx
+            .find_by_prefix(&ScheduledUpgradePrefix)
+            .await
+            .map(|(_, upgrade)| {
+                let peers = self.cfg.consensus.broadcast_public_keys.keys().copied();
+
+                (upgrade, (peers.collect(), true))
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        approvals
+            .into_iter()
+            .map(|(upgrade, peers)| (upgrade, (peers, false)))
+            .chain(scheduled)
+            .map(|(upgrade, (approvals, scheduled))| ModuleUpgradeStatus {
+                upgrade,
+                approvals,
+                scheduled,
+                supported: supports_version(
+                    &self.cfg,
+                    &self.module_inits,
+                    upgrade.module_instance_id,
+                    upgrade.version,
+                ),
+            })
+            .collect()
+    }
+
+    pub async fn get_peer_health(&self) -> BTreeMap<PeerId, PeerHealth> {
+        self.peer_status_channels
+            .get_all_health()
+            .await
+            .into_iter()
+            .map(|(peer, health)| {
+                let health = health.unwrap_or_else(|e| {
+                    debug!(target: LOG_NET_API, %peer, "Unable to get peer health: {e}");
+                    PeerHealth::default()
+                });
+
+                (peer, health)
+            })
+            .collect()
+    }
+
+    pub async fn get_federation_audit(&self) -> ApiResult<AuditSummary> {
         let mut dbtx = self.db.begin_transaction().await;
         let mut audit = Audit::default();
         let mut module_instance_id_to_kind: HashMap<ModuleInstanceId, String> = HashMap::new();
@@ -411,6 +1018,7 @@ impl ConsensusApi {
                 )
                 .await
         }
+        audit_fee_income(&mut dbtx, &mut audit).await;
         Ok(AuditSummary::from_audit(
             &audit,
             &module_instance_id_to_kind,
@@ -465,18 +1073,19 @@ impl HasApiContext<ConsensusApi> for ConsensusApi {
     ) -> (&ConsensusApi, ApiEndpointContext<'_>) {
         let mut db = self.db.clone();
         let mut dbtx = self.db.begin_transaction().await;
+        let has_auth = request.auth == Some(self.cfg.private.api_auth.clone());
+        let mut capabilities = BTreeSet::new();
         if let Some(id) = id {
+            if !has_auth {
+                capabilities = scoped_capabilities(&mut dbtx.dbtx_ref(), &request.auth, id).await;
+            }
             db = self.db.with_prefix_module_id(id);
             dbtx = dbtx.with_prefix_module_id(id)
         }
         (
             self,
-            ApiEndpointContext::new(
-                db,
-                dbtx,
-                request.auth == Some(self.cfg.private.api_auth.clone()),
-                request.auth.clone(),
-            ),
+            ApiEndpointContext::new(db, dbtx, has_auth, request.auth.clone())
+                .with_capabilities(capabilities),
         )
     }
 }
@@ -515,7 +1124,10 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
 
                 fedimint.submit_transaction(transaction)
                     .await
-                    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+                    .map_err(|e| match e.downcast_ref::<FederationBusy>() {
+                        Some(busy) => ApiError::federation_busy(busy),
+                        None => ApiError::bad_request(e.to_string()),
+                    })?;
 
                 Ok(tx_id)
             }
@@ -532,6 +1144,12 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok(tx_hash)
             }
         },
+        api_endpoint! {
+            AWAIT_TRANSACTION_OUTCOME_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, txid: TransactionId| -> TransactionOutcome {
+                Ok(fedimint.await_transaction_outcome(txid).await)
+            }
+        },
         api_endpoint! {
             AWAIT_OUTPUT_OUTCOME_ENDPOINT,
             async |fedimint: &ConsensusApi, _context, outpoint: OutPoint| -> SerdeOutputOutcome {
@@ -563,12 +1181,63 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 })
             }
         },
+        api_endpoint! {
+            JOIN_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, request: JoinRequest| -> ClientConfigResponse {
+                let signature = context.wait_key_exists(ClientConfigSignatureKey).await;
+                let client_config = fedimint.join(&mut context.dbtx(), request).await?;
+                Ok(ClientConfigResponse {
+                    client_config,
+                    signature
+                })
+            }
+        },
         api_endpoint! {
             CONFIG_HASH_ENDPOINT,
             async |fedimint: &ConsensusApi, _context, _v: ()| -> sha256::Hash {
                 Ok(fedimint.cfg.consensus.consensus_hash())
             }
         },
+        api_endpoint! {
+            SERVER_TIME_ENDPOINT,
+            async |_fedimint: &ConsensusApi, _context, _v: ()| -> SystemTime {
+                Ok(fedimint_core::time::now())
+            }
+        },
+        api_endpoint! {
+            FEE_INCOME_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, _v: ()| -> FeeIncome {
+                Ok(context
+                    .dbtx()
+                    .get_value(&FeeIncomeKey)
+                    .await
+                    .unwrap_or_default())
+            }
+        },
+        api_endpoint! {
+            KEY_EPOCHS_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> Vec<KeyEpoch> {
+                Ok(fedimint.cfg.consensus.key_epochs.clone())
+            }
+        },
+        api_endpoint! {
+            CONSENSUS_CONFIG_VERSIONS_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, _v: ()| -> Vec<ConsensusConfigVersion> {
+                Ok(consensus_config_versions(&mut context.dbtx()).await)
+            }
+        },
+        api_endpoint! {
+            API_ENDPOINT_UPDATES_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, _v: ()| -> Vec<ApiEndpointUpdate> {
+                Ok(context
+                    .dbtx()
+                    .find_by_prefix(&ApiEndpointUpdatePrefix)
+                    .await
+                    .map(|(_, update)| update)
+                    .collect()
+                    .await)
+            }
+        },
         api_endpoint! {
             STATUS_ENDPOINT,
             async |fedimint: &ConsensusApi, _context, _v: ()| -> StatusResponse {
@@ -600,6 +1269,64 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok((&fedimint.await_signed_block(index).await).into())
             }
         },
+        api_endpoint! {
+            AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, index: u64| -> SerdeModuleEncoding<SignedBlockHeader> {
+                Ok((&fedimint.await_signed_block(index).await.signed_header(index)).into())
+            }
+        },
+        api_endpoint! {
+            AWAIT_TRANSACTION_PROOF_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, txid: TransactionId| -> SerdeModuleEncoding<AcceptedItemProof> {
+                Ok((&fedimint.await_transaction_proof(txid).await?).into())
+            }
+        },
+        api_endpoint! {
+            AWAIT_TRANSACTION_RECEIPT_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, txid: TransactionId| -> SerdeModuleEncoding<TransactionReceipt> {
+                Ok((&fedimint.await_transaction_receipt(txid).await?).into())
+            }
+        },
+        api_endpoint! {
+            STATE_PROOF_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, key: Vec<u8>| -> SerdeModuleEncoding<StateProof> {
+                Ok((&state_proof(&mut context.dbtx(), key).await?).into())
+            }
+        },
+        api_endpoint! {
+            SIGNED_BLOCKS_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, range: SessionRange| -> SnapshotResponse<SerdeModuleEncoding<Vec<SignedBlock>>> {
+                let response = fedimint.get_signed_blocks(range).await;
+
+                Ok(SnapshotResponse {
+                    value: (&response.value).into(),
+                    snapshot: response.snapshot,
+                })
+            }
+        },
+        api_endpoint! {
+            SESSION_TRANSACTIONS_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, range: SessionRange| -> SnapshotResponse<SerdeModuleEncoding<Vec<LocatedTransaction>>> {
+                let response = fedimint.get_session_transactions(range).await;
+
+                Ok(SnapshotResponse {
+                    value: (&response.value).into(),
+                    snapshot: response.snapshot,
+                })
+            }
+        },
+        api_endpoint! {
+            TRANSACTION_LOCATION_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, txid: TransactionId| -> SnapshotResponse<Option<TransactionLocation>> {
+                Ok(fedimint.get_transaction_location(txid).await)
+            }
+        },
+        api_endpoint! {
+            TRANSACTION_DEPENDENCIES_ENDPOINT,
+            async |fedimint: &ConsensusApi, _context, txid: TransactionId| -> Option<TransactionDependencies> {
+                Ok(fedimint.get_transaction_dependencies(txid).await)
+            }
+        },
         api_endpoint! {
             AUDIT_ENDPOINT,
             async |fedimint: &ConsensusApi, context, _v: ()| -> AuditSummary {
@@ -607,6 +1334,391 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok(fedimint.get_federation_audit().await?)
             }
         },
+        api_endpoint! {
+            PEER_HEALTH_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> BTreeMap<PeerId, PeerHealth> {
+                check_auth(context)?;
+                Ok(fedimint.get_peer_health().await)
+            }
+        },
+        api_endpoint! {
+            MODULE_FAILURES_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> BTreeMap<ModuleInstanceId, ModuleFailure> {
+                check_auth(context)?;
+                Ok(fedimint.module_failures.get_all(&mut context.dbtx()).await)
+            }
+        },
+        api_endpoint! {
+            SAFE_MODE_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Option<StorageFailure> {
+                check_auth(context)?;
+                Ok(fedimint.safe_mode.get().await)
+            }
+        },
+        api_endpoint! {
+            SAFETY_HALT_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Option<SafetyViolation> {
+                check_auth(context)?;
+                Ok(fedimint.safety_halt.get())
+            }
+        },
+        api_endpoint! {
+            OVERRIDE_SAFETY_HALT_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, halt_override: SafetyHaltOverride| -> () {
+                check_auth(context)?;
+                fedimint
+                    .safety_halt
+                    .override_halt(halt_override)
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            FINAL_STATE_ATTESTATION_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, _v: ()| -> Option<SignedFinalStateAttestation> {
+                Ok(context.dbtx().get_value(&FinalStateAttestationKey).await)
+            }
+        },
+        api_endpoint! {
+            ATTEST_FINAL_STATE_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, attestation: FinalStateAttestation| -> () {
+                check_auth(context)?;
+
+                if attestation.federation_id != fedimint.cfg.consensus.federation_id() {
+                    return Err(ApiError::bad_request(
+                        "Attestation is for a different federation".to_string(),
+                    ));
+                }
+
+                if attestation.successor.id == attestation.federation_id {
+                    return Err(ApiError::bad_request(
+                        "The federation cannot be its own successor".to_string(),
+                    ));
+                }
+
+                // the share is submitted with the next consensus proposal
+                context
+                    .dbtx()
+                    .insert_entry(&ProposedFinalStateAttestationKey, &attestation)
+                    .await;
+
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            FEDERATION_META_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, _v: ()| -> Option<SignedFederationMeta> {
+                Ok(context.dbtx().get_value(&FederationMetaKey).await)
+            }
+        },
+        api_endpoint! {
+            PROPOSE_FEDERATION_META_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, meta: FederationMeta| -> () {
+                check_auth(context)?;
+
+                if meta.federation_id != fedimint.cfg.consensus.federation_id() {
+                    return Err(ApiError::bad_request(
+                        "Metadata is for a different federation".to_string(),
+                    ));
+                }
+
+                if let Some(signed) = context.dbtx().get_value(&FederationMetaKey).await {
+                    if meta.version <= signed.meta.version {
+                        return Err(ApiError::bad_request(format!(
+                            "The version has to be larger than {}",
+                            signed.meta.version
+                        )));
+                    }
+                }
+
+                // the share is submitted with the next consensus proposal
+                context
+                    .dbtx()
+                    .insert_entry(&ProposedFederationMetaKey, &meta)
+                    .await;
+
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            STALL_DIAGNOSTICS_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Option<StallDiagnostics> {
+                check_auth(context)?;
+                Ok(fedimint.stall_watchdog.diagnostics().await)
+            }
+        },
+        api_endpoint! {
+            CONSENSUS_ITEM_LOGGING_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> ConsensusItemLogging {
+                check_auth(context)?;
+                Ok(fedimint.item_log_filter.get())
+            }
+        },
+        api_endpoint! {
+            PROPOSE_MODULE_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, request: ProposeModuleRequest| -> sha256::Hash {
+                check_auth(context)?;
+
+                let proposal = propose_module(
+                    &fedimint.cfg,
+                    &fedimint.module_inits,
+                    &request.kind,
+                    &request.params,
+                )
+                .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                let id = proposal.id();
+
+                // proposing the module counts as our approval
+                context
+                    .dbtx()
+                    .insert_entry(&ApprovedModuleKey(id), &proposal)
+                    .await;
+
+                Ok(id)
+            }
+        },
+        api_endpoint! {
+            MODULE_PROPOSALS_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Vec<ModuleProposalStatus> {
+                check_auth(context)?;
+                Ok(fedimint.module_proposals(&mut context.dbtx()).await)
+            }
+        },
+        api_endpoint! {
+            APPROVE_MODULE_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, id: sha256::Hash| -> () {
+                check_auth(context)?;
+
+                let mut dbtx = context.dbtx();
+
+                let proposal = dbtx
+                    .get_value(&ModuleProposalKey(id))
+                    .await
+                    .ok_or_else(|| ApiError::bad_request("Unknown module proposal".to_string()))?;
+
+                // we only approve a module we are able to run with the config we received
+                our_module_config(&fedimint.cfg, &fedimint.module_inits, &proposal)
+                    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                // the approval is submitted with the next consensus proposal
+                dbtx.insert_entry(&ApprovedModuleKey(id), &proposal).await;
+
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            SCHEDULE_UPGRADE_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, upgrade: ModuleUpgrade| -> () {
+                check_auth(context)?;
+
+                let mut dbtx = context.dbtx();
+
+                let session_index = dbtx.find_by_prefix(&SignedBlockPrefix).await.count().await;
+
+                // we may schedule an upgrade before our binary supports it, as long as we
+                // upgrade the binary before the activation session
+                check_upgrade(&mut dbtx, &fedimint.cfg, &upgrade, session_index as u64)
+                    .await
+                    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                // the schedule is submitted with the next consensus proposal
+                dbtx.insert_entry(&ApprovedUpgradeKey(upgrade), &()).await;
+
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            MODULE_UPGRADES_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Vec<ModuleUpgradeStatus> {
+                check_auth(context)?;
+                Ok(fedimint.module_upgrades(&mut context.dbtx()).await)
+            }
+        },
+        api_endpoint! {
+            ROTATE_KEYS_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> () {
+                check_auth(context)?;
+
+                let mut dbtx = context.dbtx();
+
+                if dbtx.get_value(&ScheduledKeyRotationKey).await.is_some() {
+                    return Err(ApiError::bad_request(
+                        "The key rotation is already scheduled".to_string(),
+                    ));
+                }
+
+                // dealing again replaces a deal of ours another guardian could not confirm
+                let rotation =
+                    deal_keys(&fedimint.cfg).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                // the deal is submitted with the next consensus proposal
+                dbtx.insert_entry(&OurKeyRotationKey, &rotation).await;
+
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            UPDATE_API_ENDPOINT_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, url: PeerUrl| -> () {
+                check_auth(context)?;
+
+                // the update is signed and submitted with the next consensus proposal
+                context.dbtx().insert_entry(&RequestedApiEndpointKey, &url).await;
+
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            CREATE_INVITE_CODE_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, request: CreateInviteCodeRequest| -> InviteCode {
+                check_auth(context)?;
+                Ok(fedimint.create_invite_code(&mut context.dbtx(), request).await)
+            }
+        },
+        api_endpoint! {
+            INVITE_CODES_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Vec<InviteCodeStatus> {
+                check_auth(context)?;
+                Ok(fedimint.invite_codes(&mut context.dbtx()).await)
+            }
+        },
+        api_endpoint! {
+            REVOKE_INVITE_CODE_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, token: ClientConfigDownloadToken| -> () {
+                check_auth(context)?;
+
+                let mut dbtx = context.dbtx();
+                let key = ManagedInviteCodeKey(token);
+
+                let Some(mut code) = dbtx.get_value(&key).await else {
+                    return Err(ApiError::bad_request("Invite code not found".to_string()));
+                };
+
+                code.revoked = true;
+                dbtx.insert_entry(&key, &code).await;
+
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            CREATE_SCOPED_TOKEN_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, request: CreateScopedTokenRequest| -> ApiAuth {
+                check_auth(context)?;
+
+                if fedimint.modules.get(request.module_instance_id).is_none() {
+                    return Err(ApiError::bad_request(format!(
+                        "Unknown module {}",
+                        request.module_instance_id
+                    )));
+                }
+
+                create_scoped_token(&mut context.dbtx(), request).await
+            }
+        },
+        api_endpoint! {
+            SCOPED_TOKENS_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, _v: ()| -> Vec<ScopedToken> {
+                check_auth(context)?;
+                Ok(scoped_tokens(&mut context.dbtx()).await)
+            }
+        },
+        api_endpoint! {
+            REVOKE_SCOPED_TOKEN_ENDPOINT,
+            async |_fedimint: &ConsensusApi, context, name: String| -> () {
+                check_auth(context)?;
+                revoke_scoped_token(&mut context.dbtx(), &name).await
+            }
+        },
+        api_endpoint! {
+            API_USAGE_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, days: u64| -> ApiUsageReport {
+                check_auth(context)?;
+                Ok(fedimint.api_usage.report(days).await)
+            }
+        },
+        api_endpoint! {
+            KEY_ROTATION_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Option<KeyRotationStatus> {
+                check_auth(context)?;
+                Ok(key_rotation_status(&mut context.dbtx(), &fedimint.cfg).await)
+            }
+        },
+        api_endpoint! {
+            DUMP_DIAGNOSTICS_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> DiagnosticsDump {
+                check_auth(context)?;
+                fedimint
+                    .diagnostics_dumps
+                    .request()
+                    .await
+                    .map_err(|e| ApiError::server_error(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            DB_CONFLICTS_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Option<ConflictReport> {
+                check_auth(context)?;
+                let Some(mut report) = fedimint.db.conflict_report() else {
+                    return Ok(None);
+                };
+                for hot_spot in &mut report.hot_spots {
+                    hot_spot.module_kind = hot_spot
+                        .module_instance_id
+                        .and_then(|id| fedimint.modules.get_with_kind(id))
+                        .map(|(kind, _)| kind.clone());
+                }
+                Ok(Some(report))
+            }
+        },
+        api_endpoint! {
+            CREATE_CHECKPOINT_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, name: String| -> GuardianCheckpoint {
+                check_auth(context)?;
+                fedimint
+                    .checkpoints()?
+                    .create(&name, "Created by the admin")
+                    .await
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            CHECKPOINTS_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Vec<GuardianCheckpoint> {
+                check_auth(context)?;
+                fedimint
+                    .checkpoints()?
+                    .list()
+                    .map_err(|e| ApiError::server_error(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            RESTORE_CHECKPOINT_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, name: String| -> () {
+                check_auth(context)?;
+                fedimint
+                    .checkpoints()?
+                    .schedule_restore(&name)
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            EXPORT_CONFIG_BUNDLE_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, passphrase: String| -> ConfigBundle {
+                check_auth(context)?;
+                export_config_bundle(&fedimint.cfg, &passphrase)
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            SET_CONSENSUS_ITEM_LOGGING_ENDPOINT,
+            async |fedimint: &ConsensusApi, context, logging: ConsensusItemLogging| -> () {
+                check_auth(context)?;
+                fedimint
+                    .item_log_filter
+                    .set(logging)
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
         api_endpoint! {
             GET_VERIFY_CONFIG_HASH_ENDPOINT,
             async |fedimint: &ConsensusApi, context, _v: ()| -> BTreeMap<PeerId, sha256::Hash> {
@@ -0,0 +1,283 @@
+//! Protection of the API server against connection floods
+//!
+//! Every connection to the API is accepted by a proxy in front of the API
+//! server, which listens on an ephemeral port on localhost. The proxy
+//! terminates TLS if configured and enforces the [`ApiLimits`] of the local
+//! config before a connection takes up a slot of the API server:
+//!
+//! * every IP address can only hold a limited number of connections, unless
+//!   it is a loopback address
+//! * a client has to send its websocket handshake within
+//!   [`HANDSHAKE_TIMEOUT`], otherwise it is disconnected
+//! * a client that stops reading its responses is evicted once writing to it
+//!   stalled for the slow client timeout, so its responses do not pile up in
+//!   our buffers
+//!
+//! The API server itself limits the total number of connections and, per
+//! [`ApiLimits::max_requests_in_flight`], the requests handled at once.
+//! Rejected and evicted connections are counted by reason in the
+//! `api_connections_rejected_total` metric.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use fedimint_core::task::{sleep, timeout};
+use fedimint_logging::LOG_NET_API;
+use fedimint_metrics::{lazy_static, opts, register_int_counter_vec, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use super::api_tls::ApiTls;
+
+/// How long a client may take to send the first bytes of its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of the chunks we forward responses to clients in
+const BUFFER_SIZE: usize = 16 * 1024;
+
+lazy_static! {
+    static ref API_CONNECTIONS_REJECTED: IntCounterVec = register_int_counter_vec!(
+        opts!(
+            "api_connections_rejected_total",
+            "API connections that were rejected or evicted by the connection guard"
+        ),
+        &["reason"]
+    )
+    .unwrap();
+}
+
+/// Limits protecting the API server from clients holding on to connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiLimits {
+    /// How many connections a single IP address may hold, except for
+    /// loopback addresses which a reverse proxy in front of us connects from
+    pub max_connections_per_ip: u32,
+    /// How many requests are handled at once, further requests are rejected
+    /// until others complete
+    pub max_requests_in_flight: u64,
+    /// How long writing a response to a client may stall before we evict it
+    pub slow_client_timeout_secs: u64,
+}
+
+impl Default for ApiLimits {
+    fn default() -> Self {
+        ApiLimits {
+            max_connections_per_ip: 32,
+            max_requests_in_flight: 1_000,
+            slow_client_timeout_secs: 30,
+        }
+    }
+}
+
+impl ApiLimits {
+    pub fn slow_client_timeout(&self) -> Duration {
+        Duration::from_secs(self.slow_client_timeout_secs)
+    }
+}
+
+/// Counts the open connections of every IP address
+#[derive(Debug, Default)]
+struct ConnectionsPerIp(Mutex<HashMap<IpAddr, u32>>);
+
+impl ConnectionsPerIp {
+    /// Counts a connection of `ip` until the returned guard is dropped, unless
+    /// it already holds `max` connections
+    fn try_open(self: &Arc<Self>, ip: IpAddr, max: u32) -> Option<IpConnection> {
+        let mut connections = self.0.lock().expect("lock poisoned");
+        let count = connections.entry(ip).or_default();
+
+        if max <= *count {
+            return None;
+        }
+
+        *count += 1;
+
+        Some(IpConnection {
+            connections: self.clone(),
+            ip,
+        })
+    }
+}
+
+struct IpConnection {
+    connections: Arc<ConnectionsPerIp>,
+    ip: IpAddr,
+}
+
+impl Drop for IpConnection {
+    fn drop(&mut self) {
+        let mut connections = self.connections.0.lock().expect("lock poisoned");
+
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Accepts the connections of clients and forwards them to the API server
+pub struct ApiGuard {
+    limits: ApiLimits,
+    tls: Option<ApiTls>,
+    connections: Arc<ConnectionsPerIp>,
+}
+
+impl ApiGuard {
+    pub fn new(limits: ApiLimits, tls: Option<ApiTls>) -> Self {
+        ApiGuard {
+            limits,
+            tls,
+            connections: Arc::new(ConnectionsPerIp::default()),
+        }
+    }
+
+    /// Accepts connections on the listener and forwards them to the API server
+    /// listening on `backend`
+    pub async fn serve(self, listener: TcpListener, backend: SocketAddr) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(target: LOG_NET_API, error = %e, "Could not accept API connection");
+                    sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+
+            let max_connections = if addr.ip().is_loopback() {
+                u32::MAX
+            } else {
+                self.limits.max_connections_per_ip
+            };
+
+            let Some(connection) = self.connections.try_open(addr.ip(), max_connections) else {
+                API_CONNECTIONS_REJECTED
+                    .with_label_values(&["per_ip_limit"])
+                    .inc();
+                debug!(target: LOG_NET_API, %addr, "Too many API connections from address");
+                continue;
+            };
+
+            let tls = self.tls.clone();
+            let slow_client_timeout = self.limits.slow_client_timeout();
+
+            tokio::spawn(async move {
+                let result = match tls {
+                    Some(tls) => match tls.accept(stream).await {
+                        Ok(stream) => forward(stream, backend, slow_client_timeout).await,
+                        Err(e) => Err(e),
+                    },
+                    None => forward(stream, backend, slow_client_timeout).await,
+                };
+
+                if let Err(e) = result {
+                    debug!(target: LOG_NET_API, %addr, error = %e, "API connection closed");
+                }
+
+                drop(connection);
+            });
+        }
+    }
+}
+
+/// Forwards the client's stream to the API server until either side closes it,
+/// the client fails to send its handshake in time or stops reading
+async fn forward<S>(
+    client: S,
+    backend: SocketAddr,
+    slow_client_timeout: Duration,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut client_read, mut client_write) = tokio::io::split(client);
+    let mut handshake = vec![0; BUFFER_SIZE];
+
+    let len = match timeout(HANDSHAKE_TIMEOUT, client_read.read(&mut handshake)).await {
+        Ok(len) => len?,
+        Err(_) => {
+            API_CONNECTIONS_REJECTED
+                .with_label_values(&["handshake_timeout"])
+                .inc();
+            bail!("Client did not send its handshake in time");
+        }
+    };
+
+    if len == 0 {
+        return Ok(());
+    }
+
+    let backend_stream = TcpStream::connect(backend).await?;
+    let (mut backend_read, mut backend_write) = backend_stream.into_split();
+    backend_write.write_all(&handshake[..len]).await?;
+
+    let requests = tokio::io::copy(&mut client_read, &mut backend_write);
+
+    let responses = async {
+        let mut buffer = handshake;
+
+        loop {
+            let len = backend_read.read(&mut buffer).await?;
+
+            if len == 0 {
+                return anyhow::Ok(());
+            }
+
+            let write = async {
+                client_write.write_all(&buffer[..len]).await?;
+                client_write.flush().await
+            };
+
+            match timeout(slow_client_timeout, write).await {
+                Ok(result) => result.context("Could not write to client")?,
+                Err(_) => {
+                    API_CONNECTIONS_REJECTED
+                        .with_label_values(&["slow_client"])
+                        .inc();
+                    bail!("Client stopped reading its responses");
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        result = requests => result.map(|_| ()).context("Could not read from client"),
+        result = responses => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    use super::ConnectionsPerIp;
+
+    #[test]
+    fn limits_connections_per_ip() {
+        let connections = Arc::new(ConnectionsPerIp::default());
+        let ip: IpAddr = [10, 0, 0, 1].into();
+        let other: IpAddr = [10, 0, 0, 2].into();
+
+        let first = connections.try_open(ip, 2).expect("Below limit");
+        let second = connections.try_open(ip, 2).expect("Below limit");
+
+        assert!(connections.try_open(ip, 2).is_none());
+        assert!(connections.try_open(other, 2).is_some());
+
+        drop(first);
+        assert_eq!(connections.0.lock().unwrap().get(&ip), Some(&1));
+        assert!(connections.try_open(ip, 2).is_some());
+
+        drop(second);
+        assert!(connections.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,226 @@
+//! TLS termination for the client facing API
+//!
+//! The API server does not terminate TLS itself, so if an [`ApiTlsConfig`] is
+//! set the proxy in front of it, see [`super::api_guard`], completes the TLS
+//! handshake of every client and forwards the decrypted streams. Clients then
+//! connect with `wss://`.
+//!
+//! Certificates are typically issued by an ACME certificate authority like
+//! Let's Encrypt. The [`ApiTlsConfig::renew_command`], e.g. `certbot renew`,
+//! is run once a day and the certificate is reloaded whenever its files
+//! change, so renewals apply without restarting the guardian.
+
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{bail, format_err, Context};
+use fedimint_core::task::{sleep, timeout, TaskGroup, TaskHandle};
+use fedimint_logging::LOG_NET_API;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::{any_supported_type, CertifiedKey};
+use tokio_rustls::rustls::{self, Certificate, PrivateKey};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+/// How often we check whether the certificate files changed
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often we run the renew command
+const RENEW_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a client may take to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate the API is served with over TLS
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiTlsConfig {
+    /// PEM file with the certificate chain, starting with our certificate
+    pub cert_chain_file: PathBuf,
+    /// PEM file with the private key of our certificate
+    pub private_key_file: PathBuf,
+    /// Command renewing the certificate files if they are about to expire,
+    /// e.g. an ACME client like `certbot renew`
+    #[serde(default)]
+    pub renew_command: Option<PathBuf>,
+}
+
+/// Reads the certificate chain and private key from their PEM files
+fn load_certified_key(cfg: &ApiTlsConfig) -> anyhow::Result<CertifiedKey> {
+    let certs = read_pem(&cfg.cert_chain_file)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if certs.is_empty() {
+        bail!("No certificate found in {}", cfg.cert_chain_file.display());
+    }
+
+    let key = read_pem(&cfg.private_key_file)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .with_context(|| format!("No private key found in {}", cfg.private_key_file.display()))?;
+
+    let key = any_supported_type(&key).map_err(|_| format_err!("Unsupported private key type"))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn read_pem(path: &Path) -> anyhow::Result<Vec<rustls_pemfile::Item>> {
+    let file = fs::File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+
+    Ok(rustls_pemfile::read_all(&mut BufReader::new(file))?)
+}
+
+fn modified(cfg: &ApiTlsConfig) -> anyhow::Result<(SystemTime, SystemTime)> {
+    Ok((
+        fs::metadata(&cfg.cert_chain_file)?.modified()?,
+        fs::metadata(&cfg.private_key_file)?.modified()?,
+    ))
+}
+
+/// Serves the certificate we loaded last
+struct ReloadingCertResolver(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().expect("lock poisoned").clone())
+    }
+}
+
+/// Accepts the TLS connections of clients to the API
+#[derive(Clone)]
+pub struct ApiTls {
+    cfg: ApiTlsConfig,
+    resolver: Arc<ReloadingCertResolver>,
+    acceptor: TlsAcceptor,
+}
+
+impl ApiTls {
+    pub fn new(cfg: ApiTlsConfig) -> anyhow::Result<Self> {
+        let resolver = Arc::new(ReloadingCertResolver(RwLock::new(Arc::new(
+            load_certified_key(&cfg)?,
+        ))));
+
+        let mut server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(ApiTls {
+            cfg,
+            resolver,
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+        })
+    }
+
+    /// Spawns a task that runs the renew command and reloads the certificate
+    /// whenever its files change
+    pub async fn spawn_renewal(&self, task_group: &mut TaskGroup) {
+        let tls = self.clone();
+
+        task_group
+            .spawn("api tls renewal", move |task_handle| async move {
+                tls.run_renewal(task_handle).await
+            })
+            .await;
+    }
+
+    async fn run_renewal(&self, task_handle: TaskHandle) {
+        let mut last_modified = modified(&self.cfg).ok();
+        let mut last_renewal: Option<Instant> = None;
+
+        while !task_handle.is_shutting_down() {
+            if let Some(command) = &self.cfg.renew_command {
+                if last_renewal.map_or(true, |renewal| renewal.elapsed() >= RENEW_INTERVAL) {
+                    last_renewal = Some(Instant::now());
+
+                    match tokio::process::Command::new(command).status().await {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => {
+                            warn!(target: LOG_NET_API, %status, "Certificate renew command failed");
+                        }
+                        Err(e) => {
+                            warn!(
+                                target: LOG_NET_API,
+                                error = %e,
+                                "Could not run certificate renew command"
+                            );
+                        }
+                    }
+                }
+            }
+
+            let current = modified(&self.cfg).ok();
+
+            if current.is_some() && current != last_modified {
+                match load_certified_key(&self.cfg) {
+                    Ok(key) => {
+                        *self.resolver.0.write().expect("lock poisoned") = Arc::new(key);
+                        last_modified = current;
+                        info!(target: LOG_NET_API, "Reloaded API certificate");
+                    }
+                    // the renewal might not have written both files yet
+                    Err(e) => {
+                        warn!(target: LOG_NET_API, error = %e, "Could not reload API certificate");
+                    }
+                }
+            }
+
+            sleep(RELOAD_INTERVAL).await;
+        }
+    }
+
+    /// Completes the TLS handshake of a client that connected to the API
+    pub async fn accept(&self, stream: TcpStream) -> anyhow::Result<TlsStream<TcpStream>> {
+        timeout(HANDSHAKE_TIMEOUT, self.acceptor.accept(stream))
+            .await
+            .context("TLS handshake timed out")?
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{load_certified_key, ApiTlsConfig};
+
+    #[test]
+    fn loads_certificate_from_pem_files() {
+        let dir = tempfile::tempdir().expect("Creates temp dir");
+        let cert = rcgen::generate_simple_self_signed(vec!["guardian.example".to_string()])
+            .expect("Generates certificate");
+
+        let cfg = ApiTlsConfig {
+            cert_chain_file: dir.path().join("cert.pem"),
+            private_key_file: dir.path().join("key.pem"),
+            renew_command: None,
+        };
+
+        fs::write(
+            &cfg.cert_chain_file,
+            cert.serialize_pem().expect("Serializes"),
+        )
+        .unwrap();
+        assert!(load_certified_key(&cfg).is_err());
+
+        fs::write(&cfg.private_key_file, cert.serialize_private_key_pem()).unwrap();
+        let key = load_certified_key(&cfg).expect("Loads certificate");
+        assert_eq!(key.cert.len(), 1);
+    }
+}
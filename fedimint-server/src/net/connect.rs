@@ -6,10 +6,12 @@ use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use anyhow::format_err;
+use anyhow::{bail, format_err};
 use async_trait::async_trait;
+use fedimint_core::net::socks::connect_socks5;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::PeerId;
 use futures::Stream;
@@ -57,6 +59,10 @@ pub trait Connector<M> {
 }
 
 /// TCP connector with encryption and authentication
+///
+/// Connections to the onion addresses of peers with a configured proxy are
+/// tunneled through it, which allows reaching peers running behind Tor onion
+/// services.
 #[derive(Debug)]
 pub struct TlsTcpConnector {
     our_certificate: rustls::Certificate,
@@ -66,6 +72,7 @@ pub struct TlsTcpConnector {
     /// understands
     cert_store: RootCertStore,
     peer_names: BTreeMap<PeerId, String>,
+    peer_proxies: BTreeMap<PeerId, SafeUrl>,
 }
 
 #[derive(Debug, Clone)]
@@ -73,6 +80,8 @@ pub struct TlsConfig {
     pub our_private_key: rustls::PrivateKey,
     pub peer_certs: BTreeMap<PeerId, rustls::Certificate>,
     pub peer_names: BTreeMap<PeerId, String>,
+    /// SOCKS5 proxies (e.g. Tor) used to connect to individual peers
+    pub peer_proxies: BTreeMap<PeerId, SafeUrl>,
 }
 
 #[derive(Debug, Clone)]
@@ -95,6 +104,7 @@ impl TlsTcpConnector {
             peer_certs: Arc::new(PeerCertStore::new(cfg.peer_certs)),
             cert_store,
             peer_names: cfg.peer_names,
+            peer_proxies: cfg.peer_proxies,
         }
     }
 }
@@ -145,11 +155,13 @@ impl PeerCertStore {
 
         let (_, tls_session) = tls_conn.get_ref();
         let auth_peer = self.authenticate_peer(tls_session.peer_certificates())?;
+        let compression = tls_session.alpn_protocol() == Some(COMPRESSION_ALPN_PROTOCOL);
 
         let framed =
             BidiFramed::<_, WriteHalf<TlsStream<TcpStream>>, ReadHalf<TlsStream<TcpStream>>>::new(
                 tls_conn,
             )
+            .with_compression(compression)
             .into_dyn();
         Ok((auth_peer, framed))
     }
@@ -161,7 +173,7 @@ where
     M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
 {
     async fn connect_framed(&self, destination: SafeUrl, peer: PeerId) -> ConnectResult<M> {
-        let cfg = rustls::ClientConfig::builder()
+        let mut cfg = rustls::ClientConfig::builder()
             .with_safe_defaults()
             .with_root_certificates(self.cert_store.clone())
             .with_single_cert(
@@ -169,17 +181,20 @@ where
                 self.our_private_key.clone(),
             )
             .expect("Failed to create TLS config");
+        cfg.alpn_protocols = vec![COMPRESSION_ALPN_PROTOCOL.to_vec()];
 
         let fake_domain =
             rustls::ServerName::try_from(dns_sanitize(&self.peer_names[&peer]).as_str())
                 .expect("Always a valid DNS name");
 
+        let proxy = self
+            .peer_proxies
+            .get(&peer)
+            .filter(|_| destination.is_onion_address());
+
         let connector = TlsConnector::from(Arc::new(cfg));
         let tls_conn = connector
-            .connect(
-                fake_domain,
-                TcpStream::connect(parse_host_port(destination)?).await?,
-            )
+            .connect(fake_domain, connect_tcp(destination, proxy).await?)
             .await?;
 
         let (_, tls_session) = tls_conn.get_ref();
@@ -191,10 +206,13 @@ where
             return Err(anyhow::anyhow!("Connected to unexpected peer"));
         }
 
+        let compression = tls_session.alpn_protocol() == Some(COMPRESSION_ALPN_PROTOCOL);
+
         let framed =
             BidiFramed::<_, WriteHalf<TlsStream<TcpStream>>, ReadHalf<TlsStream<TcpStream>>>::new(
                 tls_conn,
             )
+            .with_compression(compression)
             .into_dyn();
 
         Ok((peer, framed))
@@ -202,7 +220,7 @@ where
 
     async fn listen(&self, bind_addr: SocketAddr) -> Result<ConnectionListener<M>, anyhow::Error> {
         let verifier = AllowAnyAuthenticatedClient::new(self.cert_store.clone());
-        let config = rustls::ServerConfig::builder()
+        let mut config = rustls::ServerConfig::builder()
             .with_safe_defaults()
             .with_client_cert_verifier(verifier)
             .with_single_cert(
@@ -210,6 +228,7 @@ where
                 self.our_private_key.clone(),
             )
             .unwrap();
+        config.alpn_protocols = vec![COMPRESSION_ALPN_PROTOCOL.to_vec()];
         let listener = TcpListener::bind(bind_addr).await?;
         let peer_certs = self.peer_certs.clone();
 
@@ -226,6 +245,225 @@ where
     }
 }
 
+/// ALPN protocol identifier advertised by peers that support zstd compressed
+/// frames on TLS connections
+///
+/// Peers running older versions neither send nor select an ALPN protocol, in
+/// which case the connection falls back to uncompressed frames.
+const COMPRESSION_ALPN_PROTOCOL: &[u8] = b"fedimint-p2p-zstd";
+
+/// ALPN protocol identifier of QUIC peer connections
+///
+/// Every peer that supports QUIC also supports compressed frames, so QUIC
+/// connections are always compressed.
+const QUIC_ALPN_PROTOCOL: &[u8] = b"fedimint-p2p";
+
+/// How often we send keep-alive packets on idle QUIC connections so that NAT
+/// mappings do not expire
+const QUIC_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// QUIC connector with encryption and authentication
+///
+/// Uses the same certificates as [`TlsTcpConnector`]. Contrary to TCP, QUIC
+/// connections survive NAT rebinding and reconnecting to a peer we were
+/// connected to before can send data in the first round trip (0-RTT).
+#[derive(Debug)]
+pub struct QuicConnector {
+    peer_certs: Arc<PeerCertStore>,
+    peer_names: BTreeMap<PeerId, String>,
+    peer_proxies: BTreeMap<PeerId, SafeUrl>,
+    /// Shared between all connections since it caches the session tickets used
+    /// for 0-RTT
+    client_config: quinn::ClientConfig,
+    server_config: quinn::ServerConfig,
+    /// All our connections are made from one endpoint, it is created with the
+    /// first connection since binding its socket requires the runtime
+    client_endpoint: Mutex<Option<quinn::Endpoint>>,
+}
+
+type QuicBidiFramed<M> = BidiFramed<M, quinn::SendStream, quinn::RecvStream>;
+
+impl QuicConnector {
+    pub fn new(cfg: TlsConfig, our_id: PeerId) -> QuicConnector {
+        let our_certificate = cfg.peer_certs.get(&our_id).expect("exists").clone();
+
+        let mut cert_store = RootCertStore::empty();
+        for cert in cfg.peer_certs.values() {
+            cert_store
+                .add(cert)
+                .expect("Could not add peer certificate");
+        }
+
+        let mut transport = quinn::TransportConfig::default();
+        transport.keep_alive_interval(Some(QUIC_KEEP_ALIVE_INTERVAL));
+        let transport = Arc::new(transport);
+
+        let mut client_crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(cert_store.clone())
+            .with_single_cert(vec![our_certificate.clone()], cfg.our_private_key.clone())
+            .expect("Failed to create TLS config");
+        client_crypto.alpn_protocols = vec![QUIC_ALPN_PROTOCOL.to_vec()];
+        client_crypto.enable_early_data = true;
+
+        let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
+        client_config.transport_config(transport.clone());
+
+        let mut server_crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(cert_store))
+            .with_single_cert(vec![our_certificate], cfg.our_private_key)
+            .expect("Failed to create TLS config");
+        server_crypto.alpn_protocols = vec![QUIC_ALPN_PROTOCOL.to_vec()];
+        // QUIC requires either no early data or an unlimited amount of it
+        server_crypto.max_early_data_size = u32::MAX;
+
+        let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
+        server_config.transport_config(transport);
+
+        QuicConnector {
+            peer_certs: Arc::new(PeerCertStore::new(cfg.peer_certs)),
+            peer_names: cfg.peer_names,
+            peer_proxies: cfg.peer_proxies,
+            client_config,
+            server_config,
+            client_endpoint: Mutex::new(None),
+        }
+    }
+
+    fn client_endpoint(&self) -> anyhow::Result<quinn::Endpoint> {
+        let mut client_endpoint = self.client_endpoint.lock().expect("Lock poisoned");
+
+        if let Some(endpoint) = client_endpoint.as_ref() {
+            return Ok(endpoint.clone());
+        }
+
+        // A dual stack socket reaches peers over IPv4 and IPv6, hosts without
+        // IPv6 fall back to IPv4
+        let mut endpoint = quinn::Endpoint::client("[::]:0".parse().expect("valid address"))
+            .or_else(|_| quinn::Endpoint::client("0.0.0.0:0".parse().expect("valid address")))?;
+        endpoint.set_default_client_config(self.client_config.clone());
+
+        *client_endpoint = Some(endpoint.clone());
+
+        Ok(endpoint)
+    }
+}
+
+impl PeerCertStore {
+    fn authenticate_quic_peer(&self, connection: &quinn::Connection) -> anyhow::Result<PeerId> {
+        let certificates = connection
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<rustls::Certificate>>().ok());
+
+        self.authenticate_peer(certificates.as_deref().map(Vec::as_slice))
+    }
+
+    async fn accept_quic_connection<M>(
+        &self,
+        connecting: quinn::Connecting,
+    ) -> Result<(PeerId, AnyFramedTransport<M>), anyhow::Error>
+    where
+        M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
+    {
+        let connection = connecting.await?;
+
+        let auth_peer = self.authenticate_quic_peer(&connection)?;
+
+        let (send, recv) = connection.accept_bi().await?;
+        let framed = QuicBidiFramed::<M>::new_from_halves(send, recv)
+            .with_compression(true)
+            .into_dyn();
+
+        Ok((auth_peer, framed))
+    }
+}
+
+#[async_trait]
+impl<M> Connector<M> for QuicConnector
+where
+    M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
+{
+    async fn connect_framed(&self, destination: SafeUrl, peer: PeerId) -> ConnectResult<M> {
+        if self.peer_proxies.contains_key(&peer) && destination.is_onion_address() {
+            bail!("QUIC connections can not be tunneled through a SOCKS5 proxy");
+        }
+
+        let addr = tokio::net::lookup_host(parse_host_port(destination.clone())?)
+            .await?
+            .next()
+            .ok_or_else(|| format_err!("Could not resolve {destination}"))?;
+
+        let connecting = self
+            .client_endpoint()?
+            .connect(addr, &dns_sanitize(&self.peer_names[&peer]))?;
+
+        let connection = match connecting.into_0rtt() {
+            Ok((connection, zero_rtt_accepted)) => {
+                // Streams opened before the peer rejected our early data are
+                // unusable, so we only open ours once the handshake completed
+                zero_rtt_accepted.await;
+                connection
+            }
+            Err(connecting) => connecting.await?,
+        };
+
+        if let Some(reason) = connection.close_reason() {
+            return Err(reason.into());
+        }
+
+        // A resumed session was only authenticated against the peer's name, so
+        // we check the certificate of the completed handshake in any case
+        if self.peer_certs.authenticate_quic_peer(&connection)? != peer {
+            return Err(anyhow::anyhow!("Connected to unexpected peer"));
+        }
+
+        let (send, recv) = connection.open_bi().await?;
+        let framed = QuicBidiFramed::<M>::new_from_halves(send, recv)
+            .with_compression(true)
+            .into_dyn();
+
+        Ok((peer, framed))
+    }
+
+    async fn listen(&self, bind_addr: SocketAddr) -> Result<ConnectionListener<M>, anyhow::Error> {
+        let endpoint = quinn::Endpoint::server(self.server_config.clone(), bind_addr)?;
+        let peer_certs = self.peer_certs.clone();
+
+        let stream = futures::stream::unfold(endpoint, move |endpoint| {
+            let peer_certs = peer_certs.clone();
+
+            Box::pin(async move {
+                // the stream ends once the endpoint is closed
+                let connecting = endpoint.accept().await?;
+                let res = peer_certs.accept_quic_connection(connecting).await;
+                Some((res, endpoint))
+            })
+        });
+        Ok(Box::pin(stream))
+    }
+}
+
+/// Opens a TCP connection to `destination`, tunneled through a SOCKS5 `proxy`
+/// if one is given
+pub async fn connect_tcp(
+    destination: SafeUrl,
+    proxy: Option<&SafeUrl>,
+) -> anyhow::Result<TcpStream> {
+    let Some(proxy) = proxy else {
+        return Ok(TcpStream::connect(parse_host_port(destination)?).await?);
+    };
+
+    let host = destination
+        .host_str()
+        .ok_or_else(|| format_err!("Missing host in {destination}"))?;
+    let port = destination
+        .port()
+        .ok_or_else(|| format_err!("Missing port in {destination}"))?;
+
+    connect_socks5(proxy, host, port).await
+}
+
 /// Sanitizes name as valid domain name
 pub fn dns_sanitize(name: &str) -> String {
     let sanitized = name.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
@@ -833,6 +1071,7 @@ pub mod mock {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::net::SocketAddr;
 
     use fedimint_core::task::spawn;
@@ -841,7 +1080,7 @@ mod tests {
     use futures::{SinkExt, StreamExt};
 
     use crate::config::gen_cert_and_key;
-    use crate::net::connect::{ConnectionListener, Connector, TlsConfig};
+    use crate::net::connect::{ConnectionListener, Connector, QuicConnector, TlsConfig};
     use crate::net::framed::AnyFramedTransport;
     use crate::TlsTcpConnector;
 
@@ -867,6 +1106,7 @@ mod tests {
                     .enumerate()
                     .map(|(peer, (_, _))| (PeerId::from(peer as u16), format!("peer-{peer}")))
                     .collect(),
+                peer_proxies: BTreeMap::new(),
             })
             .collect()
     }
@@ -908,6 +1148,94 @@ mod tests {
         server_task.await.unwrap();
     }
 
+    #[tokio::test]
+    async fn connect_success_quic() {
+        let bind_addr: SocketAddr = "127.0.0.1:7002".parse().unwrap();
+        let url: SafeUrl = "fedimint://127.0.0.1:7002".parse().unwrap();
+        let connectors = gen_connector_config(5)
+            .into_iter()
+            .enumerate()
+            .map(|(id, cfg)| QuicConnector::new(cfg, PeerId::from(id as u16)))
+            .collect::<Vec<_>>();
+
+        let mut server: ConnectionListener<u64> = connectors[0].listen(bind_addr).await.unwrap();
+
+        let server_task = spawn("server next await", async move {
+            let (peer, mut conn) = server.next().await.unwrap().unwrap();
+            assert_eq!(peer.to_usize(), 2);
+            let received = conn.next().await.unwrap().unwrap();
+            assert_eq!(received, 42);
+            conn.send(21).await.unwrap();
+            // the stream either ends or errors once the client is gone
+            assert!(conn.next().await.map_or(true, |res| res.is_err()));
+        })
+        .expect("some handle on non-wasm");
+
+        let (peer_of_a, mut client_a): (_, AnyFramedTransport<u64>) = connectors[2]
+            .connect_framed(url.clone(), PeerId::from(0))
+            .await
+            .unwrap();
+        assert_eq!(peer_of_a.to_usize(), 0);
+        client_a.send(42).await.unwrap();
+        let received = client_a.next().await.unwrap().unwrap();
+        assert_eq!(received, 21);
+        drop(client_a);
+
+        server_task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconnect_quic() {
+        let bind_addr: SocketAddr = "127.0.0.1:7003".parse().unwrap();
+        let url: SafeUrl = "fedimint://127.0.0.1:7003".parse().unwrap();
+        let connectors = gen_connector_config(5)
+            .into_iter()
+            .enumerate()
+            .map(|(id, cfg)| QuicConnector::new(cfg, PeerId::from(id as u16)))
+            .collect::<Vec<_>>();
+
+        let mut server: ConnectionListener<u64> = connectors[0].listen(bind_addr).await.unwrap();
+
+        let server_task = spawn("server next await", async move {
+            for round in 0..2 {
+                let (peer, mut conn) = server.next().await.unwrap().unwrap();
+                assert_eq!(peer.to_usize(), 2);
+                assert_eq!(conn.next().await.unwrap().unwrap(), round);
+                conn.send(round).await.unwrap();
+                // the stream either ends or errors once the client is gone
+                assert!(conn.next().await.map_or(true, |res| res.is_err()));
+            }
+        })
+        .expect("some handle on non-wasm");
+
+        // the second connection resumes the session of the first one with 0-RTT
+        // from the same endpoint
+        let endpoint = connectors[2]
+            .client_endpoint()
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        for round in 0..2 {
+            let (peer, mut conn): (_, AnyFramedTransport<u64>) = connectors[2]
+                .connect_framed(url.clone(), PeerId::from(0))
+                .await
+                .unwrap();
+            assert_eq!(peer.to_usize(), 0);
+            conn.send(round).await.unwrap();
+            assert_eq!(conn.next().await.unwrap().unwrap(), round);
+        }
+        assert_eq!(
+            connectors[2]
+                .client_endpoint()
+                .unwrap()
+                .local_addr()
+                .unwrap(),
+            endpoint
+        );
+
+        server_task.await.unwrap();
+    }
+
     #[tokio::test]
     async fn connect_reject() {
         let bind_addr: SocketAddr = "127.0.0.1:7001".parse().unwrap();
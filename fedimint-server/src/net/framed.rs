@@ -9,12 +9,34 @@ use std::task::{Context, Poll};
 
 use bytes::{Buf, BufMut, BytesMut};
 use fedimint_logging::LOG_NET_PEER;
+use fedimint_metrics::{lazy_static, opts, register_int_counter, IntCounter};
 use futures::{Sink, Stream};
 use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::{error, trace};
 
+/// zstd compression level used for compressed frames, chosen for speed since
+/// most consensus messages are small
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Upper bound on the size of a decompressed frame, protects against
+/// decompression bombs sent by malicious peers
+const MAX_DECOMPRESSED_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+lazy_static! {
+    static ref P2P_UNCOMPRESSED_BYTES: IntCounter = register_int_counter!(opts!(
+        "p2p_compression_uncompressed_bytes",
+        "Bytes of compressed p2p frames before compression"
+    ))
+    .unwrap();
+    static ref P2P_COMPRESSED_BYTES: IntCounter = register_int_counter!(opts!(
+        "p2p_compression_compressed_bytes",
+        "Bytes of compressed p2p frames after compression"
+    ))
+    .unwrap();
+}
+
 /// Owned [`FramedTransport`] trait object
 pub type AnyFramedTransport<M> = Box<dyn FramedTransport<M> + Send + Unpin + 'static>;
 
@@ -64,6 +86,7 @@ pub struct BidiFramed<T, WH, RH> {
 /// Framed codec that uses [`bincode`] to encode structs with [`serde`] support
 #[derive(Debug)]
 pub struct BincodeCodec<T> {
+    compression: bool,
     _pd: PhantomData<T>,
 }
 
@@ -88,6 +111,25 @@ where
         }
     }
 
+    /// Builds a new `BidiFramed` codec around streams that are already split
+    /// into a write and a read half, e.g. QUIC send and receive streams
+    pub fn new_from_halves(write: WH, read: RH) -> BidiFramed<T, WH, RH> {
+        BidiFramed {
+            sink: FramedSink::new(write, BincodeCodec::new()),
+            stream: FramedStream::new(read, BincodeCodec::new()),
+        }
+    }
+
+    /// Enables zstd compression of the frames in both directions
+    ///
+    /// Both sides of the connection have to agree on this, so it must only be
+    /// enabled if the peer advertised support for it during the handshake.
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.sink.encoder_mut().compression = compression;
+        self.stream.decoder_mut().compression = compression;
+        self
+    }
+
     /// Splits the codec in its sending and receiving parts
     ///
     /// This can be useful in cases where potentially simultaneous read and
@@ -174,6 +216,7 @@ where
 impl<T> BincodeCodec<T> {
     fn new() -> BincodeCodec<T> {
         BincodeCodec {
+            compression: false,
             _pd: Default::default(),
         }
     }
@@ -186,6 +229,26 @@ where
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: T, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
+        if self.compression {
+            let serialized = bincode::serialize(&item).map_err(|e| {
+                error!(
+                    target: LOG_NET_PEER,
+                    "Serializing message failed: {:?}", item
+                );
+                e
+            })?;
+            let compressed = zstd::bulk::compress(&serialized, COMPRESSION_LEVEL)?;
+
+            P2P_UNCOMPRESSED_BYTES.inc_by(serialized.len() as u64);
+            P2P_COMPRESSED_BYTES.inc_by(compressed.len() as u64);
+
+            dst.reserve(8 + compressed.len());
+            dst.put_u64(compressed.len() as u64);
+            dst.put_slice(&compressed);
+
+            return Ok(());
+        }
+
         // First, write a dummy length field and remember its position
         let old_len = dst.len();
         dst.writer().write_all(&[0u8; 8]).unwrap();
@@ -230,6 +293,13 @@ where
             trace!(length, "Received full message");
         }
 
+        if self.compression {
+            let frame = src.split_to(length as usize + 8);
+            let decompressed = zstd::bulk::decompress(&frame[8..], MAX_DECOMPRESSED_FRAME_SIZE)?;
+
+            return Ok(Some(bincode::deserialize(&decompressed)?));
+        }
+
         src.reader()
             .read_exact(&mut [0u8; 8][..])
             .expect("minimum length checked");
@@ -278,6 +348,35 @@ mod tests {
         assert!(framed_recipient.next().await.is_none());
     }
 
+    #[tokio::test]
+    async fn test_roundtrip_compressed() {
+        let input = vec![vec![42u8; 4096], vec![], vec![1, 2, 3]];
+        let (sender, recipient) = tokio::io::duplex(1024);
+
+        let mut framed_sender =
+            BidiFramed::<Vec<u8>, WriteHalf<DuplexStream>, ReadHalf<DuplexStream>>::new(sender)
+                .with_compression(true);
+
+        let mut framed_recipient =
+            BidiFramed::<Vec<u8>, WriteHalf<DuplexStream>, ReadHalf<DuplexStream>>::new(recipient)
+                .with_compression(true);
+
+        let receive = tokio::spawn(async move {
+            let mut received = vec![];
+            while let Some(item) = framed_recipient.next().await {
+                received.push(item.unwrap());
+            }
+            received
+        });
+
+        for item in &input {
+            framed_sender.send(item.clone()).await.unwrap();
+        }
+        drop(framed_sender);
+
+        assert_eq!(receive.await.unwrap(), input);
+    }
+
     #[tokio::test]
     async fn test_not_try_parse_partial() {
         #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
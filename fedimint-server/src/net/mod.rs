@@ -1,4 +1,10 @@
 pub mod api;
+pub mod api_guard;
+pub mod api_tls;
 pub mod connect;
 pub mod framed;
 pub mod peers;
+pub mod replica;
+pub mod scoped_tokens;
+pub mod usage;
+pub mod well_known;
@@ -10,14 +10,18 @@ use std::collections::{BTreeSet, HashMap};
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::ops::Sub;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::Context;
 use async_trait::async_trait;
-use fedimint_core::api::PeerConnectionStatus;
+use fedimint_core::api::{PeerConnectionStatus, PeerHealth};
 use fedimint_core::cancellable::{Cancellable, Cancelled};
+use fedimint_core::net::addresses::PeerAddresses;
 use fedimint_core::net::peers::IPeerConnections;
 use fedimint_core::task::{sleep_until, TaskGroup, TaskHandle};
+use fedimint_core::time::now;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::PeerId;
 use fedimint_logging::LOG_NET_PEER;
@@ -41,6 +45,21 @@ use crate::net::framed::AnyFramedTransport;
 /// that need to be re-sent in case of very one-sided communication.
 const PING_INTERVAL: Duration = Duration::from_secs(10);
 
+/// After how many consecutive failed handshakes we quarantine a peer, which
+/// means we stop queueing messages for it until a connection succeeds again
+const QUARANTINE_FAILED_HANDSHAKES: u64 = 10;
+
+/// How many seconds worth of outbound bandwidth we queue for a throttled peer
+/// before we start dropping messages to it
+const THROTTLE_MAX_QUEUED_SECS: u64 = 10;
+
+/// Every how many seconds we measure the clock offset to a connected peer
+/// again, besides when the connection is established
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How far the clock of a peer may be off from ours before we warn about it
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(10);
+
 /// Owned [`Connector`](crate::net::connect::Connector) trait object used by
 /// [`ReconnectPeerConnections`]
 pub type PeerConnector<M> = AnyConnector<PeerMessage<M>>;
@@ -59,8 +78,23 @@ pub struct ReconnectPeerConnections<T> {
 
 #[derive(Clone)]
 struct PeerConnection<T> {
-    outgoing: async_channel::Sender<T>,
+    outgoing: async_channel::Sender<(T, u64)>,
     incoming: async_channel::Receiver<T>,
+    shared: Arc<SharedPeerState>,
+    /// Limit on the serialized size of the queued messages, only set if the
+    /// outbound bandwidth to the peer is throttled
+    max_queued_bytes: Option<u64>,
+}
+
+/// State shared between a [`PeerConnection`] and its io task
+#[derive(Debug, Default)]
+struct SharedPeerState {
+    /// We drop outgoing messages to a quarantined peer
+    quarantined: AtomicBool,
+    /// Serialized size of the messages in the outgoing queue
+    queued_bytes: AtomicU64,
+    /// Number of outgoing messages we dropped since the queue was full
+    messages_dropped: AtomicU64,
 }
 
 /// Specifies the network configuration for federation-internal communication
@@ -71,8 +105,12 @@ pub struct NetworkConfig {
     /// Our listen address for incoming connections from other federation
     /// members
     pub bind_addr: SocketAddr,
-    /// Map of all peers' connection information we want to be connected to
-    pub peers: HashMap<PeerId, SafeUrl>,
+    /// Map of all peers' addresses in order of preference we want to be
+    /// connected to
+    pub peers: HashMap<PeerId, Vec<SafeUrl>>,
+    /// Limit on the outbound bandwidth to each peer in bytes per second
+    #[serde(default)]
+    pub max_outbound_bytes_per_sec: Option<u64>,
 }
 
 /// Internal message type for [`ReconnectPeerConnections`], just public because
@@ -81,6 +119,14 @@ pub struct NetworkConfig {
 pub enum PeerMessage<M> {
     Message(M),
     Ping,
+    /// Asks the peer for its time, carries the time we sent the request at
+    TimeRequest(SystemTime),
+    /// Answers a [`PeerMessage::TimeRequest`] with the time it was sent at and
+    /// the time of the peer when answering
+    TimeResponse {
+        requested_at: SystemTime,
+        peer_time: SystemTime,
+    },
 }
 
 struct PeerConnectionStateMachine<M> {
@@ -89,7 +135,7 @@ struct PeerConnectionStateMachine<M> {
 }
 
 struct PeerStatusQuery {
-    response_sender: oneshot::Sender<PeerConnectionStatus>,
+    response_sender: oneshot::Sender<PeerHealth>,
 }
 
 type PeerStatusChannelSender = Sender<PeerStatusQuery>;
@@ -103,6 +149,14 @@ pub struct PeerStatusChannels(HashMap<PeerId, PeerStatusChannelSender>);
 
 impl PeerStatusChannels {
     pub async fn get_all_status(&self) -> HashMap<PeerId, anyhow::Result<PeerConnectionStatus>> {
+        self.get_all_health()
+            .await
+            .into_iter()
+            .map(|(peer_id, health)| (peer_id, health.map(|health| health.connection_status)))
+            .collect()
+    }
+
+    pub async fn get_all_health(&self) -> HashMap<PeerId, anyhow::Result<PeerHealth>> {
         let results = self.0.iter().map(|(peer_id, sender)| async {
             let (response_sender, response_receiver) = oneshot::channel();
             let query = PeerStatusQuery { response_sender };
@@ -177,14 +231,116 @@ impl DelayCalculator {
 
 struct CommonPeerConnectionState<M> {
     incoming: async_channel::Sender<M>,
-    outgoing: async_channel::Receiver<M>,
+    outgoing: async_channel::Receiver<(M, u64)>,
     our_id: PeerId,
     peer_id: PeerId,
-    peer_address: SafeUrl,
+    peer_addresses: PeerAddresses,
     delay_calculator: DelayCalculator,
     connect: SharedAnyConnector<PeerMessage<M>>,
     incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
     status_query_receiver: PeerStatusChannelReceiver,
+    health: PeerHealthTracker,
+    throttle: Option<OutboundThrottle>,
+}
+
+/// Tracks the [`PeerHealth`] of a peer connection from within its io task
+#[derive(Default)]
+struct PeerHealthTracker {
+    messages_received: u64,
+    messages_sent: u64,
+    bytes_received: u64,
+    bytes_sent: u64,
+    connection_errors: u64,
+    reconnects: u64,
+    failed_handshakes: u64,
+    last_message: Option<Instant>,
+    clock_offset_ms: Option<i64>,
+    shared: Arc<SharedPeerState>,
+}
+
+impl PeerHealthTracker {
+    fn snapshot(&self, connection_status: PeerConnectionStatus) -> PeerHealth {
+        PeerHealth {
+            connection_status,
+            messages_received: self.messages_received,
+            messages_sent: self.messages_sent,
+            bytes_received: self.bytes_received,
+            bytes_sent: self.bytes_sent,
+            messages_dropped: self.shared.messages_dropped.load(Ordering::Relaxed),
+            connection_errors: self.connection_errors,
+            reconnects: self.reconnects,
+            failed_handshakes: self.failed_handshakes,
+            last_message_secs_ago: self
+                .last_message
+                .map(|last_message| last_message.elapsed().as_secs()),
+            quarantined: self.shared.quarantined.load(Ordering::Relaxed),
+            clock_offset_ms: self.clock_offset_ms,
+        }
+    }
+
+    /// Records a successful handshake, returns whether it lifted the
+    /// quarantine of the peer
+    fn record_handshake(&mut self) -> bool {
+        self.reconnects += 1;
+        self.failed_handshakes = 0;
+
+        self.shared.quarantined.swap(false, Ordering::Relaxed)
+    }
+
+    /// Records a failed handshake, returns whether the peer got quarantined
+    /// by it after [`QUARANTINE_FAILED_HANDSHAKES`] consecutive failures
+    fn record_failed_handshake(&mut self) -> bool {
+        self.failed_handshakes += 1;
+
+        QUARANTINE_FAILED_HANDSHAKES <= self.failed_handshakes
+            && !self.shared.quarantined.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// Token bucket limiting the outbound bandwidth to a peer
+///
+/// A message is sent as soon as the budget is not negative, even if it is
+/// larger than the remaining budget, so arbitrarily large messages can be
+/// sent. Subsequent messages have to wait until the debt is paid off.
+#[derive(Debug)]
+struct OutboundThrottle {
+    bytes_per_sec: u64,
+    /// Bytes we may send right away, we allow bursts of up to one second
+    /// worth of bandwidth
+    budget: f64,
+    last_refill: Instant,
+}
+
+impl OutboundThrottle {
+    fn new(bytes_per_sec: u64) -> Self {
+        OutboundThrottle {
+            bytes_per_sec,
+            budget: bytes_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let refill = (now - self.last_refill).as_secs_f64() * self.bytes_per_sec as f64;
+
+        self.budget = f64::min(self.budget + refill, self.bytes_per_sec as f64);
+        self.last_refill = now;
+    }
+
+    /// Returns until when we have to wait before sending the next message
+    fn throttled_until(&mut self) -> Option<Instant> {
+        self.refill();
+
+        (self.budget < 0.0).then(|| {
+            self.last_refill + Duration::from_secs_f64(-self.budget / self.bytes_per_sec as f64)
+        })
+    }
+
+    fn consume(&mut self, bytes: u64) {
+        self.refill();
+        self.budget -= bytes as f64;
+    }
 }
 
 struct DisconnectedPeerConnectionState {
@@ -195,6 +351,7 @@ struct DisconnectedPeerConnectionState {
 struct ConnectedPeerConnectionState<M> {
     connection: AnyFramedTransport<PeerMessage<M>>,
     next_ping: Instant,
+    next_clock_check: Instant,
 }
 
 enum PeerConnectionState<M> {
@@ -222,7 +379,7 @@ where
         let mut status_query_senders = HashMap::new();
         let mut connections = HashMap::new();
 
-        for (peer, peer_address) in cfg.peers.iter().filter(|(&peer, _)| peer != cfg.identity) {
+        for (peer, peer_addresses) in cfg.peers.iter().filter(|(&peer, _)| peer != cfg.identity) {
             let (connection_sender, connection_receiver) =
                 tokio::sync::mpsc::channel::<AnyFramedTransport<PeerMessage<T>>>(4);
             let (status_query_sender, status_query_receiver) =
@@ -231,7 +388,8 @@ where
             let connection = PeerConnection::new(
                 cfg.identity,
                 *peer,
-                peer_address.clone(),
+                PeerAddresses::new(peer_addresses.clone()),
+                cfg.max_outbound_bytes_per_sec,
                 delay_calculator,
                 shared_connector.clone(),
                 connection_receiver,
@@ -299,22 +457,34 @@ where
             }
         }
     }
-    #[allow(dead_code)]
-    pub fn send_sync(&self, msg: T, recipient: Recipient) {
+    /// Queues `msg` for sending and returns the peers it was dropped for since
+    /// their outgoing queue is full
+    ///
+    /// The caller is expected to cope with dropped messages, e.g. by
+    /// retransmitting them later, instead of us queuing them without bound.
+    pub fn send_sync(&self, msg: T, recipient: Recipient) -> Vec<PeerId> {
+        let mut dropped = vec![];
+
         match recipient {
             Recipient::Everyone => {
-                for connection in self.connections.values() {
-                    connection.send(msg.clone());
+                for (peer, connection) in &self.connections {
+                    if !connection.send(msg.clone()) {
+                        dropped.push(*peer);
+                    }
                 }
             }
             Recipient::Peer(peer) => {
                 if let Some(connection) = self.connections.get(&peer) {
-                    connection.send(msg.clone());
+                    if !connection.send(msg) {
+                        dropped.push(peer);
+                    }
                 } else {
                     trace!(target: LOG_NET_PEER,peer = ?peer, "Not sending message to unknown peer (maybe banned)");
                 }
             }
         }
+
+        dropped
     }
 }
 
@@ -375,7 +545,7 @@ where
 
 impl<M> PeerConnectionStateMachine<M>
 where
-    M: Debug + Clone,
+    M: Debug + Clone + Serialize,
 {
     async fn run(mut self, task_handle: &TaskHandle) {
         let peer = self.common.peer_id;
@@ -421,17 +591,25 @@ where
 
 impl<M> CommonPeerConnectionState<M>
 where
-    M: Debug + Clone,
+    M: Debug + Clone + Serialize,
 {
     async fn state_transition_connected(
         &mut self,
         mut connected: ConnectedPeerConnectionState<M>,
         task_handle: &TaskHandle,
     ) -> Option<PeerConnectionState<M>> {
+        // while throttled we leave the messages in the bounded outgoing queue, so
+        // that the sender notices once it is full
+        let throttled_until = self
+            .throttle
+            .as_mut()
+            .and_then(OutboundThrottle::throttled_until);
+
         Some(tokio::select! {
-            maybe_msg = self.outgoing.recv() => {
+            maybe_msg = self.outgoing.recv(), if throttled_until.is_none() => {
                 match maybe_msg {
-                    Ok(msg) => {
+                    Ok((msg, size)) => {
+                        self.health.shared.queued_bytes.fetch_sub(size, Ordering::Relaxed);
                         self.send_message_connected(connected, PeerMessage::Message(msg))
                             .await
                     },
@@ -456,24 +634,50 @@ where
                 }
             },
             Some(status_query) = self.status_query_receiver.recv() => {
-                if status_query.response_sender.send(PeerConnectionStatus::Connected).is_err() {
+                let health = self.health.snapshot(PeerConnectionStatus::Connected);
+                if status_query.response_sender.send(health).is_err() {
                     let peer_id = self.peer_id;
                     debug!(target: LOG_NET_PEER, %peer_id, "Could not send peer status response: receiver dropped");
                 }
                 PeerConnectionState::Connected(connected)
             },
+            () = tokio::time::sleep_until(throttled_until.unwrap_or_else(Instant::now)), if throttled_until.is_some() => {
+                PeerConnectionState::Connected(connected)
+            },
             Some(message_res) = connected.connection.next() => {
                 match message_res {
                     Ok(peer_message) => {
-                        if let PeerMessage::Message(msg) = peer_message {
-                            if self.incoming.try_send(msg).is_err(){
-                                debug!(target: LOG_NET_PEER, "Could not relay incoming message since the channel is full");
-                            }
+                        self.health.last_message = Some(Instant::now());
+                        self.health.bytes_received += serialized_size(&peer_message);
+
+                        match peer_message {
+                            PeerMessage::Message(msg) => {
+                                self.health.messages_received += 1;
+
+                                if self.incoming.try_send(msg).is_err(){
+                                    debug!(target: LOG_NET_PEER, "Could not relay incoming message since the channel is full");
+                                }
+
+                                PeerConnectionState::Connected(connected)
+                            },
+                            PeerMessage::Ping => PeerConnectionState::Connected(connected),
+                            PeerMessage::TimeRequest(requested_at) => {
+                                let response = PeerMessage::TimeResponse {
+                                    requested_at,
+                                    peer_time: now(),
+                                };
+                                self.send_message_connected(connected, response).await
+                            },
+                            PeerMessage::TimeResponse { requested_at, peer_time } => {
+                                self.record_clock_offset(requested_at, peer_time);
+                                PeerConnectionState::Connected(connected)
+                            },
                         }
-
-                        PeerConnectionState::Connected(connected)
                     },
-                    Err(e) => self.disconnect_err(e, 0),
+                    Err(e) => {
+                        self.health.connection_errors += 1;
+                        self.disconnect_err(e, 0)
+                    },
                 }
             },
             _ = sleep_until(connected.next_ping.into()) => {
@@ -481,6 +685,11 @@ where
                 self.send_message_connected(connected, PeerMessage::Ping)
                     .await
             },
+            _ = sleep_until(connected.next_clock_check.into()) => {
+                connected.next_clock_check = Instant::now() + CLOCK_CHECK_INTERVAL;
+                self.send_message_connected(connected, PeerMessage::TimeRequest(now()))
+                    .await
+            },
             _ = task_handle.make_shutdown_rx().await => {
                 return None;
             },
@@ -496,13 +705,65 @@ where
             our_id = ?self.our_id,
             peer = ?self.peer_id, %disconnect_count,
             "Initializing new connection");
-        match new_connection.send(PeerMessage::Ping).await {
-            Ok(()) => PeerConnectionState::Connected(ConnectedPeerConnectionState {
-                connection: new_connection,
-                next_ping: Instant::now(),
-            }),
-            Err(e) => self.disconnect_err(e, disconnect_count),
+        // the first message asks for the peer's time, so we learn the offset of
+        // its clock with every connection
+        match new_connection.send(PeerMessage::TimeRequest(now())).await {
+            Ok(()) => {
+                if self.health.record_handshake() {
+                    info!(target: LOG_NET_PEER, peer = ?self.peer_id, "Lifting quarantine of peer");
+                }
+
+                PeerConnectionState::Connected(ConnectedPeerConnectionState {
+                    connection: new_connection,
+                    next_ping: Instant::now(),
+                    next_clock_check: Instant::now() + CLOCK_CHECK_INTERVAL,
+                })
+            }
+            Err(e) => self.handshake_failed(e, disconnect_count),
+        }
+    }
+
+    fn handshake_failed(
+        &mut self,
+        err: anyhow::Error,
+        disconnect_count: u64,
+    ) -> PeerConnectionState<M> {
+        if self.health.record_failed_handshake() {
+            warn!(
+                target: LOG_NET_PEER,
+                peer = ?self.peer_id,
+                failed_handshakes = %self.health.failed_handshakes,
+                "Quarantining peer, we will stop sending messages to it until we reconnect"
+            );
+
+            // drop the messages we have queued up so far, the atomic broadcast does
+            // not rely on a reliable network layer
+            while let Ok((_, size)) = self.outgoing.try_recv() {
+                self.health
+                    .shared
+                    .queued_bytes
+                    .fetch_sub(size, Ordering::Relaxed);
+            }
+        }
+
+        self.disconnect_err(err, disconnect_count)
+    }
+
+    /// Records the offset of the peer's clock measured by one of our time
+    /// requests, warning the operator if it is too far off
+    fn record_clock_offset(&mut self, requested_at: SystemTime, peer_time: SystemTime) {
+        let offset_ms = clock_offset_ms(requested_at, peer_time, now());
+
+        if is_clock_skewed(offset_ms) {
+            warn!(
+                target: LOG_NET_PEER,
+                peer = ?self.peer_id,
+                %offset_ms,
+                "Clock of peer is off from ours, check that both guardians sync their time via NTP"
+            );
         }
+
+        self.health.clock_offset_ms = Some(offset_ms);
     }
 
     fn disconnect(&self, mut disconnect_count: u64) -> PeerConnectionState<M> {
@@ -541,15 +802,33 @@ where
         mut connected: ConnectedPeerConnectionState<M>,
         peer_message: PeerMessage<M>,
     ) -> PeerConnectionState<M> {
+        let is_message = matches!(peer_message, PeerMessage::Message(_));
+        let size = serialized_size(&peer_message);
+
+        if let Some(throttle) = &mut self.throttle {
+            throttle.consume(size);
+        }
+
         if let Err(e) = connected.connection.send(peer_message).await {
+            self.health.connection_errors += 1;
             return self.disconnect_err(e, 0);
         }
 
         connected.next_ping = Instant::now() + PING_INTERVAL;
 
         match connected.connection.flush().await {
-            Ok(()) => PeerConnectionState::Connected(connected),
-            Err(e) => self.disconnect_err(e, 0),
+            Ok(()) => {
+                if is_message {
+                    self.health.messages_sent += 1;
+                }
+                self.health.bytes_sent += size;
+
+                PeerConnectionState::Connected(connected)
+            }
+            Err(e) => {
+                self.health.connection_errors += 1;
+                self.disconnect_err(e, 0)
+            }
         }
     }
 
@@ -571,7 +850,8 @@ where
                 }
             },
             Some(status_query) = self.status_query_receiver.recv() => {
-                if status_query.response_sender.send(PeerConnectionStatus::Disconnected).is_err() {
+                let health = self.health.snapshot(PeerConnectionStatus::Disconnected);
+                if status_query.response_sender.send(health).is_err() {
                     let peer_id = self.peer_id;
                     debug!(target: LOG_NET_PEER, %peer_id, "Could not send peer status response: receiver dropped");
                 }
@@ -605,13 +885,47 @@ where
                 self.connect(conn, disconnected.failed_reconnect_counter)
                     .await
             }
-            Err(e) => self.disconnect_err(e, disconnected.failed_reconnect_counter),
+            Err(e) => self.handshake_failed(e, disconnected.failed_reconnect_counter),
         }
     }
 
-    async fn try_reconnect(&self) -> Result<AnyFramedTransport<PeerMessage<M>>, anyhow::Error> {
+    /// Tries the addresses of the peer in the order of their health and
+    /// preference until a connection succeeds
+    async fn try_reconnect(&mut self) -> Result<AnyFramedTransport<PeerMessage<M>>, anyhow::Error> {
         debug!(target: LOG_NET_PEER, our_id = ?self.our_id, peer = ?self.peer_id, "Trying to reconnect");
-        let addr = self.peer_address.clone();
+        let mut last_err = None;
+
+        for addr in self
+            .peer_addresses
+            .connection_order(fedimint_core::time::now())
+        {
+            match self.try_connect_address(addr.clone()).await {
+                Ok(conn) => {
+                    self.peer_addresses.record_success(&addr);
+                    return Ok(conn);
+                }
+                Err(e) => {
+                    debug!(
+                        target: LOG_NET_PEER,
+                        peer = ?self.peer_id,
+                        %addr,
+                        %e,
+                        "Could not connect to address"
+                    );
+                    self.peer_addresses
+                        .record_failure(&addr, fedimint_core::time::now());
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        Err(last_err.expect("A peer has at least one address"))
+    }
+
+    async fn try_connect_address(
+        &self,
+        addr: SafeUrl,
+    ) -> Result<AnyFramedTransport<PeerMessage<M>>, anyhow::Error> {
         let (connected_peer, conn) = self.connect.connect_framed(addr, self.peer_id).await?;
 
         if connected_peer == self.peer_id {
@@ -627,13 +941,14 @@ where
 
 impl<M> PeerConnection<M>
 where
-    M: Debug + Clone + Send + Sync + 'static,
+    M: Debug + Clone + Serialize + Send + Sync + 'static,
 {
     #[allow(clippy::too_many_arguments)]
     async fn new(
         our_id: PeerId,
         peer_id: PeerId,
-        peer_address: SafeUrl,
+        peer_addresses: PeerAddresses,
+        max_outbound_bytes_per_sec: Option<u64>,
         delay_calculator: DelayCalculator,
         connect: SharedAnyConnector<PeerMessage<M>>,
         incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
@@ -642,6 +957,9 @@ where
     ) -> PeerConnection<M> {
         let (outgoing_sender, outgoing_receiver) = async_channel::bounded(1024);
         let (incoming_sender, incoming_receiver) = async_channel::bounded(1024);
+        let shared = Arc::new(SharedPeerState::default());
+
+        let shared_io = shared.clone();
 
         task_group
             .spawn(
@@ -652,11 +970,13 @@ where
                         outgoing_receiver,
                         our_id,
                         peer_id,
-                        peer_address,
+                        peer_addresses,
                         delay_calculator,
                         connect,
                         incoming_connections,
                         status_query_receiver,
+                        shared_io,
+                        max_outbound_bytes_per_sec.map(OutboundThrottle::new),
                         &handle,
                     )
                     .await
@@ -667,13 +987,37 @@ where
         PeerConnection {
             outgoing: outgoing_sender,
             incoming: incoming_receiver,
+            shared,
+            max_queued_bytes: max_outbound_bytes_per_sec
+                .map(|bytes_per_sec| bytes_per_sec.saturating_mul(THROTTLE_MAX_QUEUED_SECS)),
         }
     }
 
-    fn send(&self, msg: M) {
-        if self.outgoing.try_send(msg).is_err() {
-            debug!(target: LOG_NET_PEER, "Could not send outgoing message since the channel is full");
+    /// Queues `msg` for sending, returns `false` if the message was dropped
+    /// because the outgoing queue is full
+    fn send(&self, msg: M) -> bool {
+        if self.shared.quarantined.load(Ordering::Relaxed) {
+            trace!(target: LOG_NET_PEER, "Not sending message to quarantined peer");
+            return true;
         }
+
+        let size = serialized_size(&msg);
+        // we reserve the bytes before queueing the message, so the io task can never
+        // release them before we added them
+        let queued_bytes = self.shared.queued_bytes.fetch_add(size, Ordering::Relaxed);
+
+        let queue_full = self.max_queued_bytes.map_or(false, |max_queued_bytes| {
+            max_queued_bytes < queued_bytes + size
+        });
+
+        if queue_full || self.outgoing.try_send((msg, size)).is_err() {
+            self.shared.queued_bytes.fetch_sub(size, Ordering::Relaxed);
+            self.shared.messages_dropped.fetch_add(1, Ordering::Relaxed);
+            debug!(target: LOG_NET_PEER, "Could not send outgoing message since the queue is full");
+            return false;
+        }
+
+        true
     }
 
     async fn receive(&mut self) -> Cancellable<M> {
@@ -684,14 +1028,16 @@ where
     #[instrument(skip_all, fields(peer))]
     async fn run_io_thread(
         incoming: async_channel::Sender<M>,
-        outgoing: async_channel::Receiver<M>,
+        outgoing: async_channel::Receiver<(M, u64)>,
         our_id: PeerId,
         peer_id: PeerId,
-        peer_address: SafeUrl,
+        peer_addresses: PeerAddresses,
         delay_calculator: DelayCalculator,
         connect: SharedAnyConnector<PeerMessage<M>>,
         incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
         status_query_receiver: PeerStatusChannelReceiver,
+        shared: Arc<SharedPeerState>,
+        throttle: Option<OutboundThrottle>,
         task_handle: &TaskHandle,
     ) {
         let common = CommonPeerConnectionState {
@@ -699,11 +1045,16 @@ where
             outgoing,
             our_id,
             peer_id,
-            peer_address,
+            peer_addresses,
             delay_calculator,
             connect,
             incoming_connections,
             status_query_receiver,
+            health: PeerHealthTracker {
+                shared,
+                ..Default::default()
+            },
+            throttle,
         };
         let initial_state = PeerConnectionState::Disconnected(DisconnectedPeerConnectionState {
             reconnect_at: Instant::now(),
@@ -719,15 +1070,45 @@ where
     }
 }
 
+/// Offset of the peer's clock to ours in milliseconds, assuming it took the
+/// time in the middle of the round trip between `sent_at` and `received_at`
+pub(crate) fn clock_offset_ms(
+    sent_at: SystemTime,
+    peer_time: SystemTime,
+    received_at: SystemTime,
+) -> i64 {
+    let round_trip = received_at.duration_since(sent_at).unwrap_or_default();
+    let our_time = sent_at + round_trip / 2;
+
+    match peer_time.duration_since(our_time) {
+        Ok(ahead) => ahead.as_millis() as i64,
+        Err(behind) => -(behind.duration().as_millis() as i64),
+    }
+}
+
+/// Whether a clock offset exceeds [`MAX_CLOCK_SKEW`]
+pub(crate) fn is_clock_skewed(offset_ms: i64) -> bool {
+    MAX_CLOCK_SKEW.as_millis() < u128::from(offset_ms.unsigned_abs())
+}
+
+/// Size of `value` on the wire before compression
+fn serialized_size<T: Serialize>(value: &T) -> u64 {
+    bincode::serialized_size(value).unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use std::time::Duration;
+    use std::time::{Duration, UNIX_EPOCH};
 
+    use fedimint_core::api::PeerConnectionStatus;
     use fedimint_core::task::{sleep, TaskGroup};
     use fedimint_core::PeerId;
 
-    use super::DelayCalculator;
+    use super::{
+        clock_offset_ms, is_clock_skewed, DelayCalculator, OutboundThrottle, PeerHealthTracker,
+        QUARANTINE_FAILED_HANDSHAKES,
+    };
     use crate::net::connect::mock::{MockNetwork, StreamReliability};
     use crate::net::connect::Connector;
     use crate::net::peers::{NetworkConfig, ReconnectPeerConnections};
@@ -747,7 +1128,7 @@ mod tests {
             .iter()
             .enumerate()
             .map(|(idx, &peer)| {
-                let cfg = peer.parse().unwrap();
+                let cfg = vec![peer.parse().unwrap()];
                 (PeerId::from(idx as u16 + 1), cfg)
             })
             .collect::<HashMap<_, _>>();
@@ -759,6 +1140,7 @@ mod tests {
                     identity: PeerId::from(id),
                     bind_addr: bind.parse().unwrap(),
                     peers: peers_ref.clone(),
+                    max_outbound_bytes_per_sec: None,
                 };
                 let connect = net_ref
                     .connector(cfg.identity, StreamReliability::MILDLY_UNRELIABLE)
@@ -795,12 +1177,126 @@ mod tests {
             let status = peer_status_client_c.get_all_status().await;
             assert_eq!(status.len(), 2);
             assert!(status.values().all(|s| s.is_ok()));
+
+            // the clock offset is measured when connecting, all peers share our clock
+            let health = peer_status_client_a.get_all_health().await;
+            assert!(health
+                .values()
+                .filter_map(|health| health.as_ref().ok()?.clock_offset_ms)
+                .all(|offset_ms| !is_clock_skewed(offset_ms)));
         }
 
         task_group.shutdown();
         task_group.join_all(None).await.unwrap();
     }
 
+    #[test]
+    fn test_clock_offset() {
+        let sent_at = UNIX_EPOCH + Duration::from_secs(100);
+        let received_at = sent_at + Duration::from_millis(200);
+
+        // corrected by half the round trip
+        assert_eq!(clock_offset_ms(sent_at, sent_at, received_at), -100);
+        assert_eq!(
+            clock_offset_ms(sent_at, sent_at + Duration::from_secs(5), received_at),
+            4900
+        );
+
+        assert!(!is_clock_skewed(-4900));
+        assert!(is_clock_skewed(60_000));
+        assert!(is_clock_skewed(-60_000));
+    }
+
+    #[test]
+    fn test_outbound_throttle() {
+        let mut throttle = OutboundThrottle::new(1_000);
+        assert!(throttle.throttled_until().is_none());
+
+        // a message larger than the budget is still sent, but the next one has to
+        // wait until the debt is paid off
+        throttle.consume(3_000);
+        let throttled_for = throttle.throttled_until().unwrap() - tokio::time::Instant::now();
+        assert!(throttled_for <= Duration::from_secs(2));
+        assert!(Duration::from_millis(1_900) < throttled_for);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let mut health = PeerHealthTracker::default();
+        let quarantined = |health: &PeerHealthTracker| {
+            health
+                .snapshot(PeerConnectionStatus::Disconnected)
+                .quarantined
+        };
+
+        // the peer is only quarantined once the threshold is reached
+        for _ in 1..QUARANTINE_FAILED_HANDSHAKES {
+            assert!(!health.record_failed_handshake());
+        }
+        assert!(!quarantined(&health));
+        assert!(health.record_failed_handshake());
+        assert!(quarantined(&health));
+
+        // further failures keep the peer quarantined without quarantining it again
+        assert!(!health.record_failed_handshake());
+        assert!(quarantined(&health));
+        assert_eq!(
+            health
+                .snapshot(PeerConnectionStatus::Disconnected)
+                .failed_handshakes,
+            QUARANTINE_FAILED_HANDSHAKES + 1
+        );
+
+        // a single successful handshake lifts the quarantine and resets the count
+        assert!(health.record_handshake());
+        assert!(!quarantined(&health));
+        let snapshot = health.snapshot(PeerConnectionStatus::Disconnected);
+        assert_eq!(snapshot.failed_handshakes, 0);
+        assert_eq!(snapshot.reconnects, 1);
+        assert!(!health.record_handshake());
+
+        // failures before the last success do not count towards the threshold
+        for _ in 1..QUARANTINE_FAILED_HANDSHAKES {
+            assert!(!health.record_failed_handshake());
+        }
+        assert!(!health.record_handshake());
+        for _ in 1..QUARANTINE_FAILED_HANDSHAKES {
+            assert!(!health.record_failed_handshake());
+        }
+        assert!(!quarantined(&health));
+        assert!(health.record_failed_handshake());
+        assert!(quarantined(&health));
+    }
+
+    #[test]
+    fn test_reconnection_backoff() {
+        // the delay grows exponentially between the floor and the ceiling
+        for (c, expected_ms) in [
+            (
+                DelayCalculator::PROD_DEFAULT,
+                [10, 16, 64, 256, 1_024, 4_096, 10_000, 10_000],
+            ),
+            (
+                DelayCalculator::TEST_DEFAULT,
+                [2_000, 2_000, 2_000, 2_000, 2_000, 4_096, 10_000, 10_000],
+            ),
+        ] {
+            for (disconnect_count, expected_ms) in (1..).zip(expected_ms) {
+                // plus a jitter of up to 10%
+                let delay = c.reconnection_delay(disconnect_count);
+                let delay_ms = (delay.as_secs_f64() * 1000.0).round() as u64;
+                assert!(
+                    (expected_ms..=expected_ms + expected_ms / 10).contains(&delay_ms),
+                    "Delay of {delay_ms}ms after {disconnect_count} disconnects"
+                );
+            }
+
+            // the ceiling holds no matter how often the peer disconnected
+            let delay = c.reconnection_delay(u64::MAX);
+            assert!(delay <= Duration::from_millis(11_000));
+        }
+    }
+
     #[test]
     fn test_delay_calculator() {
         let c = DelayCalculator::TEST_DEFAULT;
@@ -0,0 +1,248 @@
+//! Read-only snapshot of the consensus history for the API
+//!
+//! Recovering clients and block explorers read the signed blocks of many past
+//! sessions. Serving them from the live database would contend with the
+//! consensus, which has to commit every consensus item, so the API reads the
+//! history from an in-memory replica instead. A background task copies the
+//! sessions completed since its last refresh into the replica every
+//! [`REFRESH_INTERVAL`]. Since signed blocks are never modified this only has
+//! to read the new sessions from the live database.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use fedimint_core::api::{SessionRange, SnapshotInfo, SnapshotResponse};
+use fedimint_core::block::{SignedBlock, TransactionLocation};
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::{sleep, RwLock, TaskGroup, TaskHandle};
+use fedimint_core::time::now;
+use fedimint_core::TransactionId;
+use tracing::debug;
+
+use crate::db::{AcceptedTransactionLocationKey, SignedBlockKey};
+use crate::LOG_CORE;
+
+/// How often the replica copies newly completed sessions from the live
+/// database
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    session_count: u64,
+    taken_at: SystemTime,
+}
+
+/// In-memory copy of the signed blocks and the transaction location index
+#[derive(Debug, Clone)]
+pub struct HistoryReplica {
+    live: Database,
+    replica: Database,
+    snapshot: Arc<RwLock<Snapshot>>,
+}
+
+impl HistoryReplica {
+    /// Creates the replica and takes the first snapshot right away
+    pub async fn new(live: Database, decoders: ModuleDecoderRegistry) -> Self {
+        let replica = Self {
+            live,
+            replica: Database::new(MemDatabase::new(), decoders),
+            snapshot: Arc::new(RwLock::new(Snapshot {
+                session_count: 0,
+                taken_at: now(),
+            })),
+        };
+
+        replica.refresh().await;
+
+        replica
+    }
+
+    pub async fn spawn(&self, task_group: &mut TaskGroup) {
+        let replica = self.clone();
+
+        task_group
+            .spawn("history replica", move |task_handle| async move {
+                replica.run(task_handle).await;
+            })
+            .await;
+    }
+
+    async fn run(&self, task_handle: TaskHandle) {
+        while !task_handle.is_shutting_down() {
+            sleep(REFRESH_INTERVAL).await;
+
+            self.refresh().await;
+        }
+    }
+
+    /// Copies the sessions completed since the last snapshot from the live
+    /// database
+    ///
+    /// Must not be called concurrently, which holds as only the background task
+    /// refreshes the replica once it has been created.
+    pub async fn refresh(&self) {
+        let previous = *self.snapshot.read().await;
+
+        let mut live_dbtx = self.live.begin_transaction().await;
+        let mut replica_dbtx = self.replica.begin_transaction().await;
+
+        let mut session_count = previous.session_count;
+
+        while let Some(signed_block) = live_dbtx.get_value(&SignedBlockKey(session_count)).await {
+            for located in signed_block.block.located_transactions(session_count) {
+                replica_dbtx
+                    .insert_entry(
+                        &AcceptedTransactionLocationKey(located.transaction.tx_hash()),
+                        &located.location,
+                    )
+                    .await;
+            }
+
+            replica_dbtx
+                .insert_entry(&SignedBlockKey(session_count), &signed_block)
+                .await;
+
+            session_count += 1;
+        }
+
+        replica_dbtx.commit_tx().await;
+
+        if session_count != previous.session_count {
+            debug!(
+                target: LOG_CORE,
+                from = previous.session_count,
+                to = session_count,
+                "Copied sessions into history replica"
+            );
+        }
+
+        *self.snapshot.write().await = Snapshot {
+            session_count,
+            taken_at: now(),
+        };
+    }
+
+    pub async fn info(&self) -> SnapshotInfo {
+        let snapshot = *self.snapshot.read().await;
+
+        SnapshotInfo {
+            session_count: snapshot.session_count,
+            age: now().duration_since(snapshot.taken_at).unwrap_or_default(),
+        }
+    }
+
+    /// Returns the signed block if its session is contained in the snapshot
+    pub async fn signed_block(&self, index: u64) -> Option<SignedBlock> {
+        self.replica
+            .begin_transaction()
+            .await
+            .get_value(&SignedBlockKey(index))
+            .await
+    }
+
+    /// Returns the signed blocks of the sessions in `range` that are contained
+    /// in the snapshot
+    pub async fn signed_blocks(&self, range: SessionRange) -> SnapshotResponse<Vec<SignedBlock>> {
+        // the replica only grows, so it contains at least the sessions of the
+        // snapshot we read first
+        let snapshot = self.info().await;
+        let mut dbtx = self.replica.begin_transaction().await;
+        let mut signed_blocks = vec![];
+
+        for session_index in range.indices() {
+            if session_index >= snapshot.session_count {
+                break;
+            }
+
+            let signed_block = dbtx
+                .get_value(&SignedBlockKey(session_index))
+                .await
+                .expect("Session is contained in the snapshot");
+
+            signed_blocks.push(signed_block);
+        }
+
+        SnapshotResponse {
+            value: signed_blocks,
+            snapshot,
+        }
+    }
+
+    pub async fn transaction_location(
+        &self,
+        txid: TransactionId,
+    ) -> SnapshotResponse<Option<TransactionLocation>> {
+        let snapshot = self.info().await;
+
+        let location = self
+            .replica
+            .begin_transaction()
+            .await
+            .get_value(&AcceptedTransactionLocationKey(txid))
+            .await
+            .filter(|location| location.session_index < snapshot.session_count);
+
+        SnapshotResponse {
+            value: location,
+            snapshot,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::api::SessionRange;
+    use fedimint_core::block::{Block, SignedBlock};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt};
+
+    use super::HistoryReplica;
+    use crate::db::SignedBlockKey;
+
+    async fn complete_session(db: &Database, index: u64) {
+        let mut dbtx = db.begin_transaction().await;
+
+        dbtx.insert_entry(
+            &SignedBlockKey(index),
+            &SignedBlock {
+                block: Block { items: vec![] },
+                signatures: BTreeMap::new(),
+            },
+        )
+        .await;
+
+        dbtx.commit_tx().await;
+    }
+
+    #[tokio::test]
+    async fn serves_sessions_up_to_last_refresh() {
+        let live = MemDatabase::new().into_database();
+
+        complete_session(&live, 0).await;
+
+        let replica = HistoryReplica::new(live.clone(), Default::default()).await;
+
+        complete_session(&live, 1).await;
+
+        let range = SessionRange {
+            start_index: 0,
+            limit: 10,
+        };
+
+        let response = replica.signed_blocks(range).await;
+        assert_eq!(response.value.len(), 1);
+        assert_eq!(response.snapshot.session_count, 1);
+        assert!(replica.signed_block(1).await.is_none());
+
+        replica.refresh().await;
+
+        let response = replica.signed_blocks(range).await;
+        assert_eq!(response.value.len(), 2);
+        assert_eq!(response.snapshot.session_count, 2);
+        assert!(replica.signed_block(1).await.is_some());
+    }
+}
@@ -0,0 +1,160 @@
+//! Tokens that authenticate requests to the endpoints of a single module
+//!
+//! Module endpoints are public unless the module requires a capability via
+//! [`ApiEndpointContext::check_capability`](fedimint_core::module::ApiEndpointContext::check_capability).
+//! The guardian's password grants every capability. Besides it, the admin can
+//! create tokens scoped to one module instance that grant some of the
+//! capabilities the module defines, e.g. a token for a service that bumps the
+//! fees of the wallet without being able to administer the guardian. We only
+//! store the hash of every token, revoking it takes effect with the next
+//! request.
+
+use std::collections::BTreeSet;
+
+use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::api::{CreateScopedTokenRequest, ScopedToken};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::module::{ApiAuth, ApiError};
+use fedimint_core::time::now;
+use futures::StreamExt;
+use rand::rngs::OsRng;
+use rand::Rng;
+
+use crate::db::{ScopedTokenKey, ScopedTokenPrefix};
+
+fn token_hash(auth: &ApiAuth) -> sha256::Hash {
+    sha256::Hash::hash(auth.0.as_bytes())
+}
+
+/// Creates a token with a unique name, returns the token which is not stored
+pub async fn create_scoped_token(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    request: CreateScopedTokenRequest,
+) -> Result<ApiAuth, ApiError> {
+    if request.name.is_empty() || request.capabilities.is_empty() {
+        return Err(ApiError::bad_request(
+            "A scoped token needs a name and at least one capability".to_string(),
+        ));
+    }
+
+    if scoped_tokens(dbtx)
+        .await
+        .iter()
+        .any(|token| token.name == request.name)
+    {
+        return Err(ApiError::bad_request(format!(
+            "Scoped token {} already exists",
+            request.name
+        )));
+    }
+
+    let auth = ApiAuth(OsRng.gen::<[u8; 32]>().to_hex());
+
+    let token = ScopedToken {
+        name: request.name,
+        module_instance_id: request.module_instance_id,
+        capabilities: request.capabilities,
+        created_at: now(),
+    };
+
+    dbtx.insert_new_entry(&ScopedTokenKey(token_hash(&auth)), &token)
+        .await;
+
+    Ok(auth)
+}
+
+/// The scoped tokens created by our admin
+pub async fn scoped_tokens(dbtx: &mut DatabaseTransactionRef<'_>) -> Vec<ScopedToken> {
+    dbtx.find_by_prefix(&ScopedTokenPrefix)
+        .await
+        .map(|(_, token)| token)
+        .collect::<Vec<_>>()
+        .await
+}
+
+pub async fn revoke_scoped_token(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    name: &str,
+) -> Result<(), ApiError> {
+    let key = dbtx
+        .find_by_prefix(&ScopedTokenPrefix)
+        .await
+        .filter(|(_, token)| std::future::ready(token.name == name))
+        .map(|(key, _)| key)
+        .next()
+        .await
+        .ok_or_else(|| ApiError::bad_request(format!("Unknown scoped token {name}")))?;
+
+    dbtx.remove_entry(&key).await;
+
+    Ok(())
+}
+
+/// The capabilities the auth of a request grants on the module, none if it is
+/// not a token scoped to the module
+pub async fn scoped_capabilities(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    auth: &Option<ApiAuth>,
+    module_instance_id: ModuleInstanceId,
+) -> BTreeSet<String> {
+    let Some(auth) = auth else {
+        return BTreeSet::new();
+    };
+
+    match dbtx.get_value(&ScopedTokenKey(token_hash(auth))).await {
+        Some(token) if token.module_instance_id == module_instance_id => token.capabilities,
+        _ => BTreeSet::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_core::api::CreateScopedTokenRequest;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+
+    use super::{create_scoped_token, revoke_scoped_token, scoped_capabilities};
+
+    #[tokio::test]
+    async fn scopes_capabilities_to_module() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let mut dbtx = db.begin_transaction().await;
+        let request = CreateScopedTokenRequest {
+            name: "fee-bumper".to_string(),
+            module_instance_id: 2,
+            capabilities: BTreeSet::from(["fees".to_string()]),
+        };
+
+        let auth = Some(
+            create_scoped_token(&mut dbtx.dbtx_ref(), request.clone())
+                .await
+                .unwrap(),
+        );
+
+        assert!(create_scoped_token(&mut dbtx.dbtx_ref(), request)
+            .await
+            .is_err());
+        assert_eq!(
+            scoped_capabilities(&mut dbtx.dbtx_ref(), &auth, 2).await,
+            BTreeSet::from(["fees".to_string()])
+        );
+        assert!(scoped_capabilities(&mut dbtx.dbtx_ref(), &auth, 1)
+            .await
+            .is_empty());
+
+        revoke_scoped_token(&mut dbtx.dbtx_ref(), "fee-bumper")
+            .await
+            .unwrap();
+
+        assert!(scoped_capabilities(&mut dbtx.dbtx_ref(), &auth, 2)
+            .await
+            .is_empty());
+        assert!(revoke_scoped_token(&mut dbtx.dbtx_ref(), "fee-bumper")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! Accounting and limiting of the API usage by API token
+//!
+//! The [`ApiTokenConfig`]s of the local config map token names to the hash of
+//! the token and its quota. Requests carrying a configured token in their
+//! `auth` field are accounted to it, all other requests are not accounted.
+//! Usage is kept in memory and written to the database periodically, so a
+//! crash loses at most the usage of the last [`FLUSH_INTERVAL`].
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::module::{ApiAuth, ApiError};
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::time::now;
+use fedimint_core::usage::{usage_day, ApiQuota, ApiUsage, ApiUsageReport};
+use fedimint_logging::LOG_NET_API;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::config::reload::LiveConfig;
+use crate::consensus::conflict::retry_on_conflict;
+use crate::db::{ApiUsageDayPrefix, ApiUsageKey};
+
+/// How often the usage is written to the database
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The maximum number of days a usage report can cover
+const MAX_REPORT_DAYS: u64 = 366;
+
+/// An API token handed out to a client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiTokenConfig {
+    /// SHA256 hash of the token, such that the config does not contain it
+    pub token_hash: sha256::Hash,
+    #[serde(default)]
+    pub quota: ApiQuota,
+}
+
+/// Counts the serialized bytes of a response without allocating them
+struct ByteCounter(u64);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the number of bytes of the response as sent to the client
+pub fn response_bytes(response: &serde_json::Value) -> u64 {
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, response).expect("Counting can't fail");
+    counter.0
+}
+
+/// Tracks the usage of the API by API token
+#[derive(Clone)]
+pub struct ApiUsageTracker {
+    db: Database,
+    live_config: LiveConfig,
+    /// Usage of the days since we started that has not been written yet, or
+    /// that of today
+    usage: Arc<Mutex<BTreeMap<(u64, String), ApiUsage>>>,
+    usage_changed_tx: Arc<watch::Sender<()>>,
+}
+
+impl ApiUsageTracker {
+    pub async fn new(db: Database, live_config: LiveConfig, task_group: &mut TaskGroup) -> Self {
+        let today = usage_day(now());
+
+        let usage: BTreeMap<_, _> = db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&ApiUsageDayPrefix(today))
+            .await
+            .map(|(key, usage)| ((key.day, key.token), usage))
+            .collect()
+            .await;
+
+        let usage = Arc::new(Mutex::new(usage));
+        let (usage_changed_tx, mut usage_changed_rx) = watch::channel(());
+
+        task_group
+            .spawn("api usage tracker", {
+                let db = db.clone();
+                let usage = usage.clone();
+
+                |_| async move {
+                    // the loop ends once the tracker has been dropped
+                    while let Ok(()) = usage_changed_rx.changed().await {
+                        sleep(FLUSH_INTERVAL).await;
+
+                        let today = usage_day(now());
+                        let flushed = {
+                            let mut usage = usage.lock().expect("lock poisoned");
+                            let flushed = usage.clone();
+                            // we keep today's usage to enforce the quotas
+                            usage.retain(|(day, _), _| *day >= today);
+                            flushed
+                        };
+
+                        let result = retry_on_conflict("flush_api_usage", || async {
+                            let mut dbtx = db.begin_transaction().await;
+
+                            for ((day, token), usage) in &flushed {
+                                let key = ApiUsageKey {
+                                    day: *day,
+                                    token: token.clone(),
+                                };
+
+                                dbtx.insert_entry(&key, usage).await;
+                            }
+
+                            dbtx.commit_tx_result().await
+                        })
+                        .await;
+
+                        if let Err(e) = result {
+                            warn!(target: LOG_NET_API, "Could not persist API usage: {e}");
+                        }
+                    }
+                }
+            })
+            .await;
+
+        ApiUsageTracker {
+            db,
+            live_config,
+            usage,
+            usage_changed_tx: Arc::new(usage_changed_tx),
+        }
+    }
+
+    /// Returns the name of the configured token the request was sent with and
+    /// its quota
+    pub fn token(&self, auth: &Option<ApiAuth>) -> Option<(String, ApiQuota)> {
+        let token_hash = sha256::Hash::hash(auth.as_ref()?.0.as_bytes());
+
+        self.live_config.with(|live_config| {
+            live_config
+                .api_tokens
+                .iter()
+                .find(|(_, token)| token.token_hash == token_hash)
+                .map(|(name, token)| (name.clone(), token.quota.clone()))
+        })
+    }
+
+    /// Rejects the request if the token exhausted its quota for today
+    pub fn check_quota(&self, token: &str, quota: &ApiQuota) -> Result<(), ApiError> {
+        let usage = self.usage.lock().expect("lock poisoned");
+
+        match usage
+            .get(&(usage_day(now()), token.to_owned()))
+            .and_then(|usage| quota.exceeded_by(usage))
+        {
+            Some(limit) => Err(ApiError::over_quota(format!(
+                "API token {token} exceeded its quota of {limit}"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record(&self, token: String, endpoint: &str, request_bytes: u64, response_bytes: u64) {
+        self.usage
+            .lock()
+            .expect("lock poisoned")
+            .entry((usage_day(now()), token))
+            .or_default()
+            .record(endpoint, request_bytes, response_bytes);
+
+        self.usage_changed_tx.send_replace(());
+    }
+
+    /// Returns the usage of the given number of most recent days, including
+    /// today
+    pub async fn report(&self, days: u64) -> ApiUsageReport {
+        let today = usage_day(now());
+        let first_day = (today + 1).saturating_sub(days.min(MAX_REPORT_DAYS));
+
+        let mut report = ApiUsageReport::new();
+        let mut dbtx = self.db.begin_transaction().await;
+
+        for day in first_day..=today {
+            let usage = dbtx
+                .find_by_prefix(&ApiUsageDayPrefix(day))
+                .await
+                .map(|(key, usage)| (key.token, usage))
+                .collect::<BTreeMap<_, _>>()
+                .await;
+
+            if !usage.is_empty() {
+                report.insert(day, usage);
+            }
+        }
+
+        // the usage that has not been written yet is more recent
+        for ((day, token), usage) in self.usage.lock().expect("lock poisoned").iter() {
+            if first_day <= *day {
+                report
+                    .entry(*day)
+                    .or_default()
+                    .insert(token.clone(), usage.clone());
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::response_bytes;
+
+    #[test]
+    fn counts_serialized_response_bytes() {
+        let response = json!({"session_count": 42, "peers": [0, 1, 2]});
+
+        assert_eq!(
+            response_bytes(&response),
+            serde_json::to_vec(&response).unwrap().len() as u64
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! HTTP endpoint serving the signed client config under a well-known path
+//!
+//! Served under [`WELL_KNOWN_CONFIG_PATH`] of the federation's domain, e.g.
+//! behind the reverse proxy terminating HTTPS for it, the config lets users
+//! join with just the domain instead of an invite code. Unlike the config API
+//! endpoint, this does not require or count against a download token, so it
+//! is only enabled if the guardian sets a bind address for it.
+
+use std::net::SocketAddr;
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use fedimint_core::config::{ClientConfig, ClientConfigResponse, WELL_KNOWN_CONFIG_PATH};
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::task::TaskGroup;
+use fedimint_logging::LOG_NET_API;
+use tracing::{error, info};
+
+use crate::db::ClientConfigSignatureKey;
+
+#[derive(Clone)]
+struct WellKnownState {
+    db: Database,
+    client_cfg: ClientConfig,
+}
+
+async fn get_client_config(State(state): State<WellKnownState>) -> (StatusCode, HeaderMap, String) {
+    let mut headers = HeaderMap::new();
+    // wallets running in browsers fetch the config from other origins
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+
+    // the signature is created by consensus during the first session
+    let Some(signature) = state
+        .db
+        .begin_transaction()
+        .await
+        .get_value(&ClientConfigSignatureKey)
+        .await
+    else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            headers,
+            "The client config has not been signed yet".to_string(),
+        );
+    };
+
+    let response = ClientConfigResponse {
+        client_config: state.client_cfg,
+        signature,
+    };
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+
+    (
+        StatusCode::OK,
+        headers,
+        serde_json::to_string(&response).expect("Client config serializes to JSON"),
+    )
+}
+
+/// Spawns the HTTP server serving the signed client config on `bind_address`
+pub async fn spawn_well_known_server(
+    bind_address: SocketAddr,
+    db: Database,
+    client_cfg: ClientConfig,
+    task_group: &mut TaskGroup,
+) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route(WELL_KNOWN_CONFIG_PATH, get(get_client_config))
+        .with_state(WellKnownState { db, client_cfg });
+    let server = axum::Server::try_bind(&bind_address)?.serve(app.into_make_service());
+
+    let shutdown_rx = task_group.make_handle().make_shutdown_rx().await;
+    task_group
+        .spawn("well-known config", move |_| async move {
+            let graceful = server.with_graceful_shutdown(async {
+                shutdown_rx.await;
+            });
+
+            if let Err(e) = graceful.await {
+                error!(target: LOG_NET_API, "Error serving the well-known config: {e:?}");
+            }
+        })
+        .await;
+
+    info!(
+        target: LOG_NET_API,
+        "Serving the client config at {bind_address}{WELL_KNOWN_CONFIG_PATH}"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use fedimint_core::config::{ClientConfigResponse, ServerModuleInitRegistry};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::epoch::SerdeSignature;
+    use fedimint_core::module::DynServerModuleInit;
+    use fedimint_core::PeerId;
+    use fedimint_dummy_server::DummyGen;
+
+    use super::{get_client_config, WellKnownState};
+    use crate::config::ServerConfig;
+    use crate::db::ClientConfigSignatureKey;
+    use crate::simulation::config_gen_params;
+
+    #[tokio::test]
+    async fn serves_config_once_signed() {
+        let peers = (0..4).map(PeerId::from).collect::<BTreeSet<_>>();
+        let registry = ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]);
+        let cfgs = ServerConfig::trusted_dealer_gen(&config_gen_params(&peers), registry.clone());
+        let client_cfg = cfgs[&PeerId::from(0)]
+            .consensus
+            .to_client_config(&registry)
+            .expect("Client config is valid");
+
+        let state = WellKnownState {
+            db: Database::new(MemDatabase::new(), Default::default()),
+            client_cfg: client_cfg.clone(),
+        };
+
+        let (status, _, _) = get_client_config(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        let signature =
+            SerdeSignature(threshold_crypto::SecretKey::random().sign(client_cfg.consensus_hash()));
+        let mut dbtx = state.db.begin_transaction().await;
+        dbtx.insert_new_entry(&ClientConfigSignatureKey, &signature)
+            .await;
+        dbtx.commit_tx().await;
+
+        let (status, _, body) = get_client_config(State(state)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let response: ClientConfigResponse = serde_json::from_str(&body).expect("Valid JSON");
+        assert_eq!(response.client_config, client_cfg);
+        assert_eq!(response.signature, signature);
+        // the signature is not from the federation's key
+        assert!(!response.verify_signature());
+    }
+}
@@ -0,0 +1,280 @@
+//! Pre-flight checks of a guardian's config before it is launched
+//!
+//! `fedimintd preflight` connects to every peer listed in the config and checks
+//! that its P2P port is reachable and presents the TLS certificate from the
+//! config, that its API is reachable and runs the same consensus config, that
+//! its clock agrees with ours and that its bitcoin backend is at the same
+//! height as ours. The resulting [`PreflightReport`] is a go/no-go decision
+//! for launching the federation, whereas these mismatches otherwise only
+//! surface as a guardian waiting for its peers forever.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context};
+use bitcoin_hashes::sha256;
+use fedimint_core::api::{IFederationApi, WsFederationApi};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::endpoint_constants::{
+    BLOCK_COUNT_LOCAL_ENDPOINT, CONFIG_HASH_ENDPOINT, SERVER_TIME_ENDPOINT,
+};
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::task::timeout;
+use fedimint_core::time::now;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CORE;
+use futures::future::join_all;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::{PeerTransport, ServerConfig};
+use crate::net::connect::{AnyConnector, Connector, QuicConnector, TlsTcpConnector};
+use crate::net::peers::{clock_offset_ms, is_clock_skewed};
+
+/// How long we wait for a connection to or a response of a peer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many blocks the bitcoin backend of a peer may be behind or ahead of
+/// ours, as the backends learn about new blocks at slightly different times
+pub const MAX_BLOCK_COUNT_DIFFERENCE: u64 = 2;
+
+/// Outcome of the pre-flight checks of a guardian
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreflightReport {
+    pub peer: PeerId,
+    pub consensus_hash: sha256::Hash,
+    /// Block count of our bitcoin backend, if it could be queried
+    pub block_count: Option<u64>,
+    pub peers: BTreeMap<PeerId, PeerPreflight>,
+}
+
+impl PreflightReport {
+    /// Every peer passed all checks, so the federation can be launched
+    pub fn passed(&self) -> bool {
+        self.peers.values().all(|peer| peer.problems.is_empty())
+    }
+}
+
+/// What we found out about a peer, fields are `None` if it could not be
+/// checked
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerPreflight {
+    /// We connected to the P2P port and the peer authenticated itself with the
+    /// TLS certificate from the config
+    pub p2p_authenticated: bool,
+    pub consensus_hash: Option<sha256::Hash>,
+    /// Clock of the peer minus ours in milliseconds, corrected by half the
+    /// round trip time of the request
+    pub clock_offset_ms: Option<i64>,
+    /// Block count of the peer's bitcoin backend
+    pub block_count: Option<u64>,
+    /// Why the federation should not be launched yet, empty if the peer is
+    /// ready
+    pub problems: Vec<String>,
+}
+
+impl PeerPreflight {
+    /// Compares what the peer reported to our `consensus_hash` and
+    /// `block_count`
+    fn problems_with(&self, consensus_hash: sha256::Hash, block_count: Option<u64>) -> Vec<String> {
+        let mut problems = vec![];
+
+        if let Some(peer_hash) = self.consensus_hash {
+            if peer_hash != consensus_hash {
+                problems.push(format!(
+                    "Consensus config hash {peer_hash} differs from ours {consensus_hash}"
+                ));
+            }
+        }
+
+        if let Some(offset) = self.clock_offset_ms {
+            if is_clock_skewed(offset) {
+                problems.push(format!("Clock is off by {offset}ms"));
+            }
+        }
+
+        if let (Some(peer_count), Some(our_count)) = (self.block_count, block_count) {
+            if MAX_BLOCK_COUNT_DIFFERENCE < peer_count.abs_diff(our_count) {
+                problems.push(format!(
+                    "Bitcoin backend is at block {peer_count}, ours at {our_count}"
+                ));
+            }
+        }
+
+        problems
+    }
+}
+
+/// Runs the checks against every peer in `cfg`, `block_count` is the height
+/// of our bitcoin backend and `wallet_instance` the module whose peers report
+/// theirs
+pub async fn run_preflight(
+    cfg: &ServerConfig,
+    block_count: Option<u64>,
+    wallet_instance: Option<ModuleInstanceId>,
+) -> PreflightReport {
+    let our_id = cfg.local.identity;
+    let consensus_hash: sha256::Hash = cfg.consensus.consensus_hash();
+    let mut api = WsFederationApi::from_endpoints(&cfg.consensus.api_endpoints);
+    if let Some(proxy) = cfg.local.api_proxy() {
+        api = api.with_socks5_proxy(proxy);
+    }
+
+    let connector: AnyConnector<()> = match cfg.local.p2p_transport {
+        PeerTransport::TlsTcp => TlsTcpConnector::new(cfg.tls_config(), our_id).into_dyn(),
+        PeerTransport::Quic => QuicConnector::new(cfg.tls_config(), our_id).into_dyn(),
+    };
+
+    let checks = cfg
+        .local
+        .p2p_endpoints
+        .iter()
+        .filter(|(peer, _)| **peer != our_id)
+        .map(|(peer, endpoint)| {
+            let (api, connector) = (&api, &connector);
+            async move {
+                let mut problems = vec![];
+
+                let p2p_authenticated = match connect_p2p(connector, *peer, endpoint.urls()).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        problems.push(format!("P2P connection failed: {e:#}"));
+                        false
+                    }
+                };
+
+                let mut preflight = PeerPreflight {
+                    p2p_authenticated,
+                    ..PeerPreflight::default()
+                };
+
+                match query_api(api, *peer, wallet_instance).await {
+                    Ok((peer_hash, clock_offset_ms, peer_block_count)) => {
+                        preflight.consensus_hash = Some(peer_hash);
+                        preflight.clock_offset_ms = Some(clock_offset_ms);
+                        preflight.block_count = peer_block_count;
+                    }
+                    Err(e) => problems.push(format!("API request failed: {e:#}")),
+                }
+
+                problems.extend(preflight.problems_with(consensus_hash, block_count));
+                preflight.problems = problems;
+
+                (*peer, preflight)
+            }
+        });
+
+    let report = PreflightReport {
+        peer: our_id,
+        consensus_hash,
+        block_count,
+        peers: join_all(checks).await.into_iter().collect(),
+    };
+
+    info!(target: LOG_CORE, passed = report.passed(), "Pre-flight checks finished");
+
+    report
+}
+
+/// Connects to the first reachable P2P url of the peer, which authenticates
+/// it with the certificate from the config
+async fn connect_p2p(
+    connector: &AnyConnector<()>,
+    peer: PeerId,
+    urls: Vec<SafeUrl>,
+) -> anyhow::Result<()> {
+    let mut errors = vec![];
+
+    for url in urls {
+        match timeout(REQUEST_TIMEOUT, connector.connect_framed(url.clone(), peer)).await {
+            Ok(Ok(_)) => return Ok(()),
+            Ok(Err(e)) => errors.push(format!("{url}: {e}")),
+            Err(_) => errors.push(format!("{url}: timed out")),
+        }
+    }
+
+    bail!(errors.join(", "))
+}
+
+/// Returns the consensus hash, the clock offset and the block count of the
+/// bitcoin backend of the peer
+async fn query_api(
+    api: &WsFederationApi,
+    peer: PeerId,
+    wallet_instance: Option<ModuleInstanceId>,
+) -> anyhow::Result<(sha256::Hash, i64, Option<u64>)> {
+    let consensus_hash = request(api, peer, CONFIG_HASH_ENDPOINT)
+        .await
+        .context("Peer is not running the consensus")?;
+
+    let sent_at = now();
+    let peer_time: SystemTime = request(api, peer, SERVER_TIME_ENDPOINT).await?;
+    let received_at = now();
+    let clock_offset_ms = clock_offset_ms(sent_at, peer_time, received_at);
+
+    let block_count = match wallet_instance {
+        Some(instance) => {
+            let module_api = api.with_module(instance);
+            let response = timeout(
+                REQUEST_TIMEOUT,
+                module_api.request_raw(
+                    peer,
+                    BLOCK_COUNT_LOCAL_ENDPOINT,
+                    &[ApiRequestErased::default().to_json()],
+                ),
+            )
+            .await??;
+            serde_json::from_value::<Option<u32>>(response)?.map(u64::from)
+        }
+        None => None,
+    };
+
+    Ok((consensus_hash, clock_offset_ms, block_count))
+}
+
+async fn request<T: DeserializeOwned>(
+    api: &WsFederationApi,
+    peer: PeerId,
+    method: &str,
+) -> anyhow::Result<T> {
+    let response = timeout(
+        REQUEST_TIMEOUT,
+        api.request_raw(peer, method, &[ApiRequestErased::default().to_json()]),
+    )
+    .await??;
+
+    Ok(serde_json::from_value(response)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::{sha256, Hash};
+
+    use super::PeerPreflight;
+
+    #[test]
+    fn reports_mismatches() {
+        let hash = sha256::Hash::hash(b"consensus");
+        let peer = PeerPreflight {
+            p2p_authenticated: true,
+            consensus_hash: Some(hash),
+            clock_offset_ms: Some(-500),
+            block_count: Some(101),
+            problems: vec![],
+        };
+        assert!(peer.problems_with(hash, Some(100)).is_empty());
+        assert!(peer.problems_with(hash, None).is_empty());
+
+        let skewed = PeerPreflight {
+            clock_offset_ms: Some(-60_000),
+            ..peer.clone()
+        };
+        assert_eq!(skewed.problems_with(hash, Some(100)).len(), 1);
+
+        let other_hash = sha256::Hash::hash(b"other");
+        assert_eq!(peer.problems_with(other_hash, Some(110)).len(), 2);
+    }
+}
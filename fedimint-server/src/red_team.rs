@@ -0,0 +1,206 @@
+//! Malicious guardians for security testing
+//!
+//! A guardian configured with [`Faults`] deviates from the protocol in the
+//! atomic broadcast, while running the same consensus otherwise, such that we
+//! can continuously exercise how its peers tolerate a Byzantine guardian. The
+//! faults are used by the simulation and, in builds with the `red-team`
+//! feature, read from [`FM_RED_TEAM_FAULTS_ENV`] by `fedimintd`, so a single
+//! guardian of a devfed can be turned malicious. Never enable the feature for
+//! a guardian holding real funds.
+
+use std::str::FromStr;
+
+use anyhow::bail;
+use fedimint_core::block::SchnorrSignature;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::epoch::{ConsensusItem, ConsensusLimits, SerdeSignatureShare};
+use fedimint_core::meta::FederationMetaShare;
+use fedimint_core::migration::FinalStateAttestationShare;
+use fedimint_logging::LOG_CONSENSUS;
+use rand::rngs::OsRng;
+use threshold_crypto::SecretKeySet;
+use tracing::warn;
+
+use crate::atomic_broadcast::Message;
+use crate::net::peers::PeerMessage;
+
+/// Comma separated list of the [`Faults`] of the guardian, e.g.
+/// `equivocate,oversized_batches`
+pub const FM_RED_TEAM_FAULTS_ENV: &str = "FM_RED_TEAM_FAULTS";
+
+/// The ways in which a malicious guardian deviates from the protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Faults {
+    /// Broadcasts an invalid signature for every block
+    pub invalid_block_signatures: bool,
+    /// Replaces our threshold signature shares, e.g. for the client config,
+    /// with invalid ones
+    pub invalid_signature_shares: bool,
+    /// Includes every consensus item twice in our batches
+    pub duplicate_items: bool,
+    /// Pads our batches beyond the batch size limit
+    pub oversized_batches: bool,
+    /// Does not send the units of the atomic broadcast to our peers, while
+    /// still taking part in the rest of the protocol
+    pub withhold_units: bool,
+    /// Discards the backup of our units whenever we start a session, such that
+    /// we create a second unit for every round we completed before a restart
+    pub equivocate: bool,
+    /// Sends every message to our peers twice, only applied by the
+    /// simulation
+    pub replay_messages: bool,
+    /// Follows every message with one that cannot be decoded, only applied by
+    /// the simulation
+    pub garbage_messages: bool,
+}
+
+impl FromStr for Faults {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut faults = Faults::default();
+
+        for fault in s
+            .split(',')
+            .map(str::trim)
+            .filter(|fault| !fault.is_empty())
+        {
+            match fault {
+                "invalid_block_signatures" => faults.invalid_block_signatures = true,
+                "invalid_signature_shares" => faults.invalid_signature_shares = true,
+                "duplicate_items" => faults.duplicate_items = true,
+                "oversized_batches" => faults.oversized_batches = true,
+                "withhold_units" => faults.withhold_units = true,
+                "equivocate" => faults.equivocate = true,
+                "replay_messages" => faults.replay_messages = true,
+                "garbage_messages" => faults.garbage_messages = true,
+                fault => bail!("Unknown fault {fault}"),
+            }
+        }
+
+        Ok(faults)
+    }
+}
+
+impl Faults {
+    /// Reads the faults from [`FM_RED_TEAM_FAULTS_ENV`], none if it is not set
+    pub fn from_env() -> anyhow::Result<Self> {
+        let faults = match std::env::var(FM_RED_TEAM_FAULTS_ENV) {
+            Ok(faults) => faults.parse()?,
+            Err(_) => Faults::default(),
+        };
+
+        if faults != Faults::default() {
+            warn!(target: LOG_CONSENSUS, ?faults, "Running as a malicious guardian");
+        }
+
+        Ok(faults)
+    }
+
+    pub fn tamper_signature(&self, signature: SchnorrSignature) -> SchnorrSignature {
+        if self.invalid_block_signatures {
+            return SchnorrSignature([0; 64]);
+        }
+
+        signature
+    }
+
+    pub fn tamper_batch(
+        &self,
+        items: &[ConsensusItem],
+        bytes: Vec<u8>,
+        limits: &ConsensusLimits,
+    ) -> Vec<u8> {
+        if !self.invalid_signature_shares && !self.duplicate_items && !self.oversized_batches {
+            return bytes;
+        }
+
+        let mut items = items
+            .iter()
+            .map(|item| self.tamper_item(item))
+            .collect::<Vec<_>>();
+
+        if self.duplicate_items {
+            items = items
+                .into_iter()
+                .flat_map(|item| [item.clone(), item])
+                .collect();
+        }
+
+        let mut bytes = items
+            .consensus_encode_to_vec()
+            .expect("Writing to a vector cant fail");
+
+        if self.oversized_batches {
+            bytes.resize(bytes.len().max(limits.max_batch_bytes as usize + 1), 0);
+        }
+
+        bytes
+    }
+
+    fn tamper_item(&self, item: &ConsensusItem) -> ConsensusItem {
+        if !self.invalid_signature_shares {
+            return item.clone();
+        }
+
+        match item {
+            ConsensusItem::ClientConfigSignatureShare(..) => {
+                ConsensusItem::ClientConfigSignatureShare(invalid_share())
+            }
+            ConsensusItem::FinalStateAttestationShare(share) => {
+                ConsensusItem::FinalStateAttestationShare(FinalStateAttestationShare {
+                    attestation: share.attestation.clone(),
+                    share: invalid_share(),
+                })
+            }
+            ConsensusItem::FederationMetaShare(share) => {
+                ConsensusItem::FederationMetaShare(FederationMetaShare {
+                    meta: share.meta.clone(),
+                    share: invalid_share(),
+                })
+            }
+            item => item.clone(),
+        }
+    }
+
+    pub fn tamper_message(&self, message: PeerMessage<Message>) -> Vec<PeerMessage<Message>> {
+        let mut messages = vec![message.clone()];
+
+        if self.replay_messages {
+            messages.push(message);
+        }
+
+        if self.garbage_messages {
+            messages.push(PeerMessage::Message(Message(vec![0xff; 64])));
+        }
+
+        messages
+    }
+}
+
+fn invalid_share() -> SerdeSignatureShare {
+    let share = SecretKeySet::random(0, &mut OsRng)
+        .secret_key_share(0)
+        .sign("not the signed message");
+
+    SerdeSignatureShare(share)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Faults;
+
+    #[test]
+    fn parses_faults() {
+        assert_eq!("".parse::<Faults>().unwrap(), Faults::default());
+        assert_eq!(
+            "equivocate, oversized_batches".parse::<Faults>().unwrap(),
+            Faults {
+                equivocate: true,
+                oversized_batches: true,
+                ..Faults::default()
+            }
+        );
+        assert!("equivocate,be_nice".parse::<Faults>().is_err());
+    }
+}
@@ -0,0 +1,336 @@
+//! Signs with the broadcast key and the auth key share of the guardian
+//!
+//! The consensus signs broadcast messages and blocks with the broadcast key
+//! and the client config and final state attestations with the auth key share
+//! through a [`Signer`]. The [`LocalSigner`] holds the keys of the private
+//! config in memory, while the [`RemoteSigner`] delegates to a signer daemon,
+//! e.g. one that keeps the keys in an HSM via PKCS#11. The daemon is expected
+//! to serve the following endpoints, authenticated with a bearer token:
+//!
+//! * `GET /public_keys`: returns `{"broadcast_public_key": ..,
+//!   "auth_public_key_share": ..}`
+//! * `POST /sign_broadcast` with `{"message": ..}`: returns `{"signature":
+//!   ..}`, the BIP-340 schnorr signature of the 32 byte message
+//! * `POST /sign_auth` with `{"message": ..}`: returns `{"signature_share":
+//!   ..}`, the threshold BLS signature share of the message
+//!
+//! All keys, messages and signatures are hex encoded. The broadcast secret key
+//! is still needed in the private config to encrypt the shares of a key
+//! rotation, after which the rotated keys have to be imported into the HSM.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow as format_err, bail, ensure, Context};
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use fedimint_core::task::{block_in_place, sleep};
+use fedimint_core::util::SafeUrl;
+use fedimint_logging::LOG_CONSENSUS;
+use hbbft::crypto::{PublicKeyShare, SecretKeyShare, SignatureShare, SIG_SIZE};
+use secp256k1_zkp::{schnorr, All, KeyPair, Message, PublicKey, Secp256k1};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+
+/// Initial delay before retrying a failed signing request, doubled on every
+/// failure
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Maximum delay between retries of a failed signing request
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Creates signatures with the keys of the guardian
+///
+/// Signing is synchronous since the atomic broadcast signs its units
+/// synchronously. A guardian cannot contribute to the consensus without its
+/// keys, so implementations block until they can produce a valid signature.
+pub trait Signer: fmt::Debug + Send + Sync {
+    /// Signs the message with our broadcast key
+    fn sign_broadcast(&self, message: &Message) -> schnorr::Signature;
+
+    /// Signs the message with our auth key share
+    fn sign_auth(&self, message: &[u8]) -> SignatureShare;
+}
+
+pub type DynSigner = Arc<dyn Signer>;
+
+/// Signs with the keys of our private config
+pub struct LocalSigner {
+    keypair: KeyPair,
+    auth_sks: SecretKeyShare,
+    secp: Secp256k1<All>,
+}
+
+impl LocalSigner {
+    pub fn new(cfg: &ServerConfig) -> Self {
+        let secp = Secp256k1::new();
+
+        Self {
+            keypair: cfg.private.broadcast_secret_key.keypair(&secp),
+            auth_sks: cfg.private.auth_sks.0.clone(),
+            secp,
+        }
+    }
+}
+
+impl fmt::Debug for LocalSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalSigner")
+            .field("broadcast_public_key", &self.keypair.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Signer for LocalSigner {
+    fn sign_broadcast(&self, message: &Message) -> schnorr::Signature {
+        self.secp.sign_schnorr(message, &self.keypair)
+    }
+
+    fn sign_auth(&self, message: &[u8]) -> SignatureShare {
+        self.auth_sks.sign(message)
+    }
+}
+
+/// Location and credentials of a remote signer daemon
+#[derive(Clone)]
+pub struct RemoteSignerConfig {
+    pub url: SafeUrl,
+    pub auth_token: String,
+}
+
+impl fmt::Debug for RemoteSignerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteSignerConfig")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PublicKeysResponse {
+    broadcast_public_key: String,
+    auth_public_key_share: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SignRequest {
+    message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SignBroadcastResponse {
+    signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SignAuthResponse {
+    signature_share: String,
+}
+
+/// Signs with keys held by a signer daemon
+///
+/// Requires a multi-threaded runtime, since signing blocks the calling worker
+/// thread until the daemon responds.
+#[derive(Debug)]
+pub struct RemoteSigner {
+    cfg: RemoteSignerConfig,
+    broadcast_public_key: PublicKey,
+    auth_public_key_share: PublicKeyShare,
+    secp: Secp256k1<All>,
+    http: reqwest::Client,
+}
+
+impl RemoteSigner {
+    /// Connects to the signer daemon and checks that it holds the keys of our
+    /// config
+    pub async fn connect(
+        cfg: RemoteSignerConfig,
+        server_cfg: &ServerConfig,
+    ) -> anyhow::Result<Self> {
+        let identity = server_cfg.local.identity;
+        let signer = Self {
+            cfg,
+            broadcast_public_key: *server_cfg
+                .consensus
+                .broadcast_public_keys
+                .get(&identity)
+                .context("Our broadcast public key is missing from the config")?,
+            auth_public_key_share: server_cfg
+                .consensus
+                .auth_pk_set
+                .public_key_share(identity.to_usize()),
+            secp: Secp256k1::new(),
+            http: reqwest::Client::new(),
+        };
+
+        let keys: PublicKeysResponse = signer.request("public_keys", None).await?;
+
+        ensure!(
+            keys.broadcast_public_key == signer.broadcast_public_key.to_string(),
+            "Remote signer holds broadcast key {}, but our config expects {}",
+            keys.broadcast_public_key,
+            signer.broadcast_public_key
+        );
+        ensure!(
+            keys.auth_public_key_share == signer.auth_public_key_share.to_bytes().to_hex(),
+            "Remote signer holds a different auth key share than our config"
+        );
+
+        info!(target: LOG_CONSENSUS, url = %signer.cfg.url, "Connected to remote signer");
+
+        Ok(signer)
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: Option<&SignRequest>,
+    ) -> anyhow::Result<T> {
+        let url = self.cfg.url.join(path)?;
+        let request = match body {
+            Some(body) => self
+                .http
+                .post(url.reap_guts())
+                .header("content-type", "application/json")
+                .body(serde_json::to_vec(body)?),
+            None => self.http.get(url.reap_guts()),
+        };
+
+        let response = request.bearer_auth(&self.cfg.auth_token).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("Remote signer responded with {status}: {text}");
+        }
+
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+
+    async fn try_sign_broadcast(&self, message: &Message) -> anyhow::Result<schnorr::Signature> {
+        let request = SignRequest {
+            message: message[..].to_hex(),
+        };
+        let response: SignBroadcastResponse =
+            self.request("sign_broadcast", Some(&request)).await?;
+        let signature = schnorr::Signature::from_slice(&Vec::<u8>::from_hex(&response.signature)?)?;
+
+        self.secp
+            .verify_schnorr(
+                &signature,
+                message,
+                &self.broadcast_public_key.x_only_public_key().0,
+            )
+            .context("Remote signer returned an invalid broadcast signature")?;
+
+        Ok(signature)
+    }
+
+    async fn try_sign_auth(&self, message: &[u8]) -> anyhow::Result<SignatureShare> {
+        let request = SignRequest {
+            message: message.to_hex(),
+        };
+        let response: SignAuthResponse = self.request("sign_auth", Some(&request)).await?;
+        let bytes: [u8; SIG_SIZE] = Vec::<u8>::from_hex(&response.signature_share)?
+            .try_into()
+            .map_err(|_| format_err!("Signature share has an invalid length"))?;
+        let share = SignatureShare::from_bytes(bytes)
+            .map_err(|e| format_err!("Invalid signature share: {e:?}"))?;
+
+        ensure!(
+            self.auth_public_key_share.verify(&share, message),
+            "Remote signer returned an invalid signature share"
+        );
+
+        Ok(share)
+    }
+
+    /// Blocks the current worker thread until the signer daemon returns a
+    /// valid signature
+    fn sign_with_retry<T, F, Fut>(&self, key: &str, sign: F) -> T
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = anyhow::Result<T>>,
+    {
+        block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async {
+                let mut delay = INITIAL_RETRY_DELAY;
+
+                loop {
+                    match sign().await {
+                        Ok(signature) => return signature,
+                        Err(e) => {
+                            warn!(
+                                target: LOG_CONSENSUS,
+                                key,
+                                "Remote signer failed to sign, retrying in {delay:?}: {e:?}"
+                            );
+                            sleep(delay).await;
+                            delay = (delay * 2).min(MAX_RETRY_DELAY);
+                        }
+                    }
+                }
+            })
+        })
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn sign_broadcast(&self, message: &Message) -> schnorr::Signature {
+        self.sign_with_retry("broadcast", || self.try_sign_broadcast(message))
+    }
+
+    fn sign_auth(&self, message: &[u8]) -> SignatureShare {
+        self.sign_with_retry("auth", || self.try_sign_auth(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    use aleph_bft::{Index, Keychain as KeychainTrait};
+    use fedimint_core::config::ServerModuleInitRegistry;
+    use fedimint_core::module::DynServerModuleInit;
+    use fedimint_core::PeerId;
+    use fedimint_dummy_server::DummyGen;
+
+    use super::{LocalSigner, Signer};
+    use crate::atomic_broadcast::Keychain;
+    use crate::config::ServerConfig;
+    use crate::simulation::config_gen_params;
+
+    #[test]
+    fn local_signer_signs_with_the_config_keys() {
+        let peers = (0..4).map(PeerId::from).collect::<BTreeSet<_>>();
+        let registry = ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]);
+        let cfgs = ServerConfig::trusted_dealer_gen(&config_gen_params(&peers), registry);
+        let message = b"signed by the guardians";
+
+        let keychains = cfgs
+            .iter()
+            .map(|(peer, cfg)| {
+                Keychain::new(
+                    *peer,
+                    cfg.consensus.broadcast_public_keys.clone(),
+                    Arc::new(LocalSigner::new(cfg)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for keychain in &keychains {
+            let signature = keychain.sign(message);
+            assert!(keychains[0].verify(message, &signature, keychain.index()));
+        }
+
+        for (peer, cfg) in &cfgs {
+            let share = LocalSigner::new(cfg).sign_auth(message);
+            let pk_share = cfg.consensus.auth_pk_set.public_key_share(peer.to_usize());
+            assert!(pk_share.verify(&share, message));
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! In-memory API of the simulated peers
+//!
+//! Requests are dispatched to the [`server_endpoints`] of the peers
+//! [`ConsensusApi`] directly instead of going through a websocket, but are
+//! subject to the same latency and partitions as the peer to peer messages.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use fedimint_core::api::{DynModuleApi, IFederationApi, IGlobalFederationApi, JsonRpcResult};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::module::{ApiEndpoint, ApiRequestErased};
+use fedimint_core::task::sleep;
+use fedimint_core::PeerId;
+use serde_json::Value;
+
+use super::network::SimulatedLinks;
+use crate::net::api::{server_endpoints, ConsensusApi};
+use crate::HasApiContext;
+
+/// The [`ConsensusApi`] of every simulated peer that is currently running
+#[derive(Clone, Default)]
+pub struct SimulatedApis(Arc<Mutex<BTreeMap<PeerId, Arc<ConsensusApi>>>>);
+
+impl SimulatedApis {
+    pub fn insert(&self, peer: PeerId, api: ConsensusApi) {
+        self.0
+            .lock()
+            .expect("Api lock poisoned")
+            .insert(peer, Arc::new(api));
+    }
+
+    pub fn remove(&self, peer: PeerId) {
+        self.0.lock().expect("Api lock poisoned").remove(&peer);
+    }
+
+    fn get(&self, peer: PeerId) -> Option<Arc<ConsensusApi>> {
+        self.0
+            .lock()
+            .expect("Api lock poisoned")
+            .get(&peer)
+            .cloned()
+    }
+}
+
+/// Federation API as seen by one of the simulated peers
+#[derive(Clone)]
+pub struct SimulatedPeerApi {
+    our_id: PeerId,
+    peers: BTreeSet<PeerId>,
+    apis: SimulatedApis,
+    endpoints: Arc<Vec<ApiEndpoint<ConsensusApi>>>,
+    links: SimulatedLinks,
+}
+
+impl SimulatedPeerApi {
+    pub fn new(
+        our_id: PeerId,
+        peers: BTreeSet<PeerId>,
+        apis: SimulatedApis,
+        links: SimulatedLinks,
+    ) -> Self {
+        Self {
+            our_id,
+            peers,
+            apis,
+            endpoints: Arc::new(server_endpoints()),
+            links,
+        }
+    }
+
+    fn unreachable(&self, peer_id: PeerId) -> jsonrpsee_core::Error {
+        jsonrpsee_core::Error::Transport(anyhow!(
+            "Peer {peer_id} is unreachable from peer {}",
+            self.our_id
+        ))
+    }
+}
+
+impl Debug for SimulatedPeerApi {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimulatedPeerApi")
+            .field("our_id", &self.our_id)
+            .field("peers", &self.peers)
+            .finish()
+    }
+}
+
+impl IGlobalFederationApi for SimulatedPeerApi {}
+
+#[async_trait]
+impl IFederationApi for SimulatedPeerApi {
+    fn all_peers(&self) -> &BTreeSet<PeerId> {
+        &self.peers
+    }
+
+    fn with_module(&self, _id: ModuleInstanceId) -> DynModuleApi {
+        unimplemented!("The simulation only serves the global API")
+    }
+
+    async fn request_raw(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        params: &[Value],
+    ) -> JsonRpcResult<Value> {
+        let (latency, _) = self
+            .links
+            .sample_latency(self.our_id, peer_id)
+            .ok_or_else(|| self.unreachable(peer_id))?;
+
+        sleep(latency).await;
+
+        let api = self
+            .apis
+            .get(peer_id)
+            .ok_or_else(|| self.unreachable(peer_id))?;
+
+        let endpoint = self
+            .endpoints
+            .iter()
+            .find(|endpoint| endpoint.path == method)
+            .ok_or_else(|| jsonrpsee_core::Error::Custom(format!("Unknown method {method}")))?;
+
+        let request: ApiRequestErased = params
+            .first()
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default();
+
+        let (state, context) =
+            HasApiContext::<ConsensusApi>::context(api.as_ref(), &request, None).await;
+
+        let response = (endpoint.handler)(state, context, request)
+            .await
+            .map_err(|e| jsonrpsee_core::Error::Custom(e.message))?;
+
+        // the response has to travel back over the link as well
+        if !self.links.is_linked(self.our_id, peer_id) {
+            return Err(self.unreachable(peer_id));
+        }
+
+        Ok(response)
+    }
+}
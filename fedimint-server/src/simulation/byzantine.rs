@@ -0,0 +1,222 @@
+//! Malicious peers for the simulation
+//!
+//! A peer configured with [`Faults`] deviates from the protocol in the atomic
+//! broadcast and on the network, while running the same consensus otherwise.
+//! Equivocation is simulated by restarting a peer without the backup of its
+//! units via [`Step::RestartWithoutBackup`] or by restarting a peer with
+//! [`Faults::equivocate`], such that it creates a second unit for the rounds it
+//! has already completed.
+//!
+//! [`Step::RestartWithoutBackup`]: super::Step::RestartWithoutBackup
+
+pub use crate::red_team::Faults;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::PeerId;
+
+    use super::Faults;
+    use crate::db::ClientConfigSignatureKey;
+    use crate::simulation::{Scenario, Simulation, Step};
+
+    fn malicious() -> PeerId {
+        PeerId::from(3)
+    }
+
+    fn honest() -> BTreeSet<PeerId> {
+        [0, 1, 2].into_iter().map(PeerId::from).collect()
+    }
+
+    fn all() -> BTreeSet<PeerId> {
+        [0, 1, 2, 3].into_iter().map(PeerId::from).collect()
+    }
+
+    async fn run_with_faults(seed: u64, faults: Faults) -> Simulation {
+        let mut simulation = Simulation::new(4, seed);
+
+        simulation.set_faults(malicious(), faults);
+        simulation.start_all().await;
+
+        simulation
+            .run(Scenario::new().then(Step::AwaitSessions(honest(), 2)))
+            .await;
+
+        simulation
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn honest_peers_ignore_oversized_batches() {
+        let simulation = run_with_faults(
+            4,
+            Faults {
+                oversized_batches: true,
+                ..Faults::default()
+            },
+        )
+        .await;
+
+        for peer in honest() {
+            for signed_block in simulation.signed_blocks(peer).await {
+                assert!(signed_block
+                    .block
+                    .items
+                    .iter()
+                    .all(|accepted_item| accepted_item.peer != malicious()));
+            }
+        }
+
+        simulation.assert_consistent_among(&honest()).await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn honest_peers_reject_invalid_and_duplicate_items() {
+        let simulation = run_with_faults(
+            5,
+            Faults {
+                invalid_signature_shares: true,
+                duplicate_items: true,
+                ..Faults::default()
+            },
+        )
+        .await;
+
+        for peer in honest() {
+            // the client config is signed by the shares of the honest peers alone
+            simulation
+                .db(peer)
+                .wait_key_exists(&ClientConfigSignatureKey)
+                .await;
+
+            let mut accepted_items = vec![];
+
+            for signed_block in simulation.signed_blocks(peer).await {
+                for accepted_item in signed_block.block.items {
+                    assert!(
+                        !(accepted_item.peer == malicious()
+                            && matches!(
+                                accepted_item.item,
+                                ConsensusItem::ClientConfigSignatureShare(..)
+                            )),
+                        "Peer {peer} accepted an invalid signature share"
+                    );
+
+                    assert!(
+                        !accepted_items.contains(&accepted_item),
+                        "Peer {peer} accepted a duplicate item"
+                    );
+
+                    accepted_items.push(accepted_item);
+                }
+            }
+        }
+
+        simulation.assert_consistent_among(&honest()).await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn honest_peers_ignore_invalid_block_signatures() {
+        let simulation = run_with_faults(
+            6,
+            Faults {
+                invalid_block_signatures: true,
+                ..Faults::default()
+            },
+        )
+        .await;
+
+        for peer in honest() {
+            for signed_block in simulation.signed_blocks(peer).await {
+                assert!(!signed_block.signatures.contains_key(&malicious()));
+            }
+        }
+
+        simulation.assert_consistent_among(&honest()).await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn peers_tolerate_replayed_and_garbage_messages() {
+        let mut simulation = run_with_faults(
+            7,
+            Faults {
+                replay_messages: true,
+                garbage_messages: true,
+                ..Faults::default()
+            },
+        )
+        .await;
+
+        // the malicious peer still follows the atomic broadcast otherwise
+        simulation
+            .run(Scenario::new().then(Step::AwaitSessions(all(), 3)))
+            .await;
+
+        simulation.assert_consistent().await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn honest_peers_tolerate_equivocation() {
+        let mut simulation = Simulation::new(4, 8);
+
+        simulation.start_all().await;
+
+        simulation
+            .run(
+                Scenario::new()
+                    .then(Step::AwaitSessions(all(), 1))
+                    .then(Step::RestartWithoutBackup(malicious()))
+                    .then(Step::AwaitSessions(honest(), 3)),
+            )
+            .await;
+
+        simulation.assert_consistent_among(&honest()).await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn honest_peers_tolerate_withheld_units() {
+        let simulation = run_with_faults(
+            9,
+            Faults {
+                withhold_units: true,
+                ..Faults::default()
+            },
+        )
+        .await;
+
+        for peer in honest() {
+            for signed_block in simulation.signed_blocks(peer).await {
+                assert!(!signed_block.signatures.contains_key(&malicious()));
+            }
+        }
+
+        simulation.assert_consistent_among(&honest()).await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn honest_peers_tolerate_equivocation_after_restart() {
+        let mut simulation = Simulation::new(4, 10);
+
+        simulation.set_faults(
+            malicious(),
+            Faults {
+                equivocate: true,
+                ..Faults::default()
+            },
+        );
+        simulation.start_all().await;
+
+        simulation
+            .run(
+                Scenario::new()
+                    .then(Step::AwaitSessions(all(), 1))
+                    .then(Step::Stop(malicious()))
+                    .then(Step::Start(malicious()))
+                    .then(Step::AwaitSessions(honest(), 3)),
+            )
+            .await;
+
+        simulation.assert_consistent_among(&honest()).await;
+    }
+}
@@ -0,0 +1,503 @@
+//! Simulation of a federation in a single process
+//!
+//! Runs a [`ConsensusServer`] for every peer on an in-memory network whose
+//! latency, message ordering and partitions are controlled by a seeded random
+//! number generator and a [`Scenario`], such that a failing scenario can be
+//! replayed with the same seed. The network and all timers of the
+//! fedimint consensus are driven by the tokio clock, which the tests pause such
+//! that simulated time only advances while all peers are idle. The atomic
+//! broadcast schedules its rounds with its own timers however, which is why the
+//! simulation shortens the round delay instead.
+
+mod api;
+pub mod byzantine;
+mod network;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use fedimint_core::admin_client::{ConfigGenParamsConsensus, PeerServerParams};
+use fedimint_core::block::SignedBlock;
+use fedimint_core::config::{
+    ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry, META_FEDERATION_NAME_KEY,
+};
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::epoch::ConsensusLimits;
+use fedimint_core::fee::FeeSchedule;
+use fedimint_core::module::{ApiAuth, ServerModuleInit};
+use fedimint_core::task::{sleep, spawn, TaskGroup};
+use fedimint_core::PeerId;
+use fedimint_dummy_common::config::DummyGenParams;
+use fedimint_dummy_server::DummyGen;
+use futures::StreamExt;
+use tokio::task::JoinHandle;
+
+use self::api::{SimulatedApis, SimulatedPeerApi};
+use self::byzantine::Faults;
+pub use self::network::LinkConditions;
+use self::network::{SimulatedLinks, SimulatedNetwork};
+use crate::atomic_broadcast::Message;
+use crate::config::api::ConfigGenParamsLocal;
+use crate::config::{gen_cert_and_key, ConfigGenParams, DynServerModuleInit, ServerConfig};
+use crate::consensus::server::ConsensusServer;
+use crate::db::{AlephUnitsPrefix, SignedBlockKey, SignedBlockPrefix};
+use crate::net::connect::Connector;
+use crate::net::peers::{DelayCalculator, PeerMessage};
+use crate::signer::LocalSigner;
+
+/// Round delay of the atomic broadcast in the simulation
+const SIMULATED_ROUND_DELAY: Duration = Duration::from_millis(1);
+
+/// A single step of a [`Scenario`]
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// Advances the simulated time
+    Sleep(Duration),
+    /// Waits until all given peers have completed the given number of sessions
+    AwaitSessions(BTreeSet<PeerId>, u64),
+    /// Cuts all links between the given peers and the remaining ones
+    Partition(BTreeSet<PeerId>),
+    /// Cuts all links of a single peer
+    Isolate(PeerId),
+    /// Restores all links that have been cut
+    Heal,
+    /// Sets the conditions of all links without explicit conditions
+    Conditions(LinkConditions),
+    /// Sets the conditions of the messages sent from one peer to another
+    LinkConditions {
+        from: PeerId,
+        to: PeerId,
+        conditions: LinkConditions,
+    },
+    /// Crashes a peer, it keeps its database
+    Stop(PeerId),
+    /// Restarts a peer that has been stopped
+    Start(PeerId),
+    /// Restarts a running peer after deleting the units it has created in the
+    /// current session, such that it equivocates
+    RestartWithoutBackup(PeerId),
+}
+
+/// Steps that are applied to a [`Simulation`] one after another
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+}
+
+struct RunningPeer {
+    task_group: TaskGroup,
+    handle: JoinHandle<()>,
+}
+
+/// A federation whose peers run in the same process on a simulated network
+pub struct Simulation {
+    configs: BTreeMap<PeerId, ServerConfig>,
+    server_init: ServerModuleInitRegistry,
+    dbs: BTreeMap<PeerId, Database>,
+    network: SimulatedNetwork<PeerMessage<Message>>,
+    apis: SimulatedApis,
+    running: BTreeMap<PeerId, RunningPeer>,
+    faults: BTreeMap<PeerId, Faults>,
+}
+
+impl Simulation {
+    /// Creates a federation of `num_peers` peers, none of which is running yet
+    pub fn new(num_peers: u16, seed: u64) -> Self {
+        let peers = (0..num_peers).map(PeerId::from).collect::<BTreeSet<_>>();
+        let server_init = ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyGen)]);
+
+        let configs =
+            ServerConfig::trusted_dealer_gen(&config_gen_params(&peers), server_init.clone());
+
+        let dbs = configs
+            .iter()
+            .map(|(peer, config)| {
+                let decoders = server_init
+                    .available_decoders(config.consensus.iter_module_instances())
+                    .expect("Decoders for all modules are available");
+
+                (*peer, Database::new(MemDatabase::new(), decoders))
+            })
+            .collect();
+
+        Self {
+            configs,
+            server_init,
+            dbs,
+            network: SimulatedNetwork::new(peers, seed),
+            apis: SimulatedApis::default(),
+            running: BTreeMap::new(),
+            faults: BTreeMap::new(),
+        }
+    }
+
+    pub fn peers(&self) -> BTreeSet<PeerId> {
+        self.configs.keys().copied().collect()
+    }
+
+    pub fn links(&self) -> &SimulatedLinks {
+        self.network.links()
+    }
+
+    pub fn db(&self, peer: PeerId) -> &Database {
+        &self.dbs[&peer]
+    }
+
+    /// Makes a peer malicious once it is started the next time
+    pub fn set_faults(&mut self, peer: PeerId, faults: Faults) {
+        self.faults.insert(peer, faults);
+    }
+
+    /// Starts all peers that are not running yet
+    pub async fn start_all(&mut self) {
+        for peer in self.peers() {
+            if !self.running.contains_key(&peer) {
+                self.start(peer).await;
+            }
+        }
+    }
+
+    pub async fn start(&mut self, peer: PeerId) {
+        assert!(!self.running.contains_key(&peer), "Peer {peer} is running");
+
+        let mut task_group = TaskGroup::new();
+
+        let (server, api) = ConsensusServer::new_with(
+            self.configs[&peer].clone(),
+            self.dbs[&peer].clone(),
+            self.server_init.clone(),
+            Arc::new(LocalSigner::new(&self.configs[&peer])),
+            self.network.connector(peer).into_dyn(),
+            DelayCalculator::TEST_DEFAULT,
+            &mut task_group,
+        )
+        .await
+        .expect("Failed to init server");
+
+        let peer_api =
+            SimulatedPeerApi::new(peer, self.peers(), self.apis.clone(), self.links().clone());
+
+        let faults = self.faults.get(&peer).copied().unwrap_or_default();
+
+        let server = server
+            .with_peer_api(peer_api.into())
+            .with_round_delay(SIMULATED_ROUND_DELAY)
+            .with_faults(faults);
+
+        self.network.set_tamper(
+            peer,
+            Arc::new(move |message| faults.tamper_message(message)),
+        );
+
+        self.apis.insert(peer, api);
+
+        let task_handle = task_group.make_handle();
+
+        let handle = spawn("simulated consensus", async move {
+            server
+                .run(task_handle)
+                .await
+                .expect("Simulated consensus failed");
+        })
+        .expect("some handle on non-wasm");
+
+        self.running
+            .insert(peer, RunningPeer { task_group, handle });
+    }
+
+    /// Crashes a peer without giving it the chance to shut down cleanly
+    pub fn stop(&mut self, peer: PeerId) {
+        let running = self
+            .running
+            .remove(&peer)
+            .unwrap_or_else(|| panic!("Peer {peer} is not running"));
+
+        running.handle.abort();
+        running.task_group.shutdown();
+
+        self.apis.remove(peer);
+        self.network.stop_listening(peer);
+    }
+
+    pub async fn run(&mut self, scenario: Scenario) {
+        for step in scenario.steps {
+            match step {
+                Step::Sleep(duration) => sleep(duration).await,
+                Step::AwaitSessions(peers, count) => self.await_sessions(&peers, count).await,
+                Step::Partition(side) => self.links().partition(&side),
+                Step::Isolate(peer) => self.links().isolate(peer),
+                Step::Heal => self.links().heal(),
+                Step::Conditions(conditions) => self.links().set_default_conditions(conditions),
+                Step::LinkConditions {
+                    from,
+                    to,
+                    conditions,
+                } => self.links().set_conditions(from, to, conditions),
+                Step::Stop(peer) => self.stop(peer),
+                Step::Start(peer) => self.start(peer).await,
+                Step::RestartWithoutBackup(peer) => self.restart_without_backup(peer).await,
+            }
+        }
+    }
+
+    async fn restart_without_backup(&mut self, peer: PeerId) {
+        self.stop(peer);
+
+        let mut dbtx = self.dbs[&peer].begin_transaction().await;
+        dbtx.remove_by_prefix(&AlephUnitsPrefix).await;
+        dbtx.commit_tx().await;
+
+        self.start(peer).await;
+    }
+
+    pub async fn await_sessions(&self, peers: &BTreeSet<PeerId>, count: u64) {
+        for peer in peers {
+            if let Some(index) = count.checked_sub(1) {
+                self.dbs[peer].wait_key_exists(&SignedBlockKey(index)).await;
+            }
+        }
+    }
+
+    pub async fn signed_blocks(&self, peer: PeerId) -> Vec<SignedBlock> {
+        self.dbs[&peer]
+            .begin_transaction()
+            .await
+            .find_by_prefix(&SignedBlockPrefix)
+            .await
+            .map(|(_, signed_block)| signed_block)
+            .collect()
+            .await
+    }
+
+    /// Asserts that no two peers have signed different blocks for the same
+    /// session
+    pub async fn assert_consistent(&self) {
+        self.assert_consistent_among(&self.peers()).await;
+    }
+
+    /// Asserts that no two of the given peers have signed different blocks for
+    /// the same session
+    pub async fn assert_consistent_among(&self, peers: &BTreeSet<PeerId>) {
+        let mut blocks = BTreeMap::new();
+
+        for peer in peers.iter().copied() {
+            for (index, signed_block) in self.signed_blocks(peer).await.into_iter().enumerate() {
+                let block = blocks
+                    .entry(index)
+                    .or_insert_with(|| signed_block.block.clone());
+
+                assert_eq!(
+                    *block, signed_block.block,
+                    "Peer {peer} diverged in session {index}"
+                );
+            }
+        }
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        for peer in self.running.keys().copied().collect::<Vec<_>>() {
+            self.stop(peer);
+        }
+    }
+}
+
+pub(crate) fn config_gen_params(peers: &BTreeSet<PeerId>) -> HashMap<PeerId, ConfigGenParams> {
+    let tls_keys = peers
+        .iter()
+        .map(|peer| {
+            let name = format!("peer-{}", peer.to_usize());
+
+            (*peer, gen_cert_and_key(&name).expect("Generates TLS keys"))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    // the urls are never dialed, the simulated network routes by peer id
+    let connections = peers
+        .iter()
+        .map(|peer| {
+            let port = 10000 + 2 * u16::from(*peer);
+
+            let params = PeerServerParams {
+                cert: tls_keys[peer].0.clone(),
+                p2p_url: format!("fedimint://127.0.0.1:{port}")
+                    .parse()
+                    .expect("Valid url"),
+                api_url: format!("ws://127.0.0.1:{}", port + 1)
+                    .parse()
+                    .expect("Valid url"),
+                p2p_fallback_urls: vec![],
+                api_fallback_urls: vec![],
+                name: format!("peer-{}", peer.to_usize()),
+                status: None,
+            };
+
+            (*peer, params)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut modules = ServerModuleConfigGenParamsRegistry::default();
+    modules.attach_config_gen_params(0, DummyGen::kind(), DummyGenParams::default());
+
+    peers
+        .iter()
+        .map(|peer| {
+            let port = 10000 + 2 * u16::from(*peer);
+
+            let params = ConfigGenParams {
+                local: ConfigGenParamsLocal {
+                    our_id: *peer,
+                    our_private_key: tls_keys[peer].1.clone(),
+                    api_auth: ApiAuth("pass".to_string()),
+                    p2p_bind: format!("127.0.0.1:{port}").parse().expect("Valid address"),
+                    api_bind: format!("127.0.0.1:{}", port + 1)
+                        .parse()
+                        .expect("Valid address"),
+                    download_token_limit: None,
+                    max_connections: 10,
+                    socks5_proxy: None,
+                    p2p_max_outbound_bytes_per_sec: None,
+                    api_tls: None,
+                },
+                consensus: ConfigGenParamsConsensus {
+                    peers: connections.clone(),
+                    meta: BTreeMap::from([(
+                        META_FEDERATION_NAME_KEY.to_owned(),
+                        "simulation".to_string(),
+                    )]),
+                    modules: modules.clone(),
+                    limits: ConsensusLimits::default(),
+                    fees: FeeSchedule::default(),
+                },
+            };
+
+            (*peer, params)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    use fedimint_core::PeerId;
+
+    use super::{LinkConditions, Scenario, Simulation, Step};
+
+    fn peers(ids: &[u16]) -> BTreeSet<PeerId> {
+        ids.iter().copied().map(PeerId::from).collect()
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn peers_agree_on_blocks_despite_reordering() {
+        let mut simulation = Simulation::new(4, 0);
+
+        simulation.start_all().await;
+
+        simulation
+            .run(
+                Scenario::new()
+                    .then(Step::Conditions(LinkConditions {
+                        min_latency: Duration::from_millis(1),
+                        max_latency: Duration::from_millis(200),
+                        reorder: true,
+                    }))
+                    .then(Step::LinkConditions {
+                        from: PeerId::from(0),
+                        to: PeerId::from(1),
+                        conditions: LinkConditions {
+                            min_latency: Duration::from_secs(1),
+                            max_latency: Duration::from_secs(2),
+                            reorder: true,
+                        },
+                    })
+                    .then(Step::AwaitSessions(peers(&[0, 1, 2, 3]), 2)),
+            )
+            .await;
+
+        simulation.assert_consistent().await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn isolated_peer_catches_up_after_partition_heals() {
+        let mut simulation = Simulation::new(4, 1);
+
+        simulation.start_all().await;
+
+        simulation
+            .run(
+                Scenario::new()
+                    .then(Step::Isolate(PeerId::from(3)))
+                    .then(Step::AwaitSessions(peers(&[0, 1, 2]), 2))
+                    .then(Step::Heal)
+                    .then(Step::AwaitSessions(peers(&[0, 1, 2, 3]), 3)),
+            )
+            .await;
+
+        simulation.assert_consistent().await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn split_federation_halts_until_partition_heals() {
+        let mut simulation = Simulation::new(4, 2);
+
+        simulation.start_all().await;
+
+        simulation
+            .run(
+                Scenario::new()
+                    .then(Step::Partition(peers(&[0, 1])))
+                    .then(Step::Sleep(Duration::from_secs(60))),
+            )
+            .await;
+
+        for peer in simulation.peers() {
+            assert!(simulation.signed_blocks(peer).await.is_empty());
+        }
+
+        simulation
+            .run(
+                Scenario::new()
+                    .then(Step::Heal)
+                    .then(Step::AwaitSessions(peers(&[0, 1, 2, 3]), 2)),
+            )
+            .await;
+
+        simulation.assert_consistent().await;
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn crashed_peer_recovers_after_restart() {
+        let mut simulation = Simulation::new(4, 3);
+
+        simulation.start_all().await;
+
+        simulation
+            .run(
+                Scenario::new()
+                    .then(Step::AwaitSessions(peers(&[0, 1, 2, 3]), 1))
+                    .then(Step::Stop(PeerId::from(0)))
+                    .then(Step::AwaitSessions(peers(&[1, 2, 3]), 2))
+                    .then(Step::Start(PeerId::from(0)))
+                    .then(Step::AwaitSessions(peers(&[0, 1, 2, 3]), 3)),
+            )
+            .await;
+
+        simulation.assert_consistent().await;
+    }
+}
@@ -0,0 +1,550 @@
+//! In-memory network between the simulated peers
+//!
+//! Messages are passed over channels without any serialization. Every message
+//! is delayed by a latency sampled from the seeded random number generator of
+//! the network, and messages may overtake each other if reordering is enabled
+//! for a link. Partitioning the network tears down all connections across the
+//! partition and refuses new ones until the partition is healed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use fedimint_core::task::spawn;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::PeerId;
+use futures::channel::mpsc::{self, SendError, UnboundedReceiver, UnboundedSender};
+use futures::sink::SinkMapErr;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use crate::net::connect::{ConnectResult, ConnectionListener, Connector};
+use crate::net::framed::FramedTransport;
+
+/// Latency and ordering of the messages sent from one peer to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkConditions {
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// Whether a message may overtake messages that were sent before it
+    pub reorder: bool,
+}
+
+impl LinkConditions {
+    pub const RELIABLE: LinkConditions = LinkConditions {
+        min_latency: Duration::from_millis(1),
+        max_latency: Duration::from_millis(10),
+        reorder: false,
+    };
+}
+
+impl Default for LinkConditions {
+    fn default() -> Self {
+        Self::RELIABLE
+    }
+}
+
+#[derive(Debug)]
+struct LinkState {
+    peers: BTreeSet<PeerId>,
+    rng: StdRng,
+    /// Unordered pairs of peers that can not reach each other
+    cut: BTreeSet<(PeerId, PeerId)>,
+    conditions: BTreeMap<(PeerId, PeerId), LinkConditions>,
+    default_conditions: LinkConditions,
+}
+
+/// The links between the simulated peers, which faults are injected into
+#[derive(Debug, Clone)]
+pub struct SimulatedLinks {
+    state: Arc<Mutex<LinkState>>,
+    /// Notifies open connections that they have to check whether their link
+    /// was cut
+    changes: Arc<watch::Sender<()>>,
+}
+
+impl SimulatedLinks {
+    fn new(peers: BTreeSet<PeerId>, seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(LinkState {
+                peers,
+                rng: StdRng::seed_from_u64(seed),
+                cut: BTreeSet::new(),
+                conditions: BTreeMap::new(),
+                default_conditions: LinkConditions::default(),
+            })),
+            changes: Arc::new(watch::channel(()).0),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LinkState> {
+        self.state.lock().expect("Link state lock poisoned")
+    }
+
+    /// Cuts all links between `side` and the remaining peers
+    pub fn partition(&self, side: &BTreeSet<PeerId>) {
+        let mut state = self.lock();
+        let peers = state.peers.clone();
+
+        for a in side {
+            for b in peers.difference(side) {
+                state.cut.insert(ordered(*a, *b));
+            }
+        }
+
+        drop(state);
+        self.changes.send_replace(());
+    }
+
+    /// Cuts all links of `peer`
+    pub fn isolate(&self, peer: PeerId) {
+        self.partition(&BTreeSet::from([peer]));
+    }
+
+    /// Restores all links that have been cut
+    pub fn heal(&self) {
+        self.lock().cut.clear();
+        self.changes.send_replace(());
+    }
+
+    /// Sets the conditions of the messages sent from `from` to `to`
+    pub fn set_conditions(&self, from: PeerId, to: PeerId, conditions: LinkConditions) {
+        self.lock().conditions.insert((from, to), conditions);
+    }
+
+    /// Sets the conditions of all links without explicit conditions
+    pub fn set_default_conditions(&self, conditions: LinkConditions) {
+        self.lock().default_conditions = conditions;
+    }
+
+    pub fn is_linked(&self, a: PeerId, b: PeerId) -> bool {
+        !self.lock().cut.contains(&ordered(a, b))
+    }
+
+    /// Samples the latency of a message, returns `None` if the link is cut
+    pub(super) fn sample_latency(&self, from: PeerId, to: PeerId) -> Option<(Duration, bool)> {
+        let mut state = self.lock();
+
+        if state.cut.contains(&ordered(from, to)) {
+            return None;
+        }
+
+        let conditions = state
+            .conditions
+            .get(&(from, to))
+            .copied()
+            .unwrap_or(state.default_conditions);
+
+        let latency = state
+            .rng
+            .gen_range(conditions.min_latency..=conditions.max_latency);
+
+        Some((latency, conditions.reorder))
+    }
+}
+
+fn ordered(a: PeerId, b: PeerId) -> (PeerId, PeerId) {
+    (a.min(b), a.max(b))
+}
+
+/// Replaces every message a peer sends with the messages its peers receive,
+/// e.g. in order to simulate a malicious peer
+pub type Tamper<M> = Arc<dyn Fn(M) -> Vec<M> + Send + Sync>;
+
+/// In-memory network carrying messages of type `M`
+#[derive(Clone)]
+pub struct SimulatedNetwork<M> {
+    links: SimulatedLinks,
+    listeners: Arc<Mutex<BTreeMap<PeerId, UnboundedSender<ConnectResult<M>>>>>,
+    tampers: Arc<Mutex<BTreeMap<PeerId, Tamper<M>>>>,
+}
+
+impl<M> Debug for SimulatedNetwork<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimulatedNetwork")
+            .field("links", &self.links)
+            .finish()
+    }
+}
+
+impl<M: Send + 'static> SimulatedNetwork<M> {
+    pub fn new(peers: BTreeSet<PeerId>, seed: u64) -> Self {
+        Self {
+            links: SimulatedLinks::new(peers, seed),
+            listeners: Arc::new(Mutex::new(BTreeMap::new())),
+            tampers: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn links(&self) -> &SimulatedLinks {
+        &self.links
+    }
+
+    pub fn connector(&self, id: PeerId) -> SimulatedConnector<M> {
+        SimulatedConnector {
+            id,
+            network: self.clone(),
+        }
+    }
+
+    /// Stops accepting connections for a peer that has been stopped
+    pub fn stop_listening(&self, peer: PeerId) {
+        self.listeners
+            .lock()
+            .expect("Listener lock poisoned")
+            .remove(&peer);
+    }
+
+    /// Tampers with all messages sent by a peer on connections established
+    /// from now on
+    pub fn set_tamper(&self, peer: PeerId, tamper: Tamper<M>) {
+        self.tampers
+            .lock()
+            .expect("Tamper lock poisoned")
+            .insert(peer, tamper);
+    }
+
+    fn tamper(&self, peer: PeerId) -> Option<Tamper<M>> {
+        self.tampers
+            .lock()
+            .expect("Tamper lock poisoned")
+            .get(&peer)
+            .cloned()
+    }
+
+    fn connect(&self, from: PeerId, to: PeerId) -> ConnectResult<M> {
+        if !self.links.is_linked(from, to) {
+            bail!("Peer {to} is unreachable from peer {from}");
+        }
+
+        let (from_sink, from_outgoing) = mpsc::unbounded();
+        let (to_sink, to_outgoing) = mpsc::unbounded();
+        let (from_incoming_sender, from_incoming) = mpsc::unbounded();
+        let (to_incoming_sender, to_incoming) = mpsc::unbounded();
+
+        self.listeners
+            .lock()
+            .expect("Listener lock poisoned")
+            .get(&to)
+            .ok_or_else(|| anyhow!("Peer {to} is not listening"))?
+            .unbounded_send(Ok((
+                from,
+                Box::new(SimulatedTransport::new(to_sink, to_incoming)),
+            )))
+            .map_err(|_| anyhow!("Peer {to} is not listening"))?;
+
+        spawn(
+            "simulated link",
+            forward(
+                self.links.clone(),
+                from,
+                to,
+                self.tamper(from),
+                from_outgoing,
+                to_incoming_sender,
+            ),
+        );
+        spawn(
+            "simulated link",
+            forward(
+                self.links.clone(),
+                to,
+                from,
+                self.tamper(to),
+                to_outgoing,
+                from_incoming_sender,
+            ),
+        );
+
+        Ok((
+            to,
+            Box::new(SimulatedTransport::new(from_sink, from_incoming)),
+        ))
+    }
+}
+
+/// Delivers the messages sent from `from` to `to` once their latency has
+/// elapsed, until either side closes the connection or the link is cut
+async fn forward<M>(
+    links: SimulatedLinks,
+    from: PeerId,
+    to: PeerId,
+    tamper: Option<Tamper<M>>,
+    mut outgoing: UnboundedReceiver<M>,
+    incoming: UnboundedSender<anyhow::Result<M>>,
+) {
+    let mut changes = links.changes.subscribe();
+    let mut in_flight = BTreeMap::<(Instant, u64), M>::new();
+    let mut last_delivery = Instant::now();
+    let mut sequence = 0;
+
+    'forward: loop {
+        let next_delivery = in_flight.keys().next().map(|(instant, _)| *instant);
+
+        tokio::select! {
+            message = outgoing.next() => {
+                let Some(message) = message else {
+                    return;
+                };
+
+                let messages = match &tamper {
+                    Some(tamper) => tamper(message),
+                    None => vec![message],
+                };
+
+                for message in messages {
+                    let Some((latency, reorder)) = links.sample_latency(from, to) else {
+                        break 'forward;
+                    };
+
+                    let mut delivery = Instant::now() + latency;
+
+                    if !reorder {
+                        delivery = delivery.max(last_delivery);
+                    }
+
+                    last_delivery = delivery;
+                    in_flight.insert((delivery, sequence), message);
+                    sequence += 1;
+                }
+            }
+            () = sleep_until(next_delivery) => {
+                let (_, message) = in_flight.pop_first().expect("A message is in flight");
+
+                if incoming.unbounded_send(Ok(message)).is_err() {
+                    return;
+                }
+            }
+            changed = changes.changed() => {
+                if changed.is_err() || !links.is_linked(from, to) {
+                    break;
+                }
+            }
+        }
+    }
+
+    incoming
+        .unbounded_send(Err(anyhow!("Link between {from} and {to} was cut")))
+        .ok();
+}
+
+async fn sleep_until(instant: Option<Instant>) {
+    match instant {
+        Some(instant) => tokio::time::sleep_until(instant).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Connects a simulated peer to the [`SimulatedNetwork`]
+#[derive(Debug)]
+pub struct SimulatedConnector<M> {
+    id: PeerId,
+    network: SimulatedNetwork<M>,
+}
+
+#[async_trait]
+impl<M: Send + 'static> Connector<M> for SimulatedConnector<M> {
+    async fn connect_framed(&self, _destination: SafeUrl, peer: PeerId) -> ConnectResult<M> {
+        self.network.connect(self.id, peer)
+    }
+
+    async fn listen(&self, _bind_addr: SocketAddr) -> Result<ConnectionListener<M>, anyhow::Error> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        self.network
+            .listeners
+            .lock()
+            .expect("Listener lock poisoned")
+            .insert(self.id, sender);
+
+        Ok(Box::pin(receiver))
+    }
+}
+
+type SimulatedSink<M> = SinkMapErr<UnboundedSender<M>, fn(SendError) -> anyhow::Error>;
+
+/// One end of a connection in the [`SimulatedNetwork`]
+struct SimulatedTransport<M> {
+    sink: SimulatedSink<M>,
+    stream: UnboundedReceiver<anyhow::Result<M>>,
+}
+
+impl<M> SimulatedTransport<M> {
+    fn new(sink: UnboundedSender<M>, stream: UnboundedReceiver<anyhow::Result<M>>) -> Self {
+        Self {
+            sink: sink.sink_map_err(anyhow::Error::from as fn(SendError) -> anyhow::Error),
+            stream,
+        }
+    }
+}
+
+impl<M> Sink<M> for SimulatedTransport<M> {
+    type Error = anyhow::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<anyhow::Result<()>> {
+        Pin::new(&mut self.sink).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: M) -> anyhow::Result<()> {
+        Pin::new(&mut self.sink).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<anyhow::Result<()>> {
+        Pin::new(&mut self.sink).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<anyhow::Result<()>> {
+        Pin::new(&mut self.sink).poll_close(cx)
+    }
+}
+
+impl<M> Stream for SimulatedTransport<M> {
+    type Item = anyhow::Result<M>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+impl<M: Send + 'static> FramedTransport<M> for SimulatedTransport<M> {
+    fn borrow_split(
+        &mut self,
+    ) -> (
+        &'_ mut (dyn Sink<M, Error = anyhow::Error> + Send + Unpin),
+        &'_ mut (dyn Stream<Item = anyhow::Result<M>> + Send + Unpin),
+    ) {
+        (&mut self.sink, &mut self.stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    use fedimint_core::PeerId;
+    use futures::{SinkExt, StreamExt};
+
+    use super::{LinkConditions, SimulatedNetwork};
+    use crate::net::connect::Connector;
+
+    fn peers() -> BTreeSet<PeerId> {
+        (0..2).map(PeerId::from).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn messages_are_delivered_in_order() {
+        let network = SimulatedNetwork::<u64>::new(peers(), 0);
+        let url = "ws://127.0.0.1:1".parse().expect("Valid url");
+        let bind = "127.0.0.1:1".parse().expect("Valid address");
+
+        let mut listener = network
+            .connector(PeerId::from(1))
+            .listen(bind)
+            .await
+            .unwrap();
+        let (_, mut a) = network
+            .connector(PeerId::from(0))
+            .connect_framed(url, PeerId::from(1))
+            .await
+            .unwrap();
+        let (peer, mut b) = listener.next().await.unwrap().unwrap();
+
+        assert_eq!(peer, PeerId::from(0));
+
+        for message in 0..100 {
+            a.send(message).await.unwrap();
+        }
+
+        for message in 0..100 {
+            assert_eq!(b.next().await.unwrap().unwrap(), message);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reordering_links_reorder_messages() {
+        let network = SimulatedNetwork::<u64>::new(peers(), 0);
+        let url = "ws://127.0.0.1:1".parse().expect("Valid url");
+        let bind = "127.0.0.1:1".parse().expect("Valid address");
+
+        network.links().set_default_conditions(LinkConditions {
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(100),
+            reorder: true,
+        });
+
+        let mut listener = network
+            .connector(PeerId::from(1))
+            .listen(bind)
+            .await
+            .unwrap();
+        let (_, mut a) = network
+            .connector(PeerId::from(0))
+            .connect_framed(url, PeerId::from(1))
+            .await
+            .unwrap();
+        let (_, mut b) = listener.next().await.unwrap().unwrap();
+
+        for message in 0..100 {
+            a.send(message).await.unwrap();
+        }
+
+        let mut received = vec![];
+
+        for _ in 0..100 {
+            received.push(b.next().await.unwrap().unwrap());
+        }
+
+        assert_ne!(received, (0..100).collect::<Vec<_>>());
+
+        received.sort_unstable();
+
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn partition_tears_down_connections() {
+        let network = SimulatedNetwork::<u64>::new(peers(), 0);
+        let url: fedimint_core::util::SafeUrl = "ws://127.0.0.1:1".parse().expect("Valid url");
+        let bind = "127.0.0.1:1".parse().expect("Valid address");
+
+        let mut listener = network
+            .connector(PeerId::from(1))
+            .listen(bind)
+            .await
+            .unwrap();
+        let (_, mut a) = network
+            .connector(PeerId::from(0))
+            .connect_framed(url.clone(), PeerId::from(1))
+            .await
+            .unwrap();
+        let (_, mut b) = listener.next().await.unwrap().unwrap();
+
+        network.links().isolate(PeerId::from(1));
+
+        assert!(a.next().await.unwrap().is_err());
+        assert!(b.next().await.unwrap().is_err());
+        assert!(network
+            .connector(PeerId::from(0))
+            .connect_framed(url.clone(), PeerId::from(1))
+            .await
+            .is_err());
+
+        network.links().heal();
+
+        assert!(network
+            .connector(PeerId::from(0))
+            .connect_framed(url, PeerId::from(1))
+            .await
+            .is_ok());
+    }
+}
@@ -1,30 +1,28 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 use fedimint_client::module::init::ClientModuleInitRegistry;
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::{ClientArc, ClientBuilder, FederationInfo};
-use fedimint_core::admin_client::{ConfigGenParamsConsensus, PeerServerParams};
 use fedimint_core::api::InviteCode;
 use fedimint_core::config::{
     ClientConfig, FederationId, ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry,
-    META_FEDERATION_NAME_KEY,
 };
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::db::Database;
-use fedimint_core::module::ApiAuth;
+use fedimint_core::fee::FeeSchedule;
 use fedimint_core::task::TaskGroup;
 use fedimint_core::PeerId;
 use fedimint_logging::LOG_TEST;
-use fedimint_server::config::api::ConfigGenParamsLocal;
-use fedimint_server::config::{gen_cert_and_key, ConfigGenParams, ServerConfig};
+use fedimint_server::config::{local_config_gen_params, ServerConfig};
 use fedimint_server::consensus::server::ConsensusServer;
 use fedimint_server::net::connect::mock::{MockNetwork, StreamReliability};
-use fedimint_server::net::connect::{parse_host_port, Connector};
+use fedimint_server::net::connect::Connector;
 use fedimint_server::net::peers::DelayCalculator;
+use fedimint_server::signer::LocalSigner;
 use fedimint_server::FedimintServer;
 use rand::thread_rng;
-use tokio_rustls::rustls;
 use tracing::info;
 
 /// Test fixture for a running fedimint federation
@@ -99,14 +97,19 @@ impl FederationTest {
         num_peers: u16,
         base_port: u16,
         params: ServerModuleConfigGenParamsRegistry,
+        fees: FeeSchedule,
         server_init: ServerModuleInitRegistry,
         client_init: ClientModuleInitRegistry,
         primary_client: ModuleInstanceId,
     ) -> Self {
         let peers = (0..num_peers).map(PeerId::from).collect::<Vec<_>>();
-        let params =
+        let mut params =
             local_config_gen_params(&peers, base_port, params).expect("Generates local config");
 
+        for peer_params in params.values_mut() {
+            peer_params.consensus.fees = fees.clone();
+        }
+
         let configs = ServerConfig::trusted_dealer_gen(&params, server_init.clone());
         let network = MockNetwork::new();
 
@@ -123,6 +126,7 @@ impl FederationTest {
                 config.clone(),
                 db.clone(),
                 server_init.clone(),
+                Arc::new(LocalSigner::new(&config)),
                 connections,
                 DelayCalculator::TEST_DEFAULT,
                 &mut task,
@@ -130,7 +134,7 @@ impl FederationTest {
             .await
             .expect("Failed to init server");
 
-            let api_handle = FedimintServer::spawn_consensus_api(consensus_api, false).await;
+            let api_handle = FedimintServer::spawn_consensus_api(consensus_api, None, false).await;
 
             task.spawn("fedimintd", move |handle| async move {
                 consensus_server.run(handle).await.unwrap();
@@ -148,71 +152,3 @@ impl FederationTest {
         }
     }
 }
-
-/// Creates the config gen params for each peer
-///
-/// Uses peers * 2 ports offset from `base_port`
-pub fn local_config_gen_params(
-    peers: &[PeerId],
-    base_port: u16,
-    server_config_gen: ServerModuleConfigGenParamsRegistry,
-) -> anyhow::Result<HashMap<PeerId, ConfigGenParams>> {
-    // Generate TLS cert and private key
-    let tls_keys: HashMap<PeerId, (rustls::Certificate, rustls::PrivateKey)> = peers
-        .iter()
-        .map(|peer| {
-            (
-                *peer,
-                gen_cert_and_key(&format!("peer-{}", peer.to_usize())).unwrap(),
-            )
-        })
-        .collect();
-
-    // Generate the P2P and API URL on 2 different ports for each peer
-    let connections: BTreeMap<PeerId, PeerServerParams> = peers
-        .iter()
-        .map(|peer| {
-            let peer_port = base_port + u16::from(*peer) * 2;
-            let p2p_url = format!("fedimint://127.0.0.1:{peer_port}");
-            let api_url = format!("ws://127.0.0.1:{}", peer_port + 1);
-
-            let params: PeerServerParams = PeerServerParams {
-                cert: tls_keys[peer].0.clone(),
-                p2p_url: p2p_url.parse().expect("Should parse"),
-                api_url: api_url.parse().expect("Should parse"),
-                name: format!("peer-{}", peer.to_usize()),
-                status: None,
-            };
-            (*peer, params)
-        })
-        .collect();
-
-    peers
-        .iter()
-        .map(|peer| {
-            let p2p_bind = parse_host_port(connections[peer].clone().p2p_url)?;
-            let api_bind = parse_host_port(connections[peer].clone().api_url)?;
-
-            let params = ConfigGenParams {
-                local: ConfigGenParamsLocal {
-                    our_id: *peer,
-                    our_private_key: tls_keys[peer].1.clone(),
-                    api_auth: ApiAuth("pass".to_string()),
-                    p2p_bind: p2p_bind.parse().expect("Valid address"),
-                    api_bind: api_bind.parse().expect("Valid address"),
-                    download_token_limit: None,
-                    max_connections: 10,
-                },
-                consensus: ConfigGenParamsConsensus {
-                    peers: connections.clone(),
-                    meta: BTreeMap::from([(
-                        META_FEDERATION_NAME_KEY.to_owned(),
-                        "federation_name".to_string(),
-                    )]),
-                    modules: server_config_gen.clone(),
-                },
-            };
-            Ok((*peer, params))
-        })
-        .collect::<anyhow::Result<HashMap<_, _>>>()
-}
@@ -12,6 +12,7 @@ use fedimint_core::config::{
     ModuleInitParams, ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry,
 };
 use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+use fedimint_core::fee::FeeSchedule;
 use fedimint_core::module::{DynServerModuleInit, IServerModuleInit};
 use fedimint_core::task::{MaybeSend, MaybeSync, TaskGroup};
 use fedimint_logging::{TracingSetup, LOG_TEST};
@@ -36,6 +37,7 @@ pub struct Fixtures {
     clients: Vec<DynClientModuleInit>,
     servers: Vec<DynServerModuleInit>,
     params: ServerModuleConfigGenParamsRegistry,
+    fees: FeeSchedule,
     primary_client: ModuleInstanceId,
     bitcoin_rpc: BitcoinRpcConfig,
     bitcoin: Arc<dyn BitcoinTest>,
@@ -79,6 +81,7 @@ impl Fixtures {
             clients: vec![],
             servers: vec![],
             params: Default::default(),
+            fees: FeeSchedule::default(),
             primary_client: 0,
             bitcoin_rpc: config,
             bitcoin,
@@ -109,6 +112,12 @@ impl Fixtures {
         self
     }
 
+    /// Charge the fees of the schedule on the transactions of the fed
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
+
     /// Starts a new federation with default number of peers for testing
     pub async fn new_fed(&self) -> FederationTest {
         self.new_fed_with_peers(self.num_peers).await
@@ -122,6 +131,7 @@ impl Fixtures {
             tokio::task::block_in_place(|| fedimint_portalloc::port_alloc(num_peers * 2))
                 .expect("Failed to allocate a port range"),
             self.params.clone(),
+            self.fees.clone(),
             ServerModuleInitRegistry::from(self.servers.clone()),
             ClientModuleInitRegistry::from(self.clients.clone()),
             self.primary_client,
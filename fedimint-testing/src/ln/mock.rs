@@ -14,9 +14,10 @@ use lightning_invoice::{
     Bolt11Invoice, Bolt11InvoiceDescription, Currency, Description, InvoiceBuilder, PaymentSecret,
     SignedRawBolt11Invoice, DEFAULT_EXPIRY_TIME,
 };
+use ln_gateway::gateway_lnrpc::get_channel_liquidity_response::ChannelLiquidity;
 use ln_gateway::gateway_lnrpc::{
-    self, EmptyResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse,
-    PayInvoiceRequest, PayInvoiceResponse,
+    self, EmptyResponse, GetChannelLiquidityResponse, GetNodeInfoResponse, GetRouteHintsResponse,
+    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
 };
 use ln_gateway::lnrpc_client::{HtlcResult, ILnRpcClient, LightningRpcError, RouteHtlcStream};
 use rand::rngs::OsRng;
@@ -28,6 +29,10 @@ use crate::gateway::LightningNodeType;
 
 pub const INVALID_INVOICE_DESCRIPTION: &str = "INVALID";
 
+/// Capacity of the single channel `FakeLightningTest` pretends to have, all of
+/// it on our side until we pay invoices over it
+pub const FAKE_CHANNEL_CAPACITY: Amount = Amount::from_sats(1_000_000);
+
 #[derive(Debug)]
 pub struct FakeLightningTest {
     pub gateway_node_pub_key: secp256k1::PublicKey,
@@ -120,7 +125,6 @@ impl ILnRpcClient for FakeLightningTest {
     ) -> Result<PayInvoiceResponse, LightningRpcError> {
         let signed = invoice.invoice.parse::<SignedRawBolt11Invoice>().unwrap();
         let invoice = Bolt11Invoice::from_signed(signed).unwrap();
-        *self.amount_sent.lock().unwrap() += invoice.amount_milli_satoshis().unwrap();
 
         if invoice.description()
             == Bolt11InvoiceDescription::Direct(
@@ -132,6 +136,8 @@ impl ILnRpcClient for FakeLightningTest {
             });
         }
 
+        *self.amount_sent.lock().unwrap() += invoice.amount_milli_satoshis().unwrap();
+
         Ok(PayInvoiceResponse {
             preimage: [0; 32].to_vec(),
         })
@@ -162,4 +168,24 @@ impl ILnRpcClient for FakeLightningTest {
     ) -> Result<EmptyResponse, LightningRpcError> {
         Ok(EmptyResponse {})
     }
+
+    async fn channel_liquidity(&self) -> Result<GetChannelLiquidityResponse, LightningRpcError> {
+        // `FakeLightningTest` has no lightning connection, so it pretends the invoices
+        // it paid moved the liquidity of a single channel to the remote side
+        let amount_sent = *self.amount_sent.lock().unwrap();
+        let ctx = bitcoin::secp256k1::Secp256k1::new();
+        let remote_key = SecretKey::from_slice(&[1; 32]).expect("Valid secret key");
+
+        Ok(GetChannelLiquidityResponse {
+            channels: vec![ChannelLiquidity {
+                remote_pub_key: PublicKey::from_secret_key(&ctx, &remote_key)
+                    .serialize()
+                    .to_vec(),
+                short_channel_id: 0,
+                outbound_liquidity_msat: FAKE_CHANNEL_CAPACITY.msats.saturating_sub(amount_sent),
+                inbound_liquidity_msat: amount_sent,
+                active: true,
+            }],
+        })
+    }
 }
@@ -16,8 +16,8 @@ use ldk_node::io::SqliteStore;
 use ldk_node::{Builder, Event, LogLevel, NetAddress, Node};
 use lightning_invoice::Bolt11Invoice;
 use ln_gateway::gateway_lnrpc::{
-    EmptyResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse,
-    PayInvoiceRequest, PayInvoiceResponse,
+    EmptyResponse, GetChannelLiquidityResponse, GetNodeInfoResponse, GetRouteHintsResponse,
+    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
 };
 use ln_gateway::lnd::GatewayLndClient;
 use ln_gateway::lnrpc_client::{
@@ -142,6 +142,10 @@ impl ILnRpcClient for ClnLightningTest {
     ) -> Result<EmptyResponse, LightningRpcError> {
         self.lnrpc.complete_htlc(htlc).await
     }
+
+    async fn channel_liquidity(&self) -> Result<GetChannelLiquidityResponse, LightningRpcError> {
+        self.lnrpc.channel_liquidity().await
+    }
 }
 
 impl ClnLightningTest {
@@ -300,6 +304,10 @@ impl ILnRpcClient for LndLightningTest {
     ) -> Result<EmptyResponse, LightningRpcError> {
         self.lnrpc.complete_htlc(htlc).await
     }
+
+    async fn channel_liquidity(&self) -> Result<GetChannelLiquidityResponse, LightningRpcError> {
+        self.lnrpc.channel_liquidity().await
+    }
 }
 
 impl LndLightningTest {
@@ -0,0 +1,287 @@
+//! Verification of the ecash liabilities of a federation
+//!
+//! The signed blocks commit to every accepted transaction, so the verified
+//! consensus history doubles as the federation's proof of liabilities: the
+//! ecash issued by mint outputs minus the ecash redeemed by mint inputs. An
+//! auditor tallies the outstanding ecash of the verified blocks with
+//! [`EcashLiabilities`] and compares it against the reserves of the
+//! federation, while a user checks with [`EcashLiabilities::covers`] that
+//! their notes are part of that total.
+
+use std::collections::{BTreeSet, HashMap};
+
+use fedimint_core::config::ClientConfig;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::module::CommonModuleInit;
+use fedimint_core::Amount;
+use fedimint_mint_common::config::MintClientConfig;
+use fedimint_mint_common::{MintCommonGen, MintInput, MintOutput, Nonce, Note};
+use thiserror::Error;
+
+use crate::VerifiedBlock;
+
+/// Why a note is not covered by the liabilities of the federation
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum NoteCoverageError {
+    #[error("The federation does not issue notes of {0}")]
+    InvalidAmountTier(Amount),
+    #[error("The note is not signed by the federation")]
+    InvalidSignature,
+    #[error("The note expired or is not signed by the federation")]
+    Expired,
+    #[error("The note was redeemed in session {0}")]
+    Redeemed(u64),
+}
+
+/// The ecash issued and redeemed in the verified blocks of a federation
+#[derive(Debug, Clone)]
+pub struct EcashLiabilities {
+    mint_instances: BTreeSet<ModuleInstanceId>,
+    issued: Amount,
+    redeemed: Amount,
+    /// The session every redeemed note was redeemed in
+    redeemed_nonces: HashMap<Nonce, u64>,
+    /// The session following the last block that was tallied
+    next_session_index: u64,
+}
+
+impl EcashLiabilities {
+    /// Tallies the ecash of every mint module of the federation
+    pub fn new(config: &ClientConfig) -> Self {
+        Self::from_instances(
+            config
+                .modules
+                .iter()
+                .filter(|(_, module)| module.is_kind(&MintCommonGen::KIND))
+                .map(|(instance, _)| *instance),
+        )
+    }
+
+    /// Tallies the ecash of the mint modules with the given instance ids
+    pub fn from_instances(mint_instances: impl IntoIterator<Item = ModuleInstanceId>) -> Self {
+        Self {
+            mint_instances: mint_instances.into_iter().collect(),
+            issued: Amount::ZERO,
+            redeemed: Amount::ZERO,
+            redeemed_nonces: HashMap::new(),
+            next_session_index: 0,
+        }
+    }
+
+    /// Adds the mint inputs and outputs of the transactions accepted in the
+    /// block, which has to be verified with the federation's
+    /// [`BlockVerifier`](crate::BlockVerifier)
+    ///
+    /// The block has to be decoded with the mint decoder, as the items of
+    /// modules decoded as raw bytes are not counted.
+    pub fn add_block(&mut self, block: &VerifiedBlock) {
+        for (_, transaction) in block.transactions() {
+            for input in &transaction.inputs {
+                if !self.mint_instances.contains(&input.module_instance_id()) {
+                    continue;
+                }
+
+                if let Some(input) = input.as_any().downcast_ref::<MintInput>() {
+                    self.redeemed += input.amount;
+                    self.redeemed_nonces
+                        .insert(input.note.nonce, block.session_index);
+                }
+            }
+
+            for output in &transaction.outputs {
+                if !self.mint_instances.contains(&output.module_instance_id()) {
+                    continue;
+                }
+
+                if let Some(output) = output.as_any().downcast_ref::<MintOutput>() {
+                    self.issued += output.amount;
+                }
+            }
+        }
+
+        self.next_session_index = block.session_index + 1;
+    }
+
+    /// The ecash issued in the tallied blocks
+    pub fn issued(&self) -> Amount {
+        self.issued
+    }
+
+    /// The ecash redeemed in the tallied blocks
+    pub fn redeemed(&self) -> Amount {
+        self.redeemed
+    }
+
+    /// The ecash the federation owes its users as of the last tallied block
+    pub fn outstanding(&self) -> Amount {
+        self.issued.saturating_sub(self.redeemed)
+    }
+
+    /// The number of sessions that have been tallied
+    pub fn session_count(&self) -> u64 {
+        self.next_session_index
+    }
+
+    /// Checks that the note of the given amount counts towards the
+    /// [`outstanding`](Self::outstanding) liabilities, i.e. that it was signed
+    /// by the federation and has not been redeemed in the tallied blocks
+    pub fn covers(
+        &self,
+        mint_config: &MintClientConfig,
+        amount: Amount,
+        note: &Note,
+    ) -> Result<(), NoteCoverageError> {
+        let public_key = mint_config
+            .tbs_pks
+            .get(amount)
+            .ok_or(NoteCoverageError::InvalidAmountTier(amount))?;
+
+        match &mint_config.note_expiry {
+            Some(note_expiry) => {
+                // expired notes can not be redeemed anymore, so they are not
+                // covered even though they count towards the issued ecash
+                note_expiry
+                    .live_note_epoch(*public_key, amount, note, self.next_session_index)
+                    .ok_or(NoteCoverageError::Expired)?;
+            }
+            None => {
+                if !note.verify(*public_key) {
+                    return Err(NoteCoverageError::InvalidSignature);
+                }
+            }
+        }
+
+        if let Some(session_index) = self.redeemed_nonces.get(&note.nonce) {
+            return Err(NoteCoverageError::Redeemed(*session_index));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::block::{AcceptedItem, SignedBlockHeader};
+    use fedimint_core::core::{DynInput, DynOutput};
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::transaction::Transaction;
+    use fedimint_core::{Amount, PeerId, Tiered};
+    use fedimint_mint_common::config::{FeeConsensus, MintClientConfig};
+    use fedimint_mint_common::{BlindNonce, MintInput, MintOutput, Nonce, Note};
+    use secp256k1_zkp::{SecretKey, SECP256K1};
+    use tbs::{blind_message, combine_valid_shares, sign_blinded_msg, unblind_signature};
+
+    use super::{EcashLiabilities, NoteCoverageError};
+    use crate::VerifiedBlock;
+
+    const MINT_INSTANCE: u16 = 1;
+
+    fn issue_note(sks: &[tbs::SecretKeyShare], seed: u8) -> (BlindNonce, Note) {
+        let spend_key = SecretKey::from_slice(&[seed; 32])
+            .expect("Valid secret key")
+            .keypair(SECP256K1);
+        let nonce = Nonce(spend_key.x_only_public_key().0);
+        let blinding_key = tbs::BlindingKey::random();
+        let blinded_message = blind_message(nonce.to_message(), blinding_key);
+
+        let shares = sks
+            .iter()
+            .map(|sk| sign_blinded_msg(blinded_message, *sk))
+            .enumerate()
+            .collect::<Vec<_>>();
+        let signature = unblind_signature(blinding_key, combine_valid_shares(shares, 3));
+
+        (BlindNonce(blinded_message), Note { nonce, signature })
+    }
+
+    fn block(session_index: u64, transaction: Transaction) -> VerifiedBlock {
+        VerifiedBlock {
+            session_index,
+            header: SignedBlockHeader {
+                header: [0; 40],
+                signatures: BTreeMap::new(),
+            },
+            items: vec![AcceptedItem {
+                item: ConsensusItem::Transaction(transaction),
+                peer: PeerId::from(0),
+            }],
+        }
+    }
+
+    #[test]
+    fn tallies_outstanding_ecash() {
+        let amount = Amount::from_msats(1024);
+        let (pk, _, sks) = tbs::dealer_keygen(3, 4);
+        let mint_config = MintClientConfig {
+            tbs_pks: Tiered::from_iter([(amount, pk)]),
+            fee_consensus: FeeConsensus::default(),
+            peer_tbs_pks: BTreeMap::new(),
+            max_notes_per_denomination: 0,
+            note_expiry: None,
+        };
+
+        let (blind_nonce, note) = issue_note(&sks, 1);
+        let (other_blind_nonce, other_note) = issue_note(&sks, 2);
+
+        let issuance = Transaction {
+            inputs: vec![],
+            outputs: [blind_nonce, other_blind_nonce]
+                .into_iter()
+                .map(|blind_nonce| {
+                    DynOutput::from_typed(
+                        MINT_INSTANCE,
+                        MintOutput {
+                            amount,
+                            blind_nonce,
+                        },
+                    )
+                })
+                .collect(),
+            signature: None,
+        };
+        let redemption = Transaction {
+            inputs: vec![DynInput::from_typed(
+                MINT_INSTANCE,
+                MintInput {
+                    amount,
+                    note: other_note.clone(),
+                },
+            )],
+            outputs: vec![],
+            signature: None,
+        };
+
+        let mut liabilities = EcashLiabilities::from_instances([MINT_INSTANCE]);
+        liabilities.add_block(&block(0, issuance.clone()));
+        liabilities.add_block(&block(1, redemption));
+
+        assert_eq!(liabilities.issued(), Amount::from_msats(2048));
+        assert_eq!(liabilities.redeemed(), amount);
+        assert_eq!(liabilities.outstanding(), amount);
+        assert_eq!(liabilities.session_count(), 2);
+
+        assert_eq!(liabilities.covers(&mint_config, amount, &note), Ok(()));
+        assert_eq!(
+            liabilities.covers(&mint_config, amount, &other_note),
+            Err(NoteCoverageError::Redeemed(1))
+        );
+        let unknown_tier = Amount::from_msats(2048);
+        assert_eq!(
+            liabilities.covers(&mint_config, unknown_tier, &note),
+            Err(NoteCoverageError::InvalidAmountTier(unknown_tier))
+        );
+
+        let (_, forged_note) = issue_note(&tbs::dealer_keygen(3, 4).2, 3);
+        assert_eq!(
+            liabilities.covers(&mint_config, amount, &forged_note),
+            Err(NoteCoverageError::InvalidSignature)
+        );
+
+        // inputs and outputs of other modules are not counted
+        let mut other_module = EcashLiabilities::from_instances([MINT_INSTANCE + 1]);
+        other_module.add_block(&block(0, issuance));
+        assert_eq!(other_module.issued(), Amount::ZERO);
+    }
+}
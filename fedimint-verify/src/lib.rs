@@ -0,0 +1,565 @@
+//! Verification of the consensus history of a federation
+//!
+//! This crate allows third parties such as auditors to independently verify
+//! the signed blocks of a federation, e.g. from the block explorer API or an
+//! archive bucket, without depending on any guardian code. Given the client
+//! config of the federation a [`BlockVerifier`] checks that every block
+//!
+//! * is the next block in the sequence of sessions,
+//! * decodes with the decoders of the federations modules,
+//! * carries a valid threshold signature of the atomic broadcast over its
+//!   header, which commits to the accepted items via their merkle root.
+//!
+//! The [`liabilities`] of the federation, i.e. its outstanding ecash, can then
+//! be tallied from the verified blocks.
+//!
+//! ```no_run
+//! # async fn audit(
+//! #     config: fedimint_core::config::ClientConfig,
+//! #     blocks: impl futures::Stream<Item = Vec<u8>>,
+//! # ) -> Result<(), fedimint_verify::VerifyError> {
+//! use futures::StreamExt;
+//!
+//! let mut verified = fedimint_verify::BlockVerifier::new(&config).verify_stream(blocks);
+//!
+//! while let Some(block) = verified.next().await {
+//!     let block = block?;
+//!     println!("Session {} has {} items", block.session_index, block.items.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod liabilities;
+
+use std::collections::BTreeMap;
+
+use fedimint_core::block::{
+    AcceptedItem, AcceptedItemProof, HeaderSignatureError, SignedBlock, SignedBlockHeader,
+    TransactionLocation,
+};
+use fedimint_core::config::{ClientConfig, ClientConfigResponse, FederationId};
+use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
+use fedimint_core::encoding::{Decodable, DecodeError};
+use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::CommonModuleInit;
+use fedimint_core::rotation::{broadcast_public_keys_at, KeyEpoch};
+use fedimint_core::transaction::Transaction;
+use fedimint_core::{PeerId, TransactionId};
+use fedimint_ln_common::LightningCommonGen;
+use fedimint_mint_common::MintCommonGen;
+use fedimint_wallet_common::WalletCommonGen;
+use futures::{Stream, StreamExt};
+use thiserror::Error;
+
+/// Why the consensus history of a federation failed to verify
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error("The client config is not signed by federation {0}")]
+    InvalidConfigSignature(FederationId),
+    #[error("Module instance {instance} of kind {kind} has no registered decoder")]
+    MissingDecoder {
+        instance: ModuleInstanceId,
+        kind: ModuleKind,
+    },
+    #[error("Block of session {session_index} failed to decode: {error}")]
+    Decoding {
+        session_index: u64,
+        error: DecodeError,
+    },
+    #[error("Invalid signature of block of session {session_index}: {error}")]
+    Signature {
+        session_index: u64,
+        error: HeaderSignatureError,
+    },
+    #[error("Expected header of session {session_index} to be {expected:?}, got {actual:?}")]
+    HeaderMismatch {
+        session_index: u64,
+        expected: [u8; 40],
+        actual: [u8; 40],
+    },
+    #[error("Transaction {txid} is not included in the block of session {session_index}")]
+    NotIncluded {
+        session_index: u64,
+        txid: TransactionId,
+    },
+}
+
+/// Verifies the signature of a client config downloaded from an untrusted
+/// source, e.g. an archive bucket, against the id of the federation
+pub fn verify_client_config(
+    response: ClientConfigResponse,
+    federation_id: FederationId,
+) -> Result<ClientConfig, VerifyError> {
+    if !federation_id.0.verify(
+        &response.signature.0,
+        response.client_config.consensus_hash(),
+    ) {
+        return Err(VerifyError::InvalidConfigSignature(federation_id));
+    }
+
+    Ok(response.client_config)
+}
+
+/// Verifies that a transaction was accepted by the federation knowing only
+/// the signed header of the block it was accepted in, e.g. that the
+/// transaction issuing a user's notes is part of the federation's liabilities
+pub fn verify_transaction_inclusion(
+    header: &SignedBlockHeader,
+    broadcast_public_keys: &BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+    proof: &AcceptedItemProof,
+    transaction: &Transaction,
+) -> Result<(), VerifyError> {
+    let session_index = header.index();
+
+    header
+        .check_signatures(broadcast_public_keys)
+        .map_err(|error| VerifyError::Signature {
+            session_index,
+            error,
+        })?;
+
+    if !proof.verify(
+        &ConsensusItem::Transaction(transaction.clone()),
+        &header.header,
+    ) {
+        return Err(VerifyError::NotIncluded {
+            session_index,
+            txid: transaction.tx_hash(),
+        });
+    }
+
+    Ok(())
+}
+
+/// A block whose threshold signature has been verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedBlock {
+    pub session_index: u64,
+    /// The signed header, which can be handed to light clients
+    pub header: SignedBlockHeader,
+    /// The decoded items accepted during the session in consensus order
+    pub items: Vec<AcceptedItem>,
+}
+
+impl VerifiedBlock {
+    /// The transactions accepted during the session together with their
+    /// location in the consensus history
+    pub fn transactions(&self) -> impl Iterator<Item = (TransactionLocation, &Transaction)> {
+        let session_index = self.session_index;
+
+        self.items
+            .iter()
+            .enumerate()
+            .filter_map(
+                move |(item_index, accepted_item)| match &accepted_item.item {
+                    ConsensusItem::Transaction(transaction) => Some((
+                        TransactionLocation {
+                            session_index,
+                            item_index: item_index as u64,
+                        },
+                        transaction,
+                    )),
+                    _ => None,
+                },
+            )
+    }
+}
+
+/// Verifies the signed blocks of a federation in order, starting at session
+/// zero unless specified otherwise with [`BlockVerifier::starting_at`]
+#[derive(Debug, Clone)]
+pub struct BlockVerifier {
+    broadcast_public_keys: BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+    key_epochs: Vec<KeyEpoch>,
+    decoders: ModuleDecoderRegistry,
+    next_session_index: u64,
+}
+
+impl BlockVerifier {
+    /// Creates a verifier for the federation with the given config
+    ///
+    /// Items of the lightning, mint and wallet modules are fully decoded,
+    /// while items of any other modules are kept as raw bytes, which is
+    /// sufficient to verify the blocks. Use [`BlockVerifier::with_decoders`]
+    /// to decode the items of custom modules.
+    pub fn new(config: &ClientConfig) -> Self {
+        let mut decoders = BTreeMap::new();
+        decoders.insert(LightningCommonGen::KIND, LightningCommonGen::decoder());
+        decoders.insert(MintCommonGen::KIND, MintCommonGen::decoder());
+        decoders.insert(WalletCommonGen::KIND, WalletCommonGen::decoder());
+
+        let decoders = ModuleDecoderRegistry::from_iter(config.modules.iter().filter_map(
+            |(instance, module)| {
+                let decoder = decoders.get(&module.kind)?;
+                Some((*instance, module.kind.clone(), decoder.clone()))
+            },
+        ))
+        .with_fallback();
+
+        Self::from_parts(config.global.broadcast_public_keys.clone(), decoders)
+    }
+
+    /// Creates a verifier that decodes the items of every module of the
+    /// federation with the decoders registered for its kind, failing if a
+    /// module has no decoder
+    pub fn with_decoders(
+        config: &ClientConfig,
+        decoders: &BTreeMap<ModuleKind, Decoder>,
+    ) -> Result<Self, VerifyError> {
+        let decoders = config
+            .modules
+            .iter()
+            .map(|(instance, module)| {
+                decoders
+                    .get(&module.kind)
+                    .map(|decoder| (*instance, module.kind.clone(), decoder.clone()))
+                    .ok_or_else(|| VerifyError::MissingDecoder {
+                        instance: *instance,
+                        kind: module.kind.clone(),
+                    })
+            })
+            .collect::<Result<ModuleDecoderRegistry, _>>()?;
+
+        Ok(Self::from_parts(
+            config.global.broadcast_public_keys.clone(),
+            decoders,
+        ))
+    }
+
+    /// Creates a verifier from the broadcast public keys of the federation
+    /// and a decoder registry for its modules
+    pub fn from_parts(
+        broadcast_public_keys: BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+        decoders: ModuleDecoderRegistry,
+    ) -> Self {
+        Self {
+            broadcast_public_keys,
+            key_epochs: vec![],
+            decoders,
+            next_session_index: 0,
+        }
+    }
+
+    /// Verifies the blocks signed before key rotations with the broadcast
+    /// public keys replaced by them, as returned by the federation's
+    /// `key_epochs` endpoint
+    pub fn with_key_epochs(mut self, key_epochs: Vec<KeyEpoch>) -> Self {
+        self.key_epochs = key_epochs;
+        self
+    }
+
+    /// Continues the verification at `session_index`, e.g. to resume an audit
+    /// from a header that was verified previously
+    pub fn starting_at(mut self, session_index: u64) -> Self {
+        self.next_session_index = session_index;
+        self
+    }
+
+    /// The index of the session whose block is expected next
+    pub fn next_session_index(&self) -> u64 {
+        self.next_session_index
+    }
+
+    /// The decoders used for the items of the federations modules
+    pub fn decoders(&self) -> &ModuleDecoderRegistry {
+        &self.decoders
+    }
+
+    /// Decodes and verifies the consensus encoding of the next signed block
+    pub fn verify_encoded(&mut self, bytes: &[u8]) -> Result<VerifiedBlock, VerifyError> {
+        let signed_block =
+            SignedBlock::consensus_decode(&mut &bytes[..], &self.decoders).map_err(|error| {
+                VerifyError::Decoding {
+                    session_index: self.next_session_index,
+                    error,
+                }
+            })?;
+
+        self.verify(signed_block)
+    }
+
+    /// Verifies the next signed block
+    pub fn verify(&mut self, signed_block: SignedBlock) -> Result<VerifiedBlock, VerifyError> {
+        let session_index = self.next_session_index;
+        let header = signed_block.signed_header(session_index);
+
+        let broadcast_public_keys =
+            broadcast_public_keys_at(&self.key_epochs, &self.broadcast_public_keys, session_index);
+
+        header
+            .check_signatures(broadcast_public_keys)
+            .map_err(|error| VerifyError::Signature {
+                session_index,
+                error,
+            })?;
+
+        self.next_session_index += 1;
+
+        Ok(VerifiedBlock {
+            session_index,
+            header,
+            items: signed_block.block.items,
+        })
+    }
+
+    /// Verifies the next signed block and checks that its header matches a
+    /// header obtained otherwise, e.g. from a light client
+    pub fn verify_against_header(
+        &mut self,
+        signed_block: SignedBlock,
+        expected: &SignedBlockHeader,
+    ) -> Result<VerifiedBlock, VerifyError> {
+        let session_index = self.next_session_index;
+        let actual = signed_block.block.header(session_index);
+
+        if actual != expected.header {
+            return Err(VerifyError::HeaderMismatch {
+                session_index,
+                expected: expected.header,
+                actual,
+            });
+        }
+
+        self.verify(signed_block)
+    }
+
+    /// Verifies a stream of consensus encoded signed blocks in order
+    ///
+    /// Once a block fails to verify the following blocks can not be verified
+    /// either, so the stream ends after the first error.
+    pub fn verify_stream<S>(
+        mut self,
+        blocks: S,
+    ) -> impl Stream<Item = Result<VerifiedBlock, VerifyError>>
+    where
+        S: Stream<Item = Vec<u8>>,
+    {
+        blocks
+            .map(move |bytes| self.verify_encoded(&bytes))
+            .scan(false, |failed, result| {
+                if *failed {
+                    return futures::future::ready(None);
+                }
+
+                *failed = result.is_err();
+
+                futures::future::ready(Some(result))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::block::{
+        broadcast_message_hash, AcceptedItem, Block, HeaderSignatureError, SchnorrSignature,
+        SignedBlock,
+    };
+    use fedimint_core::encoding::Encodable;
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::rotation::KeyEpoch;
+    use fedimint_core::transaction::Transaction;
+    use fedimint_core::PeerId;
+    use futures::StreamExt;
+    use secp256k1_zkp::{KeyPair, Message, SecretKey, SECP256K1};
+
+    use super::{verify_transaction_inclusion, BlockVerifier, VerifyError};
+
+    fn keypairs(seed: u8) -> BTreeMap<PeerId, KeyPair> {
+        (0..4u16)
+            .map(|peer| {
+                let secret_key =
+                    SecretKey::from_slice(&[peer as u8 + seed; 32]).expect("Valid secret key");
+                (PeerId::from(peer), secret_key.keypair(SECP256K1))
+            })
+            .collect()
+    }
+
+    fn verifier(keypairs: &BTreeMap<PeerId, KeyPair>) -> BlockVerifier {
+        let public_keys = keypairs
+            .iter()
+            .map(|(peer, keypair)| (*peer, keypair.public_key()))
+            .collect();
+
+        BlockVerifier::from_parts(public_keys, ModuleDecoderRegistry::default())
+    }
+
+    fn signed_block(
+        keypairs: &BTreeMap<PeerId, KeyPair>,
+        session_index: u64,
+        signers: usize,
+    ) -> SignedBlock {
+        sign_block(keypairs, Block { items: vec![] }, session_index, signers)
+    }
+
+    fn sign_block(
+        keypairs: &BTreeMap<PeerId, KeyPair>,
+        block: Block,
+        session_index: u64,
+        signers: usize,
+    ) -> SignedBlock {
+        let public_keys = keypairs
+            .iter()
+            .map(|(peer, keypair)| (*peer, keypair.public_key()))
+            .collect();
+
+        let message = broadcast_message_hash(&public_keys, &block.header(session_index));
+
+        let signatures = keypairs
+            .iter()
+            .take(signers)
+            .map(|(peer, keypair)| {
+                let signature = SECP256K1.sign_schnorr_no_aux_rand(&message, keypair);
+                (*peer, SchnorrSignature(*signature.as_ref()))
+            })
+            .collect();
+
+        SignedBlock { block, signatures }
+    }
+
+    #[test]
+    fn verifies_blocks_in_order() {
+        let keypairs = keypairs(1);
+        let mut verifier = verifier(&keypairs);
+
+        for session_index in 0..3 {
+            let block = verifier
+                .verify(signed_block(&keypairs, session_index, 3))
+                .expect("Block is valid");
+            assert_eq!(block.session_index, session_index);
+        }
+
+        // a block signed for a different session does not verify
+        assert!(matches!(
+            verifier.verify(signed_block(&keypairs, 7, 3)),
+            Err(VerifyError::Signature {
+                session_index: 3,
+                error: HeaderSignatureError::InvalidSignature(_)
+            })
+        ));
+    }
+
+    #[test]
+    fn verifies_blocks_signed_before_key_rotation() {
+        let old_keypairs = keypairs(1);
+        let new_keypairs = keypairs(10);
+
+        let key_epochs = vec![KeyEpoch {
+            rotated_at_session: 2,
+            broadcast_public_keys: old_keypairs
+                .iter()
+                .map(|(peer, keypair)| (*peer, keypair.public_key()))
+                .collect(),
+        }];
+
+        let mut verifier = verifier(&new_keypairs).with_key_epochs(key_epochs);
+
+        for session_index in 0..2 {
+            verifier
+                .verify(signed_block(&old_keypairs, session_index, 3))
+                .expect("Block is signed with the keys of its epoch");
+        }
+
+        assert!(matches!(
+            verifier.verify(signed_block(&old_keypairs, 2, 3)),
+            Err(VerifyError::Signature {
+                session_index: 2,
+                ..
+            })
+        ));
+
+        verifier
+            .verify(signed_block(&new_keypairs, 2, 3))
+            .expect("Block is signed with the rotated keys");
+    }
+
+    #[test]
+    fn verifies_transaction_inclusion() {
+        let keypairs = keypairs(1);
+        let public_keys = keypairs
+            .iter()
+            .map(|(peer, keypair)| (*peer, keypair.public_key()))
+            .collect();
+
+        let transactions = (0..3)
+            .map(|i| Transaction {
+                inputs: vec![],
+                outputs: vec![],
+                signature: Some(SECP256K1.sign_schnorr_no_aux_rand(
+                    &Message::from_slice(&[i; 32]).unwrap(),
+                    &keypairs[&PeerId::from(0)],
+                )),
+            })
+            .collect::<Vec<_>>();
+
+        let block = Block {
+            items: transactions
+                .iter()
+                .map(|transaction| AcceptedItem {
+                    item: ConsensusItem::Transaction(transaction.clone()),
+                    peer: PeerId::from(0),
+                })
+                .collect(),
+        };
+        let proof = block.accepted_item_proof(5, 1).expect("Item exists");
+        let header = sign_block(&keypairs, block, 5, 3).signed_header(5);
+
+        verify_transaction_inclusion(&header, &public_keys, &proof, &transactions[1])
+            .expect("Transaction is included");
+
+        assert!(matches!(
+            verify_transaction_inclusion(&header, &public_keys, &proof, &transactions[2]),
+            Err(VerifyError::NotIncluded {
+                session_index: 5,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_insufficient_signatures() {
+        let keypairs = keypairs(1);
+
+        assert!(matches!(
+            verifier(&keypairs).verify(signed_block(&keypairs, 0, 2)),
+            Err(VerifyError::Signature {
+                session_index: 0,
+                error: HeaderSignatureError::InsufficientSignatures {
+                    signatures: 2,
+                    threshold: 3
+                }
+            })
+        ));
+    }
+
+    #[test]
+    fn stream_ends_after_first_error() {
+        let keypairs = keypairs(1);
+
+        let blocks = vec![
+            signed_block(&keypairs, 0, 4),
+            signed_block(&keypairs, 5, 4),
+            signed_block(&keypairs, 2, 4),
+        ]
+        .into_iter()
+        .map(|block| {
+            block
+                .consensus_encode_to_vec()
+                .expect("Encoding can't fail")
+        });
+
+        let results = futures::executor::block_on(
+            verifier(&keypairs)
+                .verify_stream(futures::stream::iter(blocks))
+                .collect::<Vec<_>>(),
+        );
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+}
@@ -11,7 +11,7 @@ use rand::thread_rng;
 async fn client(invite_code: &InviteCode) -> Result<fedimint_client::ClientArc> {
     let mut builder = fedimint_client::ClientBuilder::default();
     builder.with_module(LightningClientGen);
-    builder.with_module(MintClientGen);
+    builder.with_module(MintClientGen::default());
     builder.with_module(WalletClientGen::default());
     builder.with_primary_module(1);
     builder.with_federation_info(FederationInfo::from_invite_code(invite_code.clone()).await?);
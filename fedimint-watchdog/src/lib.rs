@@ -0,0 +1,545 @@
+//! Checks a guardian against the rest of its federation from the outside
+//!
+//! The watchdog runs next to `fedimintd` and regularly compares the signed
+//! block headers published by the watched guardian with the headers it
+//! fetches independently from the other guardians. Since it does not share
+//! any state with the guardian, it catches silent local corruption, like a
+//! damaged database serving a different history, that the guardian would not
+//! report itself. It also alerts if the guardian stops completing sessions
+//! while the rest of the federation makes progress, or if the whole
+//! federation stalls.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, ensure};
+use bitcoin_hashes::hex::ToHex;
+use fedimint_core::api::{FederationApiExt, GlobalFederationApi, InviteCode, WsFederationApi};
+use fedimint_core::block::SignedBlockHeader;
+use fedimint_core::config::ClientConfig;
+use fedimint_core::endpoint_constants::{
+    AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT, FETCH_BLOCK_COUNT_ENDPOINT,
+};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::{ApiRequestErased, SerdeModuleEncoding};
+use fedimint_core::query::TrustedPeer;
+use fedimint_core::rotation::{broadcast_public_keys_at, KeyEpoch};
+use fedimint_core::task::{sleep, timeout};
+use fedimint_core::{NumPeers, PeerId};
+use fedimint_logging::LOG_WATCHDOG;
+use futures::future::join_all;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{debug, error, info, warn};
+
+/// How long we wait for a single guardian to respond
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the watchdog detected about the watched guardian
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Error)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WatchdogAlert {
+    #[error("Guardian serves an invalid header for session {session_index}: {reason}")]
+    InvalidHeader { session_index: u64, reason: String },
+    #[error(
+        "Guardian serves header {local_header} for session {session_index}, but the federation \
+         signed {federation_header}"
+    )]
+    Divergence {
+        session_index: u64,
+        local_header: String,
+        federation_header: String,
+    },
+    #[error(
+        "Guardian is stuck at {session_count} sessions for {stalled_for_secs}s, while the \
+         federation completed {federation_session_count}"
+    )]
+    LocalStall {
+        session_count: u64,
+        federation_session_count: u64,
+        stalled_for_secs: u64,
+    },
+    #[error("Federation is stuck at {session_count} sessions for {stalled_for_secs}s")]
+    FederationStall {
+        session_count: u64,
+        stalled_for_secs: u64,
+    },
+}
+
+/// Compares the header served by the watched guardian with the header of the
+/// same session that was fetched from the other guardians and verified
+pub fn compare_headers(
+    session_index: u64,
+    local: &SignedBlockHeader,
+    federation: &SignedBlockHeader,
+    broadcast_public_keys: &BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+) -> Option<WatchdogAlert> {
+    if let Err(e) = local.check_signatures(broadcast_public_keys) {
+        return Some(WatchdogAlert::InvalidHeader {
+            session_index,
+            reason: e.to_string(),
+        });
+    }
+
+    if local.header != federation.header {
+        return Some(WatchdogAlert::Divergence {
+            session_index,
+            local_header: local.header.to_hex(),
+            federation_header: federation.header.to_hex(),
+        });
+    }
+
+    None
+}
+
+/// Tracks for how long a session count has not increased
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    session_count: u64,
+    since: SystemTime,
+}
+
+impl Progress {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            session_count: 0,
+            since: now,
+        }
+    }
+
+    pub fn session_count(&self) -> u64 {
+        self.session_count
+    }
+
+    /// Records the current session count and returns for how long it has not
+    /// increased
+    pub fn update(&mut self, session_count: u64, now: SystemTime) -> Duration {
+        if session_count > self.session_count {
+            self.session_count = session_count;
+            self.since = now;
+        }
+
+        self.stalled_for(now)
+    }
+
+    pub fn stalled_for(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.since).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    /// Invite code used to download the client config of the federation
+    pub invite_code: InviteCode,
+    /// The guardian to watch
+    pub peer_id: PeerId,
+    pub poll_interval: Duration,
+    /// How long a session may take before we alert
+    pub stall_timeout: Duration,
+    /// Command to run with the alert serialized as JSON as its only argument
+    pub alert_command: Option<PathBuf>,
+}
+
+pub struct Watchdog {
+    cfg: WatchdogConfig,
+    client_cfg: ClientConfig,
+    api: WsFederationApi,
+    key_epochs: Vec<KeyEpoch>,
+    /// The next session whose header we compare
+    next_session: Option<u64>,
+    local_progress: Progress,
+    federation_progress: Progress,
+    last_alert: Option<WatchdogAlert>,
+}
+
+impl Watchdog {
+    pub async fn new(cfg: WatchdogConfig) -> anyhow::Result<Self> {
+        let client_cfg = download_client_config(&cfg.invite_code).await?;
+
+        ensure!(
+            client_cfg.global.api_endpoints.contains_key(&cfg.peer_id),
+            "Guardian {} is not part of the federation",
+            cfg.peer_id
+        );
+
+        let now = fedimint_core::time::now();
+
+        Ok(Self {
+            api: WsFederationApi::from_config(&client_cfg),
+            client_cfg,
+            cfg,
+            key_epochs: vec![],
+            next_session: None,
+            local_progress: Progress::new(now),
+            federation_progress: Progress::new(now),
+            last_alert: None,
+        })
+    }
+
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        info!(
+            target: LOG_WATCHDOG,
+            peer_id = %self.cfg.peer_id,
+            federation_id = %self.client_cfg.global.federation_id,
+            "Watching guardian"
+        );
+
+        loop {
+            let alert = self.check(fedimint_core::time::now()).await;
+            self.report(alert).await;
+
+            sleep(self.cfg.poll_interval).await;
+        }
+    }
+
+    /// Compares the headers of the sessions completed since the last check
+    /// and checks that the guardian and the federation make progress
+    async fn check(&mut self, now: SystemTime) -> Option<WatchdogAlert> {
+        self.refresh_key_epochs().await;
+
+        let session_count = match self.session_count(self.cfg.peer_id).await {
+            Ok(session_count) => Some(session_count),
+            Err(e) => {
+                warn!(target: LOG_WATCHDOG, "Could not fetch the guardian's session count: {e}");
+                None
+            }
+        };
+
+        if let Some(session_count) = session_count {
+            // we start with the last completed session instead of checking
+            // the entire history
+            let next_session = *self
+                .next_session
+                .get_or_insert(session_count.saturating_sub(1));
+
+            for session_index in next_session..session_count {
+                match self.compare_session(session_index).await {
+                    Ok(Some(alert)) => return Some(alert),
+                    Ok(None) => self.next_session = Some(session_index + 1),
+                    Err(e) => {
+                        warn!(target: LOG_WATCHDOG, session_index, "Comparison failed: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+
+        let local_stalled_for = match session_count {
+            Some(session_count) => self.local_progress.update(session_count, now),
+            None => self.local_progress.stalled_for(now),
+        };
+
+        let federation_stalled_for = match self.federation_session_count().await {
+            Some(session_count) => self.federation_progress.update(session_count, now),
+            None => self.federation_progress.stalled_for(now),
+        };
+
+        if self.cfg.stall_timeout <= federation_stalled_for {
+            return Some(WatchdogAlert::FederationStall {
+                session_count: self.federation_progress.session_count(),
+                stalled_for_secs: federation_stalled_for.as_secs(),
+            });
+        }
+
+        if self.cfg.stall_timeout <= local_stalled_for
+            && self.local_progress.session_count() < self.federation_progress.session_count()
+        {
+            return Some(WatchdogAlert::LocalStall {
+                session_count: self.local_progress.session_count(),
+                federation_session_count: self.federation_progress.session_count(),
+                stalled_for_secs: local_stalled_for.as_secs(),
+            });
+        }
+
+        None
+    }
+
+    /// Alerts once per kind of alert until the guardian recovers
+    async fn report(&mut self, alert: Option<WatchdogAlert>) {
+        if self.last_alert.as_ref().map(mem::discriminant) == alert.as_ref().map(mem::discriminant)
+        {
+            return;
+        }
+
+        match &alert {
+            Some(alert) => {
+                error!(target: LOG_WATCHDOG, %alert, "Guardian failed a check");
+
+                if let Some(command) = &self.cfg.alert_command {
+                    let status = tokio::process::Command::new(command)
+                        .arg(serde_json::to_string(alert).expect("Serialization can't fail"))
+                        .status()
+                        .await;
+
+                    match status {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => warn!(target: LOG_WATCHDOG, %status, "Alert command failed"),
+                        Err(e) => {
+                            warn!(target: LOG_WATCHDOG, error = %e, "Could not run alert command")
+                        }
+                    }
+                }
+            }
+            None => info!(target: LOG_WATCHDOG, "Guardian passes all checks again"),
+        }
+
+        self.last_alert = alert;
+    }
+
+    /// Fetches the key epochs, and the client config with the current
+    /// broadcast public keys if they were rotated since we fetched it
+    async fn refresh_key_epochs(&mut self) {
+        let key_epochs = match self.api.key_epochs().await {
+            Ok(key_epochs) => key_epochs,
+            Err(e) => {
+                warn!(target: LOG_WATCHDOG, "Could not fetch the key epochs: {e}");
+                return;
+            }
+        };
+
+        if key_epochs.len() > self.key_epochs.len() {
+            match download_client_config(&self.cfg.invite_code).await {
+                Ok(client_cfg) => self.client_cfg = client_cfg,
+                Err(e) => {
+                    warn!(target: LOG_WATCHDOG, "Could not fetch the rotated keys: {e}");
+                    return;
+                }
+            }
+        }
+
+        self.key_epochs = key_epochs;
+    }
+
+    async fn compare_session(&self, session_index: u64) -> anyhow::Result<Option<WatchdogAlert>> {
+        let broadcast_public_keys = broadcast_public_keys_at(
+            &self.key_epochs,
+            &self.client_cfg.global.broadcast_public_keys,
+            session_index,
+        );
+
+        let local = self
+            .signed_block_header(self.cfg.peer_id, session_index)
+            .await?;
+        let federation = self
+            .federation_block_header(session_index, broadcast_public_keys)
+            .await?;
+
+        debug!(target: LOG_WATCHDOG, session_index, "Compared headers");
+
+        Ok(compare_headers(
+            session_index,
+            &local,
+            &federation,
+            broadcast_public_keys,
+        ))
+    }
+
+    /// Fetches the header of the session from the other guardians until one
+    /// serves a header with a valid threshold signature
+    async fn federation_block_header(
+        &self,
+        session_index: u64,
+        broadcast_public_keys: &BTreeMap<PeerId, secp256k1_zkp::PublicKey>,
+    ) -> anyhow::Result<SignedBlockHeader> {
+        for peer_id in self.other_peers() {
+            match self.signed_block_header(peer_id, session_index).await {
+                Ok(header) if header.verify(broadcast_public_keys) => return Ok(header),
+                Ok(_) => {
+                    warn!(target: LOG_WATCHDOG, %peer_id, session_index, "Invalid header");
+                }
+                Err(e) => {
+                    debug!(target: LOG_WATCHDOG, %peer_id, session_index, "No header: {e}");
+                }
+            }
+        }
+
+        Err(anyhow!("No other guardian served a valid header"))
+    }
+
+    /// Returns the session count that at least one honest guardian other
+    /// than the watched one has reached
+    async fn federation_session_count(&self) -> Option<u64> {
+        let mut session_counts = join_all(
+            self.other_peers()
+                .map(|peer_id| async move { self.session_count(peer_id).await.ok() }),
+        )
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        session_counts.sort_unstable_by(|a, b| b.cmp(a));
+
+        let max_evil = self.client_cfg.global.api_endpoints.max_evil();
+
+        session_counts.get(max_evil).copied()
+    }
+
+    fn other_peers(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.client_cfg
+            .global
+            .api_endpoints
+            .keys()
+            .copied()
+            .filter(|peer_id| *peer_id != self.cfg.peer_id)
+    }
+
+    async fn session_count(&self, peer_id: PeerId) -> anyhow::Result<u64> {
+        Ok(timeout(
+            REQUEST_TIMEOUT,
+            self.api.request_with_strategy(
+                TrustedPeer::new(peer_id),
+                FETCH_BLOCK_COUNT_ENDPOINT.to_owned(),
+                ApiRequestErased::default(),
+            ),
+        )
+        .await??)
+    }
+
+    async fn signed_block_header(
+        &self,
+        peer_id: PeerId,
+        session_index: u64,
+    ) -> anyhow::Result<SignedBlockHeader> {
+        let response: SerdeModuleEncoding<SignedBlockHeader> = timeout(
+            REQUEST_TIMEOUT,
+            self.api.request_with_strategy(
+                TrustedPeer::new(peer_id),
+                AWAIT_SIGNED_BLOCK_HEADER_ENDPOINT.to_owned(),
+                ApiRequestErased::new(session_index),
+            ),
+        )
+        .await??;
+
+        let header = response
+            .try_into_inner(&ModuleDecoderRegistry::default())
+            .map_err(|e| anyhow!(e.to_string()))?;
+
+        ensure!(
+            header.index() == session_index,
+            "Header has the wrong index"
+        );
+
+        Ok(header)
+    }
+}
+
+async fn download_client_config(invite_code: &InviteCode) -> anyhow::Result<ClientConfig> {
+    Ok(WsFederationApi::from_invite_code(&[invite_code.clone()])
+        .download_client_config(invite_code)
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use fedimint_core::block::{broadcast_message_hash, SchnorrSignature, SignedBlockHeader};
+    use fedimint_core::PeerId;
+    use secp256k1_zkp::{KeyPair, PublicKey, SecretKey, SECP256K1};
+
+    use super::{compare_headers, Progress, WatchdogAlert};
+
+    fn keypairs() -> BTreeMap<PeerId, KeyPair> {
+        (0..4u16)
+            .map(|peer| {
+                let secret_key =
+                    SecretKey::from_slice(&[peer as u8 + 1; 32]).expect("Valid secret key");
+                (PeerId::from(peer), secret_key.keypair(SECP256K1))
+            })
+            .collect()
+    }
+
+    fn signed_header(
+        header: [u8; 40],
+        keypairs: &BTreeMap<PeerId, KeyPair>,
+        signers: usize,
+    ) -> SignedBlockHeader {
+        let public_keys = public_keys(keypairs);
+        let message = broadcast_message_hash(&public_keys, &header);
+
+        SignedBlockHeader {
+            header,
+            signatures: keypairs
+                .iter()
+                .take(signers)
+                .map(|(peer, keypair)| {
+                    let signature = SECP256K1.sign_schnorr_no_aux_rand(&message, keypair);
+                    (*peer, SchnorrSignature(*signature.as_ref()))
+                })
+                .collect(),
+        }
+    }
+
+    fn public_keys(keypairs: &BTreeMap<PeerId, KeyPair>) -> BTreeMap<PeerId, PublicKey> {
+        keypairs
+            .iter()
+            .map(|(peer, keypair)| (*peer, keypair.public_key()))
+            .collect()
+    }
+
+    #[test]
+    fn detects_diverging_headers() {
+        let keypairs = keypairs();
+        let public_keys = public_keys(&keypairs);
+        let federation = signed_header([1; 40], &keypairs, 3);
+
+        assert_eq!(
+            compare_headers(1, &federation, &federation, &public_keys),
+            None
+        );
+
+        assert!(matches!(
+            compare_headers(
+                1,
+                &signed_header([1; 40], &keypairs, 2),
+                &federation,
+                &public_keys
+            ),
+            Some(WatchdogAlert::InvalidHeader {
+                session_index: 1,
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            compare_headers(
+                1,
+                &signed_header([2; 40], &keypairs, 4),
+                &federation,
+                &public_keys
+            ),
+            Some(WatchdogAlert::Divergence {
+                session_index: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn tracks_progress() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut progress = Progress::new(start);
+
+        assert_eq!(
+            progress.update(0, start + Duration::from_secs(5)),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            progress.update(3, start + Duration::from_secs(10)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            progress.update(3, start + Duration::from_secs(30)),
+            Duration::from_secs(20)
+        );
+        assert_eq!(
+            progress.update(2, start + Duration::from_secs(40)),
+            Duration::from_secs(30)
+        );
+        assert_eq!(progress.session_count(), 3);
+    }
+}
@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use fedimint_core::api::InviteCode;
+use fedimint_core::PeerId;
+use fedimint_logging::TracingSetup;
+use fedimint_watchdog::{Watchdog, WatchdogConfig};
+
+/// Checks a guardian against the rest of its federation and alerts if it
+/// diverges or stalls
+#[derive(Debug, Parser)]
+struct Opts {
+    /// Invite code of the federation
+    #[arg(long, env = "FM_WATCHDOG_INVITE_CODE")]
+    invite_code: InviteCode,
+    /// Peer id of the guardian to watch
+    #[arg(long, env = "FM_WATCHDOG_PEER_ID")]
+    peer_id: PeerId,
+    /// Seconds between checks
+    #[arg(long, env = "FM_WATCHDOG_POLL_INTERVAL_SECS", default_value = "10")]
+    poll_interval_secs: u64,
+    /// Seconds without a completed session after which we alert
+    #[arg(long, env = "FM_WATCHDOG_STALL_TIMEOUT_SECS", default_value = "600")]
+    stall_timeout_secs: u64,
+    /// Command to run with the alert as JSON argument, e.g. to page the
+    /// operator
+    #[arg(long, env = "FM_WATCHDOG_ALERT_COMMAND")]
+    alert_command: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    TracingSetup::default().init()?;
+
+    let opts = Opts::parse();
+
+    Watchdog::new(WatchdogConfig {
+        invite_code: opts.invite_code,
+        peer_id: opts.peer_id,
+        poll_interval: Duration::from_secs(opts.poll_interval_secs),
+        stall_timeout: Duration::from_secs(opts.stall_timeout_secs),
+        alert_command: opts.alert_command,
+    })
+    .await?
+    .run()
+    .await
+}
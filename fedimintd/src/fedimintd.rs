@@ -1,33 +1,56 @@
 use std::collections::BTreeMap;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 use std::time::Duration;
 
-use anyhow::format_err;
-use clap::Parser;
+use anyhow::{ensure, format_err, Context};
+use clap::{Parser, Subcommand};
+use fedimint_announcements_server::AnnouncementsGen;
+use fedimint_bitcoind::create_bitcoind;
 use fedimint_core::admin_client::ConfigGenParamsRequest;
 use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
 use fedimint_core::config::{
-    ModuleInitParams, ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry,
+    ConfigBundle, ModuleInitParams, ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry,
 };
 use fedimint_core::core::{ModuleInstanceId, ModuleKind};
 use fedimint_core::db::Database;
+use fedimint_core::epoch::ConsensusLimits;
+use fedimint_core::fee::FeeSchedule;
 use fedimint_core::module::ServerModuleInit;
 use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::timing;
+use fedimint_core::transaction::TransactionLimits;
 use fedimint_core::util::{write_overwrite, SafeUrl};
+use fedimint_core::Amount;
 use fedimint_ln_server::LightningGen;
 use fedimint_logging::TracingSetup;
+use fedimint_mint_server::common::expiry::NoteExpiry;
 use fedimint_mint_server::MintGen;
+use fedimint_notarization_server::NotarizationGen;
+use fedimint_oracle_server::OracleGen;
+use fedimint_payout_server::PayoutGen;
+use fedimint_server::archive::BlockArchiveConfig;
 use fedimint_server::config::api::ConfigGenSettings;
-use fedimint_server::config::io::{CODE_VERSION, DB_FILE, PLAINTEXT_PASSWORD};
+use fedimint_server::config::bundle::import_config_bundle;
+use fedimint_server::config::io::{
+    read_server_config, remove_password_file, CODE_VERSION, DB_FILE, PLAINTEXT_PASSWORD, SALT_FILE,
+};
+use fedimint_server::drill::Drill;
+use fedimint_server::net::api_tls::ApiTlsConfig;
+use fedimint_server::preflight::run_preflight;
+use fedimint_server::signer::RemoteSignerConfig;
 use fedimint_server::FedimintServer;
+use fedimint_wallet_server::common::config::ConfirmationTier;
+use fedimint_wallet_server::common::taproot::PegInDescriptorKind;
 use fedimint_wallet_server::WalletGen;
 use futures::FutureExt;
 use tokio::select;
 use tracing::{debug, error, info, warn};
 
-use crate::attach_default_module_init_params;
+use crate::dev_fed::{run_dev_fed, DevFedOpts, RegtestHarness};
+use crate::ui::spawn_ui;
+use crate::{attach_default_module_init_params, ExtraModule};
 
 /// Time we will wait before forcefully shutting down tasks
 const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
@@ -44,12 +67,27 @@ pub struct ServerOpts {
     // the API
     #[arg(long, env = "FM_PASSWORD")]
     pub password: Option<String>,
+    /// Command printing the password on stdout, e.g. to fetch it from a key
+    /// management service instead of passing it in plaintext
+    #[arg(long, env = "FM_PASSWORD_COMMAND", conflicts_with = "password")]
+    pub password_command: Option<PathBuf>,
+    /// Never write the password to the data dir, it then has to be provided on
+    /// every start with the options above or through the admin API
+    #[arg(long, env = "FM_NO_PASSWORD_FILE", default_value = "false")]
+    pub no_password_file: bool,
     /// Enable tokio console logging
     #[arg(long, env = "FM_TOKIO_CONSOLE_BIND")]
     pub tokio_console_bind: Option<SocketAddr>,
     /// Enable telemetry logging
     #[arg(long, default_value = "false")]
     pub with_telemetry: bool,
+    /// Export traces to an OpenTelemetry collector at this OTLP/gRPC endpoint,
+    /// e.g. `http://localhost:4317`
+    #[arg(long, env = "FM_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// Log in JSON, e.g. for Loki or Elasticsearch
+    #[arg(long, env = "FM_LOG_JSON", default_value = "false")]
+    pub log_json: bool,
 
     /// Address we bind to for federation communication
     #[arg(long, env = "FM_BIND_P2P", default_value = "127.0.0.1:8173")]
@@ -63,20 +101,215 @@ pub struct ServerOpts {
     /// Our API address for clients to connect to us
     #[arg(long, env = "FM_API_URL", default_value = "ws://127.0.0.1:8174")]
     api_url: SafeUrl,
+    /// Further external addresses for our peers in order of preference, e.g.
+    /// our IPv6 or onion address, tried if the P2P URL is unreachable
+    #[arg(long, env = "FM_P2P_FALLBACK_URLS", value_delimiter = ',')]
+    p2p_fallback_urls: Vec<SafeUrl>,
+    /// Further API addresses for clients in order of preference, tried if the
+    /// API URL is unreachable
+    #[arg(long, env = "FM_API_FALLBACK_URLS", value_delimiter = ',')]
+    api_fallback_urls: Vec<SafeUrl>,
+    /// PEM file with the certificate chain to serve the API over TLS with,
+    /// written to the local config during config gen
+    #[arg(long, env = "FM_API_TLS_CERT_CHAIN", requires = "api_tls_private_key")]
+    api_tls_cert_chain: Option<PathBuf>,
+    /// PEM file with the private key of the API certificate
+    #[arg(long, env = "FM_API_TLS_PRIVATE_KEY", requires = "api_tls_cert_chain")]
+    api_tls_private_key: Option<PathBuf>,
+    /// Command renewing the API certificate, e.g. an ACME client like `certbot
+    /// renew`, run once a day
+    #[arg(
+        long,
+        env = "FM_API_TLS_RENEW_COMMAND",
+        requires = "api_tls_cert_chain"
+    )]
+    api_tls_renew_command: Option<PathBuf>,
+    /// SOCKS5 proxy (e.g. Tor at `socks5://127.0.0.1:9050`) used to connect to
+    /// the P2P and API endpoints of peers running behind onion services
+    #[arg(long, env = "FM_P2P_SOCKS5_PROXY")]
+    p2p_socks5_proxy: Option<SafeUrl>,
+    /// Limit on the outbound bandwidth to each peer in bytes per second, for
+    /// guardians on metered connections
+    #[arg(long, env = "FM_P2P_MAX_OUTBOUND_BYTES_PER_SEC")]
+    p2p_max_outbound_bytes_per_sec: Option<u64>,
+    /// Endpoint of an S3-compatible object storage to archive signed blocks
+    /// in, so auditors and light clients can fetch the consensus history
+    /// without querying the guardians
+    #[arg(
+        long,
+        env = "FM_ARCHIVE_ENDPOINT",
+        requires_all = ["archive_bucket", "archive_access_key_id", "archive_secret_access_key"]
+    )]
+    archive_endpoint: Option<SafeUrl>,
+    /// Bucket to archive signed blocks in
+    #[arg(long, env = "FM_ARCHIVE_BUCKET")]
+    archive_bucket: Option<String>,
+    /// Region of the archive bucket
+    #[arg(long, env = "FM_ARCHIVE_REGION", default_value = "us-east-1")]
+    archive_region: String,
+    /// Access key id used to upload to the archive bucket
+    #[arg(long, env = "FM_ARCHIVE_ACCESS_KEY_ID")]
+    archive_access_key_id: Option<String>,
+    /// Secret access key used to upload to the archive bucket
+    #[arg(long, env = "FM_ARCHIVE_SECRET_ACCESS_KEY")]
+    archive_secret_access_key: Option<String>,
     /// The bitcoin network that fedimint will be running on
     #[arg(long, env = "FM_BITCOIN_NETWORK", default_value = "regtest")]
     network: bitcoin::network::constants::Network,
     /// The bitcoin network that fedimint will be running on
     #[arg(long, env = "FM_FINALITY_DELAY", default_value = "10")]
     finality_delay: u32,
+    /// Custom note denominations in msats to use during config generation
+    /// instead of the powers of two
+    #[arg(long, env = "FM_MINT_DENOMINATIONS", value_delimiter = ',')]
+    mint_denominations: Vec<Amount>,
+    /// Number of sessions after which notes have to be reissued, notes never
+    /// expire if unset
+    #[arg(long, env = "FM_MINT_NOTE_LIFETIME")]
+    mint_note_lifetime: Option<u64>,
+    /// Number of sessions the mint still accepts notes after their lifetime
+    #[arg(long, env = "FM_MINT_NOTE_GRACE_PERIOD", default_value = "0")]
+    mint_note_grace_period: u64,
+    /// The kind of descriptor peg-ins are locked to, only `wsh` is supported
+    /// until taproot peg-outs can be signed along the MuSig2 key path
+    #[arg(long, env = "FM_WALLET_DESCRIPTOR", default_value = "wsh")]
+    wallet_descriptor: PegInDescriptorKind,
+    /// Peg-ins of at least the amount in sats require more confirmations than
+    /// the finality delay, e.g. `100000:6,10000000:12`
+    #[arg(long, env = "FM_WALLET_CONFIRMATION_TIERS", value_delimiter = ',')]
+    wallet_confirmation_tiers: Vec<ConfirmationTier>,
+    /// Modules to set up the federation with in addition to the default ones,
+    /// e.g. `payout,oracle`, out of payout, oracle, announcements and
+    /// notarization
+    #[arg(long, env = "FM_EXTRA_MODULES", value_delimiter = ',')]
+    extra_modules: Vec<ExtraModule>,
 
     #[arg(long, env = "FM_BIND_METRICS_API")]
     bind_metrics_api: Option<SocketAddr>,
 
+    /// Address to serve the signed client config on under
+    /// `/.well-known/fedimint/config`, so users can join with the
+    /// federation's domain instead of an invite code. Put it behind the
+    /// reverse proxy serving HTTPS for that domain.
+    #[arg(long, env = "FM_BIND_WELL_KNOWN")]
+    bind_well_known: Option<SocketAddr>,
+
+    /// Address to serve the web UI for the setup and operation of the
+    /// guardian on, which calls the API at `--api-url` with the password
+    /// entered in the UI. Only bind it to an address reachable by the
+    /// guardian's operators.
+    #[arg(long, env = "FM_BIND_UI")]
+    bind_ui: Option<SocketAddr>,
+
     /// List of default meta values to use during config generation (format:
     /// `key1=value1,key2=value,...`)
     #[arg(long, env = FM_EXTRA_DKG_META_VAR, value_parser = parse_map, default_value="")]
     extra_dkg_meta: BTreeMap<String, String>,
+
+    /// Default limit on the size of a single consensus item in bytes to use
+    /// during config generation
+    #[arg(long, env = "FM_MAX_CONSENSUS_ITEM_BYTES")]
+    max_consensus_item_bytes: Option<u32>,
+
+    /// Default limit on the size of a batch of consensus items in bytes to
+    /// use during config generation
+    #[arg(long, env = "FM_MAX_CONSENSUS_BATCH_BYTES")]
+    max_consensus_batch_bytes: Option<u32>,
+
+    /// Default limit on the size of a transaction in bytes to use during
+    /// config generation
+    #[arg(long, env = "FM_MAX_TRANSACTION_BYTES")]
+    max_transaction_bytes: Option<u32>,
+
+    /// Default limit on the number of inputs of a transaction to use during
+    /// config generation
+    #[arg(long, env = "FM_MAX_TRANSACTION_INPUTS")]
+    max_transaction_inputs: Option<u32>,
+
+    /// Default limit on the number of outputs of a transaction to use during
+    /// config generation
+    #[arg(long, env = "FM_MAX_TRANSACTION_OUTPUTS")]
+    max_transaction_outputs: Option<u32>,
+
+    /// Command to run with the violation as JSON argument when the consensus
+    /// halts since a safety invariant was violated, e.g. to page the operator
+    #[arg(long, env = "FM_ALERT_COMMAND")]
+    alert_command: Option<PathBuf>,
+
+    /// URL of a signer daemon holding our broadcast key and auth key share,
+    /// e.g. in an HSM, instead of signing with the keys of our private config
+    #[arg(
+        long,
+        env = "FM_REMOTE_SIGNER_URL",
+        requires = "remote_signer_auth_token"
+    )]
+    remote_signer_url: Option<SafeUrl>,
+    /// Bearer token to authenticate with the signer daemon
+    #[arg(long, env = "FM_REMOTE_SIGNER_AUTH_TOKEN")]
+    remote_signer_auth_token: Option<String>,
+
+    /// Config bundle exported by the admin API to restore this guardian from,
+    /// ignored if the data dir already contains a config
+    #[arg(
+        long,
+        env = "FM_IMPORT_CONFIG_BUNDLE",
+        requires = "config_bundle_passphrase"
+    )]
+    import_config_bundle: Option<PathBuf>,
+    /// Passphrase the private config of the bundle was encrypted with
+    #[arg(long, env = "FM_CONFIG_BUNDLE_PASSPHRASE")]
+    config_bundle_passphrase: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<ServerCommand>,
+}
+
+#[derive(Subcommand)]
+enum ServerCommand {
+    /// Removes the plaintext password from the data dir of an existing
+    /// deployment after checking that it decrypts the private config, run with
+    /// `--no-password-file` afterwards
+    RemovePasswordFile,
+    /// Rehearses the loss of the guardian: wipes its database after taking a
+    /// checkpoint, recovers it from the config and reports how long it took to
+    /// catch up with its peers
+    Drill {
+        /// How long we wait for the guardian to catch up
+        #[arg(long, default_value = "3600")]
+        timeout_secs: u64,
+        /// Keep the recovered database instead of restoring the checkpoint on
+        /// the next start
+        #[arg(long, default_value = "false")]
+        keep_recovered: bool,
+    },
+    /// Runs a federation of several guardians in this process with in-memory
+    /// databases and prints its invite code, for developing modules and
+    /// clients without setting up the guardians one by one
+    DevFed(DevFedOpts),
+    /// Checks that every peer in the config is reachable, authenticates with
+    /// its TLS certificate, runs the same consensus config, has a clock and
+    /// bitcoin backend agreeing with ours and prints a go/no-go report
+    Preflight,
+}
+
+/// Runs the password command and returns its output without the trailing
+/// newline
+async fn run_password_command(command: &Path) -> anyhow::Result<String> {
+    let output = tokio::process::Command::new(command)
+        .stderr(Stdio::inherit())
+        .output()
+        .await
+        .with_context(|| format!("Could not run password command {}", command.display()))?;
+
+    ensure!(
+        output.status.success(),
+        "Password command failed with {}",
+        output.status
+    );
+
+    let password = String::from_utf8(output.stdout).context("Password is not valid UTF-8")?;
+
+    Ok(password.trim_end_matches(['\n', '\r']).to_owned())
 }
 
 fn parse_map(s: &str) -> anyhow::Result<BTreeMap<String, String>> {
@@ -172,10 +405,16 @@ impl Fedimintd {
         self
     }
 
+    /// Supports the default modules and the optional ones, which are only
+    /// set up if enabled with `--extra-modules`
     pub fn with_default_modules(self) -> Self {
         self.with_module(LightningGen)
             .with_module(MintGen)
             .with_module(WalletGen)
+            .with_module(PayoutGen)
+            .with_module(OracleGen)
+            .with_module(AnnouncementsGen)
+            .with_module(NotarizationGen)
     }
 
     pub async fn run(self) -> ! {
@@ -183,9 +422,26 @@ impl Fedimintd {
         TracingSetup::default()
             .tokio_console_bind(opts.tokio_console_bind)
             .with_jaeger(opts.with_telemetry)
+            .with_otlp(opts.otlp_endpoint.clone())
+            .with_json(opts.log_json)
             .init()
             .unwrap();
 
+        if let Some(ServerCommand::RemovePasswordFile) = opts.command {
+            match remove_password_file(opts.data_dir.clone()) {
+                Ok(()) => {
+                    info!(
+                        "Removed the password file, the password now has to be provided on start"
+                    );
+                    std::process::exit(0);
+                }
+                Err(error) => {
+                    error!(?error, "Could not remove the password file");
+                    std::process::exit(1);
+                }
+            }
+        }
+
         let mut root_task_group = TaskGroup::new();
         root_task_group.install_kill_handler();
 
@@ -253,17 +509,50 @@ impl Fedimintd {
 
 async fn run(
     opts: ServerOpts,
-    task_group: TaskGroup,
+    mut task_group: TaskGroup,
     module_inits: ServerModuleInitRegistry,
     mut module_inits_params: ServerModuleConfigGenParamsRegistry,
 ) -> anyhow::Result<()> {
+    let regtest_harness = match &opts.command {
+        Some(ServerCommand::DevFed(dev_fed_opts)) if dev_fed_opts.bitcoind => {
+            ensure!(
+                opts.network == bitcoin::Network::Regtest,
+                "The bitcoind of the dev federation only runs on regtest"
+            );
+            Some(RegtestHarness::start(&opts.data_dir, dev_fed_opts).await?)
+        }
+        _ => None,
+    };
+    let bitcoin_rpc = match &regtest_harness {
+        Some(regtest_harness) => regtest_harness.rpc_config(),
+        None => BitcoinRpcConfig::from_env_vars()?,
+    };
+
     attach_default_module_init_params(
-        BitcoinRpcConfig::from_env_vars()?,
+        bitcoin_rpc.clone(),
         &mut module_inits_params,
         opts.network,
         opts.finality_delay,
+        opts.mint_denominations.clone(),
+        opts.mint_note_lifetime
+            .map(|lifetime| NoteExpiry::new(lifetime, opts.mint_note_grace_period)),
+        opts.wallet_descriptor,
+        opts.wallet_confirmation_tiers.clone(),
+        &opts.extra_modules,
     );
 
+    if let Some(ServerCommand::DevFed(dev_fed_opts)) = opts.command {
+        std::fs::create_dir_all(&opts.data_dir)?;
+        return run_dev_fed(
+            dev_fed_opts,
+            &opts.data_dir,
+            task_group,
+            module_inits,
+            module_inits_params,
+        )
+        .await;
+    }
+
     let module_kinds = module_inits_params
         .iter_modules()
         .map(|(id, kind, _)| (id, kind));
@@ -273,16 +562,116 @@ async fn run(
         decoders.clone(),
     );
 
-    // TODO: Fedimintd should use the config gen API
-    // on each run we want to pass the currently passed password, so we need to
-    // overwrite
-    if let Some(password) = opts.password {
-        write_overwrite(opts.data_dir.join(PLAINTEXT_PASSWORD), password)?;
+    if let (Some(path), Some(passphrase)) =
+        (&opts.import_config_bundle, &opts.config_bundle_passphrase)
+    {
+        if opts.data_dir.join(SALT_FILE).exists() {
+            info!("Data dir already contains a config, not importing the config bundle");
+        } else {
+            let bundle: ConfigBundle = serde_json::from_slice(&std::fs::read(path)?)?;
+            let cfg = import_config_bundle(
+                &bundle,
+                passphrase,
+                opts.data_dir.clone(),
+                &module_inits,
+                !opts.no_password_file,
+            )?;
+            info!(peer = %cfg.local.identity, "Imported config bundle");
+        }
+    }
+
+    let drill = match opts.command {
+        Some(ServerCommand::Drill {
+            timeout_secs,
+            keep_recovered,
+        }) => Some((
+            Drill::start(opts.data_dir.clone(), db.clone()).await?,
+            Duration::from_secs(timeout_secs),
+            keep_recovered,
+        )),
+        _ => None,
     };
+
+    let password = match (opts.password, &opts.password_command) {
+        (Some(password), _) => Some(password),
+        (None, Some(command)) => Some(run_password_command(command).await?),
+        (None, None) => None,
+    };
+
+    if let Some(ServerCommand::Preflight) = opts.command {
+        return preflight(&opts.data_dir, password, &bitcoin_rpc, &task_group).await;
+    }
+
+    if opts.no_password_file {
+        ensure!(
+            !opts.data_dir.join(PLAINTEXT_PASSWORD).exists(),
+            "The data dir contains a password file, remove it with the remove-password-file command"
+        );
+    } else if let Some(password) = &password {
+        // TODO: Fedimintd should use the config gen API
+        // on each run we want to pass the currently passed password, so we need to
+        // overwrite
+        write_overwrite(opts.data_dir.join(PLAINTEXT_PASSWORD), password)?;
+    }
+    let default_limits = ConsensusLimits::default();
+    let max_item_bytes = opts
+        .max_consensus_item_bytes
+        .unwrap_or(default_limits.max_item_bytes);
     let default_params = ConfigGenParamsRequest {
         meta: opts.extra_dkg_meta.clone(),
         modules: module_inits_params,
+        limits: ConsensusLimits {
+            max_item_bytes,
+            max_batch_bytes: opts
+                .max_consensus_batch_bytes
+                .unwrap_or(default_limits.max_batch_bytes),
+            transaction: TransactionLimits {
+                // a transaction has to fit into a consensus item
+                max_bytes: opts
+                    .max_transaction_bytes
+                    .unwrap_or(default_limits.transaction.max_bytes.min(max_item_bytes)),
+                max_inputs: opts
+                    .max_transaction_inputs
+                    .unwrap_or(default_limits.transaction.max_inputs),
+                max_outputs: opts
+                    .max_transaction_outputs
+                    .unwrap_or(default_limits.transaction.max_outputs),
+            },
+        },
+        fees: FeeSchedule::default(),
     };
+    let archive = match (
+        opts.archive_endpoint,
+        opts.archive_bucket,
+        opts.archive_access_key_id,
+        opts.archive_secret_access_key,
+    ) {
+        (Some(endpoint), Some(bucket), Some(access_key_id), Some(secret_access_key)) => {
+            Some(BlockArchiveConfig {
+                endpoint,
+                bucket,
+                region: opts.archive_region,
+                access_key_id,
+                secret_access_key,
+            })
+        }
+        _ => None,
+    };
+    let api_tls = match (opts.api_tls_cert_chain, opts.api_tls_private_key) {
+        (Some(cert_chain_file), Some(private_key_file)) => Some(ApiTlsConfig {
+            cert_chain_file,
+            private_key_file,
+            renew_command: opts.api_tls_renew_command,
+        }),
+        _ => None,
+    };
+    let remote_signer = match (opts.remote_signer_url, opts.remote_signer_auth_token) {
+        (Some(url), Some(auth_token)) => Some(RemoteSignerConfig { url, auth_token }),
+        _ => None,
+    };
+    if let Some(bind_ui) = opts.bind_ui {
+        spawn_ui(bind_ui, opts.api_url.clone(), &mut task_group).await?;
+    }
     let mut api = FedimintServer {
         data_dir: opts.data_dir,
         settings: ConfigGenSettings {
@@ -291,13 +680,32 @@ async fn run(
             api_bind: opts.bind_api,
             p2p_url: opts.p2p_url,
             api_url: opts.api_url,
+            p2p_fallback_urls: opts.p2p_fallback_urls,
+            api_fallback_urls: opts.api_fallback_urls,
             default_params,
             max_connections: fedimint_server::config::max_connections(),
+            socks5_proxy: opts.p2p_socks5_proxy,
+            p2p_max_outbound_bytes_per_sec: opts.p2p_max_outbound_bytes_per_sec,
+            api_tls,
             registry: module_inits,
+            persist_password: !opts.no_password_file,
         },
         db,
+        archive,
+        well_known_bind: opts.bind_well_known,
+        alert_command: opts.alert_command,
+        remote_signer,
+        password,
     };
-    if let Some(bind_metrics_api) = opts.bind_metrics_api.as_ref() {
+    if let Some((drill, timeout, keep_recovered)) = drill {
+        select! {
+            api_result = api.run(task_group.clone()) => api_result?,
+            report = drill.run(timeout, keep_recovered) => {
+                task_group.shutdown();
+                ensure!(report?.passed(), "The guardian failed the disaster drill");
+            }
+        }
+    } else if let Some(bind_metrics_api) = opts.bind_metrics_api.as_ref() {
         let (api_result, metrics_api_result) = futures::join!(
             api.run(task_group.clone()),
             spawn_metrics_server(bind_metrics_api, task_group)
@@ -310,6 +718,51 @@ async fn run(
     Ok(())
 }
 
+/// Runs the pre-flight checks against the peers in the config of the data dir
+/// and prints the report
+async fn preflight(
+    data_dir: &Path,
+    password: Option<String>,
+    bitcoin_rpc: &BitcoinRpcConfig,
+    task_group: &TaskGroup,
+) -> anyhow::Result<()> {
+    let password = match password {
+        Some(password) => password,
+        None => std::fs::read_to_string(data_dir.join(PLAINTEXT_PASSWORD))
+            .context("The pre-flight checks need the password of the config")?,
+    };
+    let cfg = read_server_config(&password, data_dir.to_path_buf())?;
+
+    let wallet_instance = cfg
+        .consensus
+        .iter_module_instances()
+        .find(|(_, kind)| **kind == fedimint_wallet_server::common::KIND)
+        .map(|(instance, _)| instance);
+
+    let block_count = async {
+        create_bitcoind(bitcoin_rpc, task_group.make_handle())?
+            .get_block_count()
+            .await
+    };
+    let block_count = match block_count.await {
+        Ok(block_count) => Some(block_count),
+        Err(error) => {
+            warn!(?error, "Could not query our bitcoin backend");
+            None
+        }
+    };
+
+    let report = run_preflight(&cfg, block_count, wallet_instance).await;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    ensure!(
+        report.passed(),
+        "The federation is not ready to be launched"
+    );
+
+    Ok(())
+}
+
 async fn spawn_metrics_server(
     bind_address: &SocketAddr,
     mut task_group: TaskGroup,
@@ -1,33 +1,99 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use anyhow::bail;
 use bitcoin::Network;
+use fedimint_announcements_common::config::AnnouncementsGenParams;
+use fedimint_announcements_server::AnnouncementsGen;
 use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
 use fedimint_core::config::ServerModuleConfigGenParamsRegistry;
 use fedimint_core::core::{
-    LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
+    ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
     LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
 use fedimint_core::module::ServerModuleInit;
 use fedimint_core::util::SafeUrl;
+use fedimint_core::Amount;
 use fedimint_ln_common::config::{
     LightningGenParams, LightningGenParamsConsensus, LightningGenParamsLocal,
 };
 use fedimint_ln_server::LightningGen;
 use fedimint_mint_server::common::config::{MintGenParams, MintGenParamsConsensus};
+use fedimint_mint_server::common::expiry::NoteExpiry;
 use fedimint_mint_server::MintGen;
+use fedimint_notarization_common::config::NotarizationGenParams;
+use fedimint_notarization_server::NotarizationGen;
+use fedimint_oracle_common::config::OracleGenParams;
+use fedimint_oracle_server::OracleGen;
+use fedimint_payout_common::config::PayoutGenParams;
+use fedimint_payout_server::PayoutGen;
 use fedimint_wallet_server::common::config::{
-    WalletGenParams, WalletGenParamsConsensus, WalletGenParamsLocal,
+    ConfirmationTier, WalletGenParams, WalletGenParamsConsensus, WalletGenParamsLocal,
 };
+use fedimint_wallet_server::common::taproot::PegInDescriptorKind;
 use fedimint_wallet_server::WalletGen;
 
 /// Module for creating `fedimintd` binary with custom modules
 pub mod fedimintd;
 
+/// Federation of several guardians in one process for local development
+mod dev_fed;
+
+/// Web UI for the setup and operation of a guardian
+mod ui;
+
+/// Optional modules a federation can be set up with in addition to the
+/// lightning, mint and wallet modules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExtraModule {
+    /// Pays the fee income of the federation out to the guardians
+    Payout,
+    /// Agrees on the bitcoin price in fiat currencies
+    Oracle,
+    /// Publishes signed announcements of the guardians to clients
+    Announcements,
+    /// Commits hashes into the signed blocks of the federation
+    Notarization,
+}
+
+impl FromStr for ExtraModule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "payout" => Ok(ExtraModule::Payout),
+            "oracle" => Ok(ExtraModule::Oracle),
+            "announcements" => Ok(ExtraModule::Announcements),
+            "notarization" => Ok(ExtraModule::Notarization),
+            _ => {
+                bail!("Unknown module {s}, expected payout, oracle, announcements or notarization")
+            }
+        }
+    }
+}
+
 /// Generates the configuration for the modules configured in the server binary
 pub fn attach_default_module_init_params(
     bitcoin_rpc: BitcoinRpcConfig,
     module_init_params: &mut ServerModuleConfigGenParamsRegistry,
     network: Network,
     finality_delay: u32,
+    mint_denominations: Vec<Amount>,
+    mint_note_expiry: Option<NoteExpiry>,
+    wallet_descriptor_kind: PegInDescriptorKind,
+    wallet_confirmation_tiers: Vec<ConfirmationTier>,
+    extra_modules: &[ExtraModule],
 ) {
+    let mut mint_consensus = if mint_denominations.is_empty() {
+        MintGenParamsConsensus::new(2)
+    } else {
+        MintGenParamsConsensus::with_denominations(mint_denominations)
+    };
+
+    if let Some(note_expiry) = mint_note_expiry {
+        mint_consensus = mint_consensus.with_note_expiry(note_expiry);
+    }
+
     module_init_params
         .attach_config_gen_params(
             LEGACY_HARDCODED_INSTANCE_ID_WALLET,
@@ -42,6 +108,8 @@ pub fn attach_default_module_init_params(
                     // commit anyway
                     finality_delay,
                     client_default_bitcoin_rpc: default_esplora_server(network),
+                    peg_in_descriptor_kind: wallet_descriptor_kind,
+                    peg_in_confirmation_tiers: wallet_confirmation_tiers,
                 },
             },
         )
@@ -50,7 +118,7 @@ pub fn attach_default_module_init_params(
             MintGen::kind(),
             MintGenParams {
                 local: Default::default(),
-                consensus: MintGenParamsConsensus::new(2),
+                consensus: mint_consensus,
             },
         )
         .attach_config_gen_params(
@@ -61,6 +129,44 @@ pub fn attach_default_module_init_params(
                 consensus: LightningGenParamsConsensus { network },
             },
         );
+
+    // The extra modules follow the default ones in a fixed order, such that
+    // every guardian assigns them the same instance ids
+    let extra_modules = extra_modules.iter().copied().collect::<BTreeSet<_>>();
+    let first_instance_id = LEGACY_HARDCODED_INSTANCE_ID_WALLET + 1;
+
+    for (module_instance_id, module) in (first_instance_id..).zip(extra_modules) {
+        attach_extra_module_init_params(module_init_params, module_instance_id, module);
+    }
+}
+
+fn attach_extra_module_init_params(
+    module_init_params: &mut ServerModuleConfigGenParamsRegistry,
+    module_instance_id: ModuleInstanceId,
+    module: ExtraModule,
+) {
+    match module {
+        ExtraModule::Payout => module_init_params.attach_config_gen_params(
+            module_instance_id,
+            PayoutGen::kind(),
+            PayoutGenParams::default(),
+        ),
+        ExtraModule::Oracle => module_init_params.attach_config_gen_params(
+            module_instance_id,
+            OracleGen::kind(),
+            OracleGenParams::default(),
+        ),
+        ExtraModule::Announcements => module_init_params.attach_config_gen_params(
+            module_instance_id,
+            AnnouncementsGen::kind(),
+            AnnouncementsGenParams::default(),
+        ),
+        ExtraModule::Notarization => module_init_params.attach_config_gen_params(
+            module_instance_id,
+            NotarizationGen::kind(),
+            NotarizationGenParams::default(),
+        ),
+    };
 }
 
 pub fn default_esplora_server(network: Network) -> BitcoinRpcConfig {
@@ -0,0 +1,113 @@
+//! Web UI for guardians served by fedimintd
+//!
+//! The page guides a guardian through the setup ceremony, from entering the
+//! peer info over watching the DKG progress to verifying the configs, and
+//! shows the health of the consensus and the audit afterwards, so guardians
+//! don't need the CLI. It calls the guardian's API through this server, which
+//! only forwards the endpoints in [`UI_ENDPOINTS`] and passes on the password
+//! entered in the page, so everything besides the page itself is behind the
+//! admin auth of the API.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Html;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use fedimint_core::api::{IFederationApi, WsFederationApi};
+use fedimint_core::endpoint_constants::{
+    AUDIT_ENDPOINT, AUTH_ENDPOINT, DKG_STATUS_ENDPOINT, GET_CONFIG_GEN_PEERS_ENDPOINT,
+    GET_DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT, GET_VERIFY_CONFIG_HASH_ENDPOINT, RUN_DKG_ENDPOINT,
+    SET_CONFIG_GEN_CONNECTIONS_ENDPOINT, SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT,
+    START_CONSENSUS_ENDPOINT, STATUS_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
+};
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::task::TaskGroup;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::PeerId;
+use tracing::{error, info};
+
+/// Endpoints of the guardian's API the page can call
+const UI_ENDPOINTS: &[&str] = &[
+    AUDIT_ENDPOINT,
+    AUTH_ENDPOINT,
+    DKG_STATUS_ENDPOINT,
+    GET_CONFIG_GEN_PEERS_ENDPOINT,
+    GET_DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT,
+    GET_VERIFY_CONFIG_HASH_ENDPOINT,
+    RUN_DKG_ENDPOINT,
+    SET_CONFIG_GEN_CONNECTIONS_ENDPOINT,
+    SET_CONFIG_GEN_PARAMS_ENDPOINT,
+    SET_PASSWORD_ENDPOINT,
+    START_CONSENSUS_ENDPOINT,
+    STATUS_ENDPOINT,
+    VERIFIED_CONFIGS_ENDPOINT,
+];
+
+/// The page, which only contains static HTML and JS
+const UI_PAGE: &str = include_str!("ui.html");
+
+#[derive(Clone)]
+struct UiState {
+    api: Arc<WsFederationApi>,
+}
+
+async fn get_page() -> Html<&'static str> {
+    Html(UI_PAGE)
+}
+
+/// Forwards the request of the page to the guardian's API, the request
+/// contains the password entered in the page if the endpoint needs it
+async fn call_api(
+    State(state): State<UiState>,
+    Path(method): Path<String>,
+    Json(request): Json<ApiRequestErased>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    if !UI_ENDPOINTS.contains(&method.as_str()) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Endpoint {method} is not available in the UI"),
+        ));
+    }
+
+    state
+        .api
+        .request_raw(PeerId::from(0), &method, &[request.to_json()])
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))
+}
+
+/// Spawns the HTTP server serving the UI on `bind_address`, which talks to the
+/// guardian's API at `api_url`
+pub async fn spawn_ui(
+    bind_address: SocketAddr,
+    api_url: SafeUrl,
+    task_group: &mut TaskGroup,
+) -> anyhow::Result<()> {
+    let api = WsFederationApi::new(vec![(PeerId::from(0), api_url)]);
+    let app = Router::new()
+        .route("/", get(get_page))
+        .route("/api/:method", post(call_api))
+        .with_state(UiState { api: Arc::new(api) });
+    let server = axum::Server::try_bind(&bind_address)?.serve(app.into_make_service());
+
+    let shutdown_rx = task_group.make_handle().make_shutdown_rx().await;
+    task_group
+        .spawn("guardian ui", move |_| async move {
+            let graceful = server.with_graceful_shutdown(async {
+                shutdown_rx.await;
+            });
+
+            if let Err(e) = graceful.await {
+                error!("Error serving the guardian UI: {e:?}");
+            }
+        })
+        .await;
+
+    info!("Serving the guardian UI at http://{bind_address}");
+
+    Ok(())
+}
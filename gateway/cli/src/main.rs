@@ -3,10 +3,15 @@ use clap::{CommandFactory, Parser, Subcommand};
 use fedimint_core::config::FederationId;
 use fedimint_core::util::SafeUrl;
 use fedimint_logging::TracingSetup;
+use lightning_invoice::Bolt11Invoice;
+use ln_gateway::db::PaymentLogKey;
+use ln_gateway::fees::{FeePolicy, PeakLoadMultiplier};
+use ln_gateway::float::FloatPolicy;
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
 use ln_gateway::rpc::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, RestorePayload,
-    SetConfigurationPayload, WithdrawPayload,
+    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, FeeQuotePayload,
+    PaymentHistoryPayload, PaymentStatsPayload, RestorePayload, SetConfigurationPayload,
+    SetFederationFeesPayload, SetFeePolicyPayload, SetFloatPolicyPayload, WithdrawPayload,
 };
 use serde::Serialize;
 
@@ -54,7 +59,61 @@ pub enum Commands {
     ConnectFed {
         /// InviteCode code to connect to the federation
         invite_code: String,
+
+        /// Lightning node to serve the federation through, the least loaded
+        /// one if omitted
+        #[clap(long)]
+        lightning_node: Option<String>,
+    },
+    /// List the liquidity and earnings of every connected federation
+    Federations,
+    /// Set the routing fees charged to the users of a federation
+    SetFederationFees {
+        #[clap(long)]
+        federation_id: FederationId,
+
+        /// The fees as `base_msat,proportional_millionths`
+        #[clap(long)]
+        routing_fees: String,
+    },
+    /// Raise the routing fees of all federations while the gateway is busy
+    SetFeePolicy {
+        /// Multiplies the fees once the number of payments routed within the
+        /// last ten minutes is reached, as
+        /// `<min_payments>:<multiplier_percent>`
+        #[clap(long = "peak-load-multiplier")]
+        peak_load_multipliers: Vec<PeakLoadMultiplier>,
+    },
+    /// Quote the fee the gateway currently charges for paying an invoice
+    QuoteFee {
+        #[clap(long)]
+        federation_id: FederationId,
+
+        invoice: Bolt11Invoice,
+    },
+    /// List the routed payments, the most recent first
+    Payments {
+        /// Only list the payments of this federation
+        #[clap(long)]
+        federation_id: Option<FederationId>,
+
+        /// Continue listing after the payment the previous page ended with,
+        /// as `<timestamp>:<operation_id>`
+        #[clap(long)]
+        before: Option<PaymentLogKey>,
+
+        #[clap(long, default_value_t = 50)]
+        limit: usize,
     },
+    /// List how many payments succeeded per federation and route and the fees
+    /// they earned
+    PaymentStats {
+        /// Only list the stats of this federation
+        #[clap(long)]
+        federation_id: Option<FederationId>,
+    },
+    /// List the liquidity of the channels of every lightning node
+    Channels,
     /// Make a backup of snapshot of all ecash
     Backup {
         #[clap(long)]
@@ -81,6 +140,21 @@ pub enum Commands {
         #[clap(long)]
         network: Option<bitcoin::Network>,
     },
+    /// Keep the ecash balance in a federation within bounds by automatically
+    /// moving funds between the lightning node and the federation
+    SetFloatPolicy {
+        #[clap(long)]
+        federation_id: FederationId,
+
+        /// Minimum ecash balance in msat, omit together with `max_float` to
+        /// remove the policy
+        #[clap(long, requires = "max_float")]
+        min_float: Option<fedimint_core::Amount>,
+
+        /// Maximum ecash balance in msat
+        #[clap(long, requires = "min_float")]
+        max_float: Option<fedimint_core::Amount>,
+    },
 }
 
 #[tokio::main]
@@ -128,13 +202,86 @@ async fn main() -> anyhow::Result<()> {
 
             print_response(response).await;
         }
-        Commands::ConnectFed { invite_code } => {
+        Commands::ConnectFed {
+            invite_code,
+            lightning_node,
+        } => {
             let response = client()
-                .connect_federation(ConnectFedPayload { invite_code })
+                .connect_federation(ConnectFedPayload {
+                    invite_code,
+                    lightning_node,
+                })
                 .await?;
 
             print_response(response).await;
         }
+        Commands::Federations => {
+            let response = client().get_federations().await?;
+
+            print_response(response).await;
+        }
+        Commands::SetFederationFees {
+            federation_id,
+            routing_fees,
+        } => {
+            client()
+                .set_federation_fees(SetFederationFeesPayload {
+                    federation_id,
+                    routing_fees,
+                })
+                .await?;
+        }
+        Commands::SetFeePolicy {
+            peak_load_multipliers,
+        } => {
+            client()
+                .set_fee_policy(SetFeePolicyPayload {
+                    policy: FeePolicy {
+                        peak_load_multipliers,
+                    },
+                })
+                .await?;
+        }
+        Commands::QuoteFee {
+            federation_id,
+            invoice,
+        } => {
+            let response = client()
+                .quote_fee(FeeQuotePayload {
+                    federation_id,
+                    invoice,
+                })
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::Payments {
+            federation_id,
+            before,
+            limit,
+        } => {
+            let response = client()
+                .get_payments(PaymentHistoryPayload {
+                    federation_id,
+                    before,
+                    limit,
+                })
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::PaymentStats { federation_id } => {
+            let response = client()
+                .get_payment_stats(PaymentStatsPayload { federation_id })
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::Channels => {
+            let response = client().get_channels().await?;
+
+            print_response(response).await;
+        }
         Commands::Backup { federation_id } => {
             client().backup(BackupPayload { federation_id }).await?;
         }
@@ -164,6 +311,25 @@ async fn main() -> anyhow::Result<()> {
                 })
                 .await?;
         }
+        Commands::SetFloatPolicy {
+            federation_id,
+            min_float,
+            max_float,
+        } => {
+            let policy = min_float
+                .zip(max_float)
+                .map(|(min_float, max_float)| FloatPolicy {
+                    min_float,
+                    max_float,
+                });
+
+            client()
+                .set_float_policy(SetFloatPolicyPayload {
+                    federation_id,
+                    policy,
+                })
+                .await?;
+        }
     }
 
     Ok(())
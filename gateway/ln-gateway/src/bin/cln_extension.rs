@@ -17,10 +17,12 @@ use fedimint_core::Amount;
 use ln_gateway::gateway_lnrpc::gateway_lightning_server::{
     GatewayLightning, GatewayLightningServer,
 };
+use ln_gateway::gateway_lnrpc::get_channel_liquidity_response::ChannelLiquidity;
 use ln_gateway::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
 use ln_gateway::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Forward, Settle};
 use ln_gateway::gateway_lnrpc::{
-    EmptyRequest, EmptyResponse, GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse,
+    CreateInvoiceRequest, CreateInvoiceResponse, EmptyRequest, EmptyResponse,
+    GetChannelLiquidityResponse, GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse,
     InterceptHtlcRequest, InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
 };
 use secp256k1::PublicKey;
@@ -415,6 +417,52 @@ impl GatewayLightning for ClnRpcService {
         Ok(tonic::Response::new(outcome))
     }
 
+    async fn create_invoice(
+        &self,
+        request: tonic::Request<CreateInvoiceRequest>,
+    ) -> Result<tonic::Response<CreateInvoiceResponse>, tonic::Status> {
+        let CreateInvoiceRequest {
+            amount_msat,
+            description,
+            expiry_secs,
+        } = request.into_inner();
+
+        // CLN requires a unique label for every invoice
+        let label = format!("fedimint-gateway-{}", rand::random::<u64>());
+
+        let outcome = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call(cln_rpc::Request::Invoice(model::requests::InvoiceRequest {
+                amount_msat: cln_rpc::primitives::AmountOrAny::Amount(
+                    cln_rpc::primitives::Amount::from_msat(amount_msat),
+                ),
+                description,
+                label,
+                expiry: Some(expiry_secs),
+                fallbacks: None,
+                preimage: None,
+                exposeprivatechannels: None,
+                cltv: None,
+                deschashonly: None,
+            }))
+            .await
+            .map(|response| match response {
+                cln_rpc::Response::Invoice(model::responses::InvoiceResponse {
+                    bolt11, ..
+                }) => Ok(CreateInvoiceResponse { invoice: bolt11 }),
+                _ => Err(ClnExtensionError::RpcWrongResponse),
+            })
+            .map_err(|e| {
+                error!("cln invoice rpc returned error {:?}", e);
+                tonic::Status::internal(e.to_string())
+            })?
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+
+        Ok(tonic::Response::new(outcome))
+    }
+
     type RouteHtlcsStream = ReceiverStream<Result<InterceptHtlcRequest, Status>>;
 
     async fn route_htlcs(
@@ -506,6 +554,49 @@ impl GatewayLightning for ClnRpcService {
         }
         Ok(tonic::Response::new(EmptyResponse {}))
     }
+
+    async fn get_channel_liquidity(
+        &self,
+        _request: tonic::Request<EmptyRequest>,
+    ) -> Result<tonic::Response<GetChannelLiquidityResponse>, Status> {
+        let mut client = self
+            .rpc_client()
+            .await
+            .map_err(|err| tonic::Status::internal(err.to_string()))?;
+
+        let listfunds_response = client
+            .call(cln_rpc::Request::ListFunds(
+                model::requests::ListfundsRequest { spent: None },
+            ))
+            .await
+            .map_err(|err| tonic::Status::internal(err.to_string()))?;
+
+        let channels = match listfunds_response {
+            cln_rpc::Response::ListFunds(listfunds) => Ok(listfunds.channels),
+            _ => Err(ClnExtensionError::RpcWrongResponse),
+        }
+        .map_err(|err| tonic::Status::internal(err.to_string()))?
+        .into_iter()
+        // Channels without a short channel id are not confirmed yet
+        .filter_map(|chan| {
+            chan.short_channel_id.map(|scid| ChannelLiquidity {
+                remote_pub_key: chan.peer_id.serialize().to_vec(),
+                short_channel_id: scid_to_u64(scid),
+                outbound_liquidity_msat: chan.our_amount_msat.msat(),
+                inbound_liquidity_msat: (chan.amount_msat - chan.our_amount_msat).msat(),
+                active: chan.connected
+                    && matches!(
+                        chan.state,
+                        cln_rpc::primitives::ChannelState::CHANNELD_NORMAL
+                    ),
+            })
+        })
+        .collect();
+
+        Ok(tonic::Response::new(GetChannelLiquidityResponse {
+            channels,
+        }))
+    }
 }
 
 #[derive(Debug, Error)]
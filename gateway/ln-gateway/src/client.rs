@@ -6,14 +6,17 @@ use std::sync::Arc;
 use fedimint_client::module::init::ClientModuleInitRegistry;
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::{get_config_from_db, ClientBuilder, FederationInfo};
+use fedimint_core::api::InviteCode;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_ln_client::LightningClientGen;
 use futures::StreamExt;
 use rand::thread_rng;
 use tracing::info;
 
 use crate::db::{FederationConfig, FederationIdKey, FederationIdKeyPrefix};
+use crate::fees::FeeMultiplier;
 use crate::lnrpc_client::ILnRpcClient;
 use crate::state_machine::GatewayClientGen;
 use crate::{FederationToClientMap, GatewayError, Result, ScidToFederationMap};
@@ -49,6 +52,7 @@ impl GatewayClientBuilder {
         lnrpc: Arc<dyn ILnRpcClient>,
         all_clients: FederationToClientMap,
         all_scids: ScidToFederationMap,
+        fee_multiplier: FeeMultiplier,
         old_client: Option<fedimint_client::ClientArc>,
         gateway_db: Database,
     ) -> Result<fedimint_client::ClientArc> {
@@ -68,6 +72,7 @@ impl GatewayClientBuilder {
             node_pub_key,
             lightning_alias,
             fees,
+            fee_multiplier,
             timelock_delta,
             mint_channel_id,
             gateway_db,
@@ -80,25 +85,61 @@ impl GatewayClientBuilder {
             client_builder.with_old_client_database(old_client);
         } else {
             let db_path = self.work_dir.join(format!("{federation_id}.db"));
-            {
-                let rocksdb = fedimint_rocksdb::RocksDb::open(db_path.clone()).map_err(|e| {
-                    GatewayError::DatabaseError(anyhow::anyhow!("Error opening rocksdb: {e:?}"))
-                })?;
-
-                // Initialize a client database to check if a config was previously saved in it
-                let db = Database::new(rocksdb, ModuleDecoderRegistry::default());
-                if (get_config_from_db(&db).await).is_none() {
-                    client_builder
-                        .with_federation_info(FederationInfo::from_invite_code(invite_code).await?);
-                }
-            }
+            Self::open_database(&mut client_builder, db_path, invite_code).await?;
+        }
+
+        Self::build_client(client_builder).await
+    }
+
+    /// Builds a regular user client for the federation that the gateway uses as
+    /// counterparty when rebalancing its float, see [`crate::float`]
+    ///
+    /// Unlike the gateway client it can receive and send lightning payments
+    /// through the other gateways registered with the federation.
+    pub async fn build_float_client(
+        &self,
+        invite_code: InviteCode,
+    ) -> Result<fedimint_client::ClientArc> {
+        let mut registry = self.registry.clone();
+        registry.attach(LightningClientGen);
+
+        let mut client_builder = ClientBuilder::default();
+        client_builder.with_module_inits(registry);
+        client_builder.with_primary_module(self.primary_module);
 
+        let db_path = self.work_dir.join(format!("{}-float.db", invite_code.id));
+        Self::open_database(&mut client_builder, db_path, invite_code).await?;
+
+        Self::build_client(client_builder).await
+    }
+
+    async fn open_database(
+        client_builder: &mut ClientBuilder,
+        db_path: PathBuf,
+        invite_code: InviteCode,
+    ) -> Result<()> {
+        {
             let rocksdb = fedimint_rocksdb::RocksDb::open(db_path.clone()).map_err(|e| {
                 GatewayError::DatabaseError(anyhow::anyhow!("Error opening rocksdb: {e:?}"))
             })?;
-            client_builder.with_raw_database(rocksdb);
+
+            // Initialize a client database to check if a config was previously saved in it
+            let db = Database::new(rocksdb, ModuleDecoderRegistry::default());
+            if (get_config_from_db(&db).await).is_none() {
+                client_builder
+                    .with_federation_info(FederationInfo::from_invite_code(invite_code).await?);
+            }
         }
 
+        let rocksdb = fedimint_rocksdb::RocksDb::open(db_path).map_err(|e| {
+            GatewayError::DatabaseError(anyhow::anyhow!("Error opening rocksdb: {e:?}"))
+        })?;
+        client_builder.with_raw_database(rocksdb);
+
+        Ok(())
+    }
+
+    async fn build_client(mut client_builder: ClientBuilder) -> Result<fedimint_client::ClientArc> {
         let client_secret = match client_builder
             .load_decodable_client_secret::<[u8; 64]>()
             .await
@@ -1,13 +1,22 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
 use bitcoin::Network;
 use bitcoin_hashes::sha256;
 use fedimint_core::api::InviteCode;
 use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
 use fedimint_ln_common::serde_routing_fees;
 use lightning_invoice::RoutingFees;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
+use tracing::warn;
+
+use crate::fees::FeePolicy;
+use crate::float::FloatPolicy;
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -17,6 +26,12 @@ pub enum DbKeyPrefix {
     GatewayPublicKey = 0x06,
     GatewayConfiguration = 0x07,
     PreimageAuthentication = 0x08,
+    FloatPolicy = 0x09,
+    FederationNode = 0x0A,
+    FederationEarnings = 0x0B,
+    FeePolicy = 0x0C,
+    PaymentLog = 0x0D,
+    PaymentStats = 0x0E,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -88,3 +103,288 @@ impl_db_record!(
     value = sha256::Hash,
     db_prefix = DbKeyPrefix::PreimageAuthentication
 );
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FloatPolicyKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FloatPolicyKeyPrefix;
+
+impl_db_record!(
+    key = FloatPolicyKey,
+    value = FloatPolicy,
+    db_prefix = DbKeyPrefix::FloatPolicy,
+);
+
+impl_db_lookup!(key = FloatPolicyKey, query_prefix = FloatPolicyKeyPrefix);
+
+/// The name of the lightning node serving the federation, see
+/// [`crate::nodes`]
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FederationNodeKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationNodeKeyPrefix;
+
+impl_db_record!(
+    key = FederationNodeKey,
+    value = String,
+    db_prefix = DbKeyPrefix::FederationNode,
+);
+
+impl_db_lookup!(
+    key = FederationNodeKey,
+    query_prefix = FederationNodeKeyPrefix
+);
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct FeePolicyKey;
+
+impl_db_record!(
+    key = FeePolicyKey,
+    value = FeePolicy,
+    db_prefix = DbKeyPrefix::FeePolicy,
+);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FederationEarningsKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationEarningsKeyPrefix;
+
+/// The payments the gateway routed for a federation and the fees it earned
+/// doing so
+#[derive(Debug, Clone, Default, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct FederationEarnings {
+    /// Number of intercepted HTLCs the federation bought the preimage for
+    pub payments_received: u64,
+    /// Routing fees of the intercepted HTLCs the federation bought the
+    /// preimage for
+    pub incoming_fees: Amount,
+    /// Number of invoices paid on behalf of the federation's users
+    pub payments_sent: u64,
+    /// Fees charged on top of the invoices paid on behalf of the federation's
+    /// users, before the fees of routing the payments over lightning
+    pub outgoing_fees: Amount,
+}
+
+impl FederationEarnings {
+    pub fn total_fees(&self) -> Amount {
+        self.incoming_fees + self.outgoing_fees
+    }
+}
+
+impl_db_record!(
+    key = FederationEarningsKey,
+    value = FederationEarnings,
+    db_prefix = DbKeyPrefix::FederationEarnings,
+);
+
+impl_db_lookup!(
+    key = FederationEarningsKey,
+    query_prefix = FederationEarningsKeyPrefix
+);
+
+/// Adds the payments and fees to the earnings of the federation
+pub async fn record_federation_earnings(
+    gateway_db: &Database,
+    federation_id: FederationId,
+    earnings: FederationEarnings,
+) {
+    let result = gateway_db
+        .autocommit(
+            |dbtx| {
+                let earnings = earnings.clone();
+                Box::pin(async move {
+                    let key = FederationEarningsKey { id: federation_id };
+                    let mut total = dbtx.get_value(&key).await.unwrap_or_default();
+                    total.payments_received += earnings.payments_received;
+                    total.incoming_fees += earnings.incoming_fees;
+                    total.payments_sent += earnings.payments_sent;
+                    total.outgoing_fees += earnings.outgoing_fees;
+                    dbtx.insert_entry(&key, &total).await;
+                    Ok::<(), anyhow::Error>(())
+                })
+            },
+            None,
+        )
+        .await;
+
+    if let Err(e) = result {
+        warn!(%federation_id, "Failed to record the earnings of the federation: {e:?}");
+    }
+}
+
+/// How the gateway routed a payment
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Hash,
+    Ord,
+    PartialOrd,
+    Encodable,
+    Decodable,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentRoute {
+    /// An invoice paid over lightning on behalf of a federation's user
+    Lightning,
+    /// An invoice of a user of another federation served by the gateway, paid
+    /// by funding their incoming contract directly
+    DirectSwap,
+    /// A transfer to a user of another federation served by the gateway
+    Transfer,
+    /// An intercepted HTLC the federation was asked to buy the preimage for
+    Incoming,
+}
+
+/// Payments are logged in the order they completed
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PaymentLogKey {
+    /// Microseconds since the unix epoch
+    pub timestamp: u64,
+    pub operation_id: OperationId,
+}
+
+impl std::fmt::Display for PaymentLogKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.timestamp, self.operation_id)
+    }
+}
+
+impl FromStr for PaymentLogKey {
+    type Err = anyhow::Error;
+
+    /// Parses `<timestamp>:<operation_id>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (timestamp, operation_id) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected <timestamp>:<operation_id>, got {s}"))?;
+
+        Ok(PaymentLogKey {
+            timestamp: timestamp.parse()?,
+            operation_id: operation_id.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PaymentLogKeyPrefix;
+
+/// The outcome of a payment the gateway routed for a federation
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PaymentLogEntry {
+    pub federation_id: FederationId,
+    pub route: PaymentRoute,
+    /// The amount paid to the recipient
+    pub amount: Amount,
+    /// The fee the gateway earned, zero if the payment failed
+    pub fee: Amount,
+    /// Why the payment failed, `None` if it succeeded
+    pub error: Option<String>,
+}
+
+impl_db_record!(
+    key = PaymentLogKey,
+    value = PaymentLogEntry,
+    db_prefix = DbKeyPrefix::PaymentLog,
+);
+
+impl_db_lookup!(key = PaymentLogKey, query_prefix = PaymentLogKeyPrefix);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct PaymentStatsKey {
+    pub federation_id: FederationId,
+    pub route: PaymentRoute,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PaymentStatsKeyPrefix;
+
+/// How many of the payments over a route succeeded, kept alongside the
+/// payment log so the stats do not require scanning it
+#[derive(Debug, Clone, Default, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PaymentStats {
+    pub succeeded: u64,
+    pub failed: u64,
+    /// The fees earned by the payments that succeeded
+    pub fees: Amount,
+}
+
+impl PaymentStats {
+    /// The share of the payments that succeeded, `None` if there were none
+    pub fn success_rate(&self) -> Option<f64> {
+        match self.succeeded + self.failed {
+            0 => None,
+            total => Some(self.succeeded as f64 / total as f64),
+        }
+    }
+}
+
+impl_db_record!(
+    key = PaymentStatsKey,
+    value = PaymentStats,
+    db_prefix = DbKeyPrefix::PaymentStats,
+);
+
+impl_db_lookup!(key = PaymentStatsKey, query_prefix = PaymentStatsKeyPrefix);
+
+/// Logs the outcome of a payment and adds it to the stats of its route
+pub async fn record_payment(
+    gateway_db: &Database,
+    operation_id: OperationId,
+    entry: PaymentLogEntry,
+) {
+    let timestamp = fedimint_core::time::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time is after the unix epoch")
+        .as_micros() as u64;
+    let federation_id = entry.federation_id;
+
+    let result = gateway_db
+        .autocommit(
+            |dbtx| {
+                let entry = entry.clone();
+                Box::pin(async move {
+                    let key = PaymentStatsKey {
+                        federation_id: entry.federation_id,
+                        route: entry.route,
+                    };
+                    let mut stats = dbtx.get_value(&key).await.unwrap_or_default();
+                    if entry.error.is_none() {
+                        stats.succeeded += 1;
+                        stats.fees += entry.fee;
+                    } else {
+                        stats.failed += 1;
+                    }
+                    dbtx.insert_entry(&key, &stats).await;
+                    dbtx.insert_entry(
+                        &PaymentLogKey {
+                            timestamp,
+                            operation_id,
+                        },
+                        &entry,
+                    )
+                    .await;
+                    Ok::<(), anyhow::Error>(())
+                })
+            },
+            None,
+        )
+        .await;
+
+    if let Err(e) = result {
+        warn!(%federation_id, "Failed to record the payment: {e:?}");
+    }
+}
@@ -0,0 +1,181 @@
+//! Dynamic routing fees
+//!
+//! Every federation is charged its own routing fees, which are the gateway-wide
+//! `routing_fees` unless fees were set for the federation. A [`FeePolicy`]
+//! raises the fees of all federations while the gateway is busy: the gateway
+//! counts the payments it routed within the last [`LOAD_WINDOW`] and scales the
+//! fees by the multiplier of the highest [`PeakLoadMultiplier`] whose number
+//! of payments was reached. Whenever the multiplier changes, the gateway
+//! announces the new fees to all federations, so clients comparing the
+//! gateways registered with their federation see the fees currently charged.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, ensure};
+use fedimint_core::encoding::{Decodable, Encodable};
+use lightning_invoice::RoutingFees;
+use serde::{Deserialize, Serialize};
+
+/// How long a routed payment counts towards the load of the gateway
+pub const LOAD_WINDOW: Duration = Duration::from_secs(600);
+
+/// How often the gateway checks whether the fee multiplier changed
+pub const LOAD_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Multiplier of the fees in percent while the gateway is not busy
+pub const BASE_MULTIPLIER_PERCENT: u64 = 100;
+
+/// The multiplier in percent the routing fees are currently scaled by, shared
+/// with the gateway clients that announce the fees to their federation
+pub type FeeMultiplier = Arc<AtomicU64>;
+
+/// Raises the routing fees while the gateway is busy
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize,
+)]
+pub struct FeePolicy {
+    pub peak_load_multipliers: Vec<PeakLoadMultiplier>,
+}
+
+/// Multiplier of the routing fees once the gateway routed the number of
+/// payments within the [`LOAD_WINDOW`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PeakLoadMultiplier {
+    pub min_payments: u64,
+    /// Multiplier of the fees in percent, at least [`BASE_MULTIPLIER_PERCENT`]
+    pub multiplier_percent: u64,
+}
+
+impl FromStr for PeakLoadMultiplier {
+    type Err = anyhow::Error;
+
+    /// Parses `<min_payments>:<multiplier_percent>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min_payments, multiplier_percent) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected <min_payments>:<multiplier_percent>, got {s}"))?;
+
+        Ok(PeakLoadMultiplier {
+            min_payments: min_payments.parse()?,
+            multiplier_percent: multiplier_percent.parse()?,
+        })
+    }
+}
+
+impl FeePolicy {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for multiplier in &self.peak_load_multipliers {
+            ensure!(
+                BASE_MULTIPLIER_PERCENT <= multiplier.multiplier_percent,
+                "Peak load multiplier of {}% would lower the fees",
+                multiplier.multiplier_percent
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The multiplier in percent of the fees after routing the number of
+    /// payments within the [`LOAD_WINDOW`]
+    pub fn multiplier_percent(&self, payments: u64) -> u64 {
+        self.peak_load_multipliers
+            .iter()
+            .filter(|multiplier| multiplier.min_payments <= payments)
+            .map(|multiplier| multiplier.multiplier_percent)
+            .max()
+            .unwrap_or(BASE_MULTIPLIER_PERCENT)
+            .max(BASE_MULTIPLIER_PERCENT)
+    }
+}
+
+/// Scales both the base and the proportional fee by the multiplier
+pub fn scale_fees(fees: RoutingFees, multiplier_percent: u64) -> RoutingFees {
+    let scale = |fee: u32| {
+        (u64::from(fee) * multiplier_percent / BASE_MULTIPLIER_PERCENT)
+            .try_into()
+            .unwrap_or(u32::MAX)
+    };
+
+    RoutingFees {
+        base_msat: scale(fees.base_msat),
+        proportional_millionths: scale(fees.proportional_millionths),
+    }
+}
+
+/// The payments the gateway routed within the [`LOAD_WINDOW`]
+#[derive(Debug, Clone, Default)]
+pub struct PaymentLoad {
+    payments: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl PaymentLoad {
+    pub fn record_payment(&self) {
+        let mut payments = self.payments.lock().expect("Lock is not poisoned");
+        payments.push_back(Instant::now());
+    }
+
+    /// The number of payments routed within the [`LOAD_WINDOW`]
+    pub fn payments(&self) -> u64 {
+        let mut payments = self.payments.lock().expect("Lock is not poisoned");
+        while payments
+            .front()
+            .map_or(false, |routed| LOAD_WINDOW < routed.elapsed())
+        {
+            payments.pop_front();
+        }
+
+        payments.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use lightning_invoice::RoutingFees;
+
+    use super::{scale_fees, FeePolicy, PeakLoadMultiplier, BASE_MULTIPLIER_PERCENT};
+
+    #[test]
+    fn highest_reached_multiplier_applies() {
+        let policy = FeePolicy {
+            peak_load_multipliers: vec![
+                PeakLoadMultiplier::from_str("100:150").unwrap(),
+                PeakLoadMultiplier::from_str("500:300").unwrap(),
+            ],
+        };
+        assert!(policy.validate().is_ok());
+
+        assert_eq!(policy.multiplier_percent(0), BASE_MULTIPLIER_PERCENT);
+        assert_eq!(policy.multiplier_percent(99), BASE_MULTIPLIER_PERCENT);
+        assert_eq!(policy.multiplier_percent(100), 150);
+        assert_eq!(policy.multiplier_percent(1_000), 300);
+        assert_eq!(
+            FeePolicy::default().multiplier_percent(1_000),
+            BASE_MULTIPLIER_PERCENT
+        );
+
+        let fees = RoutingFees {
+            base_msat: 1_000,
+            proportional_millionths: 2_000,
+        };
+        assert_eq!(scale_fees(fees, BASE_MULTIPLIER_PERCENT), fees);
+        assert_eq!(
+            scale_fees(fees, 150),
+            RoutingFees {
+                base_msat: 1_500,
+                proportional_millionths: 3_000,
+            }
+        );
+
+        let lowering = FeePolicy {
+            peak_load_multipliers: vec![PeakLoadMultiplier::from_str("10:50").unwrap()],
+        };
+        assert!(lowering.validate().is_err());
+        assert!(PeakLoadMultiplier::from_str("10").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+//! Automatic rebalancing of the gateway's ecash float
+//!
+//! To serve swaps the gateway needs ecash in every federation (to fund
+//! incoming contracts) as well as liquidity in its lightning node (to pay
+//! outgoing invoices). Since swaps tend to flow mostly in one direction, one
+//! of the two runs dry over time. If a [`FloatPolicy`] is set for a
+//! federation the gateway periodically checks its ecash balance and moves
+//! funds between its node and its ecash float by paying itself via lightning:
+//!
+//! * **Refill**: the float client creates an invoice that the gateway's node
+//!   pays, the received ecash is then transferred to the gateway client.
+//! * **Drain**: ecash is transferred from the gateway client to the float
+//!   client, which uses it to pay an invoice created by the gateway's node.
+//!
+//! The float client is a regular user client of the federation. Its lightning
+//! payments are routed through another gateway registered with the federation,
+//! since routing them through ourselves would just move ecash from the gateway
+//! client to the float client and back.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure};
+use fedimint_client::ClientArc;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::Amount;
+use fedimint_ln_client::{LightningClientExt, LnPayState, LnReceiveState, PayType};
+use fedimint_mint_client::{MintClientExt, ReissueExternalNotesState};
+use futures::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::gateway_lnrpc::{CreateInvoiceRequest, PayInvoiceRequest};
+use crate::lnrpc_client::ILnRpcClient;
+
+/// How often the gateway checks its float against the [`FloatPolicy`]
+pub const FLOAT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the invoices used for rebalancing stay valid
+const REBALANCE_INVOICE_EXPIRY: Duration = Duration::from_secs(600);
+
+/// Maximum fee the gateway's node pays when refilling the float, in millionths
+/// of the refilled amount
+const REBALANCE_MAX_FEE_PPM: u64 = 10_000;
+
+/// Maximum CLTV delta the gateway's node accepts when refilling the float
+const REBALANCE_MAX_DELAY: u64 = 1008;
+
+/// How long ecash transferred between the gateway and the float client can be
+/// reissued before the sender tries to cancel the spend
+const NOTE_TRANSFER_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Bounds within which the gateway keeps its ecash balance in a federation
+///
+/// Once the balance leaves the bounds it is rebalanced to the midpoint of
+/// them, so that the next rebalancing is not triggered right away.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct FloatPolicy {
+    pub min_float: Amount,
+    pub max_float: Amount,
+}
+
+/// A rebalancing the gateway needs to perform to satisfy its [`FloatPolicy`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Rebalance {
+    /// Move the amount from the lightning node to the ecash float
+    Refill(Amount),
+    /// Move the amount from the ecash float to the lightning node
+    Drain(Amount),
+}
+
+impl FloatPolicy {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.min_float <= self.max_float,
+            "Minimum float {} exceeds maximum float {}",
+            self.min_float,
+            self.max_float
+        );
+        Ok(())
+    }
+
+    pub fn target_float(&self) -> Amount {
+        Amount::from_msats((self.min_float.msats + self.max_float.msats) / 2)
+    }
+
+    /// Returns the rebalancing needed for an ecash balance of `balance`, if
+    /// any
+    pub fn rebalance(&self, balance: Amount) -> Option<Rebalance> {
+        if balance < self.min_float {
+            Some(Rebalance::Refill(self.target_float() - balance))
+        } else if self.max_float < balance {
+            Some(Rebalance::Drain(balance - self.target_float()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Moves funds between the gateway's lightning node and its ecash float in
+/// one federation
+pub struct FloatRebalancer<'a> {
+    pub gateway_id: secp256k1::PublicKey,
+    pub gateway_client: &'a ClientArc,
+    pub float_client: &'a ClientArc,
+    pub lnrpc: Arc<dyn ILnRpcClient>,
+}
+
+impl<'a> FloatRebalancer<'a> {
+    pub async fn rebalance(&self, rebalance: Rebalance) -> anyhow::Result<()> {
+        self.select_counterparty_gateway().await?;
+
+        match rebalance {
+            Rebalance::Refill(amount) => self.refill(amount).await,
+            Rebalance::Drain(amount) => self.drain(amount).await,
+        }
+    }
+
+    /// Routes the lightning payments of the float client through any other
+    /// gateway registered with the federation
+    async fn select_counterparty_gateway(&self) -> anyhow::Result<()> {
+        let gateway = self
+            .float_client
+            .fetch_registered_gateways()
+            .await?
+            .into_iter()
+            .find(|announcement| announcement.info.gateway_id != self.gateway_id)
+            .ok_or_else(|| anyhow!("No other gateway is registered with the federation"))?;
+
+        self.float_client
+            .set_active_gateway(&gateway.info.gateway_id)
+            .await
+    }
+
+    async fn refill(&self, amount: Amount) -> anyhow::Result<()> {
+        let (operation_id, invoice) = self
+            .float_client
+            .create_bolt11_invoice(
+                amount,
+                "Gateway float refill".to_string(),
+                Some(REBALANCE_INVOICE_EXPIRY.as_secs()),
+                (),
+            )
+            .await?;
+
+        self.lnrpc
+            .pay(PayInvoiceRequest {
+                invoice: invoice.to_string(),
+                max_delay: REBALANCE_MAX_DELAY,
+                max_fee_msat: amount.msats * REBALANCE_MAX_FEE_PPM / 1_000_000,
+                payment_hash: invoice.payment_hash().to_vec(),
+            })
+            .await?;
+
+        let mut updates = self
+            .float_client
+            .subscribe_ln_receive(operation_id)
+            .await?
+            .into_stream();
+
+        loop {
+            match updates.next().await {
+                Some(LnReceiveState::Claimed) => break,
+                Some(LnReceiveState::Canceled { reason }) => {
+                    bail!("Receiving the refill failed: {reason}")
+                }
+                Some(_) => {}
+                None => bail!("Ran out of state updates while receiving the refill"),
+            }
+        }
+
+        // Also transfers any leftovers of previously failed rebalancings
+        let float_balance = self.float_client.get_balance().await;
+        transfer_notes(self.float_client, self.gateway_client, float_balance).await?;
+
+        info!(%amount, "Refilled gateway float");
+        Ok(())
+    }
+
+    async fn drain(&self, amount: Amount) -> anyhow::Result<()> {
+        transfer_notes(self.gateway_client, self.float_client, amount).await?;
+
+        // The counterparty gateway charges its routing fees on top of the invoice
+        // amount, so we leave room for them
+        let gateway = self.float_client.select_active_gateway().await?;
+        let float_balance = self.float_client.get_balance().await;
+        let fee_msats = u64::from(gateway.fees.base_msat)
+            + float_balance.msats * u64::from(gateway.fees.proportional_millionths) / 1_000_000;
+        let invoice_amount = float_balance
+            .msats
+            .checked_sub(fee_msats)
+            .filter(|msats| *msats > 0)
+            .ok_or_else(|| anyhow!("Float balance {float_balance} does not cover the fees"))?;
+
+        let invoice: Bolt11Invoice = self
+            .lnrpc
+            .create_invoice(CreateInvoiceRequest {
+                amount_msat: invoice_amount,
+                description: "Gateway float drain".to_string(),
+                expiry_secs: REBALANCE_INVOICE_EXPIRY.as_secs(),
+            })
+            .await?
+            .invoice
+            .parse()?;
+
+        let payment = self.float_client.pay_bolt11_invoice(invoice).await?;
+        let PayType::Lightning(operation_id) = payment.payment_type else {
+            bail!("Invoice of our own node was unexpectedly paid internally");
+        };
+
+        let mut updates = self
+            .float_client
+            .subscribe_ln_pay(operation_id)
+            .await?
+            .into_stream();
+
+        while let Some(update) = updates.next().await {
+            match update {
+                LnPayState::Success { .. } => {
+                    info!(%amount, "Drained gateway float");
+                    return Ok(());
+                }
+                LnPayState::Canceled | LnPayState::Refunded { .. } => {
+                    bail!("Paying the drain invoice failed")
+                }
+                LnPayState::UnexpectedError { error_message } => {
+                    bail!("Paying the drain invoice failed: {error_message}")
+                }
+                _ => {}
+            }
+        }
+
+        bail!("Ran out of state updates while draining the float")
+    }
+}
+
+/// Transfers ecash notes of at least `amount` between two clients of the same
+/// federation
+async fn transfer_notes(from: &ClientArc, to: &ClientArc, amount: Amount) -> anyhow::Result<()> {
+    if amount == Amount::ZERO {
+        return Ok(());
+    }
+
+    let (_, notes) = from.spend_notes(amount, NOTE_TRANSFER_TIMEOUT, ()).await?;
+    let operation_id = to.reissue_external_notes(notes, ()).await?;
+
+    let mut updates = to
+        .subscribe_reissue_external_notes(operation_id)
+        .await?
+        .into_stream();
+
+    while let Some(update) = updates.next().await {
+        match update {
+            ReissueExternalNotesState::Done => return Ok(()),
+            ReissueExternalNotesState::Failed(error) => {
+                bail!("Reissuing transferred notes failed: {error}")
+            }
+            _ => {}
+        }
+    }
+
+    bail!("Ran out of state updates while reissuing transferred notes")
+}
+
+/// Checks the float of the gateway in a federation against `policy` and
+/// rebalances it if needed
+pub async fn check_float(
+    gateway_id: secp256k1::PublicKey,
+    gateway_client: &ClientArc,
+    float_client: &ClientArc,
+    lnrpc: Arc<dyn ILnRpcClient>,
+    policy: FloatPolicy,
+) {
+    let balance = gateway_client.get_balance().await;
+    let Some(rebalance) = policy.rebalance(balance) else {
+        return;
+    };
+
+    info!(%balance, ?rebalance, "Gateway float is out of bounds, rebalancing");
+
+    let rebalancer = FloatRebalancer {
+        gateway_id,
+        gateway_client,
+        float_client,
+        lnrpc,
+    };
+
+    if let Err(error) = rebalancer.rebalance(rebalance).await {
+        warn!(%balance, ?rebalance, "Failed to rebalance gateway float: {error:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use super::{FloatPolicy, Rebalance};
+
+    #[test]
+    fn rebalance_to_target_float() {
+        let policy = FloatPolicy {
+            min_float: Amount::from_sats(1_000),
+            max_float: Amount::from_sats(3_000),
+        };
+        assert!(policy.validate().is_ok());
+        assert_eq!(policy.target_float(), Amount::from_sats(2_000));
+
+        assert_eq!(
+            policy.rebalance(Amount::from_sats(500)),
+            Some(Rebalance::Refill(Amount::from_sats(1_500)))
+        );
+        assert_eq!(policy.rebalance(Amount::from_sats(1_000)), None);
+        assert_eq!(policy.rebalance(Amount::from_sats(3_000)), None);
+        assert_eq!(
+            policy.rebalance(Amount::from_sats(4_000)),
+            Some(Rebalance::Drain(Amount::from_sats(2_000)))
+        );
+
+        let invalid = FloatPolicy {
+            min_float: Amount::from_sats(2),
+            max_float: Amount::from_sats(1),
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -1,7 +1,10 @@
 pub mod client;
 pub mod db;
+pub mod fees;
+pub mod float;
 pub mod lnd;
 pub mod lnrpc_client;
+pub mod nodes;
 pub mod rpc;
 pub mod state_machine;
 pub mod types;
@@ -45,12 +48,14 @@ use fedimint_core::time::now;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::{push_db_pair_items, Amount};
 use fedimint_ln_client::pay::PayInvoicePayload;
+use fedimint_ln_client::transfer::TransferPayload;
 use fedimint_ln_common::config::{GatewayFee, LightningClientConfig};
 use fedimint_ln_common::contracts::Preimage;
 use fedimint_ln_common::route_hints::RouteHint;
 use fedimint_ln_common::LightningCommonGen;
 use fedimint_mint_client::{MintClientGen, MintCommonGen};
 use fedimint_wallet_client::{WalletClientExt, WalletClientGen, WalletCommonGen, WithdrawState};
+use futures::future;
 use futures::stream::StreamExt;
 use gateway_lnrpc::intercept_htlc_response::Action;
 use gateway_lnrpc::{GetNodeInfoResponse, InterceptHtlcResponse};
@@ -61,21 +66,35 @@ use rpc::{FederationInfo, SetConfigurationPayload};
 use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 use state_machine::pay::OutgoingPaymentError;
+use state_machine::transfer::TransferError;
 use state_machine::GatewayClientExt;
 use strum::IntoEnumIterator;
 use thiserror::Error;
 use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
 
-use crate::db::{FederationConfig, FederationIdKeyPrefix};
+use crate::db::{
+    FederationConfig, FederationEarnings, FederationEarningsKey, FederationEarningsKeyPrefix,
+    FederationIdKey, FederationIdKeyPrefix, FederationNodeKey, FederationNodeKeyPrefix,
+    FeePolicyKey, FloatPolicyKey, FloatPolicyKeyPrefix, PaymentLogEntry, PaymentLogKey,
+    PaymentLogKeyPrefix, PaymentStats, PaymentStatsKey, PaymentStatsKeyPrefix,
+};
+use crate::fees::{
+    scale_fees, FeeMultiplier, PaymentLoad, BASE_MULTIPLIER_PERCENT, LOAD_CHECK_INTERVAL,
+};
+use crate::float::{FloatPolicy, FLOAT_CHECK_INTERVAL};
 use crate::gateway_lnrpc::intercept_htlc_response::Forward;
 use crate::lnrpc_client::GatewayLightningBuilder;
+use crate::nodes::{least_loaded_node, LightningNode, NODE_HEALTH_CHECK_INTERVAL, PRIMARY_NODE};
 use crate::rpc::rpc_server::run_webserver;
 use crate::rpc::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, GatewayInfo,
-    InfoPayload, RestorePayload, WithdrawPayload,
+    BackupPayload, BalancePayload, ChannelInfo, ChannelsPayload, ConnectFedPayload,
+    DepositAddressPayload, FederationLiquidity, FederationsPayload, FeeQuote, FeeQuotePayload,
+    GatewayInfo, InfoPayload, NodeChannels, PaymentHistory, PaymentHistoryPayload, PaymentRecord,
+    PaymentStatsPayload, RestorePayload, RouteStats, SetFederationFeesPayload, SetFeePolicyPayload,
+    SetFloatPolicyPayload, WithdrawPayload,
 };
-use crate::state_machine::GatewayExtPayStates;
+use crate::state_machine::{GatewayExtPayStates, GatewayExtTransferStates};
 
 /// LND HTLC interceptor can't handle SCID of 0, so start from 1
 pub const INITIAL_SCID: u64 = 1;
@@ -138,6 +157,11 @@ pub struct GatewayOpts {
     /// Number of route hints to return in invoices
     #[arg(long = "num-route-hints", env = "FM_NUMBER_OF_ROUTE_HINTS")]
     pub num_route_hints: Option<u32>,
+
+    /// JSON file naming additional lightning nodes to serve federations
+    /// through, see [`nodes`]
+    #[arg(long = "lightning-nodes", env = "FM_GATEWAY_LIGHTNING_NODES")]
+    pub lightning_nodes: Option<PathBuf>,
 }
 
 impl GatewayOpts {
@@ -243,6 +267,26 @@ pub struct Gateway {
     // ID generator that atomically increments. Used for creation of new short channel ids that
     // represent federations.
     channel_id_generator: Arc<Mutex<AtomicU64>>,
+
+    // Map of `FederationId` -> float `Client`. Used as counterparty when rebalancing the ecash
+    // float of the gateway, built lazily once a float policy is set for a federation.
+    float_clients: FederationToClientMap,
+
+    // Builders of the additional lightning nodes by name, the node built by `lightning_builder`
+    // is the `PRIMARY_NODE`.
+    node_builders: BTreeMap<String, Arc<dyn LightningBuilder + Send + Sync>>,
+
+    // Map of names to the lightning nodes the gateway is currently connected to.
+    lightning_nodes: Arc<RwLock<BTreeMap<String, LightningNode>>>,
+
+    // Map of `FederationId` -> name of the lightning node serving the federation's swaps.
+    federation_nodes: Arc<RwLock<BTreeMap<FederationId, String>>>,
+
+    // The multiplier in percent the routing fees are currently scaled by, see `fees`.
+    fee_multiplier: FeeMultiplier,
+
+    // The payments routed recently, from which the fee multiplier is derived.
+    payment_load: PaymentLoad,
 }
 
 impl Gateway {
@@ -275,6 +319,12 @@ impl Gateway {
             scid_to_federation: Arc::new(RwLock::new(BTreeMap::new())),
             gateway_id: Gateway::get_gateway_id(gateway_db).await,
             channel_id_generator: Arc::new(Mutex::new(AtomicU64::new(INITIAL_SCID))),
+            float_clients: Arc::new(RwLock::new(BTreeMap::new())),
+            node_builders: BTreeMap::new(),
+            lightning_nodes: Arc::new(RwLock::new(BTreeMap::new())),
+            federation_nodes: Arc::new(RwLock::new(BTreeMap::new())),
+            fee_multiplier: Arc::new(AtomicU64::new(BASE_MULTIPLIER_PERCENT)),
+            payment_load: PaymentLoad::default(),
         })
     }
 
@@ -293,7 +343,7 @@ impl Gateway {
         // Gateway module will be attached when the federation clients are created
         // because the LN RPC will be injected with `GatewayClientGen`.
         let mut registry = ClientModuleInitRegistry::new();
-        registry.attach(MintClientGen);
+        registry.attach(MintClientGen::default());
         registry.attach(WalletClientGen::default());
 
         let decoders = registry.available_decoders(DEFAULT_MODULE_KINDS.iter().cloned())?;
@@ -309,6 +359,18 @@ impl Gateway {
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
         );
 
+        let node_builders = match &opts.lightning_nodes {
+            Some(path) => nodes::read_lightning_nodes(path)?
+                .into_iter()
+                .map(|(name, lightning_mode)| {
+                    let builder: Arc<dyn LightningBuilder + Send + Sync> =
+                        Arc::new(GatewayLightningBuilder { lightning_mode });
+                    (name, builder)
+                })
+                .collect(),
+            None => BTreeMap::new(),
+        };
+
         info!(
             "Starting gatewayd (version: {})",
             env!("FEDIMINT_BUILD_CODE_VERSION")
@@ -326,6 +388,12 @@ impl Gateway {
             gateway_db,
             clients: Arc::new(RwLock::new(BTreeMap::new())),
             scid_to_federation: Arc::new(RwLock::new(BTreeMap::new())),
+            float_clients: Arc::new(RwLock::new(BTreeMap::new())),
+            node_builders,
+            lightning_nodes: Arc::new(RwLock::new(BTreeMap::new())),
+            federation_nodes: Arc::new(RwLock::new(BTreeMap::new())),
+            fee_multiplier: Arc::new(AtomicU64::new(BASE_MULTIPLIER_PERCENT)),
+            payment_load: PaymentLoad::default(),
         })
     }
 
@@ -373,6 +441,61 @@ impl Gateway {
                         );
                     }
                 }
+                DbKeyPrefix::FloatPolicy => {
+                    push_db_pair_items!(
+                        dbtx,
+                        FloatPolicyKeyPrefix,
+                        FloatPolicyKey,
+                        FloatPolicy,
+                        gateway_items,
+                        "Float Policies"
+                    );
+                }
+                DbKeyPrefix::FederationNode => {
+                    push_db_pair_items!(
+                        dbtx,
+                        FederationNodeKeyPrefix,
+                        FederationNodeKey,
+                        String,
+                        gateway_items,
+                        "Federation Lightning Nodes"
+                    );
+                }
+                DbKeyPrefix::FederationEarnings => {
+                    push_db_pair_items!(
+                        dbtx,
+                        FederationEarningsKeyPrefix,
+                        FederationEarningsKey,
+                        FederationEarnings,
+                        gateway_items,
+                        "Federation Earnings"
+                    );
+                }
+                DbKeyPrefix::PaymentLog => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PaymentLogKeyPrefix,
+                        PaymentLogKey,
+                        PaymentLogEntry,
+                        gateway_items,
+                        "Payment Log"
+                    );
+                }
+                DbKeyPrefix::PaymentStats => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PaymentStatsKeyPrefix,
+                        PaymentStatsKey,
+                        PaymentStats,
+                        gateway_items,
+                        "Payment Stats"
+                    );
+                }
+                DbKeyPrefix::FeePolicy => {
+                    if let Some(fee_policy) = dbtx.get_value(&FeePolicyKey).await {
+                        gateway_items.insert("Fee Policy".to_string(), Box::new(fee_policy));
+                    }
+                }
                 DbKeyPrefix::GatewayPublicKey => {
                     if let Some(public_key) = dbtx.get_value(&GatewayPublicKey).await {
                         gateway_items
@@ -481,14 +604,24 @@ impl Gateway {
                                             }
                                         }
 
-                                        self.register_clients_timer(&mut htlc_task_group).await;
-                                        self.load_clients(
+                                        let primary_node = LightningNode::new(
+                                            PRIMARY_NODE.to_string(),
                                             ln_client.clone(),
                                             lightning_public_key,
                                             lightning_alias.clone()
-                                        )
-                                        .await
-                                        .expect("Failed to load gateway clients");
+                                        );
+                                        self.lightning_nodes
+                                            .write()
+                                            .await
+                                            .insert(PRIMARY_NODE.to_string(), primary_node.clone());
+
+                                        self.register_clients_timer(&mut htlc_task_group).await;
+                                        self.rebalance_float_timer(&mut htlc_task_group).await;
+                                        self.node_health_timer(&mut htlc_task_group).await;
+                                        self.fee_multiplier_timer(&mut htlc_task_group).await;
+                                        self.load_clients(PRIMARY_NODE)
+                                            .await
+                                            .expect("Failed to load gateway clients");
 
                                         info!("Successfully loaded Gateway clients.");
                                         self.set_gateway_state(GatewayState::Running {
@@ -498,9 +631,19 @@ impl Gateway {
                                             lightning_network
                                         }).await;
 
+                                        for (name, builder) in self.node_builders.clone() {
+                                            self.spawn_lightning_node(
+                                                name,
+                                                builder,
+                                                lightning_network,
+                                                &mut htlc_task_group
+                                            )
+                                            .await;
+                                        }
+
                                         // Blocks until the connection to the lightning node breaks or we receive the shutdown signal
                                         tokio::select! {
-                                            _ = self.handle_htlc_stream(stream, handle.clone()) => {
+                                            _ = self.handle_htlc_stream(stream, &primary_node, handle.clone()) => {
                                                 warn!("HTLC Stream Lightning connection broken. Gateway is disconnected");
                                             },
                                             _ = handle.make_shutdown_rx().await => {
@@ -537,12 +680,101 @@ impl Gateway {
         if let Err(e) = htlc_task_group.shutdown_join_all(None).await {
             error!("HTLC task group shutdown errors: {}", e);
         }
+        self.lightning_nodes.write().await.clear();
     }
 
-    pub async fn handle_htlc_stream(&self, mut stream: RouteHtlcStream<'_>, handle: TaskHandle) {
-        let GatewayState::Running { lnrpc, .. } = self.state.read().await.clone() else {
-            panic!("Gateway isn't in a running state")
-        };
+    /// Spawns a task that connects to an additional lightning node, loads the
+    /// clients of the federations it serves and handles its HTLC stream,
+    /// reconnecting whenever the connection breaks
+    async fn spawn_lightning_node(
+        &self,
+        name: String,
+        builder: Arc<dyn LightningBuilder + Send + Sync>,
+        network: Network,
+        task_group: &mut TaskGroup,
+    ) {
+        let gateway = self.clone();
+        let tg = task_group.clone();
+        task_group
+            .spawn(format!("lightning node {name}"), move |handle| async move {
+                while !handle.is_shutting_down() {
+                    let mut node_task_group = tg.make_subgroup().await;
+
+                    match gateway
+                        .connect_lightning_node(
+                            &name,
+                            builder.as_ref(),
+                            network,
+                            &mut node_task_group,
+                        )
+                        .await
+                    {
+                        Ok((node, stream)) => {
+                            info!(node = %name, "Connected to lightning node");
+                            gateway
+                                .lightning_nodes
+                                .write()
+                                .await
+                                .insert(name.clone(), node.clone());
+
+                            if let Err(e) = gateway.load_clients(&name).await {
+                                warn!(node = %name, "Failed to load gateway clients: {e:?}");
+                            }
+
+                            tokio::select! {
+                                _ = gateway.handle_htlc_stream(stream, &node, handle.clone()) => {
+                                    warn!(node = %name, "HTLC stream of lightning node broke");
+                                }
+                                _ = handle.make_shutdown_rx().await => {}
+                            }
+
+                            node.set_healthy(false);
+                        }
+                        Err(e) => {
+                            warn!(node = %name, "Failed to connect to lightning node: {e:?}");
+                        }
+                    }
+
+                    if let Err(e) = node_task_group.shutdown_join_all(None).await {
+                        error!(node = %name, "HTLC task group shutdown errors: {e}");
+                    }
+
+                    if !handle.is_shutting_down() {
+                        sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            })
+            .await;
+    }
+
+    async fn connect_lightning_node(
+        &self,
+        name: &str,
+        builder: &(dyn LightningBuilder + Send + Sync),
+        network: Network,
+        task_group: &mut TaskGroup,
+    ) -> Result<(LightningNode, RouteHtlcStream<'static>)> {
+        let (stream, lnrpc) = builder.build().await.route_htlcs(task_group).await?;
+        let (public_key, alias, node_network) = fetch_lightning_node_info(lnrpc.clone()).await?;
+
+        // Federations only support a single network, so all nodes have to share it
+        if node_network != network {
+            return Err(GatewayError::UnsupportedNetwork(node_network));
+        }
+
+        Ok((
+            LightningNode::new(name.to_string(), lnrpc, public_key, alias),
+            stream,
+        ))
+    }
+
+    /// Handles the HTLCs intercepted by the node until its stream ends
+    pub async fn handle_htlc_stream(
+        &self,
+        mut stream: RouteHtlcStream<'_>,
+        node: &LightningNode,
+        handle: TaskHandle,
+    ) {
         loop {
             match stream.next().await {
                 Some(Ok(htlc_request)) => {
@@ -550,7 +782,14 @@ impl Gateway {
                         break;
                     }
                     let scid_to_feds = self.scid_to_federation.read().await;
-                    let federation_id = scid_to_feds.get(&htlc_request.short_channel_id);
+                    let federation_nodes = self.federation_nodes.read().await;
+                    // HTLCs for a federation served by another node are not ours to settle
+                    let federation_id =
+                        scid_to_feds
+                            .get(&htlc_request.short_channel_id)
+                            .filter(|federation_id| {
+                                federation_nodes.get(federation_id) == Some(&node.name)
+                            });
                     // Just forward the HTLC if we do not have a federation that
                     // corresponds to the short channel id
                     if let Some(federation_id) = federation_id {
@@ -562,7 +801,11 @@ impl Gateway {
                             let htlc = htlc_request.clone().try_into();
                             if let Ok(htlc) = htlc {
                                 match client.gateway_handle_intercepted_htlc(htlc).await {
-                                    Ok(_) => continue,
+                                    Ok(_) => {
+                                        node.record_htlc(htlc_request.incoming_amount_msat);
+                                        self.payment_load.record_payment();
+                                        continue;
+                                    }
                                     Err(e) => {
                                         info!("Got error intercepting HTLC: {e:?}, will retry...")
                                     }
@@ -581,7 +824,7 @@ impl Gateway {
                         htlc_id: htlc_request.htlc_id,
                     };
 
-                    if let Err(error) = lnrpc.complete_htlc(outcome).await {
+                    if let Err(error) = node.lnrpc.complete_htlc(outcome).await {
                         error!("Error sending HTLC response to lightning node: {error:?}");
                     }
                 }
@@ -620,6 +863,14 @@ impl Gateway {
             for (federation_id, client) in federation_clients {
                 federations.push(self.make_federation_info(&client, federation_id).await);
             }
+            let federation_counts = self.node_federation_counts().await;
+            let lightning_nodes = self
+                .lightning_nodes
+                .read()
+                .await
+                .values()
+                .map(|node| node.info(federation_counts.get(&node.name).copied().unwrap_or(0)))
+                .collect();
 
             return Ok(GatewayInfo {
                 federations,
@@ -631,6 +882,7 @@ impl Gateway {
                 gateway_id: self.gateway_id,
                 gateway_state: self.state.read().await.to_string(),
                 network: Some(gateway_config.network),
+                lightning_nodes,
             });
         }
 
@@ -644,6 +896,7 @@ impl Gateway {
             gateway_id: self.gateway_id,
             gateway_state: self.state.read().await.to_string(),
             network: None,
+            lightning_nodes: vec![],
         })
     }
 
@@ -700,7 +953,9 @@ impl Gateway {
 
     async fn handle_pay_invoice_msg(&self, payload: PayInvoicePayload) -> Result<Preimage> {
         if let GatewayState::Running { .. } = self.state.read().await.clone() {
-            let client = self.select_client(payload.federation_id).await?;
+            let federation_id = payload.federation_id;
+            let client = self.select_client(federation_id).await?;
+            self.payment_load.record_payment();
             let operation_id = client.gateway_pay_bolt11_invoice(payload).await?;
             let mut updates = client
                 .gateway_subscribe_ln_pay(operation_id)
@@ -709,7 +964,12 @@ impl Gateway {
 
             while let Some(update) = updates.next().await {
                 match update {
-                    GatewayExtPayStates::Success { preimage, .. } => return Ok(preimage),
+                    GatewayExtPayStates::Success { preimage, .. } => {
+                        if let Ok(node) = self.federation_node(federation_id).await {
+                            node.record_payment();
+                        }
+                        return Ok(preimage);
+                    }
                     GatewayExtPayStates::Fail {
                         error,
                         error_message,
@@ -732,20 +992,60 @@ impl Gateway {
         Err(GatewayError::Disconnected)
     }
 
+    async fn handle_transfer_msg(&self, payload: TransferPayload) -> Result<Preimage> {
+        if let GatewayState::Running { .. } = self.state.read().await.clone() {
+            let federation_id = payload.federation_id;
+            let client = self.select_client(federation_id).await?;
+            self.payment_load.record_payment();
+            let operation_id = client.gateway_transfer(payload).await?;
+            let mut updates = client
+                .gateway_subscribe_transfer(operation_id)
+                .await?
+                .into_stream();
+
+            while let Some(update) = updates.next().await {
+                match update {
+                    GatewayExtTransferStates::Success { preimage, .. } => {
+                        return Ok(preimage);
+                    }
+                    GatewayExtTransferStates::Fail {
+                        error,
+                        error_message,
+                    } => {
+                        error!(error_message);
+                        return Err(GatewayError::TransferError(Box::new(error)));
+                    }
+                    GatewayExtTransferStates::Canceled { error } => {
+                        return Err(GatewayError::TransferError(Box::new(error)));
+                    }
+                    GatewayExtTransferStates::ContractDoesNotExist { contract_id } => {
+                        return Err(GatewayError::TransferError(Box::new(
+                            TransferError::ContractDoesNotExist { contract_id },
+                        )));
+                    }
+                    _ => {}
+                };
+            }
+
+            return Err(GatewayError::UnexpectedState(
+                "Ran out of state updates while transferring".to_string(),
+            ));
+        }
+
+        Err(GatewayError::Disconnected)
+    }
+
     async fn handle_connect_federation(
         &mut self,
         payload: ConnectFedPayload,
     ) -> Result<FederationInfo> {
-        if let GatewayState::Running {
-            lnrpc,
-            lightning_public_key,
-            lightning_alias,
-            ..
-        } = self.state.read().await.clone()
-        {
+        if let GatewayState::Running { .. } = self.state.read().await.clone() {
             let invite_code = InviteCode::from_str(&payload.invite_code).map_err(|e| {
                 GatewayError::InvalidMetadata(format!("Invalid federation member string {e:?}"))
             })?;
+            let node = self
+                .select_lightning_node(invite_code.id, payload.lightning_node)
+                .await?;
 
             // `GatewayConfiguration` should always exist in the database when we are in the
             // `Running` state.
@@ -763,17 +1063,27 @@ impl Gateway {
                 .await
                 .fetch_add(1, Ordering::SeqCst);
 
+            // Federations we reconnect to keep the fees configured for them
             let federation_id = invite_code.id;
+            let fees = self
+                .gateway_db
+                .begin_transaction()
+                .await
+                .get_value(&FederationIdKey { id: federation_id })
+                .await
+                .map_or(gateway_config.routing_fees, |config| config.fees);
             let gw_client_cfg = FederationConfig {
                 invite_code,
                 mint_channel_id,
                 timelock_delta: 10,
-                fees: gateway_config.routing_fees,
+                fees,
             };
 
-            let route_hints =
-                Self::fetch_lightning_route_hints(lnrpc.clone(), gateway_config.num_route_hints)
-                    .await?;
+            let route_hints = Self::fetch_lightning_route_hints(
+                node.lnrpc.clone(),
+                gateway_config.num_route_hints,
+            )
+            .await?;
             let old_client = self.clients.read().await.get(&federation_id).cloned();
             let all_clients = self.clients.clone();
             let all_scids = self.scid_to_federation.clone();
@@ -782,11 +1092,12 @@ impl Gateway {
                 .client_builder
                 .build(
                     gw_client_cfg.clone(),
-                    lightning_public_key,
-                    lightning_alias,
-                    lnrpc.clone(),
+                    node.public_key,
+                    node.alias.clone(),
+                    node.lnrpc.clone(),
                     all_clients,
                     all_scids,
+                    self.fee_multiplier.clone(),
                     old_client,
                     self.gateway_db.clone(),
                 )
@@ -810,8 +1121,15 @@ impl Gateway {
                 .write()
                 .await
                 .insert(mint_channel_id, federation_id);
+            self.federation_nodes
+                .write()
+                .await
+                .insert(federation_id, node.name.clone());
+            info!(%federation_id, node = %node.name, "Serving federation through lightning node");
 
-            let dbtx = self.gateway_db.begin_transaction().await;
+            let mut dbtx = self.gateway_db.begin_transaction().await;
+            dbtx.insert_entry(&FederationNodeKey { id: federation_id }, &node.name)
+                .await;
             self.client_builder
                 .save_config(gw_client_cfg.clone(), dbtx)
                 .await?;
@@ -918,6 +1236,306 @@ impl Gateway {
         Ok(())
     }
 
+    pub async fn handle_set_float_policy_msg(
+        &self,
+        SetFloatPolicyPayload {
+            federation_id,
+            policy,
+        }: SetFloatPolicyPayload,
+    ) -> Result<()> {
+        // Make sure we are connected to the federation
+        self.select_client(federation_id).await?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let key = FloatPolicyKey { id: federation_id };
+        if let Some(policy) = policy {
+            policy
+                .validate()
+                .map_err(|e| GatewayError::GatewayConfigurationError(e.to_string()))?;
+            dbtx.insert_entry(&key, &policy).await;
+            info!(%federation_id, ?policy, "Set float policy");
+        } else {
+            dbtx.remove_entry(&key).await;
+            info!(%federation_id, "Removed float policy");
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)
+    }
+
+    /// Lists the liquidity and earnings of every federation we are connected to
+    pub async fn handle_federations_msg(
+        &self,
+        _payload: FederationsPayload,
+    ) -> Result<Vec<FederationLiquidity>> {
+        let clients = self.clients.read().await.clone();
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let mut federations = Vec::new();
+
+        for (federation_id, client) in clients {
+            let config = dbtx
+                .get_value(&FederationIdKey { id: federation_id })
+                .await
+                .ok_or(GatewayError::InvalidMetadata(format!(
+                    "No config for federation with id {federation_id}"
+                )))?;
+            let earnings = dbtx
+                .get_value(&FederationEarningsKey { id: federation_id })
+                .await
+                .unwrap_or_default();
+            let lightning_node = self
+                .federation_nodes
+                .read()
+                .await
+                .get(&federation_id)
+                .cloned()
+                .unwrap_or_else(|| PRIMARY_NODE.to_string());
+
+            federations.push(FederationLiquidity {
+                federation_id,
+                balance_msat: client.get_balance().await,
+                lightning_node,
+                fees: config.fees,
+                earnings,
+            });
+        }
+
+        Ok(federations)
+    }
+
+    /// Sets the fees charged to the users of a federation, which we announce
+    /// to the federation right away
+    pub async fn handle_set_federation_fees_msg(
+        &self,
+        SetFederationFeesPayload {
+            federation_id,
+            routing_fees,
+        }: SetFederationFeesPayload,
+    ) -> Result<()> {
+        if let GatewayState::Running { .. } = self.state.read().await.clone() {
+            let fees = GatewayFee::from_str(routing_fees.as_str())?.0;
+            let old_client = self.select_client(federation_id).await?;
+            let node = self.federation_node(federation_id).await?;
+
+            // `GatewayConfiguration` should always exist in the database when we are in the
+            // `Running` state.
+            let gateway_config = self
+                .get_gateway_configuration()
+                .await
+                .expect("Gateway configuration should be set");
+
+            let mut dbtx = self.gateway_db.begin_transaction().await;
+            let mut config = dbtx
+                .get_value(&FederationIdKey { id: federation_id })
+                .await
+                .ok_or(GatewayError::InvalidMetadata(format!(
+                    "No config for federation with id {federation_id}"
+                )))?;
+            config.fees = fees;
+
+            // The fees are part of the gateway client module, so we rebuild the client on top
+            // of the database of the old one
+            let client = self
+                .client_builder
+                .build(
+                    config.clone(),
+                    node.public_key,
+                    node.alias.clone(),
+                    node.lnrpc.clone(),
+                    self.clients.clone(),
+                    self.scid_to_federation.clone(),
+                    self.fee_multiplier.clone(),
+                    Some(old_client),
+                    self.gateway_db.clone(),
+                )
+                .await?;
+
+            let route_hints = Self::fetch_lightning_route_hints(
+                node.lnrpc.clone(),
+                gateway_config.num_route_hints,
+            )
+            .await?;
+            client
+                .register_with_federation(
+                    self.gateway_parameters.api_addr.clone(),
+                    route_hints,
+                    GW_ANNOUNCEMENT_TTL,
+                    self.gateway_id,
+                )
+                .await?;
+            self.clients.write().await.insert(federation_id, client);
+            self.client_builder.save_config(config, dbtx).await?;
+            info!(%federation_id, ?fees, "Set federation fees");
+
+            return Ok(());
+        }
+
+        Err(GatewayError::Disconnected)
+    }
+
+    /// Sets the policy raising the routing fees of all federations while the
+    /// gateway is busy, which takes effect with the next load check
+    pub async fn handle_set_fee_policy_msg(
+        &self,
+        SetFeePolicyPayload { policy }: SetFeePolicyPayload,
+    ) -> Result<()> {
+        policy
+            .validate()
+            .map_err(|e| GatewayError::GatewayConfigurationError(e.to_string()))?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&FeePolicyKey, &policy).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+        info!(?policy, "Set fee policy");
+
+        Ok(())
+    }
+
+    /// Quotes the fee we currently charge for paying the invoice on behalf of
+    /// a user of the federation
+    pub async fn handle_fee_quote_msg(
+        &self,
+        FeeQuotePayload {
+            federation_id,
+            invoice,
+        }: FeeQuotePayload,
+    ) -> Result<FeeQuote> {
+        let config = self
+            .gateway_db
+            .begin_transaction()
+            .await
+            .get_value(&FederationIdKey { id: federation_id })
+            .await
+            .ok_or(GatewayError::InvalidMetadata(format!(
+                "No federation with id {federation_id}"
+            )))?;
+        let invoice_amount = invoice
+            .amount_milli_satoshis()
+            .map(Amount::from_msats)
+            .ok_or(GatewayError::InvalidMetadata(
+                "Invoice is missing amount".to_string(),
+            ))?;
+
+        let multiplier_percent = self.fee_multiplier.load(Ordering::SeqCst);
+        let fees = scale_fees(config.fees, multiplier_percent);
+
+        Ok(FeeQuote {
+            invoice_amount,
+            fee: GatewayFee(fees).fee_for(invoice_amount),
+            fees,
+            multiplier_percent,
+        })
+    }
+
+    /// Lists a page of the payments we routed, the most recent first
+    pub async fn handle_payment_history_msg(
+        &self,
+        PaymentHistoryPayload {
+            federation_id,
+            before,
+            limit,
+        }: PaymentHistoryPayload,
+    ) -> Result<PaymentHistory> {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let mut payments = dbtx
+            .find_by_prefix_sorted_descending(&PaymentLogKeyPrefix)
+            .await
+            .filter(|(key, entry)| {
+                let on_page = before.as_ref().map_or(true, |before| {
+                    (key.timestamp, key.operation_id.0) < (before.timestamp, before.operation_id.0)
+                });
+                let of_federation = federation_id.map_or(true, |id| id == entry.federation_id);
+                future::ready(on_page && of_federation)
+            })
+            .take(limit.saturating_add(1))
+            .map(|(key, entry)| PaymentRecord { key, entry })
+            .collect::<Vec<_>>()
+            .await;
+
+        // We fetched one more payment than requested to tell if there is another page
+        let next = if limit < payments.len() {
+            payments.truncate(limit);
+            payments.last().map(|payment| payment.key.clone())
+        } else {
+            None
+        };
+
+        Ok(PaymentHistory { payments, next })
+    }
+
+    /// Lists how many of the payments over each route succeeded per federation
+    pub async fn handle_payment_stats_msg(
+        &self,
+        PaymentStatsPayload { federation_id }: PaymentStatsPayload,
+    ) -> Result<Vec<RouteStats>> {
+        Ok(self
+            .gateway_db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&PaymentStatsKeyPrefix)
+            .await
+            .filter(|(key, _)| {
+                future::ready(federation_id.map_or(true, |id| id == key.federation_id))
+            })
+            .map(|(key, stats)| RouteStats {
+                federation_id: key.federation_id,
+                route: key.route,
+                success_rate: stats.success_rate(),
+                stats,
+            })
+            .collect()
+            .await)
+    }
+
+    /// Lists the liquidity of the channels of every lightning node
+    pub async fn handle_channels_msg(
+        &self,
+        _payload: ChannelsPayload,
+    ) -> Result<Vec<NodeChannels>> {
+        let nodes = self.lightning_nodes.read().await.clone();
+        let mut node_channels = Vec::new();
+
+        for (name, node) in nodes {
+            let (channels, error) = match node.lnrpc.channel_liquidity().await {
+                Ok(response) => (
+                    response
+                        .channels
+                        .into_iter()
+                        .filter_map(|channel| {
+                            Some(ChannelInfo {
+                                remote_pub_key: PublicKey::from_slice(&channel.remote_pub_key)
+                                    .ok()?,
+                                short_channel_id: channel.short_channel_id,
+                                outbound_liquidity: Amount::from_msats(
+                                    channel.outbound_liquidity_msat,
+                                ),
+                                inbound_liquidity: Amount::from_msats(
+                                    channel.inbound_liquidity_msat,
+                                ),
+                                active: channel.active,
+                            })
+                        })
+                        .collect(),
+                    None,
+                ),
+                Err(e) => {
+                    warn!(node = %name, "Failed to list the channels of the lightning node: {e:?}");
+                    (vec![], Some(e.to_string()))
+                }
+            };
+
+            node_channels.push(NodeChannels {
+                lightning_node: name,
+                channels,
+                error,
+            });
+        }
+
+        Ok(node_channels)
+    }
+
     /// This function will return a `GatewayConfiguration` one of two
     /// ways. To avoid conflicting configs, the below order is the
     /// order in which the gateway will respect configurations:
@@ -981,20 +1599,104 @@ impl Gateway {
             )))
     }
 
-    async fn load_clients(
-        &mut self,
-        lnrpc: Arc<dyn ILnRpcClient>,
-        lightning_public_key: PublicKey,
-        lightning_alias: String,
-    ) -> Result<()> {
-        if let GatewayState::Connected = self.state.read().await.clone() {
-            let dbtx = self.gateway_db.begin_transaction().await;
+    /// Returns the lightning node serving the federation
+    async fn federation_node(&self, federation_id: FederationId) -> Result<LightningNode> {
+        let name = self
+            .federation_nodes
+            .read()
+            .await
+            .get(&federation_id)
+            .cloned()
+            .unwrap_or_else(|| PRIMARY_NODE.to_string());
+
+        self.lightning_nodes
+            .read()
+            .await
+            .get(&name)
+            .cloned()
+            .ok_or(GatewayError::UnknownLightningNode(name))
+    }
+
+    /// Chooses the lightning node to serve a federation we connect to, which is
+    /// either the requested node, the node already serving the federation or
+    /// the healthy node serving the fewest federations
+    async fn select_lightning_node(
+        &self,
+        federation_id: FederationId,
+        requested: Option<String>,
+    ) -> Result<LightningNode> {
+        let nodes = self.lightning_nodes.read().await.clone();
+
+        if let Some(name) = requested {
+            return nodes
+                .get(&name)
+                .cloned()
+                .ok_or(GatewayError::UnknownLightningNode(name));
+        }
+
+        let current_node = self
+            .federation_nodes
+            .read()
+            .await
+            .get(&federation_id)
+            .cloned();
+        if let Some(node) = current_node.and_then(|name| nodes.get(&name)) {
+            if node.is_healthy() {
+                return Ok(node.clone());
+            }
+        }
+
+        let federation_counts = self.node_federation_counts().await;
+        let name = least_loaded_node(
+            nodes
+                .values()
+                .map(|node| (node.name.as_str(), node.is_healthy())),
+            &federation_counts,
+        )
+        .ok_or(GatewayError::Disconnected)?;
+
+        Ok(nodes[name].clone())
+    }
+
+    /// Returns the number of federations served by each lightning node
+    async fn node_federation_counts(&self) -> BTreeMap<String, u64> {
+        let mut counts = BTreeMap::new();
+        for name in self.federation_nodes.read().await.values() {
+            *counts.entry(name.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Loads the clients of the federations served by the node, which has to
+    /// be connected
+    async fn load_clients(&self, node_name: &str) -> Result<()> {
+        if let Some(node) = self.lightning_nodes.read().await.get(node_name).cloned() {
+            let mut dbtx = self.gateway_db.begin_transaction().await;
+            let assigned_nodes = dbtx
+                .find_by_prefix(&FederationNodeKeyPrefix)
+                .await
+                .map(|(key, name)| (key.id, name))
+                .collect::<BTreeMap<FederationId, String>>()
+                .await;
             let configs = self.client_builder.load_configs(dbtx).await?;
             let channel_id_generator = self.channel_id_generator.lock().await;
             let mut next_channel_id = channel_id_generator.load(Ordering::SeqCst);
 
             for config in configs {
+                if config.mint_channel_id > next_channel_id {
+                    next_channel_id = config.mint_channel_id + 1;
+                }
+
                 let federation_id = config.invite_code.id;
+
+                // Federations connected before a node could be chosen are served by the primary
+                let assigned_node = assigned_nodes
+                    .get(&federation_id)
+                    .map_or(PRIMARY_NODE, String::as_str);
+                if assigned_node != node_name {
+                    continue;
+                }
+
                 let old_client = self.clients.read().await.get(&federation_id).cloned();
                 let all_clients = self.clients.clone();
                 let all_scids = self.scid_to_federation.clone();
@@ -1003,11 +1705,12 @@ impl Gateway {
                     .client_builder
                     .build(
                         config.clone(),
-                        lightning_public_key,
-                        lightning_alias.clone(),
-                        lnrpc.clone(),
+                        node.public_key,
+                        node.alias.clone(),
+                        node.lnrpc.clone(),
                         all_clients,
                         all_scids,
+                        self.fee_multiplier.clone(),
                         old_client,
                         self.gateway_db.clone(),
                     )
@@ -1022,13 +1725,13 @@ impl Gateway {
                         .write()
                         .await
                         .insert(scid, federation_id);
+                    self.federation_nodes
+                        .write()
+                        .await
+                        .insert(federation_id, node_name.to_string());
                 } else {
                     warn!("Failed to load client for federation: {federation_id}");
                 }
-
-                if config.mint_channel_id > next_channel_id {
-                    next_channel_id = config.mint_channel_id + 1;
-                }
             }
             channel_id_generator.store(next_channel_id, Ordering::SeqCst);
             Ok(())
@@ -1037,6 +1740,148 @@ impl Gateway {
         }
     }
 
+    async fn rebalance_float_timer(&mut self, task_group: &mut TaskGroup) {
+        let gateway = self.clone();
+        task_group
+            .spawn("rebalance float", move |handle| async move {
+                let rebalance_loop = async {
+                    loop {
+                        // The clients are still being loaded when the task is started
+                        sleep(FLOAT_CHECK_INTERVAL).await;
+                        gateway.rebalance_floats().await;
+                    }
+                };
+
+                // Rebalancing the float can take a long time, so we allow shutdown signals to
+                // interrupt it. Any ecash in transit between the gateway and the float client
+                // is picked up again by the next refill.
+                tokio::select! {
+                    _ = handle.make_shutdown_rx().await => {
+                        info!("rebalance float task received shutdown signal")
+                    }
+                    _ = rebalance_loop => {}
+                }
+            })
+            .await;
+    }
+
+    /// Rebalances the float of every federation that has a [`FloatPolicy`]
+    async fn rebalance_floats(&self) {
+        let GatewayState::Running { .. } = self.state.read().await.clone() else {
+            return;
+        };
+
+        let policies = self
+            .gateway_db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&FloatPolicyKeyPrefix)
+            .await
+            .collect::<Vec<(FloatPolicyKey, FloatPolicy)>>()
+            .await;
+
+        for (FloatPolicyKey { id: federation_id }, policy) in policies {
+            let Some(client) = self.clients.read().await.get(&federation_id).cloned() else {
+                continue;
+            };
+            let Ok(node) = self.federation_node(federation_id).await else {
+                continue;
+            };
+
+            match self.float_client(federation_id).await {
+                Ok(float_client) => {
+                    float::check_float(
+                        self.gateway_id,
+                        &client,
+                        &float_client,
+                        node.lnrpc.clone(),
+                        policy,
+                    )
+                    .await;
+                }
+                Err(e) => {
+                    warn!("Could not build float client for federation {federation_id}: {e:?}");
+                }
+            }
+        }
+    }
+
+    async fn float_client(&self, federation_id: FederationId) -> Result<ClientArc> {
+        if let Some(float_client) = self.float_clients.read().await.get(&federation_id) {
+            return Ok(float_client.clone());
+        }
+
+        let config = self
+            .gateway_db
+            .begin_transaction()
+            .await
+            .get_value(&FederationIdKey { id: federation_id })
+            .await
+            .ok_or(GatewayError::InvalidMetadata(format!(
+                "No federation with id {federation_id}"
+            )))?;
+        let float_client = self
+            .client_builder
+            .build_float_client(config.invite_code)
+            .await?;
+
+        self.float_clients
+            .write()
+            .await
+            .insert(federation_id, float_client.clone());
+        Ok(float_client)
+    }
+
+    async fn fee_multiplier_timer(&mut self, task_group: &mut TaskGroup) {
+        let gateway = self.clone();
+        task_group
+            .spawn("update fee multiplier", move |handle| async move {
+                let update_loop = async {
+                    loop {
+                        sleep(LOAD_CHECK_INTERVAL).await;
+                        gateway.update_fee_multiplier().await;
+                    }
+                };
+
+                tokio::select! {
+                    _ = handle.make_shutdown_rx().await => {
+                        info!("fee multiplier task received shutdown signal")
+                    }
+                    _ = update_loop => {}
+                }
+            })
+            .await;
+    }
+
+    /// Derives the fee multiplier from the recent payments and announces the
+    /// scaled fees to all federations if the multiplier changed
+    async fn update_fee_multiplier(&self) {
+        let policy = self
+            .gateway_db
+            .begin_transaction()
+            .await
+            .get_value(&FeePolicyKey)
+            .await
+            .unwrap_or_default();
+        let payments = self.payment_load.payments();
+        let multiplier_percent = policy.multiplier_percent(payments);
+
+        let previous = self
+            .fee_multiplier
+            .swap(multiplier_percent, Ordering::SeqCst);
+        if previous == multiplier_percent {
+            return;
+        }
+
+        info!(
+            payments,
+            previous, multiplier_percent, "Fee multiplier changed, announcing the new fees"
+        );
+        if let Some(gateway_config) = self.get_gateway_configuration().await {
+            self.register_clients(gateway_config.num_route_hints).await;
+        }
+    }
+
     async fn register_clients_timer(&mut self, task_group: &mut TaskGroup) {
         let gateway = self.clone();
         task_group
@@ -1045,29 +1890,8 @@ impl Gateway {
                     loop {
                         if let Some(gateway_config) = gateway.get_gateway_configuration().await {
                             let gateway_state = gateway.state.read().await.clone();
-                            if let GatewayState::Running { lnrpc, .. } = &gateway_state {
-                                match Self::fetch_lightning_route_hints(lnrpc.clone(), gateway_config.num_route_hints).await {
-                                    Ok(route_hints) => {
-                                        for (federation_id, client) in gateway.clients.read().await.iter() {
-                                            if let Err(e) = client
-                                                .register_with_federation(
-                                                    gateway.gateway_parameters.api_addr.clone(),
-                                                    route_hints.clone(),
-                                                    GW_ANNOUNCEMENT_TTL,
-                                                    gateway.gateway_id,
-                                                )
-                                                .await
-                                            {
-                                                error!("Error registering federation {federation_id}: {e:?}");
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!(
-                                            "Could not retrieve route hints, gateway will not be registered: {e:?}"
-                                        );
-                                    }
-                                }
+                            if let GatewayState::Running { .. } = &gateway_state {
+                                gateway.register_clients(gateway_config.num_route_hints).await;
                             } else {
                                 warn!(
                                     "GatewayState must be Running to register with federation. Current state: {:?}",
@@ -1101,6 +1925,73 @@ impl Gateway {
             .await;
     }
 
+    /// Registers the gateway with every federation, announcing the route hints
+    /// of the lightning node serving the federation
+    async fn register_clients(&self, num_route_hints: u32) {
+        let mut route_hints = BTreeMap::new();
+
+        for (federation_id, client) in self.clients.read().await.iter() {
+            let node = match self.federation_node(*federation_id).await {
+                Ok(node) => node,
+                Err(e) => {
+                    warn!("Cannot register federation {federation_id}: {e:?}");
+                    continue;
+                }
+            };
+
+            if !route_hints.contains_key(&node.name) {
+                match Self::fetch_lightning_route_hints(node.lnrpc.clone(), num_route_hints).await {
+                    Ok(hints) => {
+                        route_hints.insert(node.name.clone(), hints);
+                    }
+                    Err(e) => {
+                        error!(
+                            node = %node.name,
+                            "Could not retrieve route hints, gateway will not be registered: {e:?}"
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            if let Err(e) = client
+                .register_with_federation(
+                    self.gateway_parameters.api_addr.clone(),
+                    route_hints[&node.name].clone(),
+                    GW_ANNOUNCEMENT_TTL,
+                    self.gateway_id,
+                )
+                .await
+            {
+                error!("Error registering federation {federation_id}: {e:?}");
+            }
+        }
+    }
+
+    async fn node_health_timer(&mut self, task_group: &mut TaskGroup) {
+        let gateway = self.clone();
+        task_group
+            .spawn("check lightning node health", move |handle| async move {
+                let health_check_loop = async {
+                    loop {
+                        sleep(NODE_HEALTH_CHECK_INTERVAL).await;
+                        let nodes = gateway.lightning_nodes.read().await.clone();
+                        for node in nodes.values() {
+                            node.check_health().await;
+                        }
+                    }
+                };
+
+                tokio::select! {
+                    _ = handle.make_shutdown_rx().await => {
+                        info!("lightning node health task received shutdown signal")
+                    }
+                    _ = health_check_loop => {}
+                }
+            })
+            .await;
+    }
+
     async fn fetch_lightning_route_hints_try(
         lnrpc: &dyn ILnRpcClient,
         num_route_hints: u32,
@@ -1267,6 +2158,8 @@ pub enum GatewayError {
     LightningRpcError(#[from] LightningRpcError),
     #[error("Outgoing Payment Error {}", OptStacktrace(0))]
     OutgoingPaymentError(#[from] Box<OutgoingPaymentError>),
+    #[error("Transfer Error {}", OptStacktrace(0))]
+    TransferError(#[from] Box<TransferError>),
     #[error("Invalid Metadata: {}", OptStacktrace(0))]
     InvalidMetadata(String),
     #[error("Unexpected state: {}", OptStacktrace(0))]
@@ -1277,6 +2170,8 @@ pub enum GatewayError {
     GatewayConfigurationError(String),
     #[error("Unsupported Network: {0}")]
     UnsupportedNetwork(Network),
+    #[error("Not connected to a lightning node named {0}")]
+    UnknownLightningNode(String),
 }
 
 impl IntoResponse for GatewayError {
@@ -1290,6 +2185,12 @@ impl IntoResponse for GatewayError {
                     .to_string(),
                 StatusCode::BAD_REQUEST,
             ),
+            GatewayError::TransferError(_) => (
+                "Error while transferring to the other federation. Transfer contract will be \
+                 refunded."
+                    .to_string(),
+                StatusCode::BAD_REQUEST,
+            ),
             GatewayError::Disconnected => (
                 "The gateway is disconnected from the Lightning Node".to_string(),
                 StatusCode::NOT_FOUND,
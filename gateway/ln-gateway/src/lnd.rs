@@ -11,7 +11,7 @@ use tokio_stream::wrappers::ReceiverStream;
 use tonic::Status;
 use tonic_lnd::lnrpc::failure::FailureCode;
 use tonic_lnd::lnrpc::payment::PaymentStatus;
-use tonic_lnd::lnrpc::{ChanInfoRequest, GetInfoRequest, ListChannelsRequest};
+use tonic_lnd::lnrpc::{ChanInfoRequest, GetInfoRequest, Invoice, ListChannelsRequest};
 use tonic_lnd::routerrpc::{
     CircuitKey, ForwardHtlcInterceptResponse, ResolveHoldForwardAction, SendPaymentRequest,
     TrackPaymentRequest,
@@ -20,11 +20,13 @@ use tonic_lnd::tonic::Code;
 use tonic_lnd::{connect, Client as LndClient};
 use tracing::{debug, error, info, trace, warn};
 
+use crate::gateway_lnrpc::get_channel_liquidity_response::ChannelLiquidity;
 use crate::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
 use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Forward, Settle};
 use crate::gateway_lnrpc::{
-    EmptyResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest,
-    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse, GetChannelLiquidityResponse,
+    GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse,
+    PayInvoiceRequest, PayInvoiceResponse,
 };
 use crate::lnrpc_client::{
     ILnRpcClient, LightningRpcError, RouteHtlcStream, MAX_LIGHTNING_RETRIES,
@@ -483,6 +485,55 @@ impl ILnRpcClient for GatewayLndClient {
         return Ok(PayInvoiceResponse { preimage });
     }
 
+    async fn create_invoice(
+        &self,
+        request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        let CreateInvoiceRequest {
+            amount_msat,
+            description,
+            expiry_secs,
+        } = request;
+
+        let value_msat: i64 =
+            amount_msat
+                .try_into()
+                .map_err(|error| LightningRpcError::FailedToCreateInvoice {
+                    failure_reason: format!("amount_msat exceeds valid LND range {error:?}"),
+                })?;
+        let expiry: i64 =
+            expiry_secs
+                .try_into()
+                .map_err(|error| LightningRpcError::FailedToCreateInvoice {
+                    failure_reason: format!("expiry_secs exceeds valid LND range {error:?}"),
+                })?;
+
+        let mut client = Self::connect(
+            self.address.clone(),
+            self.tls_cert.clone(),
+            self.macaroon.clone(),
+        )
+        .await?;
+
+        let invoice = client
+            .lightning()
+            .add_invoice(Invoice {
+                memo: description,
+                value_msat,
+                expiry,
+                ..Default::default()
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToCreateInvoice {
+                failure_reason: format!("Failed to add invoice {status:?}"),
+            })?
+            .into_inner();
+
+        Ok(CreateInvoiceResponse {
+            invoice: invoice.payment_request,
+        })
+    }
+
     async fn route_htlcs<'a>(
         self: Box<Self>,
         task_group: &mut TaskGroup,
@@ -557,4 +608,51 @@ impl ILnRpcClient for GatewayLndClient {
             failure_reason: "Gatewayd has not started to route HTLCs".to_string(),
         })
     }
+
+    async fn channel_liquidity(&self) -> Result<GetChannelLiquidityResponse, LightningRpcError> {
+        let mut client = Self::connect(
+            self.address.clone(),
+            self.tls_cert.clone(),
+            self.macaroon.clone(),
+        )
+        .await?;
+        let channels = client
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: false,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToGetChannelLiquidity {
+                failure_reason: format!("Failed to list channels {status:?}"),
+            })?
+            .into_inner()
+            .channels;
+
+        let channels = channels
+            .into_iter()
+            .map(|chan| {
+                let remote_pub_key = PublicKey::from_str(&chan.remote_pubkey)
+                    .map_err(|e| LightningRpcError::FailedToGetChannelLiquidity {
+                        failure_reason: format!("Invalid remote public key {e:?}"),
+                    })?
+                    .serialize()
+                    .to_vec();
+
+                // LND reports the balances in satoshis
+                Ok(ChannelLiquidity {
+                    remote_pub_key,
+                    short_channel_id: chan.chan_id,
+                    outbound_liquidity_msat: chan.local_balance.max(0) as u64 * 1000,
+                    inbound_liquidity_msat: chan.remote_balance.max(0) as u64 * 1000,
+                    active: chan.active,
+                })
+            })
+            .collect::<Result<Vec<_>, LightningRpcError>>()?;
+
+        Ok(GetChannelLiquidityResponse { channels })
+    }
 }
@@ -15,7 +15,8 @@ use tracing::info;
 
 use crate::gateway_lnrpc::gateway_lightning_client::GatewayLightningClient;
 use crate::gateway_lnrpc::{
-    EmptyRequest, EmptyResponse, GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse,
+    CreateInvoiceRequest, CreateInvoiceResponse, EmptyRequest, EmptyResponse,
+    GetChannelLiquidityResponse, GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse,
     InterceptHtlcRequest, InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
 };
 use crate::lnd::GatewayLndClient;
@@ -43,6 +44,10 @@ pub enum LightningRpcError {
     FailedToOpenChannel { failure_reason: String },
     #[error("Failed to get Invoice: {failure_reason}")]
     FailedToGetInvoice { failure_reason: String },
+    #[error("Failed to create invoice: {failure_reason}")]
+    FailedToCreateInvoice { failure_reason: String },
+    #[error("Failed to get channel liquidity: {failure_reason}")]
+    FailedToGetChannelLiquidity { failure_reason: String },
 }
 
 #[async_trait]
@@ -62,6 +67,17 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         invoice: PayInvoiceRequest,
     ) -> Result<PayInvoiceResponse, LightningRpcError>;
 
+    /// Create an invoice that is paid to the lightning node itself, as opposed
+    /// to the invoices of federation users that are intercepted
+    async fn create_invoice(
+        &self,
+        _request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToCreateInvoice {
+            failure_reason: "Not supported by this lightning node".to_string(),
+        })
+    }
+
     // Consumes the current lightning client because `route_htlcs` should only be
     // called once per client. A stream of intercepted HTLCs and a `Arc<dyn
     // ILnRpcClient> are returned to the caller. The caller can use this new
@@ -76,6 +92,14 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         &self,
         htlc: InterceptHtlcResponse,
     ) -> Result<EmptyResponse, LightningRpcError>;
+
+    /// Get the outbound and inbound liquidity of every channel of the
+    /// lightning node
+    async fn channel_liquidity(&self) -> Result<GetChannelLiquidityResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToGetChannelLiquidity {
+            failure_reason: "Not supported by this lightning node".to_string(),
+        })
+    }
 }
 
 /// An `ILnRpcClient` that wraps around `GatewayLightningClient` for
@@ -168,6 +192,20 @@ impl ILnRpcClient for NetworkLnRpcClient {
         Ok(res.into_inner())
     }
 
+    async fn create_invoice(
+        &self,
+        request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        let req = Request::new(request);
+        let mut client = Self::connect(self.connection_url.clone()).await?;
+        let res = client.create_invoice(req).await.map_err(|status| {
+            LightningRpcError::FailedToCreateInvoice {
+                failure_reason: status.message().to_string(),
+            }
+        })?;
+        Ok(res.into_inner())
+    }
+
     async fn route_htlcs<'a>(
         self: Box<Self>,
         _task_group: &mut TaskGroup,
@@ -197,6 +235,17 @@ impl ILnRpcClient for NetworkLnRpcClient {
         })?;
         Ok(res.into_inner())
     }
+
+    async fn channel_liquidity(&self) -> Result<GetChannelLiquidityResponse, LightningRpcError> {
+        let req = Request::new(EmptyRequest {});
+        let mut client = Self::connect(self.connection_url.clone()).await?;
+        let res = client.get_channel_liquidity(req).await.map_err(|status| {
+            LightningRpcError::FailedToGetChannelLiquidity {
+                failure_reason: status.message().to_string(),
+            }
+        })?;
+        Ok(res.into_inner())
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,198 @@
+//! Serving swaps through several lightning nodes
+//!
+//! Besides the node it is started with, the gateway can attach further nodes
+//! listed in the file passed as `--lightning-nodes`, such that operators can
+//! shard their liquidity across nodes without running a gateway per node. The
+//! file maps the name of every additional node to its [`LightningMode`]:
+//!
+//! ```json
+//! { "lnd-2": { "Lnd": { "lnd_rpc_addr": "...", "lnd_tls_cert": "...", "lnd_macaroon": "..." } } }
+//! ```
+//!
+//! Every federation is served by a single node, since the gateway registers
+//! the node's public key and route hints with the federation. The node is
+//! either named when connecting the federation or, if none is named, the
+//! healthy node serving the fewest federations. The gateway checks the health
+//! of every node periodically and accounts the HTLCs and payments per node.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{ensure, Context};
+use fedimint_core::Amount;
+use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::lnrpc_client::ILnRpcClient;
+use crate::{fetch_lightning_node_info, LightningMode};
+
+/// Name of the node the gateway is started with, which also serves the
+/// federations connected before a node could be chosen
+pub const PRIMARY_NODE: &str = "primary";
+
+/// How often the gateway checks that its nodes are reachable
+pub const NODE_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Reads the additional nodes from a JSON file mapping their names to their
+/// [`LightningMode`]
+pub fn read_lightning_nodes(path: &Path) -> anyhow::Result<BTreeMap<String, LightningMode>> {
+    let file = std::fs::read(path)
+        .with_context(|| format!("Failed to read lightning nodes from {}", path.display()))?;
+    let nodes: BTreeMap<String, LightningMode> = serde_json::from_slice(&file)?;
+
+    ensure!(
+        !nodes.contains_key(PRIMARY_NODE),
+        "The name {PRIMARY_NODE} is reserved for the node the gateway is started with"
+    );
+
+    Ok(nodes)
+}
+
+/// Swaps served by a node since the gateway connected to it
+#[derive(Debug, Default)]
+struct NodeAccounting {
+    htlcs_intercepted: AtomicU64,
+    incoming_msat: AtomicU64,
+    payments_sent: AtomicU64,
+}
+
+/// A lightning node the gateway is connected to
+#[derive(Debug, Clone)]
+pub struct LightningNode {
+    pub name: String,
+    pub lnrpc: Arc<dyn ILnRpcClient>,
+    pub public_key: PublicKey,
+    pub alias: String,
+    healthy: Arc<AtomicBool>,
+    accounting: Arc<NodeAccounting>,
+}
+
+impl LightningNode {
+    pub fn new(
+        name: String,
+        lnrpc: Arc<dyn ILnRpcClient>,
+        public_key: PublicKey,
+        alias: String,
+    ) -> Self {
+        LightningNode {
+            name,
+            lnrpc,
+            public_key,
+            alias,
+            healthy: Arc::new(AtomicBool::new(true)),
+            accounting: Arc::new(NodeAccounting::default()),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    pub fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::SeqCst);
+    }
+
+    /// Queries the node and marks it unhealthy if it is unreachable or was
+    /// replaced by a node with a different public key
+    pub async fn check_health(&self) -> bool {
+        let healthy = match fetch_lightning_node_info(self.lnrpc.clone()).await {
+            Ok((public_key, ..)) if public_key == self.public_key => true,
+            Ok((public_key, ..)) => {
+                warn!(node = %self.name, %public_key, "Lightning node changed its public key");
+                false
+            }
+            Err(e) => {
+                warn!(node = %self.name, "Lightning node is unreachable: {e:?}");
+                false
+            }
+        };
+
+        self.set_healthy(healthy);
+
+        healthy
+    }
+
+    pub fn record_htlc(&self, incoming_msat: u64) {
+        self.accounting
+            .htlcs_intercepted
+            .fetch_add(1, Ordering::SeqCst);
+        self.accounting
+            .incoming_msat
+            .fetch_add(incoming_msat, Ordering::SeqCst);
+    }
+
+    pub fn record_payment(&self) {
+        self.accounting.payments_sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn info(&self, federations: u64) -> LightningNodeInfo {
+        LightningNodeInfo {
+            name: self.name.clone(),
+            pub_key: self.public_key,
+            alias: self.alias.clone(),
+            healthy: self.is_healthy(),
+            federations,
+            htlcs_intercepted: self.accounting.htlcs_intercepted.load(Ordering::SeqCst),
+            incoming_msat: Amount::from_msats(self.accounting.incoming_msat.load(Ordering::SeqCst)),
+            payments_sent: self.accounting.payments_sent.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Status and accounting of a lightning node of the gateway
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LightningNodeInfo {
+    pub name: String,
+    pub pub_key: PublicKey,
+    pub alias: String,
+    pub healthy: bool,
+    /// Number of federations whose swaps are routed through the node
+    pub federations: u64,
+    pub htlcs_intercepted: u64,
+    /// Sum of the incoming HTLCs intercepted for the federations
+    pub incoming_msat: Amount,
+    /// Number of invoices paid on behalf of the federations
+    pub payments_sent: u64,
+}
+
+/// Picks the node for a federation connected without naming one, that is the
+/// healthy node serving the fewest federations
+pub fn least_loaded_node<'a>(
+    nodes: impl IntoIterator<Item = (&'a str, bool)>,
+    federations: &BTreeMap<String, u64>,
+) -> Option<&'a str> {
+    nodes
+        .into_iter()
+        .filter(|(_, healthy)| *healthy)
+        .map(|(name, _)| name)
+        .min_by_key(|name| (federations.get(*name).copied().unwrap_or(0), *name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::least_loaded_node;
+
+    #[test]
+    fn picks_healthy_node_with_fewest_federations() {
+        let federations = BTreeMap::from([("primary".to_string(), 2), ("b".to_string(), 1)]);
+
+        assert_eq!(
+            least_loaded_node([("primary", true), ("b", true), ("c", true)], &federations),
+            Some("c")
+        );
+        assert_eq!(
+            least_loaded_node([("primary", true), ("b", true), ("c", false)], &federations),
+            Some("b")
+        );
+        assert_eq!(
+            least_loaded_node([("primary", false), ("b", false)], &federations),
+            None
+        );
+    }
+}
@@ -10,18 +10,27 @@ use fedimint_core::config::{ClientConfig, FederationId};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::Amount;
 use fedimint_ln_client::pay::PayInvoicePayload;
+use fedimint_ln_client::transfer::TransferPayload;
 use fedimint_ln_common::contracts::Preimage;
-use fedimint_ln_common::{route_hints, serde_option_routing_fees};
+use fedimint_ln_common::{route_hints, serde_option_routing_fees, serde_routing_fees};
 use futures::Future;
-use lightning_invoice::RoutingFees;
+use lightning_invoice::{Bolt11Invoice, RoutingFees};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tokio::sync::oneshot;
 
+use crate::db::{FederationEarnings, PaymentLogEntry, PaymentLogKey, PaymentRoute, PaymentStats};
+use crate::fees::FeePolicy;
+use crate::float::FloatPolicy;
+use crate::nodes::LightningNodeInfo;
 use crate::{Gateway, Result};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ConnectFedPayload {
     pub invite_code: String,
+    /// The lightning node to serve the federation through, see
+    /// [`crate::nodes`]
+    #[serde(default)]
+    pub lightning_node: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -64,6 +73,23 @@ pub struct FederationInfo {
     pub config: ClientConfig,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FederationsPayload;
+
+/// Liquidity and earnings of one of the feds we are connected to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FederationLiquidity {
+    pub federation_id: FederationId,
+    /// The ecash balance of the gateway in the fed
+    pub balance_msat: Amount,
+    /// The lightning node serving the fed, see [`crate::nodes`]
+    pub lightning_node: String,
+    /// The fees the gateway charges the fed's users
+    #[serde(with = "serde_routing_fees")]
+    pub fees: RoutingFees,
+    pub earnings: FederationEarnings,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct GatewayInfo {
     pub version_hash: String,
@@ -76,6 +102,8 @@ pub struct GatewayInfo {
     pub gateway_id: secp256k1::PublicKey,
     pub gateway_state: String,
     pub network: Option<Network>,
+    #[serde(default)]
+    pub lightning_nodes: Vec<LightningNodeInfo>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -86,6 +114,115 @@ pub struct SetConfigurationPayload {
     pub network: Option<Network>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFederationFeesPayload {
+    pub federation_id: FederationId,
+    /// The fees charged to the fed's users in the same format as the
+    /// gateway-wide `routing_fees`
+    pub routing_fees: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFloatPolicyPayload {
+    pub federation_id: FederationId,
+    /// Removes the float policy if `None`
+    pub policy: Option<FloatPolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFeePolicyPayload {
+    pub policy: FeePolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeeQuotePayload {
+    pub federation_id: FederationId,
+    pub invoice: Bolt11Invoice,
+}
+
+/// The fee the gateway currently charges for paying an invoice on behalf of
+/// a federation's user
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FeeQuote {
+    pub invoice_amount: Amount,
+    /// The fee the user has to lock in the outgoing contract on top of the
+    /// invoice amount
+    pub fee: Amount,
+    /// The fees charged to the federation, scaled by the current peak load
+    /// multiplier
+    #[serde(with = "serde_routing_fees")]
+    pub fees: RoutingFees,
+    pub multiplier_percent: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PaymentHistoryPayload {
+    /// Only lists the payments of the fed if set
+    #[serde(default)]
+    pub federation_id: Option<FederationId>,
+    /// Only lists the payments that completed before this one, see
+    /// [`PaymentHistory::next`]
+    #[serde(default)]
+    pub before: Option<PaymentLogKey>,
+    pub limit: usize,
+}
+
+/// A page of the payments the gateway routed, the most recent first
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PaymentHistory {
+    pub payments: Vec<PaymentRecord>,
+    /// Where the next page starts, `None` if this is the last page
+    pub next: Option<PaymentLogKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PaymentRecord {
+    #[serde(flatten)]
+    pub key: PaymentLogKey,
+    #[serde(flatten)]
+    pub entry: PaymentLogEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PaymentStatsPayload {
+    /// Only lists the stats of the fed if set
+    #[serde(default)]
+    pub federation_id: Option<FederationId>,
+}
+
+/// How many of the payments of a fed over a route succeeded
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RouteStats {
+    pub federation_id: FederationId,
+    pub route: PaymentRoute,
+    #[serde(flatten)]
+    pub stats: PaymentStats,
+    pub success_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelsPayload;
+
+/// The channels of one of the gateway's lightning nodes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NodeChannels {
+    pub lightning_node: String,
+    pub channels: Vec<ChannelInfo>,
+    /// Why the channels could not be listed, in which case there are none
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChannelInfo {
+    pub remote_pub_key: secp256k1::PublicKey,
+    pub short_channel_id: u64,
+    /// How much the node can send over the channel
+    pub outbound_liquidity: Amount,
+    /// How much the node can receive over the channel
+    pub inbound_liquidity: Amount,
+    pub active: bool,
+}
+
 #[derive(Debug)]
 pub enum GatewayRequest {
     Info(GatewayRequestInner<InfoPayload>),
@@ -98,6 +235,15 @@ pub enum GatewayRequest {
     Restore(GatewayRequestInner<RestorePayload>),
     Shutdown,
     SetConfiguration(GatewayRequestInner<SetConfigurationPayload>),
+    SetFloatPolicy(GatewayRequestInner<SetFloatPolicyPayload>),
+    Federations(GatewayRequestInner<FederationsPayload>),
+    SetFederationFees(GatewayRequestInner<SetFederationFeesPayload>),
+    SetFeePolicy(GatewayRequestInner<SetFeePolicyPayload>),
+    FeeQuote(GatewayRequestInner<FeeQuotePayload>),
+    Transfer(GatewayRequestInner<TransferPayload>),
+    PaymentHistory(GatewayRequestInner<PaymentHistoryPayload>),
+    PaymentStats(GatewayRequestInner<PaymentStatsPayload>),
+    Channels(GatewayRequestInner<ChannelsPayload>),
 }
 
 #[derive(Debug)]
@@ -147,6 +293,31 @@ impl_gateway_request_trait!(
     (),
     GatewayRequest::SetConfiguration
 );
+impl_gateway_request_trait!(SetFloatPolicyPayload, (), GatewayRequest::SetFloatPolicy);
+impl_gateway_request_trait!(
+    FederationsPayload,
+    Vec<FederationLiquidity>,
+    GatewayRequest::Federations
+);
+impl_gateway_request_trait!(
+    SetFederationFeesPayload,
+    (),
+    GatewayRequest::SetFederationFees
+);
+impl_gateway_request_trait!(SetFeePolicyPayload, (), GatewayRequest::SetFeePolicy);
+impl_gateway_request_trait!(FeeQuotePayload, FeeQuote, GatewayRequest::FeeQuote);
+impl_gateway_request_trait!(TransferPayload, Preimage, GatewayRequest::Transfer);
+impl_gateway_request_trait!(
+    PaymentHistoryPayload,
+    PaymentHistory,
+    GatewayRequest::PaymentHistory
+);
+impl_gateway_request_trait!(
+    PaymentStatsPayload,
+    Vec<RouteStats>,
+    GatewayRequest::PaymentStats
+);
+impl_gateway_request_trait!(ChannelsPayload, Vec<NodeChannels>, GatewayRequest::Channels);
 
 impl<T> GatewayRequestInner<T>
 where
@@ -10,10 +10,15 @@ use serde::Serialize;
 use thiserror::Error;
 
 use super::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, RestorePayload,
-    SetConfigurationPayload, WithdrawPayload,
+    BackupPayload, BalancePayload, ChannelsPayload, ConnectFedPayload, DepositAddressPayload,
+    FederationsPayload, FeeQuotePayload, PaymentHistoryPayload, PaymentStatsPayload,
+    RestorePayload, SetConfigurationPayload, SetFederationFeesPayload, SetFeePolicyPayload,
+    SetFloatPolicyPayload, WithdrawPayload,
+};
+use crate::rpc::{
+    FederationInfo, FederationLiquidity, FeeQuote, GatewayInfo, NodeChannels, PaymentHistory,
+    RouteStats,
 };
-use crate::rpc::{FederationInfo, GatewayInfo};
 
 pub struct GatewayRpcClient {
     // Base URL to gateway web server
@@ -92,6 +97,70 @@ impl GatewayRpcClient {
         self.call(url, payload).await
     }
 
+    pub async fn set_float_policy(&self, payload: SetFloatPolicyPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join("/set_float_policy")
+            .expect("invalid base url");
+        self.call(url, payload).await
+    }
+
+    pub async fn get_federations(&self) -> GatewayRpcResult<Vec<FederationLiquidity>> {
+        let url = self
+            .base_url
+            .join("/federations")
+            .expect("invalid base url");
+        self.call(url, FederationsPayload).await
+    }
+
+    pub async fn set_federation_fees(
+        &self,
+        payload: SetFederationFeesPayload,
+    ) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join("/set_federation_fees")
+            .expect("invalid base url");
+        self.call(url, payload).await
+    }
+
+    pub async fn set_fee_policy(&self, payload: SetFeePolicyPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join("/set_fee_policy")
+            .expect("invalid base url");
+        self.call(url, payload).await
+    }
+
+    pub async fn quote_fee(&self, payload: FeeQuotePayload) -> GatewayRpcResult<FeeQuote> {
+        let url = self.base_url.join("/quote_fee").expect("invalid base url");
+        self.call(url, payload).await
+    }
+
+    pub async fn get_payments(
+        &self,
+        payload: PaymentHistoryPayload,
+    ) -> GatewayRpcResult<PaymentHistory> {
+        let url = self.base_url.join("/payments").expect("invalid base url");
+        self.call(url, payload).await
+    }
+
+    pub async fn get_payment_stats(
+        &self,
+        payload: PaymentStatsPayload,
+    ) -> GatewayRpcResult<Vec<RouteStats>> {
+        let url = self
+            .base_url
+            .join("/payment_stats")
+            .expect("invalid base url");
+        self.call(url, payload).await
+    }
+
+    pub async fn get_channels(&self) -> GatewayRpcResult<Vec<NodeChannels>> {
+        let url = self.base_url.join("/channels").expect("invalid base url");
+        self.call(url, ChannelsPayload).await
+    }
+
     async fn call<P, T: DeserializeOwned>(
         &self,
         url: SafeUrl,
@@ -7,14 +7,17 @@ use axum_macros::debug_handler;
 use bitcoin_hashes::hex::ToHex;
 use fedimint_core::task::TaskGroup;
 use fedimint_ln_client::pay::PayInvoicePayload;
+use fedimint_ln_client::transfer::TransferPayload;
 use serde_json::json;
 use tower_http::cors::CorsLayer;
 use tower_http::validate_request::ValidateRequestHeaderLayer;
 use tracing::{error, instrument};
 
 use super::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, InfoPayload,
-    RestorePayload, SetConfigurationPayload, WithdrawPayload,
+    BackupPayload, BalancePayload, ChannelsPayload, ConnectFedPayload, DepositAddressPayload,
+    FederationsPayload, FeeQuotePayload, InfoPayload, PaymentHistoryPayload, PaymentStatsPayload,
+    RestorePayload, SetConfigurationPayload, SetFederationFeesPayload, SetFeePolicyPayload,
+    SetFloatPolicyPayload, WithdrawPayload,
 };
 use crate::db::GatewayConfiguration;
 use crate::{Gateway, GatewayError};
@@ -29,6 +32,8 @@ pub async fn run_webserver(
         // Public routes on gateway webserver
         let routes = Router::new()
             .route("/pay_invoice", post(pay_invoice))
+            .route("/quote_fee", post(quote_fee))
+            .route("/transfer", post(transfer))
             .route("/id", get(get_gateway_id));
 
         // Authenticated, public routes used for gateway administration
@@ -41,6 +46,13 @@ pub async fn run_webserver(
             .route("/backup", post(backup))
             .route("/restore", post(restore))
             .route("/set_configuration", post(set_configuration))
+            .route("/set_float_policy", post(set_float_policy))
+            .route("/federations", post(federations))
+            .route("/set_federation_fees", post(set_federation_fees))
+            .route("/set_fee_policy", post(set_fee_policy))
+            .route("/payments", post(payments))
+            .route("/payment_stats", post(payment_stats))
+            .route("/channels", post(channels))
             .layer(ValidateRequestHeaderLayer::bearer(&gateway_config.password));
         (routes, admin_routes)
     } else {
@@ -127,6 +139,26 @@ async fn pay_invoice(
     Ok(Json(json!(preimage.0.to_hex())))
 }
 
+/// Quote the fee for paying an invoice before funding the outgoing contract
+#[instrument(skip_all, err)]
+async fn quote_fee(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<FeeQuotePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let quote = gateway.handle_fee_quote_msg(payload).await?;
+    Ok(Json(json!(quote)))
+}
+
+/// Transfer the funds of a transfer contract to a user of another federation
+#[instrument(skip_all, err)]
+async fn transfer(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<TransferPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let preimage = gateway.handle_transfer_msg(payload).await?;
+    Ok(Json(json!(preimage)))
+}
+
 /// Connect a new federation
 #[instrument(skip_all, err)]
 async fn connect_fed(
@@ -166,6 +198,77 @@ async fn set_configuration(
     Ok(Json(json!(())))
 }
 
+#[instrument(skip_all, err)]
+async fn set_float_policy(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFloatPolicyPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_float_policy_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+/// List the liquidity and earnings of every connected federation
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn federations(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<FederationsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let federations = gateway.handle_federations_msg(payload).await?;
+    Ok(Json(json!(federations)))
+}
+
+#[instrument(skip_all, err)]
+async fn set_federation_fees(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFederationFeesPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_federation_fees_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err)]
+async fn set_fee_policy(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFeePolicyPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_fee_policy_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+/// List a page of the routed payments, the most recent first
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn payments(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<PaymentHistoryPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let history = gateway.handle_payment_history_msg(payload).await?;
+    Ok(Json(json!(history)))
+}
+
+/// List the success rates and fees of the payments per federation and route
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn payment_stats(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<PaymentStatsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let stats = gateway.handle_payment_stats_msg(payload).await?;
+    Ok(Json(json!(stats)))
+}
+
+/// List the liquidity of the channels of every lightning node
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn channels(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ChannelsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let channels = gateway.handle_channels_msg(payload).await?;
+    Ok(Json(json!(channels)))
+}
+
 #[instrument(skip_all, err)]
 async fn get_gateway_id(
     Extension(gateway): Extension<Gateway>,
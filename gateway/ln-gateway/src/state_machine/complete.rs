@@ -2,6 +2,7 @@ use fedimint_client::sm::{State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::Amount;
 use fedimint_ln_client::incoming::IncomingSmStates;
 use fedimint_ln_common::contracts::Preimage;
 use futures::StreamExt;
@@ -9,6 +10,9 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use super::{GatewayClientContext, GatewayClientStateMachines};
+use crate::db::{
+    record_federation_earnings, record_payment, FederationEarnings, PaymentLogEntry, PaymentRoute,
+};
 use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Settle};
 use crate::gateway_lnrpc::InterceptHtlcResponse;
 
@@ -47,6 +51,12 @@ pub struct GatewayCompleteCommon {
     pub operation_id: OperationId,
     pub incoming_chan_id: u64,
     pub htlc_id: u64,
+    /// The amount the federation pays for the preimage, which is what the
+    /// HTLC forwards
+    pub amount: Amount,
+    /// The routing fee of the HTLC we earn once the federation bought the
+    /// preimage
+    pub fee: Amount,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
@@ -90,9 +100,13 @@ impl WaitForPreimageState {
         common: GatewayCompleteCommon,
     ) -> Vec<StateTransition<GatewayCompleteStateMachine>> {
         vec![StateTransition::new(
-            Self::await_preimage(context, common.clone()),
+            Self::await_preimage(context.clone(), common.clone()),
             move |_dbtx, result, _old_state| {
-                Box::pin(Self::transition_complete_htlc(result, common.clone()))
+                Box::pin(Self::transition_complete_htlc(
+                    result,
+                    context.clone(),
+                    common.clone(),
+                ))
             },
         )]
     }
@@ -122,8 +136,38 @@ impl WaitForPreimageState {
 
     async fn transition_complete_htlc(
         result: Result<Preimage, CompleteHtlcError>,
+        context: GatewayClientContext,
         common: GatewayCompleteCommon,
     ) -> GatewayCompleteStateMachine {
+        if result.is_ok() {
+            record_federation_earnings(
+                &context.gateway_db,
+                context.federation_id,
+                FederationEarnings {
+                    payments_received: 1,
+                    incoming_fees: common.fee,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+        record_payment(
+            &context.gateway_db,
+            common.operation_id,
+            PaymentLogEntry {
+                federation_id: context.federation_id,
+                route: PaymentRoute::Incoming,
+                amount: common.amount,
+                fee: if result.is_ok() {
+                    common.fee
+                } else {
+                    Amount::ZERO
+                },
+                error: result.as_ref().err().map(ToString::to_string),
+            },
+        )
+        .await;
+
         match result {
             Ok(preimage) => GatewayCompleteStateMachine {
                 common,
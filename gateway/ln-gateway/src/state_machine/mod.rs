@@ -1,6 +1,8 @@
 pub mod complete;
 pub mod pay;
+pub mod transfer;
 
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -29,6 +31,7 @@ use fedimint_ln_client::incoming::{
     FundingOfferState, IncomingSmCommon, IncomingSmError, IncomingSmStates, IncomingStateMachine,
 };
 use fedimint_ln_client::pay::PayInvoicePayload;
+use fedimint_ln_client::transfer::TransferPayload;
 use fedimint_ln_common::api::LnFederationApi;
 use fedimint_ln_common::config::LightningClientConfig;
 use fedimint_ln_common::contracts::{ContractId, Preimage};
@@ -49,11 +52,16 @@ use self::pay::{
     GatewayPayCommon, GatewayPayInvoice, GatewayPayStateMachine, GatewayPayStates,
     OutgoingPaymentError,
 };
+use crate::fees::{scale_fees, FeeMultiplier};
 use crate::gateway_lnrpc::InterceptHtlcRequest;
 use crate::lnrpc_client::ILnRpcClient;
 use crate::state_machine::complete::{
     GatewayCompleteCommon, GatewayCompleteStates, WaitForPreimageState,
 };
+use crate::state_machine::transfer::{
+    GatewayTransferCommon, GatewayTransferFundRecipient, GatewayTransferStateMachine,
+    GatewayTransferStates, TransferError,
+};
 use crate::{FederationToClientMap, ScidToFederationMap};
 
 pub const GW_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(600);
@@ -102,10 +110,35 @@ pub enum GatewayExtReceiveStates {
     },
 }
 
+/// The high-level state of a transfer to another federation started with
+/// [`GatewayClientExt::gateway_transfer`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum GatewayExtTransferStates {
+    Created,
+    Preimage {
+        preimage: Preimage,
+    },
+    Success {
+        preimage: Preimage,
+        out_points: Vec<OutPoint>,
+    },
+    Canceled {
+        error: TransferError,
+    },
+    Fail {
+        error: TransferError,
+        error_message: String,
+    },
+    ContractDoesNotExist {
+        contract_id: ContractId,
+    },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum GatewayMeta {
     Pay,
     Receive,
+    Transfer,
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -148,6 +181,16 @@ pub trait GatewayClientExt {
         &self,
         operation_id: OperationId,
     ) -> anyhow::Result<UpdateStreamOrOutcome<GatewayExtReceiveStates>>;
+
+    /// Transfer the funds of a transfer contract of a user of this federation
+    /// to a user of another federation served by this gateway
+    async fn gateway_transfer(&self, payload: TransferPayload) -> anyhow::Result<OperationId>;
+
+    /// Subscribe to updates when the gateway is carrying out a transfer
+    async fn gateway_subscribe_transfer(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<GatewayExtTransferStates>>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -344,6 +387,102 @@ impl GatewayClientExt for ClientArc {
             .await?;
         Ok(operation_id)
     }
+
+    async fn gateway_transfer(&self, payload: TransferPayload) -> anyhow::Result<OperationId> {
+        let (_, instance) = self.get_first_module::<GatewayClientModule>(&KIND);
+
+        self.db()
+            .autocommit(
+                |dbtx| {
+                    let payload = payload.clone();
+                    Box::pin(async move {
+                        let operation_id = OperationId(payload.contract_id.into_inner());
+
+                        let state_machine =
+                            GatewayClientStateMachines::Transfer(GatewayTransferStateMachine {
+                                common: GatewayTransferCommon { operation_id },
+                                state: GatewayTransferStates::FundRecipient(
+                                    GatewayTransferFundRecipient { payload },
+                                ),
+                            });
+
+                        self.add_state_machines(dbtx, vec![state_machine.into_dyn(instance.id)])
+                            .await?;
+                        self.operation_log()
+                            .add_operation_log_entry(
+                                dbtx,
+                                operation_id,
+                                KIND.as_str(),
+                                GatewayMeta::Transfer,
+                            )
+                            .await;
+
+                        Ok(operation_id)
+                    })
+                },
+                Some(100),
+            )
+            .await
+            .map_err(|e| match e {
+                AutocommitError::ClosureError { error, .. } => error,
+                AutocommitError::CommitFailed { last_error, .. } => {
+                    anyhow::anyhow!("Commit to DB failed: {last_error}")
+                }
+            })
+    }
+
+    async fn gateway_subscribe_transfer(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<GatewayExtTransferStates>> {
+        let mut stream = self
+            .get_first_module::<GatewayClientModule>(&KIND)
+            .0
+            .notifier
+            .subscribe(operation_id)
+            .await;
+        let operation = ln_operation(self, operation_id).await?;
+        let client = self.clone();
+
+        Ok(operation.outcome_or_updates(self.db(), operation_id, || {
+            stream! {
+                yield GatewayExtTransferStates::Created;
+
+                loop {
+                    if let Some(GatewayClientStateMachines::Transfer(state)) = stream.next().await {
+                        match state.state {
+                            GatewayTransferStates::Claimed(out_points, preimage) => {
+                                yield GatewayExtTransferStates::Preimage{ preimage: preimage.clone() };
+
+                                if client.await_primary_module_outputs(operation_id, out_points.clone()).await.is_ok() {
+                                    yield GatewayExtTransferStates::Success{ preimage, out_points };
+                                    return;
+                                }
+                            }
+                            GatewayTransferStates::Canceled { txid, contract_id: _, error } => {
+                                match client.transaction_updates(operation_id).await.await_tx_accepted(txid).await {
+                                    Ok(()) => {
+                                        yield GatewayExtTransferStates::Canceled{ error };
+                                        return;
+                                    }
+                                    Err(e) => {
+                                        yield GatewayExtTransferStates::Fail { error, error_message: format!("Cancel transaction {txid} was not accepted by the federation. OperationId: {operation_id} Error: {e:?}") };
+                                    }
+                                }
+                            }
+                            GatewayTransferStates::ContractDoesNotExist(contract_id) => {
+                                yield GatewayExtTransferStates::ContractDoesNotExist { contract_id };
+                            }
+                            GatewayTransferStates::Failed{ error, error_message } => {
+                                yield GatewayExtTransferStates::Fail{ error, error_message };
+                            },
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -356,6 +495,7 @@ pub struct GatewayClientGen {
     pub timelock_delta: u64,
     pub mint_channel_id: u64,
     pub fees: RoutingFees,
+    pub fee_multiplier: FeeMultiplier,
     pub gateway_db: Database,
 }
 
@@ -398,7 +538,9 @@ impl ClientModuleInit for GatewayClientGen {
             timelock_delta: self.timelock_delta,
             mint_channel_id: self.mint_channel_id,
             fees: self.fees,
+            fee_multiplier: self.fee_multiplier.clone(),
             gateway_db: self.gateway_db.clone(),
+            federation_id: *args.federation_id(),
         })
     }
 }
@@ -414,6 +556,7 @@ pub struct GatewayClientContext {
     pub ln_decoder: Decoder,
     notifier: ModuleNotifier<DynGlobalClientContext, GatewayClientStateMachines>,
     gateway_db: Database,
+    federation_id: FederationId,
 }
 
 impl Context for GatewayClientContext {}
@@ -440,8 +583,10 @@ pub struct GatewayClientModule {
     timelock_delta: u64,
     mint_channel_id: u64,
     fees: RoutingFees,
+    fee_multiplier: FeeMultiplier,
     module_api: DynModuleApi,
     gateway_db: Database,
+    federation_id: FederationId,
 }
 
 impl ClientModule for GatewayClientModule {
@@ -460,6 +605,7 @@ impl ClientModule for GatewayClientModule {
             ln_decoder: self.decoder(),
             notifier: self.notifier.clone(),
             gateway_db: self.gateway_db.clone(),
+            federation_id: self.federation_id,
         }
     }
 
@@ -508,7 +654,7 @@ impl GatewayClientModule {
                 lightning_alias: self.lightning_alias.clone(),
                 api,
                 route_hints,
-                fees: self.fees,
+                fees: scale_fees(self.fees, self.fee_multiplier.load(Ordering::SeqCst)),
                 gateway_id,
             },
             ttl,
@@ -564,6 +710,10 @@ impl GatewayClientModule {
                             operation_id,
                             incoming_chan_id: htlc.incoming_chan_id,
                             htlc_id: htlc.htlc_id,
+                            amount: htlc.outgoing_amount_msat,
+                            fee: htlc
+                                .incoming_amount_msat
+                                .saturating_sub(htlc.outgoing_amount_msat),
                         },
                         state: GatewayCompleteStates::WaitForPreimage(WaitForPreimageState),
                     }),
@@ -616,6 +766,7 @@ pub enum GatewayClientStateMachines {
     Pay(GatewayPayStateMachine),
     Receive(IncomingStateMachine),
     Complete(GatewayCompleteStateMachine),
+    Transfer(GatewayTransferStateMachine),
 }
 
 impl IntoDynInstance for GatewayClientStateMachines {
@@ -654,6 +805,12 @@ impl State for GatewayClientStateMachines {
                     GatewayClientStateMachines::Complete
                 )
             }
+            GatewayClientStateMachines::Transfer(transfer_state) => {
+                sm_enum_variant_translation!(
+                    transfer_state.transitions(context, global_context),
+                    GatewayClientStateMachines::Transfer
+                )
+            }
         }
     }
 
@@ -662,6 +819,7 @@ impl State for GatewayClientStateMachines {
             GatewayClientStateMachines::Pay(pay_state) => pay_state.operation_id(),
             GatewayClientStateMachines::Receive(receive_state) => receive_state.operation_id(),
             GatewayClientStateMachines::Complete(complete_state) => complete_state.operation_id(),
+            GatewayClientStateMachines::Transfer(transfer_state) => transfer_state.operation_id(),
         }
     }
 }
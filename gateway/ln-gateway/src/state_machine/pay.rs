@@ -25,7 +25,10 @@ use tokio_stream::StreamExt;
 use super::{
     GatewayClientContext, GatewayClientExt, GatewayClientStateMachines, GatewayExtReceiveStates,
 };
-use crate::db::PreimageAuthentication;
+use crate::db::{
+    record_federation_earnings, record_payment, FederationEarnings, PaymentLogEntry, PaymentRoute,
+    PreimageAuthentication,
+};
 use crate::fetch_lightning_node_info;
 use crate::gateway_lnrpc::{PayInvoiceRequest, PayInvoiceResponse};
 use crate::lnrpc_client::LightningRpcError;
@@ -285,6 +288,14 @@ impl GatewayPayInvoice {
         {
             Ok(PayInvoiceResponse { preimage, .. }) => {
                 let slice: [u8; 32] = preimage.try_into().expect("Failed to parse preimage");
+                record_outgoing_payment(
+                    &context,
+                    &common,
+                    &contract,
+                    PaymentRoute::Lightning,
+                    None,
+                )
+                .await;
                 GatewayPayStateMachine {
                     common,
                     state: GatewayPayStates::ClaimOutgoingContract(Box::new(
@@ -296,6 +307,14 @@ impl GatewayPayInvoice {
                 }
             }
             Err(error) => {
+                record_outgoing_payment(
+                    &context,
+                    &common,
+                    &contract,
+                    PaymentRoute::Lightning,
+                    Some(error.to_string()),
+                )
+                .await;
                 let outgoing_error = OutgoingPaymentError {
                     contract_id: contract.contract.contract_id(),
                     contract: Some(contract.clone()),
@@ -315,54 +334,47 @@ impl GatewayPayInvoice {
     }
 
     async fn buy_preimage_via_direct_swap(
+        context: &GatewayClientContext,
         client: ClientArc,
         invoice: Bolt11Invoice,
         contract: OutgoingContractAccount,
         common: GatewayPayCommon,
     ) -> GatewayPayStateMachine {
-        match invoice.try_into() {
-            Ok(swap_params) => match client.gateway_handle_direct_swap(swap_params).await {
-                Ok(operation_id) => GatewayPayStateMachine {
-                    common,
-                    state: GatewayPayStates::WaitForSwapPreimage(Box::new(
-                        GatewayPayWaitForSwapPreimage {
-                            contract,
-                            federation_id: client.federation_id(),
-                            operation_id,
-                        },
-                    )),
-                },
-                Err(e) => {
-                    let outgoing_payment_error = OutgoingPaymentError {
-                        contract_id: contract.contract.contract_id(),
-                        contract: Some(contract.clone()),
-                        error_type: OutgoingPaymentErrorType::SwapFailed {
-                            swap_error: format!("Failed to initiate direct swap: {}", e),
-                        },
-                    };
-                    GatewayPayStateMachine {
-                        common,
-                        state: GatewayPayStates::CancelContract(Box::new(
-                            GatewayPayCancelContract {
-                                contract: contract.clone(),
-                                error: outgoing_payment_error,
-                            },
-                        )),
-                    }
-                }
+        let swap_result = match invoice.try_into() {
+            Ok(swap_params) => client.gateway_handle_direct_swap(swap_params).await,
+            Err(e) => Err(e),
+        };
+
+        match swap_result {
+            Ok(operation_id) => GatewayPayStateMachine {
+                common,
+                state: GatewayPayStates::WaitForSwapPreimage(Box::new(
+                    GatewayPayWaitForSwapPreimage {
+                        contract,
+                        federation_id: client.federation_id(),
+                        operation_id,
+                    },
+                )),
             },
             Err(e) => {
+                let swap_error = format!("Failed to initiate direct swap: {}", e);
+                record_outgoing_payment(
+                    context,
+                    &common,
+                    &contract,
+                    PaymentRoute::DirectSwap,
+                    Some(swap_error.clone()),
+                )
+                .await;
                 let outgoing_payment_error = OutgoingPaymentError {
                     contract_id: contract.contract.contract_id(),
                     contract: Some(contract.clone()),
-                    error_type: OutgoingPaymentErrorType::SwapFailed {
-                        swap_error: format!("Failed to initiate direct swap: {}", e),
-                    },
+                    error_type: OutgoingPaymentErrorType::SwapFailed { swap_error },
                 };
                 GatewayPayStateMachine {
                     common,
                     state: GatewayPayStates::CancelContract(Box::new(GatewayPayCancelContract {
-                        contract: contract.clone(),
+                        contract,
                         error: outgoing_payment_error,
                     })),
                 }
@@ -405,6 +417,7 @@ impl GatewayPayInvoice {
                 .await
                 {
                     return Self::buy_preimage_via_direct_swap(
+                        &context,
                         client,
                         payment_parameters.invoice.clone(),
                         contract.clone(),
@@ -613,6 +626,23 @@ impl GatewayPayClaimOutgoingContract {
 
         let out_points = global_context.claim_input(dbtx, client_input).await.1;
 
+        // The fee is what the contract pays us on top of the invoice
+        let invoice_amount = contract
+            .contract
+            .invoice
+            .amount_milli_satoshis()
+            .map_or(Amount::ZERO, Amount::from_msats);
+        record_federation_earnings(
+            &context.gateway_db,
+            context.federation_id,
+            FederationEarnings {
+                payments_sent: 1,
+                outgoing_fees: contract.amount.saturating_sub(invoice_amount),
+                ..Default::default()
+            },
+        )
+        .await;
+
         GatewayPayStateMachine {
             common,
             state: GatewayPayStates::Preimage(out_points, preimage),
@@ -637,10 +667,16 @@ impl GatewayPayWaitForSwapPreimage {
         let operation_id = self.operation_id;
         let contract = self.contract.clone();
         vec![StateTransition::new(
-            Self::await_preimage(context, federation_id, operation_id, contract.clone()),
+            Self::await_preimage(
+                context.clone(),
+                federation_id,
+                operation_id,
+                contract.clone(),
+            ),
             move |_dbtx, result, _old_state| {
                 let c2 = contract.clone();
                 Box::pin(Self::transition_claim_outgoing_contract(
+                    context.clone(),
                     common.clone(),
                     result,
                     c2,
@@ -705,10 +741,21 @@ impl GatewayPayWaitForSwapPreimage {
     }
 
     async fn transition_claim_outgoing_contract(
+        context: GatewayClientContext,
         common: GatewayPayCommon,
         result: Result<Preimage, OutgoingPaymentError>,
         contract: OutgoingContractAccount,
     ) -> GatewayPayStateMachine {
+        let error = result.as_ref().err().map(ToString::to_string);
+        record_outgoing_payment(
+            &context,
+            &common,
+            &contract,
+            PaymentRoute::DirectSwap,
+            error,
+        )
+        .await;
+
         match result {
             Ok(preimage) => GatewayPayStateMachine {
                 common,
@@ -799,3 +846,36 @@ impl GatewayPayCancelContract {
         }
     }
 }
+
+/// Logs the outcome of buying the preimage for the outgoing contract over the
+/// route
+async fn record_outgoing_payment(
+    context: &GatewayClientContext,
+    common: &GatewayPayCommon,
+    contract: &OutgoingContractAccount,
+    route: PaymentRoute,
+    error: Option<String>,
+) {
+    let amount = contract
+        .contract
+        .invoice
+        .amount_milli_satoshis()
+        .map_or(Amount::ZERO, Amount::from_msats);
+    let fee = match error {
+        None => contract.amount.saturating_sub(amount),
+        Some(_) => Amount::ZERO,
+    };
+
+    record_payment(
+        &context.gateway_db,
+        common.operation_id,
+        PaymentLogEntry {
+            federation_id: context.federation_id,
+            route,
+            amount,
+            fee,
+            error,
+        },
+    )
+    .await;
+}
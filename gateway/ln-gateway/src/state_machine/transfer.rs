@@ -0,0 +1,537 @@
+use std::sync::Arc;
+
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
+use fedimint_client::transaction::{ClientInput, ClientOutput};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, OutPoint, TransactionId};
+use fedimint_ln_client::transfer::TransferPayload;
+use fedimint_ln_common::api::LnFederationApi;
+use fedimint_ln_common::contracts::transfer::TransferContractAccount;
+use fedimint_ln_common::contracts::{ContractId, FundedContract, IdentifiableContract, Preimage};
+use fedimint_ln_common::{LightningInput, LightningOutput};
+use futures::future;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio_stream::StreamExt;
+
+use super::{
+    GatewayClientContext, GatewayClientExt, GatewayClientStateMachines, GatewayExtReceiveStates,
+    SwapParameters,
+};
+use crate::db::{
+    record_federation_earnings, record_payment, FederationEarnings, PaymentLogEntry, PaymentRoute,
+};
+
+#[cfg_attr(doc, aquamarine::aquamarine)]
+/// State machine that carries out a transfer from a user of this federation to
+/// a user of another federation served by the gateway. The gateway buys the
+/// preimage of the transfer contract by funding the recipient's incoming
+/// contract in the other federation.
+///
+/// ```mermaid
+/// graph LR
+/// classDef virtual fill:#fff,stroke-dasharray: 5 5
+///
+///    FundRecipient -- fetch contract failed --> ContractDoesNotExist
+///    FundRecipient -- validate contract failed --> CancelContract
+///    FundRecipient -- direct swap failed --> CancelContract
+///    FundRecipient -- direct swap started --> WaitForPreimage
+///    WaitForPreimage -- received preimage --> ClaimContract
+///    WaitForPreimage -- wait for preimage failed --> CancelContract
+///    ClaimContract -- claim tx submission --> Claimed
+///    CancelContract -- cancel tx submission successful --> Canceled
+///    CancelContract -- cancel tx submission unsuccessful --> Failed
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum GatewayTransferStates {
+    FundRecipient(GatewayTransferFundRecipient),
+    WaitForPreimage(Box<GatewayTransferWaitForPreimage>),
+    ClaimContract(Box<GatewayTransferClaimContract>),
+    CancelContract(Box<GatewayTransferCancelContract>),
+    Claimed(Vec<OutPoint>, Preimage),
+    ContractDoesNotExist(ContractId),
+    Canceled {
+        txid: TransactionId,
+        contract_id: ContractId,
+        error: TransferError,
+    },
+    Failed {
+        error: TransferError,
+        error_message: String,
+    },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct GatewayTransferCommon {
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct GatewayTransferStateMachine {
+    pub common: GatewayTransferCommon,
+    pub state: GatewayTransferStates,
+}
+
+impl State for GatewayTransferStateMachine {
+    type ModuleContext = GatewayClientContext;
+
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        context: &Self::ModuleContext,
+        global_context: &Self::GlobalContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            GatewayTransferStates::FundRecipient(fund_recipient) => fund_recipient.transitions(
+                global_context.clone(),
+                context.clone(),
+                self.common.clone(),
+            ),
+            GatewayTransferStates::WaitForPreimage(wait_for_preimage) => {
+                wait_for_preimage.transitions(context.clone(), self.common.clone())
+            }
+            GatewayTransferStates::ClaimContract(claim_contract) => claim_contract.transitions(
+                global_context.clone(),
+                context.clone(),
+                self.common.clone(),
+            ),
+            GatewayTransferStates::CancelContract(cancel_contract) => cancel_contract.transitions(
+                global_context.clone(),
+                context.clone(),
+                self.common.clone(),
+            ),
+            _ => {
+                vec![]
+            }
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.common.operation_id
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize, Encodable, Decodable, Clone, Eq, PartialEq)]
+pub enum TransferError {
+    #[error("Transfer contract {contract_id} does not exist")]
+    ContractDoesNotExist { contract_id: ContractId },
+    #[error("Contract {contract_id} is not a transfer contract")]
+    NotTransferContract { contract_id: ContractId },
+    #[error("The contract is already cancelled and can't be processed by the gateway")]
+    CancelledContract,
+    #[error("The contract is keyed to another gateway")]
+    NotOurKey,
+    #[error("Transfer contract is underfunded, wants us to transfer {0}, but only contains {1}")]
+    Underfunded(Amount, Amount),
+    #[error("The contract's timeout is in the past or does not allow for a safety margin")]
+    TimeoutTooClose,
+    #[error("The gateway does not serve federation {0}")]
+    UnknownFederation(FederationId),
+    #[error("An error occurred while funding the recipient's incoming contract: {swap_error}")]
+    SwapFailed { swap_error: String },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct GatewayTransferFundRecipient {
+    pub payload: TransferPayload,
+}
+
+impl GatewayTransferFundRecipient {
+    fn transitions(
+        &self,
+        global_context: DynGlobalClientContext,
+        context: GatewayClientContext,
+        common: GatewayTransferCommon,
+    ) -> Vec<StateTransition<GatewayTransferStateMachine>> {
+        let contract_id = self.payload.contract_id;
+        vec![StateTransition::new(
+            Self::await_transfer_contract(global_context, contract_id),
+            move |_dbtx, result, _old_state| {
+                Box::pin(Self::transition_fund_recipient(
+                    context.clone(),
+                    result,
+                    common.clone(),
+                    contract_id,
+                ))
+            },
+        )]
+    }
+
+    async fn await_transfer_contract(
+        global_context: DynGlobalClientContext,
+        contract_id: ContractId,
+    ) -> Result<(TransferContractAccount, Option<u64>), TransferError> {
+        let account = global_context
+            .module_api()
+            .wait_contract(contract_id)
+            .await
+            .map_err(|_| TransferError::ContractDoesNotExist { contract_id })?;
+
+        let FundedContract::Transfer(contract) = account.contract else {
+            return Err(TransferError::NotTransferContract { contract_id });
+        };
+
+        let consensus_block_count = global_context
+            .module_api()
+            .fetch_consensus_block_count()
+            .await
+            .ok()
+            .flatten();
+
+        Ok((
+            TransferContractAccount {
+                amount: account.amount,
+                contract,
+            },
+            consensus_block_count,
+        ))
+    }
+
+    async fn transition_fund_recipient(
+        context: GatewayClientContext,
+        result: Result<(TransferContractAccount, Option<u64>), TransferError>,
+        common: GatewayTransferCommon,
+        contract_id: ContractId,
+    ) -> GatewayTransferStateMachine {
+        let (contract, consensus_block_count) = match result {
+            Ok(result) => result,
+            Err(_) => {
+                return GatewayTransferStateMachine {
+                    common,
+                    state: GatewayTransferStates::ContractDoesNotExist(contract_id),
+                };
+            }
+        };
+
+        let state = match Self::fund_recipient(&context, &contract, consensus_block_count).await {
+            Ok((federation_id, operation_id)) => {
+                GatewayTransferStates::WaitForPreimage(Box::new(GatewayTransferWaitForPreimage {
+                    contract,
+                    federation_id,
+                    operation_id,
+                }))
+            }
+            Err(error) => {
+                GatewayTransferStates::CancelContract(Box::new(GatewayTransferCancelContract {
+                    contract,
+                    error,
+                }))
+            }
+        };
+
+        GatewayTransferStateMachine { common, state }
+    }
+
+    /// Funds the incoming contract of the recipient in their federation, which
+    /// reveals the preimage to us once the federation decrypted it
+    async fn fund_recipient(
+        context: &GatewayClientContext,
+        account: &TransferContractAccount,
+        consensus_block_count: Option<u64>,
+    ) -> Result<(FederationId, OperationId), TransferError> {
+        Self::validate_transfer_account(
+            account,
+            context.redeem_key,
+            context.timelock_delta,
+            consensus_block_count,
+        )?;
+
+        let federation_id = account.contract.federation_id;
+        let client = context
+            .all_clients
+            .read()
+            .await
+            .get(&federation_id)
+            .cloned()
+            .ok_or(TransferError::UnknownFederation(federation_id))?;
+
+        let operation_id = client
+            .gateway_handle_direct_swap(SwapParameters {
+                payment_hash: account.contract.hash,
+                amount_msat: account.contract.amount,
+            })
+            .await
+            .map_err(|e| TransferError::SwapFailed {
+                swap_error: format!("Failed to initiate direct swap: {e}"),
+            })?;
+
+        Ok((federation_id, operation_id))
+    }
+
+    fn validate_transfer_account(
+        account: &TransferContractAccount,
+        redeem_key: bitcoin::KeyPair,
+        timelock_delta: u64,
+        consensus_block_count: Option<u64>,
+    ) -> Result<(), TransferError> {
+        let our_pub_key = secp256k1::XOnlyPublicKey::from_keypair(&redeem_key).0;
+
+        if account.contract.cancelled {
+            return Err(TransferError::CancelledContract);
+        }
+
+        if account.contract.gateway_key != our_pub_key {
+            return Err(TransferError::NotOurKey);
+        }
+
+        if account.amount < account.contract.amount {
+            return Err(TransferError::Underfunded(
+                account.contract.amount,
+                account.amount,
+            ));
+        }
+
+        // The recipient's federation has to accept our funding before the payer can
+        // claim the contract back, which we cannot tell without the block count
+        let consensus_block_count = consensus_block_count.ok_or(TransferError::TimeoutTooClose)?;
+        (account.contract.timelock as u64)
+            .checked_sub(consensus_block_count.saturating_sub(1))
+            .and_then(|delta| delta.checked_sub(timelock_delta))
+            .ok_or(TransferError::TimeoutTooClose)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct GatewayTransferWaitForPreimage {
+    contract: TransferContractAccount,
+    /// The recipient's federation
+    federation_id: FederationId,
+    /// The direct swap funding the recipient's incoming contract
+    operation_id: OperationId,
+}
+
+impl GatewayTransferWaitForPreimage {
+    fn transitions(
+        &self,
+        context: GatewayClientContext,
+        common: GatewayTransferCommon,
+    ) -> Vec<StateTransition<GatewayTransferStateMachine>> {
+        let contract = self.contract.clone();
+        vec![StateTransition::new(
+            Self::await_preimage(context, self.federation_id, self.operation_id),
+            move |_dbtx, result, _old_state| {
+                let contract = contract.clone();
+                let state = match result {
+                    Ok(preimage) => GatewayTransferStates::ClaimContract(Box::new(
+                        GatewayTransferClaimContract { contract, preimage },
+                    )),
+                    Err(error) => GatewayTransferStates::CancelContract(Box::new(
+                        GatewayTransferCancelContract { contract, error },
+                    )),
+                };
+                let common = common.clone();
+
+                Box::pin(async move { GatewayTransferStateMachine { common, state } })
+            },
+        )]
+    }
+
+    async fn await_preimage(
+        context: GatewayClientContext,
+        federation_id: FederationId,
+        operation_id: OperationId,
+    ) -> Result<Preimage, TransferError> {
+        let client = context
+            .all_clients
+            .read()
+            .await
+            .get(&federation_id)
+            .cloned()
+            .ok_or(TransferError::UnknownFederation(federation_id))?;
+
+        let mut stream = client
+            .gateway_subscribe_ln_receive(operation_id)
+            .await
+            .map_err(|e| TransferError::SwapFailed {
+                swap_error: format!("Failed to subscribe to ln receive of direct swap: {e}"),
+            })?
+            .into_stream();
+
+        loop {
+            if let Some(state) = stream.next().await {
+                match state {
+                    GatewayExtReceiveStates::Funding => {
+                        continue;
+                    }
+                    GatewayExtReceiveStates::Preimage(preimage) => {
+                        return Ok(preimage);
+                    }
+                    _ => {
+                        return Err(TransferError::SwapFailed {
+                            swap_error: "Failed to receive preimage".to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct GatewayTransferClaimContract {
+    contract: TransferContractAccount,
+    preimage: Preimage,
+}
+
+impl GatewayTransferClaimContract {
+    fn transitions(
+        &self,
+        global_context: DynGlobalClientContext,
+        context: GatewayClientContext,
+        common: GatewayTransferCommon,
+    ) -> Vec<StateTransition<GatewayTransferStateMachine>> {
+        let contract = self.contract.clone();
+        let preimage = self.preimage.clone();
+        vec![StateTransition::new(
+            future::ready(()),
+            move |dbtx, _, _| {
+                Box::pin(Self::transition_claim_contract(
+                    dbtx,
+                    global_context.clone(),
+                    context.clone(),
+                    common.clone(),
+                    contract.clone(),
+                    preimage.clone(),
+                ))
+            },
+        )]
+    }
+
+    async fn transition_claim_contract(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        global_context: DynGlobalClientContext,
+        context: GatewayClientContext,
+        common: GatewayTransferCommon,
+        contract: TransferContractAccount,
+        preimage: Preimage,
+    ) -> GatewayTransferStateMachine {
+        let client_input = ClientInput::<LightningInput, GatewayClientStateMachines> {
+            input: contract.claim(preimage.clone()),
+            state_machines: Arc::new(|_, _| vec![]),
+            keys: vec![context.redeem_key],
+        };
+
+        let out_points = global_context.claim_input(dbtx, client_input).await.1;
+
+        record_federation_earnings(
+            &context.gateway_db,
+            context.federation_id,
+            FederationEarnings {
+                payments_sent: 1,
+                outgoing_fees: contract.gateway_fee(),
+                ..Default::default()
+            },
+        )
+        .await;
+        record_payment(
+            &context.gateway_db,
+            common.operation_id,
+            PaymentLogEntry {
+                federation_id: context.federation_id,
+                route: PaymentRoute::Transfer,
+                amount: contract.contract.amount,
+                fee: contract.gateway_fee(),
+                error: None,
+            },
+        )
+        .await;
+
+        GatewayTransferStateMachine {
+            common,
+            state: GatewayTransferStates::Claimed(out_points, preimage),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct GatewayTransferCancelContract {
+    contract: TransferContractAccount,
+    error: TransferError,
+}
+
+impl GatewayTransferCancelContract {
+    fn transitions(
+        &self,
+        global_context: DynGlobalClientContext,
+        context: GatewayClientContext,
+        common: GatewayTransferCommon,
+    ) -> Vec<StateTransition<GatewayTransferStateMachine>> {
+        let contract = self.contract.clone();
+        let error = self.error.clone();
+        vec![StateTransition::new(
+            future::ready(()),
+            move |dbtx, _, _| {
+                Box::pin(Self::transition_canceled(
+                    dbtx,
+                    contract.clone(),
+                    global_context.clone(),
+                    context.clone(),
+                    common.clone(),
+                    error.clone(),
+                ))
+            },
+        )]
+    }
+
+    /// Cancels the contract so the payer does not have to wait for the
+    /// timelock to get a refund
+    async fn transition_canceled(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        contract: TransferContractAccount,
+        global_context: DynGlobalClientContext,
+        context: GatewayClientContext,
+        common: GatewayTransferCommon,
+        error: TransferError,
+    ) -> GatewayTransferStateMachine {
+        record_payment(
+            &context.gateway_db,
+            common.operation_id,
+            PaymentLogEntry {
+                federation_id: context.federation_id,
+                route: PaymentRoute::Transfer,
+                amount: contract.contract.amount,
+                fee: Amount::ZERO,
+                error: Some(error.to_string()),
+            },
+        )
+        .await;
+
+        let cancel_signature = context.secp.sign_schnorr(
+            &contract.contract.cancellation_message().into(),
+            &context.redeem_key,
+        );
+        let client_output = ClientOutput::<LightningOutput, GatewayClientStateMachines> {
+            output: LightningOutput::CancelOutgoing {
+                contract: contract.contract.contract_id(),
+                gateway_signature: cancel_signature,
+            },
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+
+        match global_context.fund_output(dbtx, client_output).await {
+            Ok((txid, _)) => GatewayTransferStateMachine {
+                common,
+                state: GatewayTransferStates::Canceled {
+                    txid,
+                    contract_id: contract.contract.contract_id(),
+                    error,
+                },
+            },
+            Err(e) => GatewayTransferStateMachine {
+                common,
+                state: GatewayTransferStates::Failed {
+                    error,
+                    error_message: format!(
+                        "Failed to submit cancel transaction to federation {e:?}"
+                    ),
+                },
+            },
+        }
+    }
+}
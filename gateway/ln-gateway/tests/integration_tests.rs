@@ -9,20 +9,29 @@ use std::time::Duration;
 use assert_matches::assert_matches;
 use bitcoin::Network;
 use bitcoin_hashes::{sha256, Hash};
+use fedimint_client::db::DbKeyPrefix;
+use fedimint_client::module::init::ClientModuleInitRegistry;
+use fedimint_client::multi::MultiClient;
+use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
 use fedimint_client::ClientArc;
 use fedimint_core::config::FederationId;
 use fedimint_core::core::{IntoDynInstance, OperationId};
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCore};
+use fedimint_core::encoding::Encodable;
 use fedimint_core::task::sleep;
 use fedimint_core::util::{NextOrPending, SafeUrl};
 use fedimint_core::{msats, sats, Amount, OutPoint, TransactionId};
 use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
 use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyGen;
+use fedimint_ln_client::multi::{FederationSend, LightningMultiClientExt};
 use fedimint_ln_client::pay::PayInvoicePayload;
 use fedimint_ln_client::{
     LightningClientExt, LightningClientGen, LightningClientModule, LightningClientStateMachines,
-    LightningOperationMeta, LnPayState, LnReceiveState, OutgoingLightningPayment, PayType,
+    LightningOperationMeta, LnPayState, LnReceiveState, OutgoingLightningPayment, OutgoingTransfer,
+    PayType, TransferReceiveState, TransferSendState,
 };
 use fedimint_ln_common::api::LnFederationApi;
 use fedimint_ln_common::config::{GatewayFee, LightningGenParams};
@@ -36,12 +45,17 @@ use fedimint_testing::btc::BitcoinTest;
 use fedimint_testing::federation::FederationTest;
 use fedimint_testing::fixtures::Fixtures;
 use fedimint_testing::gateway::{GatewayTest, LightningNodeType, DEFAULT_GATEWAY_PASSWORD};
+use fedimint_testing::ln::mock::FAKE_CHANNEL_CAPACITY;
 use fedimint_testing::ln::LightningTest;
-use futures::Future;
+use futures::{Future, StreamExt};
 use lightning_invoice::Bolt11Invoice;
+use ln_gateway::db::PaymentRoute;
 use ln_gateway::gateway_lnrpc::GetNodeInfoResponse;
 use ln_gateway::rpc::rpc_client::{GatewayRpcClient, GatewayRpcError, GatewayRpcResult};
-use ln_gateway::rpc::{BalancePayload, ConnectFedPayload, SetConfigurationPayload};
+use ln_gateway::rpc::{
+    BalancePayload, ConnectFedPayload, FeeQuotePayload, PaymentHistoryPayload, PaymentStatsPayload,
+    SetConfigurationPayload, SetFederationFeesPayload,
+};
 use ln_gateway::state_machine::{
     GatewayClientExt, GatewayClientModule, GatewayClientStateMachines, GatewayExtPayStates,
     GatewayExtReceiveStates, GatewayMeta, Htlc, GW_ANNOUNCEMENT_TTL,
@@ -178,6 +192,48 @@ async fn pay_valid_invoice(
     Ok(())
 }
 
+async fn pay_unpayable_invoice(
+    invoice: Bolt11Invoice,
+    user_client: &ClientArc,
+    gateway: &ClientArc,
+) -> anyhow::Result<()> {
+    // User client pays test invoice
+    let OutgoingLightningPayment {
+        payment_type,
+        contract_id,
+        fee: _,
+    } = user_client.pay_bolt11_invoice(invoice.clone()).await?;
+    match payment_type {
+        PayType::Lightning(pay_op) => {
+            let mut pay_sub = user_client.subscribe_ln_pay(pay_op).await?.into_stream();
+            assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+            let funded = pay_sub.ok().await?;
+            assert_matches!(funded, LnPayState::Funded);
+
+            let payload = PayInvoicePayload {
+                federation_id: user_client.federation_id(),
+                contract_id,
+                payment_hash: *invoice.payment_hash(),
+                preimage_auth: Hash::hash(&[0; 32]),
+            };
+
+            let gw_pay_op = gateway.gateway_pay_bolt11_invoice(payload).await?;
+            let mut gw_pay_sub = gateway
+                .gateway_subscribe_ln_pay(gw_pay_op)
+                .await?
+                .into_stream();
+            assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
+            assert_matches!(gw_pay_sub.ok().await?, GatewayExtPayStates::Canceled { .. });
+
+            // Assert that the user receives a refund
+            assert_matches!(pay_sub.ok().await?, LnPayState::WaitingForRefund { .. });
+            assert_matches!(pay_sub.ok().await?, LnPayState::Refunded { .. });
+        }
+        _ => panic!("Expected Lightning payment!"),
+    }
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_can_pay_ldk_node() -> anyhow::Result<()> {
     // Running LDK Node with the mock services doesnt provide any additional
@@ -320,39 +376,105 @@ async fn test_gateway_client_pay_unpayable_invoice() -> anyhow::Result<()> {
                 .unpayable_invoice(sats(250), None)
                 .unwrap();
 
-            // User client pays test invoice
-            let OutgoingLightningPayment {
-                payment_type,
-                contract_id,
-                fee: _,
-            } = user_client.pay_bolt11_invoice(invoice.clone()).await?;
-            match payment_type {
-                PayType::Lightning(pay_op) => {
-                    let mut pay_sub = user_client.subscribe_ln_pay(pay_op).await?.into_stream();
-                    assert_eq!(pay_sub.ok().await?, LnPayState::Created);
-                    let funded = pay_sub.ok().await?;
-                    assert_matches!(funded, LnPayState::Funded);
+            pay_unpayable_invoice(invoice, &user_client, &gateway).await?;
 
-                    let payload = PayInvoicePayload {
-                        federation_id: user_client.federation_id(),
-                        contract_id,
-                        payment_hash: *invoice.payment_hash(),
-                        preimage_auth: Hash::hash(&[0; 32]),
-                    };
+            Ok(())
+        },
+    )
+    .await
+}
 
-                    let gw_pay_op = gateway.gateway_pay_bolt11_invoice(payload).await?;
-                    let mut gw_pay_sub = gateway
-                        .gateway_subscribe_ln_pay(gw_pay_op)
-                        .await?
-                        .into_stream();
-                    assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
-                    assert_matches!(gw_pay_sub.ok().await?, GatewayExtPayStates::Canceled { .. });
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_reports_payment_history_stats_and_liquidity() -> anyhow::Result<()> {
+    single_federation_test(
+        |gateway, other_lightning_client, fed, user_client, _| async move {
+            let id = fed.invite_code().id;
+            let rpc = gateway
+                .get_rpc()
+                .await
+                .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+            let gateway_client = gateway.select_client(id).await;
 
-                    // Assert that the user receives a refund
-                    assert_matches!(pay_sub.ok().await?, LnPayState::WaitingForRefund { .. });
-                    assert_matches!(pay_sub.ok().await?, LnPayState::Refunded { .. });
-                }
-                _ => panic!("Expected Lightning payment!"),
+            // Print money for user client
+            let (_, outpoint) = user_client.print_money(sats(1000)).await?;
+            user_client.receive_money(outpoint).await?;
+
+            // One payment succeeds, then one fails
+            let invoice = other_lightning_client.invoice(sats(250), None).await?;
+            pay_valid_invoice(invoice, &user_client, &gateway_client).await?;
+            let invoice = other_lightning_client.unpayable_invoice(sats(100), None)?;
+            pay_unpayable_invoice(invoice, &user_client, &gateway_client).await?;
+
+            // The history lists the most recent payment first
+            let history = rpc
+                .get_payments(PaymentHistoryPayload {
+                    federation_id: Some(id),
+                    before: None,
+                    limit: 10,
+                })
+                .await?;
+            assert_eq!(history.next, None);
+            assert_eq!(history.payments.len(), 2);
+            let failed = &history.payments[0].entry;
+            assert_eq!(failed.federation_id, id);
+            assert_eq!(failed.route, PaymentRoute::Lightning);
+            assert_eq!(failed.amount, sats(100));
+            assert_eq!(failed.fee, Amount::ZERO);
+            assert!(failed.error.is_some());
+            let succeeded = &history.payments[1].entry;
+            assert_eq!(succeeded.federation_id, id);
+            assert_eq!(succeeded.route, PaymentRoute::Lightning);
+            assert_eq!(succeeded.amount, sats(250));
+            assert_eq!(succeeded.error, None);
+
+            // Paging through the history returns the same payments
+            let first_page = rpc
+                .get_payments(PaymentHistoryPayload {
+                    federation_id: Some(id),
+                    before: None,
+                    limit: 1,
+                })
+                .await?;
+            assert_eq!(first_page.payments, history.payments[..1]);
+            let next = first_page.next.expect("There is a second page");
+            assert_eq!(next, history.payments[0].key);
+            let second_page = rpc
+                .get_payments(PaymentHistoryPayload {
+                    federation_id: Some(id),
+                    before: Some(next),
+                    limit: 1,
+                })
+                .await?;
+            assert_eq!(second_page.payments, history.payments[1..]);
+            assert_eq!(second_page.next, None);
+
+            // Both payments count towards the success rate of the lightning route
+            let stats = rpc
+                .get_payment_stats(PaymentStatsPayload {
+                    federation_id: Some(id),
+                })
+                .await?;
+            assert_eq!(stats.len(), 1);
+            assert_eq!(stats[0].federation_id, id);
+            assert_eq!(stats[0].route, PaymentRoute::Lightning);
+            assert_eq!(stats[0].stats.succeeded, 1);
+            assert_eq!(stats[0].stats.failed, 1);
+            assert_eq!(stats[0].stats.fees, succeeded.fee);
+            assert_eq!(stats[0].success_rate, Some(0.5));
+
+            // The mock node moves the amount it paid to the remote side of its channel
+            if !Fixtures::is_real_test() {
+                let nodes = rpc.get_channels().await?;
+                assert_eq!(nodes.len(), 1);
+                assert_eq!(nodes[0].error, None);
+                assert_eq!(nodes[0].channels.len(), 1);
+                let channel = &nodes[0].channels[0];
+                assert!(channel.active);
+                assert_eq!(channel.inbound_liquidity, sats(250));
+                assert_eq!(
+                    channel.outbound_liquidity + channel.inbound_liquidity,
+                    FAKE_CHANNEL_CAPACITY
+                );
             }
 
             Ok(())
@@ -828,6 +950,7 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
     // set
     let join_payload = ConnectFedPayload {
         invite_code: fed.invite_code().to_string(),
+        lightning_node: None,
     };
 
     verify_rpc(
@@ -965,6 +1088,7 @@ async fn test_gateway_supports_connecting_multiple_federations() -> anyhow::Resu
             let info = rpc
                 .connect_federation(ConnectFedPayload {
                     invite_code: invite1.to_string(),
+                    lightning_node: None,
                 })
                 .await
                 .unwrap();
@@ -975,6 +1099,7 @@ async fn test_gateway_supports_connecting_multiple_federations() -> anyhow::Resu
             let info = rpc
                 .connect_federation(ConnectFedPayload {
                     invite_code: invite2.to_string(),
+                    lightning_node: None,
                 })
                 .await
                 .unwrap();
@@ -1043,6 +1168,60 @@ async fn test_gateway_shows_balance_for_any_connected_federation() -> anyhow::Re
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_sets_fees_per_federation() -> anyhow::Result<()> {
+    multi_federation_test(
+        LightningNodeType::Lnd,
+        |gateway, rpc, fed1, fed2, _| async move {
+            let id1 = fed1.invite_code().id;
+            let id2 = fed2.invite_code().id;
+            let user_client = fed1.new_client().await;
+
+            connect_federations(&rpc, &[fed1, fed2]).await.unwrap();
+            rpc.set_federation_fees(SetFederationFeesPayload {
+                federation_id: id1,
+                routing_fees: "1000,2000".to_string(),
+            })
+            .await?;
+
+            let federations = rpc.get_federations().await?;
+            assert_eq!(federations.len(), 2);
+            for federation in federations {
+                let expected_fees = if federation.federation_id == id1 {
+                    GatewayFee::from_str("1000,2000")?.0
+                } else {
+                    assert_eq!(federation.federation_id, id2);
+                    DEFAULT_FEES
+                };
+                assert_eq!(federation.fees, expected_fees);
+            }
+
+            // The new fees are announced to the federation right away
+            let gateways = user_client.fetch_registered_gateways().await?;
+            assert!(gateways.into_iter().any(|announcement| {
+                announcement.info.gateway_id == gateway.get_gateway_id()
+                    && announcement.info.fees == GatewayFee::from_str("1000,2000").unwrap().0
+            }));
+
+            // The quote matches the fee the client locks in the outgoing contract
+            let (_, invoice) = user_client
+                .create_bolt11_invoice(msats(100_000), "description".into(), None, "test fee quote")
+                .await?;
+            let quote = rpc
+                .quote_fee(FeeQuotePayload {
+                    federation_id: id1,
+                    invoice,
+                })
+                .await?;
+            assert_eq!(quote.invoice_amount, msats(100_000));
+            assert_eq!(quote.multiplier_percent, 100);
+            assert_eq!(quote.fee, msats(1_000 + 200));
+            Ok(())
+        },
+    )
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_executes_swaps_between_connected_federations() -> anyhow::Result<()> {
     multi_federation_test(
@@ -1120,6 +1299,383 @@ async fn test_gateway_executes_swaps_between_connected_federations() -> anyhow::
             );
             assert_eq!(post_balances[1], pre_balances[1] - invoice_amt.msats);
 
+            // The fee is earned in the federation the invoice was paid from
+            let federations = rpc.get_federations().await?;
+            let earnings1 = &federations
+                .iter()
+                .find(|federation| federation.federation_id == id1)
+                .expect("Federation 1 is connected")
+                .earnings;
+            assert_eq!(earnings1.payments_sent, 1);
+            assert_eq!(earnings1.outgoing_fees, fee);
+            let earnings2 = &federations
+                .iter()
+                .find(|federation| federation.federation_id == id2)
+                .expect("Federation 2 is connected")
+                .earnings;
+            assert_eq!(earnings2.payments_sent, 0);
+            assert_eq!(earnings2.total_fees(), Amount::ZERO);
+
+            // The swap shows up in the payment history and stats of federation 1
+            let history = rpc
+                .get_payments(PaymentHistoryPayload {
+                    federation_id: None,
+                    before: None,
+                    limit: 10,
+                })
+                .await?;
+            assert_eq!(history.next, None);
+            assert_eq!(history.payments.len(), 1);
+            let payment = &history.payments[0].entry;
+            assert_eq!(payment.federation_id, id1);
+            assert_eq!(payment.route, PaymentRoute::DirectSwap);
+            assert_eq!(payment.amount, invoice_amt);
+            assert_eq!(payment.fee, fee);
+            assert_eq!(payment.error, None);
+
+            let stats = rpc
+                .get_payment_stats(PaymentStatsPayload {
+                    federation_id: Some(id1),
+                })
+                .await?;
+            assert_eq!(stats.len(), 1);
+            assert_eq!(stats[0].route, PaymentRoute::DirectSwap);
+            assert_eq!(stats[0].stats.succeeded, 1);
+            assert_eq!(stats[0].success_rate, Some(1.0));
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_transfers_between_connected_federations() -> anyhow::Result<()> {
+    multi_federation_test(
+        LightningNodeType::Lnd,
+        |gateway, rpc, fed1, fed2, _| async move {
+            let id1 = fed1.invite_code().id;
+            let id2 = fed2.invite_code().id;
+
+            let client1 = fed1.new_client().await;
+            let client2 = fed2.new_client().await;
+
+            connect_federations(&rpc, &[fed1, fed2]).await.unwrap();
+            send_msats_to_gateway(&gateway, id1, 10_000).await;
+            send_msats_to_gateway(&gateway, id2, 10_000).await;
+            let pre_balances = get_balances(&rpc, &[id1, id2]).await;
+
+            let deposit_amt = msats(5_000);
+            let (_, outpoint) = client1.print_money(deposit_amt).await?;
+            client1.receive_money(outpoint).await?;
+
+            // User requests a transfer in federation 2, no invoice involved
+            let transfer_amt = msats(2_500);
+            let (receive_op, request) = client2
+                .create_transfer_request(transfer_amt, None, "test gw transfer")
+                .await?;
+            assert_eq!(request.federation_id, id2);
+            let mut receive_sub = client2
+                .subscribe_transfer_receive(receive_op)
+                .await?
+                .into_stream();
+
+            // The request cannot be paid from its own federation
+            assert!(client2.pay_transfer_request(request.clone()).await.is_err());
+
+            // A client pays the request from federation 1
+            let OutgoingTransfer {
+                operation_id: send_op,
+                contract_id: _,
+                fee,
+            } = client1.pay_transfer_request(request).await?;
+            let mut send_sub = client1
+                .subscribe_transfer_send(send_op)
+                .await?
+                .into_stream();
+            assert_eq!(send_sub.ok().await?, TransferSendState::Created);
+            assert_eq!(send_sub.ok().await?, TransferSendState::Funded);
+            let mut update = send_sub.ok().await?;
+            if update == TransferSendState::AwaitingChange {
+                update = send_sub.ok().await?;
+            }
+            assert_matches!(update, TransferSendState::Success { .. });
+            assert_eq!(
+                client1.get_balance().await,
+                deposit_amt - transfer_amt - fee
+            );
+
+            // The recipient claims the transfer in federation 2
+            assert_eq!(receive_sub.ok().await?, TransferReceiveState::Created);
+            assert_matches!(
+                receive_sub.ok().await?,
+                TransferReceiveState::WaitingForTransfer { .. }
+            );
+            assert_eq!(receive_sub.ok().await?, TransferReceiveState::Funded);
+            assert_eq!(receive_sub.ok().await?, TransferReceiveState::AwaitingFunds);
+            assert_eq!(receive_sub.ok().await?, TransferReceiveState::Claimed);
+            assert_eq!(client2.get_balance().await, transfer_amt);
+
+            // The gateway takes the transfer plus its fee in federation 1 and pays the
+            // transfer in federation 2
+            let post_balances = get_balances(&rpc, &[id1, id2]).await;
+            assert_eq!(
+                post_balances[0],
+                pre_balances[0] + (transfer_amt + fee).msats
+            );
+            assert_eq!(post_balances[1], pre_balances[1] - transfer_amt.msats);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+/// Opens the multi-federation wallet stored in `db`
+async fn open_multi_client(db: &Database, secret: &[u8; 64]) -> anyhow::Result<MultiClient> {
+    let mut module_inits = ClientModuleInitRegistry::new();
+    module_inits.attach(DummyClientGen);
+    module_inits.attach(LightningClientGen);
+    MultiClient::new(
+        db.clone(),
+        module_inits,
+        0,
+        PlainRootSecretStrategy::to_root_secret(secret),
+    )
+    .await
+}
+
+/// Prefix of the database partition of the client of a federation
+fn federation_db_prefix(federation_id: FederationId) -> Vec<u8> {
+    let mut prefix = vec![DbKeyPrefix::FederationClientDatabase as u8];
+    prefix.extend(federation_id.consensus_encode_to_vec().unwrap());
+    prefix
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multi_client_persists_joined_federations_separately() -> anyhow::Result<()> {
+    multi_federation_test(LightningNodeType::Lnd, |_, _, fed1, fed2, _| async move {
+        let id1 = fed1.invite_code().id;
+        let id2 = fed2.invite_code().id;
+
+        let db: Database = MemDatabase::new().into();
+        let secret = PlainRootSecretStrategy::random(&mut rand::thread_rng());
+
+        let multi_client = open_multi_client(&db, &secret).await?;
+        let client1 = multi_client.join(fed1.invite_code()).await?;
+        multi_client.join(fed2.invite_code()).await?;
+        assert_eq!(multi_client.federation_ids().await.len(), 2);
+
+        let deposit_amt = msats(5_000);
+        let (print_op, outpoint) = client1.print_money(deposit_amt).await?;
+        client1.receive_money(outpoint).await?;
+
+        // The funds and the operation only show up in federation 1
+        let balances = multi_client.get_balances().await;
+        assert_eq!(balances[&id1], deposit_amt);
+        assert_eq!(balances[&id2], Amount::ZERO);
+        assert_eq!(multi_client.get_balance().await, deposit_amt);
+        let operations = multi_client.list_operations(10, None).await;
+        assert!(!operations.is_empty());
+        assert!(operations
+            .iter()
+            .all(|(federation_id, _, _)| *federation_id == id1));
+        assert!(operations
+            .iter()
+            .any(|(_, key, _)| key.operation_id == print_op));
+
+        // Each client only writes to the partition of its federation
+        let mut dbtx = db.begin_transaction().await;
+        let keys = dbtx
+            .raw_find_by_prefix(&[])
+            .await?
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        let partition1 = federation_db_prefix(id1);
+        let partition2 = federation_db_prefix(id2);
+        assert!(keys.iter().any(|key| key.starts_with(&partition1)));
+        assert!(keys.iter().any(|key| key.starts_with(&partition2)));
+        assert!(keys.iter().all(|key| {
+            key[0] == DbKeyPrefix::JoinedFederation as u8
+                || key.starts_with(&partition1)
+                || key.starts_with(&partition2)
+        }));
+        drop(dbtx);
+
+        // Reopening the wallet restores both federations with their state
+        drop(client1);
+        drop(multi_client);
+        let multi_client = open_multi_client(&db, &secret).await?;
+        let balances = multi_client.get_balances().await;
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[&id1], deposit_amt);
+        assert_eq!(balances[&id2], Amount::ZERO);
+
+        // Joining again returns the restored client instead of a new one
+        let client1 = multi_client.join(fed1.invite_code()).await?;
+        assert_eq!(client1.get_balance().await, deposit_amt);
+        assert_eq!(multi_client.federation_ids().await.len(), 2);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multi_client_leaves_federation() -> anyhow::Result<()> {
+    multi_federation_test(LightningNodeType::Lnd, |_, _, fed1, fed2, _| async move {
+        let id1 = fed1.invite_code().id;
+        let id2 = fed2.invite_code().id;
+
+        let db: Database = MemDatabase::new().into();
+        let secret = PlainRootSecretStrategy::random(&mut rand::thread_rng());
+
+        let multi_client = open_multi_client(&db, &secret).await?;
+        let client1 = multi_client.join(fed1.invite_code()).await?;
+        multi_client.join(fed2.invite_code()).await?;
+
+        let deposit_amt = msats(5_000);
+        let (print_op, outpoint) = client1.print_money(deposit_amt).await?;
+        client1.receive_money(outpoint).await?;
+
+        // Leaving federation 2 does not touch federation 1
+        multi_client.leave(id2).await?;
+        assert_eq!(multi_client.federation_ids().await, vec![id1]);
+        assert!(multi_client.get(id2).await.is_none());
+        assert_eq!(multi_client.get_balance().await, deposit_amt);
+        assert!(multi_client
+            .list_operations(10, None)
+            .await
+            .iter()
+            .any(|(_, key, _)| key.operation_id == print_op));
+        assert!(multi_client.leave(id2).await.is_err());
+
+        // Federation 1 can not be left while holding funds
+        assert!(multi_client.leave(id1).await.is_err());
+
+        // Only federation 1 is restored when reopening the wallet
+        drop(client1);
+        drop(multi_client);
+        let multi_client = open_multi_client(&db, &secret).await?;
+        assert_eq!(multi_client.federation_ids().await, vec![id1]);
+        assert_eq!(multi_client.get_balance().await, deposit_amt);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multi_client_sends_between_federations() -> anyhow::Result<()> {
+    multi_federation_test(
+        LightningNodeType::Lnd,
+        |gateway, rpc, fed1, fed2, _| async move {
+            let id1 = fed1.invite_code().id;
+            let id2 = fed2.invite_code().id;
+
+            let db: Database = MemDatabase::new().into();
+            let secret = PlainRootSecretStrategy::random(&mut rand::thread_rng());
+            let multi_client = open_multi_client(&db, &secret).await?;
+            let client1 = multi_client.join(fed1.invite_code()).await?;
+            let client2 = multi_client.join(fed2.invite_code()).await?;
+
+            connect_federations(&rpc, &[fed1, fed2]).await.unwrap();
+            send_msats_to_gateway(&gateway, id1, 10_000).await;
+            send_msats_to_gateway(&gateway, id2, 10_000).await;
+
+            let deposit_amt = msats(5_000);
+            let (_, outpoint) = client1.print_money(deposit_amt).await?;
+            client1.receive_money(outpoint).await?;
+
+            assert!(multi_client
+                .send_between_federations(id1, id1, msats(1_000))
+                .await
+                .is_err());
+
+            let send_amt = msats(2_500);
+            let FederationSend {
+                payment,
+                receive_operation_id,
+            } = multi_client
+                .send_between_federations(id1, id2, send_amt)
+                .await?;
+            let mut receive_sub = client2
+                .subscribe_ln_receive(receive_operation_id)
+                .await?
+                .into_stream();
+
+            match payment.payment_type {
+                PayType::Lightning(pay_op) => {
+                    let mut pay_sub = client1.subscribe_ln_pay(pay_op).await?.into_stream();
+                    assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+                    assert_matches!(pay_sub.ok().await?, LnPayState::Funded);
+                }
+                _ => panic!("Expected Lightning payment!"),
+            }
+
+            assert_eq!(receive_sub.ok().await?, LnReceiveState::Created);
+            assert_matches!(
+                receive_sub.ok().await?,
+                LnReceiveState::WaitingForPayment { .. }
+            );
+            assert_matches!(receive_sub.ok().await?, LnReceiveState::Funded);
+            assert_matches!(
+                receive_sub.ok().await?,
+                LnReceiveState::AwaitingFunds { .. }
+            );
+            assert_matches!(receive_sub.ok().await?, LnReceiveState::Claimed);
+
+            let balances = multi_client.get_balances().await;
+            assert_eq!(balances[&id1], deposit_amt - send_amt - payment.fee);
+            assert_eq!(balances[&id2], send_amt);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_multi_client_send_between_federations_is_refunded() -> anyhow::Result<()> {
+    multi_federation_test(
+        LightningNodeType::Lnd,
+        |gateway, rpc, fed1, fed2, _| async move {
+            let id1 = fed1.invite_code().id;
+            let id2 = fed2.invite_code().id;
+
+            let db: Database = MemDatabase::new().into();
+            let secret = PlainRootSecretStrategy::random(&mut rand::thread_rng());
+            let multi_client = open_multi_client(&db, &secret).await?;
+            let client1 = multi_client.join(fed1.invite_code()).await?;
+            multi_client.join(fed2.invite_code()).await?;
+
+            // The gateway has no funds in federation 2, so it can not complete the swap
+            connect_federations(&rpc, &[fed1, fed2]).await.unwrap();
+            send_msats_to_gateway(&gateway, id1, 10_000).await;
+
+            let deposit_amt = msats(5_000);
+            let (_, outpoint) = client1.print_money(deposit_amt).await?;
+            client1.receive_money(outpoint).await?;
+
+            let FederationSend { payment, .. } = multi_client
+                .send_between_federations(id1, id2, msats(2_500))
+                .await?;
+            match payment.payment_type {
+                PayType::Lightning(pay_op) => {
+                    let mut pay_sub = client1.subscribe_ln_pay(pay_op).await?.into_stream();
+                    assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+                    assert_matches!(pay_sub.ok().await?, LnPayState::Funded);
+                    assert_matches!(pay_sub.ok().await?, LnPayState::WaitingForRefund { .. });
+                    assert_matches!(pay_sub.ok().await?, LnPayState::Refunded { .. });
+                }
+                _ => panic!("Expected Lightning payment!"),
+            }
+
+            let balances = multi_client.get_balances().await;
+            assert_eq!(balances[&id1], deposit_amt);
+            assert_eq!(balances[&id2], Amount::ZERO);
+
             Ok(())
         },
     )
@@ -1141,8 +1697,11 @@ pub async fn connect_federations(
 ) -> anyhow::Result<()> {
     for fed in feds {
         let invite_code = fed.invite_code().to_string();
-        rpc.connect_federation(ConnectFedPayload { invite_code })
-            .await?;
+        rpc.connect_federation(ConnectFedPayload {
+            invite_code,
+            lightning_node: None,
+        })
+        .await?;
     }
     Ok(())
 }
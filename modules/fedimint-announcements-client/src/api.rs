@@ -0,0 +1,71 @@
+use fedimint_announcements_common::{
+    Announcement, AnnouncementVote, AnnouncementVotes, PublishedAnnouncements,
+};
+use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
+use fedimint_core::endpoint_constants::{
+    ANNOUNCEMENTS_ENDPOINT, ANNOUNCEMENT_VOTES_ENDPOINT, ANNOUNCEMENT_VOTE_ENDPOINT,
+    AWAIT_ANNOUNCEMENT_ENDPOINT,
+};
+use fedimint_core::module::{ApiAuth, ApiRequestErased};
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send};
+
+#[apply(async_trait_maybe_send!)]
+pub trait AnnouncementsFederationApi {
+    async fn announcements(&self, since: u64) -> FederationResult<PublishedAnnouncements>;
+
+    /// Waits until the announcement with the id is published
+    async fn await_announcement(&self, id: u64) -> FederationResult<Announcement>;
+
+    async fn announcement_votes(&self) -> FederationResult<Vec<AnnouncementVotes>>;
+
+    /// Votes for a change as a guardian, must only be sent to the guardian the
+    /// `auth` belongs to
+    async fn announcement_vote(
+        &self,
+        vote: AnnouncementVote,
+        auth: ApiAuth,
+    ) -> FederationResult<()>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> AnnouncementsFederationApi for T
+where
+    T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn announcements(&self, since: u64) -> FederationResult<PublishedAnnouncements> {
+        self.request_current_consensus(
+            ANNOUNCEMENTS_ENDPOINT.to_string(),
+            ApiRequestErased::new(since),
+        )
+        .await
+    }
+
+    async fn await_announcement(&self, id: u64) -> FederationResult<Announcement> {
+        self.request_current_consensus(
+            AWAIT_ANNOUNCEMENT_ENDPOINT.to_string(),
+            ApiRequestErased::new(id),
+        )
+        .await
+    }
+
+    async fn announcement_votes(&self) -> FederationResult<Vec<AnnouncementVotes>> {
+        self.request_current_consensus(
+            ANNOUNCEMENT_VOTES_ENDPOINT.to_string(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn announcement_vote(
+        &self,
+        vote: AnnouncementVote,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request_current_consensus(
+            ANNOUNCEMENT_VOTE_ENDPOINT.to_string(),
+            ApiRequestErased::new(vote).with_auth(auth),
+        )
+        .await
+    }
+}
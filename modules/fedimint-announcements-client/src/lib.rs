@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use async_stream::stream;
+pub use fedimint_announcements_common as common;
+use fedimint_announcements_common::config::{AnnouncementLimits, AnnouncementsClientConfig};
+use fedimint_announcements_common::{
+    Announcement, AnnouncementVotes, AnnouncementsCommonGen, AnnouncementsModuleTypes,
+    PublishedAnnouncements, KIND,
+};
+use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
+use fedimint_client::module::ClientModule;
+use fedimint_client::sm::Context;
+use fedimint_client::ClientArc;
+use fedimint_core::db::DatabaseTransactionRef;
+use fedimint_core::module::{
+    ApiVersion, ExtendsCommonModuleInit, ModuleCommon, MultiApiVersion, TransactionItemAmount,
+};
+use fedimint_core::task::sleep;
+use fedimint_core::util::BoxStream;
+use fedimint_core::{apply, async_trait_maybe_send};
+use states::AnnouncementsStateMachine;
+use tracing::warn;
+
+use crate::api::AnnouncementsFederationApi;
+
+pub mod api;
+pub mod states;
+
+/// How long we wait before asking the federation for the next announcement
+/// again after a request failed
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Exposed API calls for client apps
+#[apply(async_trait_maybe_send!)]
+pub trait AnnouncementsClientExt {
+    /// Returns the announcements with an id of at least `since`, including
+    /// the retracted ones
+    async fn announcements(&self, since: u64) -> anyhow::Result<PublishedAnnouncements>;
+
+    /// Waits until the announcement with the id is published
+    async fn await_announcement(&self, id: u64) -> anyhow::Result<Announcement>;
+
+    /// Yields the announcements starting with the id `since` in the order they
+    /// are published, waiting for new ones forever. Retractions of yielded
+    /// announcements are not reported, [`Self::announcements`] returns them.
+    async fn subscribe_announcements(&self, since: u64) -> BoxStream<'static, (u64, Announcement)>;
+
+    /// Returns the votes that did not reach the threshold yet
+    async fn announcement_votes(&self) -> anyhow::Result<Vec<AnnouncementVotes>>;
+
+    /// Returns the limits of the announcements
+    fn announcement_limits(&self) -> AnnouncementLimits;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl AnnouncementsClientExt for ClientArc {
+    async fn announcements(&self, since: u64) -> anyhow::Result<PublishedAnnouncements> {
+        let (_announcements, instance) = self.get_first_module::<AnnouncementsClientModule>(&KIND);
+        Ok(instance.api.announcements(since).await?)
+    }
+
+    async fn await_announcement(&self, id: u64) -> anyhow::Result<Announcement> {
+        let (_announcements, instance) = self.get_first_module::<AnnouncementsClientModule>(&KIND);
+        Ok(instance.api.await_announcement(id).await?)
+    }
+
+    async fn subscribe_announcements(&self, since: u64) -> BoxStream<'static, (u64, Announcement)> {
+        let (_announcements, instance) = self.get_first_module::<AnnouncementsClientModule>(&KIND);
+
+        Box::pin(stream! {
+            let mut next = since;
+
+            loop {
+                match instance.api.await_announcement(next).await {
+                    Ok(announcement) => {
+                        yield (next, announcement);
+                        next += 1;
+                    }
+                    Err(e) => {
+                        warn!("Failed to fetch announcement {next}: {e}");
+                        sleep(RETRY_DELAY).await;
+                    }
+                }
+            }
+        })
+    }
+
+    async fn announcement_votes(&self) -> anyhow::Result<Vec<AnnouncementVotes>> {
+        let (_announcements, instance) = self.get_first_module::<AnnouncementsClientModule>(&KIND);
+        Ok(instance.api.announcement_votes().await?)
+    }
+
+    fn announcement_limits(&self) -> AnnouncementLimits {
+        let (announcements, _instance) = self.get_first_module::<AnnouncementsClientModule>(&KIND);
+        announcements.cfg.limits
+    }
+}
+
+#[derive(Debug)]
+pub struct AnnouncementsClientModule {
+    cfg: AnnouncementsClientConfig,
+}
+
+/// Data needed by the state machine
+#[derive(Debug, Clone)]
+pub struct AnnouncementsClientContext;
+
+impl Context for AnnouncementsClientContext {}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientModule for AnnouncementsClientModule {
+    type Common = AnnouncementsModuleTypes;
+    type ModuleStateMachineContext = AnnouncementsClientContext;
+    type States = AnnouncementsStateMachine;
+
+    fn context(&self) -> Self::ModuleStateMachineContext {
+        AnnouncementsClientContext
+    }
+
+    fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
+        match *input {}
+    }
+
+    fn output_amount(
+        &self,
+        output: &<Self::Common as ModuleCommon>::Output,
+    ) -> TransactionItemAmount {
+        match *output {}
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnouncementsClientGen;
+
+#[apply(async_trait_maybe_send!)]
+impl ExtendsCommonModuleInit for AnnouncementsClientGen {
+    type Common = AnnouncementsCommonGen;
+
+    async fn dump_database(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        // The client does not store anything
+        Box::new(std::iter::empty())
+    }
+}
+
+/// Generates the client module
+#[apply(async_trait_maybe_send!)]
+impl ClientModuleInit for AnnouncementsClientGen {
+    type Module = AnnouncementsClientModule;
+
+    fn supported_api_versions(&self) -> MultiApiVersion {
+        MultiApiVersion::try_from_iter([ApiVersion { major: 0, minor: 0 }])
+            .expect("no version conflicts")
+    }
+
+    async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
+        Ok(AnnouncementsClientModule {
+            cfg: args.cfg().clone(),
+        })
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeSet;
+
+use fedimint_core::config::EmptyGenParams;
+use fedimint_core::core::ModuleKind;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{plugin_types_trait_impl_config, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::{AnnouncementDraft, AnnouncementsCommonGen, AnnouncementsError};
+
+/// Parameters necessary to generate this module's configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnnouncementsGenParams {
+    pub local: EmptyGenParams,
+    pub consensus: AnnouncementsGenParamsConsensus,
+}
+
+/// Consensus parameters for config generation
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnnouncementsGenParamsConsensus {
+    pub limits: AnnouncementLimits,
+}
+
+/// Contains all the configuration for the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnouncementsConfig {
+    pub local: AnnouncementsConfigLocal,
+    pub private: AnnouncementsConfigPrivate,
+    pub consensus: AnnouncementsConfigConsensus,
+}
+
+/// Contains all the configuration for the client
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable, Hash)]
+pub struct AnnouncementsClientConfig {
+    pub limits: AnnouncementLimits,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct AnnouncementsConfigLocal;
+
+/// Will be the same for every federation member
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct AnnouncementsConfigConsensus {
+    /// Guardians allowed to vote
+    pub peers: BTreeSet<PeerId>,
+    pub limits: AnnouncementLimits,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnouncementsConfigPrivate;
+
+/// Bounds the size of the announcements, which every client may download
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct AnnouncementLimits {
+    pub max_title_len: u32,
+    pub max_body_len: u32,
+    /// Retracted announcements count towards the limit as they are kept
+    pub max_announcements: u32,
+}
+
+impl Default for AnnouncementLimits {
+    fn default() -> Self {
+        AnnouncementLimits {
+            max_title_len: 128,
+            max_body_len: 4096,
+            max_announcements: 1024,
+        }
+    }
+}
+
+impl AnnouncementLimits {
+    /// Checks the sizes of the title and the body and the time window of an
+    /// announcement
+    pub fn validate(&self, draft: &AnnouncementDraft) -> Result<(), AnnouncementsError> {
+        if draft.title.is_empty() {
+            return Err(AnnouncementsError::EmptyTitle);
+        }
+
+        if draft.title.len() > self.max_title_len as usize {
+            return Err(AnnouncementsError::TitleTooLong(
+                draft.title.len(),
+                self.max_title_len,
+            ));
+        }
+
+        if draft.body.len() > self.max_body_len as usize {
+            return Err(AnnouncementsError::BodyTooLong(
+                draft.body.len(),
+                self.max_body_len,
+            ));
+        }
+
+        match (draft.starts_at, draft.ends_at) {
+            (Some(starts_at), Some(ends_at)) if ends_at < starts_at => {
+                Err(AnnouncementsError::EndsBeforeStart)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Wire together the configs for this module
+plugin_types_trait_impl_config!(
+    AnnouncementsCommonGen,
+    AnnouncementsGenParams,
+    EmptyGenParams,
+    AnnouncementsGenParamsConsensus,
+    AnnouncementsConfig,
+    AnnouncementsConfigLocal,
+    AnnouncementsConfigPrivate,
+    AnnouncementsConfigConsensus,
+    AnnouncementsClientConfig
+);
+
+#[cfg(test)]
+mod tests {
+    use super::AnnouncementLimits;
+    use crate::{AnnouncementDraft, AnnouncementKind, AnnouncementsError};
+
+    #[test]
+    fn rejects_invalid_drafts() {
+        let limits = AnnouncementLimits {
+            max_title_len: 11,
+            max_body_len: 8,
+            max_announcements: 1,
+        };
+        let draft = AnnouncementDraft {
+            kind: AnnouncementKind::Maintenance,
+            title: "Maintenance".to_string(),
+            body: "Upgrade".to_string(),
+            starts_at: Some(1_700_000_000),
+            ends_at: Some(1_700_003_600),
+        };
+        assert_eq!(limits.validate(&draft), Ok(()));
+
+        let empty = AnnouncementDraft {
+            title: String::new(),
+            ..draft.clone()
+        };
+        assert_eq!(limits.validate(&empty), Err(AnnouncementsError::EmptyTitle));
+
+        let long_body = AnnouncementDraft {
+            body: "Upgrade to v1".to_string(),
+            ..draft.clone()
+        };
+        assert_eq!(
+            limits.validate(&long_body),
+            Err(AnnouncementsError::BodyTooLong(13, 8))
+        );
+
+        let reversed = AnnouncementDraft {
+            ends_at: Some(1_600_000_000),
+            ..draft
+        };
+        assert_eq!(
+            limits.validate(&reversed),
+            Err(AnnouncementsError::EndsBeforeStart)
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Announcements the guardians of a federation publish to its users, like
+//! upcoming maintenance windows or changes of the federation's policies.
+//!
+//! Guardians vote on publishing or retracting an announcement and the vote
+//! takes effect once a threshold of guardians agreed on it, so clients can
+//! trust an announcement as much as any other consensus state. Announcements
+//! are numbered in the order they were published, which lets clients follow
+//! new ones. The module does not support transactions.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use config::AnnouncementsClientConfig;
+use fedimint_core::core::{Decoder, ModuleKind};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
+use fedimint_core::{plugin_types_trait_impl_common, PeerId};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// The client and server configuration
+pub mod config;
+
+/// Unique name for this module
+pub const KIND: ModuleKind = ModuleKind::from_static_str("announcements");
+
+/// Modules are non-compatible with older versions
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+
+/// Non-transaction items that will be submitted to consensus
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub enum AnnouncementsConsensusItem {
+    /// A guardian's vote to publish or retract an announcement
+    Vote(AnnouncementVote),
+}
+
+/// Changes to the published announcements the guardians vote on
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnouncementVote {
+    /// Publishes the announcement under the next free id
+    Publish(AnnouncementDraft),
+    /// Marks the announcement with the id as retracted, it stays retrievable
+    /// so clients following the announcements learn about the retraction
+    Retract(u64),
+}
+
+/// What an announcement is about, allows clients to present them accordingly
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnouncementKind {
+    Info,
+    /// The federation will be unavailable between `starts_at` and `ends_at`
+    Maintenance,
+    /// The policies of the federation change at `starts_at`
+    PolicyChange,
+}
+
+/// The content of an announcement
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct AnnouncementDraft {
+    pub kind: AnnouncementKind,
+    pub title: String,
+    pub body: String,
+    /// Unix timestamp in seconds the announcement becomes relevant at
+    pub starts_at: Option<u64>,
+    /// Unix timestamp in seconds the announcement stops being relevant at
+    pub ends_at: Option<u64>,
+}
+
+/// An announcement the guardians agreed on
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct Announcement {
+    pub draft: AnnouncementDraft,
+    pub retracted: bool,
+}
+
+/// Announcements by their id
+pub type PublishedAnnouncements = BTreeMap<u64, Announcement>;
+
+/// Votes for a change that did not reach the threshold yet
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AnnouncementVotes {
+    pub vote: AnnouncementVote,
+    pub voters: Vec<PeerId>,
+}
+
+/// The module has no inputs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum AnnouncementsInput {}
+
+/// The module has no outputs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum AnnouncementsOutput {}
+
+/// The module has no outputs, so there are no outcomes either
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum AnnouncementsOutputOutcome {}
+
+/// Reasons for rejecting a vote
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
+pub enum AnnouncementsError {
+    #[error("Titles must not be empty")]
+    EmptyTitle,
+    #[error("Title is {0} bytes long, but at most {1} bytes are allowed")]
+    TitleTooLong(usize, u32),
+    #[error("Body is {0} bytes long, but at most {1} bytes are allowed")]
+    BodyTooLong(usize, u32),
+    #[error("The announcement ends before it starts")]
+    EndsBeforeStart,
+    #[error("The maximum of {0} announcements is published already")]
+    TooManyAnnouncements(u32),
+    #[error("Announcement {0} does not exist")]
+    UnknownAnnouncement(u64),
+    #[error("Announcement {0} is retracted already")]
+    AlreadyRetracted(u64),
+    #[error("Guardian already has the maximum of {0} pending votes")]
+    TooManyVotes(usize),
+}
+
+/// Contains the types defined above
+pub struct AnnouncementsModuleTypes;
+
+// Wire together the types for this module
+plugin_types_trait_impl_common!(
+    AnnouncementsModuleTypes,
+    AnnouncementsClientConfig,
+    AnnouncementsInput,
+    AnnouncementsOutput,
+    AnnouncementsOutputOutcome,
+    AnnouncementsConsensusItem
+);
+
+#[derive(Debug)]
+pub struct AnnouncementsCommonGen;
+
+impl CommonModuleInit for AnnouncementsCommonGen {
+    const CONSENSUS_VERSION: ModuleConsensusVersion = CONSENSUS_VERSION;
+    const KIND: ModuleKind = KIND;
+
+    type ClientConfig = AnnouncementsClientConfig;
+
+    fn decoder() -> Decoder {
+        AnnouncementsModuleTypes::decoder_builder().build()
+    }
+}
+
+impl fmt::Display for AnnouncementsClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AnnouncementsClientConfig")
+    }
+}
+
+impl fmt::Display for AnnouncementsInput {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for AnnouncementsOutput {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for AnnouncementsOutputOutcome {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for AnnouncementsConsensusItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnouncementsConsensusItem::Vote(AnnouncementVote::Publish(draft)) => {
+                write!(f, "Vote to publish \"{}\"", draft.title)
+            }
+            AnnouncementsConsensusItem::Vote(AnnouncementVote::Retract(id)) => {
+                write!(f, "Vote to retract announcement {id}")
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use fedimint_announcements_common::{Announcement, AnnouncementVote};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, PeerId};
+use serde::Serialize;
+use strum_macros::EnumIter;
+
+/// Namespaces DB keys for this module
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    Announcement = 0x01,
+    Vote = 0x02,
+    Proposal = 0x03,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Published announcements by their sequential id
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct AnnouncementKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct AnnouncementPrefix;
+
+impl_db_record!(
+    key = AnnouncementKey,
+    value = Announcement,
+    db_prefix = DbKeyPrefix::Announcement,
+    // Allows clients to wait for the next announcement
+    notify_on_modify = true
+);
+impl_db_lookup!(key = AnnouncementKey, query_prefix = AnnouncementPrefix);
+
+/// Votes of the guardians that did not reach the threshold yet
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct AnnouncementVoteKey(pub AnnouncementVote, pub PeerId);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct AnnouncementVoteKeyPrefix(pub AnnouncementVote);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct AnnouncementVotePrefix;
+
+impl_db_record!(
+    key = AnnouncementVoteKey,
+    value = (),
+    db_prefix = DbKeyPrefix::Vote,
+);
+impl_db_lookup!(
+    key = AnnouncementVoteKey,
+    query_prefix = AnnouncementVoteKeyPrefix,
+    query_prefix = AnnouncementVotePrefix
+);
+
+/// Our own votes, submitted to consensus until the threshold is reached
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct AnnouncementProposalKey(pub AnnouncementVote);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct AnnouncementProposalPrefix;
+
+impl_db_record!(
+    key = AnnouncementProposalKey,
+    value = (),
+    db_prefix = DbKeyPrefix::Proposal,
+);
+impl_db_lookup!(
+    key = AnnouncementProposalKey,
+    query_prefix = AnnouncementProposalPrefix
+);
@@ -0,0 +1,466 @@
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use async_trait::async_trait;
+use fedimint_announcements_common::config::{
+    AnnouncementsClientConfig, AnnouncementsConfig, AnnouncementsConfigConsensus,
+    AnnouncementsConfigLocal, AnnouncementsConfigPrivate, AnnouncementsGenParams,
+};
+use fedimint_announcements_common::{
+    Announcement, AnnouncementVote, AnnouncementVotes, AnnouncementsCommonGen,
+    AnnouncementsConsensusItem, AnnouncementsError, AnnouncementsInput, AnnouncementsModuleTypes,
+    AnnouncementsOutput, AnnouncementsOutputOutcome, PublishedAnnouncements, CONSENSUS_VERSION,
+};
+use fedimint_core::config::{
+    ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
+    TypedServerModuleConfig, TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{
+    DatabaseTransactionRef, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::endpoint_constants::{
+    ANNOUNCEMENTS_ENDPOINT, ANNOUNCEMENT_VOTES_ENDPOINT, ANNOUNCEMENT_VOTE_ENDPOINT,
+    AWAIT_ANNOUNCEMENT_ENDPOINT,
+};
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::{
+    api_endpoint, ApiEndpoint, ApiEndpointContext, ApiError, CoreConsensusVersion,
+    ExtendsCommonModuleInit, InputMeta, ModuleConsensusVersion, ModuleError, PeerHandle,
+    ServerModuleInit, ServerModuleInitArgs, SupportedModuleApiVersions, TransactionItemAmount,
+};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::{push_db_pair_items, NumPeers, OutPoint, PeerId, ServerModule};
+use fedimint_server::check_auth;
+use futures::{future, StreamExt};
+use strum::IntoEnumIterator;
+use tracing::info;
+
+use crate::db::{
+    AnnouncementKey, AnnouncementPrefix, AnnouncementProposalKey, AnnouncementProposalPrefix,
+    AnnouncementVoteKey, AnnouncementVoteKeyPrefix, AnnouncementVotePrefix, DbKeyPrefix,
+};
+
+mod db;
+
+/// Votes are only removed once they reach the threshold, so we bound the
+/// number of votes every guardian can have pending
+const MAX_PENDING_VOTES: usize = 16;
+
+/// Generates the module
+#[derive(Debug, Clone)]
+pub struct AnnouncementsGen;
+
+#[async_trait]
+impl ExtendsCommonModuleInit for AnnouncementsGen {
+    type Common = AnnouncementsCommonGen;
+
+    /// Dumps all database items for debugging
+    async fn dump_database(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        let mut items: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> = BTreeMap::new();
+        let filtered_prefixes = DbKeyPrefix::iter().filter(|f| {
+            prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+        });
+
+        for table in filtered_prefixes {
+            match table {
+                DbKeyPrefix::Announcement => {
+                    push_db_pair_items!(
+                        dbtx,
+                        AnnouncementPrefix,
+                        AnnouncementKey,
+                        Announcement,
+                        items,
+                        "Announcements"
+                    );
+                }
+                DbKeyPrefix::Vote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        AnnouncementVotePrefix,
+                        AnnouncementVoteKey,
+                        (),
+                        items,
+                        "Announcement Votes"
+                    );
+                }
+                DbKeyPrefix::Proposal => {
+                    push_db_pair_items!(
+                        dbtx,
+                        AnnouncementProposalPrefix,
+                        AnnouncementProposalKey,
+                        (),
+                        items,
+                        "Announcement Proposals"
+                    );
+                }
+            }
+        }
+
+        Box::new(items.into_iter())
+    }
+}
+
+#[async_trait]
+impl ServerModuleInit for AnnouncementsGen {
+    type Params = AnnouncementsGenParams;
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+
+    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        &[CONSENSUS_VERSION]
+    }
+
+    fn supported_api_versions(&self) -> SupportedModuleApiVersions {
+        SupportedModuleApiVersions::from_raw(u32::MAX, 0, &[(0, 0)])
+    }
+
+    async fn init(&self, args: &ServerModuleInitArgs<Self>) -> anyhow::Result<DynServerModule> {
+        Ok(Announcements::new(args.cfg().to_typed()?, args.our_peer_id()).into())
+    }
+
+    fn trusted_dealer_gen(
+        &self,
+        peers: &[PeerId],
+        params: &ConfigGenModuleParams,
+    ) -> BTreeMap<PeerId, ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        peers
+            .iter()
+            .map(|&peer| {
+                let config = AnnouncementsConfig {
+                    local: AnnouncementsConfigLocal,
+                    private: AnnouncementsConfigPrivate,
+                    consensus: AnnouncementsConfigConsensus {
+                        peers: peers.iter().copied().collect(),
+                        limits: params.consensus.limits,
+                    },
+                };
+                (peer, config.to_erased())
+            })
+            .collect()
+    }
+
+    async fn distributed_gen(
+        &self,
+        peers: &PeerHandle,
+        params: &ConfigGenModuleParams,
+    ) -> DkgResult<ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        Ok(AnnouncementsConfig {
+            local: AnnouncementsConfigLocal,
+            private: AnnouncementsConfigPrivate,
+            consensus: AnnouncementsConfigConsensus {
+                peers: peers.peers.iter().copied().collect(),
+                limits: params.consensus.limits,
+            },
+        }
+        .to_erased())
+    }
+
+    fn get_client_config(
+        &self,
+        config: &ServerModuleConsensusConfig,
+    ) -> anyhow::Result<AnnouncementsClientConfig> {
+        let config = AnnouncementsConfigConsensus::from_erased(config)?;
+        Ok(AnnouncementsClientConfig {
+            limits: config.limits,
+        })
+    }
+
+    fn validate_config(&self, identity: &PeerId, config: ServerModuleConfig) -> anyhow::Result<()> {
+        let config = config.to_typed::<AnnouncementsConfig>()?;
+
+        if !config.consensus.peers.contains(identity) {
+            bail!("We are not allowed to vote");
+        }
+        Ok(())
+    }
+}
+
+/// Announcements module
+#[derive(Debug)]
+pub struct Announcements {
+    pub cfg: AnnouncementsConfig,
+    pub our_peer_id: PeerId,
+}
+
+#[async_trait]
+impl ServerModule for Announcements {
+    type Common = AnnouncementsModuleTypes;
+    type Gen = AnnouncementsGen;
+
+    async fn consensus_proposal(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> Vec<AnnouncementsConsensusItem> {
+        let proposals: Vec<_> = dbtx
+            .find_by_prefix(&AnnouncementProposalPrefix)
+            .await
+            .collect()
+            .await;
+
+        let mut items = vec![];
+
+        // Keep voting for our proposals until they reach the threshold, which
+        // removes the proposal
+        for (AnnouncementProposalKey(vote), ()) in proposals {
+            if dbtx
+                .get_value(&AnnouncementVoteKey(vote.clone(), self.our_peer_id))
+                .await
+                .is_some()
+            {
+                continue;
+            }
+
+            if self
+                .validate_vote(dbtx, &vote, self.our_peer_id)
+                .await
+                .is_ok()
+            {
+                items.push(AnnouncementsConsensusItem::Vote(vote));
+            }
+        }
+
+        items
+    }
+
+    async fn process_consensus_item<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransactionRef<'b>,
+        consensus_item: AnnouncementsConsensusItem,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        let AnnouncementsConsensusItem::Vote(vote) = consensus_item;
+
+        if dbtx
+            .get_value(&AnnouncementVoteKey(vote.clone(), peer_id))
+            .await
+            .is_some()
+        {
+            bail!("Already received this vote");
+        }
+
+        self.validate_vote(dbtx, &vote, peer_id).await?;
+
+        dbtx.insert_entry(&AnnouncementVoteKey(vote.clone(), peer_id), &())
+            .await;
+
+        let votes = dbtx
+            .find_by_prefix(&AnnouncementVoteKeyPrefix(vote.clone()))
+            .await
+            .count()
+            .await;
+
+        if votes < self.cfg.consensus.peers.threshold() {
+            return Ok(());
+        }
+
+        dbtx.remove_by_prefix(&AnnouncementVoteKeyPrefix(vote.clone()))
+            .await;
+        dbtx.remove_entry(&AnnouncementProposalKey(vote.clone()))
+            .await;
+
+        match vote {
+            AnnouncementVote::Publish(draft) => {
+                let id = dbtx.find_by_prefix(&AnnouncementPrefix).await.count().await as u64;
+
+                info!(id, title = %draft.title, "Guardians published an announcement");
+
+                dbtx.insert_entry(
+                    &AnnouncementKey(id),
+                    &Announcement {
+                        draft,
+                        retracted: false,
+                    },
+                )
+                .await;
+            }
+            AnnouncementVote::Retract(id) => {
+                let mut announcement = dbtx
+                    .get_value(&AnnouncementKey(id))
+                    .await
+                    .expect("Checked by validate_vote");
+
+                info!(id, "Guardians retracted an announcement");
+
+                announcement.retracted = true;
+                dbtx.insert_entry(&AnnouncementKey(id), &announcement).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn process_input<'a, 'b, 'c>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'c>,
+        input: &'b AnnouncementsInput,
+    ) -> Result<InputMeta, ModuleError> {
+        match *input {}
+    }
+
+    async fn process_output<'a, 'b>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'b>,
+        output: &'a AnnouncementsOutput,
+        _out_point: OutPoint,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        match *output {}
+    }
+
+    async fn output_status(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _out_point: OutPoint,
+    ) -> Option<AnnouncementsOutputOutcome> {
+        None
+    }
+
+    async fn audit(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _audit: &mut Audit,
+        _module_instance_id: ModuleInstanceId,
+    ) {
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![
+            api_endpoint! {
+                // Returns the announcements with an id of at least `since`
+                ANNOUNCEMENTS_ENDPOINT,
+                async |_module: &Announcements, context, since: u64| -> PublishedAnnouncements {
+                    let mut dbtx = context.dbtx();
+                    Ok(dbtx
+                        .find_by_prefix(&AnnouncementPrefix)
+                        .await
+                        .filter(|(AnnouncementKey(id), _)| future::ready(since <= *id))
+                        .map(|(AnnouncementKey(id), announcement)| (id, announcement))
+                        .collect()
+                        .await)
+                }
+            },
+            api_endpoint! {
+                // API waits for the announcement to be published
+                AWAIT_ANNOUNCEMENT_ENDPOINT,
+                async |module: &Announcements, context, id: u64| -> Announcement {
+                    Ok(module.await_announcement(context, id).await)
+                }
+            },
+            api_endpoint! {
+                ANNOUNCEMENT_VOTES_ENDPOINT,
+                async |_module: &Announcements, context, _params: ()| -> Vec<AnnouncementVotes> {
+                    let mut dbtx = context.dbtx();
+                    let votes: Vec<_> = dbtx
+                        .find_by_prefix(&AnnouncementVotePrefix)
+                        .await
+                        .collect()
+                        .await;
+                    let mut pending: Vec<AnnouncementVotes> = vec![];
+
+                    // votes for the same change are adjacent as they share the prefix
+                    for (AnnouncementVoteKey(vote, peer_id), ()) in votes {
+                        match pending.last_mut() {
+                            Some(last) if last.vote == vote => last.voters.push(peer_id),
+                            _ => pending.push(AnnouncementVotes {
+                                vote,
+                                voters: vec![peer_id],
+                            }),
+                        }
+                    }
+
+                    Ok(pending)
+                }
+            },
+            api_endpoint! {
+                // Guardians vote for a change, it is submitted to consensus by us
+                ANNOUNCEMENT_VOTE_ENDPOINT,
+                async |module: &Announcements, context, vote: AnnouncementVote| -> () {
+                    check_auth(context)?;
+
+                    let mut dbtx = context.dbtx();
+
+                    module
+                        .validate_vote(&mut dbtx, &vote, module.our_peer_id)
+                        .await
+                        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                    dbtx.insert_entry(&AnnouncementProposalKey(vote), &()).await;
+                    Ok(())
+                }
+            },
+        ]
+    }
+}
+
+impl Announcements {
+    /// Create new module instance
+    pub fn new(cfg: AnnouncementsConfig, our_peer_id: PeerId) -> Announcements {
+        Announcements { cfg, our_peer_id }
+    }
+
+    async fn await_announcement(
+        &self,
+        context: &mut ApiEndpointContext<'_>,
+        id: u64,
+    ) -> Announcement {
+        let future = context.wait_key_exists(AnnouncementKey(id));
+        future.await
+    }
+
+    /// Checks that a vote of the voter would be accepted
+    async fn validate_vote(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        vote: &AnnouncementVote,
+        voter: PeerId,
+    ) -> Result<(), AnnouncementsError> {
+        let limits = self.cfg.consensus.limits;
+
+        match vote {
+            AnnouncementVote::Publish(draft) => {
+                limits.validate(draft)?;
+
+                let announcements = dbtx.find_by_prefix(&AnnouncementPrefix).await.count().await;
+
+                if announcements >= limits.max_announcements as usize {
+                    return Err(AnnouncementsError::TooManyAnnouncements(
+                        limits.max_announcements,
+                    ));
+                }
+            }
+            AnnouncementVote::Retract(id) => match dbtx.get_value(&AnnouncementKey(*id)).await {
+                Some(announcement) if announcement.retracted => {
+                    return Err(AnnouncementsError::AlreadyRetracted(*id));
+                }
+                Some(_) => {}
+                None => return Err(AnnouncementsError::UnknownAnnouncement(*id)),
+            },
+        }
+
+        if dbtx
+            .get_value(&AnnouncementVoteKey(vote.clone(), voter))
+            .await
+            .is_none()
+        {
+            let pending_votes = dbtx
+                .find_by_prefix(&AnnouncementVotePrefix)
+                .await
+                .filter(|(AnnouncementVoteKey(_, peer_id), ())| future::ready(*peer_id == voter))
+                .count()
+                .await;
+
+            if pending_votes >= MAX_PENDING_VOTES {
+                return Err(AnnouncementsError::TooManyVotes(MAX_PENDING_VOTES));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -362,6 +362,7 @@ impl ServerModule for Dummy {
             },
             // IMPORTANT: include the pubkey to validate the user signed this tx
             pub_keys: vec![input.account],
+            fee_payout: Amount::ZERO,
         })
     }
 
@@ -397,6 +398,11 @@ impl ServerModule for Dummy {
         dbtx.get_value(&DummyOutcomeKey(out_point)).await
     }
 
+    /// The balances of the accounts
+    fn committed_state_prefixes(&self) -> Vec<u8> {
+        vec![DbKeyPrefix::Funds as u8]
+    }
+
     async fn audit(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -1,19 +1,21 @@
 use std::sync::Arc;
 
 use anyhow::bail;
-use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
+use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
+use fedimint_client::ClientArc;
 use fedimint_core::api::GlobalFederationApi;
 use fedimint_core::config::ClientModuleConfig;
-use fedimint_core::core::{IntoDynInstance, ModuleKind};
+use fedimint_core::core::{IntoDynInstance, ModuleInstanceId, ModuleKind};
 use fedimint_core::module::ModuleConsensusVersion;
-use fedimint_core::{sats, Amount};
+use fedimint_core::transaction::{Transaction, TransactionOutcome, TransactionRejection};
+use fedimint_core::{sats, Amount, TransactionId};
 use fedimint_dummy_client::states::DummyStateMachine;
 use fedimint_dummy_client::{DummyClientExt, DummyClientGen, DummyClientModule};
 use fedimint_dummy_common::config::{DummyClientConfig, DummyGenParams};
-use fedimint_dummy_common::DummyOutput;
+use fedimint_dummy_common::{fed_key_pair, DummyInput, DummyOutput};
 use fedimint_dummy_server::DummyGen;
 use fedimint_testing::fixtures::Fixtures;
-use secp256k1::Secp256k1;
+use secp256k1::{KeyPair, Secp256k1, XOnlyPublicKey};
 use tracing::debug;
 
 fn fixtures() -> Fixtures {
@@ -121,3 +123,143 @@ async fn unbalanced_transactions_get_rejected() -> anyhow::Result<()> {
         Err(e) => bail!("Unexpected error: {e:?}"),
     }
 }
+
+/// Transactions that are ordered by consensus but fail to process are
+/// rejected, which the federation reports until the transaction is accepted
+/// when it is submitted again.
+#[tokio::test(flavor = "multi_thread")]
+async fn rejected_transactions_report_their_rejection() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let client = fed.new_client().await;
+    let (_dummy, instance) =
+        client.get_first_module::<DummyClientModule>(&fedimint_dummy_common::KIND);
+    let secp = Secp256k1::new();
+    let account = KeyPair::new(&secp, &mut rand::thread_rng());
+    let recipient = KeyPair::new(&secp, &mut rand::thread_rng())
+        .x_only_public_key()
+        .0;
+
+    let funding = dummy_transaction(
+        instance.id,
+        fed_key_pair(),
+        &[(account.x_only_public_key().0, sats(1000))],
+    );
+    let funding_txid = client.api().submit_transaction(funding).await?;
+    assert_eq!(
+        await_outcome(&client, funding_txid).await?,
+        TransactionOutcome::Accepted
+    );
+
+    // Both spends are valid on their own, so both get ordered by consensus, but
+    // only the one processed first can be funded by the account
+    let spend1 = dummy_transaction(instance.id, account, &[(client.account(), sats(1000))]);
+    let spend2 = dummy_transaction(instance.id, account, &[(recipient, sats(1000))]);
+    let (txid1, txid2) = (spend1.tx_hash(), spend2.tx_hash());
+    let (submitted1, submitted2) = tokio::join!(
+        client.api().submit_transaction(spend1.clone()),
+        client.api().submit_transaction(spend2.clone())
+    );
+    submitted1?;
+    submitted2?;
+
+    let outcomes = (
+        await_outcome(&client, txid1).await?,
+        await_outcome(&client, txid2).await?,
+    );
+    let (rejected_tx, rejection) = match outcomes {
+        (TransactionOutcome::Accepted, TransactionOutcome::Rejected(rejection)) => {
+            (spend2, rejection)
+        }
+        (TransactionOutcome::Rejected(rejection), TransactionOutcome::Accepted) => {
+            (spend1, rejection)
+        }
+        outcomes => bail!("Expected exactly one spend to be accepted, got {outcomes:?}"),
+    };
+    assert!(
+        matches!(
+            rejection,
+            TransactionRejection::InvalidInput {
+                index: 0,
+                module_instance_id,
+                ..
+            } if module_instance_id == instance.id
+        ),
+        "Unexpected rejection {rejection:?}"
+    );
+
+    // The rejection is persisted, so it is reported again
+    let rejected_txid = rejected_tx.tx_hash();
+    assert_eq!(
+        await_outcome(&client, rejected_txid).await?,
+        TransactionOutcome::Rejected(rejection)
+    );
+
+    // Once the account is funded again the rejected transaction is accepted
+    let refunding = dummy_transaction(
+        instance.id,
+        fed_key_pair(),
+        &[
+            (account.x_only_public_key().0, sats(500)),
+            (account.x_only_public_key().0, sats(500)),
+        ],
+    );
+    let refunding_txid = client.api().submit_transaction(refunding).await?;
+    assert_eq!(
+        await_outcome(&client, refunding_txid).await?,
+        TransactionOutcome::Accepted
+    );
+    client.api().submit_transaction(rejected_tx).await?;
+    assert_eq!(
+        await_outcome(&client, rejected_txid).await?,
+        TransactionOutcome::Accepted
+    );
+
+    Ok(())
+}
+
+/// Transaction spending the funds of `account` to the given accounts
+fn dummy_transaction(
+    module_instance_id: ModuleInstanceId,
+    account: KeyPair,
+    outputs: &[(XOnlyPublicKey, Amount)],
+) -> Transaction {
+    let input = ClientInput {
+        input: DummyInput {
+            amount: outputs.iter().map(|(_, amount)| *amount).sum(),
+            account: account.x_only_public_key().0,
+        },
+        keys: vec![account],
+        state_machines: Arc::new(move |_, _| Vec::<DummyStateMachine>::new()),
+    };
+    let outputs = outputs
+        .iter()
+        .map(|(account, amount)| {
+            ClientOutput {
+                output: DummyOutput {
+                    amount: *amount,
+                    account: *account,
+                },
+                state_machines: Arc::new(move |_, _| Vec::<DummyStateMachine>::new()),
+            }
+            .into_dyn(module_instance_id)
+        })
+        .collect();
+
+    let tx = TransactionBuilder::new()
+        .with_input(input.into_dyn(module_instance_id))
+        .with_outputs(outputs);
+    tx.build(&Secp256k1::new(), rand::thread_rng()).0
+}
+
+/// Waits until consensus either accepted or rejected the transaction
+async fn await_outcome(
+    client: &ClientArc,
+    txid: TransactionId,
+) -> anyhow::Result<TransactionOutcome> {
+    loop {
+        match client.api().await_transaction_outcome(txid).await? {
+            TransactionOutcome::Pending => continue,
+            outcome => return Ok(outcome),
+        }
+    }
+}
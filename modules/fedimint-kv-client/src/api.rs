@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
+use fedimint_core::endpoint_constants::{
+    KV_ENTRIES_ENDPOINT, KV_ENTRY_ENDPOINT, KV_VOTES_ENDPOINT, KV_VOTE_ENDPOINT,
+};
+use fedimint_core::module::{ApiAuth, ApiRequestErased};
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_kv_common::{KvEntry, KvPendingVotes, KvVote};
+
+#[apply(async_trait_maybe_send!)]
+pub trait KvFederationApi {
+    async fn kv_entry(&self, key: String) -> FederationResult<Option<KvEntry>>;
+
+    async fn kv_entries(&self) -> FederationResult<BTreeMap<String, KvEntry>>;
+
+    async fn kv_votes(&self) -> FederationResult<KvPendingVotes>;
+
+    /// Votes for a value as a guardian, must only be sent to the guardian the
+    /// `auth` belongs to
+    async fn kv_vote(&self, vote: KvVote, auth: ApiAuth) -> FederationResult<()>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> KvFederationApi for T
+where
+    T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn kv_entry(&self, key: String) -> FederationResult<Option<KvEntry>> {
+        self.request_current_consensus(KV_ENTRY_ENDPOINT.to_string(), ApiRequestErased::new(key))
+            .await
+    }
+
+    async fn kv_entries(&self) -> FederationResult<BTreeMap<String, KvEntry>> {
+        self.request_current_consensus(KV_ENTRIES_ENDPOINT.to_string(), ApiRequestErased::default())
+            .await
+    }
+
+    async fn kv_votes(&self) -> FederationResult<KvPendingVotes> {
+        self.request_current_consensus(KV_VOTES_ENDPOINT.to_string(), ApiRequestErased::default())
+            .await
+    }
+
+    async fn kv_vote(&self, vote: KvVote, auth: ApiAuth) -> FederationResult<()> {
+        self.request_current_consensus(
+            KV_VOTE_ENDPOINT.to_string(),
+            ApiRequestErased::new(vote).with_auth(auth),
+        )
+        .await
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
+use fedimint_client::module::ClientModule;
+use fedimint_client::sm::Context;
+use fedimint_client::ClientArc;
+use fedimint_core::db::DatabaseTransactionRef;
+use fedimint_core::module::{
+    ApiVersion, ExtendsCommonModuleInit, ModuleCommon, MultiApiVersion, TransactionItemAmount,
+};
+use fedimint_core::{apply, async_trait_maybe_send};
+pub use fedimint_kv_common as common;
+use fedimint_kv_common::config::{KvClientConfig, KvLimits, KvWritePolicy};
+use fedimint_kv_common::{KvCommonGen, KvEntry, KvModuleTypes, KvPendingVotes, KIND};
+use states::KvStateMachine;
+
+use crate::api::KvFederationApi;
+
+pub mod api;
+pub mod states;
+
+/// Exposed API calls for client apps
+#[apply(async_trait_maybe_send!)]
+pub trait KvClientExt {
+    /// Returns the value the guardians agreed on for the key
+    async fn kv_entry(&self, key: &str) -> anyhow::Result<Option<KvEntry>>;
+
+    /// Returns all values the guardians agreed on
+    async fn kv_entries(&self) -> anyhow::Result<BTreeMap<String, KvEntry>>;
+
+    /// Returns the votes that did not reach the required number of votes yet
+    async fn kv_votes(&self) -> anyhow::Result<KvPendingVotes>;
+
+    /// Returns the limits of the store
+    fn kv_limits(&self) -> KvLimits;
+
+    /// Returns the write policy of the key
+    fn kv_write_policy(&self, key: &str) -> KvWritePolicy;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl KvClientExt for ClientArc {
+    async fn kv_entry(&self, key: &str) -> anyhow::Result<Option<KvEntry>> {
+        let (_kv, instance) = self.get_first_module::<KvClientModule>(&KIND);
+        Ok(instance.api.kv_entry(key.to_string()).await?)
+    }
+
+    async fn kv_entries(&self) -> anyhow::Result<BTreeMap<String, KvEntry>> {
+        let (_kv, instance) = self.get_first_module::<KvClientModule>(&KIND);
+        Ok(instance.api.kv_entries().await?)
+    }
+
+    async fn kv_votes(&self) -> anyhow::Result<KvPendingVotes> {
+        let (_kv, instance) = self.get_first_module::<KvClientModule>(&KIND);
+        Ok(instance.api.kv_votes().await?)
+    }
+
+    fn kv_limits(&self) -> KvLimits {
+        let (kv, _instance) = self.get_first_module::<KvClientModule>(&KIND);
+        kv.cfg.limits
+    }
+
+    fn kv_write_policy(&self, key: &str) -> KvWritePolicy {
+        let (kv, _instance) = self.get_first_module::<KvClientModule>(&KIND);
+        kv.cfg.policies.get(key)
+    }
+}
+
+#[derive(Debug)]
+pub struct KvClientModule {
+    cfg: KvClientConfig,
+}
+
+/// Data needed by the state machine
+#[derive(Debug, Clone)]
+pub struct KvClientContext;
+
+impl Context for KvClientContext {}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientModule for KvClientModule {
+    type Common = KvModuleTypes;
+    type ModuleStateMachineContext = KvClientContext;
+    type States = KvStateMachine;
+
+    fn context(&self) -> Self::ModuleStateMachineContext {
+        KvClientContext
+    }
+
+    fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
+        match *input {}
+    }
+
+    fn output_amount(
+        &self,
+        output: &<Self::Common as ModuleCommon>::Output,
+    ) -> TransactionItemAmount {
+        match *output {}
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KvClientGen;
+
+#[apply(async_trait_maybe_send!)]
+impl ExtendsCommonModuleInit for KvClientGen {
+    type Common = KvCommonGen;
+
+    async fn dump_database(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        // The client does not store anything
+        Box::new(std::iter::empty())
+    }
+}
+
+/// Generates the client module
+#[apply(async_trait_maybe_send!)]
+impl ClientModuleInit for KvClientGen {
+    type Module = KvClientModule;
+
+    fn supported_api_versions(&self) -> MultiApiVersion {
+        MultiApiVersion::try_from_iter([ApiVersion { major: 0, minor: 0 }])
+            .expect("no version conflicts")
+    }
+
+    async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
+        Ok(KvClientModule {
+            cfg: args.cfg().clone(),
+        })
+    }
+}
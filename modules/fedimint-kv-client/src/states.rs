@@ -0,0 +1,35 @@
+use fedimint_client::sm::{DynState, State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::core::{IntoDynInstance, ModuleInstanceId, OperationId};
+use fedimint_core::encoding::{Decodable, Encodable};
+
+use crate::KvClientContext;
+
+/// The module has no operations, so there are no states to track either
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum KvStateMachine {}
+
+impl State for KvStateMachine {
+    type ModuleContext = KvClientContext;
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        _global_context: &Self::GlobalContext,
+    ) -> Vec<StateTransition<Self>> {
+        match *self {}
+    }
+
+    fn operation_id(&self) -> OperationId {
+        match *self {}
+    }
+}
+
+impl IntoDynInstance for KvStateMachine {
+    type DynType = DynState<DynGlobalClientContext>;
+
+    fn into_dyn(self, instance_id: ModuleInstanceId) -> Self::DynType {
+        DynState::from_typed(instance_id, self)
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use fedimint_core::config::EmptyGenParams;
+use fedimint_core::core::ModuleKind;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{plugin_types_trait_impl_config, NumPeers, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::{KvCommonGen, KvError, KvVote};
+
+/// Parameters necessary to generate this module's configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KvGenParams {
+    pub local: EmptyGenParams,
+    pub consensus: KvGenParamsConsensus,
+}
+
+/// Consensus parameters for config generation
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KvGenParamsConsensus {
+    pub limits: KvLimits,
+    pub policies: KvPolicies,
+}
+
+/// Contains all the configuration for the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KvConfig {
+    pub local: KvConfigLocal,
+    pub private: KvConfigPrivate,
+    pub consensus: KvConfigConsensus,
+}
+
+/// Contains all the configuration for the client
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable, Hash)]
+pub struct KvClientConfig {
+    pub limits: KvLimits,
+    pub policies: KvPolicies,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct KvConfigLocal;
+
+/// Will be the same for every federation member
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct KvConfigConsensus {
+    /// Guardians allowed to vote
+    pub peers: BTreeSet<PeerId>,
+    pub limits: KvLimits,
+    pub policies: KvPolicies,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KvConfigPrivate;
+
+/// Bounds the size of the store, it is meant for small pieces of data only
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct KvLimits {
+    pub max_key_len: u32,
+    pub max_value_len: u32,
+    pub max_entries: u32,
+}
+
+impl Default for KvLimits {
+    fn default() -> Self {
+        KvLimits {
+            max_key_len: 64,
+            max_value_len: 4096,
+            max_entries: 256,
+        }
+    }
+}
+
+impl KvLimits {
+    /// Checks the sizes of the key and value of a vote
+    pub fn validate(&self, vote: &KvVote) -> Result<(), KvError> {
+        if vote.key.is_empty() {
+            return Err(KvError::EmptyKey);
+        }
+
+        if vote.key.len() > self.max_key_len as usize {
+            return Err(KvError::KeyTooLong(vote.key.len(), self.max_key_len));
+        }
+
+        match &vote.value {
+            Some(value) if value.len() > self.max_value_len as usize => {
+                Err(KvError::ValueTooLarge(value.len(), self.max_value_len))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Write policies by key prefix, keys without a matching prefix use
+/// [`KvWritePolicy::Threshold`]
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+#[serde(transparent)]
+pub struct KvPolicies(pub BTreeMap<String, KvWritePolicy>);
+
+impl KvPolicies {
+    /// Returns the policy of the longest prefix of the key
+    pub fn get(&self, key: &str) -> KvWritePolicy {
+        self.0
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(KvWritePolicy::Threshold, |(_, policy)| *policy)
+    }
+}
+
+/// Determines which votes are needed to change the value of a key
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(rename_all = "snake_case")]
+pub enum KvWritePolicy {
+    /// A threshold of guardians can set or remove the key
+    Threshold,
+    /// All guardians need to agree to set or remove the key
+    Unanimous,
+    /// A threshold of guardians can set the key once, afterwards it can not be
+    /// changed or removed anymore
+    WriteOnce,
+}
+
+impl KvWritePolicy {
+    /// Number of guardians that need to vote for the same value
+    pub fn required_votes(&self, peers: &BTreeSet<PeerId>) -> usize {
+        match self {
+            KvWritePolicy::Threshold | KvWritePolicy::WriteOnce => peers.threshold(),
+            KvWritePolicy::Unanimous => peers.total(),
+        }
+    }
+}
+
+// Wire together the configs for this module
+plugin_types_trait_impl_config!(
+    KvCommonGen,
+    KvGenParams,
+    EmptyGenParams,
+    KvGenParamsConsensus,
+    KvConfig,
+    KvConfigLocal,
+    KvConfigPrivate,
+    KvConfigConsensus,
+    KvClientConfig
+);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use fedimint_core::PeerId;
+
+    use super::{KvLimits, KvPolicies, KvWritePolicy};
+    use crate::{KvError, KvVote};
+
+    #[test]
+    fn longest_prefix_determines_policy() {
+        let policies = KvPolicies(BTreeMap::from([
+            ("meta.".to_string(), KvWritePolicy::Unanimous),
+            ("meta.genesis.".to_string(), KvWritePolicy::WriteOnce),
+        ]));
+
+        assert_eq!(policies.get("other"), KvWritePolicy::Threshold);
+        assert_eq!(policies.get("meta.name"), KvWritePolicy::Unanimous);
+        assert_eq!(policies.get("meta.genesis.hash"), KvWritePolicy::WriteOnce);
+
+        let peers = (0..4u16).map(PeerId::from).collect::<BTreeSet<_>>();
+
+        assert_eq!(KvWritePolicy::Threshold.required_votes(&peers), 3);
+        assert_eq!(KvWritePolicy::Unanimous.required_votes(&peers), 4);
+    }
+
+    #[test]
+    fn rejects_oversized_votes() {
+        let limits = KvLimits {
+            max_key_len: 4,
+            max_value_len: 2,
+            max_entries: 1,
+        };
+        let vote = |key: &str, value: Option<Vec<u8>>| KvVote {
+            key: key.to_string(),
+            value,
+        };
+
+        assert_eq!(limits.validate(&vote("", None)), Err(KvError::EmptyKey));
+        assert_eq!(
+            limits.validate(&vote("key", Some(vec![0; 3]))),
+            Err(KvError::ValueTooLarge(3, 2))
+        );
+        assert_eq!(
+            limits.validate(&vote("long key", None)),
+            Err(KvError::KeyTooLong(8, 4))
+        );
+        assert_eq!(limits.validate(&vote("key", Some(vec![0; 2]))), Ok(()));
+    }
+}
@@ -0,0 +1,142 @@
+//! A key-value store for small pieces of data the guardians of a federation
+//! need to agree on, like settings shared by guardian tooling or other modules.
+//!
+//! Guardians vote on the value of a key and the value is written once as many
+//! guardians as required by the write policy of the key voted for it. The
+//! module does not support transactions.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use config::KvClientConfig;
+use fedimint_core::core::{Decoder, ModuleKind};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
+use fedimint_core::{plugin_types_trait_impl_common, PeerId};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// The client and server configuration
+pub mod config;
+
+/// Unique name for this module
+pub const KIND: ModuleKind = ModuleKind::from_static_str("kv");
+
+/// Modules are non-compatible with older versions
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+
+/// Non-transaction items that will be submitted to consensus
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub enum KvConsensusItem {
+    /// A guardian's vote for the value of a key
+    Vote(KvVote),
+}
+
+/// Vote for the value of a key, voting for `None` removes the key
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct KvVote {
+    pub key: String,
+    pub value: Option<Vec<u8>>,
+}
+
+/// A value the guardians agreed on
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct KvEntry {
+    pub value: Vec<u8>,
+    /// Incremented every time the value changes
+    pub revision: u64,
+}
+
+/// Votes that did not reach the required number of votes yet by key
+pub type KvPendingVotes = BTreeMap<String, BTreeMap<PeerId, Option<Vec<u8>>>>;
+
+/// The module has no inputs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum KvInput {}
+
+/// The module has no outputs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum KvOutput {}
+
+/// The module has no outputs, so there are no outcomes either
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum KvOutputOutcome {}
+
+/// Reasons for rejecting a vote
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
+pub enum KvError {
+    #[error("Keys must not be empty")]
+    EmptyKey,
+    #[error("Key is {0} bytes long, but at most {1} bytes are allowed")]
+    KeyTooLong(usize, u32),
+    #[error("Value is {0} bytes long, but at most {1} bytes are allowed")]
+    ValueTooLarge(usize, u32),
+    #[error("The store already holds the maximum of {0} entries")]
+    TooManyEntries(u32),
+    #[error("Guardian already has the maximum of {0} pending votes")]
+    TooManyVotes(u32),
+    #[error("Key {0} can only be written once")]
+    WriteOnce(String),
+}
+
+/// Contains the types defined above
+pub struct KvModuleTypes;
+
+// Wire together the types for this module
+plugin_types_trait_impl_common!(
+    KvModuleTypes,
+    KvClientConfig,
+    KvInput,
+    KvOutput,
+    KvOutputOutcome,
+    KvConsensusItem
+);
+
+#[derive(Debug)]
+pub struct KvCommonGen;
+
+impl CommonModuleInit for KvCommonGen {
+    const CONSENSUS_VERSION: ModuleConsensusVersion = CONSENSUS_VERSION;
+    const KIND: ModuleKind = KIND;
+
+    type ClientConfig = KvClientConfig;
+
+    fn decoder() -> Decoder {
+        KvModuleTypes::decoder_builder().build()
+    }
+}
+
+impl fmt::Display for KvClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KvClientConfig")
+    }
+}
+
+impl fmt::Display for KvInput {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for KvOutput {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for KvOutputOutcome {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for KvConsensusItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvConsensusItem::Vote(vote) => match &vote.value {
+                Some(value) => write!(f, "Vote to set {} ({} bytes)", vote.key, value.len()),
+                None => write!(f, "Vote to remove {}", vote.key),
+            },
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, PeerId};
+use fedimint_kv_common::KvEntry;
+use serde::Serialize;
+use strum_macros::EnumIter;
+
+/// Namespaces DB keys for this module
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    Entry = 0x01,
+    Vote = 0x02,
+    Proposal = 0x03,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Values the guardians agreed on
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct KvEntryKey(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct KvEntryPrefix;
+
+impl_db_record!(
+    key = KvEntryKey,
+    value = KvEntry,
+    db_prefix = DbKeyPrefix::Entry,
+    // Allows clients to wait for a value to change
+    notify_on_modify = true
+);
+impl_db_lookup!(key = KvEntryKey, query_prefix = KvEntryPrefix);
+
+/// Votes of the guardians that did not reach the required number of votes yet
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct KvVoteKey(pub String, pub PeerId);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct KvVoteKeyPrefix(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct KvVotePrefix;
+
+impl_db_record!(
+    key = KvVoteKey,
+    value = Option<Vec<u8>>,
+    db_prefix = DbKeyPrefix::Vote,
+);
+impl_db_lookup!(
+    key = KvVoteKey,
+    query_prefix = KvVoteKeyPrefix,
+    query_prefix = KvVotePrefix
+);
+
+/// Our own votes, submitted to consensus until the value is written
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct KvProposalKey(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct KvProposalPrefix;
+
+impl_db_record!(
+    key = KvProposalKey,
+    value = Option<Vec<u8>>,
+    db_prefix = DbKeyPrefix::Proposal,
+);
+impl_db_lookup!(key = KvProposalKey, query_prefix = KvProposalPrefix);
@@ -0,0 +1,441 @@
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use async_trait::async_trait;
+use fedimint_core::config::{
+    ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
+    TypedServerModuleConfig, TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{
+    DatabaseTransactionRef, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::endpoint_constants::{
+    KV_ENTRIES_ENDPOINT, KV_ENTRY_ENDPOINT, KV_VOTES_ENDPOINT, KV_VOTE_ENDPOINT,
+};
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::{
+    api_endpoint, ApiEndpoint, ApiError, CoreConsensusVersion, ExtendsCommonModuleInit, InputMeta,
+    ModuleConsensusVersion, ModuleError, PeerHandle, ServerModuleInit, ServerModuleInitArgs,
+    SupportedModuleApiVersions, TransactionItemAmount,
+};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::{push_db_pair_items, OutPoint, PeerId, ServerModule};
+use fedimint_kv_common::config::{
+    KvClientConfig, KvConfig, KvConfigConsensus, KvConfigLocal, KvConfigPrivate, KvGenParams,
+    KvWritePolicy,
+};
+use fedimint_kv_common::{
+    KvCommonGen, KvConsensusItem, KvEntry, KvError, KvInput, KvModuleTypes, KvOutput,
+    KvOutputOutcome, KvPendingVotes, KvVote, CONSENSUS_VERSION,
+};
+use fedimint_server::check_auth;
+use futures::{future, StreamExt};
+use strum::IntoEnumIterator;
+use tracing::info;
+
+use crate::db::{
+    DbKeyPrefix, KvEntryKey, KvEntryPrefix, KvProposalKey, KvProposalPrefix, KvVoteKey,
+    KvVoteKeyPrefix, KvVotePrefix,
+};
+
+mod db;
+
+/// Generates the module
+#[derive(Debug, Clone)]
+pub struct KvGen;
+
+#[async_trait]
+impl ExtendsCommonModuleInit for KvGen {
+    type Common = KvCommonGen;
+
+    /// Dumps all database items for debugging
+    async fn dump_database(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        let mut items: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> = BTreeMap::new();
+        let filtered_prefixes = DbKeyPrefix::iter().filter(|f| {
+            prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+        });
+
+        for table in filtered_prefixes {
+            match table {
+                DbKeyPrefix::Entry => {
+                    push_db_pair_items!(
+                        dbtx,
+                        KvEntryPrefix,
+                        KvEntryKey,
+                        KvEntry,
+                        items,
+                        "KV Entries"
+                    );
+                }
+                DbKeyPrefix::Vote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        KvVotePrefix,
+                        KvVoteKey,
+                        Option<Vec<u8>>,
+                        items,
+                        "KV Votes"
+                    );
+                }
+                DbKeyPrefix::Proposal => {
+                    push_db_pair_items!(
+                        dbtx,
+                        KvProposalPrefix,
+                        KvProposalKey,
+                        Option<Vec<u8>>,
+                        items,
+                        "KV Proposals"
+                    );
+                }
+            }
+        }
+
+        Box::new(items.into_iter())
+    }
+}
+
+#[async_trait]
+impl ServerModuleInit for KvGen {
+    type Params = KvGenParams;
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+
+    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        &[CONSENSUS_VERSION]
+    }
+
+    fn supported_api_versions(&self) -> SupportedModuleApiVersions {
+        SupportedModuleApiVersions::from_raw(u32::MAX, 0, &[(0, 0)])
+    }
+
+    async fn init(&self, args: &ServerModuleInitArgs<Self>) -> anyhow::Result<DynServerModule> {
+        Ok(Kv::new(args.cfg().to_typed()?, args.our_peer_id()).into())
+    }
+
+    fn trusted_dealer_gen(
+        &self,
+        peers: &[PeerId],
+        params: &ConfigGenModuleParams,
+    ) -> BTreeMap<PeerId, ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        peers
+            .iter()
+            .map(|&peer| {
+                let config = KvConfig {
+                    local: KvConfigLocal,
+                    private: KvConfigPrivate,
+                    consensus: KvConfigConsensus {
+                        peers: peers.iter().copied().collect(),
+                        limits: params.consensus.limits,
+                        policies: params.consensus.policies.clone(),
+                    },
+                };
+                (peer, config.to_erased())
+            })
+            .collect()
+    }
+
+    async fn distributed_gen(
+        &self,
+        peers: &PeerHandle,
+        params: &ConfigGenModuleParams,
+    ) -> DkgResult<ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        Ok(KvConfig {
+            local: KvConfigLocal,
+            private: KvConfigPrivate,
+            consensus: KvConfigConsensus {
+                peers: peers.peers.iter().copied().collect(),
+                limits: params.consensus.limits,
+                policies: params.consensus.policies,
+            },
+        }
+        .to_erased())
+    }
+
+    fn get_client_config(
+        &self,
+        config: &ServerModuleConsensusConfig,
+    ) -> anyhow::Result<KvClientConfig> {
+        let config = KvConfigConsensus::from_erased(config)?;
+        Ok(KvClientConfig {
+            limits: config.limits,
+            policies: config.policies,
+        })
+    }
+
+    fn validate_config(&self, identity: &PeerId, config: ServerModuleConfig) -> anyhow::Result<()> {
+        let config = config.to_typed::<KvConfig>()?;
+
+        if !config.consensus.peers.contains(identity) {
+            bail!("We are not allowed to vote");
+        }
+        Ok(())
+    }
+}
+
+/// Key-value store module
+#[derive(Debug)]
+pub struct Kv {
+    pub cfg: KvConfig,
+    pub our_peer_id: PeerId,
+}
+
+#[async_trait]
+impl ServerModule for Kv {
+    type Common = KvModuleTypes;
+    type Gen = KvGen;
+
+    async fn consensus_proposal(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> Vec<KvConsensusItem> {
+        let proposals: Vec<_> = dbtx.find_by_prefix(&KvProposalPrefix).await.collect().await;
+
+        let mut items = vec![];
+
+        // Keep voting for our proposals until our vote has been counted
+        for (KvProposalKey(key), value) in proposals {
+            let entry = dbtx.get_value(&KvEntryKey(key.clone())).await;
+
+            if entry.map(|entry| entry.value) == value {
+                continue;
+            }
+
+            let our_vote = dbtx
+                .get_value(&KvVoteKey(key.clone(), self.our_peer_id))
+                .await;
+
+            if our_vote == Some(value.clone()) {
+                continue;
+            }
+
+            let vote = KvVote { key, value };
+
+            if self
+                .validate_vote(dbtx, &vote, self.our_peer_id)
+                .await
+                .is_ok()
+            {
+                items.push(KvConsensusItem::Vote(vote));
+            }
+        }
+
+        items
+    }
+
+    async fn process_consensus_item<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransactionRef<'b>,
+        consensus_item: KvConsensusItem,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        let KvConsensusItem::Vote(vote) = consensus_item;
+
+        if dbtx
+            .get_value(&KvVoteKey(vote.key.clone(), peer_id))
+            .await
+            .as_ref()
+            == Some(&vote.value)
+        {
+            bail!("Already received this vote");
+        }
+
+        self.validate_vote(dbtx, &vote, peer_id).await?;
+
+        dbtx.insert_entry(&KvVoteKey(vote.key.clone(), peer_id), &vote.value)
+            .await;
+
+        let votes = dbtx
+            .find_by_prefix(&KvVoteKeyPrefix(vote.key.clone()))
+            .await
+            .filter(|(_, value)| future::ready(*value == vote.value))
+            .count()
+            .await;
+
+        let required_votes = self
+            .cfg
+            .consensus
+            .policies
+            .get(&vote.key)
+            .required_votes(&self.cfg.consensus.peers);
+
+        if votes < required_votes {
+            return Ok(());
+        }
+
+        dbtx.remove_by_prefix(&KvVoteKeyPrefix(vote.key.clone()))
+            .await;
+
+        let entry = dbtx.get_value(&KvEntryKey(vote.key.clone())).await;
+
+        match vote.value {
+            Some(value) if entry.as_ref().map(|entry| &entry.value) != Some(&value) => {
+                info!(key = %vote.key, "Guardians agreed on a new value");
+
+                let revision = entry.map_or(0, |entry| entry.revision + 1);
+
+                dbtx.insert_entry(&KvEntryKey(vote.key), &KvEntry { value, revision })
+                    .await;
+            }
+            Some(_) => {}
+            None => {
+                info!(key = %vote.key, "Guardians agreed to remove the key");
+
+                dbtx.remove_entry(&KvEntryKey(vote.key)).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn process_input<'a, 'b, 'c>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'c>,
+        input: &'b KvInput,
+    ) -> Result<InputMeta, ModuleError> {
+        match *input {}
+    }
+
+    async fn process_output<'a, 'b>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'b>,
+        output: &'a KvOutput,
+        _out_point: OutPoint,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        match *output {}
+    }
+
+    async fn output_status(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _out_point: OutPoint,
+    ) -> Option<KvOutputOutcome> {
+        None
+    }
+
+    async fn audit(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _audit: &mut Audit,
+        _module_instance_id: ModuleInstanceId,
+    ) {
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![
+            api_endpoint! {
+                KV_ENTRY_ENDPOINT,
+                async |_module: &Kv, context, key: String| -> Option<KvEntry> {
+                    let mut dbtx = context.dbtx();
+                    Ok(dbtx.get_value(&KvEntryKey(key)).await)
+                }
+            },
+            api_endpoint! {
+                KV_ENTRIES_ENDPOINT,
+                async |_module: &Kv, context, _params: ()| -> BTreeMap<String, KvEntry> {
+                    let mut dbtx = context.dbtx();
+                    Ok(dbtx
+                        .find_by_prefix(&KvEntryPrefix)
+                        .await
+                        .map(|(KvEntryKey(key), entry)| (key, entry))
+                        .collect()
+                        .await)
+                }
+            },
+            api_endpoint! {
+                KV_VOTES_ENDPOINT,
+                async |_module: &Kv, context, _params: ()| -> KvPendingVotes {
+                    let mut dbtx = context.dbtx();
+                    let votes: Vec<_> = dbtx.find_by_prefix(&KvVotePrefix).await.collect().await;
+                    let mut votes_by_key: KvPendingVotes = BTreeMap::new();
+
+                    for (KvVoteKey(key, peer_id), value) in votes {
+                        votes_by_key.entry(key).or_default().insert(peer_id, value);
+                    }
+
+                    Ok(votes_by_key)
+                }
+            },
+            api_endpoint! {
+                // Guardians vote for a value, it is submitted to consensus by us
+                KV_VOTE_ENDPOINT,
+                async |module: &Kv, context, vote: KvVote| -> () {
+                    check_auth(context)?;
+
+                    let mut dbtx = context.dbtx();
+
+                    module
+                        .validate_vote(&mut dbtx, &vote, module.our_peer_id)
+                        .await
+                        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                    dbtx.insert_entry(&KvProposalKey(vote.key), &vote.value).await;
+                    Ok(())
+                }
+            },
+        ]
+    }
+}
+
+impl Kv {
+    /// Create new module instance
+    pub fn new(cfg: KvConfig, our_peer_id: PeerId) -> Kv {
+        Kv { cfg, our_peer_id }
+    }
+
+    /// Checks that a vote of the peer would be accepted by the write policy
+    /// and limits of the store
+    async fn validate_vote(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        vote: &KvVote,
+        peer_id: PeerId,
+    ) -> Result<(), KvError> {
+        let limits = self.cfg.consensus.limits;
+
+        limits.validate(vote)?;
+
+        let entry = dbtx.get_value(&KvEntryKey(vote.key.clone())).await;
+
+        if entry.is_some() && self.cfg.consensus.policies.get(&vote.key) == KvWritePolicy::WriteOnce
+        {
+            return Err(KvError::WriteOnce(vote.key.clone()));
+        }
+
+        if entry.is_none() && vote.value.is_some() {
+            let entries = dbtx.find_by_prefix(&KvEntryPrefix).await.count().await;
+
+            if entries >= limits.max_entries as usize {
+                return Err(KvError::TooManyEntries(limits.max_entries));
+            }
+        }
+
+        // Votes are only removed once they succeed, so bound the number of
+        // votes every guardian can have pending
+        if dbtx
+            .get_value(&KvVoteKey(vote.key.clone(), peer_id))
+            .await
+            .is_none()
+        {
+            let pending_votes = dbtx
+                .find_by_prefix(&KvVotePrefix)
+                .await
+                .filter(|(KvVoteKey(_, voter), _)| future::ready(*voter == peer_id))
+                .count()
+                .await;
+
+            if pending_votes >= limits.max_entries as usize {
+                return Err(KvError::TooManyVotes(limits.max_entries));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,10 @@
 mod db;
 pub mod incoming;
+pub mod migration;
+pub mod multi;
 pub mod pay;
 mod receive;
+pub mod transfer;
 
 use std::collections::BTreeMap;
 use std::iter::once;
@@ -14,9 +17,11 @@ use async_stream::stream;
 use bitcoin::{KeyPair, Network};
 use bitcoin_hashes::{sha256, Hash};
 use db::{DbKeyPrefix, LightningGatewayKey, PaymentResult, PaymentResultKey};
+use fedimint_client::balance::{BalanceItem, ContractDirection, OperationFees, PendingStage};
 use fedimint_client::derivable_secret::ChildId;
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::{ClientModule, IClientModule};
+use fedimint_client::oplog::OperationLogEntry;
 use fedimint_client::oplog::UpdateStreamOrOutcome;
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{DynState, ModuleNotifier, State, StateTransition};
@@ -38,11 +43,14 @@ use fedimint_core::{
     apply, async_trait_maybe_send, push_db_pair_items, Amount, OutPoint, TransactionId,
 };
 use fedimint_ln_common::api::LnFederationApi;
-use fedimint_ln_common::config::LightningClientConfig;
+use fedimint_ln_common::config::{GatewayFee, LightningClientConfig};
 use fedimint_ln_common::contracts::incoming::{IncomingContract, IncomingContractOffer};
 use fedimint_ln_common::contracts::outgoing::{
     OutgoingContract, OutgoingContractAccount, OutgoingContractData,
 };
+use fedimint_ln_common::contracts::transfer::{
+    TransferContract, TransferContractAccount, TransferRequest,
+};
 use fedimint_ln_common::contracts::{
     Contract, ContractId, DecryptedPreimage, EncryptedPreimage, IdentifiableContract, Preimage,
 };
@@ -78,6 +86,11 @@ use crate::receive::{
     LightningReceiveError, LightningReceiveStateMachine, LightningReceiveStates,
     LightningReceiveSubmittedOffer,
 };
+use crate::transfer::{
+    TransferReceiveCommon, TransferReceiveStateMachine, TransferReceiveStates,
+    TransferReceiveSubmittedOffer, TransferSendCommon, TransferSendCreated,
+    TransferSendStateMachine, TransferSendStates,
+};
 
 /// Number of blocks until outgoing lightning contracts times out and user
 /// client can get refund
@@ -123,6 +136,34 @@ pub trait LightningClientExt {
         &self,
         operation_id: OperationId,
     ) -> anyhow::Result<UpdateStreamOrOutcome<LnReceiveState>>;
+
+    /// Requests a transfer from a user of another federation that is served
+    /// by our active gateway, which they pay with
+    /// [`LightningClientExt::pay_transfer_request`]
+    async fn create_transfer_request<M: Serialize + Send + Sync>(
+        &self,
+        amount: Amount,
+        expiry_time: Option<u64>,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, TransferRequest)>;
+
+    async fn subscribe_transfer_receive(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<TransferReceiveState>>;
+
+    /// Pays the transfer request of a user of another federation through the
+    /// gateway named in the request, which has to be registered with our
+    /// federation as well
+    async fn pay_transfer_request(
+        &self,
+        request: TransferRequest,
+    ) -> anyhow::Result<OutgoingTransfer>;
+
+    async fn subscribe_transfer_send(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<TransferSendState>>;
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
@@ -192,6 +233,43 @@ pub enum LnReceiveState {
     Claimed,
 }
 
+/// The high-level state of a transfer to a user of another federation,
+/// started with [`LightningClientExt::pay_transfer_request`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferSendState {
+    Created,
+    Canceled,
+    Funded,
+    WaitingForRefund {
+        block_height: u32,
+        gateway_error: GatewayPayError,
+    },
+    AwaitingChange,
+    Success {
+        preimage: Preimage,
+    },
+    Refunded {
+        gateway_error: GatewayPayError,
+    },
+    UnexpectedError {
+        error_message: String,
+    },
+}
+
+/// The high-level state of a transfer from a user of another federation,
+/// started with [`LightningClientExt::create_transfer_request`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferReceiveState {
+    Created,
+    WaitingForTransfer { request: TransferRequest },
+    Canceled { reason: LightningReceiveError },
+    Funded,
+    AwaitingFunds,
+    Claimed,
+}
+
 async fn invoice_has_internal_payment_markers(
     invoice: &Bolt11Invoice,
     markers: (secp256k1::PublicKey, u64),
@@ -506,6 +584,230 @@ impl LightningClientExt for ClientArc {
         }))
     }
 
+    async fn create_transfer_request<M: Serialize + Send + Sync>(
+        &self,
+        amount: Amount,
+        expiry_time: Option<u64>,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, TransferRequest)> {
+        let (lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
+        let gateway = self
+            .select_active_gateway()
+            .await
+            .context("Transfers from other federations require a gateway")?;
+
+        let (operation_id, request, output) = lightning.create_transfer_receive_output(
+            amount,
+            expiry_time,
+            gateway.gateway_id,
+            self.get_config().global.federation_id,
+            rand::rngs::OsRng,
+        )?;
+        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+        let extra_meta = serde_json::to_value(extra_meta).expect("extra_meta is serializable");
+        let operation_meta_gen = |txid, _| LightningOperationMeta::TransferReceive {
+            out_point: OutPoint { txid, out_idx: 0 },
+            request: request.clone(),
+            extra_meta: extra_meta.clone(),
+        };
+        let (txid, _) = self
+            .finalize_and_submit_transaction(
+                operation_id,
+                LightningCommonGen::KIND.as_str(),
+                operation_meta_gen,
+                tx,
+            )
+            .await?;
+
+        // The gateway can only fund the incoming contract once the offer was accepted
+        self.transaction_updates(operation_id)
+            .await
+            .await_tx_accepted(txid)
+            .await
+            .map_err(|e| anyhow::anyhow!("Offer transaction was not accepted: {e:?}"))?;
+
+        Ok((operation_id, request))
+    }
+
+    async fn subscribe_transfer_receive(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<TransferReceiveState>> {
+        let operation = ln_operation(self, operation_id).await?;
+        let (out_point, request) = match operation.meta::<LightningOperationMeta>() {
+            LightningOperationMeta::TransferReceive {
+                out_point, request, ..
+            } => (out_point, request),
+            _ => bail!("Operation is not a transfer from another federation"),
+        };
+
+        let tx_accepted_future = self
+            .transaction_updates(operation_id)
+            .await
+            .await_tx_accepted(out_point.txid);
+
+        let client = self.clone();
+
+        Ok(operation.outcome_or_updates(self.db(), operation_id, || {
+            stream! {
+                let lightning = client
+                    .get_first_module::<LightningClientModule>(&KIND)
+                    .0;
+
+                yield TransferReceiveState::Created;
+
+                if tx_accepted_future.await.is_err() {
+                    yield TransferReceiveState::Canceled { reason: LightningReceiveError::Rejected };
+                    return;
+                }
+                yield TransferReceiveState::WaitingForTransfer { request };
+
+                if let Err(e) = lightning.await_transfer_funded(operation_id).await {
+                    yield TransferReceiveState::Canceled { reason: e };
+                    return;
+                }
+                yield TransferReceiveState::Funded;
+
+                match lightning.await_transfer_claimed(operation_id).await {
+                    Ok(out_points) => {
+                        yield TransferReceiveState::AwaitingFunds;
+
+                        if client.await_primary_module_outputs(operation_id, out_points).await.is_ok() {
+                            yield TransferReceiveState::Claimed;
+                            return;
+                        }
+
+                        yield TransferReceiveState::Canceled { reason: LightningReceiveError::Rejected };
+                    }
+                    Err(e) => {
+                        yield TransferReceiveState::Canceled { reason: e };
+                    }
+                }
+            }
+        }))
+    }
+
+    async fn pay_transfer_request(
+        &self,
+        request: TransferRequest,
+    ) -> anyhow::Result<OutgoingTransfer> {
+        let (lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
+        let federation_id = self.get_config().global.federation_id;
+        ensure!(
+            request.federation_id != federation_id,
+            "Transfer request is for our own federation, pay an invoice instead"
+        );
+        ensure!(!request.is_expired(), "Transfer request expired");
+
+        let gateway = self
+            .fetch_registered_gateways()
+            .await?
+            .into_iter()
+            .map(|gw| gw.info)
+            .find(|gw| gw.gateway_id == request.gateway_id)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Gateway {} of the transfer request is not registered with our federation",
+                    request.gateway_id
+                )
+            })?;
+
+        let (operation_id, output, contract_id, fee) = lightning
+            .create_transfer_send_output(
+                instance.api,
+                request.clone(),
+                gateway,
+                federation_id,
+                rand::rngs::OsRng,
+            )
+            .await?;
+
+        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+        let operation_meta_gen = |txid, change| LightningOperationMeta::TransferSend {
+            out_point: OutPoint { txid, out_idx: 0 },
+            request: request.clone(),
+            fee,
+            change,
+        };
+
+        self.finalize_and_submit_transaction(
+            operation_id,
+            LightningCommonGen::KIND.as_str(),
+            operation_meta_gen,
+            tx,
+        )
+        .await?;
+
+        Ok(OutgoingTransfer {
+            operation_id,
+            contract_id,
+            fee,
+        })
+    }
+
+    async fn subscribe_transfer_send(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<TransferSendState>> {
+        let operation = ln_operation(self, operation_id).await?;
+        let (out_point, change) = match operation.meta::<LightningOperationMeta>() {
+            LightningOperationMeta::TransferSend {
+                out_point, change, ..
+            } => (out_point, change),
+            _ => bail!("Operation is not a transfer to another federation"),
+        };
+
+        let client = self.clone();
+
+        Ok(operation.outcome_or_updates(self.db(), operation_id, || {
+            stream! {
+                let lightning = client.get_first_module::<LightningClientModule>(&KIND).0;
+
+                yield TransferSendState::Created;
+
+                if client
+                    .transaction_updates(operation_id)
+                    .await
+                    .await_tx_accepted(out_point.txid)
+                    .await
+                    .is_err()
+                {
+                    yield TransferSendState::Canceled;
+                    return;
+                }
+                yield TransferSendState::Funded;
+
+                match lightning.await_transfer_success(operation_id).await {
+                    Ok(preimage) => {
+                        if !change.is_empty() {
+                            yield TransferSendState::AwaitingChange;
+                            if client.await_primary_module_outputs(operation_id, change).await.is_err() {
+                                yield TransferSendState::UnexpectedError { error_message: "Error occurred while waiting for the primary module's output".to_string() };
+                                return;
+                            }
+                        }
+
+                        yield TransferSendState::Success { preimage };
+                        return;
+                    }
+                    Err(PayError::Refundable(block_height, error)) => {
+                        yield TransferSendState::WaitingForRefund { block_height, gateway_error: error.clone() };
+
+                        if let Ok(out_points) = lightning.await_transfer_refund(operation_id).await {
+                            if client.await_primary_module_outputs(operation_id, out_points).await.is_ok() {
+                                yield TransferSendState::Refunded { gateway_error: error };
+                                return;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                yield TransferSendState::UnexpectedError { error_message: "Error occurred trying to get refund. Refund was not successful".to_string() };
+            }
+        }))
+    }
+
     async fn subscribe_ln_pay(
         &self,
         operation_id: OperationId,
@@ -627,6 +929,17 @@ pub enum LightningOperationMeta {
         invoice: Bolt11Invoice,
         extra_meta: serde_json::Value,
     },
+    TransferSend {
+        out_point: OutPoint,
+        request: TransferRequest,
+        fee: Amount,
+        change: Vec<OutPoint>,
+    },
+    TransferReceive {
+        out_point: OutPoint,
+        request: TransferRequest,
+        extra_meta: serde_json::Value,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -721,6 +1034,7 @@ pub struct LightningClientModule {
     preimage_auth: KeyPair,
 }
 
+#[apply(async_trait_maybe_send!)]
 impl ClientModule for LightningClientModule {
     type Common = LightningModuleTypes;
     type ModuleStateMachineContext = LightningClientContext;
@@ -757,6 +1071,127 @@ impl ClientModule for LightningClientModule {
             }
         }
     }
+
+    async fn get_balance_breakdown(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        active_states: Vec<LightningClientStateMachines>,
+    ) -> Vec<BalanceItem> {
+        // Funded incoming contracts and refunds are claimed right away, so their
+        // funds are reported as pending notes by the primary module instead
+        active_states
+            .into_iter()
+            .filter_map(|state| match state {
+                LightningClientStateMachines::LightningPay(LightningPayStateMachine {
+                    common,
+                    state:
+                        LightningPayStates::CreatedOutgoingLnContract(_)
+                        | LightningPayStates::Funded(_)
+                        | LightningPayStates::Refundable(_),
+                }) => {
+                    let account = common.contract.contract_account;
+
+                    Some(BalanceItem::LightningContract {
+                        operation_id: common.operation_id,
+                        contract_id: account.contract.contract_id().to_string(),
+                        direction: ContractDirection::Outgoing,
+                        amount: account.amount,
+                        expires_at_block: Some(account.contract.timelock),
+                    })
+                }
+                LightningClientStateMachines::TransferSend(TransferSendStateMachine {
+                    common,
+                    state:
+                        TransferSendStates::Created(_)
+                        | TransferSendStates::Funded
+                        | TransferSendStates::Refundable(_),
+                }) => Some(BalanceItem::LightningContract {
+                    operation_id: common.operation_id,
+                    contract_id: common.contract.contract.contract_id().to_string(),
+                    direction: ContractDirection::Outgoing,
+                    amount: common.contract.amount,
+                    expires_at_block: Some(common.contract.contract.timelock),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    async fn get_pending_stages(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _operation: &OperationLogEntry,
+        active_states: Vec<LightningClientStateMachines>,
+    ) -> Vec<PendingStage> {
+        let fees = &self.cfg.fee_consensus;
+
+        active_states
+            .into_iter()
+            .filter_map(|state| match state {
+                LightningClientStateMachines::LightningPay(LightningPayStateMachine {
+                    common,
+                    state,
+                }) => {
+                    let stage = match state {
+                        LightningPayStates::CreatedOutgoingLnContract(_) => "funding_contract",
+                        LightningPayStates::Funded(_) => "funded",
+                        LightningPayStates::Refundable(_) => "refundable",
+                        LightningPayStates::Refund(_) => "refunding",
+                        _ => return None,
+                    };
+
+                    // the gateway fee is part of the contract amount
+                    Some(PendingStage {
+                        stage: stage.to_string(),
+                        locked: common.contract.contract_account.amount,
+                        fees: OperationFees {
+                            federation: fees.contract_output,
+                            gateway: common.gateway_fee,
+                            on_chain: Amount::ZERO,
+                        },
+                    })
+                }
+                LightningClientStateMachines::TransferSend(TransferSendStateMachine {
+                    common,
+                    state,
+                }) => {
+                    let stage = match state {
+                        TransferSendStates::Created(_) => "funding_contract",
+                        TransferSendStates::Funded => "funded",
+                        TransferSendStates::Refundable(_) => "refundable",
+                        TransferSendStates::Refund(_) => "refunding",
+                        _ => return None,
+                    };
+
+                    Some(PendingStage {
+                        stage: stage.to_string(),
+                        locked: common.contract.amount,
+                        fees: OperationFees::federation(fees.contract_output),
+                    })
+                }
+                // nothing is locked until the incoming contract is funded, which is claimed
+                // right away
+                LightningClientStateMachines::Receive(LightningReceiveStateMachine {
+                    state,
+                    ..
+                }) => {
+                    let stage = match state {
+                        LightningReceiveStates::SubmittedOffer(_) => "submitting_offer",
+                        LightningReceiveStates::ConfirmedInvoice(_) => "awaiting_payment",
+                        LightningReceiveStates::Funded(_) => "claiming",
+                        _ => return None,
+                    };
+
+                    Some(PendingStage {
+                        stage: stage.to_string(),
+                        locked: Amount::ZERO,
+                        fees: OperationFees::federation(fees.contract_input),
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 #[derive(Error, Debug, Serialize, Deserialize)]
@@ -881,17 +1316,9 @@ impl LightningClientModule {
             .amount_milli_satoshis()
             .context("MissingInvoiceAmount")?;
 
-        let fees = gateway.fees;
-        let base_fee = fees.base_msat as u64;
-        let margin_fee: u64 = if fees.proportional_millionths > 0 {
-            let fee_percent = 1000000 / fees.proportional_millionths as u64;
-            invoice_amount_msat / fee_percent
-        } else {
-            0
-        };
-
-        let contract_amount_msat = invoice_amount_msat + base_fee + margin_fee;
-        let contract_amount = Amount::from_msats(contract_amount_msat);
+        let invoice_amount = Amount::from_msats(invoice_amount_msat);
+        let gateway_fee = GatewayFee(gateway.fees).fee_for(invoice_amount);
+        let contract_amount = invoice_amount + gateway_fee;
 
         let user_sk = bitcoin::KeyPair::new(&self.secp, &mut rng);
 
@@ -922,7 +1349,7 @@ impl LightningClientModule {
                         operation_id,
                         federation_id: fed_id,
                         contract: outgoing_payment.clone(),
-                        gateway_fee: Amount::from_msats(base_fee + margin_fee),
+                        gateway_fee,
                         preimage_auth,
                         payment_hash,
                     },
@@ -1081,6 +1508,227 @@ impl LightningClientModule {
         }
     }
 
+    async fn await_transfer_funded(
+        &self,
+        operation_id: OperationId,
+    ) -> Result<(), LightningReceiveError> {
+        let mut stream = self.notifier.subscribe(operation_id).await;
+        loop {
+            if let Some(LightningClientStateMachines::TransferReceive(state)) = stream.next().await
+            {
+                match state.state {
+                    TransferReceiveStates::Funded(_) | TransferReceiveStates::Success(_) => {
+                        return Ok(())
+                    }
+                    TransferReceiveStates::Canceled(e) => return Err(e),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    async fn await_transfer_claimed(
+        &self,
+        operation_id: OperationId,
+    ) -> Result<Vec<OutPoint>, LightningReceiveError> {
+        let mut stream = self.notifier.subscribe(operation_id).await;
+        loop {
+            if let Some(LightningClientStateMachines::TransferReceive(state)) = stream.next().await
+            {
+                match state.state {
+                    TransferReceiveStates::Success(out_points) => return Ok(out_points),
+                    TransferReceiveStates::Canceled(e) => return Err(e),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Wait for the gateway to carry out the transfer or for the contract to become
+    // refundable
+    async fn await_transfer_success(
+        &self,
+        operation_id: OperationId,
+    ) -> Result<Preimage, PayError> {
+        let mut stream = self.notifier.subscribe(operation_id).await;
+        loop {
+            if let Some(LightningClientStateMachines::TransferSend(state)) = stream.next().await {
+                match state.state {
+                    TransferSendStates::Success(preimage) => return Ok(preimage),
+                    TransferSendStates::Refundable(refundable) => {
+                        return Err(PayError::Refundable(
+                            refundable.block_timelock,
+                            refundable.error,
+                        ));
+                    }
+                    TransferSendStates::Canceled => return Err(PayError::Canceled),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    async fn await_transfer_refund(
+        &self,
+        operation_id: OperationId,
+    ) -> Result<Vec<OutPoint>, PayError> {
+        let mut stream = self.notifier.subscribe(operation_id).await;
+        loop {
+            if let Some(LightningClientStateMachines::TransferSend(state)) = stream.next().await {
+                match state.state {
+                    TransferSendStates::Refunded(out_points) => return Ok(out_points),
+                    TransferSendStates::Failure(reason) => return Err(PayError::Failed(reason)),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Create an output that locks the requested amount plus the fee of the
+    /// gateway in a transfer contract, which the gateway can claim once it
+    /// funded the incoming contract of the recipient in their federation
+    async fn create_transfer_send_output<'a>(
+        &'a self,
+        api: DynModuleApi,
+        request: TransferRequest,
+        gateway: LightningGateway,
+        federation_id: FederationId,
+        mut rng: impl RngCore + CryptoRng + 'a,
+    ) -> anyhow::Result<(
+        OperationId,
+        ClientOutput<LightningOutput, LightningClientStateMachines>,
+        ContractId,
+        Amount,
+    )> {
+        // Do not create the funding transaction if the gateway is not currently
+        // available
+        self.verify_gateway_availability(&gateway).await?;
+
+        let consensus_count = api
+            .fetch_consensus_block_count()
+            .await?
+            .ok_or(format_err!("Cannot get consensus block count"))?;
+        let absolute_timelock = consensus_count + OUTGOING_LN_CONTRACT_TIMELOCK - 1;
+
+        let gateway_fee = GatewayFee(gateway.fees).fee_for(request.amount);
+        let contract_amount = request.amount + gateway_fee;
+
+        let user_sk = bitcoin::KeyPair::new(&self.secp, &mut rng);
+        let contract = TransferContract {
+            hash: request.hash,
+            gateway_key: gateway.gateway_redeem_key,
+            timelock: absolute_timelock as u32,
+            user_key: user_sk.x_only_public_key().0,
+            federation_id: request.federation_id,
+            amount: request.amount,
+            cancelled: false,
+        };
+
+        let contract_id = contract.contract_id();
+        let operation_id = OperationId(contract_id.into_inner());
+        let contract_account = TransferContractAccount {
+            amount: contract_amount,
+            contract: contract.clone(),
+        };
+        let sm_gen = Arc::new(move |funding_txid: TransactionId, _input_idx: u64| {
+            vec![LightningClientStateMachines::TransferSend(
+                TransferSendStateMachine {
+                    common: TransferSendCommon {
+                        operation_id,
+                        federation_id,
+                        recovery_key: user_sk,
+                        contract: contract_account.clone(),
+                        gateway: gateway.clone(),
+                    },
+                    state: TransferSendStates::Created(TransferSendCreated { funding_txid }),
+                },
+            )]
+        });
+
+        let ln_output = LightningOutput::Contract(ContractOutput {
+            amount: contract_amount,
+            contract: Contract::Transfer(contract),
+        });
+
+        Ok((
+            operation_id,
+            ClientOutput {
+                output: ln_output,
+                state_machines: sm_gen,
+            },
+            contract_id,
+            gateway_fee,
+        ))
+    }
+
+    /// Create an offer for a transfer from another federation, which the
+    /// gateway accepts by funding an incoming contract
+    fn create_transfer_receive_output(
+        &self,
+        amount: Amount,
+        expiry_time: Option<u64>,
+        gateway_id: secp256k1::PublicKey,
+        federation_id: FederationId,
+        mut rng: impl RngCore + CryptoRng,
+    ) -> anyhow::Result<(
+        OperationId,
+        TransferRequest,
+        ClientOutput<LightningOutput, LightningClientStateMachines>,
+    )> {
+        let payment_keypair = KeyPair::new(&self.secp, &mut rng);
+        let preimage: [u8; 32] = payment_keypair.x_only_public_key().0.serialize();
+        let payment_hash = bitcoin::secp256k1::hashes::sha256::Hash::hash(&preimage);
+
+        let expires_at = fedimint_core::time::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs()
+            + expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME);
+        let request = TransferRequest {
+            federation_id,
+            gateway_id,
+            amount,
+            hash: payment_hash,
+            expires_at,
+        };
+
+        let operation_id = OperationId(payment_hash.into_inner());
+
+        let sm_request = request.clone();
+        let sm_gen = Arc::new(move |txid: TransactionId, _input_idx: u64| {
+            vec![LightningClientStateMachines::TransferReceive(
+                TransferReceiveStateMachine {
+                    common: TransferReceiveCommon {
+                        operation_id,
+                        request: sm_request.clone(),
+                        payment_keypair,
+                    },
+                    state: TransferReceiveStates::SubmittedOffer(TransferReceiveSubmittedOffer {
+                        offer_txid: txid,
+                    }),
+                },
+            )]
+        });
+
+        let ln_output = LightningOutput::Offer(IncomingContractOffer {
+            amount,
+            hash: payment_hash,
+            encrypted_preimage: EncryptedPreimage::new(
+                Preimage(preimage),
+                &self.cfg.threshold_pub_key,
+            ),
+            expiry_time,
+        });
+
+        Ok((
+            operation_id,
+            request,
+            ClientOutput {
+                output: ln_output,
+                state_machines: sm_gen,
+            },
+        ))
+    }
+
     #[allow(clippy::too_many_arguments)]
     async fn create_lightning_receive_output<'a>(
         &'a self,
@@ -1200,6 +1848,8 @@ pub enum LightningClientStateMachines {
     InternalPay(IncomingStateMachine),
     LightningPay(LightningPayStateMachine),
     Receive(LightningReceiveStateMachine),
+    TransferSend(TransferSendStateMachine),
+    TransferReceive(TransferReceiveStateMachine),
 }
 
 impl IntoDynInstance for LightningClientStateMachines {
@@ -1238,6 +1888,18 @@ impl State for LightningClientStateMachines {
                     LightningClientStateMachines::Receive
                 )
             }
+            LightningClientStateMachines::TransferSend(transfer_send_state) => {
+                sm_enum_variant_translation!(
+                    transfer_send_state.transitions(context, global_context),
+                    LightningClientStateMachines::TransferSend
+                )
+            }
+            LightningClientStateMachines::TransferReceive(transfer_receive_state) => {
+                sm_enum_variant_translation!(
+                    transfer_receive_state.transitions(context, global_context),
+                    LightningClientStateMachines::TransferReceive
+                )
+            }
         }
     }
 
@@ -1250,6 +1912,12 @@ impl State for LightningClientStateMachines {
                 lightning_pay_state.operation_id()
             }
             LightningClientStateMachines::Receive(receive_state) => receive_state.operation_id(),
+            LightningClientStateMachines::TransferSend(transfer_send_state) => {
+                transfer_send_state.operation_id()
+            }
+            LightningClientStateMachines::TransferReceive(transfer_receive_state) => {
+                transfer_receive_state.operation_id()
+            }
         }
     }
 }
@@ -1321,6 +1989,13 @@ pub struct OutgoingLightningPayment {
     pub fee: Amount,
 }
 
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OutgoingTransfer {
+    pub operation_id: OperationId,
+    pub contract_id: ContractId,
+    pub fee: Amount,
+}
+
 async fn set_payment_result(
     dbtx: &mut DatabaseTransactionRef<'_>,
     payment_hash: sha256::Hash,
@@ -0,0 +1,213 @@
+//! Moves a user's funds from a federation that is shutting down to its
+//! successor
+//!
+//! Once the guardians of the closing federation threshold signed a
+//! [`FinalStateAttestation`] the client pays invoices of the successor
+//! federation with its remaining balance until only dust is left. Payments are
+//! capped and spaced out according to the [`MigrationLimits`] such that the
+//! gateways are not drained by all users migrating at once.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure};
+use async_stream::stream;
+use fedimint_client::ClientArc;
+use fedimint_core::migration::FinalStateAttestation;
+use fedimint_core::task::sleep;
+use fedimint_core::Amount;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{LightningClientExt, LnPayState, LnReceiveState, PayType};
+
+/// Limits the rate at which a client migrates its balance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationLimits {
+    /// The largest invoice we pay to the successor federation at once
+    pub max_payment: Amount,
+    /// How long we wait after a payment before starting the next one
+    pub min_interval: Duration,
+}
+
+impl Default for MigrationLimits {
+    fn default() -> Self {
+        MigrationLimits {
+            max_payment: Amount::from_sats(1_000_000),
+            min_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// How much of the balance has been moved to the successor federation so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationProgress {
+    /// Received by the successor federation
+    pub migrated: Amount,
+    /// Paid to the gateways of the closing federation
+    pub fees: Amount,
+    /// Left in the closing federation
+    pub remaining: Amount,
+    pub payments: u64,
+}
+
+/// The updates of a migration started with [`migrate_balance`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationUpdate {
+    /// The guardians of the closing federation attested the successor
+    Verified {
+        attestation: FinalStateAttestation,
+    },
+    Migrating(MigrationProgress),
+    /// The remaining balance does not cover the fees of another payment
+    Finished(MigrationProgress),
+    Failed {
+        progress: MigrationProgress,
+        error: String,
+    },
+}
+
+/// Migrates the balance of the `closing` client to the `successor` client,
+/// which has to be joined to the successor federation attested by the
+/// guardians of the closing federation
+pub fn migrate_balance(
+    closing: ClientArc,
+    successor: ClientArc,
+    limits: MigrationLimits,
+) -> BoxStream<'static, MigrationUpdate> {
+    Box::pin(stream! {
+        let mut progress = MigrationProgress {
+            remaining: closing.get_balance().await,
+            ..MigrationProgress::default()
+        };
+
+        match verify_successor(&closing, &successor).await {
+            Ok(attestation) => yield MigrationUpdate::Verified { attestation },
+            Err(e) => {
+                yield MigrationUpdate::Failed { progress, error: e.to_string() };
+                return;
+            }
+        }
+
+        loop {
+            let amount = match next_payment_amount(&closing, progress.remaining, &limits).await {
+                Ok(Some(amount)) => amount,
+                Ok(None) => {
+                    info!(%progress.migrated, %progress.remaining, "Finished migrating balance");
+                    yield MigrationUpdate::Finished(progress);
+                    return;
+                }
+                Err(e) => {
+                    yield MigrationUpdate::Failed { progress, error: e.to_string() };
+                    return;
+                }
+            };
+
+            if let Err(e) = migrate_payment(&closing, &successor, amount).await {
+                warn!(%amount, error = %e, "Migration payment failed");
+                yield MigrationUpdate::Failed { progress, error: e.to_string() };
+                return;
+            }
+
+            let remaining = closing.get_balance().await;
+
+            progress.migrated += amount;
+            progress.fees += progress.remaining.saturating_sub(remaining + amount);
+            progress.remaining = remaining;
+            progress.payments += 1;
+
+            yield MigrationUpdate::Migrating(progress);
+
+            sleep(limits.min_interval).await;
+        }
+    })
+}
+
+async fn verify_successor(
+    closing: &ClientArc,
+    successor: &ClientArc,
+) -> anyhow::Result<FinalStateAttestation> {
+    let federation_id = closing.federation_id();
+
+    let attestation = closing
+        .api()
+        .fetch_final_state_attestation(&federation_id)
+        .await?
+        .ok_or_else(|| anyhow!("The federation did not attest its final state yet"))?;
+
+    let attestation = attestation.verify(&federation_id)?.clone();
+
+    ensure!(
+        successor.federation_id() == attestation.successor.id,
+        "The successor client is joined to a federation other than the attested successor"
+    );
+
+    Ok(attestation)
+}
+
+/// The largest invoice amount within the limits whose gateway fees are still
+/// covered by our remaining balance, if there is any
+async fn next_payment_amount(
+    closing: &ClientArc,
+    remaining: Amount,
+    limits: &MigrationLimits,
+) -> anyhow::Result<Option<Amount>> {
+    let fees = closing.select_active_gateway().await?.fees;
+
+    let affordable_msats = remaining
+        .msats
+        .saturating_sub(u64::from(fees.base_msat))
+        .saturating_mul(1_000_000)
+        / (1_000_000 + u64::from(fees.proportional_millionths));
+
+    let amount = Amount::from_msats(affordable_msats.min(limits.max_payment.msats));
+
+    Ok((amount != Amount::ZERO).then_some(amount))
+}
+
+async fn migrate_payment(
+    closing: &ClientArc,
+    successor: &ClientArc,
+    amount: Amount,
+) -> anyhow::Result<()> {
+    let (receive_operation_id, invoice) = successor
+        .create_bolt11_invoice(amount, "Federation migration".to_string(), None, ())
+        .await?;
+
+    let pay_operation_id = match closing.pay_bolt11_invoice(invoice).await?.payment_type {
+        PayType::Lightning(operation_id) => operation_id,
+        PayType::Internal(..) => bail!("The successor invoice is payable within the federation"),
+    };
+
+    let mut pay_updates = closing
+        .subscribe_ln_pay(pay_operation_id)
+        .await?
+        .into_stream();
+
+    loop {
+        match pay_updates.next().await {
+            Some(LnPayState::Success { .. }) => break,
+            Some(LnPayState::Created | LnPayState::Funded | LnPayState::AwaitingChange) => {}
+            Some(other) => bail!("Paying the successor federation failed: {other:?}"),
+            None => bail!("Ran out of state updates while paying the successor federation"),
+        }
+    }
+
+    let mut receive_updates = successor
+        .subscribe_ln_receive(receive_operation_id)
+        .await?
+        .into_stream();
+
+    loop {
+        match receive_updates.next().await {
+            Some(LnReceiveState::Claimed) => return Ok(()),
+            Some(LnReceiveState::Canceled { reason }) => {
+                bail!("Receiving in the successor federation failed: {reason}")
+            }
+            Some(_) => {}
+            None => bail!("Ran out of state updates while receiving in the successor federation"),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::{ensure, Context};
+use fedimint_client::multi::MultiClient;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::{apply, async_trait_maybe_send, Amount};
+use serde::Serialize;
+
+use crate::{LightningClientExt, OutgoingLightningPayment};
+
+/// Description of the invoices paying from one federation of a
+/// [`MultiClient`] to another, which does not reveal the federations to the
+/// gateway
+const FEDERATION_SEND_DESCRIPTION: &str = "Transfer between federations";
+
+#[apply(async_trait_maybe_send!)]
+pub trait LightningMultiClientExt {
+    /// Sends `amount` from the wallet in federation `from` to the one in
+    /// federation `to` by paying an invoice created by the client of `to` with
+    /// the client of `from`. The progress of the two sides can be followed with
+    /// [`LightningClientExt::subscribe_ln_pay`] and
+    /// [`LightningClientExt::subscribe_ln_receive`] on the respective clients.
+    async fn send_between_federations(
+        &self,
+        from: FederationId,
+        to: FederationId,
+        amount: Amount,
+    ) -> anyhow::Result<FederationSend>;
+}
+
+/// The two operations sending funds between federations
+#[derive(Debug, Serialize)]
+pub struct FederationSend {
+    /// Operation paying the invoice in the federation the funds are sent from
+    pub payment: OutgoingLightningPayment,
+    /// Operation receiving the funds in the federation they are sent to
+    pub receive_operation_id: OperationId,
+}
+
+#[apply(async_trait_maybe_send!)]
+impl LightningMultiClientExt for MultiClient {
+    async fn send_between_federations(
+        &self,
+        from: FederationId,
+        to: FederationId,
+        amount: Amount,
+    ) -> anyhow::Result<FederationSend> {
+        ensure!(from != to, "Can not send funds to the same federation");
+
+        let from_client = self
+            .get(from)
+            .await
+            .context("The federation to send from was not joined")?;
+        let to_client = self
+            .get(to)
+            .await
+            .context("The federation to send to was not joined")?;
+
+        let (receive_operation_id, invoice) = to_client
+            .create_bolt11_invoice(amount, FEDERATION_SEND_DESCRIPTION.to_string(), None, ())
+            .await?;
+        let payment = from_client.pay_bolt11_invoice(invoice).await?;
+
+        Ok(FederationSend {
+            payment,
+            receive_operation_id,
+        })
+    }
+}
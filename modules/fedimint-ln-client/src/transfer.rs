@@ -0,0 +1,585 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use bitcoin::KeyPair;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
+use fedimint_client::transaction::ClientInput;
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::task::sleep;
+use fedimint_core::{OutPoint, TransactionId};
+use fedimint_ln_common::api::LnFederationApi;
+use fedimint_ln_common::contracts::incoming::IncomingContractAccount;
+use fedimint_ln_common::contracts::transfer::{TransferContractAccount, TransferRequest};
+use fedimint_ln_common::contracts::{ContractId, IdentifiableContract, Preimage};
+use fedimint_ln_common::{LightningClientContext, LightningGateway, LightningInput};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::pay::GatewayPayError;
+use crate::receive::LightningReceiveError;
+use crate::LightningClientStateMachines;
+
+/// How long the recipient keeps waiting for a transfer after its request
+/// expired, in case the payer's gateway started it just before
+const TRANSFER_EXPIRY_BUFFER: Duration = Duration::from_secs(60);
+
+#[cfg_attr(doc, aquamarine::aquamarine)]
+/// State machine that requests the gateway to transfer the funds of a
+/// transfer contract to a user of another federation and claims them back if
+/// the gateway does not.
+///
+/// ```mermaid
+/// graph LR
+/// classDef virtual fill:#fff,stroke-dasharray: 5 5
+///
+///  Created -- await transaction failed --> Canceled
+///  Created -- await transaction acceptance --> Funded
+///  Funded -- await gateway transfer success --> Success
+///  Funded -- await gateway transfer failed --> Refundable
+///  Refundable -- gateway cancelled contract --> Refund
+///  Refundable -- contract timeout --> Refund
+///  Refund -- await transaction acceptance --> Refunded
+///  Refund -- await transaction rejected --> Failure
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum TransferSendStates {
+    Created(TransferSendCreated),
+    Canceled,
+    Funded,
+    Success(Preimage),
+    Refundable(TransferSendRefundable),
+    Refund(TransferSendRefund),
+    Refunded(Vec<OutPoint>),
+    Failure(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferSendCommon {
+    pub operation_id: OperationId,
+    /// Our federation, which the transfer contract is funded in
+    pub federation_id: FederationId,
+    pub recovery_key: KeyPair,
+    pub contract: TransferContractAccount,
+    pub gateway: LightningGateway,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferSendStateMachine {
+    pub common: TransferSendCommon,
+    pub state: TransferSendStates,
+}
+
+impl State for TransferSendStateMachine {
+    type ModuleContext = LightningClientContext;
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        global_context: &Self::GlobalContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            TransferSendStates::Created(created) => {
+                created.transitions(&self.common, global_context)
+            }
+            TransferSendStates::Funded => {
+                let gateway = self.common.gateway.clone();
+                let payload = TransferPayload::new(&self.common);
+                let hash = self.common.contract.contract.hash;
+                vec![StateTransition::new(
+                    gateway_transfer(gateway, payload, hash),
+                    |_dbtx, result, old_state| {
+                        Box::pin(transition_gateway_transfer(result, old_state))
+                    },
+                )]
+            }
+            TransferSendStates::Refundable(refundable) => {
+                refundable.transitions(&self.common, global_context)
+            }
+            TransferSendStates::Refund(refund) => refund.transitions(&self.common, global_context),
+            TransferSendStates::Canceled
+            | TransferSendStates::Success(_)
+            | TransferSendStates::Refunded(_)
+            | TransferSendStates::Failure(_) => vec![],
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.common.operation_id
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferSendCreated {
+    pub funding_txid: TransactionId,
+}
+
+impl TransferSendCreated {
+    fn transitions(
+        &self,
+        common: &TransferSendCommon,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<TransferSendStateMachine>> {
+        let global_context = global_context.clone();
+        let operation_id = common.operation_id;
+        let txid = self.funding_txid;
+        vec![StateTransition::new(
+            async move { global_context.await_tx_accepted(operation_id, txid).await },
+            |_dbtx, result, old_state| {
+                let state = match result {
+                    Ok(()) => TransferSendStates::Funded,
+                    Err(_) => TransferSendStates::Canceled,
+                };
+
+                Box::pin(async move {
+                    TransferSendStateMachine {
+                        common: old_state.common,
+                        state,
+                    }
+                })
+            },
+        )]
+    }
+}
+
+/// Asks the gateway to carry out the transfer, which returns the preimage once
+/// it was paid to the recipient
+async fn gateway_transfer(
+    gateway: LightningGateway,
+    payload: TransferPayload,
+    hash: sha256::Hash,
+) -> Result<Preimage, GatewayPayError> {
+    let response = reqwest::Client::new()
+        .post(
+            gateway
+                .api
+                .join("transfer")
+                .expect("'transfer' contains no invalid characters for a URL")
+                .as_str(),
+        )
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|e| GatewayPayError::GatewayInternalError {
+            error_code: None,
+            error_message: e.to_string(),
+        })?;
+
+    if !response.status().is_success() {
+        return Err(GatewayPayError::GatewayInternalError {
+            error_code: Some(response.status().as_u16()),
+            error_message: response
+                .text()
+                .await
+                .expect("Could not retrieve text from response"),
+        });
+    }
+
+    let preimage =
+        response
+            .json::<Preimage>()
+            .await
+            .map_err(|e| GatewayPayError::GatewayInternalError {
+                error_code: None,
+                error_message: format!("Error retrieving preimage from response: {e}"),
+            })?;
+
+    if sha256::Hash::hash(&preimage.0) != hash {
+        return Err(GatewayPayError::GatewayInternalError {
+            error_code: None,
+            error_message: "Gateway returned an invalid preimage".to_string(),
+        });
+    }
+
+    Ok(preimage)
+}
+
+async fn transition_gateway_transfer(
+    result: Result<Preimage, GatewayPayError>,
+    old_state: TransferSendStateMachine,
+) -> TransferSendStateMachine {
+    let state = match result {
+        Ok(preimage) => TransferSendStates::Success(preimage),
+        Err(error) => TransferSendStates::Refundable(TransferSendRefundable {
+            block_timelock: old_state.common.contract.contract.timelock,
+            error,
+        }),
+    };
+
+    TransferSendStateMachine {
+        common: old_state.common,
+        state,
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferSendRefundable {
+    pub block_timelock: u32,
+    pub error: GatewayPayError,
+}
+
+impl TransferSendRefundable {
+    fn transitions(
+        &self,
+        common: &TransferSendCommon,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<TransferSendStateMachine>> {
+        let contract_id = common.contract.contract.contract_id();
+        let cancelled_context = global_context.clone();
+        let timeout_context = global_context.clone();
+        vec![
+            StateTransition::new(
+                Self::await_contract_cancelled(contract_id, global_context.clone()),
+                move |dbtx, (), old_state| {
+                    Box::pin(Self::refund(dbtx, old_state, cancelled_context.clone()))
+                },
+            ),
+            StateTransition::new(
+                Self::await_contract_timeout(global_context.clone(), self.block_timelock),
+                move |dbtx, (), old_state| {
+                    Box::pin(Self::refund(dbtx, old_state, timeout_context.clone()))
+                },
+            ),
+        ]
+    }
+
+    async fn await_contract_cancelled(
+        contract_id: ContractId,
+        global_context: DynGlobalClientContext,
+    ) {
+        loop {
+            match global_context
+                .module_api()
+                .wait_outgoing_contract_cancelled(contract_id)
+                .await
+            {
+                Ok(_) => return,
+                Err(error) => {
+                    error!("Error waiting for transfer contract to be cancelled: {error:?}");
+                }
+            }
+
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn await_contract_timeout(global_context: DynGlobalClientContext, timelock: u32) {
+        loop {
+            match global_context
+                .module_api()
+                .wait_block_height(timelock as u64)
+                .await
+            {
+                Ok(_) => return,
+                Err(error) => error!("Error waiting for block height: {timelock} {error:?}"),
+            }
+
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// Claims the funds of the cancelled or expired transfer contract back
+    async fn refund(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        old_state: TransferSendStateMachine,
+        global_context: DynGlobalClientContext,
+    ) -> TransferSendStateMachine {
+        let refund_input = ClientInput::<LightningInput, LightningClientStateMachines> {
+            input: old_state.common.contract.refund(),
+            keys: vec![old_state.common.recovery_key],
+            // The input of the refund tx is managed by this state machine, so no new state machines
+            // need to be created
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+
+        let (txid, out_points) = global_context.claim_input(dbtx, refund_input).await;
+
+        TransferSendStateMachine {
+            common: old_state.common,
+            state: TransferSendStates::Refund(TransferSendRefund { txid, out_points }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferSendRefund {
+    pub txid: TransactionId,
+    pub out_points: Vec<OutPoint>,
+}
+
+impl TransferSendRefund {
+    fn transitions(
+        &self,
+        common: &TransferSendCommon,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<TransferSendStateMachine>> {
+        let global_context = global_context.clone();
+        let operation_id = common.operation_id;
+        let txid = self.txid;
+        let out_points = self.out_points.clone();
+        vec![StateTransition::new(
+            async move { global_context.await_tx_accepted(operation_id, txid).await },
+            move |_dbtx, result, old_state| {
+                let state = match result {
+                    Ok(()) => TransferSendStates::Refunded(out_points.clone()),
+                    Err(_) => {
+                        TransferSendStates::Failure("Refund Transaction was rejected.".to_string())
+                    }
+                };
+
+                Box::pin(async move {
+                    TransferSendStateMachine {
+                        common: old_state.common,
+                        state,
+                    }
+                })
+            },
+        )]
+    }
+}
+
+/// Request to the gateway to carry out the transfer funded by the contract
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Decodable, Encodable)]
+#[serde(rename_all = "snake_case")]
+pub struct TransferPayload {
+    /// Federation the transfer contract is funded in
+    pub federation_id: FederationId,
+    pub contract_id: ContractId,
+}
+
+impl TransferPayload {
+    pub fn new(common: &TransferSendCommon) -> Self {
+        Self {
+            federation_id: common.federation_id,
+            contract_id: common.contract.contract.contract_id(),
+        }
+    }
+}
+
+#[cfg_attr(doc, aquamarine::aquamarine)]
+/// State machine that waits for a gateway to fund the incoming contract of a
+/// transfer we requested from a user of another federation and claims it.
+///
+/// ```mermaid
+/// graph LR
+/// classDef virtual fill:#fff,stroke-dasharray: 5 5
+///
+///     SubmittedOffer -- await transaction rejection --> Canceled
+///     SubmittedOffer -- await transaction acceptance --> AwaitingTransfer
+///     AwaitingTransfer -- await contract creation + decryption --> Funded
+///     AwaitingTransfer -- await request expiry --> Canceled
+///     Funded -- await claim tx acceptance --> Success
+///     Funded -- await claim tx rejection --> Canceled
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum TransferReceiveStates {
+    SubmittedOffer(TransferReceiveSubmittedOffer),
+    Canceled(LightningReceiveError),
+    AwaitingTransfer,
+    Funded(TransferReceiveFunded),
+    Success(Vec<OutPoint>),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferReceiveCommon {
+    pub operation_id: OperationId,
+    pub request: TransferRequest,
+    pub payment_keypair: KeyPair,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferReceiveStateMachine {
+    pub common: TransferReceiveCommon,
+    pub state: TransferReceiveStates,
+}
+
+impl State for TransferReceiveStateMachine {
+    type ModuleContext = LightningClientContext;
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        global_context: &Self::GlobalContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            TransferReceiveStates::SubmittedOffer(submitted_offer) => {
+                submitted_offer.transitions(&self.common, global_context)
+            }
+            TransferReceiveStates::AwaitingTransfer => {
+                let global_context = global_context.clone();
+                let contract_id = self.common.request.hash.into();
+                vec![
+                    StateTransition::new(
+                        await_incoming_contract_account(contract_id, global_context.clone()),
+                        move |dbtx, result, old_state| {
+                            Box::pin(transition_funded(
+                                dbtx,
+                                result,
+                                old_state,
+                                global_context.clone(),
+                            ))
+                        },
+                    ),
+                    StateTransition::new(
+                        await_request_expiry(self.common.request.expires_at),
+                        |_dbtx, (), old_state| {
+                            Box::pin(async move {
+                                TransferReceiveStateMachine {
+                                    common: old_state.common,
+                                    state: TransferReceiveStates::Canceled(
+                                        LightningReceiveError::Timeout,
+                                    ),
+                                }
+                            })
+                        },
+                    ),
+                ]
+            }
+            TransferReceiveStates::Funded(funded) => {
+                funded.transitions(&self.common, global_context)
+            }
+            TransferReceiveStates::Canceled(_) | TransferReceiveStates::Success(_) => vec![],
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.common.operation_id
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferReceiveSubmittedOffer {
+    pub offer_txid: TransactionId,
+}
+
+impl TransferReceiveSubmittedOffer {
+    fn transitions(
+        &self,
+        common: &TransferReceiveCommon,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<TransferReceiveStateMachine>> {
+        let global_context = global_context.clone();
+        let operation_id = common.operation_id;
+        let txid = self.offer_txid;
+        vec![StateTransition::new(
+            async move { global_context.await_tx_accepted(operation_id, txid).await },
+            |_dbtx, result, old_state| {
+                let state = match result {
+                    Ok(()) => TransferReceiveStates::AwaitingTransfer,
+                    Err(_) => TransferReceiveStates::Canceled(LightningReceiveError::Rejected),
+                };
+
+                Box::pin(async move {
+                    TransferReceiveStateMachine {
+                        common: old_state.common,
+                        state,
+                    }
+                })
+            },
+        )]
+    }
+}
+
+async fn await_incoming_contract_account(
+    contract_id: ContractId,
+    global_context: DynGlobalClientContext,
+) -> Result<IncomingContractAccount, LightningReceiveError> {
+    loop {
+        match global_context
+            .module_api()
+            .wait_preimage_decrypted(contract_id)
+            .await
+        {
+            Ok((incoming_contract_account, preimage)) => match preimage {
+                Some(_) => return Ok(incoming_contract_account),
+                None => return Err(LightningReceiveError::InvalidPreimage),
+            },
+            Err(error) => {
+                error!("Transfer error waiting for preimage decryption: {error:?}");
+            }
+        }
+
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn await_request_expiry(expires_at: u64) {
+    let expiry = UNIX_EPOCH + Duration::from_secs(expires_at) + TRANSFER_EXPIRY_BUFFER;
+    let remaining = expiry
+        .duration_since(fedimint_core::time::now())
+        .unwrap_or_default();
+
+    sleep(remaining).await
+}
+
+async fn transition_funded(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    result: Result<IncomingContractAccount, LightningReceiveError>,
+    old_state: TransferReceiveStateMachine,
+    global_context: DynGlobalClientContext,
+) -> TransferReceiveStateMachine {
+    let contract = match result {
+        Ok(contract) => contract,
+        Err(error) => {
+            return TransferReceiveStateMachine {
+                common: old_state.common,
+                state: TransferReceiveStates::Canceled(error),
+            };
+        }
+    };
+
+    let claim_input = ClientInput::<LightningInput, LightningClientStateMachines> {
+        input: contract.claim(),
+        keys: vec![old_state.common.payment_keypair],
+        // The input of the claim tx is managed by this state machine, so no new state machines
+        // need to be created
+        state_machines: Arc::new(|_, _| vec![]),
+    };
+
+    let (txid, out_points) = global_context.claim_input(dbtx, claim_input).await;
+
+    TransferReceiveStateMachine {
+        common: old_state.common,
+        state: TransferReceiveStates::Funded(TransferReceiveFunded { txid, out_points }),
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct TransferReceiveFunded {
+    pub txid: TransactionId,
+    pub out_points: Vec<OutPoint>,
+}
+
+impl TransferReceiveFunded {
+    fn transitions(
+        &self,
+        common: &TransferReceiveCommon,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<TransferReceiveStateMachine>> {
+        let global_context = global_context.clone();
+        let operation_id = common.operation_id;
+        let txid = self.txid;
+        let out_points = self.out_points.clone();
+        vec![StateTransition::new(
+            async move { global_context.await_tx_accepted(operation_id, txid).await },
+            move |_dbtx, result, old_state| {
+                let state = match result {
+                    Ok(()) => TransferReceiveStates::Success(out_points.clone()),
+                    Err(_) => TransferReceiveStates::Canceled(LightningReceiveError::ClaimRejected),
+                };
+
+                Box::pin(async move {
+                    TransferReceiveStateMachine {
+                        common: old_state.common,
+                        state,
+                    }
+                })
+            },
+        )]
+    }
+}
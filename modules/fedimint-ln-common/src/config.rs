@@ -146,3 +146,19 @@ impl FromStr for GatewayFee {
         }))
     }
 }
+
+impl GatewayFee {
+    /// The fee the gateway charges for paying an invoice of the amount, which
+    /// the client locks in the outgoing contract on top of the invoice amount
+    pub fn fee_for(&self, invoice_amount: fedimint_core::Amount) -> fedimint_core::Amount {
+        let base_fee = u64::from(self.0.base_msat);
+        let margin_fee = if self.0.proportional_millionths > 0 {
+            let fee_percent = 1_000_000 / u64::from(self.0.proportional_millionths);
+            invoice_amount.msats / fee_percent
+        } else {
+            0
+        };
+
+        fedimint_core::Amount::from_msats(base_fee + margin_fee)
+    }
+}
@@ -1,5 +1,6 @@
 pub mod incoming;
 pub mod outgoing;
+pub mod transfer;
 
 use std::io::Error;
 
@@ -29,6 +30,7 @@ hash_newtype!(
 pub enum Contract {
     Incoming(incoming::IncomingContract),
     Outgoing(outgoing::OutgoingContract),
+    Transfer(transfer::TransferContract),
 }
 
 /// A contract after execution as saved in the database
@@ -37,6 +39,7 @@ pub enum Contract {
 pub enum FundedContract {
     Incoming(incoming::FundedIncomingContract),
     Outgoing(outgoing::OutgoingContract),
+    Transfer(transfer::TransferContract),
 }
 
 /// Outcome of a contract. Only incoming contracts currently need to communicate
@@ -45,6 +48,7 @@ pub enum FundedContract {
 pub enum ContractOutcome {
     Incoming(DecryptedPreimage),
     Outgoing(OutgoingContractOutcome),
+    Transfer(TransferContractOutcome),
 }
 
 impl ContractOutcome {
@@ -52,6 +56,7 @@ impl ContractOutcome {
         match self {
             ContractOutcome::Incoming(o) => o.is_permanent(),
             ContractOutcome::Outgoing(_) => true,
+            ContractOutcome::Transfer(_) => true,
         }
     }
 }
@@ -59,11 +64,15 @@ impl ContractOutcome {
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct OutgoingContractOutcome {}
 
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct TransferContractOutcome {}
+
 impl IdentifiableContract for Contract {
     fn contract_id(&self) -> ContractId {
         match self {
             Contract::Incoming(c) => c.contract_id(),
             Contract::Outgoing(c) => c.contract_id(),
+            Contract::Transfer(c) => c.contract_id(),
         }
     }
 }
@@ -73,6 +82,7 @@ impl IdentifiableContract for FundedContract {
         match self {
             FundedContract::Incoming(c) => c.contract.contract_id(),
             FundedContract::Outgoing(c) => c.contract_id(),
+            FundedContract::Transfer(c) => c.contract_id(),
         }
     }
 }
@@ -84,6 +94,7 @@ impl Contract {
         match self {
             Contract::Incoming(_) => ContractOutcome::Incoming(DecryptedPreimage::Pending),
             Contract::Outgoing(_) => ContractOutcome::Outgoing(OutgoingContractOutcome {}),
+            Contract::Transfer(_) => ContractOutcome::Transfer(TransferContractOutcome {}),
         }
     }
 
@@ -97,6 +108,7 @@ impl Contract {
                 })
             }
             Contract::Outgoing(outgoing) => FundedContract::Outgoing(outgoing),
+            Contract::Transfer(transfer) => FundedContract::Transfer(transfer),
         }
     }
 }
@@ -0,0 +1,158 @@
+//! Transfers between federations served by the same gateway
+//!
+//! A user of one federation can pay a user of another federation without a
+//! lightning invoice if a gateway is registered with both. The recipient
+//! submits an [`IncomingContractOffer`](super::incoming::IncomingContractOffer)
+//! to their federation as for any incoming payment and hands the payer a
+//! [`TransferRequest`]. The payer locks the requested amount plus the fee of
+//! the gateway in a [`TransferContract`] hashlocked to the payment hash of the
+//! offer. The gateway buys the preimage by funding the incoming contract in the
+//! recipient's federation and claims the transfer contract with it, so either
+//! both sides of the transfer happen or the payer gets a refund once the
+//! gateway cancels the contract or its timelock expires.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use bitcoin_hashes::Hash as BitcoinHash;
+use fedimint_core::config::FederationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{serde_as_encodable_hex, Amount};
+use serde::{Deserialize, Serialize};
+
+use super::Preimage;
+use crate::contracts::{ContractId, IdentifiableContract};
+use crate::LightningInput;
+
+const CONTRACT_ID_TAG: &str = "transfer contract";
+
+const CANCELLATION_TAG: &str = "transfer contract cancellation";
+
+/// Specialized smart contract for transfers to another federation.
+///
+/// Like an [`OutgoingContract`](super::outgoing::OutgoingContract) the funds
+/// can be claimed by the gateway with the preimage of the payment hash before
+/// the timelock expires and by the user afterwards, but instead of an invoice
+/// the contract names the federation and the amount the gateway has to fund
+/// the recipient's incoming contract with.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct TransferContract {
+    /// Payment hash of the recipient's offer
+    pub hash: bitcoin_hashes::sha256::Hash,
+    /// Public key of the gateway allowed to claim the funds before the
+    /// timelock expires
+    pub gateway_key: secp256k1::XOnlyPublicKey,
+    /// Block height at which the money will be spendable by the user key
+    pub timelock: u32,
+    /// Public key of the user that can claim the money back after the timelock
+    /// expires
+    pub user_key: secp256k1::XOnlyPublicKey,
+    /// Federation of the recipient
+    pub federation_id: FederationId,
+    /// Amount the recipient's offer asks for, the contract holds it plus the
+    /// fee of the gateway
+    pub amount: Amount,
+    /// Flag that can be set by the gateway and allows the user to claim an
+    /// early refund
+    pub cancelled: bool,
+}
+
+impl IdentifiableContract for TransferContract {
+    fn contract_id(&self) -> ContractId {
+        let mut engine = ContractId::engine();
+        Encodable::consensus_encode(&CONTRACT_ID_TAG.as_bytes(), &mut engine)
+            .expect("Hashing never fails");
+        Encodable::consensus_encode(&self.hash, &mut engine).expect("Hashing never fails");
+        Encodable::consensus_encode(&self.gateway_key, &mut engine).expect("Hashing never fails");
+        Encodable::consensus_encode(&self.timelock, &mut engine).expect("Hashing never fails");
+        Encodable::consensus_encode(&self.user_key, &mut engine).expect("Hashing never fails");
+        Encodable::consensus_encode(&self.federation_id, &mut engine).expect("Hashing never fails");
+        Encodable::consensus_encode(&self.amount, &mut engine).expect("Hashing never fails");
+        ContractId::from_engine(engine)
+    }
+}
+
+impl TransferContract {
+    pub fn cancellation_message(&self) -> bitcoin_hashes::sha256::Hash {
+        let mut engine = bitcoin_hashes::sha256::Hash::engine();
+        Encodable::consensus_encode(&CANCELLATION_TAG.as_bytes(), &mut engine)
+            .expect("Hashing never fails");
+        Encodable::consensus_encode(&self.contract_id(), &mut engine).expect("Hashing never fails");
+        bitcoin_hashes::sha256::Hash::from_engine(engine)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct TransferContractAccount {
+    pub amount: Amount,
+    pub contract: TransferContract,
+}
+
+impl TransferContractAccount {
+    /// The fee the gateway earns by claiming the contract
+    pub fn gateway_fee(&self) -> Amount {
+        self.amount.saturating_sub(self.contract.amount)
+    }
+
+    pub fn claim(&self, preimage: Preimage) -> LightningInput {
+        LightningInput {
+            contract_id: self.contract.contract_id(),
+            amount: self.amount,
+            witness: Some(preimage),
+        }
+    }
+
+    pub fn refund(&self) -> LightningInput {
+        LightningInput {
+            contract_id: self.contract.contract_id(),
+            amount: self.amount,
+            witness: None,
+        }
+    }
+}
+
+/// What the recipient of a transfer hands the payer, the counterpart of an
+/// invoice
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct TransferRequest {
+    /// Federation the recipient's offer was submitted to
+    pub federation_id: FederationId,
+    /// Gateway serving the recipient's federation that should carry out the
+    /// transfer
+    pub gateway_id: secp256k1::PublicKey,
+    pub amount: Amount,
+    /// Payment hash of the recipient's offer
+    pub hash: bitcoin_hashes::sha256::Hash,
+    /// Seconds since the unix epoch after which the recipient no longer waits
+    /// for the transfer
+    pub expires_at: u64,
+}
+
+serde_as_encodable_hex!(TransferRequest);
+
+impl TransferRequest {
+    pub fn is_expired(&self) -> bool {
+        let now = fedimint_core::time::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("Time is after the unix epoch");
+
+        self.expires_at <= now.as_secs()
+    }
+}
+
+impl Display for TransferRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let hex = self
+            .consensus_encode_to_hex()
+            .expect("Encoding to a string never fails");
+        f.write_str(&hex)
+    }
+}
+
+impl FromStr for TransferRequest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Decodable::consensus_decode_hex(s, &Default::default())?)
+    }
+}
@@ -20,6 +20,7 @@ pub enum DbKeyPrefix {
     BlockCountVote = 0x46,
     EncryptedPreimageIndex = 0x47,
     LightningAuditItem = 0x48,
+    ContractFunding = 0x49,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -42,6 +43,28 @@ impl_db_record!(
 );
 impl_db_lookup!(key = ContractKey, query_prefix = ContractKeyPrefix);
 
+/// The outputs that funded a contract, such that the guardians can tell which
+/// transactions an input spending the contract depends on
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ContractFundingKey(pub ContractId, pub OutPoint);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ContractFundingKeyPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ContractFundingContractIdPrefix(pub ContractId);
+
+impl_db_record!(
+    key = ContractFundingKey,
+    value = (),
+    db_prefix = DbKeyPrefix::ContractFunding,
+);
+impl_db_lookup!(
+    key = ContractFundingKey,
+    query_prefix = ContractFundingKeyPrefix,
+    query_prefix = ContractFundingContractIdPrefix
+);
+
 #[derive(Debug, Encodable, Decodable, Serialize)]
 pub struct ContractUpdateKey(pub OutPoint);
 
@@ -76,6 +99,7 @@ impl_db_lookup!(
 pub enum LightningAuditItemKey {
     Incoming(ContractId),
     Outgoing(ContractId),
+    Transfer(ContractId),
 }
 
 impl LightningAuditItemKey {
@@ -87,6 +111,9 @@ impl LightningAuditItemKey {
             FundedContract::Incoming(incoming) => {
                 LightningAuditItemKey::Incoming(incoming.contract.contract_id())
             }
+            FundedContract::Transfer(transfer) => {
+                LightningAuditItemKey::Transfer(transfer.contract_id())
+            }
         }
     }
 }
@@ -0,0 +1,31 @@
+//! Events emitted by the lightning module, see
+//! [`fedimint_core::module::event`]
+
+use fedimint_core::module::event::ModuleEvent;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+use crate::contracts::ContractId;
+use crate::LightningGatewayAnnouncement;
+
+/// A gateway registered or renewed its registration with the guardian
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GatewayRegistered {
+    pub gateway: LightningGatewayAnnouncement,
+}
+
+impl ModuleEvent for GatewayRegistered {
+    const KIND: &'static str = "gateway_registered";
+}
+
+/// The account of a contract was funded, spent from or otherwise changed its
+/// state, query the contract for its current state
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ContractUpdated {
+    pub contract_id: ContractId,
+    pub amount: Amount,
+}
+
+impl ModuleEvent for ContractUpdated {
+    const KIND: &'static str = "contract_updated";
+}
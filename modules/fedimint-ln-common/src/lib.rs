@@ -13,6 +13,7 @@ pub mod api;
 pub mod config;
 pub mod contracts;
 pub mod db;
+pub mod events;
 
 use std::time::{Duration, SystemTime};
 
@@ -43,8 +44,8 @@ pub struct LightningInput {
     /// While for now we only support spending the entire contract we need to
     /// avoid
     pub amount: Amount,
-    /// Of the three contract types only the outgoing one needs any other
-    /// witness data than a signature. The signature is aggregated on the
+    /// Of the contract types only the outgoing and transfer ones need any
+    /// other witness data than a signature. The signature is aggregated on the
     /// transaction level, so only the optional preimage remains.
     pub witness: Option<Preimage>,
 }
@@ -64,7 +65,8 @@ impl std::fmt::Display for LightningInput {
 /// There are three sub-types:
 ///   * Normal contracts users may lock funds in
 ///   * Offers to buy preimages (see `contracts::incoming` docs)
-///   * Early cancellation of outgoing contracts before their timeout
+///   * Early cancellation of outgoing and transfer contracts before their
+///     timeout
 ///
 /// The offer type exists to register `IncomingContractOffer`s. Instead of
 /// patching in a second way of letting clients submit consensus items outside
@@ -77,7 +79,7 @@ pub enum LightningOutput {
     Contract(ContractOutput),
     /// Create incoming contract offer
     Offer(contracts::incoming::IncomingContractOffer),
-    /// Allow early refund of outgoing contract
+    /// Allow early refund of outgoing or transfer contract
     CancelOutgoing {
         /// Contract to update
         contract: ContractId,
@@ -104,6 +106,13 @@ impl std::fmt::Display for LightningOutput {
                         amount, outgoing.hash
                     )
                 }
+                Contract::Transfer(transfer) => {
+                    write!(
+                        f,
+                        "LN Transfer Contract for {} to federation {} hash {}",
+                        amount, transfer.federation_id, transfer.hash
+                    )
+                }
             },
             LightningOutput::Offer(offer) => {
                 write!(f, "LN offer for {} with hash {}", offer.amount, offer.hash)
@@ -467,7 +476,7 @@ pub enum LightningError {
     InsufficientIncomingFunding(Amount, Amount),
     #[error("No offer found for payment hash {0}")]
     NoOffer(secp256k1::hashes::sha256::Hash),
-    #[error("Only outgoing contracts support cancellation")]
+    #[error("Only outgoing and transfer contracts support cancellation")]
     NotOutgoingContract,
     #[error("Cancellation request wasn't properly signed")]
     InvalidCancellationSignature,
@@ -19,6 +19,7 @@ use fedimint_core::endpoint_constants::{
     WAIT_OFFER_ENDPOINT, WAIT_OUTGOING_CONTRACT_CANCELLED_ENDPOINT, WAIT_PREIMAGE_DECRYPTION,
 };
 use fedimint_core::module::audit::Audit;
+use fedimint_core::module::event::emit_event;
 use fedimint_core::module::{
     api_endpoint, ApiEndpoint, ApiEndpointContext, CoreConsensusVersion, ExtendsCommonModuleInit,
     InputMeta, IntoModuleError, ModuleConsensusVersion, ModuleError, PeerHandle, ServerModuleInit,
@@ -41,12 +42,14 @@ use fedimint_ln_common::contracts::{
 };
 use fedimint_ln_common::db::{
     AgreedDecryptionShareContractIdPrefix, AgreedDecryptionShareKey,
-    AgreedDecryptionShareKeyPrefix, BlockCountVoteKey, BlockCountVotePrefix, ContractKey,
+    AgreedDecryptionShareKeyPrefix, BlockCountVoteKey, BlockCountVotePrefix,
+    ContractFundingContractIdPrefix, ContractFundingKey, ContractFundingKeyPrefix, ContractKey,
     ContractKeyPrefix, ContractUpdateKey, ContractUpdateKeyPrefix, DbKeyPrefix,
     EncryptedPreimageIndexKey, EncryptedPreimageIndexKeyPrefix, LightningAuditItemKey,
     LightningAuditItemKeyPrefix, LightningGatewayKey, LightningGatewayKeyPrefix, OfferKey,
     OfferKeyPrefix, ProposeDecryptionShareKey, ProposeDecryptionShareKeyPrefix,
 };
+use fedimint_ln_common::events::{ContractUpdated, GatewayRegistered};
 use fedimint_ln_common::{
     ContractAccount, LightningCommonGen, LightningConsensusItem, LightningError,
     LightningGatewayAnnouncement, LightningGatewayRegistration, LightningInput,
@@ -84,6 +87,11 @@ lazy_static! {
         "contracts::FundedContract::Outgoing"
     ))
     .unwrap();
+    static ref LN_FUNDED_CONTRACT_TRANSFER: IntCounter = register_int_counter!(opts!(
+        "ln_funded_contract_transfer",
+        "contracts::FundedContract::Transfer"
+    ))
+    .unwrap();
     static ref AMOUNTS_BUCKETS_SATS: Vec<f64> = vec![0.0, 0.5, 1.0, 1000.0];
     static ref LN_FUNDED_CONTRACT_INCOMING_ACCOUNT_AMOUNTS_SATS: Histogram =
         register_histogram!(histogram_opts!(
@@ -99,13 +107,22 @@ lazy_static! {
             AMOUNTS_BUCKETS_SATS.clone()
         ))
         .unwrap();
-    static ref ALL_METRICS: [Box<dyn prometheus::core::Collector>; 6] = [
+    static ref LN_FUNDED_CONTRACT_TRANSFER_ACCOUNT_AMOUNTS_SATS: Histogram =
+        register_histogram!(histogram_opts!(
+            "ln_funded_contract_transfer_account_amounts_sats",
+            "contracts::FundedContract::Transfer account amounts in sats",
+            AMOUNTS_BUCKETS_SATS.clone()
+        ))
+        .unwrap();
+    static ref ALL_METRICS: [Box<dyn prometheus::core::Collector>; 8] = [
         Box::new(LN_INCOMING_OFFER.clone()),
         Box::new(LN_OUTPUT_OUTCOME_CANCEL_OUTGOING_CONTRACT.clone()),
         Box::new(LN_FUNDED_CONTRACT_INCOMING.clone()),
         Box::new(LN_FUNDED_CONTRACT_OUTGOING.clone()),
+        Box::new(LN_FUNDED_CONTRACT_TRANSFER.clone()),
         Box::new(LN_FUNDED_CONTRACT_INCOMING_ACCOUNT_AMOUNTS_SATS.clone()),
         Box::new(LN_FUNDED_CONTRACT_OUTGOING_ACCOUNT_AMOUNTS_SATS.clone()),
+        Box::new(LN_FUNDED_CONTRACT_TRANSFER_ACCOUNT_AMOUNTS_SATS.clone()),
     ];
 }
 
@@ -218,6 +235,16 @@ impl ExtendsCommonModuleInit for LightningGen {
                         "Lightning Audit Items"
                     );
                 }
+                DbKeyPrefix::ContractFunding => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ContractFundingKeyPrefix,
+                        ContractFundingKey,
+                        (),
+                        lightning,
+                        "Contract Fundings"
+                    );
+                }
             }
         }
 
@@ -418,6 +445,9 @@ impl ServerModule for Lightning {
                     FundedContract::Outgoing(..) => {
                         bail!("Contract account for this decryption share is outgoing");
                     }
+                    FundedContract::Transfer(..) => {
+                        bail!("Contract account for this decryption share is a transfer");
+                    }
                 };
 
                 if contract.decrypted_preimage != DecryptedPreimage::Pending {
@@ -502,6 +532,14 @@ impl ServerModule for Lightning {
                 incoming.contract.decrypted_preimage = decrypted_preimage.clone();
                 trace!(?contract_account, "Updating contract account");
                 dbtx.insert_entry(&contract_db_key, &contract_account).await;
+                emit_event(
+                    dbtx,
+                    &ContractUpdated {
+                        contract_id,
+                        amount: contract_account.amount,
+                    },
+                )
+                .await;
 
                 // Update output outcome
                 let mut outcome = dbtx
@@ -563,30 +601,20 @@ impl ServerModule for Lightning {
         let consensus_block_count = self.consensus_block_count(dbtx).await;
 
         let pub_key = match &account.contract {
-            FundedContract::Outgoing(outgoing) => {
-                if outgoing.timelock as u64 + 1 > consensus_block_count && !outgoing.cancelled {
-                    // If the timelock hasn't expired yet …
-                    let preimage_hash = bitcoin_hashes::sha256::Hash::hash(
-                        &input
-                            .witness
-                            .as_ref()
-                            .ok_or(LightningError::MissingPreimage)
-                            .into_module_error_other()?
-                            .0,
-                    );
-
-                    // … and the spender provides a valid preimage …
-                    if preimage_hash != outgoing.hash {
-                        return Err(LightningError::InvalidPreimage).into_module_error_other();
-                    }
-
-                    // … then the contract account can be spent using the gateway key,
-                    outgoing.gateway_key
-                } else {
-                    // otherwise the user can claim the funds back.
-                    outgoing.user_key
-                }
-            }
+            FundedContract::Outgoing(outgoing) => hash_locked_spend_key(
+                input,
+                outgoing.hash,
+                outgoing.gateway_key,
+                outgoing.user_key,
+                outgoing.timelock as u64 + 1 > consensus_block_count && !outgoing.cancelled,
+            )?,
+            FundedContract::Transfer(transfer) => hash_locked_spend_key(
+                input,
+                transfer.hash,
+                transfer.gateway_key,
+                transfer.user_key,
+                transfer.timelock as u64 + 1 > consensus_block_count && !transfer.cancelled,
+            )?,
             FundedContract::Incoming(incoming) => match &incoming.contract.decrypted_preimage {
                 // Once the preimage has been decrypted …
                 DecryptedPreimage::Pending => {
@@ -608,6 +636,14 @@ impl ServerModule for Lightning {
 
         dbtx.insert_entry(&ContractKey(input.contract_id), &account)
             .await;
+        emit_event(
+            dbtx,
+            &ContractUpdated {
+                contract_id: input.contract_id,
+                amount: account.amount,
+            },
+        )
+        .await;
 
         // When a contract reaches a terminal state, the associated amount will be
         // updated to 0. At this point, the contract no longer needs to be tracked
@@ -625,9 +661,22 @@ impl ServerModule for Lightning {
                 fee: self.cfg.consensus.fee_consensus.contract_input,
             },
             pub_keys: vec![pub_key],
+            fee_payout: Amount::ZERO,
         })
     }
 
+    async fn input_dependencies<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransactionRef<'b>,
+        input: &'a LightningInput,
+    ) -> Vec<OutPoint> {
+        dbtx.find_by_prefix(&ContractFundingContractIdPrefix(input.contract_id))
+            .await
+            .map(|(key, ())| key.1)
+            .collect()
+            .await
+    }
+
     async fn process_output<'a, 'b>(
         &'a self,
         dbtx: &mut DatabaseTransactionRef<'b>,
@@ -696,9 +745,29 @@ impl ServerModule for Lightning {
                                 .observe(updated_contract_account.amount.msats as f64 / 1000.0);
                             LN_FUNDED_CONTRACT_OUTGOING.inc();
                         }
+                        FundedContract::Transfer(_) => {
+                            LN_FUNDED_CONTRACT_TRANSFER_ACCOUNT_AMOUNTS_SATS
+                                .observe(updated_contract_account.amount.msats as f64 / 1000.0);
+                            LN_FUNDED_CONTRACT_TRANSFER.inc();
+                        }
                     }
                 }
 
+                emit_event(
+                    dbtx,
+                    &ContractUpdated {
+                        contract_id: contract.contract.contract_id(),
+                        amount: updated_contract_account.amount,
+                    },
+                )
+                .await;
+
+                dbtx.insert_entry(
+                    &ContractFundingKey(contract.contract.contract_id(), out_point),
+                    &(),
+                )
+                .await;
+
                 dbtx.insert_new_entry(
                     &ContractUpdateKey(out_point),
                     &LightningOutputOutcome::Contract {
@@ -777,9 +846,14 @@ impl ServerModule for Lightning {
                     .ok_or(LightningError::UnknownContract(*contract))
                     .into_module_error_other()?;
 
-                let outgoing_contract = match &contract_account.contract {
-                    FundedContract::Outgoing(contract) => contract,
-                    _ => {
+                let (cancellation_message, gateway_key) = match &contract_account.contract {
+                    FundedContract::Outgoing(contract) => {
+                        (contract.cancellation_message(), contract.gateway_key)
+                    }
+                    FundedContract::Transfer(contract) => {
+                        (contract.cancellation_message(), contract.gateway_key)
+                    }
+                    FundedContract::Incoming(_) => {
                         return Err(LightningError::NotOutgoingContract).into_module_error_other();
                     }
                 };
@@ -787,8 +861,8 @@ impl ServerModule for Lightning {
                 secp256k1::global::SECP256K1
                     .verify_schnorr(
                         gateway_signature,
-                        &outgoing_contract.cancellation_message().into(),
-                        &outgoing_contract.gateway_key,
+                        &cancellation_message.into(),
+                        &gateway_key,
                     )
                     .map_err(|_| LightningError::InvalidCancellationSignature)
                     .into_module_error_other()?;
@@ -799,20 +873,27 @@ impl ServerModule for Lightning {
                         .await
                         .expect("Contract exists if output is valid");
 
-                    let outgoing_contract = match &mut contract_account.contract {
-                        FundedContract::Outgoing(contract) => contract,
-                        _ => {
+                    match &mut contract_account.contract {
+                        FundedContract::Outgoing(contract) => contract.cancelled = true,
+                        FundedContract::Transfer(contract) => contract.cancelled = true,
+                        FundedContract::Incoming(_) => {
                             panic!("Contract type was checked in validate_output");
                         }
-                    };
-
-                    outgoing_contract.cancelled = true;
+                    }
 
                     contract_account
                 };
 
                 dbtx.insert_entry(&ContractKey(*contract), &updated_contract_account)
                     .await;
+                emit_event(
+                    dbtx,
+                    &ContractUpdated {
+                        contract_id: *contract,
+                        amount: updated_contract_account.amount,
+                    },
+                )
+                .await;
 
                 dbtx.insert_new_entry(
                     &ContractUpdateKey(out_point),
@@ -835,6 +916,11 @@ impl ServerModule for Lightning {
         dbtx.get_value(&ContractUpdateKey(out_point)).await
     }
 
+    /// The contract accounts and the offers of the gateways
+    fn committed_state_prefixes(&self) -> Vec<u8> {
+        vec![DbKeyPrefix::Contract as u8, DbKeyPrefix::Offer as u8]
+    }
+
     async fn audit(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -1026,6 +1112,7 @@ impl Lightning {
             context.wait_value_matches(ContractKey(contract_id), |contract| {
                 match &contract.contract {
                     FundedContract::Outgoing(c) => c.cancelled,
+                    FundedContract::Transfer(c) => c.cancelled,
                     _ => false,
                 }
             });
@@ -1106,9 +1193,11 @@ impl Lightning {
 
         dbtx.insert_entry(
             &LightningGatewayKey(gateway.info.node_pub_key),
-            &gateway.anchor(),
+            &gateway.clone().anchor(),
         )
         .await;
+
+        emit_event(dbtx, &GatewayRegistered { gateway }).await;
     }
 
     async fn delete_expired_gateways(&self, dbtx: &mut DatabaseTransactionRef<'_>) {
@@ -1131,12 +1220,47 @@ impl Lightning {
     }
 }
 
+/// The key that has to sign the spend of a contract locked to a payment hash:
+/// while the contract is `hash_locked` the gateway key if the spender provides
+/// the preimage, afterwards the user key
+fn hash_locked_spend_key(
+    input: &LightningInput,
+    hash: bitcoin_hashes::sha256::Hash,
+    gateway_key: secp256k1::XOnlyPublicKey,
+    user_key: secp256k1::XOnlyPublicKey,
+    hash_locked: bool,
+) -> Result<secp256k1::XOnlyPublicKey, ModuleError> {
+    if !hash_locked {
+        // If the timelock expired or the gateway cancelled the contract the user can
+        // claim the funds back …
+        return Ok(user_key);
+    }
+
+    let preimage_hash = bitcoin_hashes::sha256::Hash::hash(
+        &input
+            .witness
+            .as_ref()
+            .ok_or(LightningError::MissingPreimage)
+            .into_module_error_other()?
+            .0,
+    );
+
+    // … otherwise the spender has to provide a valid preimage …
+    if preimage_hash != hash {
+        return Err(LightningError::InvalidPreimage).into_module_error_other();
+    }
+
+    // … and the contract account can be spent using the gateway key.
+    Ok(gateway_key)
+}
+
 #[cfg(test)]
 mod tests {
     use assert_matches::assert_matches;
     use bitcoin_hashes::Hash as BitcoinHash;
     use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
     use fedimint_core::config::ConfigGenModuleParams;
+    use fedimint_core::config::FederationId;
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
     use fedimint_core::encoding::Encodable;
@@ -1151,11 +1275,13 @@ mod tests {
         FundedIncomingContract, IncomingContract, IncomingContractOffer,
     };
     use fedimint_ln_common::contracts::outgoing::OutgoingContract;
+    use fedimint_ln_common::contracts::transfer::TransferContract;
     use fedimint_ln_common::contracts::{
-        DecryptedPreimage, EncryptedPreimage, FundedContract, IdentifiableContract, Preimage,
+        Contract, DecryptedPreimage, EncryptedPreimage, FundedContract, IdentifiableContract,
+        Preimage,
     };
     use fedimint_ln_common::db::{ContractKey, LightningAuditItemKey};
-    use fedimint_ln_common::{ContractAccount, LightningInput, LightningOutput};
+    use fedimint_ln_common::{ContractAccount, ContractOutput, LightningInput, LightningOutput};
     use lightning_invoice::Bolt11Invoice;
     use rand::rngs::OsRng;
     use secp256k1::{generate_keypair, XOnlyPublicKey};
@@ -1321,6 +1447,7 @@ mod tests {
             pub_keys: vec![preimage
                 .to_public_key()
                 .expect("should create Schnorr pubkey from preimage")],
+            fee_payout: Amount::ZERO,
         };
 
         assert_eq!(processed_input_meta, expected_input_meta);
@@ -1381,6 +1508,7 @@ mod tests {
                 fee: Amount { msats: 0 },
             },
             pub_keys: vec![gateway_key],
+            fee_payout: Amount::ZERO,
         };
 
         assert_eq!(processed_input_meta, expected_input_meta);
@@ -1388,6 +1516,149 @@ mod tests {
         let audit_item = module_dbtx.get_value(&audit_key).await;
         assert_eq!(audit_item, None);
     }
+
+    #[test_log::test(tokio::test)]
+    async fn process_input_for_transfer_contracts() {
+        let (server_cfg, _) = build_configs();
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.dbtx_ref_with_prefix_module_id(42);
+        let mut tg = TaskGroup::new();
+        let server = Lightning::new(server_cfg[0].clone(), &mut tg).unwrap();
+
+        let preimage = Preimage([42u8; 32]);
+        let gateway_key = random_x_only_pub_key();
+        let user_key = random_x_only_pub_key();
+        let transfer_contract = TransferContract {
+            hash: preimage.consensus_hash(),
+            gateway_key,
+            timelock: 1000000,
+            user_key,
+            federation_id: FederationId::dummy(),
+            amount: Amount { msats: 900 },
+            cancelled: false,
+        };
+        let contract_id = transfer_contract.contract_id();
+        let amount = Amount { msats: 1000 };
+
+        module_dbtx
+            .insert_new_entry(
+                &ContractKey(contract_id),
+                &ContractAccount {
+                    amount,
+                    contract: FundedContract::Transfer(transfer_contract.clone()),
+                },
+            )
+            .await;
+
+        // the gateway has to provide the preimage while the contract is locked
+        let wrong_preimage = LightningInput {
+            contract_id,
+            amount: Amount { msats: 500 },
+            witness: Some(Preimage([21u8; 32])),
+        };
+        assert!(server
+            .process_input(&mut module_dbtx, &wrong_preimage)
+            .await
+            .is_err());
+
+        let claim = LightningInput {
+            contract_id,
+            amount: Amount { msats: 500 },
+            witness: Some(preimage),
+        };
+        assert_eq!(
+            server
+                .process_input(&mut module_dbtx, &claim)
+                .await
+                .expect("should process valid transfer contract")
+                .pub_keys,
+            vec![gateway_key]
+        );
+
+        // once the gateway cancelled the transfer the user can claim a refund
+        module_dbtx
+            .insert_entry(
+                &ContractKey(contract_id),
+                &ContractAccount {
+                    amount: Amount { msats: 500 },
+                    contract: FundedContract::Transfer(TransferContract {
+                        cancelled: true,
+                        ..transfer_contract
+                    }),
+                },
+            )
+            .await;
+
+        let refund = LightningInput {
+            contract_id,
+            amount: Amount { msats: 500 },
+            witness: None,
+        };
+        assert_eq!(
+            server
+                .process_input(&mut module_dbtx, &refund)
+                .await
+                .expect("should process refund of cancelled transfer contract")
+                .pub_keys,
+            vec![user_key]
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn inputs_depend_on_contract_fundings() {
+        let (server_cfg, _) = build_configs();
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.dbtx_ref_with_prefix_module_id(42);
+        let mut tg = TaskGroup::new();
+        let server = Lightning::new(server_cfg[0].clone(), &mut tg).unwrap();
+
+        let invoice = str::parse::<Bolt11Invoice>("lnbc10u1pjq37rgsp5cry9r0qqdzp0tl0m27jedvxtrazq0v8xh5rfvzuhm7yxydg50m9qpp5r0cjzjzt7pjwae8trp6dtteh6hstdakzv68atpqx0zshaexghpwsdqqcqpjrzjqfzekav6v27ra0lf3geqmg3hj3xvfu652cuyhk8aa7naqdqvwh6x7zagh5qqy3qqqyqqqqqpqqqqqqgq9q9qyysgq6vf5z83a2q2ua9nwanmc7pql26pwt8smt2xzwp7kjd0mgplmy925s5yz6nlfxt99p2dlffw82gw8kte7lv87pcf4nahslg2vyhhkzwqqxuqmgp")
+            .expect("should parse a valid invoice string");
+        let contract = Contract::Outgoing(OutgoingContract {
+            hash: Preimage([42u8; 32]).consensus_hash(),
+            gateway_key: random_x_only_pub_key(),
+            timelock: 1000000,
+            user_key: random_x_only_pub_key(),
+            invoice,
+            cancelled: false,
+        });
+        let output = LightningOutput::Contract(ContractOutput {
+            amount: Amount { msats: 1000 },
+            contract: contract.clone(),
+        });
+        let lightning_input = LightningInput {
+            contract_id: contract.contract_id(),
+            amount: Amount { msats: 2000 },
+            witness: None,
+        };
+
+        assert!(server
+            .input_dependencies(&mut module_dbtx, &lightning_input)
+            .await
+            .is_empty());
+
+        // the contract is funded by two transactions
+        let fundings = [1u8, 2].map(|byte| OutPoint {
+            txid: TransactionId::from_slice(&[byte; 32]).unwrap(),
+            out_idx: 0,
+        });
+
+        for out_point in fundings {
+            server
+                .process_output(&mut module_dbtx, &output, out_point)
+                .await
+                .expect("should process valid outgoing contract");
+        }
+
+        assert_eq!(
+            server
+                .input_dependencies(&mut module_dbtx, &lightning_input)
+                .await,
+            fundings.to_vec()
+        );
+    }
 }
 
 #[cfg(test)]
@@ -1710,6 +1981,8 @@ mod fedimint_migration_tests {
                                 "validate_migrations was not able to read both LightningAuditItemKeys"
                             );
                         }
+                        // Introduced after the v0 snapshot was created
+                        DbKeyPrefix::ContractFunding => {}
                     }
                 }
                 Ok(())
@@ -0,0 +1,28 @@
+use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
+use fedimint_core::endpoint_constants::SPENT_NOTES_ENDPOINT;
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_mint_common::Nonce;
+
+#[apply(async_trait_maybe_send!)]
+pub trait MintFederationApi {
+    /// Whether the notes with the nonces were spent, at most
+    /// [`MAX_SPENT_NOTES_PER_REQUEST`](fedimint_mint_common::MAX_SPENT_NOTES_PER_REQUEST)
+    /// at once
+    async fn spent_notes(&self, nonces: Vec<Nonce>) -> FederationResult<Vec<bool>>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> MintFederationApi for T
+where
+    T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn spent_notes(&self, nonces: Vec<Nonce>) -> FederationResult<Vec<bool>> {
+        self.request_current_consensus(
+            SPENT_NOTES_ENDPOINT.to_string(),
+            ApiRequestErased::new(nonces),
+        )
+        .await
+    }
+}
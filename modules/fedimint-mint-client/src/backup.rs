@@ -1,13 +1,19 @@
+use anyhow::bail;
 use fedimint_client::sm::Executor;
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::api::{DynGlobalApi, GlobalFederationApi};
 use fedimint_core::core::ModuleInstanceId;
-use fedimint_core::db::DatabaseTransactionRef;
-use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::db::{DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::{Amount, OutPoint, Tiered, TieredMulti};
+use fedimint_logging::LOG_CLIENT_RECOVERY_MINT;
 use serde::{Deserialize, Serialize};
+use tracing::debug;
 
 use super::MintClientModule;
+use crate::backup::recovery::RESTORE_NOTES_CHUNK_SIZE;
+use crate::client_db::RestoreNotesChunkKey;
 use crate::output::{MintOutputStateMachine, NoteIssuanceRequest};
 use crate::{MintClientStateMachines, NoteIndex, SpendableNote};
 
@@ -37,6 +43,82 @@ impl EcashBackup {
     }
 }
 
+/// Decodes the spendable notes of an encoded [`EcashBackup`] one at a time
+///
+/// Clients with hundreds of thousands of notes can not afford to decode their
+/// whole backup at once on memory constrained devices, so the restore reads
+/// the notes in chunks and keeps only the remainder of the backup in memory.
+pub struct EcashBackupNotesReader<'a> {
+    bytes: &'a [u8],
+    /// Number of amount tiers we did not start reading yet
+    tiers_left: u64,
+    /// Number of notes left in the current amount tier
+    notes_left: u64,
+    amount: Option<Amount>,
+}
+
+impl<'a> EcashBackupNotesReader<'a> {
+    pub fn new(mut bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        let tiers_left = u64::consensus_decode(&mut bytes, &ModuleDecoderRegistry::default())?;
+
+        Ok(Self {
+            bytes,
+            tiers_left,
+            notes_left: 0,
+            amount: None,
+        })
+    }
+
+    /// Decodes the next note, returns `None` once all notes have been read
+    pub fn next_note(&mut self) -> Result<Option<(Amount, SpendableNote)>, DecodeError> {
+        let modules = ModuleDecoderRegistry::default();
+
+        while self.notes_left == 0 {
+            if self.tiers_left == 0 {
+                return Ok(None);
+            }
+
+            let amount = Amount::consensus_decode(&mut self.bytes, &modules)?;
+
+            // the tiers are encoded from a map, so they have to be unique
+            if self.amount.map_or(false, |previous| previous >= amount) {
+                return Err(DecodeError::new_custom(anyhow::anyhow!(
+                    "Amount tiers of the backup are not sorted"
+                )));
+            }
+
+            self.amount = Some(amount);
+            self.notes_left = u64::consensus_decode(&mut self.bytes, &modules)?;
+            self.tiers_left -= 1;
+        }
+
+        let note = SpendableNote::consensus_decode(&mut self.bytes, &modules)?;
+
+        self.notes_left -= 1;
+
+        Ok(Some((
+            self.amount
+                .expect("Set before reading the first note of a tier"),
+            note,
+        )))
+    }
+
+    /// Skips the remaining notes and decodes the rest of the backup, whose
+    /// spendable notes are left empty
+    pub fn finish(mut self) -> Result<EcashBackup, DecodeError> {
+        while self.next_note()?.is_some() {}
+
+        let modules = ModuleDecoderRegistry::default();
+
+        Ok(EcashBackup {
+            spendable_notes: TieredMulti::default(),
+            pending_notes: Decodable::consensus_decode(&mut self.bytes, &modules)?,
+            epoch_count: Decodable::consensus_decode(&mut self.bytes, &modules)?,
+            next_note_idx: Decodable::consensus_decode(&mut self.bytes, &modules)?,
+        })
+    }
+}
+
 impl MintClientModule {
     pub async fn prepare_plaintext_ecash_backup(
         &self,
@@ -45,14 +127,26 @@ impl MintClientModule {
         api: DynGlobalApi,
         module_instance_id: ModuleInstanceId,
     ) -> anyhow::Result<EcashBackup> {
+        let active_states = executor.get_active_states().await;
+
+        // A backup taken before the restore finished would lack the restored notes
+        // and replace the backup we are restoring from
+        if active_states.iter().any(|(dyn_state, _)| {
+            dyn_state.module_instance_id() == module_instance_id
+                && matches!(
+                    dyn_state.as_any().downcast_ref::<MintClientStateMachines>(),
+                    Some(MintClientStateMachines::Restore(_))
+                )
+        }) {
+            bail!("Can not back up e-cash while a restore is in progress");
+        }
+
         // fetch consensus height first - so we dont miss anything when scanning
         let fedimint_block_count = api.fetch_block_count().await?;
 
         let notes = Self::get_all_spendable_notes(dbtx).await;
 
-        let pending_notes: Vec<(OutPoint, Amount, NoteIssuanceRequest)> = executor
-            .get_active_states()
-            .await
+        let pending_notes: Vec<(OutPoint, Amount, NoteIssuanceRequest)> = active_states
             .into_iter()
             .filter_map(|(dyn_state, _active_state)| {
                 if dyn_state.module_instance_id() != module_instance_id {
@@ -91,4 +185,106 @@ impl MintClientModule {
             epoch_count: fedimint_block_count,
         })
     }
+
+    /// Stores the spendable notes of an encoded backup in chunks to be
+    /// imported once the restore scanned the history, returns the rest of the
+    /// backup
+    pub(crate) async fn stage_backup_notes(
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        snapshot: &[u8],
+    ) -> anyhow::Result<EcashBackup> {
+        let mut reader = EcashBackupNotesReader::new(snapshot)?;
+        let mut chunk = Vec::with_capacity(RESTORE_NOTES_CHUNK_SIZE);
+        let mut chunk_idx = 0;
+
+        loop {
+            let note = reader.next_note()?;
+            let done = note.is_none();
+
+            chunk.extend(note);
+
+            if chunk.len() == RESTORE_NOTES_CHUNK_SIZE || (done && !chunk.is_empty()) {
+                dbtx.insert_new_entry(&RestoreNotesChunkKey(chunk_idx), &chunk)
+                    .await;
+
+                chunk.clear();
+                chunk_idx += 1;
+            }
+
+            if done {
+                break;
+            }
+        }
+
+        debug!(
+            target: LOG_CLIENT_RECOVERY_MINT,
+            chunks = chunk_idx,
+            "Staged spendable notes of the backup"
+        );
+
+        Ok(reader.finish()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::encoding::Encodable;
+    use fedimint_core::{Amount, Tiered, TieredMulti};
+    use secp256k1::KeyPair;
+    use threshold_crypto::G1Affine;
+
+    use super::{EcashBackup, EcashBackupNotesReader};
+    use crate::{NoteIndex, SpendableNote};
+
+    fn note(seed: u8) -> SpendableNote {
+        SpendableNote {
+            signature: tbs::Signature(G1Affine::generator()),
+            spend_key: KeyPair::from_seckey_slice(secp256k1::SECP256K1, &[seed; 32])
+                .expect("Valid secret key"),
+        }
+    }
+
+    #[test]
+    fn notes_reader_decodes_backup() {
+        let spendable_notes: TieredMulti<SpendableNote> = [
+            (Amount::from_msats(1), note(1)),
+            (Amount::from_msats(1), note(2)),
+            (Amount::from_msats(4), note(3)),
+            (Amount::from_msats(1024), note(4)),
+        ]
+        .into_iter()
+        .collect();
+
+        let backup = EcashBackup {
+            spendable_notes: spendable_notes.clone(),
+            pending_notes: vec![],
+            epoch_count: 42,
+            next_note_idx: Tiered::from_iter([(Amount::from_msats(1), NoteIndex(3))]),
+        };
+        let bytes = backup
+            .consensus_encode_to_vec()
+            .expect("Encoding can't fail");
+
+        let mut reader = EcashBackupNotesReader::new(&bytes).expect("Valid backup");
+        let mut notes = vec![];
+
+        while let Some(note) = reader.next_note().expect("Valid backup") {
+            notes.push(note);
+        }
+
+        assert_eq!(notes, spendable_notes.into_iter_items().collect::<Vec<_>>());
+
+        let rest = EcashBackupNotesReader::new(&bytes)
+            .expect("Valid backup")
+            .finish()
+            .expect("Valid backup");
+
+        assert_eq!(
+            rest,
+            EcashBackup {
+                spendable_notes: TieredMulti::default(),
+                ..backup
+            }
+        );
+    }
 }
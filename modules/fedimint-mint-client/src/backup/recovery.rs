@@ -7,22 +7,25 @@ use fedimint_client::DynGlobalClientContext;
 use fedimint_core::api::{DynGlobalApi, GlobalFederationApi};
 use fedimint_core::block::Block;
 use fedimint_core::core::{OperationId, LEGACY_HARDCODED_INSTANCE_ID_MINT};
-use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::db::{DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::epoch::ConsensusItem;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::yield_now;
 use fedimint_core::transaction::Transaction;
 use fedimint_core::{Amount, NumPeers, OutPoint, PeerId, Tiered, TieredMulti};
 use fedimint_derive_secret::DerivableSecret;
 use fedimint_logging::LOG_CLIENT_RECOVERY_MINT;
 use fedimint_mint_common::{MintInput, MintOutput, Nonce};
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use tbs::{AggregatePublicKey, BlindedMessage, PublicKeyShare};
 use threshold_crypto::G1Affine;
+use tokio::sync::watch;
 use tracing::{debug, info, trace, warn};
 
 use super::EcashBackup;
-use crate::client_db::{NextECashNoteIndexKey, NoteKey};
+use crate::client_db::{NextECashNoteIndexKey, NoteKey, RestoreNotesChunkKeyPrefix};
 use crate::output::{
     MintOutputCommon, MintOutputStateMachine, MintOutputStatesCreated, NoteIssuanceRequest,
 };
@@ -30,6 +33,9 @@ use crate::{
     MintClientContext, MintClientModule, MintClientStateMachines, NoteIndex, SpendableNote,
 };
 
+/// Maximum number of spendable notes of a backup imported in one transition
+pub(crate) const RESTORE_NOTES_CHUNK_SIZE: usize = 1000;
+
 #[derive(Debug)]
 pub struct EcashRecoveryFinalState {
     spendable_notes: TieredMulti<SpendableNote>,
@@ -128,117 +134,137 @@ impl MintRestoreInProgressState {
         let global_context_2 = global_context.clone();
         let secret = context.secret.clone();
         let self_clone = self.clone();
-        vec![StateTransition::new(
-            async move {
-                self_clone
-                    .make_progress(
-                        global_context.api().clone(),
-                        global_context.decoders().clone(),
-                        secret,
+        let paused_state = self.clone();
+        vec![
+            StateTransition::new(
+                await_restore_paused(context.restore_paused.clone(), true),
+                move |_dbtx, (), old_state_machine: MintRestoreStateMachine| {
+                    let paused_state = paused_state.clone();
+                    Box::pin(async move {
+                        info!(target: LOG_CLIENT_RECOVERY_MINT, "Pausing restore");
+                        MintRestoreStateMachine {
+                            operation_id: old_state_machine.operation_id,
+                            state: MintRestoreStates::Paused(paused_state),
+                        }
+                    })
+                },
+            ),
+            StateTransition::new(
+                async move {
+                    self_clone
+                        .make_progress(
+                            global_context.api().clone(),
+                            global_context.decoders().clone(),
+                            secret,
+                        )
+                        .await
+                        .consensus_encode_to_hex()
+                        .expect("Serialization here can't fail")
+                },
+                move |dbtx, new_state_hex, old_state_machine: MintRestoreStateMachine| {
+                    let new_state = MintRestoreInProgressState::consensus_decode_hex(
+                        &new_state_hex,
+                        &Default::default(),
                     )
-                    .await
-                    .consensus_encode_to_hex()
-                    .expect("Serialization here can't fail")
-            },
-            move |dbtx, new_state_hex, old_state_machine: MintRestoreStateMachine| {
-                let new_state = MintRestoreInProgressState::consensus_decode_hex(
-                    &new_state_hex,
-                    &Default::default(),
-                )
-                .expect("Deserialization here can't fail");
-                let global_context = global_context_2.clone();
-                Box::pin(async move {
-                    if new_state.is_done() {
-                        debug!(
-                            target: LOG_CLIENT_RECOVERY_MINT,
-                            ?new_state,
-                            "Finalizing restore"
-                        );
-
-                        let finalized = new_state.finalize();
-
-                        let restored_amount = finalized
-                            .unconfirmed_notes
-                            .iter()
-                            .map(|entry| entry.1)
-                            .sum::<Amount>()
-                            + finalized.spendable_notes.total_amount();
-
-                        {
-                            let mut dbtx = dbtx.module_tx();
-
+                    .expect("Deserialization here can't fail");
+                    let global_context = global_context_2.clone();
+                    Box::pin(async move {
+                        if new_state.is_done() {
                             debug!(
                                 target: LOG_CLIENT_RECOVERY_MINT,
-                                len = finalized.spendable_notes.count_items(),
-                                "Restoring spendable notes"
+                                ?new_state,
+                                "Finalizing restore"
                             );
-                            for (amount, note) in finalized.spendable_notes {
-                                let key = NoteKey {
-                                    amount,
-                                    nonce: note.nonce(),
-                                };
-                                dbtx.insert_new_entry(&key, &note).await;
-                            }
 
-                            for (amount, note_idx) in finalized.next_note_idx.iter() {
+                            let finalized = new_state.finalize();
+
+                            let restored_amount = finalized
+                                .unconfirmed_notes
+                                .iter()
+                                .map(|entry| entry.1)
+                                .sum::<Amount>()
+                                + finalized.spendable_notes.total_amount();
+
+                            {
+                                let mut dbtx = dbtx.module_tx();
+
                                 debug!(
                                     target: LOG_CLIENT_RECOVERY_MINT,
-                                    %amount,
-                                    %note_idx,
-                                    "Restoring NextECashNodeIndex"
+                                    len = finalized.spendable_notes.count_items(),
+                                    "Restoring spendable notes"
                                 );
-                                dbtx.insert_entry(
-                                    &NextECashNoteIndexKey(amount),
-                                    &note_idx.as_u64(),
-                                )
-                                .await;
+                                for (amount, note) in finalized.spendable_notes {
+                                    let key = NoteKey {
+                                        amount,
+                                        nonce: note.nonce(),
+                                    };
+                                    dbtx.insert_new_entry(&key, &note).await;
+                                }
+
+                                for (amount, note_idx) in finalized.next_note_idx.iter() {
+                                    debug!(
+                                        target: LOG_CLIENT_RECOVERY_MINT,
+                                        %amount,
+                                        %note_idx,
+                                        "Restoring NextECashNodeIndex"
+                                    );
+                                    dbtx.insert_entry(
+                                        &NextECashNoteIndexKey(amount),
+                                        &note_idx.as_u64(),
+                                    )
+                                    .await;
+                                }
                             }
-                        }
 
-                        debug!(
-                            target: LOG_CLIENT_RECOVERY_MINT,
-                            len = finalized.unconfirmed_notes.len(),
-                            "Restoring unconfigured notes state machines"
-                        );
-
-                        for (out_point, amount, issuance_request) in finalized.unconfirmed_notes {
-                            global_context
-                                .add_state_machine(
-                                    dbtx,
-                                    MintClientStateMachines::Output(MintOutputStateMachine {
-                                        common: MintOutputCommon {
-                                            operation_id,
-                                            out_point,
-                                        },
-                                        state: crate::output::MintOutputStates::Created(
-                                            MintOutputStatesCreated {
-                                                amount,
-                                                issuance_request,
+                            debug!(
+                                target: LOG_CLIENT_RECOVERY_MINT,
+                                len = finalized.unconfirmed_notes.len(),
+                                "Restoring unconfigured notes state machines"
+                            );
+
+                            for (out_point, amount, issuance_request) in finalized.unconfirmed_notes
+                            {
+                                global_context
+                                    .add_state_machine(
+                                        dbtx,
+                                        MintClientStateMachines::Output(MintOutputStateMachine {
+                                            common: MintOutputCommon {
+                                                operation_id,
+                                                out_point,
                                             },
-                                        ),
-                                    }),
-                                )
-                                .await
-                                .expect("Adding state machine can't fail")
-                        }
+                                            state: crate::output::MintOutputStates::Created(
+                                                MintOutputStatesCreated {
+                                                    amount,
+                                                    issuance_request,
+                                                },
+                                            ),
+                                        }),
+                                    )
+                                    .await
+                                    .expect("Adding state machine can't fail")
+                            }
 
-                        MintRestoreStateMachine {
-                            operation_id: old_state_machine.operation_id,
-                            state: MintRestoreStates::Success(restored_amount),
-                        }
-                    } else {
-                        debug!(
-                            target: LOG_CLIENT_RECOVERY_MINT,
-                            "Saving restore progress checkpoint"
-                        );
-                        MintRestoreStateMachine {
-                            operation_id: old_state_machine.operation_id,
-                            state: MintRestoreStates::InProgress(new_state),
+                            MintRestoreStateMachine {
+                                operation_id: old_state_machine.operation_id,
+                                state: MintRestoreStates::ImportingNotes(MintRestoreImportState {
+                                    imported_notes: 0,
+                                    restored_amount,
+                                }),
+                            }
+                        } else {
+                            debug!(
+                                target: LOG_CLIENT_RECOVERY_MINT,
+                                "Saving restore progress checkpoint"
+                            );
+                            MintRestoreStateMachine {
+                                operation_id: old_state_machine.operation_id,
+                                state: MintRestoreStates::InProgress(new_state),
+                            }
                         }
-                    }
-                })
-            },
-        )]
+                    })
+                },
+            ),
+        ]
     }
 
     async fn make_progress<'a>(
@@ -257,6 +283,9 @@ impl MintRestoreInProgressState {
         {
             if let ConsensusItem::Transaction(transaction) = accepted_item.item {
                 self.handle_transaction(&transaction, &secret);
+                // blocks can hold many transactions, which must not block the single threaded
+                // event loop of WASM clients for too long
+                yield_now().await;
             }
         }
 
@@ -540,6 +569,92 @@ impl MintRestoreInProgressState {
     }
 }
 
+/// Imports the spendable notes of the backup that were staged in chunks by
+/// [`MintClientModule::stage_backup_notes`], one chunk per transition
+///
+/// Every chunk is removed in the same transaction that imports its notes and
+/// saves the new state, so an interrupted restore resumes with the first chunk
+/// that was not imported yet.
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub(crate) struct MintRestoreImportState {
+    imported_notes: u64,
+    restored_amount: Amount,
+}
+
+impl MintRestoreImportState {
+    fn transitions(
+        &self,
+        operation_id: OperationId,
+    ) -> Vec<StateTransition<MintRestoreStateMachine>> {
+        let state = self.clone();
+        vec![StateTransition::new(
+            futures::future::ready(()),
+            move |dbtx, (), _old_state_machine: MintRestoreStateMachine| {
+                let state = state.clone();
+                Box::pin(async move {
+                    state
+                        .import_chunk(operation_id, &mut dbtx.module_tx())
+                        .await
+                })
+            },
+        )]
+    }
+
+    async fn import_chunk(
+        mut self,
+        operation_id: OperationId,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> MintRestoreStateMachine {
+        let chunk = dbtx
+            .find_by_prefix(&RestoreNotesChunkKeyPrefix)
+            .await
+            .next()
+            .await;
+
+        let Some((key, notes)) = chunk else {
+            info!(
+                target: LOG_CLIENT_RECOVERY_MINT,
+                imported_notes = self.imported_notes,
+                restored_amount = %self.restored_amount,
+                "Finished restore"
+            );
+
+            return MintRestoreStateMachine {
+                operation_id,
+                state: MintRestoreStates::Success(self.restored_amount),
+            };
+        };
+
+        dbtx.remove_entry(&key).await;
+
+        for (amount, note) in notes {
+            dbtx.insert_new_entry(
+                &NoteKey {
+                    amount,
+                    nonce: note.nonce(),
+                },
+                &note,
+            )
+            .await;
+
+            self.imported_notes += 1;
+            self.restored_amount += amount;
+        }
+
+        debug!(
+            target: LOG_CLIENT_RECOVERY_MINT,
+            chunk = key.0,
+            imported_notes = self.imported_notes,
+            "Imported chunk of spendable notes"
+        );
+
+        MintRestoreStateMachine {
+            operation_id,
+            state: MintRestoreStates::ImportingNotes(self),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub(crate) struct MintRestoreFailedState {
     pub reason: String,
@@ -566,6 +681,23 @@ impl State for MintRestoreStateMachine {
             }
             MintRestoreStates::Failed(_) => vec![],
             MintRestoreStates::Success(_) => vec![],
+            MintRestoreStates::ImportingNotes(state) => state.transitions(self.operation_id),
+            MintRestoreStates::Paused(state) => {
+                let state = state.clone();
+                vec![StateTransition::new(
+                    await_restore_paused(context.restore_paused.clone(), false),
+                    move |_dbtx, (), old_state_machine: MintRestoreStateMachine| {
+                        let state = state.clone();
+                        Box::pin(async move {
+                            info!(target: LOG_CLIENT_RECOVERY_MINT, "Resuming restore");
+                            MintRestoreStateMachine {
+                                operation_id: old_state_machine.operation_id,
+                                state: MintRestoreStates::InProgress(state),
+                            }
+                        })
+                    },
+                )]
+            }
         }
     }
 
@@ -591,4 +723,95 @@ pub(crate) enum MintRestoreStates {
     Success(Amount),
     /// Something went wrong, and restore failed
     Failed(MintRestoreFailedState),
+    /// The history was scanned, importing the spendable notes of the backup
+    ImportingNotes(MintRestoreImportState),
+    /// Scanning the history was paused, the blocks scanned so far are kept to
+    /// resume where it stopped
+    Paused(MintRestoreInProgressState),
+}
+
+impl MintRestoreStates {
+    pub(crate) fn progress(&self) -> RestoreProgress {
+        match self {
+            MintRestoreStates::InProgress(state) => RestoreProgress::ScanningBlocks {
+                scanned: state.next_epoch.saturating_sub(state.start_epoch),
+                total: state.end_epoch.saturating_sub(state.start_epoch),
+            },
+            MintRestoreStates::Paused(state) => RestoreProgress::Paused {
+                scanned: state.next_epoch.saturating_sub(state.start_epoch),
+                total: state.end_epoch.saturating_sub(state.start_epoch),
+            },
+            MintRestoreStates::ImportingNotes(state) => RestoreProgress::ImportingNotes {
+                imported: state.imported_notes,
+            },
+            MintRestoreStates::Success(amount) => RestoreProgress::Success {
+                restored_amount: *amount,
+            },
+            MintRestoreStates::Failed(state) => RestoreProgress::Failed {
+                reason: state.reason.clone(),
+            },
+        }
+    }
+}
+
+/// Progress of restoring the e-cash of a backup, see
+/// [`MintClientExt::subscribe_restore_progress`](crate::MintClientExt::subscribe_restore_progress)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreProgress {
+    /// Scanning the blocks since the backup was taken for notes issued to us
+    ScanningBlocks {
+        scanned: u64,
+        total: u64,
+    },
+    /// The blocks were scanned, importing the spendable notes of the backup
+    ImportingNotes {
+        imported: u64,
+    },
+    Success {
+        restored_amount: Amount,
+    },
+    Failed {
+        reason: String,
+    },
+    /// Scanning the blocks was paused, see
+    /// [`MintClientExt::pause_restore`](crate::MintClientExt::pause_restore)
+    Paused {
+        scanned: u64,
+        total: u64,
+    },
+}
+
+impl RestoreProgress {
+    /// How much of the restore is done in percent, which is mostly scanning
+    /// the blocks. `None` if the restore failed.
+    pub fn percent(&self) -> Option<u8> {
+        match self {
+            RestoreProgress::ScanningBlocks { scanned, total }
+            | RestoreProgress::Paused { scanned, total } => {
+                // the restore is only done once the notes were imported
+                let percent = scanned.saturating_mul(100) / max(*total, 1);
+                Some(percent.min(99) as u8)
+            }
+            RestoreProgress::ImportingNotes { .. } => Some(99),
+            RestoreProgress::Success { .. } => Some(100),
+            RestoreProgress::Failed { .. } => None,
+        }
+    }
+}
+
+/// Waits until the restore is paused or resumed through
+/// [`MintClientContext::restore_paused`], which is unset until either happened
+/// since the client started, such that a paused restore stays paused
+async fn await_restore_paused(mut restore_paused: watch::Receiver<Option<bool>>, paused: bool) {
+    loop {
+        if *restore_paused.borrow_and_update() == Some(paused) {
+            return;
+        }
+
+        if restore_paused.changed().await.is_err() {
+            // the module is gone, so the state machine is not driven anymore
+            std::future::pending::<()>().await;
+        }
+    }
 }
@@ -11,6 +11,7 @@ use crate::SpendableNote;
 pub enum DbKeyPrefix {
     Note = 0x20,
     NextECashNoteIndex = 0x2a,
+    RestoreNotesChunk = 0x2b,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -50,3 +51,21 @@ impl_db_lookup!(
     key = NextECashNoteIndexKey,
     query_prefix = NextECashNoteIndexKeyPrefix
 );
+
+/// Spendable notes of a backup that the restore did not import yet, see
+/// [`crate::backup::EcashBackupNotesReader`]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct RestoreNotesChunkKey(pub u64);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct RestoreNotesChunkKeyPrefix;
+
+impl_db_record!(
+    key = RestoreNotesChunkKey,
+    value = Vec<(Amount, SpendableNote)>,
+    db_prefix = DbKeyPrefix::RestoreNotesChunk,
+);
+impl_db_lookup!(
+    key = RestoreNotesChunkKey,
+    query_prefix = RestoreNotesChunkKeyPrefix
+);
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, TieredSummary, TransactionId};
+use fedimint_mint_common::config::FeeConsensus;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::client_db::{NoteKey, NoteKeyPrefix};
+use crate::input::spend_note_input;
+use crate::MintClientContext;
+
+/// The number of notes of each denomination the client tries to hold, which
+/// can be configured through [`MintClientGen`](crate::MintClientGen)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct DenominationTarget {
+    /// Notes we issue to ourselves are split into denominations such that we
+    /// hold this many notes of each denomination
+    pub notes_per_denomination: u16,
+    /// Once we hold more notes of a denomination than this, the surplus is
+    /// reissued in the background after the next issuance. Notes are never
+    /// consolidated if not set.
+    ///
+    /// Values below `notes_per_denomination + 1` are raised to it, as the
+    /// reissued notes could exceed the threshold again otherwise.
+    pub consolidate_above: Option<u16>,
+}
+
+impl Default for DenominationTarget {
+    fn default() -> Self {
+        DenominationTarget {
+            notes_per_denomination: 2,
+            consolidate_above: None,
+        }
+    }
+}
+
+impl DenominationTarget {
+    /// The number of notes of each denomination we hold in excess of the
+    /// target and should reissue. Notes of the largest denomination of the
+    /// federation are never in excess since they cannot be merged any further.
+    pub fn surplus(&self, held: &TieredSummary, max_denomination: Amount) -> TieredSummary {
+        let mut surplus = TieredSummary::default();
+
+        let Some(consolidate_above) = self.consolidate_above else {
+            return surplus;
+        };
+        let threshold =
+            consolidate_above.max(self.notes_per_denomination.saturating_add(1)) as usize;
+
+        for (amount, count) in held.iter() {
+            if amount < max_denomination && threshold < count {
+                surplus.inc(amount, count - self.notes_per_denomination as usize);
+            }
+        }
+
+        surplus
+    }
+}
+
+/// State machine reissuing the notes of denominations we hold too many of
+/// once an issuance was accepted, which only exists if consolidation is
+/// enabled by the [`DenominationTarget`].
+///
+/// ```mermaid
+/// graph LR
+///     Created -- containing tx rejected --> Aborted
+///     Created -- containing tx accepted --> Consolidated
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum MintConsolidateStates {
+    /// The notes were requested, we are waiting for their transaction to be
+    /// accepted
+    Created,
+    /// The transaction requesting the notes was rejected
+    Aborted,
+    /// The surplus notes were reissued in the transaction, if there were any
+    Consolidated(MintConsolidateStatesConsolidated),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintConsolidateCommon {
+    pub(crate) operation_id: OperationId,
+    /// The transaction requesting the notes
+    pub(crate) txid: TransactionId,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintConsolidateStateMachine {
+    pub(crate) common: MintConsolidateCommon,
+    pub(crate) state: MintConsolidateStates,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintConsolidateStatesConsolidated {
+    pub(crate) consolidation_txid: Option<TransactionId>,
+}
+
+impl State for MintConsolidateStateMachine {
+    type ModuleContext = MintClientContext;
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        context: &Self::ModuleContext,
+        global_context: &Self::GlobalContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            MintConsolidateStates::Created => {
+                let context = context.clone();
+                let global_context = global_context.clone();
+                vec![StateTransition::new(
+                    await_issuance_accepted(global_context.clone(), self.common.clone()),
+                    move |dbtx, result, old_state| {
+                        Box::pin(transition_consolidate(
+                            dbtx,
+                            result,
+                            old_state,
+                            context.clone(),
+                            global_context.clone(),
+                        ))
+                    },
+                )]
+            }
+            MintConsolidateStates::Aborted | MintConsolidateStates::Consolidated(_) => {
+                vec![]
+            }
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.common.operation_id
+    }
+}
+
+async fn await_issuance_accepted(
+    global_context: DynGlobalClientContext,
+    common: MintConsolidateCommon,
+) -> Result<(), String> {
+    global_context
+        .await_tx_accepted(common.operation_id, common.txid)
+        .await
+}
+
+/// Reissues the surplus notes we hold at this point, the change is issued
+/// according to the [`DenominationTarget`] again. Notes of the accepted
+/// issuance that were not stored yet are considered after the next one.
+async fn transition_consolidate(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    result: Result<(), String>,
+    old_state: MintConsolidateStateMachine,
+    context: MintClientContext,
+    global_context: DynGlobalClientContext,
+) -> MintConsolidateStateMachine {
+    assert!(matches!(old_state.state, MintConsolidateStates::Created));
+
+    if result.is_err() {
+        return MintConsolidateStateMachine {
+            common: old_state.common,
+            state: MintConsolidateStates::Aborted,
+        };
+    }
+
+    let notes = dbtx
+        .module_tx()
+        .find_by_prefix(&NoteKeyPrefix)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut held = TieredSummary::default();
+    for (key, _) in &notes {
+        held.inc(key.amount, 1);
+    }
+
+    let mut surplus = context
+        .denomination_target
+        .surplus(&held, *context.tbs_pks.max_tier())
+        .iter()
+        .collect::<BTreeMap<_, _>>();
+
+    let mut surplus_notes = vec![];
+    for (key, note) in notes {
+        if let Some(count) = surplus.get_mut(&key.amount).filter(|count| 0 < **count) {
+            *count -= 1;
+            surplus_notes.push((key, note));
+        }
+    }
+
+    let consolidation_txid = if worth_consolidating(&surplus_notes, &context.fee_consensus) {
+        let operation_id = old_state.common.operation_id;
+        let mut inputs = vec![];
+
+        for (key, spendable_note) in surplus_notes {
+            dbtx.module_tx().remove_entry(&key).await;
+            inputs.push(spend_note_input(operation_id, key.amount, spendable_note));
+        }
+
+        debug!(notes = inputs.len(), "Consolidating surplus notes");
+        Some(global_context.claim_inputs(dbtx, inputs).await.0)
+    } else {
+        None
+    };
+
+    MintConsolidateStateMachine {
+        common: old_state.common,
+        state: MintConsolidateStates::Consolidated(MintConsolidateStatesConsolidated {
+            consolidation_txid,
+        }),
+    }
+}
+
+/// Whether the notes are worth more than the fees of reissuing them, assuming
+/// at most one note is issued for every note we spend
+fn worth_consolidating<N>(notes: &[(NoteKey, N)], fees: &FeeConsensus) -> bool {
+    let amount = notes.iter().map(|(key, _)| key.amount).sum::<Amount>();
+    let fee = (fees.note_spend_abs + fees.note_issuance_abs) * notes.len() as u64;
+
+    !notes.is_empty() && fee < amount
+}
@@ -222,6 +222,35 @@ impl MintInputStateRefund {
     }
 }
 
+/// Creates an input spending one of our notes, whose state machine refunds the
+/// note if the transaction is rejected
+pub(crate) fn spend_note_input(
+    operation_id: OperationId,
+    amount: Amount,
+    spendable_note: SpendableNote,
+) -> ClientInput<MintInput, MintClientStateMachines> {
+    ClientInput {
+        input: MintInput {
+            amount,
+            note: spendable_note.note(),
+        },
+        keys: vec![spendable_note.spend_key],
+        state_machines: Arc::new(move |txid, input_idx| {
+            vec![MintClientStateMachines::Input(MintInputStateMachine {
+                common: MintInputCommon {
+                    operation_id,
+                    txid,
+                    input_idx,
+                },
+                state: MintInputStates::Created(MintInputStateCreated {
+                    amount,
+                    spendable_note,
+                }),
+            })]
+        }),
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct MintInputStateSuccess {}
 
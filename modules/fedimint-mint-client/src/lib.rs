@@ -1,14 +1,23 @@
+/// Federation API endpoints of the mint module
+pub mod api;
 // Backup and restore logic
 pub(crate) mod backup;
 /// Database keys used throughout the mint client module
 mod client_db;
+/// State machines reissuing notes of denominations we hold too many of
+mod consolidate;
 /// State machines for mint inputs
 mod input;
+/// Encrypted e-cash notes transferred while one of the parties is offline
+mod offline;
 /// State machines for out-of-band transmitted e-cash notes
 mod oob;
 /// State machines for mint outputs
 mod output;
-
+/// State machines reissuing notes before they expire
+mod refresh;
+/// Strategies for selecting the notes we spend
+mod select;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::ffi;
@@ -22,6 +31,7 @@ use async_stream::stream;
 use backup::recovery::{MintRestoreStateMachine, MintRestoreStates};
 use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine as BitcoinHashEngine};
 use client_db::DbKeyPrefix;
+use fedimint_client::balance::{BalanceItem, OperationFees, PendingStage};
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::{ClientModule, IClientModule};
 use fedimint_client::oplog::{OperationLogEntry, UpdateStreamOrOutcome};
@@ -48,7 +58,8 @@ use fedimint_core::{
 };
 use fedimint_derive_secret::{ChildId, DerivableSecret};
 pub use fedimint_mint_common as common;
-use fedimint_mint_common::config::MintClientConfig;
+use fedimint_mint_common::config::{FeeConsensus, MintClientConfig};
+use fedimint_mint_common::expiry::NoteExpiryClientConfig;
 pub use fedimint_mint_common::*;
 use futures::{pin_mut, StreamExt};
 use secp256k1::{All, KeyPair, Secp256k1};
@@ -58,19 +69,31 @@ use tbs::AggregatePublicKey;
 use thiserror::Error;
 use tracing::{debug, info, warn};
 
+use crate::api::MintFederationApi;
 use crate::backup::recovery::MintRestoreInProgressState;
+pub use crate::backup::recovery::RestoreProgress;
 use crate::backup::EcashBackup;
 use crate::client_db::{
     NextECashNoteIndexKey, NextECashNoteIndexKeyPrefix, NoteKey, NoteKeyPrefix,
+    RestoreNotesChunkKey, RestoreNotesChunkKeyPrefix,
+};
+pub use crate::consolidate::DenominationTarget;
+use crate::consolidate::{
+    MintConsolidateCommon, MintConsolidateStateMachine, MintConsolidateStates,
 };
 use crate::input::{
     MintInputCommon, MintInputStateCreated, MintInputStateMachine, MintInputStates,
 };
+pub use crate::offline::{
+    DoubleSpendError, OfflineNotes, OfflineNotesChunk, OfflineNotesKey, MAX_OFFLINE_CHUNK_LEN,
+};
 use crate::oob::{MintOOBStateMachine, MintOOBStates, MintOOBStatesCreated};
 use crate::output::{
-    MintOutputCommon, MintOutputStateMachine, MintOutputStates, MintOutputStatesCreated,
+    MintOutputCommon, MintOutputStateMachine, MintOutputStates, MintOutputStatesCreatedMulti,
     NoteIssuanceRequest,
 };
+use crate::refresh::{MintRefreshCommon, MintRefreshStateMachine, MintRefreshStates};
+pub use crate::select::{NoteSelectionStrategy, SelectNotesMinimizingChange, SelectNotesRandomly};
 
 const MINT_E_CASH_TYPE_CHILD_ID: ChildId = ChildId(0);
 
@@ -158,7 +181,8 @@ pub trait MintClientExt {
     ) -> anyhow::Result<UpdateStreamOrOutcome<ReissueExternalNotesState>>;
 
     /// Fetches and removes notes of *at least* amount `min_amount` from the
-    /// wallet to be sent to the recipient out of band. These spends can be
+    /// wallet to be sent to the recipient out of band, selected with the
+    /// [`NoteSelectionStrategy`] of the module. These spends can be
     /// canceled by calling [`MintClientExt::try_cancel_spend_notes`] as long as
     /// the recipient hasn't reissued the e-cash notes themselves yet.
     ///
@@ -206,6 +230,46 @@ pub trait MintClientExt {
 
     /// Awaits the backup restoration to complete
     async fn await_restore_finished(&self) -> anyhow::Result<Amount>;
+
+    /// Subscribe to the progress of the backup restoration, the stream ends
+    /// once it succeeded or failed
+    async fn subscribe_restore_progress(&self) -> BoxStream<'static, RestoreProgress>;
+
+    /// Pauses scanning the blocks of a backup restoration, the blocks scanned
+    /// so far are kept and the restore stays paused across restarts of the
+    /// client until it is resumed with [`MintClientExt::resume_restore`]
+    fn pause_restore(&self);
+
+    /// Resumes a backup restoration paused with
+    /// [`MintClientExt::pause_restore`]
+    fn resume_restore(&self);
+
+    /// Spends notes like [`MintClientExt::spend_notes`] and encrypts them with
+    /// a random key, such that they can be handed over as QR codes to a
+    /// recipient who reissues them with
+    /// [`MintClientExt::reissue_offline_notes`] once they are online. The key
+    /// should be handed over separately from the chunks.
+    async fn spend_notes_offline<M: Serialize + Send>(
+        &self,
+        min_amount: Amount,
+        try_cancel_after: Duration,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, OfflineNotes)>;
+
+    /// Returns the number of notes of each denomination the federation has
+    /// seen being spent
+    async fn find_spent_notes(&self, oob_notes: &OOBNotes) -> anyhow::Result<TieredSummary>;
+
+    /// Decrypts notes created with [`MintClientExt::spend_notes_offline`] and
+    /// reissues them. Fails with a [`DoubleSpendError`] if the federation has
+    /// seen some of them being spent already. If that cannot be checked
+    /// because the federation is unreachable, the notes are still reissued
+    /// and the operation fails later if they were spent.
+    async fn reissue_offline_notes<M: Serialize + Send>(
+        &self,
+        offline_notes: OfflineNotes,
+        extra_meta: M,
+    ) -> anyhow::Result<OperationId>;
 }
 
 /// The high-level state of a reissue operation started with
@@ -362,13 +426,13 @@ impl MintClientExt for ClientArc {
         try_cancel_after: Duration,
         extra_meta: M,
     ) -> anyhow::Result<(OperationId, OOBNotes)> {
-        self.spend_notes_with_selector(
-            &SelectNotesWithAtleastAmount,
-            min_amount,
-            try_cancel_after,
-            extra_meta,
-        )
-        .await
+        let note_selection = self
+            .get_first_module::<MintClientModule>(&KIND)
+            .0
+            .note_selection;
+
+        self.spend_notes_with_selector(&note_selection, min_amount, try_cancel_after, extra_meta)
+            .await
     }
 
     async fn spend_notes_with_selector<M: Serialize + Send>(
@@ -447,6 +511,7 @@ impl MintClientExt for ClientArc {
         }
 
         let tbs_pks = &mint.cfg.tbs_pks;
+        let session_index = mint.expiry_session_index().await?;
 
         for (idx, (amt, snote)) in notes.iter_items().enumerate() {
             let key = tbs_pks
@@ -454,8 +519,8 @@ impl MintClientExt for ClientArc {
                 .ok_or_else(|| anyhow!("Note {idx} uses an invalid amount tier {amt}"))?;
 
             let note = snote.note();
-            if !note.verify(*key) {
-                bail!("Note {idx} has an invalid federation signature");
+            if !mint.verify_note(*key, amt, &note, session_index) {
+                bail!("Note {idx} has an invalid federation signature or expired");
             }
 
             let expected_nonce = Nonce(snote.spend_key.x_only_public_key().0);
@@ -538,6 +603,90 @@ impl MintClientExt for ClientArc {
         let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
         mint.await_restore_finished().await
     }
+
+    async fn subscribe_restore_progress(&self) -> BoxStream<'static, RestoreProgress> {
+        let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
+        mint.subscribe_restore_progress().await
+    }
+
+    fn pause_restore(&self) {
+        let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
+        mint.restore_paused.send_replace(Some(true));
+    }
+
+    fn resume_restore(&self) {
+        let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
+        mint.restore_paused.send_replace(Some(false));
+    }
+
+    async fn spend_notes_offline<M: Serialize + Send>(
+        &self,
+        min_amount: Amount,
+        try_cancel_after: Duration,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, OfflineNotes)> {
+        let (operation_id, oob_notes) = self
+            .spend_notes(min_amount, try_cancel_after, extra_meta)
+            .await?;
+
+        let offline_notes = OfflineNotes::encrypt(&oob_notes, OfflineNotesKey::random())?;
+
+        Ok((operation_id, offline_notes))
+    }
+
+    async fn find_spent_notes(&self, oob_notes: &OOBNotes) -> anyhow::Result<TieredSummary> {
+        let (_mint, instance) = self.get_first_module::<MintClientModule>(&KIND);
+        let notes = oob_notes
+            .notes
+            .iter_items()
+            .map(|(amount, note)| (amount, note.nonce()))
+            .collect::<Vec<_>>();
+
+        let mut spent_notes = TieredSummary::default();
+        for chunk in notes.chunks(MAX_SPENT_NOTES_PER_REQUEST) {
+            let nonces = chunk.iter().map(|(_, nonce)| *nonce).collect();
+            let spent = instance.api.spent_notes(nonces).await?;
+            ensure!(
+                spent.len() == chunk.len(),
+                "Federation returned the status of {} instead of {} notes",
+                spent.len(),
+                chunk.len()
+            );
+
+            for ((amount, _), spent) in chunk.iter().zip(spent) {
+                if spent {
+                    spent_notes.inc(*amount, 1);
+                }
+            }
+        }
+
+        Ok(spent_notes)
+    }
+
+    async fn reissue_offline_notes<M: Serialize + Send>(
+        &self,
+        offline_notes: OfflineNotes,
+        extra_meta: M,
+    ) -> anyhow::Result<OperationId> {
+        let oob_notes = offline_notes.decrypt()?;
+
+        match self.find_spent_notes(&oob_notes).await {
+            Ok(spent_notes) if spent_notes.count_items() != 0 => {
+                return Err(DoubleSpendError {
+                    spent_notes: spent_notes.count_items(),
+                    spent_amount: spent_notes.total_amount(),
+                    total_amount: oob_notes.total_amount(),
+                }
+                .into());
+            }
+            Ok(_) => {}
+            Err(error) => {
+                warn!("Could not check whether the notes were spent already: {error}");
+            }
+        }
+
+        self.reissue_external_notes(oob_notes, extra_meta).await
+    }
 }
 
 async fn mint_operation(
@@ -575,8 +724,25 @@ pub enum MintOperationMetaVariants {
     },
 }
 
-#[derive(Debug, Clone)]
-pub struct MintClientGen;
+/// Initializes the mint client module, configured with the strategy we select
+/// the notes we spend with and the denominations we try to hold
+#[derive(Debug, Clone, Default)]
+pub struct MintClientGen {
+    pub note_selection: NoteSelectionStrategy,
+    pub denomination_target: DenominationTarget,
+}
+
+impl MintClientGen {
+    pub fn new(
+        note_selection: NoteSelectionStrategy,
+        denomination_target: DenominationTarget,
+    ) -> Self {
+        Self {
+            note_selection,
+            denomination_target,
+        }
+    }
+}
 
 #[apply(async_trait_maybe_send!)]
 impl ExtendsCommonModuleInit for MintClientGen {
@@ -615,6 +781,16 @@ impl ExtendsCommonModuleInit for MintClientGen {
                         "NextECashNoteIndex"
                     );
                 }
+                DbKeyPrefix::RestoreNotesChunk => {
+                    push_db_pair_items!(
+                        dbtx,
+                        RestoreNotesChunkKeyPrefix,
+                        RestoreNotesChunkKey,
+                        Vec<(Amount, SpendableNote)>,
+                        mint_client_items,
+                        "Restore Notes Chunks"
+                    );
+                }
             }
         }
 
@@ -633,6 +809,7 @@ impl ClientModuleInit for MintClientGen {
 
     async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
         let (cancel_oob_payment_bc, _) = tokio::sync::broadcast::channel(16);
+        let (restore_paused, _) = tokio::sync::watch::channel(None);
         Ok(MintClientModule {
             federation_id: *args.federation_id(),
             cfg: args.cfg().clone(),
@@ -640,6 +817,10 @@ impl ClientModuleInit for MintClientGen {
             secp: Secp256k1::new(),
             notifier: args.notifier().clone(),
             cancel_oob_payment_bc,
+            restore_paused,
+            api: args.api().clone(),
+            note_selection: self.note_selection,
+            denomination_target: self.denomination_target,
         })
     }
 }
@@ -677,6 +858,12 @@ pub struct MintClientModule {
     secp: Secp256k1<All>,
     notifier: ModuleNotifier<DynGlobalClientContext, MintClientStateMachines>,
     cancel_oob_payment_bc: tokio::sync::broadcast::Sender<OperationId>,
+    /// Whether the backup restoration was paused, unset until it was paused
+    /// or resumed since the client started
+    restore_paused: tokio::sync::watch::Sender<Option<bool>>,
+    api: DynGlobalApi,
+    note_selection: NoteSelectionStrategy,
+    denomination_target: DenominationTarget,
 }
 
 // TODO: wrap in Arc
@@ -687,6 +874,10 @@ pub struct MintClientContext {
     pub peer_tbs_pks: BTreeMap<PeerId, Tiered<tbs::PublicKeyShare>>,
     pub secret: DerivableSecret,
     pub cancel_oob_payment_bc: tokio::sync::broadcast::Sender<OperationId>,
+    pub restore_paused: tokio::sync::watch::Receiver<Option<bool>>,
+    pub note_expiry: Option<NoteExpiryClientConfig>,
+    pub fee_consensus: FeeConsensus,
+    pub denomination_target: DenominationTarget,
 }
 
 impl MintClientContext {
@@ -710,6 +901,10 @@ impl ClientModule for MintClientModule {
             peer_tbs_pks: self.cfg.peer_tbs_pks.clone(),
             secret: self.secret.clone(),
             cancel_oob_payment_bc: self.cancel_oob_payment_bc.clone(),
+            restore_paused: self.restore_paused.subscribe(),
+            note_expiry: self.cfg.note_expiry.clone(),
+            fee_consensus: self.cfg.fee_consensus.clone(),
+            denomination_target: self.denomination_target,
         }
     }
 
@@ -826,7 +1021,7 @@ impl ClientModule for MintClientModule {
             .get_active_states()
             .await
             .into_iter()
-            .any(|s| s.0.module_instance_id() == module_instance_id)
+            .any(|s| is_pending_state(&s.0, module_instance_id))
         {
             warn!(
                 target: LOG_TARGET,
@@ -835,10 +1030,18 @@ impl ClientModule for MintClientModule {
             bail!("Found existing active state machines. Mint module recovery must be started on an empty state.")
         }
 
-        let snapshot = snapshot
-            .map(|mut s| EcashBackup::consensus_decode(&mut s, &Default::default()))
-            .transpose()?
-            .unwrap_or(EcashBackup::new_empty());
+        // the spendable notes are imported in chunks at the end of the restore, such
+        // that large backups never have to be held in memory at once
+        let snapshot = match snapshot {
+            Some(snapshot) => {
+                Self::stage_backup_notes(
+                    &mut dbtx.dbtx_ref_with_prefix_module_id(module_instance_id),
+                    snapshot,
+                )
+                .await?
+            }
+            None => EcashBackup::new_empty(),
+        };
 
         let current_block_count = api.fetch_block_count().await?;
         let state = MintRestoreInProgressState::from_backup(
@@ -876,6 +1079,7 @@ impl ClientModule for MintClientModule {
     ) -> anyhow::Result<()> {
         debug!(target: LOG_TARGET, "Wiping mint module state");
         Self::wipe_all_spendable_notes(dbtx).await;
+        dbtx.remove_by_prefix(&RestoreNotesChunkKeyPrefix).await;
         // TODO: wipe active states or all states?
         Ok(())
     }
@@ -899,8 +1103,13 @@ impl ClientModule for MintClientModule {
         operation_id: OperationId,
         amount: Amount,
     ) -> Vec<ClientOutput<MintOutput, MintClientStateMachines>> {
-        // FIXME: don't hardcode notes per denomination
-        self.create_output(dbtx, operation_id, 2, amount).await
+        self.create_output(
+            dbtx,
+            operation_id,
+            self.denomination_target.notes_per_denomination,
+            amount,
+        )
+        .await
     }
 
     async fn await_primary_module_output(
@@ -926,6 +1135,10 @@ impl ClientModule for MintClientModule {
                             state: MintOutputStates::Succeeded(_),
                             ..
                         }) => Some(()),
+                        MintClientStateMachines::Output(MintOutputStateMachine {
+                            state: MintOutputStates::SucceededMulti(_),
+                            ..
+                        }) => Some(()),
                         MintClientStateMachines::Input(MintInputStateMachine {
                             state: MintInputStates::Created(_),
                             ..
@@ -949,6 +1162,101 @@ impl ClientModule for MintClientModule {
         )
     }
 
+    async fn get_balance_breakdown(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        active_states: Vec<MintClientStateMachines>,
+    ) -> Vec<BalanceItem> {
+        let notes = self
+            .get_wallet_summary(dbtx)
+            .await
+            .iter()
+            .map(|(denomination, count)| BalanceItem::Notes {
+                denomination,
+                count,
+            })
+            .collect::<Vec<_>>();
+
+        let pending = active_states.into_iter().filter_map(|state| match state {
+            MintClientStateMachines::Output(MintOutputStateMachine {
+                common,
+                state: MintOutputStates::Created(created),
+            }) => Some(BalanceItem::PendingNotes {
+                operation_id: common.operation_id,
+                amount: created.amount,
+            }),
+            MintClientStateMachines::Output(MintOutputStateMachine {
+                common,
+                state: MintOutputStates::CreatedMulti(created),
+            }) => Some(BalanceItem::PendingNotes {
+                operation_id: common.operation_id,
+                amount: created
+                    .issuance_requests
+                    .values()
+                    .map(|(amount, _)| *amount)
+                    .sum(),
+            }),
+            _ => None,
+        });
+
+        notes.into_iter().chain(pending).collect()
+    }
+
+    async fn get_pending_stages(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _operation: &OperationLogEntry,
+        active_states: Vec<MintClientStateMachines>,
+    ) -> Vec<PendingStage> {
+        let fees = &self.cfg.fee_consensus;
+
+        active_states
+            .into_iter()
+            .filter_map(|state| match state {
+                MintClientStateMachines::Output(MintOutputStateMachine {
+                    state: MintOutputStates::Created(created),
+                    ..
+                }) => Some(PendingStage {
+                    stage: "issuing_notes".to_string(),
+                    locked: created.amount,
+                    fees: OperationFees::federation(fees.note_issuance_abs),
+                }),
+                MintClientStateMachines::Output(MintOutputStateMachine {
+                    state: MintOutputStates::CreatedMulti(created),
+                    ..
+                }) => Some(PendingStage {
+                    stage: "issuing_notes".to_string(),
+                    locked: created
+                        .issuance_requests
+                        .values()
+                        .map(|(amount, _)| *amount)
+                        .sum(),
+                    fees: OperationFees::federation(
+                        fees.note_issuance_abs * created.issuance_requests.len() as u64,
+                    ),
+                }),
+                MintClientStateMachines::Input(MintInputStateMachine {
+                    state: MintInputStates::Created(created),
+                    ..
+                }) => Some(PendingStage {
+                    stage: "spending_notes".to_string(),
+                    locked: created.amount,
+                    fees: OperationFees::federation(fees.note_spend_abs),
+                }),
+                // the notes can still be refunded until the recipient reissued them
+                MintClientStateMachines::OOB(MintOOBStateMachine {
+                    state: MintOOBStates::Created(created),
+                    ..
+                }) => Some(PendingStage {
+                    stage: "spent_out_of_band".to_string(),
+                    locked: created.amount,
+                    fees: OperationFees::default(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
     async fn leave(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -965,7 +1273,7 @@ impl ClientModule for MintClientModule {
             .get_active_states()
             .await
             .into_iter()
-            .any(|s| s.0.module_instance_id() == module_instance_id)
+            .any(|s| is_pending_state(&s.0, module_instance_id))
         {
             bail!("Pending operations")
         }
@@ -988,10 +1296,16 @@ impl MintClientModule {
             .await
     }
 
-    // TODO: put "notes per denomination" default into cfg
     /// Creates a mint output with exactly the given `amount`, issuing e-cash
     /// notes such that the client holds `notes_per_denomination` notes of each
     /// e-cash note denomination held.
+    ///
+    /// If the [`DenominationTarget`] the module was initialized with enables
+    /// consolidation, the notes of denominations we hold too many of are
+    /// reissued once the output was accepted.
+    ///
+    /// The notes are issued in a single batch, so all of the outputs have to
+    /// be added to the same transaction.
     pub async fn create_output(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -1011,41 +1325,117 @@ impl MintClientModule {
             notes_per_denomination,
         );
 
-        let mut outputs = Vec::new();
+        let mut requests = Vec::new();
 
         for (amount, num) in denominations.iter() {
             for _ in 0..num {
                 let (issuance_request, blind_nonce) = self.new_ecash_note(amount, dbtx).await;
 
-                let state_generator = Arc::new(move |txid, out_idx| {
-                    vec![MintClientStateMachines::Output(MintOutputStateMachine {
-                        common: MintOutputCommon {
-                            operation_id,
-                            out_point: OutPoint { txid, out_idx },
-                        },
-                        state: MintOutputStates::Created(MintOutputStatesCreated {
-                            amount,
-                            issuance_request,
-                        }),
-                    })]
-                });
-
                 debug!(
                     %amount,
                     "Generated issuance request"
                 );
 
-                outputs.push(ClientOutput {
+                requests.push((amount, issuance_request, blind_nonce));
+            }
+        }
+
+        // if notes expire a single state machine reissues the notes we still hold
+        // once they are due, notes not worth the fees of reissuing them are left
+        // to expire
+        let refresh_notes = self.cfg.note_expiry.as_ref().map(|_| {
+            let refresh_fee =
+                self.cfg.fee_consensus.note_spend_abs + self.cfg.fee_consensus.note_issuance_abs;
+
+            requests
+                .iter()
+                .filter(|(amount, _, _)| refresh_fee < *amount)
+                .map(|(amount, issuance_request, _)| (*amount, issuance_request.nonce()))
+                .collect::<Vec<_>>()
+        });
+
+        let consolidate = self.denomination_target.consolidate_above.is_some();
+
+        // the notes are issued in a single batch, whose state machine is created
+        // with the last of the outputs once the indices of all of them are known
+        let batch_size = requests.len();
+        let batches = Arc::new(std::sync::Mutex::new(BTreeMap::<
+            TransactionId,
+            BTreeMap<u64, (Amount, NoteIssuanceRequest)>,
+        >::new()));
+
+        requests
+            .into_iter()
+            .map(|(amount, issuance_request, blind_nonce)| {
+                let batches = batches.clone();
+                let refresh_notes = refresh_notes.clone();
+
+                let state_generator = Arc::new(move |txid, out_idx| {
+                    let issuance_requests = {
+                        let mut batches = batches.lock().expect("Lock poisoned");
+                        let batch = batches.entry(txid).or_default();
+                        batch.insert(out_idx, (amount, issuance_request));
+
+                        if batch.len() < batch_size {
+                            return vec![];
+                        }
+
+                        batches.remove(&txid).expect("Batch was just inserted")
+                    };
+
+                    let first_out_idx = *issuance_requests
+                        .keys()
+                        .next()
+                        .expect("Batch contains an output");
+
+                    let mut state_machines =
+                        vec![MintClientStateMachines::Output(MintOutputStateMachine {
+                            common: MintOutputCommon {
+                                operation_id,
+                                out_point: OutPoint {
+                                    txid,
+                                    out_idx: first_out_idx,
+                                },
+                            },
+                            state: MintOutputStates::CreatedMulti(MintOutputStatesCreatedMulti {
+                                issuance_requests,
+                            }),
+                        })];
+
+                    if let Some(notes) = refresh_notes.clone() {
+                        state_machines.push(MintClientStateMachines::Refresh(
+                            MintRefreshStateMachine {
+                                common: MintRefreshCommon {
+                                    operation_id,
+                                    txid,
+                                    notes,
+                                },
+                                state: MintRefreshStates::Created,
+                            },
+                        ));
+                    }
+
+                    if consolidate {
+                        state_machines.push(MintClientStateMachines::Consolidate(
+                            MintConsolidateStateMachine {
+                                common: MintConsolidateCommon { operation_id, txid },
+                                state: MintConsolidateStates::Created,
+                            },
+                        ));
+                    }
+
+                    state_machines
+                });
+
+                ClientOutput {
                     output: MintOutput {
                         amount,
                         blind_nonce,
                     },
                     state_machines: state_generator,
-                });
-            }
-        }
-
-        outputs
+                }
+            })
+            .collect()
     }
 
     /// Wait for the e-cash notes to be retrieved. If this is not possible
@@ -1065,17 +1455,39 @@ impl MintClientModule {
                     return None;
                 };
 
-                if state.common.out_point != out_point {
+                if state.common.out_point.txid != out_point.txid {
                     return None;
                 }
 
+                let is_output = state.common.out_point == out_point;
+
                 match state.state {
-                    MintOutputStates::Succeeded(succeeded) => Some(Ok(succeeded.amount)),
-                    MintOutputStates::Aborted(_) => Some(Err(anyhow!("Transaction was rejected"))),
-                    MintOutputStates::Failed(failed) => Some(Err(anyhow!(
+                    MintOutputStates::Succeeded(succeeded) if is_output => {
+                        Some(Ok(succeeded.amount))
+                    }
+                    MintOutputStates::Aborted(_) if is_output => {
+                        Some(Err(anyhow!("Transaction was rejected")))
+                    }
+                    MintOutputStates::Failed(failed) if is_output => Some(Err(anyhow!(
                         "Failed to finalize transaction: {}",
                         failed.error
                     ))),
+                    MintOutputStates::SucceededMulti(succeeded) => {
+                        succeeded.amounts.get(&out_point.out_idx).copied().map(Ok)
+                    }
+                    MintOutputStates::AbortedMulti(aborted)
+                        if aborted.out_idxs.contains(&out_point.out_idx) =>
+                    {
+                        Some(Err(anyhow!("Transaction was rejected")))
+                    }
+                    MintOutputStates::FailedMulti(failed)
+                        if failed.out_idxs.contains(&out_point.out_idx) =>
+                    {
+                        Some(Err(anyhow!(
+                            "Failed to finalize transaction: {}",
+                            failed.error
+                        )))
+                    }
                     _ => None,
                 }
             });
@@ -1098,7 +1510,7 @@ impl MintClientModule {
         );
 
         let spendable_selected_notes =
-            Self::select_notes(dbtx, &SelectNotesWithAtleastAmount, min_amount).await?;
+            Self::select_notes(dbtx, &self.note_selection, min_amount).await?;
 
         for (amount, note) in spendable_selected_notes.iter_items() {
             dbtx.remove_entry(&NoteKey {
@@ -1119,6 +1531,7 @@ impl MintClientModule {
         notes: TieredMulti<SpendableNote>,
     ) -> anyhow::Result<Vec<ClientInput<MintInput, MintClientStateMachines>>> {
         let mut inputs = Vec::new();
+        let session_index = self.expiry_session_index().await?;
 
         for (amount, spendable_note) in notes.into_iter() {
             let key = self
@@ -1129,7 +1542,7 @@ impl MintClientModule {
 
             let note = spendable_note.note();
 
-            if !note.verify(*key) {
+            if !self.verify_note(*key, amount, &note, session_index) {
                 bail!("Invalid note");
             }
 
@@ -1157,6 +1570,33 @@ impl MintClientModule {
         Ok(inputs)
     }
 
+    /// The index of the current session if notes expire, which determines the
+    /// epochs whose notes the mint still accepts
+    async fn expiry_session_index(&self) -> anyhow::Result<Option<u64>> {
+        if self.cfg.note_expiry.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(self.api.fetch_block_count().await?))
+    }
+
+    /// Checks the federation's signature on the note, if notes expire it has
+    /// to be signed for an epoch the mint still accepts in the session
+    fn verify_note(
+        &self,
+        amount_key: AggregatePublicKey,
+        amount: Amount,
+        note: &Note,
+        session_index: Option<u64>,
+    ) -> bool {
+        match (&self.cfg.note_expiry, session_index) {
+            (Some(note_expiry), Some(session_index)) => note_expiry
+                .live_note_epoch(amount_key, amount, note, session_index)
+                .is_some(),
+            _ => note.verify(amount_key),
+        }
+    }
+
     async fn spend_notes_oob(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -1255,6 +1695,34 @@ impl MintClientModule {
         Err(anyhow!("Restore stream closed without success or failure"))
     }
 
+    async fn subscribe_restore_progress(&self) -> BoxStream<'static, RestoreProgress> {
+        let mut restore_stream = self
+            .notifier
+            .subscribe(MINT_BACKUP_RESTORE_OPERATION_ID)
+            .await;
+
+        Box::pin(stream! {
+            while let Some(state) = restore_stream.next().await {
+                // notes pending at the time of the backup are issued in the same operation
+                let MintClientStateMachines::Restore(restore_step) = state else {
+                    continue;
+                };
+
+                let progress = restore_step.state.progress();
+                let finished = matches!(
+                    progress,
+                    RestoreProgress::Success { .. } | RestoreProgress::Failed { .. }
+                );
+
+                yield progress;
+
+                if finished {
+                    break;
+                }
+            }
+        })
+    }
+
     /// Select notes with `requested_amount` using `notes_selector`.
     async fn select_notes(
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -1491,6 +1959,8 @@ pub enum MintClientStateMachines {
     Input(MintInputStateMachine),
     OOB(MintOOBStateMachine),
     Restore(MintRestoreStateMachine),
+    Refresh(MintRefreshStateMachine),
+    Consolidate(MintConsolidateStateMachine),
 }
 
 impl IntoDynInstance for MintClientStateMachines {
@@ -1535,6 +2005,18 @@ impl State for MintClientStateMachines {
                     MintClientStateMachines::Restore
                 )
             }
+            MintClientStateMachines::Refresh(refresh_state) => {
+                sm_enum_variant_translation!(
+                    refresh_state.transitions(context, global_context),
+                    MintClientStateMachines::Refresh
+                )
+            }
+            MintClientStateMachines::Consolidate(consolidate_state) => {
+                sm_enum_variant_translation!(
+                    consolidate_state.transitions(context, global_context),
+                    MintClientStateMachines::Consolidate
+                )
+            }
         }
     }
 
@@ -1544,10 +2026,26 @@ impl State for MintClientStateMachines {
             MintClientStateMachines::Input(redemption_state) => redemption_state.operation_id(),
             MintClientStateMachines::OOB(oob_state) => oob_state.operation_id(),
             MintClientStateMachines::Restore(state) => state.operation_id(),
+            MintClientStateMachines::Refresh(state) => state.operation_id(),
+            MintClientStateMachines::Consolidate(state) => state.operation_id(),
         }
     }
 }
 
+/// Whether the state belongs to a pending operation of the module instance,
+/// refreshes and consolidations of notes do not count as they only reissue
+/// notes we hold
+fn is_pending_state(
+    state: &DynState<DynGlobalClientContext>,
+    module_instance_id: ModuleInstanceId,
+) -> bool {
+    state.module_instance_id() == module_instance_id
+        && !matches!(
+            state.as_any().downcast_ref::<MintClientStateMachines>(),
+            Some(MintClientStateMachines::Refresh(_) | MintClientStateMachines::Consolidate(_))
+        )
+}
+
 /// A [`Note`] with associated secret key that allows to proof ownership (spend
 /// it)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, Encodable, Decodable)]
@@ -1644,7 +2142,8 @@ mod tests {
     use fedimint_core::{Amount, Tiered, TieredMulti, TieredSummary};
     use itertools::Itertools;
 
-    use crate::{select_notes_from_stream, OOBNotes};
+    use crate::select::{select_notes_minimizing_change, select_notes_randomly};
+    use crate::{select_notes_from_stream, DenominationTarget, OOBNotes};
 
     #[test_log::test(tokio::test)]
     async fn select_notes_avg_test() {
@@ -1725,6 +2224,88 @@ mod tests {
         assert_eq!(error.total_amount, Amount::from_sats(10));
     }
 
+    #[test]
+    fn select_notes_minimizing_change_prefers_exact_amount_over_fewer_notes() {
+        let held = || {
+            notes(vec![(Amount::from_sats(6), 2), (Amount::from_sats(10), 1)])
+                .into_iter_items()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            select_notes_minimizing_change(held(), Amount::from_sats(12)).unwrap(),
+            notes(vec![(Amount::from_sats(6), 2)])
+        );
+        assert_eq!(
+            select_notes_minimizing_change(held(), Amount::from_sats(11)).unwrap(),
+            notes(vec![(Amount::from_sats(6), 2)])
+        );
+        assert_eq!(
+            select_notes_minimizing_change(held(), Amount::from_sats(10)).unwrap(),
+            notes(vec![(Amount::from_sats(10), 1)])
+        );
+        let error = select_notes_minimizing_change(held(), Amount::from_sats(23)).unwrap_err();
+        assert_eq!(error.total_amount, Amount::from_sats(22));
+    }
+
+    #[test]
+    fn select_notes_randomly_only_selects_needed_notes() {
+        let held = notes(vec![
+            (Amount::from_sats(1), 10),
+            (Amount::from_sats(5), 10),
+            (Amount::from_sats(20), 10),
+        ])
+        .into_iter_items()
+        .collect::<Vec<_>>();
+        let requested_amount = Amount::from_sats(27);
+
+        for _ in 0..100 {
+            let selected =
+                select_notes_randomly(held.clone(), requested_amount, &mut rand::thread_rng())
+                    .unwrap();
+            let total_amount = selected.total_amount();
+            assert!(requested_amount <= total_amount);
+            for (amount, _) in selected.iter_items() {
+                assert!(total_amount - amount < requested_amount);
+            }
+        }
+
+        let error = select_notes_randomly(held, Amount::from_sats(1000), &mut rand::thread_rng())
+            .unwrap_err();
+        assert_eq!(error.total_amount, Amount::from_sats(260));
+    }
+
+    #[test]
+    fn surplus_exceeds_target_except_for_largest_denomination() {
+        let mut held = TieredSummary::default();
+        held.inc(Amount::from_sats(1), 6);
+        held.inc(Amount::from_sats(2), 3);
+        held.inc(Amount::from_sats(4), 10);
+
+        let target = DenominationTarget {
+            notes_per_denomination: 2,
+            consolidate_above: Some(4),
+        };
+        let surplus = target.surplus(&held, Amount::from_sats(4));
+        assert_eq!(
+            surplus.iter().collect::<Vec<_>>(),
+            vec![(Amount::from_sats(1), 4)]
+        );
+
+        // the threshold is raised above the target
+        let target = DenominationTarget {
+            notes_per_denomination: 2,
+            consolidate_above: Some(0),
+        };
+        let surplus = target.surplus(&held, Amount::from_sats(4));
+        assert_eq!(
+            surplus.iter().collect::<Vec<_>>(),
+            vec![(Amount::from_sats(1), 4)]
+        );
+
+        let surplus = DenominationTarget::default().surplus(&held, Amount::from_sats(4));
+        assert_eq!(surplus.count_items(), 0);
+    }
+
     fn reverse_sorted_note_stream(
         notes: Vec<(Amount, usize)>,
     ) -> impl futures::Stream<Item = (Amount, String)> {
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{bail, ensure};
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_aead::{LessSafeKey, UnboundKey, CHACHA20_POLY1305};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::Amount;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::OOBNotes;
+
+/// Maximum number of encrypted bytes per chunk, such that a chunk encoded as a
+/// string fits into a QR code that is still easy to scan
+pub const MAX_OFFLINE_CHUNK_LEN: usize = 600;
+
+/// Key the notes are encrypted with, which the sender hands the recipient
+/// separately from the chunks, so whoever sees the chunks cannot take the
+/// notes
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OfflineNotesKey([u8; 32]);
+
+impl OfflineNotesKey {
+    pub fn random() -> Self {
+        OfflineNotesKey(OsRng.gen())
+    }
+
+    fn aead_key(&self) -> LessSafeKey {
+        LessSafeKey::new(
+            UnboundKey::new(&CHACHA20_POLY1305, &self.0).expect("Key has the right length"),
+        )
+    }
+}
+
+impl std::fmt::Debug for OfflineNotesKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OfflineNotesKey(..)")
+    }
+}
+
+impl Display for OfflineNotesKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.to_hex())
+    }
+}
+
+impl FromStr for OfflineNotesKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(OfflineNotesKey(FromHex::from_hex(s)?))
+    }
+}
+
+/// Part of e-cash notes encrypted with an [`OfflineNotesKey`], small enough to
+/// be shown as a QR code. The chunks can be handed over in any order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable)]
+pub struct OfflineNotesChunk {
+    /// Identifies the encrypted notes the chunk belongs to
+    pub id: u64,
+    pub index: u16,
+    pub count: u16,
+    pub data: Vec<u8>,
+}
+
+impl Display for OfflineNotesChunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let bytes = self.consensus_encode_to_vec().expect("Encodes correctly");
+        f.write_str(&base64::encode(&bytes))
+    }
+}
+
+impl FromStr for OfflineNotesChunk {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = base64::decode(s)?;
+        Ok(Decodable::consensus_decode(
+            &mut std::io::Cursor::new(bytes),
+            &ModuleDecoderRegistry::default(),
+        )?)
+    }
+}
+
+/// E-cash notes encrypted for transferring them out of band while one of the
+/// parties is offline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineNotes {
+    pub key: OfflineNotesKey,
+    pub chunks: Vec<OfflineNotesChunk>,
+}
+
+impl OfflineNotes {
+    pub fn encrypt(notes: &OOBNotes, key: OfflineNotesKey) -> anyhow::Result<Self> {
+        let plaintext = notes.consensus_encode_to_vec()?;
+        let ciphertext = fedimint_aead::encrypt(plaintext, &key.aead_key())?;
+
+        let hash = sha256::Hash::hash(&ciphertext);
+        let id = u64::from_be_bytes(hash[..8].try_into().expect("Hash is longer than 8 bytes"));
+
+        let count = ciphertext.chunks(MAX_OFFLINE_CHUNK_LEN).len();
+        ensure!(count <= u16::MAX as usize, "Too many notes to encrypt");
+
+        let chunks = ciphertext
+            .chunks(MAX_OFFLINE_CHUNK_LEN)
+            .enumerate()
+            .map(|(index, data)| OfflineNotesChunk {
+                id,
+                index: index as u16,
+                count: count as u16,
+                data: data.to_vec(),
+            })
+            .collect();
+
+        Ok(OfflineNotes { key, chunks })
+    }
+
+    /// Reassembles and decrypts the notes, chunks that were handed over more
+    /// than once are ignored
+    pub fn decrypt(&self) -> anyhow::Result<OOBNotes> {
+        let Some(first) = self.chunks.first() else {
+            bail!("No chunks to decrypt");
+        };
+
+        let mut chunks = BTreeMap::new();
+        for chunk in &self.chunks {
+            ensure!(
+                chunk.id == first.id && chunk.count == first.count,
+                "Chunk {} belongs to different notes",
+                chunk.index
+            );
+            ensure!(
+                chunk.index < chunk.count,
+                "Chunk index {} out of range",
+                chunk.index
+            );
+
+            if let Some(data) = chunks.insert(chunk.index, &chunk.data) {
+                ensure!(
+                    data == &chunk.data,
+                    "Chunk {} was handed over with different contents",
+                    chunk.index
+                );
+            }
+        }
+
+        if chunks.len() != first.count as usize {
+            bail!(
+                "Missing {} of {} chunks",
+                first.count as usize - chunks.len(),
+                first.count
+            );
+        }
+
+        let mut ciphertext = chunks.into_values().flatten().copied().collect::<Vec<_>>();
+        let plaintext = fedimint_aead::decrypt(&mut ciphertext, &self.key.aead_key())?;
+
+        Ok(Decodable::consensus_decode(
+            &mut std::io::Cursor::new(plaintext),
+            &ModuleDecoderRegistry::default(),
+        )?)
+    }
+}
+
+/// Some of the notes were already spent, so reissuing them would fail
+#[derive(Debug, Clone, Error, Serialize, Deserialize)]
+#[error("{spent_notes} notes worth {spent_amount} of {total_amount} were already spent")]
+pub struct DoubleSpendError {
+    pub spent_notes: usize,
+    pub spent_amount: Amount,
+    pub total_amount: Amount,
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::config::FederationId;
+    use fedimint_core::{Amount, TieredMulti};
+    use secp256k1::KeyPair;
+    use threshold_crypto::G1Affine;
+
+    use super::{OfflineNotes, OfflineNotesChunk, OfflineNotesKey};
+    use crate::{OOBNotes, SpendableNote};
+
+    fn oob_notes() -> OOBNotes {
+        let notes: TieredMulti<SpendableNote> = (1..=20)
+            .map(|seed| {
+                let note = SpendableNote {
+                    signature: tbs::Signature(G1Affine::generator()),
+                    spend_key: KeyPair::from_seckey_slice(secp256k1::SECP256K1, &[seed; 32])
+                        .expect("Valid secret key"),
+                };
+                (Amount::from_msats(1 << (seed % 4)), note)
+            })
+            .collect();
+
+        OOBNotes {
+            federation_id_prefix: FederationId(threshold_crypto::SecretKey::random().public_key())
+                .to_prefix(),
+            notes,
+        }
+    }
+
+    #[test]
+    fn offline_notes_survive_chunking_in_any_order() {
+        let notes = oob_notes();
+        let offline_notes = OfflineNotes::encrypt(&notes, OfflineNotesKey::random()).unwrap();
+        assert!(1 < offline_notes.chunks.len());
+
+        // chunks are scanned in any order and possibly more than once
+        let mut chunks = offline_notes
+            .chunks
+            .iter()
+            .map(|chunk| chunk.to_string().parse::<OfflineNotesChunk>().unwrap())
+            .rev()
+            .collect::<Vec<_>>();
+        chunks.push(chunks[0].clone());
+
+        let key = offline_notes.key.to_string().parse().unwrap();
+        let decrypted = OfflineNotes { key, chunks }.decrypt().unwrap();
+        assert_eq!(decrypted.to_string(), notes.to_string());
+
+        let wrong_key = OfflineNotes {
+            key: OfflineNotesKey::random(),
+            ..offline_notes.clone()
+        };
+        assert!(wrong_key.decrypt().is_err());
+
+        let mut missing_chunk = offline_notes;
+        missing_chunk.chunks.pop();
+        assert!(missing_chunk.decrypt().is_err());
+    }
+}
@@ -4,16 +4,23 @@ use std::time::Duration;
 use anyhow::{anyhow, bail};
 use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
-use fedimint_core::api::{deserialize_outcome, FederationApiExt, SerdeOutputOutcome};
+use fedimint_core::api::{
+    deserialize_outcome, FederationApiExt, GlobalFederationApi, SerdeOutputOutcome,
+};
 use fedimint_core::core::{Decoder, OperationId};
 use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::endpoint_constants::AWAIT_OUTPUT_OUTCOME_ENDPOINT;
+use fedimint_core::endpoint_constants::{
+    AWAIT_OUTPUT_OUTCOMES_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT,
+};
 use fedimint_core::module::ApiRequestErased;
 use fedimint_core::query::FilterMapThreshold;
 use fedimint_core::task::sleep;
 use fedimint_core::{Amount, NumPeers, OutPoint, PeerId, Tiered, TransactionId};
 use fedimint_derive_secret::{ChildId, DerivableSecret};
+use fedimint_mint_common::expiry::{
+    epoch_peer_public_key_shares, epoch_public_keys, NoteExpiryClientConfig,
+};
 use fedimint_mint_common::{BlindNonce, MintOutputOutcome, Nonce, Note};
 use secp256k1::{KeyPair, Secp256k1, Signing};
 use serde::{Deserialize, Serialize};
@@ -48,6 +55,24 @@ const BLINDING_KEY_CHILD_ID: ChildId = ChildId(1);
 ///     Outcome -- invalid blind signatures  --> Failed
 ///     end
 /// ```
+///
+/// The notes of a single [`MintClientModule::create_output`] call are issued
+/// in a batch, whose state machine fetches and verifies the blind signature
+/// shares of all of them at once:
+///
+/// ```mermaid
+/// graph LR
+///     classDef virtual fill:#fff,stroke-dasharray: 5 5
+///
+///     CreatedMulti -- containing tx rejected --> AbortedMulti
+///     CreatedMulti -- await output outcomes --> Outcomes["Outcomes Received"]:::virtual
+///     subgraph Await Outcomes
+///     Outcomes -- valid blind signatures  --> SucceededMulti
+///     Outcomes -- invalid blind signatures  --> FailedMulti
+///     end
+/// ```
+///
+/// [`MintClientModule::create_output`]: crate::MintClientModule::create_output
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub enum MintOutputStates {
     /// Issuance request was created, we are waiting for blind signatures
@@ -63,6 +88,16 @@ pub enum MintOutputStates {
     /// The issuance was completed successfully and the e-cash notes added to
     /// our wallet
     Succeeded(MintOutputStatesSucceeded),
+    /// Issuance requests for a batch of outputs were created, we are waiting
+    /// for the blind signatures of all of them
+    CreatedMulti(MintOutputStatesCreatedMulti),
+    /// The transaction containing the batch was rejected
+    AbortedMulti(MintOutputStatesAbortedMulti),
+    /// The transaction containing the batch was accepted but its notes could
+    /// not be finalized, see [`MintOutputStates::Failed`]
+    FailedMulti(MintOutputStatesFailedMulti),
+    /// The notes of the batch were added to our wallet
+    SucceededMulti(MintOutputStatesSucceededMulti),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Decodable, Encodable)]
@@ -99,6 +134,14 @@ impl State for MintOutputStateMachine {
             MintOutputStates::Succeeded(_) => {
                 vec![]
             }
+            MintOutputStates::CreatedMulti(created) => {
+                created.transitions(context, global_context, self.common)
+            }
+            MintOutputStates::AbortedMulti(_)
+            | MintOutputStates::FailedMulti(_)
+            | MintOutputStates::SucceededMulti(_) => {
+                vec![]
+            }
         }
     }
 
@@ -123,6 +166,7 @@ impl MintOutputStatesCreated {
         common: MintOutputCommon,
     ) -> Vec<StateTransition<MintOutputStateMachine>> {
         let tbs_pks = context.tbs_pks.clone();
+        let note_expiry = context.note_expiry.clone();
         vec![
             // Check if transaction was rejected
             StateTransition::new(
@@ -138,6 +182,7 @@ impl MintOutputStatesCreated {
                     self.amount,
                     self.issuance_request,
                     context.peer_tbs_pks.clone(),
+                    context.note_expiry.clone(),
                 ),
                 move |dbtx, output_outcomes, old_state| {
                     Box::pin(Self::transition_outcome_ready(
@@ -146,6 +191,7 @@ impl MintOutputStatesCreated {
                         old_state,
                         // TODO: avoid clone of whole object
                         tbs_pks.clone(),
+                        note_expiry.clone(),
                     ))
                 },
             ),
@@ -181,7 +227,16 @@ impl MintOutputStatesCreated {
         amount: Amount,
         request: NoteIssuanceRequest,
         peer_tbs_pks: BTreeMap<PeerId, Tiered<PublicKeyShare>>,
-    ) -> Result<BTreeMap<PeerId, MintOutputOutcome>, String> {
+        note_expiry: Option<NoteExpiryClientConfig>,
+    ) -> Result<(BTreeMap<PeerId, MintOutputOutcome>, Option<u64>), String> {
+        let (peer_tbs_pks, epoch) = issuance_peer_keys(
+            &global_context,
+            common.out_point.txid,
+            peer_tbs_pks,
+            note_expiry,
+        )
+        .await;
+
         loop {
             let decoder = module_decoder.clone();
             let pks = peer_tbs_pks.clone();
@@ -201,7 +256,7 @@ impl MintOutputStatesCreated {
                 )
                 .await
             {
-                Ok(outcome) => return Ok(outcome),
+                Ok(outcome) => return Ok((outcome, epoch)),
                 Err(error) => {
                     if !error.is_retryable() {
                         return Err(error.to_string());
@@ -220,9 +275,10 @@ impl MintOutputStatesCreated {
 
     async fn transition_outcome_ready(
         dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
-        output_outcomes_result: Result<BTreeMap<PeerId, MintOutputOutcome>, String>,
+        output_outcomes_result: Result<(BTreeMap<PeerId, MintOutputOutcome>, Option<u64>), String>,
         old_state: MintOutputStateMachine,
         mint_keys: Tiered<AggregatePublicKey>,
+        note_expiry: Option<NoteExpiryClientConfig>,
     ) -> MintOutputStateMachine {
         let (amount, issuance_request) = match old_state.state {
             MintOutputStates::Created(created) => (created.amount, created.issuance_request),
@@ -232,7 +288,9 @@ impl MintOutputStatesCreated {
         // if the query obtained a threshold of valid blind signature shares, we combine
         // the shares, finalize the issuance request with the blind signature
         // and store the resulting note in the database
-        let note_res = output_outcomes_result.and_then(|blind_signature_shares| {
+        let note_res = output_outcomes_result.and_then(|(blind_signature_shares, epoch)| {
+            let mint_keys = issuance_mint_keys(mint_keys, epoch, note_expiry.as_ref());
+
             match mint_keys.tier(&amount) {
                 Ok(amount_key) => issuance_request
                     .finalize(
@@ -281,6 +339,271 @@ impl MintOutputStatesCreated {
     }
 }
 
+/// See [`MintOutputStates`]
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintOutputStatesCreatedMulti {
+    /// The amount and issuance request of every note by its output index
+    pub(crate) issuance_requests: BTreeMap<u64, (Amount, NoteIssuanceRequest)>,
+}
+
+impl MintOutputStatesCreatedMulti {
+    fn transitions(
+        &self,
+        context: &MintClientContext,
+        global_context: &DynGlobalClientContext,
+        common: MintOutputCommon,
+    ) -> Vec<StateTransition<MintOutputStateMachine>> {
+        let tbs_pks = context.tbs_pks.clone();
+        let note_expiry = context.note_expiry.clone();
+        vec![
+            // Check if transaction was rejected
+            StateTransition::new(
+                MintOutputStatesCreated::await_tx_rejected(global_context.clone(), common),
+                |_dbtx, (), state| Box::pin(Self::transition_tx_rejected(state)),
+            ),
+            // Check for the outcomes of all outputs
+            StateTransition::new(
+                Self::await_outcomes_ready(
+                    global_context.clone(),
+                    common,
+                    self.issuance_requests.clone(),
+                    context.peer_tbs_pks.clone(),
+                    context.note_expiry.clone(),
+                ),
+                move |dbtx, output_outcomes, old_state| {
+                    Box::pin(Self::transition_outcomes_ready(
+                        dbtx,
+                        output_outcomes,
+                        old_state,
+                        tbs_pks.clone(),
+                        note_expiry.clone(),
+                    ))
+                },
+            ),
+        ]
+    }
+
+    async fn transition_tx_rejected(old_state: MintOutputStateMachine) -> MintOutputStateMachine {
+        let MintOutputStates::CreatedMulti(created) = old_state.state else {
+            panic!("Unexpected prior state")
+        };
+
+        MintOutputStateMachine {
+            common: old_state.common,
+            state: MintOutputStates::AbortedMulti(MintOutputStatesAbortedMulti {
+                out_idxs: created.issuance_requests.into_keys().collect(),
+            }),
+        }
+    }
+
+    async fn await_outcomes_ready(
+        global_context: DynGlobalClientContext,
+        common: MintOutputCommon,
+        issuance_requests: BTreeMap<u64, (Amount, NoteIssuanceRequest)>,
+        peer_tbs_pks: BTreeMap<PeerId, Tiered<PublicKeyShare>>,
+        note_expiry: Option<NoteExpiryClientConfig>,
+    ) -> Result<(BTreeMap<PeerId, Vec<MintOutputOutcome>>, Option<u64>), String> {
+        let (peer_tbs_pks, epoch) = issuance_peer_keys(
+            &global_context,
+            common.out_point.txid,
+            peer_tbs_pks,
+            note_expiry,
+        )
+        .await;
+
+        let out_points = issuance_requests
+            .keys()
+            .map(|out_idx| OutPoint {
+                txid: common.out_point.txid,
+                out_idx: *out_idx,
+            })
+            .collect::<Vec<_>>();
+
+        // we blind the nonces once instead of for the response of every peer
+        let blind_nonces = issuance_requests
+            .values()
+            .map(|(amount, request)| (*amount, request.recover_blind_nonce()))
+            .collect::<Vec<_>>();
+
+        loop {
+            let pks = peer_tbs_pks.clone();
+            let blind_nonces = blind_nonces.clone();
+
+            match global_context
+                .module_api()
+                .request_with_strategy(
+                    // this query collects a threshold of 2f + 1 peers whose shares for the
+                    // whole batch are valid
+                    FilterMapThreshold::new(
+                        move |peer, outcomes| {
+                            verify_blind_share_batch(peer, outcomes, &blind_nonces, &pks)
+                        },
+                        global_context.api().all_peers().total(),
+                    ),
+                    AWAIT_OUTPUT_OUTCOMES_ENDPOINT.to_owned(),
+                    ApiRequestErased::new(out_points.clone()),
+                )
+                .await
+            {
+                Ok(outcomes) => return Ok((outcomes, epoch)),
+                Err(error) => {
+                    if !error.is_retryable() {
+                        return Err(error.to_string());
+                    }
+
+                    trace!(
+                        "Awaiting outcomes to become ready failed, retrying in {}s: {error}",
+                        RETRY_DELAY.as_secs()
+                    );
+
+                    sleep(RETRY_DELAY).await;
+                }
+            };
+        }
+    }
+
+    async fn transition_outcomes_ready(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        output_outcomes_result: Result<
+            (BTreeMap<PeerId, Vec<MintOutputOutcome>>, Option<u64>),
+            String,
+        >,
+        old_state: MintOutputStateMachine,
+        mint_keys: Tiered<AggregatePublicKey>,
+        note_expiry: Option<NoteExpiryClientConfig>,
+    ) -> MintOutputStateMachine {
+        let MintOutputStates::CreatedMulti(created) = old_state.state else {
+            panic!("Unexpected prior state")
+        };
+
+        // the shares of every peer are in the order of the output indices
+        let notes_res = output_outcomes_result.and_then(|(blind_signature_shares, epoch)| {
+            let mint_keys = issuance_mint_keys(mint_keys, epoch, note_expiry.as_ref());
+
+            created
+                .issuance_requests
+                .iter()
+                .enumerate()
+                .map(|(idx, (out_idx, (amount, issuance_request)))| {
+                    let amount_key = mint_keys.tier(amount).map_err(|error| {
+                        NoteFinalizationError::InvalidAmountTier(error.0).to_string()
+                    })?;
+
+                    let note = issuance_request
+                        .finalize(
+                            combine_valid_shares(
+                                blind_signature_shares
+                                    .iter()
+                                    .map(|(peer, shares)| (peer.to_usize(), shares[idx].0)),
+                                blind_signature_shares.len(),
+                            ),
+                            *amount_key,
+                        )
+                        .map_err(|e| e.to_string())?;
+
+                    Ok((*out_idx, *amount, note))
+                })
+                .collect::<Result<Vec<_>, String>>()
+        });
+
+        match notes_res {
+            Ok(notes) => {
+                for (_, amount, note) in &notes {
+                    if let Some(note) = dbtx
+                        .module_tx()
+                        .insert_entry(
+                            &NoteKey {
+                                amount: *amount,
+                                nonce: note.nonce(),
+                            },
+                            note,
+                        )
+                        .await
+                    {
+                        error!(
+                            ?note,
+                            "E-cash note was replaced in DB, this should never happen!"
+                        )
+                    }
+                }
+
+                MintOutputStateMachine {
+                    common: old_state.common,
+                    state: MintOutputStates::SucceededMulti(MintOutputStatesSucceededMulti {
+                        amounts: notes
+                            .into_iter()
+                            .map(|(out_idx, amount, _)| (out_idx, amount))
+                            .collect(),
+                    }),
+                }
+            }
+            Err(error) => MintOutputStateMachine {
+                common: old_state.common,
+                state: MintOutputStates::FailedMulti(MintOutputStatesFailedMulti {
+                    out_idxs: created.issuance_requests.into_keys().collect(),
+                    error,
+                }),
+            },
+        }
+    }
+}
+
+/// The key shares of every peer the notes of the transaction are signed with,
+/// if notes expire the mint signs with the keys of the epoch the transaction
+/// was accepted in
+async fn issuance_peer_keys(
+    global_context: &DynGlobalClientContext,
+    txid: TransactionId,
+    peer_tbs_pks: BTreeMap<PeerId, Tiered<PublicKeyShare>>,
+    note_expiry: Option<NoteExpiryClientConfig>,
+) -> (BTreeMap<PeerId, Tiered<PublicKeyShare>>, Option<u64>) {
+    match note_expiry {
+        Some(note_expiry) => {
+            let session_index = await_issuance_session(global_context, txid).await;
+            let epoch = note_expiry.expiry.epoch(session_index);
+            let pks = epoch_peer_public_key_shares(&peer_tbs_pks, &note_expiry.peer_tbs_pks, epoch);
+
+            (pks, Some(epoch))
+        }
+        None => (peer_tbs_pks, None),
+    }
+}
+
+/// The keys the notes issued in the epoch are verified with
+fn issuance_mint_keys(
+    mint_keys: Tiered<AggregatePublicKey>,
+    epoch: Option<u64>,
+    note_expiry: Option<&NoteExpiryClientConfig>,
+) -> Tiered<AggregatePublicKey> {
+    match (epoch, note_expiry) {
+        (Some(epoch), Some(note_expiry)) => {
+            epoch_public_keys(&mint_keys, &note_expiry.tbs_pks, epoch)
+        }
+        _ => mint_keys,
+    }
+}
+
+/// Waits for the index of the session the transaction was accepted in
+pub(crate) async fn await_issuance_session(
+    global_context: &DynGlobalClientContext,
+    txid: TransactionId,
+) -> u64 {
+    loop {
+        match global_context.api().fetch_transaction_location(txid).await {
+            Ok(response) => {
+                if let Some(location) = response.value {
+                    return location.session_index;
+                }
+            }
+            Err(error) => {
+                trace!("Fetching the location of transaction {txid} failed: {error}");
+            }
+        }
+
+        sleep(RETRY_DELAY).await;
+    }
+}
+
 pub fn verify_blind_share(
     peer: PeerId,
     outcome: SerdeOutputOutcome,
@@ -304,6 +627,45 @@ pub fn verify_blind_share(
     Ok(outcome)
 }
 
+/// Verifies the blind signature shares a peer returned for a batch of outputs
+/// with a single batch verification, see [`tbs::verify_blind_shares`]
+pub fn verify_blind_share_batch(
+    peer: PeerId,
+    outcomes: Vec<MintOutputOutcome>,
+    blind_nonces: &[(Amount, BlindNonce)],
+    peer_tbs_pks: &BTreeMap<PeerId, Tiered<PublicKeyShare>>,
+) -> anyhow::Result<Vec<MintOutputOutcome>> {
+    if outcomes.len() != blind_nonces.len() {
+        bail!(
+            "Expected {} blind signature shares, got {}",
+            blind_nonces.len(),
+            outcomes.len()
+        );
+    }
+
+    let pks = peer_tbs_pks
+        .get(&peer)
+        .ok_or_else(|| anyhow!("Unknown peer"))?;
+
+    let batch = blind_nonces
+        .iter()
+        .zip(&outcomes)
+        .map(|((amount, blind_nonce), outcome)| {
+            let amount_key = pks
+                .tier(amount)
+                .map_err(|_| anyhow!("Invalid Amount Tier"))?;
+
+            Ok((blind_nonce.0, outcome.0, *amount_key))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if !tbs::verify_blind_shares(&batch) {
+        bail!("Invalid blind signature")
+    }
+
+    Ok(outcomes)
+}
+
 /// See [`MintOutputStates`]
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct MintOutputStatesAborted;
@@ -320,6 +682,26 @@ pub struct MintOutputStatesSucceeded {
     pub amount: Amount,
 }
 
+/// See [`MintOutputStates`]
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintOutputStatesAbortedMulti {
+    pub out_idxs: Vec<u64>,
+}
+
+/// See [`MintOutputStates`]
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintOutputStatesFailedMulti {
+    pub out_idxs: Vec<u64>,
+    pub error: String,
+}
+
+/// See [`MintOutputStates`]
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintOutputStatesSucceededMulti {
+    /// The amount of the note issued in every output by its index
+    pub amounts: BTreeMap<u64, Amount>,
+}
+
 /// Single [`Note`] issuance request to the mint.f
 ///
 /// Keeps the data to generate [`SpendableNote`] once the
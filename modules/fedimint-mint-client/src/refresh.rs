@@ -0,0 +1,190 @@
+use std::time::Duration;
+
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::api::GlobalFederationApi;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::task::sleep;
+use fedimint_core::{Amount, TransactionId};
+use fedimint_mint_common::Nonce;
+use tracing::{debug, trace};
+
+use crate::client_db::NoteKey;
+use crate::input::spend_note_input;
+use crate::output::await_issuance_session;
+use crate::MintClientContext;
+
+/// How often we check whether the session the notes are due in was reached
+const REFRESH_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// State machine reissuing the notes of an issuance before they expire, which
+/// only exists if the federation lets notes expire.
+///
+/// ```mermaid
+/// graph LR
+///     Created -- containing tx rejected --> Aborted
+///     Created -- containing tx accepted --> Scheduled
+///     Scheduled -- notes are due --> Refreshed
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum MintRefreshStates {
+    /// The notes were requested, we are waiting for the session their
+    /// transaction is accepted in
+    Created,
+    /// The transaction requesting the notes was rejected
+    Aborted,
+    /// The notes were issued and are due for reissuance in the session
+    Scheduled(MintRefreshStatesScheduled),
+    /// The notes we still held were reissued in the transaction, if any
+    Refreshed(MintRefreshStatesRefreshed),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintRefreshCommon {
+    pub(crate) operation_id: OperationId,
+    /// The transaction requesting the notes
+    pub(crate) txid: TransactionId,
+    /// The amounts and nonces of the notes issued by the transaction
+    pub(crate) notes: Vec<(Amount, Nonce)>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintRefreshStateMachine {
+    pub(crate) common: MintRefreshCommon,
+    pub(crate) state: MintRefreshStates,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintRefreshStatesScheduled {
+    pub(crate) refresh_session: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintRefreshStatesRefreshed {
+    pub(crate) refresh_txid: Option<TransactionId>,
+}
+
+impl State for MintRefreshStateMachine {
+    type ModuleContext = MintClientContext;
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        context: &Self::ModuleContext,
+        global_context: &Self::GlobalContext,
+    ) -> Vec<StateTransition<Self>> {
+        let Some(note_expiry) = context.note_expiry.clone() else {
+            return vec![];
+        };
+
+        match &self.state {
+            MintRefreshStates::Created => {
+                vec![StateTransition::new(
+                    await_issuance(global_context.clone(), self.common.clone()),
+                    move |_dbtx, result, old_state| {
+                        let state = match result {
+                            Ok(session_index) => {
+                                let epoch = note_expiry.expiry.epoch(session_index);
+                                MintRefreshStates::Scheduled(MintRefreshStatesScheduled {
+                                    refresh_session: note_expiry.expiry.refresh_session(epoch),
+                                })
+                            }
+                            Err(_) => MintRefreshStates::Aborted,
+                        };
+
+                        Box::pin(async move {
+                            MintRefreshStateMachine {
+                                common: old_state.common,
+                                state,
+                            }
+                        })
+                    },
+                )]
+            }
+            MintRefreshStates::Scheduled(scheduled) => {
+                let global_context = global_context.clone();
+                vec![StateTransition::new(
+                    await_refresh_session(global_context.clone(), scheduled.refresh_session),
+                    move |dbtx, (), old_state| {
+                        Box::pin(transition_refresh(dbtx, old_state, global_context.clone()))
+                    },
+                )]
+            }
+            MintRefreshStates::Aborted | MintRefreshStates::Refreshed(_) => {
+                vec![]
+            }
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.common.operation_id
+    }
+}
+
+async fn await_issuance(
+    global_context: DynGlobalClientContext,
+    common: MintRefreshCommon,
+) -> Result<u64, String> {
+    global_context
+        .await_tx_accepted(common.operation_id, common.txid)
+        .await?;
+
+    Ok(await_issuance_session(&global_context, common.txid).await)
+}
+
+async fn await_refresh_session(global_context: DynGlobalClientContext, refresh_session: u64) {
+    loop {
+        match global_context.api().fetch_block_count().await {
+            Ok(session_index) if refresh_session <= session_index => return,
+            Ok(_) => {}
+            Err(error) => {
+                trace!("Fetching the current session failed: {error}");
+            }
+        }
+
+        sleep(REFRESH_POLL_INTERVAL).await;
+    }
+}
+
+/// Reissues the notes of the issuance we still hold, the ones we spent in the
+/// meantime are no longer in our wallet
+async fn transition_refresh(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    old_state: MintRefreshStateMachine,
+    global_context: DynGlobalClientContext,
+) -> MintRefreshStateMachine {
+    assert!(matches!(old_state.state, MintRefreshStates::Scheduled(_)));
+
+    let operation_id = old_state.common.operation_id;
+    let mut inputs = vec![];
+
+    for (amount, nonce) in &old_state.common.notes {
+        let amount = *amount;
+        let Some(spendable_note) = dbtx
+            .module_tx()
+            .remove_entry(&NoteKey {
+                amount,
+                nonce: *nonce,
+            })
+            .await
+        else {
+            continue;
+        };
+
+        inputs.push(spend_note_input(operation_id, amount, spendable_note));
+    }
+
+    let refresh_txid = if inputs.is_empty() {
+        None
+    } else {
+        debug!(notes = inputs.len(), "Reissuing notes that are due");
+        Some(global_context.claim_inputs(dbtx, inputs).await.0)
+    };
+
+    MintRefreshStateMachine {
+        common: old_state.common,
+        state: MintRefreshStates::Refreshed(MintRefreshStatesRefreshed { refresh_txid }),
+    }
+}
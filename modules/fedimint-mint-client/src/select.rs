@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+
+use fedimint_core::{apply, async_trait_maybe_send, Amount, TieredMulti};
+use futures::StreamExt;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{InsufficientBalanceError, NotesSelector, SelectNotesWithAtleastAmount};
+
+/// How many subsets of notes we try at most when looking for the one with the
+/// least change, before settling for the best one found so far
+const MAX_CHANGE_SEARCH_TRIES: usize = 100_000;
+
+/// The strategy the client selects the notes it spends with, which can be
+/// configured through [`MintClientGen`](crate::MintClientGen)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteSelectionStrategy {
+    /// Spend as few notes as possible, see [`SelectNotesWithAtleastAmount`]
+    #[default]
+    MinimizeNotes,
+    /// Spend the notes leaving the least change, see
+    /// [`SelectNotesMinimizingChange`]
+    MinimizeChange,
+    /// Spend randomly chosen notes, see [`SelectNotesRandomly`]
+    Random,
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<Note: Send> NotesSelector<Note> for NoteSelectionStrategy {
+    async fn select_notes(
+        &self,
+        #[cfg(not(target_family = "wasm"))] stream: impl futures::Stream<Item = (Amount, Note)> + Send,
+        #[cfg(target_family = "wasm")] stream: impl futures::Stream<Item = (Amount, Note)>,
+        requested_amount: Amount,
+    ) -> anyhow::Result<TieredMulti<Note>> {
+        match self {
+            NoteSelectionStrategy::MinimizeNotes => {
+                SelectNotesWithAtleastAmount
+                    .select_notes(stream, requested_amount)
+                    .await
+            }
+            NoteSelectionStrategy::MinimizeChange => {
+                SelectNotesMinimizingChange
+                    .select_notes(stream, requested_amount)
+                    .await
+            }
+            NoteSelectionStrategy::Random => {
+                SelectNotesRandomly
+                    .select_notes(stream, requested_amount)
+                    .await
+            }
+        }
+    }
+}
+
+/// Select notes with total amount of *at least* `requested_amount` that
+/// exceeds it by as little as possible, even if that takes more notes than
+/// [`SelectNotesWithAtleastAmount`] would spend.
+///
+/// Less change means fewer notes have to be issued to us in the same
+/// transaction.
+pub struct SelectNotesMinimizingChange;
+
+#[apply(async_trait_maybe_send!)]
+impl<Note: Send> NotesSelector<Note> for SelectNotesMinimizingChange {
+    async fn select_notes(
+        &self,
+        #[cfg(not(target_family = "wasm"))] stream: impl futures::Stream<Item = (Amount, Note)> + Send,
+        #[cfg(target_family = "wasm")] stream: impl futures::Stream<Item = (Amount, Note)>,
+        requested_amount: Amount,
+    ) -> anyhow::Result<TieredMulti<Note>> {
+        let notes = stream.collect::<Vec<_>>().await;
+        Ok(select_notes_minimizing_change(notes, requested_amount)?)
+    }
+}
+
+/// Select randomly chosen notes with total amount of *at least*
+/// `requested_amount`, dropping the chosen notes that turn out not to be
+/// needed.
+///
+/// Spending the same denominations for the same amount makes payments of a
+/// client easier to tell apart from the ones of other clients, at the cost of
+/// spending more notes and receiving more change.
+pub struct SelectNotesRandomly;
+
+#[apply(async_trait_maybe_send!)]
+impl<Note: Send> NotesSelector<Note> for SelectNotesRandomly {
+    async fn select_notes(
+        &self,
+        #[cfg(not(target_family = "wasm"))] stream: impl futures::Stream<Item = (Amount, Note)> + Send,
+        #[cfg(target_family = "wasm")] stream: impl futures::Stream<Item = (Amount, Note)>,
+        requested_amount: Amount,
+    ) -> anyhow::Result<TieredMulti<Note>> {
+        let notes = stream.collect::<Vec<_>>().await;
+        Ok(select_notes_randomly(
+            notes,
+            requested_amount,
+            &mut rand::thread_rng(),
+        )?)
+    }
+}
+
+// We search the number of notes to spend of every denomination depth-first,
+// starting with the largest denomination, and stop at the first selection
+// without change. Selections exceeding the best one found so far are skipped,
+// as are denominations that cannot make up for the pending amount anymore.
+pub(crate) fn select_notes_minimizing_change<Note>(
+    mut notes: Vec<(Amount, Note)>,
+    requested_amount: Amount,
+) -> Result<TieredMulti<Note>, InsufficientBalanceError> {
+    let total_amount = notes.iter().map(|(amount, _)| *amount).sum::<Amount>();
+    if total_amount < requested_amount {
+        return Err(InsufficientBalanceError {
+            requested_amount,
+            total_amount,
+        });
+    }
+
+    if requested_amount == Amount::ZERO {
+        return Ok(TieredMulti::default());
+    }
+
+    notes.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let mut tiers: Vec<(u64, u64)> = vec![];
+    for (amount, _) in &notes {
+        match tiers.last_mut() {
+            Some((msats, count)) if *msats == amount.msats => *count += 1,
+            _ => tiers.push((amount.msats, 1)),
+        }
+    }
+
+    let mut search = ChangeSearch::new(&tiers, requested_amount.msats);
+    search.search(0, 0);
+
+    let mut selected_counts = tiers
+        .iter()
+        .zip(search.best_counts)
+        .map(|((msats, _), count)| (Amount::from_msats(*msats), count))
+        .collect::<BTreeMap<_, _>>();
+
+    Ok(notes
+        .into_iter()
+        .filter(|(amount, _)| {
+            let count = selected_counts
+                .get_mut(amount)
+                .expect("Every note has a tier");
+            match *count {
+                0 => false,
+                _ => {
+                    *count -= 1;
+                    true
+                }
+            }
+        })
+        .collect())
+}
+
+struct ChangeSearch<'a> {
+    /// Denominations in msats with the number of notes we hold, descending
+    tiers: &'a [(u64, u64)],
+    /// Total amount of the notes of the tier and all smaller ones
+    remaining: Vec<u64>,
+    requested: u64,
+    counts: Vec<u64>,
+    best_amount: u64,
+    best_counts: Vec<u64>,
+    tries: usize,
+}
+
+impl<'a> ChangeSearch<'a> {
+    fn new(tiers: &'a [(u64, u64)], requested: u64) -> Self {
+        let mut remaining = vec![0; tiers.len() + 1];
+        for (idx, (msats, count)) in tiers.iter().enumerate().rev() {
+            remaining[idx] = remaining[idx + 1] + msats * count;
+        }
+
+        // spending all notes is the worst selection we can fall back to
+        ChangeSearch {
+            tiers,
+            best_amount: remaining[0],
+            best_counts: tiers.iter().map(|(_, count)| *count).collect(),
+            remaining,
+            requested,
+            counts: vec![0; tiers.len()],
+            tries: 0,
+        }
+    }
+
+    /// Returns true once the search is over, because a selection without
+    /// change was found or we ran out of tries
+    fn search(&mut self, tier: usize, selected: u64) -> bool {
+        if MAX_CHANGE_SEARCH_TRIES <= self.tries {
+            return true;
+        }
+        self.tries += 1;
+
+        if self.requested <= selected {
+            if selected < self.best_amount {
+                self.best_amount = selected;
+                self.best_counts = self.counts.clone();
+            }
+            return selected == self.requested;
+        }
+
+        if tier == self.tiers.len() || selected + self.remaining[tier] < self.requested {
+            return false;
+        }
+
+        let (msats, count) = self.tiers[tier];
+        let pending = self.requested - selected;
+        let needed = pending / msats + u64::from(pending % msats != 0);
+
+        for notes in (0..=count.min(needed)).rev() {
+            let amount = selected + notes * msats;
+            if self.best_amount <= amount {
+                continue;
+            }
+
+            self.counts[tier] = notes;
+            if self.search(tier + 1, amount) {
+                return true;
+            }
+        }
+        self.counts[tier] = 0;
+
+        false
+    }
+}
+
+pub(crate) fn select_notes_randomly<Note>(
+    mut notes: Vec<(Amount, Note)>,
+    requested_amount: Amount,
+    rng: &mut impl Rng,
+) -> Result<TieredMulti<Note>, InsufficientBalanceError> {
+    notes.shuffle(rng);
+
+    let mut selected_amount = Amount::ZERO;
+    let mut selected = vec![];
+    for (amount, note) in notes {
+        if requested_amount <= selected_amount {
+            break;
+        }
+        selected_amount += amount;
+        selected.push((amount, note));
+    }
+
+    if selected_amount < requested_amount {
+        return Err(InsufficientBalanceError {
+            requested_amount,
+            total_amount: selected_amount,
+        });
+    }
+
+    // the last notes we picked may cover the amount on their own, so we drop the
+    // largest notes we do not need
+    selected.sort_by(|(a, _), (b, _)| b.cmp(a));
+    selected.retain(|(amount, _)| {
+        if requested_amount <= selected_amount - *amount {
+            selected_amount -= *amount;
+            false
+        } else {
+            true
+        }
+    });
+
+    Ok(selected.into_iter().collect())
+}
@@ -1,5 +1,6 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
+use anyhow::ensure;
 use fedimint_core::config::EmptyGenParams;
 use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
@@ -8,6 +9,7 @@ use fedimint_core::{plugin_types_trait_impl_config, Amount, PeerId, Tiered};
 use serde::{Deserialize, Serialize};
 use tbs::{AggregatePublicKey, PublicKeyShare};
 
+use crate::expiry::{NoteExpiry, NoteExpiryClientConfig, NoteExpiryConsensus};
 use crate::MintCommonGen;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +21,12 @@ pub struct MintGenParams {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MintGenParamsConsensus {
     denomination_base: u16,
+    /// Custom note denominations replacing the powers of the base
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    denominations: Option<Vec<Amount>>,
+    /// Expiry of the issued notes, notes never expire if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note_expiry: Option<NoteExpiry>,
 }
 
 // The maximum size of an E-Cash note (1,000,000 coins)
@@ -28,7 +36,32 @@ const MAX_DENOMINATION_SIZE: Amount = Amount::from_sats(1_000_000 * 100_000_000)
 
 impl MintGenParamsConsensus {
     pub fn new(denomination_base: u16) -> Self {
-        Self { denomination_base }
+        Self {
+            denomination_base,
+            denominations: None,
+            note_expiry: None,
+        }
+    }
+
+    /// Issues notes of the given denominations instead of the powers of a
+    /// base, e.g. to avoid splitting large amounts into many notes
+    pub fn with_denominations(denominations: Vec<Amount>) -> Self {
+        Self {
+            denomination_base: 2,
+            denominations: Some(denominations),
+            note_expiry: None,
+        }
+    }
+
+    /// Lets the issued notes expire, which requires clients to reissue them
+    /// regularly
+    pub fn with_note_expiry(mut self, note_expiry: NoteExpiry) -> Self {
+        self.note_expiry = Some(note_expiry);
+        self
+    }
+
+    pub fn note_expiry(&self) -> Option<NoteExpiry> {
+        self.note_expiry
     }
 
     pub fn denomination_base(&self) -> u16 {
@@ -36,11 +69,61 @@ impl MintGenParamsConsensus {
     }
 
     pub fn gen_denominations(&self) -> Vec<Amount> {
-        Tiered::gen_denominations(self.denomination_base, MAX_DENOMINATION_SIZE)
-            .tiers()
-            .cloned()
-            .collect()
+        match &self.denominations {
+            Some(denominations) => denominations
+                .iter()
+                .copied()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+            None => Tiered::gen_denominations(self.denomination_base, MAX_DENOMINATION_SIZE)
+                .tiers()
+                .cloned()
+                .collect(),
+        }
     }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match &self.denominations {
+            Some(denominations) => {
+                validate_denominations(denominations)?;
+
+                ensure!(
+                    denominations.contains(&Amount::from_msats(1)),
+                    "The denominations have to include 1 msat to represent every amount"
+                );
+            }
+            None => ensure!(
+                2 <= self.denomination_base,
+                "The denomination base has to be at least 2"
+            ),
+        }
+
+        if let Some(note_expiry) = &self.note_expiry {
+            note_expiry.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks denominations the mint is asked to issue notes of, either at setup
+/// or when adding tiers later on
+pub fn validate_denominations(denominations: &[Amount]) -> anyhow::Result<()> {
+    ensure!(!denominations.is_empty(), "No denominations given");
+
+    for denomination in denominations {
+        ensure!(
+            *denomination != Amount::ZERO,
+            "Denominations have to be positive"
+        );
+        ensure!(
+            *denomination <= MAX_DENOMINATION_SIZE,
+            "Denomination {denomination} exceeds the maximum of {MAX_DENOMINATION_SIZE}"
+        );
+    }
+
+    Ok(())
 }
 
 impl Default for MintGenParams {
@@ -71,12 +154,20 @@ pub struct MintConfigConsensus {
     pub fee_consensus: FeeConsensus,
     /// The maximum amount of change a client can request
     pub max_notes_per_denomination: u16,
+    /// The expiry of notes and the expiry key shares of all peers if notes
+    /// expire
+    #[serde(default)]
+    pub note_expiry: Option<NoteExpiryConsensus>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MintConfigPrivate {
     /// Secret keys for blind-signing ecash of varying note denominations
     pub tbs_sks: Tiered<tbs::SecretKeyShare>,
+    /// Secret keys the keys of the issuance epochs are derived from if notes
+    /// expire
+    #[serde(default)]
+    pub expiry_tbs_sks: Option<Tiered<tbs::SecretKeyShare>>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable, Hash)]
@@ -85,6 +176,8 @@ pub struct MintClientConfig {
     pub fee_consensus: FeeConsensus,
     pub peer_tbs_pks: BTreeMap<PeerId, Tiered<tbs::PublicKeyShare>>,
     pub max_notes_per_denomination: u16,
+    #[serde(default)]
+    pub note_expiry: Option<NoteExpiryClientConfig>,
 }
 
 impl std::fmt::Display for MintClientConfig {
@@ -1,11 +1,13 @@
 use std::time::SystemTime;
 
+use bitcoin_hashes::sha256;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_lookup, impl_db_record, Amount, OutPoint};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, OutPoint, PeerId};
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
-use crate::{MintOutputOutcome, Nonce};
+use crate::tiers::{TierApproval, TierDeal};
+use crate::{MintOutput, MintOutputOutcome, Nonce};
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -14,6 +16,12 @@ pub enum DbKeyPrefix {
     OutputOutcome = 0x13,
     MintAuditItem = 0x14,
     EcashBackup = 0x15,
+    PendingSignature = 0x16,
+    RequestedTiers = 0x17,
+    TierApproval = 0x18,
+    TierDeal = 0x19,
+    TierConfirmation = 0x1a,
+    SessionIndex = 0x1b,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -46,12 +54,31 @@ impl_db_record!(
     key = MintOutputOutcomeKey,
     value = MintOutputOutcome,
     db_prefix = DbKeyPrefix::OutputOutcome,
+    notify_on_modify = true,
 );
 impl_db_lookup!(
     key = MintOutputOutcomeKey,
     query_prefix = MintOutputOutcomePrefix
 );
 
+/// Output accepted in the current session whose blind signature share is
+/// created in a batch once the session ends
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct MintPendingSignatureKey(pub OutPoint);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct MintPendingSignaturePrefix;
+
+impl_db_record!(
+    key = MintPendingSignatureKey,
+    value = MintOutput,
+    db_prefix = DbKeyPrefix::PendingSignature,
+);
+impl_db_lookup!(
+    key = MintPendingSignatureKey,
+    query_prefix = MintPendingSignaturePrefix
+);
+
 /// Represents the amounts of issued (signed) and redeemed (verified) notes for
 /// auditing
 #[derive(Debug, Clone, Encodable, Decodable, Serialize)]
@@ -96,3 +123,70 @@ pub struct ECashUserBackupSnapshot {
     #[serde(with = "fedimint_core::hex::serde")]
     pub data: Vec<u8>,
 }
+
+/// The tiers our admin requested to add, which we approve until they are
+/// part of our config
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct RequestedTiersKey;
+
+impl_db_record!(
+    key = RequestedTiersKey,
+    value = Vec<Amount>,
+    db_prefix = DbKeyPrefix::RequestedTiers,
+);
+
+/// The latest tier approval ordered for a guardian
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct TierApprovalKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TierApprovalPrefix;
+
+impl_db_record!(
+    key = TierApprovalKey,
+    value = TierApproval,
+    db_prefix = DbKeyPrefix::TierApproval,
+);
+impl_db_lookup!(key = TierApprovalKey, query_prefix = TierApprovalPrefix);
+
+/// The latest tier deal ordered for a guardian
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct TierDealKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TierDealPrefix;
+
+impl_db_record!(
+    key = TierDealKey,
+    value = TierDeal,
+    db_prefix = DbKeyPrefix::TierDeal,
+);
+impl_db_lookup!(key = TierDealKey, query_prefix = TierDealPrefix);
+
+/// The hash of the tier deals a guardian confirmed
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct TierConfirmationKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TierConfirmationPrefix;
+
+impl_db_record!(
+    key = TierConfirmationKey,
+    value = sha256::Hash,
+    db_prefix = DbKeyPrefix::TierConfirmation,
+);
+impl_db_lookup!(
+    key = TierConfirmationKey,
+    query_prefix = TierConfirmationPrefix
+);
+
+/// The index of the session the mint currently processes items of, which
+/// determines the issuance epoch if notes expire
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct SessionIndexKey;
+
+impl_db_record!(
+    key = SessionIndexKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::SessionIndex,
+);
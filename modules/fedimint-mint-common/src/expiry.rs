@@ -0,0 +1,226 @@
+//! Expiry of notes after a number of sessions
+//!
+//! Since notes are blind signed, the mint can not tell when a note it is
+//! asked to redeem was issued. If the federation enables note expiry, the mint
+//! therefore signs the notes issued in every epoch of
+//! [`NoteExpiry::epoch_length`] sessions with a key of its own, which is the
+//! tier key plus a multiple of a second shared key that depends on the epoch.
+//! Only the keys of epochs whose notes have not expired are accepted, so a
+//! note has to be reissued before [`NoteExpiry::expiry_session`]. Clients
+//! reissue their notes once they are due at [`NoteExpiry::refresh_session`],
+//! which leaves them the grace period to get the reissuance accepted.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use anyhow::ensure;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, PeerId, Tiered};
+use serde::{Deserialize, Serialize};
+use tbs::{AggregatePublicKey, PublicKeyShare, Scalar, SecretKeyShare};
+
+use crate::Note;
+
+/// Number of epochs a lifetime is split into, a note is due for reissuance
+/// once it is older than about three quarters of its lifetime
+const EPOCHS_PER_LIFETIME: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct NoteExpiry {
+    /// Number of sessions whose notes are signed with the same epoch key
+    pub epoch_length: u64,
+    /// Number of sessions after the start of their epoch after which notes
+    /// are due for reissuance
+    pub lifetime: u64,
+    /// Number of sessions the mint still accepts notes after they became due
+    pub grace_period: u64,
+}
+
+impl NoteExpiry {
+    pub fn new(lifetime: u64, grace_period: u64) -> Self {
+        Self {
+            epoch_length: ((lifetime + EPOCHS_PER_LIFETIME - 1) / EPOCHS_PER_LIFETIME).max(1),
+            lifetime,
+            grace_period,
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(0 < self.epoch_length, "The epoch length has to be positive");
+        ensure!(
+            self.epoch_length <= self.lifetime,
+            "The lifetime of notes has to span at least one epoch"
+        );
+        ensure!(
+            self.grace_period <= self.lifetime,
+            "The grace period can not exceed the lifetime of notes"
+        );
+        ensure!(
+            self.lifetime / self.epoch_length <= EPOCHS_PER_LIFETIME,
+            "The lifetime of notes can span at most {EPOCHS_PER_LIFETIME} epochs"
+        );
+
+        Ok(())
+    }
+
+    /// The epoch the notes issued in the session are signed for
+    pub fn epoch(&self, session_index: u64) -> u64 {
+        session_index / self.epoch_length
+    }
+
+    /// The session from which the notes of the epoch are due for reissuance
+    pub fn refresh_session(&self, epoch: u64) -> u64 {
+        epoch * self.epoch_length + self.lifetime
+    }
+
+    /// The session from which the mint rejects the notes of the epoch
+    pub fn expiry_session(&self, epoch: u64) -> u64 {
+        self.refresh_session(epoch) + self.grace_period
+    }
+
+    /// The epochs whose notes the mint accepts in the session
+    pub fn live_epochs(&self, session_index: u64) -> RangeInclusive<u64> {
+        let first = match session_index.checked_sub(self.lifetime + self.grace_period) {
+            Some(expired) => expired / self.epoch_length + 1,
+            None => 0,
+        };
+
+        first..=self.epoch(session_index)
+    }
+}
+
+/// The factor of the expiry key in the key of an epoch, which is never zero
+/// such that no epoch is signed with the tier key alone
+fn epoch_factor(epoch: u64) -> Scalar {
+    Scalar::from(epoch + 1)
+}
+
+/// The secret key shares we sign the notes of the epoch with
+pub fn epoch_secret_key_shares(
+    tbs_sks: &Tiered<SecretKeyShare>,
+    expiry_sks: &Tiered<SecretKeyShare>,
+    epoch: u64,
+) -> Tiered<SecretKeyShare> {
+    tbs_sks
+        .iter()
+        .filter_map(|(amount, sk)| {
+            let expiry_sk = expiry_sks.get(amount)?;
+            Some((amount, sk.add_multiple(*expiry_sk, epoch_factor(epoch))))
+        })
+        .collect()
+}
+
+/// The public key shares of every peer for the notes of the epoch
+pub fn epoch_peer_public_key_shares(
+    peer_tbs_pks: &BTreeMap<PeerId, Tiered<PublicKeyShare>>,
+    peer_expiry_pks: &BTreeMap<PeerId, Tiered<PublicKeyShare>>,
+    epoch: u64,
+) -> BTreeMap<PeerId, Tiered<PublicKeyShare>> {
+    peer_tbs_pks
+        .iter()
+        .filter_map(|(peer, pks)| {
+            let expiry_pks = peer_expiry_pks.get(peer)?;
+            let epoch_pks = pks
+                .iter()
+                .filter_map(|(amount, pk)| {
+                    let expiry_pk = expiry_pks.get(amount)?;
+                    Some((amount, pk.add_multiple(*expiry_pk, epoch_factor(epoch))))
+                })
+                .collect();
+
+            Some((*peer, epoch_pks))
+        })
+        .collect()
+}
+
+/// The aggregate public keys the notes of the epoch are verified with
+pub fn epoch_public_keys(
+    tbs_pks: &Tiered<AggregatePublicKey>,
+    expiry_pks: &Tiered<AggregatePublicKey>,
+    epoch: u64,
+) -> Tiered<AggregatePublicKey> {
+    tbs_pks
+        .iter()
+        .filter_map(|(amount, pk)| {
+            let expiry_pk = expiry_pks.get(amount)?;
+            Some((amount, pk.add_multiple(*expiry_pk, epoch_factor(epoch))))
+        })
+        .collect()
+}
+
+/// The expiry of notes and the key shares of every peer the keys of an epoch
+/// are derived from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct NoteExpiryConsensus {
+    pub expiry: NoteExpiry,
+    pub peer_tbs_pks: BTreeMap<PeerId, Tiered<PublicKeyShare>>,
+}
+
+/// The expiry of notes and the aggregate keys clients derive the keys of an
+/// epoch from
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct NoteExpiryClientConfig {
+    pub expiry: NoteExpiry,
+    pub tbs_pks: Tiered<AggregatePublicKey>,
+    pub peer_tbs_pks: BTreeMap<PeerId, Tiered<PublicKeyShare>>,
+}
+
+impl NoteExpiryClientConfig {
+    /// Finds the epoch whose key signed the note among the epochs the mint
+    /// accepts in the session, returns `None` if the note expired or is not
+    /// signed by the mint at all
+    pub fn live_note_epoch(
+        &self,
+        amount_key: AggregatePublicKey,
+        amount: Amount,
+        note: &Note,
+        session_index: u64,
+    ) -> Option<u64> {
+        let expiry_pk = self.tbs_pks.get(amount)?;
+
+        self.expiry
+            .live_epochs(session_index)
+            .rev()
+            .find(|epoch| note.verify(amount_key.add_multiple(*expiry_pk, epoch_factor(*epoch))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NoteExpiry;
+
+    #[test]
+    fn notes_are_due_before_they_expire() {
+        let expiry = NoteExpiry::new(100, 20);
+
+        expiry.validate().unwrap();
+        assert_eq!(expiry.epoch_length, 25);
+
+        for session_index in 0..1000 {
+            let epoch = expiry.epoch(session_index);
+
+            // notes are due within their lifetime and expire after the grace period
+            assert!(expiry.refresh_session(epoch) <= session_index + 100);
+            assert!(session_index + 75 < expiry.refresh_session(epoch));
+            assert_eq!(
+                expiry.expiry_session(epoch),
+                expiry.refresh_session(epoch) + 20
+            );
+
+            let live = expiry.live_epochs(session_index);
+            assert_eq!(*live.end(), epoch);
+            assert!(live.clone().count() <= 6);
+
+            for live_epoch in live.clone() {
+                assert!(session_index < expiry.expiry_session(live_epoch));
+            }
+
+            if 0 < *live.start() {
+                assert!(expiry.expiry_session(live.start() - 1) <= session_index);
+            }
+        }
+
+        assert!(NoteExpiry::new(0, 0).validate().is_err());
+        assert!(NoteExpiry::new(100, 101).validate().is_err());
+    }
+}
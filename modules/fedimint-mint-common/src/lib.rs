@@ -7,14 +7,18 @@ use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
 use fedimint_core::tiered::InvalidAmountTierError;
 use fedimint_core::{plugin_types_trait_impl_common, Amount, PeerId};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tiers::{TierApproval, TierConfirmation, TierDeal};
 use tracing::error;
 
 pub mod config;
 
 pub mod common;
 pub mod db;
+pub mod expiry;
+pub mod tiers;
 
 pub const KIND: ModuleKind = ModuleKind::from_static_str("mint");
 const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
@@ -22,14 +26,43 @@ const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
 /// By default, the maximum notes per denomination when change-making for users
 pub const DEFAULT_MAX_NOTES_PER_DENOMINATION: u16 = 3;
 
+/// Capability of a token scoped to the mint to request new note tiers
+pub const TIERS_CAPABILITY: &str = "tiers";
+
+/// Maximum number of outputs whose blind signature shares a client can request
+/// at once
+pub const MAX_OUTPUT_OUTCOMES_PER_REQUEST: usize = 1000;
+
+/// Maximum number of notes a client can ask the federation about at once
+/// whether they were spent
+pub const MAX_SPENT_NOTES_PER_REQUEST: usize = 1000;
+
 /// Data structures taking into account different amount tiers
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
-pub struct MintConsensusItem;
+pub enum MintConsensusItem {
+    TierApproval(TierApproval),
+    TierDeal(TierDeal),
+    TierConfirmation(TierConfirmation),
+}
 
 impl std::fmt::Display for MintConsensusItem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "MintConsensusItem")
+        match self {
+            MintConsensusItem::TierApproval(approval) => {
+                write!(f, "Tier Approval: {}", approval.tiers.iter().format(", "))
+            }
+            MintConsensusItem::TierDeal(deal) => {
+                write!(f, "Tier Deal: {}", deal.tiers.iter().format(", "))
+            }
+            MintConsensusItem::TierConfirmation(confirmation) => {
+                write!(
+                    f,
+                    "Tier Confirmation: {}",
+                    confirmation.tiers.iter().format(", ")
+                )
+            }
+        }
     }
 }
 
@@ -220,6 +253,8 @@ pub enum MintError {
     InvalidSignature,
     #[error("Exceeded maximum notes per denomination {0}, found {1}")]
     ExceededMaxNotes(u16, usize),
+    #[error("One of the notes expired or had an invalid signature")]
+    ExpiredNote,
 }
 
 impl From<InvalidAmountTierError> for MintError {
@@ -0,0 +1,61 @@
+//! Adding note denominations to a running mint
+//!
+//! Once the admins of all guardians requested the same tiers, every guardian
+//! announces them in a [`TierApproval`] together with a key the evaluations
+//! dealt to it are encrypted to. After all guardians approved the same tiers,
+//! every guardian deals a random polynomial per tier as a [`TierDeal`]. The
+//! secret key share of a guardian for a new tier is the sum of the evaluations
+//! dealt to it, while the public key shares follow from the sum of the
+//! commitments. Every guardian checks the evaluations dealt to it and confirms
+//! the deals with a [`TierConfirmation`]. Once all guardians confirmed, the
+//! tiers are added to the mint config before the next session, from which
+//! clients discover them.
+
+use std::collections::BTreeMap;
+
+use bitcoin_hashes::sha256;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, PeerId};
+use serde::{Deserialize, Serialize};
+use tbs::PublicKeyShare;
+
+/// Announces that the admin of a guardian requested the tiers
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct TierApproval {
+    /// The requested tiers in ascending order
+    pub tiers: Vec<Amount>,
+    /// The key the evaluations dealt to the guardian are encrypted to
+    pub encryption_key: secp256k1_zkp::PublicKey,
+}
+
+/// The polynomials a guardian deals for the approved tiers
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct TierDeal {
+    pub tiers: Vec<Amount>,
+    /// Commitment to the coefficients of the polynomial of every tier
+    pub commitments: Vec<Vec<PublicKeyShare>>,
+    /// The evaluations of the polynomials for every guardian, encrypted to
+    /// the key of its approval
+    pub shares: BTreeMap<PeerId, Vec<u8>>,
+}
+
+/// Confirms that the evaluations dealt to a guardian match the commitments
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct TierConfirmation {
+    pub tiers: Vec<Amount>,
+    /// The hash of the deals of all guardians by their peer id
+    pub deals: sha256::Hash,
+}
+
+/// The addition of tiers as seen by the consensus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TierAdditionStatus {
+    /// The tiers requested by our admin, if any
+    pub requested: Option<Vec<Amount>>,
+    /// The tiers approved by every guardian
+    pub approvals: BTreeMap<PeerId, Vec<Amount>>,
+    /// The guardians whose deal has been ordered so far
+    pub deals: Vec<PeerId>,
+    /// The guardians that confirmed the current deals
+    pub confirmations: Vec<PeerId>,
+}
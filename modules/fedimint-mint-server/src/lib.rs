@@ -1,7 +1,11 @@
-use std::collections::{BTreeMap, HashMap};
+pub mod signing;
+pub mod tiers;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::FromIterator;
+use std::sync::Arc;
 
-use anyhow::bail;
+use anyhow::{anyhow, bail, ensure};
 use fedimint_core::config::{
     ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
@@ -10,7 +14,10 @@ use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::{
     DatabaseTransactionRef, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
 };
-use fedimint_core::endpoint_constants::{BACKUP_ENDPOINT, RECOVER_ENDPOINT};
+use fedimint_core::endpoint_constants::{
+    ADD_NOTE_TIERS_ENDPOINT, AWAIT_OUTPUT_OUTCOMES_ENDPOINT, BACKUP_ENDPOINT,
+    NOTE_TIER_ADDITION_ENDPOINT, RECOVER_ENDPOINT, SPENT_NOTES_ENDPOINT,
+};
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::{
     api_endpoint, ApiEndpoint, ApiError, CoreConsensusVersion, ExtendsCommonModuleInit, InputMeta,
@@ -29,25 +36,40 @@ use fedimint_mint_common::config::{
 };
 use fedimint_mint_common::db::{
     DbKeyPrefix, ECashUserBackupSnapshot, EcashBackupKey, EcashBackupKeyPrefix, MintAuditItemKey,
-    MintAuditItemKeyPrefix, MintOutputOutcomeKey, MintOutputOutcomePrefix, NonceKey,
-    NonceKeyPrefix,
+    MintAuditItemKeyPrefix, MintOutputOutcomeKey, MintOutputOutcomePrefix, MintPendingSignatureKey,
+    MintPendingSignaturePrefix, NonceKey, NonceKeyPrefix, RequestedTiersKey, SessionIndexKey,
+    TierApprovalKey, TierApprovalPrefix, TierConfirmationKey, TierConfirmationPrefix, TierDealKey,
+    TierDealPrefix,
+};
+use fedimint_mint_common::expiry::{
+    epoch_secret_key_shares, NoteExpiryClientConfig, NoteExpiryConsensus,
 };
+use fedimint_mint_common::tiers::{TierAdditionStatus, TierApproval, TierConfirmation, TierDeal};
 pub use fedimint_mint_common::{BackupRequest, SignedBackupRequest};
 use fedimint_mint_common::{
     MintCommonGen, MintConsensusItem, MintError, MintInput, MintModuleTypes, MintOutput,
-    MintOutputOutcome, DEFAULT_MAX_NOTES_PER_DENOMINATION,
+    MintOutputOutcome, Nonce, DEFAULT_MAX_NOTES_PER_DENOMINATION, MAX_OUTPUT_OUTCOMES_PER_REQUEST,
+    MAX_SPENT_NOTES_PER_REQUEST, TIERS_CAPABILITY,
 };
 use fedimint_server::config::distributedgen::{scalar, PeerHandleOps};
 use futures::StreamExt;
 use itertools::Itertools;
 use secp256k1_zkp::SECP256K1;
 use strum::IntoEnumIterator;
-use tbs::{
-    dealer_keygen, sign_blinded_msg, Aggregatable, AggregatePublicKey, PublicKeyShare,
-    SecretKeyShare,
-};
+use tbs::{dealer_keygen, Aggregatable, AggregatePublicKey, PublicKeyShare, SecretKeyShare};
 use threshold_crypto::group::Curve;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+use crate::signing::{BlindSigner, ParallelSigner};
+use crate::tiers::{
+    agreed_tiers, check_deal, check_tiers, confirmed_config, deal_tiers, deals_hash, has_tiers,
+    our_approval, our_tier_shares, remove_added_tiers, tier_addition_status, tier_approvals,
+    tier_confirmations, tier_deals,
+};
+
+/// Keys the DKG of the expiry keys by, such that its messages are not mixed up
+/// with the ones of the tier keys
+const NOTE_EXPIRY_DKG_KEY: &str = "note-expiry";
 
 #[derive(Debug, Clone)]
 pub struct MintGen;
@@ -100,6 +122,56 @@ impl ExtendsCommonModuleInit for MintGen {
                         "User Ecash Backup"
                     );
                 }
+                DbKeyPrefix::PendingSignature => {
+                    push_db_pair_items!(
+                        dbtx,
+                        MintPendingSignaturePrefix,
+                        MintPendingSignatureKey,
+                        MintOutput,
+                        mint,
+                        "Pending Signatures"
+                    );
+                }
+                DbKeyPrefix::RequestedTiers => {
+                    if let Some(tiers) = dbtx.get_value(&RequestedTiersKey).await {
+                        mint.insert("Requested Tiers".to_string(), Box::new(tiers));
+                    }
+                }
+                DbKeyPrefix::TierApproval => {
+                    push_db_pair_items!(
+                        dbtx,
+                        TierApprovalPrefix,
+                        TierApprovalKey,
+                        TierApproval,
+                        mint,
+                        "Tier Approvals"
+                    );
+                }
+                DbKeyPrefix::TierDeal => {
+                    push_db_pair_items!(
+                        dbtx,
+                        TierDealPrefix,
+                        TierDealKey,
+                        TierDeal,
+                        mint,
+                        "Tier Deals"
+                    );
+                }
+                DbKeyPrefix::TierConfirmation => {
+                    push_db_pair_items!(
+                        dbtx,
+                        TierConfirmationPrefix,
+                        TierConfirmationKey,
+                        bitcoin_hashes::sha256::Hash,
+                        mint,
+                        "Tier Confirmations"
+                    );
+                }
+                DbKeyPrefix::SessionIndex => {
+                    if let Some(session_index) = dbtx.get_value(&SessionIndexKey).await {
+                        mint.insert("Session Index".to_string(), Box::new(session_index));
+                    }
+                }
             }
         }
 
@@ -124,6 +196,32 @@ impl ServerModuleInit for MintGen {
         Ok(Mint::new(args.cfg().to_typed()?).into())
     }
 
+    fn parse_params(&self, params: &ConfigGenModuleParams) -> anyhow::Result<MintGenParams> {
+        let params = params.to_typed::<MintGenParams>()?;
+
+        params.consensus.validate()?;
+
+        Ok(params)
+    }
+
+    /// Adds the tiers confirmed by all guardians to our config
+    async fn update_config(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        identity: &PeerId,
+        config: &ServerModuleConfig,
+    ) -> anyhow::Result<Option<ServerModuleConfig>> {
+        let typed = config.to_typed::<MintConfig>()?;
+
+        Ok(confirmed_config(dbtx, &typed, *identity)
+            .await?
+            .map(|updated| {
+                let mut updated = updated.to_erased();
+                updated.consensus.version = config.consensus.version;
+                updated
+            }))
+    }
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -141,6 +239,17 @@ impl ServerModuleInit for MintGen {
             })
             .collect::<HashMap<_, _>>();
 
+        let expiry_keys = params.consensus.note_expiry().map(|expiry| {
+            let keys = params
+                .consensus
+                .gen_denominations()
+                .iter()
+                .map(|&amount| (amount, dealer_keygen(peers.threshold(), peers.len())))
+                .collect::<HashMap<_, _>>();
+
+            (expiry, keys)
+        });
+
         let mint_cfg: BTreeMap<_, MintConfig> = peers
             .iter()
             .map(|&peer| {
@@ -163,6 +272,23 @@ impl ServerModuleInit for MintGen {
                             .collect(),
                         fee_consensus: FeeConsensus::default(),
                         max_notes_per_denomination: DEFAULT_MAX_NOTES_PER_DENOMINATION,
+                        note_expiry: expiry_keys.as_ref().map(|(expiry, keys)| {
+                            NoteExpiryConsensus {
+                                expiry: *expiry,
+                                peer_tbs_pks: peers
+                                    .iter()
+                                    .map(|&key_peer| {
+                                        let pks = keys
+                                            .iter()
+                                            .map(|(amount, (_, pks, _))| {
+                                                (*amount, pks[key_peer.to_usize()])
+                                            })
+                                            .collect();
+                                        (key_peer, pks)
+                                    })
+                                    .collect(),
+                            }
+                        }),
                     },
                     private: MintConfigPrivate {
                         tbs_sks: params
@@ -171,6 +297,11 @@ impl ServerModuleInit for MintGen {
                             .iter()
                             .map(|amount| (*amount, tbs_keys[amount].2[peer.to_usize()]))
                             .collect(),
+                        expiry_tbs_sks: expiry_keys.as_ref().map(|(_, keys)| {
+                            keys.iter()
+                                .map(|(amount, (_, _, sks))| (*amount, sks[peer.to_usize()]))
+                                .collect()
+                        }),
                     },
                 };
                 (peer, config)
@@ -199,6 +330,29 @@ impl ServerModuleInit for MintGen {
             .map(|(amount, keys)| (amount, keys.tbs()))
             .collect::<HashMap<_, _>>();
 
+        let expiry_keys = match params.consensus.note_expiry() {
+            Some(expiry) => {
+                let g2 = peers
+                    .run_dkg_multi_g2(
+                        params
+                            .consensus
+                            .gen_denominations()
+                            .into_iter()
+                            .map(|amount| (NOTE_EXPIRY_DKG_KEY.to_string(), amount))
+                            .collect(),
+                    )
+                    .await?;
+
+                let keys = g2
+                    .into_iter()
+                    .map(|((_, amount), keys)| (amount, keys.tbs()))
+                    .collect::<HashMap<_, _>>();
+
+                Some((expiry, keys))
+            }
+            None => None,
+        };
+
         let server = MintConfig {
             local: MintConfigLocal,
             private: MintConfigPrivate {
@@ -206,6 +360,11 @@ impl ServerModuleInit for MintGen {
                     .iter()
                     .map(|(amount, (_, sks))| (*amount, *sks))
                     .collect(),
+                expiry_tbs_sks: expiry_keys.as_ref().map(|(_, keys)| {
+                    keys.iter()
+                        .map(|(amount, (_, sks))| (*amount, *sks))
+                        .collect()
+                }),
             },
             consensus: MintConfigConsensus {
                 peer_tbs_pks: peers
@@ -225,6 +384,27 @@ impl ServerModuleInit for MintGen {
                     .collect(),
                 fee_consensus: Default::default(),
                 max_notes_per_denomination: DEFAULT_MAX_NOTES_PER_DENOMINATION,
+                note_expiry: expiry_keys
+                    .as_ref()
+                    .map(|(expiry, keys)| NoteExpiryConsensus {
+                        expiry: *expiry,
+                        peer_tbs_pks: peers
+                            .peer_ids()
+                            .iter()
+                            .map(|peer| {
+                                let pks = keys
+                                    .iter()
+                                    .map(|(amount, (pks, _))| {
+                                        let pks =
+                                            PublicKeyShare(pks.evaluate(scalar(peer)).to_affine());
+                                        (*amount, pks)
+                                    })
+                                    .collect::<Tiered<_>>();
+
+                                (*peer, pks)
+                            })
+                            .collect(),
+                    }),
             },
         };
 
@@ -255,6 +435,35 @@ impl ServerModuleInit for MintGen {
             bail!("No msat 1 denomination");
         }
 
+        match (
+            &config.private.expiry_tbs_sks,
+            &config.consensus.note_expiry,
+        ) {
+            (Some(expiry_sks), Some(note_expiry)) => {
+                note_expiry.expiry.validate()?;
+
+                let expiry_pks = note_expiry
+                    .peer_tbs_pks
+                    .get(identity)
+                    .ok_or_else(|| anyhow!("No expiry pubkey shares of ours"))?;
+
+                ensure!(
+                    expiry_sks
+                        .iter()
+                        .map(|(amount, sk)| (amount, sk.to_pub_key_share()))
+                        .collect::<Tiered<_>>()
+                        == *expiry_pks,
+                    "Mint expiry key doesn't match pubkey share"
+                );
+                ensure!(
+                    expiry_sks.tiers().eq(config.private.tbs_sks.tiers()),
+                    "Mint expiry keys don't match the amount tiers"
+                );
+            }
+            (None, None) => {}
+            _ => bail!("Mint expiry keys are incomplete"),
+        }
+
         Ok(())
     }
 
@@ -285,15 +494,43 @@ impl ServerModuleInit for MintGen {
             fee_consensus: config.fee_consensus.clone(),
             peer_tbs_pks: config.peer_tbs_pks.clone(),
             max_notes_per_denomination: config.max_notes_per_denomination,
+            note_expiry: config.note_expiry.as_ref().map(note_expiry_client_config),
         })
     }
 }
+
+/// The expiry of notes together with the aggregate keys the keys of an
+/// issuance epoch are derived from
+fn note_expiry_client_config(note_expiry: &NoteExpiryConsensus) -> NoteExpiryClientConfig {
+    let tbs_pks = TieredMultiZip::new(
+        note_expiry
+            .peer_tbs_pks
+            .values()
+            .map(|keys| keys.iter())
+            .collect(),
+    )
+    .map(|(amt, keys)| {
+        let keys = keys.into_iter().copied().collect::<Vec<_>>();
+        (amt, keys.aggregate(note_expiry.peer_tbs_pks.threshold()))
+    })
+    .collect();
+
+    NoteExpiryClientConfig {
+        expiry: note_expiry.expiry,
+        tbs_pks,
+        peer_tbs_pks: note_expiry.peer_tbs_pks.clone(),
+    }
+}
 /// Federated mint member mint
 #[derive(Debug)]
 pub struct Mint {
     cfg: MintConfig,
+    our_id: PeerId,
     sec_key: Tiered<SecretKeyShare>,
+    /// The expiry of notes and the keys of the issuance epochs if notes expire
+    note_expiry: Option<(NoteExpiryClientConfig, Tiered<SecretKeyShare>)>,
     pub_key: HashMap<Amount, AggregatePublicKey>,
+    signer: Arc<dyn BlindSigner>,
 }
 #[apply(async_trait_maybe_send!)]
 impl ServerModule for Mint {
@@ -302,18 +539,138 @@ impl ServerModule for Mint {
 
     async fn consensus_proposal(
         &self,
-        _dbtx: &mut DatabaseTransactionRef<'_>,
+        dbtx: &mut DatabaseTransactionRef<'_>,
     ) -> Vec<MintConsensusItem> {
-        Vec::new()
+        let Some(requested) = dbtx.get_value(&RequestedTiersKey).await else {
+            return Vec::new();
+        };
+
+        if has_tiers(&self.cfg, &requested) {
+            return Vec::new();
+        }
+
+        let approvals = tier_approvals(dbtx).await;
+        let approval = our_approval(&self.cfg, &requested);
+
+        if approvals.get(&self.our_id) != Some(&approval) {
+            return vec![MintConsensusItem::TierApproval(approval)];
+        }
+
+        if agreed_tiers(&self.cfg, &approvals).is_none() {
+            return Vec::new();
+        }
+
+        let deals = tier_deals(dbtx).await;
+
+        if deals.get(&self.our_id).map(|deal| &deal.tiers) != Some(&requested) {
+            return match deal_tiers(&self.cfg, &approvals) {
+                Ok(deal) => vec![MintConsensusItem::TierDeal(deal)],
+                Err(error) => {
+                    warn!(%error, "Failed to deal the tiers");
+                    Vec::new()
+                }
+            };
+        }
+
+        if !deals.keys().eq(self.cfg.consensus.peer_tbs_pks.keys()) {
+            return Vec::new();
+        }
+
+        let hash = deals_hash(&deals);
+
+        if tier_confirmations(dbtx).await.get(&self.our_id) == Some(&hash) {
+            return Vec::new();
+        }
+
+        match our_tier_shares(&self.cfg, self.our_id, &approvals, &deals) {
+            Ok(..) => vec![MintConsensusItem::TierConfirmation(TierConfirmation {
+                tiers: requested,
+                deals: hash,
+            })],
+            Err(error) => {
+                warn!(%error, "The evaluations dealt to us do not match the commitments");
+                Vec::new()
+            }
+        }
     }
 
     async fn process_consensus_item<'a, 'b>(
         &'a self,
-        _dbtx: &mut DatabaseTransactionRef<'b>,
-        _consensus_item: MintConsensusItem,
-        _peer_id: PeerId,
+        dbtx: &mut DatabaseTransactionRef<'b>,
+        consensus_item: MintConsensusItem,
+        peer_id: PeerId,
     ) -> anyhow::Result<()> {
-        bail!("Mint does not process consensus items");
+        let confirmations = tier_confirmations(dbtx).await;
+
+        ensure!(
+            !confirmations
+                .keys()
+                .eq(self.cfg.consensus.peer_tbs_pks.keys()),
+            "All guardians confirmed the tiers already"
+        );
+
+        match consensus_item {
+            MintConsensusItem::TierApproval(approval) => {
+                check_tiers(&self.cfg, &approval.tiers)?;
+
+                if dbtx
+                    .insert_entry(&TierApprovalKey(peer_id), &approval)
+                    .await
+                    .as_ref()
+                    == Some(&approval)
+                {
+                    bail!("Already processed this tier approval");
+                }
+
+                // a changed approval invalidates the deals made for the previous ones
+                dbtx.remove_by_prefix(&TierDealPrefix).await;
+                dbtx.remove_by_prefix(&TierConfirmationPrefix).await;
+            }
+            MintConsensusItem::TierDeal(deal) => {
+                check_deal(&self.cfg, &tier_approvals(dbtx).await, &deal)?;
+
+                if dbtx
+                    .insert_entry(&TierDealKey(peer_id), &deal)
+                    .await
+                    .as_ref()
+                    == Some(&deal)
+                {
+                    bail!("Already processed this tier deal");
+                }
+
+                dbtx.remove_by_prefix(&TierConfirmationPrefix).await;
+            }
+            MintConsensusItem::TierConfirmation(confirmation) => {
+                let deals = tier_deals(dbtx).await;
+
+                ensure!(
+                    deals.keys().eq(self.cfg.consensus.peer_tbs_pks.keys()),
+                    "Not all guardians dealt the tiers yet"
+                );
+
+                ensure!(
+                    confirmation.deals == deals_hash(&deals),
+                    "The confirmation is for different deals"
+                );
+
+                if dbtx
+                    .insert_entry(&TierConfirmationKey(peer_id), &confirmation.deals)
+                    .await
+                    == Some(confirmation.deals)
+                {
+                    bail!("Already processed this tier confirmation");
+                }
+
+                if confirmations.len() + 1 == self.cfg.consensus.peer_tbs_pks.len() {
+                    info!(
+                        tiers = %confirmation.tiers.iter().format(", "),
+                        "All guardians confirmed the tiers, adding them after this session"
+                    );
+                }
+            }
+        }
+
+        Ok(())
     }
 
     async fn process_input<'a, 'b, 'c>(
@@ -327,8 +684,22 @@ impl ServerModule for Mint {
             .ok_or(MintError::InvalidAmountTier(input.amount))
             .into_module_error_other()?;
 
-        if !input.note.verify(*amount_key) {
-            return Err(MintError::InvalidSignature).into_module_error_other();
+        match &self.note_expiry {
+            Some((note_expiry, _)) => {
+                let session_index = dbtx.get_value(&SessionIndexKey).await.unwrap_or(0);
+
+                if note_expiry
+                    .live_note_epoch(*amount_key, input.amount, &input.note, session_index)
+                    .is_none()
+                {
+                    return Err(MintError::ExpiredNote).into_module_error_other();
+                }
+            }
+            None => {
+                if !input.note.verify(*amount_key) {
+                    return Err(MintError::InvalidSignature).into_module_error_other();
+                }
+            }
         }
 
         if dbtx
@@ -351,6 +722,7 @@ impl ServerModule for Mint {
                 fee: self.cfg.consensus.fee_consensus.note_spend_abs,
             },
             pub_keys: vec![*input.note.spend_key()],
+            fee_payout: Amount::ZERO,
         })
     }
 
@@ -360,17 +732,14 @@ impl ServerModule for Mint {
         output: &'a MintOutput,
         out_point: OutPoint,
     ) -> Result<TransactionItemAmount, ModuleError> {
-        let amount_key = self
-            .sec_key
-            .get(output.amount)
-            .ok_or(MintError::InvalidAmountTier(output.amount))
-            .into_module_error_other()?;
+        if self.sec_key.get(output.amount).is_none() {
+            return Err(MintError::InvalidAmountTier(output.amount)).into_module_error_other();
+        }
 
-        dbtx.insert_new_entry(
-            &MintOutputOutcomeKey(out_point),
-            &MintOutputOutcome(sign_blinded_msg(output.blind_nonce.0, *amount_key)),
-        )
-        .await;
+        // the output is signed together with the others of this session in
+        // `end_session`
+        dbtx.insert_new_entry(&MintPendingSignatureKey(out_point), output)
+            .await;
 
         dbtx.insert_new_entry(&MintAuditItemKey::Issuance(out_point), &output.amount)
             .await;
@@ -389,6 +758,66 @@ impl ServerModule for Mint {
         dbtx.get_value(&MintOutputOutcomeKey(out_point)).await
     }
 
+    async fn end_session(&self, dbtx: &mut DatabaseTransactionRef<'_>, session_index: u64) {
+        remove_added_tiers(dbtx, &self.cfg).await;
+
+        dbtx.insert_entry(&SessionIndexKey, &(session_index + 1))
+            .await;
+
+        let pending = dbtx
+            .find_by_prefix(&MintPendingSignaturePrefix)
+            .await
+            .map(|(key, output)| (key.0, output))
+            .collect::<Vec<_>>()
+            .await;
+
+        if pending.is_empty() {
+            return;
+        }
+
+        // the notes of the session are signed with the keys of its epoch
+        let epoch_sks = self.note_expiry.as_ref().map(|(note_expiry, expiry_sks)| {
+            let epoch = note_expiry.expiry.epoch(session_index);
+            epoch_secret_key_shares(&self.sec_key, expiry_sks, epoch)
+        });
+        let sec_key = epoch_sks.as_ref().unwrap_or(&self.sec_key);
+
+        let batch = pending
+            .iter()
+            .map(|(_, output)| {
+                let amount_key = sec_key
+                    .get(output.amount)
+                    .expect("Amount tier was checked when the output was processed");
+
+                (output.blind_nonce.0, *amount_key)
+            })
+            .collect::<Vec<_>>();
+
+        let signature_shares = self.signer.sign(&batch);
+
+        for ((out_point, _), signature_share) in pending.iter().zip(signature_shares) {
+            dbtx.remove_entry(&MintPendingSignatureKey(*out_point))
+                .await;
+            dbtx.insert_new_entry(
+                &MintOutputOutcomeKey(*out_point),
+                &MintOutputOutcome(signature_share),
+            )
+            .await;
+        }
+
+        debug!(
+            session_index,
+            outputs = pending.len(),
+            "Signed the outputs of the session"
+        );
+    }
+
+    /// The spent notes, such that a light client can verify that a note it
+    /// received was not spent before
+    fn committed_state_prefixes(&self) -> Vec<u8> {
+        vec![DbKeyPrefix::NoteNonce as u8]
+    }
+
     async fn audit(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -453,6 +882,71 @@ impl ServerModule for Mint {
                         .handle_recover_request(&mut context.dbtx(), id).await)
                 }
             },
+            api_endpoint! {
+                AWAIT_OUTPUT_OUTCOMES_ENDPOINT,
+                async |_module: &Mint, context, out_points: Vec<OutPoint>| -> Vec<MintOutputOutcome> {
+                    if MAX_OUTPUT_OUTCOMES_PER_REQUEST < out_points.len() {
+                        return Err(ApiError::bad_request(format!(
+                            "Requested more than {MAX_OUTPUT_OUTCOMES_PER_REQUEST} outcomes"
+                        )));
+                    }
+
+                    // the blind signature shares of a session are all written when it ends,
+                    // so after the first one of a transaction the others are there as well
+                    let mut outcomes = Vec::with_capacity(out_points.len());
+
+                    for out_point in out_points {
+                        let outcome = context.wait_key_exists(MintOutputOutcomeKey(out_point));
+                        outcomes.push(outcome.await);
+                    }
+
+                    Ok(outcomes)
+                }
+            },
+            api_endpoint! {
+                ADD_NOTE_TIERS_ENDPOINT,
+                async |module: &Mint, context, tiers: Vec<Amount>| -> () {
+                    context.check_capability(TIERS_CAPABILITY)?;
+
+                    let tiers = tiers
+                        .into_iter()
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect::<Vec<_>>();
+
+                    check_tiers(&module.cfg, &tiers)
+                        .map_err(|error| ApiError::bad_request(error.to_string()))?;
+
+                    context.dbtx().insert_entry(&RequestedTiersKey, &tiers).await;
+
+                    Ok(())
+                }
+            },
+            api_endpoint! {
+                NOTE_TIER_ADDITION_ENDPOINT,
+                async |_module: &Mint, context, _params: ()| -> TierAdditionStatus {
+                    context.check_capability(TIERS_CAPABILITY)?;
+
+                    Ok(tier_addition_status(&mut context.dbtx()).await)
+                }
+            },
+            api_endpoint! {
+                SPENT_NOTES_ENDPOINT,
+                async |_module: &Mint, context, nonces: Vec<Nonce>| -> Vec<bool> {
+                    if MAX_SPENT_NOTES_PER_REQUEST < nonces.len() {
+                        return Err(ApiError::bad_request(format!(
+                            "Requested more than {MAX_SPENT_NOTES_PER_REQUEST} notes"
+                        )));
+                    }
+
+                    let mut spent = Vec::with_capacity(nonces.len());
+                    for nonce in nonces {
+                        spent.push(context.dbtx().get_value(&NonceKey(nonce)).await.is_some());
+                    }
+
+                    Ok(spent)
+                }
+            },
         ]
     }
 }
@@ -550,13 +1044,33 @@ impl Mint {
         })
         .collect();
 
+        let note_expiry = cfg.consensus.note_expiry.as_ref().map(|note_expiry| {
+            let expiry_sks = cfg
+                .private
+                .expiry_tbs_sks
+                .clone()
+                .expect("Expiry keys were checked when validating the config");
+
+            (note_expiry_client_config(note_expiry), expiry_sks)
+        });
+
         Mint {
             cfg: cfg.clone(),
+            our_id,
+            note_expiry,
             sec_key: cfg.private.tbs_sks,
             pub_key: aggregate_pub_keys,
+            signer: Arc::new(ParallelSigner),
         }
     }
 
+    /// Replaces the signer creating the blind signature shares of the notes we
+    /// issue, which signs on all available cores by default
+    pub fn with_signer(mut self, signer: Arc<dyn BlindSigner>) -> Mint {
+        self.signer = signer;
+        self
+    }
+
     pub fn pub_key(&self) -> HashMap<Amount, AggregatePublicKey> {
         self.pub_key.clone()
     }
@@ -621,6 +1135,7 @@ mod test {
                     .peer_tbs_pks,
                 fee_consensus: FeeConsensus::default(),
                 max_notes_per_denomination: 0,
+                note_expiry: None,
             },
             private: MintConfigPrivate {
                 tbs_sks: mint_server_cfg1[0]
@@ -628,6 +1143,7 @@ mod test {
                     .unwrap()
                     .private
                     .tbs_sks,
+                expiry_tbs_sks: None,
             },
         });
     }
@@ -865,6 +1381,13 @@ mod fedimint_migration_tests {
                                 "validate_migrations was not able to read any EcashBackups"
                             );
                         }
+                        // Introduced after the v0 snapshot was created
+                        DbKeyPrefix::PendingSignature
+                        | DbKeyPrefix::RequestedTiers
+                        | DbKeyPrefix::TierApproval
+                        | DbKeyPrefix::TierDeal
+                        | DbKeyPrefix::TierConfirmation
+                        | DbKeyPrefix::SessionIndex => {}
                     }
                 }
                 Ok(())
@@ -0,0 +1,93 @@
+//! Creation of the blind signature shares for the notes issued in a session
+//!
+//! Instead of signing every output as it is processed, the mint collects the
+//! outputs of a session and signs them in one batch once the session ends.
+//! How a batch is signed is pluggable through the [`BlindSigner`] trait, so
+//! guardians can e.g. move signing to dedicated hardware.
+
+use std::fmt::Debug;
+use std::num::NonZeroUsize;
+use std::thread;
+
+use tbs::{sign_blinded_msgs, BlindedMessage, BlindedSignatureShare, SecretKeyShare};
+
+/// Batches smaller than this are not worth spawning threads for
+const MIN_CHUNK_SIZE: usize = 64;
+
+/// Creates the blind signature shares for a batch of blinded messages
+pub trait BlindSigner: Debug + Send + Sync {
+    /// Signs every message with its key share, returning the signature shares
+    /// in the order of the batch
+    fn sign(&self, batch: &[(BlindedMessage, SecretKeyShare)]) -> Vec<BlindedSignatureShare>;
+}
+
+/// Signs the batch on the calling thread
+#[derive(Debug, Clone, Default)]
+pub struct SequentialSigner;
+
+impl BlindSigner for SequentialSigner {
+    fn sign(&self, batch: &[(BlindedMessage, SecretKeyShare)]) -> Vec<BlindedSignatureShare> {
+        sign_blinded_msgs(batch)
+    }
+}
+
+/// Splits the batch across all available cores
+#[derive(Debug, Clone, Default)]
+pub struct ParallelSigner;
+
+impl BlindSigner for ParallelSigner {
+    fn sign(&self, batch: &[(BlindedMessage, SecretKeyShare)]) -> Vec<BlindedSignatureShare> {
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let chunk_size = MIN_CHUNK_SIZE.max((batch.len() + threads - 1) / threads);
+
+        if batch.len() <= chunk_size {
+            return sign_blinded_msgs(batch);
+        }
+
+        thread::scope(|scope| {
+            batch
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(|| sign_blinded_msgs(chunk)))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Signing thread panicked"))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tbs::{blind_message, dealer_keygen, sign_blinded_msg, BlindingKey, Message};
+
+    use super::{BlindSigner, ParallelSigner, SequentialSigner};
+
+    #[test]
+    fn signers_match_individual_signing() {
+        let (_, _, sks) = dealer_keygen(3, 4);
+
+        let batch = (0..1000u16)
+            .map(|i| {
+                let msg = Message::from_bytes(&i.to_be_bytes());
+                (
+                    blind_message(msg, BlindingKey::random()),
+                    sks[i as usize % 4],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let expected = batch
+            .iter()
+            .map(|(msg, sks)| sign_blinded_msg(*msg, *sks).0)
+            .collect::<Vec<_>>();
+
+        for signer in [&SequentialSigner as &dyn BlindSigner, &ParallelSigner] {
+            let sigs = signer.sign(&batch);
+            assert_eq!(
+                sigs.into_iter().map(|sig| sig.0).collect::<Vec<_>>(),
+                expected
+            );
+            assert!(signer.sign(&[]).is_empty());
+        }
+    }
+}
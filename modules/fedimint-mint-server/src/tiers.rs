@@ -0,0 +1,531 @@
+//! Generating the keys of note tiers added to a running mint, see
+//! [`fedimint_mint_common::tiers`]
+//!
+//! The polynomials we deal and the key the evaluations dealt to us are
+//! encrypted to are derived from our secret key share of the smallest tier
+//! and the requested tiers. Therefore we do not have to store any secrets
+//! until the keys of the new tiers are written to our config, and a deal we
+//! replace deals the same polynomials.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, ensure};
+use bitcoin_hashes::{sha256, sha512, Hash, HashEngine};
+use fedimint_aead::{decrypt, encrypt, LessSafeKey, UnboundKey, CHACHA20_POLY1305};
+use fedimint_core::db::{DatabaseTransactionRef, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::{Amount, NumPeers, PeerId};
+use fedimint_mint_common::config::{validate_denominations, MintConfig};
+use fedimint_mint_common::db::{
+    RequestedTiersKey, TierApprovalPrefix, TierConfirmationPrefix, TierDealPrefix,
+};
+use fedimint_mint_common::tiers::{TierAdditionStatus, TierApproval, TierDeal};
+use fedimint_server::config::distributedgen::scalar;
+use futures::StreamExt;
+use secp256k1_zkp::ecdh::SharedSecret;
+use secp256k1_zkp::{PublicKey, SecretKey, SECP256K1};
+use tbs::poly::Poly;
+use tbs::{PublicKeyShare, Scalar, SecretKeyShare};
+use threshold_crypto::group::Curve;
+use threshold_crypto::G2Projective;
+
+/// Checks tiers requested by an admin or approved by a guardian, which needs
+/// to be deterministic since all guardians have to accept the same consensus
+/// items
+pub fn check_tiers(cfg: &MintConfig, tiers: &[Amount]) -> anyhow::Result<()> {
+    validate_denominations(tiers)?;
+
+    ensure!(
+        tiers.windows(2).all(|pair| pair[0] < pair[1]),
+        "The tiers are not in ascending order"
+    );
+
+    ensure!(
+        cfg.consensus.note_expiry.is_none(),
+        "Tiers can not be added while notes expire"
+    );
+
+    for tier in tiers {
+        ensure!(
+            cfg.private.tbs_sks.get(*tier).is_none(),
+            "The mint already issues notes of {tier}"
+        );
+    }
+
+    Ok(())
+}
+
+/// Whether the tiers are part of our config
+pub fn has_tiers(cfg: &MintConfig, tiers: &[Amount]) -> bool {
+    tiers
+        .iter()
+        .all(|tier| cfg.private.tbs_sks.get(*tier).is_some())
+}
+
+/// Our approval of the tiers requested by our admin
+pub fn our_approval(cfg: &MintConfig, tiers: &[Amount]) -> TierApproval {
+    TierApproval {
+        tiers: tiers.to_vec(),
+        encryption_key: encryption_key(cfg, tiers).public_key(SECP256K1),
+    }
+}
+
+/// The tiers all guardians approved, if they approved the same ones
+pub fn agreed_tiers(
+    cfg: &MintConfig,
+    approvals: &BTreeMap<PeerId, TierApproval>,
+) -> Option<Vec<Amount>> {
+    if !approvals.keys().eq(cfg.consensus.peer_tbs_pks.keys()) {
+        return None;
+    }
+
+    let tiers = &approvals.values().next()?.tiers;
+
+    approvals
+        .values()
+        .all(|approval| &approval.tiers == tiers)
+        .then(|| tiers.clone())
+}
+
+/// Deals a polynomial for every agreed tier, whose evaluations are encrypted
+/// to the keys the guardians approved the tiers with
+pub fn deal_tiers(
+    cfg: &MintConfig,
+    approvals: &BTreeMap<PeerId, TierApproval>,
+) -> anyhow::Result<TierDeal> {
+    let tiers = agreed_tiers(cfg, approvals)
+        .ok_or_else(|| anyhow!("Not all guardians approved the same tiers yet"))?;
+
+    let our_key = encryption_key(cfg, &tiers);
+
+    let polys = tiers
+        .iter()
+        .map(|tier| dealt_poly(cfg, &tiers, *tier))
+        .collect::<Vec<_>>();
+
+    let commitments = polys
+        .iter()
+        .map(|poly| {
+            poly.coefficients()
+                .map(|coefficient| {
+                    PublicKeyShare((G2Projective::generator() * coefficient).to_affine())
+                })
+                .collect()
+        })
+        .collect();
+
+    let shares = approvals
+        .iter()
+        .map(|(peer, approval)| {
+            let evaluations = polys
+                .iter()
+                .flat_map(|poly| poly.evaluate(scalar(peer)).to_bytes())
+                .collect::<Vec<u8>>();
+
+            Ok((
+                *peer,
+                encrypt(
+                    evaluations,
+                    &shared_key(&our_key, &approval.encryption_key)?,
+                )?,
+            ))
+        })
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+    Ok(TierDeal {
+        tiers,
+        commitments,
+        shares,
+    })
+}
+
+/// Checks a deal ordered by consensus, which needs to be deterministic since
+/// all guardians have to accept the same consensus items
+pub fn check_deal(
+    cfg: &MintConfig,
+    approvals: &BTreeMap<PeerId, TierApproval>,
+    deal: &TierDeal,
+) -> anyhow::Result<()> {
+    let tiers = agreed_tiers(cfg, approvals)
+        .ok_or_else(|| anyhow!("Not all guardians approved the same tiers yet"))?;
+
+    ensure!(
+        deal.tiers == tiers,
+        "The deal is not for the approved tiers"
+    );
+
+    ensure!(
+        deal.commitments.len() == tiers.len(),
+        "The deal does not contain a commitment for every tier"
+    );
+
+    let threshold = cfg.consensus.peer_tbs_pks.threshold();
+
+    ensure!(
+        deal.commitments
+            .iter()
+            .all(|commitment| commitment.len() == threshold),
+        "The commitments do not have the {threshold} coefficients the threshold requires"
+    );
+
+    ensure!(
+        deal.shares.keys().eq(cfg.consensus.peer_tbs_pks.keys()),
+        "The deal does not contain a share for every guardian"
+    );
+
+    Ok(())
+}
+
+/// The hash guardians confirm the deals of all guardians with
+pub fn deals_hash(deals: &BTreeMap<PeerId, TierDeal>) -> sha256::Hash {
+    deals.consensus_hash()
+}
+
+/// Decrypts the evaluations dealt to us and checks them against the
+/// commitments of their dealers, which we do before we confirm the deals.
+/// Returns the sum of the evaluations for every tier, which is our secret key
+/// share of the tier.
+pub fn our_tier_shares(
+    cfg: &MintConfig,
+    our_id: PeerId,
+    approvals: &BTreeMap<PeerId, TierApproval>,
+    deals: &BTreeMap<PeerId, TierDeal>,
+) -> anyhow::Result<Vec<Scalar>> {
+    let tiers = agreed_tiers(cfg, approvals)
+        .ok_or_else(|| anyhow!("Not all guardians approved the same tiers yet"))?;
+
+    ensure!(
+        deals.keys().eq(cfg.consensus.peer_tbs_pks.keys()),
+        "Not all guardians dealt the tiers yet"
+    );
+
+    let our_key = encryption_key(cfg, &tiers);
+    let mut shares = vec![Scalar::from(0); tiers.len()];
+
+    for (dealer, deal) in deals {
+        let mut ciphertext = deal
+            .shares
+            .get(&our_id)
+            .ok_or_else(|| anyhow!("The deal of guardian {dealer} contains no share for us"))?
+            .clone();
+
+        let key = shared_key(&our_key, &approvals[dealer].encryption_key)?;
+        let bytes = decrypt(&mut ciphertext, &key)?;
+
+        ensure!(
+            bytes.len() == 32 * tiers.len(),
+            "The shares dealt by guardian {dealer} have the wrong length"
+        );
+
+        for ((share, bytes), commitment) in shares
+            .iter_mut()
+            .zip(bytes.chunks(32))
+            .zip(&deal.commitments)
+        {
+            let bytes: [u8; 32] = bytes.try_into().expect("Chunks have 32 bytes");
+
+            let evaluation = Option::<Scalar>::from(Scalar::from_bytes(&bytes))
+                .ok_or_else(|| anyhow!("The share dealt by guardian {dealer} is not a scalar"))?;
+
+            ensure!(
+                G2Projective::generator() * evaluation == evaluate_commitment(commitment, our_id),
+                "The share dealt by guardian {dealer} does not match its commitment"
+            );
+
+            *share += evaluation;
+        }
+    }
+
+    Ok(shares)
+}
+
+/// Our config with the keys of the tiers dealt by all guardians
+pub fn config_with_tiers(
+    cfg: &MintConfig,
+    our_id: PeerId,
+    approvals: &BTreeMap<PeerId, TierApproval>,
+    deals: &BTreeMap<PeerId, TierDeal>,
+) -> anyhow::Result<MintConfig> {
+    let tiers = agreed_tiers(cfg, approvals)
+        .ok_or_else(|| anyhow!("Not all guardians approved the same tiers yet"))?;
+
+    let shares = our_tier_shares(cfg, our_id, approvals, deals)?;
+
+    let mut updated = cfg.clone();
+
+    for (index, (tier, share)) in tiers.iter().zip(shares).enumerate() {
+        updated.private.tbs_sks.insert(*tier, SecretKeyShare(share));
+
+        for (peer, pks) in &mut updated.consensus.peer_tbs_pks {
+            let pk = deals
+                .values()
+                .map(|deal| evaluate_commitment(&deal.commitments[index], *peer))
+                .fold(G2Projective::identity(), |sum, pk| sum + pk);
+
+            pks.insert(*tier, PublicKeyShare(pk.to_affine()));
+        }
+    }
+
+    ensure!(
+        updated.consensus.peer_tbs_pks.get(&our_id) == Some(&updated.private.tbs_sks.to_public()),
+        "Our new secret key shares do not match the public key shares"
+    );
+
+    Ok(updated)
+}
+
+/// Our config with the tiers all guardians confirmed, unless they are part of
+/// it already
+pub async fn confirmed_config(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+    cfg: &MintConfig,
+    our_id: PeerId,
+) -> anyhow::Result<Option<MintConfig>> {
+    let confirmations = tier_confirmations(dbtx).await;
+
+    if !confirmations.keys().eq(cfg.consensus.peer_tbs_pks.keys()) {
+        return Ok(None);
+    }
+
+    let approvals = tier_approvals(dbtx).await;
+
+    let Some(tiers) = agreed_tiers(cfg, &approvals) else {
+        return Ok(None);
+    };
+
+    if has_tiers(cfg, &tiers) {
+        return Ok(None);
+    }
+
+    let deals = tier_deals(dbtx).await;
+
+    config_with_tiers(cfg, our_id, &approvals, &deals).map(Some)
+}
+
+/// Removes the records of the addition once the tiers are part of our config
+pub async fn remove_added_tiers(dbtx: &mut DatabaseTransactionRef<'_>, cfg: &MintConfig) {
+    let approvals = tier_approvals(dbtx).await;
+
+    if let Some(tiers) = agreed_tiers(cfg, &approvals) {
+        if has_tiers(cfg, &tiers) {
+            dbtx.remove_by_prefix(&TierApprovalPrefix).await;
+            dbtx.remove_by_prefix(&TierDealPrefix).await;
+            dbtx.remove_by_prefix(&TierConfirmationPrefix).await;
+        }
+    }
+
+    if let Some(requested) = dbtx.get_value(&RequestedTiersKey).await {
+        if has_tiers(cfg, &requested) {
+            dbtx.remove_entry(&RequestedTiersKey).await;
+        }
+    }
+}
+
+pub async fn tier_approvals(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+) -> BTreeMap<PeerId, TierApproval> {
+    dbtx.find_by_prefix(&TierApprovalPrefix)
+        .await
+        .map(|(key, approval)| (key.0, approval))
+        .collect()
+        .await
+}
+
+pub async fn tier_deals(dbtx: &mut DatabaseTransactionRef<'_>) -> BTreeMap<PeerId, TierDeal> {
+    dbtx.find_by_prefix(&TierDealPrefix)
+        .await
+        .map(|(key, deal)| (key.0, deal))
+        .collect()
+        .await
+}
+
+pub async fn tier_confirmations(
+    dbtx: &mut DatabaseTransactionRef<'_>,
+) -> BTreeMap<PeerId, sha256::Hash> {
+    dbtx.find_by_prefix(&TierConfirmationPrefix)
+        .await
+        .map(|(key, deals)| (key.0, deals))
+        .collect()
+        .await
+}
+
+/// The addition of tiers in progress as seen by us
+pub async fn tier_addition_status(dbtx: &mut DatabaseTransactionRef<'_>) -> TierAdditionStatus {
+    TierAdditionStatus {
+        requested: dbtx.get_value(&RequestedTiersKey).await,
+        approvals: tier_approvals(dbtx)
+            .await
+            .into_iter()
+            .map(|(peer, approval)| (peer, approval.tiers))
+            .collect(),
+        deals: tier_deals(dbtx).await.into_keys().collect(),
+        confirmations: tier_confirmations(dbtx).await.into_keys().collect(),
+    }
+}
+
+/// The public key share of a guardian committed to by the coefficients
+fn evaluate_commitment(commitment: &[PublicKeyShare], peer: PeerId) -> G2Projective {
+    Poly::<G2Projective, Scalar>::from(
+        commitment
+            .iter()
+            .map(|coefficient| G2Projective::from(coefficient.0))
+            .collect(),
+    )
+    .evaluate(scalar(&peer))
+}
+
+/// The key the evaluations dealt to us for the given tiers are encrypted to
+fn encryption_key(cfg: &MintConfig, tiers: &[Amount]) -> SecretKey {
+    let secret = derive_secret(cfg, tiers, b"encryption-key");
+
+    SecretKey::from_slice(&secret[..32]).expect("Hash is a valid secret key")
+}
+
+/// The polynomial we deal for a tier, whose degree matches the one of the
+/// existing tiers
+fn dealt_poly(cfg: &MintConfig, tiers: &[Amount], tier: Amount) -> Poly<Scalar, Scalar> {
+    let threshold = cfg.consensus.peer_tbs_pks.threshold();
+
+    Poly::from(
+        (0..threshold)
+            .map(|index| {
+                let tag = format!("poly-{}-{index}", tier.msats);
+
+                Scalar::from_bytes_wide(&derive_secret(cfg, tiers, tag.as_bytes()))
+            })
+            .collect(),
+    )
+}
+
+/// Derives a secret for the addition of the given tiers from our secret key
+/// share of the smallest tier
+fn derive_secret(cfg: &MintConfig, tiers: &[Amount], tag: &[u8]) -> [u8; 64] {
+    let (_, sks) = cfg
+        .private
+        .tbs_sks
+        .iter()
+        .next()
+        .expect("The mint has at least one tier");
+
+    let mut engine = sha512::Hash::engine();
+    engine.input(b"fedimint-mint-tiers");
+    engine.input(tag);
+    engine.input(
+        &tiers
+            .to_vec()
+            .consensus_encode_to_vec()
+            .expect("Writing to a vec can't fail"),
+    );
+    engine.input(&sks.0.to_bytes());
+
+    sha512::Hash::from_engine(engine).into_inner()
+}
+
+/// The key shared by the owners of the two encryption keys
+fn shared_key(our_key: &SecretKey, their_key: &PublicKey) -> anyhow::Result<LessSafeKey> {
+    let secret = SharedSecret::new(their_key, our_key);
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &secret.secret_bytes())
+        .map_err(|_| anyhow!("Unable to create key"))?;
+
+    Ok(LessSafeKey::new(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::config::{ConfigGenModuleParams, TypedServerModuleConfig};
+    use fedimint_core::module::ServerModuleInit;
+    use fedimint_core::{Amount, PeerId};
+    use fedimint_mint_common::config::{MintConfig, MintGenParams, MintGenParamsConsensus};
+    use tbs::{blind_message, combine_valid_shares, sign_blinded_msg, unblind_signature};
+
+    use super::{check_deal, config_with_tiers, deal_tiers, our_approval, our_tier_shares};
+    use crate::MintGen;
+
+    #[test]
+    fn added_tiers_issue_valid_notes() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let params = MintGenParams {
+            local: Default::default(),
+            consensus: MintGenParamsConsensus::with_denominations(vec![
+                Amount::from_msats(1),
+                Amount::from_msats(1_000),
+            ]),
+        };
+        let cfgs = MintGen
+            .trusted_dealer_gen(&peers, &ConfigGenModuleParams::from_typed(params).unwrap())
+            .into_iter()
+            .map(|(peer, cfg)| (peer, cfg.to_typed::<MintConfig>().unwrap()))
+            .collect::<BTreeMap<_, _>>();
+
+        let tiers = vec![Amount::from_msats(500), Amount::from_msats(1_000_000)];
+
+        let approvals = cfgs
+            .iter()
+            .map(|(peer, cfg)| (*peer, our_approval(cfg, &tiers)))
+            .collect::<BTreeMap<_, _>>();
+
+        let deals = cfgs
+            .iter()
+            .map(|(peer, cfg)| {
+                (
+                    *peer,
+                    deal_tiers(cfg, &approvals).expect("Tiers are agreed"),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        for deal in deals.values() {
+            assert!(check_deal(&cfgs[&peers[0]], &approvals, deal).is_ok());
+        }
+
+        let updated = cfgs
+            .iter()
+            .map(|(peer, cfg)| {
+                let updated =
+                    config_with_tiers(cfg, *peer, &approvals, &deals).expect("Deals are valid");
+
+                (*peer, updated)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        for tier in &tiers {
+            let message = tbs::Message::from_bytes(b"note of an added tier");
+            let blinding_key = tbs::BlindingKey::random();
+            let blinded = blind_message(message, blinding_key);
+
+            let shares = updated
+                .values()
+                .take(3)
+                .enumerate()
+                .map(|(index, cfg)| {
+                    let sks = *cfg.private.tbs_sks.get(*tier).expect("Tier was added");
+
+                    (index, sign_blinded_msg(blinded, sks))
+                })
+                .collect::<Vec<_>>();
+
+            let signature = unblind_signature(blinding_key, combine_valid_shares(shares, 3));
+
+            let client_cfg = MintGen
+                .get_client_config(&updated[&peers[0]].clone().to_erased().consensus)
+                .unwrap();
+
+            assert!(tbs::verify(
+                message,
+                signature,
+                *client_cfg.tbs_pks.get(*tier).expect("Tier was added")
+            ));
+        }
+
+        // a guardian does not confirm shares that do not match the commitment
+        let mut tampered = deals.clone();
+        tampered
+            .get_mut(&peers[2])
+            .expect("Deal exists")
+            .commitments = deals[&peers[1]].commitments.clone();
+
+        assert!(our_tier_shares(&cfgs[&peers[0]], peers[0], &approvals, &tampered).is_err());
+    }
+}
@@ -1,17 +1,32 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::bail;
+use fedimint_client::backup::BackupRecord;
+use fedimint_client::module::init::ClientModuleInitRegistry;
+use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
+use fedimint_client::{get_config_from_db, Client, ClientArc, ClientBuilder, FederationInfo};
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
+use fedimint_core::task::{sleep, timeout};
+use fedimint_core::tiered_multi::TieredSummary;
+use fedimint_core::time::now;
 use fedimint_core::util::NextOrPending;
 use fedimint_core::{sats, Amount};
 use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
 use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyGen;
 use fedimint_mint_client::{
-    MintClientExt, MintClientGen, OOBNotes, ReissueExternalNotesState, SpendOOBState,
+    MintClientExt, MintClientGen, MintClientModule, OOBNotes, ReissueExternalNotesState,
+    RestoreProgress, SpendOOBState,
 };
 use fedimint_mint_common::config::MintGenParams;
 use fedimint_mint_server::MintGen;
+use fedimint_testing::federation::FederationTest;
 use fedimint_testing::fixtures::{Fixtures, TIMEOUT};
 
 fn fixtures() -> Fixtures {
-    let fixtures = Fixtures::new_primary(MintClientGen, MintGen, MintGenParams::default());
+    let fixtures =
+        Fixtures::new_primary(MintClientGen::default(), MintGen, MintGenParams::default());
     fixtures.with_module(DummyClientGen, DummyGen, DummyGenParams::default())
 }
 
@@ -84,3 +99,189 @@ async fn error_zero_value_oob_receive() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backs_up_on_schedule_and_restores_with_progress() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let secret = [1; 64];
+
+    // Without any backup the first scheduled one is uploaded right away
+    let mut client_builder = client_builder(&fed, MemDatabase::new().into()).await?;
+    client_builder.with_backup_interval(Duration::from_millis(500));
+    let client = client_builder
+        .build(PlainRootSecretStrategy::to_root_secret(&secret))
+        .await?;
+    let first_backup = await_backup_after(&client, UNIX_EPOCH).await?;
+
+    let (op, outpoint) = client.print_money(sats(1000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+    let printed_at = now();
+
+    // Once the interval passed another backup including the notes is uploaded
+    let backup = await_backup_after(&client, printed_at).await?;
+    assert!(backup.fedimint_block_count >= first_backup.fedimint_block_count);
+    let uploaded = client
+        .download_backup_from_federation()
+        .await?
+        .expect("The backup was uploaded");
+    assert!(uploaded.fedimint_block_count >= backup.fedimint_block_count);
+    drop(client);
+
+    let (client, _) = client_builder(&fed, MemDatabase::new().into())
+        .await?
+        .build_restoring_from_backup(PlainRootSecretStrategy::to_root_secret(&secret))
+        .await?;
+    let mut progress = client.subscribe_restore_progress().await;
+
+    // The restore only ever moves forward until it succeeded
+    let mut last_scanned = 0;
+    let mut last_imported = 0;
+    let mut scanning = true;
+    let restored_amount = loop {
+        match progress.ok().await? {
+            RestoreProgress::ScanningBlocks { scanned, total } => {
+                assert!(scanning, "Scanning again after importing notes");
+                assert!(scanned <= total);
+                assert!(last_scanned <= scanned);
+                last_scanned = scanned;
+            }
+            RestoreProgress::ImportingNotes { imported } => {
+                scanning = false;
+                assert!(last_imported <= imported);
+                last_imported = imported;
+            }
+            RestoreProgress::Success { restored_amount } => break restored_amount,
+            RestoreProgress::Failed { reason } => bail!("Restore failed: {reason}"),
+            RestoreProgress::Paused { .. } => bail!("The restore was never paused"),
+        }
+    };
+    assert_eq!(restored_amount, sats(1000));
+    assert_eq!(client.await_restore_finished().await?, sats(1000));
+    assert_eq!(client.get_balance().await, sats(1000));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_resumes_where_it_was_paused() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let secret = [2; 64];
+
+    // Notes issued over several transactions, which a restore without any
+    // backup finds by scanning all blocks
+    let client = client_builder(&fed, MemDatabase::new().into())
+        .await?
+        .build(PlainRootSecretStrategy::to_root_secret(&secret))
+        .await?;
+    for _ in 0..4 {
+        let (op, outpoint) = client.print_money(sats(250)).await?;
+        client.await_primary_module_output(op, outpoint).await?;
+    }
+    let notes = note_summary(&client).await;
+    drop(client);
+
+    let db: Database = MemDatabase::new().into();
+    let (client, _) = client_builder(&fed, db.clone())
+        .await?
+        .build_restoring_from_backup(PlainRootSecretStrategy::to_root_secret(&secret))
+        .await?;
+    client.pause_restore();
+    let mut progress = client.subscribe_restore_progress().await;
+    let (paused_scanned, paused_total) = loop {
+        match progress.ok().await? {
+            RestoreProgress::ScanningBlocks { .. } => {}
+            RestoreProgress::Paused { scanned, total } => break (scanned, total),
+            other => bail!("Expected the restore to pause, got {other:?}"),
+        }
+    };
+    assert!(paused_scanned <= paused_total);
+    drop(progress);
+    drop(client);
+
+    // The paused restore is persisted and stays paused when the client is opened
+    // again, until it is resumed
+    let client = client_builder(&fed, db)
+        .await?
+        .build(PlainRootSecretStrategy::to_root_secret(&secret))
+        .await?;
+    let mut progress = client.subscribe_restore_progress().await;
+    loop {
+        if let RestoreProgress::Paused { scanned, total } = progress.ok().await? {
+            assert_eq!((scanned, total), (paused_scanned, paused_total));
+            break;
+        }
+    }
+    client.resume_restore();
+
+    // Scanning continues from the persisted blocks and the progress only grows
+    // until it reaches 100 percent
+    let mut last_percent = 0;
+    let restored_amount = loop {
+        let update = progress.ok().await?;
+        let percent = update.percent().expect("The restore does not fail");
+        assert!((last_percent..=100).contains(&percent));
+        last_percent = percent;
+
+        match update {
+            RestoreProgress::ScanningBlocks { scanned, total } => {
+                assert!(paused_scanned <= scanned);
+                assert_eq!(total, paused_total);
+            }
+            RestoreProgress::ImportingNotes { .. } => {}
+            RestoreProgress::Success { restored_amount } => break restored_amount,
+            other => bail!("Unexpected progress after resuming {other:?}"),
+        }
+    };
+    assert_eq!(last_percent, 100);
+    assert_eq!(restored_amount, sats(1000));
+    assert_eq!(client.await_restore_finished().await?, sats(1000));
+    assert_eq!(note_summary(&client).await, notes);
+
+    Ok(())
+}
+
+/// Builder of a client of `fed` storing its state in `db`
+async fn client_builder(fed: &FederationTest, db: Database) -> anyhow::Result<ClientBuilder> {
+    let mut module_inits = ClientModuleInitRegistry::new();
+    module_inits.attach(MintClientGen::default());
+    module_inits.attach(DummyClientGen);
+
+    let mut client_builder = Client::builder();
+    client_builder.with_module_inits(module_inits);
+    client_builder.with_primary_module(0);
+    // a client opened again reads the config from its database
+    if get_config_from_db(&db).await.is_none() {
+        client_builder
+            .with_federation_info(FederationInfo::from_invite_code(fed.invite_code()).await?);
+    }
+    client_builder.with_database(db);
+    Ok(client_builder)
+}
+
+/// Summary of the spendable notes of the client
+async fn note_summary(client: &ClientArc) -> TieredSummary {
+    let (mint, instance) = client.get_first_module::<MintClientModule>(&fedimint_mint_client::KIND);
+    mint.get_wallet_summary(
+        &mut client
+            .db()
+            .begin_transaction()
+            .await
+            .dbtx_ref_with_prefix_module_id(instance.id),
+    )
+    .await
+}
+
+/// Waits until the client recorded a backup uploaded after `time`
+async fn await_backup_after(client: &ClientArc, time: SystemTime) -> anyhow::Result<BackupRecord> {
+    timeout(TIMEOUT, async {
+        loop {
+            let latest = client.backup_records().await.into_iter().next();
+            match latest {
+                Some((timestamp, record)) if time < timestamp => return record,
+                _ => sleep(Duration::from_millis(100)).await,
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("No backup was uploaded after {time:?}"))
+}
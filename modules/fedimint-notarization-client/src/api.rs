@@ -0,0 +1,26 @@
+use bitcoin_hashes::sha256;
+use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
+use fedimint_core::endpoint_constants::NOTARIZATION_ENDPOINT;
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send, OutPoint};
+
+#[apply(async_trait_maybe_send!)]
+pub trait NotarizationFederationApi {
+    /// Returns the output that committed the hash, if it was notarized
+    async fn notarization(&self, hash: sha256::Hash) -> FederationResult<Option<OutPoint>>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> NotarizationFederationApi for T
+where
+    T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn notarization(&self, hash: sha256::Hash) -> FederationResult<Option<OutPoint>> {
+        self.request_current_consensus(
+            NOTARIZATION_ENDPOINT.to_string(),
+            ApiRequestErased::new(hash),
+        )
+        .await
+    }
+}
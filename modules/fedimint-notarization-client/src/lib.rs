@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, ensure, Context as _};
+use bitcoin_hashes::sha256;
+use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
+use fedimint_client::module::ClientModule;
+use fedimint_client::sm::Context;
+use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
+use fedimint_client::ClientArc;
+use fedimint_core::api::{GlobalFederationApi, SessionRange};
+use fedimint_core::core::{IntoDynInstance, OperationId};
+use fedimint_core::db::DatabaseTransactionRef;
+use fedimint_core::module::{
+    ApiVersion, ExtendsCommonModuleInit, ModuleCommon, MultiApiVersion, TransactionItemAmount,
+};
+use fedimint_core::rotation::broadcast_public_keys_at;
+use fedimint_core::{apply, async_trait_maybe_send, Amount};
+pub use fedimint_notarization_common as common;
+use fedimint_notarization_common::config::NotarizationClientConfig;
+use fedimint_notarization_common::{
+    NotarizationCommonGen, NotarizationModuleTypes, NotarizationOutput, NotarizationProof, KIND,
+};
+use states::NotarizationStateMachine;
+
+use crate::api::NotarizationFederationApi;
+
+pub mod api;
+pub mod states;
+
+/// Exposed API calls for client apps
+#[apply(async_trait_maybe_send!)]
+pub trait NotarizationClientExt {
+    /// Returns the fee the federation charges for notarizing a hash
+    fn notarization_fee(&self) -> Amount;
+
+    /// Pays the fee to commit the hash into the next signed block and waits
+    /// for the proof of the commitment, which takes until the end of the
+    /// session. If the hash was notarized before, the proof of the earlier
+    /// commitment is returned without paying again.
+    async fn notarize(&self, hash: sha256::Hash) -> anyhow::Result<NotarizationProof>;
+
+    /// Returns the verified proof of the commitment of the hash, `None` if it
+    /// was not notarized
+    async fn notarization_proof(
+        &self,
+        hash: sha256::Hash,
+    ) -> anyhow::Result<Option<NotarizationProof>>;
+
+    /// Verifies the proof against the broadcast public keys of the federation
+    /// at the session the hash was committed in
+    async fn verify_notarization(&self, proof: &NotarizationProof) -> anyhow::Result<()>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl NotarizationClientExt for ClientArc {
+    fn notarization_fee(&self) -> Amount {
+        let (notarization, _instance) = self.get_first_module::<NotarizationClientModule>(&KIND);
+        notarization.cfg.fee
+    }
+
+    async fn notarize(&self, hash: sha256::Hash) -> anyhow::Result<NotarizationProof> {
+        if let Some(proof) = self.notarization_proof(hash).await? {
+            return Ok(proof);
+        }
+
+        let (_notarization, instance) = self.get_first_module::<NotarizationClientModule>(&KIND);
+
+        let operation_id = OperationId(rand::random());
+        let output = ClientOutput {
+            output: NotarizationOutput { hash },
+            state_machines: Arc::new(move |_, _| Vec::<NotarizationStateMachine>::new()),
+        };
+
+        // The primary module funds the fee
+        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+        let (txid, _) = self
+            .finalize_and_submit_transaction(operation_id, KIND.as_str(), move |_, _| hash, tx)
+            .await?;
+
+        self.transaction_updates(operation_id)
+            .await
+            .await_tx_accepted(txid)
+            .await
+            .map_err(|e| anyhow!("Notarization transaction was rejected: {e}"))?;
+
+        self.notarization_proof(hash)
+            .await?
+            .context("Accepted notarization is missing")
+    }
+
+    async fn notarization_proof(
+        &self,
+        hash: sha256::Hash,
+    ) -> anyhow::Result<Option<NotarizationProof>> {
+        let (_notarization, instance) = self.get_first_module::<NotarizationClientModule>(&KIND);
+
+        let Some(out_point) = instance.api.notarization(hash).await? else {
+            return Ok(None);
+        };
+
+        // The receipt only proves the inclusion of the transaction, so we need
+        // the transaction itself to show that it commits the hash
+        let session_index = self
+            .api()
+            .await_transaction_proof(out_point.txid)
+            .await?
+            .session_index;
+        let range = SessionRange {
+            start_index: session_index,
+            limit: 1,
+        };
+        let transaction = self
+            .api()
+            .fetch_session_transactions(range, self.decoders())
+            .await?
+            .value
+            .into_iter()
+            .map(|located| located.transaction)
+            .find(|transaction| transaction.tx_hash() == out_point.txid)
+            .context("Notarization transaction is missing in its session")?;
+
+        let proof = NotarizationProof {
+            hash,
+            out_point,
+            receipt: self.transaction_receipt(&transaction).await?,
+            transaction,
+        };
+
+        self.verify_notarization(&proof).await?;
+
+        Ok(Some(proof))
+    }
+
+    async fn verify_notarization(&self, proof: &NotarizationProof) -> anyhow::Result<()> {
+        let (_notarization, instance) = self.get_first_module::<NotarizationClientModule>(&KIND);
+
+        // the block may have been signed with broadcast keys replaced since
+        let key_epochs = self.api().key_epochs().await?;
+        let broadcast_public_keys = broadcast_public_keys_at(
+            &key_epochs,
+            &self.get_config().global.broadcast_public_keys,
+            proof.session_index(),
+        );
+
+        ensure!(
+            proof.verify(instance.id, broadcast_public_keys),
+            "Invalid notarization proof for {}",
+            proof.hash
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct NotarizationClientModule {
+    cfg: NotarizationClientConfig,
+}
+
+/// Data needed by the state machine
+#[derive(Debug, Clone)]
+pub struct NotarizationClientContext;
+
+impl Context for NotarizationClientContext {}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientModule for NotarizationClientModule {
+    type Common = NotarizationModuleTypes;
+    type ModuleStateMachineContext = NotarizationClientContext;
+    type States = NotarizationStateMachine;
+
+    fn context(&self) -> Self::ModuleStateMachineContext {
+        NotarizationClientContext
+    }
+
+    fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
+        match *input {}
+    }
+
+    fn output_amount(
+        &self,
+        _output: &<Self::Common as ModuleCommon>::Output,
+    ) -> TransactionItemAmount {
+        TransactionItemAmount {
+            amount: Amount::ZERO,
+            fee: self.cfg.fee,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NotarizationClientGen;
+
+#[apply(async_trait_maybe_send!)]
+impl ExtendsCommonModuleInit for NotarizationClientGen {
+    type Common = NotarizationCommonGen;
+
+    async fn dump_database(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        // The client does not store anything
+        Box::new(std::iter::empty())
+    }
+}
+
+/// Generates the client module
+#[apply(async_trait_maybe_send!)]
+impl ClientModuleInit for NotarizationClientGen {
+    type Module = NotarizationClientModule;
+
+    fn supported_api_versions(&self) -> MultiApiVersion {
+        MultiApiVersion::try_from_iter([ApiVersion { major: 0, minor: 0 }])
+            .expect("no version conflicts")
+    }
+
+    async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
+        Ok(NotarizationClientModule {
+            cfg: args.cfg().clone(),
+        })
+    }
+}
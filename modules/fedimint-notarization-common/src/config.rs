@@ -0,0 +1,69 @@
+use fedimint_core::config::EmptyGenParams;
+use fedimint_core::core::ModuleKind;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{plugin_types_trait_impl_config, Amount};
+use serde::{Deserialize, Serialize};
+
+use crate::NotarizationCommonGen;
+
+/// Parameters necessary to generate this module's configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotarizationGenParams {
+    pub local: EmptyGenParams,
+    pub consensus: NotarizationGenParamsConsensus,
+}
+
+/// Consensus parameters for config generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotarizationGenParamsConsensus {
+    pub fee: Amount,
+}
+
+impl Default for NotarizationGenParamsConsensus {
+    fn default() -> Self {
+        NotarizationGenParamsConsensus {
+            fee: Amount::from_sats(1),
+        }
+    }
+}
+
+/// Contains all the configuration for the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotarizationConfig {
+    pub local: NotarizationConfigLocal,
+    pub private: NotarizationConfigPrivate,
+    pub consensus: NotarizationConfigConsensus,
+}
+
+/// Contains all the configuration for the client
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable, Hash)]
+pub struct NotarizationClientConfig {
+    pub fee: Amount,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct NotarizationConfigLocal;
+
+/// Will be the same for every federation member
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct NotarizationConfigConsensus {
+    /// Fee charged for every notarized hash, it keeps the blocks from being
+    /// filled with commitments for free
+    pub fee: Amount,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotarizationConfigPrivate;
+
+// Wire together the configs for this module
+plugin_types_trait_impl_config!(
+    NotarizationCommonGen,
+    NotarizationGenParams,
+    EmptyGenParams,
+    NotarizationGenParamsConsensus,
+    NotarizationConfig,
+    NotarizationConfigLocal,
+    NotarizationConfigPrivate,
+    NotarizationConfigConsensus,
+    NotarizationClientConfig
+);
@@ -0,0 +1,253 @@
+//! Commits 32 byte hashes, e.g. of documents, into the threshold signed blocks
+//! of a federation, for timestamping and notary services.
+//!
+//! A client pays the fee of the module for an output containing the hash. Once
+//! the transaction is included in a signed block the client assembles a
+//! [`NotarizationProof`] from the transaction and its
+//! [`TransactionReceipt`], which proves to anyone knowing the broadcast public
+//! keys of the federation that the hash was committed in that session, without
+//! contacting the federation. Every hash can only be notarized once, so the
+//! first commitment is the only one.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bitcoin_hashes::sha256;
+use config::NotarizationClientConfig;
+use fedimint_core::block::TransactionReceipt;
+use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
+use fedimint_core::transaction::Transaction;
+use fedimint_core::{plugin_types_trait_impl_common, OutPoint, PeerId};
+use secp256k1_zkp::PublicKey;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// The client and server configuration
+pub mod config;
+
+/// Unique name for this module
+pub const KIND: ModuleKind = ModuleKind::from_static_str("notarization");
+
+/// Modules are non-compatible with older versions
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+
+/// The module has no consensus items
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum NotarizationConsensusItem {}
+
+/// The module has no inputs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum NotarizationInput {}
+
+/// Commits the hash into the block the transaction is included in, the
+/// transaction has to pay the fee of the module
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct NotarizationOutput {
+    pub hash: sha256::Hash,
+}
+
+/// The hash the output committed
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct NotarizationOutputOutcome(pub sha256::Hash);
+
+/// Proves that the federation committed a hash in a signed block
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct NotarizationProof {
+    pub hash: sha256::Hash,
+    /// The output of [`Self::transaction`] containing the hash
+    pub out_point: OutPoint,
+    pub transaction: Transaction,
+    /// Proves that the transaction was included in the block of
+    /// [`Self::session_index`]
+    pub receipt: TransactionReceipt,
+}
+
+impl NotarizationProof {
+    /// The index of the session the hash was committed in, which orders the
+    /// commitment relative to the rest of the consensus history
+    pub fn session_index(&self) -> u64 {
+        self.receipt.proof.session_index
+    }
+
+    /// Verifies that the hash was committed by the notarization module with
+    /// the instance id, where `public_keys` are the broadcast public keys of
+    /// the federation at [`Self::session_index`]
+    pub fn verify(
+        &self,
+        module_instance_id: ModuleInstanceId,
+        public_keys: &BTreeMap<PeerId, PublicKey>,
+    ) -> bool {
+        let Some(output) = self
+            .transaction
+            .outputs
+            .get(self.out_point.out_idx as usize)
+        else {
+            return false;
+        };
+
+        let commits_hash = output.module_instance_id() == module_instance_id
+            && output
+                .as_any()
+                .downcast_ref::<NotarizationOutput>()
+                .map_or(false, |output| output.hash == self.hash);
+
+        commits_hash
+            && self.out_point.txid == self.receipt.txid
+            && self.receipt.verify(&self.transaction, public_keys)
+    }
+}
+
+/// Reasons for rejecting an output
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
+pub enum NotarizationError {
+    #[error("Hash {0} was already notarized in output {1}")]
+    AlreadyNotarized(sha256::Hash, OutPoint),
+}
+
+/// Contains the types defined above
+pub struct NotarizationModuleTypes;
+
+// Wire together the types for this module
+plugin_types_trait_impl_common!(
+    NotarizationModuleTypes,
+    NotarizationClientConfig,
+    NotarizationInput,
+    NotarizationOutput,
+    NotarizationOutputOutcome,
+    NotarizationConsensusItem
+);
+
+#[derive(Debug)]
+pub struct NotarizationCommonGen;
+
+impl CommonModuleInit for NotarizationCommonGen {
+    const CONSENSUS_VERSION: ModuleConsensusVersion = CONSENSUS_VERSION;
+    const KIND: ModuleKind = KIND;
+
+    type ClientConfig = NotarizationClientConfig;
+
+    fn decoder() -> Decoder {
+        NotarizationModuleTypes::decoder_builder().build()
+    }
+}
+
+impl fmt::Display for NotarizationClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NotarizationClientConfig")
+    }
+}
+
+impl fmt::Display for NotarizationInput {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for NotarizationOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Notarize {}", self.hash)
+    }
+}
+
+impl fmt::Display for NotarizationOutputOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Notarized {}", self.0)
+    }
+}
+
+impl fmt::Display for NotarizationConsensusItem {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::block::{
+        broadcast_message_hash, AcceptedItem, Block, SchnorrSignature, SignedBlockHeader,
+        TransactionReceipt,
+    };
+    use fedimint_core::core::DynOutput;
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::transaction::Transaction;
+    use fedimint_core::{OutPoint, PeerId};
+    use secp256k1_zkp::SECP256K1;
+
+    use super::{NotarizationOutput, NotarizationProof};
+
+    #[test]
+    fn proof_verifies_committed_hash() {
+        let keypairs = (0..4u8)
+            .map(|i| {
+                secp256k1_zkp::SecretKey::from_slice(&[i + 1; 32])
+                    .expect("Valid secret key")
+                    .keypair(SECP256K1)
+            })
+            .collect::<Vec<_>>();
+        let public_keys = keypairs
+            .iter()
+            .enumerate()
+            .map(|(peer, keypair)| (PeerId::from(peer as u16), keypair.public_key()))
+            .collect::<BTreeMap<_, _>>();
+
+        let hash = sha256::Hash::hash(b"document");
+        let transaction = Transaction {
+            inputs: vec![],
+            outputs: vec![DynOutput::from_typed(3, NotarizationOutput { hash })],
+            signature: None,
+        };
+        let block = Block {
+            items: vec![AcceptedItem {
+                item: ConsensusItem::Transaction(transaction.clone()),
+                peer: PeerId::from(0),
+            }],
+        };
+
+        let header = block.header(5);
+        let message = broadcast_message_hash(&public_keys, &header);
+        let signatures = keypairs
+            .iter()
+            .enumerate()
+            .map(|(peer, keypair)| {
+                let signature = SECP256K1.sign_schnorr_no_aux_rand(&message, keypair);
+                (
+                    PeerId::from(peer as u16),
+                    SchnorrSignature(signature.as_ref().to_owned()),
+                )
+            })
+            .collect();
+
+        let proof = NotarizationProof {
+            hash,
+            out_point: OutPoint {
+                txid: transaction.tx_hash(),
+                out_idx: 0,
+            },
+            receipt: TransactionReceipt {
+                txid: transaction.tx_hash(),
+                proof: block.accepted_item_proof(5, 0).expect("Item exists"),
+                signed_header: SignedBlockHeader { header, signatures },
+            },
+            transaction,
+        };
+
+        assert_eq!(proof.session_index(), 5);
+        assert!(proof.verify(3, &public_keys));
+
+        // the output belongs to another module instance
+        assert!(!proof.verify(4, &public_keys));
+
+        let mut other_hash = proof.clone();
+        other_hash.hash = sha256::Hash::hash(b"other document");
+        assert!(!other_hash.verify(3, &public_keys));
+
+        let mut missing_output = proof;
+        missing_output.out_point.out_idx = 1;
+        assert!(!missing_output.verify(3, &public_keys));
+    }
+}
@@ -0,0 +1,50 @@
+use bitcoin_hashes::sha256;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, OutPoint};
+use serde::Serialize;
+use strum_macros::EnumIter;
+
+/// Namespaces DB keys for this module
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    Notarization = 0x01,
+    Outcome = 0x02,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// The output that committed the hash
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NotarizationKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NotarizationPrefix;
+
+impl_db_record!(
+    key = NotarizationKey,
+    value = OutPoint,
+    db_prefix = DbKeyPrefix::Notarization,
+);
+impl_db_lookup!(key = NotarizationKey, query_prefix = NotarizationPrefix);
+
+/// The hash committed by an output
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NotarizationOutcomeKey(pub OutPoint);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NotarizationOutcomePrefix;
+
+impl_db_record!(
+    key = NotarizationOutcomeKey,
+    value = sha256::Hash,
+    db_prefix = DbKeyPrefix::Outcome,
+);
+impl_db_lookup!(
+    key = NotarizationOutcomeKey,
+    query_prefix = NotarizationOutcomePrefix
+);
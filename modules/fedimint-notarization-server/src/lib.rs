@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use bitcoin_hashes::sha256;
+use fedimint_core::config::{
+    ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
+    TypedServerModuleConfig, TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{
+    DatabaseTransactionRef, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::endpoint_constants::NOTARIZATION_ENDPOINT;
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::{
+    api_endpoint, ApiEndpoint, CoreConsensusVersion, ExtendsCommonModuleInit, InputMeta,
+    IntoModuleError, ModuleConsensusVersion, ModuleError, PeerHandle, ServerModuleInit,
+    ServerModuleInitArgs, SupportedModuleApiVersions, TransactionItemAmount,
+};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::{push_db_pair_items, Amount, OutPoint, PeerId, ServerModule};
+use fedimint_notarization_common::config::{
+    NotarizationClientConfig, NotarizationConfig, NotarizationConfigConsensus,
+    NotarizationConfigLocal, NotarizationConfigPrivate, NotarizationGenParams,
+};
+use fedimint_notarization_common::{
+    NotarizationCommonGen, NotarizationConsensusItem, NotarizationError, NotarizationInput,
+    NotarizationModuleTypes, NotarizationOutput, NotarizationOutputOutcome, CONSENSUS_VERSION,
+};
+use futures::StreamExt;
+use strum::IntoEnumIterator;
+
+use crate::db::{
+    DbKeyPrefix, NotarizationKey, NotarizationOutcomeKey, NotarizationOutcomePrefix,
+    NotarizationPrefix,
+};
+
+mod db;
+
+/// Generates the module
+#[derive(Debug, Clone)]
+pub struct NotarizationGen;
+
+#[async_trait]
+impl ExtendsCommonModuleInit for NotarizationGen {
+    type Common = NotarizationCommonGen;
+
+    /// Dumps all database items for debugging
+    async fn dump_database(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        let mut items: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> = BTreeMap::new();
+        let filtered_prefixes = DbKeyPrefix::iter().filter(|f| {
+            prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+        });
+
+        for table in filtered_prefixes {
+            match table {
+                DbKeyPrefix::Notarization => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NotarizationPrefix,
+                        NotarizationKey,
+                        OutPoint,
+                        items,
+                        "Notarizations"
+                    );
+                }
+                DbKeyPrefix::Outcome => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NotarizationOutcomePrefix,
+                        NotarizationOutcomeKey,
+                        sha256::Hash,
+                        items,
+                        "Notarization Outcomes"
+                    );
+                }
+            }
+        }
+
+        Box::new(items.into_iter())
+    }
+}
+
+#[async_trait]
+impl ServerModuleInit for NotarizationGen {
+    type Params = NotarizationGenParams;
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+
+    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        &[CONSENSUS_VERSION]
+    }
+
+    fn supported_api_versions(&self) -> SupportedModuleApiVersions {
+        SupportedModuleApiVersions::from_raw(u32::MAX, 0, &[(0, 0)])
+    }
+
+    async fn init(&self, args: &ServerModuleInitArgs<Self>) -> anyhow::Result<DynServerModule> {
+        Ok(Notarization::new(args.cfg().to_typed()?).into())
+    }
+
+    fn trusted_dealer_gen(
+        &self,
+        peers: &[PeerId],
+        params: &ConfigGenModuleParams,
+    ) -> BTreeMap<PeerId, ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        peers
+            .iter()
+            .map(|&peer| {
+                let config = NotarizationConfig {
+                    local: NotarizationConfigLocal,
+                    private: NotarizationConfigPrivate,
+                    consensus: NotarizationConfigConsensus {
+                        fee: params.consensus.fee,
+                    },
+                };
+                (peer, config.to_erased())
+            })
+            .collect()
+    }
+
+    async fn distributed_gen(
+        &self,
+        _peers: &PeerHandle,
+        params: &ConfigGenModuleParams,
+    ) -> DkgResult<ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        Ok(NotarizationConfig {
+            local: NotarizationConfigLocal,
+            private: NotarizationConfigPrivate,
+            consensus: NotarizationConfigConsensus {
+                fee: params.consensus.fee,
+            },
+        }
+        .to_erased())
+    }
+
+    fn get_client_config(
+        &self,
+        config: &ServerModuleConsensusConfig,
+    ) -> anyhow::Result<NotarizationClientConfig> {
+        let config = NotarizationConfigConsensus::from_erased(config)?;
+        Ok(NotarizationClientConfig { fee: config.fee })
+    }
+
+    fn validate_config(
+        &self,
+        _identity: &PeerId,
+        config: ServerModuleConfig,
+    ) -> anyhow::Result<()> {
+        config.to_typed::<NotarizationConfig>()?;
+        Ok(())
+    }
+}
+
+/// Notarization module
+#[derive(Debug)]
+pub struct Notarization {
+    pub cfg: NotarizationConfig,
+}
+
+#[async_trait]
+impl ServerModule for Notarization {
+    type Common = NotarizationModuleTypes;
+    type Gen = NotarizationGen;
+
+    async fn consensus_proposal(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> Vec<NotarizationConsensusItem> {
+        vec![]
+    }
+
+    async fn process_consensus_item<'a, 'b>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'b>,
+        consensus_item: NotarizationConsensusItem,
+        _peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        match consensus_item {}
+    }
+
+    async fn process_input<'a, 'b, 'c>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'c>,
+        input: &'b NotarizationInput,
+    ) -> Result<InputMeta, ModuleError> {
+        match *input {}
+    }
+
+    async fn process_output<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransactionRef<'b>,
+        output: &'a NotarizationOutput,
+        out_point: OutPoint,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        // Only the first commitment of a hash counts, so later ones would just
+        // waste the fee
+        if let Some(notarized) = dbtx.get_value(&NotarizationKey(output.hash)).await {
+            return Err(NotarizationError::AlreadyNotarized(output.hash, notarized))
+                .into_module_error_other();
+        }
+
+        dbtx.insert_new_entry(&NotarizationKey(output.hash), &out_point)
+            .await;
+        dbtx.insert_new_entry(&NotarizationOutcomeKey(out_point), &output.hash)
+            .await;
+
+        Ok(TransactionItemAmount {
+            amount: Amount::ZERO,
+            fee: self.cfg.consensus.fee,
+        })
+    }
+
+    async fn output_status(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        out_point: OutPoint,
+    ) -> Option<NotarizationOutputOutcome> {
+        dbtx.get_value(&NotarizationOutcomeKey(out_point))
+            .await
+            .map(NotarizationOutputOutcome)
+    }
+
+    async fn audit(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _audit: &mut Audit,
+        _module_instance_id: ModuleInstanceId,
+    ) {
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![api_endpoint! {
+            // Returns the output that committed the hash
+            NOTARIZATION_ENDPOINT,
+            async |_module: &Notarization, context, hash: sha256::Hash| -> Option<OutPoint> {
+                let mut dbtx = context.dbtx();
+                Ok(dbtx.get_value(&NotarizationKey(hash)).await)
+            }
+        }]
+    }
+}
+
+impl Notarization {
+    /// Create new module instance
+    pub fn new(cfg: NotarizationConfig) -> Notarization {
+        Notarization { cfg }
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
+use fedimint_core::endpoint_constants::{
+    ORACLE_RATES_ENDPOINT, ORACLE_RATE_ENDPOINT, ORACLE_SUBMISSIONS_ENDPOINT,
+};
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_oracle_common::{OracleRate, OracleSubmissions};
+
+#[apply(async_trait_maybe_send!)]
+pub trait OracleFederationApi {
+    async fn oracle_rate(&self, currency: String) -> FederationResult<Option<OracleRate>>;
+
+    async fn oracle_rates(&self) -> FederationResult<BTreeMap<String, OracleRate>>;
+
+    async fn oracle_submissions(&self, currency: String) -> FederationResult<OracleSubmissions>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> OracleFederationApi for T
+where
+    T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn oracle_rate(&self, currency: String) -> FederationResult<Option<OracleRate>> {
+        self.request_current_consensus(
+            ORACLE_RATE_ENDPOINT.to_string(),
+            ApiRequestErased::new(currency),
+        )
+        .await
+    }
+
+    async fn oracle_rates(&self) -> FederationResult<BTreeMap<String, OracleRate>> {
+        self.request_current_consensus(
+            ORACLE_RATES_ENDPOINT.to_string(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn oracle_submissions(&self, currency: String) -> FederationResult<OracleSubmissions> {
+        self.request_current_consensus(
+            ORACLE_SUBMISSIONS_ENDPOINT.to_string(),
+            ApiRequestErased::new(currency),
+        )
+        .await
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context as _};
+use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
+use fedimint_client::module::ClientModule;
+use fedimint_client::sm::Context;
+use fedimint_client::ClientArc;
+use fedimint_core::db::DatabaseTransactionRef;
+use fedimint_core::module::{
+    ApiVersion, ExtendsCommonModuleInit, ModuleCommon, MultiApiVersion, TransactionItemAmount,
+};
+use fedimint_core::{apply, async_trait_maybe_send};
+pub use fedimint_oracle_common as common;
+use fedimint_oracle_common::config::OracleClientConfig;
+use fedimint_oracle_common::{
+    OracleCommonGen, OracleModuleTypes, OracleRate, OracleSubmissions, KIND,
+};
+use states::OracleStateMachine;
+
+use crate::api::OracleFederationApi;
+
+pub mod api;
+pub mod states;
+
+/// Exposed API calls for client apps
+#[apply(async_trait_maybe_send!)]
+pub trait OracleClientExt {
+    /// Returns the rate the guardians agreed on for the currency, fails if
+    /// there is none or it is stale
+    async fn oracle_rate(&self, currency: &str) -> anyhow::Result<OracleRate>;
+
+    /// Returns the latest rates of all currencies, including stale ones
+    async fn oracle_rates(&self) -> anyhow::Result<BTreeMap<String, OracleRate>>;
+
+    /// Returns the latest submission of every guardian for the currency
+    async fn oracle_submissions(&self, currency: &str) -> anyhow::Result<OracleSubmissions>;
+
+    /// Returns the currencies tracked by the oracle
+    fn oracle_currencies(&self) -> BTreeSet<String>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl OracleClientExt for ClientArc {
+    async fn oracle_rate(&self, currency: &str) -> anyhow::Result<OracleRate> {
+        let (oracle, instance) = self.get_first_module::<OracleClientModule>(&KIND);
+
+        let rate = instance
+            .api
+            .oracle_rate(currency.to_string())
+            .await?
+            .with_context(|| format!("The guardians did not agree on a rate for {currency} yet"))?;
+
+        let now = fedimint_core::time::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time is after the unix epoch")
+            .as_secs();
+
+        if rate.is_stale(now, oracle.cfg.max_age_secs) {
+            bail!(
+                "The rate for {currency} is stale, it was last updated at {}",
+                rate.timestamp
+            );
+        }
+
+        Ok(rate)
+    }
+
+    async fn oracle_rates(&self) -> anyhow::Result<BTreeMap<String, OracleRate>> {
+        let (_oracle, instance) = self.get_first_module::<OracleClientModule>(&KIND);
+        Ok(instance.api.oracle_rates().await?)
+    }
+
+    async fn oracle_submissions(&self, currency: &str) -> anyhow::Result<OracleSubmissions> {
+        let (_oracle, instance) = self.get_first_module::<OracleClientModule>(&KIND);
+        Ok(instance
+            .api
+            .oracle_submissions(currency.to_string())
+            .await?)
+    }
+
+    fn oracle_currencies(&self) -> BTreeSet<String> {
+        let (oracle, _instance) = self.get_first_module::<OracleClientModule>(&KIND);
+        oracle.cfg.currencies.clone()
+    }
+}
+
+#[derive(Debug)]
+pub struct OracleClientModule {
+    cfg: OracleClientConfig,
+}
+
+/// Data needed by the state machine
+#[derive(Debug, Clone)]
+pub struct OracleClientContext;
+
+impl Context for OracleClientContext {}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientModule for OracleClientModule {
+    type Common = OracleModuleTypes;
+    type ModuleStateMachineContext = OracleClientContext;
+    type States = OracleStateMachine;
+
+    fn context(&self) -> Self::ModuleStateMachineContext {
+        OracleClientContext
+    }
+
+    fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
+        match *input {}
+    }
+
+    fn output_amount(
+        &self,
+        output: &<Self::Common as ModuleCommon>::Output,
+    ) -> TransactionItemAmount {
+        match *output {}
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OracleClientGen;
+
+#[apply(async_trait_maybe_send!)]
+impl ExtendsCommonModuleInit for OracleClientGen {
+    type Common = OracleCommonGen;
+
+    async fn dump_database(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        // The client does not store anything
+        Box::new(std::iter::empty())
+    }
+}
+
+/// Generates the client module
+#[apply(async_trait_maybe_send!)]
+impl ClientModuleInit for OracleClientGen {
+    type Module = OracleClientModule;
+
+    fn supported_api_versions(&self) -> MultiApiVersion {
+        MultiApiVersion::try_from_iter([ApiVersion { major: 0, minor: 0 }])
+            .expect("no version conflicts")
+    }
+
+    async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
+        Ok(OracleClientModule {
+            cfg: args.cfg().clone(),
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeSet;
+
+use fedimint_core::core::ModuleKind;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{plugin_types_trait_impl_config, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::OracleCommonGen;
+
+/// Parameters necessary to generate this module's configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OracleGenParams {
+    pub local: OracleGenParamsLocal,
+    pub consensus: OracleGenParamsConsensus,
+}
+
+/// Local parameters for config generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OracleGenParamsLocal {
+    pub sources: Vec<PriceSource>,
+    pub fetch_interval_secs: u64,
+}
+
+impl Default for OracleGenParamsLocal {
+    fn default() -> Self {
+        let source = |url: &str, json_pointer: &str| PriceSource {
+            currency: "USD".to_string(),
+            url: url.parse().expect("Valid url"),
+            json_pointer: json_pointer.to_string(),
+        };
+
+        OracleGenParamsLocal {
+            sources: vec![
+                source(
+                    "https://api.coinbase.com/v2/prices/BTC-USD/spot",
+                    "/data/amount",
+                ),
+                source(
+                    "https://api.kraken.com/0/public/Ticker?pair=XBTUSD",
+                    "/result/XXBTZUSD/c/0",
+                ),
+                source("https://www.bitstamp.net/api/v2/ticker/btcusd/", "/last"),
+            ],
+            fetch_interval_secs: 60,
+        }
+    }
+}
+
+/// Consensus parameters for config generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OracleGenParamsConsensus {
+    pub currencies: BTreeSet<String>,
+    pub max_age_secs: u64,
+    pub max_deviation_ppm: u64,
+}
+
+impl Default for OracleGenParamsConsensus {
+    fn default() -> Self {
+        OracleGenParamsConsensus {
+            currencies: BTreeSet::from(["USD".to_string()]),
+            max_age_secs: 600,
+            max_deviation_ppm: 50_000,
+        }
+    }
+}
+
+/// Contains all the configuration for the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OracleConfig {
+    pub local: OracleConfigLocal,
+    pub private: OracleConfigPrivate,
+    pub consensus: OracleConfigConsensus,
+}
+
+/// Contains all the configuration for the client
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable, Hash)]
+pub struct OracleClientConfig {
+    pub currencies: BTreeSet<String>,
+    pub max_age_secs: u64,
+}
+
+/// Where the guardian fetches prices from, every guardian may choose its own
+/// sources
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct OracleConfigLocal {
+    pub sources: Vec<PriceSource>,
+    pub fetch_interval_secs: u64,
+}
+
+/// Will be the same for every federation member
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct OracleConfigConsensus {
+    /// Guardians submitting prices
+    pub peers: BTreeSet<PeerId>,
+    pub currencies: BTreeSet<String>,
+    /// Submissions and rates older than this are stale
+    pub max_age_secs: u64,
+    /// Submissions deviating further from the median price are outliers
+    pub max_deviation_ppm: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OracleConfigPrivate;
+
+/// An HTTP endpoint returning a JSON document that contains the price of one
+/// bitcoin in `currency`, as a number or a string
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PriceSource {
+    pub currency: String,
+    pub url: SafeUrl,
+    /// Locates the price in the document, see RFC 6901
+    pub json_pointer: String,
+}
+
+// Wire together the configs for this module
+plugin_types_trait_impl_config!(
+    OracleCommonGen,
+    OracleGenParams,
+    OracleGenParamsLocal,
+    OracleGenParamsConsensus,
+    OracleConfig,
+    OracleConfigLocal,
+    OracleConfigPrivate,
+    OracleConfigConsensus,
+    OracleClientConfig
+);
@@ -0,0 +1,315 @@
+//! A price oracle agreeing on the price of bitcoin in fiat currencies.
+//!
+//! Every guardian fetches the price from the sources in its local config and
+//! submits the median of them as a consensus item. The agreed rate is the
+//! median of the latest submissions of the guardians, see [`aggregate`], which
+//! serves as a reference for modules and clients that need to convert between
+//! bitcoin and fiat. The module does not support transactions.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use config::OracleClientConfig;
+use fedimint_core::core::{Decoder, ModuleKind};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
+use fedimint_core::{plugin_types_trait_impl_common, PeerId};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// The client and server configuration
+pub mod config;
+
+/// Unique name for this module
+pub const KIND: ModuleKind = ModuleKind::from_static_str("oracle");
+
+/// Modules are non-compatible with older versions
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+
+/// Non-transaction items that will be submitted to consensus
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub enum OracleConsensusItem {
+    /// The price a guardian observed
+    Price(PriceSubmission),
+}
+
+/// The price of one bitcoin a guardian observed at `timestamp`
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PriceSubmission {
+    pub currency: String,
+    /// In hundredths of the currency, e.g. cents
+    pub price: u64,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// The latest submission of every guardian for a currency
+pub type OracleSubmissions = BTreeMap<PeerId, PriceSubmission>;
+
+/// The rate the guardians agreed on
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct OracleRate {
+    /// Price of one bitcoin in hundredths of the currency
+    pub price: u64,
+    /// Median of the timestamps of the submissions, in seconds since the unix
+    /// epoch
+    pub timestamp: u64,
+    /// Guardians whose submissions the rate was computed from
+    pub peers: Vec<PeerId>,
+}
+
+impl OracleRate {
+    /// Whether the rate is older than `max_age_secs` at `now`, in seconds
+    /// since the unix epoch
+    pub fn is_stale(&self, now: u64, max_age_secs: u64) -> bool {
+        self.timestamp.saturating_add(max_age_secs) < now
+    }
+}
+
+/// The outcome of the last fetches of a price source of a guardian, surfaced
+/// on the status endpoint of the guardian
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceSourceHealth {
+    pub currency: String,
+    /// The price returned by the last successful fetch
+    pub price: Option<u64>,
+    /// Seconds since the unix epoch of the last successful fetch
+    pub last_success: Option<u64>,
+    /// Why the last fetch failed, `None` if it succeeded
+    pub error: Option<String>,
+}
+
+/// Computes the rate from the latest submissions of the guardians, `None` if
+/// fewer than `threshold` guardians submitted a fresh price that agrees with
+/// the others.
+///
+/// The submissions are judged by the median of their timestamps, which the
+/// guardians agree on without consulting their clocks and which faulty
+/// guardians can not move past the timestamps of the honest ones. Submissions
+/// older than `max_age_secs` are discarded, as are outliers deviating from the
+/// median price by more than `max_deviation_ppm` parts per million.
+pub fn aggregate(
+    submissions: &OracleSubmissions,
+    threshold: usize,
+    max_age_secs: u64,
+    max_deviation_ppm: u64,
+) -> Option<OracleRate> {
+    if submissions.len() < threshold {
+        return None;
+    }
+
+    let timestamp = median(submissions.values().map(|s| s.timestamp).collect())?;
+
+    let fresh = submissions
+        .iter()
+        .filter(|(_, s)| timestamp <= s.timestamp.saturating_add(max_age_secs))
+        .collect::<Vec<_>>();
+
+    let median_price = median(fresh.iter().map(|(_, s)| s.price).collect())?;
+    let max_deviation = u128::from(median_price) * u128::from(max_deviation_ppm) / 1_000_000;
+
+    let (peers, prices): (Vec<PeerId>, Vec<u64>) = fresh
+        .into_iter()
+        .filter(|(_, s)| u128::from(s.price.abs_diff(median_price)) <= max_deviation)
+        .map(|(peer, s)| (*peer, s.price))
+        .unzip();
+
+    if peers.len() < threshold {
+        return None;
+    }
+
+    Some(OracleRate {
+        price: median(prices)?,
+        timestamp,
+        peers,
+    })
+}
+
+/// The median of the values, the mean of the two middle values for an even
+/// number of values
+pub fn median(mut values: Vec<u64>) -> Option<u64> {
+    values.sort_unstable();
+
+    let middle = values.len() / 2;
+
+    match values.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(values[middle]),
+        _ => Some(((u128::from(values[middle - 1]) + u128::from(values[middle])) / 2) as u64),
+    }
+}
+
+/// The module has no inputs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum OracleInput {}
+
+/// The module has no outputs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum OracleOutput {}
+
+/// The module has no outputs, so there are no outcomes either
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum OracleOutputOutcome {}
+
+/// Reasons for rejecting a submission
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
+pub enum OracleError {
+    #[error("Currency {0} is not tracked by the oracle")]
+    UnknownCurrency(String),
+    #[error("Prices must not be zero")]
+    ZeroPrice,
+    #[error("Submission is not newer than the last one of the guardian")]
+    OutdatedSubmission,
+}
+
+/// Contains the types defined above
+pub struct OracleModuleTypes;
+
+// Wire together the types for this module
+plugin_types_trait_impl_common!(
+    OracleModuleTypes,
+    OracleClientConfig,
+    OracleInput,
+    OracleOutput,
+    OracleOutputOutcome,
+    OracleConsensusItem
+);
+
+#[derive(Debug)]
+pub struct OracleCommonGen;
+
+impl CommonModuleInit for OracleCommonGen {
+    const CONSENSUS_VERSION: ModuleConsensusVersion = CONSENSUS_VERSION;
+    const KIND: ModuleKind = KIND;
+
+    type ClientConfig = OracleClientConfig;
+
+    fn decoder() -> Decoder {
+        OracleModuleTypes::decoder_builder().build()
+    }
+}
+
+impl fmt::Display for OracleClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OracleClientConfig")
+    }
+}
+
+impl fmt::Display for OracleInput {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for OracleOutput {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for OracleOutputOutcome {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for OracleConsensusItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OracleConsensusItem::Price(submission) => write!(
+                f,
+                "Price of {}.{:02} {}",
+                submission.price / 100,
+                submission.price % 100,
+                submission.currency
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::PeerId;
+
+    use super::{aggregate, PriceSubmission};
+
+    fn submissions(prices: &[(u64, u64)]) -> BTreeMap<PeerId, PriceSubmission> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(peer, (price, timestamp))| {
+                let submission = PriceSubmission {
+                    currency: "USD".to_string(),
+                    price: *price,
+                    timestamp: *timestamp,
+                };
+                (PeerId::from(peer as u16), submission)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rejects_outliers() {
+        let rate = aggregate(
+            &submissions(&[(100, 10), (102, 10), (104, 10), (1_000, 10)]),
+            3,
+            60,
+            50_000,
+        )
+        .expect("Three guardians agree");
+
+        assert_eq!(rate.price, 102);
+        assert_eq!(rate.timestamp, 10);
+        assert_eq!(rate.peers, vec![0.into(), 1.into(), 2.into()]);
+
+        // with two outliers the remaining guardians are below the threshold
+        assert_eq!(
+            aggregate(
+                &submissions(&[(100, 10), (102, 10), (500, 10), (1_000, 10)]),
+                3,
+                60,
+                50_000
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn discards_stale_submissions() {
+        let rate = aggregate(
+            &submissions(&[(100, 1_000), (200, 1_000), (300, 1_000), (10, 10)]),
+            3,
+            60,
+            1_000_000,
+        )
+        .expect("Three guardians submitted fresh prices");
+
+        assert_eq!(rate.price, 200);
+        assert_eq!(rate.peers, vec![0.into(), 1.into(), 2.into()]);
+
+        // a single guardian can not make the others stale
+        let rate = aggregate(
+            &submissions(&[(100, 1_000), (200, 1_000), (300, 1_000), (10, 100_000)]),
+            3,
+            60,
+            1_000_000,
+        )
+        .expect("The median timestamp is the one of the honest guardians");
+
+        assert_eq!(rate.timestamp, 1_000);
+        assert!(rate.is_stale(1_061, 60));
+        assert!(!rate.is_stale(1_060, 60));
+
+        assert_eq!(
+            aggregate(
+                &submissions(&[(100, 1_000), (200, 1_000)]),
+                3,
+                60,
+                1_000_000
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, PeerId};
+use fedimint_oracle_common::{OracleRate, PriceSubmission};
+use serde::Serialize;
+use strum_macros::EnumIter;
+
+/// Namespaces DB keys for this module
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    Submission = 0x01,
+    Rate = 0x02,
+    LocalPrice = 0x03,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// The latest submission of every guardian by currency
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct OracleSubmissionKey(pub String, pub PeerId);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct OracleSubmissionCurrencyPrefix(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OracleSubmissionPrefix;
+
+impl_db_record!(
+    key = OracleSubmissionKey,
+    value = PriceSubmission,
+    db_prefix = DbKeyPrefix::Submission,
+);
+impl_db_lookup!(
+    key = OracleSubmissionKey,
+    query_prefix = OracleSubmissionCurrencyPrefix,
+    query_prefix = OracleSubmissionPrefix
+);
+
+/// The rates the guardians agreed on by currency
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct OracleRateKey(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OracleRatePrefix;
+
+impl_db_record!(
+    key = OracleRateKey,
+    value = OracleRate,
+    db_prefix = DbKeyPrefix::Rate,
+    // Allows clients to wait for a rate to be updated
+    notify_on_modify = true
+);
+impl_db_lookup!(key = OracleRateKey, query_prefix = OracleRatePrefix);
+
+/// The median of our sources by currency, submitted to consensus once it is
+/// newer than our last submission
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct OracleLocalPriceKey(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OracleLocalPricePrefix;
+
+impl_db_record!(
+    key = OracleLocalPriceKey,
+    value = PriceSubmission,
+    db_prefix = DbKeyPrefix::LocalPrice,
+);
+impl_db_lookup!(
+    key = OracleLocalPriceKey,
+    query_prefix = OracleLocalPricePrefix
+);
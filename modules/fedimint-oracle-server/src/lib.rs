@@ -0,0 +1,566 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{bail, format_err, Context};
+use async_trait::async_trait;
+use fedimint_core::config::{
+    ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
+    TypedServerModuleConfig, TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{
+    Database, DatabaseTransactionRef, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::endpoint_constants::{
+    ORACLE_RATES_ENDPOINT, ORACLE_RATE_ENDPOINT, ORACLE_SUBMISSIONS_ENDPOINT,
+};
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::{
+    api_endpoint, ApiEndpoint, CoreConsensusVersion, ExtendsCommonModuleInit, InputMeta,
+    ModuleConsensusVersion, ModuleError, ModuleHealth, PeerHandle, ServerModuleInit,
+    ServerModuleInitArgs, SupportedModuleApiVersions, TransactionItemAmount,
+};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::task::{sleep, timeout, TaskGroup, TaskHandle};
+use fedimint_core::{push_db_pair_items, NumPeers, OutPoint, PeerId, ServerModule};
+use fedimint_oracle_common::config::{
+    OracleClientConfig, OracleConfig, OracleConfigConsensus, OracleConfigLocal,
+    OracleConfigPrivate, OracleGenParams, PriceSource,
+};
+use fedimint_oracle_common::{
+    aggregate, median, OracleCommonGen, OracleConsensusItem, OracleError, OracleInput,
+    OracleModuleTypes, OracleOutput, OracleOutputOutcome, OracleRate, OracleSubmissions,
+    PriceSourceHealth, PriceSubmission, CONSENSUS_VERSION,
+};
+use futures::future::join_all;
+use futures::StreamExt;
+use strum::IntoEnumIterator;
+use tracing::{debug, warn};
+
+use crate::db::{
+    DbKeyPrefix, OracleLocalPriceKey, OracleLocalPricePrefix, OracleRateKey, OracleRatePrefix,
+    OracleSubmissionCurrencyPrefix, OracleSubmissionKey, OracleSubmissionPrefix,
+};
+
+mod db;
+
+/// How long we wait for a price source to respond
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Generates the module
+#[derive(Debug, Clone)]
+pub struct OracleGen;
+
+#[async_trait]
+impl ExtendsCommonModuleInit for OracleGen {
+    type Common = OracleCommonGen;
+
+    /// Dumps all database items for debugging
+    async fn dump_database(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        let mut items: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> = BTreeMap::new();
+        let filtered_prefixes = DbKeyPrefix::iter().filter(|f| {
+            prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+        });
+
+        for table in filtered_prefixes {
+            match table {
+                DbKeyPrefix::Submission => {
+                    push_db_pair_items!(
+                        dbtx,
+                        OracleSubmissionPrefix,
+                        OracleSubmissionKey,
+                        PriceSubmission,
+                        items,
+                        "Oracle Submissions"
+                    );
+                }
+                DbKeyPrefix::Rate => {
+                    push_db_pair_items!(
+                        dbtx,
+                        OracleRatePrefix,
+                        OracleRateKey,
+                        OracleRate,
+                        items,
+                        "Oracle Rates"
+                    );
+                }
+                DbKeyPrefix::LocalPrice => {
+                    push_db_pair_items!(
+                        dbtx,
+                        OracleLocalPricePrefix,
+                        OracleLocalPriceKey,
+                        PriceSubmission,
+                        items,
+                        "Oracle Local Prices"
+                    );
+                }
+            }
+        }
+
+        Box::new(items.into_iter())
+    }
+}
+
+#[async_trait]
+impl ServerModuleInit for OracleGen {
+    type Params = OracleGenParams;
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+
+    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        &[CONSENSUS_VERSION]
+    }
+
+    fn supported_api_versions(&self) -> SupportedModuleApiVersions {
+        SupportedModuleApiVersions::from_raw(u32::MAX, 0, &[(0, 0)])
+    }
+
+    async fn init(&self, args: &ServerModuleInitArgs<Self>) -> anyhow::Result<DynServerModule> {
+        Ok(Oracle::new(
+            args.cfg().to_typed()?,
+            args.db().clone(),
+            &mut args.task_group().clone(),
+            args.our_peer_id(),
+        )
+        .await
+        .into())
+    }
+
+    fn trusted_dealer_gen(
+        &self,
+        peers: &[PeerId],
+        params: &ConfigGenModuleParams,
+    ) -> BTreeMap<PeerId, ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        peers
+            .iter()
+            .map(|&peer| {
+                let config = OracleConfig {
+                    local: OracleConfigLocal {
+                        sources: params.local.sources.clone(),
+                        fetch_interval_secs: params.local.fetch_interval_secs,
+                    },
+                    private: OracleConfigPrivate,
+                    consensus: OracleConfigConsensus {
+                        peers: peers.iter().copied().collect(),
+                        currencies: params.consensus.currencies.clone(),
+                        max_age_secs: params.consensus.max_age_secs,
+                        max_deviation_ppm: params.consensus.max_deviation_ppm,
+                    },
+                };
+                (peer, config.to_erased())
+            })
+            .collect()
+    }
+
+    async fn distributed_gen(
+        &self,
+        peers: &PeerHandle,
+        params: &ConfigGenModuleParams,
+    ) -> DkgResult<ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        Ok(OracleConfig {
+            local: OracleConfigLocal {
+                sources: params.local.sources,
+                fetch_interval_secs: params.local.fetch_interval_secs,
+            },
+            private: OracleConfigPrivate,
+            consensus: OracleConfigConsensus {
+                peers: peers.peers.iter().copied().collect(),
+                currencies: params.consensus.currencies,
+                max_age_secs: params.consensus.max_age_secs,
+                max_deviation_ppm: params.consensus.max_deviation_ppm,
+            },
+        }
+        .to_erased())
+    }
+
+    fn get_client_config(
+        &self,
+        config: &ServerModuleConsensusConfig,
+    ) -> anyhow::Result<OracleClientConfig> {
+        let config = OracleConfigConsensus::from_erased(config)?;
+        Ok(OracleClientConfig {
+            currencies: config.currencies,
+            max_age_secs: config.max_age_secs,
+        })
+    }
+
+    fn validate_config(&self, identity: &PeerId, config: ServerModuleConfig) -> anyhow::Result<()> {
+        let config = config.to_typed::<OracleConfig>()?;
+
+        if !config.consensus.peers.contains(identity) {
+            bail!("We are not allowed to submit prices");
+        }
+
+        for source in &config.local.sources {
+            if !config.consensus.currencies.contains(&source.currency) {
+                bail!(
+                    "Price source {} is for {}, which is not tracked",
+                    source.url,
+                    source.currency
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Price oracle module
+#[derive(Debug)]
+pub struct Oracle {
+    pub cfg: OracleConfig,
+    pub our_peer_id: PeerId,
+    /// Outcome of the last fetches by the url of the source
+    source_health: Arc<Mutex<BTreeMap<String, PriceSourceHealth>>>,
+}
+
+#[async_trait]
+impl ServerModule for Oracle {
+    type Common = OracleModuleTypes;
+    type Gen = OracleGen;
+
+    async fn consensus_proposal(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> Vec<OracleConsensusItem> {
+        let local_prices: Vec<_> = dbtx
+            .find_by_prefix(&OracleLocalPricePrefix)
+            .await
+            .collect()
+            .await;
+
+        let mut items = vec![];
+
+        for (OracleLocalPriceKey(currency), local_price) in local_prices {
+            // Stale prices would be discarded anyway
+            if local_price.timestamp + self.cfg.consensus.max_age_secs < unix_now() {
+                continue;
+            }
+
+            let submission = dbtx
+                .get_value(&OracleSubmissionKey(currency, self.our_peer_id))
+                .await;
+
+            if submission.map_or(true, |s| s.timestamp < local_price.timestamp) {
+                items.push(OracleConsensusItem::Price(local_price));
+            }
+        }
+
+        items
+    }
+
+    async fn process_consensus_item<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransactionRef<'b>,
+        consensus_item: OracleConsensusItem,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        let OracleConsensusItem::Price(submission) = consensus_item;
+        let currency = submission.currency.clone();
+
+        if !self.cfg.consensus.currencies.contains(&currency) {
+            return Err(OracleError::UnknownCurrency(currency).into());
+        }
+
+        if submission.price == 0 {
+            return Err(OracleError::ZeroPrice.into());
+        }
+
+        if let Some(previous) = dbtx
+            .get_value(&OracleSubmissionKey(currency.clone(), peer_id))
+            .await
+        {
+            if submission.timestamp <= previous.timestamp {
+                return Err(OracleError::OutdatedSubmission.into());
+            }
+        }
+
+        dbtx.insert_entry(&OracleSubmissionKey(currency.clone(), peer_id), &submission)
+            .await;
+
+        let submissions = dbtx
+            .find_by_prefix(&OracleSubmissionCurrencyPrefix(currency.clone()))
+            .await
+            .map(|(OracleSubmissionKey(_, peer_id), submission)| (peer_id, submission))
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        // Without agreement the previous rate is kept, clients notice it is
+        // getting stale
+        if let Some(rate) = aggregate(
+            &submissions,
+            self.cfg.consensus.peers.threshold(),
+            self.cfg.consensus.max_age_secs,
+            self.cfg.consensus.max_deviation_ppm,
+        ) {
+            debug!(%currency, price = rate.price, "Guardians agreed on a new rate");
+
+            dbtx.insert_entry(&OracleRateKey(currency), &rate).await;
+        }
+
+        Ok(())
+    }
+
+    async fn process_input<'a, 'b, 'c>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'c>,
+        input: &'b OracleInput,
+    ) -> Result<InputMeta, ModuleError> {
+        match *input {}
+    }
+
+    async fn process_output<'a, 'b>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'b>,
+        output: &'a OracleOutput,
+        _out_point: OutPoint,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        match *output {}
+    }
+
+    async fn health(&self) -> Option<ModuleHealth> {
+        let sources = self.source_health.lock().expect("Failed to lock").clone();
+        let now = unix_now();
+
+        // Every currency needs a fresh price from at least one of our sources
+        let healthy = self.cfg.consensus.currencies.iter().all(|currency| {
+            sources.values().any(|source| {
+                source.currency == *currency
+                    && source.error.is_none()
+                    && source.last_success.map_or(false, |last_success| {
+                        now <= last_success + self.cfg.consensus.max_age_secs
+                    })
+            })
+        });
+
+        Some(ModuleHealth {
+            healthy,
+            details: serde_json::to_value(&sources).expect("Can be serialized"),
+        })
+    }
+
+    async fn output_status(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _out_point: OutPoint,
+    ) -> Option<OracleOutputOutcome> {
+        None
+    }
+
+    async fn audit(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _audit: &mut Audit,
+        _module_instance_id: ModuleInstanceId,
+    ) {
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![
+            api_endpoint! {
+                ORACLE_RATE_ENDPOINT,
+                async |_module: &Oracle, context, currency: String| -> Option<OracleRate> {
+                    let mut dbtx = context.dbtx();
+                    Ok(dbtx.get_value(&OracleRateKey(currency)).await)
+                }
+            },
+            api_endpoint! {
+                ORACLE_RATES_ENDPOINT,
+                async |_module: &Oracle, context, _params: ()| -> BTreeMap<String, OracleRate> {
+                    let mut dbtx = context.dbtx();
+                    Ok(dbtx
+                        .find_by_prefix(&OracleRatePrefix)
+                        .await
+                        .map(|(OracleRateKey(currency), rate)| (currency, rate))
+                        .collect()
+                        .await)
+                }
+            },
+            api_endpoint! {
+                // The latest submission of every guardian, so clients can see
+                // which guardians the rate is based on
+                ORACLE_SUBMISSIONS_ENDPOINT,
+                async |_module: &Oracle, context, currency: String| -> OracleSubmissions {
+                    let mut dbtx = context.dbtx();
+                    Ok(dbtx
+                        .find_by_prefix(&OracleSubmissionCurrencyPrefix(currency))
+                        .await
+                        .map(|(OracleSubmissionKey(_, peer_id), submission)| (peer_id, submission))
+                        .collect()
+                        .await)
+                }
+            },
+        ]
+    }
+}
+
+impl Oracle {
+    /// Create new module instance and spawn the task fetching the prices
+    pub async fn new(
+        cfg: OracleConfig,
+        db: Database,
+        task_group: &mut TaskGroup,
+        our_peer_id: PeerId,
+    ) -> Oracle {
+        let source_health = Arc::new(Mutex::new(BTreeMap::new()));
+
+        let fetcher_cfg = cfg.clone();
+        let fetcher_health = source_health.clone();
+        task_group
+            .spawn("oracle price fetcher", move |handle| async move {
+                run_price_fetcher(fetcher_cfg, db, fetcher_health, &handle).await;
+            })
+            .await;
+
+        Oracle {
+            cfg,
+            our_peer_id,
+            source_health,
+        }
+    }
+}
+
+/// Seconds since the unix epoch
+fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time is after the unix epoch")
+        .as_secs()
+}
+
+/// Fetches the prices of all sources every interval and stores the median per
+/// currency for our next proposal
+async fn run_price_fetcher(
+    cfg: OracleConfig,
+    db: Database,
+    health: Arc<Mutex<BTreeMap<String, PriceSourceHealth>>>,
+    tg_handle: &TaskHandle,
+) {
+    let http = reqwest::Client::new();
+
+    while !tg_handle.is_shutting_down() {
+        let results = join_all(
+            cfg.local
+                .sources
+                .iter()
+                .map(|source| fetch_price(&http, source)),
+        )
+        .await;
+
+        let timestamp = unix_now();
+        let mut prices: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+
+        {
+            let mut health = health.lock().expect("Failed to lock");
+
+            for (source, result) in cfg.local.sources.iter().zip(results) {
+                let source_health =
+                    health
+                        .entry(source.url.to_string())
+                        .or_insert_with(|| PriceSourceHealth {
+                            currency: source.currency.clone(),
+                            ..Default::default()
+                        });
+
+                match result {
+                    Ok(price) => {
+                        prices.entry(&source.currency).or_default().push(price);
+                        source_health.price = Some(price);
+                        source_health.last_success = Some(timestamp);
+                        source_health.error = None;
+                    }
+                    Err(error) => {
+                        warn!(url = %source.url, %error, "Fetching the price failed");
+                        source_health.error = Some(format!("{error:#}"));
+                    }
+                }
+            }
+        }
+
+        let mut dbtx = db.begin_transaction().await;
+
+        for (currency, prices) in prices {
+            let submission = PriceSubmission {
+                currency: currency.to_string(),
+                price: median(prices).expect("Every currency has a price"),
+                timestamp,
+            };
+
+            dbtx.insert_entry(&OracleLocalPriceKey(currency.to_string()), &submission)
+                .await;
+        }
+
+        dbtx.commit_tx().await;
+
+        sleep(Duration::from_secs(cfg.local.fetch_interval_secs)).await;
+    }
+}
+
+async fn fetch_price(http: &reqwest::Client, source: &PriceSource) -> anyhow::Result<u64> {
+    let document: serde_json::Value = timeout(FETCH_TIMEOUT, async {
+        http.get(source.url.clone().reap_guts())
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await
+    })
+    .await
+    .context("Price source did not respond in time")??;
+
+    parse_price(&document, &source.json_pointer)
+}
+
+/// Reads the price at the pointer, as exchanges return prices as JSON numbers
+/// or as strings, and converts it to hundredths of the currency
+fn parse_price(document: &serde_json::Value, json_pointer: &str) -> anyhow::Result<u64> {
+    let value = document
+        .pointer(json_pointer)
+        .ok_or_else(|| format_err!("No value at {json_pointer}"))?;
+
+    let price = match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(string) => string.parse::<f64>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format_err!("Value at {json_pointer} is not a price: {value}"))?;
+
+    if !price.is_finite() || price <= 0.0 {
+        bail!("Invalid price {price}");
+    }
+
+    Ok((price * 100.0).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse_price;
+
+    #[test]
+    fn parses_numbers_and_strings() {
+        let document = json!({
+            "data": { "amount": "27123.456" },
+            "result": { "XXBTZUSD": { "c": [27100.5, "1.0"] } },
+            "last": "-1",
+        });
+
+        assert_eq!(parse_price(&document, "/data/amount").unwrap(), 2_712_346);
+        assert_eq!(
+            parse_price(&document, "/result/XXBTZUSD/c/0").unwrap(),
+            2_710_050
+        );
+        assert!(parse_price(&document, "/last").is_err());
+        assert!(parse_price(&document, "/result").is_err());
+        assert!(parse_price(&document, "/missing").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use fedimint_core::util::SafeUrl;
+use fedimint_dummy_client::DummyClientGen;
+use fedimint_dummy_common::config::DummyGenParams;
+use fedimint_dummy_server::DummyGen;
+use fedimint_oracle_client::{OracleClientExt, OracleClientGen};
+use fedimint_oracle_common::config::{
+    OracleGenParams, OracleGenParamsConsensus, OracleGenParamsLocal, PriceSource,
+};
+use fedimint_oracle_server::OracleGen;
+use fedimint_testing::fixtures::Fixtures;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// The prices the source returns to the successive fetches of the guardians,
+/// the last one is an outlier
+const PRICES: [&str; 4] = ["100.00", "102.00", "104.00", "1000.00"];
+
+const MAX_AGE_SECS: u64 = 20;
+
+/// Serves a JSON document containing the next of [`PRICES`] to every request
+async fn spawn_price_source() -> anyhow::Result<SafeUrl> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = SafeUrl::parse(&format!("http://{}/price", listener.local_addr()?))?;
+    let requests = Arc::new(AtomicUsize::new(0));
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let requests = requests.clone();
+
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+
+                let price = PRICES[requests.fetch_add(1, Ordering::SeqCst) % PRICES.len()];
+                let body = format!("{{\"price\": {price}}}");
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+
+    Ok(url)
+}
+
+fn fixtures(url: SafeUrl) -> Fixtures {
+    // Every guardian fetches the price once, so each submits a different one
+    let params = OracleGenParams {
+        local: OracleGenParamsLocal {
+            sources: vec![PriceSource {
+                currency: "USD".to_string(),
+                url,
+                json_pointer: "/price".to_string(),
+            }],
+            fetch_interval_secs: 3600,
+        },
+        consensus: OracleGenParamsConsensus {
+            currencies: BTreeSet::from(["USD".to_string()]),
+            max_age_secs: MAX_AGE_SECS,
+            max_deviation_ppm: 50_000,
+        },
+    };
+
+    Fixtures::new_primary(DummyClientGen, DummyGen, DummyGenParams::default()).with_module(
+        OracleClientGen,
+        OracleGen,
+        params,
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn guardians_agree_on_median_without_outlier_until_stale() -> anyhow::Result<()> {
+    let url = spawn_price_source().await?;
+    let fed = fixtures(url).new_fed().await;
+    let client = fed.new_client().await;
+
+    let submissions = loop {
+        let submissions = client.oracle_submissions("USD").await?;
+        if submissions.len() == PRICES.len() && client.oracle_rates().await?.contains_key("USD") {
+            break submissions;
+        }
+        fedimint_core::task::sleep(Duration::from_secs(1)).await;
+    };
+
+    let prices = submissions
+        .values()
+        .map(|submission| submission.price)
+        .collect::<BTreeSet<_>>();
+    assert_eq!(prices, BTreeSet::from([10_000, 10_200, 10_400, 100_000]));
+
+    // The outlier deviates more than 5% from the median of 103.00, the rate is
+    // the median of the remaining prices
+    let outlier = submissions
+        .iter()
+        .find(|(_, submission)| submission.price == 100_000)
+        .map(|(peer, _)| *peer)
+        .expect("Outlier was submitted");
+    let rate = client.oracle_rate("USD").await?;
+    assert_eq!(rate.price, 10_200);
+    assert_eq!(rate.peers.len(), 3);
+    assert!(!rate.peers.contains(&outlier));
+
+    // Nobody fetches a new price before the rate becomes stale, which clients
+    // still see but refuse to use
+    fedimint_core::task::sleep(Duration::from_secs(MAX_AGE_SECS + 2)).await;
+    assert!(client.oracle_rate("USD").await.is_err());
+    assert_eq!(client.oracle_rates().await?["USD"], rate);
+
+    Ok(())
+}
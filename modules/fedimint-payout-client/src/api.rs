@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
+use fedimint_core::endpoint_constants::{
+    PAYOUTS_ENDPOINT, PAYOUT_SHARES_ENDPOINT, PAYOUT_VOTES_ENDPOINT, PAYOUT_VOTE_ENDPOINT,
+};
+use fedimint_core::module::{ApiAuth, ApiRequestErased};
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send, PeerId};
+use fedimint_payout_common::{ApprovedPayout, PayoutRequest, PayoutShare, PayoutVotes};
+
+#[apply(async_trait_maybe_send!)]
+pub trait PayoutFederationApi {
+    async fn payout_shares(&self) -> FederationResult<BTreeMap<PeerId, PayoutShare>>;
+
+    async fn payouts(&self) -> FederationResult<BTreeMap<u64, ApprovedPayout>>;
+
+    async fn payout_votes(&self) -> FederationResult<Vec<PayoutVotes>>;
+
+    /// Votes for a payout as a guardian, must only be sent to the guardian the
+    /// `auth` belongs to
+    async fn payout_vote(&self, request: PayoutRequest, auth: ApiAuth) -> FederationResult<()>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> PayoutFederationApi for T
+where
+    T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn payout_shares(&self) -> FederationResult<BTreeMap<PeerId, PayoutShare>> {
+        self.request_current_consensus(
+            PAYOUT_SHARES_ENDPOINT.to_string(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn payouts(&self) -> FederationResult<BTreeMap<u64, ApprovedPayout>> {
+        self.request_current_consensus(PAYOUTS_ENDPOINT.to_string(), ApiRequestErased::default())
+            .await
+    }
+
+    async fn payout_votes(&self) -> FederationResult<Vec<PayoutVotes>> {
+        self.request_current_consensus(
+            PAYOUT_VOTES_ENDPOINT.to_string(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn payout_vote(&self, request: PayoutRequest, auth: ApiAuth) -> FederationResult<()> {
+        self.request_current_consensus(
+            PAYOUT_VOTE_ENDPOINT.to_string(),
+            ApiRequestErased::new(request).with_auth(auth),
+        )
+        .await
+    }
+}
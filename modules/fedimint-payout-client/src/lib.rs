@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Context as _};
+use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
+use fedimint_client::module::ClientModule;
+use fedimint_client::sm::Context;
+use fedimint_client::transaction::{ClientInput, TransactionBuilder};
+use fedimint_client::ClientArc;
+use fedimint_core::core::{IntoDynInstance, KeyPair, OperationId};
+use fedimint_core::db::DatabaseTransactionRef;
+use fedimint_core::module::{
+    ApiVersion, ExtendsCommonModuleInit, ModuleCommon, MultiApiVersion, TransactionItemAmount,
+};
+use fedimint_core::{apply, async_trait_maybe_send, Amount, PeerId};
+pub use fedimint_payout_common as common;
+use fedimint_payout_common::config::{PayoutClientConfig, PayoutPolicy};
+use fedimint_payout_common::{
+    ApprovedPayout, PayoutCommonGen, PayoutInput, PayoutModuleTypes, PayoutShare, PayoutVotes, KIND,
+};
+use secp256k1::{Secp256k1, XOnlyPublicKey};
+use states::PayoutStateMachine;
+
+use crate::api::PayoutFederationApi;
+
+pub mod api;
+pub mod states;
+
+/// Exposed API calls for client apps
+#[apply(async_trait_maybe_send!)]
+pub trait PayoutClientExt {
+    /// Returns the share of the fee income of every guardian
+    async fn payout_shares(&self) -> anyhow::Result<BTreeMap<PeerId, PayoutShare>>;
+
+    /// Returns all payouts the guardians approved by their id
+    async fn payouts(&self) -> anyhow::Result<BTreeMap<u64, ApprovedPayout>>;
+
+    /// Returns the payout requests that did not reach the threshold of votes
+    /// yet
+    async fn payout_votes(&self) -> anyhow::Result<Vec<PayoutVotes>>;
+
+    /// The key to request payouts for, only this client can claim them
+    fn payout_key(&self) -> XOnlyPublicKey;
+
+    /// Returns how the fee income is split between the guardians
+    fn payout_policy(&self) -> PayoutPolicy;
+
+    /// Returns the smallest payout the guardians approve
+    fn min_payout(&self) -> Amount;
+
+    /// Claims the approved payout, the amount is added to the balance of the
+    /// primary module, from where it can be pegged out or spent on a
+    /// lightning payment
+    async fn claim_payout(&self, id: u64) -> anyhow::Result<(OperationId, Amount)>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl PayoutClientExt for ClientArc {
+    async fn payout_shares(&self) -> anyhow::Result<BTreeMap<PeerId, PayoutShare>> {
+        let (_payout, instance) = self.get_first_module::<PayoutClientModule>(&KIND);
+        Ok(instance.api.payout_shares().await?)
+    }
+
+    async fn payouts(&self) -> anyhow::Result<BTreeMap<u64, ApprovedPayout>> {
+        let (_payout, instance) = self.get_first_module::<PayoutClientModule>(&KIND);
+        Ok(instance.api.payouts().await?)
+    }
+
+    async fn payout_votes(&self) -> anyhow::Result<Vec<PayoutVotes>> {
+        let (_payout, instance) = self.get_first_module::<PayoutClientModule>(&KIND);
+        Ok(instance.api.payout_votes().await?)
+    }
+
+    fn payout_key(&self) -> XOnlyPublicKey {
+        let (payout, _instance) = self.get_first_module::<PayoutClientModule>(&KIND);
+        payout.key.x_only_public_key().0
+    }
+
+    fn payout_policy(&self) -> PayoutPolicy {
+        let (payout, _instance) = self.get_first_module::<PayoutClientModule>(&KIND);
+        payout.cfg.policy.clone()
+    }
+
+    fn min_payout(&self) -> Amount {
+        let (payout, _instance) = self.get_first_module::<PayoutClientModule>(&KIND);
+        payout.cfg.min_payout
+    }
+
+    async fn claim_payout(&self, id: u64) -> anyhow::Result<(OperationId, Amount)> {
+        let (payout_module, instance) = self.get_first_module::<PayoutClientModule>(&KIND);
+
+        let Some(payout) = instance.api.payouts().await?.remove(&id) else {
+            bail!("Payout {id} was not approved");
+        };
+
+        if payout.claimed {
+            bail!("Payout {id} was already claimed");
+        }
+
+        if payout.request.key != payout_module.key.x_only_public_key().0 {
+            bail!("Payout {id} was requested for another key");
+        }
+
+        let operation_id = OperationId(rand::random());
+        let input = ClientInput {
+            input: PayoutInput {
+                id,
+                amount: payout.request.amount,
+            },
+            keys: vec![payout_module.key],
+            state_machines: Arc::new(move |_, _| Vec::<PayoutStateMachine>::new()),
+        };
+
+        // The primary module creates the change for the payout
+        let tx = TransactionBuilder::new().with_input(input.into_dyn(instance.id));
+        let (_, change) = self
+            .finalize_and_submit_transaction(operation_id, KIND.as_str(), move |_, _| id, tx)
+            .await?;
+
+        let amount = self
+            .await_primary_module_outputs(operation_id, change)
+            .await
+            .context("Waiting for the change of the payout")?;
+
+        Ok((operation_id, amount))
+    }
+}
+
+#[derive(Debug)]
+pub struct PayoutClientModule {
+    cfg: PayoutClientConfig,
+    key: KeyPair,
+}
+
+/// Data needed by the state machine
+#[derive(Debug, Clone)]
+pub struct PayoutClientContext;
+
+impl Context for PayoutClientContext {}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientModule for PayoutClientModule {
+    type Common = PayoutModuleTypes;
+    type ModuleStateMachineContext = PayoutClientContext;
+    type States = PayoutStateMachine;
+
+    fn context(&self) -> Self::ModuleStateMachineContext {
+        PayoutClientContext
+    }
+
+    fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
+        TransactionItemAmount {
+            amount: input.amount,
+            fee: Amount::ZERO,
+        }
+    }
+
+    fn output_amount(
+        &self,
+        output: &<Self::Common as ModuleCommon>::Output,
+    ) -> TransactionItemAmount {
+        match *output {}
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PayoutClientGen;
+
+#[apply(async_trait_maybe_send!)]
+impl ExtendsCommonModuleInit for PayoutClientGen {
+    type Common = PayoutCommonGen;
+
+    async fn dump_database(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        // The client does not store anything
+        Box::new(std::iter::empty())
+    }
+}
+
+/// Generates the client module
+#[apply(async_trait_maybe_send!)]
+impl ClientModuleInit for PayoutClientGen {
+    type Module = PayoutClientModule;
+
+    fn supported_api_versions(&self) -> MultiApiVersion {
+        MultiApiVersion::try_from_iter([ApiVersion { major: 0, minor: 0 }])
+            .expect("no version conflicts")
+    }
+
+    async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
+        Ok(PayoutClientModule {
+            cfg: args.cfg().clone(),
+            key: args
+                .module_root_secret()
+                .clone()
+                .to_secp_key(&Secp256k1::new()),
+        })
+    }
+}
@@ -0,0 +1,36 @@
+use fedimint_client::sm::{DynState, State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::core::{IntoDynInstance, ModuleInstanceId, OperationId};
+use fedimint_core::encoding::{Decodable, Encodable};
+
+use crate::PayoutClientContext;
+
+/// Claimed payouts end up as change of the primary module, which tracks them,
+/// so the module has no states of its own
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum PayoutStateMachine {}
+
+impl State for PayoutStateMachine {
+    type ModuleContext = PayoutClientContext;
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        _global_context: &Self::GlobalContext,
+    ) -> Vec<StateTransition<Self>> {
+        match *self {}
+    }
+
+    fn operation_id(&self) -> OperationId {
+        match *self {}
+    }
+}
+
+impl IntoDynInstance for PayoutStateMachine {
+    type DynType = DynState<DynGlobalClientContext>;
+
+    fn into_dyn(self, instance_id: ModuleInstanceId) -> Self::DynType {
+        DynState::from_typed(instance_id, self)
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use fedimint_core::config::EmptyGenParams;
+use fedimint_core::core::ModuleKind;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{plugin_types_trait_impl_config, Amount, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::{PayoutCommonGen, PayoutError};
+
+/// Parameters necessary to generate this module's configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PayoutGenParams {
+    pub local: EmptyGenParams,
+    pub consensus: PayoutGenParamsConsensus,
+}
+
+/// Consensus parameters for config generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayoutGenParamsConsensus {
+    pub policy: PayoutPolicy,
+    pub min_payout: Amount,
+}
+
+impl Default for PayoutGenParamsConsensus {
+    fn default() -> Self {
+        PayoutGenParamsConsensus {
+            policy: PayoutPolicy::Equal,
+            min_payout: Amount::from_sats(1_000),
+        }
+    }
+}
+
+/// Contains all the configuration for the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayoutConfig {
+    pub local: PayoutConfigLocal,
+    pub private: PayoutConfigPrivate,
+    pub consensus: PayoutConfigConsensus,
+}
+
+/// Contains all the configuration for the client
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable, Hash)]
+pub struct PayoutClientConfig {
+    pub peers: BTreeSet<PeerId>,
+    pub policy: PayoutPolicy,
+    pub min_payout: Amount,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct PayoutConfigLocal;
+
+/// Will be the same for every federation member
+#[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
+pub struct PayoutConfigConsensus {
+    /// Guardians sharing the fee income, every one of them votes on payouts
+    pub peers: BTreeSet<PeerId>,
+    pub policy: PayoutPolicy,
+    /// Smaller payouts are rejected, so the guardians don't need to vote on
+    /// dust
+    pub min_payout: Amount,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayoutConfigPrivate;
+
+/// How the fee income is split between the guardians
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutPolicy {
+    /// Every guardian is entitled to the same share
+    Equal,
+    /// Guardians are entitled to shares proportional to their weight,
+    /// guardians without a weight to nothing
+    Weighted(BTreeMap<PeerId, u64>),
+}
+
+impl PayoutPolicy {
+    /// Checks that the policy only distributes fees to the `peers`
+    pub fn validate(&self, peers: &BTreeSet<PeerId>) -> Result<(), PayoutError> {
+        if let PayoutPolicy::Weighted(weights) = self {
+            if let Some(peer) = weights.keys().find(|peer| !peers.contains(peer)) {
+                return Err(PayoutError::UnknownPeer(*peer));
+            }
+
+            if weights.values().sum::<u64>() == 0 {
+                return Err(PayoutError::ZeroWeights);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The part of `collected` the peer is entitled to, rounded down so the
+    /// shares never exceed what was collected
+    pub fn share(&self, peers: &BTreeSet<PeerId>, peer: PeerId, collected: Amount) -> Amount {
+        let (weight, total) = match self {
+            PayoutPolicy::Equal if peers.contains(&peer) => (1, peers.len() as u64),
+            PayoutPolicy::Equal => (0, 1),
+            PayoutPolicy::Weighted(weights) => (
+                weights.get(&peer).copied().unwrap_or(0),
+                weights.values().sum::<u64>().max(1),
+            ),
+        };
+
+        let share = u128::from(collected.msats) * u128::from(weight) / u128::from(total);
+
+        Amount::from_msats(share as u64)
+    }
+}
+
+// Wire together the configs for this module
+plugin_types_trait_impl_config!(
+    PayoutCommonGen,
+    PayoutGenParams,
+    EmptyGenParams,
+    PayoutGenParamsConsensus,
+    PayoutConfig,
+    PayoutConfigLocal,
+    PayoutConfigPrivate,
+    PayoutConfigConsensus,
+    PayoutClientConfig
+);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use fedimint_core::{Amount, PeerId};
+
+    use super::PayoutPolicy;
+    use crate::PayoutError;
+
+    fn peers() -> BTreeSet<PeerId> {
+        (0..3u16).map(PeerId::from).collect()
+    }
+
+    #[test]
+    fn shares_never_exceed_collected() {
+        let collected = Amount::from_msats(1_000);
+        let weighted =
+            PayoutPolicy::Weighted(BTreeMap::from([(PeerId::from(0), 2), (PeerId::from(1), 1)]));
+
+        let equal_shares = peers()
+            .into_iter()
+            .map(|peer| PayoutPolicy::Equal.share(&peers(), peer, collected))
+            .collect::<Vec<_>>();
+        assert_eq!(equal_shares, vec![Amount::from_msats(333); 3]);
+
+        let weighted_shares = peers()
+            .into_iter()
+            .map(|peer| weighted.share(&peers(), peer, collected))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            weighted_shares,
+            vec![
+                Amount::from_msats(666),
+                Amount::from_msats(333),
+                Amount::ZERO
+            ]
+        );
+
+        assert_eq!(
+            PayoutPolicy::Equal.share(&peers(), PeerId::from(3), collected),
+            Amount::ZERO
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_weights() {
+        assert_eq!(PayoutPolicy::Equal.validate(&peers()), Ok(()));
+        assert_eq!(
+            PayoutPolicy::Weighted(BTreeMap::from([(PeerId::from(3), 1)])).validate(&peers()),
+            Err(PayoutError::UnknownPeer(PeerId::from(3)))
+        );
+        assert_eq!(
+            PayoutPolicy::Weighted(BTreeMap::from([(PeerId::from(0), 0)])).validate(&peers()),
+            Err(PayoutError::ZeroWeights)
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Distributes the fee income of a federation to its guardians.
+//!
+//! Every guardian is entitled to a share of the fees collected by the
+//! federation according to the [`config::PayoutPolicy`]. A guardian withdraws
+//! from its share with a payout request, which a threshold of guardians has to
+//! vote for. The approved payout is claimed with an input signed by the key of
+//! the request, the transaction spends it on e-cash, a peg-out or a lightning
+//! payment like any other input.
+
+use std::fmt;
+
+use config::PayoutClientConfig;
+use fedimint_core::core::{Decoder, ModuleKind};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
+use fedimint_core::{plugin_types_trait_impl_common, Amount, PeerId};
+use secp256k1::XOnlyPublicKey;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// The client and server configuration
+pub mod config;
+
+/// Unique name for this module
+pub const KIND: ModuleKind = ModuleKind::from_static_str("payout");
+
+/// Modules are non-compatible with older versions
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+
+/// Non-transaction items that will be submitted to consensus
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub enum PayoutConsensusItem {
+    /// A guardian's vote to approve a payout
+    Vote(PayoutRequest),
+}
+
+/// Withdrawal of `amount` from the share of `peer`, claimable with `key` once
+/// approved
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PayoutRequest {
+    pub peer: PeerId,
+    pub amount: Amount,
+    pub key: XOnlyPublicKey,
+}
+
+/// A payout approved by the guardians
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ApprovedPayout {
+    pub request: PayoutRequest,
+    /// Set once a transaction spent the payout
+    pub claimed: bool,
+}
+
+/// A payout request that did not reach the threshold of votes yet
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PayoutVotes {
+    pub request: PayoutRequest,
+    pub voters: Vec<PeerId>,
+}
+
+/// The share of the fee income of a guardian
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PayoutShare {
+    /// Part of the fees collected so far the guardian is entitled to
+    pub entitled: Amount,
+    /// Sum of the payouts approved for the guardian
+    pub approved: Amount,
+}
+
+impl PayoutShare {
+    /// What the guardian can still request
+    pub fn available(&self) -> Amount {
+        self.entitled.saturating_sub(self.approved)
+    }
+}
+
+/// Claims the approved payout with the given id, the amount has to match the
+/// payout
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct PayoutInput {
+    pub id: u64,
+    pub amount: Amount,
+}
+
+/// The module has no outputs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum PayoutOutput {}
+
+/// The module has no outputs, so there are no outcomes either
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum PayoutOutputOutcome {}
+
+/// Reasons for rejecting a payout request or a claim
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
+pub enum PayoutError {
+    #[error("Guardian {0} is not entitled to any fees")]
+    UnknownPeer(PeerId),
+    #[error("Payout of {0} is below the minimum payout of {1}")]
+    BelowMinimum(Amount, Amount),
+    #[error("Payout of {amount} exceeds the available share of {available}")]
+    ExceedsShare { amount: Amount, available: Amount },
+    #[error("Guardian already has the maximum of {0} pending votes")]
+    TooManyVotes(usize),
+    #[error("Payout {0} does not exist")]
+    UnknownPayout(u64),
+    #[error("Payout {0} was already claimed")]
+    AlreadyClaimed(u64),
+    #[error("Payout is {0}, but the input claims {1}")]
+    WrongAmount(Amount, Amount),
+    #[error("The weights of the guardians must not sum up to zero")]
+    ZeroWeights,
+}
+
+/// Contains the types defined above
+pub struct PayoutModuleTypes;
+
+// Wire together the types for this module
+plugin_types_trait_impl_common!(
+    PayoutModuleTypes,
+    PayoutClientConfig,
+    PayoutInput,
+    PayoutOutput,
+    PayoutOutputOutcome,
+    PayoutConsensusItem
+);
+
+#[derive(Debug)]
+pub struct PayoutCommonGen;
+
+impl CommonModuleInit for PayoutCommonGen {
+    const CONSENSUS_VERSION: ModuleConsensusVersion = CONSENSUS_VERSION;
+    const KIND: ModuleKind = KIND;
+
+    type ClientConfig = PayoutClientConfig;
+
+    fn decoder() -> Decoder {
+        PayoutModuleTypes::decoder_builder().build()
+    }
+}
+
+impl fmt::Display for PayoutClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PayoutClientConfig")
+    }
+}
+
+impl fmt::Display for PayoutInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Claim payout {} of {}", self.id, self.amount)
+    }
+}
+
+impl fmt::Display for PayoutOutput {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for PayoutOutputOutcome {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for PayoutConsensusItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayoutConsensusItem::Vote(request) => write!(
+                f,
+                "Vote to pay out {} to guardian {}",
+                request.amount, request.peer
+            ),
+        }
+    }
+}
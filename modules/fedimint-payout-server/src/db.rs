@@ -0,0 +1,82 @@
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, PeerId};
+use fedimint_payout_common::{ApprovedPayout, PayoutRequest};
+use serde::Serialize;
+use strum_macros::EnumIter;
+
+/// Namespaces DB keys for this module
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    FeesCollected = 0x01,
+    Payout = 0x02,
+    Vote = 0x03,
+    Proposal = 0x04,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Fees collected by the federation as of the end of the last session, the
+/// shares of the guardians are computed from it
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct FeesCollectedKey;
+
+impl_db_record!(
+    key = FeesCollectedKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::FeesCollected,
+);
+
+/// Payouts approved by the guardians by their id
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct PayoutKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PayoutPrefix;
+
+impl_db_record!(
+    key = PayoutKey,
+    value = ApprovedPayout,
+    db_prefix = DbKeyPrefix::Payout,
+);
+impl_db_lookup!(key = PayoutKey, query_prefix = PayoutPrefix);
+
+/// Votes of the guardians for payout requests that did not reach the threshold
+/// yet
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct PayoutVoteKey(pub PayoutRequest, pub PeerId);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct PayoutVoteRequestPrefix(pub PayoutRequest);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PayoutVotePrefix;
+
+impl_db_record!(
+    key = PayoutVoteKey,
+    value = (),
+    db_prefix = DbKeyPrefix::Vote,
+);
+impl_db_lookup!(
+    key = PayoutVoteKey,
+    query_prefix = PayoutVoteRequestPrefix,
+    query_prefix = PayoutVotePrefix
+);
+
+/// Our own votes, submitted to consensus until the payout is approved
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct PayoutProposalKey(pub PayoutRequest);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PayoutProposalPrefix;
+
+impl_db_record!(
+    key = PayoutProposalKey,
+    value = (),
+    db_prefix = DbKeyPrefix::Proposal,
+);
+impl_db_lookup!(key = PayoutProposalKey, query_prefix = PayoutProposalPrefix);
@@ -0,0 +1,526 @@
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use async_trait::async_trait;
+use fedimint_core::config::{
+    ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
+    TypedServerModuleConfig, TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{
+    DatabaseTransactionRef, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::endpoint_constants::{
+    PAYOUTS_ENDPOINT, PAYOUT_SHARES_ENDPOINT, PAYOUT_VOTES_ENDPOINT, PAYOUT_VOTE_ENDPOINT,
+};
+use fedimint_core::fee::FeeIncome;
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::{
+    api_endpoint, ApiEndpoint, ApiError, CoreConsensusVersion, ExtendsCommonModuleInit, InputMeta,
+    IntoModuleError, ModuleConsensusVersion, ModuleError, PeerHandle, ServerModuleInit,
+    ServerModuleInitArgs, SupportedModuleApiVersions, TransactionItemAmount,
+};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::{push_db_pair_items, Amount, NumPeers, OutPoint, PeerId, ServerModule};
+use fedimint_payout_common::config::{
+    PayoutClientConfig, PayoutConfig, PayoutConfigConsensus, PayoutConfigLocal,
+    PayoutConfigPrivate, PayoutGenParams,
+};
+use fedimint_payout_common::{
+    ApprovedPayout, PayoutCommonGen, PayoutConsensusItem, PayoutError, PayoutInput,
+    PayoutModuleTypes, PayoutOutput, PayoutOutputOutcome, PayoutRequest, PayoutShare, PayoutVotes,
+    CONSENSUS_VERSION,
+};
+use fedimint_server::check_auth;
+use futures::{future, StreamExt};
+use strum::IntoEnumIterator;
+use tracing::info;
+
+use crate::db::{
+    DbKeyPrefix, FeesCollectedKey, PayoutKey, PayoutPrefix, PayoutProposalKey,
+    PayoutProposalPrefix, PayoutVoteKey, PayoutVotePrefix, PayoutVoteRequestPrefix,
+};
+
+mod db;
+
+/// Votes are only removed once a payout is approved, so we bound the number of
+/// votes every guardian can have pending
+const MAX_PENDING_VOTES: usize = 16;
+
+/// Generates the module
+#[derive(Debug, Clone)]
+pub struct PayoutGen;
+
+#[async_trait]
+impl ExtendsCommonModuleInit for PayoutGen {
+    type Common = PayoutCommonGen;
+
+    /// Dumps all database items for debugging
+    async fn dump_database(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        let mut items: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> = BTreeMap::new();
+        let filtered_prefixes = DbKeyPrefix::iter().filter(|f| {
+            prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+        });
+
+        for table in filtered_prefixes {
+            match table {
+                DbKeyPrefix::FeesCollected => {
+                    if let Some(collected) = dbtx.get_value(&FeesCollectedKey).await {
+                        items.insert("Fees Collected".to_string(), Box::new(collected));
+                    }
+                }
+                DbKeyPrefix::Payout => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PayoutPrefix,
+                        PayoutKey,
+                        ApprovedPayout,
+                        items,
+                        "Payouts"
+                    );
+                }
+                DbKeyPrefix::Vote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PayoutVotePrefix,
+                        PayoutVoteKey,
+                        (),
+                        items,
+                        "Payout Votes"
+                    );
+                }
+                DbKeyPrefix::Proposal => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PayoutProposalPrefix,
+                        PayoutProposalKey,
+                        (),
+                        items,
+                        "Payout Proposals"
+                    );
+                }
+            }
+        }
+
+        Box::new(items.into_iter())
+    }
+}
+
+#[async_trait]
+impl ServerModuleInit for PayoutGen {
+    type Params = PayoutGenParams;
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+
+    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        &[CONSENSUS_VERSION]
+    }
+
+    fn supported_api_versions(&self) -> SupportedModuleApiVersions {
+        SupportedModuleApiVersions::from_raw(u32::MAX, 0, &[(0, 0)])
+    }
+
+    async fn init(&self, args: &ServerModuleInitArgs<Self>) -> anyhow::Result<DynServerModule> {
+        Ok(Payout::new(args.cfg().to_typed()?, args.our_peer_id()).into())
+    }
+
+    fn trusted_dealer_gen(
+        &self,
+        peers: &[PeerId],
+        params: &ConfigGenModuleParams,
+    ) -> BTreeMap<PeerId, ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        peers
+            .iter()
+            .map(|&peer| {
+                let config = PayoutConfig {
+                    local: PayoutConfigLocal,
+                    private: PayoutConfigPrivate,
+                    consensus: PayoutConfigConsensus {
+                        peers: peers.iter().copied().collect(),
+                        policy: params.consensus.policy.clone(),
+                        min_payout: params.consensus.min_payout,
+                    },
+                };
+                (peer, config.to_erased())
+            })
+            .collect()
+    }
+
+    async fn distributed_gen(
+        &self,
+        peers: &PeerHandle,
+        params: &ConfigGenModuleParams,
+    ) -> DkgResult<ServerModuleConfig> {
+        let params = self.parse_params(params).unwrap();
+
+        Ok(PayoutConfig {
+            local: PayoutConfigLocal,
+            private: PayoutConfigPrivate,
+            consensus: PayoutConfigConsensus {
+                peers: peers.peers.iter().copied().collect(),
+                policy: params.consensus.policy,
+                min_payout: params.consensus.min_payout,
+            },
+        }
+        .to_erased())
+    }
+
+    fn get_client_config(
+        &self,
+        config: &ServerModuleConsensusConfig,
+    ) -> anyhow::Result<PayoutClientConfig> {
+        let config = PayoutConfigConsensus::from_erased(config)?;
+        Ok(PayoutClientConfig {
+            peers: config.peers,
+            policy: config.policy,
+            min_payout: config.min_payout,
+        })
+    }
+
+    fn validate_config(&self, identity: &PeerId, config: ServerModuleConfig) -> anyhow::Result<()> {
+        let config = config.to_typed::<PayoutConfig>()?;
+
+        if !config.consensus.peers.contains(identity) {
+            bail!("We are not allowed to vote");
+        }
+
+        config.consensus.policy.validate(&config.consensus.peers)?;
+
+        Ok(())
+    }
+}
+
+/// Fee payout module
+#[derive(Debug)]
+pub struct Payout {
+    pub cfg: PayoutConfig,
+    pub our_peer_id: PeerId,
+}
+
+#[async_trait]
+impl ServerModule for Payout {
+    type Common = PayoutModuleTypes;
+    type Gen = PayoutGen;
+
+    async fn consensus_proposal(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+    ) -> Vec<PayoutConsensusItem> {
+        let proposals: Vec<_> = dbtx
+            .find_by_prefix(&PayoutProposalPrefix)
+            .await
+            .collect()
+            .await;
+
+        let mut items = vec![];
+
+        // Keep voting for our proposals until the payout is approved, which
+        // removes the proposal
+        for (PayoutProposalKey(request), ()) in proposals {
+            if dbtx
+                .get_value(&PayoutVoteKey(request.clone(), self.our_peer_id))
+                .await
+                .is_some()
+            {
+                continue;
+            }
+
+            if self
+                .validate_request(dbtx, &request, self.our_peer_id)
+                .await
+                .is_ok()
+            {
+                items.push(PayoutConsensusItem::Vote(request));
+            }
+        }
+
+        items
+    }
+
+    async fn process_consensus_item<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransactionRef<'b>,
+        consensus_item: PayoutConsensusItem,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        let PayoutConsensusItem::Vote(request) = consensus_item;
+
+        if dbtx
+            .get_value(&PayoutVoteKey(request.clone(), peer_id))
+            .await
+            .is_some()
+        {
+            bail!("Already received this vote");
+        }
+
+        self.validate_request(dbtx, &request, peer_id).await?;
+
+        dbtx.insert_entry(&PayoutVoteKey(request.clone(), peer_id), &())
+            .await;
+
+        let votes = dbtx
+            .find_by_prefix(&PayoutVoteRequestPrefix(request.clone()))
+            .await
+            .count()
+            .await;
+
+        if votes < self.cfg.consensus.peers.threshold() {
+            return Ok(());
+        }
+
+        dbtx.remove_by_prefix(&PayoutVoteRequestPrefix(request.clone()))
+            .await;
+        dbtx.remove_entry(&PayoutProposalKey(request.clone())).await;
+
+        let id = dbtx.find_by_prefix(&PayoutPrefix).await.count().await as u64;
+
+        info!(
+            id,
+            peer = %request.peer,
+            amount = %request.amount,
+            "Guardians approved a payout"
+        );
+
+        dbtx.insert_entry(
+            &PayoutKey(id),
+            &ApprovedPayout {
+                request,
+                claimed: false,
+            },
+        )
+        .await;
+
+        Ok(())
+    }
+
+    async fn process_input<'a, 'b, 'c>(
+        &'a self,
+        dbtx: &mut DatabaseTransactionRef<'c>,
+        input: &'b PayoutInput,
+    ) -> Result<InputMeta, ModuleError> {
+        let Some(mut payout) = dbtx.get_value(&PayoutKey(input.id)).await else {
+            return Err(PayoutError::UnknownPayout(input.id)).into_module_error_other();
+        };
+
+        if payout.claimed {
+            return Err(PayoutError::AlreadyClaimed(input.id)).into_module_error_other();
+        }
+
+        if payout.request.amount != input.amount {
+            return Err(PayoutError::WrongAmount(
+                payout.request.amount,
+                input.amount,
+            ))
+            .into_module_error_other();
+        }
+
+        payout.claimed = true;
+        dbtx.insert_entry(&PayoutKey(input.id), &payout).await;
+
+        Ok(InputMeta {
+            amount: TransactionItemAmount {
+                amount: input.amount,
+                fee: Amount::ZERO,
+            },
+            // Only the guardian the payout was approved for can claim it
+            pub_keys: vec![payout.request.key],
+            // The core rejects the transaction should the fee income of the
+            // federation not cover the payout
+            fee_payout: input.amount,
+        })
+    }
+
+    async fn process_output<'a, 'b>(
+        &'a self,
+        _dbtx: &mut DatabaseTransactionRef<'b>,
+        output: &'a PayoutOutput,
+        _out_point: OutPoint,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        match *output {}
+    }
+
+    async fn update_fee_income(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        fee_income: FeeIncome,
+    ) {
+        dbtx.insert_entry(&FeesCollectedKey, &fee_income.collected)
+            .await;
+    }
+
+    async fn output_status(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _out_point: OutPoint,
+    ) -> Option<PayoutOutputOutcome> {
+        None
+    }
+
+    async fn audit(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        _audit: &mut Audit,
+        _module_instance_id: ModuleInstanceId,
+    ) {
+        // Approved payouts are paid from the fee income, which the core
+        // already audits as a liability until the payouts are claimed
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![
+            api_endpoint! {
+                PAYOUT_SHARES_ENDPOINT,
+                async |module: &Payout, context, _params: ()| -> BTreeMap<PeerId, PayoutShare> {
+                    let mut dbtx = context.dbtx();
+                    let mut shares = BTreeMap::new();
+
+                    for peer in &module.cfg.consensus.peers {
+                        shares.insert(*peer, module.share(&mut dbtx, *peer).await);
+                    }
+
+                    Ok(shares)
+                }
+            },
+            api_endpoint! {
+                PAYOUTS_ENDPOINT,
+                async |_module: &Payout, context, _params: ()| -> BTreeMap<u64, ApprovedPayout> {
+                    let mut dbtx = context.dbtx();
+                    Ok(dbtx
+                        .find_by_prefix(&PayoutPrefix)
+                        .await
+                        .map(|(PayoutKey(id), payout)| (id, payout))
+                        .collect()
+                        .await)
+                }
+            },
+            api_endpoint! {
+                PAYOUT_VOTES_ENDPOINT,
+                async |_module: &Payout, context, _params: ()| -> Vec<PayoutVotes> {
+                    let mut dbtx = context.dbtx();
+                    let votes: Vec<_> = dbtx
+                        .find_by_prefix(&PayoutVotePrefix)
+                        .await
+                        .collect()
+                        .await;
+                    let mut pending: Vec<PayoutVotes> = vec![];
+
+                    // votes for the same request are adjacent as they share the prefix
+                    for (PayoutVoteKey(request, peer_id), ()) in votes {
+                        match pending.last_mut() {
+                            Some(last) if last.request == request => last.voters.push(peer_id),
+                            _ => pending.push(PayoutVotes {
+                                request,
+                                voters: vec![peer_id],
+                            }),
+                        }
+                    }
+
+                    Ok(pending)
+                }
+            },
+            api_endpoint! {
+                // Guardians vote for a payout, it is submitted to consensus by us
+                PAYOUT_VOTE_ENDPOINT,
+                async |module: &Payout, context, request: PayoutRequest| -> () {
+                    check_auth(context)?;
+
+                    let mut dbtx = context.dbtx();
+
+                    module
+                        .validate_request(&mut dbtx, &request, module.our_peer_id)
+                        .await
+                        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                    dbtx.insert_entry(&PayoutProposalKey(request), &()).await;
+                    Ok(())
+                }
+            },
+        ]
+    }
+}
+
+impl Payout {
+    /// Create new module instance
+    pub fn new(cfg: PayoutConfig, our_peer_id: PeerId) -> Payout {
+        Payout { cfg, our_peer_id }
+    }
+
+    /// The share of the peer in the fees collected up to the end of the last
+    /// session
+    async fn share(&self, dbtx: &mut DatabaseTransactionRef<'_>, peer: PeerId) -> PayoutShare {
+        let collected = dbtx
+            .get_value(&FeesCollectedKey)
+            .await
+            .unwrap_or(Amount::ZERO);
+
+        let approved = dbtx
+            .find_by_prefix(&PayoutPrefix)
+            .await
+            .filter(|(_, payout)| future::ready(payout.request.peer == peer))
+            .fold(Amount::ZERO, |sum, (_, payout)| {
+                future::ready(sum + payout.request.amount)
+            })
+            .await;
+
+        PayoutShare {
+            entitled: self
+                .cfg
+                .consensus
+                .policy
+                .share(&self.cfg.consensus.peers, peer, collected),
+            approved,
+        }
+    }
+
+    /// Checks that a vote of the voter for the request would be accepted
+    async fn validate_request(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        request: &PayoutRequest,
+        voter: PeerId,
+    ) -> Result<(), PayoutError> {
+        if !self.cfg.consensus.peers.contains(&request.peer) {
+            return Err(PayoutError::UnknownPeer(request.peer));
+        }
+
+        if request.amount < self.cfg.consensus.min_payout {
+            return Err(PayoutError::BelowMinimum(
+                request.amount,
+                self.cfg.consensus.min_payout,
+            ));
+        }
+
+        let available = self.share(dbtx, request.peer).await.available();
+
+        if request.amount > available {
+            return Err(PayoutError::ExceedsShare {
+                amount: request.amount,
+                available,
+            });
+        }
+
+        if dbtx
+            .get_value(&PayoutVoteKey(request.clone(), voter))
+            .await
+            .is_none()
+        {
+            let pending_votes = dbtx
+                .find_by_prefix(&PayoutVotePrefix)
+                .await
+                .filter(|(PayoutVoteKey(_, peer_id), ())| future::ready(*peer_id == voter))
+                .count()
+                .await;
+
+            if pending_votes >= MAX_PENDING_VOTES {
+                return Err(PayoutError::TooManyVotes(MAX_PENDING_VOTES));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use fedimint_client::ClientArc;
+use fedimint_core::api::FederationApiExt;
+use fedimint_core::endpoint_constants::FEE_INCOME_ENDPOINT;
+use fedimint_core::fee::{FeeIncome, FeeRate, FeeSchedule, ModuleFeeSchedule};
+use fedimint_core::module::{ApiAuth, ApiRequestErased};
+use fedimint_core::{sats, Amount, PeerId};
+use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
+use fedimint_dummy_common::config::DummyGenParams;
+use fedimint_dummy_server::DummyGen;
+use fedimint_payout_client::api::PayoutFederationApi;
+use fedimint_payout_client::{PayoutClientExt, PayoutClientGen, PayoutClientModule};
+use fedimint_payout_common::config::{PayoutGenParams, PayoutGenParamsConsensus, PayoutPolicy};
+use fedimint_payout_common::PayoutRequest;
+use fedimint_payout_server::PayoutGen;
+use fedimint_testing::fixtures::Fixtures;
+
+/// The dummy module is the primary module at instance 0, which charges a flat
+/// fee on its inputs
+const DUMMY_INPUT_FEE: Amount = Amount::from_sats(400);
+
+fn fixtures() -> Fixtures {
+    let fees = FeeSchedule {
+        modules: BTreeMap::from([(
+            0,
+            ModuleFeeSchedule {
+                input: FeeRate {
+                    base: DUMMY_INPUT_FEE,
+                    parts_per_million: 0,
+                },
+                output: FeeRate::ZERO,
+            },
+        )]),
+    };
+
+    Fixtures::new_primary(DummyClientGen, DummyGen, DummyGenParams::default())
+        .with_module(
+            PayoutClientGen,
+            PayoutGen,
+            PayoutGenParams {
+                consensus: PayoutGenParamsConsensus {
+                    policy: PayoutPolicy::Equal,
+                    min_payout: sats(10),
+                },
+                ..Default::default()
+            },
+        )
+        .with_fees(fees)
+}
+
+async fn fee_income(client: &ClientArc) -> anyhow::Result<FeeIncome> {
+    Ok(client
+        .api()
+        .request_current_consensus(FEE_INCOME_ENDPOINT.to_string(), ApiRequestErased::default())
+        .await?)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn guardians_vote_approve_and_claim_payout() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let client = fed.new_client().await;
+
+    // Pay the input fee of the dummy module into the fee income
+    let (_, outpoint) = client.print_money(sats(1000)).await?;
+    client.receive_money(outpoint).await?;
+    assert_eq!(client.get_balance().await, sats(600));
+
+    // The income is split equally between the four guardians once the session
+    // containing the transaction ended
+    let peer = PeerId::from(0);
+    loop {
+        let shares = client.payout_shares().await?;
+        if shares.get(&peer).map(|share| share.entitled) == Some(sats(100)) {
+            break;
+        }
+        fedimint_core::task::sleep(Duration::from_secs(1)).await;
+    }
+
+    // Every guardian shares the API password in tests, so the vote reaches a
+    // threshold
+    let request = PayoutRequest {
+        peer,
+        amount: sats(100),
+        key: client.payout_key(),
+    };
+    let (_payout, instance) =
+        client.get_first_module::<PayoutClientModule>(&fedimint_payout_common::KIND);
+    instance
+        .api
+        .payout_vote(request.clone(), ApiAuth("pass".to_string()))
+        .await?;
+
+    let id = loop {
+        let approved = client
+            .payouts()
+            .await?
+            .into_iter()
+            .find(|(_, payout)| payout.request == request);
+        if let Some((id, _)) = approved {
+            break id;
+        }
+        fedimint_core::task::sleep(Duration::from_secs(1)).await;
+    };
+
+    let (_, amount) = client.claim_payout(id).await?;
+    assert_eq!(amount, sats(100));
+    assert_eq!(client.get_balance().await, sats(700));
+
+    // The core checks the payout against the fee income of the federation
+    let income = fee_income(&client).await?;
+    assert_eq!(income.collected, DUMMY_INPUT_FEE);
+    assert_eq!(income.paid_out, sats(100));
+
+    assert!(client.payouts().await?[&id].claimed);
+    assert!(client.claim_payout(id).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn payout_above_share_is_not_approved() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let client = fed.new_client().await;
+
+    let (_, outpoint) = client.print_money(sats(1000)).await?;
+    client.receive_money(outpoint).await?;
+
+    let peer = PeerId::from(1);
+    loop {
+        let shares = client.payout_shares().await?;
+        if shares.get(&peer).map(|share| share.entitled) == Some(sats(100)) {
+            break;
+        }
+        fedimint_core::task::sleep(Duration::from_secs(1)).await;
+    }
+
+    let request = PayoutRequest {
+        peer,
+        amount: sats(101),
+        key: client.payout_key(),
+    };
+    let (_payout, instance) =
+        client.get_first_module::<PayoutClientModule>(&fedimint_payout_common::KIND);
+    assert!(instance
+        .api
+        .payout_vote(request, ApiAuth("pass".to_string()))
+        .await
+        .is_err());
+    assert!(client.payouts().await?.is_empty());
+
+    Ok(())
+}
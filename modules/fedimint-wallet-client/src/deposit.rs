@@ -17,18 +17,26 @@ use secp256k1::KeyPair;
 use tracing::{debug, instrument, trace, warn};
 
 use crate::api::WalletFederationApi;
-use crate::{WalletClientContext, WalletClientStates};
+use crate::{BitcoinTransactionData, WalletClientContext, WalletClientStates};
 
 const TRANSACTION_STATUS_FETCH_INTERVAL: Duration = Duration::from_secs(1);
 
+/// Maximum number of deposits to the same address that are claimed together in
+/// a single federation transaction, which keeps the transaction well below the
+/// size limit for consensus items
+pub(crate) const MAX_PEG_IN_CLAIM_BATCH: usize = 8;
+
 // FIXME: deal with RBF
-// FIXME: deal with multiple deposits
 #[aquamarine::aquamarine]
 /// The state machine driving forward a deposit (aka peg-in).
 ///
+/// All deposits to the address that are seen before they are claimed, up to
+/// [`MAX_PEG_IN_CLAIM_BATCH`], are claimed together in a single transaction.
+///
 /// ```mermaid
 /// graph LR
-///     Created -- Transaction seen --> AwaitingConfirmations["Waiting for confirmations"]
+///     Created -- Transactions seen --> AwaitingConfirmations["Waiting for confirmations"]
+///     AwaitingConfirmations -- More transactions seen --> AwaitingConfirmations
 ///     AwaitingConfirmations -- Confirmations received --> Claiming
 ///     AwaitingConfirmations -- "Retransmit seen tx (planned)" --> AwaitingConfirmations
 ///     Created -- "No transactions seen for [time]" --> Timeout["Timed out"]
@@ -52,12 +60,9 @@ impl State for DepositStateMachine {
             DepositStates::Created(created_state) => {
                 vec![
                     StateTransition::new(
-                        await_created_btc_transaction_submitted(
-                            context.clone(),
-                            created_state.tweak_key,
-                        ),
-                        |_db, (btc_tx, out_idx), old_state| {
-                            Box::pin(transition_tx_seen(old_state, btc_tx, out_idx))
+                        await_deposits(context.clone(), created_state.tweak_key, vec![]),
+                        |_db, deposits, old_state| {
+                            Box::pin(transition_deposits_seen(old_state, deposits))
                         },
                     ),
                     StateTransition::new(
@@ -69,21 +74,51 @@ impl State for DepositStateMachine {
             DepositStates::WaitingForConfirmations(waiting_state) => {
                 let global_context = global_context.clone();
                 vec![StateTransition::new(
-                    await_btc_transaction_confirmed(
+                    await_btc_deposits_confirmed(
                         context.clone(),
                         global_context.clone(),
-                        waiting_state.clone(),
+                        vec![waiting_state.deposit()],
                     ),
-                    move |dbtx, txout_proof, old_state| {
-                        Box::pin(transition_btc_tx_confirmed(
+                    move |dbtx, txout_proofs, old_state| {
+                        Box::pin(transition_btc_deposits_confirmed(
                             dbtx,
                             global_context.clone(),
                             old_state,
-                            txout_proof,
+                            txout_proofs,
                         ))
                     },
                 )]
             }
+            DepositStates::WaitingForBatchConfirmations(waiting_state) => {
+                let global_context = global_context.clone();
+                vec![
+                    StateTransition::new(
+                        await_deposits(
+                            context.clone(),
+                            waiting_state.tweak_key,
+                            waiting_state.deposits.clone(),
+                        ),
+                        |_db, deposits, old_state| {
+                            Box::pin(transition_deposits_seen(old_state, deposits))
+                        },
+                    ),
+                    StateTransition::new(
+                        await_btc_deposits_confirmed(
+                            context.clone(),
+                            global_context.clone(),
+                            waiting_state.deposits.clone(),
+                        ),
+                        move |dbtx, txout_proofs, old_state| {
+                            Box::pin(transition_btc_deposits_confirmed(
+                                dbtx,
+                                global_context.clone(),
+                                old_state,
+                                txout_proofs,
+                            ))
+                        },
+                    ),
+                ]
+            }
             DepositStates::Claiming(_) => {
                 vec![]
             }
@@ -98,43 +133,63 @@ impl State for DepositStateMachine {
     }
 }
 
-async fn await_created_btc_transaction_submitted(
+/// Waits until deposits to the address tweaked with `tweak` are seen that are
+/// not `known` yet and returns all of them, `known` ones first
+async fn await_deposits(
     context: WalletClientContext,
     tweak: KeyPair,
-) -> (bitcoin::Transaction, u32) {
-    let script = context
-        .wallet_descriptor
-        .tweak(&tweak.public_key().to_x_only_pubkey(), &context.secp)
-        .script_pubkey();
+    known: Vec<BitcoinTransactionData>,
+) -> Vec<BitcoinTransactionData> {
+    if known.len() >= MAX_PEG_IN_CLAIM_BATCH {
+        // further deposits will have to be claimed with a new address
+        return std::future::pending().await;
+    }
+
+    // Addresses handed out before the federation migrated to taproot are still
+    // locked to the legacy descriptor
+    let scripts = std::iter::once(&context.wallet_descriptor)
+        .chain(context.legacy_wallet_descriptor.as_ref())
+        .map(|descriptor| {
+            descriptor
+                .tweak(&tweak.public_key().to_x_only_pubkey(), &context.secp)
+                .script_pubkey()
+        })
+        .collect::<Vec<_>>();
     loop {
-        match context.rpc.watch_script_history(&script).await {
+        match watch_scripts_history(&context, &scripts).await {
             Ok(received) => {
-                // TODO: fix
-                if received.len() > 1 {
-                    warn!("More than one transaction was sent to deposit address, only considering the first one");
+                let mut deposits = known.clone();
+
+                for transaction in received {
+                    for (idx, output) in transaction.output.iter().enumerate() {
+                        if !scripts.contains(&output.script_pubkey) {
+                            continue;
+                        }
+
+                        let deposit = BitcoinTransactionData {
+                            btc_transaction: transaction.clone(),
+                            out_idx: idx as u32,
+                        };
+
+                        if !deposits.contains(&deposit) {
+                            deposits.push(deposit);
+                        }
+                    }
                 }
 
-                if let Some(transaction) = received.into_iter().next() {
-                    let out_idx = transaction
-                        .output
-                        .iter()
-                        .enumerate()
-                        .find_map(|(idx, output)| {
-                            if output.script_pubkey == script {
-                                Some(idx as u32)
-                            } else {
-                                None
-                            }
-                        })
-                        .expect("TODO: handle invalid tx returned by API");
-
-                    return (transaction, out_idx);
-                } else {
-                    trace!("No transactions received yet for script {script:?}");
+                if deposits.len() > MAX_PEG_IN_CLAIM_BATCH {
+                    warn!("More than {MAX_PEG_IN_CLAIM_BATCH} deposits were sent to deposit address, only considering the first ones");
+                    deposits.truncate(MAX_PEG_IN_CLAIM_BATCH);
                 }
+
+                if deposits.len() > known.len() {
+                    return deposits;
+                }
+
+                trace!("No new transactions received yet for scripts {scripts:?}");
             }
             Err(e) => {
-                warn!("Error fetching transaction history for {script:?}: {e}");
+                warn!("Error fetching transaction history for {scripts:?}: {e}");
             }
         }
 
@@ -142,26 +197,41 @@ async fn await_created_btc_transaction_submitted(
     }
 }
 
-async fn transition_tx_seen(
+async fn watch_scripts_history(
+    context: &WalletClientContext,
+    scripts: &[bitcoin::Script],
+) -> anyhow::Result<Vec<bitcoin::Transaction>> {
+    let mut received = vec![];
+    for script in scripts {
+        received.extend(context.rpc.watch_script_history(script).await?);
+    }
+
+    Ok(received)
+}
+
+async fn transition_deposits_seen(
     old_state: DepositStateMachine,
-    btc_transaction: bitcoin::Transaction,
-    out_idx: u32,
+    deposits: Vec<BitcoinTransactionData>,
 ) -> DepositStateMachine {
     let DepositStateMachine {
         operation_id,
         state: old_state,
     } = old_state;
 
-    match old_state {
-        DepositStates::Created(created_state) => DepositStateMachine {
-            operation_id,
-            state: DepositStates::WaitingForConfirmations(WaitingForConfirmationsDepositState {
-                tweak_key: created_state.tweak_key,
-                btc_transaction,
-                out_idx,
-            }),
-        },
+    let tweak_key = match old_state {
+        DepositStates::Created(created_state) => created_state.tweak_key,
+        DepositStates::WaitingForBatchConfirmations(waiting_state) => waiting_state.tweak_key,
         state => panic!("Invalid previous state: {state:?}"),
+    };
+
+    DepositStateMachine {
+        operation_id,
+        state: DepositStates::WaitingForBatchConfirmations(
+            WaitingForBatchConfirmationsDepositState {
+                tweak_key,
+                deposits,
+            },
+        ),
     }
 }
 
@@ -183,13 +253,15 @@ async fn transition_deposit_timeout(old_state: DepositStateMachine) -> DepositSt
     }
 }
 
+/// Waits until all `deposits` are confirmed and returns their proofs in the
+/// same order
 #[instrument(skip_all, level = "debug")]
-async fn await_btc_transaction_confirmed(
+async fn await_btc_deposits_confirmed(
     context: WalletClientContext,
     global_context: DynGlobalClientContext,
-    waiting_state: WaitingForConfirmationsDepositState,
-) -> TxOutProof {
-    loop {
+    deposits: Vec<BitcoinTransactionData>,
+) -> Vec<TxOutProof> {
+    'retry: loop {
         // TODO: make everything subscriptions
         // Wait for confirmation
         let consensus_block_count = match global_context
@@ -206,84 +278,105 @@ async fn await_btc_transaction_confirmed(
         };
         debug!(consensus_block_count, "Fetched consensus block count");
 
-        let confirmation_block_count = match context
-            .rpc
-            .get_tx_block_height(&waiting_state.btc_transaction.txid())
-            .await
-        {
-            Ok(Some(confirmation_height)) => Some(confirmation_height + 1),
-            Ok(None) => None,
-            Err(e) => {
-                warn!("Failed to fetch confirmation height: {e:?}");
+        for deposit in &deposits {
+            // larger peg-ins may require more confirmations than the federation's
+            // finality delay
+            let amount = deposit.btc_transaction.output[deposit.out_idx as usize].value;
+            let extra_confirmations =
+                context.extra_confirmations(bitcoin::Amount::from_sat(amount));
+
+            let confirmation_block_count = match context
+                .rpc
+                .get_tx_block_height(&deposit.btc_transaction.txid())
+                .await
+            {
+                Ok(Some(confirmation_height)) => {
+                    Some(confirmation_height + 1 + extra_confirmations)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    warn!("Failed to fetch confirmation height: {e:?}");
+                    sleep(TRANSACTION_STATUS_FETCH_INTERVAL).await;
+                    continue 'retry;
+                }
+            };
+
+            debug!(
+                ?confirmation_block_count,
+                "Fetched confirmation block count"
+            );
+
+            if !confirmation_block_count
+                .map(|confirmation_block_count| consensus_block_count >= confirmation_block_count)
+                .unwrap_or(false)
+            {
+                trace!("Not confirmed yet, confirmation_block_count={confirmation_block_count:?}, consensus_block_count={consensus_block_count}");
                 sleep(TRANSACTION_STATUS_FETCH_INTERVAL).await;
-                continue;
+                continue 'retry;
             }
-        };
-
-        debug!(
-            ?confirmation_block_count,
-            "Fetched confirmation block count"
-        );
-
-        if !confirmation_block_count
-            .map(|confirmation_block_count| consensus_block_count >= confirmation_block_count)
-            .unwrap_or(false)
-        {
-            trace!("Not confirmed yet, confirmation_block_count={confirmation_block_count:?}, consensus_block_count={consensus_block_count}");
-            sleep(TRANSACTION_STATUS_FETCH_INTERVAL).await;
-            continue;
         }
 
-        // Get txout proof
-        let txout_proof = match context
-            .rpc
-            .get_txout_proof(waiting_state.btc_transaction.txid())
-            .await
-        {
-            Ok(txout_proof) => txout_proof,
-            Err(e) => {
-                warn!("Failed to fetch transaction proof: {e:?}");
-                sleep(TRANSACTION_STATUS_FETCH_INTERVAL).await;
-                continue;
-            }
-        };
+        let mut txout_proofs = Vec::with_capacity(deposits.len());
+
+        for deposit in &deposits {
+            // Get txout proof
+            let txout_proof = match context
+                .rpc
+                .get_txout_proof(deposit.btc_transaction.txid())
+                .await
+            {
+                Ok(txout_proof) => txout_proof,
+                Err(e) => {
+                    warn!("Failed to fetch transaction proof: {e:?}");
+                    sleep(TRANSACTION_STATUS_FETCH_INTERVAL).await;
+                    continue 'retry;
+                }
+            };
 
-        debug!(proof_block_hash = ?txout_proof.block_header.block_hash(), "Generated merkle proof");
+            debug!(proof_block_hash = ?txout_proof.block_header.block_hash(), "Generated merkle proof");
+
+            txout_proofs.push(txout_proof);
+        }
 
-        return txout_proof;
+        return txout_proofs;
     }
 }
 
-async fn transition_btc_tx_confirmed(
+async fn transition_btc_deposits_confirmed(
     dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
     global_context: DynGlobalClientContext,
     old_state: DepositStateMachine,
-    txout_proof: TxOutProof,
+    txout_proofs: Vec<TxOutProof>,
 ) -> DepositStateMachine {
-    let awaiting_confirmation_state = match old_state.state {
-        DepositStates::WaitingForConfirmations(s) => s,
+    let (tweak_key, deposits) = match old_state.state {
+        DepositStates::WaitingForConfirmations(s) => (s.tweak_key, vec![s.deposit()]),
+        DepositStates::WaitingForBatchConfirmations(s) => (s.tweak_key, s.deposits),
         _ => panic!("Invalid previous state"),
     };
 
-    let wallet_input = WalletInput(Box::new(
-        PegInProof::new(
-            txout_proof,
-            awaiting_confirmation_state.btc_transaction,
-            awaiting_confirmation_state.out_idx,
-            awaiting_confirmation_state
-                .tweak_key
-                .public_key()
-                .to_x_only_pubkey(),
-        )
-        .expect("TODO: handle API returning faulty proofs"),
-    ));
-    let client_input = ClientInput::<WalletInput, WalletClientStates> {
-        input: wallet_input,
-        keys: vec![awaiting_confirmation_state.tweak_key],
-        state_machines: Arc::new(|_, _| vec![]),
-    };
+    let client_inputs = deposits
+        .into_iter()
+        .zip(txout_proofs)
+        .map(|(deposit, txout_proof)| {
+            let wallet_input = WalletInput(Box::new(
+                PegInProof::new(
+                    txout_proof,
+                    deposit.btc_transaction,
+                    deposit.out_idx,
+                    tweak_key.public_key().to_x_only_pubkey(),
+                )
+                .expect("TODO: handle API returning faulty proofs"),
+            ));
+
+            ClientInput::<WalletInput, WalletClientStates> {
+                input: wallet_input,
+                keys: vec![tweak_key],
+                state_machines: Arc::new(|_, _| vec![]),
+            }
+        })
+        .collect();
 
-    let (fm_txid, change) = global_context.claim_input(dbtx, client_input).await;
+    let (fm_txid, change) = global_context.claim_inputs(dbtx, client_inputs).await;
 
     DepositStateMachine {
         operation_id: old_state.operation_id,
@@ -300,6 +393,7 @@ pub enum DepositStates {
     WaitingForConfirmations(WaitingForConfirmationsDepositState),
     Claiming(ClaimingDepositState),
     TimedOut(TimedOutDepositState),
+    WaitingForBatchConfirmations(WaitingForBatchConfirmationsDepositState),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
@@ -308,6 +402,7 @@ pub struct CreatedDepositState {
     pub(crate) timeout_at: SystemTime,
 }
 
+/// A single deposit seen by an older client, which is claimed on its own
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct WaitingForConfirmationsDepositState {
     /// Key pair of which the public was used to tweak the federation's wallet
@@ -321,6 +416,26 @@ pub struct WaitingForConfirmationsDepositState {
     pub(crate) out_idx: u32,
 }
 
+impl WaitingForConfirmationsDepositState {
+    pub(crate) fn deposit(&self) -> BitcoinTransactionData {
+        BitcoinTransactionData {
+            btc_transaction: self.btc_transaction.clone(),
+            out_idx: self.out_idx,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct WaitingForBatchConfirmationsDepositState {
+    /// Key pair of which the public was used to tweak the federation's wallet
+    /// descriptor. The secret key is later used to sign the fedimint claim
+    /// transaction for all deposits.
+    tweak_key: KeyPair,
+    /// The deposits to our address in the order we have seen them, at most
+    /// [`MAX_PEG_IN_CLAIM_BATCH`]
+    pub(crate) deposits: Vec<BitcoinTransactionData>,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct ClaimingDepositState {
     /// Fedimint transaction id in which the deposit is being claimed.
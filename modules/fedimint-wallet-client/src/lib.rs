@@ -6,17 +6,18 @@ mod withdraw;
 
 use std::collections::BTreeMap;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail, ensure, Context as AnyhowContext};
 use async_stream::stream;
 use bitcoin::{Address, Network};
 use client_db::DbKeyPrefix;
 use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
+use fedimint_client::balance::{BalanceItem, OperationFees, PendingStage};
 use fedimint_client::derivable_secret::{ChildId, DerivableSecret};
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::{ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{OperationLogEntry, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{Context, DynState, ModuleNotifier, State, StateTransition};
 use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
@@ -32,9 +33,9 @@ use fedimint_core::module::{
     ApiVersion, CommonModuleInit, ExtendsCommonModuleInit, ModuleCommon, MultiApiVersion,
     TransactionItemAmount,
 };
-use fedimint_core::task::TaskGroup;
+use fedimint_core::task::{timeout, TaskGroup};
 use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint};
-use fedimint_wallet_common::config::WalletClientConfig;
+use fedimint_wallet_common::config::{peg_in_confirmations, ConfirmationTier, WalletClientConfig};
 use fedimint_wallet_common::tweakable::Tweakable;
 pub use fedimint_wallet_common::*;
 use futures::{Stream, StreamExt};
@@ -98,7 +99,7 @@ pub trait WalletClientExt {
     ) -> anyhow::Result<UpdateStreamOrOutcome<WithdrawState>>;
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
 pub struct BitcoinTransactionData {
     /// The bitcoin transaction is saved as soon as we see it so the transaction
     /// can be re-transmitted if it's evicted from the mempool.
@@ -224,42 +225,53 @@ impl WalletClientExt for ClientArc {
                         None => return,
                     }
 
-                    let tx_data = match next_deposit_state(&mut operation_stream).await {
-                        Some(DepositStates::WaitingForConfirmations(inner)) => {
-                            let tx_data = BitcoinTransactionData { btc_transaction: inner.btc_transaction, out_idx: inner.out_idx };
-                            yield DepositState::WaitingForConfirmation(tx_data.clone());
-                            tx_data
-                        },
-                        Some(DepositStates::TimedOut(_)) => {
-                            yield DepositState::Failed("Deposit timed out".to_string());
-                            return;
-                        },
-                        Some(s) => {
-                            panic!("Unexpected state {s:?}")
-                        },
-                        None => return,
+                    // deposits to the same address are claimed together, so we report each
+                    // of them as soon as it is seen and all of them once they are claimed
+                    let mut deposits: Vec<BitcoinTransactionData> = vec![];
+
+                    let claiming = loop {
+                        match next_deposit_state(&mut operation_stream).await {
+                            Some(DepositStates::WaitingForConfirmations(inner)) => {
+                                let tx_data = inner.deposit();
+                                yield DepositState::WaitingForConfirmation(tx_data.clone());
+                                deposits.push(tx_data);
+                            },
+                            Some(DepositStates::WaitingForBatchConfirmations(inner)) => {
+                                for tx_data in inner.deposits.into_iter().skip(deposits.len()) {
+                                    yield DepositState::WaitingForConfirmation(tx_data.clone());
+                                    deposits.push(tx_data);
+                                }
+                            },
+                            Some(DepositStates::Claiming(claiming)) if !deposits.is_empty() => {
+                                break claiming;
+                            },
+                            Some(DepositStates::TimedOut(_)) if deposits.is_empty() => {
+                                yield DepositState::Failed("Deposit timed out".to_string());
+                                return;
+                            },
+                            Some(s) => {
+                                panic!("Unexpected state {s:?}")
+                            },
+                            None => return,
+                        }
                     };
 
-                    let claiming = match next_deposit_state(&mut operation_stream).await {
-                        Some(DepositStates::Claiming(claiming)) => claiming,
-                        Some(s) => {
-                            panic!("Unexpected state {s:?}")
-                        },
-                        None => return,
-                    };
-                    yield DepositState::Confirmed(tx_data.clone());
+                    for tx_data in &deposits {
+                        yield DepositState::Confirmed(tx_data.clone());
+                    }
 
                     if let Err(e) = tx_subscriber.await_tx_accepted(claiming.transaction_id).await {
                         yield DepositState::Failed(format!("Failed to claim: {e:?}"));
                         return;
                     }
 
+                    client.await_primary_module_outputs(operation_id, claiming.change)
+                        .await
+                        .expect("Cannot fail if tx was accepted and federation is honest");
 
-                        client.await_primary_module_outputs(operation_id, claiming.change)
-                            .await
-                            .expect("Cannot fail if tx was accepted and federation is honest");
-
-                    yield DepositState::Claimed(tx_data.clone());
+                    for tx_data in deposits {
+                        yield DepositState::Claimed(tx_data);
+                    }
                 }
             }),
         )
@@ -522,6 +534,11 @@ pub struct WalletClientModule {
     secp: Secp256k1<All>,
 }
 
+/// How long we wait for the bitcoin backend when reporting the confirmations
+/// of pending peg-ins
+const BALANCE_RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[apply(async_trait_maybe_send!)]
 impl ClientModule for WalletClientModule {
     type Common = WalletModuleTypes;
     type ModuleStateMachineContext = WalletClientContext;
@@ -531,6 +548,9 @@ impl ClientModule for WalletClientModule {
         WalletClientContext {
             rpc: self.rpc.clone(),
             wallet_descriptor: self.cfg.peg_in_descriptor.clone(),
+            legacy_wallet_descriptor: self.cfg.legacy_peg_in_descriptor.clone(),
+            finality_delay: self.cfg.finality_delay,
+            peg_in_confirmation_tiers: self.cfg.peg_in_confirmation_tiers.clone(),
             wallet_decoder: self.decoder(),
             secp: Default::default(),
         }
@@ -552,18 +572,172 @@ impl ClientModule for WalletClientModule {
             fee: self.cfg.fee_consensus.peg_out_abs,
         }
     }
+
+    async fn get_balance_breakdown(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        active_states: Vec<WalletClientStates>,
+    ) -> Vec<BalanceItem> {
+        let deposits = active_states
+            .into_iter()
+            .flat_map(|state| match state {
+                WalletClientStates::Deposit(DepositStateMachine {
+                    operation_id,
+                    state: DepositStates::WaitingForConfirmations(waiting),
+                }) => vec![(operation_id, waiting.deposit())],
+                WalletClientStates::Deposit(DepositStateMachine {
+                    operation_id,
+                    state: DepositStates::WaitingForBatchConfirmations(waiting),
+                }) => waiting
+                    .deposits
+                    .into_iter()
+                    .map(|deposit| (operation_id, deposit))
+                    .collect(),
+                _ => vec![],
+            })
+            .collect::<Vec<_>>();
+
+        if deposits.is_empty() {
+            return vec![];
+        }
+
+        // we only report the confirmations if both the federation and our bitcoin
+        // backend can be reached, the balance is still accurate otherwise
+        let consensus_block_count = self.module_api.fetch_consensus_block_count().await.ok();
+
+        let mut items = Vec::with_capacity(deposits.len());
+
+        for (operation_id, deposit) in deposits {
+            let txid = deposit.btc_transaction.txid();
+            let amount = deposit.btc_transaction.output[deposit.out_idx as usize].value;
+
+            let extra_confirmations = u64::from(
+                self.cfg
+                    .peg_in_confirmations(bitcoin::Amount::from_sat(amount))
+                    - self.cfg.finality_delay,
+            );
+            let confirmation_block_count =
+                match timeout(BALANCE_RPC_TIMEOUT, self.rpc.get_tx_block_height(&txid)).await {
+                    Ok(Ok(height)) => height.map(|height| height + 1 + extra_confirmations),
+                    _ => None,
+                };
+
+            items.push(BalanceItem::PegIn {
+                operation_id,
+                txid,
+                amount: Amount::from_sats(amount),
+                confirmations_remaining: consensus_block_count
+                    .zip(confirmation_block_count)
+                    .map(|(consensus, confirmation)| confirmation.saturating_sub(consensus)),
+            });
+        }
+
+        items
+    }
+
+    async fn get_pending_stages(
+        &self,
+        _dbtx: &mut DatabaseTransactionRef<'_>,
+        operation: &OperationLogEntry,
+        active_states: Vec<WalletClientStates>,
+    ) -> Vec<PendingStage> {
+        let fees = &self.cfg.fee_consensus;
+
+        let withdrawing = active_states.iter().any(|state| {
+            matches!(
+                state,
+                WalletClientStates::Withdraw(WithdrawStateMachine {
+                    state: WithdrawStates::Created(_),
+                    ..
+                })
+            )
+        });
+        let withdraw = withdrawing
+            .then(|| withdraw_stage(operation, fees.peg_out_abs))
+            .flatten();
+
+        active_states
+            .into_iter()
+            .flat_map(|state| match state {
+                WalletClientStates::Deposit(DepositStateMachine {
+                    state: DepositStates::WaitingForConfirmations(waiting),
+                    ..
+                }) => vec![waiting.deposit()],
+                WalletClientStates::Deposit(DepositStateMachine {
+                    state: DepositStates::WaitingForBatchConfirmations(waiting),
+                    ..
+                }) => waiting.deposits,
+                _ => vec![],
+            })
+            .map(|deposit| PendingStage {
+                stage: "awaiting_confirmations".to_string(),
+                locked: Amount::from_sats(
+                    deposit.btc_transaction.output[deposit.out_idx as usize].value,
+                ),
+                fees: OperationFees::federation(fees.peg_in_abs),
+            })
+            .chain(withdraw)
+            .collect()
+    }
+}
+
+/// The stage of a withdrawal whose peg-out was not processed by the federation
+/// yet, the amount and fees of which are only recorded in the meta data of the
+/// operation
+fn withdraw_stage(operation: &OperationLogEntry, peg_out_fee: Amount) -> Option<PendingStage> {
+    if operation.operation_module_kind() != WalletCommonGen::KIND.as_str() {
+        return None;
+    }
+
+    match operation.meta::<WalletOperationMeta>() {
+        WalletOperationMeta::Withdraw { amount, fee, .. } => Some(PendingStage {
+            stage: "withdrawing".to_string(),
+            locked: Amount::from_sats(amount.to_sat() + fee.amount().to_sat()),
+            fees: OperationFees {
+                federation: peg_out_fee,
+                gateway: Amount::ZERO,
+                on_chain: Amount::from_sats(fee.amount().to_sat()),
+            },
+        }),
+        WalletOperationMeta::RbfWithdraw { rbf, .. } => Some(PendingStage {
+            stage: "bumping_fees".to_string(),
+            locked: Amount::from_sats(rbf.fees.amount().to_sat()),
+            fees: OperationFees {
+                federation: peg_out_fee,
+                gateway: Amount::ZERO,
+                on_chain: Amount::from_sats(rbf.fees.amount().to_sat()),
+            },
+        }),
+        WalletOperationMeta::Deposit { .. } => None,
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct WalletClientContext {
     rpc: DynBitcoindRpc,
     wallet_descriptor: PegInDescriptor,
+    /// The descriptor addresses handed out before the federation migrated to
+    /// taproot are locked to
+    legacy_wallet_descriptor: Option<PegInDescriptor>,
+    finality_delay: u32,
+    peg_in_confirmation_tiers: Vec<ConfirmationTier>,
     wallet_decoder: Decoder,
     secp: Secp256k1<All>,
 }
 
 impl Context for WalletClientContext {}
 
+impl WalletClientContext {
+    /// How many blocks the federation has to know beyond the one confirming a
+    /// peg-in of the amount before it accepts the peg-in
+    fn extra_confirmations(&self, amount: bitcoin::Amount) -> u64 {
+        let confirmations =
+            peg_in_confirmations(self.finality_delay, &self.peg_in_confirmation_tiers, amount);
+
+        (confirmations - self.finality_delay).into()
+    }
+}
+
 impl WalletClientModule {
     fn get_rpc_config(cfg: &WalletClientConfig) -> BitcoinRpcConfig {
         if let Ok(rpc_config) = BitcoinRpcConfig::from_env_vars() {
@@ -1,4 +1,7 @@
 use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::Context;
 
 use bitcoin::Network;
 use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
@@ -6,12 +9,12 @@ use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::util::SafeUrl;
-use fedimint_core::{plugin_types_trait_impl_config, Feerate, PeerId};
-use miniscript::descriptor::Wsh;
+use fedimint_core::{plugin_types_trait_impl_config, Feerate, NumPeers, PeerId};
 use secp256k1::SecretKey;
 use serde::{Deserialize, Serialize};
 
 use crate::keys::CompressedPublicKey;
+use crate::taproot::PegInDescriptorKind;
 use crate::{PegInDescriptor, WalletCommonGen};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +38,8 @@ impl WalletGenParams {
                     ))
                     .expect("Failed to parse default esplora server"),
                 },
+                peg_in_descriptor_kind: PegInDescriptorKind::default(),
+                peg_in_confirmation_tiers: vec![],
             },
         }
     }
@@ -51,6 +56,12 @@ pub struct WalletGenParamsConsensus {
     pub finality_delay: u32,
     /// See [`WalletConfigConsensus::client_default_bitcoin_rpc`].
     pub client_default_bitcoin_rpc: BitcoinRpcConfig,
+    /// The kind of descriptor peg-ins are locked to
+    #[serde(default)]
+    pub peg_in_descriptor_kind: PegInDescriptorKind,
+    /// See [`WalletConfigConsensus::peg_in_confirmation_tiers`]
+    #[serde(default)]
+    pub peg_in_confirmation_tiers: Vec<ConfirmationTier>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -78,11 +89,20 @@ pub struct WalletConfigConsensus {
     pub network: Network,
     /// The federations public peg-in-descriptor
     pub peg_in_descriptor: PegInDescriptor,
+    /// The descriptor peg-ins were locked to before the federation migrated to
+    /// the taproot descriptor. The UTXOs we received before are still spent
+    /// with it, and clients may still claim deposits to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy_peg_in_descriptor: Option<PegInDescriptor>,
     /// The public keys for the bitcoin multisig
     pub peer_peg_in_keys: BTreeMap<PeerId, CompressedPublicKey>,
     /// How many bitcoin blocks to wait before considering a transaction
     /// confirmed
     pub finality_delay: u32,
+    /// Peg-ins of larger amounts have to be confirmed by more blocks than the
+    /// finality delay, see [`peg_in_confirmations`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peg_in_confirmation_tiers: Vec<ConfirmationTier>,
     /// If we cannot determine the feerate from our bitcoin node, default to
     /// this
     pub default_fee: Feerate,
@@ -102,10 +122,16 @@ pub struct WalletConfigConsensus {
 pub struct WalletClientConfig {
     /// The federations public peg-in-descriptor
     pub peg_in_descriptor: PegInDescriptor,
+    /// See [`WalletConfigConsensus::legacy_peg_in_descriptor`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy_peg_in_descriptor: Option<PegInDescriptor>,
     /// The bitcoin network the client will use
     pub network: Network,
     /// Confirmations required for a peg in to be accepted by federation
     pub finality_delay: u32,
+    /// See [`WalletConfigConsensus::peg_in_confirmation_tiers`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peg_in_confirmation_tiers: Vec<ConfirmationTier>,
     pub fee_consensus: FeeConsensus,
     /// Points to a Bitcoin API that the client can use to interact with the
     /// Bitcoin blockchain (mostly for deposits). *Eventually the backend should
@@ -149,10 +175,10 @@ impl WalletConfig {
         finality_delay: u32,
         bitcoin_rpc: BitcoinRpcConfig,
         client_default_bitcoin_rpc: BitcoinRpcConfig,
+        peg_in_descriptor_kind: PegInDescriptorKind,
+        peg_in_confirmation_tiers: Vec<ConfirmationTier>,
     ) -> Self {
-        let peg_in_descriptor = PegInDescriptor::Wsh(
-            Wsh::new_sortedmulti(threshold, pubkeys.values().copied().collect()).unwrap(),
-        );
+        let peg_in_descriptor = peg_in_descriptor_kind.descriptor(threshold, &pubkeys);
 
         Self {
             local: WalletConfigLocal { bitcoin_rpc },
@@ -160,8 +186,10 @@ impl WalletConfig {
             consensus: WalletConfigConsensus {
                 network,
                 peg_in_descriptor,
+                legacy_peg_in_descriptor: None,
                 peer_peg_in_keys: pubkeys,
                 finality_delay,
+                peg_in_confirmation_tiers,
                 default_fee: Feerate { sats_per_kvb: 1000 },
                 fee_consensus: Default::default(),
                 client_default_bitcoin_rpc,
@@ -170,6 +198,33 @@ impl WalletConfig {
     }
 }
 
+impl WalletConfigConsensus {
+    /// Confirmations required for a peg-in of the amount to be accepted
+    pub fn peg_in_confirmations(&self, amount: bitcoin::Amount) -> u32 {
+        peg_in_confirmations(self.finality_delay, &self.peg_in_confirmation_tiers, amount)
+    }
+
+    /// The config after migrating peg-ins from the legacy P2WSH descriptor to
+    /// the taproot descriptor, `None` if they are not locked to the former or
+    /// the taproot descriptor is not selectable yet
+    pub fn migrated_to_taproot(&self) -> Option<WalletConfigConsensus> {
+        if !PegInDescriptorKind::Tr.is_selectable()
+            || PegInDescriptorKind::of(&self.peg_in_descriptor) != Some(PegInDescriptorKind::Wsh)
+        {
+            return None;
+        }
+
+        let threshold = self.peer_peg_in_keys.threshold();
+
+        Some(WalletConfigConsensus {
+            peg_in_descriptor: PegInDescriptorKind::Tr
+                .descriptor(threshold, &self.peer_peg_in_keys),
+            legacy_peg_in_descriptor: Some(self.peg_in_descriptor.clone()),
+            ..self.clone()
+        })
+    }
+}
+
 impl WalletClientConfig {
     pub fn new(
         peg_in_descriptor: PegInDescriptor,
@@ -179,12 +234,58 @@ impl WalletClientConfig {
     ) -> Self {
         Self {
             peg_in_descriptor,
+            legacy_peg_in_descriptor: None,
             network,
             finality_delay,
+            peg_in_confirmation_tiers: vec![],
             fee_consensus: Default::default(),
             default_bitcoin_rpc,
         }
     }
+
+    /// Confirmations required for a peg-in of the amount to be accepted
+    pub fn peg_in_confirmations(&self, amount: bitcoin::Amount) -> u32 {
+        peg_in_confirmations(self.finality_delay, &self.peg_in_confirmation_tiers, amount)
+    }
+}
+
+/// Requires peg-ins of at least `min_amount` to be confirmed by
+/// `confirmations` blocks, which can not be less than the finality delay
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ConfirmationTier {
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub min_amount: bitcoin::Amount,
+    pub confirmations: u32,
+}
+
+/// Parses a tier from `<min amount in sats>:<confirmations>`
+impl FromStr for ConfirmationTier {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min_amount, confirmations) = s
+            .split_once(':')
+            .context("Expected <min amount in sats>:<confirmations>")?;
+
+        Ok(ConfirmationTier {
+            min_amount: bitcoin::Amount::from_sat(min_amount.parse()?),
+            confirmations: confirmations.parse()?,
+        })
+    }
+}
+
+/// Confirmations required for a peg-in of the amount, the most of all tiers
+/// it reaches and at least the finality delay
+pub fn peg_in_confirmations(
+    finality_delay: u32,
+    tiers: &[ConfirmationTier],
+    amount: bitcoin::Amount,
+) -> u32 {
+    tiers
+        .iter()
+        .filter(|tier| tier.min_amount <= amount)
+        .map(|tier| tier.confirmations)
+        .fold(finality_delay, u32::max)
 }
 
 plugin_types_trait_impl_config!(
@@ -198,3 +299,32 @@ plugin_types_trait_impl_config!(
     WalletConfigConsensus,
     WalletClientConfig
 );
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{peg_in_confirmations, ConfirmationTier};
+
+    #[test]
+    fn larger_peg_ins_require_more_confirmations() {
+        let tiers = ["100000:6", "10000000:12"]
+            .into_iter()
+            .map(|tier| ConfirmationTier::from_str(tier).unwrap())
+            .collect::<Vec<_>>();
+
+        let confirmations = |sats| peg_in_confirmations(1, &tiers, bitcoin::Amount::from_sat(sats));
+
+        assert_eq!(confirmations(99_999), 1);
+        assert_eq!(confirmations(100_000), 6);
+        assert_eq!(confirmations(20_000_000), 12);
+
+        // tiers below the finality delay do not lower it
+        assert_eq!(
+            peg_in_confirmations(10, &tiers, bitcoin::Amount::from_sat(100_000)),
+            10
+        );
+
+        assert!(ConfirmationTier::from_str("100000").is_err());
+    }
+}
@@ -5,7 +5,10 @@ use secp256k1::ecdsa::Signature;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
-use crate::{PendingTransaction, SpendableUTXO, UnsignedTransaction, WalletOutputOutcome};
+use crate::{
+    PegOut, PegOutInputSignature, PendingTransaction, SpendableUTXO, UnsignedTransaction,
+    WalletOutputOutcome,
+};
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -19,6 +22,16 @@ pub enum DbKeyPrefix {
     PegOutTxSigCi = 0x36,
     PegOutBitcoinOutPoint = 0x37,
     PegOutNonce = 0x38,
+    FeeRateFloor = 0x39,
+    TaprootPegOutTxSigCi = 0x3a,
+    MigratedUtxo = 0x3b,
+    TaprootMigrationRequest = 0x3c,
+    TaprootMigrationApproval = 0x3d,
+    PegOutFeeBumpRequest = 0x3e,
+    PegOutFeeBumpVote = 0x3f,
+    QueuedPegOut = 0x40,
+    PegOutFee = 0x41,
+    BlockHeight = 0x42,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -40,6 +53,21 @@ impl_db_record!(
 );
 impl_db_lookup!(key = BlockHashKey, query_prefix = BlockHashKeyPrefix);
 
+/// The height of the blocks we synced since the height is recorded, older
+/// blocks are confirmed deeply enough for any peg-in
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct BlockHeightKey(pub BlockHash);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct BlockHeightPrefix;
+
+impl_db_record!(
+    key = BlockHeightKey,
+    value = u32,
+    db_prefix = DbKeyPrefix::BlockHeight,
+);
+impl_db_lookup!(key = BlockHeightKey, query_prefix = BlockHeightPrefix);
+
 #[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable, Serialize)]
 pub struct UTXOKey(pub bitcoin::OutPoint);
 
@@ -101,6 +129,106 @@ impl_db_lookup!(
     query_prefix = PegOutTxSignatureCIPrefix
 );
 
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct TaprootPegOutTxSignatureCI(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct TaprootPegOutTxSignatureCIPrefix;
+
+impl_db_record!(
+    key = TaprootPegOutTxSignatureCI,
+    value = Vec<PegOutInputSignature>,
+    db_prefix = DbKeyPrefix::TaprootPegOutTxSigCi,
+);
+impl_db_lookup!(
+    key = TaprootPegOutTxSignatureCI,
+    query_prefix = TaprootPegOutTxSignatureCIPrefix
+);
+
+/// Marks a UTXO locked to the current peg-in descriptor after the federation
+/// migrated to the taproot descriptor, the UTXOs without a mark are locked to
+/// the legacy descriptor
+#[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable, Serialize)]
+pub struct MigratedUTXOKey(pub bitcoin::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct MigratedUTXOPrefixKey;
+
+impl_db_record!(
+    key = MigratedUTXOKey,
+    value = (),
+    db_prefix = DbKeyPrefix::MigratedUtxo,
+);
+impl_db_lookup!(key = MigratedUTXOKey, query_prefix = MigratedUTXOPrefixKey);
+
+/// Our admin requested migrating peg-ins to the taproot descriptor
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct TaprootMigrationRequestKey;
+
+impl_db_record!(
+    key = TaprootMigrationRequestKey,
+    value = (),
+    db_prefix = DbKeyPrefix::TaprootMigrationRequest,
+);
+
+/// The guardians that approved migrating peg-ins to the taproot descriptor
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct TaprootMigrationApprovalKey(pub PeerId);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct TaprootMigrationApprovalPrefix;
+
+impl_db_record!(
+    key = TaprootMigrationApprovalKey,
+    value = (),
+    db_prefix = DbKeyPrefix::TaprootMigrationApproval,
+);
+impl_db_lookup!(
+    key = TaprootMigrationApprovalKey,
+    query_prefix = TaprootMigrationApprovalPrefix
+);
+
+/// The fee rates our admin requested for pending peg-outs
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutFeeBumpRequestKey(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutFeeBumpRequestPrefix;
+
+impl_db_record!(
+    key = PegOutFeeBumpRequestKey,
+    value = fedimint_core::Feerate,
+    db_prefix = DbKeyPrefix::PegOutFeeBumpRequest,
+);
+impl_db_lookup!(
+    key = PegOutFeeBumpRequestKey,
+    query_prefix = PegOutFeeBumpRequestPrefix
+);
+
+/// The fee rates the guardians voted to bump pending peg-outs to
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutFeeBumpVoteKey {
+    pub txid: Txid,
+    pub peer: PeerId,
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutFeeBumpVotePrefix;
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutFeeBumpVoteTxidPrefix(pub Txid);
+
+impl_db_record!(
+    key = PegOutFeeBumpVoteKey,
+    value = fedimint_core::Feerate,
+    db_prefix = DbKeyPrefix::PegOutFeeBumpVote,
+);
+impl_db_lookup!(
+    key = PegOutFeeBumpVoteKey,
+    query_prefix = PegOutFeeBumpVotePrefix,
+    query_prefix = PegOutFeeBumpVoteTxidPrefix
+);
+
 #[derive(Clone, Debug, Encodable, Decodable, Serialize)]
 pub struct PegOutBitcoinTransaction(pub fedimint_core::OutPoint);
 
@@ -118,6 +246,35 @@ impl_db_lookup!(
     query_prefix = PegOutBitcoinTransactionPrefix
 );
 
+/// The peg-outs accepted in the current session, which are batched into a
+/// single transaction at the end of the session
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct QueuedPegOutKey(pub fedimint_core::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct QueuedPegOutPrefix;
+
+impl_db_record!(
+    key = QueuedPegOutKey,
+    value = PegOut,
+    db_prefix = DbKeyPrefix::QueuedPegOut,
+);
+impl_db_lookup!(key = QueuedPegOutKey, query_prefix = QueuedPegOutPrefix);
+
+/// The share of the fees of its batch attributed to a peg-out
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutFeeKey(pub fedimint_core::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutFeePrefix;
+
+impl_db_record!(
+    key = PegOutFeeKey,
+    value = bitcoin::Amount,
+    db_prefix = DbKeyPrefix::PegOutFee,
+);
+impl_db_lookup!(key = PegOutFeeKey, query_prefix = PegOutFeePrefix);
+
 #[derive(Clone, Debug, Encodable, Decodable, Serialize)]
 pub struct BlockCountVoteKey(pub PeerId);
 
@@ -146,6 +303,17 @@ impl_db_record!(
 
 impl_db_lookup!(key = FeeRateVoteKey, query_prefix = FeeRateVotePrefix);
 
+/// Lower bound on our fee rate votes set via the API, such that transactions
+/// stuck in the mempool can be sped up by raising the consensus fee rate
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct FeeRateFloorKey;
+
+impl_db_record!(
+    key = FeeRateFloorKey,
+    value = fedimint_core::Feerate,
+    db_prefix = DbKeyPrefix::FeeRateFloor
+);
+
 #[derive(Clone, Debug, Encodable, Decodable)]
 pub struct PegOutNonceKey;
 
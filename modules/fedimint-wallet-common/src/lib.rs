@@ -22,6 +22,7 @@ use crate::txoproof::{PegInProof, PegInProofError};
 pub mod config;
 pub mod db;
 pub mod keys;
+pub mod taproot;
 pub mod tweakable;
 pub mod txoproof;
 
@@ -30,6 +31,13 @@ const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
 
 pub const CONFIRMATION_TARGET: u16 = 10;
 
+/// Capability of a token scoped to the wallet to set our fee rate floor
+pub const FEES_CAPABILITY: &str = "fees";
+
+/// Capability of a token scoped to the wallet to request the migration of
+/// peg-ins to the taproot descriptor
+pub const DESCRIPTOR_CAPABILITY: &str = "descriptor";
+
 pub type PartialSig = Vec<u8>;
 
 pub type PegInDescriptor = Descriptor<CompressedPublicKey>;
@@ -42,6 +50,13 @@ pub enum WalletConsensusItem {
                       * * verification logic */
     Feerate(Feerate),
     PegOutSignature(PegOutSignatureItem),
+    TaprootPegOutSignature(TaprootPegOutSignatureItem),
+    /// Our admin requested migrating peg-ins to the taproot descriptor, which
+    /// happens once all guardians approved
+    ApproveTaprootMigration,
+    /// Our admin requested bumping the fees of a stuck peg-out, which is
+    /// replaced once a threshold of guardians voted for it
+    PegOutFeeBump(PegOutFeeBumpItem),
 }
 
 impl std::fmt::Display for WalletConsensusItem {
@@ -60,6 +75,23 @@ impl std::fmt::Display for WalletConsensusItem {
             WalletConsensusItem::PegOutSignature(sig) => {
                 write!(f, "Wallet PegOut signature for Bitcoin TxId {}", sig.txid)
             }
+            WalletConsensusItem::TaprootPegOutSignature(sig) => {
+                write!(
+                    f,
+                    "Wallet taproot PegOut signature for Bitcoin TxId {}",
+                    sig.txid
+                )
+            }
+            WalletConsensusItem::ApproveTaprootMigration => {
+                write!(f, "Wallet taproot migration approval")
+            }
+            WalletConsensusItem::PegOutFeeBump(bump) => {
+                write!(
+                    f,
+                    "Wallet PegOut fee bump to {} sats per kvb for Bitcoin TxId {}",
+                    bump.fee_rate.sats_per_kvb, bump.txid
+                )
+            }
         }
     }
 }
@@ -70,6 +102,31 @@ pub struct PegOutSignatureItem {
     pub signature: Vec<secp256k1::ecdsa::Signature>,
 }
 
+/// The signatures of a guardian for a peg-out spending UTXOs locked to the
+/// taproot descriptor, with one signature per input of the kind the descriptor
+/// of its UTXO requires
+#[derive(Clone, Debug, Serialize, Deserialize, Encodable, Decodable)]
+pub struct TaprootPegOutSignatureItem {
+    pub txid: Txid,
+    pub signature: Vec<PegOutInputSignature>,
+}
+
+/// A vote to replace a pending peg-out with one paying the fee rate
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PegOutFeeBumpItem {
+    pub txid: Txid,
+    pub fee_rate: Feerate,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum PegOutInputSignature {
+    /// Signature of an input spending a UTXO locked to the legacy descriptor
+    Ecdsa(secp256k1::ecdsa::Signature),
+    /// Signature of an input spending a UTXO locked to the taproot descriptor
+    /// along its script path
+    Schnorr(secp256k1::schnorr::Signature),
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
 pub struct SpendableUTXO {
     #[serde(with = "::fedimint_core::encoding::as_hex")]
@@ -84,9 +141,11 @@ pub struct PendingTransaction {
     pub tx: Transaction,
     pub tweak: [u8; 32],
     pub change: bitcoin::Amount,
+    /// The recipient of the first peg-out if the transaction batches several
     pub destination: Script,
     pub fees: PegOutFees,
     pub selected_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+    /// The total amount of all peg-outs of the transaction
     pub peg_out_amount: Amount,
     pub rbf: Option<Rbf>,
 }
@@ -115,8 +174,10 @@ pub struct UnsignedTransaction {
     pub signatures: Vec<(PeerId, PegOutSignatureItem)>,
     pub change: bitcoin::Amount,
     pub fees: PegOutFees,
+    /// The recipient of the first peg-out if the transaction batches several
     pub destination: Script,
     pub selected_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+    /// The total amount of all peg-outs of the transaction
     pub peg_out_amount: Amount,
     pub rbf: Option<Rbf>,
 }
@@ -209,6 +270,23 @@ pub enum WalletOutput {
     Rbf(Rbf),
 }
 
+/// The health of the bitcoin backend of a guardian as checked periodically,
+/// surfaced on the status endpoint of the guardian
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BitcoinBackendHealth {
+    /// The kind of backend, e.g. `bitcoind`, `esplora` or `electrum`
+    pub kind: String,
+    /// The block count reported by the last successful check
+    pub block_count: Option<u64>,
+    /// The fee rate estimated by the last successful check, `None` if the
+    /// backend does not estimate fees yet
+    pub fee_rate: Option<Feerate>,
+    /// Seconds since the unix epoch of the last successful check
+    pub last_success: Option<u64>,
+    /// Why the last check failed, `None` if it succeeded
+    pub error: Option<String>,
+}
+
 /// Allows a user to bump the fees of a `PendingTransaction`
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct Rbf {
@@ -265,6 +343,26 @@ impl PartialEq for PegOutSignatureItem {
 
 impl Eq for PegOutSignatureItem {}
 
+impl std::hash::Hash for TaprootPegOutSignatureItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.txid.hash(state);
+        for sig in self.signature.iter() {
+            match sig {
+                PegOutInputSignature::Ecdsa(sig) => sig.serialize_der().hash(state),
+                PegOutInputSignature::Schnorr(sig) => sig[..].hash(state),
+            }
+        }
+    }
+}
+
+impl PartialEq for TaprootPegOutSignatureItem {
+    fn eq(&self, other: &TaprootPegOutSignatureItem) -> bool {
+        self.txid == other.txid && self.signature == other.signature
+    }
+}
+
+impl Eq for TaprootPegOutSignatureItem {}
+
 plugin_types_trait_impl_common!(
     WalletModuleTypes,
     WalletClientConfig,
@@ -300,6 +398,10 @@ pub enum WalletError {
     TxWeightIncorrect(u64, u64),
     #[error("Peg-out fee rate is below min relay fee")]
     BelowMinRelayFee,
+    #[error("Peg-out batch is empty")]
+    EmptyPegOutBatch,
+    #[error("The peg-in of {0} requires {1} confirmations")]
+    PegInNotConfirmed(bitcoin::Amount, u32),
 }
 
 #[derive(Debug, Error)]
@@ -316,6 +418,8 @@ pub enum ProcessPegOutSigError {
     InvalidSignature,
     #[error("Duplicate signature")]
     DuplicateSignature,
+    #[error("Signature of the wrong kind for the UTXO spent by input {0}")]
+    WrongSignatureKind(usize),
     #[error("Missing change tweak")]
     MissingOrMalformedChangeTweak,
     #[error("Error finalizing PSBT {0:?}")]
@@ -0,0 +1,273 @@
+//! Taproot peg-in descriptors
+//!
+//! The taproot descriptor locks peg-ins to an internal key aggregating the
+//! peg-in keys of all guardians with the MuSig2 key aggregation (BIP-327), plus
+//! a single script leaf requiring the signatures of a threshold of guardians.
+//!
+//! Federations cannot select the taproot descriptor yet. Only the key
+//! aggregation of MuSig2 is implemented, the wallet does not run the MuSig2
+//! nonce and partial signature rounds, so peg-outs would have to be signed
+//! along the script path and reveal the `multi_a` leaf on chain. Until the
+//! federation can spend along the key path, see
+//! [`PegInDescriptorKind::is_selectable`], peg-ins stay locked to the P2WSH
+//! multisig and the migration from the legacy descriptor is disabled.
+//!
+//! Once selectable, federations that started with the legacy P2WSH multisig
+//! can migrate to the taproot descriptor, after which the UTXOs locked to the
+//! legacy descriptor are still spent with it, see
+//! [`WalletConfigConsensus::legacy_peg_in_descriptor`](crate::config::WalletConfigConsensus::legacy_peg_in_descriptor).
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::bail;
+use bitcoin::hashes::{sha256, Hash as BitcoinHash, HashEngine};
+use fedimint_core::PeerId;
+use miniscript::descriptor::{TapTree, Wsh};
+use miniscript::{Descriptor, Miniscript, Tap, Terminal};
+use secp256k1::{PublicKey, Scalar, Secp256k1, Verification};
+use serde::{Deserialize, Serialize};
+
+use crate::keys::CompressedPublicKey;
+use crate::PegInDescriptor;
+
+/// The kind of descriptor the federation locks peg-ins to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PegInDescriptorKind {
+    /// P2WSH `sortedmulti` of the peg-in keys
+    #[default]
+    Wsh,
+    /// P2TR with the MuSig2 aggregate of the peg-in keys as internal key and a
+    /// `multi_a` leaf of the peg-in keys, not selectable yet
+    Tr,
+}
+
+impl PegInDescriptorKind {
+    pub fn descriptor(
+        &self,
+        threshold: usize,
+        pubkeys: &BTreeMap<PeerId, CompressedPublicKey>,
+    ) -> PegInDescriptor {
+        let mut keys = pubkeys.values().copied().collect::<Vec<_>>();
+
+        match self {
+            PegInDescriptorKind::Wsh => {
+                PegInDescriptor::Wsh(Wsh::new_sortedmulti(threshold, keys).unwrap())
+            }
+            PegInDescriptorKind::Tr => {
+                keys.sort();
+
+                let internal_key = CompressedPublicKey::new(musig_key_agg(
+                    secp256k1::SECP256K1,
+                    &keys.iter().map(|key| key.key).collect::<Vec<_>>(),
+                ));
+
+                let leaf = Miniscript::<CompressedPublicKey, Tap>::from_ast(Terminal::MultiA(
+                    threshold, keys,
+                ))
+                .expect("Threshold does not exceed the number of keys");
+
+                Descriptor::new_tr(internal_key, Some(TapTree::Leaf(Arc::new(leaf))))
+                    .expect("Descriptor with a single leaf is valid")
+            }
+        }
+    }
+
+    /// Whether federations may lock peg-ins to the descriptor
+    ///
+    /// The taproot descriptor only becomes selectable once peg-outs spend
+    /// along its MuSig2 key path, which requires the MuSig2 signing rounds.
+    pub fn is_selectable(&self) -> bool {
+        match self {
+            PegInDescriptorKind::Wsh => true,
+            PegInDescriptorKind::Tr => false,
+        }
+    }
+
+    /// The kind of the descriptor, if it is one we lock peg-ins to
+    pub fn of(descriptor: &PegInDescriptor) -> Option<Self> {
+        match descriptor {
+            Descriptor::Wsh(_) => Some(PegInDescriptorKind::Wsh),
+            Descriptor::Tr(_) => Some(PegInDescriptorKind::Tr),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for PegInDescriptorKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wsh" => Ok(PegInDescriptorKind::Wsh),
+            "tr" => bail!("Taproot peg-in descriptors are not supported yet"),
+            _ => bail!("Unknown peg-in descriptor kind {s}, expected wsh"),
+        }
+    }
+}
+
+/// Aggregates the keys with MuSig2 (BIP-327) after sorting them, such that the
+/// aggregate key does not depend on their order
+pub fn musig_key_agg<C: Verification>(secp: &Secp256k1<C>, keys: &[PublicKey]) -> PublicKey {
+    let mut keys = keys.to_vec();
+    keys.sort_by_key(PublicKey::serialize);
+
+    key_agg(secp, &keys)
+}
+
+/// The `KeyAgg` algorithm of BIP-327, which depends on the order of the keys
+fn key_agg<C: Verification>(secp: &Secp256k1<C>, keys: &[PublicKey]) -> PublicKey {
+    let mut engine = tagged_engine("KeyAgg list");
+    for key in keys {
+        engine.input(&key.serialize());
+    }
+    let list_hash = sha256::Hash::from_engine(engine);
+
+    // the coefficient of the second distinct key is one
+    let second_key = keys.iter().find(|key| **key != keys[0]).copied();
+
+    let terms = keys
+        .iter()
+        .map(|key| {
+            if Some(*key) == second_key {
+                return *key;
+            }
+
+            let mut engine = tagged_engine("KeyAgg coefficient");
+            engine.input(&list_hash[..]);
+            engine.input(&key.serialize());
+            let coefficient = Scalar::from_be_bytes(sha256::Hash::from_engine(engine).into_inner())
+                .expect("Hash exceeds the curve order with negligible probability");
+
+            key.mul_tweak(secp, &coefficient)
+                .expect("Coefficient is zero with negligible probability")
+        })
+        .collect::<Vec<_>>();
+
+    PublicKey::combine_keys(&terms.iter().collect::<Vec<_>>())
+        .expect("Aggregate key is infinity with negligible probability")
+}
+
+/// Engine of the tagged hash with the tag as defined in BIP-340
+fn tagged_engine(tag: &str) -> sha256::HashEngine {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use bitcoin::Network;
+    use fedimint_core::module::__reexports::serde_json;
+    use fedimint_core::PeerId;
+    use rand::rngs::OsRng;
+    use secp256k1::{PublicKey, XOnlyPublicKey};
+
+    use super::{key_agg, musig_key_agg, PegInDescriptorKind};
+    use crate::keys::CompressedPublicKey;
+    use crate::tweakable::Tweakable;
+
+    #[test]
+    fn key_aggregation_does_not_depend_on_order() {
+        let secp = secp256k1::Secp256k1::new();
+        let mut keys = (0..4)
+            .map(|_| secp.generate_keypair(&mut OsRng).1)
+            .collect::<Vec<_>>();
+
+        let aggregate = musig_key_agg(&secp, &keys);
+        keys.reverse();
+        assert_eq!(aggregate, musig_key_agg(&secp, &keys));
+
+        // the coefficients keep the aggregate from being the plain sum of the keys
+        let sum = secp256k1::PublicKey::combine_keys(&keys.iter().collect::<Vec<_>>()).unwrap();
+        assert_ne!(aggregate, sum);
+        assert_ne!(aggregate, musig_key_agg(&secp, &keys[..3]));
+    }
+
+    /// The valid test vectors of `key_agg_vectors.json` in BIP-327
+    #[test]
+    fn key_aggregation_matches_bip327_vectors() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let pubkeys = [
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+        ]
+        .map(|key| PublicKey::from_str(key).unwrap());
+
+        let vectors: [(&[usize], &str); 4] = [
+            (
+                &[0, 1, 2],
+                "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C",
+            ),
+            (
+                &[2, 1, 0],
+                "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B",
+            ),
+            (
+                &[0, 0, 0],
+                "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935",
+            ),
+            (
+                &[0, 0, 1, 1],
+                "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E",
+            ),
+        ];
+
+        for (key_indices, expected) in vectors {
+            let keys = key_indices
+                .iter()
+                .map(|index| pubkeys[*index])
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                key_agg(&secp, &keys).x_only_public_key().0,
+                XOnlyPublicKey::from_str(expected).unwrap()
+            );
+        }
+
+        // sorting the keys is the KeySort algorithm of BIP-327
+        assert_eq!(
+            musig_key_agg(&secp, &pubkeys),
+            key_agg(&secp, &[pubkeys[2], pubkeys[0], pubkeys[1]])
+        );
+    }
+
+    #[test]
+    fn taproot_descriptor_tweaks_to_taproot_address() {
+        let secp = secp256k1::Secp256k1::new();
+        let pubkeys = (0..4)
+            .map(|peer| {
+                let key = CompressedPublicKey::new(secp.generate_keypair(&mut OsRng).1);
+                (PeerId::from(peer), key)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let descriptor = PegInDescriptorKind::Tr.descriptor(3, &pubkeys);
+        assert_eq!(
+            PegInDescriptorKind::of(&descriptor),
+            Some(PegInDescriptorKind::Tr)
+        );
+        assert!(descriptor.max_satisfaction_weight().is_ok());
+
+        let tweaked = descriptor.tweak(&[42; 32], &secp);
+        assert!(tweaked.script_pubkey().is_v1_p2tr());
+        assert_ne!(tweaked.script_pubkey(), descriptor.script_pubkey());
+        assert!(tweaked.address(Network::Regtest).is_ok());
+
+        // the descriptor survives the round trip through the config
+        let serialized = serde_json::to_string(&descriptor).unwrap();
+        assert_eq!(
+            descriptor,
+            serde_json::from_str::<crate::PegInDescriptor>(&serialized).unwrap()
+        );
+    }
+}
@@ -1,28 +1,36 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::{Infallible, TryInto};
-#[cfg(not(target_family = "wasm"))]
+use std::sync::Arc;
 use std::time::Duration;
+use std::time::UNIX_EPOCH;
 
 use anyhow::{bail, format_err, Context};
 use bitcoin::hashes::{sha256, Hash as BitcoinHash, HashEngine, Hmac, HmacEngine};
 use bitcoin::policy::DEFAULT_MIN_RELAY_TX_FEE;
 use bitcoin::secp256k1::{All, Secp256k1, Verification};
 use bitcoin::util::psbt::{Input, PartiallySignedTransaction};
-use bitcoin::util::sighash::SighashCache;
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
 use bitcoin::{
-    Address, BlockHash, EcdsaSig, EcdsaSighashType, Network, PackedLockTime, Script, Sequence,
-    Transaction, TxIn, TxOut, Txid,
+    Address, BlockHash, EcdsaSig, EcdsaSighashType, Network, PackedLockTime, SchnorrSig,
+    SchnorrSighashType, Script, Sequence, Transaction, TxIn, TxOut, Txid,
 };
 use common::config::WalletConfigConsensus;
 use common::db::{
-    BlockCountVoteKey, BlockCountVotePrefix, DbKeyPrefix, FeeRateVoteKey, FeeRateVotePrefix,
-    PegOutNonceKey,
+    BlockCountVoteKey, BlockCountVotePrefix, BlockHeightKey, BlockHeightPrefix, DbKeyPrefix,
+    FeeRateFloorKey, FeeRateVoteKey, FeeRateVotePrefix, MigratedUTXOKey, MigratedUTXOPrefixKey,
+    PegOutFeeBumpRequestKey, PegOutFeeBumpRequestPrefix, PegOutFeeBumpVoteKey,
+    PegOutFeeBumpVotePrefix, PegOutFeeBumpVoteTxidPrefix, PegOutFeeKey, PegOutFeePrefix,
+    PegOutNonceKey, QueuedPegOutKey, QueuedPegOutPrefix, TaprootMigrationApprovalKey,
+    TaprootMigrationApprovalPrefix, TaprootMigrationRequestKey, TaprootPegOutTxSignatureCI,
+    TaprootPegOutTxSignatureCIPrefix,
 };
 use common::{
-    proprietary_tweak_key, PegOutFees, PegOutSignatureItem, PendingTransaction,
-    ProcessPegOutSigError, SpendableUTXO, UnsignedTransaction, WalletCommonGen,
-    WalletConsensusItem, WalletError, WalletInput, WalletModuleTypes, WalletOutput,
-    WalletOutputOutcome, CONFIRMATION_TARGET,
+    proprietary_tweak_key, BitcoinBackendHealth, PegInDescriptor, PegOut, PegOutFeeBumpItem,
+    PegOutFees, PegOutInputSignature, PegOutSignatureItem, PendingTransaction,
+    ProcessPegOutSigError, SpendableUTXO, TaprootPegOutSignatureItem, UnsignedTransaction,
+    WalletCommonGen, WalletConsensusItem, WalletError, WalletInput, WalletModuleTypes,
+    WalletOutput, WalletOutputOutcome, CONFIRMATION_TARGET, DESCRIPTOR_CAPABILITY, FEES_CAPABILITY,
 };
 use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
 use fedimint_core::config::{
@@ -36,18 +44,21 @@ use fedimint_core::db::{
 };
 use fedimint_core::encoding::Encodable;
 use fedimint_core::endpoint_constants::{
-    BLOCK_COUNT_ENDPOINT, BLOCK_COUNT_LOCAL_ENDPOINT, PEG_OUT_FEES_ENDPOINT,
+    BLOCK_COUNT_ENDPOINT, BLOCK_COUNT_LOCAL_ENDPOINT, BUMP_PEG_OUT_FEE_ENDPOINT,
+    MIGRATE_TO_TAPROOT_ENDPOINT, PEG_OUT_FEES_ENDPOINT, SET_FEE_RATE_FLOOR_ENDPOINT,
+    TAPROOT_MIGRATION_ENDPOINT,
 };
+use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::{
-    api_endpoint, ApiEndpoint, CoreConsensusVersion, ExtendsCommonModuleInit, InputMeta,
-    IntoModuleError, ModuleConsensusVersion, ModuleError, PeerHandle, ServerModuleInit,
-    ServerModuleInitArgs, SupportedModuleApiVersions, TransactionItemAmount,
+    api_endpoint, ApiEndpoint, ApiError, CoreConsensusVersion, ExtendsCommonModuleInit, InputMeta,
+    IntoModuleError, ModuleConsensusVersion, ModuleError, ModuleHealth, PeerHandle,
+    ServerModuleInit, ServerModuleInitArgs, SupportedModuleApiVersions, TransactionItemAmount,
 };
 use fedimint_core::server::DynServerModule;
 #[cfg(not(target_family = "wasm"))]
 use fedimint_core::task::sleep;
-use fedimint_core::task::{TaskGroup, TaskHandle};
+use fedimint_core::task::{timeout, TaskGroup, TaskHandle};
 use fedimint_core::{
     apply, async_trait_maybe_send, push_db_key_items, push_db_pair_items, Feerate, NumPeers,
     OutPoint, PeerId, ServerModule,
@@ -62,13 +73,14 @@ use fedimint_wallet_common::db::{
     UnsignedTransactionPrefixKey,
 };
 use fedimint_wallet_common::keys::CompressedPublicKey;
+use fedimint_wallet_common::taproot::PegInDescriptorKind;
 use fedimint_wallet_common::tweakable::Tweakable;
 use fedimint_wallet_common::Rbf;
 use futures::StreamExt;
 use miniscript::psbt::PsbtExt;
 use miniscript::{translate_hash_fail, Descriptor, TranslatePk};
 use rand::rngs::OsRng;
-use secp256k1::{Message, Scalar};
+use secp256k1::{KeyPair, Message, Scalar};
 use strum::IntoEnumIterator;
 use tracing::{debug, info, instrument, trace, warn};
 
@@ -170,6 +182,95 @@ impl ExtendsCommonModuleInit for WalletGen {
                         "Fee Rate Votes"
                     );
                 }
+
+                DbKeyPrefix::FeeRateFloor => {
+                    if let Some(floor) = dbtx.get_value(&FeeRateFloorKey).await {
+                        wallet.insert("Fee Rate Floor".to_string(), Box::new(floor));
+                    }
+                }
+                DbKeyPrefix::TaprootPegOutTxSigCi => {
+                    push_db_pair_items!(
+                        dbtx,
+                        TaprootPegOutTxSignatureCIPrefix,
+                        TaprootPegOutTxSignatureCI,
+                        Vec<PegOutInputSignature>,
+                        wallet,
+                        "Taproot Peg Out Transaction Signatures"
+                    );
+                }
+                DbKeyPrefix::MigratedUtxo => {
+                    push_db_key_items!(
+                        dbtx,
+                        MigratedUTXOPrefixKey,
+                        MigratedUTXOKey,
+                        wallet,
+                        "Migrated UTXOs"
+                    );
+                }
+                DbKeyPrefix::TaprootMigrationRequest => {
+                    if dbtx.get_value(&TaprootMigrationRequestKey).await.is_some() {
+                        wallet.insert("Taproot Migration Request".to_string(), Box::new(()));
+                    }
+                }
+                DbKeyPrefix::TaprootMigrationApproval => {
+                    push_db_key_items!(
+                        dbtx,
+                        TaprootMigrationApprovalPrefix,
+                        TaprootMigrationApprovalKey,
+                        wallet,
+                        "Taproot Migration Approvals"
+                    );
+                }
+                DbKeyPrefix::PegOutFeeBumpRequest => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutFeeBumpRequestPrefix,
+                        PegOutFeeBumpRequestKey,
+                        Feerate,
+                        wallet,
+                        "Peg Out Fee Bump Requests"
+                    );
+                }
+                DbKeyPrefix::PegOutFeeBumpVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutFeeBumpVotePrefix,
+                        PegOutFeeBumpVoteKey,
+                        Feerate,
+                        wallet,
+                        "Peg Out Fee Bump Votes"
+                    );
+                }
+                DbKeyPrefix::QueuedPegOut => {
+                    push_db_pair_items!(
+                        dbtx,
+                        QueuedPegOutPrefix,
+                        QueuedPegOutKey,
+                        PegOut,
+                        wallet,
+                        "Queued Peg Outs"
+                    );
+                }
+                DbKeyPrefix::PegOutFee => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutFeePrefix,
+                        PegOutFeeKey,
+                        bitcoin::Amount,
+                        wallet,
+                        "Peg Out Fees"
+                    );
+                }
+                DbKeyPrefix::BlockHeight => {
+                    push_db_pair_items!(
+                        dbtx,
+                        BlockHeightPrefix,
+                        BlockHeightKey,
+                        u32,
+                        wallet,
+                        "Block Heights"
+                    );
+                }
             }
         }
 
@@ -201,6 +302,16 @@ impl ServerModuleInit for WalletGen {
         .into())
     }
 
+    fn parse_params(&self, params: &ConfigGenModuleParams) -> anyhow::Result<WalletGenParams> {
+        let params = params.to_typed::<WalletGenParams>()?;
+
+        if !params.consensus.peg_in_descriptor_kind.is_selectable() {
+            bail!("Taproot peg-in descriptors are not supported yet");
+        }
+
+        Ok(params)
+    }
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -228,6 +339,8 @@ impl ServerModuleInit for WalletGen {
                     params.consensus.finality_delay,
                     params.local.bitcoin_rpc.clone(),
                     params.consensus.client_default_bitcoin_rpc.clone(),
+                    params.consensus.peg_in_descriptor_kind,
+                    params.consensus.peg_in_confirmation_tiers.clone(),
                 );
                 (*id, cfg)
             })
@@ -246,7 +359,7 @@ impl ServerModuleInit for WalletGen {
     ) -> DkgResult<ServerModuleConfig> {
         let params = self.parse_params(params).unwrap();
         let secp = secp256k1::Secp256k1::new();
-        let (sk, pk) = secp.generate_keypair(&mut OsRng);
+        let (sk, pk) = secp.generate_keypair(&mut peers.dkg_rng("wallet"));
         let our_key = CompressedPublicKey { key: pk };
         let peer_peg_in_keys: BTreeMap<PeerId, CompressedPublicKey> = peers
             .exchange_pubkeys("wallet".to_string(), our_key.key)
@@ -263,11 +376,43 @@ impl ServerModuleInit for WalletGen {
             params.consensus.finality_delay,
             params.local.bitcoin_rpc.clone(),
             params.consensus.client_default_bitcoin_rpc.clone(),
+            params.consensus.peg_in_descriptor_kind,
+            params.consensus.peg_in_confirmation_tiers,
         );
 
         Ok(wallet_cfg.to_erased())
     }
 
+    /// Migrates peg-ins to the taproot descriptor once all guardians approved
+    async fn update_config(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        _identity: &PeerId,
+        config: &ServerModuleConfig,
+    ) -> anyhow::Result<Option<ServerModuleConfig>> {
+        let typed = config.to_typed::<WalletConfig>()?;
+
+        let Some(consensus) = typed.consensus.migrated_to_taproot() else {
+            return Ok(None);
+        };
+
+        let approvals = dbtx
+            .find_by_prefix(&TaprootMigrationApprovalPrefix)
+            .await
+            .map(|(key, _)| key.0)
+            .collect::<BTreeSet<_>>()
+            .await;
+
+        if !approvals.iter().eq(typed.consensus.peer_peg_in_keys.keys()) {
+            return Ok(None);
+        }
+
+        let mut updated = WalletConfig { consensus, ..typed }.to_erased();
+        updated.consensus.version = config.consensus.version;
+
+        Ok(Some(updated))
+    }
+
     fn validate_config(&self, identity: &PeerId, config: ServerModuleConfig) -> anyhow::Result<()> {
         let config = config.to_typed::<WalletConfig>()?;
         let pubkey = secp256k1::PublicKey::from_secret_key_global(&config.private.peg_in_key);
@@ -282,6 +427,18 @@ impl ServerModuleInit for WalletGen {
             bail!(" Bitcoin wallet private key doesn't match multisig pubkey");
         }
 
+        if let Some(tier) = config
+            .consensus
+            .peg_in_confirmation_tiers
+            .iter()
+            .find(|tier| tier.confirmations < config.consensus.finality_delay)
+        {
+            bail!(
+                "Peg-ins of {} can not require fewer confirmations than the finality delay",
+                tier.min_amount
+            );
+        }
+
         Ok(())
     }
 
@@ -292,9 +449,11 @@ impl ServerModuleInit for WalletGen {
         let config = WalletConfigConsensus::from_erased(config)?;
         Ok(WalletClientConfig {
             peg_in_descriptor: config.peg_in_descriptor,
+            legacy_peg_in_descriptor: config.legacy_peg_in_descriptor,
             network: config.network,
             fee_consensus: config.fee_consensus,
             finality_delay: config.finality_delay,
+            peg_in_confirmation_tiers: config.peg_in_confirmation_tiers,
             default_bitcoin_rpc: config.client_default_bitcoin_rpc,
         })
     }
@@ -321,6 +480,55 @@ impl ServerModule for Wallet {
             .collect::<Vec<WalletConsensusItem>>()
             .await;
 
+        items.extend(
+            dbtx.find_by_prefix(&TaprootPegOutTxSignatureCIPrefix)
+                .await
+                .map(|(key, val)| {
+                    WalletConsensusItem::TaprootPegOutSignature(TaprootPegOutSignatureItem {
+                        txid: key.0,
+                        signature: val,
+                    })
+                })
+                .collect::<Vec<WalletConsensusItem>>()
+                .await,
+        );
+
+        if self.cfg.consensus.migrated_to_taproot().is_some()
+            && dbtx.get_value(&TaprootMigrationRequestKey).await.is_some()
+            && dbtx
+                .get_value(&TaprootMigrationApprovalKey(self.our_peer_id))
+                .await
+                .is_none()
+        {
+            items.push(WalletConsensusItem::ApproveTaprootMigration);
+        }
+
+        let fee_bump_requests = dbtx
+            .find_by_prefix(&PegOutFeeBumpRequestPrefix)
+            .await
+            .map(|(key, fee_rate)| (key.0, fee_rate))
+            .collect::<Vec<_>>()
+            .await;
+
+        for (txid, fee_rate) in fee_bump_requests {
+            let current_vote = dbtx
+                .get_value(&PegOutFeeBumpVoteKey {
+                    txid,
+                    peer: self.our_peer_id,
+                })
+                .await;
+
+            if current_vote != Some(fee_rate)
+                && dbtx.get_value(&PendingTransactionKey(txid)).await.is_some()
+                && !self.is_being_replaced(dbtx, txid).await
+            {
+                items.push(WalletConsensusItem::PegOutFeeBump(PegOutFeeBumpItem {
+                    txid,
+                    fee_rate,
+                }));
+            }
+        }
+
         // TODO: We should not be panicking
         let block_count = self.get_block_count().await.expect("bitcoind rpc failed");
         let block_count_proposal = block_count.saturating_sub(self.cfg.consensus.finality_delay);
@@ -345,7 +553,11 @@ impl ServerModule for Wallet {
             .await
             .unwrap_or(self.cfg.consensus.default_fee);
         // TODO: We should not be panicking
-        let fee_rate_proposal = self.get_fee_rate().await.expect("bitcoind rpc failed");
+        let fee_rate = self.get_fee_rate().await.expect("bitcoind rpc failed");
+        let fee_rate_proposal = dbtx
+            .get_value(&FeeRateFloorKey)
+            .await
+            .map_or(fee_rate, |floor| fee_rate.max(floor));
 
         if fee_rate_proposal != current_fee_rate_vote {
             items.push(WalletConsensusItem::Feerate(fee_rate_proposal));
@@ -354,6 +566,22 @@ impl ServerModule for Wallet {
         items
     }
 
+    /// The signature shares of a peg-out are classed by its transaction, since
+    /// a threshold of them finalizes it and the rest would be discarded
+    fn consensus_item_class(&self, consensus_item: &WalletConsensusItem) -> Option<u64> {
+        let txid = match consensus_item {
+            WalletConsensusItem::PegOutSignature(signature) => signature.txid,
+            WalletConsensusItem::TaprootPegOutSignature(signature) => signature.txid,
+            _ => return None,
+        };
+
+        Some(u64::from_be_bytes(
+            txid.into_inner()[..8]
+                .try_into()
+                .expect("Txid has 32 bytes"),
+        ))
+    }
+
     async fn process_consensus_item<'a, 'b>(
         &'a self,
         dbtx: &mut DatabaseTransactionRef<'b>,
@@ -424,35 +652,40 @@ impl ServerModule for Wallet {
                 }
             }
             WalletConsensusItem::PegOutSignature(peg_out_signature) => {
-                let txid = peg_out_signature.txid;
-
-                if dbtx.get_value(&PendingTransactionKey(txid)).await.is_some() {
-                    bail!("Already received a threshold of valid signatures");
+                let signature = peg_out_signature
+                    .signature
+                    .into_iter()
+                    .map(PegOutInputSignature::Ecdsa)
+                    .collect::<Vec<_>>();
+
+                self.process_peg_out_signature(dbtx, peer_id, peg_out_signature.txid, &signature)
+                    .await?;
+            }
+            WalletConsensusItem::TaprootPegOutSignature(peg_out_signature) => {
+                self.process_peg_out_signature(
+                    dbtx,
+                    peer_id,
+                    peg_out_signature.txid,
+                    &peg_out_signature.signature,
+                )
+                .await?;
+            }
+            WalletConsensusItem::ApproveTaprootMigration => {
+                if self.cfg.consensus.migrated_to_taproot().is_none() {
+                    bail!("Peg-ins are not locked to the legacy descriptor");
                 }
 
-                let mut unsigned = dbtx
-                    .get_value(&UnsignedTransactionKey(txid))
+                if dbtx
+                    .insert_entry(&TaprootMigrationApprovalKey(peer_id), &())
                     .await
-                    .context("Unsigned transaction does not exist")?;
-
-                self.sign_peg_out_psbt(&mut unsigned.psbt, &peer_id, &peg_out_signature)
-                    .context("Peg out signature is invalid")?;
-
-                dbtx.insert_entry(&UnsignedTransactionKey(txid), &unsigned)
-                    .await;
-
-                if let Ok(pending_tx) = self.finalize_peg_out_psbt(unsigned) {
-                    // We were able to finalize the transaction, so we will delete the
-                    // PSBT and instead keep the extracted tx for periodic transmission
-                    // as well as to accept the change into our wallet eventually once
-                    // it confirms.
-                    dbtx.insert_new_entry(&PendingTransactionKey(txid), &pending_tx)
-                        .await;
-
-                    dbtx.remove_entry(&PegOutTxSignatureCI(txid)).await;
-                    dbtx.remove_entry(&UnsignedTransactionKey(txid)).await;
+                    .is_some()
+                {
+                    bail!("Taproot migration approval is redundant");
                 }
             }
+            WalletConsensusItem::PegOutFeeBump(bump) => {
+                self.process_peg_out_fee_bump(dbtx, peer_id, bump).await?;
+            }
         }
 
         Ok(())
@@ -468,9 +701,26 @@ impl ServerModule for Wallet {
                 .into_module_error_other();
         }
 
-        input
-            .verify(&self.secp, &self.cfg.consensus.peg_in_descriptor)
-            .into_module_error_other()?;
+        let amount = bitcoin::Amount::from_sat(input.tx_output().value);
+        if !self
+            .peg_in_is_confirmed(dbtx, input.proof_block(), amount)
+            .await
+        {
+            return Err(WalletError::PegInNotConfirmed(
+                amount,
+                self.cfg.consensus.peg_in_confirmations(amount),
+            ))
+            .into_module_error_other();
+        }
+
+        // clients may still claim deposits to addresses of the legacy descriptor
+        let migrated = match input.verify(&self.secp, &self.cfg.consensus.peg_in_descriptor) {
+            Ok(()) => self.cfg.consensus.legacy_peg_in_descriptor.is_some(),
+            Err(error) => match &self.cfg.consensus.legacy_peg_in_descriptor {
+                Some(legacy) if input.verify(&self.secp, legacy).is_ok() => false,
+                _ => return Err(error).into_module_error_other(),
+            },
+        };
 
         debug!(outpoint = %input.outpoint(), "Claiming peg-in");
 
@@ -488,12 +738,18 @@ impl ServerModule for Wallet {
             return Err(WalletError::PegInAlreadyClaimed).into_module_error_other();
         }
 
+        if migrated {
+            dbtx.insert_entry(&MigratedUTXOKey(input.outpoint()), &())
+                .await;
+        }
+
         Ok(InputMeta {
             amount: TransactionItemAmount {
                 amount: fedimint_core::Amount::from_sats(input.tx_output().value),
                 fee: self.cfg.consensus.fee_consensus.peg_in_abs,
             },
             pub_keys: vec![*input.tweak_contract_key()],
+            fee_payout: fedimint_core::Amount::ZERO,
         })
     }
 
@@ -503,70 +759,58 @@ impl ServerModule for Wallet {
         output: &'a WalletOutput,
         out_point: OutPoint,
     ) -> Result<TransactionItemAmount, ModuleError> {
-        let change_tweak = self.consensus_nonce(dbtx).await;
-
-        let mut tx = self
-            .create_peg_out_tx(dbtx, output, &change_tweak)
-            .await
-            .into_module_error_other()?;
-
         let fee_rate = self.consensus_fee_rate(dbtx).await;
 
-        self.offline_wallet()
-            .validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)
-            .into_module_error_other()?;
-
-        self.offline_wallet().sign_psbt(&mut tx.psbt);
-
-        let txid = tx.psbt.unsigned_tx.txid();
+        match output {
+            WalletOutput::PegOut(peg_out) => {
+                // The fees are quoted for a transaction paying the peg-out alone, which
+                // does not depend on the change tweak
+                let tx = self
+                    .create_peg_out_tx(dbtx, output, &[0; 32])
+                    .await
+                    .into_module_error_other()?;
 
-        info!(
-            %txid,
-            "Signing peg out",
-        );
+                self.offline_wallet()
+                    .validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)
+                    .into_module_error_other()?;
 
-        let sigs = tx
-            .psbt
-            .inputs
-            .iter_mut()
-            .map(|input| {
-                assert_eq!(
-                    input.partial_sigs.len(),
-                    1,
-                    "There was already more than one (our) or no signatures in input"
-                );
+                // Ensure we can still fund the batch including this peg-out
+                let mut peg_outs = dbtx
+                    .find_by_prefix(&QueuedPegOutPrefix)
+                    .await
+                    .map(|(_, peg_out)| peg_out)
+                    .collect::<Vec<_>>()
+                    .await;
+                peg_outs.push(peg_out.clone());
 
-                // TODO: don't put sig into PSBT in the first place
-                // We actually take out our own signature so everyone finalizes the tx in the
-                // same epoch.
-                let sig = std::mem::take(&mut input.partial_sigs)
-                    .into_values()
-                    .next()
-                    .expect("asserted previously");
+                self.create_peg_out_batch_tx(dbtx, &peg_outs, &[], &[0; 32])
+                    .await
+                    .into_module_error_other()?;
 
-                // We drop SIGHASH_ALL, because we always use that and it is only present in the
-                // PSBT for compatibility with other tools.
-                secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
-                    .expect("we serialized it ourselves that way")
-            })
-            .collect::<Vec<_>>();
+                dbtx.insert_new_entry(&QueuedPegOutKey(out_point), peg_out)
+                    .await;
+            }
+            WalletOutput::Rbf(_) => {
+                let change_tweak = self.consensus_nonce(dbtx).await;
 
-        // Delete used UTXOs
-        for input in tx.psbt.unsigned_tx.input.iter() {
-            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
-        }
+                let tx = self
+                    .create_peg_out_tx(dbtx, output, &change_tweak)
+                    .await
+                    .into_module_error_other()?;
 
-        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
-            .await;
+                self.offline_wallet()
+                    .validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)
+                    .into_module_error_other()?;
 
-        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
-            .await;
+                let txid = self.sign_peg_out_tx(dbtx, tx).await;
 
-        dbtx.insert_new_entry(
-            &PegOutBitcoinTransaction(out_point),
-            &WalletOutputOutcome(txid),
-        )
-        .await;
+                dbtx.insert_new_entry(
+                    &PegOutBitcoinTransaction(out_point),
+                    &WalletOutputOutcome(txid),
+                )
+                .await;
+            }
+        }
 
         Ok(TransactionItemAmount {
             amount: output.amount().into(),
@@ -582,6 +826,38 @@ impl ServerModule for Wallet {
         dbtx.get_value(&PegOutBitcoinTransaction(out_point)).await
     }
 
+    async fn end_session(&self, dbtx: &mut DatabaseTransactionRef<'_>, _session_index: u64) {
+        self.process_queued_peg_outs(dbtx).await;
+
+        // the migration is complete once we run the migrated config
+        if self.cfg.consensus.legacy_peg_in_descriptor.is_some() {
+            dbtx.remove_entry(&TaprootMigrationRequestKey).await;
+            dbtx.remove_by_prefix(&TaprootMigrationApprovalPrefix).await;
+        }
+    }
+
+    /// Our UTXOs with the descriptor they are locked to and the pending
+    /// withdrawals, whether they are still being signed or were broadcast
+    /// already
+    fn committed_state_prefixes(&self) -> Vec<u8> {
+        vec![
+            DbKeyPrefix::Utxo as u8,
+            DbKeyPrefix::MigratedUtxo as u8,
+            DbKeyPrefix::UnsignedTransaction as u8,
+            DbKeyPrefix::PendingTransaction as u8,
+            DbKeyPrefix::QueuedPegOut as u8,
+        ]
+    }
+
+    async fn health(&self) -> Option<ModuleHealth> {
+        let health = self.bitcoin_health.lock().expect("Failed to lock").clone();
+
+        Some(ModuleHealth {
+            healthy: health.error.is_none() && health.last_success.is_some(),
+            details: serde_json::to_value(&health).expect("Can be serialized"),
+        })
+    }
+
     async fn audit(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -615,6 +891,11 @@ impl ServerModule for Wallet {
                 },
             )
             .await;
+        audit
+            .add_items(dbtx, module_instance_id, &QueuedPegOutPrefix, |_, v| {
+                (v.amount + v.fees.amount()).to_sat() as i64 * -1000
+            })
+            .await;
     }
 
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
@@ -641,14 +922,21 @@ impl ServerModule for Wallet {
                     // Since we are only calculating the tx size we can use an arbitrary dummy nonce.
                     let dummy_tweak = [0; 32];
 
+                    let utxos = module.available_utxos(&mut context.dbtx()).await;
+                    let legacy_utxos = module.legacy_utxos(&mut context.dbtx(), &utxos).await;
+
+                    let peg_out = TxOut {
+                        value: sats,
+                        script_pubkey: address.script_pubkey(),
+                    };
                     let tx = module.offline_wallet().create_tx(
-                        bitcoin::Amount::from_sat(sats),
-                        address.script_pubkey(),
+                        vec![peg_out],
                         vec![],
-                        module.available_utxos(&mut context.dbtx()).await,
+                        utxos,
                         feerate,
                         &dummy_tweak,
-                        None
+                        None,
+                        &legacy_utxos,
                     );
 
                     match tx {
@@ -661,6 +949,78 @@ impl ServerModule for Wallet {
                     }
                 }
             },
+            api_endpoint! {
+                SET_FEE_RATE_FLOOR_ENDPOINT,
+                async |_module: &Wallet, context, floor: Option<Feerate>| -> () {
+                    context.check_capability(FEES_CAPABILITY)?;
+
+                    match floor {
+                        Some(floor) => {
+                            context.dbtx().insert_entry(&FeeRateFloorKey, &floor).await;
+                        }
+                        None => {
+                            context.dbtx().remove_entry(&FeeRateFloorKey).await;
+                        }
+                    }
+
+                    Ok(())
+                }
+            },
+            api_endpoint! {
+                BUMP_PEG_OUT_FEE_ENDPOINT,
+                async |_module: &Wallet, context, bump: PegOutFeeBumpItem| -> () {
+                    context.check_capability(FEES_CAPABILITY)?;
+
+                    let mut dbtx = context.dbtx();
+                    if dbtx.get_value(&PendingTransactionKey(bump.txid)).await.is_none() {
+                        return Err(ApiError::bad_request(format!(
+                            "Peg-out {} is not pending",
+                            bump.txid
+                        )));
+                    }
+
+                    dbtx.insert_entry(&PegOutFeeBumpRequestKey(bump.txid), &bump.fee_rate)
+                        .await;
+
+                    Ok(())
+                }
+            },
+            api_endpoint! {
+                MIGRATE_TO_TAPROOT_ENDPOINT,
+                async |module: &Wallet, context, _params: ()| -> () {
+                    context.check_capability(DESCRIPTOR_CAPABILITY)?;
+
+                    if !PegInDescriptorKind::Tr.is_selectable() {
+                        return Err(ApiError::bad_request(
+                            "Taproot peg-in descriptors are not supported yet".to_string(),
+                        ));
+                    }
+
+                    if module.cfg.consensus.migrated_to_taproot().is_none() {
+                        return Err(ApiError::bad_request(
+                            "Peg-ins are not locked to the legacy descriptor".to_string(),
+                        ));
+                    }
+
+                    context.dbtx().insert_entry(&TaprootMigrationRequestKey, &()).await;
+
+                    Ok(())
+                }
+            },
+            api_endpoint! {
+                TAPROOT_MIGRATION_ENDPOINT,
+                async |_module: &Wallet, context, _params: ()| -> Vec<PeerId> {
+                    context.check_capability(DESCRIPTOR_CAPABILITY)?;
+
+                    Ok(context
+                        .dbtx()
+                        .find_by_prefix(&TaprootMigrationApprovalPrefix)
+                        .await
+                        .map(|(key, _)| key.0)
+                        .collect()
+                        .await)
+                }
+            },
         ]
     }
 }
@@ -672,6 +1032,8 @@ pub struct Wallet {
     btc_rpc: DynBitcoindRpc,
     /// The result of last successful get_block_count
     block_count_local: std::sync::Mutex<Option<u32>>,
+    /// The result of the last health check of the bitcoin backend
+    bitcoin_health: Arc<std::sync::Mutex<BitcoinBackendHealth>>,
     our_peer_id: PeerId,
 }
 
@@ -701,6 +1063,25 @@ impl Wallet {
             })
             .await;
 
+        let bitcoin_health = Arc::new(std::sync::Mutex::new(BitcoinBackendHealth {
+            kind: cfg.local.bitcoin_rpc.kind.clone(),
+            ..Default::default()
+        }));
+        let health_check_bitcoind_rpc = bitcoind.clone();
+        let health_check_health = bitcoin_health.clone();
+        let network = cfg.consensus.network;
+        task_group
+            .spawn("bitcoin health check", move |handle| async move {
+                run_bitcoin_health_check(
+                    health_check_bitcoind_rpc,
+                    network,
+                    health_check_health,
+                    &handle,
+                )
+                .await;
+            })
+            .await;
+
         let bitcoind_rpc = bitcoind;
 
         let bitcoind_net = bitcoind_rpc
@@ -718,6 +1099,7 @@ impl Wallet {
             cfg,
             secp: Default::default(),
             block_count_local: Default::default(),
+            bitcoin_health,
             btc_rpc: bitcoind_rpc,
             our_peer_id,
         };
@@ -740,67 +1122,334 @@ impl Wallet {
         Ok(wallet)
     }
 
-    /// Try to attach signatures to a pending peg-out tx.
-    fn sign_peg_out_psbt(
+    async fn process_peg_out_signature(
         &self,
-        psbt: &mut PartiallySignedTransaction,
-        peer: &PeerId,
-        signature: &PegOutSignatureItem,
-    ) -> Result<(), ProcessPegOutSigError> {
-        let peer_key = self
-            .cfg
-            .consensus
-            .peer_peg_in_keys
-            .get(peer)
-            .expect("always called with valid peer id");
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        peer_id: PeerId,
+        txid: Txid,
+        signature: &[PegOutInputSignature],
+    ) -> anyhow::Result<()> {
+        if dbtx.get_value(&PendingTransactionKey(txid)).await.is_some() {
+            bail!("Already received a threshold of valid signatures");
+        }
 
-        if psbt.inputs.len() != signature.signature.len() {
-            return Err(ProcessPegOutSigError::WrongSignatureCount(
-                psbt.inputs.len(),
-                signature.signature.len(),
-            ));
+        let mut unsigned = dbtx
+            .get_value(&UnsignedTransactionKey(txid))
+            .await
+            .context("Unsigned transaction does not exist")?;
+
+        self.sign_peg_out_psbt(&mut unsigned.psbt, &peer_id, signature)
+            .context("Peg out signature is invalid")?;
+
+        dbtx.insert_entry(&UnsignedTransactionKey(txid), &unsigned)
+            .await;
+
+        if let Ok(pending_tx) = self.finalize_peg_out_psbt(unsigned) {
+            // We were able to finalize the transaction, so we will delete the
+            // PSBT and instead keep the extracted tx for periodic transmission
+            // as well as to accept the change into our wallet eventually once
+            // it confirms.
+            dbtx.insert_new_entry(&PendingTransactionKey(txid), &pending_tx)
+                .await;
+
+            dbtx.remove_entry(&PegOutTxSignatureCI(txid)).await;
+            dbtx.remove_entry(&TaprootPegOutTxSignatureCI(txid)).await;
+            dbtx.remove_entry(&UnsignedTransactionKey(txid)).await;
         }
 
-        let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
-        for (idx, (input, signature)) in psbt
-            .inputs
-            .iter_mut()
-            .zip(signature.signature.iter())
-            .enumerate()
-        {
-            let tx_hash = tx_hasher
-                .segwit_signature_hash(
-                    idx,
-                    input
-                        .witness_script
-                        .as_ref()
-                        .expect("Missing witness script"),
-                    input.witness_utxo.as_ref().expect("Missing UTXO").value,
-                    EcdsaSighashType::All,
+        Ok(())
+    }
+
+    /// Signs the peg-out tx and stores it until a threshold of guardians
+    /// signed, with our signatures to be proposed to our peers
+    async fn sign_peg_out_tx(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        mut tx: UnsignedTransaction,
+    ) -> Txid {
+        self.offline_wallet().sign_psbt(&mut tx.psbt);
+
+        let txid = tx.psbt.unsigned_tx.txid();
+
+        info!(
+            %txid,
+            "Signing peg out",
+        );
+
+        let sigs = tx
+            .psbt
+            .inputs
+            .iter_mut()
+            .map(|input| {
+                // TODO: don't put sig into PSBT in the first place
+                // We actually take out our own signature so everyone finalizes the tx in the
+                // same epoch.
+                if !input.tap_script_sigs.is_empty() {
+                    assert_eq!(
+                        input.tap_script_sigs.len(),
+                        1,
+                        "There was already more than one (our) signature in input"
+                    );
+
+                    let sig = std::mem::take(&mut input.tap_script_sigs)
+                        .into_values()
+                        .next()
+                        .expect("asserted previously");
+
+                    return PegOutInputSignature::Schnorr(sig.sig);
+                }
+
+                assert_eq!(
+                    input.partial_sigs.len(),
+                    1,
+                    "There was already more than one (our) or no signatures in input"
+                );
+
+                let sig = std::mem::take(&mut input.partial_sigs)
+                    .into_values()
+                    .next()
+                    .expect("asserted previously");
+
+                // We drop SIGHASH_ALL, because we always use that and it is only present in the
+                // PSBT for compatibility with other tools.
+                PegOutInputSignature::Ecdsa(
+                    secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
+                        .expect("we serialized it ourselves that way"),
                 )
-                .map_err(|_| ProcessPegOutSigError::SighashError)?;
+            })
+            .collect::<Vec<_>>();
+
+        // Delete used UTXOs
+        for input in tx.psbt.unsigned_tx.input.iter() {
+            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
+        }
+
+        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
+            .await;
+
+        let ecdsa_sigs = sigs
+            .iter()
+            .map(|sig| match sig {
+                PegOutInputSignature::Ecdsa(sig) => Some(*sig),
+                PegOutInputSignature::Schnorr(_) => None,
+            })
+            .collect::<Option<Vec<_>>>();
+
+        // peg-outs spending only UTXOs of the legacy descriptor are signed as before
+        match ecdsa_sigs {
+            Some(ecdsa_sigs) => {
+                dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &ecdsa_sigs)
+                    .await;
+            }
+            None => {
+                dbtx.insert_new_entry(&TaprootPegOutTxSignatureCI(txid), &sigs)
+                    .await;
+            }
+        }
+
+        txid
+    }
+
+    /// Records the vote of the peer to bump the fees of a pending peg-out and
+    /// replaces it once a threshold of guardians voted
+    async fn process_peg_out_fee_bump(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        peer_id: PeerId,
+        bump: PegOutFeeBumpItem,
+    ) -> anyhow::Result<()> {
+        let pending_tx = dbtx
+            .get_value(&PendingTransactionKey(bump.txid))
+            .await
+            .context("Peg-out is not pending")?;
+
+        if self.is_being_replaced(dbtx, bump.txid).await {
+            bail!("Peg-out is already being replaced");
+        }
+
+        // BIP-125 requires the replacement to pay at least the minimum relay fee
+        // for its own bandwidth on top of the fees of the original
+        let min_fee_rate = pending_tx.fees.fee_rate.sats_per_kvb + DEFAULT_MIN_RELAY_TX_FEE as u64;
+        if bump.fee_rate.sats_per_kvb < min_fee_rate {
+            bail!("Fee bump does not exceed the minimum relay fee");
+        }
+
+        let key = PegOutFeeBumpVoteKey {
+            txid: bump.txid,
+            peer: peer_id,
+        };
+        if dbtx.insert_entry(&key, &bump.fee_rate).await == Some(bump.fee_rate) {
+            bail!("Fee bump vote is redundant");
+        }
+
+        let mut fee_rates = dbtx
+            .find_by_prefix(&PegOutFeeBumpVoteTxidPrefix(bump.txid))
+            .await
+            .map(|(_, fee_rate)| fee_rate)
+            .collect::<Vec<_>>()
+            .await;
+
+        let threshold = self.cfg.consensus.peer_peg_in_keys.threshold();
+        if fee_rates.len() < threshold {
+            return Ok(());
+        }
+
+        // The highest fee rate a threshold of guardians voted for at least
+        fee_rates.sort();
+        let fee_rate = fee_rates[fee_rates.len() - threshold];
+
+        let rbf = Rbf {
+            fees: PegOutFees::new(
+                fee_rate.sats_per_kvb - pending_tx.fees.fee_rate.sats_per_kvb,
+                pending_tx.fees.total_weight,
+            ),
+            txid: bump.txid,
+        };
+
+        let change_tweak = self.consensus_nonce(dbtx).await;
+        let tx = self
+            .create_peg_out_tx(dbtx, &WalletOutput::Rbf(rbf), &change_tweak)
+            .await
+            .context("Failed to create the replacement")?;
+
+        let txid = self.sign_peg_out_tx(dbtx, tx).await;
+
+        info!(replaced = %bump.txid, %txid, ?fee_rate, "Bumping the fees of a peg-out");
+
+        dbtx.remove_by_prefix(&PegOutFeeBumpVoteTxidPrefix(bump.txid))
+            .await;
+        dbtx.remove_entry(&PegOutFeeBumpRequestKey(bump.txid)).await;
+
+        Ok(())
+    }
+
+    /// Whether a replacement of the peg-out is being signed or was signed
+    /// already
+    async fn is_being_replaced(&self, dbtx: &mut DatabaseTransactionRef<'_>, txid: Txid) -> bool {
+        let unsigned_replacements = dbtx
+            .find_by_prefix(&UnsignedTransactionPrefixKey)
+            .await
+            .filter(|(_, tx)| std::future::ready(matches!(&tx.rbf, Some(rbf) if rbf.txid == txid)))
+            .count()
+            .await;
+
+        let pending_replacements = dbtx
+            .find_by_prefix(&PendingTransactionPrefixKey)
+            .await
+            .filter(|(_, tx)| std::future::ready(matches!(&tx.rbf, Some(rbf) if rbf.txid == txid)))
+            .count()
+            .await;
+
+        unsigned_replacements + pending_replacements != 0
+    }
+
+    /// Try to attach signatures to a pending peg-out tx.
+    fn sign_peg_out_psbt(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        peer: &PeerId,
+        signature: &[PegOutInputSignature],
+    ) -> Result<(), ProcessPegOutSigError> {
+        let peer_key = self
+            .cfg
+            .consensus
+            .peer_peg_in_keys
+            .get(peer)
+            .expect("always called with valid peer id");
+
+        if psbt.inputs.len() != signature.len() {
+            return Err(ProcessPegOutSigError::WrongSignatureCount(
+                psbt.inputs.len(),
+                signature.len(),
+            ));
+        }
 
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+            .collect::<Vec<_>>();
+
+        let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
+        for (idx, (input, signature)) in psbt.inputs.iter_mut().zip(signature.iter()).enumerate() {
             let tweak = input
                 .proprietary
                 .get(&proprietary_tweak_key())
                 .expect("we saved it with a tweak");
 
             let tweaked_peer_key = peer_key.tweak(tweak, &self.secp);
-            self.secp
-                .verify_ecdsa(
-                    &Message::from_slice(&tx_hash[..]).unwrap(),
-                    signature,
-                    &tweaked_peer_key.key,
-                )
-                .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
 
-            if input
-                .partial_sigs
-                .insert(tweaked_peer_key.into(), EcdsaSig::sighash_all(*signature))
-                .is_some()
-            {
-                // Should never happen since peers only sign a PSBT once
-                return Err(ProcessPegOutSigError::DuplicateSignature);
+            match signature {
+                PegOutInputSignature::Ecdsa(signature) => {
+                    let tx_hash = tx_hasher
+                        .segwit_signature_hash(
+                            idx,
+                            input
+                                .witness_script
+                                .as_ref()
+                                .ok_or(ProcessPegOutSigError::WrongSignatureKind(idx))?,
+                            input.witness_utxo.as_ref().expect("Missing UTXO").value,
+                            EcdsaSighashType::All,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::SighashError)?;
+
+                    self.secp
+                        .verify_ecdsa(
+                            &Message::from_slice(&tx_hash[..]).unwrap(),
+                            signature,
+                            &tweaked_peer_key.key,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+                    if input
+                        .partial_sigs
+                        .insert(tweaked_peer_key.into(), EcdsaSig::sighash_all(*signature))
+                        .is_some()
+                    {
+                        // Should never happen since peers only sign a PSBT once
+                        return Err(ProcessPegOutSigError::DuplicateSignature);
+                    }
+                }
+                PegOutInputSignature::Schnorr(signature) => {
+                    let (script, leaf_version) = input
+                        .tap_scripts
+                        .values()
+                        .next()
+                        .ok_or(ProcessPegOutSigError::WrongSignatureKind(idx))?;
+                    let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+
+                    let tx_hash = tx_hasher
+                        .taproot_script_spend_signature_hash(
+                            idx,
+                            &Prevouts::All(&prevouts),
+                            leaf_hash,
+                            SchnorrSighashType::Default,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::SighashError)?;
+
+                    let x_only_peer_key = tweaked_peer_key.key.x_only_public_key().0;
+                    self.secp
+                        .verify_schnorr(
+                            signature,
+                            &Message::from_slice(&tx_hash[..]).unwrap(),
+                            &x_only_peer_key,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+                    let signature = SchnorrSig {
+                        sig: *signature,
+                        hash_ty: SchnorrSighashType::Default,
+                    };
+
+                    if input
+                        .tap_script_sigs
+                        .insert((x_only_peer_key, leaf_hash), signature)
+                        .is_some()
+                    {
+                        // Should never happen since peers only sign a PSBT once
+                        return Err(ProcessPegOutSigError::DuplicateSignature);
+                    }
+                }
             }
         }
         Ok(())
@@ -981,11 +1630,10 @@ impl Wallet {
                 }
             }
 
-            dbtx.insert_new_entry(
-                &BlockHashKey(BlockHash::from_inner(block_hash.into_inner())),
-                &(),
-            )
-            .await;
+            let block_hash = BlockHash::from_inner(block_hash.into_inner());
+            dbtx.insert_new_entry(&BlockHashKey(block_hash), &()).await;
+            dbtx.insert_new_entry(&BlockHeightKey(block_hash), &height)
+                .await;
         }
     }
 
@@ -1004,24 +1652,47 @@ impl Wallet {
             .peg_in_descriptor
             .tweak(&pending_tx.tweak, &self.secp)
             .script_pubkey();
+        // The change of a transaction created before the migration to taproot
+        // is still locked to the legacy descriptor
+        let legacy_script_pk =
+            self.cfg
+                .consensus
+                .legacy_peg_in_descriptor
+                .as_ref()
+                .map(|descriptor| {
+                    descriptor
+                        .tweak(&pending_tx.tweak, &self.secp)
+                        .script_pubkey()
+                });
+
         for (idx, output) in pending_tx.tx.output.iter().enumerate() {
+            let outpoint = bitcoin::OutPoint {
+                txid: pending_tx.tx.txid(),
+                vout: idx as u32,
+            };
+
             if output.script_pubkey == script_pk {
-                dbtx.insert_entry(
-                    &UTXOKey(bitcoin::OutPoint {
-                        txid: pending_tx.tx.txid(),
-                        vout: idx as u32,
-                    }),
-                    &SpendableUTXO {
-                        tweak: pending_tx.tweak,
-                        amount: bitcoin::Amount::from_sat(output.value),
-                    },
-                )
-                .await;
+                if legacy_script_pk.is_some() {
+                    dbtx.insert_entry(&MigratedUTXOKey(outpoint), &()).await;
+                }
+            } else if Some(&output.script_pubkey) != legacy_script_pk.as_ref() {
+                continue;
             }
+
+            dbtx.insert_entry(
+                &UTXOKey(outpoint),
+                &SpendableUTXO {
+                    tweak: pending_tx.tweak,
+                    amount: bitcoin::Amount::from_sat(output.value),
+                },
+            )
+            .await;
         }
     }
 
-    /// Removes the `PendingTransaction` and any transactions tied to it via RBF
+    /// Removes the `PendingTransaction` and any transactions tied to it via RBF,
+    /// the UTXOs selected by the transactions that did not confirm are
+    /// spendable again unless the confirmed transaction spent them
     async fn remove_rbf_transactions<'a>(
         &self,
         dbtx: &mut DatabaseTransactionRef<'a>,
@@ -1034,12 +1705,28 @@ impl Wallet {
             .collect::<BTreeMap<Txid, PendingTransaction>>()
             .await;
 
+        let spent = pending_tx
+            .tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<BTreeSet<_>>();
+
         // We need to search and remove all `PendingTransactions` invalidated by RBF
+        let mut removed_txids = BTreeSet::new();
         let mut pending_to_remove = vec![pending_tx.clone()];
         while let Some(removed) = pending_to_remove.pop() {
             all_transactions.remove(&removed.tx.txid());
             dbtx.remove_entry(&PendingTransactionKey(removed.tx.txid()))
                 .await;
+            dbtx.remove_entry(&PegOutFeeBumpRequestKey(removed.tx.txid()))
+                .await;
+            dbtx.remove_by_prefix(&PegOutFeeBumpVoteTxidPrefix(removed.tx.txid()))
+                .await;
+            removed_txids.insert(removed.tx.txid());
+
+            self.release_utxos(dbtx, &removed.selected_utxos, &spent)
+                .await;
 
             // Search for tx that this `removed` has as RBF
             if let Some(rbf) = &removed.rbf {
@@ -1057,6 +1744,44 @@ impl Wallet {
                 }
             }
         }
+
+        // Replacements that are still being signed can no longer confirm
+        let unsigned_replacements = dbtx
+            .find_by_prefix(&UnsignedTransactionPrefixKey)
+            .await
+            .filter(|(_, tx)| {
+                std::future::ready(
+                    matches!(&tx.rbf, Some(rbf) if removed_txids.contains(&rbf.txid)),
+                )
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        for (key, unsigned) in unsigned_replacements {
+            dbtx.remove_entry(&PegOutTxSignatureCI(key.0)).await;
+            dbtx.remove_entry(&TaprootPegOutTxSignatureCI(key.0)).await;
+            dbtx.remove_entry(&key).await;
+
+            self.release_utxos(dbtx, &unsigned.selected_utxos, &spent)
+                .await;
+        }
+    }
+
+    /// Returns the UTXOs to our wallet unless they are spent
+    async fn release_utxos(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        utxos: &[(UTXOKey, SpendableUTXO)],
+        spent: &BTreeSet<bitcoin::OutPoint>,
+    ) {
+        for (utxo_key, utxo) in utxos {
+            if spent.contains(&utxo_key.0) {
+                // We no longer need to know the descriptor of a spent UTXO
+                dbtx.remove_entry(&MigratedUTXOKey(utxo_key.0)).await;
+            } else {
+                dbtx.insert_entry(utxo_key, utxo).await;
+            }
+        }
     }
 
     async fn block_is_known(
@@ -1067,6 +1792,32 @@ impl Wallet {
         dbtx.get_value(&BlockHashKey(block_hash)).await.is_some()
     }
 
+    /// Whether the block is deep enough in the chain for a peg-in of the
+    /// amount, the blocks we know are confirmed by the finality delay already
+    async fn peg_in_is_confirmed(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        block_hash: BlockHash,
+        amount: bitcoin::Amount,
+    ) -> bool {
+        let confirmations = self.cfg.consensus.peg_in_confirmations(amount);
+        let extra_confirmations = confirmations.saturating_sub(self.cfg.consensus.finality_delay);
+
+        if extra_confirmations == 0 {
+            return true;
+        }
+
+        let Some(height) = dbtx.get_value(&BlockHeightKey(block_hash)).await else {
+            return true;
+        };
+
+        self.consensus_block_count(dbtx)
+            .await
+            .map_or(false, |block_count| {
+                height + extra_confirmations < block_count
+            })
+    }
+
     async fn create_peg_out_tx(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -1074,34 +1825,257 @@ impl Wallet {
         change_tweak: &[u8; 32],
     ) -> Result<UnsignedTransaction, WalletError> {
         match output {
-            WalletOutput::PegOut(peg_out) => self.offline_wallet().create_tx(
-                peg_out.amount,
-                peg_out.recipient.script_pubkey(),
-                vec![],
-                self.available_utxos(dbtx).await,
-                peg_out.fees.fee_rate,
-                change_tweak,
-                None,
-            ),
+            WalletOutput::PegOut(peg_out) => {
+                self.create_peg_out_batch_tx(dbtx, &[peg_out.clone()], &[], change_tweak)
+                    .await
+            }
             WalletOutput::Rbf(rbf) => {
                 let tx = dbtx
                     .get_value(&PendingTransactionKey(rbf.txid))
                     .await
                     .ok_or(WalletError::RbfTransactionIdNotFound)?;
 
+                let utxos = self.available_utxos(dbtx).await;
+                let all_utxos = tx
+                    .selected_utxos
+                    .iter()
+                    .chain(utxos.iter())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let legacy_utxos = self.legacy_utxos(dbtx, &all_utxos).await;
+
+                // the replacement pays the same users, who may be several if the
+                // transaction batches their peg-outs
+                let change_script = self.offline_wallet().derive_script(&tx.tweak);
+                let peg_outs = tx
+                    .tx
+                    .output
+                    .into_iter()
+                    .filter(|output| output.script_pubkey != change_script)
+                    .collect();
+
                 self.offline_wallet().create_tx(
-                    tx.peg_out_amount,
-                    tx.destination,
+                    peg_outs,
                     tx.selected_utxos,
-                    self.available_utxos(dbtx).await,
+                    utxos,
                     tx.fees.fee_rate,
                     change_tweak,
                     Some(rbf.clone()),
+                    &legacy_utxos,
                 )
             }
         }
     }
 
+    /// Creates a transaction paying all the peg-outs at the highest fee rate
+    /// any of them committed to, such that none of them confirms later than it
+    /// paid for. Every peg-out is increased by its refund, if any.
+    async fn create_peg_out_batch_tx(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        peg_outs: &[PegOut],
+        refunds: &[bitcoin::Amount],
+        change_tweak: &[u8; 32],
+    ) -> Result<UnsignedTransaction, WalletError> {
+        let fee_rate = peg_outs
+            .iter()
+            .map(|peg_out| peg_out.fees.fee_rate)
+            .max()
+            .ok_or(WalletError::EmptyPegOutBatch)?;
+
+        let outputs = peg_outs
+            .iter()
+            .enumerate()
+            .map(|(idx, peg_out)| {
+                let refund = refunds.get(idx).copied().unwrap_or(bitcoin::Amount::ZERO);
+
+                TxOut {
+                    value: (peg_out.amount + refund).to_sat(),
+                    script_pubkey: peg_out.recipient.script_pubkey(),
+                }
+            })
+            .collect();
+
+        let utxos = self.available_utxos(dbtx).await;
+        let legacy_utxos = self.legacy_utxos(dbtx, &utxos).await;
+
+        self.offline_wallet().create_tx(
+            outputs,
+            vec![],
+            utxos,
+            fee_rate,
+            change_tweak,
+            None,
+            &legacy_utxos,
+        )
+    }
+
+    /// Creates the transaction of a batch whose peg-outs all cover their share
+    /// of its fees, refunding every peg-out the fees it saves by being batched
+    /// by adding them to its output. Returns the transaction with the share of
+    /// the fees and the refund of every peg-out.
+    ///
+    /// The refunds may require another input, which raises the fees. Since the
+    /// transaction needs no more inputs after lowering the refunds by the
+    /// difference, we recompute them once and fall back to the transaction
+    /// without refunds if a peg-out still does not cover its share.
+    async fn create_refunding_batch_tx(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        peg_outs: &[PegOut],
+        change_tweak: &[u8; 32],
+    ) -> Result<
+        (
+            UnsignedTransaction,
+            Vec<bitcoin::Amount>,
+            Vec<bitcoin::Amount>,
+        ),
+        WalletError,
+    > {
+        let tx = self
+            .create_peg_out_batch_tx(dbtx, peg_outs, &[], change_tweak)
+            .await?;
+        let fees = attribute_peg_out_fees(&tx.fees, peg_outs);
+        let no_refunds = vec![bitcoin::Amount::ZERO; peg_outs.len()];
+
+        let mut refunds = peg_out_refunds(peg_outs, &fees);
+
+        for _ in 0..2 {
+            let Ok(refunding_tx) = self
+                .create_peg_out_batch_tx(dbtx, peg_outs, &refunds, change_tweak)
+                .await
+            else {
+                break;
+            };
+
+            let refunding_fees = attribute_peg_out_fees(&refunding_tx.fees, peg_outs);
+
+            if covers_peg_out_fees(peg_outs, &refunding_fees, &refunds) {
+                return Ok((refunding_tx, refunding_fees, refunds));
+            }
+
+            refunds = peg_out_refunds(peg_outs, &refunding_fees);
+        }
+
+        Ok((tx, fees, no_refunds))
+    }
+
+    /// Batches the peg-outs accepted in the session into a single transaction
+    /// at the highest fee rate any of them committed to
+    ///
+    /// A peg-out that committed to a lower fee rate may not cover its share of
+    /// the fees of the batch, in which case it is paid in a transaction of its
+    /// own at its own fee rate instead of being subsidized by the federation.
+    /// The same applies to all peg-outs if the federation can not fund the
+    /// batch. Peg-outs the federation can not fund at all stay queued for the
+    /// next session.
+    async fn process_queued_peg_outs(&self, dbtx: &mut DatabaseTransactionRef<'_>) {
+        let mut batch = dbtx
+            .find_by_prefix(&QueuedPegOutPrefix)
+            .await
+            .map(|(key, peg_out)| (key.0, peg_out))
+            .collect::<Vec<(OutPoint, PegOut)>>()
+            .await;
+
+        if batch.is_empty() {
+            return;
+        }
+
+        let change_tweak = self.consensus_nonce(dbtx).await;
+        let mut individual = vec![];
+        let mut batched = None;
+
+        while batch.len() > 1 {
+            let peg_outs = batch
+                .iter()
+                .map(|(_, peg_out)| peg_out.clone())
+                .collect::<Vec<_>>();
+
+            let tx = match self
+                .create_peg_out_batch_tx(dbtx, &peg_outs, &[], &change_tweak)
+                .await
+            {
+                Ok(tx) => tx,
+                Err(error) => {
+                    warn!(%error, peg_outs = batch.len(), "Could not batch the queued peg-outs");
+                    break;
+                }
+            };
+
+            let fees = attribute_peg_out_fees(&tx.fees, &peg_outs);
+
+            let (covered, uncovered): (Vec<_>, Vec<_>) = batch
+                .into_iter()
+                .zip(fees)
+                .partition(|((_, peg_out), fee)| *fee <= peg_out.fees.amount());
+
+            batch = covered.into_iter().map(|(queued, _)| queued).collect();
+
+            if uncovered.is_empty() {
+                batched = self
+                    .create_refunding_batch_tx(dbtx, &peg_outs, &change_tweak)
+                    .await
+                    .ok();
+                break;
+            }
+
+            individual.extend(uncovered.into_iter().map(|(queued, _)| queued));
+        }
+
+        match batched {
+            Some((tx, fees, refunds)) => {
+                let txid = self.sign_peg_out_tx(dbtx, tx).await;
+
+                info!(%txid, peg_outs = batch.len(), "Batched peg-outs");
+
+                for (((out_point, _), fee), refund) in batch.into_iter().zip(fees).zip(refunds) {
+                    debug!(%out_point, %fee, %refund, "Batched peg-out");
+                    self.record_peg_out_tx(dbtx, out_point, txid, fee).await;
+                }
+            }
+            None => individual.extend(batch),
+        }
+
+        for (out_point, peg_out) in individual {
+            let change_tweak = self.consensus_nonce(dbtx).await;
+
+            match self
+                .create_peg_out_batch_tx(dbtx, &[peg_out], &[], &change_tweak)
+                .await
+            {
+                Ok(tx) => {
+                    let fee = tx.fees.amount();
+                    let txid = self.sign_peg_out_tx(dbtx, tx).await;
+
+                    info!(%txid, %out_point, "Paid peg-out individually");
+
+                    self.record_peg_out_tx(dbtx, out_point, txid, fee).await;
+                }
+                Err(error) => {
+                    warn!(%error, %out_point, "Could not pay the queued peg-out");
+                }
+            }
+        }
+    }
+
+    /// Records the Bitcoin transaction paying a queued peg-out and its share of
+    /// the fees
+    async fn record_peg_out_tx(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        out_point: OutPoint,
+        txid: Txid,
+        fee: bitcoin::Amount,
+    ) {
+        dbtx.remove_entry(&QueuedPegOutKey(out_point)).await;
+        dbtx.insert_new_entry(&PegOutFeeKey(out_point), &fee).await;
+        dbtx.insert_new_entry(
+            &PegOutBitcoinTransaction(out_point),
+            &WalletOutputOutcome(txid),
+        )
+        .await;
+    }
+
     async fn available_utxos(
         &self,
         dbtx: &mut DatabaseTransactionRef<'_>,
@@ -1112,6 +2086,27 @@ impl Wallet {
             .await
     }
 
+    /// The UTXOs that are still locked to the legacy descriptor after the
+    /// migration to taproot
+    async fn legacy_utxos(
+        &self,
+        dbtx: &mut DatabaseTransactionRef<'_>,
+        utxos: &[(UTXOKey, SpendableUTXO)],
+    ) -> BTreeSet<bitcoin::OutPoint> {
+        if self.cfg.consensus.legacy_peg_in_descriptor.is_none() {
+            return BTreeSet::new();
+        }
+
+        let mut legacy_utxos = BTreeSet::new();
+        for (utxo_key, _) in utxos {
+            if dbtx.get_value(&MigratedUTXOKey(utxo_key.0)).await.is_none() {
+                legacy_utxos.insert(utxo_key.0);
+            }
+        }
+
+        legacy_utxos
+    }
+
     pub async fn get_wallet_value(&self, dbtx: &mut DatabaseTransactionRef<'_>) -> bitcoin::Amount {
         let sat_sum = self
             .available_utxos(dbtx)
@@ -1125,12 +2120,74 @@ impl Wallet {
     fn offline_wallet(&self) -> StatelessWallet {
         StatelessWallet {
             descriptor: &self.cfg.consensus.peg_in_descriptor,
+            legacy_descriptor: self.cfg.consensus.legacy_peg_in_descriptor.as_ref(),
             secret_key: &self.cfg.private.peg_in_key,
             secp: &self.secp,
         }
     }
 }
 
+/// How often we check the health of the bitcoin backend
+const BITCOIN_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long the bitcoin backend may take to answer a health check
+const BITCOIN_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Periodically checks that the bitcoin backend is reachable and on the right
+/// network, since calls to it otherwise retry until they succeed
+#[instrument(level = "debug", skip_all)]
+async fn run_bitcoin_health_check(
+    rpc: DynBitcoindRpc,
+    network: Network,
+    health: Arc<std::sync::Mutex<BitcoinBackendHealth>>,
+    tg_handle: &TaskHandle,
+) {
+    while !tg_handle.is_shutting_down() {
+        let result = timeout(
+            BITCOIN_HEALTH_CHECK_TIMEOUT,
+            check_bitcoin_backend(&rpc, network),
+        )
+        .await
+        .unwrap_or_else(|_| Err(format_err!("Bitcoin backend did not respond in time")));
+
+        {
+            let mut health = health.lock().expect("Failed to lock");
+            match result {
+                Ok((block_count, fee_rate)) => {
+                    health.block_count = Some(block_count);
+                    health.fee_rate = fee_rate;
+                    health.last_success = fedimint_core::time::now()
+                        .duration_since(UNIX_EPOCH)
+                        .ok()
+                        .map(|since_epoch| since_epoch.as_secs());
+                    health.error = None;
+                }
+                Err(error) => {
+                    warn!(%error, "Bitcoin backend health check failed");
+                    health.error = Some(error.to_string());
+                }
+            }
+        }
+
+        sleep(BITCOIN_HEALTH_CHECK_INTERVAL).await;
+    }
+}
+
+async fn check_bitcoin_backend(
+    rpc: &DynBitcoindRpc,
+    network: Network,
+) -> anyhow::Result<(u64, Option<Feerate>)> {
+    let backend_network = rpc.get_network().await?;
+    if backend_network != network {
+        bail!("Bitcoin backend is on {backend_network}, expected {network}");
+    }
+
+    let block_count = rpc.get_block_count().await?;
+    let fee_rate = rpc.get_fee_rate(CONFIRMATION_TARGET).await?;
+
+    Ok((block_count, fee_rate))
+}
+
 #[instrument(level = "debug", skip_all)]
 pub async fn run_broadcast_pending_tx(db: Database, rpc: DynBitcoindRpc, tg_handle: &TaskHandle) {
     while !tg_handle.is_shutting_down() {
@@ -1165,10 +2222,76 @@ pub async fn broadcast_pending_tx(mut dbtx: DatabaseTransaction<'_>, rpc: &DynBi
     }
 }
 
-struct StatelessWallet<'a> {
-    descriptor: &'a Descriptor<CompressedPublicKey>,
-    secret_key: &'a secp256k1::SecretKey,
-    secp: &'a secp256k1::Secp256k1<secp256k1::All>,
+struct StatelessWallet<'a> {
+    descriptor: &'a PegInDescriptor,
+    /// The descriptor UTXOs received before the migration to taproot are
+    /// locked to
+    legacy_descriptor: Option<&'a PegInDescriptor>,
+    secret_key: &'a secp256k1::SecretKey,
+    secp: &'a secp256k1::Secp256k1<secp256k1::All>,
+}
+
+/// The weight of the output paying a peg-out to the script
+fn peg_out_weight(script: &Script) -> u64 {
+    (script.len() * 4 + 1 + 32) as u64
+}
+
+/// Splits the fees of a batch among its peg-outs, each of them pays for its
+/// own output and an equal share of the rest of the transaction
+fn attribute_peg_out_fees(fees: &PegOutFees, peg_outs: &[PegOut]) -> Vec<bitcoin::Amount> {
+    let own_fees = peg_outs
+        .iter()
+        .map(|peg_out| {
+            fees.fee_rate
+                .calculate_fee(peg_out_weight(&peg_out.recipient.script_pubkey()))
+        })
+        .collect::<Vec<_>>();
+
+    let shared = fees
+        .amount()
+        .to_sat()
+        .saturating_sub(own_fees.iter().map(|fee| fee.to_sat()).sum());
+    let count = peg_outs.len() as u64;
+
+    own_fees
+        .into_iter()
+        .enumerate()
+        .map(|(idx, own_fee)| {
+            // the first peg-outs pay the remainder of the split
+            let share = shared / count + u64::from((idx as u64) < shared % count);
+            own_fee + bitcoin::Amount::from_sat(share)
+        })
+        .collect()
+}
+
+/// The fees every peg-out paid but saves by being batched, given its share of
+/// the fees of the batch
+fn peg_out_refunds(peg_outs: &[PegOut], fees: &[bitcoin::Amount]) -> Vec<bitcoin::Amount> {
+    peg_outs
+        .iter()
+        .zip(fees)
+        .map(|(peg_out, fee)| {
+            peg_out
+                .fees
+                .amount()
+                .checked_sub(*fee)
+                .unwrap_or(bitcoin::Amount::ZERO)
+        })
+        .collect()
+}
+
+/// Whether every peg-out paid for its share of the fees of the batch and its
+/// refund, such that the federation does not subsidize any of them
+fn covers_peg_out_fees(
+    peg_outs: &[PegOut],
+    fees: &[bitcoin::Amount],
+    refunds: &[bitcoin::Amount],
+) -> bool {
+    peg_outs
+        .iter()
+        .zip(fees)
+        .zip(refunds)
+        .all(|((peg_out, fee), refund)| *fee + *refund <= peg_out.fees.amount())
 }
 
 impl<'a> StatelessWallet<'a> {
@@ -1226,23 +2349,24 @@ impl<'a> StatelessWallet<'a> {
 
     /// Attempts to create a tx ready to be signed from available UTXOs.
     //
-    // * `peg_out_amount`: How much the peg-out should be
-    // * `destination`: The address the user is pegging-out to
+    // * `peg_outs`: The outputs paying the users pegging-out, several if the
+    //   transaction batches their peg-outs
     // * `included_utxos`: UXTOs that must be included (for RBF)
     // * `remaining_utxos`: All other spendable UXTOs
     // * `fee_rate`: How much needs to be spent on fees
     // * `change_tweak`: How the federation can recognize it's change UTXO
     // * `rbf`: If this is an RBF transaction
+    // * `legacy_utxos`: UTXOs locked to the legacy descriptor
     #[allow(clippy::too_many_arguments)]
     fn create_tx(
         &self,
-        peg_out_amount: bitcoin::Amount,
-        destination: Script,
+        peg_outs: Vec<TxOut>,
         mut included_utxos: Vec<(UTXOKey, SpendableUTXO)>,
         mut remaining_utxos: Vec<(UTXOKey, SpendableUTXO)>,
         mut fee_rate: Feerate,
         change_tweak: &[u8],
         rbf: Option<Rbf>,
+        legacy_utxos: &BTreeSet<bitcoin::OutPoint>,
     ) -> Result<UnsignedTransaction, WalletError> {
         // Add the rbf fees to the existing tx fees
         if let Some(rbf) = &rbf {
@@ -1255,11 +2379,16 @@ impl<'a> StatelessWallet<'a> {
         //    calculation
         // We then go on to calculate the base size of the transaction `total_weight`
         // and the maximum weight per added input which we will add every time
-        // we select an input.
+        // we select an input, which depends on the descriptor of the input.
         let change_script = self.derive_script(change_tweak);
-        let out_weight = (destination.len() * 4 + 1 + 32
+        let peg_out_amount =
+            bitcoin::Amount::from_sat(peg_outs.iter().map(|peg_out| peg_out.value).sum());
+        let out_weight = peg_outs
+            .iter()
+            .map(|peg_out| peg_out_weight(&peg_out.script_pubkey))
+            .sum::<u64>()
             // Add change script weight, it's very likely to be needed if not we just overpay in fees
-            + 1 // script len varint, 1 byte for all addresses we accept
+            + (1 // script len varint, 1 byte for all addresses we accept
             + change_script.len() * 4 // script len
             + 32) as u64; // value
         let mut total_weight = 16 + // version
@@ -1267,13 +2396,14 @@ impl<'a> StatelessWallet<'a> {
             12 + // up to 2**16-1 outputs
             out_weight + // weight of all outputs
             16; // lock time
-        let max_input_weight = (self
-            .descriptor
-            .max_satisfaction_weight()
-            .expect("is satisfyable") +
-            128 + // TxOutHash
-            16 + // TxOutIndex
-            16) as u64; // sequence
+        let max_input_weight = |descriptor: &PegInDescriptor| {
+            (descriptor
+                .max_satisfaction_weight()
+                .expect("is satisfyable") +
+                128 + // TxOutHash
+                16 + // TxOutIndex
+                16) as u64 // sequence
+        };
 
         // Ensure deterministic ordering of UTXOs for all peers
         included_utxos.sort_by_key(|(_, utxo)| utxo.amount);
@@ -1289,7 +2419,8 @@ impl<'a> StatelessWallet<'a> {
             match included_utxos.pop() {
                 Some((utxo_key, utxo)) => {
                     total_selected_value += utxo.amount;
-                    total_weight += max_input_weight;
+                    total_weight +=
+                        max_input_weight(self.utxo_descriptor(&utxo_key.0, legacy_utxos));
                     fees = fee_rate.calculate_fee(total_weight);
                     selected_utxos.push((utxo_key, utxo));
                 }
@@ -1300,16 +2431,16 @@ impl<'a> StatelessWallet<'a> {
         // We always pay ourselves change back to ensure that we don't lose anything due
         // to dust
         let change = total_selected_value - fees - peg_out_amount;
-        let output: Vec<TxOut> = vec![
-            TxOut {
-                value: peg_out_amount.to_sat(),
-                script_pubkey: destination.clone(),
-            },
-            TxOut {
-                value: change.to_sat(),
-                script_pubkey: change_script,
-            },
-        ];
+        let destination = peg_outs
+            .first()
+            .map(|peg_out| peg_out.script_pubkey.clone())
+            .unwrap_or_default();
+        let peg_out_count = peg_outs.len();
+        let mut output = peg_outs;
+        output.push(TxOut {
+            value: change.to_sat(),
+            script_pubkey: change_script,
+        });
         let mut change_out = bitcoin::util::psbt::Output::default();
         change_out
             .proprietary
@@ -1317,6 +2448,7 @@ impl<'a> StatelessWallet<'a> {
 
         info!(
             inputs = selected_utxos.len(),
+            peg_outs = peg_out_count,
             input_sats = total_selected_value.to_sat(),
             peg_out_sats = peg_out_amount.to_sat(),
             fees_sats = fees.to_sat(),
@@ -1351,47 +2483,14 @@ impl<'a> StatelessWallet<'a> {
             unknown: Default::default(),
             inputs: selected_utxos
                 .iter()
-                .map(|(_utxo_key, utxo)| {
-                    let script_pubkey = self
-                        .descriptor
-                        .tweak(&utxo.tweak, self.secp)
-                        .script_pubkey();
-                    Input {
-                        non_witness_utxo: None,
-                        witness_utxo: Some(TxOut {
-                            value: utxo.amount.to_sat(),
-                            script_pubkey,
-                        }),
-                        partial_sigs: Default::default(),
-                        sighash_type: None,
-                        redeem_script: None,
-                        witness_script: Some(
-                            self.descriptor
-                                .tweak(&utxo.tweak, self.secp)
-                                .script_code()
-                                .expect("Failed to tweak descriptor"),
-                        ),
-                        bip32_derivation: Default::default(),
-                        final_script_sig: None,
-                        final_script_witness: None,
-                        ripemd160_preimages: Default::default(),
-                        sha256_preimages: Default::default(),
-                        hash160_preimages: Default::default(),
-                        hash256_preimages: Default::default(),
-                        proprietary: vec![(proprietary_tweak_key(), utxo.tweak.to_vec())]
-                            .into_iter()
-                            .collect(),
-                        tap_key_sig: Default::default(),
-                        tap_script_sigs: Default::default(),
-                        tap_scripts: Default::default(),
-                        tap_key_origins: Default::default(),
-                        tap_internal_key: Default::default(),
-                        tap_merkle_root: Default::default(),
-                        unknown: Default::default(),
-                    }
+                .map(|(utxo_key, utxo)| {
+                    self.psbt_input(self.utxo_descriptor(&utxo_key.0, legacy_utxos), utxo)
                 })
                 .collect(),
-            outputs: vec![Default::default(), change_out],
+            outputs: std::iter::repeat(Default::default())
+                .take(peg_out_count)
+                .chain([change_out])
+                .collect(),
         };
 
         Ok(UnsignedTransaction {
@@ -1409,9 +2508,86 @@ impl<'a> StatelessWallet<'a> {
         })
     }
 
+    /// The descriptor the UTXO is locked to
+    fn utxo_descriptor(
+        &self,
+        outpoint: &bitcoin::OutPoint,
+        legacy_utxos: &BTreeSet<bitcoin::OutPoint>,
+    ) -> &'a PegInDescriptor {
+        match self.legacy_descriptor {
+            Some(legacy_descriptor) if legacy_utxos.contains(outpoint) => legacy_descriptor,
+            _ => self.descriptor,
+        }
+    }
+
+    fn psbt_input(&self, descriptor: &PegInDescriptor, utxo: &SpendableUTXO) -> Input {
+        let tweaked = descriptor.tweak(&utxo.tweak, self.secp);
+
+        let mut input = Input {
+            non_witness_utxo: None,
+            witness_utxo: Some(TxOut {
+                value: utxo.amount.to_sat(),
+                script_pubkey: tweaked.script_pubkey(),
+            }),
+            partial_sigs: Default::default(),
+            sighash_type: None,
+            redeem_script: None,
+            witness_script: None,
+            bip32_derivation: Default::default(),
+            final_script_sig: None,
+            final_script_witness: None,
+            ripemd160_preimages: Default::default(),
+            sha256_preimages: Default::default(),
+            hash160_preimages: Default::default(),
+            hash256_preimages: Default::default(),
+            proprietary: vec![(proprietary_tweak_key(), utxo.tweak.to_vec())]
+                .into_iter()
+                .collect(),
+            tap_key_sig: Default::default(),
+            tap_script_sigs: Default::default(),
+            tap_scripts: Default::default(),
+            tap_key_origins: Default::default(),
+            tap_internal_key: Default::default(),
+            tap_merkle_root: Default::default(),
+            unknown: Default::default(),
+        };
+
+        match &tweaked {
+            Descriptor::Tr(tr) => {
+                // We spend along the script path, so the finalizer needs the leaf
+                // script and its control block
+                let spend_info = tr.spend_info();
+                input.tap_internal_key = Some(spend_info.internal_key());
+                input.tap_merkle_root = spend_info.merkle_root();
+
+                for (_, leaf) in tr.iter_scripts() {
+                    let script = leaf.encode();
+                    let control_block = spend_info
+                        .control_block(&(script.clone(), LeafVersion::TapScript))
+                        .expect("Leaf is part of the tree");
+                    input
+                        .tap_scripts
+                        .insert(control_block, (script, LeafVersion::TapScript));
+                }
+            }
+            _ => {
+                input.witness_script =
+                    Some(tweaked.script_code().expect("Failed to tweak descriptor"));
+            }
+        }
+
+        input
+    }
+
     fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) {
         let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
 
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+            .collect::<Vec<_>>();
+
         for (idx, (psbt_input, _tx_input)) in psbt
             .inputs
             .iter_mut()
@@ -1427,6 +2603,36 @@ impl<'a> StatelessWallet<'a> {
                 self.secret_key.tweak(tweak, self.secp)
             };
 
+            if let Some((script, leaf_version)) = psbt_input.tap_scripts.values().next() {
+                let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+
+                let tx_hash = tx_hasher
+                    .taproot_script_spend_signature_hash(
+                        idx,
+                        &Prevouts::All(&prevouts),
+                        leaf_hash,
+                        SchnorrSighashType::Default,
+                    )
+                    .expect("Failed to create taproot sighash");
+
+                let key_pair = KeyPair::from_secret_key(self.secp, &tweaked_secret);
+                let signature = self.secp.sign_schnorr_with_rng(
+                    &Message::from_slice(&tx_hash[..]).unwrap(),
+                    &key_pair,
+                    &mut OsRng,
+                );
+
+                psbt_input.tap_script_sigs.insert(
+                    (key_pair.x_only_public_key().0, leaf_hash),
+                    SchnorrSig {
+                        sig: signature,
+                        hash_ty: SchnorrSighashType::Default,
+                    },
+                );
+
+                continue;
+            }
+
             let tx_hash = tx_hasher
                 .segwit_signature_hash(
                     idx,
@@ -1502,16 +2708,39 @@ impl<'a> StatelessWallet<'a> {
 #[cfg(test)]
 mod tests {
 
+    use std::collections::{BTreeMap, BTreeSet};
     use std::str::FromStr;
 
+    use bitcoin::policy::DEFAULT_MIN_RELAY_TX_FEE;
     use bitcoin::Network::{Bitcoin, Testnet};
-    use bitcoin::{Address, Amount, Network, OutPoint, Txid};
-    use fedimint_core::{BitcoinHash, Feerate};
-    use fedimint_wallet_common::{PegOut, PegOutFees, Rbf, WalletOutput};
+    use bitcoin::{Address, Amount, Network, OutPoint, TxOut, Txid};
+    use fedimint_bitcoind::{DynBitcoindRpc, IBitcoindRpc};
+    use fedimint_core::config::ConfigGenModuleParams;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt};
+    use fedimint_core::module::ServerModuleInit;
+    use fedimint_core::task::TaskGroup;
+    use fedimint_core::{BitcoinHash, Feerate, PeerId, ServerModule};
+    use fedimint_testing::btc::mock::{FakeBitcoinFactory, FakeBitcoinTest};
+    use fedimint_testing::btc::BitcoinTest;
+    use fedimint_wallet_common::config::WalletGenParams;
+    use fedimint_wallet_common::db::{PendingTransactionPrefixKey, UTXOPrefixKey};
+    use fedimint_wallet_common::taproot::PegInDescriptorKind;
+    use fedimint_wallet_common::tweakable::Tweakable;
+    use fedimint_wallet_common::txoproof::PegInProof;
+    use fedimint_wallet_common::{
+        PegOut, PegOutFeeBumpItem, PegOutFees, PendingTransaction, Rbf, WalletConsensusItem,
+        WalletInput, WalletOutput,
+    };
+    use futures::StreamExt;
     use miniscript::descriptor::Wsh;
+    use miniscript::psbt::PsbtExt;
 
     use crate::common::PegInDescriptor;
-    use crate::{CompressedPublicKey, OsRng, SpendableUTXO, StatelessWallet, UTXOKey, WalletError};
+    use crate::{
+        attribute_peg_out_fees, covers_peg_out_fees, peg_out_refunds, CompressedPublicKey, OsRng,
+        SpendableUTXO, StatelessWallet, UTXOKey, Wallet, WalletError, WalletGen,
+    };
 
     #[test]
     fn create_tx_should_validate_amounts() {
@@ -1532,6 +2761,7 @@ mod tests {
 
         let wallet = StatelessWallet {
             descriptor: &descriptor,
+            legacy_descriptor: None,
             secret_key: &secret_key,
             secp: &secp,
         };
@@ -1548,26 +2778,32 @@ mod tests {
 
         // not enough SpendableUTXO
         let tx = wallet.create_tx(
-            Amount::from_sat(2000),
-            recipient.script_pubkey(),
+            vec![TxOut {
+                value: 2000,
+                script_pubkey: recipient.script_pubkey(),
+            }],
             vec![],
             vec![(UTXOKey(OutPoint::null()), spendable.clone())],
             fee,
             &[],
             None,
+            &BTreeSet::new(),
         );
         assert_eq!(tx, Err(WalletError::NotEnoughSpendableUTXO));
 
         // successful tx creation
         let mut tx = wallet
             .create_tx(
-                Amount::from_sat(1000),
-                recipient.script_pubkey(),
+                vec![TxOut {
+                    value: 1000,
+                    script_pubkey: recipient.script_pubkey(),
+                }],
                 vec![],
                 vec![(UTXOKey(OutPoint::null()), spendable)],
                 fee,
                 &[],
                 None,
+                &BTreeSet::new(),
             )
             .expect("is ok");
 
@@ -1609,12 +2845,537 @@ mod tests {
         assert_eq!(res, Err(WalletError::WrongNetwork(Testnet, Bitcoin)));
     }
 
+    #[test]
+    fn migrated_wallet_spends_taproot_and_legacy_utxos() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let keys = (0..4)
+            .map(|peer| (PeerId::from(peer), secp.generate_keypair(&mut OsRng)))
+            .collect::<BTreeMap<_, _>>();
+        let pubkeys = keys
+            .iter()
+            .map(|(peer, (_, key))| (*peer, CompressedPublicKey { key: *key }))
+            .collect::<BTreeMap<_, _>>();
+
+        let legacy_descriptor = PegInDescriptorKind::Wsh.descriptor(3, &pubkeys);
+        let descriptor = PegInDescriptorKind::Tr.descriptor(3, &pubkeys);
+
+        let wallets = keys
+            .values()
+            .map(|(secret_key, _)| StatelessWallet {
+                descriptor: &descriptor,
+                legacy_descriptor: Some(&legacy_descriptor),
+                secret_key,
+                secp: &secp,
+            })
+            .collect::<Vec<_>>();
+
+        let legacy_outpoint = OutPoint::new(Txid::all_zeros(), 0);
+        let utxos = vec![
+            (
+                UTXOKey(legacy_outpoint),
+                SpendableUTXO {
+                    tweak: [1; 32],
+                    amount: Amount::from_sat(50_000),
+                },
+            ),
+            (
+                UTXOKey(OutPoint::new(Txid::all_zeros(), 1)),
+                SpendableUTXO {
+                    tweak: [2; 32],
+                    amount: Amount::from_sat(60_000),
+                },
+            ),
+        ];
+
+        let recipient = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf").unwrap();
+        let tx = wallets[0]
+            .create_tx(
+                vec![TxOut {
+                    value: 100_000,
+                    script_pubkey: recipient.script_pubkey(),
+                }],
+                vec![],
+                utxos,
+                Feerate { sats_per_kvb: 1000 },
+                &[3; 32],
+                None,
+                &BTreeSet::from([legacy_outpoint]),
+            )
+            .expect("is ok");
+
+        let legacy_input = tx
+            .psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .position(|input| input.previous_output == legacy_outpoint)
+            .unwrap();
+        for (idx, input) in tx.psbt.inputs.iter().enumerate() {
+            assert_eq!(idx == legacy_input, input.witness_script.is_some());
+            assert_eq!(idx == legacy_input, input.tap_scripts.is_empty());
+        }
+
+        // the change goes to the taproot descriptor
+        assert!(tx.psbt.unsigned_tx.output[1].script_pubkey.is_v1_p2tr());
+
+        let mut psbt = tx.psbt;
+        for wallet in &wallets[..2] {
+            wallet.sign_psbt(&mut psbt);
+        }
+        assert!(psbt.clone().finalize_mut(&secp).is_err());
+
+        wallets[2].sign_psbt(&mut psbt);
+        psbt.finalize_mut(&secp)
+            .expect("a threshold of peers signed");
+    }
+
+    #[test]
+    fn batched_peg_outs_share_the_fees() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let descriptor = PegInDescriptor::Wsh(
+            Wsh::new_sortedmulti(
+                3,
+                (0..4)
+                    .map(|_| secp.generate_keypair(&mut OsRng))
+                    .map(|(_, key)| CompressedPublicKey { key })
+                    .collect(),
+            )
+            .unwrap(),
+        );
+
+        let (secret_key, _) = secp.generate_keypair(&mut OsRng);
+
+        let wallet = StatelessWallet {
+            descriptor: &descriptor,
+            legacy_descriptor: None,
+            secret_key: &secret_key,
+            secp: &secp,
+        };
+
+        let utxos = (0..3)
+            .map(|vout| {
+                (
+                    UTXOKey(OutPoint::new(Txid::all_zeros(), vout)),
+                    SpendableUTXO {
+                        tweak: [vout as u8; 32],
+                        amount: Amount::from_sat(100_000),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let fee_rate = Feerate { sats_per_kvb: 1000 };
+        let recipient = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf").unwrap();
+        let peg_outs = [50_000, 60_000, 70_000]
+            .into_iter()
+            .map(|sats| PegOut {
+                recipient: recipient.clone(),
+                amount: Amount::from_sat(sats),
+                fees: PegOutFees::new(fee_rate.sats_per_kvb, 0),
+            })
+            .collect::<Vec<_>>();
+
+        let outputs = peg_outs
+            .iter()
+            .map(|peg_out| TxOut {
+                value: peg_out.amount.to_sat(),
+                script_pubkey: peg_out.recipient.script_pubkey(),
+            })
+            .collect::<Vec<_>>();
+
+        let batch = wallet
+            .create_tx(
+                outputs.clone(),
+                vec![],
+                utxos.clone(),
+                fee_rate,
+                &[3; 32],
+                None,
+                &BTreeSet::new(),
+            )
+            .expect("is ok");
+
+        // one output per peg-out and the change
+        assert_eq!(batch.psbt.unsigned_tx.output.len(), 4);
+        assert_eq!(batch.psbt.outputs.len(), 4);
+        assert_eq!(batch.peg_out_amount, Amount::from_sat(180_000));
+
+        let fees = attribute_peg_out_fees(&batch.fees, &peg_outs);
+        assert_eq!(
+            fees.iter().map(|fee| fee.to_sat()).sum::<u64>(),
+            batch.fees.amount().to_sat()
+        );
+
+        // every peg-out pays less than it would on its own
+        for (output, fee) in outputs.into_iter().zip(fees) {
+            let alone = wallet
+                .create_tx(
+                    vec![output],
+                    vec![],
+                    utxos.clone(),
+                    fee_rate,
+                    &[3; 32],
+                    None,
+                    &BTreeSet::new(),
+                )
+                .expect("is ok");
+
+            assert!(fee < alone.fees.amount());
+        }
+    }
+
+    #[test]
+    fn batched_peg_outs_are_refunded_their_savings() {
+        let recipient = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf").unwrap();
+        let peg_outs = [2000, 1000]
+            .into_iter()
+            .map(|sats_per_kvb| PegOut {
+                recipient: recipient.clone(),
+                amount: Amount::from_sat(50_000),
+                fees: PegOutFees::new(sats_per_kvb, 1000),
+            })
+            .collect::<Vec<_>>();
+
+        let paid = peg_outs
+            .iter()
+            .map(|peg_out| peg_out.fees.amount())
+            .collect::<Vec<_>>();
+
+        // both peg-outs cover their share and are refunded the rest
+        let fees = vec![paid[1], paid[1]];
+        let refunds = peg_out_refunds(&peg_outs, &fees);
+        assert_eq!(refunds, vec![paid[0] - paid[1], Amount::ZERO]);
+        assert!(covers_peg_out_fees(&peg_outs, &fees, &refunds));
+
+        // the federation would subsidize the second peg-out
+        let fees = vec![paid[0], paid[0]];
+        let refunds = peg_out_refunds(&peg_outs, &fees);
+        assert_eq!(refunds, vec![Amount::ZERO, Amount::ZERO]);
+        assert!(!covers_peg_out_fees(&peg_outs, &fees, &refunds));
+
+        // a refund may not exceed the savings once the fees were raised
+        let refunds = vec![paid[0] - paid[1], Amount::ZERO];
+        let raised = vec![paid[1] + Amount::from_sat(1), paid[1]];
+        assert!(!covers_peg_out_fees(&peg_outs, &raised, &refunds));
+    }
+
     fn rbf(sats_per_kvb: u64, total_weight: u64) -> WalletOutput {
         WalletOutput::Rbf(Rbf {
             fees: PegOutFees::new(sats_per_kvb, total_weight),
             txid: Txid::all_zeros(),
         })
     }
+
+    const FINALITY_DELAY: u64 = 10;
+
+    /// The wallets of a federation sharing a bitcoin node, each processing the
+    /// consensus items of the session in its own database
+    struct TestFederation {
+        wallets: Vec<(Wallet, Database)>,
+        bitcoin: FakeBitcoinTest,
+        _task_group: TaskGroup,
+    }
+
+    impl TestFederation {
+        async fn new(num_peers: u16) -> TestFederation {
+            let FakeBitcoinFactory { bitcoin, config } = FakeBitcoinFactory::register_new();
+            let mut task_group = TaskGroup::new();
+
+            let peers = (0..num_peers).map(PeerId::from).collect::<Vec<_>>();
+            let configs = WalletGen.trusted_dealer_gen(
+                &peers,
+                &ConfigGenModuleParams::from_typed(WalletGenParams::regtest(config)).unwrap(),
+            );
+
+            let mut wallets = vec![];
+            for (peer, config) in configs {
+                let db = MemDatabase::new().into_database();
+                let wallet = Wallet::new_with_bitcoind(
+                    config.to_typed().unwrap(),
+                    db.clone(),
+                    DynBitcoindRpc::from(bitcoin.clone()),
+                    &mut task_group,
+                    peer,
+                )
+                .await
+                .unwrap();
+
+                wallets.push((wallet, db));
+            }
+
+            TestFederation {
+                wallets,
+                bitcoin,
+                _task_group: task_group,
+            }
+        }
+
+        /// Processes the item on every wallet, which must all reach the same
+        /// decision, and keeps its changes only if it was accepted
+        async fn process_consensus_item(
+            &self,
+            peer: PeerId,
+            item: WalletConsensusItem,
+        ) -> Result<(), String> {
+            let mut results = vec![];
+            for (wallet, db) in &self.wallets {
+                let mut dbtx = db.begin_transaction().await;
+                let result = wallet
+                    .process_consensus_item(&mut dbtx.dbtx_ref(), item.clone(), peer)
+                    .await
+                    .map_err(|error| error.to_string());
+
+                if result.is_ok() {
+                    dbtx.commit_tx().await;
+                } else {
+                    dbtx.ignore_uncommitted();
+                }
+
+                results.push(result);
+            }
+
+            assert!(results.windows(2).all(|pair| pair[0] == pair[1]));
+            results.remove(0)
+        }
+
+        /// Runs a session in which every wallet proposes its consensus items
+        async fn run_session(&self) {
+            let mut items = vec![];
+            for (wallet, db) in &self.wallets {
+                let mut dbtx = db.begin_transaction().await;
+                for item in wallet.consensus_proposal(&mut dbtx.dbtx_ref()).await {
+                    items.push((wallet.our_peer_id, item));
+                }
+            }
+
+            for (peer, item) in items {
+                // signatures beyond the threshold are rejected
+                let _ = self.process_consensus_item(peer, item).await;
+            }
+
+            for (wallet, db) in &self.wallets {
+                let mut dbtx = db.begin_transaction().await;
+                wallet.end_session(&mut dbtx.dbtx_ref(), 0).await;
+                dbtx.commit_tx().await;
+            }
+        }
+
+        async fn process_input(&self, input: &WalletInput) {
+            for (wallet, db) in &self.wallets {
+                let mut dbtx = db.begin_transaction().await;
+                wallet
+                    .process_input(&mut dbtx.dbtx_ref(), input)
+                    .await
+                    .unwrap();
+                dbtx.commit_tx().await;
+            }
+        }
+
+        async fn process_output(&self, output: &WalletOutput, out_point: fedimint_core::OutPoint) {
+            for (wallet, db) in &self.wallets {
+                let mut dbtx = db.begin_transaction().await;
+                wallet
+                    .process_output(&mut dbtx.dbtx_ref(), output, out_point)
+                    .await
+                    .unwrap();
+                dbtx.commit_tx().await;
+            }
+        }
+
+        /// The pending transactions and spendable UTXOs, which every wallet
+        /// must agree on
+        async fn wallet_state(
+            &self,
+        ) -> (
+            BTreeMap<Txid, PendingTransaction>,
+            Vec<(UTXOKey, SpendableUTXO)>,
+        ) {
+            let mut states = vec![];
+            for (_, db) in &self.wallets {
+                let mut dbtx = db.begin_transaction().await;
+
+                let pending = dbtx
+                    .find_by_prefix(&PendingTransactionPrefixKey)
+                    .await
+                    .map(|(key, tx)| (key.0, tx))
+                    .collect::<BTreeMap<_, _>>()
+                    .await;
+                let utxos = dbtx
+                    .find_by_prefix(&UTXOPrefixKey)
+                    .await
+                    .collect::<Vec<_>>()
+                    .await;
+
+                states.push((pending, utxos));
+            }
+
+            assert!(states
+                .windows(2)
+                .all(|pair| pair[0].0.keys().eq(pair[1].0.keys()) && pair[0].1 == pair[1].1));
+            states.remove(0)
+        }
+
+        /// Pegs in to the federation, which has to agree on a block count first
+        /// to recognize the block of the peg-in
+        async fn peg_in(&self, amount: Amount) {
+            self.bitcoin.mine_blocks(FINALITY_DELAY).await;
+            self.run_session().await;
+
+            let (wallet, _) = &self.wallets[0];
+            let secp = secp256k1::Secp256k1::new();
+            let tweak_key = secp.generate_keypair(&mut OsRng).1.x_only_public_key().0;
+            let address = wallet
+                .cfg
+                .consensus
+                .peg_in_descriptor
+                .tweak(&tweak_key, &secp)
+                .address(wallet.cfg.consensus.network)
+                .unwrap();
+
+            let (proof, transaction) = self.bitcoin.send_and_mine_block(&address, amount).await;
+            self.bitcoin.mine_blocks(FINALITY_DELAY).await;
+            self.run_session().await;
+
+            let output_idx = transaction
+                .output
+                .iter()
+                .position(|output| output.script_pubkey == address.script_pubkey())
+                .unwrap();
+            let input = WalletInput(Box::new(
+                PegInProof::new(proof, transaction, output_idx as u32, tweak_key).unwrap(),
+            ));
+
+            self.process_input(&input).await;
+        }
+
+        /// Pegs out at the consensus fee rate and runs sessions until the
+        /// transaction was signed by a threshold of guardians
+        async fn peg_out(&self, amount: Amount) -> Txid {
+            let (wallet, db) = &self.wallets[0];
+            let mut peg_out = PegOut {
+                recipient: self.bitcoin.get_new_address().await,
+                amount,
+                fees: PegOutFees::new(0, 0),
+            };
+
+            let mut dbtx = db.begin_transaction().await;
+            peg_out.fees.fee_rate = wallet.consensus_fee_rate(&mut dbtx.dbtx_ref()).await;
+            peg_out.fees = wallet
+                .create_peg_out_tx(
+                    &mut dbtx.dbtx_ref(),
+                    &WalletOutput::PegOut(peg_out.clone()),
+                    &[0; 32],
+                )
+                .await
+                .unwrap()
+                .fees;
+            dbtx.ignore_uncommitted();
+
+            let out_point = fedimint_core::OutPoint {
+                txid: fedimint_core::TransactionId::all_zeros(),
+                out_idx: 0,
+            };
+            self.process_output(&WalletOutput::PegOut(peg_out), out_point)
+                .await;
+
+            // the peg-out is batched at the end of the session
+            self.run_session().await;
+            self.run_session().await;
+
+            let (pending, _) = self.wallet_state().await;
+            assert_eq!(pending.len(), 1);
+            *pending.keys().next().unwrap()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fee_bumps_replace_pending_peg_outs_until_one_confirms() {
+        let fed = TestFederation::new(4).await;
+        fed.peg_in(Amount::from_sat(100_000)).await;
+        let txid = fed.peg_out(Amount::from_sat(10_000)).await;
+
+        let (pending, utxos) = fed.wallet_state().await;
+        let original = pending[&txid].clone();
+        assert!(utxos.is_empty());
+        fed.bitcoin.submit_transaction(original.tx.clone()).await;
+
+        // BIP-125 requires the replacement to pay the relay fee for its own size
+        let min_fee_rate = Feerate {
+            sats_per_kvb: original.fees.fee_rate.sats_per_kvb + DEFAULT_MIN_RELAY_TX_FEE as u64,
+        };
+        let bump =
+            |fee_rate| WalletConsensusItem::PegOutFeeBump(PegOutFeeBumpItem { txid, fee_rate });
+
+        assert_eq!(
+            fed.process_consensus_item(
+                PeerId::from(0),
+                bump(Feerate {
+                    sats_per_kvb: min_fee_rate.sats_per_kvb - 1
+                })
+            )
+            .await,
+            Err("Fee bump does not exceed the minimum relay fee".to_string())
+        );
+
+        // the replacement is only signed once a threshold of guardians voted
+        for peer in 0..2 {
+            fed.process_consensus_item(PeerId::from(peer), bump(min_fee_rate))
+                .await
+                .unwrap();
+        }
+        fed.run_session().await;
+        assert_eq!(fed.wallet_state().await.0.len(), 1);
+
+        fed.process_consensus_item(PeerId::from(2), bump(min_fee_rate))
+            .await
+            .unwrap();
+        assert_eq!(
+            fed.process_consensus_item(PeerId::from(3), bump(min_fee_rate))
+                .await,
+            Err("Peg-out is already being replaced".to_string())
+        );
+        fed.run_session().await;
+
+        let (pending, _) = fed.wallet_state().await;
+        assert_eq!(pending.len(), 2);
+        let (replacement_txid, replacement) = pending
+            .into_iter()
+            .find(|(replacement_txid, _)| *replacement_txid != txid)
+            .unwrap();
+        assert_eq!(replacement.rbf.as_ref().map(|rbf| rbf.txid), Some(txid));
+        assert_eq!(replacement.selected_utxos, original.selected_utxos);
+        assert!(replacement.change < original.change);
+
+        // the mempool keeps the replacement since it pays higher fees
+        fed.bitcoin.submit_transaction(replacement.tx.clone()).await;
+        fed.bitcoin.mine_blocks(FINALITY_DELAY + 1).await;
+        fed.run_session().await;
+
+        // the original is retired and its change swapped for the replacement's
+        let (pending, utxos) = fed.wallet_state().await;
+        assert!(pending.is_empty());
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].0 .0.txid, replacement_txid);
+        assert_eq!(utxos[0].1.amount, replacement.change);
+
+        for txid in [txid, replacement_txid] {
+            assert_eq!(
+                fed.process_consensus_item(
+                    PeerId::from(0),
+                    WalletConsensusItem::PegOutFeeBump(PegOutFeeBumpItem {
+                        txid,
+                        fee_rate: Feerate {
+                            sats_per_kvb: 10 * min_fee_rate.sats_per_kvb
+                        },
+                    })
+                )
+                .await,
+                Err("Peg-out is not pending".to_string())
+            );
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1950,6 +3711,17 @@ mod fedimint_migration_tests {
                                 "validate_migrations was not able to read any fee rate votes"
                             );
                         }
+                        // Introduced after the v0 snapshot was created
+                        DbKeyPrefix::FeeRateFloor => {}
+                        DbKeyPrefix::TaprootPegOutTxSigCi => {}
+                        DbKeyPrefix::MigratedUtxo => {}
+                        DbKeyPrefix::TaprootMigrationRequest => {}
+                        DbKeyPrefix::TaprootMigrationApproval => {}
+                        DbKeyPrefix::PegOutFeeBumpRequest => {}
+                        DbKeyPrefix::PegOutFeeBumpVote => {}
+                        DbKeyPrefix::QueuedPegOut => {}
+                        DbKeyPrefix::PegOutFee => {}
+                        DbKeyPrefix::BlockHeight => {}
                     }
                 }
                 Ok(())
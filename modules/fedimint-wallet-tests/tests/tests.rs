@@ -188,6 +188,52 @@ async fn on_chain_peg_in_and_peg_out_happy_case() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn peg_ins_to_the_same_address_are_claimed_together() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_fed().await;
+    let client = fed.new_client().await;
+    let bitcoin = fixtures.bitcoin();
+    let bitcoin = bitcoin.lock_exclusive().await;
+    info!("Starting test peg_ins_to_the_same_address_are_claimed_together");
+
+    let finality_delay = 10;
+    bitcoin.mine_blocks(finality_delay).await;
+    await_consensus_to_catch_up(&client, 1).await?;
+
+    let deposits = 3;
+    let valid_until = SystemTime::now() + PEG_IN_TIMEOUT;
+    let (op, address) = client.get_deposit_address(valid_until).await?;
+    let sub = client.subscribe_deposit_updates(op).await?;
+    let mut sub = sub.into_stream();
+    assert_eq!(sub.ok().await?, DepositState::WaitingForTransaction);
+
+    for _ in 0..deposits {
+        bitcoin
+            .send_and_mine_block(&address, bsats(PEG_IN_AMOUNT_SATS))
+            .await;
+    }
+
+    // none of the deposits is confirmed before all of them have been seen
+    for _ in 0..deposits {
+        assert_matches!(sub.ok().await?, DepositState::WaitingForConfirmation(_));
+    }
+
+    bitcoin.mine_blocks(finality_delay).await;
+    for _ in 0..deposits {
+        assert_matches!(sub.ok().await?, DepositState::Confirmed(_));
+    }
+    for _ in 0..deposits {
+        assert_matches!(sub.ok().await?, DepositState::Claimed(_));
+    }
+    assert_eq!(
+        client.get_balance().await,
+        sats(deposits * PEG_IN_AMOUNT_SATS)
+    );
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 //#[ignore]
 async fn peg_out_fail_refund() -> anyhow::Result<()> {
@@ -539,6 +585,8 @@ fn build_wallet_server_configs(
                 network: bitcoin::Network::Regtest,
                 finality_delay: 10,
                 client_default_bitcoin_rpc: bitcoin_rpc.clone(),
+                peg_in_descriptor_kind: Default::default(),
+                peg_in_confirmation_tiers: vec![],
             },
         })?,
     );
@@ -378,6 +378,7 @@ rec {
       "fedimintd"
       "fedimint-cli"
       "fedimint-dbtool"
+      "fedimint-watchdog"
     ];
 
     defaultBin = "fedimintd";
@@ -429,6 +430,11 @@ rec {
       pkg = fedimint-pkgs;
       bin = "fedimint-dbtool";
     };
+  fedimint-watchdog = flakeboxLib.pickBinary
+    {
+      pkg = fedimint-pkgs;
+      bin = "fedimint-watchdog";
+    };
   gatewayd = flakeboxLib.pickBinary
     {
       pkg = gateway-pkgs;
@@ -27,7 +27,7 @@ use fedimint_mint_server::Mint;
 use fedimint_rocksdb::RocksDb;
 use fedimint_server::config::io::read_server_config;
 use fedimint_wallet_server::common::config::WalletConfig;
-use fedimint_wallet_server::common::db::{UTXOKey, UTXOPrefixKey};
+use fedimint_wallet_server::common::db::{MigratedUTXOKey, UTXOKey, UTXOPrefixKey};
 use fedimint_wallet_server::common::keys::CompressedPublicKey;
 use fedimint_wallet_server::common::tweakable::Tweakable;
 use fedimint_wallet_server::common::{
@@ -106,18 +106,20 @@ async fn main() -> anyhow::Result<()> {
 
     let opts: RecoveryTool = RecoveryTool::parse();
 
-    let (base_descriptor, base_key, network) = if let Some(config) = opts.config {
+    let (base_descriptor, legacy_descriptor, base_key, network) = if let Some(config) = opts.config
+    {
         let cfg = read_server_config(&opts.password, config).expect("Could not read config file");
         let wallet_cfg: WalletConfig = cfg
             .get_module_config_typed(LEGACY_HARDCODED_INSTANCE_ID_WALLET)
             .expect("Malformed wallet config");
         let base_descriptor = wallet_cfg.consensus.peg_in_descriptor;
+        let legacy_descriptor = wallet_cfg.consensus.legacy_peg_in_descriptor;
         let base_key = wallet_cfg.private.peg_in_key;
         let network = wallet_cfg.consensus.network;
 
-        (base_descriptor, base_key, network)
+        (base_descriptor, legacy_descriptor, base_key, network)
     } else if let (Some(descriptor), Some(key)) = (opts.descriptor, opts.key) {
-        (descriptor, key, opts.network)
+        (descriptor, None, key, opts.network)
     } else {
         panic!("Either config or descriptor will be provided by clap");
     };
@@ -142,22 +144,30 @@ async fn main() -> anyhow::Result<()> {
                 db.with_prefix_module_id(LEGACY_HARDCODED_INSTANCE_ID_WALLET)
             };
 
-            let utxos: Vec<ImportableWallet> = db
-                .begin_transaction()
-                .await
-                .find_by_prefix(&UTXOPrefixKey)
-                .await
-                .map(|(UTXOKey(outpoint), SpendableUTXO { tweak, amount })| {
-                    let descriptor = tweak_descriptor(&base_descriptor, &base_key, &tweak, network);
-
-                    ImportableWallet {
-                        outpoint,
-                        descriptor,
-                        amount_sat: amount,
+            let mut dbtx = db.begin_transaction().await;
+            let spendable_utxos: Vec<(UTXOKey, SpendableUTXO)> =
+                dbtx.find_by_prefix(&UTXOPrefixKey).await.collect().await;
+
+            let mut utxos = vec![];
+            for (UTXOKey(outpoint), SpendableUTXO { tweak, amount }) in spendable_utxos {
+                // After the migration to taproot only the UTXOs marked as migrated are
+                // locked to the current descriptor
+                let utxo_descriptor = match &legacy_descriptor {
+                    Some(legacy_descriptor)
+                        if dbtx.get_value(&MigratedUTXOKey(outpoint)).await.is_none() =>
+                    {
+                        legacy_descriptor
                     }
-                })
-                .collect()
-                .await;
+                    _ => &base_descriptor,
+                };
+                let descriptor = tweak_descriptor(utxo_descriptor, &base_key, &tweak, network);
+
+                utxos.push(ImportableWallet {
+                    outpoint,
+                    descriptor,
+                    amount_sat: amount,
+                });
+            }
 
             serde_json::to_writer(std::io::stdout().lock(), &utxos)
                 .expect("Could not encode to stdout")